@@ -1,11 +1,11 @@
 use bevy::{log, log::LogPlugin, prelude::*};
-use mr_client_lib::{MuddleClientConfig, MuddleClientPlugin, DEFAULT_SERVER_PORT};
+use mr_client_lib::{MuddleClientConfig, MuddleClientPlugin, MuddleLogPlugin, DEFAULT_SERVER_PORT};
 use mr_utils_lib::try_parse_from_env;
 use std::net::SocketAddr;
 
 fn main() {
     let mut app = App::new();
-    app.add_plugin(bevy_puffin::PuffinTracePlugin::new().with_scopes_off());
+    app.add_plugin(MuddleLogPlugin::default());
 
     mr_utils_lib::env::load_env();
 
@@ -21,6 +21,7 @@ fn main() {
         auth0_client_id: try_parse_from_env!("MUDDLE_AUTH0_CLIENT_ID"),
         matchmaker_url: try_parse_from_env!("MUDDLE_MATCHMAKER_URL"),
         server_addr: server_addr(),
+        discord_client_id: try_parse_from_env!("MUDDLE_DISCORD_CLIENT_ID"),
     })
     // Window and rendering.
     .insert_resource(Msaa { samples: 4 })
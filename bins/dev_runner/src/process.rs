@@ -0,0 +1,121 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    net::{SocketAddr, TcpStream},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(120);
+const READINESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub enum Readiness {
+    /// The process is considered ready as soon as it's spawned.
+    Spawned,
+    /// Waits until a TCP connection to the port succeeds.
+    Port(u16),
+    /// Waits until the output contains the pattern the given number of times.
+    LogLine(&'static str, usize),
+}
+
+/// A child process with its output prefixed by the service name, so that logs
+/// of all the services can be interleaved in a single terminal.
+pub struct ManagedProcess {
+    pub name: &'static str,
+    child: Child,
+    log_lines: mpsc::Receiver<String>,
+}
+
+impl ManagedProcess {
+    pub fn spawn(name: &'static str, mut command: Command) -> anyhow::Result<Self> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow::anyhow!("Failed to spawn {name}: {err}"))?;
+        log::info!("Started {name} (pid {})", child.id());
+
+        let (tx, log_lines) = mpsc::channel();
+        forward_output(name, child.stdout.take().unwrap(), tx.clone());
+        forward_output(name, child.stderr.take().unwrap(), tx);
+
+        Ok(Self {
+            name,
+            child,
+            log_lines,
+        })
+    }
+
+    pub fn wait_ready(&mut self, readiness: &Readiness) -> anyhow::Result<()> {
+        let started_at = Instant::now();
+        let mut matched_lines = 0;
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                anyhow::bail!("{} exited before getting ready ({status})", self.name);
+            }
+            let is_ready = match *readiness {
+                Readiness::Spawned => true,
+                Readiness::Port(port) => TcpStream::connect_timeout(
+                    &SocketAddr::from(([127, 0, 0, 1], port)),
+                    READINESS_POLL_INTERVAL,
+                )
+                .is_ok(),
+                Readiness::LogLine(pattern, count) => {
+                    matched_lines += self
+                        .log_lines
+                        .try_iter()
+                        .filter(|line| line.contains(pattern))
+                        .count();
+                    matched_lines >= count
+                }
+            };
+            if is_ready {
+                log::info!("{} is ready", self.name);
+                return Ok(());
+            }
+            if started_at.elapsed() > READINESS_TIMEOUT {
+                anyhow::bail!("Timed out waiting for {} to get ready", self.name);
+            }
+            std::thread::sleep(READINESS_POLL_INTERVAL);
+        }
+    }
+
+    pub fn try_wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+        // Lines are only needed for readiness checks, drop the rest.
+        self.log_lines.try_iter().for_each(drop);
+        Ok(self.child.try_wait()?)
+    }
+
+    pub fn kill(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            log::info!("Stopping {}", self.name);
+            if let Err(err) = self.child.kill() {
+                log::error!("Failed to kill {}: {:?}", self.name, err);
+            }
+        }
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for ManagedProcess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+fn forward_output(
+    name: &'static str,
+    output: impl Read + Send + 'static,
+    tx: mpsc::Sender<String>,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            println!("[{name:>11}] {line}");
+            // The receiver is dropped together with the process.
+            let _ = tx.send(line);
+        }
+    });
+}
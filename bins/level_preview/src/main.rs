@@ -0,0 +1,77 @@
+use anyhow::Context;
+use mr_client_lib::{render_level_preview, LevelPreviewSettings};
+use mr_shared_lib::game::level::SerializedLevel;
+use std::path::PathBuf;
+
+const USAGE: &str =
+    "Usage: mr_level_preview <level.json> <output.png> [--size <width>x<height>] [--warmup-frames <n>]";
+
+struct Args {
+    level_path: PathBuf,
+    output_path: PathBuf,
+    settings: LevelPreviewSettings,
+}
+
+fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(args) {
+        eprintln!("Failed to render the level preview: {err:?}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let data = std::fs::read(&args.level_path)
+        .with_context(|| format!("Failed to read {}", args.level_path.display()))?;
+    let level: SerializedLevel = serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", args.level_path.display()))?;
+    let preview = render_level_preview(level, &args.settings)?;
+    preview.save_png(&args.output_path)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Args> {
+    let mut paths = Vec::new();
+    let mut settings = LevelPreviewSettings::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => {
+                let size = args.next().context("Expected a value for --size")?;
+                let (width, height) = size
+                    .split_once('x')
+                    .with_context(|| format!("Invalid size: {size}"))?;
+                settings.width = width
+                    .parse()
+                    .with_context(|| format!("Invalid width: {width}"))?;
+                settings.height = height
+                    .parse()
+                    .with_context(|| format!("Invalid height: {height}"))?;
+            }
+            "--warmup-frames" => {
+                let frames = args
+                    .next()
+                    .context("Expected a value for --warmup-frames")?;
+                settings.warmup_frames = frames
+                    .parse()
+                    .with_context(|| format!("Invalid number of warmup frames: {frames}"))?;
+            }
+            _ if arg.starts_with("--") => anyhow::bail!("Unknown option: {arg}"),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [level_path, output_path]: [PathBuf; 2] = paths
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Expected a level path and an output path"))?;
+    Ok(Args {
+        level_path,
+        output_path,
+        settings,
+    })
+}
@@ -0,0 +1,153 @@
+use crate::{persistence::post_allocation, Config};
+use mr_messages_lib::{
+    AllocationFailureReason, GameServerState, InitLevel, PostAllocationRequest, Server,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Requests that didn't get a server within this time are reported as timed
+/// out.
+const ALLOCATION_TIMEOUT: Duration = Duration::from_secs(60);
+const EXPIRE_PENDING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An allocation request that is being handled.
+pub struct AuditedRequest {
+    request_id: Uuid,
+    /// Is known only after the id token is verified.
+    pub user_id: Option<i64>,
+    level_id: Option<i64>,
+    level_parent_id: Option<i64>,
+    protocol_version: u32,
+    received_at: Instant,
+}
+
+impl AuditedRequest {
+    pub fn new(request_id: Uuid, init_level: &InitLevel, protocol_version: u32) -> Self {
+        let (level_id, level_parent_id) = match init_level {
+            InitLevel::Existing(level_id) => (Some(*level_id), None),
+            InitLevel::Create { parent_id, .. } => (None, *parent_id),
+        };
+        Self {
+            request_id,
+            user_id: None,
+            level_id,
+            level_parent_id,
+            protocol_version,
+            received_at: Instant::now(),
+        }
+    }
+}
+
+/// Reports outcomes of allocation requests to persistence. Reporting is
+/// best-effort and never delays allocations.
+#[derive(Clone)]
+pub struct AllocationAudit {
+    reqwest_client: reqwest::Client,
+    config: Config,
+    /// Requests with posted GameServerAllocations, waiting for a server to
+    /// appear with the same request id.
+    pending: Arc<Mutex<HashMap<Uuid, AuditedRequest>>>,
+}
+
+impl AllocationAudit {
+    pub fn new(reqwest_client: reqwest::Client, config: Config) -> Self {
+        Self {
+            reqwest_client,
+            config,
+            pending: Default::default(),
+        }
+    }
+
+    pub async fn wait_for_server(&self, request: AuditedRequest) {
+        self.pending
+            .lock()
+            .await
+            .insert(request.request_id, request);
+    }
+
+    pub async fn fail_pending(&self, request_id: Uuid, reason: AllocationFailureReason) {
+        if let Some(request) = self.pending.lock().await.remove(&request_id) {
+            self.report(request, Err(reason));
+        }
+    }
+
+    /// Is expected to be called for every server update.
+    pub async fn server_updated(&self, server: &Server) {
+        if server.state != GameServerState::Allocated {
+            return;
+        }
+        if let Some(request) = self.pending.lock().await.remove(&server.request_id) {
+            self.report(request, Ok(server.name.clone()));
+        }
+    }
+
+    pub fn report(&self, request: AuditedRequest, result: Result<String, AllocationFailureReason>) {
+        let latency = request.received_at.elapsed();
+        let (server_name, failure_reason) = match result {
+            Ok(server_name) => {
+                log::info!(
+                    "Request {} got server {server_name} allocated in {latency:?}",
+                    request.request_id
+                );
+                (Some(server_name), None)
+            }
+            Err(reason) => {
+                log::warn!(
+                    "Request {} has failed after {latency:?}: {reason}",
+                    request.request_id
+                );
+                (None, Some(reason))
+            }
+        };
+        let body = PostAllocationRequest {
+            request_id: request.request_id,
+            user_id: request.user_id,
+            level_id: request.level_id,
+            level_parent_id: request.level_parent_id,
+            protocol_version: request.protocol_version,
+            server_name,
+            latency_millis: latency.as_millis().try_into().unwrap_or(u32::MAX),
+            failure_reason,
+        };
+
+        let reqwest_client = self.reqwest_client.clone();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            if let Err(err) = post_allocation(&reqwest_client, &config, &body).await {
+                log::error!(
+                    "Failed to record the allocation of request {}: {:?}",
+                    body.request_id,
+                    err
+                );
+            }
+        });
+    }
+
+    /// Reports the requests that haven't got a server in time.
+    pub async fn expire_pending(self) {
+        let mut interval = tokio::time::interval(EXPIRE_PENDING_INTERVAL);
+        loop {
+            interval.tick().await;
+            let expired = {
+                let mut pending = self.pending.lock().await;
+                let expired_ids = pending
+                    .values()
+                    .filter(|request| request.received_at.elapsed() > ALLOCATION_TIMEOUT)
+                    .map(|request| request.request_id)
+                    .collect::<Vec<_>>();
+                expired_ids
+                    .into_iter()
+                    .filter_map(|request_id| pending.remove(&request_id))
+                    .collect::<Vec<_>>()
+            };
+            for request in expired {
+                self.report(request, Err(AllocationFailureReason::TimedOut));
+            }
+        }
+    }
+}
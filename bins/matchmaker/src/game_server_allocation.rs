@@ -1,9 +1,23 @@
-use kube::{Client, CustomResource};
+use crate::GameServer;
+use kube::{
+    api::{Patch, PatchParams},
+    Client, CustomResource,
+};
+use mr_messages_lib::{
+    AllocationKind, ServerVersion, ALLOCATION_KIND_ANNOTATION, ALLOCATION_REQUEST_ID_ANNOTATION,
+    ALLOCATION_TRACE_PARENT_ANNOTATION, SERVER_VERSION_KEY,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Agones picks a server for an allocation on its own, so the server chosen by
+/// the matchmaker gets labeled with the request id, and the allocation prefers
+/// a server with that label.
+const PREFERRED_ALLOCATION_LABEL: &str = "muddle.run/preferred-allocation";
+pub const LEVEL_ID_ANNOTATION: &str = "level_id";
+
 #[derive(CustomResource, Debug, Serialize, Deserialize, Default, Clone, JsonSchema)]
 #[kube(
     group = "allocation.agones.dev",
@@ -39,29 +53,83 @@ pub struct PostGameServerAllocationParams {
     pub level_title: Option<String>,
     pub level_parent_id: Option<i64>,
     pub level_id: Option<i64>,
+    pub kind: AllocationKind,
+    /// Acceptable server versions in the order of preference.
+    pub versions: Vec<ServerVersion>,
+    /// The name of the GameServer picked by the matchmaker. If it gets
+    /// allocated by someone else first, any server of `versions` is used.
+    pub preferred_server: Option<String>,
+    pub trace_parent: Option<String>,
 }
 
 pub async fn post_game_server_allocation(
     client: Client,
     params: PostGameServerAllocationParams,
 ) -> kube::Result<()> {
+    let mut selectors = Vec::new();
+    if let Some(preferred_server) = &params.preferred_server {
+        let game_servers: kube::Api<GameServer> = kube::Api::namespaced(client.clone(), "default");
+        let patch = Patch::Merge(serde_json::json!({
+            "metadata": {
+                "labels": {
+                    PREFERRED_ALLOCATION_LABEL: params.request_id.to_string(),
+                },
+            },
+        }));
+        match game_servers
+            .patch(preferred_server, &PatchParams::default(), &patch)
+            .await
+        {
+            Ok(_) => selectors.push(GameServerSelector {
+                match_labels: [(
+                    PREFERRED_ALLOCATION_LABEL.to_owned(),
+                    params.request_id.to_string(),
+                )]
+                .into_iter()
+                .collect(),
+            }),
+            Err(err) => log::warn!(
+                "Failed to label the preferred GameServer {preferred_server}: {:?}",
+                err
+            ),
+        }
+    }
+    // Agones tries to satisfy selectors in the listed order, which lets us
+    // prefer the newest compatible version.
+    selectors.extend(params.versions.iter().map(|version| {
+        GameServerSelector {
+            match_labels: [
+                ("agones.dev/fleet".to_owned(), "mr-server".to_owned()),
+                (
+                    format!("agones.dev/sdk-{SERVER_VERSION_KEY}"),
+                    version.to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }));
+
     let api = kube::Api::namespaced(client, "default");
     api.create(
         &Default::default(),
         &GameServerAllocation {
             metadata: Default::default(),
             spec: GameServerAllocationSpec {
-                selectors: vec![GameServerSelector {
-                    match_labels: [("agones.dev/fleet".to_owned(), "mr-server".to_owned())]
-                        .into_iter()
-                        .collect(),
-                }],
+                selectors,
                 scheduling: None,
                 metadata: GameServerMetadata {
                     labels: Default::default(),
                     annotations: {
                         let mut metadata = HashMap::new();
-                        metadata.insert("request_id".to_owned(), params.request_id.to_string());
+                        metadata.insert(
+                            ALLOCATION_REQUEST_ID_ANNOTATION.to_owned(),
+                            params.request_id.to_string(),
+                        );
+                        metadata.insert(
+                            ALLOCATION_KIND_ANNOTATION.to_owned(),
+                            params.kind.to_string(),
+                        );
                         if let Some(user_id) = params.user_id {
                             metadata.insert("user_id".to_owned(), user_id.to_string());
                         }
@@ -73,7 +141,13 @@ pub async fn post_game_server_allocation(
                                 .insert("level_parent_id".to_owned(), level_parent_id.to_string());
                         }
                         if let Some(level_id) = params.level_id {
-                            metadata.insert("level_id".to_owned(), level_id.to_string());
+                            metadata.insert(LEVEL_ID_ANNOTATION.to_owned(), level_id.to_string());
+                        }
+                        if let Some(trace_parent) = params.trace_parent {
+                            metadata.insert(
+                                ALLOCATION_TRACE_PARENT_ANNOTATION.to_owned(),
+                                trace_parent,
+                            );
                         }
                         metadata
                     },
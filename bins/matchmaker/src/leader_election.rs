@@ -0,0 +1,484 @@
+use crate::{server_selection::RecentAllocationSnapshot, RelayedConnections, Servers};
+use k8s_openapi::{
+    api::{coordination::v1::Lease, core::v1::Pod},
+    apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta},
+    chrono::Utc,
+};
+use kube::{
+    api::{Api, ListParams, Patch, PatchParams, PostParams},
+    Client,
+};
+use mr_messages_lib::MatchmakerMessage;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast::Sender, watch};
+
+const LEASE_NAME: &str = "mr-matchmaker-leader";
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+const RENEW_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const PUBLISH_CONNECTED_CLIENTS_INTERVAL: Duration = Duration::from_secs(5);
+/// Followers relay allocation requests and webhook calls to this address.
+const LEADER_IP_ANNOTATION: &str = "muddle.run/leader-ip";
+/// The leader keeps the state that the next leader needs to take over in the
+/// lease, updating it with every renewal.
+const HANDOVER_STATE_ANNOTATION: &str = "muddle.run/handover-state";
+const CONNECTED_CLIENTS_ANNOTATION: &str = "muddle.run/connected-clients";
+const MATCHMAKER_POD_SELECTOR: &str = "service=mr-matchmaker";
+
+/// Identifies a matchmaker replica, is read from `MUDDLE_POD_NAME` and
+/// `MUDDLE_POD_IP` (which are expected to be set via the downward API).
+#[derive(Clone, Debug)]
+pub struct Replica {
+    pub pod_name: String,
+    pub pod_ip: IpAddr,
+}
+
+impl Replica {
+    pub fn from_env() -> Option<Self> {
+        let pod_name = std::env::var("MUDDLE_POD_NAME").ok()?;
+        let pod_ip = std::env::var("MUDDLE_POD_IP")
+            .ok()?
+            .parse()
+            .expect("Invalid MUDDLE_POD_IP");
+        Some(Self { pod_name, pod_ip })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Handles allocations, fleet autoscaler webhooks and drains outdated
+    /// servers.
+    Leader,
+    /// Serves server lists and relays everything else to the leader, if one is
+    /// known.
+    Follower { leader_ip: Option<IpAddr> },
+}
+
+#[derive(Clone)]
+pub struct Leadership {
+    role: watch::Receiver<Role>,
+    /// Is `None` if the matchmaker runs as a single replica.
+    replica: Option<(Client, Replica)>,
+}
+
+impl Leadership {
+    /// A single replica is always the leader.
+    pub fn standalone() -> Self {
+        let (_, role) = watch::channel(Role::Leader);
+        Self {
+            role,
+            replica: None,
+        }
+    }
+
+    pub fn new(client: Client, replica: Replica) -> (Self, watch::Sender<Role>) {
+        let (role_tx, role) = watch::channel(Role::Follower { leader_ip: None });
+        (
+            Self {
+                role,
+                replica: Some((client, replica)),
+            },
+            role_tx,
+        )
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.role.borrow() == Role::Leader
+    }
+
+    pub fn leader_ip(&self) -> Option<IpAddr> {
+        match *self.role.borrow() {
+            Role::Leader => None,
+            Role::Follower { leader_ip } => leader_ip,
+        }
+    }
+
+    /// Sums the clients connected to every replica, `local_clients` are the
+    /// ones connected to this one.
+    pub async fn connected_clients(&self, local_clients: usize) -> usize {
+        let Some((client, replica)) = &self.replica else {
+            return local_clients;
+        };
+        let pods: Api<Pod> = Api::namespaced(client.clone(), "default");
+        let pods = match pods
+            .list(&ListParams::default().labels(MATCHMAKER_POD_SELECTOR))
+            .await
+        {
+            Ok(pods) => pods,
+            Err(err) => {
+                log::error!("Failed to list matchmaker pods: {:?}", err);
+                return local_clients;
+            }
+        };
+        let remote_clients = pods
+            .items
+            .iter()
+            .filter(|pod| pod.metadata.name.as_ref() != Some(&replica.pod_name))
+            .filter_map(|pod| {
+                pod.metadata
+                    .annotations
+                    .as_ref()?
+                    .get(CONNECTED_CLIENTS_ANNOTATION)?
+                    .parse::<usize>()
+                    .ok()
+            })
+            .sum::<usize>();
+        local_clients + remote_clients
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct HandoverState {
+    recent_allocations: Vec<RecentAllocationSnapshot>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum LeaseAction {
+    Renew,
+    Acquire,
+    Follow,
+}
+
+/// Lease expiration is judged by the local clock: a lease is considered
+/// expired if its record hasn't changed for the lease duration, so that clock
+/// skew between replicas doesn't matter.
+#[derive(Default)]
+struct LeaseObserver {
+    record: Option<(Option<String>, Option<MicroTime>)>,
+    observed_at: Option<Instant>,
+}
+
+impl LeaseObserver {
+    fn action(&mut self, lease: &Lease, identity: &str, now: Instant) -> LeaseAction {
+        let spec = lease.spec.clone().unwrap_or_default();
+        let holder = spec.holder_identity.filter(|holder| !holder.is_empty());
+        if holder.as_deref() == Some(identity) {
+            return LeaseAction::Renew;
+        }
+        if holder.is_none() {
+            return LeaseAction::Acquire;
+        }
+
+        let record = Some((holder, spec.renew_time));
+        if self.record != record {
+            self.record = record;
+            self.observed_at = Some(now);
+        }
+        let lease_duration = spec.lease_duration_seconds.map_or(LEASE_DURATION, |secs| {
+            Duration::from_secs(secs.max(0) as u64)
+        });
+        let observed_at = self.observed_at.unwrap_or(now);
+        if now.duration_since(observed_at) >= lease_duration {
+            LeaseAction::Acquire
+        } else {
+            LeaseAction::Follow
+        }
+    }
+}
+
+/// Runs a lease-based leader election until the process receives a shutdown
+/// signal. The leader releases the lease when shutting down, so that one of
+/// the followers can take over without waiting for the lease to expire.
+pub async fn run_leader_election(
+    client: Client,
+    replica: Replica,
+    role_tx: watch::Sender<Role>,
+    servers: Servers,
+) {
+    let leases: Api<Lease> = Api::namespaced(client, "default");
+    let mut observer = LeaseObserver::default();
+    let mut last_renewed_at: Option<Instant> = None;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let is_leader = *role_tx.borrow() == Role::Leader;
+        let role = match try_acquire_or_renew(&leases, &replica, &servers, &mut observer).await {
+            Ok(role) => {
+                if role == Role::Leader {
+                    last_renewed_at = Some(Instant::now());
+                }
+                role
+            }
+            Err(err) => {
+                log::error!("Failed to update the leader lease: {:?}", err);
+                // Another replica can take over only once the lease expires, so
+                // there's no need to step down before that.
+                let has_expired = last_renewed_at.map_or(true, |renewed_at| {
+                    renewed_at.elapsed() + RENEW_INTERVAL >= LEASE_DURATION
+                });
+                if is_leader && !has_expired {
+                    Role::Leader
+                } else {
+                    Role::Follower { leader_ip: None }
+                }
+            }
+        };
+
+        if *role_tx.borrow() != role {
+            match &role {
+                Role::Leader => log::info!("Became the leader ({})", replica.pod_name),
+                Role::Follower { leader_ip } => {
+                    log::info!("Following the leader (leader ip: {:?})", leader_ip)
+                }
+            }
+            role_tx.send_replace(role);
+        }
+
+        let is_leader = *role_tx.borrow() == Role::Leader;
+        let interval = if is_leader {
+            RENEW_INTERVAL
+        } else {
+            RETRY_INTERVAL
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = &mut shutdown => {
+                if is_leader {
+                    release_lease(&leases, &replica, &servers).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+async fn try_acquire_or_renew(
+    leases: &Api<Lease>,
+    replica: &Replica,
+    servers: &Servers,
+    observer: &mut LeaseObserver,
+) -> kube::Result<Role> {
+    let Some(mut lease) = leases.get_opt(LEASE_NAME).await? else {
+        let mut lease = Lease {
+            metadata: ObjectMeta {
+                name: Some(LEASE_NAME.to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        hold_lease(&mut lease, replica, servers, true).await;
+        return match leases.create(&PostParams::default(), &lease).await {
+            Ok(_) => Ok(Role::Leader),
+            // Another replica has created the lease first.
+            Err(kube::Error::Api(err)) if err.code == 409 => Ok(Role::Follower { leader_ip: None }),
+            Err(err) => Err(err),
+        };
+    };
+
+    let action = observer.action(&lease, &replica.pod_name, Instant::now());
+    if action == LeaseAction::Follow {
+        let leader_ip = lease
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(LEADER_IP_ANNOTATION))
+            .and_then(|ip| ip.parse().ok());
+        return Ok(Role::Follower { leader_ip });
+    }
+
+    if action == LeaseAction::Acquire {
+        let handover_state = lease
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(HANDOVER_STATE_ANNOTATION))
+            .and_then(|state| {
+                serde_json::from_str::<HandoverState>(state)
+                    .map_err(|err| log::error!("Invalid handover state: {:?}", err))
+                    .ok()
+            });
+        hold_lease(&mut lease, replica, servers, true).await;
+        // Replacing fails if the lease has been modified since we've read it,
+        // which means that another replica has acquired it first.
+        match leases
+            .replace(LEASE_NAME, &PostParams::default(), &lease)
+            .await
+        {
+            Ok(_) => {}
+            Err(kube::Error::Api(err)) if err.code == 409 => {
+                return Ok(Role::Follower { leader_ip: None });
+            }
+            Err(err) => return Err(err),
+        }
+        if let Some(handover_state) = handover_state {
+            log::info!(
+                "Taking over {} recent allocations",
+                handover_state.recent_allocations.len()
+            );
+            servers
+                .restore_recent_allocations(handover_state.recent_allocations)
+                .await;
+        }
+        return Ok(Role::Leader);
+    }
+
+    hold_lease(&mut lease, replica, servers, false).await;
+    match leases
+        .replace(LEASE_NAME, &PostParams::default(), &lease)
+        .await
+    {
+        Ok(_) => Ok(Role::Leader),
+        Err(kube::Error::Api(err)) if err.code == 409 => Ok(Role::Follower { leader_ip: None }),
+        Err(err) => Err(err),
+    }
+}
+
+async fn hold_lease(lease: &mut Lease, replica: &Replica, servers: &Servers, is_acquiring: bool) {
+    let now = MicroTime(Utc::now());
+    let spec = lease.spec.get_or_insert_with(Default::default);
+    if is_acquiring {
+        spec.holder_identity = Some(replica.pod_name.clone());
+        spec.acquire_time = Some(now.clone());
+        spec.lease_transitions = Some(spec.lease_transitions.unwrap_or(0) + 1);
+    }
+    spec.renew_time = Some(now);
+    spec.lease_duration_seconds = Some(LEASE_DURATION.as_secs() as i32);
+
+    let handover_state = HandoverState {
+        recent_allocations: servers.recent_allocations_snapshot().await,
+    };
+    let annotations = lease
+        .metadata
+        .annotations
+        .get_or_insert_with(Default::default);
+    annotations.insert(LEADER_IP_ANNOTATION.to_owned(), replica.pod_ip.to_string());
+    annotations.insert(
+        HANDOVER_STATE_ANNOTATION.to_owned(),
+        serde_json::to_string(&handover_state).expect("Failed to serialize the handover state"),
+    );
+}
+
+async fn release_lease(leases: &Api<Lease>, replica: &Replica, servers: &Servers) {
+    log::info!("Releasing the leader lease");
+    let result = async {
+        let mut lease = leases.get(LEASE_NAME).await?;
+        if lease
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.holder_identity.as_ref())
+            != Some(&replica.pod_name)
+        {
+            return Ok(());
+        }
+        hold_lease(&mut lease, replica, servers, false).await;
+        if let Some(spec) = &mut lease.spec {
+            spec.holder_identity = None;
+        }
+        leases
+            .replace(LEASE_NAME, &PostParams::default(), &lease)
+            .await
+            .map(|_| ())
+    }
+    .await;
+    if let Err(err) = result {
+        log::error!("Failed to release the leader lease: {:?}", err);
+    }
+}
+
+/// Keeps the number of clients connected to this replica in its pod
+/// annotations, so that the leader can sum them up for the fleet autoscaler.
+pub async fn publish_connected_clients(
+    client: Client,
+    replica: Replica,
+    tx: Sender<MatchmakerMessage>,
+    relayed_connections: RelayedConnections,
+) {
+    let pods: Api<Pod> = Api::namespaced(client, "default");
+    let mut published = None;
+    loop {
+        let connected_clients = relayed_connections.local_clients(&tx);
+        if published != Some(connected_clients) {
+            let patch = Patch::Merge(serde_json::json!({
+                "metadata": {
+                    "annotations": {
+                        CONNECTED_CLIENTS_ANNOTATION: connected_clients.to_string(),
+                    },
+                },
+            }));
+            match pods
+                .patch(&replica.pod_name, &PatchParams::default(), &patch)
+                .await
+            {
+                Ok(_) => published = Some(connected_clients),
+                Err(err) => log::error!("Failed to publish connected clients: {:?}", err),
+            }
+        }
+        tokio::time::sleep(PUBLISH_CONNECTED_CLIENTS_INTERVAL).await;
+    }
+}
+
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen to SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::{api::coordination::v1::LeaseSpec, chrono::TimeZone};
+
+    fn lease(holder: Option<&str>, renewed_at_secs: i64) -> Lease {
+        Lease {
+            spec: Some(LeaseSpec {
+                holder_identity: holder.map(ToOwned::to_owned),
+                renew_time: Some(MicroTime(Utc.timestamp_opt(renewed_at_secs, 0).unwrap())),
+                lease_duration_seconds: Some(LEASE_DURATION.as_secs() as i32),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_lease_observer() {
+        let now = Instant::now();
+        let mut observer = LeaseObserver::default();
+
+        assert_eq!(
+            observer.action(&lease(None, 0), "a", now),
+            LeaseAction::Acquire
+        );
+        assert_eq!(
+            observer.action(&lease(Some("a"), 0), "a", now),
+            LeaseAction::Renew
+        );
+
+        // The lease is held by another replica, which keeps renewing it.
+        assert_eq!(
+            observer.action(&lease(Some("b"), 0), "a", now),
+            LeaseAction::Follow
+        );
+        let later = now + LEASE_DURATION;
+        assert_eq!(
+            observer.action(&lease(Some("b"), 10), "a", later),
+            LeaseAction::Follow
+        );
+        // Timestamps in the lease don't matter, only the time since it changed.
+        let even_later = later + LEASE_DURATION / 2;
+        assert_eq!(
+            observer.action(&lease(Some("b"), 10), "a", even_later),
+            LeaseAction::Follow
+        );
+        assert_eq!(
+            observer.action(&lease(Some("b"), 10), "a", later + LEASE_DURATION),
+            LeaseAction::Acquire
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use mr_messages_lib::{GameServerState, MatchmakerMessage, Server, ServerVersion, PLAYER_CAPACITY};
+use std::net::SocketAddr;
+use tokio::sync::broadcast::Sender;
+
+/// A comma-separated list of game server addresses. If it's set, the
+/// matchmaker doesn't talk to Kubernetes and allocates these servers instead
+/// of Agones fleets, which is useful for running the whole stack locally.
+pub const LOCAL_GAME_SERVERS_ENV: &str = "MUDDLE_LOCAL_GAME_SERVERS";
+
+pub fn local_servers_from_env() -> Option<Vec<Server>> {
+    let addrs = std::env::var(LOCAL_GAME_SERVERS_ENV).ok()?;
+    match parse_local_servers(&addrs) {
+        Ok(servers) => Some(servers),
+        Err(err) => panic!("Invalid {LOCAL_GAME_SERVERS_ENV}: {err}"),
+    }
+}
+
+/// Local servers aren't reserved for a single request, so that any number of
+/// clients can create a "new" server. This means that the level requested via
+/// the matchmaker is ignored: local servers load the one they are configured
+/// with.
+pub fn allocate_local_server(
+    server: Server,
+    tx: &Sender<MatchmakerMessage>,
+    request_id: uuid::Uuid,
+) -> Server {
+    log::info!(
+        "Allocating a local server {} ({}) for request {request_id}",
+        server.name,
+        server.addr
+    );
+    let server = Server {
+        state: GameServerState::Allocated,
+        request_id,
+        ..server
+    };
+    let _ = tx.send(MatchmakerMessage::ServerUpdated(server.clone()));
+    server
+}
+
+fn parse_local_servers(addrs: &str) -> Result<Vec<Server>, String> {
+    addrs
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .enumerate()
+        .map(|(i, addr)| {
+            let addr = addr
+                .parse::<SocketAddr>()
+                .map_err(|err| format!("{addr}: {err}"))?;
+            Ok(Server {
+                name: format!("local-{i}"),
+                state: GameServerState::Ready,
+                addr,
+                player_capacity: PLAYER_CAPACITY,
+                player_count: 0,
+                request_id: Default::default(),
+                // Local servers are expected to be built from the same sources.
+                version: ServerVersion::new(0),
+                draining: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_local_servers() {
+        let servers = parse_local_servers("127.0.0.1:3455, 127.0.0.1:3456,").unwrap();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[1].name, "local-1");
+        assert_eq!(servers[1].addr, "127.0.0.1:3456".parse().unwrap());
+        assert_eq!(servers[1].state, GameServerState::Ready);
+
+        assert!(parse_local_servers("localhost").is_err());
+    }
+}
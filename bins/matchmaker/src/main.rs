@@ -1,43 +1,74 @@
 #![feature(int_roundings)]
 #![feature(async_closure)]
 
+mod allocation_audit;
 mod game_server_allocation;
 mod jwks;
+mod leader_election;
+mod local_servers;
 mod persistence;
+mod server_list;
+mod server_selection;
+mod status_page;
+mod websocket;
 
 use crate::{
-    game_server_allocation::{post_game_server_allocation, PostGameServerAllocationParams},
+    allocation_audit::{AllocationAudit, AuditedRequest},
+    game_server_allocation::{
+        post_game_server_allocation, PostGameServerAllocationParams, LEVEL_ID_ANNOTATION,
+    },
     jwks::poll_jwks,
+    leader_election::{publish_connected_clients, run_leader_election, Leadership, Replica},
+    local_servers::{allocate_local_server, local_servers_from_env},
     persistence::get_registered_user,
+    server_list::list_servers_page,
+    server_selection::{
+        select_server, RecentAllocationSnapshot, RecentAllocations, ServerPlacement,
+    },
+    status_page::{serve_status_page, StatusPageParams},
+    websocket::{accept_connection, decode_request, encode_message, AcceptedConnection},
 };
 use future::FutureExt;
 use futures::{future, pin_mut, stream::BoxStream, SinkExt, StreamExt, TryFutureExt, TryStreamExt};
 use kube::{
-    api::{Api, ListParams, WatchEvent},
+    api::{Api, ListParams, Patch, PatchParams, WatchEvent},
     Client, CustomResource,
 };
 use mr_messages_lib::{
-    deserialize_binary, serialize_binary, GameServerState, GetRegisteredUserQuery, InitLevel,
-    MatchmakerMessage, MatchmakerRequest, Server,
+    deserialize_binary, serialize_binary,
+    validation::{format_errors, validate_level_title},
+    AllocationFailureReason, AllocationKind, GameServerState, GetRegisteredUserQuery, InitLevel,
+    ListedServer, MatchmakerMessage, MatchmakerRequest, Server, ServerFilters, ServerListPage,
+    ServerSortOrder, ServerVersion, ALLOCATION_KIND_ANNOTATION, ALLOCATION_REQUEST_ID_ANNOTATION,
+    SERVER_DRAIN_ANNOTATION, SERVER_VERSION_KEY,
+};
+use mr_utils_lib::{
+    jwks::Jwks,
+    kube_discovery,
+    telemetry::{self, TraceSpan},
+    try_parse_from_env,
 };
-use mr_utils_lib::{jwks::Jwks, kube_discovery, try_parse_from_env};
 use reqwest::Url;
 use schemars::JsonSchema;
 use serde::Deserializer;
 use serde_derive::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::Read,
     net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::{
         broadcast::{Receiver, Sender},
-        Mutex, MutexGuard,
+        mpsc, Mutex, MutexGuard,
     },
 };
-use tokio_tungstenite::{tungstenite, tungstenite::Message};
+use tokio_tungstenite::tungstenite::Message;
 
 #[derive(Clone)]
 pub struct Config {
@@ -49,9 +80,23 @@ pub struct Config {
     auth0_client_id: String,
 }
 
+/// GameServers can be labeled with a region via the fleet template, servers
+/// without the label are reported under the default one.
+const SERVER_REGION_LABEL: &str = "region";
+const DEFAULT_SERVER_REGION: &str = "default";
+const WEBSOCKET_PORT: u16 = 8080;
+const WEBHOOK_PORT: u16 = 8081;
+/// Followers connect to the leader via this path to relay allocation requests
+/// of their clients.
+const RELAY_PATH: &str = "/relay";
+
 #[derive(Clone, Default)]
 pub struct Servers {
     servers: std::sync::Arc<Mutex<HashMap<String, Server>>>,
+    /// Must be locked after `servers`.
+    placements: std::sync::Arc<Mutex<HashMap<String, ServerPlacement>>>,
+    /// Must be locked after `placements`.
+    recent_allocations: std::sync::Arc<Mutex<RecentAllocations>>,
 }
 
 #[derive(Clone, Default)]
@@ -60,6 +105,37 @@ pub struct CreateServerRequests {
         std::sync::Arc<Mutex<HashMap<SocketAddr, (uuid::Uuid, PostGameServerAllocationParams)>>>,
 }
 
+/// Counts the connections relayed from followers, which aren't clients
+/// themselves.
+#[derive(Clone, Default)]
+pub struct RelayedConnections {
+    count: Arc<AtomicUsize>,
+}
+
+impl RelayedConnections {
+    pub fn local_clients(&self, tx: &Sender<MatchmakerMessage>) -> usize {
+        tx.receiver_count()
+            .saturating_sub(self.count.load(Ordering::SeqCst))
+    }
+
+    fn track(&self) -> RelayedConnectionGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        RelayedConnectionGuard {
+            count: self.count.clone(),
+        }
+    }
+}
+
+struct RelayedConnectionGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for RelayedConnectionGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl CreateServerRequests {
     pub async fn lock(
         &self,
@@ -99,16 +175,21 @@ pub struct GameServerPlayerStatus {
 }
 
 impl Servers {
-    pub async fn init(&self, initial_list: Vec<Server>) {
+    pub async fn init(&self, initial_list: Vec<(Server, ServerPlacement)>) {
         let mut servers = self.servers.lock().await;
+        let mut placements = self.placements.lock().await;
         servers.clear();
-        for server in initial_list {
+        placements.clear();
+        for (server, placement) in initial_list {
+            placements.insert(server.name.clone(), placement);
             servers.insert(server.name.clone(), server);
         }
     }
 
-    pub async fn add(&self, server: Server) {
+    pub async fn add(&self, server: Server, placement: ServerPlacement) {
         let mut servers = self.servers.lock().await;
+        let mut placements = self.placements.lock().await;
+        placements.insert(server.name.clone(), placement);
         servers.insert(server.name.clone(), server);
     }
 
@@ -119,12 +200,131 @@ impl Servers {
 
     pub async fn remove(&self, name: &str) -> Option<Server> {
         let mut servers = self.servers.lock().await;
+        let mut placements = self.placements.lock().await;
+        placements.remove(name);
         servers.remove(name)
     }
 
-    pub async fn all(&self) -> Vec<Server> {
+    /// Solo practice servers are excluded, as they can't be joined anyway.
+    pub async fn public(&self) -> Vec<Server> {
+        let servers = self.servers.lock().await;
+        let placements = self.placements.lock().await;
+        servers
+            .values()
+            .filter(|server| is_public(&placements, &server.name))
+            .cloned()
+            .collect()
+    }
+
+    pub async fn is_public(&self, name: &str) -> bool {
+        let placements = self.placements.lock().await;
+        is_public(&placements, name)
+    }
+
+    pub async fn list(
+        &self,
+        request_id: uuid::Uuid,
+        filters: &ServerFilters,
+        sort_order: ServerSortOrder,
+        offset: u32,
+        limit: u32,
+    ) -> ServerListPage {
+        let servers = self.servers.lock().await;
+        let placements = self.placements.lock().await;
+        let listed_servers = servers
+            .values()
+            .filter(|server| is_public(&placements, &server.name))
+            .map(|server| {
+                let placement = placements.get(&server.name);
+                ListedServer {
+                    server: server.clone(),
+                    region: placement.map_or_else(
+                        || DEFAULT_SERVER_REGION.to_owned(),
+                        |placement| placement.region.clone(),
+                    ),
+                    level_id: placement.and_then(|placement| placement.level_id),
+                }
+            })
+            .collect();
+        list_servers_page(
+            listed_servers,
+            request_id,
+            filters,
+            sort_order,
+            offset,
+            limit,
+        )
+    }
+
+    /// Returns versions of the ready servers that can be allocated for a client
+    /// with the specified protocol version, the newest ones go first.
+    pub async fn compatible_versions(&self, protocol_version: u32) -> Vec<ServerVersion> {
+        let servers = self.servers.lock().await;
+        let mut versions = servers
+            .values()
+            .filter(|server| {
+                server.state == GameServerState::Ready
+                    && !server.draining
+                    && server.version.protocol == protocol_version
+            })
+            .map(|server| server.version)
+            .collect::<Vec<_>>();
+        versions.sort_by(|a, b| b.cmp(a));
+        versions.dedup();
+        versions
+    }
+
+    /// Picks a ready server to prefer for a new allocation (see
+    /// [`select_server`]) and remembers the choice, so that the following
+    /// allocations account for it before the server status gets updated.
+    pub async fn select_for_allocation(
+        &self,
+        protocol_version: u32,
+        user_id: Option<i64>,
+    ) -> Option<Server> {
+        let servers = self.servers.lock().await;
+        let placements = self.placements.lock().await;
+        let mut recent_allocations = self.recent_allocations.lock().await;
+        let now = std::time::Instant::now();
+        let server = select_server(
+            &servers,
+            &placements,
+            &recent_allocations,
+            user_id,
+            |server| !server.draining && server.version.protocol == protocol_version,
+            now,
+        )?
+        .clone();
+        let node = placements
+            .get(&server.name)
+            .map(|placement| placement.node.clone())
+            .unwrap_or_default();
+        recent_allocations.record(node, user_id, now);
+        Some(server)
+    }
+
+    pub async fn recent_allocations_snapshot(&self) -> Vec<RecentAllocationSnapshot> {
+        let recent_allocations = self.recent_allocations.lock().await;
+        recent_allocations.snapshot(std::time::Instant::now())
+    }
+
+    pub async fn restore_recent_allocations(&self, snapshot: Vec<RecentAllocationSnapshot>) {
+        let mut recent_allocations = self.recent_allocations.lock().await;
+        recent_allocations.restore(snapshot, std::time::Instant::now());
+    }
+
+    /// Returns the names of the servers running a version older than the newest
+    /// one, which haven't received a drain signal yet.
+    pub async fn outdated(&self) -> Vec<String> {
         let servers = self.servers.lock().await;
-        servers.values().cloned().collect()
+        let Some(newest_version) = servers.values().map(|server| server.version).max() else {
+            return Vec::new();
+        };
+        servers
+            .values()
+            .filter(|server| !server.draining && server.version < newest_version)
+            .map(|server| server.name.clone())
+            .collect()
     }
 
     pub async fn allocated_count(&self) -> usize {
@@ -136,6 +336,13 @@ impl Servers {
     }
 }
 
+/// Servers without a known placement are considered public.
+fn is_public(placements: &HashMap<String, ServerPlacement>, name: &str) -> bool {
+    placements.get(name).map_or(true, |placement| {
+        placement.allocation_kind == AllocationKind::Public
+    })
+}
+
 #[tokio::main]
 async fn main() {
     mr_utils_lib::env::load_env();
@@ -159,12 +366,21 @@ async fn main() {
 
     let mut builder = env_logger::Builder::from_default_env();
     builder.filter_level(log::LevelFilter::Info).init();
+    telemetry::init("mr_matchmaker");
 
     log::info!("Starting the matchmaker server...");
 
-    let client = Client::try_default()
-        .await
-        .expect("Unable to detect kubernetes environment");
+    let local_servers = local_servers_from_env();
+    let client = if local_servers.is_some() {
+        log::info!("Using local game servers, Kubernetes is disabled");
+        None
+    } else {
+        Some(
+            Client::try_default()
+                .await
+                .expect("Unable to detect kubernetes environment"),
+        )
+    };
 
     let private_persistence_url: Option<Url> =
         try_parse_from_env!("MUDDLE_PRIVATE_PERSISTENCE_URL");
@@ -175,10 +391,9 @@ async fn main() {
             .zip(private_persistence_url)
             .ok_or(()),
     )
-    .or_else(async move |_| {
-        kube_discovery::discover_persistence(cloned_client)
-            .await
-            .ok_or(())
+    .or_else(async move |_| match cloned_client {
+        Some(client) => kube_discovery::discover_persistence(client).await.ok_or(()),
+        None => Err(()),
     })
     .await
     .expect("Failed to discover the persistence service");
@@ -205,34 +420,113 @@ async fn main() {
     let servers = Servers::default();
     let create_server_requests = CreateServerRequests::default();
     let jwks = Jwks::default();
-    let mut watch_game_servers = tokio::spawn(watch_game_servers(
-        client.clone(),
-        tx.clone(),
-        servers.clone(),
-    ))
+    let reqwest_client = reqwest::Client::default();
+    let allocation_audit = AllocationAudit::new(reqwest_client.clone(), config.clone());
+    let relayed_connections = RelayedConnections::default();
+    let (leadership, elect_leader) = match (client.clone(), Replica::from_env()) {
+        (Some(client), Some(replica)) => {
+            log::info!("Running as replica {}, electing a leader", replica.pod_name);
+            let (leadership, role_tx) = Leadership::new(client.clone(), replica.clone());
+            tokio::spawn(publish_connected_clients(
+                client.clone(),
+                replica.clone(),
+                tx.clone(),
+                relayed_connections.clone(),
+            ));
+            (
+                leadership,
+                tokio::spawn(run_leader_election(
+                    client,
+                    replica,
+                    role_tx,
+                    servers.clone(),
+                )),
+            )
+        }
+        // Without knowing its pod, the matchmaker assumes it's the only replica.
+        _ => (Leadership::standalone(), tokio::spawn(future::pending())),
+    };
+    let mut elect_leader = elect_leader.fuse();
+    let mut watch_game_servers = match (client.clone(), local_servers) {
+        (Some(client), _) => tokio::spawn(watch_game_servers(
+            client,
+            tx.clone(),
+            servers.clone(),
+            allocation_audit.clone(),
+            leadership.clone(),
+        )),
+        (None, local_servers) => {
+            let local_servers = local_servers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|server| {
+                    (
+                        server,
+                        ServerPlacement {
+                            region: DEFAULT_SERVER_REGION.to_owned(),
+                            node: String::new(),
+                            level_id: None,
+                            allocation_kind: AllocationKind::Public,
+                        },
+                    )
+                })
+                .collect();
+            servers.init(local_servers).await;
+            // Local servers never change, there's nothing to watch.
+            tokio::spawn(future::pending())
+        }
+    }
+    .fuse();
+    let mut serve_webhook_service = tokio::spawn(serve_webhook_service(WebhookServiceParams {
+        tx: tx.clone(),
+        servers: servers.clone(),
+        leadership: leadership.clone(),
+        relayed_connections: relayed_connections.clone(),
+        reqwest_client: reqwest_client.clone(),
+    }))
+    .fuse();
+    let mut serve_status_page = tokio::spawn(serve_status_page(StatusPageParams {
+        tx: tx.clone(),
+        servers: servers.clone(),
+        create_server_requests: create_server_requests.clone(),
+        leadership: leadership.clone(),
+        relayed_connections: relayed_connections.clone(),
+    }))
     .fuse();
-    let mut serve_webhook_service =
-        tokio::spawn(serve_webhook_service(tx.clone(), servers.clone())).fuse();
     let mut listen_websocket = tokio::spawn(listen_websocket(HandleConnectionParams {
         tx,
         kube_client: client,
-        reqwest_client: Default::default(),
+        reqwest_client,
         servers,
         create_server_requests,
         jwks: jwks.clone(),
         config: config.clone(),
+        allocation_audit: allocation_audit.clone(),
+        leadership,
+        relayed_connections,
     }))
     .fuse();
     let mut poll_jwks = tokio::spawn(poll_jwks(config, jwks)).fuse();
+    let mut expire_pending_allocations = tokio::spawn(allocation_audit.expire_pending()).fuse();
     futures::select!(
         _ = watch_game_servers => {},
         _ = serve_webhook_service => {},
+        _ = serve_status_page => {},
         _ = listen_websocket => {},
         _ = poll_jwks => {},
+        _ = expire_pending_allocations => {},
+        _ = elect_leader => {},
     );
+    telemetry::shutdown();
 }
 
-async fn watch_game_servers(client: Client, tx: Sender<MatchmakerMessage>, servers: Servers) {
+async fn watch_game_servers(
+    client: Client,
+    tx: Sender<MatchmakerMessage>,
+    servers: Servers,
+    allocation_audit: AllocationAudit,
+    leadership: Leadership,
+) {
     let game_servers: Api<GameServer> = Api::namespaced(client, "default");
     log::info!("Watching GameServer updates...");
     let mut stream = init_stream_and_watch(game_servers.clone(), servers.clone()).await;
@@ -256,8 +550,12 @@ async fn watch_game_servers(client: Client, tx: Sender<MatchmakerMessage>, serve
                 if let Some(server_command) = server_command_from_resource(&resource) {
                     log::info!("Resource updated: {:?}", resource.status);
                     match server_command {
-                        ServerCommand::Update(server) => {
-                            servers.add(server.clone()).await;
+                        ServerCommand::Update(server, placement) => {
+                            allocation_audit.server_updated(&server).await;
+                            servers.add(server.clone(), placement).await;
+                            if leadership.is_leader() {
+                                drain_outdated_servers(game_servers.clone(), servers.clone()).await;
+                            }
                             Some(MatchmakerMessage::ServerUpdated(server))
                         }
                         ServerCommand::Delete(server_name) => {
@@ -276,7 +574,7 @@ async fn watch_game_servers(client: Client, tx: Sender<MatchmakerMessage>, serve
                 if let Some(server_command) = server_command_from_resource(&resource) {
                     log::info!("Resource deleted: {:?}", server_command);
                     match server_command {
-                        ServerCommand::Update(server) => {
+                        ServerCommand::Update(server, _) => {
                             servers.remove(&server.name).await;
                             Some(MatchmakerMessage::ServerRemoved(server.name))
                         }
@@ -323,8 +621,10 @@ async fn init_stream_and_watch<'a>(
         .items
         .into_iter()
         .filter_map(|gs| {
-            if let Some(ServerCommand::Update(server)) = server_command_from_resource(&gs) {
-                Some(server)
+            if let Some(ServerCommand::Update(server, placement)) =
+                server_command_from_resource(&gs)
+            {
+                Some((server, placement))
             } else {
                 None
             }
@@ -338,6 +638,29 @@ async fn init_stream_and_watch<'a>(
     stream
 }
 
+/// Signals the servers running an older version to shut down once they become
+/// idle. Ready servers exit immediately, so that the fleet replaces them with
+/// new ones, while allocated servers keep serving their players until the last
+/// one leaves.
+async fn drain_outdated_servers(game_servers: Api<GameServer>, servers: Servers) {
+    let patch = Patch::Merge(serde_json::json!({
+        "metadata": {
+            "annotations": {
+                SERVER_DRAIN_ANNOTATION: "true",
+            },
+        },
+    }));
+    for server_name in servers.outdated().await {
+        log::info!("Draining an outdated GameServer {server_name}...");
+        if let Err(err) = game_servers
+            .patch(&server_name, &PatchParams::default(), &patch)
+            .await
+        {
+            log::error!("Failed to drain GameServer {server_name}: {err:?}");
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct FleetAutoscaleReview {
     request: FleetAutoscaleRequest,
@@ -368,7 +691,16 @@ struct FleetStatus {
     allocated_replicas: u32,
 }
 
-async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers) {
+#[derive(Clone)]
+struct WebhookServiceParams {
+    tx: Sender<MatchmakerMessage>,
+    servers: Servers,
+    leadership: Leadership,
+    relayed_connections: RelayedConnections,
+    reqwest_client: reqwest::Client,
+}
+
+async fn serve_webhook_service(params: WebhookServiceParams) {
     let make_svc = hyper::service::make_service_fn(move |_conn| {
         fn bad_request() -> hyper::Response<hyper::Body> {
             hyper::Response::builder()
@@ -377,12 +709,16 @@ async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers)
                 .unwrap()
         }
 
-        let tx = tx.clone();
-        let servers = servers.clone();
+        let params = params.clone();
 
         let serve = move |req: hyper::Request<hyper::Body>| {
-            let tx = tx.clone();
-            let servers = servers.clone();
+            let WebhookServiceParams {
+                tx,
+                servers,
+                leadership,
+                relayed_connections,
+                reqwest_client,
+            } = params.clone();
             async move {
                 let json_string = match hyper::body::aggregate(req)
                     .await
@@ -402,6 +738,15 @@ async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers)
 
                 log::info!("Incoming request: {}", json_string);
 
+                if !leadership.is_leader() {
+                    return Ok(forward_webhook_to_leader(
+                        &reqwest_client,
+                        &leadership,
+                        json_string,
+                    )
+                    .await);
+                }
+
                 let mut fleet_autoscale_review: FleetAutoscaleReview =
                     match serde_json::from_str(&json_string) {
                         Ok(request) => request,
@@ -411,7 +756,9 @@ async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers)
                         }
                     };
 
-                let active_players = tx.receiver_count() as u32;
+                let active_players = leadership
+                    .connected_clients(relayed_connections.local_clients(&tx))
+                    .await as u32;
                 let allocated_servers = servers.allocated_count().await as u32;
                 let desired_replicas_count = active_players.min(1) + allocated_servers;
                 fleet_autoscale_review.response = Some(FleetAutoscaleResponse {
@@ -437,7 +784,7 @@ async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers)
         async { Ok::<_, std::convert::Infallible>(hyper::service::service_fn(serve)) }
     });
 
-    let addr = ([0, 0, 0, 0], 8081).into();
+    let addr = ([0, 0, 0, 0], WEBHOOK_PORT).into();
 
     let server = hyper::Server::bind(&addr).serve(make_svc);
 
@@ -448,8 +795,45 @@ async fn serve_webhook_service(tx: Sender<MatchmakerMessage>, servers: Servers)
     }
 }
 
+/// Fleet autoscaler reviews are answered by the leader, so that all the
+/// replicas scale the fleet consistently.
+async fn forward_webhook_to_leader(
+    reqwest_client: &reqwest::Client,
+    leadership: &Leadership,
+    body: String,
+) -> hyper::Response<hyper::Body> {
+    let result = async {
+        let leader_ip = leadership
+            .leader_ip()
+            .ok_or_else(|| anyhow::anyhow!("The leader is unknown"))?;
+        let response = reqwest_client
+            .post(format!("http://{leader_ip}:{WEBHOOK_PORT}"))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok::<_, anyhow::Error>(response.bytes().await?)
+    }
+    .await;
+
+    match result {
+        Ok(body) => hyper::Response::new(body.into()),
+        Err(err) => {
+            log::error!(
+                "Failed to forward the webhook request to the leader: {:?}",
+                err
+            );
+            hyper::Response::builder()
+                .status(503)
+                .body(hyper::Body::empty())
+                .unwrap()
+        }
+    }
+}
+
 async fn listen_websocket(params: HandleConnectionParams) {
-    let addr = "0.0.0.0:8080";
+    let addr = SocketAddr::from(([0, 0, 0, 0], WEBSOCKET_PORT));
     let listener = TcpListener::bind(addr).await.expect("Failed to bind");
     log::info!("Listening on: {}", addr);
 
@@ -473,12 +857,16 @@ async fn listen_websocket(params: HandleConnectionParams) {
 #[derive(Clone)]
 struct HandleConnectionParams {
     tx: Sender<MatchmakerMessage>,
-    kube_client: Client,
+    /// Is `None` if the matchmaker uses local game servers.
+    kube_client: Option<Client>,
     reqwest_client: reqwest::Client,
     servers: Servers,
     create_server_requests: CreateServerRequests,
     jwks: Jwks,
     config: Config,
+    allocation_audit: AllocationAudit,
+    leadership: Leadership,
+    relayed_connections: RelayedConnections,
 }
 
 async fn handle_connection(
@@ -489,19 +877,35 @@ async fn handle_connection(
 ) {
     log::debug!("Incoming TCP connection from: {}", addr);
 
-    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
-        Ok(ws_stream) => ws_stream,
+    let AcceptedConnection {
+        ws_stream,
+        is_relayed,
+        encoding,
+    } = match accept_connection(stream).await {
+        Ok(connection) => connection,
         Err(err) => {
             log::debug!("Error during the websocket handshake occurred: {:?}", err);
             return;
         }
     };
-    log::info!("WebSocket connection established: {}", addr);
+    log::info!(
+        "WebSocket connection established: {} ({:?})",
+        addr,
+        encoding
+    );
+    let _relayed_connection_guard = is_relayed.then(|| params.relayed_connections.track());
 
     let create_server_requests = params.create_server_requests.clone();
+    let (relayed_tx, mut relayed_rx) = mpsc::unbounded_channel();
+    // Solo practice servers are sent only to the clients that have requested
+    // them. Followers relay the requests, so they keep track of the ids too.
+    let own_request_ids = Arc::new(std::sync::Mutex::new(HashSet::new()));
+    let broadcast_own_request_ids = own_request_ids.clone();
+    let broadcast_servers = params.servers.clone();
 
     let (mut outgoing, mut incoming) = ws_stream.split();
     let drain_incoming = async move {
+        let mut leader_relay = None;
         while let Some(message) = incoming.next().await {
             let message = match message {
                 Ok(message) => message,
@@ -511,30 +915,109 @@ async fn handle_connection(
                 }
             };
 
-            let matchmaker_request = match message {
-                tungstenite::Message::Binary(data) => {
-                    match deserialize_binary::<MatchmakerRequest>(&data) {
-                        Ok(matchmaker_request) => matchmaker_request,
-                        Err(err) => {
-                            log::error!(
-                                "Failed to deserialize matchmaker request, disconnecting: {:?}",
-                                err
-                            );
-                            break;
-                        }
-                    }
+            let matchmaker_request = match decode_request(encoding, message) {
+                Some(Ok(matchmaker_request)) => matchmaker_request,
+                Some(Err(err)) => {
+                    log::error!(
+                        "Failed to deserialize matchmaker request, disconnecting: {}",
+                        err
+                    );
+                    break;
                 }
-                _ => continue,
+                None => continue,
             };
 
+            // Every replica watches the servers, so listing them doesn't need to
+            // be relayed to the leader.
+            if let MatchmakerRequest::ListServers {
+                request_id,
+                filters,
+                sort_order,
+                offset,
+                limit,
+            } = &matchmaker_request
+            {
+                let page = params
+                    .servers
+                    .list(*request_id, filters, *sort_order, *offset, *limit)
+                    .await;
+                let _ = relayed_tx.send(MatchmakerMessage::ServerList(page));
+                continue;
+            }
+
+            own_request_ids
+                .lock()
+                .unwrap()
+                .insert(matchmaker_request.request_id());
+
+            if !params.leadership.is_leader() {
+                relay_to_leader(
+                    &mut leader_relay,
+                    &params.leadership,
+                    matchmaker_request,
+                    &relayed_tx,
+                )
+                .await;
+                continue;
+            }
+
+            // Spans of a request end when it's either handled or skipped.
+            let span = TraceSpan::start("create_server", Some(&matchmaker_request.trace_parent()));
             match matchmaker_request {
                 MatchmakerRequest::CreateServer {
                     init_level,
                     request_id,
                     id_token,
+                    protocol_version,
+                    kind,
                 } => {
-                    log::info!("Received a request to create a server: {request_id}");
+                    log::info!("Received a request to create a server: {request_id} ({kind})");
+                    span.set_attribute("request_id", request_id.to_string());
+                    let mut audited_request =
+                        AuditedRequest::new(request_id, &init_level, protocol_version);
+                    // Clients validate titles as well, so it's not worth a response message.
+                    if let InitLevel::Create { title, .. } = &init_level {
+                        if let Err(errors) = validate_level_title(title) {
+                            log::warn!(
+                                "{}, skipping the request: {request_id}",
+                                format_errors("Level title", &errors)
+                            );
+                            span.set_error("invalid level title");
+                            params.allocation_audit.report(
+                                audited_request,
+                                Err(AllocationFailureReason::InvalidRequest),
+                            );
+                            continue;
+                        }
+                    }
+                    if kind == AllocationKind::SoloPractice
+                        && (id_token.is_none() || !matches!(init_level, InitLevel::Existing(_)))
+                    {
+                        log::warn!(
+                            "Solo practice requires an existing level and an id token, skipping the request: {request_id}"
+                        );
+                        span.set_error("invalid solo practice request");
+                        params.allocation_audit.report(
+                            audited_request,
+                            Err(AllocationFailureReason::InvalidRequest),
+                        );
+                        continue;
+                    }
+                    let versions = params.servers.compatible_versions(protocol_version).await;
+                    if versions.is_empty() {
+                        log::warn!(
+                            "No ready servers compatible with protocol version {protocol_version}, skipping the request: {request_id}"
+                        );
+                        span.set_error("no compatible servers");
+                        params.allocation_audit.report(
+                            audited_request,
+                            Err(AllocationFailureReason::NoCompatibleServers),
+                        );
+                        continue;
+                    }
+
                     let user_id = if let Some(id_token) = id_token {
+                        let auth_span = span.child("authorize_user");
                         let jwt = match params
                             .jwks
                             .decode(
@@ -550,10 +1033,15 @@ async fn handle_connection(
                             Ok(jwt) => jwt,
                             Err(err) => {
                                 log::warn!("Invalid JWT: {:?}", err);
+                                auth_span.set_error("invalid JWT");
                                 params
                                     .tx
                                     .send(MatchmakerMessage::InvalidJwt(request_id))
                                     .expect("Failed to send a persistence message");
+                                params.allocation_audit.report(
+                                    audited_request,
+                                    Err(AllocationFailureReason::InvalidJwt),
+                                );
                                 continue;
                             }
                         };
@@ -565,6 +1053,7 @@ async fn handle_connection(
                                 subject: jwt.claims().custom.sub.clone(),
                                 issuer: jwt.claims().custom.iss.clone(),
                             },
+                            &auth_span,
                         )
                         .await
                         .expect("Failed to get a registered user");
@@ -572,10 +1061,15 @@ async fn handle_connection(
                             Some(registered_user) => registered_user,
                             None => {
                                 log::warn!("Invalid JWT: no user found with the id_token");
+                                auth_span.set_error("user not found");
                                 params
                                     .tx
                                     .send(MatchmakerMessage::InvalidJwt(request_id))
                                     .expect("Failed to send a persistence message");
+                                params.allocation_audit.report(
+                                    audited_request,
+                                    Err(AllocationFailureReason::InvalidJwt),
+                                );
                                 continue;
                             }
                         };
@@ -583,7 +1077,17 @@ async fn handle_connection(
                     } else {
                         None
                     };
-
+                    audited_request.user_id = user_id;
+
+                    let allocation_span = span.child("allocate_server");
+                    let trace_parent = allocation_span.trace_parent();
+                    let preferred_server = params
+                        .servers
+                        .select_for_allocation(protocol_version, user_id)
+                        .await;
+                    if let Some(server) = &preferred_server {
+                        allocation_span.set_attribute("preferred_server", server.name.clone());
+                    }
                     let post_game_server_allocation_params = match init_level {
                         InitLevel::Create { title, parent_id } => PostGameServerAllocationParams {
                             request_id,
@@ -591,6 +1095,12 @@ async fn handle_connection(
                             level_title: Some(title),
                             level_parent_id: parent_id,
                             level_id: None,
+                            kind,
+                            versions,
+                            preferred_server: preferred_server
+                                .as_ref()
+                                .map(|server| server.name.clone()),
+                            trace_parent,
                         },
                         InitLevel::Existing(level_id) => PostGameServerAllocationParams {
                             request_id,
@@ -598,31 +1108,82 @@ async fn handle_connection(
                             level_title: None,
                             level_parent_id: None,
                             level_id: Some(level_id),
+                            kind,
+                            versions,
+                            preferred_server: preferred_server
+                                .as_ref()
+                                .map(|server| server.name.clone()),
+                            trace_parent,
                         },
                     };
-                    post_game_server_allocation(
-                        params.kube_client.clone(),
-                        post_game_server_allocation_params.clone(),
-                    )
-                    .await
-                    .expect("Failed to post a game server allocation");
+                    match &params.kube_client {
+                        Some(kube_client) => {
+                            // The outcome is known once the allocated server shows up, which
+                            // may happen even before the allocation request returns.
+                            params
+                                .allocation_audit
+                                .wait_for_server(audited_request)
+                                .await;
+                            if let Err(err) = post_game_server_allocation(
+                                kube_client.clone(),
+                                post_game_server_allocation_params.clone(),
+                            )
+                            .await
+                            {
+                                log::error!(
+                                    "Failed to post a game server allocation for request {request_id}: {:?}",
+                                    err
+                                );
+                                allocation_span.set_error(err.to_string());
+                                params
+                                    .allocation_audit
+                                    .fail_pending(
+                                        request_id,
+                                        AllocationFailureReason::AllocationError,
+                                    )
+                                    .await;
+                                continue;
+                            }
+                        }
+                        None => {
+                            match preferred_server
+                                .map(|server| allocate_local_server(server, &params.tx, request_id))
+                            {
+                                Some(server) => params
+                                    .allocation_audit
+                                    .report(audited_request, Ok(server.name)),
+                                None => {
+                                    log::warn!(
+                                        "No local game servers to allocate, skipping the request: {request_id}"
+                                    );
+                                    allocation_span.set_error("no local servers");
+                                    params.allocation_audit.report(
+                                        audited_request,
+                                        Err(AllocationFailureReason::AllocationError),
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
+                    }
 
                     let mut create_server_requests =
                         params.create_server_requests.requests.lock().await;
                     create_server_requests
                         .insert(addr, (request_id, post_game_server_allocation_params));
                 }
+                MatchmakerRequest::ListServers { .. } => unreachable!("Is handled by any replica"),
             }
         }
     };
 
-    let current_servers = params.servers.all().await;
+    let current_servers = params.servers.public().await;
     if let Err(err) = outgoing
-        .send(Message::Binary(
-            serialize_binary(&MatchmakerMessage::Init {
+        .send(encode_message(
+            encoding,
+            &MatchmakerMessage::Init {
                 servers: current_servers,
-            })
-            .expect("Failed to serialize an init message"),
+            },
         ))
         .await
     {
@@ -635,11 +1196,24 @@ async fn handle_connection(
     }
 
     let broadcast = async move {
-        while let Ok(message) = rx.recv().await {
-            let message = Message::binary(
-                serialize_binary(&message).expect("Failed to serialize a broadcasted message"),
-            );
-            if let Err(err) = outgoing.send(message).await {
+        loop {
+            let message = tokio::select! {
+                message = rx.recv() => match message {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+                Some(message) = relayed_rx.recv() => message,
+            };
+            if let MatchmakerMessage::ServerUpdated(server) = &message {
+                let is_own = broadcast_own_request_ids
+                    .lock()
+                    .unwrap()
+                    .contains(&server.request_id);
+                if !is_own && !broadcast_servers.is_public(&server.name).await {
+                    continue;
+                }
+            }
+            if let Err(err) = outgoing.send(encode_message(encoding, &message)).await {
                 log::warn!("Failed to send a message to {}: {:?}", addr, err);
                 break;
             }
@@ -654,9 +1228,114 @@ async fn handle_connection(
     log::info!("{} disconnected", addr);
 }
 
+/// Followers don't allocate servers, so they relay allocation requests to the
+/// leader. Every client gets its own relay connection, which lets the leader
+/// keep track of the client's requests as if the client was connected to it.
+struct LeaderRelay {
+    leader_ip: IpAddr,
+    requests: mpsc::UnboundedSender<MatchmakerRequest>,
+}
+
+impl LeaderRelay {
+    /// Messages that the leader sends only in response to a request (such as
+    /// [`MatchmakerMessage::InvalidJwt`]) are passed to `relayed_tx`, the rest
+    /// are known to the follower anyway.
+    async fn connect(
+        leader_ip: IpAddr,
+        relayed_tx: mpsc::UnboundedSender<MatchmakerMessage>,
+    ) -> anyhow::Result<Self> {
+        let url = format!("ws://{leader_ip}:{WEBSOCKET_PORT}{RELAY_PATH}");
+        let (ws_stream, _) = tokio_tungstenite::connect_async(url).await?;
+        let (mut outgoing, mut incoming) = ws_stream.split();
+        let (requests, mut requests_rx) = mpsc::unbounded_channel::<MatchmakerRequest>();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    request = requests_rx.recv() => {
+                        // The client has disconnected.
+                        let Some(request) = request else {
+                            break;
+                        };
+                        let message = Message::binary(
+                            serialize_binary(&request).expect("Failed to serialize a request"),
+                        );
+                        if let Err(err) = outgoing.send(message).await {
+                            log::warn!("Failed to relay a request to the leader: {:?}", err);
+                            break;
+                        }
+                    }
+                    message = incoming.next() => {
+                        let data = match message {
+                            Some(Ok(Message::Binary(data))) => data,
+                            Some(Ok(_)) => continue,
+                            Some(Err(err)) => {
+                                log::warn!("Leader relay connection error: {:?}", err);
+                                break;
+                            }
+                            None => break,
+                        };
+                        match deserialize_binary::<MatchmakerMessage>(&data) {
+                            Ok(message @ MatchmakerMessage::InvalidJwt(_)) => {
+                                let _ = relayed_tx.send(message);
+                            }
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::error!("Failed to deserialize a leader message: {:?}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            leader_ip,
+            requests,
+        })
+    }
+}
+
+async fn relay_to_leader(
+    leader_relay: &mut Option<LeaderRelay>,
+    leadership: &Leadership,
+    request: MatchmakerRequest,
+    relayed_tx: &mpsc::UnboundedSender<MatchmakerMessage>,
+) {
+    let request_id = request.request_id();
+    let Some(leader_ip) = leadership.leader_ip() else {
+        log::warn!("The leader is unknown, skipping the request: {request_id}");
+        return;
+    };
+    // The leader might have changed since the relay was connected.
+    let is_stale = leader_relay.as_ref().map_or(true, |relay| {
+        relay.leader_ip != leader_ip || relay.requests.is_closed()
+    });
+    if is_stale {
+        match LeaderRelay::connect(leader_ip, relayed_tx.clone()).await {
+            Ok(relay) => *leader_relay = Some(relay),
+            Err(err) => {
+                log::error!(
+                    "Failed to connect to the leader ({leader_ip}), skipping the request {request_id}: {:?}",
+                    err
+                );
+                *leader_relay = None;
+                return;
+            }
+        }
+    }
+
+    log::info!("Relaying a request to the leader ({leader_ip}): {request_id}");
+    let relay = leader_relay.as_ref().expect("Expected a leader relay");
+    if relay.requests.send(request).is_err() {
+        log::error!("The leader relay has disconnected, skipping the request: {request_id}");
+    }
+}
+
 #[derive(Debug)]
 enum ServerCommand {
-    Update(Server),
+    Update(Server, ServerPlacement),
     Delete(String),
 }
 
@@ -710,22 +1389,72 @@ fn server_command_from_resource(resource: &GameServer) -> Option<ServerCommand>
                 }
             };
 
-            let request_id = resource
+            let annotations = resource.metadata.annotations.as_ref();
+            let request_id = annotations
+                .and_then(|annotations| annotations.get(ALLOCATION_REQUEST_ID_ANNOTATION))
+                .and_then(|id| id.parse().ok())
+                .unwrap_or_default();
+            // Servers that don't expose their version are considered to be the oldest ones.
+            let version = annotations
+                .and_then(|annotations| {
+                    annotations.get(&format!("agones.dev/sdk-{SERVER_VERSION_KEY}"))
+                })
+                .and_then(|version| {
+                    version
+                        .parse()
+                        .map_err(|err| {
+                            log::warn!("Failed to parse GameServer {} version: {}", name, err);
+                        })
+                        .ok()
+                })
+                .unwrap_or_default();
+            let draining = annotations
+                .and_then(|annotations| annotations.get(SERVER_DRAIN_ANNOTATION))
+                .map_or(false, |drain| drain == "true");
+            let region = resource
                 .metadata
-                .annotations
+                .labels
                 .as_ref()
-                .and_then(|annotations| annotations.get("request_id"))
-                .and_then(|id| id.parse().ok())
+                .and_then(|labels| labels.get(SERVER_REGION_LABEL))
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_SERVER_REGION.to_owned());
+            // Allocation annotations are copied to the GameServer.
+            let level_id = annotations
+                .and_then(|annotations| annotations.get(LEVEL_ID_ANNOTATION))
+                .and_then(|level_id| level_id.parse().ok());
+            let allocation_kind = annotations
+                .and_then(|annotations| annotations.get(ALLOCATION_KIND_ANNOTATION))
+                .and_then(|kind| {
+                    kind.parse()
+                        .map_err(|err| {
+                            log::warn!(
+                                "Failed to parse GameServer {} allocation kind: {}",
+                                name,
+                                err
+                            );
+                        })
+                        .ok()
+                })
                 .unwrap_or_default();
 
-            Some(ServerCommand::Update(Server {
-                name,
-                state: status.state,
-                addr: SocketAddr::new(ip_addr, port),
-                player_capacity: status.players.capacity as u16,
-                player_count: status.players.count as u16,
-                request_id,
-            }))
+            Some(ServerCommand::Update(
+                Server {
+                    name,
+                    state: status.state,
+                    addr: SocketAddr::new(ip_addr, port),
+                    player_capacity: status.players.capacity as u16,
+                    player_count: status.players.count as u16,
+                    request_id,
+                    version,
+                    draining,
+                },
+                ServerPlacement {
+                    region,
+                    node: status.node_name.clone(),
+                    level_id,
+                    allocation_kind,
+                },
+            ))
         })
 }
 
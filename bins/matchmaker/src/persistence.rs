@@ -1,15 +1,20 @@
 use crate::Config;
-use mr_messages_lib::{GetRegisteredUserQuery, RegisteredUser};
+use mr_messages_lib::{GetRegisteredUserQuery, PostAllocationRequest, RegisteredUser};
+use mr_utils_lib::telemetry::TraceSpan;
 use reqwest::Client;
 
 pub async fn get_registered_user(
     client: &Client,
     config: &Config,
     request: GetRegisteredUserQuery,
+    span: &TraceSpan,
 ) -> anyhow::Result<Option<RegisteredUser>> {
-    let result = client
-        .get(config.private_persistence_url.join("user").unwrap())
-        .query(&request)
+    let result = span
+        .propagate(
+            client
+                .get(config.private_persistence_url.join("user").unwrap())
+                .query(&request),
+        )
         .send()
         .await;
 
@@ -17,6 +22,7 @@ pub async fn get_registered_user(
         Ok(response) => response,
         Err(err) => {
             log::error!("Failed to get a user: {:?}", err);
+            span.set_error(err.to_string());
             anyhow::bail!(err);
         }
     };
@@ -25,8 +31,23 @@ pub async fn get_registered_user(
         Ok(user) => user,
         Err(err) => {
             log::error!("Failed to get a user: {:?}", err);
+            span.set_error(err.to_string());
             anyhow::bail!(err);
         }
     };
     Ok(Some(registered_user))
 }
+
+pub async fn post_allocation(
+    client: &Client,
+    config: &Config,
+    request: &PostAllocationRequest,
+) -> anyhow::Result<()> {
+    client
+        .post(config.private_persistence_url.join("allocations").unwrap())
+        .json(request)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
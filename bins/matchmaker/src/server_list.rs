@@ -0,0 +1,114 @@
+use mr_messages_lib::{
+    ListedServer, ServerFilters, ServerListPage, ServerSortOrder, SERVER_LIST_MAX_LIMIT,
+};
+
+/// Filters, sorts and paginates the servers for a `ListServers` request.
+pub fn list_servers_page(
+    mut servers: Vec<ListedServer>,
+    request_id: uuid::Uuid,
+    filters: &ServerFilters,
+    sort_order: ServerSortOrder,
+    offset: u32,
+    limit: u32,
+) -> ServerListPage {
+    servers.retain(|listed_server| filters.matches(listed_server));
+    servers.sort_by(|a, b| {
+        let (a, b) = (&a.server, &b.server);
+        let ordering = match sort_order {
+            ServerSortOrder::MostPlayers => b.player_count.cmp(&a.player_count),
+            ServerSortOrder::MostFreeSlots => b.free_slots().cmp(&a.free_slots()),
+            ServerSortOrder::Name => std::cmp::Ordering::Equal,
+        };
+        ordering.then_with(|| a.name.cmp(&b.name))
+    });
+
+    let total = servers.len() as u32;
+    let limit = limit.min(SERVER_LIST_MAX_LIMIT) as usize;
+    ServerListPage {
+        request_id,
+        servers: servers
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit)
+            .collect(),
+        offset,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_messages_lib::{GameServerState, Server, ServerVersion, PLAYER_CAPACITY};
+
+    fn listed_server(name: &str, player_count: u16, region: &str, level_id: i64) -> ListedServer {
+        ListedServer {
+            server: Server {
+                name: name.to_owned(),
+                state: GameServerState::Allocated,
+                addr: "127.0.0.1:0".parse().unwrap(),
+                player_capacity: PLAYER_CAPACITY,
+                player_count,
+                request_id: Default::default(),
+                version: ServerVersion::new(0),
+                draining: false,
+            },
+            region: region.to_owned(),
+            level_id: Some(level_id),
+        }
+    }
+
+    fn names(page: &ServerListPage) -> Vec<&str> {
+        page.servers
+            .iter()
+            .map(|listed_server| listed_server.server.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_list_servers_page() {
+        let servers = vec![
+            listed_server("c", 1, "eu", 1),
+            listed_server("a", 3, "eu", 2),
+            listed_server("b", 1, "us", 1),
+            listed_server("d", PLAYER_CAPACITY, "eu", 1),
+        ];
+
+        let page = list_servers_page(
+            servers.clone(),
+            Default::default(),
+            &ServerFilters::default(),
+            ServerSortOrder::MostPlayers,
+            0,
+            3,
+        );
+        assert_eq!(names(&page), vec!["d", "a", "b"]);
+        assert_eq!(page.total, 4);
+
+        let page = list_servers_page(
+            servers.clone(),
+            Default::default(),
+            &ServerFilters::default(),
+            ServerSortOrder::Name,
+            3,
+            3,
+        );
+        assert_eq!(names(&page), vec!["d"]);
+
+        let filters = ServerFilters {
+            region: Some("eu".to_owned()),
+            min_free_slots: 1,
+            level_id: Some(1),
+        };
+        let page = list_servers_page(
+            servers,
+            Default::default(),
+            &filters,
+            ServerSortOrder::MostFreeSlots,
+            0,
+            u32::MAX,
+        );
+        assert_eq!(names(&page), vec!["c"]);
+        assert_eq!(page.total, 1);
+    }
+}
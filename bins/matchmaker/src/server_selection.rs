@@ -0,0 +1,340 @@
+use mr_messages_lib::{AllocationKind, GameServerState, Server};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const SERVER_LOAD_WEIGHT: f32 = 1.0;
+const NODE_UTILIZATION_WEIGHT: f32 = 2.0;
+const RECENT_ALLOCATION_WEIGHT: f32 = 0.5;
+/// Is high enough to outweigh the load of a node, so that servers of the same
+/// user end up on the same node only if there's no other choice.
+const SAME_USER_NODE_WEIGHT: f32 = 4.0;
+/// GameServer statuses lag behind allocations (a freshly allocated server
+/// reports its players only after they connect), so recent allocations are
+/// accounted for on top of the reported load.
+const RECENT_ALLOCATION_WINDOW: Duration = Duration::from_secs(120);
+
+/// Where a GameServer is scheduled. Isn't sent to clients, so it's stored
+/// separately from [`Server`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerPlacement {
+    pub region: String,
+    /// Is empty for local servers.
+    pub node: String,
+    /// The level a server has been allocated with, if it's an existing one.
+    pub level_id: Option<i64>,
+    /// Solo practice servers are sent only to the clients that have requested
+    /// them.
+    pub allocation_kind: AllocationKind,
+}
+
+#[derive(Default)]
+pub struct RecentAllocations {
+    allocations: Vec<RecentAllocation>,
+}
+
+struct RecentAllocation {
+    node: String,
+    user_id: Option<i64>,
+    allocated_at: Instant,
+}
+
+/// Instants can't be passed between processes, so allocation times are stored
+/// as ages.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecentAllocationSnapshot {
+    node: String,
+    user_id: Option<i64>,
+    age_ms: u64,
+}
+
+impl RecentAllocations {
+    pub fn record(&mut self, node: String, user_id: Option<i64>, now: Instant) {
+        self.prune(now);
+        self.allocations.push(RecentAllocation {
+            node,
+            user_id,
+            allocated_at: now,
+        });
+    }
+
+    pub fn snapshot(&self, now: Instant) -> Vec<RecentAllocationSnapshot> {
+        self.allocations
+            .iter()
+            .filter(|allocation| {
+                now.duration_since(allocation.allocated_at) < RECENT_ALLOCATION_WINDOW
+            })
+            .map(|allocation| RecentAllocationSnapshot {
+                node: allocation.node.clone(),
+                user_id: allocation.user_id,
+                age_ms: now.duration_since(allocation.allocated_at).as_millis() as u64,
+            })
+            .collect()
+    }
+
+    /// Replaces the allocations with the ones taken over from another
+    /// matchmaker replica.
+    pub fn restore(&mut self, snapshot: Vec<RecentAllocationSnapshot>, now: Instant) {
+        self.allocations = snapshot
+            .into_iter()
+            .filter_map(|allocation| {
+                Some(RecentAllocation {
+                    node: allocation.node,
+                    user_id: allocation.user_id,
+                    allocated_at: now.checked_sub(Duration::from_millis(allocation.age_ms))?,
+                })
+            })
+            .collect();
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.allocations.retain(|allocation| {
+            now.duration_since(allocation.allocated_at) < RECENT_ALLOCATION_WINDOW
+        });
+    }
+
+    /// Newer allocations weigh more, an allocation that has just happened
+    /// counts as 1.0.
+    fn node_pressure(&self, node: &str, now: Instant) -> f32 {
+        self.allocations
+            .iter()
+            .filter(|allocation| allocation.node == node)
+            .map(|allocation| {
+                let age = now.duration_since(allocation.allocated_at);
+                (1.0 - age.as_secs_f32() / RECENT_ALLOCATION_WINDOW.as_secs_f32()).max(0.0)
+            })
+            .sum()
+    }
+
+    fn has_user_on_node(&self, user_id: i64, node: &str, now: Instant) -> bool {
+        self.allocations.iter().any(|allocation| {
+            allocation.user_id == Some(user_id)
+                && allocation.node == node
+                && now.duration_since(allocation.allocated_at) < RECENT_ALLOCATION_WINDOW
+        })
+    }
+}
+
+#[derive(Default)]
+struct NodeLoad {
+    servers: usize,
+    allocated_servers: usize,
+    players: u32,
+    player_capacity: u32,
+}
+
+impl NodeLoad {
+    /// Allocated servers cost resources even while they are empty, so both the
+    /// share of allocated servers and the share of taken player slots count.
+    fn utilization(&self) -> f32 {
+        let allocated = self.allocated_servers as f32 / self.servers.max(1) as f32;
+        let players = self.players as f32 / self.player_capacity.max(1) as f32;
+        (allocated + players) / 2.0
+    }
+}
+
+/// Picks a server for a new allocation among the ready servers that satisfy
+/// `is_compatible`. Only the newest compatible version is considered, the rest
+/// are ranked by a weighted score of:
+/// - the load of the server itself,
+/// - utilization of its node (counting every server scheduled there),
+/// - allocations that have recently landed on the node,
+/// - whether the node already hosts a recently allocated server of the same
+///   user, which spreads a user's servers across nodes to survive node
+///   failures.
+pub fn select_server<'a>(
+    servers: &'a HashMap<String, Server>,
+    placements: &HashMap<String, ServerPlacement>,
+    recent_allocations: &RecentAllocations,
+    user_id: Option<i64>,
+    is_compatible: impl Fn(&Server) -> bool,
+    now: Instant,
+) -> Option<&'a Server> {
+    let node_of = |server: &Server| {
+        placements
+            .get(&server.name)
+            .map_or("", |placement| placement.node.as_str())
+    };
+
+    let mut node_loads: HashMap<&str, NodeLoad> = HashMap::new();
+    for server in servers.values() {
+        let load = node_loads.entry(node_of(server)).or_default();
+        load.servers += 1;
+        if server.state == GameServerState::Allocated {
+            load.allocated_servers += 1;
+        }
+        load.players += server.player_count as u32;
+        load.player_capacity += server.player_capacity as u32;
+    }
+
+    let candidates = servers
+        .values()
+        .filter(|server| server.state == GameServerState::Ready && is_compatible(server))
+        .collect::<Vec<_>>();
+    let newest_version = candidates.iter().map(|server| server.version).max()?;
+
+    let score = |server: &Server| {
+        let node = node_of(server);
+        let server_load = server.player_count as f32 / server.player_capacity.max(1) as f32;
+        let node_utilization = node_loads.get(node).map_or(0.0, NodeLoad::utilization);
+        let mut score = SERVER_LOAD_WEIGHT * server_load
+            + NODE_UTILIZATION_WEIGHT * node_utilization
+            + RECENT_ALLOCATION_WEIGHT * recent_allocations.node_pressure(node, now);
+        if let Some(user_id) = user_id {
+            if recent_allocations.has_user_on_node(user_id, node, now) {
+                score += SAME_USER_NODE_WEIGHT;
+            }
+        }
+        score
+    };
+
+    candidates
+        .into_iter()
+        .filter(|server| server.version == newest_version)
+        .map(|server| (score(server), server))
+        // Names break ties to keep the choice stable between requests.
+        .min_by(|(a_score, a), (b_score, b)| {
+            a_score.total_cmp(b_score).then_with(|| a.name.cmp(&b.name))
+        })
+        .map(|(_, server)| server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_messages_lib::{ServerVersion, PLAYER_CAPACITY};
+
+    fn server(name: &str, state: GameServerState, player_count: u16, build: u32) -> Server {
+        Server {
+            name: name.to_owned(),
+            state,
+            addr: "127.0.0.1:3455".parse().unwrap(),
+            player_capacity: PLAYER_CAPACITY,
+            player_count,
+            request_id: Default::default(),
+            version: ServerVersion::new(build),
+            draining: false,
+        }
+    }
+
+    fn fleet(
+        servers: &[(Server, &str)],
+    ) -> (HashMap<String, Server>, HashMap<String, ServerPlacement>) {
+        let placements = servers
+            .iter()
+            .map(|(server, node)| {
+                (
+                    server.name.clone(),
+                    ServerPlacement {
+                        region: String::new(),
+                        node: (*node).to_owned(),
+                        level_id: None,
+                        allocation_kind: AllocationKind::Public,
+                    },
+                )
+            })
+            .collect();
+        let servers = servers
+            .iter()
+            .map(|(server, _)| (server.name.clone(), server.clone()))
+            .collect();
+        (servers, placements)
+    }
+
+    fn select(
+        servers: &HashMap<String, Server>,
+        placements: &HashMap<String, ServerPlacement>,
+        recent_allocations: &RecentAllocations,
+        user_id: Option<i64>,
+        now: Instant,
+    ) -> Option<String> {
+        select_server(
+            servers,
+            placements,
+            recent_allocations,
+            user_id,
+            |_| true,
+            now,
+        )
+        .map(|server| server.name.clone())
+    }
+
+    #[test]
+    fn test_select_server_prefers_idle_nodes() {
+        let now = Instant::now();
+        let (servers, placements) = fleet(&[
+            (server("a", GameServerState::Ready, 0, 1), "busy"),
+            (server("b", GameServerState::Allocated, 5, 1), "busy"),
+            (server("c", GameServerState::Ready, 0, 1), "idle"),
+            // Newer versions win regardless of the load.
+            (server("d", GameServerState::Ready, 0, 0), "empty"),
+        ]);
+        let mut recent_allocations = RecentAllocations::default();
+        assert_eq!(
+            select(&servers, &placements, &recent_allocations, None, now),
+            Some("c".to_owned())
+        );
+
+        // Statuses haven't caught up with the allocations yet.
+        recent_allocations.record("idle".to_owned(), None, now);
+        recent_allocations.record("idle".to_owned(), None, now);
+        recent_allocations.record("idle".to_owned(), None, now);
+        assert_eq!(
+            select(&servers, &placements, &recent_allocations, None, now),
+            Some("a".to_owned())
+        );
+        let later = now + RECENT_ALLOCATION_WINDOW;
+        assert_eq!(
+            select(&servers, &placements, &recent_allocations, None, later),
+            Some("c".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_select_server_spreads_users_across_nodes() {
+        let now = Instant::now();
+        let (servers, placements) = fleet(&[
+            (server("a", GameServerState::Ready, 0, 1), "first"),
+            (server("b", GameServerState::Allocated, 5, 1), "second"),
+            (server("c", GameServerState::Ready, 0, 1), "second"),
+        ]);
+        let mut recent_allocations = RecentAllocations::default();
+        recent_allocations.record("first".to_owned(), Some(1), now);
+
+        assert_eq!(
+            select(&servers, &placements, &recent_allocations, Some(1), now),
+            Some("c".to_owned())
+        );
+        assert_eq!(
+            select(&servers, &placements, &recent_allocations, Some(2), now),
+            Some("a".to_owned())
+        );
+
+        // Taking over the allocations from another replica keeps the preference.
+        let later = now + Duration::from_secs(1);
+        let mut restored_allocations = RecentAllocations::default();
+        restored_allocations.restore(recent_allocations.snapshot(now), now);
+        assert_eq!(
+            restored_allocations.snapshot(later),
+            recent_allocations.snapshot(later)
+        );
+        assert_eq!(
+            select(&servers, &placements, &restored_allocations, Some(1), later),
+            Some("c".to_owned())
+        );
+        assert_eq!(
+            select_server(
+                &servers,
+                &placements,
+                &recent_allocations,
+                None,
+                |server| server.name != "a" && server.name != "c",
+                now,
+            ),
+            None
+        );
+    }
+}
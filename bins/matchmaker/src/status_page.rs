@@ -0,0 +1,314 @@
+use crate::{
+    leader_election::Leadership, server_selection::ServerPlacement, CreateServerRequests,
+    RelayedConnections, Servers, DEFAULT_SERVER_REGION,
+};
+use mr_messages_lib::{GameServerState, MatchmakerMessage, Server};
+use serde_derive::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+use tokio::sync::broadcast::Sender;
+
+/// Is exposed publicly, unlike the webhook port, which is reachable only from
+/// within the cluster.
+pub const STATUS_PAGE_PORT: u16 = 8084;
+const STATUS_PAGE_REFRESH_SECS: u32 = 10;
+
+/// Is served publicly, so that the community can check the platform health
+/// without starting a client.
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct PlatformStatus {
+    total_servers: usize,
+    ready_servers: usize,
+    allocated_servers: usize,
+    draining_servers: usize,
+    /// Clients connected to the matchmaker, including the ones that are
+    /// currently playing.
+    connected_clients: usize,
+    /// Clients waiting for a server to get allocated for them.
+    queue_length: usize,
+    regions: BTreeMap<String, RegionStatus>,
+}
+
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct RegionStatus {
+    servers: usize,
+    players: usize,
+    player_capacity: usize,
+}
+
+impl Servers {
+    pub async fn platform_status(
+        &self,
+        connected_clients: usize,
+        queue_length: usize,
+    ) -> PlatformStatus {
+        let servers = self.servers.lock().await;
+        let placements = self.placements.lock().await;
+        platform_status(&servers, &placements, connected_clients, queue_length)
+    }
+}
+
+fn platform_status(
+    servers: &HashMap<String, Server>,
+    placements: &HashMap<String, ServerPlacement>,
+    connected_clients: usize,
+    queue_length: usize,
+) -> PlatformStatus {
+    let mut status = PlatformStatus {
+        total_servers: servers.len(),
+        connected_clients,
+        queue_length,
+        ..Default::default()
+    };
+    for server in servers.values() {
+        match server.state {
+            GameServerState::Ready => status.ready_servers += 1,
+            GameServerState::Allocated => status.allocated_servers += 1,
+            _ => {}
+        }
+        if server.draining {
+            status.draining_servers += 1;
+        }
+        let region = placements
+            .get(&server.name)
+            .map_or(DEFAULT_SERVER_REGION, |placement| placement.region.as_str());
+        let region_status = status.regions.entry(region.to_owned()).or_default();
+        region_status.servers += 1;
+        region_status.players += server.player_count as usize;
+        region_status.player_capacity += server.player_capacity as usize;
+    }
+    status
+}
+
+impl PlatformStatus {
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"{STATUS_PAGE_REFRESH_SECS}\">\n\
+             <title>Muddle Run status</title>\n</head>\n<body>\n<h1>Muddle Run status</h1>\n\
+             <table>\n\
+             <tr><td>Servers</td><td>{}</td></tr>\n\
+             <tr><td>Ready</td><td>{}</td></tr>\n\
+             <tr><td>Allocated</td><td>{}</td></tr>\n\
+             <tr><td>Draining</td><td>{}</td></tr>\n\
+             <tr><td>Connected clients</td><td>{}</td></tr>\n\
+             <tr><td>Queue length</td><td>{}</td></tr>\n\
+             </table>\n<h2>Regions</h2>\n<table>\n\
+             <tr><th>Region</th><th>Servers</th><th>Players</th></tr>\n",
+            self.total_servers,
+            self.ready_servers,
+            self.allocated_servers,
+            self.draining_servers,
+            self.connected_clients,
+            self.queue_length,
+        );
+        for (region, region_status) in &self.regions {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}/{}</td></tr>",
+                escape_html(region),
+                region_status.servers,
+                region_status.players,
+                region_status.player_capacity,
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Clone)]
+pub struct StatusPageParams {
+    pub tx: Sender<MatchmakerMessage>,
+    pub servers: Servers,
+    pub create_server_requests: CreateServerRequests,
+    pub leadership: Leadership,
+    pub relayed_connections: RelayedConnections,
+}
+
+/// Serves `GET /status` (HTML) and `GET /status.json`.
+pub async fn serve_status_page(params: StatusPageParams) {
+    let make_svc = hyper::service::make_service_fn(move |_conn| {
+        let params = params.clone();
+
+        let serve = move |req: hyper::Request<hyper::Body>| {
+            let params = params.clone();
+            async move { Ok::<_, std::convert::Infallible>(status_page_response(req, params).await) }
+        };
+
+        async { Ok::<_, std::convert::Infallible>(hyper::service::service_fn(serve)) }
+    });
+
+    let addr = ([0, 0, 0, 0], STATUS_PAGE_PORT).into();
+
+    let server = hyper::Server::bind(&addr).serve(make_svc);
+
+    log::info!("Status page is listening on http://{}", addr);
+
+    if let Err(err) = server.await {
+        log::error!("An error occurred while serving the status page: {:?}", err);
+    }
+}
+
+async fn status_page_response(
+    req: hyper::Request<hyper::Body>,
+    params: StatusPageParams,
+) -> hyper::Response<hyper::Body> {
+    enum StatusPage {
+        Html,
+        Json,
+    }
+
+    let status_page = match (req.method(), req.uri().path()) {
+        (&hyper::Method::GET, "/status") => StatusPage::Html,
+        (&hyper::Method::GET, "/status.json") => StatusPage::Json,
+        _ => {
+            return hyper::Response::builder()
+                .status(404)
+                .body(hyper::Body::empty())
+                .unwrap();
+        }
+    };
+
+    let StatusPageParams {
+        tx,
+        servers,
+        create_server_requests,
+        leadership,
+        relayed_connections,
+    } = params;
+    let queue_length = create_server_requests.lock().await.len();
+    let connected_clients = leadership
+        .connected_clients(relayed_connections.local_clients(&tx))
+        .await;
+    let status = servers
+        .platform_status(connected_clients, queue_length)
+        .await;
+    let (content_type, body) = match status_page {
+        StatusPage::Html => ("text/html; charset=utf-8", status.to_html()),
+        StatusPage::Json => ("application/json", serde_json::to_string(&status).unwrap()),
+    };
+    hyper::Response::builder()
+        .header(hyper::header::CONTENT_TYPE, content_type)
+        .body(body.into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_messages_lib::{AllocationKind, ServerVersion, PLAYER_CAPACITY};
+
+    fn server(name: &str, state: GameServerState, player_count: u16, draining: bool) -> Server {
+        Server {
+            name: name.to_owned(),
+            state,
+            addr: "127.0.0.1:3455".parse().unwrap(),
+            player_capacity: PLAYER_CAPACITY,
+            player_count,
+            request_id: Default::default(),
+            version: ServerVersion::new(0),
+            draining,
+        }
+    }
+
+    fn placement(region: &str) -> ServerPlacement {
+        ServerPlacement {
+            region: region.to_owned(),
+            node: String::new(),
+            level_id: None,
+            allocation_kind: AllocationKind::Public,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_platform_status() {
+        let servers = Servers::default();
+        servers
+            .init(vec![
+                (
+                    server("a", GameServerState::Ready, 0, false),
+                    placement("eu"),
+                ),
+                (
+                    server("b", GameServerState::Allocated, 3, false),
+                    placement("eu"),
+                ),
+                (
+                    server("c", GameServerState::Allocated, 1, true),
+                    placement("us"),
+                ),
+                (
+                    server("d", GameServerState::Shutdown, 0, false),
+                    placement("us"),
+                ),
+            ])
+            .await;
+        // Servers without a known placement are reported under the default region.
+        servers.placements.lock().await.remove("d");
+
+        let status = servers.platform_status(5, 2).await;
+        let region = |servers, players| RegionStatus {
+            servers,
+            players,
+            player_capacity: servers * PLAYER_CAPACITY as usize,
+        };
+        assert_eq!(
+            status,
+            PlatformStatus {
+                total_servers: 4,
+                ready_servers: 1,
+                allocated_servers: 2,
+                draining_servers: 1,
+                connected_clients: 5,
+                queue_length: 2,
+                regions: BTreeMap::from([
+                    (DEFAULT_SERVER_REGION.to_owned(), region(1, 0)),
+                    ("eu".to_owned(), region(2, 3)),
+                    ("us".to_owned(), region(1, 1)),
+                ]),
+            }
+        );
+    }
+
+    #[test]
+    fn test_status_page_html() {
+        let mut servers = HashMap::new();
+        servers.insert(
+            "a".to_owned(),
+            server("a", GameServerState::Allocated, 2, false),
+        );
+        let mut placements = HashMap::new();
+        placements.insert("a".to_owned(), placement("<eu & \"west\">"));
+
+        let html = platform_status(&servers, &placements, 3, 1).to_html();
+        assert!(html.contains("<tr><td>Servers</td><td>1</td></tr>"));
+        assert!(html.contains("<tr><td>Allocated</td><td>1</td></tr>"));
+        assert!(html.contains("<tr><td>Connected clients</td><td>3</td></tr>"));
+        assert!(html.contains("<tr><td>Queue length</td><td>1</td></tr>"));
+        assert!(html.contains(&format!(
+            "<tr><td>&lt;eu &amp; &quot;west&quot;&gt;</td><td>1</td><td>2/{PLAYER_CAPACITY}</td></tr>"
+        )));
+        assert!(!html.contains("<eu"));
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("eu-west"), "eu-west");
+        assert_eq!(
+            escape_html("<a href=\"x\">&amp;</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;amp;&lt;/a&gt;"
+        );
+    }
+}
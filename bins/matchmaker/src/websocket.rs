@@ -0,0 +1,190 @@
+use crate::RELAY_PATH;
+use mr_messages_lib::{
+    EncodedMessage, EncodingError, MatchmakerEncoding, MatchmakerMessage, MatchmakerRequest,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+        Message,
+    },
+    WebSocketStream,
+};
+
+pub struct AcceptedConnection<S> {
+    pub ws_stream: WebSocketStream<S>,
+    /// Is `true` for the connections that followers open to relay requests.
+    pub is_relayed: bool,
+    /// Is negotiated with the `Sec-WebSocket-Protocol` header, the selected
+    /// subprotocol is echoed back to the client.
+    pub encoding: MatchmakerEncoding,
+}
+
+pub async fn accept_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+) -> Result<AcceptedConnection<S>, tungstenite::Error> {
+    let mut is_relayed = false;
+    let mut encoding = MatchmakerEncoding::default();
+    let negotiate = |request: &Request, mut response: Response| {
+        is_relayed = request.uri().path() == RELAY_PATH;
+        let negotiated = request
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|header| header.to_str().ok())
+            .and_then(MatchmakerEncoding::negotiate);
+        // Clients that request only unsupported subprotocols get no header in
+        // response, it's up to them whether to proceed with bincode.
+        if let Some(negotiated) = negotiated {
+            encoding = negotiated;
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(negotiated.subprotocol()),
+            );
+        }
+        Ok::<_, ErrorResponse>(response)
+    };
+    let ws_stream = tokio_tungstenite::accept_hdr_async(stream, negotiate).await?;
+    Ok(AcceptedConnection {
+        ws_stream,
+        is_relayed,
+        encoding,
+    })
+}
+
+pub fn encode_message(encoding: MatchmakerEncoding, message: &MatchmakerMessage) -> Message {
+    match encoding
+        .encode(message)
+        .expect("Failed to serialize a matchmaker message")
+    {
+        EncodedMessage::Binary(data) => Message::Binary(data),
+        EncodedMessage::Text(text) => Message::Text(text),
+    }
+}
+
+/// Returns `None` for control frames.
+pub fn decode_request(
+    encoding: MatchmakerEncoding,
+    message: Message,
+) -> Option<Result<MatchmakerRequest, EncodingError>> {
+    let message = match message {
+        Message::Binary(data) => EncodedMessage::Binary(data),
+        Message::Text(text) => EncodedMessage::Text(text),
+        Message::Ping(_) | Message::Pong(_) | Message::Close(_) | Message::Frame(_) => return None,
+    };
+    Some(encoding.decode(&message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use mr_messages_lib::{
+        ServerFilters, ServerSortOrder, MATCHMAKER_BINCODE_SUBPROTOCOL, MATCHMAKER_JSON_SUBPROTOCOL,
+    };
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    /// Connects a client requesting the subprotocol, sends an init message and
+    /// receives a request back, the way real clients talk to the matchmaker.
+    async fn exchange(
+        subprotocol: Option<&'static str>,
+    ) -> (MatchmakerEncoding, Option<String>, Message) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = accept_connection(stream).await.unwrap();
+            let init = encode_message(
+                connection.encoding,
+                &MatchmakerMessage::Init {
+                    servers: Vec::new(),
+                },
+            );
+            connection.ws_stream.send(init).await.unwrap();
+            let message = connection.ws_stream.next().await.unwrap().unwrap();
+            let request = decode_request(connection.encoding, message)
+                .unwrap()
+                .unwrap();
+            assert!(matches!(
+                request,
+                MatchmakerRequest::ListServers { limit: 10, .. }
+            ));
+            connection.encoding
+        });
+
+        let mut request = format!("ws://{addr}/").into_client_request().unwrap();
+        if let Some(subprotocol) = subprotocol {
+            request.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(subprotocol),
+            );
+        }
+        let (mut client, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+        let response_subprotocol = response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .map(|header| header.to_str().unwrap().to_owned());
+
+        let init = client.next().await.unwrap().unwrap();
+        let client_encoding = response_subprotocol
+            .as_deref()
+            .and_then(MatchmakerEncoding::negotiate)
+            .unwrap_or_default();
+        let list_servers = client_encoding
+            .encode(&MatchmakerRequest::ListServers {
+                request_id: Default::default(),
+                filters: ServerFilters::default(),
+                sort_order: ServerSortOrder::MostPlayers,
+                offset: 0,
+                limit: 10,
+            })
+            .unwrap();
+        let list_servers = match list_servers {
+            EncodedMessage::Binary(data) => Message::Binary(data),
+            EncodedMessage::Text(text) => Message::Text(text),
+        };
+        client.send(list_servers).await.unwrap();
+
+        let server_encoding = server.await.unwrap();
+        (server_encoding, response_subprotocol, init)
+    }
+
+    #[tokio::test]
+    async fn test_json_encoding() {
+        let (encoding, subprotocol, init) = exchange(Some(MATCHMAKER_JSON_SUBPROTOCOL)).await;
+        assert_eq!(encoding, MatchmakerEncoding::Json);
+        assert_eq!(subprotocol.as_deref(), Some(MATCHMAKER_JSON_SUBPROTOCOL));
+        assert_eq!(init, Message::Text(r#"{"Init":{"servers":[]}}"#.to_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_bincode_encoding() {
+        let (encoding, subprotocol, init) = exchange(Some(MATCHMAKER_BINCODE_SUBPROTOCOL)).await;
+        assert_eq!(encoding, MatchmakerEncoding::Bincode);
+        assert_eq!(subprotocol.as_deref(), Some(MATCHMAKER_BINCODE_SUBPROTOCOL));
+        let data = match init {
+            Message::Binary(data) => data,
+            message => panic!("Expected a binary message, got {message:?}"),
+        };
+        assert_eq!(
+            MatchmakerEncoding::Bincode
+                .decode::<MatchmakerMessage>(&EncodedMessage::Binary(data))
+                .unwrap(),
+            MatchmakerMessage::Init {
+                servers: Vec::new()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_subprotocol_defaults_to_bincode() {
+        // The game client doesn't request any subprotocol.
+        let (encoding, subprotocol, init) = exchange(None).await;
+        assert_eq!(encoding, MatchmakerEncoding::Bincode);
+        assert_eq!(subprotocol, None);
+        assert!(matches!(init, Message::Binary(_)));
+    }
+}
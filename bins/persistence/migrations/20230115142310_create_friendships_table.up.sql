@@ -0,0 +1,23 @@
+-- Add up migration script here
+
+CREATE TABLE friendships
+(
+    id          bigserial PRIMARY KEY,
+    -- The user who sent the friend request.
+    user_id     bigint REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    friend_id   bigint REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    is_accepted bool      DEFAULT FALSE                        NOT NULL,
+    created_at  timestamp DEFAULT current_timestamp            NOT NULL,
+    updated_at  timestamp DEFAULT current_timestamp            NOT NULL,
+    CHECK (user_id <> friend_id)
+);
+
+-- A pair of users can have only one friendship, regardless of who sent the request.
+CREATE UNIQUE INDEX friendships_pair_idx ON friendships (LEAST(user_id, friend_id), GREATEST(user_id, friend_id));
+CREATE INDEX friendships_friend_id_idx ON friendships (friend_id);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE
+    ON friendships
+    FOR EACH ROW
+EXECUTE PROCEDURE set_updated_at_column();
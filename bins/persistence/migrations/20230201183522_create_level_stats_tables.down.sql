@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX levels_updated_at_id_idx;
+DROP TRIGGER set_updated_at ON level_ratings;
+DROP TABLE level_ratings;
+DROP TABLE level_plays;
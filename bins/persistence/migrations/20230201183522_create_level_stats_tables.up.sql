@@ -0,0 +1,33 @@
+-- Add up migration script here
+
+CREATE TABLE level_plays
+(
+    id         bigserial PRIMARY KEY,
+    level_id   bigint REFERENCES levels (id) ON DELETE CASCADE NOT NULL,
+    -- Guests can play levels too.
+    user_id    bigint REFERENCES users (id) ON DELETE SET NULL,
+    created_at timestamp DEFAULT current_timestamp             NOT NULL
+);
+
+CREATE INDEX level_plays_level_id_idx ON level_plays (level_id);
+
+CREATE TABLE level_ratings
+(
+    id         bigserial PRIMARY KEY,
+    level_id   bigint REFERENCES levels (id) ON DELETE CASCADE NOT NULL,
+    user_id    bigint REFERENCES users (id) ON DELETE CASCADE  NOT NULL,
+    rating     smallint                                        NOT NULL,
+    created_at timestamp DEFAULT current_timestamp             NOT NULL,
+    updated_at timestamp DEFAULT current_timestamp             NOT NULL,
+    UNIQUE (level_id, user_id),
+    CHECK (rating BETWEEN 1 AND 5)
+);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE
+    ON level_ratings
+    FOR EACH ROW
+EXECUTE PROCEDURE set_updated_at_column();
+
+-- Supports keyset pagination of the levels list.
+CREATE INDEX levels_updated_at_id_idx ON levels (updated_at DESC, id DESC) WHERE (is_autosaved = FALSE);
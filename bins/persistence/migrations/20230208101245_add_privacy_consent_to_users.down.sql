@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+ALTER TABLE users
+    DROP COLUMN allow_session_recording,
+    DROP COLUMN allow_analytics,
+    DROP COLUMN privacy_updated_at;
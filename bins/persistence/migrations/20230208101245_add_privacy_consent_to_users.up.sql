@@ -0,0 +1,7 @@
+-- Add up migration script here
+
+ALTER TABLE users
+    ADD COLUMN allow_session_recording boolean   DEFAULT FALSE NOT NULL,
+    ADD COLUMN allow_analytics         boolean   DEFAULT FALSE NOT NULL,
+    -- Is kept separately from `updated_at` to know when consent was given or revoked.
+    ADD COLUMN privacy_updated_at      timestamp;
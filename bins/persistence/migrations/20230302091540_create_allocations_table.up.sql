@@ -0,0 +1,21 @@
+-- Add up migration script here
+
+-- An audit trail of the matchmaker allocation requests, to be able to analyze
+-- allocation failures and plan capacity.
+CREATE TABLE allocations
+(
+    id               bigserial PRIMARY KEY,
+    request_id       text                                NOT NULL,
+    user_id          bigint REFERENCES users (id) ON DELETE SET NULL,
+    -- Levels can be deleted, and requested ids aren't guaranteed to exist.
+    level_id         bigint,
+    level_parent_id  bigint,
+    protocol_version integer                             NOT NULL,
+    server_name      text,
+    latency_millis   bigint                              NOT NULL,
+    failure_reason   text,
+    created_at       timestamp DEFAULT current_timestamp NOT NULL
+);
+
+CREATE INDEX allocations_created_at_idx ON allocations (created_at);
+CREATE INDEX allocations_request_id_idx ON allocations (request_id);
@@ -0,0 +1,15 @@
+-- Add up migration script here
+
+-- Short audio cues that level creators attach to level events. Clips are
+-- served publicly only after being approved by a moderator.
+CREATE TABLE audio_clips
+(
+    id                bigserial PRIMARY KEY,
+    user_id           bigint REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    data              bytea                                         NOT NULL,
+    moderation_status text      DEFAULT 'pending'                   NOT NULL,
+    created_at        timestamp DEFAULT current_timestamp           NOT NULL,
+    moderated_at      timestamp
+);
+
+CREATE INDEX audio_clips_moderation_status_idx ON audio_clips (moderation_status);
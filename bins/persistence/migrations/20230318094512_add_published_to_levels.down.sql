@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+ALTER TABLE levels
+    DROP COLUMN published;
@@ -0,0 +1,7 @@
+-- Add up migration script here
+
+ALTER TABLE levels
+    ADD COLUMN published boolean DEFAULT FALSE NOT NULL;
+
+-- Levels created before publishing was introduced are already listed publicly.
+UPDATE levels SET published = TRUE;
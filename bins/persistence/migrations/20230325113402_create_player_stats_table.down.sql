@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TRIGGER set_updated_at ON player_stats;
+DROP TABLE player_stats;
@@ -0,0 +1,18 @@
+-- Add up migration script here
+
+-- Lifetime counters of registered users, game servers increment them as
+-- players finish and die.
+CREATE TABLE player_stats
+(
+    user_id    bigint PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    finishes   bigint    DEFAULT 0                 NOT NULL,
+    deaths     bigint    DEFAULT 0                 NOT NULL,
+    created_at timestamp DEFAULT current_timestamp NOT NULL,
+    updated_at timestamp DEFAULT current_timestamp NOT NULL
+);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE
+    ON player_stats
+    FOR EACH ROW
+EXECUTE PROCEDURE set_updated_at_column();
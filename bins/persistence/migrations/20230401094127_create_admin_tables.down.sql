@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE admin_actions;
+DROP TRIGGER set_updated_at ON admin_permissions;
+DROP TABLE admin_permissions;
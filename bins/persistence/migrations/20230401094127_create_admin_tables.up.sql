@@ -0,0 +1,34 @@
+-- Add up migration script here
+
+-- Permissions are granted by operators directly in the database, users without
+-- a row don't have any.
+CREATE TABLE admin_permissions
+(
+    user_id    bigint PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    kick       boolean   DEFAULT FALSE             NOT NULL,
+    pause      boolean   DEFAULT FALSE             NOT NULL,
+    reload     boolean   DEFAULT FALSE             NOT NULL,
+    cvars      boolean   DEFAULT FALSE             NOT NULL,
+    broadcast  boolean   DEFAULT FALSE             NOT NULL,
+    created_at timestamp DEFAULT current_timestamp NOT NULL,
+    updated_at timestamp DEFAULT current_timestamp NOT NULL
+);
+
+CREATE TRIGGER set_updated_at
+    BEFORE UPDATE
+    ON admin_permissions
+    FOR EACH ROW
+EXECUTE PROCEDURE set_updated_at_column();
+
+-- The audit log of admin commands executed by game servers.
+CREATE TABLE admin_actions
+(
+    id          bigserial PRIMARY KEY,
+    user_id     bigint REFERENCES users (id) ON DELETE SET NULL,
+    server_name text,
+    permission  text                                NOT NULL,
+    details     text                                NOT NULL,
+    created_at  timestamp DEFAULT current_timestamp NOT NULL
+);
+
+CREATE INDEX admin_actions_user_id_idx ON admin_actions (user_id);
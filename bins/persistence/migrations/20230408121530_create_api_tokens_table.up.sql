@@ -0,0 +1,18 @@
+-- Add up migration script here
+
+-- Personal API tokens for community tools. Only hashes of the secrets are
+-- stored, revoking a token deletes its row.
+CREATE TABLE api_tokens
+(
+    id                bigserial PRIMARY KEY,
+    user_id           bigint REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    name              varchar(64)                         NOT NULL,
+    token_hash        text                                NOT NULL UNIQUE,
+    read_levels       boolean   DEFAULT FALSE             NOT NULL,
+    write_levels      boolean   DEFAULT FALSE             NOT NULL,
+    read_leaderboards boolean   DEFAULT FALSE             NOT NULL,
+    last_used_at      timestamp,
+    created_at        timestamp DEFAULT current_timestamp NOT NULL
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
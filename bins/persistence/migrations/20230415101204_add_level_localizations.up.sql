@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+-- A copy of `settings.localizations` of level data, so that levels can be
+-- listed in the viewer's language without loading their data.
+ALTER TABLE levels
+    ADD COLUMN localizations jsonb DEFAULT '{}' NOT NULL;
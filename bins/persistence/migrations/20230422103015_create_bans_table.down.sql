@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE admin_permissions
+    DROP COLUMN ban;
+DROP TABLE bans;
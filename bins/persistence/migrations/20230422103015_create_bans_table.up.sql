@@ -0,0 +1,20 @@
+-- Add up migration script here
+
+-- Game servers fetch active bans and check them during handshakes. IP ranges
+-- are stored in CIDR notation and matched by the servers.
+CREATE TABLE bans
+(
+    id         bigserial PRIMARY KEY,
+    user_id    bigint REFERENCES users (id) ON DELETE CASCADE,
+    ip_range   text,
+    reason     text      DEFAULT ''                NOT NULL,
+    banned_by  bigint REFERENCES users (id) ON DELETE SET NULL,
+    expires_at timestamp,
+    created_at timestamp DEFAULT current_timestamp NOT NULL,
+    CHECK (user_id IS NOT NULL OR ip_range IS NOT NULL)
+);
+
+CREATE INDEX bans_user_id_idx ON bans (user_id);
+
+ALTER TABLE admin_permissions
+    ADD COLUMN ban boolean DEFAULT FALSE NOT NULL;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+
+-- Players submit reports from the game client, which attaches the context of
+-- the session. Screenshots are uploaded separately, right after a report.
+CREATE TABLE bug_reports
+(
+    id            bigserial PRIMARY KEY,
+    user_id       bigint REFERENCES users (id) ON DELETE CASCADE NOT NULL,
+    level_id      bigint REFERENCES levels (id) ON DELETE SET NULL,
+    frame_number  integer,
+    rtt_millis    real,
+    packet_loss   real,
+    jitter_millis real,
+    description   text                                          NOT NULL,
+    logs          text[]    DEFAULT '{}'                        NOT NULL,
+    screenshot    bytea,
+    created_at    timestamp DEFAULT current_timestamp           NOT NULL
+);
+
+CREATE INDEX bug_reports_created_at_idx ON bug_reports (created_at);
@@ -1,8 +1,18 @@
 #![feature(try_blocks)]
 
+mod pools;
+mod presence;
 mod private;
 mod public;
+mod synthetic_users;
+mod telemetry;
 
+use crate::{
+    pools::{ReadReplica, MAX_CONNECTIONS},
+    presence::PresenceStore,
+    synthetic_users::{seed_synthetic_users, DEFAULT_SYNTHETIC_USERS_COUNT},
+    telemetry::trace_request,
+};
 use actix_web::{web, App, HttpResponse, HttpServer};
 use futures::{select, FutureExt};
 use jwt_compact::Token;
@@ -17,7 +27,9 @@ use sqlx::postgres::PgPoolOptions;
 #[derive(Clone)]
 pub struct Data {
     pool: sqlx::PgPool,
+    read_replica: ReadReplica,
     jwks: Jwks,
+    presence: PresenceStore,
     config: Config,
 }
 
@@ -30,6 +42,15 @@ struct Config {
     auth0_client_id: String,
 }
 
+impl Data {
+    /// Is meant for read-only queries that can tolerate replication lag.
+    async fn acquire_read_connection(
+        &self,
+    ) -> Result<sqlx::pool::PoolConnection<sqlx::Postgres>, sqlx::Error> {
+        self.read_replica.acquire(&self.pool).await
+    }
+}
+
 async fn decode_token_helper(
     data: &Data,
     token: &str,
@@ -107,6 +128,7 @@ async fn main() -> anyhow::Result<()> {
 
     let mut builder = env_logger::Builder::from_default_env();
     builder.filter_level(log::LevelFilter::Info).init();
+    mr_utils_lib::telemetry::init("mr_persistence");
 
     let config = Config {
         google_certs_url: "https://www.googleapis.com/oauth2/v3/certs"
@@ -124,13 +146,29 @@ async fn main() -> anyhow::Result<()> {
     };
 
     let pool = PgPoolOptions::new()
-        .max_connections(10)
+        .max_connections(MAX_CONNECTIONS)
         .connect(&std::env::var("DATABASE_URL").expect("Expected DATABASE_URL"))
         .await?;
+    let read_replica =
+        ReadReplica::connect_lazy(std::env::var("MUDDLE_DATABASE_REPLICA_URL").ok().as_deref())?;
+    if read_replica.is_enabled() {
+        log::info!("Routing read-only queries to the read replica");
+    }
 
     sqlx::migrate!().run(&pool).await?;
 
     let jwks = Jwks::new();
+    if jwks.synthetic_auth().is_some() {
+        let count = std::env::var("MUDDLE_SYNTHETIC_USERS_COUNT")
+            .ok()
+            .map(|count| {
+                count
+                    .parse()
+                    .expect("Expected MUDDLE_SYNTHETIC_USERS_COUNT to be a number")
+            })
+            .unwrap_or(DEFAULT_SYNTHETIC_USERS_COUNT);
+        seed_synthetic_users(&pool, count).await?;
+    }
     let client = reqwest::Client::new();
     tokio::spawn(poll_jwks(
         client.clone(),
@@ -143,7 +181,13 @@ async fn main() -> anyhow::Result<()> {
         jwks.clone(),
     ));
 
-    let data = Data { pool, jwks, config };
+    let data = Data {
+        pool,
+        read_replica,
+        jwks,
+        presence: PresenceStore::default(),
+        config,
+    };
 
     let public_data = data.clone();
     let public = move || {
@@ -154,13 +198,31 @@ async fn main() -> anyhow::Result<()> {
         let data = public_data.clone();
         App::new()
             .wrap(cors)
+            .wrap_fn(trace_request)
             .app_data(web::Data::new(data))
             .service(public::get_user)
+            .service(public::get_player_stats)
             .service(public::register)
             .service(public::link_account)
             .service(public::patch_user)
             .service(public::get_levels)
+            // Must be registered before `get_level`, otherwise `summary` is matched as an id.
+            .service(public::get_levels_summary)
             .service(public::get_level)
+            .service(public::patch_own_level)
+            .service(public::get_friends)
+            .service(public::post_friend)
+            .service(public::accept_friend)
+            .service(public::delete_friend)
+            .service(public::get_privacy_settings)
+            .service(public::put_privacy_settings)
+            .service(public::post_audio_clip)
+            .service(public::get_audio_clip)
+            .service(public::post_bug_report)
+            .service(public::put_bug_report_screenshot)
+            .service(public::get_api_tokens)
+            .service(public::post_api_token)
+            .service(public::delete_api_token)
     };
     let mut public_server = HttpServer::new(public)
         .workers(2)
@@ -171,11 +233,25 @@ async fn main() -> anyhow::Result<()> {
     let private = move || {
         let data = data.clone();
         App::new()
+            .wrap_fn(trace_request)
             .app_data(web::Data::new(data))
+            .app_data(private::json_config())
             .service(private::get_registered_user)
+            .service(private::get_privacy_settings)
+            .service(private::post_player_stats)
+            .service(private::get_admin_permissions)
+            .service(private::post_admin_action)
+            .service(private::get_bans)
+            .service(private::post_ban)
+            .service(private::delete_bans)
             .service(private::post_level)
             .service(private::patch_level)
             .service(private::delete_level)
+            .service(private::post_presence)
+            .service(private::post_allocation)
+            .service(private::get_audio_clips)
+            .service(private::patch_audio_clip)
+            .service(private::get_pools_metrics)
     };
     let mut private_server = HttpServer::new(private)
         .workers(3)
@@ -191,6 +267,7 @@ async fn main() -> anyhow::Result<()> {
             log::error!("Private server shutdown: {:?}", r);
         }
     }
+    mr_utils_lib::telemetry::shutdown();
 
     Ok(())
 }
@@ -0,0 +1,80 @@
+use mr_messages_lib::{GetPoolsMetricsResponse, PoolMetrics};
+use sqlx::{pool::PoolConnection, postgres::PgPoolOptions, PgPool, Postgres};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub const MAX_CONNECTIONS: u32 = 10;
+/// Falling back to the primary is better than keeping a player waiting for a
+/// replica that is down.
+const REPLICA_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An optional pool of read-only connections. Queries that can tolerate
+/// replication lag (browsing levels, public profiles) are routed to it, to
+/// take the load off the primary.
+#[derive(Clone)]
+pub struct ReadReplica {
+    pool: Option<PgPool>,
+    fallbacks: Arc<AtomicU64>,
+}
+
+impl ReadReplica {
+    /// Connects lazily, so that the service can start (and serve from the
+    /// primary) even if the replica is unavailable.
+    pub fn connect_lazy(url: Option<&str>) -> anyhow::Result<Self> {
+        let pool = url
+            .map(|url| {
+                PgPoolOptions::new()
+                    .max_connections(MAX_CONNECTIONS)
+                    .acquire_timeout(REPLICA_ACQUIRE_TIMEOUT)
+                    .connect_lazy(url)
+            })
+            .transpose()?;
+        Ok(Self {
+            pool,
+            fallbacks: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    /// Acquires a replica connection, falling back to the primary if there's
+    /// no replica configured or it's unavailable.
+    pub async fn acquire(&self, primary: &PgPool) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        if let Some(pool) = &self.pool {
+            match pool.acquire().await {
+                Ok(connection) => return Ok(connection),
+                Err(err) => {
+                    self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "Failed to acquire a read replica connection, falling back to the primary: {:?}",
+                        err
+                    );
+                }
+            }
+        }
+        primary.acquire().await
+    }
+
+    pub fn metrics(&self, primary: &PgPool) -> GetPoolsMetricsResponse {
+        GetPoolsMetricsResponse {
+            primary: pool_metrics(primary),
+            replica: self.pool.as_ref().map(pool_metrics),
+            replica_fallbacks: self.fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn pool_metrics(pool: &PgPool) -> PoolMetrics {
+    PoolMetrics {
+        size: pool.size(),
+        idle: pool.num_idle() as u32,
+        max_connections: pool.options().get_max_connections(),
+    }
+}
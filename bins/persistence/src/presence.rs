@@ -0,0 +1,57 @@
+use mr_messages_lib::{PostPresenceRequest, UserPresence};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Game servers report presence every 30 seconds, if a server doesn't report
+/// for longer than that (crashed or was shut down), its users are considered
+/// offline.
+const PRESENCE_TTL: Duration = Duration::from_secs(90);
+
+/// Presence is short-lived, so we don't bother with storing it in the
+/// database.
+#[derive(Clone, Default)]
+pub struct PresenceStore {
+    presence: Arc<RwLock<HashMap<i64, (UserPresence, Instant)>>>,
+}
+
+impl PresenceStore {
+    pub fn report(&self, request: PostPresenceRequest) {
+        let PostPresenceRequest {
+            server_name,
+            level_id,
+            level_title,
+            user_ids,
+        } = request;
+        let now = Instant::now();
+
+        let mut presence = self.presence.write().unwrap();
+        presence.retain(|user_id, (user_presence, reported_at)| {
+            now.duration_since(*reported_at) < PRESENCE_TTL
+                && (user_presence.server_name != server_name || user_ids.contains(user_id))
+        });
+        for user_id in user_ids {
+            presence.insert(
+                user_id,
+                (
+                    UserPresence {
+                        server_name: server_name.clone(),
+                        level_id,
+                        level_title: level_title.clone(),
+                    },
+                    now,
+                ),
+            );
+        }
+    }
+
+    pub fn get(&self, user_id: i64) -> Option<UserPresence> {
+        let presence = self.presence.read().unwrap();
+        presence
+            .get(&user_id)
+            .filter(|(_, reported_at)| Instant::now().duration_since(*reported_at) < PRESENCE_TTL)
+            .map(|(user_presence, _)| user_presence.clone())
+    }
+}
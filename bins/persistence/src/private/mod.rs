@@ -1,11 +1,50 @@
 use crate::Data;
-use actix_web::{delete, get, patch, post, web, HttpResponse};
+use actix_web::{delete, error::JsonPayloadError, get, patch, post, web, HttpResponse};
 use mr_messages_lib::{
-    ErrorKind, ErrorResponse, GetRegisteredUserQuery, LevelData, PatchLevelRequest,
-    PostLevelRequest, PostLevelResponse, RegisteredUser,
+    parse_ip_range,
+    validation::{
+        self, sanitize_text, validate_level_data, validate_level_localizations,
+        validate_level_title, LevelDataError, LEVEL_DATA_MAX_BYTES, LEVEL_DESCRIPTION_MAX_LEN,
+        LEVEL_OBJECT_LABEL_MAX_LEN, LEVEL_TITLE_MAX_LEN,
+    },
+    AdminPermissions, AudioClipSummary, Ban, DeleteBansQuery, ErrorKind, ErrorResponse,
+    GetAudioClipsQuery, GetRegisteredUserQuery, LevelData, LevelLocalizations,
+    PatchAudioClipRequest, PatchLevelRequest, PlayerStats, PostAdminActionRequest,
+    PostAllocationRequest, PostBanRequest, PostLevelRequest, PostLevelResponse,
+    PostPlayerStatsRequest, PostPresenceRequest, PrivacySettings, RegisteredUser,
+    BAN_REASON_MAX_LEN,
 };
 use sqlx::Connection;
 
+/// Leaves some room for the fields that accompany level data.
+const MAX_JSON_PAYLOAD_BYTES: usize = LEVEL_DATA_MAX_BYTES + 16 * 1024;
+/// Game servers send the ids of the builders that have edited a level within a
+/// session, which are limited by the server capacity.
+const MAX_BUILDER_IDS: usize = 64;
+
+/// Rejects oversized bodies before they are parsed, responding in the same
+/// format as the route errors.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_JSON_PAYLOAD_BYTES)
+        .error_handler(|err, _req| {
+            let mut response = match &err {
+                JsonPayloadError::OverflowKnownLength { .. }
+                | JsonPayloadError::Overflow { .. } => HttpResponse::PayloadTooLarge(),
+                // Well-formed JSON that doesn't match the request type.
+                JsonPayloadError::Deserialize(err) if err.is_data() => {
+                    HttpResponse::UnprocessableEntity()
+                }
+                _ => HttpResponse::BadRequest(),
+            };
+            let response = response.json(ErrorResponse::<()> {
+                message: err.to_string(),
+                error_kind: ErrorKind::BadRequest,
+            });
+            actix_web::error::InternalError::from_response(err, response).into()
+        })
+}
+
 #[get("/user")]
 pub async fn get_registered_user(
     data: web::Data<Data>,
@@ -47,6 +86,352 @@ WHERE o.subject = $1 AND o.issuer = $2
     }
 }
 
+/// Is used by game servers to find out what kind of data they are allowed to
+/// collect for a player.
+#[get("/users/{id}/privacy")]
+pub async fn get_privacy_settings(data: web::Data<Data>, user_id: web::Path<i64>) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let privacy_settings = sqlx::query_as!(
+        PrivacySettings,
+        "SELECT allow_session_recording, allow_analytics FROM users WHERE id = $1",
+        user_id.into_inner(),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match privacy_settings {
+        Ok(privacy_settings) => HttpResponse::Ok().json(privacy_settings),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(ErrorResponse::<()> {
+            message: "User doesn't exist".to_owned(),
+            error_kind: ErrorKind::NotFound,
+        }),
+        Err(err) => {
+            log::error!("Failed to get privacy settings: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Is used by game servers to find out what admin commands a player is allowed
+/// to run. Users without granted permissions get all of them disabled.
+#[get("/users/{id}/admin_permissions")]
+pub async fn get_admin_permissions(data: web::Data<Data>, user_id: web::Path<i64>) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let admin_permissions = sqlx::query_as!(
+        AdminPermissions,
+        r#"
+SELECT
+    COALESCE(p.kick, FALSE) AS "kick!",
+    COALESCE(p.pause, FALSE) AS "pause!",
+    COALESCE(p.reload, FALSE) AS "reload!",
+    COALESCE(p.cvars, FALSE) AS "cvars!",
+    COALESCE(p.broadcast, FALSE) AS "broadcast!",
+    COALESCE(p.ban, FALSE) AS "ban!"
+FROM users u
+LEFT JOIN admin_permissions p ON p.user_id = u.id
+WHERE u.id = $1
+        "#,
+        user_id.into_inner(),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match admin_permissions {
+        Ok(admin_permissions) => HttpResponse::Ok().json(admin_permissions),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(ErrorResponse::<()> {
+            message: "User doesn't exist".to_owned(),
+            error_kind: ErrorKind::NotFound,
+        }),
+        Err(err) => {
+            log::error!("Failed to get admin permissions: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Appends an entry to the audit log of admin commands.
+#[post("/admin_actions")]
+pub async fn post_admin_action(
+    data: web::Data<Data>,
+    body: web::Json<PostAdminActionRequest>,
+) -> HttpResponse {
+    let PostAdminActionRequest {
+        user_id,
+        server_name,
+        permission,
+        details,
+    } = body.into_inner();
+    log::info!(
+        "Admin action of user {} on {:?} ({}): {}",
+        user_id,
+        server_name,
+        permission.as_str(),
+        details
+    );
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        "
+INSERT INTO admin_actions (user_id, server_name, permission, details)
+VALUES ($1, $2, $3, $4)
+        ",
+        user_id,
+        server_name,
+        permission.as_str(),
+        details,
+    )
+    .execute(&mut connection)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(err) => {
+            if let Some("admin_actions_user_id_fkey") =
+                err.as_database_error().and_then(|err| err.constraint())
+            {
+                return HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "User doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                });
+            }
+
+            log::error!("Failed to save an admin action: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Lists the bans that haven't expired yet, game servers cache them and poll
+/// for updates.
+#[get("/bans")]
+pub async fn get_bans(data: web::Data<Data>) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let bans = sqlx::query_as!(
+        Ban,
+        "
+SELECT id, user_id, ip_range, reason, banned_by, expires_at, created_at
+FROM bans
+WHERE expires_at IS NULL OR expires_at > now()
+ORDER BY id
+        ",
+    )
+    .fetch_all(&mut connection)
+    .await;
+
+    match bans {
+        Ok(bans) => HttpResponse::Ok().json(bans),
+        Err(err) => {
+            log::error!("Failed to get bans: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/bans")]
+pub async fn post_ban(data: web::Data<Data>, body: web::Json<PostBanRequest>) -> HttpResponse {
+    let PostBanRequest {
+        user_id,
+        ip_range,
+        reason,
+        banned_by,
+        expires_at,
+    } = body.into_inner();
+    log::info!(
+        "Banning (user: {:?}, IP range: {:?}, by: {:?}, until: {:?}): {}",
+        user_id,
+        ip_range,
+        banned_by,
+        expires_at,
+        reason
+    );
+
+    if user_id.is_none() && ip_range.is_none() {
+        return HttpResponse::BadRequest().json(ErrorResponse::<()> {
+            message: "Either user_id or ip_range must be specified".to_owned(),
+            error_kind: ErrorKind::BadRequest,
+        });
+    }
+    if let Some(ip_range) = &ip_range {
+        if parse_ip_range(ip_range).is_none() {
+            return HttpResponse::BadRequest().json(ErrorResponse::<()> {
+                message: format!("Invalid ip_range: {ip_range:?}"),
+                error_kind: ErrorKind::BadRequest,
+            });
+        }
+    }
+    let reason = sanitize_text(&reason, BAN_REASON_MAX_LEN);
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let ban = sqlx::query_as!(
+        Ban,
+        "
+INSERT INTO bans (user_id, ip_range, reason, banned_by, expires_at)
+VALUES ($1, $2, $3, $4, $5)
+RETURNING id, user_id, ip_range, reason, banned_by, expires_at, created_at
+        ",
+        user_id,
+        ip_range,
+        reason,
+        banned_by,
+        expires_at,
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match ban {
+        Ok(ban) => HttpResponse::Ok().json(ban),
+        Err(err) => {
+            if let Some("bans_user_id_fkey" | "bans_banned_by_fkey") =
+                err.as_database_error().and_then(|err| err.constraint())
+            {
+                return HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "User doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                });
+            }
+
+            log::error!("Failed to save a ban: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[delete("/bans")]
+pub async fn delete_bans(
+    data: web::Data<Data>,
+    query: web::Query<DeleteBansQuery>,
+) -> HttpResponse {
+    let DeleteBansQuery { user_id, ip_range } = query.into_inner();
+    log::info!("Unbanning (user: {:?}, IP range: {:?})", user_id, ip_range);
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        "DELETE FROM bans WHERE user_id = $1 OR ip_range = $2",
+        user_id,
+        ip_range,
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                HttpResponse::Ok().json(())
+            } else {
+                HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "Ban doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                })
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete bans: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Is used by game servers to add up the finishes and deaths of registered
+/// players.
+#[post("/users/{id}/stats")]
+pub async fn post_player_stats(
+    data: web::Data<Data>,
+    user_id: web::Path<i64>,
+    body: web::Json<PostPlayerStatsRequest>,
+) -> HttpResponse {
+    let user_id = user_id.into_inner();
+    let PostPlayerStatsRequest { finishes, deaths } = body.into_inner();
+    log::debug!(
+        "Updating stats of user {} (finishes: +{}, deaths: +{})",
+        user_id,
+        finishes,
+        deaths
+    );
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let player_stats = sqlx::query_as!(
+        PlayerStats,
+        r#"
+INSERT INTO player_stats (user_id, finishes, deaths)
+VALUES ($1, $2, $3)
+ON CONFLICT (user_id) DO UPDATE
+SET finishes = player_stats.finishes + EXCLUDED.finishes,
+    deaths   = player_stats.deaths + EXCLUDED.deaths
+RETURNING finishes, deaths
+        "#,
+        user_id,
+        i64::from(finishes),
+        i64::from(deaths),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match player_stats {
+        Ok(player_stats) => HttpResponse::Ok().json(player_stats),
+        Err(err) => {
+            if let Some("player_stats_user_id_fkey") =
+                err.as_database_error().and_then(|err| err.constraint())
+            {
+                return HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "User doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                });
+            }
+
+            log::error!("Failed to update player stats: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 #[post("/levels")]
 pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>) -> HttpResponse {
     log::debug!("Posting a level: {:?}", body);
@@ -56,6 +441,11 @@ pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>
         user_id,
         data: level_data,
     } = body.into_inner();
+    let title = sanitize_text(&title, LEVEL_TITLE_MAX_LEN);
+    let title = match validate_level_title(&title) {
+        Ok(title) => title,
+        Err(errors) => return invalid_level_title_response(&errors),
+    };
 
     let mut connection = match data.pool.acquire().await {
         Ok(c) => c,
@@ -65,7 +455,7 @@ pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>
         }
     };
 
-    let (data, parent_id, old_data) = match level_data {
+    let (mut data, parent_id, old_data) = match level_data {
         LevelData::Forked { parent_id } => {
             let data = match get_level_data(&mut connection, parent_id, false).await {
                 Ok(data) => data,
@@ -84,8 +474,12 @@ pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>
         }
         LevelData::Autosaved {
             autosaved_level_id,
-            data,
+            mut data,
         } => {
+            if let Err(err) = validate_level_data(&data) {
+                return invalid_level_data_response(err);
+            }
+            sanitize_level_labels(&mut data);
             let old_data = match get_level_data(&mut connection, autosaved_level_id, false).await {
                 Ok(data) => {
                     log::debug!(
@@ -108,7 +502,26 @@ pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>
             };
             (data, Some(autosaved_level_id), Some(old_data))
         }
-        LevelData::Data { data } => (data, None, None),
+        LevelData::Data { mut data } => {
+            if let Err(err) = validate_level_data(&data) {
+                return invalid_level_data_response(err);
+            }
+            sanitize_level_labels(&mut data);
+            (data, None, None)
+        }
+    };
+
+    let localizations = match sanitize_level_localizations(&mut data) {
+        Ok(localizations) => localizations,
+        Err(err) => return invalid_level_data_response(err),
+    };
+    // Autosaved versions keep the localizations of the data they store.
+    let inserted_localizations = match &old_data {
+        Some(old_data) => old_data
+            .pointer("/settings/localizations")
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({})),
+        None => localizations.clone(),
     };
 
     let is_autosaved = old_data.is_some();
@@ -119,23 +532,29 @@ pub async fn post_level(data: web::Data<Data>, body: web::Json<PostLevelRequest>
             PostLevelResponse,
             r#"
 INSERT INTO levels
-(title, user_id, parent_id, data, is_autosaved)
-VALUES ($1, $2, $3, $4, $5)
+(title, user_id, parent_id, data, is_autosaved, localizations)
+VALUES ($1, $2, $3, $4, $5, $6)
 RETURNING id, data, created_at, updated_at
             "#,
             title,
             user_id,
             parent_id,
             old_data.unwrap_or_else(|| data.clone()),
-            is_autosaved
+            is_autosaved,
+            inserted_localizations,
         )
         .fetch_one(&mut tx)
         .await?;
 
         if is_autosaved {
-            sqlx::query!("UPDATE levels SET data = $1 WHERE id = $2", data, parent_id)
-                .execute(&mut tx)
-                .await?;
+            sqlx::query!(
+                "UPDATE levels SET data = $1, localizations = $2 WHERE id = $3",
+                data,
+                localizations,
+                parent_id
+            )
+            .execute(&mut tx)
+            .await?;
             sqlx::query!(
                 r#"
 DELETE FROM levels
@@ -166,6 +585,56 @@ WHERE id NOT IN (
     }
 }
 
+/// Game servers sanitize labels before saving, but level data isn't parsed
+/// here otherwise, so labels are the only fields that get touched. Forked
+/// levels copy the data that has already been sanitized.
+fn sanitize_level_labels(data: &mut serde_json::Value) {
+    // Levels saved before the settings were introduced are plain arrays of
+    // objects.
+    let objects = match data {
+        serde_json::Value::Array(objects) => objects,
+        serde_json::Value::Object(level) => match level.get_mut("objects") {
+            Some(serde_json::Value::Array(objects)) => objects,
+            _ => return,
+        },
+        _ => return,
+    };
+    for object in objects {
+        if let Some(serde_json::Value::String(label)) = object.get_mut("label") {
+            *label = sanitize_text(label, LEVEL_OBJECT_LABEL_MAX_LEN);
+        }
+    }
+}
+
+/// Localizations are copied from the level settings into a separate column,
+/// so that levels can be listed without loading their data. Like labels, they
+/// get sanitized in place. Returns the value to store in the column.
+fn sanitize_level_localizations(
+    data: &mut serde_json::Value,
+) -> Result<serde_json::Value, LevelDataError> {
+    let Some(value) = data.pointer_mut("/settings/localizations") else {
+        return Ok(serde_json::json!({}));
+    };
+    let mut localizations: LevelLocalizations =
+        serde_json::from_value(value.clone()).map_err(|_| LevelDataError::InvalidFormat {
+            path: "/settings/localizations".to_owned(),
+        })?;
+    for localization in localizations.0.values_mut() {
+        localization.title = sanitize_text(&localization.title, LEVEL_TITLE_MAX_LEN);
+        localization.description =
+            sanitize_text(&localization.description, LEVEL_DESCRIPTION_MAX_LEN);
+    }
+    validate_level_localizations(localizations.0.iter().map(|(language, localization)| {
+        (
+            language.as_str(),
+            localization.title.as_str(),
+            localization.description.as_str(),
+        )
+    }))?;
+    *value = serde_json::to_value(&localizations).expect("Failed to serialize localizations");
+    Ok(value.clone())
+}
+
 async fn get_level_data(
     connection: &mut sqlx::PgConnection,
     id: i64,
@@ -193,7 +662,22 @@ pub async fn patch_level(
     body: web::Json<PatchLevelRequest>,
 ) -> HttpResponse {
     let id = id.into_inner();
-    let PatchLevelRequest { title, builder_ids } = body.into_inner();
+    let PatchLevelRequest {
+        title,
+        builder_ids,
+        published,
+    } = body.into_inner();
+    let title = title.map(|title| sanitize_text(&title, LEVEL_TITLE_MAX_LEN));
+    let title = match title.as_deref().map(validate_level_title).transpose() {
+        Ok(title) => title,
+        Err(errors) => return invalid_level_title_response(&errors),
+    };
+    if builder_ids.as_ref().map_or(0, Vec::len) > MAX_BUILDER_IDS {
+        return HttpResponse::UnprocessableEntity().json(ErrorResponse::<()> {
+            message: format!("Builder ids must not contain more than {MAX_BUILDER_IDS} items"),
+            error_kind: ErrorKind::BadRequest,
+        });
+    }
 
     let mut connection = match data.pool.acquire().await {
         Ok(c) => c,
@@ -237,6 +721,16 @@ pub async fn patch_level(
             _ => {}
         }
 
+        if let Some(published) = published {
+            sqlx::query!(
+                "UPDATE levels SET published = $1 WHERE id = $2",
+                published,
+                id
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
         tx.commit().await?;
     };
 
@@ -253,6 +747,24 @@ pub async fn patch_level(
     }
 }
 
+fn invalid_level_title_response(errors: &[validation::ValidationError]) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse::<()> {
+        message: validation::format_errors("Level title", errors),
+        error_kind: ErrorKind::BadRequest,
+    })
+}
+
+fn invalid_level_data_response(err: LevelDataError) -> HttpResponse {
+    let mut response = match err {
+        LevelDataError::TooLarge { .. } => HttpResponse::PayloadTooLarge(),
+        _ => HttpResponse::UnprocessableEntity(),
+    };
+    response.json(ErrorResponse::<LevelDataError> {
+        message: err.to_string(),
+        error_kind: ErrorKind::RouteSpecific(err),
+    })
+}
+
 #[delete("/levels/{id}")]
 pub async fn delete_level(data: web::Data<Data>, id: web::Path<i64>) -> HttpResponse {
     let id = id.into_inner();
@@ -285,3 +797,166 @@ pub async fn delete_level(data: web::Data<Data>, id: web::Path<i64>) -> HttpResp
         }
     }
 }
+
+#[post("/presence")]
+pub async fn post_presence(
+    data: web::Data<Data>,
+    body: web::Json<PostPresenceRequest>,
+) -> HttpResponse {
+    log::debug!("Reported presence: {:?}", body);
+    data.presence.report(body.into_inner());
+    HttpResponse::Ok().json(())
+}
+
+/// Is used by the matchmaker to record allocation outcomes.
+#[post("/allocations")]
+pub async fn post_allocation(
+    data: web::Data<Data>,
+    body: web::Json<PostAllocationRequest>,
+) -> HttpResponse {
+    log::debug!("Recording an allocation: {:?}", body);
+
+    let PostAllocationRequest {
+        request_id,
+        user_id,
+        level_id,
+        level_parent_id,
+        protocol_version,
+        server_name,
+        latency_millis,
+        failure_reason,
+    } = body.into_inner();
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+INSERT INTO allocations
+(request_id, user_id, level_id, level_parent_id, protocol_version, server_name, latency_millis, failure_reason)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        request_id.to_string(),
+        user_id,
+        level_id,
+        level_parent_id,
+        protocol_version as i32,
+        server_name,
+        i64::from(latency_millis),
+        failure_reason.map(|reason| reason.to_string()),
+    )
+    .execute(&mut connection)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(()),
+        Err(err) => {
+            log::error!("Failed to record an allocation: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Lists clips for moderators, without their data.
+#[get("/audio_clips")]
+pub async fn get_audio_clips(
+    data: web::Data<Data>,
+    query: web::Query<GetAudioClipsQuery>,
+) -> HttpResponse {
+    let moderation_status = query.into_inner().moderation_status;
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+SELECT id, user_id, length(data) AS "size!", created_at
+FROM audio_clips
+WHERE moderation_status = $1
+ORDER BY created_at
+        "#,
+        moderation_status.to_string(),
+    )
+    .fetch_all(&mut connection)
+    .await;
+
+    match result {
+        Ok(clips) => HttpResponse::Ok().json(
+            clips
+                .into_iter()
+                .map(|clip| AudioClipSummary {
+                    id: clip.id,
+                    user_id: clip.user_id,
+                    size: clip.size,
+                    moderation_status,
+                    created_at: clip.created_at,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => {
+            log::error!("Failed to get audio clips: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Approving a clip makes it available to everyone who plays the levels that
+/// use it.
+#[patch("/audio_clips/{id}")]
+pub async fn patch_audio_clip(
+    data: web::Data<Data>,
+    id: web::Path<i64>,
+    body: web::Json<PatchAudioClipRequest>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let PatchAudioClipRequest { moderation_status } = body.into_inner();
+    log::info!("Moderating audio clip {}: {}", id, moderation_status);
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        "UPDATE audio_clips SET moderation_status = $1, moderated_at = now() WHERE id = $2",
+        moderation_status.to_string(),
+        id
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                HttpResponse::Ok().json(())
+            } else {
+                HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "Audio clip doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                })
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to moderate an audio clip: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Helps to tell whether the pools need to be resized or a replica added.
+#[get("/metrics/pools")]
+pub async fn get_pools_metrics(data: web::Data<Data>) -> HttpResponse {
+    HttpResponse::Ok().json(data.read_replica.metrics(&data.pool))
+}
@@ -0,0 +1,293 @@
+use super::authorize_user;
+use crate::Data;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use mr_messages_lib::{
+    validation::{format_errors, sanitize_text, validate_api_token_name, API_TOKEN_NAME_MAX_LEN},
+    ApiToken, ApiTokenError, ApiTokenScopes, ErrorKind, ErrorResponse, PostApiTokenRequest,
+    PostApiTokenResponse, API_TOKEN_PREFIX, MAX_API_TOKENS_PER_USER,
+};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::{types::chrono, Connection};
+
+const API_TOKEN_SECRET_BYTES: usize = 32;
+
+/// Tokens are looked up by their hashes, as secrets are shown only once.
+/// Secrets are random, so a fast hash is enough.
+pub(super) fn hash_api_token(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn generate_api_token_secret() -> String {
+    let mut bytes = [0; API_TOKEN_SECRET_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{API_TOKEN_PREFIX}{}", hex::encode(bytes))
+}
+
+struct ApiTokenDto {
+    id: i64,
+    name: String,
+    read_levels: bool,
+    write_levels: bool,
+    read_leaderboards: bool,
+    created_at: chrono::NaiveDateTime,
+    last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<ApiTokenDto> for ApiToken {
+    fn from(token: ApiTokenDto) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            scopes: ApiTokenScopes {
+                read_levels: token.read_levels,
+                write_levels: token.write_levels,
+                read_leaderboards: token.read_leaderboards,
+            },
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+/// Tokens can be managed only with a browser login (an OIDC JWT), so that a
+/// leaked token can't be used to issue new ones.
+#[get("/api_tokens")]
+pub async fn get_api_tokens(data: web::Data<Data>, req: HttpRequest) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let api_tokens = sqlx::query_as!(
+        ApiTokenDto,
+        r#"
+SELECT id, name, read_levels, write_levels, read_leaderboards, created_at, last_used_at
+FROM api_tokens
+WHERE user_id = $1
+ORDER BY created_at DESC, id DESC
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut connection)
+    .await;
+
+    match api_tokens {
+        Ok(api_tokens) => HttpResponse::Ok().json(
+            api_tokens
+                .into_iter()
+                .map(ApiToken::from)
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => {
+            log::error!("Failed to get API tokens: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/api_tokens")]
+pub async fn post_api_token(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Json<PostApiTokenRequest>,
+) -> HttpResponse {
+    let PostApiTokenRequest { name, scopes } = body.into_inner();
+    let name = sanitize_text(&name, API_TOKEN_NAME_MAX_LEN);
+    let name = match validate_api_token_name(&name) {
+        Ok(name) => name,
+        Err(errors) => {
+            return HttpResponse::BadRequest().json(ErrorResponse::<()> {
+                message: format_errors("Token name", &errors),
+                error_kind: ErrorKind::BadRequest,
+            });
+        }
+    };
+    if scopes.is_empty() {
+        return HttpResponse::BadRequest().json(ErrorResponse::<ApiTokenError> {
+            message: "A token must have at least one scope".to_owned(),
+            error_kind: ErrorKind::RouteSpecific(ApiTokenError::NoScopes),
+        });
+    }
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    struct Count {
+        count: i64,
+    }
+    struct InsertedToken {
+        id: i64,
+        created_at: chrono::NaiveDateTime,
+    }
+    let secret = generate_api_token_secret();
+    let result: sqlx::Result<Option<InsertedToken>> = try {
+        let mut tx = connection.begin().await?;
+
+        // Locking the user row serializes concurrent requests of the same user,
+        // otherwise they could all pass the check below.
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut tx)
+            .await?;
+        let Count { count } = sqlx::query_as!(
+            Count,
+            r#"SELECT count(*) AS "count!" FROM api_tokens WHERE user_id = $1"#,
+            user_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        let inserted_token = if count < MAX_API_TOKENS_PER_USER {
+            let inserted_token = sqlx::query_as!(
+                InsertedToken,
+                r#"
+INSERT INTO api_tokens (user_id, name, token_hash, read_levels, write_levels, read_leaderboards)
+VALUES ($1, $2, $3, $4, $5, $6)
+RETURNING id, created_at
+                "#,
+                user_id,
+                name,
+                hash_api_token(&secret),
+                scopes.read_levels,
+                scopes.write_levels,
+                scopes.read_leaderboards,
+            )
+            .fetch_one(&mut tx)
+            .await?;
+            Some(inserted_token)
+        } else {
+            None
+        };
+
+        tx.commit().await?;
+        inserted_token
+    };
+
+    match result {
+        Ok(Some(InsertedToken { id, created_at })) => {
+            log::info!("Issued API token {} for user {}", id, user_id);
+            HttpResponse::Ok().json(PostApiTokenResponse {
+                token: ApiToken {
+                    id,
+                    name: name.to_owned(),
+                    scopes,
+                    created_at,
+                    last_used_at: None,
+                },
+                secret,
+            })
+        }
+        Ok(None) => HttpResponse::BadRequest().json(ErrorResponse::<ApiTokenError> {
+            message: format!(
+                "A user can't have more than {MAX_API_TOKENS_PER_USER} tokens, revoke unused ones"
+            ),
+            error_kind: ErrorKind::RouteSpecific(ApiTokenError::TooManyTokens {
+                max_tokens: MAX_API_TOKENS_PER_USER,
+            }),
+        }),
+        Err(err) => {
+            log::error!("Failed to issue an API token: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Revoked tokens stop working immediately, as they aren't cached anywhere.
+#[delete("/api_tokens/{id}")]
+pub async fn delete_api_token(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    token_id: web::Path<i64>,
+) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let token_id = token_id.into_inner();
+    let result = sqlx::query!(
+        "DELETE FROM api_tokens WHERE id = $1 AND user_id = $2",
+        token_id,
+        user_id,
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                log::info!("Revoked API token {} of user {}", token_id, user_id);
+                HttpResponse::Ok().json(())
+            } else {
+                HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "API token doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                })
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to revoke an API token: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_api_token() {
+        // SHA-256 test vector.
+        assert_eq!(
+            hash_api_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let secret = generate_api_token_secret();
+        assert_eq!(hash_api_token(&secret), hash_api_token(&secret));
+        assert_ne!(hash_api_token(&secret), secret);
+    }
+
+    #[test]
+    fn test_generate_api_token_secret() {
+        let secret = generate_api_token_secret();
+        let random_part = secret.strip_prefix(API_TOKEN_PREFIX).unwrap();
+        assert_eq!(random_part.len(), API_TOKEN_SECRET_BYTES * 2);
+        assert!(random_part.chars().all(|c| c.is_ascii_hexdigit()));
+
+        let other_secret = generate_api_token_secret();
+        assert_ne!(secret, other_secret);
+        assert_ne!(hash_api_token(&secret), hash_api_token(&other_secret));
+    }
+}
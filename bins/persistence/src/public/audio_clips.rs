@@ -0,0 +1,109 @@
+use super::authorize_user;
+use crate::Data;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use mr_messages_lib::{
+    validate_audio_clip, AudioClipError, AudioClipModerationStatus, ErrorKind, ErrorResponse,
+    PostAudioClipResponse, AUDIO_CLIP_CONTENT_TYPE,
+};
+
+/// Expects the raw clip as the body. Uploaded clips aren't served until they
+/// are approved by a moderator (see the private `patch_audio_clip`).
+#[post("/audio_clips")]
+pub async fn post_audio_clip(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    if let Err(err) = validate_audio_clip(&body) {
+        return HttpResponse::BadRequest().json(ErrorResponse::<AudioClipError> {
+            message: err.to_string(),
+            error_kind: ErrorKind::RouteSpecific(err),
+        });
+    }
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    struct InsertedClip {
+        id: i64,
+        moderation_status: String,
+    }
+    let result = sqlx::query_as!(
+        InsertedClip,
+        "INSERT INTO audio_clips (user_id, data) VALUES ($1, $2) RETURNING id, moderation_status",
+        user_id,
+        body.as_ref(),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match result {
+        Ok(clip) => {
+            log::info!(
+                "User {} uploaded audio clip {} ({} bytes)",
+                user_id,
+                clip.id,
+                body.len()
+            );
+            HttpResponse::Ok().json(PostAudioClipResponse {
+                id: clip.id,
+                moderation_status: clip
+                    .moderation_status
+                    .parse()
+                    .unwrap_or(AudioClipModerationStatus::Pending),
+            })
+        }
+        Err(err) => {
+            log::error!("Failed to insert an audio clip: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Only approved clips are served, pending and rejected ones are reported as
+/// missing.
+#[get("/audio_clips/{id}")]
+pub async fn get_audio_clip(data: web::Data<Data>, id: web::Path<i64>) -> HttpResponse {
+    let mut connection = match data.acquire_read_connection().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let result = sqlx::query!(
+        "SELECT data FROM audio_clips WHERE id = $1 AND moderation_status = 'approved'",
+        id.into_inner()
+    )
+    .fetch_optional(&mut connection)
+    .await;
+
+    match result {
+        Ok(Some(clip)) => HttpResponse::Ok()
+            .content_type(AUDIO_CLIP_CONTENT_TYPE)
+            // Approved clips never change, so clients (and browsers) can cache them forever.
+            .insert_header(("Cache-Control", "public, max-age=31536000, immutable"))
+            .body(clip.data),
+        Ok(None) => HttpResponse::NotFound().json(ErrorResponse::<()> {
+            message: "Audio clip doesn't exist".to_owned(),
+            error_kind: ErrorKind::NotFound,
+        }),
+        Err(err) => {
+            log::error!("Failed to get an audio clip: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use super::authorize_user;
+use crate::Data;
+use actix_web::{post, put, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use mr_messages_lib::{
+    truncate_bug_report_logs, validate_bug_report_description, validate_bug_report_screenshot,
+    BugReportError, ErrorKind, ErrorResponse, PostBugReportRequest, PostBugReportResponse,
+    BUG_REPORT_SCREENSHOT_MAX_SIZE,
+};
+
+#[post("/bug_reports")]
+pub async fn post_bug_report(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Json<PostBugReportRequest>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    let description = match validate_bug_report_description(&body.description) {
+        Ok(description) => description,
+        Err(err) => return bug_report_error_response(err),
+    };
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let connection_stats = body.connection_stats;
+    // Reports about levels that have been deleted (or never existed) are
+    // still accepted, just without the level reference.
+    let result = sqlx::query!(
+        "INSERT INTO bug_reports (user_id, level_id, frame_number, rtt_millis, packet_loss, jitter_millis, description, logs) VALUES ($1, (SELECT id FROM levels WHERE id = $2), $3, $4, $5, $6, $7, $8) RETURNING id",
+        user_id,
+        body.level_id,
+        body.frame_number.map(i32::from),
+        connection_stats.map(|stats| stats.rtt_millis),
+        connection_stats.map(|stats| stats.packet_loss),
+        connection_stats.map(|stats| stats.jitter_millis),
+        description,
+        &truncate_bug_report_logs(&body.logs),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match result {
+        Ok(report) => {
+            log::info!(
+                "User {} submitted bug report {} (level: {:?})",
+                user_id,
+                report.id,
+                body.level_id
+            );
+            HttpResponse::Ok().json(PostBugReportResponse { id: report.id })
+        }
+        Err(err) => {
+            log::error!("Failed to insert a bug report: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Expects a PNG file as the body. A screenshot can be attached only once, by
+/// the author of the report, within 10 minutes after submitting it.
+#[put("/bug_reports/{id}/screenshot")]
+pub async fn put_bug_report_screenshot(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    id: web::Path<i64>,
+    mut payload: web::Payload,
+) -> HttpResponse {
+    // Screenshots are larger than the default payload limit, so the body is
+    // read manually, stopping as soon as it's known to be too large.
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) => body.extend_from_slice(&chunk),
+            Err(err) => {
+                log::warn!("Failed to read a screenshot: {:?}", err);
+                return HttpResponse::BadRequest().finish();
+            }
+        }
+        if body.len() > BUG_REPORT_SCREENSHOT_MAX_SIZE {
+            break;
+        }
+    }
+    if let Err(err) = validate_bug_report_screenshot(&body) {
+        return bug_report_error_response(err);
+    }
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let id = id.into_inner();
+    let result = sqlx::query!(
+        "UPDATE bug_reports SET screenshot = $1 WHERE id = $2 AND user_id = $3 AND screenshot IS NULL AND created_at > now() - interval '10 minutes'",
+        body.as_ref(),
+        id,
+        user_id,
+    )
+    .execute(&mut connection)
+    .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            HttpResponse::Forbidden().json(ErrorResponse::<BugReportError> {
+                message: BugReportError::ScreenshotNotAllowed.to_string(),
+                error_kind: ErrorKind::RouteSpecific(BugReportError::ScreenshotNotAllowed),
+            })
+        }
+        Ok(_) => {
+            log::info!(
+                "User {} attached a screenshot to bug report {} ({} bytes)",
+                user_id,
+                id,
+                body.len()
+            );
+            HttpResponse::Ok().json(())
+        }
+        Err(err) => {
+            log::error!("Failed to attach a screenshot: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn bug_report_error_response(err: BugReportError) -> HttpResponse {
+    HttpResponse::BadRequest().json(ErrorResponse::<BugReportError> {
+        message: err.to_string(),
+        error_kind: ErrorKind::RouteSpecific(err),
+    })
+}
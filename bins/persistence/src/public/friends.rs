@@ -0,0 +1,294 @@
+use super::authorize_user;
+use crate::Data;
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use mr_messages_lib::{
+    ErrorKind, ErrorResponse, FriendDto, FriendRequestError, FriendshipStatus, PostFriendRequest,
+};
+use sqlx::{types::chrono, Connection};
+
+#[get("/friends")]
+pub async fn get_friends(data: web::Data<Data>, req: HttpRequest) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    struct FriendshipDto {
+        user_id: i64,
+        display_name: Option<String>,
+        is_accepted: bool,
+        is_outgoing: bool,
+        created_at: chrono::NaiveDateTime,
+    }
+
+    let friendships = sqlx::query_as!(
+        FriendshipDto,
+        r#"
+SELECT u.id AS "user_id!", u.display_name, f.is_accepted AS "is_accepted!", f.user_id = $1 AS "is_outgoing!", f.created_at AS "created_at!"
+FROM friendships AS f
+JOIN users AS u ON u.id = CASE WHEN f.user_id = $1 THEN f.friend_id ELSE f.user_id END
+WHERE f.user_id = $1 OR f.friend_id = $1
+ORDER BY u.display_name
+        "#,
+        user_id,
+    )
+    .fetch_all(&mut connection)
+    .await;
+
+    match friendships {
+        Ok(friendships) => HttpResponse::Ok().json(
+            friendships
+                .into_iter()
+                .map(|friendship| {
+                    let status = match (friendship.is_accepted, friendship.is_outgoing) {
+                        (true, _) => FriendshipStatus::Accepted,
+                        (false, true) => FriendshipStatus::OutgoingRequest,
+                        (false, false) => FriendshipStatus::IncomingRequest,
+                    };
+                    FriendDto {
+                        user_id: friendship.user_id,
+                        display_name: friendship.display_name,
+                        status,
+                        // We don't want to expose presence to users who aren't accepted friends.
+                        presence: (status == FriendshipStatus::Accepted)
+                            .then(|| data.presence.get(friendship.user_id))
+                            .flatten(),
+                        created_at: friendship.created_at,
+                    }
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(err) => {
+            log::error!("Failed to get friends: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Sends a friend request. If the other user has already sent a request to
+/// the current one, it gets accepted instead.
+#[post("/friends")]
+pub async fn post_friend(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Json<PostFriendRequest>,
+) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    struct UserId {
+        id: i64,
+    }
+    let friend = sqlx::query_as!(
+        UserId,
+        "SELECT id FROM users WHERE display_name = $1",
+        body.display_name.trim(),
+    )
+    .fetch_optional(&mut connection)
+    .await;
+    let friend_id = match friend {
+        Ok(Some(UserId { id })) => id,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse::<()> {
+                message: "User doesn't exist".to_owned(),
+                error_kind: ErrorKind::NotFound,
+            });
+        }
+        Err(err) => {
+            log::error!("Failed to get a user: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if friend_id == user_id {
+        return HttpResponse::BadRequest().json(ErrorResponse::<FriendRequestError> {
+            message: "Cannot send a friend request to yourself".to_owned(),
+            error_kind: ErrorKind::RouteSpecific(FriendRequestError::CannotAddSelf),
+        });
+    }
+
+    struct ExistingFriendshipDto {
+        id: i64,
+        user_id: i64,
+        is_accepted: bool,
+    }
+    let result: sqlx::Result<bool> = try {
+        let mut tx = connection.begin().await?;
+
+        let existing_friendship = sqlx::query_as!(
+            ExistingFriendshipDto,
+            r#"
+SELECT id, user_id, is_accepted
+FROM friendships
+WHERE (user_id = $1 AND friend_id = $2) OR (user_id = $2 AND friend_id = $1)
+            "#,
+            user_id,
+            friend_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let is_created = match existing_friendship {
+            None => {
+                sqlx::query!(
+                    "INSERT INTO friendships (user_id, friend_id) VALUES ($1, $2)",
+                    user_id,
+                    friend_id,
+                )
+                .execute(&mut tx)
+                .await?;
+                true
+            }
+            Some(friendship) if friendship.user_id == friend_id && !friendship.is_accepted => {
+                sqlx::query!(
+                    "UPDATE friendships SET is_accepted = TRUE WHERE id = $1",
+                    friendship.id,
+                )
+                .execute(&mut tx)
+                .await?;
+                true
+            }
+            Some(_) => false,
+        };
+
+        tx.commit().await?;
+        is_created
+    };
+
+    match result {
+        Ok(true) => HttpResponse::Ok().json(()),
+        Ok(false) => HttpResponse::BadRequest().json(ErrorResponse::<FriendRequestError> {
+            message: "Friend request already exists".to_owned(),
+            error_kind: ErrorKind::RouteSpecific(FriendRequestError::AlreadyExists),
+        }),
+        Err(err) => {
+            // Two users might have sent requests to each other at the same time.
+            if let Some("friendships_pair_idx") =
+                err.as_database_error().and_then(|err| err.constraint())
+            {
+                return HttpResponse::BadRequest().json(ErrorResponse::<FriendRequestError> {
+                    message: "Friend request already exists".to_owned(),
+                    error_kind: ErrorKind::RouteSpecific(FriendRequestError::AlreadyExists),
+                });
+            }
+
+            log::error!("Failed to insert a friend request: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/friends/{id}/accept")]
+pub async fn accept_friend(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    friend_id: web::Path<i64>,
+) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let result = sqlx::query!(
+        "UPDATE friendships SET is_accepted = TRUE WHERE user_id = $1 AND friend_id = $2 AND is_accepted = FALSE",
+        friend_id.into_inner(),
+        user_id,
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                HttpResponse::Ok().json(())
+            } else {
+                HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "Friend request doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                })
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to accept a friend request: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Removes a friend, or declines (or cancels) a pending friend request.
+#[delete("/friends/{id}")]
+pub async fn delete_friend(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    friend_id: web::Path<i64>,
+) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let result = sqlx::query!(
+        "DELETE FROM friendships WHERE (user_id = $1 AND friend_id = $2) OR (user_id = $2 AND friend_id = $1)",
+        user_id,
+        friend_id.into_inner(),
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                HttpResponse::Ok().json(())
+            } else {
+                HttpResponse::NotFound().json(ErrorResponse::<()> {
+                    message: "Friend doesn't exist".to_owned(),
+                    error_kind: ErrorKind::NotFound,
+                })
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to delete a friend: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -1,12 +1,33 @@
+mod api_tokens;
+mod audio_clips;
+mod bug_reports;
+mod friends;
+mod player_stats;
+mod privacy;
+
+pub use api_tokens::*;
+pub use audio_clips::*;
+pub use bug_reports::*;
+pub use friends::*;
+pub use player_stats::*;
+pub use privacy::*;
+
 use crate::Data;
 use actix_web::{get, http::header, patch, post, web, HttpRequest, HttpResponse};
+use api_tokens::hash_api_token;
 use headers::{authorization::Bearer, Authorization, Header};
 use jwt_compact::Token;
 use mr_messages_lib::{
-    ErrorKind, ErrorResponse, GetLevelResponse, GetLevelsRequest, GetLevelsUserFilter,
-    GetUserResponse, LevelDto, LevelPermissionDto, LevelsListItem, LinkAccount, LinkAccountError,
-    LinkAccountLoginMethod, LinkAccountRequest, PaginationParams, PatchUserError, PatchUserRequest,
-    RegisterAccountError, RegisteredUser,
+    validation::{
+        format_errors, sanitize_text, validate_display_name, validate_level_title,
+        LEVEL_TITLE_MAX_LEN,
+    },
+    ApiTokenScope, ApiTokenScopes, ErrorKind, ErrorResponse, GetLevelResponse, GetLevelsRequest,
+    GetLevelsSummaryRequest, GetLevelsSummaryResponse, GetLevelsUserFilter, GetUserResponse,
+    LevelDto, LevelLocalizations, LevelPermissionDto, LevelSummary, LevelsCursor, LevelsListItem,
+    LinkAccount, LinkAccountError, LinkAccountLoginMethod, LinkAccountRequest, PaginationParams,
+    PatchOwnLevelRequest, PatchUserError, PatchUserRequest, RegisterAccountError, RegisteredUser,
+    API_TOKEN_PREFIX,
 };
 use mr_utils_lib::JwtAuthClaims;
 use sqlx::{types::chrono, Connection};
@@ -62,7 +83,7 @@ pub async fn register(data: web::Data<Data>, req: HttpRequest) -> HttpResponse {
 
 #[get("/users/{id}")]
 pub async fn get_user(data: web::Data<Data>, user_id: web::Path<i64>) -> HttpResponse {
-    let mut connection = match data.pool.acquire().await {
+    let mut connection = match data.acquire_read_connection().await {
         Ok(c) => c,
         Err(err) => {
             log::error!("Failed to acquire a connection: {:?}", err);
@@ -214,14 +235,15 @@ pub async fn patch_user(
         }
     };
 
-    let display_name = body.0.display_name.trim();
-    if display_name.is_empty() || display_name.len() > 255 || !display_name.is_ascii() {
-        return HttpResponse::BadRequest().json(ErrorResponse::<()> {
-            message: "Display name must not be empty and can contain only ASCII characters"
-                .to_owned(),
-            error_kind: ErrorKind::BadRequest,
-        });
-    }
+    let display_name = match validate_display_name(&body.0.display_name) {
+        Ok(display_name) => display_name,
+        Err(errors) => {
+            return HttpResponse::BadRequest().json(ErrorResponse::<PatchUserError> {
+                message: format_errors("Display name", &errors),
+                error_kind: ErrorKind::RouteSpecific(PatchUserError::InvalidDisplayName(errors)),
+            });
+        }
+    };
 
     let mut connection = match data.pool.acquire().await {
         Ok(c) => c,
@@ -509,7 +531,11 @@ VALUES ($1, $2, $3, $4)
 }
 
 #[get("/levels")]
-pub async fn get_levels(data: web::Data<Data>, body: web::Query<GetLevelsRequest>) -> HttpResponse {
+pub async fn get_levels(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Query<GetLevelsRequest>,
+) -> HttpResponse {
     let GetLevelsRequest {
         user_filter,
         pagination,
@@ -520,8 +546,11 @@ pub async fn get_levels(data: web::Data<Data>, body: web::Query<GetLevelsRequest
             error_kind: ErrorKind::BadRequest,
         });
     }
+    if let Err(err) = authorize_optional(&data, &req, ApiTokenScope::ReadLevels).await {
+        return err;
+    }
 
-    let mut connection = match data.pool.acquire().await {
+    let mut connection = match data.acquire_read_connection().await {
         Ok(c) => c,
         Err(err) => {
             log::error!("Failed to acquire a connection: {:?}", err);
@@ -548,8 +577,151 @@ pub async fn get_levels(data: web::Data<Data>, body: web::Query<GetLevelsRequest
     }
 }
 
+/// Unlike `/levels`, returns the stats and builders of each level, so that
+/// the levels browser doesn't need to request them separately.
+#[get("/levels/summary")]
+pub async fn get_levels_summary(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Query<GetLevelsSummaryRequest>,
+) -> HttpResponse {
+    let GetLevelsSummaryRequest {
+        user_filter,
+        cursor,
+        limit,
+        include_unpublished,
+    } = body.into_inner();
+    if limit == 0 || limit > 100 {
+        return HttpResponse::BadRequest().json(ErrorResponse::<()> {
+            message: "The `limit` parameter must be in the range of 1..=100".to_owned(),
+            error_kind: ErrorKind::BadRequest,
+        });
+    }
+    if let Err(err) = authorize_optional(&data, &req, ApiTokenScope::ReadLevels).await {
+        return err;
+    }
+
+    let mut connection = match data.acquire_read_connection().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // Unpublished levels are listed only when browsing levels of a specific user.
+    let include_unpublished = include_unpublished && user_filter.is_some();
+    let (author_id, builder_id) = match user_filter {
+        Some(GetLevelsUserFilter::AuthorId(author_id)) => (Some(author_id), None),
+        Some(GetLevelsUserFilter::BuilderId(builder_id)) => (None, Some(builder_id)),
+        None => (None, None),
+    };
+
+    struct LevelSummaryDto {
+        id: i64,
+        title: String,
+        user_id: i64,
+        user_name: Option<String>,
+        parent_id: Option<i64>,
+        created_at: chrono::NaiveDateTime,
+        updated_at: chrono::NaiveDateTime,
+        builder_names: Vec<String>,
+        play_count: i64,
+        rating: Option<f64>,
+        rating_count: i64,
+        localizations: serde_json::Value,
+    }
+
+    // We fetch an extra row to find out whether there's a next page.
+    let levels = sqlx::query_as!(
+        LevelSummaryDto,
+        r#"
+SELECT l.id AS "id!", l.title AS "title!", u.id AS "user_id!", u.display_name AS user_name, l.parent_id, l.created_at AS "created_at!", l.updated_at AS "updated_at!",
+    COALESCE(builders.names, '{}') AS "builder_names!", plays.count AS "play_count!", ratings.average AS rating, ratings.count AS "rating_count!",
+    l.localizations AS "localizations!"
+FROM levels AS l
+JOIN users AS u ON u.id = l.user_id
+LEFT JOIN LATERAL (
+    SELECT array_agg(b.display_name ORDER BY b.display_name) AS names
+    FROM level_permissions AS lp
+    JOIN users AS b ON b.id = lp.user_id
+    WHERE lp.level_id = l.id AND b.display_name IS NOT NULL
+) AS builders ON TRUE
+LEFT JOIN LATERAL (
+    SELECT count(*) AS count FROM level_plays AS lpl WHERE lpl.level_id = l.id
+) AS plays ON TRUE
+LEFT JOIN LATERAL (
+    SELECT avg(lr.rating)::float8 AS average, count(*) AS count FROM level_ratings AS lr WHERE lr.level_id = l.id
+) AS ratings ON TRUE
+WHERE l.is_autosaved = FALSE
+    AND ($1::bigint IS NULL OR l.user_id = $1)
+    AND ($2::bigint IS NULL OR EXISTS (SELECT 1 FROM level_permissions AS lp WHERE lp.level_id = l.id AND lp.user_id = $2))
+    AND ($3::timestamp IS NULL OR (l.updated_at, l.id) < ($3::timestamp, $4::bigint))
+    AND (l.published OR $5)
+ORDER BY l.updated_at DESC, l.id DESC
+LIMIT $6
+        "#,
+        author_id,
+        builder_id,
+        cursor.as_ref().map(|cursor| cursor.after_updated_at),
+        cursor.as_ref().map(|cursor| cursor.after_id),
+        include_unpublished,
+        limit + 1,
+    )
+    .fetch_all(&mut connection)
+    .await;
+
+    let mut levels = match levels {
+        Ok(levels) => levels,
+        Err(err) => {
+            log::error!("Failed to get levels summary: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let next_cursor = if levels.len() as i64 > limit {
+        levels.truncate(limit as usize);
+        levels.last().map(|level| LevelsCursor {
+            after_updated_at: level.updated_at,
+            after_id: level.id,
+        })
+    } else {
+        None
+    };
+
+    HttpResponse::Ok().json(GetLevelsSummaryResponse {
+        levels: levels
+            .into_iter()
+            .map(|level| LevelSummary {
+                level: LevelsListItem {
+                    id: level.id,
+                    title: level.title,
+                    user_id: level.user_id,
+                    user_name: level.user_name,
+                    parent_id: level.parent_id,
+                    created_at: level.created_at,
+                    updated_at: level.updated_at,
+                },
+                builder_names: level.builder_names,
+                play_count: level.play_count,
+                rating: level.rating,
+                rating_count: level.rating_count,
+                localizations: parse_level_localizations(level.id, level.localizations),
+            })
+            .collect(),
+        next_cursor,
+    })
+}
+
+/// Game servers load levels right after they get saved, so unlike the other
+/// level endpoints, this one always reads from the primary, as a replica may
+/// lag behind.
 #[get("/levels/{id}")]
-pub async fn get_level(data: web::Data<Data>, level_id: web::Path<i64>) -> HttpResponse {
+pub async fn get_level(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    level_id: web::Path<i64>,
+) -> HttpResponse {
     let id = level_id.into_inner();
     let mut connection = match data.pool.acquire().await {
         Ok(c) => c,
@@ -559,10 +731,30 @@ pub async fn get_level(data: web::Data<Data>, level_id: web::Path<i64>) -> HttpR
         }
     };
 
+    if req.headers().contains_key(header::AUTHORIZATION) {
+        if let Err(err) =
+            authorize_scoped(&data, &req, &mut connection, ApiTokenScope::ReadLevels).await
+        {
+            return err;
+        }
+    }
+
+    struct LevelRow {
+        id: i64,
+        title: String,
+        data: serde_json::Value,
+        user_id: i64,
+        user_name: Option<String>,
+        parent_id: Option<i64>,
+        created_at: chrono::NaiveDateTime,
+        updated_at: chrono::NaiveDateTime,
+        localizations: serde_json::Value,
+    }
+
     let level = sqlx::query_as!(
-        LevelDto,
+        LevelRow,
         r#"
-SELECT l.id, l.title, l.data, u.id AS user_id, u.display_name AS user_name, l.parent_id, l.created_at, l.updated_at
+SELECT l.id, l.title, l.data, u.id AS user_id, u.display_name AS user_name, l.parent_id, l.created_at, l.updated_at, l.localizations
 FROM levels AS l
 JOIN users AS u ON u.id = l.user_id
 WHERE l.id = $1 AND l.is_autosaved = FALSE
@@ -573,7 +765,17 @@ WHERE l.id = $1 AND l.is_autosaved = FALSE
         .await;
 
     let level = match level {
-        Ok(level) => level,
+        Ok(level) => LevelDto {
+            id: level.id,
+            title: level.title,
+            data: level.data,
+            user_id: level.user_id,
+            user_name: level.user_name,
+            parent_id: level.parent_id,
+            created_at: level.created_at,
+            updated_at: level.updated_at,
+            localizations: parse_level_localizations(level.id, level.localizations),
+        },
         Err(sqlx::Error::RowNotFound) => {
             return HttpResponse::NotFound().json(ErrorResponse::<()> {
                 message: "Level doesn't exist".to_owned(),
@@ -632,6 +834,104 @@ WHERE level_id = $1"#,
     }
 }
 
+/// Lets level authors (or their API tokens with the `write_levels` scope)
+/// rename and (un)publish their levels. Level data is edited only in game.
+#[patch("/levels/{id}")]
+pub async fn patch_own_level(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    level_id: web::Path<i64>,
+    body: web::Json<PatchOwnLevelRequest>,
+) -> HttpResponse {
+    let id = level_id.into_inner();
+    let PatchOwnLevelRequest { title, published } = body.into_inner();
+    let title = title.map(|title| sanitize_text(&title, LEVEL_TITLE_MAX_LEN));
+    let title = match title.as_deref().map(validate_level_title).transpose() {
+        Ok(title) => title,
+        Err(errors) => {
+            return HttpResponse::BadRequest().json(ErrorResponse::<()> {
+                message: format_errors("Level title", &errors),
+                error_kind: ErrorKind::BadRequest,
+            });
+        }
+    };
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id =
+        match authorize_scoped(&data, &req, &mut connection, ApiTokenScope::WriteLevels).await {
+            Ok(user_id) => user_id,
+            Err(err) => {
+                return err;
+            }
+        };
+
+    struct AuthorId {
+        user_id: i64,
+    }
+    let author = sqlx::query_as!(
+        AuthorId,
+        "SELECT user_id FROM levels WHERE id = $1 AND is_autosaved = FALSE",
+        id,
+    )
+    .fetch_optional(&mut connection)
+    .await;
+    match author {
+        Ok(Some(AuthorId { user_id: author_id })) if author_id == user_id => {}
+        Ok(Some(_)) => {
+            return HttpResponse::Forbidden().json(ErrorResponse::<()> {
+                message: "Only the author can update a level".to_owned(),
+                error_kind: ErrorKind::Forbidden,
+            });
+        }
+        Ok(None) => {
+            return HttpResponse::NotFound().json(ErrorResponse::<()> {
+                message: "Level doesn't exist".to_owned(),
+                error_kind: ErrorKind::NotFound,
+            });
+        }
+        Err(err) => {
+            log::error!("Failed to get a level: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let result = sqlx::query!(
+        "UPDATE levels SET title = COALESCE($1, title), published = COALESCE($2, published) WHERE id = $3",
+        title,
+        published,
+        id,
+    )
+    .execute(&mut connection)
+    .await;
+    match result {
+        Ok(_) => HttpResponse::Ok().json(()),
+        Err(err) => {
+            log::error!("Failed to update a level: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Localizations are validated before being stored, so a level is still
+/// listed (just untranslated) if they can't be parsed.
+fn parse_level_localizations(level_id: i64, value: serde_json::Value) -> LevelLocalizations {
+    serde_json::from_value(value).unwrap_or_else(|err| {
+        log::error!(
+            "Failed to parse localizations of level {}: {:?}",
+            level_id,
+            err
+        );
+        LevelLocalizations::default()
+    })
+}
+
 async fn query_levels_by_author(
     connection: &mut sqlx::PgConnection,
     author_id: Option<i64>,
@@ -676,3 +976,249 @@ LIMIT $2 OFFSET $3
         .fetch_all(connection)
         .await
 }
+
+/// Decodes the bearer token (which must be an OIDC JWT) and returns the id of
+/// the user it belongs to.
+async fn authorize_user(
+    data: &Data,
+    req: &HttpRequest,
+    connection: &mut sqlx::PgConnection,
+) -> Result<i64, HttpResponse> {
+    let jwt = bearer_token(req)?;
+    if jwt.starts_with(API_TOKEN_PREFIX) {
+        return Err(HttpResponse::Forbidden().json(ErrorResponse::<()> {
+            message: "API tokens can't be used for this route".to_owned(),
+            error_kind: ErrorKind::Forbidden,
+        }));
+    }
+
+    let decoded_token = crate::decode_token_helper(data, &jwt, "bearer").await?;
+
+    struct UserId {
+        user_id: i64,
+    }
+    let user = sqlx::query_as!(
+        UserId,
+        r#"SELECT user_id AS "user_id!" FROM openids WHERE issuer = $1 AND subject = $2"#,
+        decoded_token.claims().custom.iss,
+        decoded_token.claims().custom.sub,
+    )
+    .fetch_optional(connection)
+    .await;
+
+    match user {
+        Ok(Some(UserId { user_id })) => Ok(user_id),
+        Ok(None) => Err(HttpResponse::Forbidden().json(ErrorResponse::<()> {
+            message: "User isn't registered".to_owned(),
+            error_kind: ErrorKind::Forbidden,
+        })),
+        Err(err) => {
+            log::error!("Failed to get a user: {:?}", err);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
+
+/// Accepts either an OIDC JWT, which grants every scope, or a personal API
+/// token that has the scope. Returns the id of the user the token belongs to.
+async fn authorize_scoped(
+    data: &Data,
+    req: &HttpRequest,
+    connection: &mut sqlx::PgConnection,
+    scope: ApiTokenScope,
+) -> Result<i64, HttpResponse> {
+    let token = bearer_token(req)?;
+    if !token.starts_with(API_TOKEN_PREFIX) {
+        return authorize_user(data, req, connection).await;
+    }
+
+    struct ApiTokenDto {
+        id: i64,
+        user_id: i64,
+        read_levels: bool,
+        write_levels: bool,
+        read_leaderboards: bool,
+    }
+    let api_token = sqlx::query_as!(
+        ApiTokenDto,
+        "SELECT id, user_id, read_levels, write_levels, read_leaderboards FROM api_tokens WHERE token_hash = $1",
+        hash_api_token(&token),
+    )
+    .fetch_optional(&mut *connection)
+    .await;
+    let api_token = match api_token {
+        Ok(Some(api_token)) => api_token,
+        Ok(None) => {
+            return Err(HttpResponse::Unauthorized().json(ErrorResponse::<()> {
+                message: "Invalid or revoked API token".to_owned(),
+                error_kind: ErrorKind::Unauthorized,
+            }));
+        }
+        Err(err) => {
+            log::error!("Failed to get an API token: {:?}", err);
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    let scopes = ApiTokenScopes {
+        read_levels: api_token.read_levels,
+        write_levels: api_token.write_levels,
+        read_leaderboards: api_token.read_leaderboards,
+    };
+    check_api_token_scope(&scopes, scope)?;
+
+    // Bots can send lots of requests, there's no need to update the row every time.
+    if let Err(err) = sqlx::query!(
+        "UPDATE api_tokens SET last_used_at = now() WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')",
+        api_token.id,
+    )
+    .execute(connection)
+    .await
+    {
+        log::error!("Failed to update API token usage: {:?}", err);
+    }
+
+    Ok(api_token.user_id)
+}
+
+fn check_api_token_scope(
+    scopes: &ApiTokenScopes,
+    scope: ApiTokenScope,
+) -> Result<(), HttpResponse> {
+    if scopes.allows(scope) {
+        Ok(())
+    } else {
+        Err(HttpResponse::Forbidden().json(ErrorResponse::<()> {
+            message: format!("API token doesn't have the `{}` scope", scope.as_str()),
+            error_kind: ErrorKind::Forbidden,
+        }))
+    }
+}
+
+/// Read routes don't require authorization, but if a token is sent, it's
+/// checked anyway, so that revoked tokens (or tokens without the scope) get
+/// rejected. Tokens are checked against the primary, as read routes use
+/// replicas, which can lag behind.
+async fn authorize_optional(
+    data: &Data,
+    req: &HttpRequest,
+    scope: ApiTokenScope,
+) -> Result<Option<i64>, HttpResponse> {
+    if !req.headers().contains_key(header::AUTHORIZATION) {
+        return Ok(None);
+    }
+    // Malformed headers are rejected without taking a connection from the pool.
+    bearer_token(req)?;
+
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+    };
+    authorize_scoped(data, req, &mut connection, scope)
+        .await
+        .map(Some)
+}
+
+fn bearer_token(req: &HttpRequest) -> Result<String, HttpResponse> {
+    let mut authorization = req.headers().get_all(header::AUTHORIZATION);
+    match Authorization::<Bearer>::decode(&mut authorization) {
+        Ok(header_value) => Ok(header_value.0.token().to_owned()),
+        Err(_) => Err(HttpResponse::Unauthorized().json(ErrorResponse::<()> {
+            message: "Unauthorized".to_owned(),
+            error_kind: ErrorKind::Unauthorized,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pools::ReadReplica, presence::PresenceStore, Config};
+    use actix_web::{http::StatusCode, test::TestRequest};
+    use mr_utils_lib::jwks::Jwks;
+    use sqlx::postgres::PgPoolOptions;
+
+    /// There is no database, so a test that acquires a connection fails.
+    fn data() -> Data {
+        Data {
+            pool: PgPoolOptions::new()
+                .connect_lazy("postgres://localhost/mr_persistence_test")
+                .unwrap(),
+            read_replica: ReadReplica::connect_lazy(None).unwrap(),
+            jwks: Jwks::default(),
+            presence: PresenceStore::default(),
+            config: Config {
+                google_certs_url: "https://localhost/google".parse().unwrap(),
+                auth0_certs_url: "https://localhost/auth0".parse().unwrap(),
+                google_web_client_id: String::new(),
+                google_desktop_client_id: String::new(),
+                auth0_client_id: String::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_api_token_scope() {
+        let scopes = ApiTokenScopes {
+            read_levels: true,
+            write_levels: false,
+            read_leaderboards: true,
+        };
+        assert!(check_api_token_scope(&scopes, ApiTokenScope::ReadLevels).is_ok());
+        assert!(check_api_token_scope(&scopes, ApiTokenScope::ReadLeaderboards).is_ok());
+        let err = check_api_token_scope(&scopes, ApiTokenScope::WriteLevels).unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+
+        let no_scopes = ApiTokenScopes {
+            read_levels: false,
+            write_levels: false,
+            read_leaderboards: false,
+        };
+        for scope in [
+            ApiTokenScope::ReadLevels,
+            ApiTokenScope::WriteLevels,
+            ApiTokenScope::ReadLeaderboards,
+        ] {
+            assert!(check_api_token_scope(&no_scopes, scope).is_err());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_optional_without_token() {
+        let req = TestRequest::default().to_http_request();
+        let user_id = authorize_optional(&data(), &req, ApiTokenScope::ReadLevels).await;
+        assert_eq!(user_id.ok(), Some(None));
+    }
+
+    #[actix_web::test]
+    async fn test_authorize_optional_malformed_header() {
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Basic dXNlcjpwYXNz"))
+            .to_http_request();
+        let err = authorize_optional(&data(), &req, ApiTokenScope::ReadLevels)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let req = TestRequest::default()
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Bearer {API_TOKEN_PREFIX}abc"),
+            ))
+            .to_http_request();
+        assert_eq!(
+            bearer_token(&req).ok(),
+            Some(format!("{API_TOKEN_PREFIX}abc"))
+        );
+
+        let req = TestRequest::default().to_http_request();
+        let err = bearer_token(&req).unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+    }
+}
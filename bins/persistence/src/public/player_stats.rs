@@ -0,0 +1,49 @@
+use super::authorize_optional;
+use crate::Data;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use mr_messages_lib::{ApiTokenScope, ErrorKind, ErrorResponse, PlayerStats};
+
+/// Users who haven't played yet get zeroes.
+#[get("/users/{id}/stats")]
+pub async fn get_player_stats(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    user_id: web::Path<i64>,
+) -> HttpResponse {
+    if let Err(err) = authorize_optional(&data, &req, ApiTokenScope::ReadLeaderboards).await {
+        return err;
+    }
+
+    let mut connection = match data.acquire_read_connection().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let player_stats = sqlx::query_as!(
+        PlayerStats,
+        r#"
+SELECT COALESCE(s.finishes, 0) AS "finishes!", COALESCE(s.deaths, 0) AS "deaths!"
+FROM users u
+LEFT JOIN player_stats s ON s.user_id = u.id
+WHERE u.id = $1
+        "#,
+        user_id.into_inner(),
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match player_stats {
+        Ok(player_stats) => HttpResponse::Ok().json(player_stats),
+        Err(sqlx::Error::RowNotFound) => HttpResponse::NotFound().json(ErrorResponse::<()> {
+            message: "User doesn't exist".to_owned(),
+            error_kind: ErrorKind::NotFound,
+        }),
+        Err(err) => {
+            log::error!("Failed to get player stats: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use super::authorize_user;
+use crate::Data;
+use actix_web::{get, put, web, HttpRequest, HttpResponse};
+use mr_messages_lib::PrivacySettings;
+
+#[get("/privacy")]
+pub async fn get_privacy_settings(data: web::Data<Data>, req: HttpRequest) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let privacy_settings = sqlx::query_as!(
+        PrivacySettings,
+        "SELECT allow_session_recording, allow_analytics FROM users WHERE id = $1",
+        user_id,
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match privacy_settings {
+        Ok(privacy_settings) => HttpResponse::Ok().json(privacy_settings),
+        Err(err) => {
+            log::error!("Failed to get privacy settings: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[put("/privacy")]
+pub async fn put_privacy_settings(
+    data: web::Data<Data>,
+    req: HttpRequest,
+    body: web::Json<PrivacySettings>,
+) -> HttpResponse {
+    let mut connection = match data.pool.acquire().await {
+        Ok(c) => c,
+        Err(err) => {
+            log::error!("Failed to acquire a connection: {:?}", err);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let user_id = match authorize_user(&data, &req, &mut connection).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            return err;
+        }
+    };
+
+    let PrivacySettings {
+        allow_session_recording,
+        allow_analytics,
+    } = body.into_inner();
+    log::info!(
+        "Updating privacy settings of user {} (session recording: {}, analytics: {})",
+        user_id,
+        allow_session_recording,
+        allow_analytics
+    );
+
+    let privacy_settings = sqlx::query_as!(
+        PrivacySettings,
+        r#"
+UPDATE users
+SET allow_session_recording = $1, allow_analytics = $2, privacy_updated_at = now()
+WHERE id = $3
+RETURNING allow_session_recording, allow_analytics
+        "#,
+        allow_session_recording,
+        allow_analytics,
+        user_id,
+    )
+    .fetch_one(&mut connection)
+    .await;
+
+    match privacy_settings {
+        Ok(privacy_settings) => HttpResponse::Ok().json(privacy_settings),
+        Err(err) => {
+            log::error!("Failed to update privacy settings: {:?}", err);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use mr_utils_lib::synthetic_auth::{synthetic_email, synthetic_subject, SYNTHETIC_ISSUER};
+use sqlx::Connection;
+
+pub const DEFAULT_SYNTHETIC_USERS_COUNT: u32 = 16;
+
+/// Makes sure that synthetic users with indexes `0..count` exist, so that
+/// scenarios can use their tokens right away. Users that already exist are
+/// left untouched.
+pub async fn seed_synthetic_users(pool: &sqlx::PgPool, count: u32) -> anyhow::Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut seeded = 0;
+    for index in 0..count {
+        let subject = synthetic_subject(index);
+        let exists = sqlx::query!(
+            r#"SELECT user_id AS "user_id!" FROM openids WHERE issuer = $1 AND subject = $2"#,
+            SYNTHETIC_ISSUER,
+            subject,
+        )
+        .fetch_optional(&mut connection)
+        .await?
+        .is_some();
+        if exists {
+            continue;
+        }
+
+        let email = synthetic_email(index);
+        let mut tx = connection.begin().await?;
+        let user = sqlx::query!(
+            "INSERT INTO users (email, display_name) VALUES ($1, $2) RETURNING id",
+            email,
+            format!("qa_{index}"),
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO openids (user_id, issuer, subject, email) VALUES ($1, $2, $3, $4)",
+            user.id,
+            SYNTHETIC_ISSUER,
+            subject,
+            email,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        seeded += 1;
+    }
+    log::info!("Seeded {seeded} synthetic users ({count} in total)");
+    Ok(())
+}
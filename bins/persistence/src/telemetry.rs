@@ -0,0 +1,38 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use futures::Future;
+use mr_utils_lib::telemetry::{TraceSpan, TRACE_PARENT_HEADER};
+
+/// Wraps every request into a span, continuing the trace of the caller (the
+/// matchmaker or a game server) if it sends the `traceparent` header.
+pub fn trace_request<S, B>(
+    req: ServiceRequest,
+    service: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let trace_parent = req
+        .headers()
+        .get(TRACE_PARENT_HEADER)
+        .and_then(|value| value.to_str().ok());
+    // Route patterns keep the number of distinct span names low (`/levels/{id}`
+    // instead of every level id).
+    let route = req.match_pattern().unwrap_or_else(|| req.path().to_owned());
+    let span = TraceSpan::start(format!("{} {}", req.method(), route), trace_parent);
+
+    let response = service.call(req);
+    async move {
+        let result = response.await;
+        match &result {
+            Ok(response) => {
+                let status = response.status();
+                span.set_attribute("http.status_code", status.as_u16().to_string());
+                if status.is_server_error() {
+                    span.set_error(status.to_string());
+                }
+            }
+            Err(err) => span.set_error(err.to_string()),
+        }
+        result
+    }
+}
@@ -0,0 +1,5 @@
+use mr_build_dotenv::load_env;
+
+fn main() {
+    load_env();
+}
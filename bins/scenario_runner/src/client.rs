@@ -0,0 +1,425 @@
+use crate::scenario::Step;
+use bevy::{
+    app::{AppExit, ScheduleRunnerSettings},
+    prelude::*,
+};
+use bevy_disturbulence::{ConnectionHandle, NetworkEvent, NetworkResource, NetworkingPlugin};
+use mr_shared_lib::{
+    constants::ConstantsFingerprint,
+    framebuffer::FrameNumber,
+    messages::{
+        Message, PlayerInputs, PlayerNetId, PlayerUpdate, ReliableClientMessage,
+        ReliableServerMessage, RespawnPlayerReason, RunnerInput, SpawnLevelObjectRequest,
+        SpawnLevelObjectRequestBody, UnreliableClientMessage, UnreliableServerMessage,
+    },
+    net::{
+        network_setup_system, ConnectionState, ConnectionStatus, MessageId, SessionId,
+        CONNECTION_TIMEOUT_MILLIS,
+    },
+    player::PlayerRole,
+    SIMULATIONS_PER_SECOND, TICKS_PER_NETWORK_BROADCAST,
+};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How many frames back an input is sent to make the server rewind. It's kept
+/// below the lag compensation limit, so that the server doesn't discard it.
+const REWIND_FRAMES: u16 = 10;
+
+type ClientOutcome = Arc<Mutex<Option<Result<(), String>>>>;
+
+/// Runs a headless game client until it executes all the steps or fails.
+/// Blocks the current thread.
+pub fn run_client(
+    user: u32,
+    server_addr: SocketAddr,
+    id_token: String,
+    steps: Vec<Step>,
+) -> Result<(), String> {
+    let outcome = ClientOutcome::default();
+    App::new()
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f32(
+            1.0 / SIMULATIONS_PER_SECOND,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(NetworkingPlugin::default())
+        .add_startup_system(network_setup_system)
+        .insert_resource(ScriptedClient {
+            user,
+            server_addr,
+            id_token,
+            steps: steps.into(),
+            step_ticks: 0,
+            connection_state: ConnectionState::default(),
+            net_id: None,
+            frame_number: None,
+            role: PlayerRole::Runner,
+            direction: Vec2::ZERO,
+            has_finished: false,
+            correlation_id: MessageId::new(0),
+            outcome: outcome.clone(),
+        })
+        .add_system(drive_scripted_client_system)
+        .run();
+
+    let result = outcome.lock().unwrap().take();
+    result.unwrap_or_else(|| Err("the client exited without a result".to_owned()))
+}
+
+#[derive(Resource)]
+struct ScriptedClient {
+    user: u32,
+    server_addr: SocketAddr,
+    id_token: String,
+    steps: VecDeque<Step>,
+    /// Ticks spent executing the current step.
+    step_ticks: u32,
+    connection_state: ConnectionState,
+    net_id: Option<PlayerNetId>,
+    /// The latest frame received from the server, advanced every tick.
+    frame_number: Option<FrameNumber>,
+    role: PlayerRole,
+    direction: Vec2,
+    /// Is set when the server respawns the player after reaching a finish.
+    has_finished: bool,
+    correlation_id: MessageId,
+    outcome: ClientOutcome,
+}
+
+enum StepStatus {
+    InProgress,
+    Done,
+    Exit,
+}
+
+fn drive_scripted_client_system(
+    mut net: NonSendMut<NetworkResource>,
+    mut network_events: EventReader<NetworkEvent>,
+    mut client: ResMut<ScriptedClient>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if let Some(frame_number) = client.frame_number.as_mut() {
+        frame_number.increment();
+    }
+
+    let result = client
+        .process_network(&mut net, network_events.iter())
+        .and_then(|()| client.execute_step(&mut net));
+    let result = match result {
+        Ok(StepStatus::InProgress) => return,
+        Ok(StepStatus::Done) => {
+            client.steps.pop_front();
+            client.step_ticks = 0;
+            if !client.steps.is_empty() {
+                return;
+            }
+            Ok(())
+        }
+        Ok(StepStatus::Exit) => Ok(()),
+        Err(err) => Err(err),
+    };
+
+    match &result {
+        Ok(()) => log::info!("Client (user {}) has executed all the steps", client.user),
+        Err(err) => log::error!("Client (user {}) has failed: {}", client.user, err),
+    }
+    *client.outcome.lock().unwrap() = Some(result);
+    exit.send(AppExit);
+}
+
+impl ScriptedClient {
+    fn process_network<'a>(
+        &mut self,
+        net: &mut NetworkResource,
+        network_events: impl Iterator<Item = &'a NetworkEvent>,
+    ) -> Result<(), String> {
+        for event in network_events {
+            match event {
+                NetworkEvent::Connected(_) => {
+                    self.connection_state
+                        .set_status(ConnectionStatus::Initialized);
+                    self.send_reliable(net, ReliableClientMessage::Initialize)?;
+                }
+                NetworkEvent::Disconnected(_) => {
+                    return Err("lost the connection to the server".to_owned());
+                }
+                NetworkEvent::Error(handle, err) => {
+                    log::warn!("Network error ({}): {:?}", handle, err);
+                }
+                _ => {}
+            }
+        }
+
+        let mut connect_message_to_send = None;
+        let mut send_handshake = false;
+        for connection in net.connections.values_mut() {
+            let channels = connection.channels().unwrap();
+
+            while let Some(Message { message, .. }) =
+                channels.recv::<Message<UnreliableServerMessage>>()
+            {
+                match message {
+                    UnreliableServerMessage::Handshake(message_id) => {
+                        if matches!(self.connection_state.status(), ConnectionStatus::Connecting)
+                            && message_id == self.connection_state.handshake_id - MessageId::new(1)
+                        {
+                            self.connection_state
+                                .set_status(ConnectionStatus::Handshaking);
+                            send_handshake = true;
+                        }
+                    }
+                    UnreliableServerMessage::DeltaUpdate(update) => {
+                        if let Err(err) = self
+                            .connection_state
+                            .acknowledge_incoming(update.frame_number)
+                        {
+                            log::warn!("Failed to acknowledge an update: {:?}", err);
+                        }
+                        if self
+                            .frame_number
+                            .map_or(true, |frame_number| frame_number < update.frame_number)
+                        {
+                            self.frame_number = Some(update.frame_number);
+                        }
+                    }
+                    UnreliableServerMessage::Ping(_) => {}
+                }
+            }
+
+            while let Some(Message {
+                message,
+                session_id,
+            }) = channels.recv::<Message<ReliableServerMessage>>()
+            {
+                match message {
+                    ReliableServerMessage::Initialize => {
+                        if !matches!(
+                            self.connection_state.status(),
+                            ConnectionStatus::Initialized
+                        ) {
+                            continue;
+                        }
+                        connect_message_to_send = Some(Message {
+                            session_id: SessionId::new(0),
+                            message: UnreliableClientMessage::Connect(
+                                self.connection_state.handshake_id,
+                            ),
+                        });
+                        self.connection_state.handshake_id += MessageId::new(1);
+                        self.connection_state
+                            .set_status(ConnectionStatus::Connecting);
+                    }
+                    ReliableServerMessage::StartGame(start_game) => {
+                        if start_game.handshake_id
+                            != self.connection_state.handshake_id - MessageId::new(1)
+                        {
+                            continue;
+                        }
+                        log::info!(
+                            "Client (user {}) has joined as {}",
+                            self.user,
+                            start_game.nickname
+                        );
+                        self.connection_state.session_id = session_id;
+                        self.connection_state
+                            .set_status(ConnectionStatus::Connected);
+                        self.net_id = Some(start_game.net_id);
+                        self.frame_number = Some(start_game.game_state.frame_number);
+                    }
+                    ReliableServerMessage::RespawnPlayer(respawn_player) => {
+                        if Some(respawn_player.net_id) == self.net_id
+                            && respawn_player.reason == RespawnPlayerReason::Finish
+                        {
+                            self.has_finished = true;
+                        }
+                    }
+                    ReliableServerMessage::Disconnect(reason) => {
+                        return Err(format!("disconnected by the server: {:?}", reason));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(message) = connect_message_to_send {
+            let handle = self.connection_handle(net)?;
+            net.send_message(handle, message)
+                .map_err(|err| format!("failed to send a Connect message: {:?}", err))?;
+        }
+        if send_handshake {
+            self.send_reliable(
+                net,
+                ReliableClientMessage::Handshake {
+                    message_id: self.connection_state.handshake_id - MessageId::new(1),
+                    id_token: Some(self.id_token.clone()),
+                    guest_id: None,
+                    guest_nickname: None,
+                    constants: ConstantsFingerprint::current(),
+                },
+            )?;
+        }
+
+        let is_broadcast_frame = self.frame_number.map_or(false, |frame_number| {
+            frame_number.value() % TICKS_PER_NETWORK_BROADCAST == 0
+        });
+        if is_broadcast_frame {
+            self.send_player_update(net, 0)?;
+        }
+
+        Ok(())
+    }
+
+    fn execute_step(&mut self, net: &mut NetworkResource) -> Result<StepStatus, String> {
+        let Some(step) = self.steps.front().cloned() else {
+            return Ok(StepStatus::Exit);
+        };
+        self.step_ticks += 1;
+
+        let status = match step {
+            Step::Join => {
+                if self.step_ticks == 1 {
+                    log::info!(
+                        "Client (user {}) is connecting to {}",
+                        self.user,
+                        self.server_addr
+                    );
+                    net.connect(&format!("http://{}", self.server_addr));
+                }
+                if self.is_connected() {
+                    StepStatus::Done
+                } else if self.step_ticks as f32
+                    > CONNECTION_TIMEOUT_MILLIS as f32 / 1000.0 * SIMULATIONS_PER_SECOND
+                {
+                    return Err("timed out connecting to the server".to_owned());
+                } else {
+                    StepStatus::InProgress
+                }
+            }
+            Step::Wait { frames } => {
+                if self.step_ticks >= frames {
+                    StepStatus::Done
+                } else {
+                    StepStatus::InProgress
+                }
+            }
+            Step::SwitchRole(role) => {
+                self.send_reliable(net, ReliableClientMessage::SwitchRole(role))?;
+                self.role = role;
+                StepStatus::Done
+            }
+            Step::SpawnLevelObject(desc) => {
+                let correlation_id = self.correlation_id.increment();
+                self.send_reliable(
+                    net,
+                    ReliableClientMessage::SpawnLevelObject(SpawnLevelObjectRequest {
+                        correlation_id,
+                        body: SpawnLevelObjectRequestBody::New(desc),
+                    }),
+                )?;
+                StepStatus::Done
+            }
+            Step::Run { direction, frames } => {
+                self.direction = direction;
+                if self.step_ticks >= frames {
+                    self.direction = Vec2::ZERO;
+                    StepStatus::Done
+                } else {
+                    StepStatus::InProgress
+                }
+            }
+            Step::ExpectFinish { timeout_frames } => {
+                if std::mem::take(&mut self.has_finished) {
+                    StepStatus::Done
+                } else if self.step_ticks >= timeout_frames {
+                    return Err(format!(
+                        "the player didn't finish in {} frames",
+                        timeout_frames
+                    ));
+                } else {
+                    StepStatus::InProgress
+                }
+            }
+            Step::DisconnectMidRewind => {
+                self.send_player_update(net, REWIND_FRAMES)?;
+                // Exiting right away drops the socket, the server is expected to
+                // time the player out while the rewind is still being processed.
+                StepStatus::Exit
+            }
+            Step::Disconnect => StepStatus::Exit,
+        };
+        Ok(status)
+    }
+
+    fn is_connected(&self) -> bool {
+        matches!(self.connection_state.status(), ConnectionStatus::Connected)
+    }
+
+    /// Sends the current input, `frames_back` allows sending it for an already
+    /// simulated frame.
+    fn send_player_update(
+        &mut self,
+        net: &mut NetworkResource,
+        frames_back: u16,
+    ) -> Result<(), String> {
+        let Some(frame_number) = self.frame_number.filter(|_| self.is_connected()) else {
+            return Ok(());
+        };
+
+        let inputs = match self.role {
+            PlayerRole::Runner => PlayerInputs::Runner {
+                inputs: vec![RunnerInput {
+                    frame_number: frame_number - FrameNumber::new(frames_back),
+                    direction: self.direction,
+                }],
+            },
+            PlayerRole::Builder => PlayerInputs::Builder {
+                camera_position: Vec2::ZERO,
+                selected_object: None,
+            },
+            PlayerRole::Spectator => PlayerInputs::Spectator,
+        };
+        let handle = self.connection_handle(net)?;
+        net.send_message(
+            handle,
+            Message {
+                session_id: self.connection_state.session_id,
+                message: UnreliableClientMessage::PlayerUpdate(PlayerUpdate {
+                    frame_number,
+                    acknowledgments: self.connection_state.incoming_acknowledgments(),
+                    inputs,
+                }),
+            },
+        )
+        .map_err(|err| format!("failed to send a PlayerUpdate message: {:?}", err))?;
+        Ok(())
+    }
+
+    fn send_reliable(
+        &self,
+        net: &mut NetworkResource,
+        message: ReliableClientMessage,
+    ) -> Result<(), String> {
+        let handle = self.connection_handle(net)?;
+        net.send_message(
+            handle,
+            Message {
+                session_id: self.connection_state.session_id,
+                message,
+            },
+        )
+        .map_err(|err| format!("failed to send a reliable message: {:?}", err))?;
+        Ok(())
+    }
+
+    fn connection_handle(&self, net: &NetworkResource) -> Result<ConnectionHandle, String> {
+        net.connections
+            .keys()
+            .next()
+            .copied()
+            .ok_or_else(|| "there's no connection to the server".to_owned())
+    }
+}
@@ -0,0 +1,98 @@
+mod client;
+mod matchmaker;
+mod scenario;
+
+use crate::scenario::Scenario;
+use mr_utils_lib::synthetic_auth::SyntheticAuth;
+use std::net::SocketAddr;
+
+fn main() {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(log::LevelFilter::Info).init();
+
+    let scenario_paths: Vec<String> = std::env::args().skip(1).collect();
+    if scenario_paths.is_empty() {
+        eprintln!("Usage: mr_scenario_runner <scenario.json>...");
+        std::process::exit(2);
+    }
+
+    let synthetic_auth = SyntheticAuth::from_env()
+        .expect("Expected the pentest mode to be enabled (see MUDDLE_PENTEST_MODE)");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create a tokio runtime");
+
+    let mut failed = 0;
+    for path in &scenario_paths {
+        let result = std::fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|scenario| Ok(serde_json::from_str::<Scenario>(&scenario)?))
+            .and_then(|scenario| run_scenario(&runtime, &synthetic_auth, scenario));
+        match result {
+            Ok(()) => log::info!("PASSED: {path}"),
+            Err(err) => {
+                log::error!("FAILED: {path}: {err:?}");
+                failed += 1;
+            }
+        }
+    }
+
+    log::info!(
+        "{} scenarios passed, {} failed",
+        scenario_paths.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_scenario(
+    runtime: &tokio::runtime::Runtime,
+    synthetic_auth: &SyntheticAuth,
+    scenario: Scenario,
+) -> anyhow::Result<()> {
+    scenario.validate().map_err(anyhow::Error::msg)?;
+    log::info!("Running scenario \"{}\"", scenario.name);
+
+    let token_ttl = chrono::Duration::hours(1);
+    let server_addr = match std::env::var("MUDDLE_SERVER_ADDR") {
+        // Useful for running scenarios against a local server, skipping the matchmaker.
+        Ok(addr) => addr.parse::<SocketAddr>()?,
+        Err(_) => {
+            let matchmaker_url = std::env::var("MUDDLE_MATCHMAKER_URL")
+                .map_err(|_| anyhow::anyhow!("Expected MUDDLE_MATCHMAKER_URL"))?;
+            let id_token = synthetic_auth.issue_token(scenario.clients[0].user, token_ttl)?;
+            runtime.block_on(matchmaker::create_server(
+                &matchmaker_url,
+                scenario.level.clone(),
+                id_token,
+            ))?
+        }
+    };
+
+    let mut handles = Vec::new();
+    for client in scenario.clients {
+        let id_token = synthetic_auth.issue_token(client.user, token_ttl)?;
+        let handle = std::thread::Builder::new()
+            .name(format!("client-{}", client.user))
+            .spawn(move || {
+                client::run_client(client.user, server_addr, id_token, client.steps)
+                    .map_err(|err| format!("client (user {}): {}", client.user, err))
+            })?;
+        handles.push(handle);
+    }
+
+    let errors: Vec<String> = handles
+        .into_iter()
+        .filter_map(|handle| match handle.join() {
+            Ok(result) => result.err(),
+            Err(_) => Some("a client thread has panicked".to_owned()),
+        })
+        .collect();
+    if !errors.is_empty() {
+        anyhow::bail!(errors.join("; "));
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use futures::{SinkExt, StreamExt};
+use mr_messages_lib::{
+    deserialize_binary, serialize_binary, AllocationKind, GameServerState, InitLevel,
+    MatchmakerMessage, MatchmakerRequest, PROTOCOL_VERSION,
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const ALLOCATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Requests a new server through the matchmaker, the same way the main menu
+/// does, and waits until it gets allocated.
+pub async fn create_server(
+    matchmaker_url: &str,
+    init_level: InitLevel,
+    id_token: String,
+) -> anyhow::Result<SocketAddr> {
+    let (mut ws_stream, _) = connect_async(matchmaker_url).await?;
+
+    let request_id = uuid::Uuid::new_v4();
+    let request = MatchmakerRequest::CreateServer {
+        init_level,
+        request_id,
+        id_token: Some(id_token),
+        protocol_version: PROTOCOL_VERSION,
+        kind: AllocationKind::Public,
+    };
+    log::info!("Sending an allocation request: {request_id}");
+    ws_stream
+        .send(Message::Binary(serialize_binary(&request)?))
+        .await?;
+
+    let wait_for_allocation = async {
+        while let Some(message) = ws_stream.next().await {
+            let Message::Binary(data) = message? else {
+                continue;
+            };
+            let servers = match deserialize_binary::<MatchmakerMessage>(&data)? {
+                MatchmakerMessage::Init { servers } => servers,
+                MatchmakerMessage::ServerUpdated(server) => vec![server],
+                MatchmakerMessage::ServerRemoved(_) | MatchmakerMessage::ServerList(_) => continue,
+                MatchmakerMessage::InvalidJwt(id) if id == request_id => {
+                    anyhow::bail!("The matchmaker rejected the synthetic token");
+                }
+                MatchmakerMessage::InvalidJwt(_) => continue,
+            };
+            if let Some(server) = servers.into_iter().find(|server| {
+                server.request_id == request_id && server.state == GameServerState::Allocated
+            }) {
+                log::info!("Server {} is allocated: {}", server.name, server.addr);
+                return Ok(server.addr);
+            }
+        }
+        anyhow::bail!("The matchmaker closed the connection before allocating a server")
+    };
+
+    tokio::time::timeout(ALLOCATION_TIMEOUT, wait_for_allocation)
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out waiting for a server allocation"))?
+}
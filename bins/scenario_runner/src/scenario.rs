@@ -0,0 +1,135 @@
+use bevy::math::Vec2;
+use mr_messages_lib::InitLevel;
+use mr_shared_lib::{game::level::LevelObjectDesc, player::PlayerRole};
+use serde::Deserialize;
+
+/// A scripted session: the first client creates a server via the matchmaker,
+/// then every client connects to it and executes its steps concurrently.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub level: InitLevel,
+    pub clients: Vec<ClientScript>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClientScript {
+    /// An index of a synthetic user (seeded by the persistence service).
+    pub user: u32,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub enum Step {
+    /// Connects to the server and waits for the game to start.
+    Join,
+    Wait {
+        frames: u32,
+    },
+    SwitchRole(PlayerRole),
+    SpawnLevelObject(LevelObjectDesc),
+    Run {
+        direction: Vec2,
+        frames: u32,
+    },
+    /// Fails the scenario if the player doesn't reach a finish in time.
+    ExpectFinish {
+        timeout_frames: u32,
+    },
+    /// Sends an input for an already simulated frame, which makes the server
+    /// rewind, and drops the connection without waiting for the result.
+    DisconnectMidRewind,
+    Disconnect,
+}
+
+impl Scenario {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.clients.is_empty() {
+            return Err("a scenario must have at least one client".to_owned());
+        }
+        for (i, client) in self.clients.iter().enumerate() {
+            let first_step = client
+                .steps
+                .iter()
+                .find(|step| !matches!(step, Step::Wait { .. }));
+            if first_step != Some(&Step::Join) {
+                return Err(format!(
+                    "client {i} must join before executing any other steps"
+                ));
+            }
+            if client
+                .steps
+                .iter()
+                .filter(|step| **step == Step::Join)
+                .count()
+                > 1
+            {
+                return Err(format!("client {i} can't join more than once"));
+            }
+            let disconnect_position = client
+                .steps
+                .iter()
+                .position(|step| matches!(step, Step::Disconnect | Step::DisconnectMidRewind));
+            if disconnect_position.map_or(false, |position| position + 1 != client.steps.len()) {
+                return Err(format!(
+                    "client {i} can't execute steps after disconnecting"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize_and_validate() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "name": "join and disconnect",
+                "level": { "Create": { "title": "QA", "parent_id": null } },
+                "clients": [
+                    {
+                        "user": 0,
+                        "steps": [
+                            "Join",
+                            { "Run": { "direction": [1.0, 0.0], "frames": 60 } },
+                            "DisconnectMidRewind"
+                        ]
+                    },
+                    {
+                        "user": 1,
+                        "steps": [{ "Wait": { "frames": 10 } }, "Join", "Disconnect"]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.clients[0].steps[1],
+            Step::Run {
+                direction: Vec2::X,
+                frames: 60
+            }
+        );
+        assert!(scenario.validate().is_ok());
+
+        let mut invalid = scenario.clone();
+        invalid.clients[1].steps.remove(1);
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = scenario;
+        invalid.clients[0].steps.push(Step::Wait { frames: 1 });
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_bundled_scenarios() {
+        for scenario in [include_str!("../scenarios/join_and_build.json")] {
+            let scenario: Scenario = serde_json::from_str(scenario).unwrap();
+            assert!(scenario.validate().is_ok());
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use bevy::{app::App, log};
 use mr_server_lib::{
-    init_level_data, watch_agones_updates, Agones, MuddleServerConfig, MuddleServerPlugin,
-    PlayerEvent, PlayerEventSender, TOKIO,
+    bootstrap, is_quarantined, isolate_simulation_thread, reserve_simulation_core,
+    BootstrapOptions, MuddleServerConfig, MuddleServerPlugin, RetryPolicy, ServerVersion, TOKIO,
 };
 use mr_utils_lib::try_parse_from_env;
 use std::{ops::Deref, time::Duration};
@@ -12,6 +12,29 @@ fn main() {
 
     mr_utils_lib::env::load_env();
 
+    let server_config = MuddleServerConfig {
+        public_persistence_url: try_parse_from_env!("MUDDLE_PUBLIC_PERSISTENCE_URL"),
+        private_persistence_url: try_parse_from_env!("MUDDLE_PRIVATE_PERSISTENCE_URL"),
+        idle_timeout_millis: try_parse_from_env!("MUDDLE_IDLE_TIMEOUT"),
+        collider_simplification_tolerance: try_parse_from_env!(
+            "MUDDLE_COLLIDER_SIMPLIFICATION_TOLERANCE"
+        ),
+        listen_port: try_parse_from_env!("MUDDLE_LISTEN_PORT"),
+        listen_ip_addr: try_parse_from_env!("MUDDLE_LISTEN_IP_ADDR"),
+        public_ip_addr: try_parse_from_env!("MUDDLE_PUBLIC_IP_ADDR"),
+        simulation_cpu_core: try_parse_from_env!("MUDDLE_SIMULATION_CPU_CORE"),
+        simulation_thread_nice: try_parse_from_env!("MUDDLE_SIMULATION_THREAD_NICE"),
+        level_file: try_parse_from_env!("MUDDLE_LEVEL_FILE"),
+        determinism_guard: try_parse_from_env!("MUDDLE_DETERMINISM_GUARD"),
+        tether_distance: try_parse_from_env!("MUDDLE_TETHER_DISTANCE"),
+        interest_radius: try_parse_from_env!("MUDDLE_INTEREST_RADIUS"),
+        record_session: try_parse_from_env!("MUDDLE_RECORD_SESSION"),
+        runtime_config_file: try_parse_from_env!("MUDDLE_RUNTIME_CONFIG_FILE"),
+        game_mode: try_parse_from_env!("MUDDLE_GAME_MODE"),
+    };
+    // Has to happen before spawning any threads, as they inherit the CPU affinity.
+    reserve_simulation_core(&server_config);
+
     // We want to exit the process on any panic (in any thread), so this is why the
     // custom hook.
     let orig_hook = std::panic::take_hook();
@@ -24,11 +47,10 @@ fn main() {
     std::panic::set_hook(Box::new(move |panic_info| {
         orig_hook(panic_info);
 
-        // TODO: track https://github.com/dimforge/rapier/issues/223 and fix `calculate_collider_shape` to avoid unwinding panics.
-        if let Some(panic_info) = panic_info.location() {
-            if panic_info.file().contains("parry") {
-                return;
-            }
+        // Such panics get caught and handled (for example, when calculating collider
+        // shapes).
+        if is_quarantined() {
+            return;
         }
 
         // A kludge to let sentry send events first and then shutdown.
@@ -38,117 +60,31 @@ fn main() {
         });
     }));
 
-    let _guard = sentry::init(sentry::ClientOptions {
+    let guard = sentry::init(sentry::ClientOptions {
         release: sentry::release_name!(),
         ..Default::default()
     });
 
     // Spawn the runtime on some other thread.
     std::thread::spawn(|| TOKIO.deref()).join().unwrap();
+    TOKIO.block_on(async { mr_utils_lib::telemetry::init("mr_server") });
 
-    let agones_sdk_grpc_port: Option<u16> = try_parse_from_env!("AGONES_SDK_GRPC_PORT");
-    let (player_tracking_tx, mut player_tracking_rx) =
-        tokio::sync::mpsc::unbounded_channel::<PlayerEvent>();
-    let agones = agones_sdk_grpc_port.map(|grpc_port| {
-        log::info!("Connecting to Agones...");
-        TOKIO
-            .block_on(async {
-                let (sdk, game_server) =
-                    rymder::Sdk::connect(Some(grpc_port), Some(Duration::from_secs(2)), None)
-                        .await?;
-
-                let mut watch_client = sdk.clone();
-                let mut player_tracking_client = sdk.clone();
-
-                if let Some(health_spec) = &game_server.health_spec {
-                    let health_check = sdk.health_check();
-                    let period = health_spec.period;
-                    tokio::spawn(async move {
-                        let mut interval = tokio::time::interval(period);
-                        loop {
-                            interval.tick().await;
-                            log::debug!("Sending Health check message...");
-                            if let Err(err) = health_check.send(()).await {
-                                log::error!("Failed to send Health message: {:?}", err);
-                            }
-                        }
-                    });
-                }
-
-                tokio::spawn(async move {
-                    while let Some(player_event) = player_tracking_rx.recv().await {
-                        let result = match player_event {
-                            PlayerEvent::Connected(uuid) => {
-                                log::info!("Sending PlayerConnect event ({})...", uuid);
-                                player_tracking_client.player_connect(uuid).await
-                            }
-                            PlayerEvent::Disconnected(uuid) => {
-                                log::info!("Sending PlayerDisconnect event ({})...", uuid);
-                                player_tracking_client.player_disconnect(uuid).await
-                            }
-                        };
-                        if let Err(err) = result {
-                            log::error!("Failed to report a player event to Agones: {:?}", err);
-                        }
-                    }
-                    log::warn!("Player tracking channel is closed");
-                });
-
-                tokio::spawn(async move {
-                    use rymder::futures_util::stream::StreamExt;
-
-                    log::info!("Starting to watch GameServer updates...");
-                    match watch_client.watch_gameserver().await {
-                        Err(e) => eprintln!("Failed to watch for GameServer updates: {e}"),
-                        Ok(mut stream) => loop {
-                            // We've received a new update, or the stream is shutting down
-                            match stream.next().await {
-                                Some(Ok(gs)) => {
-                                    log::debug!("GameServer update: {:?}", gs);
-                                }
-                                Some(Err(e)) => {
-                                    log::error!(
-                                        "GameServer Update stream encountered an error: {}",
-                                        e
-                                    );
-                                }
-                                None => {
-                                    log::info!("Server closed the GameServer watch stream");
-                                    break;
-                                }
-                            }
-                        },
-                    }
-                });
-
-                Ok::<_, rymder::Error>(Agones { sdk, game_server })
-            })
-            .unwrap()
-    });
-
-    let game_server = if let Some(agones) = agones {
-        let allocated_game_server_rx = watch_agones_updates(agones.sdk.clone());
-        app.insert_resource(agones);
-        app.insert_resource(PlayerEventSender(Some(player_tracking_tx)));
-        match allocated_game_server_rx.blocking_recv() {
-            Ok(game_server) => Some(game_server),
-            Err(err) => {
-                log::error!("Failed to receive Agones allocation status: {err:?}");
-                std::process::exit(1);
-            }
-        }
-    } else {
-        app.insert_resource(PlayerEventSender(None));
-        None
+    let build_number: Option<u32> = try_parse_from_env!("MUDDLE_BUILD_NUMBER");
+    let bootstrap_options = BootstrapOptions {
+        agones_grpc_port: try_parse_from_env!("AGONES_SDK_GRPC_PORT"),
+        version: ServerVersion::new(build_number.unwrap_or_default()),
+        retry_policy: RetryPolicy::default(),
     };
-    app.insert_resource(MuddleServerConfig {
-        public_persistence_url: try_parse_from_env!("MUDDLE_PUBLIC_PERSISTENCE_URL"),
-        private_persistence_url: try_parse_from_env!("MUDDLE_PRIVATE_PERSISTENCE_URL"),
-        idle_timeout_millis: try_parse_from_env!("MUDDLE_IDLE_TIMEOUT"),
-        listen_port: try_parse_from_env!("MUDDLE_LISTEN_PORT"),
-        listen_ip_addr: try_parse_from_env!("MUDDLE_LISTEN_IP_ADDR"),
-        public_ip_addr: try_parse_from_env!("MUDDLE_PUBLIC_IP_ADDR"),
-    });
-    TOKIO.block_on(async { init_level_data(&mut app, game_server).await });
-    app.add_plugin(MuddleServerPlugin).run();
+    app.insert_resource(server_config.clone());
+    if let Err(err) = TOKIO.block_on(bootstrap(&mut app, bootstrap_options)) {
+        err.report();
+        // Flushes the queued events.
+        drop(guard);
+        std::process::exit(1);
+    }
+    // Bevy task pools get created when adding the plugin, so the simulation thread
+    // can be isolated only after that.
+    app.add_plugin(MuddleServerPlugin);
+    isolate_simulation_thread(&server_config);
+    app.run();
 }
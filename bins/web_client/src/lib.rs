@@ -1,14 +1,27 @@
 #![allow(clippy::unused_unit)]
 
-use bevy::prelude::*;
-use mr_client_lib::{MuddleClientConfig, MuddleClientPlugin, DEFAULT_SERVER_PORT};
+use bevy::{log::LogPlugin, prelude::*};
+use mr_client_lib::{
+    AppVisibilityChanged, MuddleClientConfig, MuddleClientPlugin, MuddleLogPlugin,
+    DEFAULT_SERVER_PORT,
+};
 use mr_utils_lib::try_parse_from_env;
-use std::net::SocketAddr;
-use wasm_bindgen::prelude::*;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use wasm_bindgen::{prelude::*, JsCast};
+
+/// Visibility changes are written by the `visibilitychange` event listener and
+/// forwarded as events on the next update. Browsers may stop updating hidden
+/// tabs entirely, so both changes can arrive at once.
+#[derive(Resource, Clone, Default)]
+struct PendingVisibilityChanges(Arc<Mutex<Vec<AppVisibilityChanged>>>);
 
 #[wasm_bindgen(start)]
 pub fn main() {
     App::new()
+        .add_plugin(MuddleLogPlugin::default())
         .insert_resource(MuddleClientConfig {
             persistence_url: try_parse_from_env!("MUDDLE_PUBLIC_PERSISTENCE_URL"),
             google_client_id: try_parse_from_env!("MUDDLE_GOOGLE_CLIENT_ID"),
@@ -16,14 +29,52 @@ pub fn main() {
             auth0_client_id: try_parse_from_env!("MUDDLE_AUTH0_CLIENT_ID"),
             matchmaker_url: try_parse_from_env!("MUDDLE_MATCHMAKER_URL"),
             server_addr: server_addr(),
+            discord_client_id: None,
         })
         .insert_resource(Msaa { samples: 4 })
-        .add_plugins(bevy::DefaultPlugins)
+        .add_plugins(bevy::DefaultPlugins.build().disable::<LogPlugin>())
         .add_plugin(MuddleClientPlugin)
+        .add_startup_system(listen_to_visibility_changes)
         .add_system(resize_canvas)
+        .add_system(forward_visibility_changes)
         .run();
 }
 
+fn listen_to_visibility_changes(mut commands: Commands) {
+    let pending_changes = PendingVisibilityChanges::default();
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .expect("no global `document` exists");
+
+    let changes = pending_changes.0.clone();
+    let listener_document = document.clone();
+    let listener = Closure::<dyn FnMut()>::new(move || {
+        let change = if listener_document.hidden() {
+            AppVisibilityChanged::Hidden
+        } else {
+            AppVisibilityChanged::Visible
+        };
+        changes.lock().unwrap().push(change);
+    });
+    if let Err(err) = document
+        .add_event_listener_with_callback("visibilitychange", listener.as_ref().unchecked_ref())
+    {
+        log::error!("Failed to listen to visibility changes: {:?}", err);
+    }
+    // The listener lives as long as the page.
+    listener.forget();
+
+    commands.insert_resource(pending_changes);
+}
+
+fn forward_visibility_changes(
+    pending_changes: Res<PendingVisibilityChanges>,
+    mut visibility_events: EventWriter<AppVisibilityChanged>,
+) {
+    let changes = std::mem::take(&mut *pending_changes.0.lock().unwrap());
+    visibility_events.send_batch(changes.into_iter());
+}
+
 fn resize_canvas(mut windows: ResMut<Windows>) {
     let window = match windows.get_primary_mut() {
         Some(window) => window,
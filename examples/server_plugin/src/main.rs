@@ -0,0 +1,107 @@
+//! An example of a custom game mode: runners race to finish the level a set
+//! number of times, and the first one to do so wins the round.
+//!
+//! Runs an embedded server that loads the level from a file:
+//!
+//! ```bash
+//! cargo run -p mr_server_plugin_example -- levels/my_level.json
+//! ```
+
+use bevy::{ecs::world::World, log, utils::HashMap};
+use mr_server_embed::{run_server, ServerConfig};
+use mr_server_lib::GameServerPlugin;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    messages::{FinishResult, PlayerNetId},
+    player::Players,
+    SIMULATIONS_PER_SECOND,
+};
+
+const FINISHES_TO_WIN: u32 = 3;
+/// Standings are logged every 30 seconds.
+const STANDINGS_INTERVAL_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 * 30;
+
+#[derive(Clone, Default)]
+struct FinishRace {
+    round: u32,
+    finishes: HashMap<PlayerNetId, u32>,
+}
+
+impl FinishRace {
+    fn start_round(&mut self) {
+        self.round += 1;
+        self.finishes.clear();
+        log::info!(
+            "Round {} has started: the first to finish {} times wins",
+            self.round,
+            FINISHES_TO_WIN
+        );
+    }
+}
+
+fn nickname(world: &World, net_id: PlayerNetId) -> String {
+    world.resource::<Players>().get(&net_id).map_or_else(
+        || format!("Player {}", net_id.0),
+        |player| player.nickname.clone(),
+    )
+}
+
+impl GameServerPlugin for FinishRace {
+    fn name(&self) -> &str {
+        "finish_race"
+    }
+
+    fn on_level_loaded(&mut self, _world: &mut World) {
+        self.round = 0;
+        self.start_round();
+    }
+
+    fn on_player_join(&mut self, world: &mut World, net_id: PlayerNetId) {
+        log::info!(
+            "{} has joined round {}",
+            nickname(world, net_id),
+            self.round
+        );
+    }
+
+    fn on_player_leave(&mut self, _world: &mut World, net_id: PlayerNetId) {
+        self.finishes.remove(&net_id);
+    }
+
+    fn on_finish(&mut self, world: &mut World, net_id: PlayerNetId, _result: Option<FinishResult>) {
+        let finishes = self.finishes.entry(net_id).or_default();
+        *finishes += 1;
+        if *finishes < FINISHES_TO_WIN {
+            return;
+        }
+        log::info!("{} has won round {}", nickname(world, net_id), self.round);
+        self.start_round();
+    }
+
+    fn on_broadcast(&mut self, world: &mut World, frame_number: FrameNumber) {
+        if frame_number.value() % STANDINGS_INTERVAL_FRAMES != 0 || self.finishes.is_empty() {
+            return;
+        }
+        let mut standings = self
+            .finishes
+            .iter()
+            .map(|(net_id, finishes)| (nickname(world, *net_id), *finishes))
+            .collect::<Vec<_>>();
+        standings.sort_by(|(_, a), (_, b)| b.cmp(a));
+        for (nickname, finishes) in standings {
+            log::info!("{nickname}: {finishes}/{FINISHES_TO_WIN}");
+        }
+    }
+}
+
+fn main() {
+    let level_file = std::env::args()
+        .nth(1)
+        .expect("Expected a path to a level file");
+    let config = ServerConfig {
+        level_file: Some(level_file.into()),
+        ..Default::default()
+    }
+    .add_game_server_plugin(FinishRace::default());
+    run_server(config);
+}
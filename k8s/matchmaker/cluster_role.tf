@@ -6,7 +6,13 @@ resource "kubernetes_cluster_role" "matchmaker_role" {
   rule {
     api_groups = ["", "agones.dev", "allocation.agones.dev"]
     resources  = ["pods", "gameservers"]
-    verbs      = ["get", "watch", "list"]
+    verbs      = ["get", "watch", "list", "patch"]
+  }
+
+  rule {
+    api_groups = ["coordination.k8s.io"]
+    resources  = ["leases"]
+    verbs      = ["get", "create", "update"]
   }
 }
 
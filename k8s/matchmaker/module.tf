@@ -9,10 +9,9 @@ resource "kubernetes_deployment" "mr_matchmaker" {
         service = "mr-matchmaker"
       }
     }
-    # We can't have more replicas until we implement state sharing.
-    # To be able to serve the webhook autoscaler's endpoint correctly, we need to know how many clients there are
-    # connected to all the matchmaker replicas in total.
-    replicas = 1
+    # Replicas elect a leader via the `mr-matchmaker-leader` lease. The leader handles allocations and webhook calls,
+    # followers serve server lists and relay the rest to the leader.
+    replicas = 2
     template {
       metadata {
         labels = {
@@ -34,6 +33,26 @@ resource "kubernetes_deployment" "mr_matchmaker" {
             name           = "webhook"
             container_port = 8081
           }
+          port {
+            name           = "status"
+            container_port = 8084
+          }
+          env {
+            name = "MUDDLE_POD_NAME"
+            value_from {
+              field_ref {
+                field_path = "metadata.name"
+              }
+            }
+          }
+          env {
+            name = "MUDDLE_POD_IP"
+            value_from {
+              field_ref {
+                field_path = "status.podIP"
+              }
+            }
+          }
           env {
             name = "SENTRY_DSN"
             value_from {
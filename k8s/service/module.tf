@@ -29,6 +29,10 @@ resource "kubernetes_service" "muddle_run_service" {
       name = "persistence-pub"
       port = 8082
     }
+    port {
+      name = "status"
+      port = 8084
+    }
   }
 }
 
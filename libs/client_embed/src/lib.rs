@@ -0,0 +1,167 @@
+//! A stable entry point for embedding the muddle-run client into launchers and
+//! tools.
+//!
+//! The internals of `mr_client_lib` change together with the game, this crate
+//! exposes only what's needed to start a client and follow its lifecycle:
+//!
+//! ```no_run
+//! use mr_client_embed::{run_client, ClientConfig, ClientLifecycleEvent};
+//!
+//! let config = ClientConfig {
+//!     server_addr: Some("127.0.0.1:3455".parse().unwrap()),
+//!     ..Default::default()
+//! }
+//! .on_lifecycle_event(|event| {
+//!     if event == ClientLifecycleEvent::SessionPlaying {
+//!         println!("Joined the game");
+//!     }
+//! });
+//! run_client(config);
+//! ```
+
+use bevy::{app::AppExit, prelude::*};
+use iyes_loopless::state::CurrentState;
+use mr_client_lib::{MuddleClientConfig, MuddleClientPlugin};
+use mr_shared_lib::GameSessionState;
+use std::{net::SocketAddr, sync::Arc};
+use url::Url;
+
+pub use mr_client_lib::DEFAULT_SERVER_PORT;
+
+type LifecycleCallback = Arc<dyn Fn(ClientLifecycleEvent) + Send + Sync>;
+
+/// Configures a client started with [`run_client`].
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// Is required for authentication and browsing levels.
+    pub persistence_url: Option<Url>,
+    /// Is required for picking a server in the main menu. If neither this, nor
+    /// `server_addr` is set, the client connects to a local server.
+    pub matchmaker_url: Option<Url>,
+    /// Makes the client connect to the server directly, skipping the main menu.
+    pub server_addr: Option<SocketAddr>,
+    pub google_client_id: Option<String>,
+    pub google_client_secret: Option<String>,
+    pub auth0_client_id: Option<String>,
+    /// Is used only if `mr_client_lib` is built with the `discord` feature.
+    pub discord_client_id: Option<String>,
+    pub window_title: String,
+    pub window_width: f32,
+    pub window_height: f32,
+    lifecycle_callback: Option<LifecycleCallback>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            persistence_url: None,
+            matchmaker_url: None,
+            server_addr: None,
+            google_client_id: None,
+            google_client_secret: None,
+            auth0_client_id: None,
+            discord_client_id: None,
+            window_title: "Muddle Run".to_owned(),
+            window_width: 1024.0,
+            window_height: 768.0,
+            lifecycle_callback: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Sets the callback that gets called from the game loop on every
+    /// [`ClientLifecycleEvent`], so it's expected to return quickly.
+    pub fn on_lifecycle_event(
+        mut self,
+        callback: impl Fn(ClientLifecycleEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.lifecycle_callback = Some(Arc::new(callback));
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientLifecycleEvent {
+    /// The window is open, and the client starts loading assets.
+    Started,
+    /// The client is connecting to a server or loading a level.
+    SessionLoading,
+    SessionPlaying,
+    /// The connection is lost or the client has fallen too much behind the
+    /// server, the game resumes as soon as updates start coming again.
+    SessionPaused,
+    /// The window is closed, [`run_client`] is about to return.
+    Exiting,
+}
+
+impl From<GameSessionState> for ClientLifecycleEvent {
+    fn from(state: GameSessionState) -> Self {
+        match state {
+            GameSessionState::Loading => Self::SessionLoading,
+            GameSessionState::Playing => Self::SessionPlaying,
+            GameSessionState::Paused => Self::SessionPaused,
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+struct ClientLifecycleCallback(LifecycleCallback);
+
+/// Opens the game window and runs the client until the window is closed.
+///
+/// Has to be called from the main thread, and not more than once per process.
+pub fn run_client(config: ClientConfig) {
+    let mut app = App::new();
+    app.insert_resource(MuddleClientConfig {
+        persistence_url: config.persistence_url,
+        google_client_id: config.google_client_id,
+        google_client_secret: config.google_client_secret,
+        auth0_client_id: config.auth0_client_id,
+        matchmaker_url: config.matchmaker_url,
+        server_addr: config.server_addr,
+        discord_client_id: config.discord_client_id,
+    })
+    .insert_resource(Msaa { samples: 4 })
+    .add_plugins(DefaultPlugins.set(WindowPlugin {
+        window: WindowDescriptor {
+            title: config.window_title,
+            width: config.window_width,
+            height: config.window_height,
+            ..Default::default()
+        },
+        ..Default::default()
+    }))
+    .add_plugin(MuddleClientPlugin);
+
+    if let Some(callback) = config.lifecycle_callback {
+        app.insert_resource(ClientLifecycleCallback(callback))
+            .add_startup_system(report_started_system)
+            .add_system_to_stage(CoreStage::Last, report_session_state_system)
+            .add_system_to_stage(CoreStage::Last, report_exit_system);
+    }
+
+    app.run();
+}
+
+fn report_started_system(callback: Res<ClientLifecycleCallback>) {
+    callback(ClientLifecycleEvent::Started);
+}
+
+fn report_session_state_system(
+    callback: Res<ClientLifecycleCallback>,
+    game_state: Option<Res<CurrentState<GameSessionState>>>,
+) {
+    if let Some(game_state) = game_state.filter(|game_state| game_state.is_changed()) {
+        callback(game_state.0.clone().into());
+    }
+}
+
+fn report_exit_system(
+    callback: Res<ClientLifecycleCallback>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.iter().next().is_some() {
+        callback(ClientLifecycleEvent::Exiting);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{
+    config_storage::{self, AudioConfig, AUDIO_CONFIG_KEY},
+    helpers::PlayerParams,
+    net::{MainMenuUiChannels, MatchmakerState, PersistenceRequest},
+};
+use bevy::{
+    asset::{Assets, Handle},
+    audio::{Audio, AudioSource, PlaybackSettings},
+    ecs::system::{Local, Res, ResMut, Resource, SystemParam},
+    log,
+    utils::HashMap,
+};
+use iyes_loopless::state::CurrentState;
+use mr_messages_lib::{validate_audio_clip, PostAudioClipResponse};
+use mr_shared_lib::{
+    framebuffer::FrameNumber, game::level::LevelState, messages::RespawnPlayerReason,
+    net::MessageId, AppState,
+};
+use std::marker::PhantomData;
+
+/// Caches the audio clips of level events. Clips are immutable once uploaded,
+/// so they are kept for the whole session, even after switching levels.
+#[derive(Resource, Default)]
+pub struct AudioCues {
+    config: AudioConfig,
+    clips: HashMap<i64, AudioClipState>,
+    request_id_counter: MessageId,
+    /// The latest upload, is displayed in the builder UI.
+    pub upload_status: Option<AudioClipUploadStatus>,
+}
+
+enum AudioClipState {
+    Requested,
+    Loaded(Handle<AudioSource>),
+    /// The clip doesn't exist, isn't approved, or failed to download. It's not
+    /// requested again until the next session.
+    Unavailable,
+}
+
+#[derive(Debug)]
+pub enum AudioClipUploadStatus {
+    InProgress,
+    Uploaded(PostAudioClipResponse),
+    Failed(String),
+}
+
+impl AudioCues {
+    pub fn config(&self) -> AudioConfig {
+        self.config
+    }
+
+    /// Changes the config and saves it to the settings.
+    pub fn set_config(&mut self, config: AudioConfig) {
+        self.config = config;
+        if let Err(err) = config_storage::write(AUDIO_CONFIG_KEY, &config) {
+            log::error!("Failed to save the audio config: {:?}", err);
+        }
+    }
+
+    pub fn receive_clip(
+        &mut self,
+        clip_id: i64,
+        data: Option<Vec<u8>>,
+        audio_sources: &mut Assets<AudioSource>,
+    ) {
+        let state = match data {
+            Some(data) => {
+                log::debug!("Loaded audio clip {} ({} bytes)", clip_id, data.len());
+                AudioClipState::Loaded(audio_sources.add(AudioSource { bytes: data.into() }))
+            }
+            None => AudioClipState::Unavailable,
+        };
+        self.clips.insert(clip_id, state);
+    }
+}
+
+#[derive(SystemParam)]
+pub struct AudioClipRequestParams<'w, 's> {
+    pub audio_cues: ResMut<'w, AudioCues>,
+    matchmaker_state: Option<Res<'w, MatchmakerState>>,
+    main_menu_ui_channels: Option<Res<'w, MainMenuUiChannels>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> AudioClipRequestParams<'w, 's> {
+    /// Uploading requires a signed-in user.
+    pub fn can_upload(&self) -> bool {
+        self.matchmaker_state
+            .as_ref()
+            .map_or(false, |matchmaker_state| {
+                matchmaker_state.id_token.is_some()
+            })
+    }
+
+    pub fn upload(&mut self, data: Vec<u8>) {
+        if let Err(err) = validate_audio_clip(&data) {
+            self.audio_cues.upload_status = Some(AudioClipUploadStatus::Failed(err.to_string()));
+            return;
+        }
+        let (Some(id_token), Some(main_menu_ui_channels)) = (
+            self.matchmaker_state
+                .as_ref()
+                .and_then(|matchmaker_state| matchmaker_state.id_token.clone()),
+            self.main_menu_ui_channels.as_ref(),
+        ) else {
+            return;
+        };
+
+        let request_id = self.audio_cues.request_id_counter.increment();
+        main_menu_ui_channels
+            .persistence_request_tx
+            .send(PersistenceRequest::UploadAudioClip {
+                request_id,
+                id_token,
+                data,
+            })
+            .expect("Failed to write to a channel (persistence request)");
+        self.audio_cues.upload_status = Some(AudioClipUploadStatus::InProgress);
+    }
+}
+
+pub fn read_audio_config_system(mut audio_cues: ResMut<AudioCues>) {
+    match config_storage::read::<AudioConfig>(AUDIO_CONFIG_KEY) {
+        Ok(config) => audio_cues.config = config,
+        Err(err) => log::error!("Failed to read the audio config: {:?}", err),
+    }
+}
+
+/// Clips are fetched from the persistence service as soon as a level
+/// references them, so that they are ready by the time an event happens.
+pub fn request_audio_clips_system(
+    app_state: Res<CurrentState<AppState>>,
+    level_state: Res<LevelState>,
+    mut audio_clip_request_params: AudioClipRequestParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if app_state.0 != AppState::Playing || !audio_clip_request_params.audio_cues.config.cues_enabled
+    {
+        return;
+    }
+    let Some(main_menu_ui_channels) = audio_clip_request_params.main_menu_ui_channels.as_ref()
+    else {
+        return;
+    };
+
+    let audio_cues = &mut *audio_clip_request_params.audio_cues;
+    for clip_id in level_state.settings().audio_cues.clip_ids() {
+        if audio_cues.clips.contains_key(&clip_id) {
+            continue;
+        }
+        log::debug!("Requesting audio clip {}", clip_id);
+        let request_id = audio_cues.request_id_counter.increment();
+        main_menu_ui_channels
+            .persistence_request_tx
+            .send(PersistenceRequest::GetAudioClip {
+                request_id,
+                clip_id,
+            })
+            .expect("Failed to write to a channel (persistence request)");
+        audio_cues.clips.insert(clip_id, AudioClipState::Requested);
+    }
+}
+
+/// Cues are played only for the events of the current player.
+pub fn play_audio_cues_system(
+    audio_cues: Res<AudioCues>,
+    level_state: Res<LevelState>,
+    player_params: PlayerParams,
+    audio: Res<Audio>,
+    mut last_respawn: Local<Option<(FrameNumber, RespawnPlayerReason)>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let respawning_at = player_params
+        .current_player()
+        .and_then(|player| player.respawning_at);
+    if respawning_at == *last_respawn {
+        return;
+    }
+    *last_respawn = respawning_at;
+
+    let Some((_, reason)) = respawning_at else {
+        return;
+    };
+    if !audio_cues.config.cues_enabled {
+        return;
+    }
+    let Some(clip_id) = level_state.settings().audio_cues.get(reason) else {
+        return;
+    };
+    if let Some(AudioClipState::Loaded(source)) = audio_cues.clips.get(&clip_id) {
+        audio.play_with_settings(
+            source.clone(),
+            PlaybackSettings::ONCE.with_volume(audio_cues.config.cues_volume),
+        );
+    }
+}
@@ -1,26 +1,70 @@
 use crate::{
     components::{CameraPivotDirection, CameraPivotTag},
-    CurrentPlayerNetId, MainCameraPivotEntity,
+    helpers::PlayerParams,
+    ui::layout::UiLayout,
+    CurrentPlayerNetId, MainCameraEntity, MainCameraPivotEntity,
 };
 use bevy::{
     ecs::{
         entity::Entity,
         query::{Changed, With},
-        system::{Commands, Query, RemovedComponents, Res, SystemParam},
+        system::{Commands, Local, Query, RemovedComponents, Res, ResMut, Resource, SystemParam},
     },
     hierarchy::{BuildChildren, Parent},
     log,
+    math::{Vec2, Vec3},
     time::Time,
-    transform::components::Transform,
+    transform::components::{GlobalTransform, Transform},
 };
+use iyes_loopless::state::CurrentState;
 use mr_shared_lib::{
-    game::components::{PlayerTag, Position, Spawned},
+    game::{
+        components::{PlayerTag, Position, Spawned},
+        level::{LevelObjectDesc, LevelState},
+        level_objects::{CAMERA_ANCHOR_MAX_DURATION_SECS, CAMERA_ANCHOR_MIN_DURATION_SECS},
+    },
     messages::PlayerNetId,
+    player::PlayerRole,
     registry::EntityRegistry,
-    GameTime, PLAYER_RADIUS,
+    GameSessionState, GameTime, PLAYER_RADIUS,
 };
+use serde::{Deserialize, Serialize};
 
 const CAMERA_MOVEMENT_SPEED: f32 = 4.0;
+/// Spectators don't edit anything and fly over whole levels, so their camera
+/// moves faster.
+const SPECTATOR_CAMERA_MOVEMENT_SPEED: f32 = 12.0;
+/// The translation of the main camera relative to its pivot.
+pub const MAIN_CAMERA_OFFSET: Vec3 = Vec3::new(-3.0, -14.0, 14.0);
+const OVERVIEW_CAMERA_DISTANCE_FACTOR: f32 = 2.0;
+
+/// How far the main camera is from its pivot, is picked by the layout preset
+/// of the current role.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CameraMode {
+    #[default]
+    Close,
+    /// Shows more of the level, at the cost of details.
+    Overview,
+}
+
+impl CameraMode {
+    pub const ALL: [CameraMode; 2] = [CameraMode::Close, CameraMode::Overview];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CameraMode::Close => "Close",
+            CameraMode::Overview => "Overview",
+        }
+    }
+
+    pub fn offset(self) -> Vec3 {
+        match self {
+            CameraMode::Close => MAIN_CAMERA_OFFSET,
+            CameraMode::Overview => MAIN_CAMERA_OFFSET * OVERVIEW_CAMERA_DISTANCE_FACTOR,
+        }
+    }
+}
 
 pub type SpawnedOrDespawnedPlayers<'w, 's> = Query<
     'w,
@@ -121,6 +165,7 @@ pub fn reattach_camera_system(
 
 pub fn move_free_camera_pivot_system(
     time: Res<Time>,
+    player_params: PlayerParams,
     main_camera_pivot: Res<MainCameraPivotEntity>,
     mut camera_pivot_query: Query<(&CameraPivotDirection, &mut Transform)>,
 ) {
@@ -129,7 +174,254 @@ pub fn move_free_camera_pivot_system(
     let (direction, mut transform) = camera_pivot_query
         .get_mut(main_camera_pivot.0)
         .expect("Expected the camera to initialize in `basic_scene`");
-    let d = direction.0.normalize_or_zero() * CAMERA_MOVEMENT_SPEED * time.delta_seconds();
+    let is_spectator = player_params
+        .current_player()
+        .map_or(false, |player| player.role == PlayerRole::Spectator);
+    let speed = if is_spectator {
+        SPECTATOR_CAMERA_MOVEMENT_SPEED
+    } else {
+        CAMERA_MOVEMENT_SPEED
+    };
+    let d = direction.0.normalize_or_zero() * speed * time.delta_seconds();
     transform.translation.x += d.x;
     transform.translation.y += d.y;
 }
+
+/// Is played when a player joins a level: the camera flies through the camera
+/// anchors placed by builders, in their order. Levels without anchors don't
+/// have an intro.
+#[derive(Resource, Default)]
+pub struct LevelIntro {
+    state: LevelIntroState,
+    skip_requested: bool,
+}
+
+#[derive(Default)]
+enum LevelIntroState {
+    /// Waits for the level to load.
+    #[default]
+    Pending,
+    Playing {
+        path: IntroPath,
+        elapsed: f32,
+    },
+    Finished,
+}
+
+impl LevelIntro {
+    pub fn is_playing(&self) -> bool {
+        matches!(self.state, LevelIntroState::Playing { .. })
+    }
+
+    pub fn skip(&mut self) {
+        self.skip_requested = self.is_playing();
+    }
+}
+
+/// A Catmull-Rom spline through the anchor positions, paired with the time it
+/// takes to fly from each anchor to the next one.
+struct IntroPath {
+    anchors: Vec<(Vec2, f32)>,
+}
+
+impl IntroPath {
+    fn new(level_state: &LevelState) -> Option<Self> {
+        let mut anchors = level_state
+            .objects()
+            .values()
+            .filter_map(|level_object| match &level_object.desc {
+                LevelObjectDesc::CameraAnchor(camera_anchor) => {
+                    Some((camera_anchor.order, level_object.net_id.0, camera_anchor))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if anchors.is_empty() {
+            return None;
+        }
+        // Sorting by ids as well, as hash map iteration order isn't stable.
+        anchors.sort_by_key(|(order, net_id, _)| (*order, *net_id));
+        Some(Self {
+            anchors: anchors
+                .into_iter()
+                .map(|(_, _, camera_anchor)| {
+                    (
+                        camera_anchor.position,
+                        camera_anchor.duration_secs.clamp(
+                            CAMERA_ANCHOR_MIN_DURATION_SECS,
+                            CAMERA_ANCHOR_MAX_DURATION_SECS,
+                        ),
+                    )
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns `None` once the intro is over.
+    fn evaluate(&self, elapsed: f32) -> Option<Vec2> {
+        let mut segment_start = 0.0;
+        for (i, (position, duration)) in self.anchors.iter().enumerate() {
+            if elapsed < segment_start + duration {
+                let Some((next, _)) = self.anchors.get(i + 1) else {
+                    // The camera stays at the last anchor.
+                    return Some(*position);
+                };
+                // The tangents at the ends of the path are calculated as if the first and
+                // the last anchors were doubled.
+                let (prev, _) = self.anchors[i.saturating_sub(1)];
+                let after_next = self.anchors.get(i + 2).map_or(*next, |(p, _)| *p);
+                let t = (elapsed - segment_start) / duration;
+                return Some(catmull_rom(prev, *position, *next, after_next, t));
+            }
+            segment_start += duration;
+        }
+        None
+    }
+}
+
+/// Interpolates between `p1` and `p2`, with the tangents defined by the
+/// neighbouring points.
+fn catmull_rom(p0: Vec2, p1: Vec2, p2: Vec2, p3: Vec2, t: f32) -> Vec2 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+#[derive(SystemParam)]
+pub struct LevelIntroCameraQueries<'w, 's> {
+    main_camera: Res<'w, MainCameraEntity>,
+    main_camera_pivot: Res<'w, MainCameraPivotEntity>,
+    transforms: Query<'w, 's, &'static mut Transform>,
+    camera_pivots: Query<'w, 's, &'static GlobalTransform, With<CameraPivotTag>>,
+}
+
+/// Moves the main camera relative to its pivot, so that the camera returns
+/// to the player (or wherever the pivot is) once the intro is over.
+pub fn play_level_intro_system(
+    time: Res<Time>,
+    game_state: Res<CurrentState<GameSessionState>>,
+    level_state: Res<LevelState>,
+    ui_layout: Res<UiLayout>,
+    mut level_intro: ResMut<LevelIntro>,
+    mut queries: LevelIntroCameraQueries,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let level_intro = &mut *level_intro;
+    match (&game_state.0, &mut level_intro.state) {
+        (GameSessionState::Loading, state) => {
+            *state = LevelIntroState::Pending;
+            level_intro.skip_requested = false;
+            return;
+        }
+        (GameSessionState::Playing, state @ LevelIntroState::Pending) => {
+            *state = match IntroPath::new(&level_state) {
+                Some(path) => {
+                    log::debug!("Playing the level intro");
+                    LevelIntroState::Playing { path, elapsed: 0.0 }
+                }
+                None => LevelIntroState::Finished,
+            };
+        }
+        (_, LevelIntroState::Playing { elapsed, .. }) => {
+            *elapsed += time.delta_seconds();
+        }
+        _ => return,
+    }
+
+    let LevelIntroState::Playing { path, elapsed } = &level_intro.state else {
+        return;
+    };
+    let mut camera_transform = queries
+        .transforms
+        .get_mut(queries.main_camera.0)
+        .expect("Expected the camera to initialize in `basic_scene`");
+    let camera_position = if level_intro.skip_requested {
+        None
+    } else {
+        path.evaluate(*elapsed)
+    };
+    match camera_position {
+        Some(position) => {
+            let pivot_transform = queries
+                .camera_pivots
+                .get(queries.main_camera_pivot.0)
+                .expect("Expected the camera to initialize in `basic_scene`");
+            camera_transform.translation = pivot_transform
+                .compute_matrix()
+                .inverse()
+                .transform_point3(position.extend(0.0) + ui_layout.camera_offset());
+        }
+        None => {
+            log::debug!("Level intro is finished");
+            camera_transform.translation = ui_layout.camera_offset();
+            level_intro.state = LevelIntroState::Finished;
+            level_intro.skip_requested = false;
+        }
+    }
+}
+
+/// Moves the main camera once the camera mode changes (e.g. on switching
+/// roles). The intro controls the camera while it's playing, and puts it back
+/// on its own.
+pub fn apply_camera_mode_system(
+    ui_layout: Res<UiLayout>,
+    level_intro: Res<LevelIntro>,
+    main_camera: Res<MainCameraEntity>,
+    mut transforms: Query<&mut Transform>,
+    mut applied_offset: Local<Option<Vec3>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let offset = ui_layout.camera_offset();
+    if level_intro.is_playing() || *applied_offset == Some(offset) {
+        return;
+    }
+    *applied_offset = Some(offset);
+
+    let mut camera_transform = transforms
+        .get_mut(main_camera.0)
+        .expect("Expected the camera to initialize in `basic_scene`");
+    camera_transform.translation = offset;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intro_path_evaluate() {
+        let path = IntroPath {
+            anchors: vec![
+                (Vec2::new(0.0, 0.0), 1.0),
+                (Vec2::new(10.0, 0.0), 2.0),
+                (Vec2::new(10.0, 10.0), 0.5),
+            ],
+        };
+        // The spline passes through the anchors.
+        assert_eq!(path.evaluate(0.0), Some(Vec2::new(0.0, 0.0)));
+        assert_eq!(path.evaluate(1.0), Some(Vec2::new(10.0, 0.0)));
+        assert_eq!(path.evaluate(3.0), Some(Vec2::new(10.0, 10.0)));
+        // The camera stays at the last anchor for its duration.
+        assert_eq!(path.evaluate(3.4), Some(Vec2::new(10.0, 10.0)));
+        assert_eq!(path.evaluate(3.5), None);
+
+        let halfway = path.evaluate(2.0).unwrap();
+        assert!(halfway.x > 10.0 && halfway.y > 0.0 && halfway.y < 10.0);
+    }
+
+    #[test]
+    fn test_catmull_rom_straight_line() {
+        let point = catmull_rom(
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(3.0, 0.0),
+            0.5,
+        );
+        assert!((point - Vec2::new(1.5, 0.0)).length() < f32::EPSILON);
+    }
+}
@@ -9,6 +9,10 @@ pub struct CameraPivotTag;
 #[derive(Component)]
 pub struct CameraPivotDirection(pub Vec2);
 
+/// The directional light that is controlled by the level settings.
+#[derive(Component)]
+pub struct LevelLightTag;
+
 #[derive(Component)]
 pub struct LevelObjectControlPoint;
 
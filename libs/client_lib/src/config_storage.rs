@@ -1,11 +1,28 @@
-use crate::utils::parse_jwt;
-use bevy::ecs::system::Resource;
+use crate::{
+    input::{InputAction, InputBinding},
+    ui::{layout::LayoutPreset, theme::ThemeMode},
+    utils::parse_jwt,
+};
+use bevy::{ecs::system::Resource, math::Vec2, utils::Uuid};
 use jwt_compact::Claims;
+use mr_shared_lib::messages::FinishResult;
 use mr_utils_lib::JwtAuthClaims;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::fmt::{Debug, Formatter};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter},
+};
 
 pub const AUTH_CONFIG_KEY: &str = "auth";
+pub const THEME_CONFIG_KEY: &str = "theme";
+pub const PERSONAL_BESTS_CONFIG_KEY: &str = "personal_bests";
+pub const AUDIO_CONFIG_KEY: &str = "audio";
+pub const UI_LAYOUT_CONFIG_KEY: &str = "ui_layout";
+pub const KEY_BINDINGS_CONFIG_KEY: &str = "key_bindings";
+pub const GUEST_CONFIG_KEY: &str = "guest";
+/// Ghosts are stored per level (see `ghost_config_key`), so that only the one
+/// of the played level is read.
+pub const GHOST_CONFIG_KEY_PREFIX: &str = "ghost_";
 
 #[derive(Resource, Serialize, Deserialize, Default, Clone)]
 pub struct OfflineAuthConfig {
@@ -19,6 +36,90 @@ pub struct OfflineAuthConfig {
     pub refresh_token: String,
 }
 
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct GuestConfig {
+    /// Is generated on the first launch, see `guest::Guest`.
+    #[serde(default)]
+    pub id: Option<Uuid>,
+    #[serde(default)]
+    pub nickname: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug)]
+pub struct UiThemeConfig {
+    #[serde(default)]
+    pub mode: ThemeMode,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Custom audio cues of levels can be turned off separately from music.
+    pub cues_enabled: bool,
+    /// Ranges from 0.0 to 1.0.
+    pub cues_volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            cues_enabled: true,
+            cues_volume: 1.0,
+        }
+    }
+}
+
+/// Layout presets per player role, see `ui::layout`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UiLayoutConfig {
+    #[serde(default = "LayoutPreset::runner")]
+    pub runner: LayoutPreset,
+    #[serde(default = "LayoutPreset::builder")]
+    pub builder: LayoutPreset,
+    #[serde(default = "LayoutPreset::spectator")]
+    pub spectator: LayoutPreset,
+}
+
+impl Default for UiLayoutConfig {
+    fn default() -> Self {
+        Self {
+            runner: LayoutPreset::runner(),
+            builder: LayoutPreset::builder(),
+            spectator: LayoutPreset::spectator(),
+        }
+    }
+}
+
+/// Only the actions that a player has remapped are stored, the rest follow
+/// the defaults of `input::KeyBindings`.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct KeyBindingsConfig {
+    #[serde(default)]
+    pub overrides: HashMap<InputAction, Vec<InputBinding>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct PersonalBestsConfig {
+    /// Keyed by level ids.
+    #[serde(default)]
+    pub levels: HashMap<i64, FinishResult>,
+}
+
+/// The personal best run of the current player on a level.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct GhostConfig {
+    /// Positions are sampled every `sample_frames` simulated frames, starting
+    /// from the spawn.
+    #[serde(default)]
+    pub sample_frames: u16,
+    #[serde(default)]
+    pub positions: Vec<Vec2>,
+}
+
+pub fn ghost_config_key(level_id: i64) -> String {
+    format!("{GHOST_CONFIG_KEY_PREFIX}{level_id}")
+}
+
 impl Debug for OfflineAuthConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("OfflineAuthConfig")
@@ -60,7 +161,9 @@ impl OfflineAuthConfig {
 #[cfg(not(target_arch = "wasm32"))]
 pub fn write(name: &str, value: &impl Serialize) -> anyhow::Result<()> {
     let Some(project_dirs) = directories::ProjectDirs::from("", "", "muddle-run") else {
-        return Err(anyhow::Error::msg("Failed to determine a project directory"));
+        return Err(anyhow::Error::msg(
+            "Failed to determine a project directory",
+        ));
     };
     let config_dir = project_dirs.config_dir();
     bevy::log::debug!("Writing \"{}\" config to {:?}", name, config_dir.join(name));
@@ -72,7 +175,9 @@ pub fn write(name: &str, value: &impl Serialize) -> anyhow::Result<()> {
 #[cfg(not(target_arch = "wasm32"))]
 pub fn read<T: DeserializeOwned + Default>(name: &str) -> anyhow::Result<T> {
     let Some(project_dirs) = directories::ProjectDirs::from("", "", "muddle-run") else {
-        return Err(anyhow::Error::msg("Failed to determine a project directory"));
+        return Err(anyhow::Error::msg(
+            "Failed to determine a project directory",
+        ));
     };
     let content = match std::fs::read(project_dirs.config_dir().join(name)) {
         Ok(content) => content,
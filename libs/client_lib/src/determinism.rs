@@ -0,0 +1,363 @@
+use crate::input::PlayerRequestsQueue;
+use bevy::{
+    ecs::{
+        entity::Entity,
+        system::{Query, Res, ResMut, Resource},
+    },
+    log,
+    math::Vec2,
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        components::PlayerDirection,
+        determinism::{
+            divergent_entities, first_divergent_stage, DeterminismGuard, HashedEntity,
+            SimulationStage, StateHashMessage, StateHashRequest, BISECT_WINDOW_FRAMES,
+        },
+    },
+    messages::PlayerNetId,
+    registry::EntityRegistry,
+    SimulationTime, TICKS_PER_NETWORK_BROADCAST,
+};
+use std::{collections::VecDeque, fmt};
+
+/// A divergence usually persists once it happens, reporting every checkpoint
+/// after the first one would just flood the logs.
+const MAX_REPORTS_PER_SESSION: usize = 3;
+
+/// Compares the simulation with the checkpoints broadcast by the server and,
+/// on a mismatch, bisects the preceding frames down to the first divergent
+/// stage and entities.
+#[derive(Resource, Default)]
+pub struct DivergenceBisect {
+    received: Vec<StateHashMessage>,
+    pending_checkpoints: VecDeque<(FrameNumber, u64)>,
+    state: BisectState,
+    reports: usize,
+}
+
+#[derive(Default)]
+enum BisectState {
+    #[default]
+    Idle,
+    Stages {
+        checkpoint: FrameNumber,
+        inputs: WindowInputs,
+    },
+    Entities {
+        checkpoint: FrameNumber,
+        inputs: WindowInputs,
+        frame_number: FrameNumber,
+        stage: SimulationStage,
+    },
+}
+
+/// Player inputs of the bisected frames, which are needed to reproduce a
+/// divergence.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WindowInputs {
+    pub from: FrameNumber,
+    pub players: Vec<(PlayerNetId, Vec<Option<Vec2>>)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DivergenceReport {
+    pub checkpoint: FrameNumber,
+    pub frame_number: FrameNumber,
+    pub stage: SimulationStage,
+    pub entities: Vec<HashedEntity>,
+    pub inputs: WindowInputs,
+}
+
+impl fmt::Display for DivergenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Simulation diverged from the server before the checkpoint {}: ",
+            self.checkpoint.value()
+        )?;
+        write!(
+            f,
+            "first at frame {}, stage `{}`, entities: [",
+            self.frame_number.value(),
+            self.stage.label()
+        )?;
+        for (i, entity) in self.entities.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{entity}")?;
+        }
+        write!(f, "]")?;
+        for (net_id, inputs) in &self.inputs.players {
+            write!(
+                f,
+                "\n  Player ({}) inputs since frame {}: {:?}",
+                net_id.0,
+                self.inputs.from.value(),
+                inputs
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum BisectStep {
+    Request(StateHashRequest),
+    Report(DivergenceReport),
+}
+
+impl DivergenceBisect {
+    pub fn receive(&mut self, message: StateHashMessage) {
+        self.received.push(message);
+    }
+
+    /// Hashes of the previous session can't be compared with a new one.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Checkpoints are compared only once `server_frame` has passed them,
+    /// which means that the local hashes come from the simulation with
+    /// authoritative inputs.
+    fn process(
+        &mut self,
+        guard: &mut DeterminismGuard,
+        server_frame: FrameNumber,
+        capture_inputs: impl Fn(FrameNumber, FrameNumber) -> WindowInputs,
+    ) -> Vec<BisectStep> {
+        let mut steps = Vec::new();
+        for message in std::mem::take(&mut self.received) {
+            match message {
+                StateHashMessage::Checkpoint { frame_number, hash } => {
+                    guard.enabled = true;
+                    if self.reports < MAX_REPORTS_PER_SESSION {
+                        self.pending_checkpoints.push_back((frame_number, hash));
+                    }
+                }
+                StateHashMessage::Stages(authoritative) => {
+                    let BisectState::Stages { checkpoint, inputs } =
+                        std::mem::take(&mut self.state)
+                    else {
+                        continue;
+                    };
+                    let local = guard.stage_hashes(
+                        checkpoint - FrameNumber::new(BISECT_WINDOW_FRAMES),
+                        checkpoint,
+                    );
+                    let Some((frame_number, stage)) = first_divergent_stage(&local, &authoritative)
+                    else {
+                        log::warn!(
+                            "Couldn't find a divergent stage before the checkpoint {}",
+                            checkpoint.value()
+                        );
+                        continue;
+                    };
+                    steps.push(BisectStep::Request(StateHashRequest::Entities {
+                        frame_number,
+                        stage,
+                    }));
+                    self.state = BisectState::Entities {
+                        checkpoint,
+                        inputs,
+                        frame_number,
+                        stage,
+                    };
+                }
+                StateHashMessage::Entities {
+                    frame_number,
+                    stage,
+                    entities: authoritative,
+                } => {
+                    let BisectState::Entities {
+                        checkpoint, inputs, ..
+                    } = std::mem::take(&mut self.state)
+                    else {
+                        continue;
+                    };
+                    let local = guard.entity_hashes(frame_number, stage).unwrap_or_default();
+                    self.reports += 1;
+                    steps.push(BisectStep::Report(DivergenceReport {
+                        checkpoint,
+                        frame_number,
+                        stage,
+                        entities: divergent_entities(&local, &authoritative),
+                        inputs,
+                    }));
+                }
+                StateHashMessage::Expired => {
+                    if !matches!(self.state, BisectState::Idle) {
+                        log::warn!("Couldn't bisect a divergence: server hashes have expired");
+                        self.state = BisectState::Idle;
+                    }
+                }
+            }
+        }
+
+        while matches!(self.state, BisectState::Idle) && self.reports < MAX_REPORTS_PER_SESSION {
+            let Some(&(checkpoint, hash)) = self.pending_checkpoints.front() else {
+                break;
+            };
+            if checkpoint + FrameNumber::new(TICKS_PER_NETWORK_BROADCAST) >= server_frame {
+                break;
+            }
+            self.pending_checkpoints.pop_front();
+            // Frames simulated before the guard got enabled aren't recorded.
+            if guard
+                .final_hash(checkpoint)
+                .map_or(true, |local| local == hash)
+            {
+                continue;
+            }
+
+            let from = checkpoint - FrameNumber::new(BISECT_WINDOW_FRAMES);
+            log::warn!(
+                "State hash mismatch at the checkpoint {}, bisecting",
+                checkpoint.value()
+            );
+            steps.push(BisectStep::Request(StateHashRequest::Stages {
+                from,
+                to: checkpoint,
+            }));
+            self.state = BisectState::Stages {
+                checkpoint,
+                inputs: capture_inputs(from, checkpoint),
+            };
+        }
+
+        steps
+    }
+}
+
+pub fn bisect_divergence_system(
+    mut bisect: ResMut<DivergenceBisect>,
+    mut guard: ResMut<DeterminismGuard>,
+    mut player_requests: ResMut<PlayerRequestsQueue>,
+    time: Res<SimulationTime>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    players: Query<(Entity, &PlayerDirection)>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let capture_inputs = |from: FrameNumber, to: FrameNumber| {
+        let mut players = players
+            .iter()
+            .filter_map(|(entity, direction)| {
+                let net_id = player_registry.get_id(entity)?;
+                let mut inputs = Vec::new();
+                let mut frame_number = from;
+                while frame_number <= to {
+                    inputs.push(direction.buffer.get(frame_number).copied().flatten());
+                    frame_number += FrameNumber::new(1);
+                }
+                Some((net_id, inputs))
+            })
+            .collect::<Vec<_>>();
+        players.sort_by_key(|(net_id, _)| net_id.0);
+        WindowInputs { from, players }
+    };
+
+    for step in bisect.process(&mut guard, time.server_frame, capture_inputs) {
+        match step {
+            BisectStep::Request(request) => player_requests.state_hash.push(request),
+            BisectStep::Report(report) => log::error!("{report}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(value: u16) -> FrameNumber {
+        FrameNumber::new(value)
+    }
+
+    fn record(guard: &mut DeterminismGuard, frame_number: u16, player_hash: u64) {
+        for stage in [SimulationStage::Game, SimulationStage::Physics] {
+            let hash = if stage == SimulationStage::Physics {
+                player_hash
+            } else {
+                1
+            };
+            guard.record(
+                frame(frame_number),
+                stage,
+                HashedEntity::Player(PlayerNetId(1)),
+                hash,
+            );
+        }
+    }
+
+    #[test]
+    fn test_divergence_bisect() {
+        let inputs = WindowInputs {
+            from: frame(100 - BISECT_WINDOW_FRAMES),
+            players: vec![(PlayerNetId(1), vec![Some(Vec2::X)])],
+        };
+        let capture_inputs = |_, _| inputs.clone();
+        let mut local = DeterminismGuard::default();
+        let mut server = DeterminismGuard::default();
+        for frame_number in 90..=100 {
+            record(&mut local, frame_number, 1);
+            record(&mut server, frame_number, (frame_number >= 95) as u64 + 1);
+        }
+
+        let mut bisect = DivergenceBisect::default();
+        bisect.receive(StateHashMessage::Checkpoint {
+            frame_number: frame(100),
+            hash: server.final_hash(frame(100)).unwrap(),
+        });
+        // The checkpoint isn't compared until the client catches up with it.
+        assert!(bisect
+            .process(&mut local, frame(101), capture_inputs)
+            .is_empty());
+        assert!(local.enabled);
+
+        let steps = bisect.process(&mut local, frame(110), capture_inputs);
+        let [BisectStep::Request(StateHashRequest::Stages { from, to })] = steps[..] else {
+            panic!("Unexpected steps: {steps:?}");
+        };
+
+        bisect.receive(StateHashMessage::Stages(server.stage_hashes(from, to)));
+        let steps = bisect.process(&mut local, frame(111), capture_inputs);
+        assert_eq!(
+            steps,
+            vec![BisectStep::Request(StateHashRequest::Entities {
+                frame_number: frame(95),
+                stage: SimulationStage::Physics,
+            })]
+        );
+
+        bisect.receive(StateHashMessage::Entities {
+            frame_number: frame(95),
+            stage: SimulationStage::Physics,
+            entities: server
+                .entity_hashes(frame(95), SimulationStage::Physics)
+                .unwrap(),
+        });
+        let steps = bisect.process(&mut local, frame(112), capture_inputs);
+        assert_eq!(
+            steps,
+            vec![BisectStep::Report(DivergenceReport {
+                checkpoint: frame(100),
+                frame_number: frame(95),
+                stage: SimulationStage::Physics,
+                entities: vec![HashedEntity::Player(PlayerNetId(1))],
+                inputs: inputs.clone(),
+            })]
+        );
+
+        // Matching checkpoints don't trigger bisecting.
+        bisect.receive(StateHashMessage::Checkpoint {
+            frame_number: frame(100),
+            hash: local.final_hash(frame(100)).unwrap(),
+        });
+        assert!(bisect
+            .process(&mut local, frame(113), capture_inputs)
+            .is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    net::{ConnectedServer, ServerToConnect},
+    CurrentPlayerNetId, MuddleClientConfig,
+};
+use bevy::{
+    ecs::system::{Commands, Local, Res, ResMut, Resource},
+    log,
+    utils::Instant,
+};
+use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+use mr_messages_lib::{GameServerState, Server, PLAYER_CAPACITY};
+use mr_shared_lib::{
+    player::{PlayerRole, Players},
+    GameTime,
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::sync::mpsc::{
+    error::TryRecvError, unbounded_channel, UnboundedReceiver, UnboundedSender,
+};
+
+/// Discord drops activity updates that are sent more often than once per 15
+/// seconds (it allows bursts of 5 updates), so we don't want to spam it.
+const PRESENCE_UPDATE_PERIOD: Duration = Duration::from_secs(5);
+const RECONNECT_PERIOD: Duration = Duration::from_secs(15);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Presence {
+    pub level_title: Option<String>,
+    pub role: Option<PlayerRole>,
+    pub player_count: u16,
+    pub started_at: Option<i64>,
+    /// Discord requires a party id for the "Ask to join" button to work.
+    pub party_id: Option<String>,
+    /// Contains the server to join, see `encode_join_secret`.
+    pub join_secret: Option<String>,
+}
+
+#[derive(Resource)]
+pub struct DiscordChannels {
+    presence_tx: UnboundedSender<Presence>,
+    join_secret_rx: UnboundedReceiver<String>,
+}
+
+pub fn init_discord_presence_system(
+    mut commands: Commands,
+    client_config: Res<MuddleClientConfig>,
+) {
+    let Some(client_id) = client_config.discord_client_id.clone() else {
+        log::info!("Discord client id wasn't passed, skipping the rich presence initialization");
+        return;
+    };
+
+    let (presence_tx, presence_rx) = unbounded_channel();
+    let (join_secret_tx, join_secret_rx) = unbounded_channel();
+
+    // Discord IPC clients are blocking, so we keep a separate connection for
+    // listening to join requests, to avoid blocking presence updates.
+    let presence_client_id = client_id.clone();
+    std::thread::spawn(move || serve_presence_updates(presence_client_id, presence_rx));
+    std::thread::spawn(move || serve_join_requests(client_id, join_secret_tx));
+
+    commands.insert_resource(DiscordChannels {
+        presence_tx,
+        join_secret_rx,
+    });
+}
+
+pub fn update_discord_presence_system(
+    mut last_sent: Local<Option<(Instant, Presence)>>,
+    mut session_started_at: Local<(usize, Option<i64>)>,
+    discord_channels: Option<Res<DiscordChannels>>,
+    time: Res<GameTime>,
+    players: Res<Players>,
+    current_player_net_id: Res<CurrentPlayerNetId>,
+    connected_server: Res<ConnectedServer>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let Some(discord_channels) = discord_channels else {
+        return;
+    };
+
+    let current_player = current_player_net_id
+        .0
+        .and_then(|net_id| players.get(&net_id));
+    // `GameTime::session` is incremented every time a client starts a new game.
+    if current_player.is_none() {
+        *session_started_at = (time.session, None);
+    } else if session_started_at.0 != time.session || session_started_at.1.is_none() {
+        *session_started_at = (time.session, Some(chrono::Utc::now().timestamp()));
+    }
+
+    let presence = Presence {
+        level_title: connected_server.level_title.clone(),
+        role: current_player.map(|player| player.role),
+        player_count: players
+            .values()
+            .filter(|player| player.is_connected)
+            .count() as u16,
+        started_at: session_started_at.1,
+        party_id: connected_server
+            .server
+            .as_ref()
+            .map(|server| server.name.clone()),
+        join_secret: connected_server.server.as_ref().map(encode_join_secret),
+    };
+
+    if let Some((sent_at, last_presence)) = &*last_sent {
+        if *last_presence == presence
+            || Instant::now().duration_since(*sent_at) < PRESENCE_UPDATE_PERIOD
+        {
+            return;
+        }
+    }
+
+    if let Err(err) = discord_channels.presence_tx.send(presence.clone()) {
+        log::error!("Failed to send a Discord presence update: {:?}", err);
+    }
+    *last_sent = Some((Instant::now(), presence));
+}
+
+pub fn process_discord_join_requests_system(
+    discord_channels: Option<ResMut<DiscordChannels>>,
+    mut server_to_connect: ResMut<ServerToConnect>,
+) {
+    let Some(mut discord_channels) = discord_channels else {
+        return;
+    };
+
+    loop {
+        match discord_channels.join_secret_rx.try_recv() {
+            Ok(join_secret) => match decode_join_secret(&join_secret) {
+                Some(server) => {
+                    log::info!(
+                        "Joining a Discord friend: {} ({})",
+                        server.name,
+                        server.addr
+                    );
+                    **server_to_connect = Some(server);
+                }
+                None => {
+                    log::error!("Invalid Discord join secret: {join_secret}");
+                }
+            },
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                panic!("Failed to read from a channel (Discord join requests)")
+            }
+        }
+    }
+}
+
+fn encode_join_secret(server: &Server) -> String {
+    format!("{}/{}", server.addr, server.name)
+}
+
+fn decode_join_secret(join_secret: &str) -> Option<Server> {
+    let (addr, name) = join_secret.split_once('/')?;
+    let addr: SocketAddr = addr.parse().ok()?;
+    Some(Server {
+        name: name.to_owned(),
+        state: GameServerState::Allocated,
+        addr,
+        player_capacity: PLAYER_CAPACITY,
+        player_count: 0,
+        request_id: Default::default(),
+        version: Default::default(),
+        draining: false,
+    })
+}
+
+fn connect(client_id: &str) -> DiscordIpcClient {
+    loop {
+        match DiscordIpcClient::new(client_id).and_then(|mut client| {
+            client.connect()?;
+            Ok(client)
+        }) {
+            Ok(client) => {
+                log::info!("Connected to Discord");
+                return client;
+            }
+            Err(err) => {
+                log::debug!("Failed to connect to Discord (retrying later): {:?}", err);
+                std::thread::sleep(RECONNECT_PERIOD);
+            }
+        }
+    }
+}
+
+fn serve_presence_updates(client_id: String, mut presence_rx: UnboundedReceiver<Presence>) {
+    let mut client = connect(&client_id);
+
+    while let Some(presence) = presence_rx.blocking_recv() {
+        let details = match (presence.role, &presence.level_title) {
+            (None, _) => "In the main menu".to_owned(),
+            (Some(PlayerRole::Runner), Some(level_title)) => format!("Running: {level_title}"),
+            (Some(PlayerRole::Builder), Some(level_title)) => format!("Building: {level_title}"),
+            (Some(PlayerRole::Runner), None) => "Running".to_owned(),
+            (Some(PlayerRole::Builder), None) => "Building".to_owned(),
+            (Some(PlayerRole::Spectator), Some(level_title)) => {
+                format!("Spectating: {level_title}")
+            }
+            (Some(PlayerRole::Spectator), None) => "Spectating".to_owned(),
+        };
+
+        let mut activity = activity::Activity::new().details(&details);
+        if presence.role.is_some() {
+            let mut party =
+                activity::Party::new().size([presence.player_count as i32, PLAYER_CAPACITY as i32]);
+            if let Some(party_id) = &presence.party_id {
+                party = party.id(party_id);
+            }
+            activity = activity.state("In a game").party(party);
+        }
+        if let Some(started_at) = presence.started_at {
+            activity = activity.timestamps(activity::Timestamps::new().start(started_at));
+        }
+        if let Some(join_secret) = &presence.join_secret {
+            activity = activity.secrets(activity::Secrets::new().join(join_secret));
+        }
+
+        if let Err(err) = client.set_activity(activity) {
+            log::warn!("Failed to update Discord presence, reconnecting: {:?}", err);
+            client = connect(&client_id);
+        }
+    }
+    log::warn!("Discord presence channel is closed");
+}
+
+fn serve_join_requests(client_id: String, join_secret_tx: UnboundedSender<String>) {
+    'connection: loop {
+        let mut client = connect(&client_id);
+        let subscribe = serde_json::json!({
+            "cmd": "SUBSCRIBE",
+            "evt": "ACTIVITY_JOIN",
+            "args": {},
+            "nonce": "activity_join",
+        });
+        // Opcode 1 stands for frames (commands and events).
+        if let Err(err) = client.send(subscribe, 1) {
+            log::warn!("Failed to subscribe to Discord join requests: {:?}", err);
+            std::thread::sleep(RECONNECT_PERIOD);
+            continue;
+        }
+
+        loop {
+            let payload = match client.recv() {
+                Ok((_opcode, payload)) => payload,
+                Err(err) => {
+                    log::warn!("Discord connection error, reconnecting: {:?}", err);
+                    continue 'connection;
+                }
+            };
+            if payload["evt"] != "ACTIVITY_JOIN" {
+                continue;
+            }
+            let Some(join_secret) = payload["data"]["secret"].as_str() else {
+                log::error!("Invalid Discord ACTIVITY_JOIN event: {payload}");
+                continue;
+            };
+            if join_secret_tx.send(join_secret.to_owned()).is_err() {
+                log::warn!("Discord join requests channel is closed");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,636 @@
+//! Builders' edits are recorded as they get sent to the server, so that they
+//! can be undone (Ctrl+Z) and redone (Ctrl+Shift+Z) by sending the inverse
+//! requests.
+//!
+//! Requests that are sent together (group transforms of several selected
+//! objects, for instance) are recorded as a batch and get undone at once.
+//!
+//! Despawned objects can only be brought back as new objects, which get new
+//! net ids. Once the server confirms such a spawn, the recorded edits (and
+//! routes) referencing the old id are updated to reference the new one.
+
+use crate::{input::LevelObjectRequestsQueue, LevelObjectCorrelations};
+use bevy::{
+    ecs::system::Resource,
+    log,
+    utils::{Duration, Instant},
+};
+use mr_shared_lib::{
+    game::level::{LevelObject, LevelState, ObjectRouteDesc},
+    messages::{EntityNetId, SpawnLevelObjectRequest, SpawnLevelObjectRequestBody},
+    net::{MessageId, SessionId},
+};
+
+pub const EDIT_HISTORY_LIMIT: usize = 100;
+/// Updates of the same object that follow each other within this interval
+/// (dragging an object, for instance) are merged into a single edit.
+pub const MERGE_UPDATES_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EditedObject {
+    /// Is waiting for the server to confirm the spawn.
+    Pending(MessageId),
+    Spawned(EntityNetId),
+}
+
+#[derive(Clone, Debug)]
+struct LevelEdit {
+    object: EditedObject,
+    /// Is `None` for spawns.
+    before: Option<LevelObject>,
+    /// Is `None` for despawns. For spawns, it's filled when they get undone.
+    after: Option<LevelObject>,
+    edited_at: Instant,
+    /// Edits recorded by the same [`LevelEditHistory::record`] call share it.
+    batch: usize,
+}
+
+struct PendingRespawn {
+    correlation_id: MessageId,
+    old_net_id: EntityNetId,
+    object: LevelObject,
+}
+
+#[derive(Resource, Default)]
+pub struct LevelEditHistory {
+    /// Net ids are valid only within a session, so the history is cleared
+    /// when a new one starts.
+    session_id: Option<SessionId>,
+    undo: Vec<LevelEdit>,
+    redo: Vec<LevelEdit>,
+    pending_respawns: Vec<PendingRespawn>,
+    /// The requests pushed by undoing or redoing, which mustn't be recorded.
+    replayed_updates: Vec<LevelObject>,
+    replayed_despawns: Vec<EntityNetId>,
+    next_batch: usize,
+}
+
+impl LevelEditHistory {
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Is expected to be called with the queued requests right before they are
+    /// sent, while `level_state` still reflects the state before the edits.
+    pub fn record(
+        &mut self,
+        session_id: SessionId,
+        requests: &LevelObjectRequestsQueue,
+        level_state: &LevelState,
+        now: Instant,
+    ) {
+        if self.session_id != Some(session_id) {
+            *self = Self {
+                session_id: Some(session_id),
+                ..Default::default()
+            };
+        }
+        let batch = self.next_batch;
+        self.next_batch = self.next_batch.wrapping_add(1);
+        let last_batch = self.undo.last().map(|edit| edit.batch);
+
+        for spawn_request in &requests.spawn_requests {
+            let is_replayed = self
+                .pending_respawns
+                .iter()
+                .any(|respawn| respawn.correlation_id == spawn_request.correlation_id);
+            if !is_replayed {
+                self.push(LevelEdit {
+                    object: EditedObject::Pending(spawn_request.correlation_id),
+                    before: None,
+                    after: None,
+                    edited_at: now,
+                    batch,
+                });
+            }
+        }
+
+        for object in &requests.update_requests {
+            if let Some(i) = self.replayed_updates.iter().position(|o| o == object) {
+                self.replayed_updates.remove(i);
+                continue;
+            }
+            let edited_object = EditedObject::Spawned(object.net_id);
+            if let Some(last_edit) = self
+                .undo
+                .iter_mut()
+                .rev()
+                .take_while(|edit| Some(edit.batch) == last_batch)
+                .find(|edit| {
+                    edit.object == edited_object
+                        && now.saturating_duration_since(edit.edited_at) < MERGE_UPDATES_INTERVAL
+                })
+            {
+                last_edit.after = Some(object.clone());
+                last_edit.edited_at = now;
+                continue;
+            }
+            // Objects that the server hasn't spawned yet can't be reverted.
+            let Some(before) = level_state.object(object.net_id) else {
+                continue;
+            };
+            self.push(LevelEdit {
+                object: edited_object,
+                before: Some(before.clone()),
+                after: Some(object.clone()),
+                edited_at: now,
+                batch,
+            });
+        }
+
+        for net_id in &requests.despawn_requests {
+            if let Some(i) = self.replayed_despawns.iter().position(|id| id == net_id) {
+                self.replayed_despawns.remove(i);
+                continue;
+            }
+            let Some(before) = level_state.object(*net_id) else {
+                continue;
+            };
+            self.push(LevelEdit {
+                object: EditedObject::Spawned(*net_id),
+                before: Some(before.clone()),
+                after: None,
+                edited_at: now,
+                batch,
+            });
+        }
+    }
+
+    /// Picks up the net ids of the objects that the server has spawned.
+    pub fn resolve_spawns(
+        &mut self,
+        correlations: &LevelObjectCorrelations,
+        requests: &mut LevelObjectRequestsQueue,
+    ) {
+        for edit in self.undo.iter_mut().chain(self.redo.iter_mut()) {
+            if let EditedObject::Pending(correlation_id) = edit.object {
+                if let Some(net_id) = correlations.query(correlation_id) {
+                    edit.object = EditedObject::Spawned(net_id);
+                }
+            }
+        }
+
+        for respawn in std::mem::take(&mut self.pending_respawns) {
+            let Some(net_id) = correlations.query(respawn.correlation_id) else {
+                self.pending_respawns.push(respawn);
+                continue;
+            };
+            self.remap(respawn.old_net_id, net_id);
+            // Spawn requests carry only descriptions, so the rest is restored
+            // with an update.
+            let object = LevelObject {
+                net_id,
+                ..respawn.object
+            };
+            self.replayed_updates.push(object.clone());
+            requests.update_requests.push(object);
+        }
+    }
+
+    /// Returns `false` if there's nothing to undo or the last edits aren't
+    /// confirmed by the server yet.
+    pub fn undo(
+        &mut self,
+        level_state: &LevelState,
+        requests: &mut LevelObjectRequestsQueue,
+        correlations: &mut LevelObjectCorrelations,
+    ) -> bool {
+        self.resolve_spawns(correlations, requests);
+        let batch = pop_batch(&mut self.undo);
+        if batch.is_empty() {
+            return false;
+        }
+        let Some(net_ids) = spawned_net_ids(&batch) else {
+            log::warn!("Can't undo a spawn that isn't confirmed by the server yet");
+            self.undo.extend(batch.into_iter().rev());
+            return false;
+        };
+        for (mut edit, net_id) in batch.into_iter().zip(net_ids) {
+            if edit.before.is_none() {
+                // Redoing a spawn brings back the object in its latest state.
+                edit.after = level_state.object(net_id).cloned().or(edit.after);
+            }
+            edit.object = self.revert(
+                net_id,
+                edit.before.as_ref(),
+                level_state,
+                requests,
+                correlations,
+            );
+            self.redo.push(edit);
+        }
+        true
+    }
+
+    /// Returns `false` if there's nothing to redo or the last undone edits
+    /// aren't confirmed by the server yet.
+    pub fn redo(
+        &mut self,
+        level_state: &LevelState,
+        requests: &mut LevelObjectRequestsQueue,
+        correlations: &mut LevelObjectCorrelations,
+    ) -> bool {
+        self.resolve_spawns(correlations, requests);
+        let batch = pop_batch(&mut self.redo);
+        if batch.is_empty() {
+            return false;
+        }
+        let Some(net_ids) = spawned_net_ids(&batch) else {
+            log::warn!("Can't redo an edit that isn't confirmed by the server yet");
+            self.redo.extend(batch.into_iter().rev());
+            return false;
+        };
+        for (mut edit, net_id) in batch.into_iter().zip(net_ids) {
+            if edit.after.is_none() {
+                edit.before = level_state.object(net_id).cloned().or(edit.before);
+            }
+            edit.object = self.revert(
+                net_id,
+                edit.after.as_ref(),
+                level_state,
+                requests,
+                correlations,
+            );
+            self.undo.push(edit);
+        }
+        true
+    }
+
+    fn push(&mut self, edit: LevelEdit) {
+        if self.undo.len() == EDIT_HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    /// Pushes the requests that bring the object to the `target` state.
+    fn revert(
+        &mut self,
+        net_id: EntityNetId,
+        target: Option<&LevelObject>,
+        level_state: &LevelState,
+        requests: &mut LevelObjectRequestsQueue,
+        correlations: &mut LevelObjectCorrelations,
+    ) -> EditedObject {
+        match (target, level_state.object(net_id)) {
+            (None, Some(_)) => {
+                self.replayed_despawns.push(net_id);
+                requests.despawn_requests.push(net_id);
+                EditedObject::Spawned(net_id)
+            }
+            // Someone else has already despawned the object.
+            (None, None) => EditedObject::Spawned(net_id),
+            (Some(target), Some(_)) => {
+                let object = LevelObject {
+                    net_id,
+                    ..target.clone()
+                };
+                self.replayed_updates.push(object.clone());
+                requests.update_requests.push(object);
+                EditedObject::Spawned(net_id)
+            }
+            (Some(target), None) => {
+                let correlation_id = correlations.next_correlation_id();
+                self.pending_respawns.push(PendingRespawn {
+                    correlation_id,
+                    old_net_id: net_id,
+                    object: target.clone(),
+                });
+                requests.spawn_requests.push(SpawnLevelObjectRequest {
+                    correlation_id,
+                    body: SpawnLevelObjectRequestBody::New(target.desc.clone()),
+                });
+                EditedObject::Pending(correlation_id)
+            }
+        }
+    }
+
+    fn remap(&mut self, old_net_id: EntityNetId, new_net_id: EntityNetId) {
+        let remap_id = |net_id: &mut EntityNetId| {
+            if *net_id == old_net_id {
+                *net_id = new_net_id;
+            }
+        };
+        let edits = self.undo.iter_mut().chain(self.redo.iter_mut());
+        for edit in edits {
+            if edit.object == EditedObject::Spawned(old_net_id) {
+                edit.object = EditedObject::Spawned(new_net_id);
+            }
+            for object in edit.before.iter_mut().chain(edit.after.iter_mut()) {
+                remap_id(&mut object.net_id);
+                match object.route.as_mut().map(|route| &mut route.desc) {
+                    Some(ObjectRouteDesc::Attached(net_id) | ObjectRouteDesc::Radial(net_id)) => {
+                        net_id.iter_mut().for_each(remap_id);
+                    }
+                    Some(ObjectRouteDesc::ForwardCycle(net_ids)) => {
+                        net_ids.iter_mut().for_each(remap_id);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Pops the edits that were recorded together with the last one, starting
+/// with the last one.
+fn pop_batch(edits: &mut Vec<LevelEdit>) -> Vec<LevelEdit> {
+    let Some(batch) = edits.last().map(|edit| edit.batch) else {
+        return Vec::new();
+    };
+    let len = edits
+        .iter()
+        .rev()
+        .take_while(|edit| edit.batch == batch)
+        .count();
+    let mut popped = edits.split_off(edits.len() - len);
+    popped.reverse();
+    popped
+}
+
+/// Returns `None` if any of the edited objects is still waiting for the
+/// server to confirm the spawn.
+fn spawned_net_ids(batch: &[LevelEdit]) -> Option<Vec<EntityNetId>> {
+    batch
+        .iter()
+        .map(|edit| match edit.object {
+            EditedObject::Spawned(net_id) => Some(net_id),
+            EditedObject::Pending(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::Vec2;
+    use mr_shared_lib::{
+        game::{
+            commands::{DespawnLevelObject, UpdateLevelObject},
+            level::CollisionLogic,
+        },
+        test_harness::cube,
+    };
+
+    /// Applies the requests as the server would, and confirms the spawns.
+    fn apply(
+        level_state: &mut LevelState,
+        requests: &mut LevelObjectRequestsQueue,
+        correlations: &mut LevelObjectCorrelations,
+        next_net_id: &mut u16,
+    ) {
+        for spawn_request in std::mem::take(&mut requests.spawn_requests) {
+            let SpawnLevelObjectRequestBody::New(desc) = spawn_request.body else {
+                unreachable!()
+            };
+            let object = LevelObject {
+                net_id: EntityNetId(*next_net_id),
+                label: String::new(),
+                desc,
+                route: None,
+                collision_logic: CollisionLogic::None,
+            };
+            *next_net_id += 1;
+            correlations.correlate(spawn_request.correlation_id, object.net_id);
+            level_state.apply_update(&UpdateLevelObject {
+                object,
+                frame_number: Default::default(),
+            });
+        }
+        for object in std::mem::take(&mut requests.update_requests) {
+            level_state.apply_update(&UpdateLevelObject {
+                object,
+                frame_number: Default::default(),
+            });
+        }
+        for net_id in std::mem::take(&mut requests.despawn_requests) {
+            level_state.apply_despawn(&DespawnLevelObject {
+                net_id,
+                frame_number: Default::default(),
+            });
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let session_id = SessionId::new(0);
+        let mut history = LevelEditHistory::default();
+        let mut level_state = LevelState::default();
+        let mut requests = LevelObjectRequestsQueue::default();
+        let mut correlations = LevelObjectCorrelations::default();
+        let mut next_net_id = 1;
+        let mut now = Instant::now();
+        level_state.apply_update(&UpdateLevelObject {
+            object: cube(0, Vec2::ZERO, 1.0),
+            frame_number: Default::default(),
+        });
+
+        // Dragging a slider results in several updates, which are undone at once.
+        for size in [1.5, 2.0] {
+            requests.update_requests.push(cube(0, Vec2::ZERO, size));
+            history.record(session_id, &requests, &level_state, now);
+            apply(
+                &mut level_state,
+                &mut requests,
+                &mut correlations,
+                &mut next_net_id,
+            );
+            now += Duration::from_millis(100);
+        }
+        now += MERGE_UPDATES_INTERVAL;
+        requests.despawn_requests.push(EntityNetId(0));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert!(level_state.objects().is_empty());
+
+        // Undoing the despawn brings the object back with a new id.
+        assert!(history.undo(&level_state, &mut requests, &mut correlations));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        history.resolve_spawns(&correlations, &mut requests);
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert_eq!(
+            level_state.object(EntityNetId(1)),
+            Some(&cube(1, Vec2::ZERO, 2.0))
+        );
+
+        // The update is undone for the new object.
+        assert!(history.undo(&level_state, &mut requests, &mut correlations));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert_eq!(
+            level_state.object(EntityNetId(1)),
+            Some(&cube(1, Vec2::ZERO, 1.0))
+        );
+        assert!(!history.can_undo());
+
+        // Undoing and redoing doesn't get recorded as new edits.
+        assert!(history.redo(&level_state, &mut requests, &mut correlations));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert_eq!(
+            level_state.object(EntityNetId(1)),
+            Some(&cube(1, Vec2::ZERO, 2.0))
+        );
+        assert!(history.redo(&level_state, &mut requests, &mut correlations));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert!(level_state.objects().is_empty());
+        assert!(!history.can_redo());
+        assert!(history.can_undo());
+
+        // Net ids of the previous session are meaningless in a new one.
+        history.record(SessionId::new(1), &requests, &level_state, now);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn test_undo_spawn() {
+        let session_id = SessionId::new(0);
+        let mut history = LevelEditHistory::default();
+        let mut level_state = LevelState::default();
+        let mut requests = LevelObjectRequestsQueue::default();
+        let mut correlations = LevelObjectCorrelations::default();
+        let mut next_net_id = 0;
+        let now = Instant::now();
+
+        let correlation_id = correlations.next_correlation_id();
+        requests.spawn_requests.push(SpawnLevelObjectRequest {
+            correlation_id,
+            body: SpawnLevelObjectRequestBody::New(cube(0, Vec2::ZERO, 1.0).desc),
+        });
+        history.record(session_id, &requests, &level_state, now);
+        // The spawn can't be undone until the server confirms it.
+        assert!(!history.undo(&level_state, &mut requests, &mut correlations));
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+
+        assert!(history.undo(&level_state, &mut requests, &mut correlations));
+        assert_eq!(requests.despawn_requests, vec![EntityNetId(0)]);
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert!(level_state.objects().is_empty());
+
+        assert!(history.redo(&level_state, &mut requests, &mut correlations));
+        assert_eq!(requests.spawn_requests.len(), 1);
+    }
+
+    #[test]
+    fn test_undo_batch() {
+        let session_id = SessionId::new(0);
+        let mut history = LevelEditHistory::default();
+        let mut level_state = LevelState::default();
+        let mut requests = LevelObjectRequestsQueue::default();
+        let mut correlations = LevelObjectCorrelations::default();
+        let mut next_net_id = 2;
+        let mut now = Instant::now();
+        for net_id in 0..2 {
+            level_state.apply_update(&UpdateLevelObject {
+                object: cube(net_id, Vec2::ZERO, 1.0),
+                frame_number: Default::default(),
+            });
+        }
+
+        // Dragging a group of objects results in batches of updates, which are
+        // merged as well.
+        for size in [1.5, 2.0] {
+            requests
+                .update_requests
+                .extend([cube(0, Vec2::ZERO, size), cube(1, Vec2::ZERO, size)]);
+            history.record(session_id, &requests, &level_state, now);
+            apply(
+                &mut level_state,
+                &mut requests,
+                &mut correlations,
+                &mut next_net_id,
+            );
+            now += Duration::from_millis(100);
+        }
+        now += MERGE_UPDATES_INTERVAL;
+        requests.update_requests.push(cube(1, Vec2::ZERO, 3.0));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+
+        assert!(history.undo(&level_state, &mut requests, &mut correlations));
+        assert_eq!(requests.update_requests, vec![cube(1, Vec2::ZERO, 2.0)]);
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+
+        // The whole group is reverted at once.
+        assert!(history.undo(&level_state, &mut requests, &mut correlations));
+        history.record(session_id, &requests, &level_state, now);
+        apply(
+            &mut level_state,
+            &mut requests,
+            &mut correlations,
+            &mut next_net_id,
+        );
+        assert_eq!(
+            level_state.object(EntityNetId(0)),
+            Some(&cube(0, Vec2::ZERO, 1.0))
+        );
+        assert_eq!(
+            level_state.object(EntityNetId(1)),
+            Some(&cube(1, Vec2::ZERO, 1.0))
+        );
+        assert!(!history.can_undo());
+
+        assert!(history.redo(&level_state, &mut requests, &mut correlations));
+        assert_eq!(requests.update_requests.len(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::components::LevelLightTag;
+use bevy::{
+    asset::{AssetServer, Assets, Handle},
+    audio::{Audio, AudioSink, PlaybackSettings},
+    core_pipeline::clear_color::ClearColor,
+    ecs::{
+        query::With,
+        system::{Local, Query, Res, ResMut, SystemParam},
+    },
+    log,
+    math::Vec3,
+    pbr::DirectionalLight,
+    render::color::Color,
+    transform::components::Transform,
+};
+use iyes_loopless::state::CurrentState;
+use mr_shared_lib::{
+    game::level::{LevelSettings, LevelState, MusicTrack},
+    AppState,
+};
+
+#[derive(SystemParam)]
+pub struct MusicParams<'w, 's> {
+    asset_server: Res<'w, AssetServer>,
+    audio: Res<'w, Audio>,
+    audio_sinks: Res<'w, Assets<AudioSink>>,
+    playing: Local<'s, Option<(MusicTrack, Handle<AudioSink>)>>,
+}
+
+impl<'w, 's> MusicParams<'w, 's> {
+    fn play(&mut self, music_track: Option<MusicTrack>) {
+        if self.playing.as_ref().map(|(track, _)| *track) == music_track {
+            return;
+        }
+
+        if let Some((track, sink)) = self.playing.take() {
+            log::debug!("Stopping music track: {}", track);
+            if let Some(sink) = self.audio_sinks.get(&sink) {
+                sink.stop();
+            }
+        }
+
+        if let Some(track) = music_track {
+            log::debug!("Playing music track: {}", track);
+            let source = self.asset_server.load(track.asset_path());
+            let sink = self
+                .audio
+                .play_with_settings(source, PlaybackSettings::LOOP);
+            // `Audio` returns weak handles, a strong one keeps the sink alive.
+            *self.playing = Some((track, self.audio_sinks.get_handle(sink)));
+        }
+    }
+}
+
+/// Applies the settings of the current level to the scene. The default
+/// settings are applied when there's no level being played.
+pub fn apply_level_settings_system(
+    mut applied_settings: Local<Option<LevelSettings>>,
+    app_state: Res<CurrentState<AppState>>,
+    level_state: Res<LevelState>,
+    mut clear_color: ResMut<ClearColor>,
+    mut lights: Query<(&mut DirectionalLight, &mut Transform), With<LevelLightTag>>,
+    mut music_params: MusicParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let default_settings = LevelSettings::default();
+    let settings = if app_state.0 == AppState::Playing {
+        level_state.settings()
+    } else {
+        &default_settings
+    };
+    if applied_settings.as_ref() == Some(settings) {
+        return;
+    }
+    *applied_settings = Some(settings.clone());
+
+    clear_color.0 = level_clear_color(settings);
+    for (mut light, mut transform) in lights.iter_mut() {
+        light.illuminance = settings.light_illuminance;
+        *transform = level_light_transform(settings);
+    }
+
+    music_params.play(settings.music_track);
+}
+
+pub fn level_clear_color(settings: &LevelSettings) -> Color {
+    let [r, g, b] = settings.clear_color;
+    Color::rgb(r, g, b)
+}
+
+/// Only the direction of a directional light matters.
+pub fn level_light_transform(settings: &LevelSettings) -> Transform {
+    let elevation = settings.light_angle.to_radians();
+    let light_position = Vec3::new(0.0, -elevation.cos(), elevation.sin());
+    Transform::from_translation(light_position).looking_at(Vec3::ZERO, Vec3::X)
+}
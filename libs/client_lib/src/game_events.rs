@@ -1,11 +1,165 @@
-use bevy::ecs::system::{Query, Res};
+use crate::{
+    config_storage::{self, ghost_config_key, GhostConfig},
+    net::ConnectedServer,
+};
+use bevy::{
+    ecs::{
+        entity::Entity,
+        event::EventReader,
+        query::With,
+        system::{Commands, Query, Res, ResMut, Resource},
+    },
+    log,
+    math::Vec2,
+    render::view::Visibility,
+    transform::components::Transform,
+};
 use mr_shared_lib::{
-    framebuffer::FrameNumber, game::components::Spawned, player::PlayerSystemParamsMut,
-    util::PLAYER_RESPAWN_TIME, SimulationTime, SIMULATIONS_PER_SECOND,
+    client::components::PersonalBestGhost,
+    framebuffer::FrameNumber,
+    game::{
+        client_factories::{ClientFactory, PbrClientParams, PlayerGhostClientFactory},
+        components::{PlayerFrameSimulated, PlayerTag, Position, Spawned},
+        events::PlayerFinish,
+        level::LevelState,
+    },
+    player::PlayerSystemParamsMut,
+    SimulationTime, SIMULATIONS_PER_SECOND,
 };
 
+/// Positions of ghosts are sampled less often than the simulation runs, to
+/// keep the stored runs small. Replays interpolate between the samples.
+pub const GHOST_SAMPLE_FRAMES: u16 = 4;
+/// Longer runs aren't recorded.
+pub const GHOST_MAX_RUN_SECS: f32 = 180.0;
+
+/// Records runs of the current player and replays the personal best one on
+/// the played level.
+#[derive(Resource, Default)]
+pub struct GhostRuns {
+    /// The level that `best_run` belongs to.
+    level_id: Option<i64>,
+    best_run: GhostConfig,
+    /// Positions of the current player since the latest spawn, sampled every
+    /// `GHOST_SAMPLE_FRAMES` frames.
+    current_run: Vec<Vec2>,
+    current_run_started_at: Option<FrameNumber>,
+    /// The run stops being recorded once the player finishes (or the run
+    /// becomes too long).
+    is_current_run_finished: bool,
+    ghost_entity: Option<Entity>,
+}
+
+impl GhostRuns {
+    /// Is called for timed finishes that beat the personal best, so that the
+    /// finished run is replayed from now on.
+    pub fn save_current_run(&mut self, level_id: i64) {
+        if !self.is_current_run_finished
+            || self.current_run.is_empty()
+            || self.level_id != Some(level_id)
+        {
+            return;
+        }
+        self.best_run = GhostConfig {
+            sample_frames: GHOST_SAMPLE_FRAMES,
+            positions: self.current_run.clone(),
+        };
+        if let Err(err) = config_storage::write(&ghost_config_key(level_id), &self.best_run) {
+            log::error!("Failed to save a ghost: {:?}", err);
+        }
+    }
+
+    fn load(&mut self, level_id: Option<i64>) {
+        if self.level_id == level_id {
+            return;
+        }
+        self.level_id = level_id;
+        self.best_run = match level_id {
+            Some(level_id) => {
+                config_storage::read(&ghost_config_key(level_id)).unwrap_or_else(|err| {
+                    log::error!("Failed to read a ghost: {:?}", err);
+                    GhostConfig::default()
+                })
+            }
+            None => GhostConfig::default(),
+        };
+    }
+
+    fn record(&mut self, time: &SimulationTime, spawned_at: FrameNumber, position: &Position) {
+        if self.current_run_started_at != Some(spawned_at) {
+            self.current_run_started_at = Some(spawned_at);
+            self.current_run.clear();
+            self.is_current_run_finished = false;
+        }
+        if self.is_current_run_finished {
+            return;
+        }
+
+        // Frames after the server one can be re-simulated once corrections arrive, so
+        // they are recorded again.
+        let recorded_until =
+            spawned_at + FrameNumber::new(self.current_run.len() as u16 * GHOST_SAMPLE_FRAMES);
+        let from = recorded_until.min(time.server_frame).max(spawned_at);
+        for frame_number in from..=time.player_frame {
+            let frames_since_spawn = (frame_number - spawned_at).value();
+            if frames_since_spawn % GHOST_SAMPLE_FRAMES != 0 {
+                continue;
+            }
+            let Some(position) = position.buffer.get(frame_number) else {
+                continue;
+            };
+            let index = (frames_since_spawn / GHOST_SAMPLE_FRAMES) as usize;
+            if index >= ghost_max_samples() {
+                self.current_run.clear();
+                self.is_current_run_finished = true;
+                return;
+            }
+            if index < self.current_run.len() {
+                self.current_run[index] = *position;
+            } else {
+                self.current_run.resize(index + 1, *position);
+            }
+        }
+    }
+
+    fn finish_current_run(&mut self, frame_number: FrameNumber) {
+        let Some(started_at) = self.current_run_started_at else {
+            return;
+        };
+        if self.is_current_run_finished {
+            return;
+        }
+        let samples = ((frame_number - started_at).value() / GHOST_SAMPLE_FRAMES) as usize + 1;
+        self.current_run.truncate(samples);
+        self.is_current_run_finished = true;
+    }
+
+    /// Returns `None` once the best run is over.
+    fn replay_position(&self, frames_since_spawn: u16) -> Option<Vec2> {
+        let sample_frames = self.best_run.sample_frames;
+        if sample_frames == 0 {
+            return None;
+        }
+        let index = (frames_since_spawn / sample_frames) as usize;
+        let from = *self.best_run.positions.get(index)?;
+        let to = self
+            .best_run
+            .positions
+            .get(index + 1)
+            .copied()
+            .unwrap_or(from);
+        let t = (frames_since_spawn % sample_frames) as f32 / sample_frames as f32;
+        Some(from.lerp(to, t))
+    }
+}
+
+fn ghost_max_samples() -> usize {
+    (GHOST_MAX_RUN_SECS * SIMULATIONS_PER_SECOND) as usize / GHOST_SAMPLE_FRAMES as usize
+}
+
 pub fn process_scheduled_spawns_system(
     time: Res<SimulationTime>,
+    level_state: Res<LevelState>,
     players: PlayerSystemParamsMut,
     players_query: Query<&Spawned>,
 ) {
@@ -29,11 +183,11 @@ pub fn process_scheduled_spawns_system(
             continue;
         }
 
-        if let Some((respawning_at, _)) = player.respawning_at {
+        if let Some((respawning_at, reason)) = player.respawning_at {
             // A kludge to avoid `respawning_at` disappear immediately.
             // TODO: Probably, there's a better way to do this.
             if time.player_frame
-                > respawning_at - PLAYER_RESPAWN_TIME
+                > respawning_at - level_state.settings().respawns.delay(reason)
                     + FrameNumber::new(SIMULATIONS_PER_SECOND as u16)
             {
                 player.respawning_at = None;
@@ -41,3 +195,103 @@ pub fn process_scheduled_spawns_system(
         }
     }
 }
+
+/// Runs after every tick, as finish events don't outlive a couple of
+/// simulated frames.
+pub fn record_current_player_run_system(
+    time: Res<SimulationTime>,
+    mut ghost_runs: ResMut<GhostRuns>,
+    mut player_finish_events: EventReader<PlayerFinish>,
+    current_player: Query<
+        (Entity, &Position, &Spawned),
+        (With<PlayerTag>, With<PlayerFrameSimulated>),
+    >,
+) {
+    let current_player = current_player.get_single().ok();
+    let mut has_finished = false;
+    for PlayerFinish { player_entity, .. } in player_finish_events.iter() {
+        has_finished |= current_player.map_or(false, |(entity, ..)| entity == *player_entity);
+    }
+    let Some((_, position, spawned)) = current_player else {
+        return;
+    };
+
+    if let Some(spawned_at) = spawned.spawned_at() {
+        ghost_runs.record(&time, spawned_at, position);
+    }
+    if has_finished {
+        ghost_runs.finish_current_run(time.player_frame);
+    }
+}
+
+/// The ghost starts together with the current player on every spawn.
+pub fn replay_personal_best_ghost_system(
+    mut commands: Commands,
+    time: Res<SimulationTime>,
+    connected_server: Res<ConnectedServer>,
+    mut ghost_runs: ResMut<GhostRuns>,
+    current_player: Query<&Spawned, (With<PlayerTag>, With<PlayerFrameSimulated>)>,
+    mut pbr_client_params: PbrClientParams,
+    mut ghosts: Query<(&mut Transform, &mut Visibility), With<PersonalBestGhost>>,
+) {
+    ghost_runs.load(connected_server.level_id);
+
+    let position = current_player
+        .get_single()
+        .ok()
+        .filter(|spawned| spawned.is_spawned(time.player_frame))
+        .and_then(|spawned| spawned.spawned_at())
+        .and_then(|spawned_at| {
+            ghost_runs.replay_position((time.player_frame - spawned_at).value())
+        });
+
+    let ghost = ghost_runs
+        .ghost_entity
+        .and_then(|entity| ghosts.get_mut(entity).ok());
+    match (ghost, position) {
+        (Some((mut transform, mut visibility)), position) => {
+            if let Some(position) = position {
+                transform.translation = position.extend(transform.translation.z);
+            }
+            if visibility.is_visible != position.is_some() {
+                visibility.is_visible = position.is_some();
+            }
+        }
+        (None, Some(position)) => {
+            // The ghost is also spawned again if it gets despawned with the rest of the
+            // session entities.
+            let mut entity_commands = commands.spawn_empty();
+            PlayerGhostClientFactory::insert_components(
+                &mut entity_commands,
+                &mut pbr_client_params,
+                position,
+            );
+            ghost_runs.ghost_entity = Some(entity_commands.id());
+        }
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ghost_replay_position() {
+        let ghost_runs = GhostRuns {
+            best_run: GhostConfig {
+                sample_frames: GHOST_SAMPLE_FRAMES,
+                positions: vec![Vec2::ZERO, Vec2::new(4.0, 0.0)],
+            },
+            ..Default::default()
+        };
+        assert_eq!(ghost_runs.replay_position(0), Some(Vec2::ZERO));
+        assert_eq!(ghost_runs.replay_position(1), Some(Vec2::new(1.0, 0.0)));
+        assert_eq!(
+            ghost_runs.replay_position(GHOST_SAMPLE_FRAMES),
+            Some(Vec2::new(4.0, 0.0))
+        );
+        assert_eq!(ghost_runs.replay_position(GHOST_SAMPLE_FRAMES * 2), None);
+        assert_eq!(GhostRuns::default().replay_position(0), None);
+    }
+}
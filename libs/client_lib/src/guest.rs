@@ -0,0 +1,50 @@
+use crate::config_storage::{self, GuestConfig, GUEST_CONFIG_KEY};
+use bevy::{
+    ecs::system::{ResMut, Resource},
+    log,
+    utils::Uuid,
+};
+
+/// Is sent with the handshake when playing without an account, so that
+/// servers recognize the guest when they reconnect and keep their nickname
+/// and stats.
+#[derive(Resource, Default)]
+pub struct Guest {
+    pub id: Option<Uuid>,
+    /// Is chosen in the main menu, servers pick a random nickname if it's
+    /// empty.
+    pub nickname: Option<String>,
+}
+
+impl Guest {
+    pub fn set_nickname(&mut self, nickname: Option<String>) {
+        self.nickname = nickname;
+        self.save();
+    }
+
+    fn save(&self) {
+        let config = GuestConfig {
+            id: self.id,
+            nickname: self.nickname.clone(),
+        };
+        if let Err(err) = config_storage::write(GUEST_CONFIG_KEY, &config) {
+            log::error!("Failed to save the guest config: {:?}", err);
+        }
+    }
+}
+
+/// Generates the id on the first launch.
+pub fn read_guest_config_system(mut guest: ResMut<Guest>) {
+    let config = config_storage::read::<GuestConfig>(GUEST_CONFIG_KEY).unwrap_or_else(|err| {
+        log::error!("Failed to read the guest config: {:?}", err);
+        GuestConfig::default()
+    });
+    guest.nickname = config.nickname;
+    match config.id {
+        Some(id) => guest.id = Some(id),
+        None => {
+            guest.id = Some(Uuid::new_v4());
+            guest.save();
+        }
+    }
+}
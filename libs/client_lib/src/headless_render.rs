@@ -0,0 +1,431 @@
+//! Renders level previews to images without opening a window.
+//!
+//! The level gets spawned with the same factories, materials and lights as in
+//! the game, only the camera is placed to fit the whole level into the frame.
+
+use crate::{
+    camera::MAIN_CAMERA_OFFSET, environment::level_clear_color,
+    init_app_systems::spawn_scene_lights,
+};
+use anyhow::Context;
+use bevy::{
+    app::App,
+    asset::{Assets, Handle},
+    core_pipeline::{clear_color::ClearColor, core_3d::Camera3dBundle},
+    ecs::{
+        schedule::{ShouldRun, SystemStage},
+        system::{Commands, IntoSystem, Res, Resource},
+        world::World,
+    },
+    math::{Vec2, Vec3},
+    prelude::{DefaultPlugins, PluginGroup},
+    render::{
+        camera::{Camera, PerspectiveProjection, RenderTarget},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{self, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageDataLayout,
+            MapMode, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::Image,
+        view::Msaa,
+        RenderApp,
+    },
+    transform::components::Transform,
+    window::WindowPlugin,
+};
+use iyes_loopless::state::{CurrentState, NextState};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        client_factories::VisibilitySettings,
+        commands::{DeferredQueue, UpdateLevelObject, UpdateLevelSettings},
+        level::{LevelObjectDesc, SerializedLevel},
+        level_objects::{CubeDesc, DeathZoneDesc, FinishZoneDesc, PlaneDesc, PlaneFormDesc},
+    },
+    GameSessionState, LevelObjectsToSpawnToLoad, MuddleSharedPlugin,
+};
+use std::{
+    num::NonZeroU32,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const CAPTURE_NODE: &str = "level_preview_capture";
+const TEXTURE_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+const BYTES_PER_PIXEL: u32 = 4;
+/// Loading concave planes involves calculating their collider shapes, which
+/// may take a while for big levels.
+const MAX_LOADING_FRAMES: u32 = 600;
+/// Levels smaller than this are framed as if they were this big, to avoid
+/// zooming in too much.
+const MIN_FRAMED_RADIUS: f32 = 8.0;
+
+pub struct LevelPreviewSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Frames rendered after the level is loaded before capturing one, which
+    /// gives the render pipelines time to get compiled.
+    pub warmup_frames: u32,
+}
+
+impl Default for LevelPreviewSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            warmup_frames: 30,
+        }
+    }
+}
+
+pub struct LevelPreview {
+    pub width: u32,
+    pub height: u32,
+    /// Rows of RGBA pixels, top to bottom.
+    pub rgba: Vec<u8>,
+}
+
+impl LevelPreview {
+    pub fn save_png(&self, path: &Path) -> anyhow::Result<()> {
+        image::save_buffer(
+            path,
+            &self.rgba,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+        )
+        .with_context(|| format!("Failed to save {}", path.display()))
+    }
+}
+
+#[derive(Resource, Clone, ExtractResource)]
+struct PreviewCapture {
+    image: Handle<Image>,
+    requested: bool,
+}
+
+/// Lives in the render world.
+#[derive(Resource)]
+struct CaptureBuffer {
+    buffer: Buffer,
+    padded_bytes_per_row: u32,
+    is_copied: AtomicBool,
+}
+
+/// Renders a single frame of the level. Builds and runs a separate app, so
+/// it's meant to be called once per process (Bevy's logging can't be set up
+/// twice).
+pub fn render_level_preview(
+    level: SerializedLevel,
+    settings: &LevelPreviewSettings,
+) -> anyhow::Result<LevelPreview> {
+    anyhow::ensure!(
+        settings.width > 0 && settings.height > 0,
+        "Preview size must be positive"
+    );
+
+    let mut app = App::new();
+    app.insert_resource(Msaa { samples: 4 })
+        .add_plugins(
+            DefaultPlugins
+                .build()
+                .disable::<bevy::winit::WinitPlugin>()
+                .disable::<bevy::audio::AudioPlugin>()
+                .disable::<bevy::gilrs::GilrsPlugin>()
+                .set(WindowPlugin {
+                    add_primary_window: false,
+                    exit_on_all_closed: false,
+                    ..Default::default()
+                }),
+        )
+        .add_plugin(MuddleSharedPlugin::new(
+            IntoSystem::into_system(|| ShouldRun::Yes),
+            SystemStage::single_threaded(),
+            SystemStage::single_threaded(),
+            SystemStage::single_threaded(),
+            SystemStage::single_threaded(),
+            None,
+        ))
+        .add_plugin(ExtractResourcePlugin::<PreviewCapture>::default())
+        // Route points, ghosts and annotations are visible only to builders.
+        .insert_resource(VisibilitySettings {
+            spawn_areas: false,
+            annotations: false,
+            ..Default::default()
+        })
+        .add_system(pause_after_loading_system);
+
+    let size = Extent3d {
+        width: settings.width,
+        height: settings.height,
+        depth_or_array_layers: 1,
+    };
+    let image = init_preview_scene(&mut app.world, level, size);
+    app.insert_resource(PreviewCapture {
+        image,
+        requested: false,
+    });
+
+    let padded_bytes_per_row = padded_bytes_per_row(settings.width);
+    let buffer = app
+        .world
+        .resource::<RenderDevice>()
+        .create_buffer(&BufferDescriptor {
+            label: Some("level_preview_capture_buffer"),
+            size: (padded_bytes_per_row * settings.height) as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app.insert_resource(CaptureBuffer {
+        buffer,
+        padded_bytes_per_row,
+        is_copied: AtomicBool::new(false),
+    });
+    let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+    render_graph.add_node(CAPTURE_NODE, CaptureNode);
+    render_graph.add_node_edge(bevy::render::main_graph::node::CAMERA_DRIVER, CAPTURE_NODE)?;
+
+    let mut loading_frames = 0;
+    let mut warmup_frames = 0;
+    loop {
+        app.update();
+
+        if app.world.resource::<CurrentState<GameSessionState>>().0 == GameSessionState::Loading {
+            loading_frames += 1;
+            anyhow::ensure!(
+                loading_frames < MAX_LOADING_FRAMES,
+                "The level hasn't loaded in {MAX_LOADING_FRAMES} frames"
+            );
+            continue;
+        }
+
+        if warmup_frames < settings.warmup_frames {
+            warmup_frames += 1;
+            continue;
+        }
+        app.world.resource_mut::<PreviewCapture>().requested = true;
+
+        let capture_buffer = app.sub_app(RenderApp).world.resource::<CaptureBuffer>();
+        if capture_buffer.is_copied.load(Ordering::SeqCst) {
+            let render_device = app.world.resource::<RenderDevice>();
+            return read_capture_buffer(render_device, capture_buffer, size);
+        }
+    }
+}
+
+fn init_preview_scene(world: &mut World, level: SerializedLevel, size: Extent3d) -> Handle<Image> {
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("level_preview_target"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TEXTURE_FORMAT,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..Default::default()
+    };
+    image.resize(size);
+    let image = world.resource_mut::<Assets<Image>>().add(image);
+
+    let aspect_ratio = size.width as f32 / size.height as f32;
+    let camera_transform = preview_camera_transform(&level, aspect_ratio);
+    world.insert_resource(ClearColor(level_clear_color(&level.settings)));
+    let mut commands_queue = Default::default();
+    let mut commands = Commands::new(&mut commands_queue, world);
+    spawn_scene_lights(&mut commands, &level.settings);
+    commands.spawn(Camera3dBundle {
+        camera: Camera {
+            target: RenderTarget::Image(image.clone()),
+            ..Default::default()
+        },
+        transform: camera_transform,
+        ..Default::default()
+    });
+    commands_queue.apply(world);
+
+    world
+        .resource_mut::<DeferredQueue<UpdateLevelSettings>>()
+        .push(UpdateLevelSettings {
+            settings: level.settings,
+        });
+    world.insert_resource(LevelObjectsToSpawnToLoad(level.objects.len()));
+    let mut spawn_level_object_commands = world.resource_mut::<DeferredQueue<UpdateLevelObject>>();
+    for object in level.objects {
+        spawn_level_object_commands.push(UpdateLevelObject {
+            frame_number: FrameNumber::new(0),
+            object,
+        });
+    }
+
+    image
+}
+
+/// Keeps moving objects at their initial positions.
+fn pause_after_loading_system(
+    mut commands: Commands,
+    game_session_state: Res<CurrentState<GameSessionState>>,
+) {
+    if game_session_state.0 == GameSessionState::Playing {
+        commands.insert_resource(NextState(GameSessionState::Paused));
+    }
+}
+
+/// Looks at the center of the level from the same angle as the game camera,
+/// from far enough to fit every visible object.
+fn preview_camera_transform(level: &SerializedLevel, aspect_ratio: f32) -> Transform {
+    let (min, max) = level
+        .objects
+        .iter()
+        .filter_map(|object| {
+            let half_extent = match &object.desc {
+                LevelObjectDesc::Cube(CubeDesc { size, .. }) => Vec2::splat(*size),
+                LevelObjectDesc::Plane(PlaneDesc { form_desc, .. }) => match form_desc {
+                    PlaneFormDesc::Circle { radius } => Vec2::splat(*radius),
+                    PlaneFormDesc::Rectangle { size } => *size / 2.0,
+                    PlaneFormDesc::Concave { points } => points
+                        .iter()
+                        .fold(Vec2::ZERO, |extent, point| extent.max(point.abs())),
+                },
+                LevelObjectDesc::DeathZone(DeathZoneDesc { size, .. })
+                | LevelObjectDesc::FinishZone(FinishZoneDesc { size, .. }) => *size / 2.0,
+                LevelObjectDesc::RoutePoint(_)
+                | LevelObjectDesc::Annotation(_)
+                | LevelObjectDesc::CameraAnchor(_)
+                | LevelObjectDesc::Emitter(_) => return None,
+            };
+            let position = object.desc.position()?;
+            Some((position - half_extent, position + half_extent))
+        })
+        .reduce(|(a_min, a_max), (b_min, b_max)| (a_min.min(b_min), a_max.max(b_max)))
+        .unwrap_or((Vec2::ZERO, Vec2::ZERO));
+
+    let center = ((min + max) / 2.0).extend(0.0);
+    let radius = ((max - min) / 2.0).length().max(MIN_FRAMED_RADIUS);
+    let vertical_fov = PerspectiveProjection::default().fov;
+    let horizontal_fov = 2.0 * ((vertical_fov / 2.0).tan() * aspect_ratio).atan();
+    let distance = radius / (vertical_fov.min(horizontal_fov) / 2.0).sin();
+    Transform::from_translation(center + MAIN_CAMERA_OFFSET.normalize() * distance)
+        .looking_at(center, Vec3::Z)
+}
+
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let bytes_per_row = width * BYTES_PER_PIXEL;
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (bytes_per_row + alignment - 1) / alignment * alignment
+}
+
+fn read_capture_buffer(
+    render_device: &RenderDevice,
+    capture_buffer: &CaptureBuffer,
+    size: Extent3d,
+) -> anyhow::Result<LevelPreview> {
+    let slice = capture_buffer.buffer.slice(..);
+    let (tx, rx) = std::sync::mpsc::channel();
+    slice.map_async(MapMode::Read, move |result| {
+        let _ = tx.send(result);
+    });
+    render_device.wgpu_device().poll(wgpu::Maintain::Wait);
+    rx.recv()?.context("Failed to map the capture buffer")?;
+
+    let bytes_per_row = (size.width * BYTES_PER_PIXEL) as usize;
+    let rgba = slice
+        .get_mapped_range()
+        .chunks(capture_buffer.padded_bytes_per_row as usize)
+        .flat_map(|row| &row[..bytes_per_row])
+        .copied()
+        .collect();
+    capture_buffer.buffer.unmap();
+
+    Ok(LevelPreview {
+        width: size.width,
+        height: size.height,
+        rgba,
+    })
+}
+
+/// Copies the rendered preview into [`CaptureBuffer`] once it's requested.
+struct CaptureNode;
+
+impl render_graph::Node for CaptureNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let capture = world.resource::<PreviewCapture>();
+        let capture_buffer = world.resource::<CaptureBuffer>();
+        if !capture.requested || capture_buffer.is_copied.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let Some(gpu_image) = world.resource::<RenderAssets<Image>>().get(&capture.image) else {
+            return Ok(());
+        };
+
+        render_context.command_encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &capture_buffer.buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(capture_buffer.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: gpu_image.size.x as u32,
+                height: gpu_image.size.y as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+        capture_buffer.is_copied.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::test_harness::cube;
+
+    #[test]
+    fn test_preview_camera_fits_level() {
+        let level = SerializedLevel {
+            objects: vec![
+                cube(0, Vec2::new(-40.0, 10.0), 1.0),
+                cube(1, Vec2::new(40.0, 10.0), 1.0),
+            ],
+            settings: Default::default(),
+        };
+        let transform = preview_camera_transform(&level, 16.0 / 9.0);
+        let center = Vec3::new(0.0, 10.0, 0.0);
+        let direction = (transform.translation - center).normalize();
+        assert!(direction.abs_diff_eq(MAIN_CAMERA_OFFSET.normalize(), 1e-4));
+        // Both cubes (41 m away from the center) fit into the vertical field of view.
+        let distance = transform.translation.distance(center);
+        let vertical_fov = PerspectiveProjection::default().fov;
+        assert!(distance * (vertical_fov / 2.0).sin() >= 41.0);
+
+        let empty_level = SerializedLevel {
+            objects: Vec::new(),
+            settings: Default::default(),
+        };
+        let transform = preview_camera_transform(&empty_level, 1.0);
+        assert!(transform.translation.length() > MAIN_CAMERA_OFFSET.length());
+    }
+
+    #[test]
+    fn test_padded_bytes_per_row() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(1280), 5120);
+    }
+}
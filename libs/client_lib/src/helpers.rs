@@ -9,7 +9,7 @@ use bevy::{
         system::{Local, Query, Res, SystemParam},
     },
     input::{mouse::MouseButton, Input},
-    math::{Mat4, Vec2, Vec4},
+    math::{Mat4, Vec2, Vec3, Vec4},
     time::Time,
     utils::Instant,
     window::Window,
@@ -18,7 +18,7 @@ use mr_shared_lib::player::{Player, Players};
 use std::{marker::PhantomData, time::Duration};
 
 /// Radius in screen coordinates.
-const DRAGGING_THRESHOLD: f32 = 10.0;
+pub const DRAGGING_THRESHOLD: f32 = 10.0;
 const DOUBLE_CLICK_MAX_DELAY_SECS: f64 = 0.3;
 
 #[derive(SystemParam)]
@@ -256,3 +256,28 @@ pub fn cursor_pos_to_ray(
         direction: ray_world.normalize(),
     }
 }
+
+/// The inverse of `cursor_pos_to_ray`: returns window coordinates (with the
+/// origin at the top left corner) of a point in the world, or `None` if the
+/// point is behind the camera.
+pub fn world_to_window_pos(
+    world_position: Vec3,
+    window: &Window,
+    camera_transform: &Mat4,
+    camera_perspective: &Mat4,
+) -> Option<Vec2> {
+    let clip = camera_perspective.mul_vec4(
+        camera_transform
+            .inverse()
+            .mul_vec4(world_position.extend(1.0)),
+    );
+    if clip.w <= f32::EPSILON {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+
+    Some(Vec2::new(
+        (ndc.x + 1.0) / 2.0 * window.width(),
+        (1.0 - ndc.y) / 2.0 * window.height(),
+    ))
+}
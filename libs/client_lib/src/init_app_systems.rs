@@ -1,5 +1,7 @@
 use crate::{
-    components::{CameraPivotDirection, CameraPivotTag},
+    camera::MAIN_CAMERA_OFFSET,
+    components::{CameraPivotDirection, CameraPivotTag, LevelLightTag},
+    environment::level_light_transform,
     MainCameraEntity, MainCameraPivotEntity,
 };
 use bevy::{
@@ -11,11 +13,11 @@ use bevy::{
     hierarchy::BuildChildren,
     log,
     math::{Vec2, Vec3},
-    pbr::{PbrBundle, PointLight, PointLightBundle},
+    pbr::{DirectionalLight, DirectionalLightBundle, PbrBundle, PointLight, PointLightBundle},
     transform::components::{GlobalTransform, Transform},
 };
 use iyes_loopless::state::NextState;
-use mr_shared_lib::{client::assets::MuddleAssets, AppState};
+use mr_shared_lib::{client::assets::MuddleAssets, game::level::LevelSettings, AppState};
 
 /// This system is needed for the web version. As assets loading is blocking
 /// there, we need to trigger loading shaders before we join a game.
@@ -53,20 +55,11 @@ pub fn load_shaders_system(
 }
 
 pub fn basic_scene_system(mut commands: Commands) {
-    // Add entities to the scene.
-    commands.spawn(PointLightBundle {
-        point_light: PointLight {
-            range: 256.0,
-            intensity: 1280000.0,
-            ..Default::default()
-        },
-        transform: Transform::from_translation(Vec3::new(-64.0, -92.0, 144.0)),
-        ..Default::default()
-    });
+    spawn_scene_lights(&mut commands, &LevelSettings::default());
     // Camera.
     let main_camera_entity = commands
         .spawn(Camera3dBundle {
-            transform: Transform::from_translation(Vec3::new(-3.0, -14.0, 14.0))
+            transform: Transform::from_translation(MAIN_CAMERA_OFFSET)
                 .looking_at(Vec3::default(), Vec3::Z),
             ..Default::default()
         })
@@ -83,3 +76,26 @@ pub fn basic_scene_system(mut commands: Commands) {
     commands.insert_resource(MainCameraPivotEntity(main_camera_pivot_entity));
     commands.insert_resource(MainCameraEntity(main_camera_entity));
 }
+
+pub fn spawn_scene_lights(commands: &mut Commands, settings: &LevelSettings) {
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            range: 256.0,
+            intensity: 1280000.0,
+            ..Default::default()
+        },
+        transform: Transform::from_translation(Vec3::new(-64.0, -92.0, 144.0)),
+        ..Default::default()
+    });
+    // Is updated by `apply_level_settings_system` once a level gets loaded.
+    commands
+        .spawn(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: settings.light_illuminance,
+                ..Default::default()
+            },
+            transform: level_light_transform(settings),
+            ..Default::default()
+        })
+        .insert(LevelLightTag);
+}
@@ -1,6 +1,10 @@
 use crate::{
-    components::CameraPivotDirection, helpers, ui::debug_ui::DebugUiState, CurrentPlayerNetId,
-    MainCameraEntity, MainCameraPivotEntity,
+    components::CameraPivotDirection,
+    config_storage::{self, KeyBindingsConfig, KEY_BINDINGS_CONFIG_KEY},
+    helpers,
+    input_latency::InputLatency,
+    ui::debug_ui::DebugUiState,
+    CurrentPlayerNetId, MainCameraEntity, MainCameraPivotEntity,
 };
 use bevy::{
     ecs::system::SystemParam,
@@ -13,28 +17,321 @@ use bevy::{
 use bevy_egui::EguiContext;
 use bevy_inspector_egui::WorldInspectorParams;
 use mr_shared_lib::{
-    game::{components::Spawned, level::LevelObject},
-    messages::{EntityNetId, PlayerNetId, SpawnLevelObjectRequest},
+    game::{
+        components::{Position, Spawned},
+        determinism::StateHashRequest,
+        level::{LevelObject, LevelSettings, LevelState},
+    },
+    messages::{
+        AdminCommand, EntityNetId, Ping, PlayerNetId, PracticeBotsRequest, PracticeCheckpoint,
+        PublishLevelRequest, RespawnPlayerReason, SpawnLevelObjectRequest,
+    },
     player::{PlayerDirectionUpdate, PlayerRole, PlayerUpdates, Players},
     registry::EntityRegistry,
     GameTime, COMPONENT_FRAMEBUFFER_LIMIT,
 };
-use std::marker::PhantomData;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    marker::PhantomData,
+};
 
 const SWITCH_ROLE_COOLDOWN_SECS: u64 = 1;
+/// The settings screen offers a primary and a secondary binding per action.
+pub const MAX_BINDINGS_PER_ACTION: usize = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputAction {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    SwitchRole,
+    ToggleSpectator,
+    SetCheckpoint,
+    RestartFromCheckpoint,
+    SkipLevelIntro,
+    ToggleLeaderboard,
+    ToggleLayoutSettings,
+    ToggleKeyBindings,
+    ToggleDebugUi,
+    ReportBug,
+    Ping,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 15] = [
+        InputAction::MoveUp,
+        InputAction::MoveDown,
+        InputAction::MoveLeft,
+        InputAction::MoveRight,
+        InputAction::SwitchRole,
+        InputAction::ToggleSpectator,
+        InputAction::SetCheckpoint,
+        InputAction::RestartFromCheckpoint,
+        InputAction::SkipLevelIntro,
+        InputAction::ToggleLeaderboard,
+        InputAction::ToggleLayoutSettings,
+        InputAction::ToggleKeyBindings,
+        InputAction::ToggleDebugUi,
+        InputAction::ReportBug,
+        InputAction::Ping,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputAction::MoveUp => "Move up",
+            InputAction::MoveDown => "Move down",
+            InputAction::MoveLeft => "Move left",
+            InputAction::MoveRight => "Move right",
+            InputAction::SwitchRole => "Toggle Builder mode",
+            InputAction::ToggleSpectator => "Spectate",
+            InputAction::SetCheckpoint => "Set checkpoint",
+            InputAction::RestartFromCheckpoint => "Restart from checkpoint",
+            InputAction::SkipLevelIntro => "Skip level intro",
+            InputAction::ToggleLeaderboard => "Toggle leaderboard",
+            InputAction::ToggleLayoutSettings => "Layout settings",
+            InputAction::ToggleKeyBindings => "Key bindings",
+            InputAction::ToggleDebugUi => "Debug UI",
+            InputAction::ReportBug => "Report a bug",
+            InputAction::Ping => "Ping",
+        }
+    }
+
+    pub fn default_bindings(self) -> Vec<InputBinding> {
+        let keys: &[KeyCode] = match self {
+            InputAction::MoveUp => &[KeyCode::W, KeyCode::Up],
+            InputAction::MoveDown => &[KeyCode::S, KeyCode::Down],
+            InputAction::MoveLeft => &[KeyCode::A, KeyCode::Left],
+            InputAction::MoveRight => &[KeyCode::D, KeyCode::Right],
+            InputAction::SwitchRole => &[KeyCode::Escape],
+            InputAction::ToggleSpectator => &[KeyCode::F5],
+            InputAction::SetCheckpoint => &[KeyCode::C],
+            InputAction::RestartFromCheckpoint => &[KeyCode::R],
+            InputAction::SkipLevelIntro => &[KeyCode::Space],
+            InputAction::ToggleLeaderboard => &[KeyCode::F3],
+            InputAction::ToggleLayoutSettings => &[KeyCode::F4],
+            InputAction::ToggleKeyBindings => &[KeyCode::F1],
+            InputAction::ToggleDebugUi => &[KeyCode::Period],
+            InputAction::ReportBug => &[KeyCode::F8],
+            InputAction::Ping => &[KeyCode::G],
+        };
+        keys.iter().copied().map(InputBinding::Key).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Display for InputBinding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputBinding::Key(key_code) => write!(f, "{key_code:?}"),
+            InputBinding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse {button}"),
+            InputBinding::Mouse(button) => write!(f, "Mouse {button:?}"),
+        }
+    }
+}
+
+/// Maps input actions to keys and mouse buttons. Is read from the config on
+/// startup, and edited with the key bindings screen (see
+/// `ui::key_bindings_ui`).
+#[derive(Resource, Default)]
+pub struct KeyBindings {
+    config: KeyBindingsConfig,
+    /// Is set while the settings screen waits for a key to bind to the slot.
+    capturing: Option<(InputAction, usize)>,
+}
+
+impl KeyBindings {
+    pub fn bindings(&self, action: InputAction) -> Vec<InputBinding> {
+        self.config
+            .overrides
+            .get(&action)
+            .cloned()
+            .unwrap_or_else(|| action.default_bindings())
+    }
+
+    /// Is used for displaying hints, for example "Escape / Mouse Right".
+    pub fn label(&self, action: InputAction) -> String {
+        let bindings = self.bindings(action);
+        if bindings.is_empty() {
+            return "unbound".to_owned();
+        }
+        bindings
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+
+    /// Replaces the binding in the slot, or adds a new one if the slot is
+    /// empty. If the action already has the binding in another slot, it's
+    /// moved to this one.
+    pub fn bind(&mut self, action: InputAction, slot: usize, binding: InputBinding) {
+        let mut bindings = self.bindings(action);
+        if let Some(i) = bindings.iter().position(|existing| *existing == binding) {
+            bindings.remove(i);
+        } else if slot < bindings.len() {
+            bindings.remove(slot);
+        }
+        bindings.insert(slot.min(bindings.len()), binding);
+        bindings.truncate(MAX_BINDINGS_PER_ACTION);
+        self.set_bindings(action, bindings);
+    }
+
+    pub fn unbind(&mut self, action: InputAction, slot: usize) {
+        let mut bindings = self.bindings(action);
+        if slot < bindings.len() {
+            bindings.remove(slot);
+            self.set_bindings(action, bindings);
+        }
+    }
+
+    pub fn reset(&mut self, action: InputAction) {
+        self.config.overrides.remove(&action);
+    }
+
+    pub fn reset_all(&mut self) {
+        self.config.overrides.clear();
+    }
+
+    /// Returns other actions that are triggered by the same binding.
+    pub fn conflicts(&self, action: InputAction, binding: InputBinding) -> Vec<InputAction> {
+        InputAction::ALL
+            .into_iter()
+            .filter(|other| *other != action && self.bindings(*other).contains(&binding))
+            .collect()
+    }
+
+    pub fn pressed(
+        &self,
+        action: InputAction,
+        keyboard_input: &Input<KeyCode>,
+        mouse_button_input: &Input<MouseButton>,
+    ) -> bool {
+        self.capturing.is_none()
+            && self
+                .bindings(action)
+                .into_iter()
+                .any(|binding| match binding {
+                    InputBinding::Key(key_code) => keyboard_input.pressed(key_code),
+                    InputBinding::Mouse(button) => mouse_button_input.pressed(button),
+                })
+    }
+
+    pub fn just_pressed(
+        &self,
+        action: InputAction,
+        keyboard_input: &Input<KeyCode>,
+        mouse_button_input: &Input<MouseButton>,
+    ) -> bool {
+        self.capturing.is_none()
+            && self
+                .bindings(action)
+                .into_iter()
+                .any(|binding| match binding {
+                    InputBinding::Key(key_code) => keyboard_input.just_pressed(key_code),
+                    InputBinding::Mouse(button) => mouse_button_input.just_pressed(button),
+                })
+    }
+
+    /// Actions don't trigger while capturing, otherwise pressing a key that is
+    /// about to be bound would also act on its old binding.
+    pub fn start_capture(&mut self, action: InputAction, slot: usize) {
+        self.capturing = Some((action, slot));
+    }
+
+    pub fn cancel_capture(&mut self) {
+        self.capturing = None;
+    }
+
+    pub fn capturing(&self) -> Option<(InputAction, usize)> {
+        self.capturing
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = config_storage::write(KEY_BINDINGS_CONFIG_KEY, &self.config) {
+            log::error!("Failed to save the key bindings config: {:?}", err);
+        }
+    }
+
+    /// Bindings that match the defaults aren't stored, so that they follow
+    /// the defaults if those change in future versions.
+    fn set_bindings(&mut self, action: InputAction, bindings: Vec<InputBinding>) {
+        if bindings == action.default_bindings() {
+            self.config.overrides.remove(&action);
+        } else {
+            self.config.overrides.insert(action, bindings);
+        }
+    }
+}
+
+pub fn read_key_bindings_config_system(mut key_bindings: ResMut<KeyBindings>) {
+    match config_storage::read::<KeyBindingsConfig>(KEY_BINDINGS_CONFIG_KEY) {
+        Ok(mut config) => {
+            for bindings in config.overrides.values_mut() {
+                bindings.truncate(MAX_BINDINGS_PER_ACTION);
+            }
+            key_bindings.config = config;
+        }
+        Err(err) => log::error!("Failed to read the key bindings config: {:?}", err),
+    }
+}
+
+#[derive(SystemParam)]
+pub struct ActionInput<'w, 's> {
+    pub key_bindings: Res<'w, KeyBindings>,
+    keyboard_input: Res<'w, Input<KeyCode>>,
+    mouse_button_input: Res<'w, Input<MouseButton>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> ActionInput<'w, 's> {
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.key_bindings
+            .pressed(action, &self.keyboard_input, &self.mouse_button_input)
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        self.key_bindings
+            .just_pressed(action, &self.keyboard_input, &self.mouse_button_input)
+    }
+}
 
 /// Is drained by `send_requests`.
 #[derive(Resource, Default)]
 pub struct PlayerRequestsQueue {
     pub switch_role: Vec<PlayerRole>,
+    pub restart_from_checkpoint: Option<PracticeCheckpoint>,
+    pub practice_bots: Vec<PracticeBotsRequest>,
+    pub publish_level: Vec<PublishLevelRequest>,
+    pub reload_level: bool,
+    pub state_hash: Vec<StateHashRequest>,
+    pub admin_commands: Vec<AdminCommand>,
+    /// Are sent as unreliable messages.
+    pub pings: Vec<Ping>,
 }
 
+/// A checkpoint set manually by the current player
+/// (`InputAction::SetCheckpoint`) to restart from it with
+/// `InputAction::RestartFromCheckpoint`. Restarting works only in practice
+/// sessions, i.e. if the player is the only one on the server.
+#[derive(Resource, Default)]
+pub struct CurrentCheckpoint(pub Option<PracticeCheckpoint>);
+
 /// Is drained by `send_requests`.
 #[derive(Resource, Default)]
 pub struct LevelObjectRequestsQueue {
     pub spawn_requests: Vec<SpawnLevelObjectRequest>,
     pub update_requests: Vec<LevelObject>,
     pub despawn_requests: Vec<EntityNetId>,
+    /// Only the latest settings are sent.
+    pub settings_update_request: Option<LevelSettings>,
 }
 
 #[derive(SystemParam)]
@@ -72,14 +369,18 @@ impl Default for MouseRay {
 #[derive(SystemParam)]
 pub struct PlayerUpdatesParams<'w, 's> {
     switched_role_at: Local<'s, Option<Instant>>,
+    previous_direction: Local<'s, Vec2>,
     current_player_net_id: Res<'w, CurrentPlayerNetId>,
     players: Res<'w, Players>,
     player_registry: Res<'w, EntityRegistry<PlayerNetId>>,
-    players_query: Query<'w, 's, &'static Spawned>,
+    players_query: Query<'w, 's, (&'static Spawned, &'static Position)>,
     main_camera_pivot_entity: Res<'w, MainCameraPivotEntity>,
     camera_query: Query<'w, 's, &'static mut CameraPivotDirection>,
     player_updates: ResMut<'w, PlayerUpdates>,
     player_requests: ResMut<'w, PlayerRequestsQueue>,
+    current_checkpoint: ResMut<'w, CurrentCheckpoint>,
+    input_latency: ResMut<'w, InputLatency>,
+    level_state: Res<'w, LevelState>,
 }
 
 #[derive(SystemParam)]
@@ -97,7 +398,7 @@ pub fn track_input_events_system(
     mut world_inspector_params: ResMut<WorldInspectorParams>,
     mut player_updates_params: PlayerUpdatesParams,
     mut mouse_position: ResMut<MouseScreenPosition>,
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -106,34 +407,38 @@ pub fn track_input_events_system(
     }
 
     process_hotkeys(
-        &keyboard_input,
+        &time,
+        &action_input,
         &mut ui_params.debug_ui_state,
         &mut world_inspector_params,
         &mut player_updates_params,
     );
 
-    // Keyboard input.
+    // Movement input.
     let mut direction = Vec2::ZERO;
-    if keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left) {
+    if action_input.pressed(InputAction::MoveLeft) {
         direction.x -= 1.0;
     }
-    if keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right) {
+    if action_input.pressed(InputAction::MoveRight) {
         direction.x += 1.0;
     }
 
-    if keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up) {
+    if action_input.pressed(InputAction::MoveUp) {
         direction.y += 1.0;
     }
-    if keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down) {
+    if action_input.pressed(InputAction::MoveDown) {
         direction.y -= 1.0;
     }
+    // The server clamps directions to the unit length, so diagonal movement has to
+    // be normalized to keep the inputs the same on both sides.
+    let direction = direction.normalize_or_zero();
 
     let current_player_is_spawned = player_updates_params
         .current_player_net_id
         .0
         .and_then(|net_id| player_updates_params.player_registry.get_entity(net_id))
         .and_then(|player_entity| player_updates_params.players_query.get(player_entity).ok())
-        .map_or(false, |spawned| spawned.is_spawned(time.frame_number));
+        .map_or(false, |(spawned, _)| spawned.is_spawned(time.frame_number));
     let mut camera_pivot_direction = player_updates_params
         .camera_query
         .get_mut(player_updates_params.main_camera_pivot_entity.0)
@@ -154,9 +459,15 @@ pub fn track_input_events_system(
             }),
         );
         camera_pivot_direction.0 = Vec2::ZERO;
+        if *player_updates_params.previous_direction != direction {
+            player_updates_params
+                .input_latency
+                .record_input(time.frame_number, Instant::now());
+        }
     } else {
         camera_pivot_direction.0 = direction;
     }
+    *player_updates_params.previous_direction = direction;
 
     for ev in input_events.keys.iter() {
         if ev.state.is_pressed() {
@@ -218,12 +529,13 @@ pub fn cast_mouse_ray_system(
 }
 
 fn process_hotkeys(
-    keyboard_input: &Input<KeyCode>,
+    time: &GameTime,
+    action_input: &ActionInput,
     debug_ui_state: &mut DebugUiState,
     world_inspector_params: &mut WorldInspectorParams,
     player_updates_params: &mut PlayerUpdatesParams,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Period) {
+    if action_input.just_pressed(InputAction::ToggleDebugUi) {
         debug_ui_state.show = !debug_ui_state.show;
         world_inspector_params.enabled = debug_ui_state.show;
         #[cfg(feature = "profiler")]
@@ -240,16 +552,181 @@ fn process_hotkeys(
                     Instant::now().duration_since(switched_role_at).as_secs()
                         < SWITCH_ROLE_COOLDOWN_SECS
                 });
-        if keyboard_input.just_pressed(KeyCode::Escape) && !active_cooldown {
-            let new_role = match player.role {
-                PlayerRole::Runner => PlayerRole::Builder,
-                PlayerRole::Builder => PlayerRole::Runner,
-            };
+        let new_role = if action_input.just_pressed(InputAction::SwitchRole) {
+            match player.role {
+                PlayerRole::Runner => Some(PlayerRole::Builder),
+                PlayerRole::Builder | PlayerRole::Spectator => Some(PlayerRole::Runner),
+            }
+        } else if action_input.just_pressed(InputAction::ToggleSpectator) {
+            match player.role {
+                PlayerRole::Spectator => Some(PlayerRole::Runner),
+                PlayerRole::Runner | PlayerRole::Builder => Some(PlayerRole::Spectator),
+            }
+        } else {
+            None
+        };
+        if let Some(new_role) = new_role.filter(|_| !active_cooldown) {
             player_updates_params
                 .player_requests
                 .switch_role
                 .push(new_role);
             *player_updates_params.switched_role_at = Some(Instant::now());
         }
+
+        // The server won't accept a checkpoint set before the death anyway.
+        let has_died = matches!(player.respawning_at, Some((_, RespawnPlayerReason::Death)));
+        if has_died
+            && player_updates_params
+                .level_state
+                .settings()
+                .respawns
+                .deaths_reset_checkpoints
+        {
+            player_updates_params.current_checkpoint.0 = None;
+        }
+
+        if player.role == PlayerRole::Runner {
+            process_checkpoint_hotkeys(time, action_input, player_updates_params);
+        }
+    } else {
+        // Checkpoint positions don't make sense after reconnecting.
+        player_updates_params.current_checkpoint.0 = None;
+    }
+}
+
+fn process_checkpoint_hotkeys(
+    time: &GameTime,
+    action_input: &ActionInput,
+    player_updates_params: &mut PlayerUpdatesParams,
+) {
+    let player_position = player_updates_params
+        .current_player_net_id
+        .0
+        .and_then(|net_id| player_updates_params.player_registry.get_entity(net_id))
+        .and_then(|player_entity| player_updates_params.players_query.get(player_entity).ok())
+        .filter(|(spawned, _)| spawned.is_spawned(time.frame_number))
+        .and_then(|(_, position)| position.buffer.get(time.frame_number).copied());
+
+    if action_input.just_pressed(InputAction::SetCheckpoint) {
+        if let Some(position) = player_position {
+            log::info!(
+                "Setting a checkpoint at {:?} (frame {})",
+                position,
+                time.frame_number
+            );
+            player_updates_params.current_checkpoint.0 = Some(PracticeCheckpoint {
+                frame_number: time.frame_number,
+                position,
+            });
+        }
+    }
+    if action_input.just_pressed(InputAction::RestartFromCheckpoint) && player_position.is_some() {
+        if let Some(checkpoint) = player_updates_params.current_checkpoint.0 {
+            player_updates_params
+                .player_requests
+                .restart_from_checkpoint = Some(checkpoint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebinding() {
+        let mut key_bindings = KeyBindings::default();
+        assert_eq!(
+            key_bindings.bindings(InputAction::MoveUp),
+            vec![
+                InputBinding::Key(KeyCode::W),
+                InputBinding::Key(KeyCode::Up)
+            ]
+        );
+
+        // AZERTY layouts have Z in place of W.
+        key_bindings.bind(InputAction::MoveUp, 0, InputBinding::Key(KeyCode::Z));
+        assert_eq!(
+            key_bindings.bindings(InputAction::MoveUp),
+            vec![
+                InputBinding::Key(KeyCode::Z),
+                InputBinding::Key(KeyCode::Up)
+            ]
+        );
+        // Binding a key that the action already has moves it to the slot.
+        key_bindings.bind(InputAction::MoveUp, 0, InputBinding::Key(KeyCode::Up));
+        assert_eq!(
+            key_bindings.bindings(InputAction::MoveUp),
+            vec![InputBinding::Key(KeyCode::Up)]
+        );
+        key_bindings.bind(
+            InputAction::MoveUp,
+            1,
+            InputBinding::Mouse(MouseButton::Right),
+        );
+        assert_eq!(key_bindings.label(InputAction::MoveUp), "Up / Mouse Right");
+
+        key_bindings.unbind(InputAction::MoveUp, 0);
+        key_bindings.unbind(InputAction::MoveUp, 0);
+        assert_eq!(key_bindings.label(InputAction::MoveUp), "unbound");
+
+        key_bindings.reset(InputAction::MoveUp);
+        assert_eq!(
+            key_bindings.bindings(InputAction::MoveUp),
+            InputAction::MoveUp.default_bindings()
+        );
+    }
+
+    #[test]
+    fn test_defaults_are_not_stored() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.bind(InputAction::SetCheckpoint, 0, InputBinding::Key(KeyCode::V));
+        assert_eq!(key_bindings.config.overrides.len(), 1);
+        key_bindings.bind(InputAction::SetCheckpoint, 0, InputBinding::Key(KeyCode::C));
+        assert!(key_bindings.config.overrides.is_empty());
+    }
+
+    #[test]
+    fn test_config_serialization() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.bind(InputAction::MoveLeft, 0, InputBinding::Key(KeyCode::Q));
+        key_bindings.bind(
+            InputAction::SwitchRole,
+            1,
+            InputBinding::Mouse(MouseButton::Other(4)),
+        );
+
+        let serialized = serde_json::to_string(&key_bindings.config).unwrap();
+        let deserialized: KeyBindingsConfig = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, key_bindings.config);
+    }
+
+    #[test]
+    fn test_pressed_actions() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.bind(InputAction::MoveLeft, 0, InputBinding::Key(KeyCode::Q));
+        let mut keyboard_input = Input::<KeyCode>::default();
+        let mouse_button_input = Input::<MouseButton>::default();
+        keyboard_input.press(KeyCode::Q);
+
+        assert!(key_bindings.just_pressed(
+            InputAction::MoveLeft,
+            &keyboard_input,
+            &mouse_button_input
+        ));
+        assert!(key_bindings
+            .conflicts(InputAction::MoveLeft, InputBinding::Key(KeyCode::Q))
+            .is_empty());
+        key_bindings.bind(InputAction::SetCheckpoint, 0, InputBinding::Key(KeyCode::Q));
+        assert_eq!(
+            key_bindings.conflicts(InputAction::SetCheckpoint, InputBinding::Key(KeyCode::Q)),
+            vec![InputAction::MoveLeft]
+        );
+
+        // Actions don't trigger while the settings screen waits for a key.
+        key_bindings.start_capture(InputAction::SetCheckpoint, 0);
+        assert!(!key_bindings.pressed(InputAction::MoveLeft, &keyboard_input, &mouse_button_input));
+        key_bindings.cancel_capture();
+        assert!(key_bindings.pressed(InputAction::MoveLeft, &keyboard_input, &mouse_button_input));
     }
 }
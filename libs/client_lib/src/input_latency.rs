@@ -0,0 +1,172 @@
+use bevy::{ecs::system::Resource, log, utils::Instant};
+use mr_shared_lib::framebuffer::FrameNumber;
+use std::{collections::VecDeque, time::Duration};
+
+/// Percentiles are recalculated (and compared against the previous ones) every
+/// time this many samples are collected.
+pub const INPUT_LATENCY_WINDOW: usize = 60;
+/// Inputs that haven't been acknowledged in this time are considered lost.
+const PENDING_INPUT_TIMEOUT: Duration = Duration::from_secs(2);
+/// A window is reported as a regression if its p95 exceeds the previous one by
+/// both of the thresholds, so that small fluctuations don't spam the logs.
+const REGRESSION_RATIO: f32 = 1.25;
+const REGRESSION_MIN_DIFF: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+}
+
+/// Measures the time between a player changing their input direction and
+/// receiving a delta update, in which the server acknowledges the frame of the
+/// input. Is toggled in the debug UI.
+#[derive(Resource, Default)]
+pub struct InputLatency {
+    pub enabled: bool,
+    pending: VecDeque<(FrameNumber, Instant)>,
+    window: Vec<Duration>,
+    pub percentiles: Option<LatencyPercentiles>,
+    pub lost_inputs: usize,
+}
+
+impl InputLatency {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            *self = Self::default();
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn record_input(&mut self, frame_number: FrameNumber, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        self.pending.push_back((frame_number, now));
+    }
+
+    pub fn acknowledge(&mut self, ack_frame_number: FrameNumber, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+        while let Some((frame_number, recorded_at)) = self.pending.front().copied() {
+            let elapsed = now.duration_since(recorded_at);
+            if frame_number <= ack_frame_number {
+                self.pending.pop_front();
+                self.add_sample(elapsed);
+            } else if elapsed > PENDING_INPUT_TIMEOUT {
+                self.pending.pop_front();
+                self.lost_inputs += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Frame numbers get reset on reconnecting, so pending inputs can't be
+    /// matched anymore.
+    pub fn reset_pending(&mut self) {
+        self.pending.clear();
+    }
+
+    fn add_sample(&mut self, latency: Duration) {
+        self.window.push(latency);
+        if self.window.len() < INPUT_LATENCY_WINDOW {
+            return;
+        }
+
+        let new_percentiles = calculate_percentiles(&mut self.window);
+        self.window.clear();
+        if let Some(old_percentiles) = self.percentiles {
+            if is_regression(old_percentiles.p95, new_percentiles.p95) {
+                log::warn!(
+                    "Input latency regression: p95 {}ms -> {}ms (p50: {}ms -> {}ms)",
+                    old_percentiles.p95.as_millis(),
+                    new_percentiles.p95.as_millis(),
+                    old_percentiles.p50.as_millis(),
+                    new_percentiles.p50.as_millis(),
+                );
+            }
+        }
+        log::debug!(
+            "Input latency: p50 {}ms, p95 {}ms",
+            new_percentiles.p50.as_millis(),
+            new_percentiles.p95.as_millis()
+        );
+        self.percentiles = Some(new_percentiles);
+    }
+}
+
+fn calculate_percentiles(samples: &mut [Duration]) -> LatencyPercentiles {
+    samples.sort_unstable();
+    let percentile = |p: usize| samples[((samples.len() - 1) * p + 50) / 100];
+    LatencyPercentiles {
+        p50: percentile(50),
+        p95: percentile(95),
+    }
+}
+
+fn is_regression(old: Duration, new: Duration) -> bool {
+    new > old.mul_f32(REGRESSION_RATIO) && new > old + REGRESSION_MIN_DIFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        values.into_iter().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let mut samples = millis((1..=100).rev());
+        assert_eq!(
+            calculate_percentiles(&mut samples),
+            LatencyPercentiles {
+                p50: Duration::from_millis(51),
+                p95: Duration::from_millis(95),
+            }
+        );
+
+        let mut samples = millis([42]);
+        assert_eq!(
+            calculate_percentiles(&mut samples),
+            LatencyPercentiles {
+                p50: Duration::from_millis(42),
+                p95: Duration::from_millis(42),
+            }
+        );
+    }
+
+    #[test]
+    fn test_acknowledge() {
+        let mut input_latency = InputLatency::default();
+        let start = Instant::now();
+        input_latency.record_input(FrameNumber::new(1), start);
+        assert!(input_latency.pending.is_empty());
+
+        input_latency.set_enabled(true);
+        input_latency.record_input(FrameNumber::new(1), start);
+        input_latency.record_input(FrameNumber::new(2), start);
+        input_latency.record_input(FrameNumber::new(5), start);
+        input_latency.acknowledge(FrameNumber::new(3), start + Duration::from_millis(80));
+        assert_eq!(input_latency.window, millis([80, 80]));
+        assert_eq!(input_latency.pending.len(), 1);
+
+        input_latency.acknowledge(FrameNumber::new(4), start + PENDING_INPUT_TIMEOUT * 2);
+        assert!(input_latency.pending.is_empty());
+        assert_eq!(input_latency.lost_inputs, 1);
+    }
+
+    #[test]
+    fn test_is_regression() {
+        let old = Duration::from_millis(100);
+        assert!(!is_regression(old, Duration::from_millis(120)));
+        assert!(is_regression(old, Duration::from_millis(130)));
+
+        let old = Duration::from_millis(20);
+        assert!(!is_regression(old, Duration::from_millis(29)));
+        assert!(is_regression(old, Duration::from_millis(31)));
+    }
+}
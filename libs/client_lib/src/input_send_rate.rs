@@ -0,0 +1,246 @@
+//! Inputs don't change on most of the network broadcast ticks, and the server
+//! keeps simulating the last received ones anyway. On clean links, we replace
+//! unchanged inputs with `PlayerInputs::Unchanged` once the server has
+//! acknowledged them, and resend only the inputs that are still in flight.
+//! Once packet loss is detected, inputs are sent every tick again, along with
+//! all the unacknowledged ones.
+//!
+//! Updates themselves are never skipped: they acknowledge delta updates, and
+//! `sync_clock` relies on the server acknowledging a fresh update each tick.
+
+use bevy::{ecs::system::Resource, math::Vec2, utils::Instant};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    messages::{EntityNetId, PlayerInputs, RunnerInput},
+    net::ConnectionState,
+    MAX_INPUT_SEND_INTERVAL, TICKS_PER_NETWORK_BROADCAST,
+};
+
+/// Above this value, acknowledgments can't be relied on to skip packets or
+/// to resend fewer inputs.
+const HIGH_PACKET_LOSS: f32 = 0.02;
+
+#[derive(Resource, Default)]
+pub struct InputSendRate {
+    /// Is compared against `ConnectionState::status_updated_at` to forget the
+    /// inputs sent during a previous connection.
+    connected_at: Option<Instant>,
+    sent_inputs: Option<SentInputs>,
+}
+
+struct SentInputs {
+    state: InputState,
+    /// The frame of the first packet that contained the inputs.
+    first_sent_at: FrameNumber,
+    last_sent_at: FrameNumber,
+    is_acknowledged: bool,
+}
+
+#[derive(PartialEq, Debug)]
+enum InputState {
+    Runner {
+        /// The direction at the first frame of the sent inputs.
+        direction: Option<Vec2>,
+        changes: Vec<RunnerInput>,
+    },
+    Builder {
+        camera_position: Vec2,
+        selected_object: Option<EntityNetId>,
+    },
+    Spectator,
+}
+
+impl InputState {
+    fn new(inputs: &PlayerInputs) -> Self {
+        match inputs {
+            PlayerInputs::Runner { inputs } => Self::Runner {
+                direction: inputs.first().map(|input| input.direction),
+                changes: inputs.iter().skip(1).cloned().collect(),
+            },
+            PlayerInputs::Builder {
+                camera_position,
+                selected_object,
+            } => Self::Builder {
+                camera_position: *camera_position,
+                selected_object: *selected_object,
+            },
+            PlayerInputs::Spectator => Self::Spectator,
+            PlayerInputs::Unchanged => {
+                unreachable!("Unchanged inputs are never compared to the sent ones")
+            }
+        }
+    }
+}
+
+impl InputSendRate {
+    /// Every packet contains the inputs since the packet that was the newest
+    /// acknowledged one at the moment of sending, so on clean links, the
+    /// inputs preceding it have already reached the server. With packet loss,
+    /// acknowledgments get lost as well, so we resend everything since the
+    /// first unacknowledged packet.
+    ///
+    /// Is expected to be called before adding the packet of the current frame.
+    pub fn first_input_frame(
+        &self,
+        frame_number: FrameNumber,
+        connection_state: &ConnectionState,
+    ) -> FrameNumber {
+        if connection_state.packet_loss() < HIGH_PACKET_LOSS {
+            if let Some(newest_acknowledged) =
+                connection_state.newest_acknowledged_outgoing_packet()
+            {
+                return (newest_acknowledged + FrameNumber::new(TICKS_PER_NETWORK_BROADCAST))
+                    .min(frame_number);
+            }
+        }
+        connection_state
+            .first_unacknowledged_outgoing_packet()
+            .unwrap_or(frame_number)
+    }
+
+    /// Returns `PlayerInputs::Unchanged` instead of the inputs if the server
+    /// has acknowledged them, the link is clean, and they were last sent less
+    /// than `MAX_INPUT_SEND_INTERVAL` frames ago.
+    pub fn inputs_to_send(
+        &mut self,
+        frame_number: FrameNumber,
+        inputs: PlayerInputs,
+        connection_state: &ConnectionState,
+    ) -> PlayerInputs {
+        let connected_at = connection_state.status_updated_at();
+        if self.connected_at != Some(connected_at) {
+            self.connected_at = Some(connected_at);
+            self.sent_inputs = None;
+        }
+
+        let state = InputState::new(&inputs);
+        let Some(sent_inputs) = self
+            .sent_inputs
+            .as_mut()
+            .filter(|sent_inputs| sent_inputs.state == state)
+        else {
+            self.sent_inputs = Some(SentInputs {
+                state,
+                first_sent_at: frame_number,
+                last_sent_at: frame_number,
+                is_acknowledged: false,
+            });
+            return inputs;
+        };
+
+        // Latching the value, as a frame number compared against a much newer
+        // one may seem to be ahead of it once the counter wraps.
+        sent_inputs.is_acknowledged |= connection_state
+            .newest_acknowledged_outgoing_packet()
+            .map_or(false, |newest_acknowledged| {
+                newest_acknowledged >= sent_inputs.first_sent_at
+            });
+        let can_skip = sent_inputs.is_acknowledged
+            && connection_state.packet_loss() < HIGH_PACKET_LOSS
+            && frame_number - sent_inputs.last_sent_at < MAX_INPUT_SEND_INTERVAL;
+        if can_skip {
+            return PlayerInputs::Unchanged;
+        }
+        sent_inputs.last_sent_at = frame_number;
+        inputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Client {
+        input_send_rate: InputSendRate,
+        connection_state: ConnectionState,
+    }
+
+    impl Client {
+        /// Sends a packet, the server acknowledges everything that was sent.
+        /// Returns whether the packet contained the inputs.
+        fn send(&mut self, frame_number: u16, inputs: &PlayerInputs) -> bool {
+            let frame_number = FrameNumber::new(frame_number);
+            let sent_inputs = self.input_send_rate.inputs_to_send(
+                frame_number,
+                inputs.clone(),
+                &self.connection_state,
+            );
+            self.connection_state
+                .add_outgoing_packet(frame_number, Instant::now());
+            let acknowledgments = self.connection_state.outgoing_acknowledgments_bit_set() | 1;
+            self.connection_state
+                .apply_outgoing_acknowledgements(frame_number, acknowledgments)
+                .unwrap();
+            sent_inputs != PlayerInputs::Unchanged
+        }
+    }
+
+    fn runner_inputs(inputs: &[(u16, Vec2)]) -> PlayerInputs {
+        PlayerInputs::Runner {
+            inputs: inputs
+                .iter()
+                .map(|&(frame_number, direction)| RunnerInput {
+                    frame_number: FrameNumber::new(frame_number),
+                    direction,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_skips_acknowledged_unchanged_inputs() {
+        let mut client = Client::default();
+        let inputs = runner_inputs(&[(0, Vec2::X)]);
+
+        assert!(client.send(0, &inputs));
+        let sent_frames = (2..40)
+            .step_by(TICKS_PER_NETWORK_BROADCAST as usize)
+            .filter(|frame_number| client.send(*frame_number, &inputs))
+            .collect::<Vec<_>>();
+        let interval = MAX_INPUT_SEND_INTERVAL.value();
+        let expected_frames = (interval..40).step_by(interval as usize);
+        assert_eq!(sent_frames, expected_frames.collect::<Vec<_>>());
+
+        // Changes are sent right away.
+        let inputs = runner_inputs(&[(38, Vec2::X), (39, Vec2::Y)]);
+        assert!(client.send(40, &inputs));
+        // A direction that changes back and forth between two packets is a
+        // change as well.
+        let inputs = runner_inputs(&[(40, Vec2::Y), (41, Vec2::X), (42, Vec2::Y)]);
+        assert!(client.send(42, &inputs));
+        assert!(!client.send(44, &inputs));
+    }
+
+    #[test]
+    fn test_sends_unacknowledged_inputs() {
+        let mut client = Client::default();
+        for frame_number in (0..20).step_by(TICKS_PER_NETWORK_BROADCAST as usize) {
+            let frame_number = FrameNumber::new(frame_number);
+            assert_eq!(
+                client.input_send_rate.inputs_to_send(
+                    frame_number,
+                    PlayerInputs::Spectator,
+                    &client.connection_state
+                ),
+                PlayerInputs::Spectator
+            );
+            client
+                .connection_state
+                .add_outgoing_packet(frame_number, Instant::now());
+        }
+        let first_input_frame = client
+            .input_send_rate
+            .first_input_frame(FrameNumber::new(20), &client.connection_state);
+        assert_eq!(first_input_frame, FrameNumber::new(0));
+
+        client
+            .connection_state
+            .apply_outgoing_acknowledgements(FrameNumber::new(10), u64::MAX)
+            .unwrap();
+        let first_input_frame = client
+            .input_send_rate
+            .first_input_frame(FrameNumber::new(20), &client.connection_state);
+        assert_eq!(first_input_frame, FrameNumber::new(12));
+    }
+}
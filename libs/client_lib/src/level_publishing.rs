@@ -0,0 +1,128 @@
+use crate::input::PlayerRequestsQueue;
+use bevy::ecs::system::Resource;
+use mr_shared_lib::messages::{PublishLevelReport, PublishLevelRequest, PublishLevelStatus};
+
+/// State of the publishing dialog of the builder UI. The server runs the
+/// checks, the client only keeps the latest report to display it.
+#[derive(Resource, Default)]
+pub struct LevelPublishing {
+    pub is_dialog_open: bool,
+    pending_request: Option<PublishLevelRequest>,
+    report: Option<PublishLevelReport>,
+}
+
+impl LevelPublishing {
+    /// Opens the dialog and requests the checks to be run against the current
+    /// state of the level.
+    pub fn open(&mut self, player_requests: &mut PlayerRequestsQueue) {
+        self.is_dialog_open = true;
+        self.pending_request = None;
+        self.report = None;
+        self.request(PublishLevelRequest::Check, player_requests);
+    }
+
+    pub fn request(
+        &mut self,
+        request: PublishLevelRequest,
+        player_requests: &mut PlayerRequestsQueue,
+    ) {
+        if self.pending_request.is_some() {
+            return;
+        }
+        self.pending_request = Some(request);
+        player_requests.publish_level.push(request);
+    }
+
+    pub fn receive_report(&mut self, report: PublishLevelReport) {
+        self.pending_request = None;
+        self.report = Some(report);
+    }
+
+    pub fn pending_request(&self) -> Option<PublishLevelRequest> {
+        self.pending_request
+    }
+
+    pub fn report(&self) -> Option<&PublishLevelReport> {
+        self.report.as_ref()
+    }
+
+    /// A level can be published only if the latest checks haven't found any
+    /// errors.
+    pub fn can_publish(&self) -> bool {
+        self.pending_request.is_none()
+            && self.report.as_ref().map_or(false, |report| {
+                report.status == PublishLevelStatus::Checked && !report.has_errors()
+            })
+    }
+
+    /// Reports of the previous session may be outdated after reconnecting.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::messages::{LevelCheck, LevelCheckSeverity};
+
+    fn report(severity: LevelCheckSeverity, status: PublishLevelStatus) -> PublishLevelReport {
+        PublishLevelReport {
+            checks: vec![LevelCheck {
+                severity,
+                message: String::new(),
+            }],
+            status,
+        }
+    }
+
+    #[test]
+    fn test_level_publishing() {
+        let mut player_requests = PlayerRequestsQueue::default();
+        let mut publishing = LevelPublishing::default();
+
+        publishing.open(&mut player_requests);
+        assert!(publishing.is_dialog_open);
+        assert_eq!(
+            publishing.pending_request(),
+            Some(PublishLevelRequest::Check)
+        );
+        assert!(!publishing.can_publish());
+
+        publishing.receive_report(report(
+            LevelCheckSeverity::Error,
+            PublishLevelStatus::Checked,
+        ));
+        assert!(!publishing.can_publish());
+
+        publishing.open(&mut player_requests);
+        publishing.receive_report(report(
+            LevelCheckSeverity::Warning,
+            PublishLevelStatus::Checked,
+        ));
+        assert!(publishing.can_publish());
+
+        // Requests aren't duplicated while waiting for a report.
+        publishing.request(PublishLevelRequest::Publish, &mut player_requests);
+        publishing.request(PublishLevelRequest::Publish, &mut player_requests);
+        assert!(!publishing.can_publish());
+        assert_eq!(
+            player_requests.publish_level,
+            vec![
+                PublishLevelRequest::Check,
+                PublishLevelRequest::Check,
+                PublishLevelRequest::Publish,
+            ]
+        );
+
+        publishing.receive_report(report(
+            LevelCheckSeverity::Warning,
+            PublishLevelStatus::Published,
+        ));
+        assert!(!publishing.can_publish());
+        assert_eq!(
+            publishing.report().map(|report| &report.status),
+            Some(&PublishLevelStatus::Published)
+        );
+    }
+}
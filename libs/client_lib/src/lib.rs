@@ -2,27 +2,53 @@
 #![feature(slice_pattern)]
 #![allow(clippy::only_used_in_recursion)]
 
+#[cfg(all(feature = "headless_render", not(target_arch = "wasm32")))]
+pub use headless_render::{render_level_preview, LevelPreview, LevelPreviewSettings};
+pub use logging::MuddleLogPlugin;
 pub use net::DEFAULT_SERVER_PORT;
+pub use suspension::AppVisibilityChanged;
 
 use crate::{
-    camera::{move_free_camera_pivot_system, reattach_camera_system},
+    audio_cues::{
+        play_audio_cues_system, read_audio_config_system, request_audio_clips_system, AudioCues,
+    },
+    camera::{
+        apply_camera_mode_system, move_free_camera_pivot_system, play_level_intro_system,
+        reattach_camera_system, LevelIntro,
+    },
     config_storage::OfflineAuthConfig,
-    game_events::process_scheduled_spawns_system,
+    determinism::{bisect_divergence_system, DivergenceBisect},
+    environment::apply_level_settings_system,
+    game_events::{
+        process_scheduled_spawns_system, record_current_player_run_system,
+        replay_personal_best_ghost_system, GhostRuns,
+    },
+    guest::{read_guest_config_system, Guest},
     init_app_systems::load_shaders_system,
-    input::{LevelObjectRequestsQueue, MouseRay, MouseWorldPosition, PlayerRequestsQueue},
+    input::{
+        read_key_bindings_config_system, CurrentCheckpoint, KeyBindings, LevelObjectRequestsQueue,
+        MouseRay, MouseWorldPosition, PlayerRequestsQueue,
+    },
+    lod::update_level_object_lod_system,
     net::{
-        auth::read_offline_auth_config_system, fill_actual_frames_ahead_system,
-        has_server_to_connect, init_matchmaker_connection_system, maintain_connection_system,
-        process_network_events_system, send_network_updates_system, send_requests_system,
-        ServerToConnect, DEFAULT_SERVER_IP_ADDR,
+        auth::read_offline_auth_config_system,
+        fill_actual_frames_ahead_system, has_server_to_connect, init_matchmaker_connection_system,
+        maintain_connection_system, process_network_events_system, send_network_updates_system,
+        send_requests_system,
+        spectator::{interpolate_spectated_players_system, SpectatorSnapshots},
+        BuilderStates, ConnectedServer, ServerToConnect, DEFAULT_SERVER_IP_ADDR,
     },
+    personal_bests::{read_personal_bests_system, PersonalBests},
+    suspension::{app_suspension_system, AppSuspension},
     ui::{
         builder_ui::{EditedLevelObject, EditedObjectUpdate},
         debug_ui::update_debug_ui_state_system,
+        layout::{switch_ui_layout_system, UiLayout},
     },
     visuals::{
         control_builder_visibility_system, process_control_points_input_system,
-        spawn_control_points_system, update_player_sensor_materials_system,
+        spawn_control_points_system, update_collision_outlines_system,
+        update_player_sensor_materials_system, update_spawn_area_previews_system,
     },
 };
 use bevy::{
@@ -35,7 +61,7 @@ use bevy::{
     },
     log,
     time::Time,
-    utils::{HashMap, Instant},
+    utils::Instant,
 };
 use bevy_egui::EguiPlugin;
 use bevy_inspector_egui::{WorldInspectorParams, WorldInspectorPlugin};
@@ -44,22 +70,44 @@ use iyes_loopless::prelude::*;
 use mr_shared_lib::{
     framebuffer::{FrameNumber, Framebuffer},
     game::client_factories::VisibilitySettings,
-    messages::{EntityNetId, PlayerNetId},
+    messages::{AdminPermissions, EntityNetId, PlayerNetId},
     net::{ConnectionState, ConnectionStatus, MessageId},
     AppState, GameSessionState, GameTime, MuddleSharedPlugin, SimulationTime,
     COMPONENT_FRAMEBUFFER_LIMIT, SIMULATIONS_PER_SECOND, TICKS_PER_NETWORK_BROADCAST,
 };
-use std::{marker::PhantomData, net::SocketAddr};
+use std::{collections::VecDeque, marker::PhantomData, net::SocketAddr};
 use url::Url;
 
+mod audio_cues;
 mod camera;
 mod components;
 mod config_storage;
+mod determinism;
+#[cfg(feature = "discord")]
+mod discord;
+mod edit_history;
+mod environment;
 mod game_events;
+mod guest;
+#[cfg(all(feature = "headless_render", not(target_arch = "wasm32")))]
+mod headless_render;
 mod helpers;
 mod init_app_systems;
 mod input;
+mod input_latency;
+mod input_send_rate;
+mod level_publishing;
+mod lod;
+mod logging;
+mod memory_budget;
 mod net;
+mod offline_editing;
+mod personal_bests;
+mod server_health;
+mod session_summary;
+mod suspension;
+#[cfg(feature = "time_dilation")]
+mod time_dilation;
 mod ui;
 mod utils;
 mod visuals;
@@ -88,10 +136,17 @@ impl Plugin for MuddleClientPlugin {
             .with_system(input::cast_mouse_ray_system.after(input::track_input_events_system));
         let broadcast_updates_stage = SystemStage::single_threaded()
             .with_system(send_network_updates_system)
+            .with_system(bisect_divergence_system.before(send_requests_system))
             .with_system(send_requests_system);
+        #[cfg(debug_assertions)]
+        let broadcast_updates_stage = broadcast_updates_stage
+            .with_system(net::send_conditioned_messages_system.after(send_requests_system));
         let post_tick_stage = SystemStage::single_threaded()
             .with_system(control_builder_visibility_system)
             .with_system(update_player_sensor_materials_system)
+            .with_system(record_current_player_run_system)
+            .with_system(update_collision_outlines_system)
+            .with_system(update_spawn_area_previews_system)
             .with_system(reattach_camera_system)
             .with_system(move_free_camera_pivot_system.after(reattach_camera_system))
             .with_system(pause_simulation_system)
@@ -108,10 +163,15 @@ impl Plugin for MuddleClientPlugin {
             .init_resource::<input::MouseScreenPosition>()
             .insert_resource(ui::main_menu_ui::MainMenuUiState::new(config_server_addr))
             .add_event::<EditedObjectUpdate>()
+            .add_event::<AppVisibilityChanged>()
             // Startup systems.
             .add_startup_system(init_matchmaker_connection_system)
             .add_startup_system(init_app_systems::basic_scene_system)
             .add_startup_system(read_offline_auth_config_system)
+            .add_startup_system(read_guest_config_system)
+            .add_startup_system(read_personal_bests_system)
+            .add_startup_system(read_audio_config_system)
+            .add_startup_system(read_key_bindings_config_system)
             // Loading the app.
             .add_system(load_shaders_system.run_in_state(AppState::Loading))
             // Game.
@@ -124,8 +184,36 @@ impl Plugin for MuddleClientPlugin {
                 None,
             ))
             .add_system(process_scheduled_spawns_system)
+            .add_system(replay_personal_best_ghost_system.run_in_state(GameSessionState::Playing))
+            .add_system(apply_level_settings_system)
+            .add_system(request_audio_clips_system)
+            .add_system(play_audio_cues_system)
+            .add_system(play_level_intro_system.after(switch_ui_layout_system))
+            .add_system(
+                apply_camera_mode_system
+                    .after(switch_ui_layout_system)
+                    .after(play_level_intro_system),
+            )
+            .add_system(update_level_object_lod_system.run_in_state(GameSessionState::Playing))
+            .add_system(
+                interpolate_spectated_players_system.run_in_state(GameSessionState::Playing),
+            )
+            .add_system(offline_editing::offline_editing_system)
+            .add_system(app_suspension_system)
+            .add_system(memory_budget::track_memory_usage_system)
+            .add_system(session_summary::track_session_system.after(app_suspension_system))
             // Egui.
             .add_startup_system(ui::set_ui_scale_factor_system)
+            .add_startup_system(ui::theme::read_ui_theme_config_system)
+            .add_startup_system(ui::layout::read_ui_layout_config_system)
+            .add_system(switch_ui_layout_system)
+            .add_system(
+                ui::layout::layout_ui_system
+                    .run_not_in_state(GameSessionState::Loading)
+                    .after(switch_ui_layout_system),
+            )
+            .add_system(ui::key_bindings_ui::key_bindings_ui_system)
+            .add_system(ui::theme::apply_ui_theme_system)
             .add_system(ui::debug_ui::update_debug_visibility_system)
             .add_system(ui::debug_ui::debug_ui_system)
             .add_system(ui::debug_ui::profiler_ui_system)
@@ -135,14 +223,45 @@ impl Plugin for MuddleClientPlugin {
                     .run_not_in_state(AppState::Loading),
             )
             .add_system(ui::debug_ui::inspect_object_system)
+            .add_system(
+                ui::framebuffer_inspector::framebuffer_inspector_system
+                    .after(ui::debug_ui::inspect_object_system),
+            )
             .add_system(
                 ui::player_ui::leaderboard_ui_system.run_not_in_state(GameSessionState::Loading),
             )
             .add_system(ui::player_ui::help_ui_system.run_not_in_state(GameSessionState::Loading))
+            .add_system(
+                ui::player_ui::finish_screen_ui_system.run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(
+                ui::player_ui::practice_bots_ui_system.run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(ui::player_ui::level_intro_ui_system)
+            .add_system(ui::admin_ui::admin_ui_system.run_not_in_state(GameSessionState::Loading))
+            .add_system(ui::admin_ui::admin_broadcasts_ui_system)
+            .add_system(ui::bug_report_ui::bug_report_ui_system.run_not_in_state(AppState::Loading))
+            .add_system(ui::match_ui::match_ui_system.run_not_in_state(GameSessionState::Loading))
+            .add_system(
+                ui::player_ui::draw_tethers_system.run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(
+                ui::player_ui::draw_builder_cursors_system
+                    .run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(
+                ui::player_ui::draw_emitter_hazards_system
+                    .run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(
+                ui::ping_ui::ping_wheel_ui_system.run_not_in_state(GameSessionState::Loading),
+            )
+            .add_system(ui::ping_ui::draw_pings_system.run_not_in_state(GameSessionState::Loading))
             .add_startup_system(ui::main_menu_ui::init_menu_auth_state_system)
             .add_system_set(
                 ui::main_menu_ui::process_io_messages_system_set().label("process_io_messages"),
             )
+            .add_system(ui::main_menu_ui::open_session_summary_system)
             .add_system(
                 ui::main_menu_ui::main_menu_ui_system
                     .run_in_state(AppState::MainMenu)
@@ -152,9 +271,27 @@ impl Plugin for MuddleClientPlugin {
             // Builder mode systems.
             .add_system_set(ui::builder_ui::builder_system_set().label("builder_system_set"))
             // Add to the system set above after fixing https://github.com/mvlabat/muddle-run/issues/46.
-            .add_system(process_control_points_input_system.after("builder_system_set"))
+            .add_system(
+                process_control_points_input_system
+                    .run_if_not(ui::terrain_brush::is_terrain_brush_active)
+                    .after("builder_system_set"),
+            )
             .add_system(spawn_control_points_system.after("builder_system_set"));
 
+        #[cfg(feature = "discord")]
+        app.add_startup_system(discord::init_discord_presence_system)
+            .add_system(discord::update_discord_presence_system)
+            .add_system(
+                discord::process_discord_join_requests_system
+                    .run_in_state(AppState::MainMenu)
+                    .run_if_not(has_server_to_connect),
+            );
+
+        #[cfg(feature = "time_dilation")]
+        app.add_system(ui::debug_ui::time_dilation_ui_system);
+        #[cfg(debug_assertions)]
+        app.add_system(ui::debug_ui::network_conditioner_ui_system);
+
         // There's also `GameSessionState`, which is added by `MuddleSharedPlugin`.
         app.add_state(AppState::Loading);
 
@@ -168,17 +305,56 @@ impl Plugin for MuddleClientPlugin {
         app.init_resource::<TargetFramesAhead>();
         app.init_resource::<DelayServerTime>();
         app.init_resource::<ui::debug_ui::DebugUiState>();
+        app.init_resource::<ui::debug_ui::FrameTimeline>();
+        app.init_resource::<ui::framebuffer_inspector::FramebufferInspector>();
+        app.init_resource::<ui::bug_report_ui::BugReportUiState>();
+        app.init_resource::<input_latency::InputLatency>();
+        app.init_resource::<input_send_rate::InputSendRate>();
+        #[cfg(feature = "time_dilation")]
+        app.init_resource::<time_dilation::TimeDilation>();
+        #[cfg(debug_assertions)]
+        app.init_resource::<net::conditioner::NetworkConditioner>();
         app.init_resource::<CurrentPlayerNetId>();
         app.init_resource::<ConnectionState>();
         app.init_resource::<PlayerRequestsQueue>();
+        app.init_resource::<CurrentCheckpoint>();
+        app.init_resource::<KeyBindings>();
+        app.init_resource::<ui::key_bindings_ui::KeyBindingsUiState>();
         app.init_resource::<EditedLevelObject>();
+        app.init_resource::<ui::terrain_brush::TerrainBrush>();
+        app.init_resource::<ui::route_preview::RoutePreview>();
+        app.init_resource::<ui::collision_preview::CollisionPreview>();
         app.init_resource::<LevelObjectRequestsQueue>();
         app.init_resource::<LevelObjectCorrelations>();
+        app.init_resource::<SpectatorSnapshots>();
+        app.init_resource::<BuilderStates>();
+        app.init_resource::<edit_history::LevelEditHistory>();
         app.init_resource::<MouseRay>();
         app.init_resource::<MouseWorldPosition>();
         app.init_resource::<VisibilitySettings>();
         app.init_resource::<ServerToConnect>();
+        app.init_resource::<ConnectedServer>();
         app.init_resource::<OfflineAuthConfig>();
+        app.init_resource::<Guest>();
+        app.init_resource::<ui::theme::UiTheme>();
+        app.init_resource::<UiLayout>();
+        app.init_resource::<PersonalBests>();
+        app.init_resource::<GhostRuns>();
+        app.init_resource::<server_health::ServerHealthReport>();
+        app.init_resource::<session_summary::SessionSummary>();
+        app.init_resource::<level_publishing::LevelPublishing>();
+        app.init_resource::<ui::builder_ui::InvalidLevelObjectShapes>();
+        app.init_resource::<ui::builder_ui::LevelSaveErrors>();
+        app.init_resource::<ui::admin_ui::AdminBroadcasts>();
+        app.init_resource::<ui::match_ui::MatchStatus>();
+        app.init_resource::<ui::ping_ui::PingMarkers>();
+        app.init_resource::<AdminPermissions>();
+        app.init_resource::<AppSuspension>();
+        app.init_resource::<DivergenceBisect>();
+        app.init_resource::<AudioCues>();
+        app.init_resource::<LevelIntro>();
+        app.init_resource::<offline_editing::OfflineEditing>();
+        app.init_resource::<memory_budget::MemoryBudget>();
     }
 }
 
@@ -192,6 +368,8 @@ pub struct MuddleClientConfig {
     pub auth0_client_id: Option<String>,
     pub matchmaker_url: Option<Url>,
     pub server_addr: Option<SocketAddr>,
+    /// Is used only if the `discord` feature is enabled.
+    pub discord_client_id: Option<String>,
 }
 
 #[derive(Resource, Default)]
@@ -238,14 +416,14 @@ pub struct EstimatedServerTime {
 /// so that a clien receives updates in time before the simulation.
 /// See the `sync_clock` function.
 #[derive(Resource, Default)]
-pub struct DelayServerTime {
+pub(crate) struct DelayServerTime {
     pub frame_count: i16,
 }
 
 /// If rtt between a client and a server changes, we need to change how much a
 /// client is ahead of a server. See the `sync_clock` function.
 #[derive(Resource)]
-pub struct TargetFramesAhead {
+pub(crate) struct TargetFramesAhead {
     /// Stores the results of `SimulationTime::player_frames_ahead`.
     pub actual_frames_ahead: Framebuffer<u16>,
     pub target: u16,
@@ -264,7 +442,7 @@ impl Default for TargetFramesAhead {
 }
 
 #[derive(Resource)]
-pub struct GameTicksPerSecond {
+pub(crate) struct GameTicksPerSecond {
     pub value: f32,
 }
 
@@ -277,11 +455,15 @@ impl Default for GameTicksPerSecond {
 }
 
 #[derive(Resource, Default)]
-pub struct CurrentPlayerNetId(pub Option<PlayerNetId>);
+pub(crate) struct CurrentPlayerNetId(pub Option<PlayerNetId>);
+
+/// Spawn confirmations are awaited by both the builder UI and the edit
+/// history, so the latest ones are kept until they get pushed out by new ones.
+const LEVEL_OBJECT_CORRELATIONS_LIMIT: usize = 64;
 
 #[derive(Resource, Default)]
-pub struct LevelObjectCorrelations {
-    correlations: HashMap<MessageId, EntityNetId>,
+pub(crate) struct LevelObjectCorrelations {
+    correlations: VecDeque<(MessageId, EntityNetId)>,
     last_correlation_id: MessageId,
 }
 
@@ -293,21 +475,26 @@ impl LevelObjectCorrelations {
     }
 
     pub fn correlate(&mut self, message_id: MessageId, entity_net_id: EntityNetId) {
-        self.correlations.insert(message_id, entity_net_id);
+        if self.correlations.len() == LEVEL_OBJECT_CORRELATIONS_LIMIT {
+            self.correlations.pop_front();
+        }
+        self.correlations.push_back((message_id, entity_net_id));
     }
 
-    pub fn query(&mut self, message_id: MessageId) -> Option<EntityNetId> {
-        let entity_net_id = self.correlations.get(&message_id).copied();
-        self.correlations.clear();
-        entity_net_id
+    pub fn query(&self, message_id: MessageId) -> Option<EntityNetId> {
+        self.correlations
+            .iter()
+            .rev()
+            .find(|(id, _)| *id == message_id)
+            .map(|(_, entity_net_id)| *entity_net_id)
     }
 }
 
 #[derive(Resource)]
-pub struct MainCameraPivotEntity(pub Entity);
+pub(crate) struct MainCameraPivotEntity(pub Entity);
 
 #[derive(Resource)]
-pub struct MainCameraEntity(pub Entity);
+pub(crate) struct MainCameraEntity(pub Entity);
 
 fn pause_simulation_system(
     mut commands: Commands,
@@ -348,7 +535,7 @@ fn pause_simulation_system(
 }
 
 #[derive(SystemParam)]
-pub struct ControlTickingSpeedParams<'w, 's> {
+pub(crate) struct ControlTickingSpeedParams<'w, 's> {
     current_ticks_per_second: ResMut<'w, GameTicksPerSecond>,
     simulation_time: ResMut<'w, SimulationTime>,
     time: ResMut<'w, GameTime>,
@@ -461,6 +648,7 @@ fn ticks_per_second_slower() -> f32 {
 pub struct NetAdaptiveRunCriteriaState {
     accumulator: f64,
     started_looping_at: Option<Instant>,
+    was_suspended: bool,
 }
 
 fn net_adaptive_run_criteria(
@@ -468,11 +656,27 @@ fn net_adaptive_run_criteria(
     time: Res<Time>,
     game_ticks_per_second: Res<GameTicksPerSecond>,
     game_state: Res<CurrentState<GameSessionState>>,
+    suspension: Res<AppSuspension>,
+    #[cfg(feature = "time_dilation")] time_dilation: Res<time_dilation::TimeDilation>,
 ) -> ShouldRun {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
+    if suspension.is_suspended() {
+        state.accumulator = 0.0;
+        state.started_looping_at = None;
+        state.was_suspended = true;
+        return ShouldRun::No;
+    }
+    // The delta of the first frame after resuming covers the time of being
+    // suspended, which we don't want to simulate.
+    if std::mem::take(&mut state.was_suspended) {
+        return ShouldRun::No;
+    }
+
     // See `control_ticking_speed` for the rate value changes.
     let rate = game_ticks_per_second.value;
+    #[cfg(feature = "time_dilation")]
+    let rate = time_dilation.dilate_tick_rate(rate);
     let step = 1.0 / rate as f64;
 
     // If it's the first run after the previous render (or it's the first run ever),
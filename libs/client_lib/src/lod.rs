@@ -0,0 +1,162 @@
+//! Levels may have hundreds of objects, most of which are far away from the
+//! camera. Their meshes are swapped to the simplified variants described by
+//! `LevelObjectLod`, and the furthest decorative objects get hidden once there
+//! are too many of them, which keeps the number of draw calls bounded (this
+//! matters the most for the web client).
+
+use crate::{ui::builder_ui::EditedLevelObject, MainCameraEntity};
+use bevy::{
+    asset::Handle,
+    ecs::{
+        entity::Entity,
+        system::{Query, Res},
+    },
+    render::{mesh::Mesh, view::Visibility},
+    transform::components::GlobalTransform,
+};
+use mr_shared_lib::game::client_factories::LevelObjectLod;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const DECORATIVE_OBJECTS_BUDGET: usize = 1024;
+#[cfg(target_arch = "wasm32")]
+pub const DECORATIVE_OBJECTS_BUDGET: usize = 256;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LodCandidate {
+    pub distance: f32,
+    pub is_decorative: bool,
+    /// Whether the object is already shown with its simplest variant. Objects
+    /// that are close enough to be shown in more detail are never hidden.
+    pub is_simplest: bool,
+}
+
+/// Returns which of the candidates should be hidden: if there are more
+/// decorative objects than the budget allows, the furthest of them are hidden,
+/// as long as they are shown with their simplest variants.
+pub fn hidden_over_budget(candidates: &[LodCandidate], budget: usize) -> Vec<bool> {
+    let mut decorative = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.is_decorative)
+        .map(|(i, candidate)| (i, candidate.distance))
+        .collect::<Vec<_>>();
+    let mut hidden = vec![false; candidates.len()];
+    if decorative.len() <= budget {
+        return hidden;
+    }
+
+    decorative.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    for (i, _) in decorative.into_iter().skip(budget) {
+        hidden[i] = candidates[i].is_simplest;
+    }
+    hidden
+}
+
+pub fn update_level_object_lod_system(
+    main_camera: Res<MainCameraEntity>,
+    edited_level_object: Res<EditedLevelObject>,
+    transforms: Query<&GlobalTransform>,
+    mut level_objects: Query<(
+        Entity,
+        &GlobalTransform,
+        &LevelObjectLod,
+        &mut Handle<Mesh>,
+        &mut Visibility,
+    )>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let Ok(camera_transform) = transforms.get(main_camera.0) else {
+        return;
+    };
+    let camera_position = camera_transform.translation();
+    let edited_entity = edited_level_object
+        .object
+        .as_ref()
+        .map(|(entity, _)| *entity);
+
+    let mut entities = Vec::new();
+    let mut variants = Vec::new();
+    let mut candidates = Vec::new();
+    for (entity, transform, lod, _, _) in level_objects.iter() {
+        let distance = transform.translation().distance(camera_position);
+        // Builders need to see the object they edit in detail.
+        let variant_index = if Some(entity) == edited_entity {
+            0
+        } else {
+            lod.variant_index(distance)
+        };
+        entities.push(entity);
+        variants.push(variant_index);
+        candidates.push(LodCandidate {
+            distance,
+            is_decorative: lod.is_decorative,
+            is_simplest: lod.is_simplest(variant_index),
+        });
+    }
+
+    let hidden = hidden_over_budget(&candidates, DECORATIVE_OBJECTS_BUDGET);
+    for ((entity, variant_index), is_hidden) in entities.into_iter().zip(variants).zip(hidden) {
+        let (_, _, lod, mut mesh, mut visibility) = level_objects.get_mut(entity).unwrap();
+        // Avoiding unnecessary writes to keep change detection quiet.
+        let variant_mesh = &lod.variants[variant_index].mesh;
+        if *mesh != *variant_mesh {
+            *mesh = variant_mesh.clone();
+        }
+        if visibility.is_visible == is_hidden {
+            visibility.is_visible = !is_hidden;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::game::client_factories::LodVariant;
+
+    #[test]
+    fn test_lod_variant_index() {
+        let lod = LevelObjectLod {
+            variants: [0.0, 40.0, 80.0]
+                .into_iter()
+                .map(|min_distance| LodVariant {
+                    min_distance,
+                    mesh: Handle::default(),
+                })
+                .collect(),
+            radius: 10.0,
+            is_decorative: true,
+        };
+        assert_eq!(lod.variant_index(5.0), 0);
+        // The distance is measured to the bounding circle.
+        assert_eq!(lod.variant_index(45.0), 0);
+        assert_eq!(lod.variant_index(50.0), 1);
+        assert_eq!(lod.variant_index(1000.0), 2);
+        assert!(!lod.is_simplest(1));
+        assert!(lod.is_simplest(2));
+    }
+
+    #[test]
+    fn test_hidden_over_budget() {
+        let candidate = |distance: f32, is_decorative: bool, is_simplest: bool| LodCandidate {
+            distance,
+            is_decorative,
+            is_simplest,
+        };
+        let candidates = [
+            candidate(100.0, true, true),
+            candidate(10.0, true, false),
+            // Objects that runners collide with are always shown.
+            candidate(200.0, false, true),
+            candidate(150.0, true, true),
+            candidate(50.0, true, true),
+            // Still shown in more detail.
+            candidate(120.0, true, false),
+        ];
+        assert_eq!(
+            hidden_over_budget(&candidates, 2),
+            vec![true, false, false, true, false, false]
+        );
+        assert_eq!(hidden_over_budget(&candidates, 5), vec![false; 6]);
+    }
+}
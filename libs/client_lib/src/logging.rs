@@ -0,0 +1,152 @@
+//! Replaces Bevy's `LogPlugin` to also keep the latest log lines in memory, so
+//! that they can be attached to bug reports.
+
+use bevy::{
+    app::{App, Plugin},
+    log::{warn, Level},
+    utils::tracing::{
+        field::{Field, Visit},
+        subscriber::set_global_default,
+        Event, Subscriber,
+    },
+};
+use mr_messages_lib::BUG_REPORT_LOGS_MAX_LINES;
+use std::{collections::VecDeque, fmt, fmt::Write, sync::Mutex};
+use tracing_log::{LogTracer, NormalizeEvent};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    EnvFilter, Layer, Registry,
+};
+
+static RECENT_LOGS: Mutex<RecentLogs> = Mutex::new(RecentLogs::new());
+
+/// Returns the latest log lines, the oldest first.
+pub fn recent_logs() -> Vec<String> {
+    RECENT_LOGS.lock().unwrap().lines.iter().cloned().collect()
+}
+
+/// Accepts the same `RUST_LOG` filters as Bevy's `LogPlugin`.
+pub struct MuddleLogPlugin {
+    pub filter: String,
+    pub level: Level,
+}
+
+impl Default for MuddleLogPlugin {
+    fn default() -> Self {
+        Self {
+            filter: "wgpu=error".to_owned(),
+            level: Level::INFO,
+        }
+    }
+}
+
+impl Plugin for MuddleLogPlugin {
+    fn build(&self, _app: &mut App) {
+        let default_filter = format!("{},{}", self.level, self.filter);
+        let filter_layer = EnvFilter::try_from_default_env()
+            .or_else(|_| EnvFilter::try_new(&default_filter))
+            .unwrap();
+        let subscriber = Registry::default().with(filter_layer).with(RecentLogsLayer);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let subscriber = subscriber.with(tracing_subscriber::fmt::Layer::default());
+        #[cfg(target_arch = "wasm32")]
+        let subscriber = {
+            console_error_panic_hook::set_once();
+            subscriber.with(tracing_wasm::WASMLayer::new(
+                tracing_wasm::WASMLayerConfig::default(),
+            ))
+        };
+
+        let logger_already_set = LogTracer::init().is_err();
+        let subscriber_already_set = set_global_default(subscriber).is_err();
+        if logger_already_set || subscriber_already_set {
+            warn!("Could not set the global logger or tracing subscriber as they are already set, log lines won't be attached to bug reports");
+        }
+    }
+}
+
+struct RecentLogs {
+    lines: VecDeque<String>,
+}
+
+impl RecentLogs {
+    const fn new() -> Self {
+        Self {
+            lines: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.lines.len() == BUG_REPORT_LOGS_MAX_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+struct RecentLogsLayer;
+
+impl<S: Subscriber> Layer<S> for RecentLogsLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        // Events forwarded from the `log` crate carry their metadata in fields.
+        let normalized_metadata = event.normalized_metadata();
+        let metadata = normalized_metadata
+            .as_ref()
+            .unwrap_or_else(|| event.metadata());
+
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        RECENT_LOGS.lock().unwrap().push(format!(
+            "{} {} {}: {}{}",
+            chrono::Utc::now().format("%H:%M:%S%.3f"),
+            metadata.level(),
+            metadata.target(),
+            visitor.message,
+            visitor.fields
+        ));
+    }
+}
+
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{value:?}");
+            }
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.fields, " {name}={value:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::utils::tracing;
+
+    #[test]
+    fn test_recent_logs() {
+        let subscriber = Registry::default().with(RecentLogsLayer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(frame = 5, "Rollback is too deep");
+        });
+        assert!(recent_logs().iter().any(|line| line
+            .ends_with("WARN mr_client_lib::logging::tests: Rollback is too deep frame=5")));
+
+        let mut recent_logs = RecentLogs::new();
+        for i in 0..BUG_REPORT_LOGS_MAX_LINES + 1 {
+            recent_logs.push(i.to_string());
+        }
+        assert_eq!(recent_logs.lines.len(), BUG_REPORT_LOGS_MAX_LINES);
+        assert_eq!(recent_logs.lines.front().unwrap(), "1");
+    }
+}
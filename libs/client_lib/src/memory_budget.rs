@@ -0,0 +1,340 @@
+use crate::input::{LevelObjectRequestsQueue, PlayerRequestsQueue};
+use bevy::{
+    asset::Assets,
+    ecs::system::{Query, Res, ResMut, Resource, SystemParam},
+    log,
+    pbr::StandardMaterial,
+    render::mesh::Mesh,
+    utils::Instant,
+};
+use iyes_loopless::state::CurrentState;
+use mr_shared_lib::{
+    game::components::{PlayerDirection, Position},
+    messages::PlayerNetId,
+    player::{PlayerUpdates, Players},
+    registry::EntityRegistry,
+    GameSessionState,
+};
+use std::time::Duration;
+
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// An alarm is raised once the total usage grows this many times compared to
+/// the baseline...
+const MEMORY_GROWTH_ALARM_FACTOR: f32 = 2.0;
+/// ...and by at least this many bytes, so that small numbers of a fresh
+/// session don't cause false alarms.
+const MEMORY_GROWTH_ALARM_MIN_BYTES: usize = 4 * 1024 * 1024;
+/// Requests pile up while the client can't send them (i.e. while it's
+/// reconnecting), the oldest ones are stale by the time we reach the limit.
+pub const PENDING_REQUESTS_LIMIT: usize = 64;
+/// Meshes don't keep their GPU buffers on the CPU side, only vertex
+/// attributes (positions, normals and UVs) and indices are accounted for.
+const VERTEX_SIZE_ESTIMATE: usize = 32;
+const INDEX_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Estimated sizes (in bytes) of the major buffers. These are lower bounds:
+/// allocator overhead and hash map buckets aren't taken into account.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub player_updates: usize,
+    pub framebuffers: usize,
+    pub level_assets: usize,
+    pub pending_queues: usize,
+}
+
+impl MemoryUsage {
+    pub fn breakdown(&self) -> [(&'static str, usize); 4] {
+        [
+            ("Player updates", self.player_updates),
+            ("Component framebuffers", self.framebuffers),
+            ("Level assets", self.level_assets),
+            ("Pending queues", self.pending_queues),
+        ]
+    }
+
+    pub fn total(&self) -> usize {
+        self.breakdown().iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Keeps the latest memory usage sample to show it in the debug UI, and the
+/// baseline to detect leaks. The baseline is captured when a session starts,
+/// and is raised after every alarm, so that the same growth isn't reported
+/// twice.
+#[derive(Resource, Default)]
+pub struct MemoryBudget {
+    pub latest: MemoryUsage,
+    baseline: Option<MemoryUsage>,
+    sampled_at: Option<Instant>,
+    pub alarms: u32,
+    pub trimmed_player_updates: usize,
+    pub trimmed_requests: usize,
+}
+
+impl MemoryBudget {
+    /// Returns the baseline if the usage has grown beyond the thresholds.
+    pub fn record(&mut self, usage: MemoryUsage) -> Option<MemoryUsage> {
+        self.latest = usage;
+        let baseline = *self.baseline.get_or_insert(usage);
+        let total = usage.total();
+        let baseline_total = baseline.total();
+        if total as f32 <= baseline_total as f32 * MEMORY_GROWTH_ALARM_FACTOR
+            || total - baseline_total < MEMORY_GROWTH_ALARM_MIN_BYTES
+        {
+            return None;
+        }
+        self.alarms += 1;
+        self.baseline = Some(usage);
+        Some(baseline)
+    }
+
+    /// Sizes of different sessions (or levels) aren't comparable.
+    pub fn reset_baseline(&mut self) {
+        self.baseline = None;
+    }
+
+    fn should_sample(&mut self, now: Instant) -> bool {
+        if self.sampled_at.map_or(false, |sampled_at| {
+            now.duration_since(sampled_at) < MEMORY_SAMPLE_INTERVAL
+        }) {
+            return false;
+        }
+        self.sampled_at = Some(now);
+        true
+    }
+}
+
+#[derive(SystemParam)]
+pub struct TrackedBuffers<'w, 's> {
+    player_updates: ResMut<'w, PlayerUpdates>,
+    positions: Query<'w, 's, &'static Position>,
+    player_directions: Query<'w, 's, &'static PlayerDirection>,
+    meshes: Res<'w, Assets<Mesh>>,
+    materials: Res<'w, Assets<StandardMaterial>>,
+    player_requests: ResMut<'w, PlayerRequestsQueue>,
+    level_object_requests: ResMut<'w, LevelObjectRequestsQueue>,
+}
+
+impl<'w, 's> TrackedBuffers<'w, 's> {
+    fn usage(&self) -> MemoryUsage {
+        let player_updates = self
+            .player_updates
+            .direction
+            .values()
+            .map(|buffer| buffer.allocated_bytes())
+            .chain(
+                self.player_updates
+                    .position
+                    .values()
+                    .map(|buffer| buffer.allocated_bytes()),
+            )
+            .sum();
+        let framebuffers = self
+            .positions
+            .iter()
+            .map(|position| position.buffer.allocated_bytes())
+            .chain(
+                self.player_directions
+                    .iter()
+                    .map(|direction| direction.buffer.allocated_bytes()),
+            )
+            .sum();
+        let level_assets = self
+            .meshes
+            .iter()
+            .map(|(_, mesh)| {
+                mesh.count_vertices() * VERTEX_SIZE_ESTIMATE
+                    + mesh
+                        .indices()
+                        .map_or(0, |indices| indices.len() * INDEX_SIZE)
+            })
+            .sum::<usize>()
+            + self.materials.len() * std::mem::size_of::<StandardMaterial>();
+
+        let player_requests = &*self.player_requests;
+        let level_object_requests = &*self.level_object_requests;
+        let pending_queues = queue_bytes(&player_requests.switch_role)
+            + queue_bytes(&player_requests.practice_bots)
+            + queue_bytes(&player_requests.publish_level)
+            + queue_bytes(&player_requests.state_hash)
+            + queue_bytes(&player_requests.admin_commands)
+            + queue_bytes(&level_object_requests.spawn_requests)
+            + queue_bytes(&level_object_requests.update_requests)
+            + queue_bytes(&level_object_requests.despawn_requests);
+
+        MemoryUsage {
+            player_updates,
+            framebuffers,
+            level_assets,
+            pending_queues,
+        }
+    }
+
+    /// Returns the number of dropped requests.
+    fn trim_pending_queues(&mut self) -> usize {
+        let player_requests = &mut *self.player_requests;
+        let level_object_requests = &mut *self.level_object_requests;
+        trim_queue(&mut player_requests.switch_role)
+            + trim_queue(&mut player_requests.practice_bots)
+            + trim_queue(&mut player_requests.publish_level)
+            + trim_queue(&mut player_requests.state_hash)
+            + trim_queue(&mut player_requests.admin_commands)
+            + trim_queue(&mut level_object_requests.spawn_requests)
+            + trim_queue(&mut level_object_requests.update_requests)
+            + trim_queue(&mut level_object_requests.despawn_requests)
+    }
+}
+
+fn queue_bytes<T>(queue: &Vec<T>) -> usize {
+    queue.capacity() * std::mem::size_of::<T>()
+}
+
+/// Drops the oldest items that don't fit into `PENDING_REQUESTS_LIMIT`.
+pub fn trim_queue<T>(queue: &mut Vec<T>) -> usize {
+    let excess = queue.len().saturating_sub(PENDING_REQUESTS_LIMIT);
+    queue.drain(..excess);
+    excess
+}
+
+pub fn track_memory_usage_system(
+    mut memory_budget: ResMut<MemoryBudget>,
+    game_state: Res<CurrentState<GameSessionState>>,
+    players: Res<Players>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    mut tracked_buffers: TrackedBuffers,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if game_state.0 != GameSessionState::Playing {
+        memory_budget.reset_baseline();
+        return;
+    }
+    if !memory_budget.should_sample(Instant::now()) {
+        return;
+    }
+
+    // Updates of disconnected players are useless once their entities are
+    // despawned, as we never rewind past despawning.
+    let is_gone = |net_id: &PlayerNetId| {
+        player_registry.get_entity(*net_id).is_none()
+            && players
+                .get(net_id)
+                .map_or(true, |player| !player.is_connected)
+    };
+    let player_updates = &mut *tracked_buffers.player_updates;
+    let buffers_count = player_updates.direction.len() + player_updates.position.len();
+    player_updates
+        .direction
+        .retain(|net_id, _| !is_gone(net_id));
+    player_updates.position.retain(|net_id, _| !is_gone(net_id));
+    let trimmed_player_updates =
+        buffers_count - player_updates.direction.len() - player_updates.position.len();
+    if trimmed_player_updates > 0 {
+        log::debug!(
+            "Dropped {} player update buffers of disconnected players",
+            trimmed_player_updates
+        );
+        memory_budget.trimmed_player_updates += trimmed_player_updates;
+    }
+
+    let trimmed_requests = tracked_buffers.trim_pending_queues();
+    if trimmed_requests > 0 {
+        log::warn!("Dropped {} stale pending requests", trimmed_requests);
+        memory_budget.trimmed_requests += trimmed_requests;
+    }
+
+    let usage = tracked_buffers.usage();
+    if let Some(baseline) = memory_budget.record(usage) {
+        let breakdown = usage
+            .breakdown()
+            .iter()
+            .zip(baseline.breakdown())
+            .map(|((label, bytes), (_, baseline_bytes))| {
+                format!(
+                    "{}: {} ({})",
+                    label,
+                    format_bytes(*bytes),
+                    format_bytes_diff(*bytes, baseline_bytes)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        log::error!(
+            "Memory usage has grown from {} to {}: {}",
+            format_bytes(baseline.total()),
+            format_bytes(usage.total()),
+            breakdown
+        );
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else if bytes >= KIB {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+fn format_bytes_diff(bytes: usize, baseline_bytes: usize) -> String {
+    if bytes >= baseline_bytes {
+        format!("+{}", format_bytes(bytes - baseline_bytes))
+    } else {
+        format!("-{}", format_bytes(baseline_bytes - bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(player_updates: usize, level_assets: usize) -> MemoryUsage {
+        MemoryUsage {
+            player_updates,
+            level_assets,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_memory_growth_alarm() {
+        const MIB: usize = 1024 * 1024;
+        let mut memory_budget = MemoryBudget::default();
+        assert_eq!(memory_budget.record(usage(MIB / 4, 0)), None);
+        // Grows more than twice, but not enough in absolute numbers.
+        assert_eq!(memory_budget.record(usage(MIB, MIB)), None);
+        assert_eq!(
+            memory_budget.record(usage(4 * MIB, MIB)),
+            Some(usage(MIB / 4, 0))
+        );
+        assert_eq!(memory_budget.alarms, 1);
+        // The baseline is raised after the alarm.
+        assert_eq!(memory_budget.record(usage(8 * MIB, MIB)), None);
+
+        memory_budget.reset_baseline();
+        assert_eq!(memory_budget.record(usage(20 * MIB, MIB)), None);
+        assert_eq!(memory_budget.alarms, 1);
+    }
+
+    #[test]
+    fn test_trim_queue() {
+        let mut queue = (0..PENDING_REQUESTS_LIMIT + 3).collect::<Vec<_>>();
+        assert_eq!(trim_queue(&mut queue), 3);
+        assert_eq!(queue.len(), PENDING_REQUESTS_LIMIT);
+        assert_eq!(queue[0], 3);
+        assert_eq!(trim_queue(&mut queue), 0);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+        assert_eq!(format_bytes_diff(1024, 2048), "-1.0 KiB");
+    }
+}
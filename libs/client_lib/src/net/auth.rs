@@ -3,12 +3,14 @@ use crate::{
     config_storage::{OfflineAuthConfig, AUTH_CONFIG_KEY},
     net::persistence::PersistenceClient,
     utils::parse_jwt,
+    MuddleClientConfig,
 };
 use bevy::{ecs::system::ResMut, log};
 use core::slice::SlicePattern;
 use mr_messages_lib::{
-    ErrorKind, ErrorResponse, LinkAccount, LinkAccountError, LinkAccountLoginMethod,
-    LinkAccountRequest, PatchUserError, PatchUserRequest, RegisterAccountError, RegisteredUser,
+    validation::ValidationError, ErrorKind, ErrorResponse, LinkAccount, LinkAccountError,
+    LinkAccountLoginMethod, LinkAccountRequest, PatchUserError, PatchUserRequest,
+    RegisterAccountError, RegisteredUser,
 };
 use reqwest::IntoUrl;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -192,6 +194,7 @@ pub enum AuthMessage {
     WrongPasswordError,
     SignUpFailedError,
     DisplayNameTakenError,
+    InvalidDisplayNameError(Vec<ValidationError>),
     UnavailableError,
     InvalidOrExpiredAuthError,
     LinkAccount {
@@ -208,6 +211,22 @@ pub struct AuthConfig {
     pub auth0_client_id: String,
 }
 
+impl AuthConfig {
+    /// Returns `None` if any of the credentials isn't set, which leaves only
+    /// the guest sessions available.
+    pub fn from_client_config(client_config: &MuddleClientConfig) -> Option<Self> {
+        let google_client_secret = client_config.google_client_secret.clone();
+        if cfg!(not(target_arch = "wasm32")) && google_client_secret.is_none() {
+            return None;
+        }
+        Some(Self {
+            google_client_id: client_config.google_client_id.clone()?,
+            google_client_secret,
+            auth0_client_id: client_config.auth0_client_id.clone()?,
+        })
+    }
+}
+
 pub struct PendingOAuthRequest {
     username: Option<String>,
     login_hint: Option<String>,
@@ -685,6 +704,12 @@ impl AuthRequestsHandler {
             })) => {
                 self.send_auth_message(AuthMessage::DisplayNameTakenError);
             }
+            Some(Err(ErrorResponse {
+                error_kind: ErrorKind::RouteSpecific(PatchUserError::InvalidDisplayName(errors)),
+                ..
+            })) => {
+                self.send_auth_message(AuthMessage::InvalidDisplayNameError(errors));
+            }
             Some(Err(ErrorResponse {
                 error_kind: ErrorKind::Unauthorized | ErrorKind::NotFound | ErrorKind::Forbidden,
                 ..
@@ -0,0 +1,197 @@
+use bevy::{ecs::system::Resource, utils::Instant};
+use mr_shared_lib::messages::{Message, UnreliableClientMessage, UnreliableServerMessage};
+use rand::Rng;
+use std::time::Duration;
+
+pub const MAX_LATENCY_MILLIS: u64 = 1000;
+pub const MAX_JITTER_MILLIS: u64 = 500;
+/// Reordered messages are held back for this long on top of their latency, so
+/// that the following ones overtake them.
+const REORDERING_DELAY_MILLIS: u64 = 50;
+
+/// Simulates a bad network between `process_network_events_system` (and the
+/// systems sending updates) and the real socket, to exercise `sync_clock`
+/// locally. Is available in debug builds and is controlled in the debug UI.
+///
+/// Only unreliable messages are affected: the transport retransmits and orders
+/// reliable ones anyway, and those aren't used for syncing the clock.
+#[derive(Resource, Default)]
+pub struct NetworkConditioner {
+    pub enabled: bool,
+    pub conditions: NetworkConditions,
+    incoming: ConditionedQueue<Message<UnreliableServerMessage>>,
+    outgoing: ConditionedQueue<Message<UnreliableClientMessage>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetworkConditions {
+    /// Is applied in both directions, so the RTT grows by twice the value.
+    pub latency: Duration,
+    /// A random duration up to this value is added to `latency` for each
+    /// message.
+    pub jitter: Duration,
+    /// The probability of a message being dropped, from 0.0 to 1.0.
+    pub packet_loss: f32,
+    /// The probability of a message being held back and arriving after the
+    /// following ones, from 0.0 to 1.0.
+    pub reordering: f32,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            packet_loss: 0.0,
+            reordering: 0.0,
+        }
+    }
+}
+
+impl NetworkConditioner {
+    /// Returns the message back if the conditioner is disabled.
+    pub fn delay_incoming(
+        &mut self,
+        message: Message<UnreliableServerMessage>,
+        now: Instant,
+    ) -> Option<Message<UnreliableServerMessage>> {
+        if !self.enabled {
+            return Some(message);
+        }
+        self.incoming.push(message, &self.conditions, now);
+        None
+    }
+
+    /// Messages that were delayed before disabling the conditioner are still
+    /// released at their time.
+    pub fn release_incoming(&mut self, now: Instant) -> Vec<Message<UnreliableServerMessage>> {
+        self.incoming.release(now)
+    }
+
+    /// Returns the message back if the conditioner is disabled.
+    pub fn delay_outgoing(
+        &mut self,
+        message: Message<UnreliableClientMessage>,
+        now: Instant,
+    ) -> Option<Message<UnreliableClientMessage>> {
+        if !self.enabled {
+            return Some(message);
+        }
+        self.outgoing.push(message, &self.conditions, now);
+        None
+    }
+
+    pub fn release_outgoing(&mut self, now: Instant) -> Vec<Message<UnreliableClientMessage>> {
+        self.outgoing.release(now)
+    }
+
+    /// Returns the numbers of incoming and outgoing messages that are waiting
+    /// to be released.
+    pub fn delayed_messages_count(&self) -> (usize, usize) {
+        (self.incoming.items.len(), self.outgoing.items.len())
+    }
+
+    /// Returns the numbers of incoming and outgoing messages that have been
+    /// dropped.
+    pub fn dropped_messages_count(&self) -> (u64, u64) {
+        (self.incoming.dropped_count, self.outgoing.dropped_count)
+    }
+
+    pub fn reset_settings(&mut self) {
+        self.enabled = false;
+        self.conditions = NetworkConditions::default();
+    }
+
+    /// Delayed messages become invalid after reconnecting.
+    pub fn clear(&mut self) {
+        self.incoming.items.clear();
+        self.outgoing.items.clear();
+    }
+}
+
+struct ConditionedQueue<T> {
+    items: Vec<(Instant, T)>,
+    dropped_count: u64,
+}
+
+impl<T> Default for ConditionedQueue<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            dropped_count: 0,
+        }
+    }
+}
+
+impl<T> ConditionedQueue<T> {
+    fn push(&mut self, item: T, conditions: &NetworkConditions, now: Instant) {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(conditions.packet_loss.clamp(0.0, 1.0).into()) {
+            self.dropped_count += 1;
+            return;
+        }
+
+        let mut delay = conditions.latency + rng.gen_range(Duration::ZERO..=conditions.jitter);
+        if rng.gen_bool(conditions.reordering.clamp(0.0, 1.0).into()) {
+            delay += Duration::from_millis(REORDERING_DELAY_MILLIS);
+        }
+        self.items.push((now + delay, item));
+    }
+
+    /// Returns the items that are due, ordered by their release time. Items
+    /// with the same release time keep the order they were pushed in.
+    fn release(&mut self, now: Instant) -> Vec<T> {
+        let (mut released, delayed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.items)
+            .into_iter()
+            .partition(|(release_at, _)| *release_at <= now);
+        self.items = delayed;
+        released.sort_by_key(|(release_at, _)| *release_at);
+        released.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditioned_queue_latency_and_packet_loss() {
+        let start = Instant::now();
+        let millis = |value| start + Duration::from_millis(value);
+        let mut queue = ConditionedQueue::default();
+
+        let mut conditions = NetworkConditions {
+            latency: Duration::from_millis(100),
+            ..NetworkConditions::default()
+        };
+        queue.push(1, &conditions, millis(0));
+        queue.push(2, &conditions, millis(0));
+        queue.push(3, &conditions, millis(20));
+        conditions.packet_loss = 1.0;
+        queue.push(4, &conditions, millis(20));
+        assert_eq!(queue.dropped_count, 1);
+
+        assert!(queue.release(millis(99)).is_empty());
+        assert_eq!(queue.release(millis(100)), vec![1, 2]);
+        assert_eq!(queue.release(millis(200)), vec![3]);
+        assert!(queue.items.is_empty());
+    }
+
+    #[test]
+    fn test_conditioned_queue_reordering() {
+        let start = Instant::now();
+        let millis = |value| start + Duration::from_millis(value);
+        let mut queue = ConditionedQueue::default();
+
+        let mut conditions = NetworkConditions {
+            reordering: 1.0,
+            ..NetworkConditions::default()
+        };
+        queue.push(1, &conditions, millis(0));
+        conditions.reordering = 0.0;
+        queue.push(2, &conditions, millis(10));
+
+        assert_eq!(queue.release(millis(10)), vec![2]);
+        assert_eq!(queue.release(millis(REORDERING_DELAY_MILLIS)), vec![1]);
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
 use bevy::log;
 use futures::{select, FutureExt, SinkExt, StreamExt, TryStreamExt};
 use mr_messages_lib::{deserialize_binary, serialize_binary, MatchmakerMessage, MatchmakerRequest};
+use mr_utils_lib::executor;
 use std::time::Duration;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use url::Url;
@@ -48,7 +49,7 @@ impl MatchmakerRequestsHandler {
                 let ws_status_tx = self.status_tx.clone();
                 let disconnect_request_channel = tokio::sync::oneshot::channel();
                 disconnect_request_tx = Some(disconnect_request_channel.0);
-                tokio::task::spawn_local(handle_matchmaker_connection(
+                executor::spawn_local(handle_matchmaker_connection(
                     message_tx,
                     matchmaker_request_rx.take().unwrap(),
                     url,
@@ -62,9 +63,7 @@ impl MatchmakerRequestsHandler {
                     log::info!("Dropping the connection with the matchmaker service...");
                     let _ = self.status_tx.send(TcpConnectionStatus::Disconnected);
                 }
-                wasm_timer::Delay::new(Duration::from_millis(500))
-                    .await
-                    .unwrap();
+                executor::sleep(Duration::from_millis(500)).await;
             }
 
             current_state = connect;
@@ -1,14 +1,37 @@
-pub use persistence::{PersistenceMessage, PersistenceMessagePayload, PersistenceRequest};
+pub use persistence::{
+    PersistenceMessage, PersistenceMessagePayload, PersistenceRequest, SubmittedBugReport,
+};
 
+#[cfg(debug_assertions)]
+use crate::net::conditioner::NetworkConditioner;
+#[cfg(feature = "time_dilation")]
+use crate::time_dilation::TimeDilation;
 use crate::{
+    determinism::DivergenceBisect,
+    edit_history::LevelEditHistory,
+    game_events::GhostRuns,
+    guest::Guest,
     input::{LevelObjectRequestsQueue, PlayerRequestsQueue},
+    input_latency::InputLatency,
+    input_send_rate::InputSendRate,
+    level_publishing::LevelPublishing,
     net::{
         auth::AuthConfig,
         matchmaker::MatchmakerRequestsHandler,
         persistence::{PersistenceClient, PersistenceRequestsHandler},
+        spectator::{is_spectating, SpectatorSnapshots},
+    },
+    personal_bests::PersonalBests,
+    server_health::ServerHealthReport,
+    session_summary::SessionSummary,
+    ui::{
+        admin_ui::AdminBroadcasts,
+        builder_ui::{EditedLevelObject, InvalidLevelObjectShapes, LevelSaveErrors},
+        match_ui::MatchStatus,
+        ping_ui::PingMarkers,
     },
     CurrentPlayerNetId, DelayServerTime, EstimatedServerTime, InitialRtt, LevelObjectCorrelations,
-    MuddleClientConfig, TargetFramesAhead,
+    MainCameraPivotEntity, MuddleClientConfig, TargetFramesAhead,
 };
 use auth::{AuthMessage, AuthRequest};
 use bevy::{ecs::system::SystemParam, log, prelude::*, utils::Instant};
@@ -17,18 +40,24 @@ use futures::{select, FutureExt};
 use iyes_loopless::state::NextState;
 use mr_messages_lib::{GameServerState, MatchmakerMessage, MatchmakerRequest, Server};
 use mr_shared_lib::{
+    constants::ConstantsFingerprint,
+    delta_compression::decode_player_states,
     framebuffer::{FrameNumber, Framebuffer},
     game::{
         commands::{
             DeferredQueue, DespawnLevelObject, DespawnPlayer, DespawnReason, SpawnPlayer,
-            SwitchPlayerRole, UpdateLevelObject,
+            SwitchPlayerRole, UpdateLevelObject, UpdateLevelSettings,
         },
         components::{PlayerDirection, Spawned},
+        determinism::DeterminismGuard,
+        level::LevelState,
+        tether::Tethers,
     },
     messages::{
-        DeltaUpdate, DisconnectReason, DisconnectedPlayer, Message, PlayerInputs, PlayerNetId,
-        PlayerUpdate, ReliableClientMessage, ReliableServerMessage, RespawnPlayerReason,
-        RunnerInput, StartGame, UnreliableClientMessage, UnreliableServerMessage,
+        BuilderState, DeltaUpdate, DisconnectReason, DisconnectedPlayer, Message, PlayerInputs,
+        PlayerNetId, PlayerState, PlayerUpdate, ReliableClientMessage, ReliableServerMessage,
+        RespawnPlayerReason, RunnerInput, StartGame, UnreliableClientMessage,
+        UnreliableServerMessage,
     },
     net::{
         AcknowledgeError, ConnectionState, ConnectionStatus, MessageId, SessionId,
@@ -39,8 +68,8 @@ use mr_shared_lib::{
     AppState, GameSessionState, GameTime, LevelObjectsToSpawnToLoad, SimulationTime,
     COMPONENT_FRAMEBUFFER_LIMIT, SIMULATIONS_PER_SECOND,
 };
+use mr_utils_lib::executor;
 use std::{
-    future::Future,
     marker::PhantomData,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     time::Duration,
@@ -50,6 +79,9 @@ use tokio::sync::mpsc::{
 };
 
 pub mod auth;
+#[cfg(debug_assertions)]
+pub mod conditioner;
+pub mod spectator;
 
 #[cfg(target_arch = "wasm32")]
 mod listen_local_storage;
@@ -61,6 +93,10 @@ mod redirect_uri_server;
 pub const DEFAULT_SERVER_PORT: u16 = 3455;
 pub const DEFAULT_SERVER_IP_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
 
+/// What other builders are doing, according to the latest delta update.
+#[derive(Resource, Default)]
+pub struct BuilderStates(pub Vec<BuilderState>);
+
 #[derive(SystemParam)]
 pub struct UpdateParams<'w, 's> {
     simulation_time: ResMut<'w, SimulationTime>,
@@ -72,18 +108,60 @@ pub struct UpdateParams<'w, 's> {
     initial_rtt: ResMut<'w, InitialRtt>,
     player_updates: ResMut<'w, PlayerUpdates>,
     level_object_correlations: ResMut<'w, LevelObjectCorrelations>,
-    spawn_level_object_commands: ResMut<'w, DeferredQueue<UpdateLevelObject>>,
-    despawn_level_object_commands: ResMut<'w, DeferredQueue<DespawnLevelObject>>,
+    level_commands: LevelCommandQueues<'w, 's>,
     spawn_player_commands: ResMut<'w, DeferredQueue<SpawnPlayer>>,
     despawn_player_commands: ResMut<'w, DeferredQueue<DespawnPlayer>>,
     switch_role_commands: ResMut<'w, DeferredQueue<SwitchPlayerRole>>,
+    session: SessionParams<'w, 's>,
     spawned_query: Query<'w, 's, &'static Spawned>,
+    input_latency: ResMut<'w, InputLatency>,
+}
+
+#[derive(SystemParam)]
+pub struct SessionParams<'w, 's> {
+    connected_server: ResMut<'w, ConnectedServer>,
+    personal_bests: ResMut<'w, PersonalBests>,
+    ghost_runs: ResMut<'w, GhostRuns>,
+    server_health: ResMut<'w, ServerHealthReport>,
+    level_publishing: ResMut<'w, LevelPublishing>,
+    divergence_bisect: ResMut<'w, DivergenceBisect>,
+    determinism_guard: ResMut<'w, DeterminismGuard>,
+    invalid_level_object_shapes: ResMut<'w, InvalidLevelObjectShapes>,
+    level_save_errors: ResMut<'w, LevelSaveErrors>,
+    tethers: ResMut<'w, Tethers>,
+    spectator_snapshots: ResMut<'w, SpectatorSnapshots>,
+    coordination: CoordinationParams<'w, 's>,
+    session_summary: ResMut<'w, SessionSummary>,
+    admin_broadcasts: ResMut<'w, AdminBroadcasts>,
+    match_status: ResMut<'w, MatchStatus>,
+}
+
+/// What other players share to coordinate with each other.
+#[derive(SystemParam)]
+pub struct CoordinationParams<'w, 's> {
+    builder_states: ResMut<'w, BuilderStates>,
+    ping_markers: ResMut<'w, PingMarkers>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+#[derive(SystemParam)]
+pub struct LevelCommandQueues<'w, 's> {
+    spawn_level_object_commands: ResMut<'w, DeferredQueue<UpdateLevelObject>>,
+    despawn_level_object_commands: ResMut<'w, DeferredQueue<DespawnLevelObject>>,
+    update_level_settings_commands: ResMut<'w, DeferredQueue<UpdateLevelSettings>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
 #[derive(SystemParam)]
 pub struct NetworkParams<'w, 's> {
     net: NonSendMut<'w, NetworkResource>,
     connection_state: ResMut<'w, ConnectionState>,
+    #[cfg(feature = "time_dilation")]
+    time_dilation: ResMut<'w, TimeDilation>,
+    #[cfg(debug_assertions)]
+    network_conditioner: ResMut<'w, NetworkConditioner>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
@@ -126,6 +204,15 @@ pub fn has_server_to_connect(
 #[derive(Resource, DerefMut, Deref, Default)]
 pub struct ServerToConnect(pub Option<Server>);
 
+/// Describes the server a client is currently connected to.
+#[derive(Resource, Default)]
+pub struct ConnectedServer {
+    pub server: Option<Server>,
+    /// Is `None` for levels that aren't stored by the persistence service.
+    pub level_id: Option<i64>,
+    pub level_title: Option<String>,
+}
+
 pub fn init_matchmaker_connection_system(
     mut commands: Commands,
     client_config: Res<MuddleClientConfig>,
@@ -138,22 +225,11 @@ pub fn init_matchmaker_connection_system(
         }
     };
 
-    let auth_config = AuthConfig {
-        google_client_id: client_config
-            .google_client_id
-            .clone()
-            .expect("Expected MUDDLE_GOOGLE_CLIENT_ID"),
-        google_client_secret: client_config.google_client_secret.clone(),
-        auth0_client_id: client_config
-            .auth0_client_id
-            .clone()
-            .expect("Expected MUDDLE_AUTH0_CLIENT_ID"),
-    };
-    if cfg!(not(target_arch = "wasm32")) {
-        auth_config
-            .google_client_secret
-            .as_ref()
-            .expect("Expected MUDDLE_GOOGLE_CLIENT_SECRET");
+    let auth_config = AuthConfig::from_client_config(&client_config);
+    if auth_config.is_none() {
+        log::warn!(
+            "Auth credentials (MUDDLE_GOOGLE_CLIENT_ID, MUDDLE_GOOGLE_CLIENT_SECRET, MUDDLE_AUTH0_CLIENT_ID) aren't set, only playing as a guest is available"
+        );
     }
 
     log::info!("Matchmaker address: {}", matchmaker_url);
@@ -174,21 +250,33 @@ pub fn init_matchmaker_connection_system(
         .clone()
         .expect("Expected MUDDLE_PUBLIC_PERSISTENCE_URL");
     let persistence_client = PersistenceClient::new(Default::default(), persistence_url);
-    run_async(async move {
+    executor::run_detached(async move {
         #[cfg(not(target_arch = "wasm32"))]
         let mut serve_redirect_uri_future =
-            tokio::task::spawn_local(redirect_uri_server::serve(auth_request_tx_clone.clone()))
-                .fuse();
+            executor::spawn_local(redirect_uri_server::serve(auth_request_tx_clone.clone())).fuse();
         #[cfg(target_arch = "wasm32")]
         let mut serve_redirect_uri_future =
-            tokio::task::spawn_local(listen_local_storage::serve(auth_request_tx_clone.clone()))
+            executor::spawn_local(listen_local_storage::serve(auth_request_tx_clone.clone()))
                 .fuse();
-        let mut serve_auth_future = tokio::task::spawn_local(auth::serve_auth_requests(
-            persistence_client.clone(),
-            auth_config,
-            auth_request_rx,
-            auth_message_tx_clone,
-        ))
+        let auth_persistence_client = persistence_client.clone();
+        let mut serve_auth_future = executor::spawn_local(async move {
+            match auth_config {
+                Some(auth_config) => {
+                    auth::serve_auth_requests(
+                        auth_persistence_client,
+                        auth_config,
+                        auth_request_rx,
+                        auth_message_tx_clone,
+                    )
+                    .await
+                }
+                // Keeps the channel open, the auth screens aren't shown anyway.
+                None => {
+                    let _auth_request_rx = auth_request_rx;
+                    std::future::pending().await
+                }
+            }
+        })
         .fuse();
         let matchmaker_requests_handler = MatchmakerRequestsHandler {
             url: matchmaker_url,
@@ -197,15 +285,14 @@ pub fn init_matchmaker_connection_system(
             matchmaker_message_tx,
         };
         let mut serve_matchmaker_future =
-            tokio::task::spawn_local(matchmaker_requests_handler.serve(matchmaker_request_rx))
-                .fuse();
+            executor::spawn_local(matchmaker_requests_handler.serve(matchmaker_request_rx)).fuse();
         let persistence_requests_handler = PersistenceRequestsHandler {
             client: persistence_client.clone(),
             request_rx: persistence_request_rx,
             message_tx: persistence_message_tx,
         };
         let mut serve_persistence_future =
-            tokio::task::spawn_local(persistence_requests_handler.serve()).fuse();
+            executor::spawn_local(persistence_requests_handler.serve()).fuse();
         select! {
             _ = serve_redirect_uri_future => {
                 log::warn!("Redirect uri server task finished");
@@ -241,48 +328,12 @@ pub fn init_matchmaker_connection_system(
     });
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-pub fn run_async<F>(future: F)
-where
-    F: Future<Output = ()> + Send + 'static,
-{
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Cannot start tokio runtime");
-
-        rt.block_on(async move {
-            let local = tokio::task::LocalSet::new();
-            local
-                .run_until(async move {
-                    tokio::task::spawn_local(future).await.unwrap();
-                })
-                .await;
-        });
-    });
-}
-
-#[cfg(target_arch = "wasm32")]
-pub fn run_async<F>(future: F)
-where
-    F: Future<Output = ()> + Send + 'static,
-{
-    wasm_bindgen_futures::spawn_local(async move {
-        let local = tokio::task::LocalSet::new();
-        local
-            .run_until(async move {
-                tokio::task::spawn_local(future).await.unwrap();
-            })
-            .await;
-    });
-}
-
 #[derive(SystemParam)]
 pub struct MatchmakerParams<'w, 's> {
     matchmaker_state: Option<ResMut<'w, MatchmakerState>>,
     server_to_connect: ResMut<'w, ServerToConnect>,
     main_menu_ui_channels: Option<ResMut<'w, MainMenuUiChannels>>,
+    guest: Res<'w, Guest>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
@@ -344,7 +395,25 @@ pub fn process_network_events_system(
     for (handle, connection) in network_params.net.connections.iter_mut() {
         let channels = connection.channels().unwrap();
 
+        let mut unreliable_messages = Vec::new();
         while let Some(message) = channels.recv::<Message<UnreliableServerMessage>>() {
+            #[cfg(debug_assertions)]
+            let Some(message) = network_params
+                .network_conditioner
+                .delay_incoming(message, Instant::now())
+            else {
+                continue;
+            };
+            unreliable_messages.push(message);
+        }
+        #[cfg(debug_assertions)]
+        unreliable_messages.extend(
+            network_params
+                .network_conditioner
+                .release_incoming(Instant::now()),
+        );
+
+        for message in unreliable_messages {
             log::trace!(
                 "UnreliableServerMessage received on [{}]: {:?}",
                 handle,
@@ -391,10 +460,30 @@ pub fn process_network_events_system(
                         .connection_state
                         .set_status(ConnectionStatus::Handshaking);
                     update_params.initial_rtt.received_at = Some(Instant::now());
+                    update_params.input_latency.reset_pending();
+                    update_params.session.server_health.clear();
+                    update_params.session.level_publishing.clear();
+                    update_params.session.invalid_level_object_shapes.0.clear();
+                    update_params.session.level_save_errors.0.clear();
+                    update_params.session.divergence_bisect.clear();
+                    update_params.session.determinism_guard.enabled = false;
+                    update_params.session.determinism_guard.clear();
+                    update_params.session.coordination.ping_markers.clear();
+                    #[cfg(feature = "time_dilation")]
+                    network_params.time_dilation.clear();
+                    #[cfg(debug_assertions)]
+                    network_params.network_conditioner.clear();
                     let id_token = matchmaker_params
                         .matchmaker_state
                         .as_ref()
                         .and_then(|state| state.id_token.clone());
+                    // Registered users are recognized by their tokens.
+                    let guest = &matchmaker_params.guest;
+                    let (guest_id, guest_nickname) = if id_token.is_none() {
+                        (guest.id, guest.nickname.clone())
+                    } else {
+                        (None, None)
+                    };
                     handshake_message_to_send = Some((
                         *handle,
                         Message {
@@ -402,6 +491,9 @@ pub fn process_network_events_system(
                             message: ReliableClientMessage::Handshake {
                                 message_id,
                                 id_token,
+                                guest_id,
+                                guest_nickname,
+                                constants: ConstantsFingerprint::current(),
                             },
                         },
                     ));
@@ -428,86 +520,31 @@ pub fn process_network_events_system(
                     commands.insert_resource(NextState(GameSessionState::Loading));
                 }
                 UnreliableServerMessage::DeltaUpdate(update) => {
-                    let mut skip_update = false;
-                    if let Err(err) = network_params
-                        .connection_state
-                        .acknowledge_incoming(update.frame_number)
-                    {
-                        log::warn!(
-                            "Failed to acknowledge with frame {}, skipping: {:?}",
-                            update.frame_number,
-                            err
-                        );
-                        skip_update = true;
-                    }
-                    if let (Some(ack_frame_number), ack_bit_set) = update.acknowledgments {
-                        match network_params
-                            .connection_state
-                            .apply_outgoing_acknowledgements(ack_frame_number, ack_bit_set)
-                        {
-                            Err(err @ AcknowledgeError::OutOfRange { .. }) => {
-                                log::warn!(
-                                    "Can't apply acknowledgments for frame {} (current frame: {}), skipping: {:?}",
-                                    ack_frame_number,
-                                    update_params.game_time.frame_number,
-                                    err
-                                );
-                                skip_update = true;
-                            }
-                            Err(
-                                err @ AcknowledgeError::Inconsistent
-                                | err @ AcknowledgeError::InvalidStep,
-                            ) => {
-                                log::warn!(
-                                    "Can't apply acknowledgment for frame {} (current frame: {}), disconnecting: {:?}",
-                                    ack_frame_number,
-                                    update_params.game_time.frame_number,
-                                    err
-                                );
-                                network_params.connection_state.set_status(
-                                    ConnectionStatus::Disconnecting(
-                                        DisconnectReason::InvalidUpdate,
-                                    ),
-                                );
-                                return;
-                            }
-                            Ok(_) => {
-                                if !skip_update {
-                                    let (newest_incoming_ack, _) =
-                                        network_params.connection_state.incoming_acknowledgments();
-                                    if newest_incoming_ack.unwrap() > update.frame_number {
-                                        log::debug!(
-                                            "Old delta update (current: {}, newest: {}), skipping",
-                                            update.frame_number,
-                                            newest_incoming_ack.unwrap()
-                                        );
-                                        skip_update = true;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    skip_update = skip_update || current_player_net_id.0.is_none();
-                    if skip_update {
-                        continue;
-                    }
-
-                    if !can_process_delta_update_message(&update_params.game_time, &update) {
-                        log::warn!(
-                            "Can't process update for frame {} (current frame: {}), skipping",
-                            update.frame_number,
-                            update_params.game_time.frame_number
-                        );
+                    #[cfg(feature = "time_dilation")]
+                    let Some(update) = network_params
+                        .time_dilation
+                        .delay_update(update, Instant::now())
+                    else {
                         continue;
-                    }
-
-                    process_delta_update_message(
+                    };
+                    if receive_delta_update(
                         update,
-                        &network_params.connection_state,
+                        &mut network_params.connection_state,
                         current_player_net_id.0,
                         &mut players,
                         &mut update_params,
-                    );
+                    )
+                    .is_err()
+                    {
+                        return;
+                    }
+                }
+                UnreliableServerMessage::Ping(player_ping) => {
+                    update_params
+                        .session
+                        .coordination
+                        .ping_markers
+                        .receive(player_ping, Instant::now());
                 }
             }
 
@@ -516,6 +553,21 @@ pub fn process_network_events_system(
                 .last_valid_message_received_at = Instant::now();
         }
 
+        #[cfg(feature = "time_dilation")]
+        for update in network_params.time_dilation.release_updates(Instant::now()) {
+            if receive_delta_update(
+                update,
+                &mut network_params.connection_state,
+                current_player_net_id.0,
+                &mut players,
+                &mut update_params,
+            )
+            .is_err()
+            {
+                return;
+            }
+        }
+
         while let Some(message) = channels.recv::<Message<ReliableServerMessage>>() {
             log::trace!(
                 "ReliableServerMessage received on [{}]: {:?}",
@@ -615,6 +667,7 @@ pub fn process_network_events_system(
                         spawn_level_object.command.object.net_id,
                     );
                     update_params
+                        .level_commands
                         .spawn_level_object_commands
                         .push(spawn_level_object.command);
                 }
@@ -623,6 +676,7 @@ pub fn process_network_events_system(
                         .simulation_time
                         .rewind(update_level_object.frame_number);
                     update_params
+                        .level_commands
                         .spawn_level_object_commands
                         .push(update_level_object);
                 }
@@ -631,9 +685,16 @@ pub fn process_network_events_system(
                         .simulation_time
                         .rewind(despawn_level_object.frame_number);
                     update_params
+                        .level_commands
                         .despawn_level_object_commands
                         .push(despawn_level_object);
                 }
+                ReliableServerMessage::UpdateLevelSettings(update_level_settings) => {
+                    update_params
+                        .level_commands
+                        .update_level_settings_commands
+                        .push(update_level_settings);
+                }
                 ReliableServerMessage::SwitchRole(switch_role) => {
                     update_params
                         .simulation_time
@@ -652,6 +713,38 @@ pub fn process_network_events_system(
                 }
                 ReliableServerMessage::RespawnPlayer(respawn_player) => {
                     if let Some(player) = players.get_mut(&respawn_player.net_id) {
+                        if current_player_net_id.0 == Some(respawn_player.net_id) {
+                            let session = &mut update_params.session;
+                            match respawn_player.reason {
+                                RespawnPlayerReason::Finish => {
+                                    match respawn_player.finish {
+                                        Some(finish) => {
+                                            let level_id = session.connected_server.level_id;
+                                            let is_personal_best =
+                                                session.personal_bests.record_finish(
+                                                    level_id,
+                                                    finish,
+                                                    player.best_finish,
+                                                );
+                                            if let (true, Some(level_id)) =
+                                                (is_personal_best, level_id)
+                                            {
+                                                session.ghost_runs.save_current_run(level_id);
+                                            }
+                                        }
+                                        None => session.personal_bests.last_finish = None,
+                                    }
+                                    session.session_summary.record_finish(respawn_player.finish);
+                                }
+                                RespawnPlayerReason::Death => {
+                                    session.session_summary.record_death();
+                                }
+                                RespawnPlayerReason::Checkpoint => {}
+                            }
+                        }
+                        if let Some(finish) = respawn_player.finish {
+                            player.record_finish(finish);
+                        }
                         player.respawning_at =
                             Some((respawn_player.frame_number, respawn_player.reason));
                         match respawn_player.reason {
@@ -661,6 +754,7 @@ pub fn process_network_events_system(
                             RespawnPlayerReason::Death => {
                                 player.deaths += 1;
                             }
+                            RespawnPlayerReason::Checkpoint => {}
                         }
                     } else {
                         log::warn!(
@@ -669,6 +763,92 @@ pub fn process_network_events_system(
                         );
                     }
                 }
+                ReliableServerMessage::PublishLevelReport(report) => {
+                    log::info!("Received a publishing report: {:?}", report.status);
+                    update_params
+                        .session
+                        .level_publishing
+                        .receive_report(report);
+                }
+                ReliableServerMessage::StateHash(message) => {
+                    update_params.session.divergence_bisect.receive(message);
+                }
+                ReliableServerMessage::UpdateTethers(tethers) => {
+                    *update_params.session.tethers = tethers;
+                }
+                ReliableServerMessage::LevelSaveErrors(errors) => {
+                    if errors.is_empty() {
+                        log::info!("The level is valid again");
+                    } else {
+                        log::warn!("The level can't be saved: {:?}", errors);
+                    }
+                    update_params.session.level_save_errors.0 = errors;
+                }
+                ReliableServerMessage::InvalidLevelObjectShape(invalid_shape) => {
+                    log::warn!(
+                        "Level object ({}) has been despawned: {}",
+                        invalid_shape.net_id.0,
+                        invalid_shape.error
+                    );
+                    update_params
+                        .session
+                        .invalid_level_object_shapes
+                        .0
+                        .push(invalid_shape);
+                }
+                ReliableServerMessage::SessionStats(stats) => {
+                    update_params
+                        .session
+                        .session_summary
+                        .receive_server_stats(stats);
+                }
+                ReliableServerMessage::AdminBroadcast(message) => {
+                    log::info!("Admin broadcast: {}", message);
+                    update_params.session.admin_broadcasts.push(message);
+                }
+                ReliableServerMessage::MatchEnded(match_ended) => {
+                    log::info!(
+                        "The match has ended, the winner: {:?}",
+                        match_ended.winner.map(|net_id| net_id.0)
+                    );
+                    update_params
+                        .session
+                        .match_status
+                        .receive_results(match_ended);
+                }
+                ReliableServerMessage::UpdateAreaOfInterest(update) => {
+                    log::debug!(
+                        "Area of interest update (frame: {}, entered: {:?}, left: {:?})",
+                        update.frame_number,
+                        update.entered,
+                        update.left
+                    );
+                    // Players that enter the area get spawned with the next delta update.
+                    for net_id in update.left {
+                        let is_spawned = update_params
+                            .player_entities
+                            .get_entity(net_id)
+                            .and_then(|player_entity| {
+                                update_params.spawned_query.get(player_entity).ok()
+                            })
+                            .map_or(false, |spawned| spawned.is_spawned(update.frame_number));
+                        if is_spawned {
+                            update_params.despawn_player_commands.push(DespawnPlayer {
+                                net_id,
+                                frame_number: update.frame_number,
+                                reason: DespawnReason::NetworkUpdate,
+                            });
+                        }
+                    }
+                    update_params.simulation_time.rewind(update.frame_number);
+                }
+                ReliableServerMessage::ShutdownCountdown(secs) => {
+                    log::info!("The server is shutting down in {} seconds", secs);
+                    update_params
+                        .session
+                        .admin_broadcasts
+                        .push(format!("The server is shutting down in {secs} seconds"));
+                }
                 ReliableServerMessage::Disconnect(reason) => {
                     log::info!("Server closed the connection: {:?}", reason);
                     if let DisconnectReason::InvalidJwt = reason {
@@ -684,6 +864,19 @@ pub fn process_network_events_system(
                                 .expect("Failed to send an auth update");
                         }
                     }
+                    if let DisconnectReason::Banned { expires_at } = reason {
+                        log::warn!("Banned from the server (expires at: {:?})", expires_at);
+                        // Reconnecting would be rejected anyway.
+                        **matchmaker_params.server_to_connect = None;
+                    }
+                    if let DisconnectReason::ServerShutdown = reason {
+                        // The server rejects new connections while shutting down.
+                        **matchmaker_params.server_to_connect = None;
+                    }
+                    if let DisconnectReason::ConstantsMismatch = reason {
+                        log::error!("The server has been built with different gameplay constants, check the versions of the client and the server");
+                        **matchmaker_params.server_to_connect = None;
+                    }
                     network_params
                         .connection_state
                         .set_status(ConnectionStatus::Disconnecting(reason));
@@ -728,6 +921,7 @@ pub fn maintain_connection_system(
     mut matchmaker_params: MatchmakerParams,
     mut network_params: NetworkParams,
     mut initial_rtt: ResMut<InitialRtt>,
+    mut connected_server: ResMut<ConnectedServer>,
     mut initialised_server_to_connect_without_matchmaker: Local<bool>,
 ) {
     #[cfg(feature = "profiler")]
@@ -738,7 +932,7 @@ pub fn maintain_connection_system(
         ConnectionStatus::Connected
     ) && matchmaker_params.server_to_connect.is_some()
     {
-        **matchmaker_params.server_to_connect = None;
+        connected_server.server = matchmaker_params.server_to_connect.take();
         if let Some(matchmaker_channels) = matchmaker_params.main_menu_ui_channels.as_ref() {
             matchmaker_channels
                 .connection_request_tx
@@ -813,6 +1007,7 @@ pub fn maintain_connection_system(
     {
         network_params.net.connections.clear();
         initial_rtt.sent_at = None;
+        *connected_server = ConnectedServer::default();
         network_params
             .connection_state
             .set_status(ConnectionStatus::Uninitialized);
@@ -843,6 +1038,8 @@ pub fn maintain_connection_system(
                 player_capacity: 0,
                 player_count: 0,
                 request_id: Default::default(),
+                version: Default::default(),
+                draining: false,
             });
         };
 
@@ -859,6 +1056,9 @@ pub fn maintain_connection_system(
 #[derive(SystemParam)]
 pub struct PlayerUpdateParams<'w, 's> {
     player_directions: Query<'w, 's, &'static PlayerDirection>,
+    main_camera_pivot: Res<'w, MainCameraPivotEntity>,
+    transforms: Query<'w, 's, &'static GlobalTransform>,
+    edited_level_object: Res<'w, EditedLevelObject>,
 }
 
 pub fn send_network_updates_system(
@@ -868,6 +1068,7 @@ pub fn send_network_updates_system(
     players: Res<Players>,
     player_registry: Res<EntityRegistry<PlayerNetId>>,
     player_update_params: PlayerUpdateParams,
+    mut input_send_rate: ResMut<InputSendRate>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -898,11 +1099,6 @@ pub fn send_network_updates_system(
         return;
     }
 
-    network_params
-        .connection_state
-        // Clients don't resend updates, so we can forget about unacknowledged packets.
-        .add_outgoing_packet(time.frame_number, Instant::now());
-
     let inputs = match player.role {
         PlayerRole::Runner => {
             let player_entity = player_entity.unwrap(); // is checked above
@@ -912,21 +1108,14 @@ pub fn send_network_updates_system(
                 .get(player_entity)
                 .expect("Expected a created spawned player");
 
-            // TODO: this makes the client send more packets than the server actually needs,
-            // as lost packets  never get marked as acknowledged, even though we
-            // resend updates in future frames. Fix it.
-            let first_unacknowledged_frame = network_params
-                .connection_state
-                .first_unacknowledged_outgoing_packet()
-                .expect("Expected at least the new packet for the current frame");
+            let first_input_frame = input_send_rate
+                .first_input_frame(time.frame_number, &network_params.connection_state);
             let mut inputs: Vec<RunnerInput> = Vec::new();
             // TODO: deduplicate updates (the same code is written for server).
             for (frame_number, &direction) in player_direction
                 .buffer
                 .iter_with_interpolation()
-                // TODO: should client always send redundant inputs or only the current ones (unless
-                // packet loss is detected)?
-                .skip_while(|(frame_number, _)| *frame_number < first_unacknowledged_frame)
+                .skip_while(|(frame_number, _)| *frame_number < first_input_frame)
             {
                 if Some(direction) != inputs.last().map(|i| i.direction) {
                     inputs.push(RunnerInput {
@@ -937,21 +1126,43 @@ pub fn send_network_updates_system(
             }
             PlayerInputs::Runner { inputs }
         }
-        PlayerRole::Builder => PlayerInputs::Builder,
+        PlayerRole::Builder => PlayerInputs::Builder {
+            camera_position: player_update_params
+                .transforms
+                .get(player_update_params.main_camera_pivot.0)
+                .map_or(Vec2::ZERO, |transform| transform.translation().truncate()),
+            selected_object: player_update_params
+                .edited_level_object
+                .object
+                .as_ref()
+                .map(|(_, level_object)| level_object.net_id),
+        },
+        PlayerRole::Spectator => PlayerInputs::Spectator,
     };
 
-    let message = UnreliableClientMessage::PlayerUpdate(PlayerUpdate {
-        frame_number: time.frame_number,
-        acknowledgments: network_params.connection_state.incoming_acknowledgments(),
-        inputs,
-    });
-    let result = network_params.net.send_message(
-        connection_handle,
-        Message {
-            session_id: network_params.connection_state.session_id,
-            message,
-        },
-    );
+    let inputs =
+        input_send_rate.inputs_to_send(time.frame_number, inputs, &network_params.connection_state);
+    network_params
+        .connection_state
+        // Clients don't resend updates, so we can forget about unacknowledged packets.
+        .add_outgoing_packet(time.frame_number, Instant::now());
+
+    let message = Message {
+        session_id: network_params.connection_state.session_id,
+        message: UnreliableClientMessage::PlayerUpdate(PlayerUpdate {
+            frame_number: time.frame_number,
+            acknowledgments: network_params.connection_state.incoming_acknowledgments(),
+            inputs,
+        }),
+    };
+    #[cfg(debug_assertions)]
+    let Some(message) = network_params
+        .network_conditioner
+        .delay_outgoing(message, Instant::now())
+    else {
+        return;
+    };
+    let result = network_params.net.send_message(connection_handle, message);
     if let Err(err) = result {
         log::error!("Failed to send a message to {:?}: {:?}", address, err);
     }
@@ -961,6 +1172,9 @@ pub fn send_requests_system(
     mut network_params: NetworkParams,
     mut player_requests: ResMut<PlayerRequestsQueue>,
     mut level_object_requests: ResMut<LevelObjectRequestsQueue>,
+    mut level_edit_history: ResMut<LevelEditHistory>,
+    level_object_correlations: Res<LevelObjectCorrelations>,
+    level_state: Res<LevelState>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -988,6 +1202,95 @@ pub fn send_requests_system(
             log::error!("Failed to send SwitchRole message: {:?}", err);
         }
     }
+    if let Some(checkpoint) = player_requests.restart_from_checkpoint.take() {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::RestartFromCheckpoint(checkpoint),
+            },
+        ) {
+            log::error!("Failed to send RestartFromCheckpoint message: {:?}", err);
+        }
+    }
+    for practice_bots_request in std::mem::take(&mut player_requests.practice_bots) {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::PracticeBots(practice_bots_request),
+            },
+        ) {
+            log::error!("Failed to send PracticeBots message: {:?}", err);
+        }
+    }
+    for publish_level_request in std::mem::take(&mut player_requests.publish_level) {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::PublishLevel(publish_level_request),
+            },
+        ) {
+            log::error!("Failed to send PublishLevel message: {:?}", err);
+        }
+    }
+    if std::mem::take(&mut player_requests.reload_level) {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::ReloadLevel,
+            },
+        ) {
+            log::error!("Failed to send ReloadLevel message: {:?}", err);
+        }
+    }
+    for state_hash_request in std::mem::take(&mut player_requests.state_hash) {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::StateHashRequest(state_hash_request),
+            },
+        ) {
+            log::error!("Failed to send StateHashRequest message: {:?}", err);
+        }
+    }
+    for admin_command in std::mem::take(&mut player_requests.admin_commands) {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::AdminCommand(admin_command),
+            },
+        ) {
+            log::error!("Failed to send AdminCommand message: {:?}", err);
+        }
+    }
+    for ping in std::mem::take(&mut player_requests.pings) {
+        let message = Message {
+            session_id: network_params.connection_state.session_id,
+            message: UnreliableClientMessage::Ping(ping),
+        };
+        #[cfg(debug_assertions)]
+        let Some(message) = network_params
+            .network_conditioner
+            .delay_outgoing(message, Instant::now())
+        else {
+            continue;
+        };
+        if let Err(err) = network_params.net.send_message(connection_handle, message) {
+            log::error!("Failed to send Ping message: {:?}", err);
+        }
+    }
+    level_edit_history.resolve_spawns(&level_object_correlations, &mut level_object_requests);
+    level_edit_history.record(
+        network_params.connection_state.session_id,
+        &level_object_requests,
+        &level_state,
+        Instant::now(),
+    );
     for spawn_request in std::mem::take(&mut level_object_requests.spawn_requests) {
         if let Err(err) = network_params.net.send_message(
             connection_handle,
@@ -1021,6 +1324,126 @@ pub fn send_requests_system(
             log::error!("Failed to send SwitchRole message: {:?}", err);
         }
     }
+    if let Some(settings) = level_object_requests.settings_update_request.take() {
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: network_params.connection_state.session_id,
+                message: ReliableClientMessage::UpdateLevelSettings(settings),
+            },
+        ) {
+            log::error!("Failed to send UpdateLevelSettings message: {:?}", err);
+        }
+    }
+}
+
+/// Acknowledges an incoming delta update and applies it, unless it's outdated.
+/// Returns an error if the acknowledgments are invalid, which means that the
+/// client has to disconnect.
+fn receive_delta_update(
+    update: DeltaUpdate,
+    connection_state: &mut ConnectionState,
+    current_player_net_id: Option<PlayerNetId>,
+    players: &mut Players,
+    update_params: &mut UpdateParams,
+) -> Result<(), AcknowledgeError> {
+    // The server encodes player states against the updates that we've
+    // acknowledged, so an update that we fail to decode mustn't be acknowledged.
+    // If the baseline is missing, the server will send full states once it
+    // forgets about the acknowledgment.
+    let baseline = update
+        .players
+        .baseline_frame
+        .and_then(|frame_number| connection_state.player_states_history.get(frame_number));
+    let (player_states, snapshot) = match decode_player_states(&update.players, baseline) {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            log::warn!(
+                "Failed to decode player states of frame {}, skipping: {:?}",
+                update.frame_number,
+                err
+            );
+            return Ok(());
+        }
+    };
+
+    let mut skip_update = false;
+    if let Err(err) = connection_state.acknowledge_incoming(update.frame_number) {
+        log::warn!(
+            "Failed to acknowledge with frame {}, skipping: {:?}",
+            update.frame_number,
+            err
+        );
+        skip_update = true;
+    } else {
+        connection_state
+            .player_states_history
+            .push(update.frame_number, snapshot);
+    }
+    if let (Some(ack_frame_number), ack_bit_set) = update.acknowledgments {
+        match connection_state.apply_outgoing_acknowledgements(ack_frame_number, ack_bit_set) {
+            Err(err @ AcknowledgeError::OutOfRange { .. }) => {
+                log::warn!(
+                    "Can't apply acknowledgments for frame {} (current frame: {}), skipping: {:?}",
+                    ack_frame_number,
+                    update_params.game_time.frame_number,
+                    err
+                );
+                skip_update = true;
+            }
+            Err(err @ AcknowledgeError::Inconsistent | err @ AcknowledgeError::InvalidStep) => {
+                log::warn!(
+                    "Can't apply acknowledgment for frame {} (current frame: {}), disconnecting: {:?}",
+                    ack_frame_number,
+                    update_params.game_time.frame_number,
+                    err
+                );
+                connection_state.set_status(ConnectionStatus::Disconnecting(
+                    DisconnectReason::InvalidUpdate,
+                ));
+                return Err(err);
+            }
+            Ok(_) => {
+                update_params
+                    .input_latency
+                    .acknowledge(ack_frame_number, Instant::now());
+                if !skip_update {
+                    let (newest_incoming_ack, _) = connection_state.incoming_acknowledgments();
+                    if newest_incoming_ack.unwrap() > update.frame_number {
+                        log::debug!(
+                            "Old delta update (current: {}, newest: {}), skipping",
+                            update.frame_number,
+                            newest_incoming_ack.unwrap()
+                        );
+                        skip_update = true;
+                    }
+                }
+            }
+        }
+    }
+    skip_update = skip_update || current_player_net_id.is_none();
+    if skip_update {
+        return Ok(());
+    }
+
+    if !can_process_delta_update_message(&update_params.game_time, &update) {
+        log::warn!(
+            "Can't process update for frame {} (current frame: {}), skipping",
+            update.frame_number,
+            update_params.game_time.frame_number
+        );
+        return Ok(());
+    }
+
+    process_delta_update_message(
+        update,
+        player_states,
+        connection_state,
+        current_player_net_id,
+        players,
+        update_params,
+    );
+    Ok(())
 }
 
 fn can_process_delta_update_message(time: &GameTime, delta_update: &DeltaUpdate) -> bool {
@@ -1031,6 +1454,25 @@ fn can_process_delta_update_message(time: &GameTime, delta_update: &DeltaUpdate)
     frames_diff < COMPONENT_FRAMEBUFFER_LIMIT / 2
 }
 
+/// Sends the messages that `NetworkConditioner` has held back.
+#[cfg(debug_assertions)]
+pub fn send_conditioned_messages_system(mut network_params: NetworkParams) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let messages = network_params
+        .network_conditioner
+        .release_outgoing(Instant::now());
+    let Some(&connection_handle) = network_params.net.connections.keys().next() else {
+        return;
+    };
+
+    for message in messages {
+        if let Err(err) = network_params.net.send_message(connection_handle, message) {
+            log::error!("Failed to send a conditioned message: {:?}", err);
+        }
+    }
+}
+
 /// We need to access an actual value on each (fresh) delta update message, so
 /// we write it for every frame, as we can't predict when we'll receive those.
 pub fn fill_actual_frames_ahead_system(
@@ -1045,6 +1487,7 @@ pub fn fill_actual_frames_ahead_system(
 
 fn process_delta_update_message(
     delta_update: DeltaUpdate,
+    player_states: Vec<PlayerState>,
     connection_state: &ConnectionState,
     current_player_net_id: Option<PlayerNetId>,
     players: &mut Players,
@@ -1059,14 +1502,29 @@ fn process_delta_update_message(
         );
     }
 
-    sync_clock(&delta_update, connection_state, update_params);
+    update_params
+        .session
+        .server_health
+        .record(delta_update.server_health);
+    sync_clock(
+        &delta_update,
+        connection_state,
+        ClockParams {
+            simulation_time: &update_params.simulation_time,
+            game_frame: update_params.game_time.frame_number,
+            extra_jitter_frames: update_params.session.server_health.extra_jitter_frames(),
+            estimated_server_time: &mut update_params.estimated_server_time,
+            target_frames_ahead: &mut update_params.target_frames_ahead,
+            delay_server_time: &mut update_params.delay_server_time,
+        },
+    );
+    update_params.session.coordination.builder_states.0 = delta_update.builders;
 
     // Despawning players that aren't mentioned in the delta update.
     let players_to_remove: Vec<PlayerNetId> = players
         .iter()
         .filter_map(|(player_net_id, player)| {
-            if !delta_update
-                .players
+            if !player_states
                 .iter()
                 .any(|player| player.net_id == *player_net_id)
                 && matches!(player.role, PlayerRole::Runner)
@@ -1101,8 +1559,19 @@ fn process_delta_update_message(
         }
     }
 
+    // Spectators render buffered snapshots instead of simulating players.
+    let is_spectating = current_player_net_id
+        .and_then(|net_id| players.get(&net_id))
+        .map_or(false, is_spectating);
+    if is_spectating {
+        update_params
+            .session
+            .spectator_snapshots
+            .push(delta_update.frame_number, &player_states);
+    }
+
     let delta_update_frame = delta_update.frame_number;
-    for player_state in delta_update.players {
+    for player_state in player_states {
         let is_spawned = update_params
             .player_entities
             .get_entity(player_state.net_id)
@@ -1122,35 +1591,20 @@ fn process_delta_update_message(
                 .or_insert_with(|| Player::new(PlayerRole::Runner));
         }
 
-        let direction_updates = update_params.player_updates.get_direction_mut(
-            player_state.net_id,
-            delta_update.frame_number,
-            COMPONENT_FRAMEBUFFER_LIMIT,
-        );
-        direction_updates.insert(
-            delta_update_frame,
-            Some(PlayerDirectionUpdate {
-                direction: player_state.direction,
-                is_processed_client_input: None,
-            }),
-        );
+        if is_spectating {
+            continue;
+        }
 
-        let position_updates = update_params.player_updates.get_position_mut(
-            player_state.net_id,
-            delta_update.frame_number,
-            COMPONENT_FRAMEBUFFER_LIMIT,
-        );
-        log::trace!(
-            "Updating position for player {} (frame_number: {}): {:?}",
-            player_state.net_id.0,
-            delta_update.frame_number,
-            player_state.position
+        record_player_state(
+            &mut update_params.player_updates,
+            delta_update_frame,
+            &player_state,
         );
-        position_updates.insert(delta_update.frame_number, Some(player_state.position));
     }
 
-    // There's no need to rewind if we haven't started the game.
-    if let ConnectionStatus::Connected = connection_state.status() {
+    // There's no need to rewind if we haven't started the game, and spectators
+    // don't re-simulate players at all.
+    if !is_spectating && matches!(connection_state.status(), ConnectionStatus::Connected) {
         log::trace!(
             "Rewinding to frame {} (current server frame: {}, current player frame: {})",
             delta_update.frame_number,
@@ -1163,6 +1617,51 @@ fn process_delta_update_message(
     }
 }
 
+/// Writes the authoritative state of a player into the update buffers, which
+/// `read_movement_updates_system` then reads into the `Position` and
+/// `PlayerDirection` components.
+pub fn record_player_state(
+    player_updates: &mut PlayerUpdates,
+    frame_number: FrameNumber,
+    player_state: &PlayerState,
+) {
+    let direction_updates = player_updates.get_direction_mut(
+        player_state.net_id,
+        frame_number,
+        COMPONENT_FRAMEBUFFER_LIMIT,
+    );
+    direction_updates.insert(
+        frame_number,
+        Some(PlayerDirectionUpdate {
+            direction: player_state.direction,
+            is_processed_client_input: None,
+        }),
+    );
+
+    let position_updates = player_updates.get_position_mut(
+        player_state.net_id,
+        frame_number,
+        COMPONENT_FRAMEBUFFER_LIMIT,
+    );
+    log::trace!(
+        "Updating position for player {} (frame_number: {}): {:?}",
+        player_state.net_id.0,
+        frame_number,
+        player_state.position
+    );
+    position_updates.insert(frame_number, Some(player_state.position));
+}
+
+/// The parts of `UpdateParams` that `sync_clock` reads and adjusts.
+struct ClockParams<'a> {
+    simulation_time: &'a SimulationTime,
+    game_frame: FrameNumber,
+    extra_jitter_frames: u16,
+    estimated_server_time: &'a mut EstimatedServerTime,
+    target_frames_ahead: &'a mut TargetFramesAhead,
+    delay_server_time: &'a mut DelayServerTime,
+}
+
 /// Returns the "frame ahead" number that has to be applied to this delta
 /// update.
 ///
@@ -1201,7 +1700,7 @@ fn process_delta_update_message(
 fn sync_clock(
     delta_update: &DeltaUpdate,
     connection_state: &ConnectionState,
-    update_params: &mut UpdateParams,
+    update_params: ClockParams,
 ) {
     let (newest_acknowledged_input, _) = delta_update.acknowledgments;
 
@@ -1231,8 +1730,11 @@ fn sync_clock(
     // Update rtt, packet loss and jitter values.
     let frames_rtt = SIMULATIONS_PER_SECOND * connection_state.rtt_millis() / 1000.0;
     let packet_loss_buffer = frames_rtt * connection_state.packet_loss();
+    // Late updates of an overloaded server aren't network jitter, but they have
+    // to be buffered all the same.
     let jitter_buffer = packet_loss_buffer
-        + SIMULATIONS_PER_SECOND * connection_state.jitter_millis() * 2.0 / 1000.0;
+        + SIMULATIONS_PER_SECOND * connection_state.jitter_millis() * 2.0 / 1000.0
+        + update_params.extra_jitter_frames as f32;
 
     // Adjusting the speed to synchronize with the server clock.
     let new_delay = (update_params.simulation_time.server_frame.value() as i32
@@ -1261,7 +1763,7 @@ fn sync_clock(
         delta_update.frame_number + FrameNumber::new(new_target_frames_ahead);
     if new_estimated_server_time > update_params.estimated_server_time.frame_number {
         update_params.estimated_server_time.frame_number = new_estimated_server_time;
-        update_params.estimated_server_time.updated_at = update_params.game_time.frame_number;
+        update_params.estimated_server_time.updated_at = update_params.game_frame;
     }
 
     if cfg!(debug_assertions) {
@@ -1292,16 +1794,38 @@ fn process_start_game_message(
     update_params: &mut UpdateParams,
 ) {
     log::debug!("Processing StartGame message: {:?}", start_game);
+    // The states are encoded in full, so there's no baseline to miss.
+    let player_states = decode_player_states(&start_game.game_state.players, None)
+        .map(|(player_states, _)| player_states)
+        .unwrap_or_else(|err| {
+            log::error!("Failed to decode player states of StartGame: {:?}", err);
+            Vec::new()
+        });
     let initial_rtt = update_params.initial_rtt.duration_secs().unwrap() * 1000.0;
     log::debug!("Initial rtt: {}", initial_rtt);
     connection_state
         .set_initial_rtt_millis(update_params.initial_rtt.duration_secs().unwrap() * 1000.0);
 
     current_player_net_id.0 = Some(start_game.net_id);
+    update_params.session.connected_server.level_id = start_game.level_id;
+    update_params.session.connected_server.level_title = start_game.level_title.clone();
+    // Level objects get spawned in the next stages, so the commands will be already
+    // applied by the time we start calculating their colliders.
+    commands.insert_resource(start_game.collider_simplification);
+    *update_params.session.tethers = start_game.tethers;
+    update_params.session.match_status.game_mode = start_game.game_mode;
+    commands.insert_resource(start_game.admin_permissions);
+    // The server knows lifetime stats of registered players.
+    let lifetime_stats = start_game
+        .players
+        .iter()
+        .find(|(net_id, _)| *net_id == start_game.net_id)
+        .and_then(|(_, player)| player.lifetime_stats);
     players.insert(
         start_game.net_id,
         Player {
             uuid: start_game.uuid,
+            lifetime_stats,
             ..Player::new_with_nickname(PlayerRole::Runner, start_game.nickname)
         },
     );
@@ -1350,15 +1874,17 @@ fn process_start_game_message(
             .and_modify(|player| {
                 let deaths = player.deaths;
                 let finishes = player.finishes;
+                let best_finish = player.best_finish;
                 *player = connected_player.clone();
                 player.deaths += deaths;
                 player.finishes += finishes;
+                if let Some(best_finish) = best_finish {
+                    player.record_finish(best_finish);
+                }
             })
             .or_insert_with(|| connected_player.clone());
         if connected_player.role == PlayerRole::Runner && connected_player.respawning_at.is_none() {
-            if let Some(start_position) =
-                player_start_position(player_net_id, &start_game.game_state)
-            {
+            if let Some(start_position) = player_start_position(player_net_id, &player_states) {
                 log::info!(
                     "Spawning player {}: {}",
                     player_net_id.0,
@@ -1384,9 +1910,16 @@ fn process_start_game_message(
             );
         }
     }
+    update_params
+        .level_commands
+        .update_level_settings_commands
+        .push(UpdateLevelSettings {
+            settings: start_game.settings,
+        });
     commands.insert_resource(LevelObjectsToSpawnToLoad(start_game.objects.len()));
     for spawn_level_object in start_game.objects {
         update_params
+            .level_commands
             .spawn_level_object_commands
             .push(spawn_level_object);
     }
@@ -1409,9 +1942,13 @@ fn process_connected_player_message(
         .and_modify(|player| {
             let deaths = player.deaths;
             let finishes = player.finishes;
+            let best_finish = player.best_finish;
             *player = connected_player.clone();
             player.deaths += deaths;
             player.finishes += finishes;
+            if let Some(best_finish) = best_finish {
+                player.record_finish(best_finish);
+            }
         })
         .or_insert(connected_player);
 }
@@ -1431,10 +1968,134 @@ fn process_disconnected_player_message(
     }
 }
 
-fn player_start_position(player_net_id: PlayerNetId, delta_update: &DeltaUpdate) -> Option<Vec2> {
-    delta_update
-        .players
+fn player_start_position(
+    player_net_id: PlayerNetId,
+    player_states: &[PlayerState],
+) -> Option<Vec2> {
+    player_states
         .iter()
         .find(|player_state| player_state.net_id == player_net_id)
         .map(|player_state| player_state.position)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::{
+        delta_compression::EncodedPlayerStates, messages::ServerHealth, TICKS_PER_NETWORK_BROADCAST,
+    };
+    use std::collections::VecDeque;
+
+    /// Frames that packets of both peers spend in flight.
+    const LATENCY: u16 = 4;
+    const FRAMES_AHEAD: u16 = 10;
+
+    #[test]
+    fn test_sync_clock_with_unchanged_inputs() {
+        let mut input_send_rate = InputSendRate::default();
+        let mut client_connection = ConnectionState::default();
+        let mut server_connection = ConnectionState::default();
+        let mut simulation_time = SimulationTime::default();
+        let mut estimated_server_time = EstimatedServerTime::default();
+        let mut target_frames_ahead = TargetFramesAhead::default();
+        let mut delay_server_time = DelayServerTime::default();
+
+        let inputs = PlayerInputs::Runner {
+            inputs: vec![RunnerInput {
+                frame_number: FrameNumber::new(0),
+                direction: Vec2::X,
+            }],
+        };
+        let mut client_packets = VecDeque::new();
+        let mut server_updates = VecDeque::new();
+        let mut unchanged_inputs_count = 0;
+        let mut targets = Vec::new();
+        for frame_number in (0..200).step_by(TICKS_PER_NETWORK_BROADCAST as usize) {
+            let server_frame = FrameNumber::new(frame_number);
+            let player_frame = server_frame + FrameNumber::new(FRAMES_AHEAD);
+            simulation_time.server_frame = server_frame;
+            simulation_time.player_frame = player_frame;
+            target_frames_ahead
+                .actual_frames_ahead
+                .insert(player_frame, FRAMES_AHEAD);
+
+            // The client sends an update every tick, even if the inputs are unchanged.
+            let sent_inputs =
+                input_send_rate.inputs_to_send(player_frame, inputs.clone(), &client_connection);
+            if sent_inputs == PlayerInputs::Unchanged {
+                unchanged_inputs_count += 1;
+            }
+            client_connection.add_outgoing_packet(player_frame, Instant::now());
+            client_packets.push_back((server_frame + FrameNumber::new(LATENCY), player_frame));
+
+            while let Some(&(_, packet_frame)) = client_packets
+                .front()
+                .filter(|(arrives_at, _)| *arrives_at <= server_frame)
+            {
+                client_packets.pop_front();
+                server_connection
+                    .acknowledge_incoming(packet_frame)
+                    .unwrap();
+            }
+            server_updates.push_back((
+                server_frame + FrameNumber::new(LATENCY),
+                DeltaUpdate {
+                    frame_number: server_frame,
+                    acknowledgments: server_connection.incoming_acknowledgments(),
+                    players: EncodedPlayerStates::default(),
+                    builders: Vec::new(),
+                    server_health: ServerHealth::default(),
+                },
+            ));
+
+            while server_updates
+                .front()
+                .map_or(false, |(arrives_at, _)| *arrives_at <= server_frame)
+            {
+                let (_, delta_update) = server_updates.pop_front().unwrap();
+                client_connection
+                    .acknowledge_incoming(delta_update.frame_number)
+                    .unwrap();
+                if let (Some(acknowledged_frame), acknowledgments) = delta_update.acknowledgments {
+                    // The server acknowledges the latest update that could reach it, and
+                    // not the last one that carried inputs.
+                    assert_eq!(
+                        acknowledged_frame,
+                        delta_update.frame_number + FrameNumber::new(FRAMES_AHEAD - LATENCY)
+                    );
+                    client_connection
+                        .apply_outgoing_acknowledgements(acknowledged_frame, acknowledgments)
+                        .unwrap();
+                }
+                sync_clock(
+                    &delta_update,
+                    &client_connection,
+                    ClockParams {
+                        simulation_time: &simulation_time,
+                        game_frame: player_frame,
+                        extra_jitter_frames: 0,
+                        estimated_server_time: &mut estimated_server_time,
+                        target_frames_ahead: &mut target_frames_ahead,
+                        delay_server_time: &mut delay_server_time,
+                    },
+                );
+                if delta_update.acknowledgments.0.is_some() {
+                    targets.push(target_frames_ahead.target);
+                }
+            }
+        }
+
+        assert!(unchanged_inputs_count > 0);
+        // Skipping the updates that settle the delay. The jitter buffer may still
+        // grow by a frame once the measured round trip time starts to vary, but
+        // stale acknowledgments would swing the target by up to
+        // `MAX_INPUT_SEND_INTERVAL` frames.
+        let targets = &targets[2..];
+        let min_target = *targets.iter().min().unwrap();
+        let max_target = *targets.iter().max().unwrap();
+        assert!(
+            max_target - min_target <= 2,
+            "target frames ahead swings between {min_target} and {max_target}"
+        );
+    }
+}
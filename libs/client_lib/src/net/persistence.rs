@@ -1,8 +1,15 @@
 use bevy::log;
 use core::slice::SlicePattern;
-use mr_messages_lib::{ErrorResponse, GetLevelResponse, GetLevelsRequest, LevelsListItem};
+use mr_messages_lib::{
+    ApiToken, ApiTokenError, AudioClipError, BugReportError, ErrorResponse, FriendDto,
+    FriendRequestError, GetLevelsSummaryRequest, GetLevelsSummaryResponse, PostApiTokenRequest,
+    PostApiTokenResponse, PostAudioClipResponse, PostBugReportRequest, PostBugReportResponse,
+    PostFriendRequest, PrivacySettings, AUDIO_CLIP_CONTENT_TYPE,
+    BUG_REPORT_SCREENSHOT_CONTENT_TYPE,
+};
 use mr_shared_lib::net::MessageId;
-use reqwest::Client;
+use mr_utils_lib::executor;
+use reqwest::{Client, RequestBuilder};
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use url::Url;
@@ -33,15 +40,33 @@ impl PersistenceClient {
         id_token: Option<T>,
         body: Option<&B>,
     ) -> Option<Result<R, ErrorResponse<E>>> {
-        let mut request = self
-            .client
-            .request(method, self.public_persistence_url.join(path).unwrap());
-        if let Some(id_token) = id_token {
-            request = request.bearer_auth(id_token);
-        }
+        let mut request = self.request_builder(method, path, id_token);
         if let Some(body) = body {
             request = request.json(body);
         }
+        self.send(path, request).await
+    }
+
+    fn request_builder<T: std::fmt::Display>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        id_token: Option<T>,
+    ) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, self.public_persistence_url.join(path).unwrap());
+        match id_token {
+            Some(id_token) => request.bearer_auth(id_token),
+            None => request,
+        }
+    }
+
+    async fn send<R: DeserializeOwned, E: Serialize + DeserializeOwned + Clone>(
+        &self,
+        path: &str,
+        request: RequestBuilder,
+    ) -> Option<Result<R, ErrorResponse<E>>> {
         let result = request.send().await;
 
         let (data, status) = match result {
@@ -96,43 +121,280 @@ impl PersistenceClient {
         }
     }
 
-    pub async fn get_levels(
+    pub async fn get_levels_summary(
         &self,
-        query: &GetLevelsRequest,
-    ) -> Option<Result<Vec<LevelsListItem>, ErrorResponse<()>>> {
+        query: &GetLevelsSummaryRequest,
+    ) -> Option<Result<GetLevelsSummaryResponse, ErrorResponse<()>>> {
         let query = serde_urlencoded::to_string(query).unwrap();
         self.request(
             reqwest::Method::GET,
-            &format!("/levels?{query}"),
+            &format!("/levels/summary?{query}"),
             Option::<&str>::None,
             Option::<&()>::None,
         )
         .await
     }
 
-    pub async fn get_level(
+    pub async fn get_friends(
         &self,
-        level_id: i64,
-    ) -> Option<Result<GetLevelResponse, ErrorResponse<()>>> {
+        id_token: &str,
+    ) -> Option<Result<Vec<FriendDto>, ErrorResponse<()>>> {
         self.request(
             reqwest::Method::GET,
-            &format!("/levels/{level_id}"),
-            Option::<&str>::None,
+            "/friends",
+            Some(id_token),
+            Option::<&()>::None,
+        )
+        .await
+    }
+
+    pub async fn post_friend(
+        &self,
+        id_token: &str,
+        body: &PostFriendRequest,
+    ) -> Option<Result<(), ErrorResponse<FriendRequestError>>> {
+        self.request(
+            reqwest::Method::POST,
+            "/friends",
+            Some(id_token),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn accept_friend(
+        &self,
+        id_token: &str,
+        user_id: i64,
+    ) -> Option<Result<(), ErrorResponse<()>>> {
+        self.request(
+            reqwest::Method::POST,
+            &format!("/friends/{user_id}/accept"),
+            Some(id_token),
+            Option::<&()>::None,
+        )
+        .await
+    }
+
+    pub async fn delete_friend(
+        &self,
+        id_token: &str,
+        user_id: i64,
+    ) -> Option<Result<(), ErrorResponse<()>>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!("/friends/{user_id}"),
+            Some(id_token),
+            Option::<&()>::None,
+        )
+        .await
+    }
+
+    pub async fn get_privacy_settings(
+        &self,
+        id_token: &str,
+    ) -> Option<Result<PrivacySettings, ErrorResponse<()>>> {
+        self.request(
+            reqwest::Method::GET,
+            "/privacy",
+            Some(id_token),
+            Option::<&()>::None,
+        )
+        .await
+    }
+
+    pub async fn put_privacy_settings(
+        &self,
+        id_token: &str,
+        body: &PrivacySettings,
+    ) -> Option<Result<PrivacySettings, ErrorResponse<()>>> {
+        self.request(reqwest::Method::PUT, "/privacy", Some(id_token), Some(body))
+            .await
+    }
+
+    pub async fn get_api_tokens(
+        &self,
+        id_token: &str,
+    ) -> Option<Result<Vec<ApiToken>, ErrorResponse<()>>> {
+        self.request(
+            reqwest::Method::GET,
+            "/api_tokens",
+            Some(id_token),
+            Option::<&()>::None,
+        )
+        .await
+    }
+
+    pub async fn post_api_token(
+        &self,
+        id_token: &str,
+        body: &PostApiTokenRequest,
+    ) -> Option<Result<PostApiTokenResponse, ErrorResponse<ApiTokenError>>> {
+        self.request(
+            reqwest::Method::POST,
+            "/api_tokens",
+            Some(id_token),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn delete_api_token(
+        &self,
+        id_token: &str,
+        token_id: i64,
+    ) -> Option<Result<(), ErrorResponse<()>>> {
+        self.request(
+            reqwest::Method::DELETE,
+            &format!("/api_tokens/{token_id}"),
+            Some(id_token),
             Option::<&()>::None,
         )
         .await
     }
+
+    /// Clips are served as raw files, so only the status of a response is
+    /// checked.
+    pub async fn get_audio_clip(&self, clip_id: i64) -> Option<Vec<u8>> {
+        let result = self
+            .request_builder(
+                reqwest::Method::GET,
+                &format!("/audio_clips/{clip_id}"),
+                Option::<&str>::None,
+            )
+            .send()
+            .await;
+        let response = match result {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                log::warn!(
+                    "Failed to get audio clip {} (status: {})",
+                    clip_id,
+                    response.status().as_u16()
+                );
+                return None;
+            }
+            Err(err) => {
+                log::error!("Failed to send a request: {:?}", err);
+                return None;
+            }
+        };
+        match response.bytes().await {
+            Ok(data) => Some(data.to_vec()),
+            Err(err) => {
+                log::error!("Failed to read audio clip {}: {:?}", clip_id, err);
+                None
+            }
+        }
+    }
+
+    pub async fn post_audio_clip(
+        &self,
+        id_token: &str,
+        data: Vec<u8>,
+    ) -> Option<Result<PostAudioClipResponse, ErrorResponse<AudioClipError>>> {
+        let request = self
+            .request_builder(reqwest::Method::POST, "/audio_clips", Some(id_token))
+            .header(reqwest::header::CONTENT_TYPE, AUDIO_CLIP_CONTENT_TYPE)
+            .body(data);
+        self.send("/audio_clips", request).await
+    }
+
+    pub async fn post_bug_report(
+        &self,
+        id_token: &str,
+        body: &PostBugReportRequest,
+    ) -> Option<Result<PostBugReportResponse, ErrorResponse<BugReportError>>> {
+        self.request(
+            reqwest::Method::POST,
+            "/bug_reports",
+            Some(id_token),
+            Some(body),
+        )
+        .await
+    }
+
+    pub async fn put_bug_report_screenshot(
+        &self,
+        id_token: &str,
+        report_id: i64,
+        data: Vec<u8>,
+    ) -> Option<Result<(), ErrorResponse<BugReportError>>> {
+        let path = format!("/bug_reports/{report_id}/screenshot");
+        let request = self
+            .request_builder(reqwest::Method::PUT, &path, Some(id_token))
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                BUG_REPORT_SCREENSHOT_CONTENT_TYPE,
+            )
+            .body(data);
+        self.send(&path, request).await
+    }
 }
 
 #[derive(Debug)]
 pub enum PersistenceRequest {
-    GetLevels {
+    GetLevelsSummary {
+        request_id: MessageId,
+        body: GetLevelsSummaryRequest,
+    },
+    GetFriends {
         request_id: MessageId,
-        body: GetLevelsRequest,
+        id_token: String,
     },
-    GetLevel {
+    AddFriend {
         request_id: MessageId,
-        level_id: i64,
+        id_token: String,
+        display_name: String,
+    },
+    AcceptFriend {
+        request_id: MessageId,
+        id_token: String,
+        user_id: i64,
+    },
+    RemoveFriend {
+        request_id: MessageId,
+        id_token: String,
+        user_id: i64,
+    },
+    GetPrivacySettings {
+        request_id: MessageId,
+        id_token: String,
+    },
+    UpdatePrivacySettings {
+        request_id: MessageId,
+        id_token: String,
+        settings: PrivacySettings,
+    },
+    GetApiTokens {
+        request_id: MessageId,
+        id_token: String,
+    },
+    IssueApiToken {
+        request_id: MessageId,
+        id_token: String,
+        body: PostApiTokenRequest,
+    },
+    RevokeApiToken {
+        request_id: MessageId,
+        id_token: String,
+        token_id: i64,
+    },
+    GetAudioClip {
+        request_id: MessageId,
+        clip_id: i64,
+    },
+    UploadAudioClip {
+        request_id: MessageId,
+        id_token: String,
+        data: Vec<u8>,
+    },
+    /// The screenshot is uploaded right after the report is submitted.
+    SubmitBugReport {
+        request_id: MessageId,
+        id_token: String,
+        body: PostBugReportRequest,
+        screenshot: Option<Vec<u8>>,
     },
 }
 
@@ -153,11 +415,34 @@ impl PersistenceMessage {
 
 #[derive(Debug)]
 pub enum PersistenceMessagePayload {
-    GetLevelsResponse(Vec<LevelsListItem>),
-    GetLevelResponse(GetLevelResponse),
+    GetLevelsSummaryResponse(GetLevelsSummaryResponse),
+    /// Is also sent as a response to friends list updates.
+    GetFriendsResponse(Vec<FriendDto>),
+    /// Is also sent as a response to privacy settings updates.
+    PrivacySettingsResponse(PrivacySettings),
+    /// Is also sent as a response to revoking a token.
+    GetApiTokensResponse(Vec<ApiToken>),
+    ApiTokenIssued(PostApiTokenResponse),
+    /// Audio clip responses carry their own errors, as they are routed by the
+    /// clip rather than by the request id. The data is `None` if the clip
+    /// can't be played (it doesn't exist or isn't approved yet).
+    AudioClipResponse {
+        clip_id: i64,
+        data: Option<Vec<u8>>,
+    },
+    UploadAudioClipResponse(Result<PostAudioClipResponse, String>),
+    /// Is routed to the bug report dialog, which can be opened on any screen.
+    BugReportResponse(Result<SubmittedBugReport, String>),
     RequestFailed(String),
 }
 
+#[derive(Debug)]
+pub struct SubmittedBugReport {
+    pub id: i64,
+    /// A report is kept even if its screenshot fails to upload.
+    pub screenshot_error: Option<String>,
+}
+
 pub struct PersistenceRequestsHandler {
     pub client: PersistenceClient,
     pub request_rx: UnboundedReceiver<PersistenceRequest>,
@@ -175,12 +460,12 @@ impl PersistenceRequestsHandler {
             let client = client.clone();
             let message_tx = message_tx.clone();
             match request {
-                PersistenceRequest::GetLevels { request_id, body } => {
-                    tokio::task::spawn_local(async move {
-                        match client.get_levels(&body).await {
+                PersistenceRequest::GetLevelsSummary { request_id, body } => {
+                    executor::spawn_local(async move {
+                        match client.get_levels_summary(&body).await {
                             Some(Ok(response)) => message_tx.send(PersistenceMessage::new(
                                 request_id,
-                                PersistenceMessagePayload::GetLevelsResponse(response),
+                                PersistenceMessagePayload::GetLevelsSummaryResponse(response),
                             )),
                             _ => message_tx.send(PersistenceMessage::new(
                                 request_id,
@@ -192,25 +477,260 @@ impl PersistenceRequestsHandler {
                         .expect("Failed to send a persistence message");
                     })
                 }
-                PersistenceRequest::GetLevel {
+                PersistenceRequest::GetFriends {
+                    request_id,
+                    id_token,
+                } => executor::spawn_local(async move {
+                    send_friends_list(&client, &id_token, request_id, &message_tx).await;
+                }),
+                PersistenceRequest::AddFriend {
+                    request_id,
+                    id_token,
+                    display_name,
+                } => executor::spawn_local(async move {
+                    match client
+                        .post_friend(&id_token, &PostFriendRequest { display_name })
+                        .await
+                    {
+                        Some(Ok(())) => {
+                            send_friends_list(&client, &id_token, request_id, &message_tx).await;
+                        }
+                        Some(Err(err)) => message_tx
+                            .send(PersistenceMessage::new(
+                                request_id,
+                                PersistenceMessagePayload::RequestFailed(err.message),
+                            ))
+                            .expect("Failed to send a persistence message"),
+                        None => message_tx
+                            .send(PersistenceMessage::new(
+                                request_id,
+                                PersistenceMessagePayload::RequestFailed(
+                                    "Failed to send a friend request".to_owned(),
+                                ),
+                            ))
+                            .expect("Failed to send a persistence message"),
+                    }
+                }),
+                PersistenceRequest::AcceptFriend {
+                    request_id,
+                    id_token,
+                    user_id,
+                } => executor::spawn_local(async move {
+                    match client.accept_friend(&id_token, user_id).await {
+                        Some(Ok(())) => {
+                            send_friends_list(&client, &id_token, request_id, &message_tx).await;
+                        }
+                        _ => message_tx
+                            .send(PersistenceMessage::new(
+                                request_id,
+                                PersistenceMessagePayload::RequestFailed(
+                                    "Failed to accept the friend request".to_owned(),
+                                ),
+                            ))
+                            .expect("Failed to send a persistence message"),
+                    }
+                }),
+                PersistenceRequest::RemoveFriend {
+                    request_id,
+                    id_token,
+                    user_id,
+                } => executor::spawn_local(async move {
+                    match client.delete_friend(&id_token, user_id).await {
+                        Some(Ok(())) => {
+                            send_friends_list(&client, &id_token, request_id, &message_tx).await;
+                        }
+                        _ => message_tx
+                            .send(PersistenceMessage::new(
+                                request_id,
+                                PersistenceMessagePayload::RequestFailed(
+                                    "Failed to remove the friend".to_owned(),
+                                ),
+                            ))
+                            .expect("Failed to send a persistence message"),
+                    }
+                }),
+                PersistenceRequest::GetPrivacySettings {
                     request_id,
-                    level_id,
-                } => tokio::task::spawn_local(async move {
-                    match client.get_level(level_id).await {
+                    id_token,
+                } => executor::spawn_local(async move {
+                    match client.get_privacy_settings(&id_token).await {
                         Some(Ok(response)) => message_tx.send(PersistenceMessage::new(
                             request_id,
-                            PersistenceMessagePayload::GetLevelResponse(response),
+                            PersistenceMessagePayload::PrivacySettingsResponse(response),
                         )),
                         _ => message_tx.send(PersistenceMessage::new(
                             request_id,
                             PersistenceMessagePayload::RequestFailed(
-                                "Failed to get the level".to_owned(),
+                                "Failed to get privacy settings".to_owned(),
                             ),
                         )),
                     }
                     .expect("Failed to send a persistence message");
                 }),
+                PersistenceRequest::UpdatePrivacySettings {
+                    request_id,
+                    id_token,
+                    settings,
+                } => executor::spawn_local(async move {
+                    match client.put_privacy_settings(&id_token, &settings).await {
+                        Some(Ok(response)) => message_tx.send(PersistenceMessage::new(
+                            request_id,
+                            PersistenceMessagePayload::PrivacySettingsResponse(response),
+                        )),
+                        _ => message_tx.send(PersistenceMessage::new(
+                            request_id,
+                            PersistenceMessagePayload::RequestFailed(
+                                "Failed to update privacy settings".to_owned(),
+                            ),
+                        )),
+                    }
+                    .expect("Failed to send a persistence message");
+                }),
+                PersistenceRequest::GetApiTokens {
+                    request_id,
+                    id_token,
+                } => executor::spawn_local(async move {
+                    send_api_tokens(&client, &id_token, request_id, &message_tx).await;
+                }),
+                PersistenceRequest::IssueApiToken {
+                    request_id,
+                    id_token,
+                    body,
+                } => executor::spawn_local(async move {
+                    let payload = match client.post_api_token(&id_token, &body).await {
+                        Some(Ok(response)) => PersistenceMessagePayload::ApiTokenIssued(response),
+                        Some(Err(err)) => PersistenceMessagePayload::RequestFailed(err.message),
+                        None => PersistenceMessagePayload::RequestFailed(
+                            "Failed to issue an API token".to_owned(),
+                        ),
+                    };
+                    message_tx
+                        .send(PersistenceMessage::new(request_id, payload))
+                        .expect("Failed to send a persistence message");
+                }),
+                PersistenceRequest::RevokeApiToken {
+                    request_id,
+                    id_token,
+                    token_id,
+                } => executor::spawn_local(async move {
+                    match client.delete_api_token(&id_token, token_id).await {
+                        Some(Ok(())) => {
+                            send_api_tokens(&client, &id_token, request_id, &message_tx).await;
+                        }
+                        _ => message_tx
+                            .send(PersistenceMessage::new(
+                                request_id,
+                                PersistenceMessagePayload::RequestFailed(
+                                    "Failed to revoke the API token".to_owned(),
+                                ),
+                            ))
+                            .expect("Failed to send a persistence message"),
+                    }
+                }),
+                PersistenceRequest::GetAudioClip {
+                    request_id,
+                    clip_id,
+                } => executor::spawn_local(async move {
+                    let data = client.get_audio_clip(clip_id).await;
+                    message_tx
+                        .send(PersistenceMessage::new(
+                            request_id,
+                            PersistenceMessagePayload::AudioClipResponse { clip_id, data },
+                        ))
+                        .expect("Failed to send a persistence message");
+                }),
+                PersistenceRequest::UploadAudioClip {
+                    request_id,
+                    id_token,
+                    data,
+                } => executor::spawn_local(async move {
+                    let result = match client.post_audio_clip(&id_token, data).await {
+                        Some(Ok(response)) => Ok(response),
+                        Some(Err(err)) => Err(err.message),
+                        None => Err("Failed to upload the audio clip".to_owned()),
+                    };
+                    message_tx
+                        .send(PersistenceMessage::new(
+                            request_id,
+                            PersistenceMessagePayload::UploadAudioClipResponse(result),
+                        ))
+                        .expect("Failed to send a persistence message");
+                }),
+                PersistenceRequest::SubmitBugReport {
+                    request_id,
+                    id_token,
+                    body,
+                    screenshot,
+                } => executor::spawn_local(async move {
+                    let result = match client.post_bug_report(&id_token, &body).await {
+                        Some(Ok(response)) => {
+                            let screenshot_error = match screenshot {
+                                Some(data) => match client
+                                    .put_bug_report_screenshot(&id_token, response.id, data)
+                                    .await
+                                {
+                                    Some(Ok(())) => None,
+                                    Some(Err(err)) => Some(err.message),
+                                    None => Some("Failed to upload the screenshot".to_owned()),
+                                },
+                                None => None,
+                            };
+                            Ok(SubmittedBugReport {
+                                id: response.id,
+                                screenshot_error,
+                            })
+                        }
+                        Some(Err(err)) => Err(err.message),
+                        None => Err("Failed to submit the bug report".to_owned()),
+                    };
+                    message_tx
+                        .send(PersistenceMessage::new(
+                            request_id,
+                            PersistenceMessagePayload::BugReportResponse(result),
+                        ))
+                        .expect("Failed to send a persistence message");
+                }),
             };
         }
     }
 }
+
+async fn send_friends_list(
+    client: &PersistenceClient,
+    id_token: &str,
+    request_id: MessageId,
+    message_tx: &UnboundedSender<PersistenceMessage>,
+) {
+    match client.get_friends(id_token).await {
+        Some(Ok(response)) => message_tx.send(PersistenceMessage::new(
+            request_id,
+            PersistenceMessagePayload::GetFriendsResponse(response),
+        )),
+        _ => message_tx.send(PersistenceMessage::new(
+            request_id,
+            PersistenceMessagePayload::RequestFailed(
+                "Failed to get the list of friends".to_owned(),
+            ),
+        )),
+    }
+    .expect("Failed to send a persistence message");
+}
+
+async fn send_api_tokens(
+    client: &PersistenceClient,
+    id_token: &str,
+    request_id: MessageId,
+    message_tx: &UnboundedSender<PersistenceMessage>,
+) {
+    match client.get_api_tokens(id_token).await {
+        Some(Ok(response)) => message_tx.send(PersistenceMessage::new(
+            request_id,
+            PersistenceMessagePayload::GetApiTokensResponse(response),
+        )),
+        _ => message_tx.send(PersistenceMessage::new(
+            request_id,
+            PersistenceMessagePayload::RequestFailed("Failed to get API tokens".to_owned()),
+        )),
+    }
+    .expect("Failed to send a persistence message");
+}
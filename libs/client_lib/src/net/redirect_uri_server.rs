@@ -1,5 +1,6 @@
 use crate::net::auth::{AuthRequest, OAuthResponse};
 use bevy::log;
+use mr_utils_lib::executor;
 use tokio::sync::mpsc::UnboundedSender;
 
 pub async fn serve(auth_request_tx: UnboundedSender<AuthRequest>) {
@@ -72,7 +73,7 @@ pub async fn serve(auth_request_tx: UnboundedSender<AuthRequest>) {
         };
 
         let service = make_svc();
-        tokio::spawn(async move {
+        executor::spawn(async move {
             if let Err(err) = hyper::server::conn::http1::Builder::new()
                 .serve_connection(stream, service)
                 .await
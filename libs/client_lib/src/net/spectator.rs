@@ -0,0 +1,246 @@
+//! Spectators don't control a runner, so there's nothing to predict for them.
+//! Instead of rewinding and re-simulating every delta update, positions of
+//! the players are buffered and rendered slightly in the past, interpolating
+//! between the two surrounding snapshots. This also hides jitter of the
+//! updates coming from high-ping servers.
+
+use crate::helpers::PlayerParams;
+use bevy::{
+    ecs::{
+        query::With,
+        system::{Query, Res, ResMut, Resource},
+    },
+    math::Vec2,
+    time::Time,
+    transform::components::Transform,
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::components::PlayerTag,
+    messages::{PlayerNetId, PlayerState},
+    player::{Player, PlayerRole},
+    registry::EntityRegistry,
+    SIMULATIONS_PER_SECOND,
+};
+use std::collections::VecDeque;
+
+/// How far behind the newest snapshot spectators are rendered (in frames),
+/// which equals to 100ms.
+pub const SPECTATOR_INTERPOLATION_DELAY: f64 = SIMULATIONS_PER_SECOND as f64 / 10.0;
+/// If the playback drifts further than this (in frames), e.g. after a lag
+/// spike, it jumps to the target instead of catching up gradually.
+const MAX_PLAYBACK_DRIFT: f64 = SIMULATIONS_PER_SECOND as f64 / 2.0;
+/// The max deviation of the playback speed when catching up with the target.
+const MAX_SPEED_ADJUSTMENT: f64 = 0.1;
+const SNAPSHOTS_LIMIT: usize = 64;
+
+pub fn is_spectating(player: &Player) -> bool {
+    matches!(player.role, PlayerRole::Spectator)
+}
+
+struct Snapshot {
+    /// Unlike `FrameNumber`, doesn't wrap.
+    frame: u64,
+    positions: Vec<(PlayerNetId, Vec2)>,
+}
+
+#[derive(Resource, Default)]
+pub struct SpectatorSnapshots {
+    snapshots: VecDeque<Snapshot>,
+    last_frame_number: Option<FrameNumber>,
+    playback_frame: Option<f64>,
+}
+
+impl SpectatorSnapshots {
+    /// Late and duplicate updates are ignored.
+    pub fn push(&mut self, frame_number: FrameNumber, players: &[PlayerState]) {
+        let frame = match (self.last_frame_number, self.snapshots.back()) {
+            (Some(last_frame_number), Some(newest)) => {
+                if frame_number <= last_frame_number {
+                    return;
+                }
+                newest.frame + (frame_number - last_frame_number).value() as u64
+            }
+            _ => frame_number.value() as u64,
+        };
+        self.last_frame_number = Some(frame_number);
+
+        if self.snapshots.len() == SNAPSHOTS_LIMIT {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot {
+            frame,
+            positions: players
+                .iter()
+                .map(|player| (player.net_id, player.position))
+                .collect(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Moves the playback forward, speeding it up or slowing it down a bit to
+    /// keep it `SPECTATOR_INTERPOLATION_DELAY` frames behind the newest
+    /// snapshot.
+    pub fn advance(&mut self, delta_secs: f64) {
+        let Some(newest) = self.snapshots.back() else {
+            return;
+        };
+        let target = newest.frame as f64 - SPECTATOR_INTERPOLATION_DELAY;
+        let playback_frame = match self.playback_frame {
+            Some(playback_frame) if (target - playback_frame).abs() <= MAX_PLAYBACK_DRIFT => {
+                let drift = (target - playback_frame) / SPECTATOR_INTERPOLATION_DELAY;
+                let speed_adjustment = drift.clamp(-MAX_SPEED_ADJUSTMENT, MAX_SPEED_ADJUSTMENT);
+                playback_frame
+                    + delta_secs * SIMULATIONS_PER_SECOND as f64 * (1.0 + speed_adjustment)
+            }
+            _ => target,
+        };
+        // If updates stop coming, we hold the newest snapshot instead of
+        // extrapolating.
+        self.playback_frame = Some(playback_frame.min(newest.frame as f64));
+    }
+
+    /// Returns positions of the players at the current playback frame.
+    pub fn positions(&self) -> Vec<(PlayerNetId, Vec2)> {
+        let Some(playback_frame) = self.playback_frame else {
+            return Vec::new();
+        };
+        let next_index = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.frame as f64 >= playback_frame);
+        let (prev, next) = match next_index {
+            Some(0) => return self.snapshots[0].positions.clone(),
+            Some(i) => (&self.snapshots[i - 1], &self.snapshots[i]),
+            None => {
+                return self
+                    .snapshots
+                    .back()
+                    .map_or_else(Vec::new, |snapshot| snapshot.positions.clone());
+            }
+        };
+
+        let t = ((playback_frame - prev.frame as f64) / (next.frame - prev.frame) as f64) as f32;
+        next.positions
+            .iter()
+            .map(|&(net_id, next_position)| {
+                // Players that have just spawned don't have anything to be
+                // interpolated from.
+                let position = prev
+                    .positions
+                    .iter()
+                    .find(|(prev_net_id, _)| *prev_net_id == net_id)
+                    .map_or(next_position, |(_, prev_position)| {
+                        prev_position.lerp(next_position, t)
+                    });
+                (net_id, position)
+            })
+            .collect()
+    }
+}
+
+pub fn interpolate_spectated_players_system(
+    time: Res<Time>,
+    player_params: PlayerParams,
+    player_entities: Res<EntityRegistry<PlayerNetId>>,
+    mut spectator_snapshots: ResMut<SpectatorSnapshots>,
+    mut transforms: Query<&mut Transform, With<PlayerTag>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !player_params.current_player().map_or(false, is_spectating) {
+        if spectator_snapshots.playback_frame.is_some() {
+            spectator_snapshots.clear();
+        }
+        return;
+    }
+
+    spectator_snapshots.advance(time.delta_seconds_f64());
+    for (net_id, position) in spectator_snapshots.positions() {
+        let Some(mut transform) = player_entities
+            .get_entity(net_id)
+            .and_then(|entity| transforms.get_mut(entity).ok())
+        else {
+            continue;
+        };
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player_state(net_id: u16, position: Vec2) -> PlayerState {
+        PlayerState {
+            net_id: PlayerNetId(net_id),
+            position,
+            direction: Vec2::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_is_spectating() {
+        let player = |role| Player::new_with_nickname(role, "Muddler".to_owned());
+        assert!(is_spectating(&player(PlayerRole::Spectator)));
+        // Builders don't control a runner either, but they still need the
+        // server positions of runners (for the collision preview, for instance).
+        assert!(!is_spectating(&player(PlayerRole::Builder)));
+        assert!(!is_spectating(&player(PlayerRole::Runner)));
+    }
+
+    #[test]
+    fn test_spectator_snapshots_interpolation() {
+        let mut snapshots = SpectatorSnapshots::default();
+        for frame in 0..=20u16 {
+            snapshots.push(
+                FrameNumber::new(frame),
+                &[player_state(1, Vec2::new(frame as f32, 0.0))],
+            );
+        }
+        // Late updates are ignored.
+        snapshots.push(FrameNumber::new(5), &[player_state(1, Vec2::ZERO)]);
+
+        snapshots.advance(0.0);
+        let delayed_frame = 20.0 - SPECTATOR_INTERPOLATION_DELAY as f32;
+        let (net_id, position) = snapshots.positions()[0];
+        assert_eq!(net_id, PlayerNetId(1));
+        assert!((position.x - delayed_frame).abs() < 0.001);
+
+        // Half a frame later the position lies between the two snapshots.
+        snapshots.advance(0.5 / SIMULATIONS_PER_SECOND as f64);
+        let (_, position) = snapshots.positions()[0];
+        assert!(position.x > delayed_frame && position.x < delayed_frame + 1.0);
+
+        // Without new updates the newest snapshot is held.
+        snapshots.advance(0.5);
+        assert_eq!(
+            snapshots.positions(),
+            vec![(PlayerNetId(1), Vec2::new(20.0, 0.0))]
+        );
+    }
+
+    #[test]
+    fn test_spectator_snapshots_wrapping_frames() {
+        let mut snapshots = SpectatorSnapshots::default();
+        snapshots.push(FrameNumber::new(u16::MAX - 1), &[]);
+        snapshots.push(FrameNumber::new(u16::MAX), &[]);
+        snapshots.push(FrameNumber::new(0), &[player_state(1, Vec2::ONE)]);
+        let frames = snapshots
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.frame)
+            .collect::<Vec<_>>();
+        let first = u16::MAX as u64 - 1;
+        assert_eq!(frames, vec![first, first + 1, first + 2]);
+
+        // Players that aren't present in the previous snapshot aren't
+        // interpolated.
+        snapshots.playback_frame = Some(first as f64 + 1.5);
+        assert_eq!(snapshots.positions(), vec![(PlayerNetId(1), Vec2::ONE)]);
+    }
+}
@@ -0,0 +1,573 @@
+use crate::{
+    helpers::PlayerParams,
+    input::{LevelObjectRequestsQueue, PlayerRequestsQueue},
+    LevelObjectCorrelations,
+};
+use bevy::{
+    ecs::system::{Res, ResMut, Resource, SystemParam},
+    log,
+    utils::{HashMap, HashSet},
+};
+use iyes_loopless::state::CurrentState;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        commands::{DespawnLevelObject, UpdateLevelObject, UpdateLevelSettings},
+        level::{CollisionLogic, LevelObject, LevelSettings, LevelState, LevelStateChange},
+    },
+    messages::{EntityNetId, SpawnLevelObjectRequest, SpawnLevelObjectRequestBody},
+    net::{ConnectionState, ConnectionStatus},
+    player::PlayerRole,
+    AppState, GameSessionState, GameTime,
+};
+use std::marker::PhantomData;
+
+/// Lets builders keep editing a level while the connection is lost.
+///
+/// Offline edits are applied to the local `LevelState`. After reconnecting,
+/// they are merged (object by object) with the level that the server has by
+/// then: changes to the objects that nobody else touched are sent to the
+/// server, the rest is presented as conflicts to resolve in the merge UI.
+#[derive(Resource, Default)]
+pub struct OfflineEditing {
+    was_connected: bool,
+    session: Option<OfflineSession>,
+    /// Offline changes waiting for the server level to get loaded after
+    /// reconnecting.
+    pending: Option<OfflineChanges>,
+    builder_role_requested: bool,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+struct OfflineSession {
+    base: LevelSnapshot,
+    /// The revision of the local `LevelState` at the moment of disconnecting,
+    /// every change after it is an offline edit.
+    base_revision: u64,
+    changes_count: usize,
+}
+
+/// The level as it was when the connection got lost.
+#[derive(Default)]
+struct LevelSnapshot {
+    objects: HashMap<EntityNetId, LevelObject>,
+    settings: LevelSettings,
+}
+
+impl LevelSnapshot {
+    fn new(level_state: &LevelState) -> Self {
+        Self {
+            objects: level_state.objects().clone(),
+            settings: level_state.settings().clone(),
+        }
+    }
+}
+
+struct OfflineChanges {
+    base: LevelSnapshot,
+    /// Objects edited offline, paired with their final states (`None` if an
+    /// object got despawned).
+    objects: Vec<(EntityNetId, Option<LevelObject>)>,
+    settings: Option<LevelSettings>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeConflict {
+    /// An object was edited (or despawned) both offline and on the server.
+    Object {
+        base: LevelObject,
+        ours: Option<LevelObject>,
+        theirs: Option<LevelObject>,
+    },
+    Settings {
+        ours: LevelSettings,
+        theirs: LevelSettings,
+    },
+}
+
+impl MergeConflict {
+    pub fn label(&self) -> &str {
+        match self {
+            Self::Object { base, .. } => &base.label,
+            Self::Settings { .. } => "Level settings",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Object {
+                ours: None,
+                theirs: Some(_),
+                ..
+            } => "Deleted by you, changed on the server",
+            Self::Object {
+                ours: Some(_),
+                theirs: None,
+                ..
+            } => "Changed by you, deleted on the server",
+            Self::Object { .. } | Self::Settings { .. } => "Changed by you and on the server",
+        }
+    }
+}
+
+/// A change that can be applied by sending a request to the server.
+#[derive(Clone, Debug, PartialEq)]
+enum MergedChange {
+    Spawn(LevelObject),
+    Update(LevelObject),
+    Despawn(EntityNetId),
+    Settings(LevelSettings),
+}
+
+impl OfflineEditing {
+    pub fn is_editing_offline(&self) -> bool {
+        self.session.is_some()
+    }
+
+    pub fn is_merging(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Counts the objects (and settings) edited since disconnecting.
+    pub fn offline_changes_count(&self) -> usize {
+        self.session
+            .as_ref()
+            .map_or(0, |session| session.changes_count)
+    }
+
+    /// Applies the server's version of a conflicting change, which doesn't
+    /// require sending anything.
+    pub fn keep_theirs(&mut self, index: usize) {
+        self.conflicts.remove(index);
+    }
+
+    pub fn keep_ours(
+        &mut self,
+        index: usize,
+        requests_queue: &mut LevelObjectRequestsQueue,
+        correlations: &mut LevelObjectCorrelations,
+    ) {
+        let change = match self.conflicts.remove(index) {
+            MergeConflict::Object {
+                base,
+                ours: None,
+                theirs: Some(_),
+            } => MergedChange::Despawn(base.net_id),
+            MergeConflict::Object {
+                ours: Some(ours),
+                theirs: None,
+                ..
+            } => MergedChange::Spawn(ours),
+            MergeConflict::Object {
+                ours: Some(ours), ..
+            } => MergedChange::Update(ours),
+            // Both sides despawned the object, there's nothing to keep.
+            MergeConflict::Object { ours: None, .. } => return,
+            MergeConflict::Settings { ours, .. } => MergedChange::Settings(ours),
+        };
+        push_request(change, requests_queue, correlations);
+    }
+}
+
+#[derive(SystemParam)]
+pub struct OfflineEditingParams<'w, 's> {
+    offline_editing: ResMut<'w, OfflineEditing>,
+    level_state: ResMut<'w, LevelState>,
+    level_object_requests: ResMut<'w, LevelObjectRequestsQueue>,
+    player_requests: ResMut<'w, PlayerRequestsQueue>,
+    level_object_correlations: ResMut<'w, LevelObjectCorrelations>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+pub fn offline_editing_system(
+    app_state: Res<CurrentState<AppState>>,
+    game_session_state: Res<CurrentState<GameSessionState>>,
+    connection_state: Res<ConnectionState>,
+    game_time: Res<GameTime>,
+    player_params: PlayerParams,
+    mut params: OfflineEditingParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let OfflineEditingParams {
+        offline_editing,
+        level_state,
+        level_object_requests,
+        player_requests,
+        level_object_correlations,
+        ..
+    } = &mut params;
+    let is_connected = matches!(connection_state.status(), ConnectionStatus::Connected);
+    let was_connected = std::mem::replace(&mut offline_editing.was_connected, is_connected);
+
+    if app_state.0 != AppState::Playing {
+        // Disconnecting deliberately discards offline edits.
+        offline_editing.session = None;
+        offline_editing.pending = None;
+        offline_editing.conflicts.clear();
+        return;
+    }
+
+    let is_builder = player_params
+        .current_player()
+        .map_or(false, |player| player.role == PlayerRole::Builder);
+    if was_connected && !is_connected && is_builder && offline_editing.pending.is_none() {
+        log::info!("Connection lost, switching to offline editing");
+        offline_editing.session = Some(OfflineSession {
+            base: LevelSnapshot::new(level_state),
+            base_revision: level_state.revision(),
+            changes_count: 0,
+        });
+    }
+
+    if offline_editing.session.is_none() {
+        let can_merge = is_connected && game_session_state.0 == GameSessionState::Playing;
+        if offline_editing.pending.is_none() || !can_merge {
+            return;
+        }
+        // The server accepts level edits only from builders.
+        if !is_builder {
+            if !offline_editing.builder_role_requested {
+                offline_editing.builder_role_requested = true;
+                player_requests.switch_role.push(PlayerRole::Builder);
+            }
+            return;
+        }
+        let pending = offline_editing
+            .pending
+            .take()
+            .expect("Expected pending offline changes");
+        let (changes, conflicts) = merge(&pending, &LevelSnapshot::new(level_state));
+        log::info!(
+            "Merging offline edits: {} change(s) applied, {} conflict(s)",
+            changes.len(),
+            conflicts.len()
+        );
+        for change in changes {
+            push_request(change, level_object_requests, level_object_correlations);
+        }
+        offline_editing.conflicts = conflicts;
+        offline_editing.builder_role_requested = false;
+        return;
+    }
+
+    if !matches!(
+        connection_state.status(),
+        ConnectionStatus::Handshaking | ConnectionStatus::Connected
+    ) {
+        apply_offline_requests(level_state, level_object_requests, game_time.frame_number);
+        if let Some(session) = offline_editing.session.as_mut() {
+            let (objects, settings_changed) =
+                collect_changed(&session.base, session.base_revision, level_state);
+            session.changes_count = objects.len() + settings_changed as usize;
+        }
+        return;
+    }
+
+    // A new session has started: the server is going to send the whole level, so
+    // the local one is cleared to avoid mixing it with the offline edits.
+    let session = offline_editing
+        .session
+        .take()
+        .expect("Expected an offline session");
+    let (objects, settings_changed) =
+        collect_changed(&session.base, session.base_revision, level_state);
+    let objects = objects
+        .into_iter()
+        .map(|net_id| (net_id, level_state.object(net_id).cloned()))
+        .collect::<Vec<_>>();
+    let settings = settings_changed.then(|| level_state.settings().clone());
+    let net_ids = level_state.objects().keys().copied().collect::<Vec<_>>();
+    for net_id in net_ids {
+        level_state.apply_despawn(&DespawnLevelObject {
+            net_id,
+            frame_number: game_time.frame_number,
+        });
+    }
+    log::info!(
+        "Reconnected, {} offline change(s) will be merged once the level is loaded",
+        objects.len() + settings_changed as usize
+    );
+    offline_editing.pending = Some(OfflineChanges {
+        base: session.base,
+        objects,
+        settings,
+    });
+}
+
+/// Builders keep pushing requests while offline, they are applied locally
+/// instead of being sent.
+fn apply_offline_requests(
+    level_state: &mut LevelState,
+    requests: &mut LevelObjectRequestsQueue,
+    frame_number: FrameNumber,
+) {
+    for spawn_request in std::mem::take(&mut requests.spawn_requests) {
+        let desc = match spawn_request.body {
+            SpawnLevelObjectRequestBody::New(desc) => desc,
+            SpawnLevelObjectRequestBody::Copy(net_id) => match level_state.object(net_id) {
+                Some(object) => object.desc.clone(),
+                None => continue,
+            },
+        };
+        let Some(net_id) = offline_net_id(level_state) else {
+            log::error!("Failed to allocate an id for an object spawned offline");
+            continue;
+        };
+        level_state.apply_update(&UpdateLevelObject {
+            object: LevelObject {
+                net_id,
+                label: format!("{} (offline)", desc.label()),
+                desc,
+                route: None,
+                collision_logic: CollisionLogic::None,
+            },
+            frame_number,
+        });
+    }
+    for object in std::mem::take(&mut requests.update_requests) {
+        level_state.apply_update(&UpdateLevelObject {
+            object,
+            frame_number,
+        });
+    }
+    for net_id in std::mem::take(&mut requests.despawn_requests) {
+        level_state.apply_despawn(&DespawnLevelObject {
+            net_id,
+            frame_number,
+        });
+    }
+    if let Some(settings) = requests.settings_update_request.take() {
+        level_state.apply_settings(&UpdateLevelSettings { settings });
+    }
+}
+
+/// Objects spawned offline get temporary ids from the end of the range, the
+/// server allocates the real ones when they are merged.
+fn offline_net_id(level_state: &LevelState) -> Option<EntityNetId> {
+    (0..=u16::MAX)
+        .rev()
+        .map(EntityNetId)
+        .find(|net_id| level_state.object(*net_id).is_none())
+}
+
+/// Returns the objects changed after the base revision and whether the
+/// settings were changed. If the level has forgotten some of the changes,
+/// falls back to comparing the whole level with the base.
+fn collect_changed(
+    base: &LevelSnapshot,
+    base_revision: u64,
+    level_state: &LevelState,
+) -> (Vec<EntityNetId>, bool) {
+    let mut objects = HashSet::default();
+    let mut settings_changed = false;
+    match level_state.changes_since(base_revision) {
+        Some(changes) => {
+            for (_, change) in changes {
+                match change {
+                    LevelStateChange::SettingsUpdated { .. } => settings_changed = true,
+                    change => {
+                        objects.extend(change.net_id());
+                    }
+                }
+            }
+        }
+        None => {
+            objects.extend(base.objects.keys().copied());
+            objects.extend(level_state.objects().keys().copied());
+            settings_changed = true;
+        }
+    }
+    // Changes that were reverted offline aren't changes.
+    let mut objects = objects
+        .into_iter()
+        .filter(|net_id| base.objects.get(net_id) != level_state.object(*net_id))
+        .collect::<Vec<_>>();
+    objects.sort_by_key(|net_id| net_id.0);
+    (
+        objects,
+        settings_changed && base.settings != *level_state.settings(),
+    )
+}
+
+/// Three-way merge of the offline changes with the server level: a change is
+/// applied if the server still has the base version of an object, it's a
+/// conflict if the server has a different one.
+fn merge(
+    changes: &OfflineChanges,
+    theirs: &LevelSnapshot,
+) -> (Vec<MergedChange>, Vec<MergeConflict>) {
+    let mut merged = Vec::new();
+    let mut conflicts = Vec::new();
+    for (net_id, ours) in &changes.objects {
+        let theirs = theirs.objects.get(net_id);
+        let Some(base) = changes.base.objects.get(net_id) else {
+            // Spawned offline, nobody else could have touched it.
+            if let Some(ours) = ours {
+                merged.push(MergedChange::Spawn(ours.clone()));
+            }
+            continue;
+        };
+        if theirs == ours.as_ref() {
+            continue;
+        }
+        if theirs == Some(base) {
+            merged.push(match ours {
+                Some(ours) => MergedChange::Update(ours.clone()),
+                None => MergedChange::Despawn(*net_id),
+            });
+            continue;
+        }
+        conflicts.push(MergeConflict::Object {
+            base: base.clone(),
+            ours: ours.clone(),
+            theirs: theirs.cloned(),
+        });
+    }
+
+    if let Some(ours) = &changes.settings {
+        if theirs.settings == changes.base.settings {
+            merged.push(MergedChange::Settings(ours.clone()));
+        } else if theirs.settings != *ours {
+            conflicts.push(MergeConflict::Settings {
+                ours: ours.clone(),
+                theirs: theirs.settings.clone(),
+            });
+        }
+    }
+
+    (merged, conflicts)
+}
+
+fn push_request(
+    change: MergedChange,
+    requests_queue: &mut LevelObjectRequestsQueue,
+    correlations: &mut LevelObjectCorrelations,
+) {
+    match change {
+        // The server allocates a new id and label, routes and collision logic
+        // are lost, as a spawn request carries only the description.
+        MergedChange::Spawn(object) => {
+            requests_queue.spawn_requests.push(SpawnLevelObjectRequest {
+                correlation_id: correlations.next_correlation_id(),
+                body: SpawnLevelObjectRequestBody::New(object.desc),
+            })
+        }
+        MergedChange::Update(object) => requests_queue.update_requests.push(object),
+        MergedChange::Despawn(net_id) => requests_queue.despawn_requests.push(net_id),
+        MergedChange::Settings(settings) => {
+            requests_queue.settings_update_request = Some(settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::Vec2;
+    use mr_shared_lib::{game::level_objects::RoutePointDesc, test_harness::cube};
+
+    fn snapshot(objects: impl IntoIterator<Item = LevelObject>) -> LevelSnapshot {
+        LevelSnapshot {
+            objects: objects
+                .into_iter()
+                .map(|object| (object.net_id, object))
+                .collect(),
+            settings: LevelSettings::default(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = snapshot([
+            cube(0, Vec2::ZERO, 1.0),
+            cube(1, Vec2::ZERO, 1.0),
+            cube(2, Vec2::ZERO, 1.0),
+            cube(3, Vec2::ZERO, 1.0),
+        ]);
+        let theirs = snapshot([
+            cube(0, Vec2::ZERO, 1.0),
+            cube(1, Vec2::ZERO, 2.0),
+            cube(2, Vec2::ZERO, 3.0),
+        ]);
+        let spawned_offline = LevelObject {
+            net_id: EntityNetId(u16::MAX),
+            label: "Route point (offline)".to_owned(),
+            desc: mr_shared_lib::game::level::LevelObjectDesc::RoutePoint(RoutePointDesc {
+                position: Default::default(),
+            }),
+            route: None,
+            collision_logic: CollisionLogic::None,
+        };
+        let changes = OfflineChanges {
+            base,
+            objects: vec![
+                // Untouched on the server.
+                (EntityNetId(0), Some(cube(0, Vec2::ZERO, 4.0))),
+                // Changed in the same way.
+                (EntityNetId(1), Some(cube(1, Vec2::ZERO, 2.0))),
+                // Changed differently.
+                (EntityNetId(2), Some(cube(2, Vec2::ZERO, 4.0))),
+                // Deleted on the server.
+                (EntityNetId(3), Some(cube(3, Vec2::ZERO, 4.0))),
+                (EntityNetId(u16::MAX), Some(spawned_offline.clone())),
+            ],
+            settings: None,
+        };
+
+        let (merged, conflicts) = merge(&changes, &theirs);
+        assert_eq!(
+            merged,
+            vec![
+                MergedChange::Update(cube(0, Vec2::ZERO, 4.0)),
+                MergedChange::Spawn(spawned_offline)
+            ]
+        );
+        assert_eq!(
+            conflicts,
+            vec![
+                MergeConflict::Object {
+                    base: cube(2, Vec2::ZERO, 1.0),
+                    ours: Some(cube(2, Vec2::ZERO, 4.0)),
+                    theirs: Some(cube(2, Vec2::ZERO, 3.0)),
+                },
+                MergeConflict::Object {
+                    base: cube(3, Vec2::ZERO, 1.0),
+                    ours: Some(cube(3, Vec2::ZERO, 4.0)),
+                    theirs: None,
+                },
+            ]
+        );
+        assert_eq!(
+            conflicts[1].description(),
+            "Changed by you, deleted on the server"
+        );
+    }
+
+    #[test]
+    fn test_collect_changed_skips_reverted_edits() {
+        let mut level_state = LevelState::default();
+        for object in [cube(0, Vec2::ZERO, 1.0), cube(1, Vec2::ZERO, 1.0)] {
+            level_state.apply_update(&UpdateLevelObject {
+                object,
+                frame_number: Default::default(),
+            });
+        }
+        let base = LevelSnapshot::new(&level_state);
+        let base_revision = level_state.revision();
+
+        let mut requests = LevelObjectRequestsQueue::default();
+        requests.update_requests.push(cube(0, Vec2::ZERO, 2.0));
+        requests.update_requests.push(cube(1, Vec2::ZERO, 2.0));
+        apply_offline_requests(&mut level_state, &mut requests, Default::default());
+        requests.update_requests.push(cube(1, Vec2::ZERO, 1.0));
+        requests.despawn_requests.push(EntityNetId(0));
+        apply_offline_requests(&mut level_state, &mut requests, Default::default());
+
+        assert_eq!(
+            collect_changed(&base, base_revision, &level_state),
+            (vec![EntityNetId(0)], false)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config_storage::{self, PersonalBestsConfig, PERSONAL_BESTS_CONFIG_KEY};
+use bevy::{
+    ecs::system::{ResMut, Resource},
+    log,
+};
+use mr_shared_lib::messages::FinishResult;
+
+/// Best timed finishes of the current player on the persisted levels. They are
+/// stored locally, as finishes aren't reported to the persistence service.
+#[derive(Resource, Default)]
+pub struct PersonalBests {
+    config: PersonalBestsConfig,
+    /// The latest finish of the current player, shown on the finish screen.
+    pub last_finish: Option<LastFinish>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LastFinish {
+    pub result: FinishResult,
+    /// The best result before this finish, if there was any.
+    pub previous_best: Option<FinishResult>,
+}
+
+impl LastFinish {
+    /// Negative values mean that the personal best is beaten.
+    pub fn delta_secs(&self) -> Option<f32> {
+        self.previous_best
+            .map(|previous_best| self.result.secs() - previous_best.secs())
+    }
+}
+
+impl PersonalBests {
+    pub fn get(&self, level_id: i64) -> Option<FinishResult> {
+        self.config.levels.get(&level_id).copied()
+    }
+
+    /// Levels that aren't persisted (i.e. `level_id` is `None`) are compared
+    /// against the best finish within the session. Returns whether a new
+    /// personal best is stored.
+    pub fn record_finish(
+        &mut self,
+        level_id: Option<i64>,
+        result: FinishResult,
+        session_best: Option<FinishResult>,
+    ) -> bool {
+        let previous_best = match level_id {
+            Some(level_id) => self.get(level_id),
+            None => session_best,
+        };
+        self.last_finish = Some(LastFinish {
+            result,
+            previous_best,
+        });
+
+        let Some(level_id) = level_id else {
+            return false;
+        };
+        if previous_best.map_or(false, |previous_best| previous_best.frames <= result.frames) {
+            return false;
+        }
+        self.config.levels.insert(level_id, result);
+        if let Err(err) = config_storage::write(PERSONAL_BESTS_CONFIG_KEY, &self.config) {
+            log::error!("Failed to save personal bests: {:?}", err);
+        }
+        true
+    }
+}
+
+pub fn read_personal_bests_system(mut personal_bests: ResMut<PersonalBests>) {
+    match config_storage::read::<PersonalBestsConfig>(PERSONAL_BESTS_CONFIG_KEY) {
+        Ok(config) => personal_bests.config = config,
+        Err(err) => log::error!("Failed to read personal bests: {:?}", err),
+    }
+}
@@ -0,0 +1,91 @@
+use bevy::ecs::system::Resource;
+use mr_shared_lib::messages::{ServerHealth, TickDurationBucket};
+use std::collections::VecDeque;
+
+/// How many of the latest delta updates are taken into account. A single
+/// overloaded tick is enough to delay a burst of updates, so the diagnosis
+/// is held for a few more updates.
+const SERVER_HEALTH_HISTORY_LEN: usize = 8;
+
+/// Keeps the server health reports of the latest delta updates, to tell
+/// whether late updates are caused by the network or by the server.
+#[derive(Resource, Default)]
+pub struct ServerHealthReport {
+    history: VecDeque<ServerHealth>,
+}
+
+impl ServerHealthReport {
+    pub fn record(&mut self, health: ServerHealth) {
+        if self.history.len() == SERVER_HEALTH_HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(health);
+    }
+
+    pub fn latest(&self) -> Option<ServerHealth> {
+        self.history.back().copied()
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        self.history.iter().any(ServerHealth::is_overloaded)
+    }
+
+    /// Updates of a lagging server arrive in bursts of queued ticks, so the
+    /// jitter buffer has to fit them. An overrun tick delays the next update
+    /// by one more frame.
+    pub fn extra_jitter_frames(&self) -> u16 {
+        let queue_depth = self
+            .history
+            .iter()
+            .map(|health| health.broadcast_queue_depth)
+            .max()
+            .unwrap_or(0);
+        let has_overruns = self
+            .history
+            .iter()
+            .any(|health| health.tick_duration == TickDurationBucket::Overrun);
+        queue_depth as u16 + has_overruns as u16
+    }
+
+    /// Reports of the previous session are irrelevant after reconnecting.
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(tick_duration: TickDurationBucket, broadcast_queue_depth: u8) -> ServerHealth {
+        ServerHealth {
+            tick_duration,
+            broadcast_queue_depth,
+        }
+    }
+
+    #[test]
+    fn test_server_health_report() {
+        let mut report = ServerHealthReport::default();
+        assert!(!report.is_overloaded());
+        assert_eq!(report.extra_jitter_frames(), 0);
+
+        report.record(health(TickDurationBucket::Heavy, 1));
+        assert!(!report.is_overloaded());
+        assert_eq!(report.extra_jitter_frames(), 1);
+
+        report.record(health(TickDurationBucket::Overrun, 3));
+        assert!(report.is_overloaded());
+        assert_eq!(report.extra_jitter_frames(), 4);
+
+        for _ in 0..SERVER_HEALTH_HISTORY_LEN - 1 {
+            report.record(health(TickDurationBucket::Light, 0));
+        }
+        assert!(report.is_overloaded());
+
+        report.record(health(TickDurationBucket::Light, 0));
+        assert!(!report.is_overloaded());
+        assert_eq!(report.extra_jitter_frames(), 0);
+        assert_eq!(report.latest(), Some(ServerHealth::default()));
+    }
+}
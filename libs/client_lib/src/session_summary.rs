@@ -0,0 +1,349 @@
+use crate::{
+    net::{ConnectedServer, ServerToConnect},
+    server_health::ServerHealthReport,
+};
+use bevy::{
+    ecs::system::{Commands, Res, ResMut, Resource},
+    log,
+    utils::Instant,
+};
+use iyes_loopless::state::{CurrentState, NextState};
+use mr_messages_lib::Server;
+use mr_shared_lib::{
+    game::level::Medal,
+    messages::{DisconnectReason, FinishResult, SessionStats},
+    net::{ConnectionState, ConnectionStatus},
+    AppState, GameSessionState,
+};
+use std::time::Duration;
+
+/// Mean rtt (in milliseconds) under which the connection is considered good.
+const GOOD_RTT_MILLIS: f32 = 80.0;
+/// Mean rtt (in milliseconds) above which the connection is considered poor.
+const POOR_RTT_MILLIS: f32 = 200.0;
+const GOOD_PACKET_LOSS: f32 = 0.01;
+const POOR_PACKET_LOSS: f32 = 0.05;
+
+/// Counts what happens to the current player while they are connected to a
+/// server, to show a summary once they leave it. The counters survive
+/// rejoining after the app gets suspended.
+#[derive(Resource, Default)]
+pub struct SessionSummary {
+    current: Option<SessionCounters>,
+    /// The summary of the latest session, until it's shown by the main menu.
+    report: Option<SessionReport>,
+}
+
+#[derive(Default)]
+struct SessionCounters {
+    started_at: Option<Instant>,
+    server: Option<Server>,
+    level_id: Option<i64>,
+    level_title: Option<String>,
+    finishes: u32,
+    deaths: u32,
+    best_finish: Option<FinishResult>,
+    /// Indexed in the order of `Medal::ALL`.
+    medals: [u32; 3],
+    connection: ConnectionCounters,
+    server_stats: Option<SessionStats>,
+}
+
+#[derive(Default)]
+struct ConnectionCounters {
+    samples: u32,
+    rtt_millis_sum: f32,
+    max_rtt_millis: f32,
+    jitter_millis_sum: f32,
+    packet_loss_sum: f32,
+    overloaded_samples: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionReport {
+    /// Is `None` if the client didn't stay connected long enough to know.
+    pub server: Option<Server>,
+    /// Is `None` for levels that aren't stored by the persistence service.
+    pub level_id: Option<i64>,
+    pub level_title: Option<String>,
+    pub time_played: Duration,
+    pub finishes: u32,
+    pub deaths: u32,
+    pub best_finish: Option<FinishResult>,
+    pub medals: Vec<(Medal, u32)>,
+    pub connection: ConnectionQuality,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionQuality {
+    pub mean_rtt_millis: f32,
+    pub max_rtt_millis: f32,
+    pub mean_jitter_millis: f32,
+    pub mean_packet_loss: f32,
+    /// The share of the session during which the server was overloaded.
+    pub server_overloaded_share: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionRating {
+    Good,
+    Fair,
+    Poor,
+}
+
+impl std::fmt::Display for ConnectionRating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Self::Good => "Good",
+            Self::Fair => "Fair",
+            Self::Poor => "Poor",
+        };
+        f.write_str(label)
+    }
+}
+
+impl ConnectionQuality {
+    pub fn rating(&self) -> ConnectionRating {
+        if self.mean_rtt_millis > POOR_RTT_MILLIS || self.mean_packet_loss > POOR_PACKET_LOSS {
+            ConnectionRating::Poor
+        } else if self.mean_rtt_millis > GOOD_RTT_MILLIS || self.mean_packet_loss > GOOD_PACKET_LOSS
+        {
+            ConnectionRating::Fair
+        } else {
+            ConnectionRating::Good
+        }
+    }
+}
+
+impl SessionSummary {
+    pub fn is_tracking(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Takes the summary of the latest session, if it hasn't been taken yet.
+    pub fn take_report(&mut self) -> Option<SessionReport> {
+        self.report.take()
+    }
+
+    pub fn record_finish(&mut self, result: Option<FinishResult>) {
+        let Some(counters) = self.current.as_mut() else {
+            return;
+        };
+        counters.finishes += 1;
+        let Some(result) = result else {
+            return;
+        };
+        if counters
+            .best_finish
+            .map_or(true, |best_finish| result.frames < best_finish.frames)
+        {
+            counters.best_finish = Some(result);
+        }
+        if let Some(medal) = result.medal {
+            let i = Medal::ALL.iter().position(|m| *m == medal).unwrap();
+            counters.medals[i] += 1;
+        }
+    }
+
+    pub fn record_death(&mut self) {
+        if let Some(counters) = self.current.as_mut() {
+            counters.deaths += 1;
+        }
+    }
+
+    pub fn receive_server_stats(&mut self, stats: SessionStats) {
+        if let Some(counters) = self.current.as_mut() {
+            counters.server_stats = Some(stats);
+        }
+    }
+
+    fn start(&mut self) {
+        log::debug!("Starting tracking a session");
+        self.current = Some(SessionCounters {
+            started_at: Some(Instant::now()),
+            ..Default::default()
+        });
+    }
+
+    fn finish(&mut self) {
+        let Some(counters) = self.current.take() else {
+            return;
+        };
+        log::debug!("Finishing tracking a session");
+        self.report = Some(counters.into_report(Instant::now()));
+    }
+}
+
+impl SessionCounters {
+    fn sample_connection(&mut self, connection_state: &ConnectionState, server_overloaded: bool) {
+        let connection = &mut self.connection;
+        connection.samples += 1;
+        connection.rtt_millis_sum += connection_state.rtt_millis();
+        connection.max_rtt_millis = connection.max_rtt_millis.max(connection_state.rtt_millis());
+        connection.jitter_millis_sum += connection_state.jitter_millis();
+        connection.packet_loss_sum += connection_state.packet_loss();
+        connection.overloaded_samples += server_overloaded as u32;
+    }
+
+    /// Server counters are authoritative, but they don't cover the connections
+    /// that preceded rejoining, so the larger ones are kept.
+    fn into_report(self, finished_at: Instant) -> SessionReport {
+        let mut finishes = self.finishes;
+        let mut deaths = self.deaths;
+        let mut best_finish = self.best_finish;
+        if let Some(server_stats) = self.server_stats {
+            finishes = finishes.max(server_stats.finishes);
+            deaths = deaths.max(server_stats.deaths);
+            best_finish = match (best_finish, server_stats.best_finish) {
+                (Some(a), Some(b)) => Some(if b.frames < a.frames { b } else { a }),
+                (a, b) => a.or(b),
+            };
+        }
+
+        let samples = self.connection.samples.max(1) as f32;
+        SessionReport {
+            server: self.server,
+            level_id: self.level_id,
+            level_title: self.level_title,
+            time_played: self.started_at.map_or(Duration::ZERO, |started_at| {
+                finished_at.duration_since(started_at)
+            }),
+            finishes,
+            deaths,
+            best_finish,
+            medals: Medal::ALL
+                .into_iter()
+                .zip(self.medals)
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            connection: ConnectionQuality {
+                mean_rtt_millis: self.connection.rtt_millis_sum / samples,
+                max_rtt_millis: self.connection.max_rtt_millis,
+                mean_jitter_millis: self.connection.jitter_millis_sum / samples,
+                mean_packet_loss: self.connection.packet_loss_sum / samples,
+                server_overloaded_share: self.connection.overloaded_samples as f32 / samples,
+            },
+        }
+    }
+}
+
+/// A session starts once the client gets connected and ends once it leaves
+/// the server, unless it's going to rejoin it (after being suspended, for
+/// instance). Leaving a server brings the player back to the main menu, which
+/// shows the summary.
+pub fn track_session_system(
+    mut commands: Commands,
+    mut session_summary: ResMut<SessionSummary>,
+    connection_state: Res<ConnectionState>,
+    connected_server: Res<ConnectedServer>,
+    server_health: Res<ServerHealthReport>,
+    server_to_connect: Res<ServerToConnect>,
+    app_state: Res<CurrentState<AppState>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if matches!(connection_state.status(), ConnectionStatus::Connected) {
+        if !session_summary.is_tracking() {
+            session_summary.start();
+        }
+        let counters = session_summary.current.as_mut().unwrap();
+        if counters.server.is_none() {
+            counters.server = connected_server.server.clone();
+        }
+        counters.level_id = connected_server.level_id;
+        counters.level_title = connected_server.level_title.clone();
+        counters.sample_connection(&connection_state, server_health.is_overloaded());
+        return;
+    }
+
+    // Suspended clients don't tick, so the status stays the same until they
+    // resume and get a server to rejoin.
+    let is_rejoining = matches!(
+        connection_state.status(),
+        ConnectionStatus::Disconnecting(DisconnectReason::Suspended)
+    ) || server_to_connect.is_some();
+    if !session_summary.is_tracking() || is_rejoining {
+        return;
+    }
+    session_summary.finish();
+
+    if app_state.0 == AppState::Playing {
+        log::info!("Changing the app state to {:?}", AppState::MainMenu);
+        commands.insert_resource(NextState(AppState::MainMenu));
+        log::info!(
+            "Changing the game session state to {:?}",
+            GameSessionState::Loading
+        );
+        commands.insert_resource(NextState(GameSessionState::Loading));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finish(frames: u32, medal: Option<Medal>) -> FinishResult {
+        FinishResult { frames, medal }
+    }
+
+    #[test]
+    fn test_session_summary() {
+        let mut summary = SessionSummary::default();
+        // Nothing is counted outside of sessions.
+        summary.record_death();
+        summary.finish();
+        assert_eq!(summary.take_report(), None);
+
+        summary.start();
+        summary.record_finish(Some(finish(600, Some(Medal::Silver))));
+        summary.record_finish(Some(finish(500, Some(Medal::Gold))));
+        summary.record_finish(Some(finish(700, Some(Medal::Silver))));
+        summary.record_finish(None);
+        summary.record_death();
+        summary.finish();
+        assert!(!summary.is_tracking());
+
+        let report = summary.take_report().unwrap();
+        assert_eq!(report.finishes, 4);
+        assert_eq!(report.deaths, 1);
+        assert_eq!(report.best_finish, Some(finish(500, Some(Medal::Gold))));
+        assert_eq!(report.medals, vec![(Medal::Gold, 1), (Medal::Silver, 2)]);
+        assert_eq!(summary.take_report(), None);
+    }
+
+    #[test]
+    fn test_session_summary_merges_server_stats() {
+        let mut summary = SessionSummary::default();
+        summary.start();
+        summary.record_finish(Some(finish(600, None)));
+        summary.record_death();
+        summary.record_death();
+        // The server counters start from zero after rejoining.
+        summary.receive_server_stats(SessionStats {
+            finishes: 0,
+            deaths: 3,
+            best_finish: None,
+        });
+        summary.finish();
+
+        let report = summary.take_report().unwrap();
+        assert_eq!(report.finishes, 1);
+        assert_eq!(report.deaths, 3);
+        assert_eq!(report.best_finish, Some(finish(600, None)));
+    }
+
+    #[test]
+    fn test_connection_rating() {
+        let quality = |mean_rtt_millis, mean_packet_loss| ConnectionQuality {
+            mean_rtt_millis,
+            mean_packet_loss,
+            ..Default::default()
+        };
+        assert_eq!(quality(50.0, 0.0).rating(), ConnectionRating::Good);
+        assert_eq!(quality(120.0, 0.0).rating(), ConnectionRating::Fair);
+        assert_eq!(quality(50.0, 0.02).rating(), ConnectionRating::Fair);
+        assert_eq!(quality(250.0, 0.0).rating(), ConnectionRating::Poor);
+        assert_eq!(quality(50.0, 0.1).rating(), ConnectionRating::Poor);
+    }
+}
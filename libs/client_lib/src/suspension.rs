@@ -0,0 +1,143 @@
+use crate::net::{ConnectedServer, ServerToConnect};
+use bevy::{
+    ecs::{
+        event::EventReader,
+        system::{NonSendMut, Res, ResMut, Resource},
+    },
+    log,
+    utils::Instant,
+};
+use bevy_disturbulence::NetworkResource;
+use mr_messages_lib::Server;
+use mr_shared_lib::{
+    messages::{DisconnectReason, Message, ReliableClientMessage},
+    net::{ConnectionState, ConnectionStatus},
+};
+
+/// Is sent by platform-specific code when the app stops being rendered (a
+/// browser tab gets backgrounded, for instance) or becomes visible again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppVisibilityChanged {
+    Hidden,
+    Visible,
+}
+
+/// Browsers throttle backgrounded tabs to about an update per second, so a
+/// hidden client would fall further behind the server with every update, until
+/// it gets reset. Instead, the client stops ticking and leaves the server
+/// while hidden, and rejoins the same server once it's visible again.
+#[derive(Resource, Default)]
+pub struct AppSuspension {
+    suspended_at: Option<Instant>,
+    server_to_resume: Option<Server>,
+}
+
+impl AppSuspension {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    fn suspend(&mut self, connected_server: Option<Server>) {
+        if self.is_suspended() {
+            return;
+        }
+        self.suspended_at = Some(Instant::now());
+        self.server_to_resume = connected_server;
+    }
+
+    /// Returns the server to rejoin, if the client was connected to one.
+    fn resume(&mut self) -> Option<Server> {
+        let suspended_at = self.suspended_at.take()?;
+        log::info!(
+            "Resuming after being suspended for {:.1}s",
+            Instant::now().duration_since(suspended_at).as_secs_f32()
+        );
+        self.server_to_resume.take()
+    }
+}
+
+pub fn app_suspension_system(
+    mut visibility_events: EventReader<AppVisibilityChanged>,
+    mut suspension: ResMut<AppSuspension>,
+    mut net: NonSendMut<NetworkResource>,
+    mut connection_state: ResMut<ConnectionState>,
+    connected_server: Res<ConnectedServer>,
+    mut server_to_connect: ResMut<ServerToConnect>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for event in visibility_events.iter() {
+        match event {
+            AppVisibilityChanged::Hidden => {
+                if suspension.is_suspended() {
+                    continue;
+                }
+                log::info!("The app is hidden, suspending");
+                if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                    suspension.suspend(None);
+                    continue;
+                }
+
+                if let Some(&handle) = net.connections.keys().next() {
+                    if let Err(err) = net.send_message(
+                        handle,
+                        Message {
+                            session_id: connection_state.session_id,
+                            message: ReliableClientMessage::Suspend,
+                        },
+                    ) {
+                        log::error!("Failed to send Suspend message: {:?}", err);
+                    }
+                }
+                // The connection is reset by `maintain_connection_system` once the
+                // client starts ticking again.
+                connection_state
+                    .set_status(ConnectionStatus::Disconnecting(DisconnectReason::Suspended));
+                suspension.suspend(connected_server.server.clone());
+            }
+            AppVisibilityChanged::Visible => {
+                if let Some(server) = suspension.resume() {
+                    log::info!("Rejoining {}", server.name);
+                    **server_to_connect = Some(server);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        Server {
+            name: "server".to_owned(),
+            state: mr_messages_lib::GameServerState::Ready,
+            addr: "127.0.0.1:3455".parse().unwrap(),
+            player_capacity: 0,
+            player_count: 0,
+            request_id: Default::default(),
+            version: Default::default(),
+            draining: false,
+        }
+    }
+
+    #[test]
+    fn test_app_suspension() {
+        let mut suspension = AppSuspension::default();
+        assert_eq!(suspension.resume(), None);
+
+        suspension.suspend(Some(server()));
+        assert!(suspension.is_suspended());
+        // Repeated events don't overwrite the server to rejoin.
+        suspension.suspend(None);
+        assert_eq!(suspension.resume(), Some(server()));
+        assert!(!suspension.is_suspended());
+        assert_eq!(suspension.resume(), None);
+
+        suspension.suspend(None);
+        assert_eq!(suspension.resume(), None);
+        assert!(!suspension.is_suspended());
+    }
+}
@@ -0,0 +1,138 @@
+use bevy::{ecs::system::Resource, utils::Instant};
+use mr_shared_lib::messages::DeltaUpdate;
+use rand::Rng;
+use std::time::Duration;
+
+pub const MIN_TICK_RATE_FACTOR: f32 = 0.5;
+pub const MAX_TICK_RATE_FACTOR: f32 = 2.0;
+
+/// Skews the tick rate of the client and holds incoming delta updates back, so
+/// that clock syncing and the frames ahead logic can be exercised locally,
+/// without a bad network. Is controlled in the debug UI.
+#[derive(Resource)]
+pub struct TimeDilation {
+    /// Multiplies the tick rate picked by `control_ticking_speed_system`.
+    pub tick_rate_factor: f32,
+    /// Is added to the arrival time of every delta update.
+    pub delay: Duration,
+    /// A random duration up to this value is added to `delay` for each update,
+    /// which can make updates arrive out of order.
+    pub jitter: Duration,
+    delayed_updates: DelayedQueue<DeltaUpdate>,
+}
+
+impl Default for TimeDilation {
+    fn default() -> Self {
+        Self {
+            tick_rate_factor: 1.0,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            delayed_updates: DelayedQueue::default(),
+        }
+    }
+}
+
+impl TimeDilation {
+    pub fn dilate_tick_rate(&self, ticks_per_second: f32) -> f32 {
+        ticks_per_second
+            * self
+                .tick_rate_factor
+                .clamp(MIN_TICK_RATE_FACTOR, MAX_TICK_RATE_FACTOR)
+    }
+
+    /// Returns the update back if it doesn't need to be delayed.
+    pub fn delay_update(&mut self, update: DeltaUpdate, now: Instant) -> Option<DeltaUpdate> {
+        if self.delay.is_zero() && self.jitter.is_zero() {
+            return Some(update);
+        }
+        let jitter = rand::thread_rng().gen_range(Duration::ZERO..=self.jitter);
+        self.delayed_updates.push(update, now + self.delay + jitter);
+        None
+    }
+
+    pub fn release_updates(&mut self, now: Instant) -> Vec<DeltaUpdate> {
+        self.delayed_updates.release(now)
+    }
+
+    pub fn delayed_updates_count(&self) -> usize {
+        self.delayed_updates.items.len()
+    }
+
+    /// Updates that are already delayed are still released at their time.
+    pub fn reset_settings(&mut self) {
+        self.tick_rate_factor = 1.0;
+        self.delay = Duration::ZERO;
+        self.jitter = Duration::ZERO;
+    }
+
+    /// Delayed updates become invalid after reconnecting.
+    pub fn clear(&mut self) {
+        self.delayed_updates.items.clear();
+    }
+}
+
+struct DelayedQueue<T> {
+    items: Vec<(Instant, T)>,
+}
+
+impl<T> Default for DelayedQueue<T> {
+    fn default() -> Self {
+        Self { items: Vec::new() }
+    }
+}
+
+impl<T> DelayedQueue<T> {
+    fn push(&mut self, item: T, release_at: Instant) {
+        self.items.push((release_at, item));
+    }
+
+    /// Returns the items that are due, ordered by their release time.
+    fn release(&mut self, now: Instant) -> Vec<T> {
+        let mut released = Vec::new();
+        let mut i = 0;
+        while i < self.items.len() {
+            if self.items[i].0 <= now {
+                released.push(self.items.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        released.sort_by_key(|(release_at, _)| *release_at);
+        released.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delayed_queue_release() {
+        let start = Instant::now();
+        let millis = |value| start + Duration::from_millis(value);
+
+        let mut queue = DelayedQueue::default();
+        queue.push(1, millis(30));
+        queue.push(2, millis(10));
+        queue.push(3, millis(50));
+        queue.push(4, millis(20));
+
+        assert!(queue.release(start).is_empty());
+        assert_eq!(queue.release(millis(30)), vec![2, 4, 1]);
+        assert_eq!(queue.items.len(), 1);
+        assert_eq!(queue.release(millis(100)), vec![3]);
+        assert!(queue.items.is_empty());
+    }
+
+    #[test]
+    fn test_dilate_tick_rate() {
+        let mut time_dilation = TimeDilation::default();
+        assert_eq!(time_dilation.dilate_tick_rate(60.0), 60.0);
+
+        time_dilation.tick_rate_factor = 1.5;
+        assert_eq!(time_dilation.dilate_tick_rate(60.0), 90.0);
+
+        time_dilation.tick_rate_factor = 0.0;
+        assert_eq!(time_dilation.dilate_tick_rate(60.0), 30.0);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::{helpers::PlayerParams, input::PlayerRequestsQueue, ui::theme::spacing};
+use bevy::{
+    ecs::system::{Local, Res, ResMut, Resource},
+    utils::Instant,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_messages_lib::BAN_REASON_MAX_LEN;
+use mr_shared_lib::messages::{
+    AdminCommand, AdminPermissions, BanTarget, PlayerNetId, ADMIN_BROADCAST_MAX_LEN, ADMIN_CVARS,
+};
+use std::time::Duration;
+
+const ADMIN_BROADCAST_DISPLAY_SECS: u64 = 8;
+
+/// Messages from admins and shutdown notices from the server, each one is
+/// shown for `ADMIN_BROADCAST_DISPLAY_SECS`.
+#[derive(Resource, Default)]
+pub struct AdminBroadcasts {
+    messages: Vec<(String, Instant)>,
+}
+
+impl AdminBroadcasts {
+    pub fn push(&mut self, message: String) {
+        self.messages.push((message, Instant::now()));
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        self.messages.retain(|(_, received_at)| {
+            now.duration_since(*received_at) < Duration::from_secs(ADMIN_BROADCAST_DISPLAY_SECS)
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct AdminUiState {
+    target_player: Option<PlayerNetId>,
+    ban_hours: String,
+    ban_reason: String,
+    unban_target: String,
+    cvar_name: Option<&'static str>,
+    cvar_value: String,
+    broadcast_message: String,
+}
+
+/// Lists only the commands that the current player is allowed to run. The
+/// server checks the permissions anyway.
+pub fn admin_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut admin_ui_state: Local<AdminUiState>,
+    admin_permissions: Res<AdminPermissions>,
+    player_params: PlayerParams,
+    mut player_requests: ResMut<PlayerRequestsQueue>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !admin_permissions.any() {
+        return;
+    }
+    let admin_ui_state = &mut *admin_ui_state;
+
+    egui::Window::new("Admin")
+        .collapsible(true)
+        .default_open(false)
+        .resizable(false)
+        .anchor(
+            egui::Align2::LEFT_BOTTOM,
+            egui::Vec2::new(spacing::SCREEN_EDGE, -spacing::SCREEN_EDGE),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            if admin_permissions.kick || admin_permissions.ban {
+                let mut players = player_params
+                    .players
+                    .iter()
+                    .filter(|(net_id, player)| {
+                        player.is_connected
+                            && !player.is_bot
+                            && Some(**net_id) != player_params.current_player_net_id.0
+                    })
+                    .collect::<Vec<_>>();
+                players.sort_by_key(|(net_id, _)| net_id.0);
+                if admin_ui_state.target_player.map_or(false, |target_player| {
+                    !players.iter().any(|(net_id, _)| **net_id == target_player)
+                }) {
+                    admin_ui_state.target_player = None;
+                }
+
+                ui.horizontal(|ui| {
+                    let selected_text = admin_ui_state
+                        .target_player
+                        .and_then(|net_id| player_params.players.get(&net_id))
+                        .map_or_else(
+                            || "Select a player".to_owned(),
+                            |player| player.nickname.clone(),
+                        );
+                    egui::containers::ComboBox::from_id_source("admin_target_player")
+                        .width(160.0)
+                        .selected_text(selected_text)
+                        .show_ui(ui, |ui| {
+                            for (net_id, player) in &players {
+                                ui.selectable_value(
+                                    &mut admin_ui_state.target_player,
+                                    Some(**net_id),
+                                    player.nickname.as_str(),
+                                );
+                            }
+                        });
+                    let kick_button = egui::Button::new("Kick");
+                    if admin_permissions.kick
+                        && ui
+                            .add_enabled(admin_ui_state.target_player.is_some(), kick_button)
+                            .clicked()
+                    {
+                        let net_id = admin_ui_state.target_player.take().unwrap();
+                        player_requests
+                            .admin_commands
+                            .push(AdminCommand::Kick(net_id));
+                    }
+                });
+            }
+
+            if admin_permissions.ban {
+                let duration_secs = parse_ban_duration(&admin_ui_state.ban_hours);
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut admin_ui_state.ban_hours)
+                            .hint_text("Hours")
+                            .desired_width(50.0),
+                    );
+                    ui.add(
+                        egui::TextEdit::singleline(&mut admin_ui_state.ban_reason)
+                            .hint_text("Reason")
+                            .char_limit(BAN_REASON_MAX_LEN)
+                            .desired_width(140.0),
+                    );
+                    let ban_button = egui::Button::new("Ban");
+                    let can_ban = admin_ui_state.target_player.is_some() && duration_secs.is_some();
+                    if ui.add_enabled(can_ban, ban_button).clicked() {
+                        let net_id = admin_ui_state.target_player.take().unwrap();
+                        player_requests.admin_commands.push(AdminCommand::Ban {
+                            target: BanTarget::Player(net_id),
+                            duration_secs: duration_secs.unwrap(),
+                            reason: std::mem::take(&mut admin_ui_state.ban_reason),
+                        });
+                    }
+                });
+
+                let unban_target = parse_ban_target(&admin_ui_state.unban_target);
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut admin_ui_state.unban_target)
+                            .hint_text("User id or IP range")
+                            .desired_width(200.0),
+                    );
+                    let unban_button = egui::Button::new("Unban");
+                    if ui
+                        .add_enabled(unban_target.is_some(), unban_button)
+                        .clicked()
+                    {
+                        admin_ui_state.unban_target.clear();
+                        player_requests
+                            .admin_commands
+                            .push(AdminCommand::Unban(unban_target.unwrap()));
+                    }
+                });
+            }
+
+            if admin_permissions.pause || admin_permissions.reload {
+                ui.horizontal(|ui| {
+                    if admin_permissions.pause {
+                        if ui.button("Pause").clicked() {
+                            player_requests
+                                .admin_commands
+                                .push(AdminCommand::Pause(true));
+                        }
+                        if ui.button("Resume").clicked() {
+                            player_requests
+                                .admin_commands
+                                .push(AdminCommand::Pause(false));
+                        }
+                    }
+                    if admin_permissions.reload && ui.button("Reload files").clicked() {
+                        player_requests.admin_commands.push(AdminCommand::Reload);
+                    }
+                });
+            }
+
+            if admin_permissions.cvars {
+                ui.horizontal(|ui| {
+                    egui::containers::ComboBox::from_id_source("admin_cvar_name")
+                        .width(160.0)
+                        .selected_text(admin_ui_state.cvar_name.unwrap_or("Select a setting"))
+                        .show_ui(ui, |ui| {
+                            for cvar in ADMIN_CVARS {
+                                ui.selectable_value(
+                                    &mut admin_ui_state.cvar_name,
+                                    Some(cvar),
+                                    cvar,
+                                );
+                            }
+                        });
+                    ui.add(
+                        egui::TextEdit::singleline(&mut admin_ui_state.cvar_value)
+                            .desired_width(80.0),
+                    );
+                    let set_button = egui::Button::new("Set");
+                    let can_set = admin_ui_state.cvar_name.is_some()
+                        && !admin_ui_state.cvar_value.trim().is_empty();
+                    if ui.add_enabled(can_set, set_button).clicked() {
+                        player_requests.admin_commands.push(AdminCommand::SetCvar {
+                            name: admin_ui_state.cvar_name.unwrap().to_owned(),
+                            value: admin_ui_state.cvar_value.trim().to_owned(),
+                        });
+                    }
+                });
+            }
+
+            if admin_permissions.broadcast {
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut admin_ui_state.broadcast_message)
+                            .hint_text("Message to everyone")
+                            .char_limit(ADMIN_BROADCAST_MAX_LEN)
+                            .desired_width(200.0),
+                    );
+                    let send_button = egui::Button::new("Broadcast");
+                    if ui
+                        .add_enabled(
+                            !admin_ui_state.broadcast_message.trim().is_empty(),
+                            send_button,
+                        )
+                        .clicked()
+                    {
+                        let message = std::mem::take(&mut admin_ui_state.broadcast_message);
+                        player_requests
+                            .admin_commands
+                            .push(AdminCommand::Broadcast(message));
+                    }
+                });
+            }
+        });
+}
+
+/// An empty field means a permanent ban.
+fn parse_ban_duration(hours: &str) -> Option<Option<u64>> {
+    let hours = hours.trim();
+    if hours.is_empty() {
+        return Some(None);
+    }
+    let hours = hours.parse::<f64>().ok().filter(|hours| *hours > 0.0)?;
+    Some(Some((hours * 3600.0) as u64))
+}
+
+/// Numbers are treated as user ids, the server validates IP ranges.
+fn parse_ban_target(value: &str) -> Option<BanTarget> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(match value.parse::<i64>() {
+        Ok(user_id) => BanTarget::User(user_id),
+        Err(_) => BanTarget::IpRange(value.to_owned()),
+    })
+}
+
+pub fn admin_broadcasts_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut admin_broadcasts: ResMut<AdminBroadcasts>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    admin_broadcasts.forget_expired(Instant::now());
+    if admin_broadcasts.messages.is_empty() {
+        return;
+    }
+
+    egui::Window::new("Admin message")
+        .collapsible(false)
+        .resizable(false)
+        .title_bar(false)
+        .anchor(
+            egui::Align2::CENTER_TOP,
+            egui::Vec2::new(0.0, spacing::SCREEN_EDGE),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            for (message, _) in &admin_broadcasts.messages {
+                ui.label(egui::RichText::new(message).strong());
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_broadcasts_expire() {
+        let mut admin_broadcasts = AdminBroadcasts::default();
+        admin_broadcasts.push("Hello".to_owned());
+        let received_at = admin_broadcasts.messages[0].1;
+
+        admin_broadcasts.forget_expired(received_at + Duration::from_secs(1));
+        assert_eq!(admin_broadcasts.messages.len(), 1);
+
+        admin_broadcasts
+            .forget_expired(received_at + Duration::from_secs(ADMIN_BROADCAST_DISPLAY_SECS));
+        assert!(admin_broadcasts.messages.is_empty());
+    }
+
+    #[test]
+    fn test_parse_ban_duration() {
+        assert_eq!(parse_ban_duration(""), Some(None));
+        assert_eq!(parse_ban_duration(" 24 "), Some(Some(24 * 3600)));
+        assert_eq!(parse_ban_duration("0.5"), Some(Some(1800)));
+        assert_eq!(parse_ban_duration("0"), None);
+        assert_eq!(parse_ban_duration("-1"), None);
+        assert_eq!(parse_ban_duration("forever"), None);
+    }
+
+    #[test]
+    fn test_parse_ban_target() {
+        assert_eq!(parse_ban_target(" "), None);
+        assert_eq!(parse_ban_target("42"), Some(BanTarget::User(42)));
+        assert_eq!(
+            parse_ban_target("10.0.0.0/8"),
+            Some(BanTarget::IpRange("10.0.0.0/8".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! The context of a report (the level, the frame, connection stats and the
+//! latest log lines) is captured when the dialog is opened, as that's usually
+//! right after a player has noticed a bug.
+
+use crate::{
+    input::{ActionInput, InputAction},
+    logging::recent_logs,
+    net::{
+        ConnectedServer, MainMenuUiChannels, MatchmakerState, PersistenceRequest,
+        SubmittedBugReport,
+    },
+};
+use bevy::ecs::system::{Res, ResMut, Resource, SystemParam};
+use bevy_egui::{egui, EguiContext};
+use iyes_loopless::state::CurrentState;
+use mr_messages_lib::{
+    validate_bug_report_description, BugReportConnectionStats, PostBugReportRequest,
+    BUG_REPORT_DESCRIPTION_MAX_LEN,
+};
+use mr_shared_lib::{
+    net::{ConnectionState, ConnectionStatus, MessageId},
+    GameSessionState, SimulationTime,
+};
+use std::marker::PhantomData;
+use tokio::sync::mpsc::UnboundedSender;
+
+const ERROR_COLOR: egui::Color32 = egui::Color32::RED;
+
+#[derive(Resource, Default)]
+pub struct BugReportUiState {
+    show: bool,
+    description: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    screenshot_path: String,
+    /// Everything except the description, is captured when the dialog is
+    /// opened.
+    context: Option<PostBugReportRequest>,
+    request_id_counter: MessageId,
+    status: Option<BugReportStatus>,
+}
+
+#[derive(Debug)]
+pub enum BugReportStatus {
+    InProgress,
+    Submitted(SubmittedBugReport),
+    Failed(String),
+}
+
+impl BugReportUiState {
+    fn open(&mut self, context: PostBugReportRequest) {
+        self.show = true;
+        self.context = Some(context);
+        // The description is kept if a player closes the dialog without
+        // submitting it.
+        if !matches!(self.status, Some(BugReportStatus::InProgress)) {
+            self.status = None;
+        }
+    }
+
+    fn submit(
+        &mut self,
+        id_token: String,
+        persistence_request_tx: &UnboundedSender<PersistenceRequest>,
+    ) {
+        let description = match validate_bug_report_description(&self.description) {
+            Ok(description) => description.to_owned(),
+            Err(err) => {
+                self.status = Some(BugReportStatus::Failed(err.to_string()));
+                return;
+            }
+        };
+        let screenshot = match self.read_screenshot() {
+            Ok(screenshot) => screenshot,
+            Err(err) => {
+                self.status = Some(BugReportStatus::Failed(err));
+                return;
+            }
+        };
+        let Some(context) = self.context.clone() else {
+            return;
+        };
+
+        let request_id = self.request_id_counter.increment();
+        persistence_request_tx
+            .send(PersistenceRequest::SubmitBugReport {
+                request_id,
+                id_token,
+                body: PostBugReportRequest {
+                    description,
+                    ..context
+                },
+                screenshot,
+            })
+            .expect("Failed to write to a channel (persistence request)");
+        self.status = Some(BugReportStatus::InProgress);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_screenshot(&self) -> Result<Option<Vec<u8>>, String> {
+        let path = self.screenshot_path.trim();
+        if path.is_empty() {
+            return Ok(None);
+        }
+        let data = std::fs::read(path).map_err(|err| err.to_string())?;
+        mr_messages_lib::validate_bug_report_screenshot(&data).map_err(|err| err.to_string())?;
+        Ok(Some(data))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn read_screenshot(&self) -> Result<Option<Vec<u8>>, String> {
+        Ok(None)
+    }
+
+    pub fn receive_response(&mut self, result: Result<SubmittedBugReport, String>) {
+        self.status = Some(match result {
+            Ok(report) => {
+                self.description.clear();
+                #[cfg(not(target_arch = "wasm32"))]
+                self.screenshot_path.clear();
+                BugReportStatus::Submitted(report)
+            }
+            Err(error) => BugReportStatus::Failed(error),
+        });
+    }
+}
+
+#[derive(SystemParam)]
+pub struct BugReportParams<'w, 's> {
+    connected_server: Res<'w, ConnectedServer>,
+    connection_state: Res<'w, ConnectionState>,
+    time: Res<'w, SimulationTime>,
+    game_state: Res<'w, CurrentState<GameSessionState>>,
+    matchmaker_state: Option<Res<'w, MatchmakerState>>,
+    main_menu_ui_channels: Option<Res<'w, MainMenuUiChannels>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> BugReportParams<'w, 's> {
+    fn capture_context(&self) -> PostBugReportRequest {
+        let is_playing = self.game_state.0 != GameSessionState::Loading;
+        let is_connected = matches!(self.connection_state.status(), ConnectionStatus::Connected);
+        PostBugReportRequest {
+            description: String::new(),
+            level_id: self.connected_server.level_id,
+            frame_number: is_playing.then(|| self.time.player_frame.value()),
+            connection_stats: is_connected.then(|| BugReportConnectionStats {
+                rtt_millis: self.connection_state.rtt_millis(),
+                packet_loss: self.connection_state.packet_loss(),
+                jitter_millis: self.connection_state.jitter_millis(),
+            }),
+            logs: recent_logs(),
+        }
+    }
+
+    /// Submitting reports requires a signed-in user.
+    fn persistence_access(&self) -> Option<(String, &UnboundedSender<PersistenceRequest>)> {
+        let id_token = self.matchmaker_state.as_ref()?.id_token.clone()?;
+        let main_menu_ui_channels = self.main_menu_ui_channels.as_ref()?;
+        Some((id_token, &main_menu_ui_channels.persistence_request_tx))
+    }
+}
+
+/// Only the desktop client can attach screenshots, as it's the only one that
+/// can read files at the moment.
+pub fn bug_report_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut state: ResMut<BugReportUiState>,
+    action_input: ActionInput,
+    params: BugReportParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if action_input.just_pressed(InputAction::ReportBug) {
+        if state.show {
+            state.show = false;
+        } else {
+            state.open(params.capture_context());
+        }
+    }
+
+    if !state.show {
+        return;
+    }
+
+    let state = &mut *state;
+    let mut show = state.show;
+    let title = format!(
+        "Report a bug [{}]",
+        action_input.key_bindings.label(InputAction::ReportBug)
+    );
+    egui::Window::new(title)
+        .id(egui::Id::new("bug_report"))
+        .open(&mut show)
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let Some((id_token, persistence_request_tx)) = params.persistence_access() else {
+                ui.label("Sign in to report bugs");
+                return;
+            };
+
+            ui.label("What happened?");
+            ui.add(
+                egui::TextEdit::multiline(&mut state.description)
+                    .desired_rows(6)
+                    .char_limit(BUG_REPORT_DESCRIPTION_MAX_LEN),
+            );
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.label("Screenshot (PNG, optional)");
+                ui.text_edit_singleline(&mut state.screenshot_path);
+            });
+            #[cfg(target_arch = "wasm32")]
+            ui.label("Attaching screenshots is available in the desktop client");
+
+            if let Some(context) = &state.context {
+                egui::CollapsingHeader::new("Attached context")
+                    .default_open(false)
+                    .show(ui, |ui| context_ui(ui, context));
+            }
+
+            let is_submitting = matches!(state.status, Some(BugReportStatus::InProgress));
+            if ui
+                .add_enabled(!is_submitting, egui::Button::new("Submit"))
+                .clicked()
+            {
+                state.submit(id_token, persistence_request_tx);
+            }
+
+            match &state.status {
+                Some(BugReportStatus::InProgress) => {
+                    ui.label("Submitting...");
+                }
+                Some(BugReportStatus::Submitted(report)) => {
+                    ui.label(format!("Thanks! Submitted report #{}", report.id));
+                    if let Some(error) = &report.screenshot_error {
+                        ui.colored_label(
+                            ERROR_COLOR,
+                            format!("The screenshot wasn't attached: {error}"),
+                        );
+                    }
+                }
+                Some(BugReportStatus::Failed(error)) => {
+                    ui.colored_label(ERROR_COLOR, error);
+                }
+                None => {}
+            }
+        });
+    state.show = show;
+}
+
+fn context_ui(ui: &mut egui::Ui, context: &PostBugReportRequest) {
+    let level = context
+        .level_id
+        .map_or_else(|| "none".to_owned(), |level_id| level_id.to_string());
+    ui.label(format!("Level: {level}"));
+    let frame = context.frame_number.map_or_else(
+        || "none".to_owned(),
+        |frame_number| frame_number.to_string(),
+    );
+    ui.label(format!("Frame: {frame}"));
+    match context.connection_stats {
+        Some(stats) => ui.label(format!(
+            "RTT: {:.0}ms, packet loss: {:.1}%, jitter: {:.0}ms",
+            stats.rtt_millis,
+            stats.packet_loss * 100.0,
+            stats.jitter_millis
+        )),
+        None => ui.label("Not connected to a server"),
+    };
+    ui.label(format!("Log lines: {}", context.logs.len()));
+}
@@ -1,45 +1,100 @@
 use crate::{
-    helpers::{MouseEntityPicker, PlayerParams},
-    input::{LevelObjectRequestsQueue, MouseScreenPosition, MouseWorldPosition},
-    ui::widgets::sortable::{sortable_list, ListItem},
-    LevelObjectCorrelations,
+    audio_cues::{AudioClipRequestParams, AudioClipUploadStatus},
+    edit_history::LevelEditHistory,
+    helpers::{world_to_window_pos, MouseEntityPicker, PlayerParams},
+    input::{
+        LevelObjectRequestsQueue, MouseScreenPosition, MouseWorldPosition, PlayerRequestsQueue,
+    },
+    level_publishing::LevelPublishing,
+    net::ConnectedServer,
+    offline_editing::OfflineEditing,
+    ui::{
+        collision_preview::{draw_collision_preview_system, CollisionPreview},
+        layout::UiLayout,
+        route_preview::{draw_route_preview_system, route_preview_ui_system},
+        selection::{
+            draw_selection_system, objects_in_box, rotate_objects, translate_objects, GroupDrag,
+            SelectionBox,
+        },
+        terrain_brush::{terrain_brush_system, TerrainBrush, MAX_BRUSH_RADIUS, MIN_BRUSH_RADIUS},
+        widgets::{
+            numeric_field::NumericField,
+            sortable::{sortable_list, ListItem},
+        },
+    },
+    LevelObjectCorrelations, MainCameraEntity,
 };
 use bevy::{
     ecs::{
         entity::Entity,
         event::{EventReader, EventWriter},
-        query::WorldQuery,
+        query::{With, WorldQuery},
         schedule::{IntoSystemDescriptor, ShouldRun, SystemSet},
         system::{Local, Query, Res, ResMut, Resource, SystemParam},
     },
     input::{mouse::MouseButton, Input},
     log,
     math::Vec2,
-    transform::components::Transform,
+    render::{
+        camera::{Camera, CameraProjection, Projection},
+        color::Color,
+    },
+    transform::components::{GlobalTransform, Transform},
     utils::HashMap,
+    window::Windows,
 };
 use bevy_egui::{
     egui::{self, Ui},
-    EguiContext,
+    EguiContext, EguiSettings,
+};
+use mr_messages_lib::{
+    validation::{
+        validate_language_code, validate_level_localizations, LEVEL_DESCRIPTION_MAX_LEN,
+        LEVEL_LOCALIZATIONS_MAX_COUNT, LEVEL_OBJECT_LABEL_MAX_LEN, LEVEL_TITLE_MAX_LEN,
+    },
+    PLAYER_CAPACITY,
 };
 use mr_shared_lib::{
+    client::assets::{
+        CUBE_COLOR, CUBE_DEATH_COLOR, PLANE_COLOR, PLANE_DEATH_COLOR, PLANE_FINISH_COLOR,
+    },
     framebuffer::FrameNumber,
     game::{
+        client_factories::VisibilitySettings,
         components::{
             LevelObjectLabel, LevelObjectStaticGhostChild, LevelObjectStaticGhostParent, Spawned,
         },
+        emitter::{
+            EmitterDesc, EmitterPattern, EMITTER_MAX_PERIOD_FRAMES, EMITTER_MAX_REACH,
+            EMITTER_MIN_PERIOD_FRAMES, PROJECTILE_MAX_SPEED,
+        },
+        jump_pad::{JumpPad, JUMP_PAD_MAX_COOLDOWN_FRAMES, JUMP_PAD_MIN_COOLDOWN_FRAMES},
         level::{
-            CollisionLogic, LevelObject, LevelObjectDesc, LevelState, ObjectRoute, ObjectRouteDesc,
+            validate_spawnable_area, validate_spawnable_area_change, CollisionLogic,
+            LevelLocalization, LevelObject, LevelObjectDesc, LevelSettings, LevelState,
+            LevelValidationError, Medal, MedalTimes, MusicTrack, ObjectRoute, ObjectRouteDesc,
+            RespawnSettings,
+        },
+        level_objects::{
+            color_difference, route_length, AnnotationDesc, AnnotationKind, CameraAnchorDesc,
+            CubeDesc, DeathZoneDesc, FinishZoneDesc, ObjectAppearance, PlaneDesc, PlaneFormDesc,
+            RoutePointDesc, CAMERA_ANCHOR_MAX_DURATION_SECS, CAMERA_ANCHOR_MIN_DURATION_SECS,
+            ZONE_MIN_SIZE,
         },
-        level_objects::{CubeDesc, PlaneDesc, PlaneFormDesc, RoutePointDesc},
+        polygon::BrushOperation,
         spawn::{iter_spawned_read_only, SpawnedQuery, SpawnedQueryReadOnlyItem},
     },
-    messages::{EntityNetId, SpawnLevelObjectRequest, SpawnLevelObjectRequestBody},
+    messages::{
+        EntityNetId, InvalidLevelObjectShape, LevelCheckSeverity, PublishLevelRequest,
+        PublishLevelStatus, RespawnPlayerReason, SpawnLevelObjectRequest,
+        SpawnLevelObjectRequestBody,
+    },
     net::MessageId,
     player::PlayerRole,
     registry::EntityRegistry,
     SimulationTime, SIMULATIONS_PER_SECOND,
 };
+use std::{collections::BTreeMap, marker::PhantomData, ops::RangeInclusive};
 
 pub const DEFAULT_PLANE_CIRCLE_RADIUS: f32 = 10.0;
 pub const DEFAULT_PLANE_RECTANGLE_SIZE: [f32; 2] = [10.0, 10.0];
@@ -51,6 +106,21 @@ pub const DEFAULT_PLANE_CONCAVE_POINTS: &[[f32; 2]] = &[
     [-10.0, 5.0],
 ];
 
+pub const DEFAULT_MEASUREMENT_END: [f32; 2] = [5.0, 0.0];
+pub const DEFAULT_REGION_SIZE: [f32; 2] = [5.0, 5.0];
+pub const DEFAULT_CAMERA_ANCHOR_DURATION_SECS: f32 = 2.0;
+pub const DEFAULT_ZONE_SIZE: [f32; 2] = [2.0, 2.0];
+/// Pasted objects are shifted by this offset, so that they don't overlap
+/// with the copied one (or with the previously pasted copy).
+pub const PASTE_OFFSET: [f32; 2] = [1.0, -1.0];
+
+const ANNOTATION_COLOR: egui::Color32 = egui::Color32::from_rgb(242, 217, 77);
+const WARNING_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 165, 0);
+/// Death and finish objects with colors that are closer than this (in delta E)
+/// to the colors they can be confused with get a warning.
+const MIN_READABLE_COLOR_DIFFERENCE: f32 = 25.0;
+const ANNOTATION_STROKE_WIDTH: f32 = 2.0;
+
 pub fn default_period() -> FrameNumber {
     FrameNumber::new(SIMULATIONS_PER_SECOND as u16 * 10)
 }
@@ -61,6 +131,12 @@ pub struct EditedLevelObject {
     pub dragged_control_point_index: Option<usize>,
     pub is_being_placed: bool,
     pub is_draggable: bool,
+    /// Objects that group transforms are applied to, includes the edited
+    /// `object` (if there's any).
+    pub selection: Vec<EntityNetId>,
+    pub selection_box: Option<SelectionBox>,
+    /// Is kept when the object gets deselected or despawned.
+    pub clipboard: Option<LevelObjectDesc>,
 }
 
 impl EditedLevelObject {
@@ -69,6 +145,35 @@ impl EditedLevelObject {
         self.dragged_control_point_index = None;
         self.is_being_placed = false;
         self.is_draggable = false;
+        self.selection.clear();
+        self.selection_box = None;
+    }
+
+    /// Selects a single object, dropping the rest of the selection.
+    pub fn select(&mut self, entity: Entity, level_object: LevelObject) {
+        self.selection = vec![level_object.net_id];
+        self.object = Some((entity, level_object));
+    }
+
+    /// Returns `false` if there's no object selected.
+    pub fn copy(&mut self) -> bool {
+        match &self.object {
+            Some((_, level_object)) => {
+                self.clipboard = Some(level_object.desc.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the copied object moved by [`PASTE_OFFSET`]. The clipboard
+    /// is moved as well, so pasting several times lays out a row of copies.
+    pub fn paste(&mut self) -> Option<LevelObjectDesc> {
+        let desc = self.clipboard.as_mut()?;
+        if let Some(position) = desc.position_mut() {
+            *position += Vec2::from(PASTE_OFFSET);
+        }
+        Some(desc.clone())
     }
 }
 
@@ -87,10 +192,131 @@ pub struct LevelObjects<'w, 's> {
     pending_correlation: Local<'s, Option<MessageId>>,
     edited_level_object: ResMut<'w, EditedLevelObject>,
     requests_queue: ResMut<'w, LevelObjectRequestsQueue>,
+    edit_history: ResMut<'w, LevelEditHistory>,
     level_state: Res<'w, LevelState>,
     entity_registry: Res<'w, EntityRegistry<EntityNetId>>,
     query: Query<'w, 's, SpawnedQuery<LevelObjectQuery>>,
     ghosts_query: Query<'w, 's, (&'static LevelObjectStaticGhostParent, &'static Transform)>,
+    terrain_brush: ResMut<'w, TerrainBrush>,
+    level_save_errors: Res<'w, LevelSaveErrors>,
+}
+
+impl<'w, 's> LevelObjects<'w, 's> {
+    fn undo(&mut self, correlations: &mut LevelObjectCorrelations) {
+        self.edit_history
+            .undo(&self.level_state, &mut self.requests_queue, correlations);
+    }
+
+    fn redo(&mut self, correlations: &mut LevelObjectCorrelations) {
+        self.edit_history
+            .redo(&self.level_state, &mut self.requests_queue, correlations);
+    }
+
+    /// The pasted object gets selected once the server confirms spawning it.
+    fn paste(&mut self, correlations: &mut LevelObjectCorrelations) {
+        let Some(desc) = self.edited_level_object.paste() else {
+            return;
+        };
+        let correlation_id = correlations.next_correlation_id();
+        *self.pending_correlation = Some(correlation_id);
+        self.requests_queue
+            .spawn_requests
+            .push(SpawnLevelObjectRequest {
+                correlation_id,
+                body: SpawnLevelObjectRequestBody::New(desc),
+            });
+    }
+
+    fn duplicate(&mut self, correlations: &mut LevelObjectCorrelations) {
+        if self.edited_level_object.copy() {
+            self.paste(correlations);
+        }
+    }
+
+    fn selected_objects(&self) -> Vec<LevelObject> {
+        let edited_object = self
+            .edited_level_object
+            .object
+            .as_ref()
+            .map(|(_, level_object)| level_object);
+        self.edited_level_object
+            .selection
+            .iter()
+            .filter_map(|net_id| match edited_object {
+                // The edited object may have changes that the server hasn't confirmed yet.
+                Some(edited_object) if edited_object.net_id == *net_id => {
+                    Some(edited_object.clone())
+                }
+                _ => self.level_state.object(*net_id).cloned(),
+            })
+            .collect()
+    }
+
+    /// Pushes the updates as a single batch, keeping the edited object in
+    /// sync with them.
+    fn update_selected_objects(&mut self, level_objects: Vec<LevelObject>) {
+        for level_object in level_objects {
+            if let Some((_, edited_object)) = self
+                .edited_level_object
+                .object
+                .as_mut()
+                .filter(|(_, edited_object)| edited_object.net_id == level_object.net_id)
+            {
+                *edited_object = level_object.clone();
+            }
+            self.requests_queue.update_requests.push(level_object);
+        }
+    }
+
+    fn despawn_selected_objects(&mut self) {
+        self.requests_queue
+            .despawn_requests
+            .extend(self.edited_level_object.selection.iter().copied());
+        self.edited_level_object.deselect();
+    }
+
+    /// Replaces the selection with the objects inside the box, or adds them
+    /// to it if `extend` is `true`.
+    fn select_in_box(&mut self, selection_box: SelectionBox, corner: Vec2, extend: bool) {
+        let net_ids = objects_in_box(
+            self.level_state.objects().values(),
+            selection_box.start,
+            corner,
+        );
+        let selection = &mut self.edited_level_object.selection;
+        if !extend {
+            selection.clear();
+        }
+        for net_id in net_ids {
+            if !selection.contains(&net_id) {
+                selection.push(net_id);
+            }
+        }
+        let is_edited_object_selected =
+            self.edited_level_object
+                .object
+                .as_ref()
+                .map_or(false, |(_, level_object)| {
+                    self.edited_level_object
+                        .selection
+                        .contains(&level_object.net_id)
+                });
+        if !is_edited_object_selected {
+            self.edit_first_selected_object();
+        }
+    }
+
+    /// Is called when the edited object drops out of the selection.
+    fn edit_first_selected_object(&mut self) {
+        let selection = std::mem::take(&mut self.edited_level_object.selection);
+        self.edited_level_object.deselect();
+        self.edited_level_object.object = selection.first().and_then(|net_id| {
+            self.entity_registry
+                .get_entity(*net_id)
+                .zip(self.level_state.object(*net_id).cloned())
+        });
+        self.edited_level_object.selection = selection;
+    }
 }
 
 #[derive(SystemParam)]
@@ -101,12 +327,41 @@ pub struct MouseInput<'w, 's, Q: Send + Sync + 'static, F: Send + Sync + 'static
     pub mouse_button_input: Res<'w, Input<MouseButton>>,
 }
 
+#[derive(SystemParam)]
+pub struct BuilderViewSettings<'w, 's> {
+    visibility_settings: ResMut<'w, VisibilitySettings>,
+    ui_layout: Res<'w, UiLayout>,
+    collision_preview: ResMut<'w, CollisionPreview>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
 #[derive(Default)]
 pub struct BuilderUiState {
     select_edited_level_object_filter: String,
     route_point_filter: String,
+    /// The edited copy is overwritten only when the settings on the server
+    /// change, otherwise sliders would jump back while being dragged.
+    synced_level_settings: Option<LevelSettings>,
+    dirty_level_settings: LevelSettings,
+    /// The server rejects edits that make spawn areas too small, so they
+    /// aren't sent, and the reason is displayed instead.
+    rejected_edit: Option<LevelValidationError>,
+    new_localization_language: String,
+    selection_offset: Vec2,
+    selection_angle_degrees: f32,
 }
 
+/// Objects that the server has despawned because their collider shapes
+/// couldn't be calculated. They are listed until the builder dismisses them.
+#[derive(Resource, Default)]
+pub struct InvalidLevelObjectShapes(pub Vec<InvalidLevelObjectShape>);
+
+/// Problems that have made the server reject the latest autosave of the level,
+/// empty if the level gets saved.
+#[derive(Resource, Default)]
+pub struct LevelSaveErrors(pub Vec<LevelValidationError>);
+
 pub struct EditedObjectUpdate {
     pub old: Entity,
     pub new: Entity,
@@ -117,6 +372,16 @@ pub fn builder_system_set() -> SystemSet {
         .with_run_criteria(builder_run_criteria)
         .with_system(builder_ui_system)
         .with_system(process_builder_mouse_input_system.after(builder_ui_system))
+        .with_system(terrain_brush_system.after(builder_ui_system))
+        .with_system(audio_clips_ui_system)
+        .with_system(offline_editing_ui_system)
+        .with_system(level_publishing_ui_system)
+        .with_system(invalid_level_object_shapes_ui_system)
+        .with_system(draw_annotations_system.after(process_builder_mouse_input_system))
+        .with_system(draw_selection_system.after(process_builder_mouse_input_system))
+        .with_system(route_preview_ui_system)
+        .with_system(draw_route_preview_system.after(route_preview_ui_system))
+        .with_system(draw_collision_preview_system.after(builder_ui_system))
 }
 
 pub fn builder_run_criteria(
@@ -146,11 +411,20 @@ pub fn builder_ui_system(
     mut level_object_correlations: ResMut<LevelObjectCorrelations>,
     mut level_objects: LevelObjects,
     mut object_update: EventWriter<EditedObjectUpdate>,
+    mut view_settings: BuilderViewSettings,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
     let ctx = egui_context.ctx_mut();
 
+    let level_settings = level_objects.level_state.settings();
+    if !ctx.is_using_pointer()
+        && builder_ui_state.synced_level_settings.as_ref() != Some(level_settings)
+    {
+        builder_ui_state.synced_level_settings = Some(level_settings.clone());
+        builder_ui_state.dirty_level_settings = level_settings.clone();
+    }
+
     // Picking a level object if we received a confirmation from the server about an
     // object created by us.
     if let Some(correlation_id) = *level_objects.pending_correlation {
@@ -160,14 +434,11 @@ pub fn builder_ui_system(
                 .object
                 .as_ref()
                 .map(|(entity, _)| *entity);
-            level_objects.edited_level_object.object =
-                level_objects.entity_registry.get_entity(entity_net_id).zip(
-                    level_objects
-                        .level_state
-                        .objects
-                        .get(&entity_net_id)
-                        .cloned(),
-                );
+            level_objects.edited_level_object.object = level_objects
+                .entity_registry
+                .get_entity(entity_net_id)
+                .zip(level_objects.level_state.object(entity_net_id).cloned());
+            level_objects.edited_level_object.selection = vec![entity_net_id];
             if let Some((new_entity, edited_level_object)) =
                 &level_objects.edited_level_object.object
             {
@@ -187,6 +458,40 @@ pub fn builder_ui_system(
         }
     }
 
+    // Text fields have undo of their own.
+    if !ctx.wants_keyboard_input() {
+        let (undo_pressed, redo_pressed, copy_pressed, paste_pressed, duplicate_pressed) = {
+            let input = ctx.input();
+            let command = input.modifiers.command;
+            let z_pressed = command && input.key_pressed(egui::Key::Z);
+            (
+                z_pressed && !input.modifiers.shift,
+                z_pressed && input.modifiers.shift,
+                command && input.key_pressed(egui::Key::C),
+                command && input.key_pressed(egui::Key::V),
+                command && input.key_pressed(egui::Key::D),
+            )
+        };
+        if undo_pressed {
+            level_objects.undo(&mut level_object_correlations);
+        } else if redo_pressed {
+            level_objects.redo(&mut level_object_correlations);
+        } else if copy_pressed {
+            level_objects.edited_level_object.copy();
+        } else if paste_pressed {
+            level_objects.paste(&mut level_object_correlations);
+        } else if duplicate_pressed {
+            level_objects.duplicate(&mut level_object_correlations);
+        }
+    }
+
+    // Selected objects can be despawned by other builders.
+    let level_state = &level_objects.level_state;
+    level_objects
+        .edited_level_object
+        .selection
+        .retain(|net_id| level_state.object(*net_id).is_some());
+
     if level_objects.edited_level_object.object.is_some() {
         // When an object is updated, it may get re-spawned as a new entity. We need to
         // update the picked entity in such a case. Despawns may happen as well.
@@ -214,8 +519,7 @@ pub fn builder_ui_system(
                     if !ctx.is_using_pointer() {
                         *level_object = level_objects
                             .level_state
-                            .objects
-                            .get(&edited_object_net_id)
+                            .object(edited_object_net_id)
                             .cloned()
                             .unwrap();
                     }
@@ -234,7 +538,47 @@ pub fn builder_ui_system(
         }
     }
 
-    egui::Window::new("Builder menu").show(ctx, |ui| {
+    let builder_menu = egui::Window::new("Builder menu")
+        .min_width(view_settings.ui_layout.active().side_panel_width);
+    builder_menu.show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            let can_undo = level_objects.edit_history.can_undo();
+            if ui
+                .add_enabled(can_undo, egui::Button::new("Undo [Ctrl+Z]"))
+                .clicked()
+            {
+                level_objects.undo(&mut level_object_correlations);
+            }
+            let can_redo = level_objects.edit_history.can_redo();
+            if ui
+                .add_enabled(can_redo, egui::Button::new("Redo [Ctrl+Shift+Z]"))
+                .clicked()
+            {
+                level_objects.redo(&mut level_object_correlations);
+            }
+        });
+        ui.horizontal(|ui| {
+            let is_selected = level_objects.edited_level_object.object.is_some();
+            if ui
+                .add_enabled(is_selected, egui::Button::new("Copy [Ctrl+C]"))
+                .clicked()
+            {
+                level_objects.edited_level_object.copy();
+            }
+            let can_paste = level_objects.edited_level_object.clipboard.is_some();
+            if ui
+                .add_enabled(can_paste, egui::Button::new("Paste [Ctrl+V]"))
+                .clicked()
+            {
+                level_objects.paste(&mut level_object_correlations);
+            }
+            if ui
+                .add_enabled(is_selected, egui::Button::new("Duplicate [Ctrl+D]"))
+                .clicked()
+            {
+                level_objects.duplicate(&mut level_object_correlations);
+            }
+        });
         ui.label("Create new object:");
         ui.horizontal_wrapped(|ui| {
             if ui.button("Plane").clicked() {
@@ -251,6 +595,8 @@ pub fn builder_ui_system(
                                 size: DEFAULT_PLANE_RECTANGLE_SIZE.into(),
                             },
                             is_spawn_area: false,
+                            appearance: Default::default(),
+                            jump_pad: None,
                         })),
                     });
             }
@@ -265,6 +611,7 @@ pub fn builder_ui_system(
                         body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::Cube(CubeDesc {
                             position: mouse_input.mouse_world_position.0,
                             size: 0.4,
+                            appearance: Default::default(),
                         })),
                     });
             }
@@ -283,9 +630,173 @@ pub fn builder_ui_system(
                         )),
                     });
             }
+            if ui.button("Camera anchor").clicked() {
+                let correlation_id = level_object_correlations.next_correlation_id();
+                *level_objects.pending_correlation = Some(correlation_id);
+                // New anchors are appended to the end of the intro.
+                let order = level_objects
+                    .level_state
+                    .objects()
+                    .values()
+                    .filter_map(|level_object| match &level_object.desc {
+                        LevelObjectDesc::CameraAnchor(camera_anchor) => {
+                            Some(camera_anchor.order.saturating_add(1))
+                        }
+                        _ => None,
+                    })
+                    .max()
+                    .unwrap_or(0);
+                level_objects
+                    .requests_queue
+                    .spawn_requests
+                    .push(SpawnLevelObjectRequest {
+                        correlation_id,
+                        body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::CameraAnchor(
+                            CameraAnchorDesc {
+                                position: mouse_input.mouse_world_position.0,
+                                order,
+                                duration_secs: DEFAULT_CAMERA_ANCHOR_DURATION_SECS,
+                            },
+                        )),
+                    });
+            }
+            if ui.button("Emitter").clicked() {
+                let correlation_id = level_object_correlations.next_correlation_id();
+                *level_objects.pending_correlation = Some(correlation_id);
+                level_objects
+                    .requests_queue
+                    .spawn_requests
+                    .push(SpawnLevelObjectRequest {
+                        correlation_id,
+                        body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::Emitter(
+                            EmitterDesc {
+                                position: mouse_input.mouse_world_position.0,
+                                ..Default::default()
+                            },
+                        )),
+                    });
+            }
+            if ui.button("Death zone").clicked() {
+                let correlation_id = level_object_correlations.next_correlation_id();
+                *level_objects.pending_correlation = Some(correlation_id);
+                level_objects
+                    .requests_queue
+                    .spawn_requests
+                    .push(SpawnLevelObjectRequest {
+                        correlation_id,
+                        body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::DeathZone(
+                            DeathZoneDesc {
+                                position: mouse_input.mouse_world_position.0,
+                                size: DEFAULT_ZONE_SIZE.into(),
+                            },
+                        )),
+                    });
+            }
+            if ui.button("Finish zone").clicked() {
+                let correlation_id = level_object_correlations.next_correlation_id();
+                *level_objects.pending_correlation = Some(correlation_id);
+                level_objects
+                    .requests_queue
+                    .spawn_requests
+                    .push(SpawnLevelObjectRequest {
+                        correlation_id,
+                        body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::FinishZone(
+                            FinishZoneDesc {
+                                position: mouse_input.mouse_world_position.0,
+                                size: DEFAULT_ZONE_SIZE.into(),
+                            },
+                        )),
+                    });
+            }
+        });
+        ui.label("Create new annotation:");
+        ui.horizontal_wrapped(|ui| {
+            let annotation_kinds = [
+                AnnotationKind::Measurement {
+                    end: DEFAULT_MEASUREMENT_END.into(),
+                },
+                AnnotationKind::Note {
+                    text: "Note".to_owned(),
+                },
+                AnnotationKind::Region {
+                    size: DEFAULT_REGION_SIZE.into(),
+                },
+            ];
+            for kind in annotation_kinds {
+                if ui.button(kind.to_string()).clicked() {
+                    let correlation_id = level_object_correlations.next_correlation_id();
+                    *level_objects.pending_correlation = Some(correlation_id);
+                    level_objects
+                        .requests_queue
+                        .spawn_requests
+                        .push(SpawnLevelObjectRequest {
+                            correlation_id,
+                            body: SpawnLevelObjectRequestBody::New(LevelObjectDesc::Annotation(
+                                AnnotationDesc {
+                                    position: mouse_input.mouse_world_position.0,
+                                    kind,
+                                },
+                            )),
+                        });
+                }
+            }
         });
 
+        ui.checkbox(
+            &mut view_settings.visibility_settings.collision_outlines,
+            "Show simplified collision outlines",
+        );
+        ui.checkbox(
+            &mut view_settings.visibility_settings.annotations,
+            "Show annotations",
+        );
+        ui.checkbox(
+            &mut view_settings.visibility_settings.spawn_areas,
+            "Show spawn areas",
+        );
+        if let Err(err) =
+            validate_spawnable_area(level_objects.level_state.spawnable_area(), PLAYER_CAPACITY)
+        {
+            ui.colored_label(WARNING_COLOR, format!("Warning: {err}"));
+        }
+        if !level_objects.level_save_errors.0.is_empty() {
+            ui.colored_label(WARNING_COLOR, "The level isn't saved:");
+            for err in &level_objects.level_save_errors.0 {
+                ui.colored_label(WARNING_COLOR, format!("- {err}"));
+            }
+        }
+
         ui.separator();
+        ui.collapsing("Level settings", |ui| {
+            let BuilderUiState {
+                dirty_level_settings,
+                new_localization_language,
+                ..
+            } = &mut *builder_ui_state;
+            let level_settings = dirty_level_settings.clone();
+            level_settings_ui(ui, dirty_level_settings);
+            ui.separator();
+            level_localizations_ui(
+                ui,
+                &mut dirty_level_settings.localizations,
+                new_localization_language,
+            );
+            // Invalid medal times, delays or localizations would be rejected by the server
+            // anyway.
+            let has_valid_settings = dirty_level_settings
+                .medal_times
+                .map_or(true, |medal_times| medal_times.is_valid())
+                && dirty_level_settings.respawns.is_valid()
+                && validate_level_localizations(localization_fields(
+                    &dirty_level_settings.localizations,
+                ))
+                .is_ok();
+            if level_settings != *dirty_level_settings && has_valid_settings {
+                level_objects.requests_queue.settings_update_request =
+                    Some(dirty_level_settings.clone());
+            }
+        });
+
         ui.collapsing("Select object to edit", |ui| {
             if let Some(entity) = level_objects_filter(
                 ui,
@@ -296,24 +807,56 @@ pub fn builder_ui_system(
                 let entity_net_id = level_objects.entity_registry.get_id(entity).unwrap();
                 let level_object = level_objects
                     .level_state
-                    .objects
-                    .get(&entity_net_id)
+                    .object(entity_net_id)
                     .unwrap()
                     .clone();
-                level_objects.edited_level_object.object = Some((entity, level_object));
+                level_objects
+                    .edited_level_object
+                    .select(entity, level_object);
             }
         });
 
+        if level_objects.edited_level_object.selection.len() > 1 {
+            selection_ui(ui, &mut builder_ui_state, &mut level_objects);
+        }
+
         if let Some((_, level_object)) = level_objects.edited_level_object.object.clone() {
             let mut dirty_level_object = level_object.clone();
+            let background_color = level_objects.level_state.settings().clear_color;
             level_object_ui(
                 &mut level_objects.requests_queue,
                 ui,
                 &level_object,
                 &mut dirty_level_object,
+                background_color,
             );
 
-            if dirty_level_object.desc.position().is_some() {
+            if dirty_level_object.desc.is_simulated() {
+                let mut is_preview_enabled = view_settings
+                    .collision_preview
+                    .is_enabled(level_object.net_id);
+                if ui
+                    .checkbox(&mut is_preview_enabled, "Preview collisions")
+                    .on_hover_text("Highlights where recent player paths overlap the collider")
+                    .changed()
+                {
+                    view_settings
+                        .collision_preview
+                        .set_enabled(level_object.net_id, is_preview_enabled);
+                }
+            }
+
+            if let LevelObjectDesc::Plane(PlaneDesc {
+                form_desc: PlaneFormDesc::Concave { .. },
+                ..
+            }) = &dirty_level_object.desc
+            {
+                terrain_brush_ui(ui, &mut level_objects.terrain_brush);
+            }
+
+            if dirty_level_object.desc.position().is_some()
+                && dirty_level_object.desc.is_simulated()
+            {
                 route_settings(
                     ui,
                     &mut builder_ui_state,
@@ -322,8 +865,23 @@ pub fn builder_ui_system(
                 );
             }
 
+            if let Some(err) = &builder_ui_state.rejected_edit {
+                ui.colored_label(WARNING_COLOR, format!("The change is not applied: {err}"));
+            }
+
             if level_object != dirty_level_object {
                 assert_eq!(level_object.net_id, dirty_level_object.net_id);
+                if let Err(err) = validate_spawnable_area_change(
+                    level_objects.level_state.spawnable_area(),
+                    level_objects
+                        .level_state
+                        .spawnable_area_after(level_object.net_id, Some(&dirty_level_object)),
+                    PLAYER_CAPACITY,
+                ) {
+                    builder_ui_state.rejected_edit = Some(err);
+                    return;
+                }
+                builder_ui_state.rejected_edit = None;
                 level_objects
                     .requests_queue
                     .update_requests
@@ -348,6 +906,7 @@ pub fn process_builder_mouse_input_system(
     mut mouse_input: MouseInput<(), ()>,
     mut level_objects: LevelObjects,
     mut object_update: EventReader<EditedObjectUpdate>,
+    mut group_drag: Local<Option<GroupDrag>>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -362,27 +921,50 @@ pub fn process_builder_mouse_input_system(
         });
     }
 
+    // The brush takes over the mouse, objects can still be selected via the menu.
+    if level_objects.terrain_brush.mode.is_some() {
+        return;
+    }
+
     // Picking a level object with a mouse.
     if !egui_context.ctx_mut().wants_pointer_input() {
         mouse_input.mouse_entity_picker.process_input(&mut None);
     }
+    let is_shift_pressed = egui_context.ctx_mut().input().modifiers.shift;
 
     let mut is_being_dragged = false;
+    let was_being_placed = level_objects.edited_level_object.is_being_placed;
     // If we have a newly placed object, move it with a cursor, until left mouse
     // button is clicked.
     if let EditedLevelObject {
         object: Some((_, level_object)),
+        selection,
         is_being_placed,
         is_draggable,
         ..
     } = &mut *level_objects.edited_level_object
     {
         is_being_dragged = *is_draggable && mouse_input.mouse_entity_picker.state().is_dragged;
+        if !is_being_dragged {
+            *group_drag = None;
+        }
         if *is_being_placed || is_being_dragged {
+            let net_id = level_object.net_id;
             let object_position = level_object
                 .desc
                 .position_mut()
                 .expect("Objects without positions aren't supported yet");
+            if is_being_dragged && group_drag.is_none() {
+                let level_state = &level_objects.level_state;
+                *group_drag = Some(GroupDrag::new(
+                    *object_position,
+                    selection
+                        .iter()
+                        .filter(|selected_net_id| **selected_net_id != net_id)
+                        .filter_map(|selected_net_id| level_state.object(*selected_net_id).cloned())
+                        .collect(),
+                ));
+            }
             if (*object_position - mouse_input.mouse_world_position.0).length_squared()
                 > f32::EPSILON
             {
@@ -397,6 +979,13 @@ pub fn process_builder_mouse_input_system(
                         route: level_object.route.clone(),
                         collision_logic: level_object.collision_logic,
                     });
+                // The rest of the selected objects follow the dragged one.
+                if let Some(group_drag) = &*group_drag {
+                    level_objects
+                        .requests_queue
+                        .update_requests
+                        .extend(group_drag.moved_objects(mouse_input.mouse_world_position.0));
+                }
             }
         }
 
@@ -444,8 +1033,7 @@ pub fn process_builder_mouse_input_system(
             .and_then(|(entity, entity_net_id)| {
                 level_objects
                     .level_state
-                    .objects
-                    .get(&entity_net_id)
+                    .object(entity_net_id)
                     .map(|level_object| (entity, entity_net_id, level_object.clone()))
             })
         {
@@ -468,6 +1056,32 @@ pub fn process_builder_mouse_input_system(
             level_objects.edited_level_object.is_draggable =
                 edited_level_object.desc.is_movable_with_mouse()
                     && (edited_level_object.route.is_none() || is_ghost || matches_ghost_position);
+            let net_id = edited_level_object.net_id;
+            let is_just_clicked = mouse_input
+                .mouse_button_input
+                .just_pressed(MouseButton::Left);
+            let is_selected = level_objects
+                .edited_level_object
+                .selection
+                .contains(&net_id);
+            if is_just_clicked && is_shift_pressed && is_selected {
+                // Shift+clicking a selected object removes it from the selection.
+                level_objects
+                    .edited_level_object
+                    .selection
+                    .retain(|selected_net_id| *selected_net_id != net_id);
+                if matches!(&level_objects.edited_level_object.object, Some((_, level_object)) if level_object.net_id == net_id)
+                {
+                    level_objects.edit_first_selected_object();
+                }
+                mouse_input.mouse_entity_picker.reset();
+                return;
+            }
+            if is_just_clicked && is_shift_pressed {
+                level_objects.edited_level_object.selection.push(net_id);
+            } else if !is_selected {
+                level_objects.edited_level_object.selection = vec![net_id];
+            }
             // We don't reset edited state if the clicked object is the same.
             if !matches!(level_objects.edited_level_object.object, Some((picked_entity, _)) if picked_entity == entity)
             {
@@ -476,6 +1090,215 @@ pub fn process_builder_mouse_input_system(
             }
         }
     }
+
+    // Dragging a selection box from an empty spot.
+    if mouse_input
+        .mouse_button_input
+        .just_pressed(MouseButton::Left)
+        && mouse_input.mouse_entity_picker.picked_entity().is_none()
+        && !was_being_placed
+        && !egui_context.ctx_mut().is_pointer_over_area()
+    {
+        level_objects.edited_level_object.selection_box = Some(SelectionBox::new(
+            mouse_input.mouse_world_position.0,
+            mouse_input.mouse_screen_position.0,
+        ));
+    }
+    if !mouse_input.mouse_button_input.pressed(MouseButton::Left) {
+        if let Some(selection_box) = level_objects.edited_level_object.selection_box.take() {
+            if selection_box.is_dragged(mouse_input.mouse_screen_position.0) {
+                level_objects.select_in_box(
+                    selection_box,
+                    mouse_input.mouse_world_position.0,
+                    is_shift_pressed,
+                );
+            }
+        }
+    }
+}
+
+#[derive(SystemParam)]
+pub struct OverlayCameraParams<'w, 's> {
+    windows: Res<'w, Windows>,
+    egui_settings: Res<'w, EguiSettings>,
+    main_camera_entity: Res<'w, MainCameraEntity>,
+    cameras: Query<'w, 's, (&'static GlobalTransform, &'static Projection), With<Camera>>,
+}
+
+impl<'w, 's> OverlayCameraParams<'w, 's> {
+    pub fn world_to_egui_pos(&self, world_position: Vec2) -> Option<egui::Pos2> {
+        let window = self.windows.get_primary()?;
+        let (camera_transform, camera_projection) =
+            self.cameras.get(self.main_camera_entity.0).ok()?;
+        let window_pos = world_to_window_pos(
+            world_position.extend(0.0),
+            window,
+            &camera_transform.compute_matrix(),
+            &camera_projection.get_projection_matrix(),
+        )?;
+        let scale_factor = self.egui_settings.scale_factor as f32;
+        Some(egui::Pos2::new(
+            window_pos.x / scale_factor,
+            window_pos.y / scale_factor,
+        ))
+    }
+}
+
+/// Annotations don't have meshes (except for their anchors), we draw them in
+/// the world space with egui.
+pub fn draw_annotations_system(
+    mut egui_context: ResMut<EguiContext>,
+    visibility_settings: Res<VisibilitySettings>,
+    level_state: Res<LevelState>,
+    edited_level_object: Res<EditedLevelObject>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !visibility_settings.annotations {
+        return;
+    }
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    let stroke = egui::Stroke::new(ANNOTATION_STROKE_WIDTH, ANNOTATION_COLOR);
+    let font_id = egui::FontId::proportional(14.0);
+
+    for level_object in level_state.objects().values() {
+        // The edited object may have changes that the server hasn't confirmed yet.
+        let level_object = match &edited_level_object.object {
+            Some((_, edited_level_object)) if edited_level_object.net_id == level_object.net_id => {
+                edited_level_object
+            }
+            _ => level_object,
+        };
+        let LevelObjectDesc::Annotation(AnnotationDesc { position, kind }) = &level_object.desc
+        else {
+            continue;
+        };
+
+        match kind {
+            AnnotationKind::Measurement { end } => {
+                let Some((start_pos, end_pos)) = overlay_camera_params
+                    .world_to_egui_pos(*position)
+                    .zip(overlay_camera_params.world_to_egui_pos(*position + *end))
+                else {
+                    continue;
+                };
+                painter.line_segment([start_pos, end_pos], stroke);
+                painter.circle_filled(end_pos, ANNOTATION_STROKE_WIDTH * 2.0, ANNOTATION_COLOR);
+                painter.text(
+                    start_pos + (end_pos - start_pos) / 2.0,
+                    egui::Align2::CENTER_BOTTOM,
+                    format_distance(end.length()),
+                    font_id.clone(),
+                    ANNOTATION_COLOR,
+                );
+            }
+            AnnotationKind::Note { text } => {
+                let Some(pos) = overlay_camera_params.world_to_egui_pos(*position) else {
+                    continue;
+                };
+                painter.text(
+                    pos,
+                    egui::Align2::CENTER_BOTTOM,
+                    text,
+                    font_id.clone(),
+                    ANNOTATION_COLOR,
+                );
+            }
+            AnnotationKind::Region { size } => {
+                let half_size = *size / 2.0;
+                let corners = [
+                    Vec2::new(-half_size.x, -half_size.y),
+                    Vec2::new(half_size.x, -half_size.y),
+                    Vec2::new(half_size.x, half_size.y),
+                    Vec2::new(-half_size.x, half_size.y),
+                ]
+                .into_iter()
+                .map(|corner| overlay_camera_params.world_to_egui_pos(*position + corner))
+                .collect::<Option<Vec<_>>>();
+                let Some(corners) = corners else {
+                    continue;
+                };
+                let label_pos = corners[3];
+                painter.add(egui::Shape::convex_polygon(
+                    corners,
+                    ANNOTATION_COLOR.linear_multiply(0.15),
+                    stroke,
+                ));
+                painter.text(
+                    label_pos,
+                    egui::Align2::LEFT_BOTTOM,
+                    &level_object.label,
+                    font_id.clone(),
+                    ANNOTATION_COLOR,
+                );
+            }
+        }
+    }
+}
+
+fn selection_ui(
+    ui: &mut Ui,
+    builder_ui_state: &mut BuilderUiState,
+    level_objects: &mut LevelObjects,
+) {
+    let selection_len = level_objects.edited_level_object.selection.len();
+    ui.collapsing(format!("Selection ({selection_len} objects)"), |ui| {
+        egui::Grid::new("selection").striped(true).show(ui, |ui| {
+            ui.label("Offset");
+            ui.horizontal(|ui| {
+                let offset = &mut builder_ui_state.selection_offset;
+                NumericField::new(&mut offset.x, "selection offset x").show(ui);
+                NumericField::new(&mut offset.y, "selection offset y").show(ui);
+                if ui.button("Move").clicked() {
+                    let mut selected_objects = level_objects.selected_objects();
+                    translate_objects(&mut selected_objects, *offset);
+                    level_objects.update_selected_objects(selected_objects);
+                }
+            });
+            ui.end_row();
+
+            ui.label("Angle (degrees)");
+            ui.horizontal(|ui| {
+                let angle_degrees = &mut builder_ui_state.selection_angle_degrees;
+                ui.add(
+                    egui::widgets::DragValue::new(angle_degrees)
+                        .speed(1.0)
+                        .clamp_range(-180.0..=180.0),
+                );
+                if ui
+                    .button("Rotate")
+                    .on_hover_text("Rotates the objects around the center of the selection")
+                    .clicked()
+                {
+                    let mut selected_objects = level_objects.selected_objects();
+                    rotate_objects(&mut selected_objects, *angle_degrees);
+                    level_objects.update_selected_objects(selected_objects);
+                }
+            });
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Despawn").clicked() {
+                level_objects.despawn_selected_objects();
+            }
+            if ui
+                .button("Clear selection")
+                .on_hover_text("Keeps only the edited object selected")
+                .clicked()
+            {
+                let edited_level_object = &mut *level_objects.edited_level_object;
+                edited_level_object.selection = edited_level_object
+                    .object
+                    .iter()
+                    .map(|(_, level_object)| level_object.net_id)
+                    .collect();
+            }
+        });
+    });
 }
 
 fn level_object_ui(
@@ -483,20 +1306,24 @@ fn level_object_ui(
     ui: &mut Ui,
     level_object: &LevelObject,
     dirty_level_object: &mut LevelObject,
+    background_color: [f32; 3],
 ) {
     ui.separator();
     egui::Grid::new("editing_edited_level_object.object")
         .striped(true)
         .show(ui, |ui| {
             ui.label("Object label");
-            ui.text_edit_singleline(&mut dirty_level_object.label);
+            ui.add(
+                egui::TextEdit::singleline(&mut dirty_level_object.label)
+                    .char_limit(LEVEL_OBJECT_LABEL_MAX_LEN),
+            );
             ui.end_row();
 
             if let Some(pos) = dirty_level_object.desc.position_mut() {
                 ui.label("Position");
                 ui.horizontal(|ui| {
-                    ui.add(egui::widgets::DragValue::new(&mut pos.x).speed(0.1));
-                    ui.add(egui::widgets::DragValue::new(&mut pos.y).speed(0.1));
+                    NumericField::new(&mut pos.x, "position x").show(ui);
+                    NumericField::new(&mut pos.y, "position y").show(ui);
                 });
                 ui.end_row();
             }
@@ -504,21 +1331,66 @@ fn level_object_ui(
             match &mut dirty_level_object.desc {
                 LevelObjectDesc::Cube(CubeDesc { size, .. }) => {
                     ui.label("Size");
-                    ui.add(egui::widgets::DragValue::new(size).speed(0.01));
+                    NumericField::new(size, "cube size")
+                        .step(0.05)
+                        .clamp_range(0.01..=f32::MAX)
+                        .show(ui);
                     ui.end_row();
                 }
                 LevelObjectDesc::Plane(PlaneDesc { form_desc, .. }) => {
                     plane_form(ui, form_desc);
                 }
                 LevelObjectDesc::RoutePoint(_) => {}
-            }
-
-            ui.label("Actions");
-            ui.horizontal(|ui| {
-                if ui.button("Despawn").clicked() {
-                    level_object_requests
-                        .despawn_requests
-                        .push(level_object.net_id);
+                LevelObjectDesc::Annotation(AnnotationDesc { kind, .. }) => {
+                    annotation_kind(ui, kind);
+                }
+                LevelObjectDesc::CameraAnchor(CameraAnchorDesc {
+                    order,
+                    duration_secs,
+                    ..
+                }) => {
+                    ui.label("Intro order");
+                    ui.add(egui::widgets::DragValue::new(order).speed(0.1));
+                    ui.end_row();
+
+                    ui.label("Duration (seconds)");
+                    ui.add(
+                        egui::widgets::DragValue::new(duration_secs)
+                            .speed(0.05)
+                            .clamp_range(
+                                CAMERA_ANCHOR_MIN_DURATION_SECS..=CAMERA_ANCHOR_MAX_DURATION_SECS,
+                            ),
+                    );
+                    ui.end_row();
+                }
+                LevelObjectDesc::Emitter(dirty_emitter) => {
+                    emitter(ui, dirty_emitter);
+                }
+                LevelObjectDesc::DeathZone(DeathZoneDesc { size, .. })
+                | LevelObjectDesc::FinishZone(FinishZoneDesc { size, .. }) => {
+                    ui.label("Size");
+                    ui.horizontal(|ui| {
+                        ui.label("Width:");
+                        NumericField::new(&mut size.x, "zone width")
+                            .step(0.1)
+                            .clamp_range(ZONE_MIN_SIZE..=f32::MAX)
+                            .show(ui);
+                        ui.label("Height:");
+                        NumericField::new(&mut size.y, "zone height")
+                            .step(0.1)
+                            .clamp_range(ZONE_MIN_SIZE..=f32::MAX)
+                            .show(ui);
+                    });
+                    ui.end_row();
+                }
+            }
+
+            ui.label("Actions");
+            ui.horizontal(|ui| {
+                if ui.button("Despawn").clicked() {
+                    level_object_requests
+                        .despawn_requests
+                        .push(level_object.net_id);
                 }
             });
             ui.end_row();
@@ -533,6 +1405,8 @@ fn level_object_ui(
                 } else {
                     plane.is_spawn_area = false;
                 }
+
+                jump_pad(ui, &mut plane.jump_pad);
             }
 
             let mut possible_collision_logic = dirty_level_object.desc.possible_collision_logic();
@@ -544,7 +1418,32 @@ fn level_object_ui(
                 ui.end_row();
             }
 
-            if dirty_level_object.desc.position().is_some() {
+            let default_color = default_object_color(dirty_level_object);
+            let collision_logic = dirty_level_object.collision_logic;
+            let regular_object_color = if let LevelObjectDesc::Cube(_) = dirty_level_object.desc {
+                CUBE_COLOR
+            } else {
+                PLANE_COLOR
+            };
+            if let Some(appearance) = dirty_level_object.desc.appearance_mut() {
+                object_appearance(ui, appearance, default_color);
+                if let Some(color) = appearance.color {
+                    for warning in appearance_warnings(
+                        color,
+                        regular_object_color,
+                        collision_logic,
+                        background_color,
+                    ) {
+                        ui.label("");
+                        ui.colored_label(WARNING_COLOR, warning);
+                        ui.end_row();
+                    }
+                }
+            }
+
+            if dirty_level_object.desc.position().is_some()
+                && dirty_level_object.desc.is_simulated()
+            {
                 ui.label("Route type");
                 route_type(ui, dirty_level_object);
                 ui.end_row();
@@ -561,15 +1460,12 @@ fn level_object_ui(
                         }
 
                         ui.label("Period (frames)");
-                        ui.add(
-                            egui::widgets::DragValue::new(&mut route.period)
-                                .speed(0.1)
-                                .clamp_range(
-                                    (SIMULATIONS_PER_SECOND as u16)
-                                        .max(route.start_frame_offset.value() + 1)
-                                        ..=SIMULATIONS_PER_SECOND as u16 * 60,
-                                ),
-                        );
+                        let range = period_range(route);
+                        // The period is derived in the route settings if there's a desired
+                        // speed.
+                        ui.add_enabled_ui(route.desired_speed.is_none(), |ui| {
+                            frames_field(ui, &mut route.period, "route period", range);
+                        });
                         ui.end_row();
 
                         ui.label("Period (second)");
@@ -580,12 +1476,12 @@ fn level_object_ui(
                         ui.end_row();
 
                         ui.label("Start offset (frames)");
-                        ui.add(
-                            egui::widgets::DragValue::new(&mut route.start_frame_offset)
-                                .speed(0.1)
-                                .clamp_range(
-                                    FrameNumber::new(0)..=route.period - FrameNumber::new(1),
-                                ),
+                        let max_offset = route.period.value() - 1;
+                        frames_field(
+                            ui,
+                            &mut route.start_frame_offset,
+                            "route start offset",
+                            0..=max_offset,
                         );
                     } else {
                         // Attached and Radial route types actually behave the same, we
@@ -600,6 +1496,669 @@ fn level_object_ui(
         });
 }
 
+fn level_settings_ui(ui: &mut egui::Ui, dirty_level_settings: &mut LevelSettings) {
+    egui::Grid::new("editing_level_settings")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Background color");
+            // Egui color pickers work with linear colors.
+            let [r, g, b] = dirty_level_settings.clear_color;
+            let [r, g, b, _] = Color::rgb(r, g, b).as_linear_rgba_f32();
+            let mut linear_color = [r, g, b];
+            if ui.color_edit_button_rgb(&mut linear_color).changed() {
+                let [r, g, b] = linear_color;
+                let [r, g, b, _] = Color::rgb_linear(r, g, b).as_rgba_f32();
+                dirty_level_settings.clear_color = [r, g, b];
+            }
+            ui.end_row();
+
+            ui.label("Light illuminance");
+            ui.add(
+                egui::widgets::Slider::new(
+                    &mut dirty_level_settings.light_illuminance,
+                    0.0..=50000.0,
+                )
+                .suffix(" lx"),
+            );
+            ui.end_row();
+
+            ui.label("Light angle");
+            ui.add(
+                egui::widgets::Slider::new(&mut dirty_level_settings.light_angle, 0.0..=90.0)
+                    .suffix("°"),
+            );
+            ui.end_row();
+
+            ui.label("Music");
+            egui::containers::ComboBox::from_id_source("level_music_track")
+                .width(200.0)
+                .selected_text(
+                    dirty_level_settings
+                        .music_track
+                        .map_or_else(|| "None".to_owned(), |track| track.to_string()),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut dirty_level_settings.music_track, None, "None");
+                    for track in MusicTrack::ALL {
+                        ui.selectable_value(
+                            &mut dirty_level_settings.music_track,
+                            Some(track),
+                            track.to_string(),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            let mut has_medal_times = dirty_level_settings.medal_times.is_some();
+            ui.label("Medals");
+            if ui.checkbox(&mut has_medal_times, "Award medals").changed() {
+                dirty_level_settings.medal_times = has_medal_times.then(MedalTimes::default);
+            }
+            ui.end_row();
+
+            if let Some(medal_times) = &mut dirty_level_settings.medal_times {
+                for medal in Medal::ALL {
+                    ui.label(format!("{medal} time"));
+                    ui.add(
+                        egui::widgets::DragValue::new(medal_times.get_mut(medal))
+                            .speed(0.1)
+                            .clamp_range(0.1..=3600.0)
+                            .suffix("s"),
+                    );
+                    ui.end_row();
+                }
+                if !medal_times.is_valid() {
+                    ui.label("");
+                    ui.colored_label(
+                        WARNING_COLOR,
+                        "Better medals must have faster (or equal) times",
+                    );
+                    ui.end_row();
+                }
+            }
+
+            let respawns = &mut dirty_level_settings.respawns;
+            ui.label("Respawn delay (frames)");
+            frames_field(
+                ui,
+                &mut respawns.death_respawn_delay,
+                "respawn delay",
+                RespawnSettings::delay_range(),
+            );
+            ui.end_row();
+
+            ui.label("Finish cooldown (frames)");
+            frames_field(
+                ui,
+                &mut respawns.finish_cooldown,
+                "finish cooldown",
+                RespawnSettings::delay_range(),
+            );
+            ui.end_row();
+
+            ui.label("Death penalty");
+            ui.checkbox(
+                &mut respawns.deaths_reset_checkpoints,
+                "Dying resets checkpoints",
+            );
+            ui.end_row();
+
+            for event in RespawnPlayerReason::ALL {
+                ui.label(format!("{event:?} audio cue"));
+                ui.horizontal(|ui| {
+                    let clip_id = dirty_level_settings.audio_cues.get_mut(event);
+                    let mut has_clip = clip_id.is_some();
+                    if ui.checkbox(&mut has_clip, "").changed() {
+                        *clip_id = has_clip.then_some(1);
+                    }
+                    if let Some(clip_id) = clip_id {
+                        ui.add(
+                            egui::widgets::DragValue::new(clip_id)
+                                .clamp_range(1..=i64::MAX)
+                                .prefix("Clip #"),
+                        );
+                    }
+                });
+                ui.end_row();
+            }
+        });
+}
+
+/// Translations of the level title and description, the levels browser shows
+/// them to players whose language matches.
+fn level_localizations_ui(
+    ui: &mut egui::Ui,
+    localizations: &mut BTreeMap<String, LevelLocalization>,
+    new_language: &mut String,
+) {
+    let mut removed_language = None;
+    egui::Grid::new("editing_level_localizations")
+        .striped(true)
+        .show(ui, |ui| {
+            for (language, localization) in localizations.iter_mut() {
+                ui.label(format!("Title ({language})"));
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut localization.title)
+                            .char_limit(LEVEL_TITLE_MAX_LEN),
+                    );
+                    if ui.button("Remove").clicked() {
+                        removed_language = Some(language.clone());
+                    }
+                });
+                ui.end_row();
+
+                ui.label(format!("Description ({language})"));
+                ui.add(
+                    egui::TextEdit::singleline(&mut localization.description)
+                        .char_limit(LEVEL_DESCRIPTION_MAX_LEN),
+                );
+                ui.end_row();
+            }
+
+            ui.label("Translation");
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(new_language)
+                        .hint_text("pt-BR")
+                        .desired_width(60.0),
+                );
+                let can_add = validate_language_code(new_language).is_ok()
+                    && !localizations.contains_key(new_language.as_str())
+                    && localizations.len() < LEVEL_LOCALIZATIONS_MAX_COUNT;
+                if ui.add_enabled(can_add, egui::Button::new("Add")).clicked() {
+                    localizations
+                        .insert(std::mem::take(new_language), LevelLocalization::default());
+                }
+            });
+            ui.end_row();
+        });
+
+    if let Some(language) = removed_language {
+        localizations.remove(&language);
+    }
+    if let Err(err) = validate_level_localizations(localization_fields(localizations)) {
+        ui.colored_label(WARNING_COLOR, err.to_string());
+    }
+}
+
+fn localization_fields(
+    localizations: &BTreeMap<String, LevelLocalization>,
+) -> impl ExactSizeIterator<Item = (&str, &str, &str)> {
+    localizations.iter().map(|(language, localization)| {
+        (
+            language.as_str(),
+            localization.title.as_str(),
+            localization.description.as_str(),
+        )
+    })
+}
+
+/// Clips get their ids once uploaded, and are referenced by the ids in the
+/// level settings. Only the desktop client can read files at the moment.
+pub fn audio_clips_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut audio_clip_request_params: AudioClipRequestParams,
+    ui_layout: Res<UiLayout>,
+    #[cfg(not(target_arch = "wasm32"))] mut file_path: Local<String>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !ui_layout.active().panels.audio_clips {
+        return;
+    }
+    egui::Window::new("Audio clips")
+        .collapsible(true)
+        .default_open(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            if !audio_clip_request_params.can_upload() {
+                ui.label("Sign in to upload audio clips");
+                return;
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                ui.label("Ogg Vorbis file");
+                ui.text_edit_singleline(&mut *file_path);
+                let is_uploading = matches!(
+                    audio_clip_request_params.audio_cues.upload_status,
+                    Some(AudioClipUploadStatus::InProgress)
+                );
+                let upload_button = egui::Button::new("Upload");
+                if ui
+                    .add_enabled(!is_uploading && !file_path.is_empty(), upload_button)
+                    .clicked()
+                {
+                    match std::fs::read(file_path.trim()) {
+                        Ok(data) => audio_clip_request_params.upload(data),
+                        Err(err) => {
+                            audio_clip_request_params.audio_cues.upload_status =
+                                Some(AudioClipUploadStatus::Failed(err.to_string()));
+                        }
+                    }
+                }
+            });
+            #[cfg(target_arch = "wasm32")]
+            ui.label("Uploading audio clips is available in the desktop client");
+
+            match &audio_clip_request_params.audio_cues.upload_status {
+                Some(AudioClipUploadStatus::InProgress) => {
+                    ui.label("Uploading...");
+                }
+                Some(AudioClipUploadStatus::Uploaded(response)) => {
+                    ui.label(format!(
+                        "Uploaded clip #{} ({}), it will be played to other players once approved",
+                        response.id, response.moderation_status
+                    ));
+                }
+                Some(AudioClipUploadStatus::Failed(error)) => {
+                    ui.colored_label(WARNING_COLOR, error);
+                }
+                None => {}
+            }
+        });
+}
+
+/// Offline edits that conflict with the changes made on the server are
+/// resolved by a builder after reconnecting.
+pub fn offline_editing_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut offline_editing: ResMut<OfflineEditing>,
+    mut requests_queue: ResMut<LevelObjectRequestsQueue>,
+    mut level_object_correlations: ResMut<LevelObjectCorrelations>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if offline_editing.conflicts.is_empty() {
+        return;
+    }
+
+    egui::Window::new("Merge conflicts")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, egui::Vec2::new(0.0, 10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label("Some of your offline changes conflict with the changes made on the server");
+            let mut keep_ours = None;
+            let mut keep_theirs = None;
+            egui::Grid::new("merge_conflicts").show(ui, |ui| {
+                for (i, conflict) in offline_editing.conflicts.iter().enumerate() {
+                    ui.label(conflict.label());
+                    ui.label(conflict.description());
+                    if ui.button("Keep mine").clicked() {
+                        keep_ours = Some(i);
+                    }
+                    if ui.button("Keep server's").clicked() {
+                        keep_theirs = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Keep all mine").clicked() {
+                    while !offline_editing.conflicts.is_empty() {
+                        offline_editing.keep_ours(
+                            0,
+                            &mut requests_queue,
+                            &mut level_object_correlations,
+                        );
+                    }
+                }
+                if ui.button("Keep all server's").clicked() {
+                    offline_editing.conflicts.clear();
+                }
+            });
+
+            if let Some(i) = keep_ours {
+                offline_editing.keep_ours(i, &mut requests_queue, &mut level_object_correlations);
+            } else if let Some(i) = keep_theirs {
+                offline_editing.keep_theirs(i);
+            }
+        });
+}
+
+pub fn level_publishing_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut level_publishing: ResMut<LevelPublishing>,
+    mut player_requests: ResMut<PlayerRequestsQueue>,
+    connected_server: Res<ConnectedServer>,
+    ui_layout: Res<UiLayout>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    // Levels that aren't stored by the persistence service can't be published.
+    if connected_server.level_id.is_none() || !ui_layout.active().panels.publishing {
+        return;
+    }
+
+    egui::Window::new("Publishing")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-10.0, -10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            if !level_publishing.is_dialog_open {
+                if ui.button("Publish level...").clicked() {
+                    level_publishing.open(&mut player_requests);
+                }
+                if ui
+                    .button("Reload saved level")
+                    .on_hover_text(
+                        "Applies the changes saved on other servers. Edits that haven't been \
+                         autosaved yet are lost.",
+                    )
+                    .clicked()
+                {
+                    player_requests.reload_level = true;
+                }
+                return;
+            }
+
+            match (
+                level_publishing.pending_request(),
+                level_publishing.report(),
+            ) {
+                (None, Some(report)) => {
+                    egui::Grid::new("level_checks").show(ui, |ui| {
+                        for check in &report.checks {
+                            let (icon, color) = match check.severity {
+                                LevelCheckSeverity::Passed => ("✔", egui::Color32::GREEN),
+                                LevelCheckSeverity::Warning => ("⚠", WARNING_COLOR),
+                                LevelCheckSeverity::Error => ("✖", egui::Color32::RED),
+                            };
+                            ui.colored_label(color, icon);
+                            ui.label(&check.message);
+                            ui.end_row();
+                        }
+                    });
+                    match &report.status {
+                        PublishLevelStatus::Checked if report.has_errors() => {
+                            ui.colored_label(WARNING_COLOR, "Fix the errors to publish the level");
+                        }
+                        PublishLevelStatus::Checked => {}
+                        PublishLevelStatus::Published => {
+                            ui.label("The level is published");
+                        }
+                        PublishLevelStatus::Failed(err) => {
+                            ui.colored_label(WARNING_COLOR, err);
+                        }
+                    }
+                }
+                (Some(PublishLevelRequest::Publish), _) => {
+                    ui.label("Publishing...");
+                }
+                (Some(PublishLevelRequest::Check), _) | (None, None) => {
+                    ui.label("Running the checks...");
+                }
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let can_publish = level_publishing.can_publish();
+                if ui
+                    .add_enabled(can_publish, egui::Button::new("Publish"))
+                    .clicked()
+                {
+                    level_publishing.request(PublishLevelRequest::Publish, &mut player_requests);
+                }
+                let is_pending = level_publishing.pending_request().is_some();
+                if ui
+                    .add_enabled(!is_pending, egui::Button::new("Check again"))
+                    .clicked()
+                {
+                    level_publishing.open(&mut player_requests);
+                }
+                if ui.button("Close").clicked() {
+                    level_publishing.is_dialog_open = false;
+                }
+            });
+        });
+}
+
+pub fn invalid_level_object_shapes_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut invalid_shapes: ResMut<InvalidLevelObjectShapes>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if invalid_shapes.0.is_empty() {
+        return;
+    }
+
+    egui::Window::new("Invalid shapes")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, egui::Vec2::new(0.0, 10.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label("The following objects were removed:");
+            for invalid_shape in &invalid_shapes.0 {
+                ui.colored_label(
+                    WARNING_COLOR,
+                    format!("{}: {}", invalid_shape.label, invalid_shape.error),
+                );
+            }
+            if ui.button("Dismiss").clicked() {
+                invalid_shapes.0.clear();
+            }
+        });
+}
+
+fn annotation_kind(ui: &mut egui::Ui, dirty_annotation_kind: &mut AnnotationKind) {
+    ui.label("Annotation type");
+    ui.label(dirty_annotation_kind.to_string());
+    ui.end_row();
+
+    match dirty_annotation_kind {
+        AnnotationKind::Measurement { end } => {
+            ui.label("End");
+            ui.horizontal(|ui| {
+                NumericField::new(&mut end.x, "measurement end x").show(ui);
+                NumericField::new(&mut end.y, "measurement end y").show(ui);
+            });
+            ui.end_row();
+
+            ui.label("Distance");
+            ui.label(format_distance(end.length()));
+            ui.end_row();
+        }
+        AnnotationKind::Note { text } => {
+            ui.label("Text");
+            ui.text_edit_multiline(text);
+            ui.end_row();
+        }
+        AnnotationKind::Region { size } => {
+            ui.label("Size");
+            ui.horizontal(|ui| {
+                ui.label("Width:");
+                NumericField::new(&mut size.x, "region width")
+                    .clamp_range(0.1..=f32::MAX)
+                    .show(ui);
+                ui.label("Height:");
+                NumericField::new(&mut size.y, "region height")
+                    .clamp_range(0.1..=f32::MAX)
+                    .show(ui);
+            });
+            ui.end_row();
+        }
+    }
+}
+
+/// Periods have to leave at least a frame for moving between waypoints.
+fn period_range(route: &ObjectRoute) -> RangeInclusive<u16> {
+    let max_period = SIMULATIONS_PER_SECOND as u16 * 60;
+    let min_period = (SIMULATIONS_PER_SECOND as u16)
+        .max(route.start_frame_offset.value() + 1)
+        .max(route.total_wait().min(max_period as u32 - 1) as u16 + 1);
+    min_period..=max_period
+}
+
+fn frames_field(
+    ui: &mut egui::Ui,
+    frames: &mut FrameNumber,
+    id_source: &str,
+    range: RangeInclusive<u16>,
+) {
+    let mut value = frames.value() as f32;
+    let response = NumericField::new(&mut value, id_source)
+        .integer()
+        .clamp_range(*range.start() as f32..=*range.end() as f32)
+        .show(ui);
+    if response.changed() {
+        *frames = FrameNumber::new(value as u16);
+    }
+}
+
+fn jump_pad(ui: &mut egui::Ui, dirty_jump_pad: &mut Option<JumpPad>) {
+    ui.label("Is jump pad");
+    let mut is_jump_pad = dirty_jump_pad.is_some();
+    if ui.checkbox(&mut is_jump_pad, "").changed() {
+        *dirty_jump_pad = is_jump_pad.then(JumpPad::default);
+    }
+    ui.end_row();
+
+    let Some(jump_pad) = dirty_jump_pad else {
+        return;
+    };
+
+    // Builders set the impulse in polar coordinates, as it's easier to reason
+    // about a push strength and its direction separately.
+    let mut strength = jump_pad.impulse.length();
+    let mut angle_degrees = jump_pad.impulse.y.atan2(jump_pad.impulse.x).to_degrees();
+    ui.label("Impulse strength");
+    let strength_changed = NumericField::new(&mut strength, "jump pad impulse strength")
+        .step(0.1)
+        .clamp_range(0.0..=f32::MAX)
+        .show(ui)
+        .changed();
+    ui.end_row();
+
+    ui.label("Impulse direction (degrees)");
+    let angle_changed = ui
+        .add(
+            egui::widgets::DragValue::new(&mut angle_degrees)
+                .speed(1.0)
+                .clamp_range(-180.0..=180.0),
+        )
+        .changed();
+    ui.end_row();
+    if strength_changed || angle_changed {
+        jump_pad.impulse = Vec2::from_angle(angle_degrees.to_radians()) * strength;
+    }
+
+    ui.label("Cooldown (frames)");
+    let mut cooldown_frames = FrameNumber::new(jump_pad.cooldown_frames);
+    frames_field(
+        ui,
+        &mut cooldown_frames,
+        "jump pad cooldown",
+        JUMP_PAD_MIN_COOLDOWN_FRAMES..=JUMP_PAD_MAX_COOLDOWN_FRAMES,
+    );
+    jump_pad.cooldown_frames = cooldown_frames.value();
+    ui.end_row();
+}
+
+fn emitter(ui: &mut egui::Ui, dirty_emitter: &mut EmitterDesc) {
+    ui.label("Direction (degrees)");
+    ui.add(
+        egui::widgets::DragValue::new(&mut dirty_emitter.angle_degrees)
+            .speed(1.0)
+            .clamp_range(-180.0..=180.0),
+    );
+    ui.end_row();
+
+    ui.label("Period (frames)");
+    let mut period = FrameNumber::new(dirty_emitter.period);
+    frames_field(
+        ui,
+        &mut period,
+        "emitter period",
+        EMITTER_MIN_PERIOD_FRAMES..=EMITTER_MAX_PERIOD_FRAMES,
+    );
+    dirty_emitter.period = period.value();
+    dirty_emitter.start_frame_offset = dirty_emitter
+        .start_frame_offset
+        .min(dirty_emitter.period - 1);
+    ui.end_row();
+
+    ui.label("Start frame offset");
+    let mut start_frame_offset = FrameNumber::new(dirty_emitter.start_frame_offset);
+    frames_field(
+        ui,
+        &mut start_frame_offset,
+        "emitter start frame offset",
+        0..=dirty_emitter.period - 1,
+    );
+    dirty_emitter.start_frame_offset = start_frame_offset.value();
+    ui.end_row();
+
+    let patterns = [
+        EmitterPattern::Projectile {
+            speed: 5.0,
+            range: 8.0,
+        },
+        EmitterPattern::Beam {
+            length: 5.0,
+            sweep_degrees: 90.0,
+        },
+    ];
+    ui.label("Pattern");
+    egui::containers::ComboBox::from_id_source("emitter_pattern")
+        .width(200.0)
+        .selected_text(dirty_emitter.pattern.label())
+        .show_ui(ui, |ui| {
+            for pattern in patterns {
+                let is_selected = std::mem::discriminant(&pattern)
+                    == std::mem::discriminant(&dirty_emitter.pattern);
+                if ui.selectable_label(is_selected, pattern.label()).clicked() && !is_selected {
+                    dirty_emitter.pattern = pattern;
+                }
+            }
+        });
+    ui.end_row();
+
+    match &mut dirty_emitter.pattern {
+        EmitterPattern::Beam {
+            length,
+            sweep_degrees,
+        } => {
+            ui.label("Length");
+            NumericField::new(length, "emitter beam length")
+                .step(0.1)
+                .clamp_range(0.0..=EMITTER_MAX_REACH)
+                .show(ui);
+            ui.end_row();
+
+            ui.label("Sweep (degrees)");
+            ui.add(
+                egui::widgets::DragValue::new(sweep_degrees)
+                    .speed(1.0)
+                    .clamp_range(0.0..=360.0),
+            );
+            ui.end_row();
+        }
+        EmitterPattern::Projectile { speed, range } => {
+            ui.label("Speed (units/s)");
+            NumericField::new(speed, "emitter projectile speed")
+                .step(0.1)
+                .clamp_range(0.0..=PROJECTILE_MAX_SPEED)
+                .show(ui);
+            ui.end_row();
+
+            ui.label("Range");
+            NumericField::new(range, "emitter projectile range")
+                .step(0.1)
+                .clamp_range(0.0..=EMITTER_MAX_REACH)
+                .show(ui);
+            ui.end_row();
+        }
+    }
+}
+
+fn format_distance(distance: f32) -> String {
+    format!("{:.2} m", distance)
+}
+
 fn plane_form(ui: &mut egui::Ui, dirty_plane_form_desc: &mut PlaneFormDesc) {
     ui.label("Form type");
     plane_form_type(ui, dirty_plane_form_desc);
@@ -608,28 +2167,25 @@ fn plane_form(ui: &mut egui::Ui, dirty_plane_form_desc: &mut PlaneFormDesc) {
     match dirty_plane_form_desc {
         PlaneFormDesc::Circle { radius } => {
             ui.label("Radius");
-            ui.add(
-                egui::widgets::DragValue::new(radius)
-                    .speed(0.01)
-                    .clamp_range(1.0..=f32::MAX),
-            );
+            NumericField::new(radius, "plane radius")
+                .step(0.5)
+                .clamp_range(1.0..=f32::MAX)
+                .show(ui);
             ui.end_row();
         }
         PlaneFormDesc::Rectangle { size } => {
             ui.label("Size");
             ui.horizontal(|ui| {
                 ui.label("Width:");
-                ui.add(
-                    egui::widgets::DragValue::new(&mut size.x)
-                        .speed(0.01)
-                        .clamp_range(1.0..=f32::MAX),
-                );
+                NumericField::new(&mut size.x, "plane width")
+                    .step(0.5)
+                    .clamp_range(1.0..=f32::MAX)
+                    .show(ui);
                 ui.label("Height:");
-                ui.add(
-                    egui::widgets::DragValue::new(&mut size.y)
-                        .speed(0.01)
-                        .clamp_range(1.0..=f32::MAX),
-                );
+                NumericField::new(&mut size.y, "plane height")
+                    .step(0.5)
+                    .clamp_range(1.0..=f32::MAX)
+                    .show(ui);
             });
             ui.end_row();
         }
@@ -670,6 +2226,30 @@ fn plane_form(ui: &mut egui::Ui, dirty_plane_form_desc: &mut PlaneFormDesc) {
     }
 }
 
+fn terrain_brush_ui(ui: &mut egui::Ui, terrain_brush: &mut TerrainBrush) {
+    ui.separator();
+    ui.horizontal(|ui| {
+        ui.label("Terrain brush:");
+        ui.selectable_value(&mut terrain_brush.mode, None, "Off");
+        ui.selectable_value(&mut terrain_brush.mode, Some(BrushOperation::Add), "Add");
+        ui.selectable_value(
+            &mut terrain_brush.mode,
+            Some(BrushOperation::Subtract),
+            "Subtract",
+        );
+    });
+    ui.add(
+        egui::widgets::Slider::new(
+            &mut terrain_brush.radius,
+            MIN_BRUSH_RADIUS..=MAX_BRUSH_RADIUS,
+        )
+        .text("Radius"),
+    );
+    if let Some(err) = terrain_brush.last_error {
+        ui.colored_label(WARNING_COLOR, format!("Skipped a stamp: {err}"));
+    }
+}
+
 fn plane_form_type(ui: &mut egui::Ui, dirty_plane_form_desc: &mut PlaneFormDesc) {
     #[derive(Copy, Clone, Debug, PartialEq, Eq)]
     enum Type {
@@ -742,25 +2322,33 @@ fn route_settings(
     level_objects: &mut LevelObjects,
     dirty_level_object: &mut LevelObject,
 ) {
+    let route_length = route_length(&level_objects.level_state, dirty_level_object);
     let dirty_level_object_route = match &mut dirty_level_object.route {
         Some(route) => route,
         None => return,
     };
+    let is_forward_cycle = matches!(
+        dirty_level_object_route.desc,
+        ObjectRouteDesc::ForwardCycle(_)
+    );
 
     let response = egui::CollapsingHeader::new("Route settings").show(ui, |ui| {
         match &mut dirty_level_object_route.desc {
             ObjectRouteDesc::Attached(route_point) | ObjectRouteDesc::Radial(route_point) => {
                 let point_label = route_point
-                    .and_then(|point| level_objects.level_state.objects.get(&point))
+                    .and_then(|point| level_objects.level_state.object(point))
                     .map_or("None".to_owned(), |level_object| level_object.label.clone());
                 ui.label(format!("Route point: {point_label}"));
             }
             ObjectRouteDesc::ForwardCycle(route_points)
             | ObjectRouteDesc::ForwardBackwardsCycle(route_points) => {
+                let waits = &mut dirty_level_object_route.waits;
                 let mut list = Vec::new();
                 let mut duplicate_counts = HashMap::default();
-                for point in &*route_points {
-                    if let Some(level_object) = level_objects.level_state.objects.get(point) {
+                for (i, point) in route_points.iter().enumerate() {
+                    // Waits are moved together with their waypoints when reordering.
+                    let wait = waits.get(i).copied().unwrap_or_default();
+                    if let Some(level_object) = level_objects.level_state.object(*point) {
                         let n = duplicate_counts
                             .entry(Some(*point))
                             .and_modify(|count| *count += 1)
@@ -768,7 +2356,7 @@ fn route_settings(
                         list.push(ListItem {
                             id: egui::Id::new(level_object.net_id).with(n),
                             label: level_object.label.clone(),
-                            data: *point,
+                            data: (*point, wait),
                             sortable: true,
                         });
                     } else {
@@ -779,20 +2367,90 @@ fn route_settings(
                         list.push(ListItem {
                             id: egui::Id::new("invalid").with(n),
                             label: "<Invalid>".to_owned(),
-                            data: *point,
+                            data: (*point, wait),
                             sortable: true,
                         });
                     }
                 }
 
                 let edited = sortable_list(ui, "route settings", &mut list);
-                if edited {
-                    *route_points = list.into_iter().map(|list_item| list_item.data).collect();
+
+                // The final waypoint of a forward cycle ends the period, so it can't have a
+                // wait.
+                let waited_count = if is_forward_cycle {
+                    list.len().saturating_sub(1)
+                } else {
+                    list.len()
+                };
+                let mut wait_edited = false;
+                if waited_count > 0 {
+                    ui.label("Waits (frames)");
+                    egui::Grid::new("route_waits").striped(true).show(ui, |ui| {
+                        for (i, item) in list.iter_mut().take(waited_count).enumerate() {
+                            ui.label(format!("{}. {}", i + 1, item.label));
+                            let prev_wait = item.data.1;
+                            frames_field(
+                                ui,
+                                &mut item.data.1,
+                                &format!("route wait {i}"),
+                                0..=SIMULATIONS_PER_SECOND as u16 * 30,
+                            );
+                            wait_edited |= item.data.1 != prev_wait;
+                            ui.end_row();
+                        }
+                    });
+                }
+
+                if edited || wait_edited {
+                    *route_points = list.iter().map(|list_item| list_item.data.0).collect();
+                    *waits = list
+                        .iter()
+                        .take(waited_count)
+                        .map(|list_item| list_item.data.1)
+                        .collect();
+                    while waits.last() == Some(&FrameNumber::new(0)) {
+                        waits.pop();
+                    }
                 }
             }
         }
+
+        if !matches!(dirty_level_object_route.desc, ObjectRouteDesc::Attached(_)) {
+            ui.separator();
+            ui.horizontal(|ui| {
+                let mut has_desired_speed = dirty_level_object_route.desired_speed.is_some();
+                if ui
+                    .checkbox(&mut has_desired_speed, "Desired speed (units/s)")
+                    .on_hover_text("Derives the period from the route length and waits")
+                    .changed()
+                {
+                    // Starts with the speed that the current period results in.
+                    let travel_secs = (dirty_level_object_route.period.value() as u32)
+                        .saturating_sub(dirty_level_object_route.total_wait())
+                        .max(1) as f32
+                        / SIMULATIONS_PER_SECOND;
+                    let speed = route_length.unwrap_or(0.0) / travel_secs;
+                    let speed_range = ObjectRoute::speed_range();
+                    dirty_level_object_route.desired_speed = has_desired_speed
+                        .then(|| speed.clamp(*speed_range.start(), *speed_range.end()));
+                }
+                if let Some(desired_speed) = &mut dirty_level_object_route.desired_speed {
+                    NumericField::new(desired_speed, "route desired speed")
+                        .step(0.1)
+                        .clamp_range(ObjectRoute::speed_range())
+                        .show(ui);
+                }
+            });
+            if let Some(route_length) = route_length {
+                ui.label(format!("Route length: {}", format_distance(route_length)));
+            }
+        }
     });
 
+    if !matches!(dirty_level_object_route.desc, ObjectRouteDesc::Attached(_)) {
+        update_route_period(dirty_level_object_route, route_length);
+    }
+
     if response.body_returned.is_some() {
         if let Some(entity) = level_objects_filter(
             ui,
@@ -817,6 +2475,26 @@ fn route_settings(
     }
 }
 
+/// Keeps the period in sync with the desired speed and makes sure that waits
+/// leave time for moving (the server rejects such routes otherwise).
+fn update_route_period(route: &mut ObjectRoute, route_length: Option<f32>) {
+    if let Some(period) = route
+        .desired_speed
+        .zip(route_length)
+        .and_then(|(speed, route_length)| route.period_for_speed(route_length, speed))
+    {
+        route.period = period;
+    }
+
+    let min_period = (route.total_wait() + 1).min(u16::MAX as u32) as u16;
+    if route.period.value() < min_period {
+        route.period = FrameNumber::new(min_period);
+    }
+    if route.start_frame_offset.value() >= route.period.value() {
+        route.start_frame_offset = FrameNumber::new(route.period.value() - 1);
+    }
+}
+
 fn level_objects_filter(
     ui: &mut Ui,
     filter: &mut String,
@@ -984,14 +2662,100 @@ fn route_type(ui: &mut egui::Ui, dirty_level_object: &mut LevelObject) {
 
 fn replace_route_desc(route: &mut Option<ObjectRoute>, desc: ObjectRouteDesc) {
     if let Some(route) = route {
+        // Only linear routes have waypoints to wait at.
+        if matches!(
+            desc,
+            ObjectRouteDesc::Attached(_) | ObjectRouteDesc::Radial(_)
+        ) {
+            route.waits.clear();
+        }
+        if matches!(desc, ObjectRouteDesc::Attached(_)) {
+            route.desired_speed = None;
+        }
         route.desc = desc;
     } else {
-        *route = Some(ObjectRoute {
-            period: default_period(),
-            start_frame_offset: FrameNumber::new(0),
-            desc,
-        });
+        *route = Some(ObjectRoute::new(default_period(), desc));
+    }
+}
+
+fn object_appearance(
+    ui: &mut egui::Ui,
+    dirty_appearance: &mut ObjectAppearance,
+    default_color: [f32; 3],
+) {
+    ui.label("Color");
+    ui.horizontal(|ui| {
+        // Egui color pickers work with linear colors.
+        let [r, g, b] = dirty_appearance.color.unwrap_or(default_color);
+        let [r, g, b, _] = Color::rgb(r, g, b).as_linear_rgba_f32();
+        let mut linear_color = [r, g, b];
+        if ui.color_edit_button_rgb(&mut linear_color).changed() {
+            let [r, g, b] = linear_color;
+            let [r, g, b, _] = Color::rgb_linear(r, g, b).as_rgba_f32();
+            dirty_appearance.color = Some([r, g, b]);
+        }
+        if ui
+            .add_enabled(
+                dirty_appearance.color.is_some(),
+                egui::widgets::Button::new("Reset"),
+            )
+            .clicked()
+        {
+            dirty_appearance.color = None;
+        }
+    });
+    ui.end_row();
+
+    ui.label("Glow");
+    ui.add(egui::widgets::Slider::new(
+        &mut dirty_appearance.emissive,
+        0.0..=1.0,
+    ));
+    ui.end_row();
+}
+
+fn default_object_color(level_object: &LevelObject) -> [f32; 3] {
+    match (&level_object.desc, level_object.collision_logic) {
+        (LevelObjectDesc::Cube(_), CollisionLogic::Death) => CUBE_DEATH_COLOR,
+        (LevelObjectDesc::Cube(_), _) => CUBE_COLOR,
+        (_, CollisionLogic::Death) => PLANE_DEATH_COLOR,
+        (_, CollisionLogic::Finish) => PLANE_FINISH_COLOR,
+        (_, CollisionLogic::None) => PLANE_COLOR,
+    }
+}
+
+/// Players must be able to spot death and finish objects at a glance, so we
+/// warn builders about custom colors that make them look like something else.
+fn appearance_warnings(
+    color: [f32; 3],
+    regular_object_color: [f32; 3],
+    collision_logic: CollisionLogic,
+    background_color: [f32; 3],
+) -> Vec<&'static str> {
+    let is_close_to =
+        |other: [f32; 3]| color_difference(color, other) < MIN_READABLE_COLOR_DIFFERENCE;
+
+    let mut warnings = Vec::new();
+    match collision_logic {
+        CollisionLogic::None => return warnings,
+        CollisionLogic::Death => {
+            if is_close_to(PLANE_FINISH_COLOR) {
+                warnings.push("Can be mistaken for a finish");
+            }
+        }
+        CollisionLogic::Finish => {
+            if is_close_to(PLANE_DEATH_COLOR) || is_close_to(CUBE_DEATH_COLOR) {
+                warnings.push("Can be mistaken for a death zone");
+            }
+        }
+    }
+    if is_close_to(regular_object_color) {
+        warnings.push("Is hard to tell apart from regular objects");
+    }
+    if is_close_to(background_color) {
+        warnings.push("Blends into the background");
     }
+    warnings
 }
 
 fn collision_logic(
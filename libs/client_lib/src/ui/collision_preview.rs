@@ -0,0 +1,243 @@
+//! Builders can check how the collider of an object they edit mid-round
+//! interacts with runners without waiting for someone to hit it. The preview
+//! is local-only: recent position traces of players are tested against the
+//! object's collider with a rapier query, and the trace points that would
+//! overlap it are highlighted.
+
+use crate::ui::builder_ui::OverlayCameraParams;
+use bevy::{
+    ecs::{
+        query::With,
+        system::{Query, Res, ResMut, Resource},
+    },
+    math::Vec2,
+    utils::HashSet,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_rapier2d::{
+    geometry::Collider,
+    rapier::{
+        math::Isometry,
+        parry::{query, shape::Ball},
+    },
+};
+use mr_shared_lib::{
+    game::{
+        components::{LevelObjectTag, PlayerTag, Position},
+        level::LevelState,
+    },
+    messages::EntityNetId,
+    registry::EntityRegistry,
+    PLAYER_RADIUS,
+};
+
+/// Drawing every simulated frame of a trace would clutter the view.
+const TRACE_STEP: usize = 4;
+const TRACE_POINT_RADIUS: f32 = 2.0;
+const TRACE_COLOR: egui::Color32 = egui::Color32::from_rgb(120, 180, 255);
+const OVERLAP_POINT_RADIUS: f32 = 4.0;
+const OVERLAP_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 60, 60);
+
+/// Objects that have the collision preview enabled.
+#[derive(Resource, Default)]
+pub struct CollisionPreview {
+    objects: HashSet<EntityNetId>,
+}
+
+impl CollisionPreview {
+    pub fn is_enabled(&self, net_id: EntityNetId) -> bool {
+        self.objects.contains(&net_id)
+    }
+
+    pub fn set_enabled(&mut self, net_id: EntityNetId, enabled: bool) {
+        if enabled {
+            self.objects.insert(net_id);
+        } else {
+            self.objects.remove(&net_id);
+        }
+    }
+}
+
+/// Returns whether a player at `player_position` would overlap the collider.
+/// Level objects don't rotate, so only their positions are taken into account.
+pub fn overlaps_player(collider: &Collider, object_position: Vec2, player_position: Vec2) -> bool {
+    query::intersection_test(
+        &Isometry::translation(object_position.x, object_position.y),
+        collider.raw.as_ref(),
+        &Isometry::translation(player_position.x, player_position.y),
+        &Ball::new(PLAYER_RADIUS),
+    )
+    .unwrap_or(false)
+}
+
+/// Returns the points of a player's trace to draw, along with whether the
+/// player overlaps the object at each of them. Overlapping points are never
+/// skipped.
+pub fn collision_trace(
+    collider: &Collider,
+    object_position: &Position,
+    player_position: &Position,
+) -> Vec<(Vec2, bool)> {
+    player_position
+        .buffer
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (frame_number, position))| {
+            // Moving objects are tested at where they were at the same frame.
+            let object_position = object_position
+                .buffer
+                .get(frame_number)
+                .or_else(|| object_position.buffer.last())?;
+            let is_overlapping = overlaps_player(collider, *object_position, *position);
+            (is_overlapping || i % TRACE_STEP == 0).then_some((*position, is_overlapping))
+        })
+        .collect()
+}
+
+pub fn draw_collision_preview_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut collision_preview: ResMut<CollisionPreview>,
+    level_state: Res<LevelState>,
+    entity_registry: Res<EntityRegistry<EntityNetId>>,
+    level_objects_query: Query<(&Position, Option<&Collider>), With<LevelObjectTag>>,
+    players_query: Query<&Position, With<PlayerTag>>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    collision_preview
+        .objects
+        .retain(|net_id| level_state.object(*net_id).is_some());
+    if collision_preview.objects.is_empty() {
+        return;
+    }
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for net_id in &collision_preview.objects {
+        let Some((object_position, Some(collider))) = entity_registry
+            .get_entity(*net_id)
+            .and_then(|entity| level_objects_query.get(entity).ok())
+        else {
+            // Concave planes may still be waiting for their colliders.
+            continue;
+        };
+
+        for player_position in players_query.iter() {
+            for (position, is_overlapping) in
+                collision_trace(collider, object_position, player_position)
+            {
+                let Some(pos) = overlay_camera_params.world_to_egui_pos(position) else {
+                    continue;
+                };
+                if is_overlapping {
+                    painter.circle_filled(pos, OVERLAP_POINT_RADIUS, OVERLAP_COLOR);
+                } else {
+                    painter.circle_filled(pos, TRACE_POINT_RADIUS, TRACE_COLOR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::record_player_state;
+    use bevy::ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+    use mr_shared_lib::{
+        framebuffer::FrameNumber,
+        game::{
+            components::{PlayerDirection, Spawned},
+            movement::read_movement_updates_system,
+        },
+        messages::{PlayerNetId, PlayerState},
+        player::PlayerUpdates,
+        GameTime, SimulationTime,
+    };
+
+    #[test]
+    fn test_overlaps_player() {
+        let collider = Collider::cuboid(1.0, 1.0);
+        let object_position = Vec2::new(10.0, 0.0);
+        assert!(overlaps_player(&collider, object_position, object_position));
+        // The player's radius is taken into account.
+        assert!(overlaps_player(
+            &collider,
+            object_position,
+            Vec2::new(11.0 + PLAYER_RADIUS * 0.5, 0.0)
+        ));
+        assert!(!overlaps_player(
+            &collider,
+            object_position,
+            Vec2::new(11.0 + PLAYER_RADIUS * 2.0, 0.0)
+        ));
+        // The collider is tested at the object's position, not at the origin.
+        assert!(!overlaps_player(&collider, object_position, Vec2::ZERO));
+    }
+
+    #[test]
+    fn test_collision_trace_from_delta_updates() {
+        let runner = PlayerNetId(1);
+        let mut player_updates = PlayerUpdates::default();
+        // A runner crosses the object, as reported by delta updates.
+        for frame in 0..=20u16 {
+            record_player_state(
+                &mut player_updates,
+                FrameNumber::new(frame),
+                &PlayerState {
+                    net_id: runner,
+                    position: Vec2::new(frame as f32, 0.0),
+                    direction: Vec2::X,
+                },
+            );
+        }
+
+        let mut world = World::new();
+        world.insert_resource(GameTime {
+            session: 0,
+            frame_number: FrameNumber::new(20),
+        });
+        world.insert_resource(SimulationTime::default());
+        world.insert_resource(player_updates);
+        let runner_entity = world
+            .spawn((
+                Position::new(Vec2::ZERO, FrameNumber::new(0), 1),
+                PlayerDirection::new(Vec2::ZERO, FrameNumber::new(0), 1),
+                Spawned::new(FrameNumber::new(0)),
+            ))
+            .id();
+        let mut player_registry = EntityRegistry::<PlayerNetId>::default();
+        player_registry.register(runner, runner_entity);
+        world.insert_resource(player_registry);
+        SystemStage::single_threaded()
+            .with_system(read_movement_updates_system)
+            .run(&mut world);
+
+        let collider = Collider::cuboid(1.0, 1.0);
+        let object_position = Position::new(Vec2::new(10.0, 0.0), FrameNumber::new(0), 1);
+        let trace = collision_trace(
+            &collider,
+            &object_position,
+            world.get::<Position>(runner_entity).unwrap(),
+        );
+        assert_eq!(
+            trace,
+            vec![
+                (Vec2::new(0.0, 0.0), false),
+                (Vec2::new(4.0, 0.0), false),
+                (Vec2::new(8.0, 0.0), false),
+                (Vec2::new(9.0, 0.0), true),
+                (Vec2::new(10.0, 0.0), true),
+                (Vec2::new(11.0, 0.0), true),
+                (Vec2::new(12.0, 0.0), false),
+                (Vec2::new(16.0, 0.0), false),
+                (Vec2::new(20.0, 0.0), false),
+            ]
+        );
+    }
+}
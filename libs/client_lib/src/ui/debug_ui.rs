@@ -1,32 +1,56 @@
+#[cfg(debug_assertions)]
+use crate::net::conditioner::{NetworkConditioner, MAX_JITTER_MILLIS, MAX_LATENCY_MILLIS};
+#[cfg(feature = "time_dilation")]
+use crate::time_dilation::{TimeDilation, MAX_TICK_RATE_FACTOR, MIN_TICK_RATE_FACTOR};
 use crate::{
-    helpers::MouseEntityPicker, ui::MuddleInspectable, DelayServerTime, EstimatedServerTime,
-    GameTicksPerSecond, TargetFramesAhead,
+    helpers::MouseEntityPicker,
+    input_latency::InputLatency,
+    memory_budget::{format_bytes, MemoryBudget},
+    server_health::ServerHealthReport,
+    ui::{framebuffer_inspector::FramebufferInspector, MuddleInspectable},
+    DelayServerTime, EstimatedServerTime, GameTicksPerSecond, TargetFramesAhead,
 };
 use bevy::{
     diagnostic::{DiagnosticMeasurement, Diagnostics, FrameTimeDiagnosticsPlugin},
     ecs::system::SystemParam,
+    log,
     prelude::*,
+    utils::Instant,
+};
+use bevy_egui::{
+    egui,
+    egui::{
+        epaint::RectShape,
+        plot::{Legend, Line, Plot, PlotPoints, VLine},
+    },
+    EguiContext,
 };
-use bevy_egui::{egui, egui::epaint::RectShape, EguiContext};
 use iyes_loopless::state::CurrentState;
 use mr_shared_lib::{
     client::components::DebugUiVisibility,
     framebuffer::FrameNumber,
     game::{
         client_factories::VisibilitySettings,
+        command_log::CommandLog,
         components::{
             LevelObjectMovement, LevelObjectServerGhostChild, LevelObjectStaticGhostParent,
             PlayerDirection, Position,
         },
         level::LevelState,
     },
-    messages::{EntityNetId, PlayerNetId},
+    messages::{EntityNetId, PlayerNetId, ServerHealth},
     net::ConnectionState,
     player::Players,
     registry::EntityRegistry,
     GameSessionState, SimulationTime,
 };
-use std::{collections::VecDeque, marker::PhantomData};
+use std::{collections::VecDeque, marker::PhantomData, time::Duration};
+
+/// The frame timeline keeps this much history, the displayed window can be
+/// shorter.
+const FRAME_TIMELINE_MAX_SECS: u64 = 60;
+const FRAME_TIMELINE_DEFAULT_SECS: u64 = 10;
+const REWIND_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 90, 60);
 
 #[derive(SystemParam)]
 pub struct DebugData<'w, 's> {
@@ -37,6 +61,7 @@ pub struct DebugData<'w, 's> {
     target_frames_ahead: Res<'w, TargetFramesAhead>,
     estimated_server_time: Res<'w, EstimatedServerTime>,
     connection_state: Res<'w, ConnectionState>,
+    server_health: Res<'w, ServerHealthReport>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
@@ -62,6 +87,70 @@ pub struct DebugUiState {
     pub rtt_millis: usize,
     pub packet_loss: f32,
     pub jitter_millis: usize,
+    pub server_health: Option<ServerHealth>,
+    pub server_overloaded: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameTimelineSample {
+    pub at: Instant,
+    /// Frames are plotted relative to the estimated server frame, as the
+    /// absolute values grow too fast to notice any drift.
+    pub player_frame_offset: i32,
+    pub server_frame_offset: i32,
+    pub target_frames_ahead: u16,
+    pub jitter_buffer_len: u16,
+    /// Is set if the player frame was rewound since the previous sample.
+    pub rewind_depth: Option<u16>,
+}
+
+/// Is sampled every simulated frame, plots how the client keeps its clocks in
+/// sync with the server.
+#[derive(Resource)]
+pub struct FrameTimeline {
+    samples: VecDeque<FrameTimelineSample>,
+    last_rewind_count: u64,
+    pub window_secs: u64,
+}
+
+impl Default for FrameTimeline {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            last_rewind_count: 0,
+            window_secs: FRAME_TIMELINE_DEFAULT_SECS,
+        }
+    }
+}
+
+impl FrameTimeline {
+    pub fn push(&mut self, sample: FrameTimelineSample) {
+        while self.samples.front().map_or(false, |oldest| {
+            sample.at.duration_since(oldest.at) > Duration::from_secs(FRAME_TIMELINE_MAX_SECS)
+        }) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Returns the samples of the displayed window, with their ages in seconds
+    /// (negative, so that the latest sample is at the right edge of the plot).
+    pub fn window(&self, now: Instant) -> impl Iterator<Item = (f64, &FrameTimelineSample)> + '_ {
+        let window = Duration::from_secs(self.window_secs);
+        self.samples
+            .iter()
+            .map(move |sample| (-now.duration_since(sample.at).as_secs_f64(), sample))
+            .filter(move |(age, _)| -*age <= window.as_secs_f64())
+    }
+}
+
+/// A signed difference of wrapping frame numbers.
+pub fn frame_offset(frame_number: FrameNumber, relative_to: FrameNumber) -> i32 {
+    if frame_number >= relative_to {
+        (frame_number - relative_to).value() as i32
+    } else {
+        -((relative_to - frame_number).value() as i32)
+    }
 }
 
 pub fn update_debug_visibility_system(
@@ -81,6 +170,7 @@ pub fn update_debug_visibility_system(
 
 pub fn update_debug_ui_state_system(
     mut debug_ui_state: ResMut<DebugUiState>,
+    mut frame_timeline: ResMut<FrameTimeline>,
     debug_data: DebugData,
 ) {
     #[cfg(feature = "profiler")]
@@ -101,6 +191,22 @@ pub fn update_debug_ui_state_system(
     debug_ui_state.rtt_millis = debug_data.connection_state.rtt_millis() as usize;
     debug_ui_state.packet_loss = debug_data.connection_state.packet_loss() * 100.0;
     debug_ui_state.jitter_millis = debug_data.connection_state.jitter_millis() as usize;
+    debug_ui_state.server_health = debug_data.server_health.latest();
+    debug_ui_state.server_overloaded = debug_data.server_health.is_overloaded();
+
+    let rewind_count = debug_data.time.rewind_count();
+    let rewind_depth = (rewind_count != frame_timeline.last_rewind_count)
+        .then(|| debug_data.time.last_rewind_depth());
+    frame_timeline.last_rewind_count = rewind_count;
+    let estimated_server_frame = debug_data.estimated_server_time.frame_number;
+    frame_timeline.push(FrameTimelineSample {
+        at: Instant::now(),
+        player_frame_offset: frame_offset(debug_data.time.player_frame, estimated_server_frame),
+        server_frame_offset: frame_offset(debug_data.time.server_frame, estimated_server_frame),
+        target_frames_ahead: debug_data.target_frames_ahead.target,
+        jitter_buffer_len: debug_data.target_frames_ahead.jitter_buffer_len,
+        rewind_depth,
+    });
 }
 
 pub fn profiler_ui_system(
@@ -128,6 +234,10 @@ pub fn debug_ui_system(
     // ResMut is intentional, to avoid fighting over the Mutex from different systems.
     mut egui_context: ResMut<EguiContext>,
     mut debug_ui_state: ResMut<DebugUiState>,
+    mut input_latency: ResMut<InputLatency>,
+    mut command_log: ResMut<CommandLog>,
+    mut frame_timeline: ResMut<FrameTimeline>,
+    memory_budget: Res<MemoryBudget>,
     diagnostics: Res<Diagnostics>,
 ) {
     #[cfg(feature = "profiler")]
@@ -158,6 +268,16 @@ pub fn debug_ui_system(
                         debug_ui_state.fps_history_len,
                     );
                 });
+            egui::CollapsingHeader::new("📈 Frame timeline")
+                .default_open(false)
+                .show(ui, |ui| {
+                    frame_timeline_plot(ui, &mut frame_timeline);
+                });
+            egui::CollapsingHeader::new("🧠 Memory")
+                .default_open(false)
+                .show(ui, |ui| {
+                    memory_breakdown(ui, &memory_budget);
+                });
 
             ui.separator();
             if debug_ui_state.pause {
@@ -202,10 +322,163 @@ pub fn debug_ui_system(
             ui.label(format!("RTT: {}ms", debug_ui_state.rtt_millis));
             ui.label(format!("Packet loss: {:.2}%", debug_ui_state.packet_loss));
             ui.label(format!("Jitter: {}ms", debug_ui_state.jitter_millis));
+            if let Some(server_health) = debug_ui_state.server_health {
+                ui.label(format!(
+                    "Server tick: {:?}, queue depth: {}{}",
+                    server_health.tick_duration,
+                    server_health.broadcast_queue_depth,
+                    if debug_ui_state.server_overloaded {
+                        " (overloaded)"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+            ui.separator();
+            let mut measure_input_latency = input_latency.enabled;
+            if ui
+                .checkbox(&mut measure_input_latency, "Measure input latency")
+                .changed()
+            {
+                input_latency.set_enabled(measure_input_latency);
+            }
+            if input_latency.enabled {
+                match input_latency.percentiles {
+                    Some(percentiles) => {
+                        ui.label(format!(
+                            "Input latency: p50 {}ms, p95 {}ms",
+                            percentiles.p50.as_millis(),
+                            percentiles.p95.as_millis()
+                        ));
+                    }
+                    None => {
+                        ui.label("Input latency: collecting samples...");
+                    }
+                }
+                ui.label(format!("Lost inputs: {}", input_latency.lost_inputs));
+            }
+            ui.separator();
+            let mut record_commands = command_log.is_enabled();
+            if ui
+                .checkbox(&mut record_commands, "Record commands")
+                .changed()
+            {
+                command_log.set_enabled(record_commands);
+            }
+            ui.horizontal(|ui| {
+                ui.label(format!("Recorded commands: {}", command_log.len()));
+                if ui.button("Dump").clicked() {
+                    let dump = command_log.dump();
+                    log::info!("Recorded commands:\n{}", dump);
+                    ui.output().copied_text = dump;
+                }
+                if ui.button("Clear").clicked() {
+                    command_log.clear();
+                }
+            });
         });
     }
 }
 
+#[cfg(feature = "time_dilation")]
+pub fn time_dilation_ui_system(
+    // ResMut is intentional, to avoid fighting over the Mutex from different systems.
+    mut egui_context: ResMut<EguiContext>,
+    debug_ui_state: Res<DebugUiState>,
+    mut time_dilation: ResMut<TimeDilation>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let ctx = egui_context.ctx_mut();
+
+    if !debug_ui_state.show {
+        return;
+    }
+
+    egui::Window::new("Time dilation").show(ctx, |ui| {
+        ui.add(
+            egui::Slider::new(
+                &mut time_dilation.tick_rate_factor,
+                MIN_TICK_RATE_FACTOR..=MAX_TICK_RATE_FACTOR,
+            )
+            .text("Tick rate factor"),
+        );
+        let mut delay_millis = time_dilation.delay.as_millis() as u64;
+        if ui
+            .add(egui::Slider::new(&mut delay_millis, 0..=1000).text("Delta update delay, ms"))
+            .changed()
+        {
+            time_dilation.delay = std::time::Duration::from_millis(delay_millis);
+        }
+        let mut jitter_millis = time_dilation.jitter.as_millis() as u64;
+        if ui
+            .add(egui::Slider::new(&mut jitter_millis, 0..=500).text("Delta update jitter, ms"))
+            .changed()
+        {
+            time_dilation.jitter = std::time::Duration::from_millis(jitter_millis);
+        }
+        ui.label(format!(
+            "Delayed updates: {}",
+            time_dilation.delayed_updates_count()
+        ));
+        if ui.button("Reset").clicked() {
+            time_dilation.reset_settings();
+        }
+    });
+}
+
+#[cfg(debug_assertions)]
+pub fn network_conditioner_ui_system(
+    // ResMut is intentional, to avoid fighting over the Mutex from different systems.
+    mut egui_context: ResMut<EguiContext>,
+    debug_ui_state: Res<DebugUiState>,
+    mut network_conditioner: ResMut<NetworkConditioner>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let ctx = egui_context.ctx_mut();
+
+    if !debug_ui_state.show {
+        return;
+    }
+
+    egui::Window::new("Network conditioner").show(ctx, |ui| {
+        ui.checkbox(&mut network_conditioner.enabled, "Enabled");
+        let conditions = &mut network_conditioner.conditions;
+        let mut latency_millis = conditions.latency.as_millis() as u64;
+        if ui
+            .add(
+                egui::Slider::new(&mut latency_millis, 0..=MAX_LATENCY_MILLIS)
+                    .text("One-way latency, ms"),
+            )
+            .changed()
+        {
+            conditions.latency = std::time::Duration::from_millis(latency_millis);
+        }
+        let mut jitter_millis = conditions.jitter.as_millis() as u64;
+        if ui
+            .add(egui::Slider::new(&mut jitter_millis, 0..=MAX_JITTER_MILLIS).text("Jitter, ms"))
+            .changed()
+        {
+            conditions.jitter = std::time::Duration::from_millis(jitter_millis);
+        }
+        ui.add(egui::Slider::new(&mut conditions.packet_loss, 0.0..=1.0).text("Packet loss"));
+        ui.add(egui::Slider::new(&mut conditions.reordering, 0.0..=1.0).text("Reordering"));
+
+        let (delayed_incoming, delayed_outgoing) = network_conditioner.delayed_messages_count();
+        ui.label(format!(
+            "Delayed messages: {delayed_incoming} incoming, {delayed_outgoing} outgoing"
+        ));
+        let (dropped_incoming, dropped_outgoing) = network_conditioner.dropped_messages_count();
+        ui.label(format!(
+            "Dropped messages: {dropped_incoming} incoming, {dropped_outgoing} outgoing"
+        ));
+        if ui.button("Reset").clicked() {
+            network_conditioner.reset_settings();
+        }
+    });
+}
+
 #[derive(SystemParam)]
 pub struct InspectObjectQueries<'w, 's> {
     players: Res<'w, Players>,
@@ -229,6 +502,7 @@ pub fn inspect_object_system(
     // ResMut is intentional, to avoid fighting over the Mutex from different systems.
     debug_ui_state: Res<DebugUiState>,
     mut egui_context: ResMut<EguiContext>,
+    mut framebuffer_inspector: ResMut<FramebufferInspector>,
     mut mouse_entity_picker: MouseEntityPicker<(), ()>,
     queries: InspectObjectQueries,
 ) {
@@ -245,6 +519,7 @@ pub fn inspect_object_system(
 
     if let Some(mut entity) = mouse_entity_picker.picked_entity() {
         egui::Window::new("Inspect").show(ctx, |ui| {
+            let mut label = None;
             if let Some(player_name) = queries
                 .player_registry
                 .get_id(entity)
@@ -252,6 +527,7 @@ pub fn inspect_object_system(
                 .map(|player| player.nickname.clone())
             {
                 ui.label(format!("Player name: {player_name}"));
+                label = Some(player_name);
             }
             ui.label(format!("Entity: {entity:?}"));
             if let Ok(LevelObjectStaticGhostParent(parent_entity)) =
@@ -262,10 +538,11 @@ pub fn inspect_object_system(
             if let Some(level_object_label) = queries
                 .objects_registry
                 .get_id(entity)
-                .and_then(|object_net_id| queries.level_state.objects.get(&object_net_id))
+                .and_then(|object_net_id| queries.level_state.object(object_net_id))
                 .map(|level_object| level_object.label.clone())
             {
-                ui.label(level_object_label);
+                ui.label(level_object_label.as_str());
+                label = Some(level_object_label);
             }
             if let Ok((level_object_movement, level_object_server_ghost)) =
                 queries.level_objects.get(entity)
@@ -290,10 +567,90 @@ pub fn inspect_object_system(
             if let Ok(player_direction) = queries.player_directions.get(entity) {
                 player_direction.inspect(ui);
             }
+            let mut show_framebuffers = framebuffer_inspector.is_selected(entity);
+            if ui
+                .checkbox(&mut show_framebuffers, "Show framebuffers")
+                .changed()
+            {
+                framebuffer_inspector
+                    .toggle(entity, label.unwrap_or_else(|| format!("{entity:?}")));
+            }
         });
     }
 }
 
+fn frame_timeline_plot(ui: &mut egui::Ui, frame_timeline: &mut FrameTimeline) {
+    ui.add(
+        egui::Slider::new(&mut frame_timeline.window_secs, 1..=FRAME_TIMELINE_MAX_SECS)
+            .text("Seconds"),
+    );
+
+    let now = Instant::now();
+    let line = |name: &str, value: fn(&FrameTimelineSample) -> f64| {
+        Line::new(
+            frame_timeline
+                .window(now)
+                .map(|(age, sample)| [age, value(sample)])
+                .collect::<PlotPoints>(),
+        )
+        .name(name)
+    };
+    let rewinds = frame_timeline
+        .window(now)
+        .filter_map(|(age, sample)| sample.rewind_depth.map(|depth| (age, depth)))
+        .collect::<Vec<_>>();
+    ui.label(format!(
+        "Rewinds: {} (deepest: {} frames)",
+        rewinds.len(),
+        rewinds.iter().map(|(_, depth)| *depth).max().unwrap_or(0)
+    ));
+
+    Plot::new("frame_timeline")
+        .height(200.0)
+        .legend(Legend::default())
+        .include_x(-(frame_timeline.window_secs as f64))
+        .include_x(0.0)
+        .include_y(0.0)
+        .show(ui, |plot_ui| {
+            plot_ui.line(line("Player frame - estimated server frame", |sample| {
+                sample.player_frame_offset as f64
+            }));
+            plot_ui.line(line(
+                "Local server frame - estimated server frame",
+                |sample| sample.server_frame_offset as f64,
+            ));
+            plot_ui.line(line("Target frames ahead", |sample| {
+                sample.target_frames_ahead as f64
+            }));
+            plot_ui.line(line("Jitter buffer length", |sample| {
+                sample.jitter_buffer_len as f64
+            }));
+            for (age, _) in rewinds {
+                plot_ui.vline(VLine::new(age).name("Rewinds").color(REWIND_COLOR));
+            }
+        });
+}
+
+fn memory_breakdown(ui: &mut egui::Ui, memory_budget: &MemoryBudget) {
+    egui::Grid::new("memory_breakdown")
+        .striped(true)
+        .show(ui, |ui| {
+            for (label, bytes) in memory_budget.latest.breakdown() {
+                ui.label(label);
+                ui.label(format_bytes(bytes));
+                ui.end_row();
+            }
+            ui.strong("Total");
+            ui.strong(format_bytes(memory_budget.latest.total()));
+            ui.end_row();
+        });
+    ui.label(format!("Growth alarms: {}", memory_budget.alarms));
+    ui.label(format!(
+        "Trimmed: {} player update buffers, {} pending requests",
+        memory_budget.trimmed_player_updates, memory_budget.trimmed_requests
+    ));
+}
+
 fn graph(
     ui: &mut egui::Ui,
     history: &VecDeque<DiagnosticMeasurement>,
@@ -362,3 +719,56 @@ fn graph(
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(at: Instant) -> FrameTimelineSample {
+        FrameTimelineSample {
+            at,
+            player_frame_offset: 10,
+            server_frame_offset: -2,
+            target_frames_ahead: 12,
+            jitter_buffer_len: 4,
+            rewind_depth: None,
+        }
+    }
+
+    #[test]
+    fn test_frame_offset() {
+        assert_eq!(frame_offset(FrameNumber::new(10), FrameNumber::new(4)), 6);
+        assert_eq!(frame_offset(FrameNumber::new(4), FrameNumber::new(10)), -6);
+        // Frame numbers wrap.
+        assert_eq!(
+            frame_offset(FrameNumber::new(2), FrameNumber::new(u16::MAX)),
+            3
+        );
+        assert_eq!(
+            frame_offset(FrameNumber::new(u16::MAX), FrameNumber::new(2)),
+            -3
+        );
+    }
+
+    #[test]
+    fn test_frame_timeline_window() {
+        let start = Instant::now();
+        let mut frame_timeline = FrameTimeline::default();
+        for secs in 0..=FRAME_TIMELINE_MAX_SECS + 5 {
+            frame_timeline.push(sample(start + Duration::from_secs(secs)));
+        }
+        // The history is limited.
+        assert_eq!(
+            frame_timeline.samples.len() as u64,
+            FRAME_TIMELINE_MAX_SECS + 1
+        );
+
+        let now = start + Duration::from_secs(FRAME_TIMELINE_MAX_SECS + 5);
+        frame_timeline.window_secs = 3;
+        let ages = frame_timeline
+            .window(now)
+            .map(|(age, _)| age)
+            .collect::<Vec<_>>();
+        assert_eq!(ages, vec![-3.0, -2.0, -1.0, 0.0]);
+    }
+}
@@ -0,0 +1,427 @@
+//! Shows the framebuffer history of the entities that are picked in the debug
+//! "Inspect" window. Rows are aligned to the frame that an entity is simulated
+//! at (`SimulationTime::player_frame` for `PlayerFrameSimulated` entities,
+//! `SimulationTime::server_frame` for the rest), so that values left ahead of
+//! the simulation after a rewind stand out.
+
+use crate::ui::debug_ui::{frame_offset, DebugUiState};
+use bevy::ecs::{
+    entity::Entity,
+    system::{Query, Res, ResMut, Resource},
+};
+use bevy_egui::{
+    egui,
+    egui::plot::{Legend, Line, Plot, PlotPoints, VLine},
+    EguiContext,
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::components::{PlayerDirection, PlayerFrameSimulated, Position, Spawned},
+    SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT,
+};
+
+const AHEAD_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 90, 60);
+const PLOT_HEIGHT: f32 = 100.0;
+const TABLE_HEIGHT: f32 = 200.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotRow {
+    pub frame_number: FrameNumber,
+    /// Relative to the frame the entity is simulated at, positive offsets are
+    /// ahead of the simulation.
+    pub offset: i32,
+    /// `None` stands for a missing value, such as a missing network input.
+    pub values: Vec<Option<f32>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComponentSnapshot {
+    pub name: &'static str,
+    pub columns: &'static [&'static str],
+    /// Are ordered from the oldest frame to the latest one.
+    pub rows: Vec<SnapshotRow>,
+}
+
+impl ComponentSnapshot {
+    fn new(
+        name: &'static str,
+        columns: &'static [&'static str],
+        simulation_frame: FrameNumber,
+        values: impl Iterator<Item = (FrameNumber, Vec<Option<f32>>)>,
+    ) -> Self {
+        Self {
+            name,
+            columns,
+            rows: values
+                .map(|(frame_number, values)| SnapshotRow {
+                    frame_number,
+                    offset: frame_offset(frame_number, simulation_frame),
+                    values,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn frames_ahead(&self) -> usize {
+        self.rows.iter().filter(|row| row.offset > 0).count()
+    }
+}
+
+/// Copies the history of a component, so that it can still be inspected after
+/// the simulation moves on.
+pub trait FramebufferSnapshot {
+    fn snapshot(&self, simulation_frame: FrameNumber) -> ComponentSnapshot;
+}
+
+impl FramebufferSnapshot for Position {
+    fn snapshot(&self, simulation_frame: FrameNumber) -> ComponentSnapshot {
+        ComponentSnapshot::new(
+            "Position",
+            &["x", "y"],
+            simulation_frame,
+            self.buffer
+                .iter()
+                .map(|(frame_number, value)| (frame_number, vec![Some(value.x), Some(value.y)])),
+        )
+    }
+}
+
+impl FramebufferSnapshot for PlayerDirection {
+    fn snapshot(&self, simulation_frame: FrameNumber) -> ComponentSnapshot {
+        ComponentSnapshot::new(
+            "Direction",
+            &["x", "y"],
+            simulation_frame,
+            self.buffer.iter().map(|(frame_number, value)| {
+                (
+                    frame_number,
+                    vec![value.map(|value| value.x), value.map(|value| value.y)],
+                )
+            }),
+        )
+    }
+}
+
+impl FramebufferSnapshot for Spawned {
+    /// `Spawned` keeps commands instead of a framebuffer, so it's sampled for
+    /// the frames that component framebuffers can hold.
+    fn snapshot(&self, simulation_frame: FrameNumber) -> ComponentSnapshot {
+        ComponentSnapshot::new(
+            "Spawned",
+            &["spawned"],
+            simulation_frame,
+            (0..COMPONENT_FRAMEBUFFER_LIMIT).rev().map(|frames_ago| {
+                let frame_number = simulation_frame - FrameNumber::new(frames_ago);
+                let spawned = if self.is_spawned(frame_number) {
+                    1.0
+                } else {
+                    0.0
+                };
+                (frame_number, vec![Some(spawned)])
+            }),
+        )
+    }
+}
+
+pub struct EntitySnapshot {
+    pub entity: Entity,
+    pub label: String,
+    pub simulation_frame: FrameNumber,
+    pub components: Vec<ComponentSnapshot>,
+}
+
+/// Entities are selected in the "Inspect" window. Snapshots are refreshed
+/// every frame unless the inspector is frozen.
+#[derive(Resource, Default)]
+pub struct FramebufferInspector {
+    selected: Vec<(Entity, String)>,
+    snapshots: Vec<EntitySnapshot>,
+    pub frozen: bool,
+}
+
+impl FramebufferInspector {
+    pub fn is_selected(&self, entity: Entity) -> bool {
+        self.selected
+            .iter()
+            .any(|(selected_entity, _)| *selected_entity == entity)
+    }
+
+    pub fn toggle(&mut self, entity: Entity, label: String) {
+        if self.is_selected(entity) {
+            self.deselect(entity);
+        } else {
+            self.selected.push((entity, label));
+        }
+    }
+
+    pub fn deselect(&mut self, entity: Entity) {
+        self.selected
+            .retain(|(selected_entity, _)| *selected_entity != entity);
+        self.snapshots.retain(|snapshot| snapshot.entity != entity);
+    }
+
+    pub fn clear(&mut self) {
+        self.selected.clear();
+        self.snapshots.clear();
+    }
+}
+
+pub fn framebuffer_inspector_system(
+    // ResMut is intentional, to avoid fighting over the Mutex from different systems.
+    mut egui_context: ResMut<EguiContext>,
+    debug_ui_state: Res<DebugUiState>,
+    mut inspector: ResMut<FramebufferInspector>,
+    time: Res<SimulationTime>,
+    components: Query<(
+        Option<&Position>,
+        Option<&PlayerDirection>,
+        Option<&Spawned>,
+        Option<&PlayerFrameSimulated>,
+    )>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !debug_ui_state.show || inspector.selected.is_empty() {
+        return;
+    }
+
+    let inspector = &mut *inspector;
+    if !inspector.frozen {
+        // Despawned entities are forgotten, unless their snapshots are frozen.
+        inspector
+            .selected
+            .retain(|(entity, _)| components.contains(*entity));
+        inspector.snapshots = inspector
+            .selected
+            .iter()
+            .filter_map(|(entity, label)| {
+                let (position, player_direction, spawned, player_frame_simulated) =
+                    components.get(*entity).ok()?;
+                let simulation_frame = if player_frame_simulated.is_some() {
+                    time.player_frame
+                } else {
+                    time.server_frame
+                };
+                let components = [
+                    position.map(|c| c as &dyn FramebufferSnapshot),
+                    player_direction.map(|c| c as &dyn FramebufferSnapshot),
+                    spawned.map(|c| c as &dyn FramebufferSnapshot),
+                ]
+                .into_iter()
+                .flatten()
+                .map(|component| component.snapshot(simulation_frame))
+                .collect();
+                Some(EntitySnapshot {
+                    entity: *entity,
+                    label: label.clone(),
+                    simulation_frame,
+                    components,
+                })
+            })
+            .collect();
+    }
+
+    let mut deselected = None;
+    let mut clear = false;
+    egui::Window::new("Framebuffers")
+        .default_width(480.0)
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut inspector.frozen, "Freeze");
+                clear = ui.button("Clear").clicked();
+            });
+            for entity_snapshot in &inspector.snapshots {
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.strong(format!(
+                        "{} ({:?})",
+                        entity_snapshot.label, entity_snapshot.entity
+                    ));
+                    if ui.small_button("✖").clicked() {
+                        deselected = Some(entity_snapshot.entity);
+                    }
+                });
+                ui.label(format!(
+                    "Simulation frame: {}",
+                    entity_snapshot.simulation_frame
+                ));
+                for component in &entity_snapshot.components {
+                    component_snapshot_ui(ui, entity_snapshot.entity, component);
+                }
+            }
+        });
+
+    if clear {
+        inspector.clear();
+    } else if let Some(entity) = deselected {
+        inspector.deselect(entity);
+    }
+}
+
+fn component_snapshot_ui(ui: &mut egui::Ui, entity: Entity, snapshot: &ComponentSnapshot) {
+    let frames_ahead = snapshot.frames_ahead();
+    let header = if frames_ahead > 0 {
+        egui::RichText::new(format!(
+            "{} ({} frames, {} ahead)",
+            snapshot.name,
+            snapshot.rows.len(),
+            frames_ahead
+        ))
+        .color(AHEAD_COLOR)
+    } else {
+        egui::RichText::new(format!(
+            "{} ({} frames)",
+            snapshot.name,
+            snapshot.rows.len()
+        ))
+    };
+
+    egui::CollapsingHeader::new(header)
+        .id_source((entity, snapshot.name))
+        .default_open(false)
+        .show(ui, |ui| {
+            Plot::new((entity, snapshot.name, "plot"))
+                .height(PLOT_HEIGHT)
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    for (i, column) in snapshot.columns.iter().enumerate() {
+                        plot_ui.line(
+                            Line::new(
+                                snapshot
+                                    .rows
+                                    .iter()
+                                    .filter_map(|row| {
+                                        row.values[i].map(|value| [row.offset as f64, value as f64])
+                                    })
+                                    .collect::<PlotPoints>(),
+                            )
+                            .name(column),
+                        );
+                    }
+                    plot_ui.vline(VLine::new(0.0).name("Simulation frame"));
+                });
+
+            let row_height = ui.text_style_height(&egui::TextStyle::Body);
+            egui::ScrollArea::vertical()
+                .id_source((entity, snapshot.name, "table"))
+                .max_height(TABLE_HEIGHT)
+                .show_rows(ui, row_height, snapshot.rows.len(), |ui, row_range| {
+                    egui::Grid::new((entity, snapshot.name, "grid"))
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Frame");
+                            ui.strong("Offset");
+                            for column in snapshot.columns {
+                                ui.strong(*column);
+                            }
+                            ui.end_row();
+
+                            // The latest frames go first.
+                            for row in snapshot
+                                .rows
+                                .iter()
+                                .rev()
+                                .skip(row_range.start)
+                                .take(row_range.len())
+                            {
+                                let color = (row.offset > 0).then_some(AHEAD_COLOR);
+                                let cell = |ui: &mut egui::Ui, text: String| {
+                                    let text = egui::RichText::new(text);
+                                    ui.label(match color {
+                                        Some(color) => text.color(color),
+                                        None => text,
+                                    });
+                                };
+                                cell(ui, row.frame_number.value().to_string());
+                                cell(ui, format!("{:+}", row.offset));
+                                for value in &row.values {
+                                    cell(
+                                        ui,
+                                        value.map_or_else(
+                                            || "None".to_owned(),
+                                            |value| format!("{value:.2}"),
+                                        ),
+                                    );
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::Vec2;
+    use mr_shared_lib::game::{commands::DespawnReason, components::SpawnCommand};
+
+    #[test]
+    fn test_snapshot_alignment() {
+        let mut position = Position::new(Vec2::ZERO, FrameNumber::new(10), 0);
+        for i in 0..5 {
+            position.buffer.push(Vec2::new(i as f32, -(i as f32)));
+        }
+        let snapshot = position.snapshot(FrameNumber::new(12));
+        assert_eq!(
+            snapshot
+                .rows
+                .iter()
+                .map(|row| (row.frame_number.value(), row.offset))
+                .collect::<Vec<_>>(),
+            vec![(10, -2), (11, -1), (12, 0), (13, 1), (14, 2)]
+        );
+        assert_eq!(snapshot.rows[3].values, vec![Some(3.0), Some(-3.0)]);
+        assert_eq!(snapshot.frames_ahead(), 2);
+
+        let mut player_direction = PlayerDirection::new(Vec2::X, FrameNumber::new(0), 1);
+        player_direction.buffer.push(None);
+        let snapshot = player_direction.snapshot(FrameNumber::new(1));
+        assert_eq!(snapshot.rows[0].values, vec![Some(1.0), Some(0.0)]);
+        assert_eq!(snapshot.rows[1].values, vec![None, None]);
+        assert_eq!(snapshot.frames_ahead(), 0);
+    }
+
+    #[test]
+    fn test_spawned_snapshot() {
+        let mut spawned = Spawned::new(FrameNumber::new(5));
+        spawned.push_command(
+            FrameNumber::new(8),
+            SpawnCommand::Despawn(DespawnReason::DeathOrFinish),
+        );
+        let snapshot = spawned.snapshot(FrameNumber::new(10));
+        assert_eq!(snapshot.rows.len(), COMPONENT_FRAMEBUFFER_LIMIT as usize);
+        let latest = &snapshot.rows[snapshot.rows.len() - 7..];
+        assert_eq!(
+            latest
+                .iter()
+                .map(|row| (row.frame_number.value(), row.values[0]))
+                .collect::<Vec<_>>(),
+            vec![
+                (4, Some(0.0)),
+                (5, Some(1.0)),
+                (6, Some(1.0)),
+                (7, Some(1.0)),
+                (8, Some(0.0)),
+                (9, Some(0.0)),
+                (10, Some(0.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_framebuffer_inspector_selection() {
+        let mut inspector = FramebufferInspector::default();
+        let entity = Entity::from_raw(1);
+        inspector.toggle(entity, "Player".to_owned());
+        inspector.toggle(Entity::from_raw(2), "Cube".to_owned());
+        assert!(inspector.is_selected(entity));
+
+        inspector.toggle(entity, "Player".to_owned());
+        assert!(!inspector.is_selected(entity));
+        assert_eq!(inspector.selected.len(), 1);
+
+        inspector.clear();
+        assert!(inspector.selected.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::input::{InputAction, InputBinding, KeyBindings, MAX_BINDINGS_PER_ACTION};
+use bevy::{
+    ecs::system::{ResMut, Resource},
+    input::{keyboard::KeyCode, mouse::MouseButton, Input},
+};
+use bevy_egui::{egui, EguiContext};
+
+const CONFLICT_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 120, 80);
+
+/// The screen can be opened from the main menu as well, thus it's not a local
+/// state of the system.
+#[derive(Resource, Default)]
+pub struct KeyBindingsUiState {
+    pub show: bool,
+}
+
+pub fn key_bindings_ui_system(
+    mut state: ResMut<KeyBindingsUiState>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut mouse_button_input: ResMut<Input<MouseButton>>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let ctx = egui_context.ctx_mut();
+
+    if let Some((action, slot)) = key_bindings.capturing() {
+        // Mouse buttons are captured only outside of the window, so that the
+        // cancel button keeps working.
+        let binding = keyboard_input
+            .get_just_pressed()
+            .next()
+            .copied()
+            .map(InputBinding::Key)
+            .or_else(|| {
+                mouse_button_input
+                    .get_just_pressed()
+                    .next()
+                    .copied()
+                    .filter(|_| !ctx.is_pointer_over_area())
+                    .map(InputBinding::Mouse)
+            });
+        if let Some(binding) = binding {
+            // The key mustn't trigger the actions it was bound to before in the
+            // same frame.
+            match binding {
+                InputBinding::Key(key_code) => keyboard_input.reset(key_code),
+                InputBinding::Mouse(button) => mouse_button_input.reset(button),
+            }
+            key_bindings.cancel_capture();
+            key_bindings.bind(action, slot, binding);
+            key_bindings.save();
+        }
+    } else if !ctx.wants_keyboard_input()
+        && key_bindings.just_pressed(
+            InputAction::ToggleKeyBindings,
+            &keyboard_input,
+            &mouse_button_input,
+        )
+    {
+        state.show = !state.show;
+    }
+
+    if !state.show {
+        if key_bindings.capturing().is_some() {
+            key_bindings.cancel_capture();
+        }
+        return;
+    }
+
+    let mut show = state.show;
+    egui::Window::new("Key bindings")
+        .open(&mut show)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let capturing = key_bindings.capturing();
+            let mut changed = false;
+            egui::Grid::new("key_bindings_grid")
+                .num_columns(MAX_BINDINGS_PER_ACTION + 2)
+                .striped(true)
+                .show(ui, |ui| {
+                    for action in InputAction::ALL {
+                        ui.label(action.name());
+                        let bindings = key_bindings.bindings(action);
+                        for slot in 0..MAX_BINDINGS_PER_ACTION {
+                            let binding = bindings.get(slot).copied();
+                            let conflicts = binding
+                                .map(|binding| key_bindings.conflicts(action, binding))
+                                .unwrap_or_default();
+                            let button = if capturing == Some((action, slot)) {
+                                egui::Button::new("Press a key...")
+                            } else if let Some(binding) = binding {
+                                let mut text = egui::RichText::new(binding.to_string());
+                                if !conflicts.is_empty() {
+                                    text = text.color(CONFLICT_COLOR);
+                                }
+                                egui::Button::new(text)
+                            } else {
+                                egui::Button::new("-")
+                            };
+                            // Slots are filled in order.
+                            let mut response = ui
+                                .add_enabled(slot <= bindings.len() && capturing.is_none(), button);
+                            if !conflicts.is_empty() {
+                                let names = conflicts
+                                    .iter()
+                                    .map(|action| action.name())
+                                    .collect::<Vec<_>>()
+                                    .join(", ");
+                                response =
+                                    response.on_hover_text(format!("Also bound to: {names}"));
+                            }
+                            if response.clicked() {
+                                key_bindings.start_capture(action, slot);
+                            } else if response.secondary_clicked() {
+                                key_bindings.unbind(action, slot);
+                                changed = true;
+                            }
+                        }
+                        if ui
+                            .add_enabled(
+                                bindings != action.default_bindings() && capturing.is_none(),
+                                egui::Button::new("Reset"),
+                            )
+                            .clicked()
+                        {
+                            key_bindings.reset(action);
+                            changed = true;
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            ui.separator();
+            if capturing.is_some() {
+                ui.horizontal(|ui| {
+                    ui.label("Press a key, or click outside of this window to bind a mouse button");
+                    if ui.button("Cancel").clicked() {
+                        key_bindings.cancel_capture();
+                    }
+                });
+            } else {
+                ui.horizontal(|ui| {
+                    ui.label("Click to rebind, right-click to unbind");
+                    if ui.button("Reset all").clicked() {
+                        key_bindings.reset_all();
+                        changed = true;
+                    }
+                });
+            }
+            if changed {
+                key_bindings.save();
+            }
+        });
+    state.show = show;
+}
+
+/// Makes the screen available from the main menu.
+pub fn key_bindings_button(
+    ui: &mut egui::Ui,
+    state: &mut KeyBindingsUiState,
+    key_bindings: &KeyBindings,
+) {
+    let label = format!(
+        "Key bindings [{}]",
+        key_bindings.label(InputAction::ToggleKeyBindings)
+    );
+    if ui.button(label).clicked() {
+        state.show = !state.show;
+    }
+}
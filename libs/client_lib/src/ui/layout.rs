@@ -0,0 +1,327 @@
+//! Runners, builders and spectators need different HUDs: the leaderboard and
+//! tethers are of little use while editing a level, and a close camera makes
+//! editing harder. Each role has its own layout preset, which gets applied as
+//! soon as the current player switches roles.
+
+use crate::{
+    camera::CameraMode,
+    config_storage::{self, UiLayoutConfig, UI_LAYOUT_CONFIG_KEY},
+    helpers::PlayerParams,
+    input::{ActionInput, InputAction},
+};
+use bevy::{
+    ecs::system::{Local, Res, ResMut, Resource},
+    log,
+    math::Vec3,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::player::PlayerRole;
+use serde::{Deserialize, Serialize};
+
+pub const MIN_SIDE_PANEL_WIDTH: f32 = 150.0;
+pub const MAX_SIDE_PANEL_WIDTH: f32 = 500.0;
+
+/// Panels that are specific to the other role aren't shown regardless of
+/// these flags.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LayoutPanels {
+    pub leaderboard: bool,
+    pub help: bool,
+    pub tethers: bool,
+    /// Runners only.
+    pub practice_bots: bool,
+    /// Builders only.
+    pub publishing: bool,
+    /// Builders only.
+    pub audio_clips: bool,
+}
+
+impl Default for LayoutPanels {
+    fn default() -> Self {
+        Self {
+            leaderboard: true,
+            help: true,
+            tethers: true,
+            practice_bots: true,
+            publishing: true,
+            audio_clips: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LayoutPreset {
+    #[serde(default)]
+    pub panels: LayoutPanels,
+    /// The minimum width of the leaderboard and the builder menu.
+    pub side_panel_width: f32,
+    #[serde(default)]
+    pub camera_mode: CameraMode,
+}
+
+impl LayoutPreset {
+    pub fn runner() -> Self {
+        Self {
+            panels: LayoutPanels::default(),
+            side_panel_width: 200.0,
+            camera_mode: CameraMode::Close,
+        }
+    }
+
+    pub fn builder() -> Self {
+        Self {
+            panels: LayoutPanels {
+                leaderboard: false,
+                tethers: false,
+                ..Default::default()
+            },
+            side_panel_width: 300.0,
+            camera_mode: CameraMode::Overview,
+        }
+    }
+
+    pub fn spectator() -> Self {
+        Self {
+            panels: LayoutPanels::default(),
+            side_panel_width: 200.0,
+            camera_mode: CameraMode::Overview,
+        }
+    }
+
+    pub fn default_for(role: PlayerRole) -> Self {
+        match role {
+            PlayerRole::Runner => Self::runner(),
+            PlayerRole::Builder => Self::builder(),
+            PlayerRole::Spectator => Self::spectator(),
+        }
+    }
+
+    /// The config can be edited by hand, so the width is clamped when reading.
+    fn sanitize(&mut self) {
+        self.side_panel_width = if self.side_panel_width.is_finite() {
+            self.side_panel_width
+                .clamp(MIN_SIDE_PANEL_WIDTH, MAX_SIDE_PANEL_WIDTH)
+        } else {
+            MIN_SIDE_PANEL_WIDTH
+        };
+    }
+}
+
+#[derive(Resource)]
+pub struct UiLayout {
+    config: UiLayoutConfig,
+    /// The role of the current player, runners' preset is used when there's
+    /// no player yet.
+    role: PlayerRole,
+}
+
+impl Default for UiLayout {
+    fn default() -> Self {
+        Self {
+            config: UiLayoutConfig::default(),
+            role: PlayerRole::Runner,
+        }
+    }
+}
+
+impl UiLayout {
+    pub fn role(&self) -> PlayerRole {
+        self.role
+    }
+
+    pub fn active(&self) -> &LayoutPreset {
+        self.preset(self.role)
+    }
+
+    pub fn preset(&self, role: PlayerRole) -> &LayoutPreset {
+        match role {
+            PlayerRole::Runner => &self.config.runner,
+            PlayerRole::Builder => &self.config.builder,
+            PlayerRole::Spectator => &self.config.spectator,
+        }
+    }
+
+    pub fn camera_offset(&self) -> Vec3 {
+        self.active().camera_mode.offset()
+    }
+
+    /// Changes the preset and saves it to the settings.
+    pub fn set_preset(&mut self, role: PlayerRole, preset: LayoutPreset) {
+        match role {
+            PlayerRole::Runner => self.config.runner = preset,
+            PlayerRole::Builder => self.config.builder = preset,
+            PlayerRole::Spectator => self.config.spectator = preset,
+        }
+        if let Err(err) = config_storage::write(UI_LAYOUT_CONFIG_KEY, &self.config) {
+            log::error!("Failed to save the UI layout config: {:?}", err);
+        }
+    }
+
+    pub fn reset_preset(&mut self, role: PlayerRole) {
+        self.set_preset(role, LayoutPreset::default_for(role));
+    }
+}
+
+pub fn read_ui_layout_config_system(mut ui_layout: ResMut<UiLayout>) {
+    match config_storage::read::<UiLayoutConfig>(UI_LAYOUT_CONFIG_KEY) {
+        Ok(mut config) => {
+            config.runner.sanitize();
+            config.builder.sanitize();
+            config.spectator.sanitize();
+            ui_layout.config = config;
+        }
+        Err(err) => log::error!("Failed to read the UI layout config: {:?}", err),
+    }
+}
+
+pub fn switch_ui_layout_system(player_params: PlayerParams, mut ui_layout: ResMut<UiLayout>) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let role = player_params
+        .current_player()
+        .map_or(PlayerRole::Runner, |player| player.role);
+    if ui_layout.role != role {
+        log::debug!("Switching the UI layout to the {:?} preset", role);
+        ui_layout.role = role;
+    }
+}
+
+#[derive(Default)]
+pub struct LayoutUiState {
+    show: bool,
+    /// Defaults to the role of the current player.
+    edited_role: Option<PlayerRole>,
+}
+
+pub fn layout_ui_system(
+    mut state: Local<LayoutUiState>,
+    action_input: ActionInput,
+    mut egui_context: ResMut<EguiContext>,
+    mut ui_layout: ResMut<UiLayout>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if action_input.just_pressed(InputAction::ToggleLayoutSettings) {
+        state.show = !state.show;
+        state.edited_role = None;
+    }
+
+    if !state.show {
+        return;
+    }
+
+    let mut show = state.show;
+    let title = format!(
+        "Layout [{}]",
+        action_input
+            .key_bindings
+            .label(InputAction::ToggleLayoutSettings)
+    );
+    egui::Window::new(title)
+        .id(egui::Id::new("layout"))
+        .open(&mut show)
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let edited_role = state.edited_role.unwrap_or(ui_layout.role());
+            ui.horizontal(|ui| {
+                for (role, label) in [
+                    (PlayerRole::Runner, "Runner"),
+                    (PlayerRole::Builder, "Builder"),
+                    (PlayerRole::Spectator, "Spectator"),
+                ] {
+                    if ui.selectable_label(edited_role == role, label).clicked() {
+                        state.edited_role = Some(role);
+                    }
+                }
+            });
+            let edited_role = state.edited_role.unwrap_or(ui_layout.role());
+            ui.separator();
+
+            let mut preset = *ui_layout.preset(edited_role);
+            let panels = &mut preset.panels;
+            ui.checkbox(&mut panels.leaderboard, "Leaderboard");
+            ui.checkbox(&mut panels.help, "Help");
+            ui.checkbox(&mut panels.tethers, "Tethers");
+            match edited_role {
+                PlayerRole::Runner => {
+                    ui.checkbox(&mut panels.practice_bots, "Practice bots");
+                }
+                PlayerRole::Builder => {
+                    ui.checkbox(&mut panels.publishing, "Publishing");
+                    ui.checkbox(&mut panels.audio_clips, "Audio clips");
+                }
+                PlayerRole::Spectator => {}
+            }
+            ui.add(
+                egui::Slider::new(
+                    &mut preset.side_panel_width,
+                    MIN_SIDE_PANEL_WIDTH..=MAX_SIDE_PANEL_WIDTH,
+                )
+                .text("Side panel width"),
+            );
+            egui::ComboBox::from_label("Camera")
+                .selected_text(preset.camera_mode.label())
+                .show_ui(ui, |ui| {
+                    for camera_mode in CameraMode::ALL {
+                        ui.selectable_value(
+                            &mut preset.camera_mode,
+                            camera_mode,
+                            camera_mode.label(),
+                        );
+                    }
+                });
+            if preset != *ui_layout.preset(edited_role) {
+                ui_layout.set_preset(edited_role, preset);
+            }
+
+            ui.separator();
+            let is_default = preset == LayoutPreset::default_for(edited_role);
+            if ui
+                .add_enabled(!is_default, egui::Button::new("Reset to default"))
+                .clicked()
+            {
+                ui_layout.reset_preset(edited_role);
+            }
+        });
+    state.show = show;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ui_layout_config_defaults() {
+        let config: UiLayoutConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, UiLayoutConfig::default());
+
+        // Presets that are missing in the config fall back to the defaults of
+        // their roles.
+        let config: UiLayoutConfig = serde_json::from_str(
+            r#"{"runner": {"panels": {"leaderboard": false}, "side_panel_width": 250.0}}"#,
+        )
+        .unwrap();
+        assert!(!config.runner.panels.leaderboard);
+        assert!(config.runner.panels.help);
+        assert_eq!(config.runner.side_panel_width, 250.0);
+        assert_eq!(config.runner.camera_mode, CameraMode::Close);
+        assert_eq!(config.builder, LayoutPreset::builder());
+        assert_eq!(config.spectator, LayoutPreset::spectator());
+    }
+
+    #[test]
+    fn test_layout_preset_sanitize() {
+        let mut preset = LayoutPreset {
+            side_panel_width: 10_000.0,
+            ..LayoutPreset::runner()
+        };
+        preset.sanitize();
+        assert_eq!(preset.side_panel_width, MAX_SIDE_PANEL_WIDTH);
+
+        preset.side_panel_width = f32::NAN;
+        preset.sanitize();
+        assert_eq!(preset.side_panel_width, MIN_SIDE_PANEL_WIDTH);
+    }
+}
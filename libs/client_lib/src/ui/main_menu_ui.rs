@@ -1,16 +1,28 @@
 use crate::{
+    audio_cues::{AudioClipUploadStatus, AudioCues},
+    guest::Guest,
+    input::KeyBindings,
     net::{
-        auth::{AuthMessage, AuthRequest},
+        auth::{AuthConfig, AuthMessage, AuthRequest},
         MainMenuUiChannels, MatchmakerState, PersistenceMessagePayload, PersistenceRequest,
         ServerToConnect, TcpConnectionStatus,
     },
+    personal_bests::PersonalBests,
+    session_summary::{SessionReport, SessionSummary},
     ui::{
+        bug_report_ui::BugReportUiState,
+        key_bindings_ui::{key_bindings_button, KeyBindingsUiState},
+        player_ui::{format_finish, medal_icon},
+        theme::{backdrop_color, spacing, theme_selector, UiTheme},
         widgets::list_menu::{button_panel, MenuListItem, MenuListItemResponse, PanelButton},
         without_item_spacing,
     },
-    OfflineAuthConfig,
+    utils::preferred_languages,
+    MuddleClientConfig, OfflineAuthConfig,
 };
 use bevy::{
+    asset::Assets,
+    audio::AudioSource,
     ecs::{
         schedule::SystemSet,
         system::{Res, ResMut, Resource, SystemParam},
@@ -26,13 +38,14 @@ use bevy_egui::{
 };
 use iyes_loopless::prelude::*;
 use mr_messages_lib::{
-    GameServerState, GetLevelResponse, GetLevelsRequest, GetLevelsUserFilter, InitLevel,
-    LevelsListItem, LinkAccountLoginMethod, MatchmakerMessage, MatchmakerRequest, PaginationParams,
-    Server,
+    validation::{format_errors, validate_display_name, validate_level_title},
+    AllocationKind, ApiToken, ApiTokenScopes, FriendDto, FriendshipStatus, GameServerState,
+    GetLevelsSummaryRequest, GetLevelsUserFilter, InitLevel, LevelSummary, LevelsCursor,
+    LinkAccountLoginMethod, MatchmakerMessage, MatchmakerRequest, PostApiTokenRequest,
+    PrivacySettings, Server, PROTOCOL_VERSION,
 };
 use mr_shared_lib::net::MessageId;
 use std::{
-    collections::BTreeMap,
     marker::PhantomData,
     net::SocketAddr,
     ops::{Add, Mul, Sub},
@@ -43,15 +56,18 @@ use tokio::sync::mpsc::{error::TryRecvError, UnboundedSender};
 const ERROR_COLOR: egui::Color32 = egui::Color32::RED;
 const INVALID_EMAIL_ERROR: &str = "must be a valid email";
 const SHORT_PASSWORD_ERROR: &str = "must be 8 characters or longer";
-const EMPTY_DISPLAY_NAME_ERROR: &str = "must not be empty";
-const LONG_DISPLAY_NAME_ERROR: &str = "must not be shorter than 255 characters";
-const NON_ASCII_DISPLAY_NAME_ERROR: &str = "can contain only ASCII characters";
+// Friends' presence is updated by game servers every 30 seconds anyway.
+const FRIENDS_REFRESH_PERIOD: Duration = Duration::from_secs(15);
 
 pub struct AuthUiState {
     screen: AuthUiScreen,
     email: InputField,
     password: InputField,
     display_name: InputField,
+    guest_nickname: InputField,
+    /// Is false if the client is built without the auth credentials, in which
+    /// case only playing as a guest is available.
+    auth_is_available: bool,
     error_message: String,
     handler_is_ready: bool,
     pending_request: bool,
@@ -78,6 +94,11 @@ impl Default for AuthUiState {
                 label: "Display name",
                 ..Default::default()
             },
+            guest_nickname: InputField {
+                label: "Nickname (leave empty to get a random one)",
+                ..Default::default()
+            },
+            auth_is_available: true,
             error_message: "".to_owned(),
             handler_is_ready: false,
             pending_request: false,
@@ -93,6 +114,7 @@ impl AuthUiState {
         self.email.errors.clear();
         self.password.errors.clear();
         self.display_name.errors.clear();
+        self.guest_nickname.errors.clear();
 
         if !self.email.value.contains('@') {
             self.email.errors.push(INVALID_EMAIL_ERROR.to_owned());
@@ -102,21 +124,18 @@ impl AuthUiState {
             self.password.errors.push(SHORT_PASSWORD_ERROR.to_owned());
         }
 
-        let display_name = self.display_name.value.trim();
-        if display_name.is_empty() {
+        if let Err(errors) = validate_display_name(&self.display_name.value) {
             self.display_name
                 .errors
-                .push(EMPTY_DISPLAY_NAME_ERROR.to_owned());
+                .extend(errors.iter().map(ToString::to_string));
         }
-        if display_name.len() > 255 {
-            self.display_name
-                .errors
-                .push(LONG_DISPLAY_NAME_ERROR.to_owned());
-        }
-        if !display_name.is_ascii() {
-            self.display_name
-                .errors
-                .push(NON_ASCII_DISPLAY_NAME_ERROR.to_owned());
+
+        if !self.guest_nickname.value.trim().is_empty() {
+            if let Err(errors) = validate_display_name(&self.guest_nickname.value) {
+                self.guest_nickname
+                    .errors
+                    .extend(errors.iter().map(ToString::to_string));
+            }
         }
     }
 
@@ -130,10 +149,17 @@ impl AuthUiState {
         self.reset_form();
     }
 
+    /// Fills in the nickname the guest has used the last time.
+    pub fn switch_to_guest_nickname(&mut self, guest: &Guest) {
+        self.switch_screen(AuthUiScreen::GuestNickname);
+        self.guest_nickname.value = guest.nickname.clone().unwrap_or_default();
+    }
+
     pub fn reset_form(&mut self) {
         self.email.reset();
         self.password.reset();
         self.display_name.reset();
+        self.guest_nickname.reset();
         self.error_message.clear();
     }
 
@@ -214,6 +240,7 @@ pub enum AuthUiScreen {
     LinkAccount,
     SetDisplayName,
     GoogleOpenID,
+    GuestNickname,
 }
 
 impl Default for AuthUiScreen {
@@ -230,10 +257,11 @@ pub struct MatchmakerUiState {
     // Contains a sever name, as we don't want selection to jump every time a server is
     // added/removed.
     selected_server: Option<String>,
-    levels: BTreeMap<i64, LevelsListItem>,
+    /// Sorted by the last update time, most recent first.
+    levels: Vec<LevelSummary>,
+    levels_next_cursor: Option<LevelsCursor>,
     levels_list_filter: LevelsListFilter,
     selected_level: SelectedLevel,
-    selected_level_data: Option<GetLevelResponse>,
     screen: MatchmakerUiScreen,
     request_id_counter: MessageId,
     current_request_id: Option<MessageId>,
@@ -241,16 +269,70 @@ pub struct MatchmakerUiState {
     // We don't immediately send a request, we first wait for a `Ready` server to spin up.
     create_server_request_sent_at: Option<Instant>,
     request_error_message: Option<String>,
+    friends: FriendsUiState,
+    privacy: PrivacyUiState,
+    api_tokens: ApiTokensUiState,
+    /// The summary of the session the player has just left.
+    session_report: Option<SessionReport>,
+    /// Levels are listed with their titles localized to the first of these
+    /// languages that has a translation.
+    preferred_languages: Vec<String>,
 }
 
 impl MatchmakerUiState {
     fn has_ready_server(&self) -> bool {
         self.servers
             .values()
-            .any(|server| server.state == GameServerState::Ready)
+            .any(|server| server.state == GameServerState::Ready && server.is_compatible())
+    }
+
+    /// Returns the server if it's still running and there's room for one more
+    /// player.
+    fn joinable_server(&self, server_name: &str) -> Option<&Server> {
+        self.servers.get(server_name).filter(|server| {
+            server.state == GameServerState::Allocated
+                && server.is_compatible()
+                && server.player_count < server.player_capacity
+        })
     }
 }
 
+#[derive(Default)]
+pub struct FriendsUiState {
+    friends: Vec<FriendDto>,
+    selected_friend: Option<i64>,
+    new_friend_name: String,
+    // Friends requests don't block the rest of the matchmaker UI, so we track them
+    // separately.
+    current_request_id: Option<MessageId>,
+    requested_at: Option<Instant>,
+    request_error_message: Option<String>,
+}
+
+#[derive(Default)]
+pub struct PrivacyUiState {
+    /// The settings that are stored by the persistence service, is `None` until
+    /// fetched.
+    saved_settings: Option<PrivacySettings>,
+    edited_settings: PrivacySettings,
+    current_request_id: Option<MessageId>,
+    request_error_message: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ApiTokensUiState {
+    /// Is `None` until fetched.
+    tokens: Option<Vec<ApiToken>>,
+    selected_token: Option<i64>,
+    new_token_name: String,
+    new_token_scopes: ApiTokenScopes,
+    /// The secret of the token that has just been issued. The persistence
+    /// service stores only its hash, so it can't be shown again.
+    issued_secret: Option<String>,
+    current_request_id: Option<MessageId>,
+    request_error_message: Option<String>,
+}
+
 #[derive(Clone, PartialEq, Eq)]
 enum SelectedLevel {
     NewLevel(String),
@@ -268,6 +350,10 @@ impl Default for SelectedLevel {
 pub enum MatchmakerUiScreen {
     ServersList,
     CreateServer,
+    Friends,
+    Privacy,
+    ApiTokens,
+    SessionSummary,
 }
 
 impl Default for MatchmakerUiScreen {
@@ -308,15 +394,20 @@ impl MainMenuUiState {
                 connect_manually_ip_addr,
                 selected_server: None,
                 levels: Default::default(),
+                levels_next_cursor: None,
                 levels_list_filter: Default::default(),
                 selected_level: Default::default(),
-                selected_level_data: None,
                 screen: Default::default(),
                 request_id_counter: Default::default(),
                 current_request_id: None,
                 pending_create_server_request: None,
                 create_server_request_sent_at: None,
                 request_error_message: None,
+                friends: Default::default(),
+                privacy: Default::default(),
+                api_tokens: Default::default(),
+                session_report: None,
+                preferred_languages: preferred_languages(),
             },
         }
     }
@@ -337,6 +428,18 @@ impl Default for MainMenuUiScreen {
 #[derive(SystemParam)]
 pub struct Configs<'w, 's> {
     offline_auth_config: Res<'w, OfflineAuthConfig>,
+    personal_bests: Res<'w, PersonalBests>,
+    guest: ResMut<'w, Guest>,
+    #[system_param(ignore)]
+    _marker: PhantomData<&'s ()>,
+}
+
+#[derive(SystemParam)]
+pub struct Settings<'w, 's> {
+    ui_theme: ResMut<'w, UiTheme>,
+    audio_cues: ResMut<'w, AudioCues>,
+    key_bindings: Res<'w, KeyBindings>,
+    key_bindings_ui_state: ResMut<'w, KeyBindingsUiState>,
     #[system_param(ignore)]
     _marker: PhantomData<&'s ()>,
 }
@@ -370,7 +473,17 @@ pub fn matchmaker_is_initialised(
 pub fn init_menu_auth_state_system(
     mut main_menu_ui_state: ResMut<MainMenuUiState>,
     configs: Configs,
+    client_config: Res<MuddleClientConfig>,
 ) {
+    // Without the credentials, the auth screens would only lead to errors.
+    if AuthConfig::from_client_config(&client_config).is_none() {
+        main_menu_ui_state.auth.auth_is_available = false;
+        main_menu_ui_state
+            .auth
+            .switch_to_guest_nickname(&configs.guest);
+        return;
+    }
+
     if configs.offline_auth_config.exists() {
         main_menu_ui_state.auth.screen = AuthUiScreen::RefreshAuth;
         main_menu_ui_state.auth.logged_in_as = Some(configs.offline_auth_config.username.clone());
@@ -381,11 +494,12 @@ pub fn init_menu_auth_state_system(
 
 pub fn main_menu_ui_system(
     mut ui_context: UiContext,
-    configs: Configs,
+    mut configs: Configs,
     mut main_menu_ui_state: ResMut<MainMenuUiState>,
     matchmaker_state: Option<Res<MatchmakerState>>,
     mut main_menu_ui_channels: Option<ResMut<MainMenuUiChannels>>,
     mut server_to_connect: ResMut<ServerToConnect>,
+    mut settings: Settings,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -414,7 +528,7 @@ pub fn main_menu_ui_system(
 
     let ctx = ui_context.egui_context.ctx_mut();
     egui::CentralPanel::default()
-        .frame(egui::Frame::none().fill(egui::Color32::from_black_alpha(200)))
+        .frame(egui::Frame::none().fill(backdrop_color(&ctx.style().visuals)))
         .show(ctx, |ui| {
             ui.spacing_mut().window_margin =
                 egui::style::Margin::same(ui.visuals().window_stroke().width);
@@ -465,13 +579,17 @@ pub fn main_menu_ui_system(
                     match (*main_menu_ui_screen, main_menu_ui_channels.as_deref_mut()) {
                         (MainMenuUiScreen::Auth, Some(main_menu_ui_channels)) => {
                             egui::containers::Frame::none()
-                                .inner_margin(egui::style::Margin::symmetric(25.0, 15.0))
+                                .inner_margin(egui::style::Margin::symmetric(
+                                    spacing::EXTRA_LARGE,
+                                    spacing::LARGE,
+                                ))
                                 .show(ui, |ui| {
                                     let confirm = authentication_screen(
                                         ui,
                                         &mut main_menu_ui_channels.auth_request_tx,
                                         auth_ui_state,
                                         &configs.offline_auth_config,
+                                        &mut configs.guest,
                                     );
                                     if confirm {
                                         *main_menu_ui_screen = MainMenuUiScreen::Matchmaker;
@@ -485,13 +603,48 @@ pub fn main_menu_ui_system(
                                 matchmaker_ui_state,
                                 &mut server_to_connect,
                                 main_menu_ui_channels,
+                                &configs.personal_bests,
                             );
                         }
                     }
+
+                    ui.separator();
+                    theme_selector(ui, &mut settings.ui_theme);
+                    audio_settings(ui, &mut settings.audio_cues);
+                    key_bindings_button(
+                        ui,
+                        &mut settings.key_bindings_ui_state,
+                        &settings.key_bindings,
+                    );
                 });
         });
 }
 
+/// Opens the summary screen once the player leaves a server.
+pub fn open_session_summary_system(
+    mut main_menu_ui_state: ResMut<MainMenuUiState>,
+    mut session_summary: ResMut<SessionSummary>,
+) {
+    if let Some(report) = session_summary.take_report() {
+        main_menu_ui_state.matchmaker.session_report = Some(report);
+        main_menu_ui_state.matchmaker.screen = MatchmakerUiScreen::SessionSummary;
+    }
+}
+
+fn audio_settings(ui: &mut egui::Ui, audio_cues: &mut AudioCues) {
+    let mut config = audio_cues.config();
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut config.cues_enabled, "Level audio cues");
+        ui.add_enabled(
+            config.cues_enabled,
+            egui::widgets::Slider::new(&mut config.cues_volume, 0.0..=1.0).text("Volume"),
+        );
+    });
+    if config != audio_cues.config() {
+        audio_cues.set_config(config);
+    }
+}
+
 pub fn process_auth_messages_system(
     mut main_menu_ui_state: ResMut<MainMenuUiState>,
     mut matchmaker_state: ResMut<MatchmakerState>,
@@ -516,6 +669,12 @@ pub fn process_auth_messages_system(
                     .auth
                     .respond_with_error("Display name is already taken");
             }
+            Ok(AuthMessage::InvalidDisplayNameError(errors)) => {
+                log::debug!("Display name is invalid: {:?}", errors);
+                main_menu_ui_state
+                    .auth
+                    .respond_with_error(&format_errors("Display name", &errors));
+            }
             Ok(AuthMessage::UnavailableError) => {
                 log::debug!("Authentication unavailable");
                 main_menu_ui_state
@@ -644,6 +803,11 @@ pub fn process_matchmaker_messages_system(
                 main_menu_ui_state.screen = MainMenuUiScreen::Auth;
                 main_menu_ui_state.auth.screen = AuthUiScreen::SignIn;
             }
+            // The main menu keeps the full list received with `Init` up to date,
+            // so it doesn't request filtered pages.
+            Ok(MatchmakerMessage::ServerList(page)) => {
+                log::debug!("Ignoring a server list page: {:?}", page.request_id);
+            }
             Err(TryRecvError::Empty) => return,
             Err(TryRecvError::Disconnected) => {
                 panic!("Failed to read from a channel (matchmaker messages)")
@@ -655,19 +819,59 @@ pub fn process_matchmaker_messages_system(
 pub fn process_persistence_messages_system(
     mut main_menu_ui_state: ResMut<MainMenuUiState>,
     mut main_menu_ui_channels: ResMut<MainMenuUiChannels>,
+    mut audio_cues: ResMut<AudioCues>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+    mut bug_report_ui_state: ResMut<BugReportUiState>,
 ) {
     loop {
         let payload = match main_menu_ui_channels.persistence_message_rx.try_recv() {
             Ok(message) => {
-                if Some(message.request_id) != main_menu_ui_state.matchmaker.current_request_id {
+                match message.payload {
+                    PersistenceMessagePayload::AudioClipResponse { clip_id, data } => {
+                        audio_cues.receive_clip(clip_id, data, &mut audio_sources);
+                        continue;
+                    }
+                    PersistenceMessagePayload::UploadAudioClipResponse(result) => {
+                        audio_cues.upload_status = Some(match result {
+                            Ok(response) => AudioClipUploadStatus::Uploaded(response),
+                            Err(error) => AudioClipUploadStatus::Failed(error),
+                        });
+                        continue;
+                    }
+                    PersistenceMessagePayload::BugReportResponse(result) => {
+                        bug_report_ui_state.receive_response(result);
+                        continue;
+                    }
+                    _ => {}
+                }
+                let matchmaker_ui_state = &mut main_menu_ui_state.matchmaker;
+                if Some(message.request_id) == matchmaker_ui_state.friends.current_request_id {
+                    matchmaker_ui_state.friends.current_request_id = None;
+                    process_friends_message(&mut matchmaker_ui_state.friends, message.payload);
+                    continue;
+                }
+                if Some(message.request_id) == matchmaker_ui_state.privacy.current_request_id {
+                    matchmaker_ui_state.privacy.current_request_id = None;
+                    process_privacy_message(&mut matchmaker_ui_state.privacy, message.payload);
+                    continue;
+                }
+                if Some(message.request_id) == matchmaker_ui_state.api_tokens.current_request_id {
+                    matchmaker_ui_state.api_tokens.current_request_id = None;
+                    process_api_tokens_message(
+                        &mut matchmaker_ui_state.api_tokens,
+                        message.payload,
+                    );
+                    continue;
+                }
+                if Some(message.request_id) != matchmaker_ui_state.current_request_id {
                     log::debug!(
                         "Skipping response (message request id: {}, current: {:?})",
                         message.request_id,
-                        main_menu_ui_state.matchmaker.current_request_id
+                        matchmaker_ui_state.current_request_id
                     );
                     continue;
                 }
-                main_menu_ui_state.matchmaker.current_request_id = None;
+                matchmaker_ui_state.current_request_id = None;
                 message.payload
             }
             Err(TryRecvError::Empty) => return,
@@ -676,14 +880,28 @@ pub fn process_persistence_messages_system(
             }
         };
         match payload {
-            PersistenceMessagePayload::GetLevelsResponse(levels) => {
-                log::debug!("New levels list: {levels:?}");
-                main_menu_ui_state.matchmaker.levels =
-                    levels.into_iter().map(|level| (level.id, level)).collect();
+            PersistenceMessagePayload::GetLevelsSummaryResponse(response) => {
+                log::debug!("New levels page: {response:?}");
+                // The list is cleared when the first page is requested.
+                main_menu_ui_state.matchmaker.levels.extend(response.levels);
+                main_menu_ui_state.matchmaker.levels_next_cursor = response.next_cursor;
+            }
+            PersistenceMessagePayload::GetFriendsResponse(_) => {
+                log::warn!("Unexpected friends list response");
+            }
+            PersistenceMessagePayload::PrivacySettingsResponse(_) => {
+                log::warn!("Unexpected privacy settings response");
             }
-            PersistenceMessagePayload::GetLevelResponse(response) => {
-                log::debug!("Selected level details: {response:?}");
-                main_menu_ui_state.matchmaker.selected_level_data = Some(response);
+            PersistenceMessagePayload::GetApiTokensResponse(_)
+            | PersistenceMessagePayload::ApiTokenIssued(_) => {
+                log::warn!("Unexpected API tokens response");
+            }
+            PersistenceMessagePayload::AudioClipResponse { .. }
+            | PersistenceMessagePayload::UploadAudioClipResponse(_)
+            | PersistenceMessagePayload::BugReportResponse(_) => {
+                unreachable!(
+                    "Audio clip and bug report responses are routed before request ids are checked"
+                )
             }
             PersistenceMessagePayload::RequestFailed(error) => {
                 log::warn!("Get level request failed: {error}");
@@ -693,13 +911,114 @@ pub fn process_persistence_messages_system(
     }
 }
 
+fn process_friends_message(
+    friends_ui_state: &mut FriendsUiState,
+    payload: PersistenceMessagePayload,
+) {
+    match payload {
+        PersistenceMessagePayload::GetFriendsResponse(friends) => {
+            log::debug!("New friends list: {friends:?}");
+            if let Some(selected_friend) = friends_ui_state.selected_friend {
+                if !friends
+                    .iter()
+                    .any(|friend| friend.user_id == selected_friend)
+                {
+                    friends_ui_state.selected_friend = None;
+                }
+            }
+            friends_ui_state.friends = friends;
+            friends_ui_state.request_error_message = None;
+        }
+        PersistenceMessagePayload::RequestFailed(error) => {
+            log::warn!("Friends request failed: {error}");
+            friends_ui_state.request_error_message = Some(error);
+        }
+        PersistenceMessagePayload::GetLevelsSummaryResponse(_)
+        | PersistenceMessagePayload::PrivacySettingsResponse(_)
+        | PersistenceMessagePayload::GetApiTokensResponse(_)
+        | PersistenceMessagePayload::ApiTokenIssued(_)
+        | PersistenceMessagePayload::AudioClipResponse { .. }
+        | PersistenceMessagePayload::UploadAudioClipResponse(_)
+        | PersistenceMessagePayload::BugReportResponse(_) => {
+            log::warn!("Unexpected response to a friends request");
+        }
+    }
+}
+
+fn process_privacy_message(
+    privacy_ui_state: &mut PrivacyUiState,
+    payload: PersistenceMessagePayload,
+) {
+    match payload {
+        PersistenceMessagePayload::PrivacySettingsResponse(settings) => {
+            log::debug!("Privacy settings: {settings:?}");
+            privacy_ui_state.saved_settings = Some(settings);
+            privacy_ui_state.edited_settings = settings;
+            privacy_ui_state.request_error_message = None;
+        }
+        PersistenceMessagePayload::RequestFailed(error) => {
+            log::warn!("Privacy settings request failed: {error}");
+            privacy_ui_state.request_error_message = Some(error);
+        }
+        PersistenceMessagePayload::GetLevelsSummaryResponse(_)
+        | PersistenceMessagePayload::GetFriendsResponse(_)
+        | PersistenceMessagePayload::GetApiTokensResponse(_)
+        | PersistenceMessagePayload::ApiTokenIssued(_)
+        | PersistenceMessagePayload::AudioClipResponse { .. }
+        | PersistenceMessagePayload::UploadAudioClipResponse(_)
+        | PersistenceMessagePayload::BugReportResponse(_) => {
+            log::warn!("Unexpected response to a privacy settings request");
+        }
+    }
+}
+
+fn process_api_tokens_message(
+    api_tokens_ui_state: &mut ApiTokensUiState,
+    payload: PersistenceMessagePayload,
+) {
+    match payload {
+        PersistenceMessagePayload::GetApiTokensResponse(tokens) => {
+            log::debug!("API tokens: {tokens:?}");
+            if let Some(selected_token) = api_tokens_ui_state.selected_token {
+                if !tokens.iter().any(|token| token.id == selected_token) {
+                    api_tokens_ui_state.selected_token = None;
+                }
+            }
+            api_tokens_ui_state.tokens = Some(tokens);
+            api_tokens_ui_state.request_error_message = None;
+        }
+        PersistenceMessagePayload::ApiTokenIssued(response) => {
+            log::debug!("Issued API token: {:?}", response.token);
+            api_tokens_ui_state
+                .tokens
+                .get_or_insert_with(Vec::new)
+                .insert(0, response.token);
+            api_tokens_ui_state.issued_secret = Some(response.secret);
+            api_tokens_ui_state.new_token_name.clear();
+            api_tokens_ui_state.request_error_message = None;
+        }
+        PersistenceMessagePayload::RequestFailed(error) => {
+            log::warn!("API tokens request failed: {error}");
+            api_tokens_ui_state.request_error_message = Some(error);
+        }
+        PersistenceMessagePayload::GetLevelsSummaryResponse(_)
+        | PersistenceMessagePayload::GetFriendsResponse(_)
+        | PersistenceMessagePayload::PrivacySettingsResponse(_)
+        | PersistenceMessagePayload::AudioClipResponse { .. }
+        | PersistenceMessagePayload::UploadAudioClipResponse(_)
+        | PersistenceMessagePayload::BugReportResponse(_) => {
+            log::warn!("Unexpected response to an API tokens request");
+        }
+    }
+}
+
 fn authentication_screen(
     ui: &mut egui::Ui,
     auth_request_tx: &mut UnboundedSender<AuthRequest>,
     auth_ui_state: &mut AuthUiState,
     offline_auth_config: &OfflineAuthConfig,
+    guest: &mut Guest,
 ) -> bool {
-    ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 5.0);
     let mut confirm_auth = false;
     let mut new_screen = None;
 
@@ -715,7 +1034,7 @@ fn authentication_screen(
                     ui.label(auth_ui_state.logged_in_as.as_ref().unwrap());
                     ui.style_mut().override_text_style = None;
 
-                    ui.add_space(10.0);
+                    ui.add_space(spacing::MEDIUM);
 
                     ui.set_enabled(!auth_ui_state.pending_request);
                     if ui.button("Continue").clicked() {
@@ -728,6 +1047,9 @@ fn authentication_screen(
                         new_screen = Some(AuthUiScreen::SignIn);
                         auth_ui_state.logged_in_as = None;
                     }
+                    if ui.button("Play as guest").clicked() {
+                        new_screen = Some(AuthUiScreen::GuestNickname);
+                    }
 
                     ui.style_mut()
                         .visuals
@@ -747,7 +1069,7 @@ fn authentication_screen(
                     .expect("Failed to write to a channel (auth request)");
             }
 
-            ui.add_space(5.0);
+            ui.add_space(spacing::SMALL);
 
             ui.label(format!(
                 "Account with an email {} already exists. The login method used for this account is not available.",
@@ -768,7 +1090,7 @@ fn authentication_screen(
                         .expect("Failed to write to a channel (auth request)");
                 }
 
-                ui.add_space(5.0);
+                ui.add_space(spacing::SMALL);
 
                 ui.label(format!(
                     "Account with an email {} already exists. Please sign in to link the accounts.",
@@ -777,7 +1099,7 @@ fn authentication_screen(
                         .as_ref()
                         .expect("Expected an email when linking accounts")
                 ));
-                ui.add_space(5.0);
+                ui.add_space(spacing::SMALL);
             }
 
             if auth_ui_state.login_method_is_available("auth0") {
@@ -795,7 +1117,7 @@ fn authentication_screen(
                 }
                 auth_ui_state.password.ui(ui);
 
-                ui.add_space(5.0);
+                ui.add_space(spacing::SMALL);
 
                 let is_sign_up = matches!(auth_ui_state.screen, AuthUiScreen::SignUp);
                 let is_valid = (matches!(auth_ui_state.screen, AuthUiScreen::LinkAccount)
@@ -836,7 +1158,7 @@ fn authentication_screen(
                     ui.label(auth_ui_state.error_message.clone());
                 });
 
-                ui.add_space(5.0);
+                ui.add_space(spacing::SMALL);
             }
 
             let google_is_available = auth_ui_state.login_method_is_available("google");
@@ -864,13 +1186,12 @@ fn authentication_screen(
                     ui.separator();
                     ui.label("Don't have an account?");
                     ui.horizontal(|ui| {
-                        ui.style_mut().spacing.item_spacing = egui::Vec2::new(8.0, 5.0);
                         if ui.button("Sign Up").clicked() {
                             new_screen = Some(AuthUiScreen::SignUp);
                         }
                         ui.label("or");
-                        if ui.button("Play anonymously").clicked() {
-                            confirm_auth = true;
+                        if ui.button("Play as guest").clicked() {
+                            new_screen = Some(AuthUiScreen::GuestNickname);
                         }
                     });
                 }
@@ -878,13 +1199,12 @@ fn authentication_screen(
                     ui.separator();
                     ui.label("Already have an account?");
                     ui.horizontal(|ui| {
-                        ui.style_mut().spacing.item_spacing = egui::Vec2::new(8.0, 5.0);
                         if ui.button("Sign In").clicked() {
                             new_screen = Some(AuthUiScreen::SignIn);
                         }
                         ui.label("or");
-                        if ui.button("Play anonymously").clicked() {
-                            confirm_auth = true;
+                        if ui.button("Play as guest").clicked() {
+                            new_screen = Some(AuthUiScreen::GuestNickname);
                         }
                     });
                 }
@@ -900,13 +1220,13 @@ fn authentication_screen(
                     .send(AuthRequest::UseDifferentAccount)
                     .expect("Failed to write to a channel (auth request)");
             }
-            ui.add_space(5.0);
+            ui.add_space(spacing::SMALL);
 
             ui.label("Marvelous! You've created a brand new account. The last step is picking a display name, to show off your awesome profile.");
-            ui.add_space(5.0);
+            ui.add_space(spacing::SMALL);
 
             auth_ui_state.display_name.ui(ui);
-            ui.add_space(5.0);
+            ui.add_space(spacing::SMALL);
 
             ui.with_layout(
                 egui::Layout::top_down_justified(egui::Align::Center),
@@ -918,7 +1238,7 @@ fn authentication_screen(
                         auth_ui_state.pending_request = true;
                         auth_request_tx
                             .send(AuthRequest::SetDisplayName(
-                                auth_ui_state.display_name.value.clone(),
+                                auth_ui_state.display_name.value.trim().to_owned(),
                             ))
                             .expect("Failed to write to a channel (auth request)");
                     }
@@ -935,6 +1255,30 @@ fn authentication_screen(
                 },
             );
         }
+        AuthUiScreen::GuestNickname => {
+            if auth_ui_state.auth_is_available && ui.button("Back").clicked() {
+                new_screen = Some(AuthUiScreen::SignIn);
+            }
+            ui.add_space(spacing::SMALL);
+
+            ui.label("Servers remember guests' nicknames and stats for some time after they leave, so reconnecting doesn't reset them.");
+            ui.add_space(spacing::SMALL);
+
+            auth_ui_state.guest_nickname.ui(ui);
+            ui.add_space(spacing::SMALL);
+
+            ui.with_layout(
+                egui::Layout::top_down_justified(egui::Align::Center),
+                |ui| {
+                    ui.set_enabled(auth_ui_state.guest_nickname.is_valid());
+                    if ui.button("Play as guest").clicked() {
+                        let nickname = auth_ui_state.guest_nickname.value.trim();
+                        guest.set_nickname((!nickname.is_empty()).then(|| nickname.to_owned()));
+                        confirm_auth = true;
+                    }
+                },
+            );
+        }
         AuthUiScreen::GoogleOpenID => {
             ui.horizontal(|ui| {
                 if ui.button("Back").clicked() {
@@ -956,7 +1300,7 @@ fn authentication_screen(
                     .color = ERROR_COLOR;
                 ui.label(&auth_ui_state.error_message);
             });
-            ui.add_space(20.0);
+            ui.add_space(spacing::EXTRA_LARGE);
 
             ui.set_enabled(!auth_ui_state.pending_request);
 
@@ -980,9 +1324,11 @@ fn authentication_screen(
         }
     }
 
-    ui.add_space(5.0);
-    if let Some(new_screen) = new_screen {
-        auth_ui_state.switch_screen(new_screen);
+    ui.add_space(spacing::SMALL);
+    match new_screen {
+        Some(AuthUiScreen::GuestNickname) => auth_ui_state.switch_to_guest_nickname(guest),
+        Some(new_screen) => auth_ui_state.switch_screen(new_screen),
+        None => {}
     }
     confirm_auth
 }
@@ -993,11 +1339,17 @@ fn matchmaker_screen(
     matchmaker_ui_state: &mut MatchmakerUiState,
     server_to_connect: &mut Option<Server>,
     main_menu_ui_channels: Option<&mut MainMenuUiChannels>,
+    personal_bests: &PersonalBests,
 ) {
     match (matchmaker_ui_state.screen, matchmaker_state) {
-        (MatchmakerUiScreen::CreateServer, Some(_matchmaker_state))
-            if matchmaker_ui_state.pending_create_server_request.is_some() =>
-        {
+        (
+            MatchmakerUiScreen::CreateServer
+            | MatchmakerUiScreen::Friends
+            | MatchmakerUiScreen::Privacy
+            | MatchmakerUiScreen::ApiTokens
+            | MatchmakerUiScreen::SessionSummary,
+            Some(_matchmaker_state),
+        ) if matchmaker_ui_state.pending_create_server_request.is_some() => {
             connect_to_server_screen(ui, matchmaker_ui_state)
         }
         (MatchmakerUiScreen::CreateServer, Some(matchmaker_state)) => {
@@ -1009,16 +1361,56 @@ fn matchmaker_screen(
                     .expect("Expected UI channels to exist when matchmaker state exists")
                     .persistence_request_tx
                     .clone(),
+                personal_bests,
             )
         }
-        (MatchmakerUiScreen::ServersList | MatchmakerUiScreen::CreateServer, _) => {
-            matchmaker_servers_list_screen(
-                ui,
-                server_to_connect,
-                matchmaker_ui_state,
-                main_menu_ui_channels.map(|channels| channels.persistence_request_tx.clone()),
-            )
+        (MatchmakerUiScreen::Friends, Some(matchmaker_state)) => matchmaker_friends_screen(
+            ui,
+            matchmaker_state,
+            matchmaker_ui_state,
+            server_to_connect,
+            main_menu_ui_channels
+                .expect("Expected UI channels to exist when matchmaker state exists")
+                .persistence_request_tx
+                .clone(),
+        ),
+        (MatchmakerUiScreen::Privacy, Some(matchmaker_state)) => matchmaker_privacy_screen(
+            ui,
+            matchmaker_state,
+            matchmaker_ui_state,
+            main_menu_ui_channels
+                .expect("Expected UI channels to exist when matchmaker state exists")
+                .persistence_request_tx
+                .clone(),
+        ),
+        (MatchmakerUiScreen::ApiTokens, Some(matchmaker_state)) => matchmaker_api_tokens_screen(
+            ui,
+            matchmaker_state,
+            matchmaker_ui_state,
+            main_menu_ui_channels
+                .expect("Expected UI channels to exist when matchmaker state exists")
+                .persistence_request_tx
+                .clone(),
+        ),
+        (MatchmakerUiScreen::SessionSummary, matchmaker_state)
+            if matchmaker_ui_state.session_report.is_some() =>
+        {
+            session_summary_screen(ui, matchmaker_state, matchmaker_ui_state, server_to_connect)
         }
+        (
+            MatchmakerUiScreen::ServersList
+            | MatchmakerUiScreen::CreateServer
+            | MatchmakerUiScreen::Friends
+            | MatchmakerUiScreen::Privacy
+            | MatchmakerUiScreen::ApiTokens
+            | MatchmakerUiScreen::SessionSummary,
+            _,
+        ) => matchmaker_servers_list_screen(
+            ui,
+            server_to_connect,
+            matchmaker_ui_state,
+            main_menu_ui_channels.map(|channels| channels.persistence_request_tx.clone()),
+        ),
     }
 }
 
@@ -1032,7 +1424,7 @@ fn matchmaker_servers_list_screen(
     let mut sorted_servers = matchmaker_ui_state
         .servers
         .values()
-        .filter(|server| server.state == GameServerState::Allocated)
+        .filter(|server| server.state == GameServerState::Allocated && server.is_compatible())
         .collect::<Vec<_>>();
     sorted_servers.sort_by(|a, b| a.name.cmp(&b.name));
 
@@ -1051,20 +1443,65 @@ fn matchmaker_servers_list_screen(
                         matchmaker_ui_state.connect_manually_is_active = false;
                         matchmaker_ui_state.selected_server = None;
                         matchmaker_ui_state.screen = MatchmakerUiScreen::CreateServer;
-                        let request_id = matchmaker_ui_state.request_id_counter.increment();
-                        matchmaker_ui_state.current_request_id = Some(request_id);
-                        persistence_requests_tx
-                            .send(PersistenceRequest::GetLevels {
-                                request_id,
-                                body: GetLevelsRequest {
-                                    user_filter: None,
-                                    pagination: PaginationParams {
-                                        offset: 0,
-                                        limit: 20,
-                                    },
-                                },
-                            })
-                            .expect("Failed to write to a channel (persistence request)");
+                        matchmaker_ui_state.levels_list_filter = LevelsListFilter::All;
+                        request_levels_summary(
+                            matchmaker_ui_state,
+                            &persistence_requests_tx,
+                            None,
+                            None,
+                        );
+                    }
+
+                    let pending_requests = matchmaker_ui_state
+                        .friends
+                        .friends
+                        .iter()
+                        .filter(|friend| friend.status == FriendshipStatus::IncomingRequest)
+                        .count();
+                    let response = MenuListItem::new("Friends")
+                        .secondary_widget(|ui| {
+                            if pending_requests > 0 {
+                                ui.label(format!("Pending friend requests: {pending_requests}"));
+                            } else {
+                                ui.label("See what your friends are playing and join them");
+                            }
+                        })
+                        .image_widget(circle_image)
+                        .show(ui);
+                    if response.item.clicked() {
+                        matchmaker_ui_state.connect_manually_is_active = false;
+                        matchmaker_ui_state.selected_server = None;
+                        matchmaker_ui_state.screen = MatchmakerUiScreen::Friends;
+                        // Forces refreshing the list.
+                        matchmaker_ui_state.friends.requested_at = None;
+                    }
+
+                    let response = MenuListItem::new("Privacy settings")
+                        .secondary_widget(|ui| {
+                            ui.label("Choose what gameplay data can be linked to your account");
+                        })
+                        .image_widget(circle_image)
+                        .show(ui);
+                    if response.item.clicked() {
+                        matchmaker_ui_state.connect_manually_is_active = false;
+                        matchmaker_ui_state.selected_server = None;
+                        matchmaker_ui_state.screen = MatchmakerUiScreen::Privacy;
+                        // Forces fetching the settings.
+                        matchmaker_ui_state.privacy.saved_settings = None;
+                    }
+
+                    let response = MenuListItem::new("API tokens")
+                        .secondary_widget(|ui| {
+                            ui.label("Let community websites and bots access your levels");
+                        })
+                        .image_widget(circle_image)
+                        .show(ui);
+                    if response.item.clicked() {
+                        matchmaker_ui_state.connect_manually_is_active = false;
+                        matchmaker_ui_state.selected_server = None;
+                        matchmaker_ui_state.screen = MatchmakerUiScreen::ApiTokens;
+                        // Forces fetching the list.
+                        matchmaker_ui_state.api_tokens.tokens = None;
                     }
                 }
 
@@ -1088,6 +1525,8 @@ fn matchmaker_servers_list_screen(
                                 player_capacity: 0,
                                 player_count: 0,
                                 request_id: Default::default(),
+                                version: Default::default(),
+                                draining: false,
                             });
                         }
                         Err(err) => {
@@ -1164,10 +1603,11 @@ fn matchmaker_create_server_screen(
     matchmaker_state: &MatchmakerState,
     matchmaker_ui_state: &mut MatchmakerUiState,
     persistence_requests_tx: UnboundedSender<PersistenceRequest>,
+    personal_bests: &PersonalBests,
 ) {
     ui.set_enabled(matchmaker_ui_state.current_request_id.is_none());
 
-    let padding = egui::Vec2::new(10.0, 5.0);
+    let padding = egui::Vec2::new(spacing::MEDIUM, spacing::SMALL);
     let panel_height = 30.0;
     let mut panel_ui = ui.child_ui(
         egui::Rect::from_min_size(
@@ -1185,20 +1625,7 @@ fn matchmaker_create_server_screen(
         .clicked()
     {
         matchmaker_ui_state.selected_level = SelectedLevel::None;
-        let request_id = matchmaker_ui_state.request_id_counter.increment();
-        matchmaker_ui_state.current_request_id = Some(request_id);
-        persistence_requests_tx
-            .send(PersistenceRequest::GetLevels {
-                request_id,
-                body: GetLevelsRequest {
-                    user_filter: None,
-                    pagination: PaginationParams {
-                        offset: 0,
-                        limit: 20,
-                    },
-                },
-            })
-            .expect("Failed to write to a channel (persistence request)");
+        request_levels_summary(matchmaker_ui_state, &persistence_requests_tx, None, None);
     }
     panel_ui.set_enabled(matchmaker_state.user_id.is_some());
     if panel_ui
@@ -1210,22 +1637,14 @@ fn matchmaker_create_server_screen(
         .clicked()
     {
         matchmaker_ui_state.selected_level = SelectedLevel::None;
-        let request_id = matchmaker_ui_state.request_id_counter.increment();
-        matchmaker_ui_state.current_request_id = Some(request_id);
-        persistence_requests_tx
-            .send(PersistenceRequest::GetLevels {
-                request_id,
-                body: GetLevelsRequest {
-                    user_filter: Some(GetLevelsUserFilter::AuthorId(
-                        matchmaker_state.user_id.unwrap(),
-                    )),
-                    pagination: PaginationParams {
-                        offset: 0,
-                        limit: 20,
-                    },
-                },
-            })
-            .expect("Failed to write to a channel (persistence request)");
+        request_levels_summary(
+            matchmaker_ui_state,
+            &persistence_requests_tx,
+            Some(GetLevelsUserFilter::AuthorId(
+                matchmaker_state.user_id.unwrap(),
+            )),
+            None,
+        );
     }
     if panel_ui
         .selectable_value(
@@ -1236,22 +1655,14 @@ fn matchmaker_create_server_screen(
         .clicked()
     {
         matchmaker_ui_state.selected_level = SelectedLevel::None;
-        let request_id = matchmaker_ui_state.request_id_counter.increment();
-        matchmaker_ui_state.current_request_id = Some(request_id);
-        persistence_requests_tx
-            .send(PersistenceRequest::GetLevels {
-                request_id,
-                body: GetLevelsRequest {
-                    user_filter: Some(GetLevelsUserFilter::BuilderId(
-                        matchmaker_state.user_id.unwrap(),
-                    )),
-                    pagination: PaginationParams {
-                        offset: 0,
-                        limit: 20,
-                    },
-                },
-            })
-            .expect("Failed to write to a channel (persistence request)");
+        request_levels_summary(
+            matchmaker_ui_state,
+            &persistence_requests_tx,
+            Some(GetLevelsUserFilter::BuilderId(
+                matchmaker_state.user_id.unwrap(),
+            )),
+            None,
+        );
     }
 
     without_item_spacing(ui, |ui| {
@@ -1283,71 +1694,102 @@ fn matchmaker_create_server_screen(
         matchmaker_ui_state.selected_level = SelectedLevel::NewLevel("My new level".to_owned());
     }
 
-    for level in matchmaker_ui_state.levels.values() {
-        let selected = matchmaker_ui_state.selected_level == SelectedLevel::Existing(level.id);
-        let response = MenuListItem::new(&level.title)
-            .with_id(level.id)
+    for level in &matchmaker_ui_state.levels {
+        let LevelSummary {
+            level: level_info,
+            builder_names,
+            play_count,
+            rating,
+            rating_count,
+            localizations,
+        } = level;
+        let selected = matchmaker_ui_state.selected_level == SelectedLevel::Existing(level_info.id);
+        let personal_best = personal_bests.get(level_info.id);
+        let localization = localizations.for_languages(&matchmaker_ui_state.preferred_languages);
+        let title = localization.map_or(&level_info.title, |localization| &localization.title);
+        let response = MenuListItem::new(title)
+            .with_id(level_info.id)
             .selected(selected)
             .secondary_widget(|ui| {
                 ui.label(format!(
                     "Author: {}",
-                    level.user_name.as_deref().unwrap_or_default()
+                    level_info.user_name.as_deref().unwrap_or_default()
                 ));
+                if let Some(personal_best) = personal_best {
+                    ui.label(format!("Best: {}", format_finish(personal_best)));
+                }
             })
             .collapsing_widget(|ui| {
-                let builders = matchmaker_ui_state
-                    .selected_level_data
-                    .as_ref()
-                    .map(|level| level.level_permissions.clone())
-                    .unwrap_or_default();
-                if !builders.is_empty() {
-                    let builders = builders
-                        .into_iter()
-                        .map(|user| user.user_name.unwrap_or_default())
-                        .collect::<Vec<_>>()
-                        .join(", ");
-                    ui.label(format!("Builders: {builders}"));
+                if let Some(localization) =
+                    localization.filter(|localization| !localization.description.is_empty())
+                {
+                    ui.label(&localization.description);
+                }
+                if !builder_names.is_empty() {
+                    ui.label(format!("Builders: {}", builder_names.join(", ")));
+                }
+                ui.label(format!("Plays: {play_count}"));
+                match rating {
+                    Some(rating) => {
+                        ui.label(format!("Rating: {rating:.1} ({rating_count} ratings)"));
+                    }
+                    None => {
+                        ui.label("Rating: not rated yet");
+                    }
                 }
                 ui.label(format!(
                     "Created at: {}",
-                    level.created_at.format("%Y-%m-%d %H:%M:%S")
+                    level_info.created_at.format("%Y-%m-%d %H:%M:%S")
                 ));
                 ui.label(format!(
                     "Updated at: {}",
-                    level.updated_at.format("%Y-%m-%d %H:%M:%S")
+                    level_info.updated_at.format("%Y-%m-%d %H:%M:%S")
                 ));
             })
             .show(ui);
 
-        if response.item.clicked()
-            && matchmaker_ui_state.selected_level != SelectedLevel::Existing(level.id)
-        {
-            matchmaker_ui_state.selected_level = SelectedLevel::Existing(level.id);
-            matchmaker_ui_state.selected_level_data = None;
-            let request_id = matchmaker_ui_state.request_id_counter.increment();
-            matchmaker_ui_state.current_request_id = Some(request_id);
-            persistence_requests_tx
-                .send(PersistenceRequest::GetLevel {
-                    request_id,
-                    level_id: level.id,
-                })
-                .expect("Failed to write to a channel (persistence request)");
+        if response.item.clicked() {
+            matchmaker_ui_state.selected_level = SelectedLevel::Existing(level_info.id);
+        }
+    }
+
+    if let Some(cursor) = matchmaker_ui_state.levels_next_cursor.clone() {
+        let response = MenuListItem::new("Load more levels")
+            .image_widget(plus_image)
+            .show(ui);
+        if response.item.clicked() {
+            let user_filter = match matchmaker_ui_state.levels_list_filter {
+                LevelsListFilter::All => None,
+                LevelsListFilter::Owned => {
+                    matchmaker_state.user_id.map(GetLevelsUserFilter::AuthorId)
+                }
+                LevelsListFilter::Builder => {
+                    matchmaker_state.user_id.map(GetLevelsUserFilter::BuilderId)
+                }
+            };
+            request_levels_summary(
+                matchmaker_ui_state,
+                &persistence_requests_tx,
+                user_filter,
+                Some(cursor),
+            );
         }
     }
 
     let matchmaker_is_connected = matches!(matchmaker_state.status, TcpConnectionStatus::Connected);
     let is_authenticated = matchmaker_state.id_token.is_some();
     let (create_enabled, create_disabled_reason) = match &matchmaker_ui_state.selected_level {
-        _ if !matchmaker_is_connected => (false, "Not connected to the matchmaker"),
-        SelectedLevel::NewLevel(_) if !is_authenticated => {
-            (false, "You must be logged in to create new levels")
-        }
-        SelectedLevel::NewLevel(title) if title.is_empty() => {
-            (false, "New level title cannot be empty")
-        }
-        SelectedLevel::NewLevel(_) => (true, ""),
-        SelectedLevel::Existing(_) => (true, ""),
-        SelectedLevel::None => (false, "Select a level to create a server"),
+        _ if !matchmaker_is_connected => (false, "Not connected to the matchmaker".to_owned()),
+        SelectedLevel::NewLevel(_) if !is_authenticated => (
+            false,
+            "You must be logged in to create new levels".to_owned(),
+        ),
+        SelectedLevel::NewLevel(title) => match validate_level_title(title) {
+            Ok(_) => (true, String::new()),
+            Err(errors) => (false, format_errors("New level title", &errors)),
+        },
+        SelectedLevel::Existing(_) => (true, String::new()),
+        SelectedLevel::None => (false, "Select a level to create a server".to_owned()),
     };
     let (fork_enabled, fork_disabled_reason) = match &matchmaker_ui_state.selected_level {
         _ if !matchmaker_is_connected => (false, "Not connected to the matchmaker"),
@@ -1382,7 +1824,7 @@ fn matchmaker_create_server_screen(
         log::info!("Scheduling a create level request: {request_id}");
         let init_level = match &matchmaker_ui_state.selected_level {
             SelectedLevel::NewLevel(level_title) => InitLevel::Create {
-                title: level_title.clone(),
+                title: level_title.trim().to_owned(),
                 parent_id: None,
             },
             SelectedLevel::Existing(level_id) => InitLevel::Existing(*level_id),
@@ -1392,6 +1834,8 @@ fn matchmaker_create_server_screen(
             init_level,
             request_id,
             id_token: matchmaker_state.id_token.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            kind: AllocationKind::Public,
         };
         matchmaker_ui_state.pending_create_server_request = Some(request);
     }
@@ -1401,7 +1845,14 @@ fn matchmaker_create_server_screen(
         log::info!("Scheduling a fork level request: {request_id}");
         let init_level = match &matchmaker_ui_state.selected_level {
             SelectedLevel::Existing(level_id) => InitLevel::Create {
-                title: matchmaker_ui_state.levels[level_id].title.clone(),
+                title: matchmaker_ui_state
+                    .levels
+                    .iter()
+                    .find(|level| level.level.id == *level_id)
+                    .expect("Expected the selected level to be listed")
+                    .level
+                    .title
+                    .clone(),
                 parent_id: Some(*level_id),
             },
             _ => unreachable!(),
@@ -1410,11 +1861,646 @@ fn matchmaker_create_server_screen(
             init_level,
             request_id,
             id_token: matchmaker_state.id_token.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            kind: AllocationKind::Public,
         };
         matchmaker_ui_state.pending_create_server_request = Some(request);
     }
 }
 
+/// Requesting the first page (without a cursor) clears the current list.
+fn request_levels_summary(
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    persistence_requests_tx: &UnboundedSender<PersistenceRequest>,
+    user_filter: Option<GetLevelsUserFilter>,
+    cursor: Option<LevelsCursor>,
+) {
+    if cursor.is_none() {
+        matchmaker_ui_state.levels.clear();
+    }
+    matchmaker_ui_state.levels_next_cursor = None;
+
+    let request_id = matchmaker_ui_state.request_id_counter.increment();
+    matchmaker_ui_state.current_request_id = Some(request_id);
+    persistence_requests_tx
+        .send(PersistenceRequest::GetLevelsSummary {
+            request_id,
+            body: GetLevelsSummaryRequest {
+                // Users see their own unpublished levels, so that they can keep
+                // working on them.
+                include_unpublished: user_filter.is_some(),
+                user_filter,
+                cursor,
+                limit: 20,
+            },
+        })
+        .expect("Failed to write to a channel (persistence request)");
+}
+
+fn matchmaker_friends_screen(
+    ui: &mut egui::Ui,
+    matchmaker_state: &MatchmakerState,
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    server_to_connect: &mut Option<Server>,
+    persistence_requests_tx: UnboundedSender<PersistenceRequest>,
+) {
+    let id_token = matchmaker_state.id_token.clone();
+    let friends_ui_state = &mut matchmaker_ui_state.friends;
+
+    if let Some(id_token) = &id_token {
+        let refresh_is_due = friends_ui_state.requested_at.map_or(true, |requested_at| {
+            Instant::now().duration_since(requested_at) > FRIENDS_REFRESH_PERIOD
+        });
+        if refresh_is_due && friends_ui_state.current_request_id.is_none() {
+            let request_id = matchmaker_ui_state.request_id_counter.increment();
+            friends_ui_state.current_request_id = Some(request_id);
+            friends_ui_state.requested_at = Some(Instant::now());
+            persistence_requests_tx
+                .send(PersistenceRequest::GetFriends {
+                    request_id,
+                    id_token: id_token.clone(),
+                })
+                .expect("Failed to write to a channel (persistence request)");
+        }
+    }
+
+    egui::containers::Frame::none()
+        .inner_margin(egui::style::Margin::symmetric(
+            spacing::MEDIUM,
+            spacing::SMALL,
+        ))
+        .show(ui, |ui| {
+            ui.set_enabled(id_token.is_some() && friends_ui_state.current_request_id.is_none());
+            ui.horizontal(|ui| {
+                ui.style_mut().visuals.widgets.inactive.bg_stroke =
+                    ui.style_mut().visuals.window_stroke();
+                egui::widgets::TextEdit::singleline(&mut friends_ui_state.new_friend_name)
+                    .hint_text("Display name")
+                    .desired_width(250.0)
+                    .show(ui);
+                let add_response = ui
+                    .add_enabled(
+                        !friends_ui_state.new_friend_name.trim().is_empty(),
+                        egui::widgets::Button::new("Add friend"),
+                    )
+                    .on_disabled_hover_text("Enter a display name of a player to add");
+                if add_response.clicked() {
+                    let request_id = matchmaker_ui_state.request_id_counter.increment();
+                    friends_ui_state.current_request_id = Some(request_id);
+                    persistence_requests_tx
+                        .send(PersistenceRequest::AddFriend {
+                            request_id,
+                            id_token: id_token.clone().unwrap(),
+                            display_name: std::mem::take(&mut friends_ui_state.new_friend_name)
+                                .trim()
+                                .to_owned(),
+                        })
+                        .expect("Failed to write to a channel (persistence request)");
+                }
+            });
+
+            ui.style_mut()
+                .visuals
+                .widgets
+                .noninteractive
+                .fg_stroke
+                .color = ERROR_COLOR;
+            if id_token.is_none() {
+                ui.label("You must be logged in to add friends");
+            } else if let Some(error) = &friends_ui_state.request_error_message {
+                ui.label(error);
+            }
+        });
+
+    without_item_spacing(ui, |ui| {
+        ui.separator();
+        egui::containers::ScrollArea::vertical()
+            .max_height(420.0)
+            .show(ui, |ui| {
+                for friend in &friends_ui_state.friends {
+                    let is_selected = friends_ui_state.selected_friend == Some(friend.user_id);
+                    let response = MenuListItem::new(
+                        friend.display_name.as_deref().unwrap_or("Unknown player"),
+                    )
+                    .with_id(friend.user_id)
+                    .selected(is_selected)
+                    .secondary_widget(|ui| {
+                        ui.label(friend_status_label(friend));
+                    })
+                    .show(ui);
+                    if response.item.clicked() {
+                        friends_ui_state.selected_friend = Some(friend.user_id);
+                    }
+                }
+            });
+    });
+
+    let selected_friend = friends_ui_state.selected_friend.and_then(|user_id| {
+        friends_ui_state
+            .friends
+            .iter()
+            .find(|friend| friend.user_id == user_id)
+            .cloned()
+    });
+    let is_matchmaker_connected = matches!(matchmaker_state.status, TcpConnectionStatus::Connected);
+    let request_is_pending = friends_ui_state.current_request_id.is_some();
+    let (join_enabled, join_disabled_reason) = match &selected_friend {
+        _ if !is_matchmaker_connected => (false, "Not connected to the matchmaker"),
+        None => (false, "Select a friend to join"),
+        Some(friend) if friend.presence.is_none() => (false, "The friend isn't playing"),
+        Some(_) => (true, ""),
+    };
+    let accept_enabled = !request_is_pending
+        && selected_friend.as_ref().map_or(false, |friend| {
+            friend.status == FriendshipStatus::IncomingRequest
+        });
+    let remove_enabled = !request_is_pending && selected_friend.is_some();
+
+    let [back_response, join_response, accept_response, remove_response] = button_panel(
+        ui,
+        70.0,
+        [
+            PanelButton::new(egui::Button::new("Back")),
+            PanelButton::new(egui::Button::new("Join"))
+                .enabled(join_enabled)
+                .on_disabled_hover_text(join_disabled_reason),
+            PanelButton::new(egui::Button::new("Accept"))
+                .enabled(accept_enabled)
+                .on_disabled_hover_text("Select an incoming friend request"),
+            PanelButton::new(egui::Button::new("Remove"))
+                .enabled(remove_enabled)
+                .on_hover_text("Remove the friend or decline the friend request")
+                .on_disabled_hover_text("Select a friend to remove"),
+        ],
+    );
+
+    if back_response.clicked() {
+        matchmaker_ui_state.screen = MatchmakerUiScreen::ServersList;
+        matchmaker_ui_state.friends.selected_friend = None;
+    }
+
+    if join_response.clicked() {
+        let presence = selected_friend
+            .as_ref()
+            .and_then(|friend| friend.presence.clone())
+            .unwrap();
+        log::info!("Joining a friend on {}", presence.server_name);
+        join_level(
+            Some(presence.server_name.as_str()),
+            Some(presence.level_id),
+            matchmaker_state,
+            matchmaker_ui_state,
+            server_to_connect,
+        );
+    }
+
+    if accept_response.clicked() || remove_response.clicked() {
+        let request_id = matchmaker_ui_state.request_id_counter.increment();
+        matchmaker_ui_state.friends.current_request_id = Some(request_id);
+        let id_token = id_token.unwrap();
+        let user_id = selected_friend.unwrap().user_id;
+        let request = if accept_response.clicked() {
+            PersistenceRequest::AcceptFriend {
+                request_id,
+                id_token,
+                user_id,
+            }
+        } else {
+            PersistenceRequest::RemoveFriend {
+                request_id,
+                id_token,
+                user_id,
+            }
+        };
+        persistence_requests_tx
+            .send(request)
+            .expect("Failed to write to a channel (persistence request)");
+    }
+}
+
+fn matchmaker_privacy_screen(
+    ui: &mut egui::Ui,
+    matchmaker_state: &MatchmakerState,
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    persistence_requests_tx: UnboundedSender<PersistenceRequest>,
+) {
+    let id_token = matchmaker_state.id_token.clone();
+    let privacy_ui_state = &mut matchmaker_ui_state.privacy;
+
+    if let Some(id_token) = &id_token {
+        if privacy_ui_state.saved_settings.is_none()
+            && privacy_ui_state.current_request_id.is_none()
+            && privacy_ui_state.request_error_message.is_none()
+        {
+            let request_id = matchmaker_ui_state.request_id_counter.increment();
+            privacy_ui_state.current_request_id = Some(request_id);
+            persistence_requests_tx
+                .send(PersistenceRequest::GetPrivacySettings {
+                    request_id,
+                    id_token: id_token.clone(),
+                })
+                .expect("Failed to write to a channel (persistence request)");
+        }
+    }
+
+    egui::containers::Frame::none()
+        .inner_margin(egui::style::Margin::symmetric(
+            spacing::MEDIUM,
+            spacing::SMALL,
+        ))
+        .show(ui, |ui| {
+            ui.set_enabled(
+                privacy_ui_state.saved_settings.is_some()
+                    && privacy_ui_state.current_request_id.is_none(),
+            );
+            ui.label(
+                "Anonymous statistics (such as which parts of a level players visit the most) \
+                are collected for everyone. The following settings allow linking gameplay data \
+                to your account.",
+            );
+            ui.add_space(spacing::SMALL);
+            ui.checkbox(
+                &mut privacy_ui_state.edited_settings.allow_session_recording,
+                "Allow recording my sessions",
+            )
+            .on_hover_text("Your movement can be recorded and played back in replays");
+            ui.checkbox(
+                &mut privacy_ui_state.edited_settings.allow_analytics,
+                "Allow personalized analytics",
+            )
+            .on_hover_text("Heatmaps and other statistics can be linked to your account");
+            ui.label("The changes apply the next time you join a server.");
+
+            ui.style_mut()
+                .visuals
+                .widgets
+                .noninteractive
+                .fg_stroke
+                .color = ERROR_COLOR;
+            if id_token.is_none() {
+                ui.label("You must be logged in to change privacy settings");
+            } else if let Some(error) = &privacy_ui_state.request_error_message {
+                ui.label(error);
+            }
+        });
+
+    let has_changes = privacy_ui_state
+        .saved_settings
+        .map_or(false, |saved| saved != privacy_ui_state.edited_settings);
+    let [back_response, save_response] = button_panel(
+        ui,
+        70.0,
+        [
+            PanelButton::new(egui::Button::new("Back")),
+            PanelButton::new(egui::Button::new("Save"))
+                .enabled(has_changes && privacy_ui_state.current_request_id.is_none())
+                .on_disabled_hover_text("No changes to save"),
+        ],
+    );
+
+    if back_response.clicked() {
+        matchmaker_ui_state.screen = MatchmakerUiScreen::ServersList;
+        matchmaker_ui_state.privacy = Default::default();
+    }
+
+    if save_response.clicked() {
+        let request_id = matchmaker_ui_state.request_id_counter.increment();
+        matchmaker_ui_state.privacy.current_request_id = Some(request_id);
+        persistence_requests_tx
+            .send(PersistenceRequest::UpdatePrivacySettings {
+                request_id,
+                id_token: id_token.unwrap(),
+                settings: matchmaker_ui_state.privacy.edited_settings,
+            })
+            .expect("Failed to write to a channel (persistence request)");
+    }
+}
+
+fn matchmaker_api_tokens_screen(
+    ui: &mut egui::Ui,
+    matchmaker_state: &MatchmakerState,
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    persistence_requests_tx: UnboundedSender<PersistenceRequest>,
+) {
+    let id_token = matchmaker_state.id_token.clone();
+    let api_tokens_ui_state = &mut matchmaker_ui_state.api_tokens;
+
+    if let Some(id_token) = &id_token {
+        if api_tokens_ui_state.tokens.is_none()
+            && api_tokens_ui_state.current_request_id.is_none()
+            && api_tokens_ui_state.request_error_message.is_none()
+        {
+            let request_id = matchmaker_ui_state.request_id_counter.increment();
+            api_tokens_ui_state.current_request_id = Some(request_id);
+            persistence_requests_tx
+                .send(PersistenceRequest::GetApiTokens {
+                    request_id,
+                    id_token: id_token.clone(),
+                })
+                .expect("Failed to write to a channel (persistence request)");
+        }
+    }
+
+    egui::containers::Frame::none()
+        .inner_margin(egui::style::Margin::symmetric(
+            spacing::MEDIUM,
+            spacing::SMALL,
+        ))
+        .show(ui, |ui| {
+            ui.set_enabled(id_token.is_some() && api_tokens_ui_state.current_request_id.is_none());
+            ui.label(
+                "Community websites and bots can use personal tokens to access the API on your \
+                behalf. Issue a separate token for every tool, so that you can revoke it anytime.",
+            );
+            ui.add_space(spacing::SMALL);
+            ui.horizontal(|ui| {
+                ui.style_mut().visuals.widgets.inactive.bg_stroke =
+                    ui.style_mut().visuals.window_stroke();
+                egui::widgets::TextEdit::singleline(&mut api_tokens_ui_state.new_token_name)
+                    .hint_text("Token name")
+                    .desired_width(250.0)
+                    .show(ui);
+                let scopes = &mut api_tokens_ui_state.new_token_scopes;
+                ui.checkbox(&mut scopes.read_levels, "Read levels");
+                ui.checkbox(&mut scopes.write_levels, "Edit my levels")
+                    .on_hover_text("Rename, publish and unpublish levels that you have created");
+                ui.checkbox(&mut scopes.read_leaderboards, "Read leaderboards");
+            });
+            let issue_response = ui
+                .add_enabled(
+                    !api_tokens_ui_state.new_token_name.trim().is_empty()
+                        && !api_tokens_ui_state.new_token_scopes.is_empty(),
+                    egui::widgets::Button::new("Issue token"),
+                )
+                .on_disabled_hover_text("Enter a token name and choose at least one scope");
+            if issue_response.clicked() {
+                let request_id = matchmaker_ui_state.request_id_counter.increment();
+                api_tokens_ui_state.current_request_id = Some(request_id);
+                api_tokens_ui_state.issued_secret = None;
+                persistence_requests_tx
+                    .send(PersistenceRequest::IssueApiToken {
+                        request_id,
+                        id_token: id_token.clone().unwrap(),
+                        body: PostApiTokenRequest {
+                            name: api_tokens_ui_state.new_token_name.trim().to_owned(),
+                            scopes: api_tokens_ui_state.new_token_scopes,
+                        },
+                    })
+                    .expect("Failed to write to a channel (persistence request)");
+            }
+
+            if let Some(secret) = &api_tokens_ui_state.issued_secret {
+                ui.add_space(spacing::SMALL);
+                ui.label("Copy the token now, it won't be shown again:");
+                egui::widgets::TextEdit::singleline(&mut secret.as_str())
+                    .desired_width(f32::INFINITY)
+                    .show(ui);
+            }
+
+            ui.style_mut()
+                .visuals
+                .widgets
+                .noninteractive
+                .fg_stroke
+                .color = ERROR_COLOR;
+            if id_token.is_none() {
+                ui.label("You must be logged in to manage API tokens");
+            } else if let Some(error) = &api_tokens_ui_state.request_error_message {
+                ui.label(error);
+            }
+        });
+
+    without_item_spacing(ui, |ui| {
+        ui.separator();
+        egui::containers::ScrollArea::vertical()
+            .max_height(320.0)
+            .show(ui, |ui| {
+                for token in api_tokens_ui_state.tokens.iter().flatten() {
+                    let is_selected = api_tokens_ui_state.selected_token == Some(token.id);
+                    let response = MenuListItem::new(&token.name)
+                        .with_id(token.id)
+                        .selected(is_selected)
+                        .secondary_widget(|ui| {
+                            ui.label(api_token_label(token));
+                        })
+                        .show(ui);
+                    if response.item.clicked() {
+                        api_tokens_ui_state.selected_token = Some(token.id);
+                    }
+                }
+            });
+    });
+
+    let [back_response, revoke_response] = button_panel(
+        ui,
+        70.0,
+        [
+            PanelButton::new(egui::Button::new("Back")),
+            PanelButton::new(egui::Button::new("Revoke"))
+                .enabled(
+                    api_tokens_ui_state.selected_token.is_some()
+                        && api_tokens_ui_state.current_request_id.is_none(),
+                )
+                .on_hover_text("Tools that use the token will lose access immediately")
+                .on_disabled_hover_text("Select a token to revoke"),
+        ],
+    );
+
+    if back_response.clicked() {
+        matchmaker_ui_state.screen = MatchmakerUiScreen::ServersList;
+        matchmaker_ui_state.api_tokens = Default::default();
+    }
+
+    if revoke_response.clicked() {
+        let request_id = matchmaker_ui_state.request_id_counter.increment();
+        matchmaker_ui_state.api_tokens.current_request_id = Some(request_id);
+        persistence_requests_tx
+            .send(PersistenceRequest::RevokeApiToken {
+                request_id,
+                id_token: id_token.unwrap(),
+                token_id: matchmaker_ui_state.api_tokens.selected_token.unwrap(),
+            })
+            .expect("Failed to write to a channel (persistence request)");
+    }
+}
+
+fn api_token_label(token: &ApiToken) -> String {
+    let scopes = [
+        (token.scopes.read_levels, "read levels"),
+        (token.scopes.write_levels, "edit levels"),
+        (token.scopes.read_leaderboards, "read leaderboards"),
+    ]
+    .into_iter()
+    .filter_map(|(is_allowed, label)| is_allowed.then_some(label))
+    .collect::<Vec<_>>()
+    .join(", ");
+    let last_used = token.last_used_at.map_or_else(
+        || "never used".to_owned(),
+        |last_used_at| format!("last used {}", last_used_at.format("%Y-%m-%d %H:%M")),
+    );
+    format!("Scopes: {scopes} ({last_used})")
+}
+
+fn friend_status_label(friend: &FriendDto) -> String {
+    match (friend.status, &friend.presence) {
+        (FriendshipStatus::IncomingRequest, _) => "Wants to be your friend".to_owned(),
+        (FriendshipStatus::OutgoingRequest, _) => "Friend request sent".to_owned(),
+        (FriendshipStatus::Accepted, Some(presence)) => {
+            format!("Playing: {}", presence.level_title)
+        }
+        (FriendshipStatus::Accepted, None) => "Offline".to_owned(),
+    }
+}
+
+/// Connects to the server if it's still available (and not full). Otherwise,
+/// we request a new one with the same level, unless the level isn't persisted.
+fn join_level(
+    server_name: Option<&str>,
+    level_id: Option<i64>,
+    matchmaker_state: &MatchmakerState,
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    server_to_connect: &mut Option<Server>,
+) {
+    if let Some(server) = server_name.and_then(|name| matchmaker_ui_state.joinable_server(name)) {
+        log::info!("Joining {}", server.name);
+        *server_to_connect = Some(server.clone());
+        return;
+    }
+
+    let Some(level_id) = level_id else {
+        log::warn!("The server isn't available, and the level isn't persisted");
+        return;
+    };
+    let request_id = Uuid::new_v4();
+    log::info!("The server isn't available, scheduling a create server request: {request_id}");
+    matchmaker_ui_state.pending_create_server_request = Some(MatchmakerRequest::CreateServer {
+        init_level: InitLevel::Existing(level_id),
+        request_id,
+        id_token: matchmaker_state.id_token.clone(),
+        protocol_version: PROTOCOL_VERSION,
+        kind: AllocationKind::Public,
+    });
+}
+
+fn session_summary_screen(
+    ui: &mut egui::Ui,
+    matchmaker_state: Option<&MatchmakerState>,
+    matchmaker_ui_state: &mut MatchmakerUiState,
+    server_to_connect: &mut Option<Server>,
+) {
+    let report = matchmaker_ui_state
+        .session_report
+        .as_ref()
+        .expect("Expected a session report to show");
+    // Without the matchmaker, we can only reconnect to the same address.
+    let can_play_again = match matchmaker_state {
+        Some(_) => {
+            report.level_id.is_some()
+                || report.server.as_ref().map_or(false, |server| {
+                    matchmaker_ui_state.joinable_server(&server.name).is_some()
+                })
+        }
+        None => report.server.is_some(),
+    };
+
+    egui::containers::Frame::none()
+        .inner_margin(egui::style::Margin::symmetric(
+            spacing::MEDIUM,
+            spacing::SMALL,
+        ))
+        .show(ui, |ui| {
+            ui.with_layout(
+                egui::Layout::top_down_justified(egui::Align::Center),
+                |ui| {
+                    ui.style_mut().override_text_style = Some(egui::TextStyle::Heading);
+                    ui.label(report.level_title.as_deref().unwrap_or("Session summary"));
+                },
+            );
+            ui.add_space(spacing::SMALL);
+
+            egui::Grid::new("session summary")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    let secs_played = report.time_played.as_secs();
+                    ui.label("Time played");
+                    ui.label(format!("{}m {:02}s", secs_played / 60, secs_played % 60));
+                    ui.end_row();
+
+                    ui.label("Finishes");
+                    ui.label(report.finishes.to_string());
+                    ui.end_row();
+
+                    ui.label("Deaths");
+                    ui.label(report.deaths.to_string());
+                    ui.end_row();
+
+                    ui.label("Best time");
+                    ui.label(report.best_finish.map_or_else(|| "-".to_owned(), format_finish));
+                    ui.end_row();
+
+                    ui.label("Medals");
+                    if report.medals.is_empty() {
+                        ui.label("-");
+                    } else {
+                        let medals = report
+                            .medals
+                            .iter()
+                            .map(|(medal, count)| format!("{} ×{count}", medal_icon(*medal)))
+                            .collect::<Vec<_>>();
+                        ui.label(medals.join("  "));
+                    }
+                    ui.end_row();
+
+                    let connection = report.connection;
+                    ui.label("Connection");
+                    ui.label(connection.rating().to_string()).on_hover_text(format!(
+                        "Rtt: {:.0}ms (max {:.0}ms)\nJitter: {:.0}ms\nPacket loss: {:.1}%\nServer overloaded: {:.0}% of the time",
+                        connection.mean_rtt_millis,
+                        connection.max_rtt_millis,
+                        connection.mean_jitter_millis,
+                        connection.mean_packet_loss * 100.0,
+                        connection.server_overloaded_share * 100.0,
+                    ));
+                    ui.end_row();
+                });
+        });
+
+    let [back_response, play_again_response] = button_panel(
+        ui,
+        70.0,
+        [
+            PanelButton::new(egui::Button::new("Back")),
+            PanelButton::new(egui::Button::new("Play again"))
+                .enabled(can_play_again)
+                .on_hover_text("Join the same level")
+                .on_disabled_hover_text("The server has shut down, and the level isn't saved"),
+        ],
+    );
+
+    if back_response.clicked() {
+        matchmaker_ui_state.screen = MatchmakerUiScreen::ServersList;
+        matchmaker_ui_state.session_report = None;
+    }
+
+    if play_again_response.clicked() {
+        let report = matchmaker_ui_state.session_report.take().unwrap();
+        match matchmaker_state {
+            Some(matchmaker_state) => join_level(
+                report.server.as_ref().map(|server| server.name.as_str()),
+                report.level_id,
+                matchmaker_state,
+                matchmaker_ui_state,
+                server_to_connect,
+            ),
+            None => *server_to_connect = report.server,
+        }
+        // While a new server is being requested, the screen shows the progress.
+        if matchmaker_ui_state.pending_create_server_request.is_none() {
+            matchmaker_ui_state.screen = MatchmakerUiScreen::ServersList;
+        }
+    }
+}
+
 fn server_list(ui: &mut egui::Ui, servers: &[&Server], selected: &mut Option<String>) {
     for server in servers {
         let is_selected = selected
@@ -1437,7 +2523,7 @@ fn server_list(ui: &mut egui::Ui, servers: &[&Server], selected: &mut Option<Str
 
 fn connect_to_server_screen(ui: &mut egui::Ui, matchmaker_ui_state: &mut MatchmakerUiState) {
     ui.scope(|ui| {
-        ui.add_space(20.0);
+        ui.add_space(spacing::EXTRA_LARGE);
         ui.with_layout(
             egui::Layout::top_down_justified(egui::Align::Center),
             |ui| {
@@ -1445,7 +2531,7 @@ fn connect_to_server_screen(ui: &mut egui::Ui, matchmaker_ui_state: &mut Matchma
                 ui.label("Creating a game server...");
             },
         );
-        ui.add_space(10.0);
+        ui.add_space(spacing::MEDIUM);
     });
     let [response] = button_panel(ui, 70.0, [PanelButton::new(egui::Button::new("Back"))]);
     if response.clicked() {
@@ -0,0 +1,113 @@
+use crate::helpers::PlayerParams;
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    utils::Instant,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::{game::game_mode::GameModeKind, messages::MatchEnded};
+use std::time::Duration;
+
+const MATCH_RESULTS_DISPLAY_SECS: u64 = 10;
+
+/// The game mode of the server (it's shown in the leaderboard), and the
+/// results of the latest match (each one is shown for
+/// `MATCH_RESULTS_DISPLAY_SECS`).
+#[derive(Resource, Default)]
+pub struct MatchStatus {
+    pub game_mode: GameModeKind,
+    latest_results: Option<(MatchEnded, Instant)>,
+}
+
+impl MatchStatus {
+    pub fn receive_results(&mut self, match_ended: MatchEnded) {
+        self.latest_results = Some((match_ended, Instant::now()));
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        if self
+            .latest_results
+            .as_ref()
+            .map_or(false, |(_, received_at)| {
+                now.duration_since(*received_at) >= Duration::from_secs(MATCH_RESULTS_DISPLAY_SECS)
+            })
+        {
+            self.latest_results = None;
+        }
+    }
+}
+
+pub fn match_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut match_status: ResMut<MatchStatus>,
+    player_params: PlayerParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    match_status.forget_expired(Instant::now());
+    let Some((match_ended, _)) = &match_status.latest_results else {
+        return;
+    };
+    let nickname = |net_id| {
+        player_params
+            .players
+            .get(&net_id)
+            .map_or("?", |player| player.nickname.as_str())
+    };
+    egui::Window::new("Match results")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(egui_context.ctx_mut(), |ui| {
+            let headline = match match_ended.winner {
+                Some(winner) if Some(winner) == player_params.current_player_net_id.0 => {
+                    "You have won the match!".to_owned()
+                }
+                Some(winner) => format!("{} has won the match", nickname(winner)),
+                None => "Nobody has won the match".to_owned(),
+            };
+            ui.label(egui::RichText::new(headline).heading());
+
+            egui::Grid::new("match_results_standings")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Player");
+                    ui.label("Finishes");
+                    ui.label("Deaths");
+                    ui.label("Best time");
+                    ui.end_row();
+
+                    for (net_id, stats) in &match_ended.standings {
+                        ui.label(nickname(*net_id));
+                        ui.label(stats.finishes.to_string());
+                        ui.label(stats.deaths.to_string());
+                        ui.label(stats.best_finish.map_or_else(
+                            || "-".to_owned(),
+                            |finish| format!("{:.2}s", finish.secs()),
+                        ));
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_results_expire() {
+        let mut match_status = MatchStatus::default();
+        match_status.receive_results(MatchEnded {
+            game_mode: GameModeKind::Elimination,
+            winner: None,
+            standings: Vec::new(),
+        });
+        let received_at = match_status.latest_results.as_ref().unwrap().1;
+
+        match_status.forget_expired(received_at + Duration::from_secs(1));
+        assert!(match_status.latest_results.is_some());
+
+        match_status.forget_expired(received_at + Duration::from_secs(MATCH_RESULTS_DISPLAY_SECS));
+        assert!(match_status.latest_results.is_none());
+    }
+}
@@ -8,11 +8,23 @@ use bevy_egui::{
 };
 use mr_shared_lib::game::components::{PlayerDirection, Position};
 
+pub mod admin_ui;
+pub mod bug_report_ui;
 pub mod builder_ui;
+pub mod collision_preview;
 pub mod debug_ui;
+pub mod framebuffer_inspector;
+pub mod key_bindings_ui;
+pub mod layout;
 pub mod main_menu_ui;
+pub mod match_ui;
 pub mod overlay_ui;
+pub mod ping_ui;
 pub mod player_ui;
+pub mod route_preview;
+pub mod selection;
+pub mod terrain_brush;
+pub mod theme;
 
 mod widgets;
 
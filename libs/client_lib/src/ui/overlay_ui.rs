@@ -1,6 +1,11 @@
 use crate::{
     net::ServerToConnect,
-    ui::widgets::list_menu::{button_panel, PanelButton},
+    offline_editing::OfflineEditing,
+    server_health::ServerHealthReport,
+    ui::{
+        theme::backdrop_color,
+        widgets::list_menu::{button_panel, PanelButton},
+    },
 };
 use bevy::{
     ecs::system::{Res, ResMut},
@@ -55,9 +60,30 @@ pub fn connection_status_overlay_system(
     mut egui_context: ResMut<EguiContext>,
     mut connection_state: ResMut<ConnectionState>,
     mut server_to_connect: ResMut<ServerToConnect>,
+    server_health: Res<ServerHealthReport>,
+    offline_editing: Res<OfflineEditing>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
+    // Builders that edit offline shouldn't be blocked by the overlay.
+    if offline_editing.is_editing_offline() {
+        egui::Window::new("Offline editing")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, egui::Vec2::new(0.0, 10.0))
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.label("The connection is lost, you can keep editing the level");
+                ui.label(format!(
+                    "Changes to merge after reconnecting: {}",
+                    offline_editing.offline_changes_count()
+                ));
+                if ui.button("Disconnect").clicked() {
+                    disconnect(&mut commands, &mut connection_state, &mut server_to_connect);
+                }
+            });
+        return;
+    }
+
     if matches!(
         connection_state.status(),
         ConnectionStatus::Uninitialized | ConnectionStatus::Connected
@@ -76,7 +102,7 @@ pub fn connection_status_overlay_system(
 
     let ctx = egui_context.ctx_mut();
     egui::CentralPanel::default()
-        .frame(egui::Frame::none().fill(egui::Color32::from_black_alpha(200)))
+        .frame(egui::Frame::none().fill(backdrop_color(&ctx.style().visuals)))
         .show(ctx, |ui| {
             egui::Window::new("connection status")
                 .title_bar(false)
@@ -87,6 +113,9 @@ pub fn connection_status_overlay_system(
                 .show(ui.ctx(), |ui| {
                     ui.centered_and_justified(|ui| {
                         let text = match (&game_session_state.0, connection_state.status()) {
+                            (GameSessionState::Paused, _) if server_health.is_overloaded() => {
+                                "The server is overloaded..."
+                            }
                             (GameSessionState::Paused, _) => "No updates from the server...",
                             (
                                 _,
@@ -119,17 +148,24 @@ pub fn connection_status_overlay_system(
                         [PanelButton::new(egui::Button::new(button_label))],
                     );
                     if response.clicked() {
-                        **server_to_connect = None;
-                        connection_state
-                            .set_status(ConnectionStatus::Disconnecting(DisconnectReason::Aborted));
-                        log::info!("Changing the app state to {:?}", AppState::MainMenu);
-                        commands.insert_resource(NextState(AppState::MainMenu));
-                        log::info!(
-                            "Changing the game session state to {:?}",
-                            GameSessionState::Loading
-                        );
-                        commands.insert_resource(NextState(GameSessionState::Loading));
+                        disconnect(&mut commands, &mut connection_state, &mut server_to_connect);
                     }
                 });
         });
 }
+
+fn disconnect(
+    commands: &mut Commands,
+    connection_state: &mut ConnectionState,
+    server_to_connect: &mut ServerToConnect,
+) {
+    **server_to_connect = None;
+    connection_state.set_status(ConnectionStatus::Disconnecting(DisconnectReason::Aborted));
+    log::info!("Changing the app state to {:?}", AppState::MainMenu);
+    commands.insert_resource(NextState(AppState::MainMenu));
+    log::info!(
+        "Changing the game session state to {:?}",
+        GameSessionState::Loading
+    );
+    commands.insert_resource(NextState(GameSessionState::Loading));
+}
@@ -0,0 +1,324 @@
+//! Pings let players point each other at something without chat or voice. A
+//! ping is placed with the wheel that `InputAction::Ping` opens at the cursor,
+//! and fades out after a few seconds.
+
+use crate::{
+    helpers::PlayerParams,
+    input::{ActionInput, InputAction, MouseWorldPosition, PlayerRequestsQueue},
+    ui::builder_ui::OverlayCameraParams,
+};
+use bevy::{
+    ecs::system::{Local, Res, ResMut, Resource},
+    math::Vec2,
+    utils::Instant,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::{
+    messages::{Ping, PingKind, PlayerNetId, PlayerPing, PING_MIN_INTERVAL_MILLIS},
+    player::PlayerRole,
+};
+use std::{f32::consts::TAU, time::Duration};
+
+pub const PING_LIFETIME_SECS: f32 = 4.0;
+/// Pings fade out during the last part of their lifetime.
+const PING_FADE_OUT_SECS: f32 = 1.5;
+const PING_RADIUS: f32 = 12.0;
+const PING_STROKE_WIDTH: f32 = 2.0;
+const PING_WHEEL_RADIUS: f32 = 50.0;
+const PING_WHEEL_BUTTON_SIZE: egui::Vec2 = egui::Vec2::new(90.0, 24.0);
+
+fn ping_icon(kind: PingKind) -> &'static str {
+    match kind {
+        PingKind::LookHere => "👀",
+        PingKind::Danger => "⚠",
+        PingKind::BuildHere => "🔨",
+    }
+}
+
+fn ping_label(kind: PingKind) -> &'static str {
+    match kind {
+        PingKind::LookHere => "Look here",
+        PingKind::Danger => "Danger",
+        PingKind::BuildHere => "Build here",
+    }
+}
+
+fn ping_color(kind: PingKind) -> egui::Color32 {
+    match kind {
+        PingKind::LookHere => egui::Color32::from_rgb(120, 200, 255),
+        PingKind::Danger => egui::Color32::from_rgb(255, 90, 70),
+        PingKind::BuildHere => egui::Color32::from_rgb(255, 200, 60),
+    }
+}
+
+struct PingMarker {
+    net_id: PlayerNetId,
+    ping: Ping,
+    placed_at: Instant,
+}
+
+impl PingMarker {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.duration_since(self.placed_at) >= Duration::from_secs_f32(PING_LIFETIME_SECS)
+    }
+
+    /// Returns 1.0 until the marker starts fading out, and 0.0 once it expires.
+    fn alpha(&self, now: Instant) -> f32 {
+        let remaining_secs = PING_LIFETIME_SECS - now.duration_since(self.placed_at).as_secs_f32();
+        (remaining_secs / PING_FADE_OUT_SECS).clamp(0.0, 1.0)
+    }
+}
+
+/// Every player has at most one marker, a newer ping replaces the previous one.
+#[derive(Resource, Default)]
+pub struct PingMarkers {
+    markers: Vec<PingMarker>,
+    last_placed_at: Option<Instant>,
+}
+
+impl PingMarkers {
+    pub fn receive(&mut self, player_ping: PlayerPing, now: Instant) {
+        self.markers
+            .retain(|marker| marker.net_id != player_ping.net_id);
+        self.markers.push(PingMarker {
+            net_id: player_ping.net_id,
+            ping: player_ping.ping,
+            placed_at: now,
+        });
+    }
+
+    /// Servers don't send pings back to the players who have placed them, so
+    /// they are shown right away. Returns `false` if the ping is throttled.
+    fn place(&mut self, net_id: PlayerNetId, ping: Ping, now: Instant) -> bool {
+        if self.last_placed_at.map_or(false, |last_placed_at| {
+            now.duration_since(last_placed_at) < Duration::from_millis(PING_MIN_INTERVAL_MILLIS)
+        }) {
+            return false;
+        }
+        self.last_placed_at = Some(now);
+        self.receive(PlayerPing { net_id, ping }, now);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.markers.clear();
+        self.last_placed_at = None;
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        self.markers.retain(|marker| !marker.is_expired(now));
+    }
+}
+
+/// Keeps the world position that the wheel was opened at, so that moving the
+/// cursor to a button doesn't move the ping.
+#[derive(Default)]
+pub struct PingWheelState {
+    opened_at: Option<(Vec2, egui::Pos2)>,
+}
+
+pub fn ping_wheel_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut wheel_state: Local<PingWheelState>,
+    mut ping_markers: ResMut<PingMarkers>,
+    mut player_requests: ResMut<PlayerRequestsQueue>,
+    action_input: ActionInput,
+    mouse_world_position: Res<MouseWorldPosition>,
+    player_params: PlayerParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let (Some(net_id), Some(player)) = (
+        player_params.current_player_net_id.0,
+        player_params.current_player(),
+    ) else {
+        wheel_state.opened_at = None;
+        return;
+    };
+    if player.role == PlayerRole::Spectator {
+        wheel_state.opened_at = None;
+        return;
+    }
+
+    let ctx = egui_context.ctx_mut();
+    if action_input.just_pressed(InputAction::Ping) && !ctx.wants_keyboard_input() {
+        wheel_state.opened_at = match wheel_state.opened_at {
+            Some(_) => None,
+            None => ctx
+                .pointer_hover_pos()
+                .map(|screen_pos| (mouse_world_position.0, screen_pos)),
+        };
+    }
+
+    let Some((world_pos, screen_pos)) = wheel_state.opened_at else {
+        return;
+    };
+
+    let wheel_size = egui::Vec2::splat(PING_WHEEL_RADIUS * 2.0) + PING_WHEEL_BUTTON_SIZE;
+    let mut selected_kind = None;
+    egui::Window::new("Ping")
+        .id(egui::Id::new("ping_wheel"))
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .frame(egui::Frame::none())
+        .fixed_pos(screen_pos - wheel_size / 2.0)
+        .show(ctx, |ui| {
+            let (rect, _) = ui.allocate_exact_size(wheel_size, egui::Sense::hover());
+            ui.painter().circle_stroke(
+                rect.center(),
+                PING_WHEEL_RADIUS,
+                egui::Stroke::new(PING_STROKE_WIDTH, ui.visuals().weak_text_color()),
+            );
+            for (i, kind) in PingKind::ALL.into_iter().enumerate() {
+                // The first button is at the top, the rest go clockwise.
+                let angle = -TAU / 4.0 + i as f32 * TAU / PingKind::ALL.len() as f32;
+                let button_rect = egui::Rect::from_center_size(
+                    rect.center() + PING_WHEEL_RADIUS * egui::Vec2::angled(angle),
+                    PING_WHEEL_BUTTON_SIZE,
+                );
+                let button = egui::Button::new(
+                    egui::RichText::new(format!("{} {}", ping_icon(kind), ping_label(kind)))
+                        .color(ping_color(kind)),
+                );
+                if ui.put(button_rect, button).clicked() {
+                    selected_kind = Some(kind);
+                }
+            }
+        });
+
+    if let Some(kind) = selected_kind {
+        let ping = Ping {
+            position: world_pos,
+            kind,
+        };
+        if ping_markers.place(net_id, ping, Instant::now()) {
+            player_requests.pings.push(ping);
+        }
+        wheel_state.opened_at = None;
+    } else if ctx.input().pointer.any_click() && !ctx.is_pointer_over_area() {
+        wheel_state.opened_at = None;
+    }
+}
+
+pub fn draw_pings_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut ping_markers: ResMut<PingMarkers>,
+    player_params: PlayerParams,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let now = Instant::now();
+    ping_markers.forget_expired(now);
+    if ping_markers.markers.is_empty() {
+        return;
+    }
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for marker in &ping_markers.markers {
+        let Some(pos) = overlay_camera_params.world_to_egui_pos(marker.ping.position) else {
+            continue;
+        };
+        let color = ping_color(marker.ping.kind).linear_multiply(marker.alpha(now));
+
+        painter.circle_stroke(
+            pos,
+            PING_RADIUS,
+            egui::Stroke::new(PING_STROKE_WIDTH, color),
+        );
+        painter.circle_filled(pos, PING_STROKE_WIDTH * 1.5, color);
+        let nickname = player_params
+            .players
+            .get(&marker.net_id)
+            .map_or("", |player| player.nickname.as_str());
+        painter.text(
+            pos - egui::Vec2::new(0.0, PING_RADIUS * 1.5),
+            egui::Align2::CENTER_BOTTOM,
+            format!("{} {nickname}", ping_icon(marker.ping.kind)),
+            egui::FontId::proportional(14.0),
+            color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(kind: PingKind) -> Ping {
+        Ping {
+            position: Vec2::new(1.0, 2.0),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_ping_markers_throttle_placing() {
+        let mut ping_markers = PingMarkers::default();
+        let now = Instant::now();
+        let net_id = PlayerNetId(1);
+
+        assert!(ping_markers.place(net_id, ping(PingKind::LookHere), now));
+        assert!(!ping_markers.place(
+            net_id,
+            ping(PingKind::Danger),
+            now + Duration::from_millis(PING_MIN_INTERVAL_MILLIS / 2)
+        ));
+        assert_eq!(ping_markers.markers.len(), 1);
+        assert_eq!(ping_markers.markers[0].ping.kind, PingKind::LookHere);
+
+        assert!(ping_markers.place(
+            net_id,
+            ping(PingKind::Danger),
+            now + Duration::from_millis(PING_MIN_INTERVAL_MILLIS)
+        ));
+        assert_eq!(ping_markers.markers.len(), 1);
+        assert_eq!(ping_markers.markers[0].ping.kind, PingKind::Danger);
+    }
+
+    #[test]
+    fn test_ping_markers_replace_and_expire() {
+        let mut ping_markers = PingMarkers::default();
+        let now = Instant::now();
+
+        ping_markers.receive(
+            PlayerPing {
+                net_id: PlayerNetId(1),
+                ping: ping(PingKind::LookHere),
+            },
+            now,
+        );
+        let later = now + Duration::from_secs(1);
+        ping_markers.receive(
+            PlayerPing {
+                net_id: PlayerNetId(2),
+                ping: ping(PingKind::Danger),
+            },
+            later,
+        );
+        ping_markers.receive(
+            PlayerPing {
+                net_id: PlayerNetId(1),
+                ping: ping(PingKind::BuildHere),
+            },
+            later,
+        );
+        assert_eq!(ping_markers.markers.len(), 2);
+        assert!(ping_markers
+            .markers
+            .iter()
+            .all(|marker| marker.placed_at == later));
+
+        let marker = &ping_markers.markers[0];
+        assert_eq!(marker.alpha(later), 1.0);
+        let fading_at =
+            later + Duration::from_secs_f32(PING_LIFETIME_SECS - PING_FADE_OUT_SECS / 2.0);
+        assert!((marker.alpha(fading_at) - 0.5).abs() < 0.01);
+
+        ping_markers.forget_expired(later + Duration::from_secs_f32(PING_LIFETIME_SECS));
+        assert!(ping_markers.markers.is_empty());
+    }
+}
@@ -1,31 +1,89 @@
-use crate::helpers::PlayerParams;
+use crate::{
+    camera::LevelIntro,
+    helpers::PlayerParams,
+    input::{ActionInput, InputAction, KeyBindings, PlayerRequestsQueue},
+    net::BuilderStates,
+    personal_bests::PersonalBests,
+    ui::{
+        builder_ui::OverlayCameraParams, layout::UiLayout, match_ui::MatchStatus, theme::spacing,
+    },
+};
 use bevy::{
-    ecs::system::{Local, Res, ResMut},
-    input::{keyboard::KeyCode, Input},
+    ecs::{
+        query::With,
+        system::{Query, Res, ResMut},
+    },
+    math::Vec2,
+    transform::components::Transform,
 };
 use bevy_egui::{egui, EguiContext};
 use mr_shared_lib::{
-    messages::RespawnPlayerReason, player::PlayerRole, GameTime, SIMULATIONS_PER_SECOND,
+    framebuffer::FrameNumber,
+    game::{
+        components::{LevelObjectTag, PlayerTag, Spawned},
+        emitter::{Hazard, BEAM_HALF_WIDTH, PROJECTILE_RADIUS},
+        game_mode::GameModeKind,
+        level::{LevelObjectDesc, LevelParams, LevelState, Medal},
+        tether::Tethers,
+    },
+    messages::{
+        BotDifficulty, FinishResult, PlayerNetId, PracticeBotsRequest, RespawnPlayerReason,
+    },
+    player::PlayerRole,
+    registry::EntityRegistry,
+    GameTime, SimulationTime, SIMULATIONS_PER_SECOND,
 };
 
+const PERSONAL_BEST_COLOR: egui::Color32 = egui::Color32::from_rgb(80, 200, 120);
+const TETHER_COLOR: egui::Color32 = egui::Color32::from_rgb(140, 200, 255);
+const TETHER_STRAINED_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 90, 60);
+const TETHER_STROKE_WIDTH: f32 = 2.0;
+const BUILDER_CURSOR_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 80);
+const BUILDER_CURSOR_RADIUS: f32 = 6.0;
+const HAZARD_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 60, 90);
+
+pub fn medal_icon(medal: Medal) -> &'static str {
+    match medal {
+        Medal::Gold => "🥇",
+        Medal::Silver => "🥈",
+        Medal::Bronze => "🥉",
+    }
+}
+
+pub fn format_finish(result: FinishResult) -> String {
+    match result.medal {
+        Some(medal) => format!("{} {:.2}s", medal_icon(medal), result.secs()),
+        None => format!("{:.2}s", result.secs()),
+    }
+}
+
 pub fn help_ui_system(
     time: Res<GameTime>,
     mut egui_context: ResMut<EguiContext>,
     player_params: PlayerParams,
+    ui_layout: Res<UiLayout>,
+    key_bindings: Res<KeyBindings>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
-    let window_width = 280.0;
+    if !ui_layout.active().panels.help {
+        return;
+    }
+    let window_width = 340.0;
     let window_height = 30.0;
 
     egui::Window::new("Help")
         .title_bar(false)
         .collapsible(false)
         .resizable(false)
-        .anchor(egui::Align2::CENTER_BOTTOM, egui::Vec2::new(0.0, -40.0))
+        .anchor(
+            egui::Align2::CENTER_BOTTOM,
+            egui::Vec2::new(0.0, -spacing::SCREEN_EDGE),
+        )
         .fixed_size(egui::Vec2::new(window_width, window_height))
         .show(egui_context.ctx_mut(), |ui| {
             let current_player = player_params.current_player();
+            let is_practice_session = player_params.players.is_practice_session();
 
             ui.centered_and_justified(|ui| {
                 if let Some((respawned_at, _)) =
@@ -38,44 +96,231 @@ pub fn help_ui_system(
                         / SIMULATIONS_PER_SECOND)
                         .ceil() as u16;
                     ui.label(format!("Respawning in {respawning_in_secs}..."));
+                } else if is_practice_session
+                    && current_player.map_or(false, |player| player.role == PlayerRole::Runner)
+                {
+                    ui.label(format!(
+                        "{}: Builder mode, {}: set checkpoint, {}: restart",
+                        key_bindings.label(InputAction::SwitchRole),
+                        key_bindings.label(InputAction::SetCheckpoint),
+                        key_bindings.label(InputAction::RestartFromCheckpoint),
+                    ));
+                } else if current_player
+                    .map_or(false, |player| player.role == PlayerRole::Spectator)
+                {
+                    ui.label(format!(
+                        "Spectating, press {} or {} to join the run",
+                        key_bindings.label(InputAction::SwitchRole),
+                        key_bindings.label(InputAction::ToggleSpectator),
+                    ));
                 } else {
-                    ui.label("Press ESC to toggle Builder mode");
+                    ui.label(format!(
+                        "{}: toggle Builder mode, {}: spectate",
+                        key_bindings.label(InputAction::SwitchRole),
+                        key_bindings.label(InputAction::ToggleSpectator),
+                    ));
                 }
             });
         });
 }
 
-pub struct LeaderboardState {
-    show: bool,
+/// Level intros can be skipped with a button or with
+/// `InputAction::SkipLevelIntro`.
+pub fn level_intro_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut level_intro: ResMut<LevelIntro>,
+    action_input: ActionInput,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !level_intro.is_playing() {
+        return;
+    }
+    if action_input.just_pressed(InputAction::SkipLevelIntro) {
+        level_intro.skip();
+        return;
+    }
+
+    egui::Window::new("Level intro")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(
+            egui::Align2::RIGHT_BOTTOM,
+            egui::Vec2::new(-spacing::SCREEN_EDGE, -spacing::SCREEN_EDGE),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            if ui.button("Skip intro (Space)").clicked() {
+                level_intro.skip();
+            }
+        });
 }
 
-impl Default for LeaderboardState {
-    fn default() -> Self {
-        Self { show: true }
+/// Lets runners in practice sessions race against bots.
+pub fn practice_bots_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    player_params: PlayerParams,
+    mut player_requests: ResMut<PlayerRequestsQueue>,
+    ui_layout: Res<UiLayout>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let is_runner = player_params
+        .current_player()
+        .map_or(false, |player| player.role == PlayerRole::Runner);
+    if !is_runner
+        || !player_params.players.is_practice_session()
+        || !ui_layout.active().panels.practice_bots
+    {
+        return;
     }
+    let bots_count = player_params
+        .players
+        .values()
+        .filter(|player| player.is_bot && player.is_connected)
+        .count();
+
+    egui::Window::new("Practice bots")
+        .collapsible(true)
+        .resizable(false)
+        .anchor(
+            egui::Align2::RIGHT_BOTTOM,
+            egui::Vec2::new(-spacing::SCREEN_EDGE, -spacing::SCREEN_EDGE),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                for difficulty in BotDifficulty::ALL {
+                    if ui.button(format!("Add {}", difficulty.label())).clicked() {
+                        player_requests
+                            .practice_bots
+                            .push(PracticeBotsRequest::Spawn(difficulty));
+                    }
+                }
+            });
+            let remove_button = egui::Button::new(format!("Remove bots ({bots_count})"));
+            if ui.add_enabled(bots_count > 0, remove_button).clicked() {
+                player_requests
+                    .practice_bots
+                    .push(PracticeBotsRequest::RemoveAll);
+            }
+        });
 }
 
+/// Is shown while the current player is waiting to be respawned after
+/// finishing.
+pub fn finish_screen_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    player_params: PlayerParams,
+    personal_bests: Res<PersonalBests>,
+    level_state: Res<LevelState>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let has_finished = player_params.current_player().map_or(false, |player| {
+        matches!(player.respawning_at, Some((_, RespawnPlayerReason::Finish)))
+    });
+    if !has_finished {
+        return;
+    }
+
+    egui::Window::new("Finish")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(
+            egui::Align2::CENTER_TOP,
+            egui::Vec2::new(0.0, spacing::SCREEN_EDGE),
+        )
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.vertical_centered(|ui| {
+                let Some(last_finish) = personal_bests.last_finish else {
+                    ui.heading("Finished!");
+                    ui.label("Runs resumed from checkpoints aren't timed");
+                    return;
+                };
+
+                ui.heading(format!("Finished in {:.2}s", last_finish.result.secs()));
+                match last_finish.result.medal {
+                    Some(medal) => {
+                        ui.label(format!("{} {medal} medal", medal_icon(medal)));
+                    }
+                    None if level_state.settings().medal_times.is_some() => {
+                        ui.label("No medal");
+                    }
+                    None => {}
+                }
+                match last_finish.delta_secs() {
+                    Some(delta) if delta < 0.0 => {
+                        ui.colored_label(
+                            PERSONAL_BEST_COLOR,
+                            format!("New personal best: {delta:.2}s"),
+                        );
+                    }
+                    Some(delta) => {
+                        ui.label(format!("Personal best: +{delta:.2}s"));
+                    }
+                    None => {
+                        ui.label("New personal best");
+                    }
+                }
+
+                if let Some(medal_times) = level_state.settings().medal_times {
+                    ui.horizontal(|ui| {
+                        for medal in Medal::ALL {
+                            ui.label(format!(
+                                "{} {:.2}s",
+                                medal_icon(medal),
+                                medal_times.get(medal)
+                            ));
+                        }
+                    });
+                }
+            });
+        });
+}
+
+/// Toggling the leaderboard changes the layout preset of the current role.
 pub fn leaderboard_ui_system(
-    mut state: Local<LeaderboardState>,
-    keyboard_input: Res<Input<KeyCode>>,
+    action_input: ActionInput,
     mut egui_context: ResMut<EguiContext>,
+    mut ui_layout: ResMut<UiLayout>,
     player_params: PlayerParams,
+    level_state: Res<LevelState>,
+    match_status: Res<MatchStatus>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
-    if keyboard_input.just_pressed(KeyCode::F3) {
-        state.show = !state.show;
+    if action_input.just_pressed(InputAction::ToggleLeaderboard) {
+        let role = ui_layout.role();
+        let mut preset = *ui_layout.active();
+        preset.panels.leaderboard = !preset.panels.leaderboard;
+        ui_layout.set_preset(role, preset);
     }
 
-    if !state.show {
+    let layout = ui_layout.active();
+    if !layout.panels.leaderboard {
         return;
     }
 
-    egui::Window::new("Leaderboard [F3]")
+    let title = format!(
+        "Leaderboard [{}]",
+        action_input
+            .key_bindings
+            .label(InputAction::ToggleLeaderboard)
+    );
+    egui::Window::new(title)
+        .id(egui::Id::new("leaderboard"))
         .collapsible(false)
         .resizable(false)
-        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-35.0, 35.0))
+        .min_width(layout.side_panel_width)
+        .anchor(
+            egui::Align2::RIGHT_TOP,
+            egui::Vec2::new(-spacing::SCREEN_EDGE, spacing::SCREEN_EDGE),
+        )
         .show(egui_context.ctx_mut(), |ui| {
+            if match_status.game_mode != GameModeKind::FreeBuild {
+                ui.label(format!("Game mode: {}", match_status.game_mode.label()));
+            }
             egui::Grid::new("stats board")
                 .min_col_width(13.0)
                 .show(ui, |ui| {
@@ -90,6 +335,9 @@ pub fn leaderboard_ui_system(
                     ui.label("Nickname");
                     ui.label("Finishes");
                     ui.label("Deaths");
+                    ui.label("Best");
+                    ui.label("All time")
+                        .on_hover_text("Finishes and deaths across all sessions");
                     ui.label("");
                     ui.end_row();
                     for (net_id, player) in players.into_iter() {
@@ -97,8 +345,10 @@ pub fn leaderboard_ui_system(
                             match (player.is_connected, player.role, player.respawning_at) {
                                 (false, _, _) => "🔌",
                                 (_, PlayerRole::Builder, _) => "🔨",
+                                (_, PlayerRole::Spectator, _) => "👁",
                                 (_, _, Some((_, RespawnPlayerReason::Finish))) => "★",
                                 (_, _, Some((_, RespawnPlayerReason::Death))) => "💀",
+                                (_, _, Some((_, RespawnPlayerReason::Checkpoint))) => "🚩",
                                 _ => "",
                             };
 
@@ -107,6 +357,21 @@ pub fn leaderboard_ui_system(
                             egui::RichText::new(&player.nickname),
                             egui::RichText::new(format!("{}", player.finishes)),
                             egui::RichText::new(format!("{}", player.deaths)),
+                            egui::RichText::new(
+                                player
+                                    .best_finish
+                                    .map_or_else(|| "-".to_owned(), format_finish),
+                            ),
+                            // Guests don't have lifetime stats.
+                            egui::RichText::new(
+                                player
+                                    .lifetime_finishes()
+                                    .zip(player.lifetime_deaths())
+                                    .map_or_else(
+                                        || "-".to_owned(),
+                                        |(finishes, deaths)| format!("{finishes} / {deaths}"),
+                                    ),
+                            ),
                         ];
 
                         for column in columns {
@@ -122,5 +387,188 @@ pub fn leaderboard_ui_system(
                         ui.end_row();
                     }
                 });
+
+            let respawns = &level_state.settings().respawns;
+            let seconds = |frames: FrameNumber| frames.value() as f32 / SIMULATIONS_PER_SECOND;
+            ui.separator();
+            ui.label(format!(
+                "Respawn delay: {:.1}s",
+                seconds(respawns.death_respawn_delay)
+            ));
+            ui.label(format!(
+                "Finish cooldown: {:.1}s",
+                seconds(respawns.finish_cooldown)
+            ));
+            if respawns.deaths_reset_checkpoints {
+                ui.label("Dying resets checkpoints");
+            }
         });
 }
+
+/// Draws tethers between the rendered (i.e. predicted) positions of the
+/// players, turning them red as they get strained.
+pub fn draw_tethers_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<GameTime>,
+    tethers: Res<Tethers>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    players: Query<(&Transform, &Spawned), With<PlayerTag>>,
+    overlay_camera_params: OverlayCameraParams,
+    ui_layout: Res<UiLayout>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if tethers.pairs.is_empty() || !ui_layout.active().panels.tethers {
+        return;
+    }
+
+    let player_position = |net_id: PlayerNetId| {
+        let (transform, spawned) = players.get(player_registry.get_entity(net_id)?).ok()?;
+        spawned
+            .is_spawned(time.frame_number)
+            .then(|| transform.translation.truncate())
+    };
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for (a, b) in tethers.pairs.iter() {
+        let Some((a_position, b_position)) = player_position(*a).zip(player_position(*b)) else {
+            continue;
+        };
+        let Some((a_pos, b_pos)) = overlay_camera_params
+            .world_to_egui_pos(a_position)
+            .zip(overlay_camera_params.world_to_egui_pos(b_position))
+        else {
+            continue;
+        };
+        let strain = (a_position.distance(b_position) / tethers.max_distance).clamp(0.0, 1.0);
+        let color = egui::Color32::from_rgb(
+            lerp_channel(TETHER_COLOR.r(), TETHER_STRAINED_COLOR.r(), strain),
+            lerp_channel(TETHER_COLOR.g(), TETHER_STRAINED_COLOR.g(), strain),
+            lerp_channel(TETHER_COLOR.b(), TETHER_STRAINED_COLOR.b(), strain),
+        );
+        painter.line_segment(
+            [a_pos, b_pos],
+            egui::Stroke::new(TETHER_STROKE_WIDTH, color),
+        );
+    }
+}
+
+/// Shows where other builders are looking and which objects they edit.
+pub fn draw_builder_cursors_system(
+    mut egui_context: ResMut<EguiContext>,
+    player_params: PlayerParams,
+    builder_states: Res<BuilderStates>,
+    level_state: Res<LevelState>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if builder_states.0.is_empty() {
+        return;
+    }
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for builder_state in &builder_states.0 {
+        if Some(builder_state.net_id) == player_params.current_player_net_id.0 {
+            continue;
+        }
+        let Some(player) = player_params.players.get(&builder_state.net_id) else {
+            continue;
+        };
+        if !player.is_connected || player.role != PlayerRole::Builder {
+            continue;
+        }
+        let Some(pos) = overlay_camera_params.world_to_egui_pos(builder_state.camera_position)
+        else {
+            continue;
+        };
+
+        painter.circle_stroke(
+            pos,
+            BUILDER_CURSOR_RADIUS,
+            egui::Stroke::new(TETHER_STROKE_WIDTH, BUILDER_CURSOR_COLOR),
+        );
+        let mut label = format!("🔨 {}", player.nickname);
+        if let Some(level_object) = builder_state
+            .selected_object
+            .and_then(|net_id| level_state.object(net_id))
+        {
+            label = format!("{label}: {}", level_object.label);
+        }
+        painter.text(
+            pos + egui::Vec2::new(0.0, BUILDER_CURSOR_RADIUS * 2.0),
+            egui::Align2::CENTER_TOP,
+            label,
+            egui::FontId::proportional(14.0),
+            BUILDER_CURSOR_COLOR,
+        );
+    }
+}
+
+/// Hazards aren't entities, they are computed from the same emitter configs
+/// and frame numbers that the simulation uses, so they are drawn exactly where
+/// they can kill the current player.
+pub fn draw_emitter_hazards_system(
+    mut egui_context: ResMut<EguiContext>,
+    time: Res<SimulationTime>,
+    level: LevelParams,
+    level_objects: Query<(&Transform, &Spawned), With<LevelObjectTag>>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    // Converts a world distance to the screen one, taking the camera zoom into
+    // account.
+    let screen_distance = |position: Vec2, distance: f32| {
+        let a = overlay_camera_params.world_to_egui_pos(position)?;
+        let b = overlay_camera_params.world_to_egui_pos(position + Vec2::new(distance, 0.0))?;
+        Some((b - a).length())
+    };
+    for level_object in level.level_state.objects().values() {
+        let LevelObjectDesc::Emitter(emitter) = &level_object.desc else {
+            continue;
+        };
+        let Some((transform, spawned)) = level
+            .entity_registry
+            .get_entity(level_object.net_id)
+            .and_then(|entity| level_objects.get(entity).ok())
+        else {
+            continue;
+        };
+        if !spawned.is_spawned(time.player_frame) {
+            continue;
+        }
+        let origin = transform.translation.truncate();
+        match emitter.hazard(origin, time.player_generation, time.player_frame) {
+            Some(Hazard::Beam { start, end }) => {
+                let Some((start_pos, end_pos)) = overlay_camera_params
+                    .world_to_egui_pos(start)
+                    .zip(overlay_camera_params.world_to_egui_pos(end))
+                else {
+                    continue;
+                };
+                let width = screen_distance(start, BEAM_HALF_WIDTH * 2.0).unwrap_or(1.0);
+                painter.line_segment([start_pos, end_pos], egui::Stroke::new(width, HAZARD_COLOR));
+            }
+            Some(Hazard::Projectile { position }) => {
+                let Some(pos) = overlay_camera_params.world_to_egui_pos(position) else {
+                    continue;
+                };
+                let radius = screen_distance(position, PROJECTILE_RADIUS).unwrap_or(1.0);
+                painter.circle_filled(pos, radius, HAZARD_COLOR);
+            }
+            None => {}
+        }
+    }
+}
+
+fn lerp_channel(from: u8, to: u8, t: f32) -> u8 {
+    (from as f32 + (to as f32 - from as f32) * t).round() as u8
+}
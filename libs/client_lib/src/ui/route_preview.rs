@@ -0,0 +1,290 @@
+//! Builders can slow down, pause or scrub through the movement of level
+//! objects to inspect platform timings. The preview is purely visual: it
+//! evaluates routes with `predict_object_position` at the preview frame and
+//! draws markers on top of the objects, while the simulation (and the objects
+//! themselves) keep moving in sync with the server.
+
+use crate::ui::builder_ui::{EditedLevelObject, OverlayCameraParams};
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    time::Time,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        level::{LevelParams, ObjectRoute},
+        level_objects::closest_start_frame_to_time,
+    },
+    SimulationTime, SIMULATIONS_PER_SECOND,
+};
+
+pub const PREVIEW_SPEEDS: [f64; 4] = [0.1, 0.25, 0.5, 1.0];
+/// Frame numbers wrap after this many frames, each wrap bumps the generation.
+const FRAMES_PER_GENERATION: u64 = u16::MAX as u64 + 1;
+const PREVIEW_MARKER_RADIUS: f32 = 6.0;
+const PREVIEW_MARKER_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 170, 60);
+const PREVIEW_STROKE_WIDTH: f32 = 2.0;
+
+#[derive(Resource)]
+pub struct RoutePreview {
+    pub enabled: bool,
+    pub paused: bool,
+    pub speed: f64,
+    /// Unlike `FrameNumber`, doesn't wrap (see [`absolute_frame`]). Is `None`
+    /// until the preview gets synced with the current simulation frame.
+    frame: Option<f64>,
+}
+
+impl Default for RoutePreview {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            paused: false,
+            speed: 1.0,
+            frame: None,
+        }
+    }
+}
+
+impl RoutePreview {
+    /// Jumps back to the given (normally current) simulation frame.
+    pub fn sync(&mut self, generation: u64, frame_number: FrameNumber) {
+        self.frame = Some(absolute_frame(generation, frame_number) as f64);
+    }
+
+    pub fn advance(&mut self, delta_secs: f64) {
+        if self.paused {
+            return;
+        }
+        if let Some(frame) = &mut self.frame {
+            *frame += delta_secs * SIMULATIONS_PER_SECOND as f64 * self.speed;
+        }
+    }
+
+    /// Pauses the preview and moves it by the given number of frames.
+    pub fn step(&mut self, frames: i64) {
+        self.paused = true;
+        if let Some(frame) = &mut self.frame {
+            *frame = (frame.floor() + frames as f64).max(0.0);
+        }
+    }
+
+    pub fn set_frame(&mut self, absolute_frame: u64) {
+        self.frame = Some(absolute_frame as f64);
+    }
+
+    pub fn absolute_frame(&self) -> Option<u64> {
+        self.frame.map(|frame| frame.floor() as u64)
+    }
+
+    /// Returns the generation and the frame number to evaluate routes at.
+    pub fn preview_frame(&self) -> Option<(u64, FrameNumber)> {
+        self.absolute_frame().map(split_absolute_frame)
+    }
+}
+
+pub fn absolute_frame(generation: u64, frame_number: FrameNumber) -> u64 {
+    generation * FRAMES_PER_GENERATION + frame_number.value() as u64
+}
+
+pub fn split_absolute_frame(absolute_frame: u64) -> (u64, FrameNumber) {
+    (
+        absolute_frame / FRAMES_PER_GENERATION,
+        FrameNumber::new((absolute_frame % FRAMES_PER_GENERATION) as u16),
+    )
+}
+
+/// Returns the absolute frame at which the current period of the route
+/// started, using the same math as the simulation.
+pub fn route_cycle_start(route: &ObjectRoute, frame: u64) -> u64 {
+    let (generation, frame_number) = split_absolute_frame(frame);
+    let start = closest_start_frame_to_time(
+        generation,
+        frame_number,
+        route.start_frame_offset,
+        route.period,
+    );
+    let start = absolute_frame(generation, start);
+    // The cycle may have started before the frame counter wrapped.
+    if start > frame {
+        start.saturating_sub(FRAMES_PER_GENERATION)
+    } else {
+        start
+    }
+}
+
+pub fn route_preview_ui_system(
+    time: Res<Time>,
+    mut egui_context: ResMut<EguiContext>,
+    mut route_preview: ResMut<RoutePreview>,
+    simulation_time: Res<SimulationTime>,
+    edited_level_object: Res<EditedLevelObject>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if route_preview.enabled {
+        route_preview.advance(time.delta_seconds_f64());
+    }
+
+    egui::Window::new("Route preview")
+        .collapsible(true)
+        .default_open(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let was_enabled = route_preview.enabled;
+            ui.checkbox(&mut route_preview.enabled, "Preview routes");
+            if !route_preview.enabled {
+                ui.label("Shows where moving objects are at a slowed down or paused time");
+                return;
+            }
+            if !was_enabled || route_preview.frame.is_none() {
+                route_preview.sync(
+                    simulation_time.server_generation,
+                    simulation_time.server_frame,
+                );
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("⏴").on_hover_text("Previous frame").clicked() {
+                    route_preview.step(-1);
+                }
+                let play_label = if route_preview.paused { "▶" } else { "⏸" };
+                if ui.button(play_label).clicked() {
+                    route_preview.paused = !route_preview.paused;
+                }
+                if ui.button("⏵").on_hover_text("Next frame").clicked() {
+                    route_preview.step(1);
+                }
+                if ui.button("Live").clicked() {
+                    route_preview.sync(
+                        simulation_time.server_generation,
+                        simulation_time.server_frame,
+                    );
+                    route_preview.paused = false;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Speed");
+                for speed in PREVIEW_SPEEDS {
+                    if ui
+                        .selectable_label(route_preview.speed == speed, format!("{}x", speed))
+                        .clicked()
+                    {
+                        route_preview.speed = speed;
+                    }
+                }
+            });
+            if let Some((generation, frame_number)) = route_preview.preview_frame() {
+                ui.label(format!("Frame: {}:{}", generation, frame_number.value()));
+            }
+
+            ui.separator();
+            let route = edited_level_object
+                .object
+                .as_ref()
+                .and_then(|(_, level_object)| level_object.route.as_ref())
+                .filter(|route| route.period.value() > 0);
+            let (Some(route), Some(frame)) = (route, route_preview.absolute_frame()) else {
+                ui.label("Select an object with a route to scrub through its period");
+                return;
+            };
+            let cycle_start = route_cycle_start(route, frame);
+            let mut phase = frame.saturating_sub(cycle_start);
+            let response = ui.add(
+                egui::Slider::new(&mut phase, 0..=route.period.value() as u64 - 1)
+                    .text("Period frame"),
+            );
+            if response.changed() {
+                route_preview.paused = true;
+                route_preview.set_frame(cycle_start + phase);
+            }
+        });
+}
+
+pub fn draw_route_preview_system(
+    mut egui_context: ResMut<EguiContext>,
+    route_preview: Res<RoutePreview>,
+    level_params: LevelParams,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if !route_preview.enabled {
+        return;
+    }
+    let Some((generation, frame_number)) = route_preview.preview_frame() else {
+        return;
+    };
+
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    let stroke = egui::Stroke::new(PREVIEW_STROKE_WIDTH, PREVIEW_MARKER_COLOR);
+    for level_object in level_params.level_state.objects().values() {
+        if level_object.route.is_none() {
+            continue;
+        }
+        let Some(pos) = level_params
+            .predict_object_position(level_object.net_id, generation, frame_number)
+            .and_then(|position| overlay_camera_params.world_to_egui_pos(position))
+        else {
+            continue;
+        };
+        painter.circle_stroke(pos, PREVIEW_MARKER_RADIUS, stroke);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::game::level::ObjectRouteDesc;
+
+    #[test]
+    fn test_route_preview_playback() {
+        let mut route_preview = RoutePreview {
+            speed: 0.5,
+            ..Default::default()
+        };
+        route_preview.sync(1, FrameNumber::new(u16::MAX));
+
+        // Half the speed, so one second of playback moves by half a second of frames.
+        route_preview.advance(1.0);
+        let expected =
+            absolute_frame(1, FrameNumber::new(u16::MAX)) + (SIMULATIONS_PER_SECOND / 2.0) as u64;
+        assert_eq!(route_preview.absolute_frame(), Some(expected));
+        assert_eq!(
+            route_preview.preview_frame(),
+            Some((
+                2,
+                FrameNumber::new((SIMULATIONS_PER_SECOND / 2.0) as u16 - 1)
+            ))
+        );
+
+        // Stepping pauses the preview.
+        route_preview.step(-1);
+        route_preview.advance(1.0);
+        assert!(route_preview.paused);
+        assert_eq!(route_preview.absolute_frame(), Some(expected - 1));
+    }
+
+    #[test]
+    fn test_route_cycle_start() {
+        let route = ObjectRoute {
+            start_frame_offset: FrameNumber::new(10),
+            ..ObjectRoute::new(
+                FrameNumber::new(100),
+                ObjectRouteDesc::ForwardCycle(Vec::new()),
+            )
+        };
+        let frame = absolute_frame(1, FrameNumber::new(250));
+        let cycle_start = route_cycle_start(&route, frame);
+        assert!(cycle_start <= frame && frame - cycle_start < 100);
+
+        // Right after the frame counter wraps, the cycle has started in the
+        // previous generation.
+        let frame = absolute_frame(2, FrameNumber::new(0));
+        let cycle_start = route_cycle_start(&route, frame);
+        assert!(cycle_start <= frame && frame - cycle_start < 100);
+    }
+}
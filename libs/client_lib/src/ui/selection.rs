@@ -0,0 +1,310 @@
+//! Builders can select several objects at once, with Shift+click or by
+//! dragging a selection box from an empty spot, to move, rotate or despawn
+//! them together. Group transforms are sent as batches of updates, which
+//! also get undone at once.
+
+use crate::{
+    helpers::DRAGGING_THRESHOLD,
+    input::MouseWorldPosition,
+    ui::builder_ui::{EditedLevelObject, OverlayCameraParams},
+};
+use bevy::{
+    ecs::system::{Res, ResMut},
+    math::Vec2,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::{
+    game::{
+        level::{LevelObject, LevelObjectDesc, LevelState},
+        level_objects::{AnnotationDesc, AnnotationKind, PlaneFormDesc},
+    },
+    messages::EntityNetId,
+};
+
+const SELECTION_COLOR: egui::Color32 = egui::Color32::from_rgb(90, 200, 255);
+const SELECTION_MARKER_RADIUS: f32 = 5.0;
+const SELECTION_STROKE_WIDTH: f32 = 1.5;
+
+#[derive(Clone, Copy)]
+pub struct SelectionBox {
+    /// The world position of the corner where dragging started.
+    pub start: Vec2,
+    screen_start: Vec2,
+}
+
+impl SelectionBox {
+    pub fn new(start: Vec2, screen_start: Vec2) -> Self {
+        Self {
+            start,
+            screen_start,
+        }
+    }
+
+    /// Clicking an empty spot without dragging keeps the selection.
+    pub fn is_dragged(&self, screen_position: Vec2) -> bool {
+        self.screen_start.distance_squared(screen_position)
+            > DRAGGING_THRESHOLD * DRAGGING_THRESHOLD
+    }
+}
+
+/// Positions of the selected objects at the moment one of them started being
+/// dragged, the rest of the group follows the dragged one.
+pub struct GroupDrag {
+    origin: Vec2,
+    objects: Vec<LevelObject>,
+}
+
+impl GroupDrag {
+    /// `origin` is the position of the dragged object, which isn't expected
+    /// to be among `objects`.
+    pub fn new(origin: Vec2, objects: Vec<LevelObject>) -> Self {
+        Self { origin, objects }
+    }
+
+    pub fn moved_objects(&self, dragged_object_position: Vec2) -> Vec<LevelObject> {
+        let mut objects = self.objects.clone();
+        translate_objects(&mut objects, dragged_object_position - self.origin);
+        objects
+    }
+}
+
+/// Returns the objects which positions are inside the box.
+pub fn objects_in_box<'a>(
+    objects: impl IntoIterator<Item = &'a LevelObject>,
+    corner: Vec2,
+    opposite_corner: Vec2,
+) -> Vec<EntityNetId> {
+    let min = corner.min(opposite_corner);
+    let max = corner.max(opposite_corner);
+    let mut net_ids = objects
+        .into_iter()
+        .filter(|level_object| {
+            level_object.desc.position().map_or(false, |position| {
+                position.cmpge(min).all() && position.cmple(max).all()
+            })
+        })
+        .map(|level_object| level_object.net_id)
+        .collect::<Vec<_>>();
+    net_ids.sort_by_key(|net_id| net_id.0);
+    net_ids
+}
+
+pub fn translate_objects(objects: &mut [LevelObject], offset: Vec2) {
+    for level_object in objects {
+        if let Some(position) = level_object.desc.position_mut() {
+            *position += offset;
+        }
+    }
+}
+
+/// Rotates the objects around the center of their positions. Level objects
+/// don't have rotations of their own, so cubes, rectangles, circles and regions
+/// stay axis-aligned, only their positions get rotated. Concave planes,
+/// measurements, emitters and jump pads are rotated as a whole.
+pub fn rotate_objects(objects: &mut [LevelObject], angle_degrees: f32) {
+    let positions = objects
+        .iter()
+        .filter_map(|level_object| level_object.desc.position())
+        .collect::<Vec<_>>();
+    if positions.is_empty() {
+        return;
+    }
+    let center = positions.iter().sum::<Vec2>() / positions.len() as f32;
+    let rotation = Vec2::from_angle(angle_degrees.to_radians());
+
+    for level_object in objects {
+        if let Some(position) = level_object.desc.position_mut() {
+            *position = center + rotation.rotate(*position - center);
+        }
+        match &mut level_object.desc {
+            LevelObjectDesc::Plane(plane) => {
+                if let PlaneFormDesc::Concave { points } = &mut plane.form_desc {
+                    for point in points {
+                        *point = rotation.rotate(*point);
+                    }
+                }
+                if let Some(jump_pad) = &mut plane.jump_pad {
+                    jump_pad.impulse = rotation.rotate(jump_pad.impulse);
+                }
+            }
+            LevelObjectDesc::Annotation(AnnotationDesc {
+                kind: AnnotationKind::Measurement { end },
+                ..
+            }) => {
+                *end = rotation.rotate(*end);
+            }
+            LevelObjectDesc::Emitter(emitter) => {
+                // The builder UI expects angles within [-180, 180].
+                emitter.angle_degrees =
+                    (emitter.angle_degrees + angle_degrees + 180.0).rem_euclid(360.0) - 180.0;
+            }
+            LevelObjectDesc::Cube(_)
+            | LevelObjectDesc::RoutePoint(_)
+            | LevelObjectDesc::Annotation(_)
+            | LevelObjectDesc::CameraAnchor(_)
+            | LevelObjectDesc::DeathZone(_)
+            | LevelObjectDesc::FinishZone(_) => {}
+        }
+    }
+}
+
+pub fn draw_selection_system(
+    mut egui_context: ResMut<EguiContext>,
+    edited_level_object: Res<EditedLevelObject>,
+    level_state: Res<LevelState>,
+    mouse_world_position: Res<MouseWorldPosition>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    let stroke = egui::Stroke::new(SELECTION_STROKE_WIDTH, SELECTION_COLOR);
+
+    if let Some(selection_box) = &edited_level_object.selection_box {
+        let (start, end) = (selection_box.start, mouse_world_position.0);
+        let corners = [
+            start,
+            Vec2::new(end.x, start.y),
+            end,
+            Vec2::new(start.x, end.y),
+        ]
+        .into_iter()
+        .map(|corner| overlay_camera_params.world_to_egui_pos(corner))
+        .collect::<Option<Vec<_>>>();
+        if let Some(corners) = corners {
+            painter.add(egui::Shape::convex_polygon(
+                corners,
+                SELECTION_COLOR.linear_multiply(0.1),
+                stroke,
+            ));
+        }
+    }
+
+    // A single selected object is highlighted as the edited one.
+    if edited_level_object.selection.len() < 2 {
+        return;
+    }
+    for net_id in &edited_level_object.selection {
+        let Some(pos) = level_state
+            .object(*net_id)
+            .and_then(|level_object| level_object.desc.position())
+            .and_then(|position| overlay_camera_params.world_to_egui_pos(position))
+        else {
+            continue;
+        };
+        painter.circle_stroke(pos, SELECTION_MARKER_RADIUS, stroke);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::{
+        game::{emitter::EmitterDesc, level::CollisionLogic, level_objects::PlaneDesc},
+        test_harness::cube,
+    };
+
+    fn level_object(net_id: u16, desc: LevelObjectDesc) -> LevelObject {
+        LevelObject {
+            net_id: EntityNetId(net_id),
+            label: String::new(),
+            desc,
+            route: None,
+            collision_logic: CollisionLogic::None,
+        }
+    }
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn test_objects_in_box() {
+        let objects = [
+            cube(2, Vec2::new(1.0, 1.0), 1.0),
+            cube(0, Vec2::new(-1.0, 2.0), 1.0),
+            cube(1, Vec2::new(3.0, 1.0), 1.0),
+        ];
+        // The corners can be passed in any order.
+        let net_ids = objects_in_box(&objects, Vec2::new(2.0, 3.0), Vec2::new(-2.0, 0.0));
+        assert_eq!(net_ids, vec![EntityNetId(0), EntityNetId(2)]);
+        assert!(objects_in_box(&objects, Vec2::ZERO, Vec2::new(0.5, 0.5)).is_empty());
+    }
+
+    #[test]
+    fn test_group_drag() {
+        let group_drag = GroupDrag::new(
+            Vec2::new(1.0, 1.0),
+            vec![
+                cube(1, Vec2::new(2.0, 0.0), 1.0),
+                cube(2, Vec2::new(0.0, 3.0), 1.0),
+            ],
+        );
+        let moved_objects = group_drag.moved_objects(Vec2::new(2.0, -1.0));
+        assert_eq!(
+            moved_objects,
+            vec![
+                cube(1, Vec2::new(3.0, -2.0), 1.0),
+                cube(2, Vec2::new(1.0, 1.0), 1.0)
+            ]
+        );
+        // Offsets are calculated from the original positions, so they don't
+        // accumulate while dragging.
+        let moved_objects = group_drag.moved_objects(Vec2::new(1.0, 2.0));
+        assert_eq!(
+            moved_objects,
+            vec![
+                cube(1, Vec2::new(2.0, 1.0), 1.0),
+                cube(2, Vec2::new(0.0, 4.0), 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rotate_objects() {
+        let mut objects = vec![
+            cube(0, Vec2::new(0.0, 0.0), 1.0),
+            level_object(
+                1,
+                LevelObjectDesc::Plane(PlaneDesc {
+                    position: Vec2::new(2.0, 0.0),
+                    form_desc: PlaneFormDesc::Concave {
+                        points: vec![Vec2::new(1.0, 0.0), Vec2::new(0.0, 1.0)],
+                    },
+                    is_spawn_area: false,
+                    appearance: Default::default(),
+                    jump_pad: None,
+                }),
+            ),
+            level_object(
+                2,
+                LevelObjectDesc::Emitter(EmitterDesc {
+                    position: Vec2::new(1.0, 3.0),
+                    angle_degrees: 170.0,
+                    ..Default::default()
+                }),
+            ),
+        ];
+        rotate_objects(&mut objects, 90.0);
+
+        // The center is at (1.0, 1.0).
+        assert_close(objects[0].desc.position().unwrap(), Vec2::new(2.0, 0.0));
+        assert_close(objects[1].desc.position().unwrap(), Vec2::new(2.0, 2.0));
+        assert_close(objects[2].desc.position().unwrap(), Vec2::new(-1.0, 1.0));
+        let LevelObjectDesc::Plane(PlaneDesc {
+            form_desc: PlaneFormDesc::Concave { points },
+            ..
+        }) = &objects[1].desc
+        else {
+            unreachable!()
+        };
+        assert_close(points[0], Vec2::new(0.0, 1.0));
+        assert_close(points[1], Vec2::new(-1.0, 0.0));
+        let LevelObjectDesc::Emitter(emitter) = &objects[2].desc else {
+            unreachable!()
+        };
+        assert!((emitter.angle_degrees - -100.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    input::{LevelObjectRequestsQueue, MouseWorldPosition},
+    ui::builder_ui::{EditedLevelObject, OverlayCameraParams},
+};
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    input::{mouse::MouseButton, Input},
+    math::Vec2,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::{
+    game::{
+        level::LevelObjectDesc,
+        level_objects::{simplify_outline, PlaneDesc, PlaneFormDesc},
+        polygon::{apply_brush, is_simple_polygon, BrushError, BrushOperation},
+    },
+    messages::EntityNetId,
+};
+
+pub const DEFAULT_BRUSH_RADIUS: f32 = 1.0;
+pub const MIN_BRUSH_RADIUS: f32 = 0.1;
+pub const MAX_BRUSH_RADIUS: f32 = 10.0;
+/// A new stamp is applied once the cursor moves by this fraction of the radius.
+const BRUSH_STAMP_SPACING: f32 = 0.25;
+/// Every stamp adds a bunch of points, most of which end up lying on almost
+/// straight lines by the end of a stroke.
+const BRUSH_SIMPLIFICATION_TOLERANCE: f32 = 0.01;
+const BRUSH_COLOR: egui::Color32 = egui::Color32::from_rgb(120, 200, 255);
+const BRUSH_STROKE_WIDTH: f32 = 2.0;
+
+#[derive(Resource)]
+pub struct TerrainBrush {
+    pub mode: Option<BrushOperation>,
+    pub radius: f32,
+    /// The error of the latest skipped stamp.
+    pub last_error: Option<BrushError>,
+    target: Option<EntityNetId>,
+    stroke: Option<BrushStroke>,
+}
+
+impl Default for TerrainBrush {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            radius: DEFAULT_BRUSH_RADIUS,
+            last_error: None,
+            target: None,
+            stroke: None,
+        }
+    }
+}
+
+impl TerrainBrush {
+    fn reset(&mut self, target: Option<EntityNetId>) {
+        self.mode = None;
+        self.last_error = None;
+        self.target = target;
+        self.stroke = None;
+    }
+}
+
+/// Stamps are applied to a local copy of the plane outline, which gets sent to
+/// the server as a single update once the stroke is finished.
+struct BrushStroke {
+    points: Vec<Vec2>,
+    last_stamp: Option<Vec2>,
+}
+
+pub fn is_terrain_brush_active(terrain_brush: Res<TerrainBrush>) -> bool {
+    terrain_brush.mode.is_some()
+}
+
+pub fn terrain_brush_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut terrain_brush: ResMut<TerrainBrush>,
+    mut edited_level_object: ResMut<EditedLevelObject>,
+    mut requests_queue: ResMut<LevelObjectRequestsQueue>,
+    mouse_world_position: Res<MouseWorldPosition>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    overlay_camera_params: OverlayCameraParams,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let Some((_, level_object)) = edited_level_object.object.as_mut() else {
+        terrain_brush.reset(None);
+        return;
+    };
+    if terrain_brush.target != Some(level_object.net_id) {
+        terrain_brush.reset(Some(level_object.net_id));
+    }
+    let LevelObjectDesc::Plane(PlaneDesc {
+        position,
+        form_desc: PlaneFormDesc::Concave { points },
+        ..
+    }) = &mut level_object.desc
+    else {
+        terrain_brush.reset(Some(level_object.net_id));
+        return;
+    };
+    let Some(operation) = terrain_brush.mode else {
+        return;
+    };
+
+    let ctx = egui_context.ctx_mut();
+    let brush_position = mouse_world_position.0 - *position;
+    if mouse_button_input.just_pressed(MouseButton::Left) && !ctx.is_pointer_over_area() {
+        terrain_brush.stroke = Some(BrushStroke {
+            points: points.clone(),
+            last_stamp: None,
+        });
+        terrain_brush.last_error = None;
+    }
+
+    let radius = terrain_brush.radius;
+    if let Some(stroke) = terrain_brush.stroke.as_mut() {
+        let mut error = None;
+        if mouse_button_input.pressed(MouseButton::Left)
+            && stroke.last_stamp.map_or(true, |last_stamp| {
+                last_stamp.distance(brush_position) >= radius * BRUSH_STAMP_SPACING
+            })
+        {
+            stroke.last_stamp = Some(brush_position);
+            match apply_brush(&stroke.points, brush_position, radius, operation) {
+                Ok(new_points) => stroke.points = new_points,
+                Err(err) => error = Some(err),
+            }
+        }
+        if error.is_some() {
+            terrain_brush.last_error = error;
+        }
+    }
+
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        if let Some(stroke) = terrain_brush.stroke.take() {
+            let simplified_points =
+                simplify_outline(&stroke.points, BRUSH_SIMPLIFICATION_TOLERANCE);
+            let new_points = if is_simple_polygon(&simplified_points) {
+                simplified_points
+            } else {
+                stroke.points
+            };
+            if *points != new_points {
+                *points = new_points;
+                requests_queue.update_requests.push(level_object.clone());
+            }
+            return;
+        }
+    }
+
+    // Drawing the brush and the outline of the current stroke.
+    let painter = ctx.layer_painter(egui::LayerId::background());
+    let stroke = egui::Stroke::new(BRUSH_STROKE_WIDTH, BRUSH_COLOR);
+    if let Some((center, edge)) = overlay_camera_params
+        .world_to_egui_pos(mouse_world_position.0)
+        .zip(
+            overlay_camera_params
+                .world_to_egui_pos(mouse_world_position.0 + Vec2::new(radius, 0.0)),
+        )
+    {
+        painter.circle_stroke(center, center.distance(edge), stroke);
+    }
+    if let Some(brush_stroke) = &terrain_brush.stroke {
+        let outline = brush_stroke
+            .points
+            .iter()
+            .map(|point| overlay_camera_params.world_to_egui_pos(*position + *point))
+            .collect::<Option<Vec<_>>>();
+        if let Some(outline) = outline {
+            painter.add(egui::Shape::closed_line(outline, stroke));
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::config_storage::{self, UiThemeConfig, THEME_CONFIG_KEY};
+use bevy::{
+    asset::Assets,
+    ecs::{
+        event::EventReader,
+        system::{Local, Res, ResMut, Resource},
+    },
+    log,
+    pbr::StandardMaterial,
+    window::WindowFocused,
+};
+use bevy_egui::{egui, EguiContext};
+use mr_shared_lib::client::assets::MuddleMaterials;
+use serde::{Deserialize, Serialize};
+
+/// Spacing tokens shared by the main menu, builder and HUD panels. Prefer
+/// these to ad-hoc values, so that panels stay consistent with each other.
+pub mod spacing {
+    pub const SMALL: f32 = 5.0;
+    pub const MEDIUM: f32 = 10.0;
+    pub const LARGE: f32 = 15.0;
+    pub const EXTRA_LARGE: f32 = 20.0;
+    /// The offset of HUD panels anchored to the screen edges.
+    pub const SCREEN_EDGE: f32 = 35.0;
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThemeMode {
+    Dark,
+    Light,
+    /// Follows the OS (or browser) preference.
+    #[default]
+    System,
+}
+
+impl ThemeMode {
+    pub const ALL: [ThemeMode; 3] = [ThemeMode::System, ThemeMode::Dark, ThemeMode::Light];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ThemeMode::Dark => "Dark",
+            ThemeMode::Light => "Light",
+            ThemeMode::System => "System",
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct UiTheme {
+    mode: ThemeMode,
+    /// Is re-detected when the window gets focused, as users may switch the
+    /// OS theme while the game is running.
+    system_prefers_dark: bool,
+}
+
+impl UiTheme {
+    pub fn mode(&self) -> ThemeMode {
+        self.mode
+    }
+
+    /// Changes the mode and saves it to the settings.
+    pub fn set_mode(&mut self, mode: ThemeMode) {
+        self.mode = mode;
+        if let Err(err) = config_storage::write(THEME_CONFIG_KEY, &UiThemeConfig { mode }) {
+            log::error!("Failed to save the theme config: {:?}", err);
+        }
+    }
+
+    pub fn is_dark(&self) -> bool {
+        match self.mode {
+            ThemeMode::Dark => true,
+            ThemeMode::Light => false,
+            ThemeMode::System => self.system_prefers_dark,
+        }
+    }
+}
+
+pub fn read_ui_theme_config_system(mut ui_theme: ResMut<UiTheme>) {
+    ui_theme.system_prefers_dark = system_prefers_dark();
+    match config_storage::read::<UiThemeConfig>(THEME_CONFIG_KEY) {
+        Ok(config) => ui_theme.mode = config.mode,
+        Err(err) => log::error!("Failed to read the theme config: {:?}", err),
+    }
+}
+
+pub fn apply_ui_theme_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut ui_theme: ResMut<UiTheme>,
+    mut window_focused_events: EventReader<WindowFocused>,
+    muddle_materials: Option<Res<MuddleMaterials>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut applied: Local<Option<(bool, egui::Color32)>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if window_focused_events.iter().any(|event| event.focused) && ui_theme.mode == ThemeMode::System
+    {
+        ui_theme.system_prefers_dark = system_prefers_dark();
+    }
+
+    // Accents follow the player's skin color.
+    let accent = muddle_materials
+        .and_then(|muddle_materials| materials.get(&muddle_materials.player))
+        .map_or(DEFAULT_ACCENT, |material| {
+            let [r, g, b, _] = material.base_color.as_rgba_u8();
+            egui::Color32::from_rgb(r, g, b)
+        });
+
+    let theme = (ui_theme.is_dark(), accent);
+    if *applied == Some(theme) {
+        return;
+    }
+    *applied = Some(theme);
+    egui_context
+        .ctx_mut()
+        .set_style(themed_style(theme.0, theme.1));
+}
+
+/// A selector to be embedded into settings screens.
+pub fn theme_selector(ui: &mut egui::Ui, ui_theme: &mut UiTheme) {
+    ui.horizontal(|ui| {
+        ui.label("Theme");
+        let mut mode = ui_theme.mode();
+        egui::ComboBox::from_id_source("theme_mode")
+            .selected_text(mode.label())
+            .show_ui(ui, |ui| {
+                for option in ThemeMode::ALL {
+                    ui.selectable_value(&mut mode, option, option.label());
+                }
+            });
+        if mode != ui_theme.mode() {
+            ui_theme.set_mode(mode);
+        }
+    });
+}
+
+/// The fill of full-screen panels that dim the game behind menus and overlays.
+pub fn backdrop_color(visuals: &egui::Visuals) -> egui::Color32 {
+    if visuals.dark_mode {
+        egui::Color32::from_black_alpha(200)
+    } else {
+        egui::Color32::from_white_alpha(200)
+    }
+}
+
+const DEFAULT_ACCENT: egui::Color32 = egui::Color32::from_rgb(90, 170, 255);
+
+fn themed_style(is_dark: bool, accent: egui::Color32) -> egui::Style {
+    let mut style = egui::Style::default();
+
+    style.spacing.item_spacing = egui::Vec2::new(spacing::MEDIUM, spacing::SMALL);
+    style.spacing.window_margin = egui::style::Margin::same(spacing::MEDIUM);
+    style.spacing.button_padding = egui::Vec2::new(spacing::SMALL, spacing::SMALL / 2.0);
+
+    let (mut visuals, contrast) = if is_dark {
+        (egui::Visuals::dark(), egui::Color32::WHITE)
+    } else {
+        (egui::Visuals::light(), egui::Color32::BLACK)
+    };
+    // Light skin colors are barely visible on light backgrounds and vice versa,
+    // so accents are pulled towards the text color.
+    let accent = mix(accent, contrast, if is_dark { 0.1 } else { 0.45 });
+    visuals.selection.bg_fill = mix(visuals.widgets.noninteractive.bg_fill, accent, 0.6);
+    visuals.selection.stroke = egui::Stroke::new(1.0, mix(accent, contrast, 0.6));
+    visuals.hyperlink_color = accent;
+    visuals.widgets.hovered.bg_stroke = egui::Stroke::new(1.0, accent);
+    visuals.widgets.active.bg_stroke = egui::Stroke::new(1.0, accent);
+    style.visuals = visuals;
+
+    style
+}
+
+fn mix(from: egui::Color32, to: egui::Color32, t: f32) -> egui::Color32 {
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    egui::Color32::from_rgb(
+        channel(from.r(), to.r()),
+        channel(from.g(), to.g()),
+        channel(from.b(), to.b()),
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn system_prefers_dark() -> bool {
+    !matches!(dark_light::detect(), dark_light::Mode::Light)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn system_prefers_dark() -> bool {
+    web_sys::window()
+        .and_then(|window| window.match_media("(prefers-color-scheme: light)").ok())
+        .flatten()
+        .map_or(true, |query| !query.matches())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_themed_style() {
+        let accent = egui::Color32::from_rgb(204, 179, 153);
+
+        let dark = themed_style(true, accent);
+        assert!(dark.visuals.dark_mode);
+        assert_eq!(
+            dark.spacing.item_spacing,
+            egui::Vec2::new(spacing::MEDIUM, spacing::SMALL)
+        );
+
+        let light = themed_style(false, accent);
+        assert!(!light.visuals.dark_mode);
+        // The accent gets darker to stay readable on light backgrounds.
+        assert!(light.visuals.hyperlink_color.r() < dark.visuals.hyperlink_color.r());
+    }
+
+    #[test]
+    fn test_mix() {
+        let black = egui::Color32::BLACK;
+        let white = egui::Color32::WHITE;
+        assert_eq!(mix(black, white, 0.0), black);
+        assert_eq!(mix(black, white, 1.0), white);
+        assert_eq!(
+            mix(black, white, 0.5),
+            egui::Color32::from_rgb(128, 128, 128)
+        );
+    }
+}
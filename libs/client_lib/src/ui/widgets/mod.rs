@@ -1,2 +1,3 @@
 pub mod list_menu;
+pub mod numeric_field;
 pub mod sortable;
@@ -0,0 +1,335 @@
+use bevy_egui::egui;
+use std::{fmt, ops::RangeInclusive};
+
+/// A text field for entering exact values. Besides plain numbers, it accepts
+/// arithmetic expressions (`12.5/2`, `-(3 + 4) * 0.5`), where `prev` stands
+/// for the value before editing (`prev + 0.25`).
+///
+/// The value changes only when an edit is committed (with Enter or by moving
+/// the focus away), an invalid expression keeps the previous value. While the
+/// field is focused, arrow keys nudge the value by `step` (or by ten steps
+/// with Shift held), snapping it to multiples of the step.
+pub struct NumericField<'a> {
+    value: &'a mut f32,
+    id_source: egui::Id,
+    step: f32,
+    range: RangeInclusive<f32>,
+    integer: bool,
+}
+
+#[derive(Clone, Default)]
+struct NumericFieldState {
+    text: String,
+}
+
+impl<'a> NumericField<'a> {
+    pub fn new(value: &'a mut f32, id_source: impl std::hash::Hash) -> Self {
+        Self {
+            value,
+            id_source: egui::Id::new(id_source),
+            step: 0.1,
+            range: f32::NEG_INFINITY..=f32::INFINITY,
+            integer: false,
+        }
+    }
+
+    pub fn step(mut self, step: f32) -> Self {
+        self.step = step;
+        self
+    }
+
+    pub fn clamp_range(mut self, range: RangeInclusive<f32>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Rounds committed values, nudging steps are at least 1.
+    pub fn integer(mut self) -> Self {
+        self.integer = true;
+        self.step = self.step.max(1.0).round();
+        self
+    }
+
+    pub fn show(self, ui: &mut egui::Ui) -> egui::Response {
+        let id = ui.make_persistent_id(self.id_source);
+        let prev = *self.value;
+        let is_editing = ui.memory().has_focus(id);
+        let mut text = ui
+            .memory()
+            .data
+            .get_temp::<NumericFieldState>(id)
+            .filter(|_| is_editing)
+            .map_or_else(|| format_value(prev), |state| state.text);
+
+        let mut response = ui.add(
+            egui::TextEdit::singleline(&mut text)
+                .id(id)
+                .desired_width(60.0),
+        );
+
+        let mut committed = None;
+        if response.has_focus() {
+            let (direction, modifiers) = {
+                let input = ui.input();
+                let direction = if input.key_pressed(egui::Key::ArrowUp) {
+                    1.0
+                } else if input.key_pressed(egui::Key::ArrowDown) {
+                    -1.0
+                } else {
+                    0.0
+                };
+                (direction, input.modifiers)
+            };
+            if direction != 0.0 {
+                let step = if modifiers.shift {
+                    self.step * 10.0
+                } else {
+                    self.step
+                };
+                let base = evaluate_expression(&text, prev).unwrap_or(prev);
+                let nudged = ((base + direction * step) / step).round() * step;
+                let nudged = self.clamp(nudged);
+                text = format_value(nudged);
+                committed = Some(nudged);
+            }
+            ui.memory()
+                .data
+                .insert_temp(id, NumericFieldState { text: text.clone() });
+        } else if response.lost_focus() {
+            ui.memory().data.remove::<NumericFieldState>(id);
+            if !ui.input().key_pressed(egui::Key::Escape) {
+                committed = evaluate_expression(&text, prev)
+                    .ok()
+                    .map(|value| self.clamp(value));
+            }
+        }
+
+        if response.has_focus() {
+            if let Err(err) = evaluate_expression(&text, prev) {
+                ui.painter().rect_stroke(
+                    response.rect.expand(1.0),
+                    2.0,
+                    egui::Stroke::new(1.0, ui.visuals().error_fg_color),
+                );
+                response = response.on_hover_text(err.to_string());
+            }
+        }
+
+        if let Some(value) = committed {
+            if value != prev {
+                *self.value = value;
+                response.mark_changed();
+            }
+        }
+        response
+    }
+
+    fn clamp(&self, value: f32) -> f32 {
+        let value = if self.integer { value.round() } else { value };
+        value.clamp(*self.range.start(), *self.range.end())
+    }
+}
+
+/// Trims the noise of float arithmetic, so that `0.1 + 0.2` shows as `0.3`.
+fn format_value(value: f32) -> String {
+    let formatted = format!("{value:.4}");
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    if formatted == "-0" {
+        "0".to_owned()
+    } else {
+        formatted.to_owned()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExpressionError {
+    Empty,
+    UnexpectedCharacter(char),
+    InvalidNumber(String),
+    UnexpectedEnd,
+    UnclosedParenthesis,
+    DivisionByZero,
+    NotFinite,
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Enter a number or an expression"),
+            Self::UnexpectedCharacter(c) => write!(f, "Unexpected character: '{c}'"),
+            Self::InvalidNumber(number) => write!(f, "Invalid number: {number}"),
+            Self::UnexpectedEnd => write!(f, "The expression is incomplete"),
+            Self::UnclosedParenthesis => write!(f, "A parenthesis isn't closed"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::NotFinite => write!(f, "The result is too large"),
+        }
+    }
+}
+
+/// Evaluates `+`, `-`, `*`, `/`, parentheses, unary minus, and the `prev`
+/// variable.
+pub fn evaluate_expression(expression: &str, prev: f32) -> Result<f32, ExpressionError> {
+    let mut parser = ExpressionParser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        position: 0,
+        prev,
+    };
+    if parser.chars.is_empty() {
+        return Err(ExpressionError::Empty);
+    }
+    let value = parser.sum()?;
+    if let Some(c) = parser.peek() {
+        return Err(ExpressionError::UnexpectedCharacter(c));
+    }
+    if !value.is_finite() {
+        return Err(ExpressionError::NotFinite);
+    }
+    Ok(value)
+}
+
+struct ExpressionParser {
+    chars: Vec<char>,
+    position: usize,
+    prev: f32,
+}
+
+impl ExpressionParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn sum(&mut self) -> Result<f32, ExpressionError> {
+        let mut value = self.product()?;
+        while let Some(operator @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            let rhs = self.product()?;
+            value = if operator == '+' {
+                value + rhs
+            } else {
+                value - rhs
+            };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<f32, ExpressionError> {
+        let mut value = self.unary()?;
+        while let Some(operator @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            let rhs = self.unary()?;
+            value = if operator == '*' {
+                value * rhs
+            } else if rhs == 0.0 {
+                return Err(ExpressionError::DivisionByZero);
+            } else {
+                value / rhs
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<f32, ExpressionError> {
+        match self.peek() {
+            Some('-') => {
+                self.position += 1;
+                Ok(-self.unary()?)
+            }
+            Some('+') => {
+                self.position += 1;
+                self.unary()
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<f32, ExpressionError> {
+        match self.peek() {
+            None => Err(ExpressionError::UnexpectedEnd),
+            Some('(') => {
+                self.position += 1;
+                let value = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err(ExpressionError::UnclosedParenthesis);
+                }
+                self.position += 1;
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.position;
+                while self
+                    .peek()
+                    .map_or(false, |c| c.is_ascii_digit() || c == '.')
+                {
+                    self.position += 1;
+                }
+                let number = self.chars[start..self.position].iter().collect::<String>();
+                number
+                    .parse()
+                    .map_err(|_| ExpressionError::InvalidNumber(number))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.position;
+                while self.peek().map_or(false, |c| c.is_ascii_alphanumeric()) {
+                    self.position += 1;
+                }
+                let identifier = self.chars[start..self.position].iter().collect::<String>();
+                if identifier.eq_ignore_ascii_case("prev") {
+                    Ok(self.prev)
+                } else {
+                    Err(ExpressionError::UnexpectedCharacter(c))
+                }
+            }
+            Some(c) => Err(ExpressionError::UnexpectedCharacter(c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_expression() {
+        assert_eq!(evaluate_expression("12.5", 0.0), Ok(12.5));
+        assert_eq!(evaluate_expression(" 12.5 / 2 ", 0.0), Ok(6.25));
+        assert_eq!(evaluate_expression("prev + 0.25", 1.0), Ok(1.25));
+        assert_eq!(evaluate_expression("-prev", 3.0), Ok(-3.0));
+        assert_eq!(evaluate_expression("1 + 2 * 3", 0.0), Ok(7.0));
+        assert_eq!(evaluate_expression("(1 + 2) * -3", 0.0), Ok(-9.0));
+        assert_eq!(evaluate_expression(".5 - -.5", 0.0), Ok(1.0));
+
+        assert_eq!(evaluate_expression("  ", 0.0), Err(ExpressionError::Empty));
+        assert_eq!(
+            evaluate_expression("1 +", 0.0),
+            Err(ExpressionError::UnexpectedEnd)
+        );
+        assert_eq!(
+            evaluate_expression("(1 + 2", 0.0),
+            Err(ExpressionError::UnclosedParenthesis)
+        );
+        assert_eq!(
+            evaluate_expression("1 + 2)", 0.0),
+            Err(ExpressionError::UnexpectedCharacter(')'))
+        );
+        assert_eq!(
+            evaluate_expression("next + 1", 0.0),
+            Err(ExpressionError::UnexpectedCharacter('n'))
+        );
+        assert_eq!(
+            evaluate_expression("1.2.3", 0.0),
+            Err(ExpressionError::InvalidNumber("1.2.3".to_owned()))
+        );
+        assert_eq!(
+            evaluate_expression("prev / (1 - 1)", 1.0),
+            Err(ExpressionError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(0.1 + 0.2), "0.3");
+        assert_eq!(format_value(12.0), "12");
+        assert_eq!(format_value(-0.00001), "0");
+        assert_eq!(format_value(-2.125), "-2.125");
+    }
+}
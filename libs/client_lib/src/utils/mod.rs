@@ -13,6 +13,50 @@ pub fn parse_jwt(id_token: &str) -> Result<Claims<JwtAuthClaims>, jwt_compact::P
         })
 }
 
+/// Language codes (such as `pt-BR`) that the viewer prefers, most preferred
+/// first. Can be empty if the system doesn't tell.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn preferred_languages() -> Vec<String> {
+    // `LANGUAGE` is a colon-separated list of fallbacks, the rest are single
+    // locales.
+    let mut languages = Vec::new();
+    for var in ["LANGUAGE", "LC_ALL", "LC_MESSAGES", "LANG"] {
+        let Ok(value) = std::env::var(var) else {
+            continue;
+        };
+        for language in value.split(':').filter_map(posix_locale_language) {
+            if !languages.contains(&language) {
+                languages.push(language);
+            }
+        }
+    }
+    languages
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn preferred_languages() -> Vec<String> {
+    web_sys::window()
+        .map(|window| {
+            window
+                .navigator()
+                .languages()
+                .iter()
+                .filter_map(|language| language.as_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turns a POSIX locale (`pt_BR.UTF-8`) into a language code (`pt-BR`).
+#[cfg(not(target_arch = "wasm32"))]
+fn posix_locale_language(locale: &str) -> Option<String> {
+    let language = locale.split(['.', '@']).next().unwrap_or_default();
+    if language.is_empty() || language == "C" || language == "POSIX" {
+        return None;
+    }
+    Some(language.replace('_', "-"))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::parse_jwt;
@@ -30,4 +74,22 @@ mod tests {
             },
         );
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_posix_locale_language() {
+        use crate::utils::posix_locale_language;
+
+        assert_eq!(
+            posix_locale_language("pt_BR.UTF-8").as_deref(),
+            Some("pt-BR")
+        );
+        assert_eq!(
+            posix_locale_language("uk_UA@euro").as_deref(),
+            Some("uk-UA")
+        );
+        assert_eq!(posix_locale_language("de").as_deref(), Some("de"));
+        assert_eq!(posix_locale_language("C.UTF-8"), None);
+        assert_eq!(posix_locale_language(""), None);
+    }
 }
@@ -20,11 +20,12 @@ use bevy::{
     pbr::{PbrBundle, StandardMaterial},
     render::{mesh::Mesh, view::Visibility},
     transform::components::Transform,
+    utils::HashMap,
 };
 use mr_shared_lib::{
     client::{
         assets::{MuddleAssets, MuddleMaterials},
-        XyPlane,
+        XyCircle, XyOutline, XyPlane,
     },
     game::{
         client_factories::VisibilitySettings,
@@ -33,13 +34,16 @@ use mr_shared_lib::{
             PlayerSensor, PlayerSensors, Spawned,
         },
         level::{CollisionLogic, LevelObjectDesc, LevelParams},
+        level_objects::{simplify_outline, ColliderSimplification, PlaneDesc, PlaneFormDesc},
     },
     player::PlayerRole,
-    GameTime,
+    GameTime, PLAYER_RADIUS,
 };
+use std::marker::PhantomData;
 
 pub fn control_builder_visibility_system(
     mut prev_role: Local<Option<PlayerRole>>,
+    mut prev_show_annotations: Local<bool>,
     player_params: PlayerParams,
     level_params: LevelParams,
     mut visibility_settings: ResMut<VisibilitySettings>,
@@ -53,21 +57,31 @@ pub fn control_builder_visibility_system(
     puffin::profile_function!();
     if let Some(player) = player_params.current_player() {
         let is_builder = match player.role {
-            PlayerRole::Runner => false,
+            PlayerRole::Runner | PlayerRole::Spectator => false,
             PlayerRole::Builder => true,
         };
         visibility_settings.route_points = is_builder;
         visibility_settings.ghosts = is_builder;
 
-        // These change only on role update, there's no other reason to update them.
-        if *prev_role != Some(player.role) {
+        // These change only on role update (or toggling annotations), there's no other
+        // reason to update them.
+        if *prev_role != Some(player.role)
+            || *prev_show_annotations != visibility_settings.annotations
+        {
             for (entity, _, mut visible) in level_objects_query.iter_mut() {
                 if let Some(level_object) = level_params.level_object_by_entity(entity) {
                     match level_object.desc {
-                        LevelObjectDesc::RoutePoint(_) => {
+                        LevelObjectDesc::RoutePoint(_) | LevelObjectDesc::CameraAnchor(_) => {
                             visible.is_visible = is_builder;
                         }
-                        LevelObjectDesc::Plane(_) | LevelObjectDesc::Cube(_) => {}
+                        LevelObjectDesc::Annotation(_) => {
+                            visible.is_visible = is_builder && visibility_settings.annotations;
+                        }
+                        LevelObjectDesc::Plane(_)
+                        | LevelObjectDesc::Cube(_)
+                        | LevelObjectDesc::Emitter(_)
+                        | LevelObjectDesc::DeathZone(_)
+                        | LevelObjectDesc::FinishZone(_) => {}
                     }
                 }
             }
@@ -89,6 +103,7 @@ pub fn control_builder_visibility_system(
         }
 
         *prev_role = Some(player.role);
+        *prev_show_annotations = visibility_settings.annotations;
     }
 }
 
@@ -360,12 +375,15 @@ pub fn process_control_points_input_system(
         .control_point_parent_query
         .get_component::<LevelObjectControlPoints>(*edited_object)
         .unwrap();
-    let connected_to = points
-        .iter()
-        .enumerate()
-        .find(|(i, _)| *i == (*dragged_control_point_index + 1) % points.len());
+    let connected_to = points.iter().enumerate().find(|(i, _)| {
+        *i != *dragged_control_point_index
+            && *i == (*dragged_control_point_index + 1) % points.len()
+    });
     let connected_from = points.iter().enumerate().find(|(i, _)| {
-        if *dragged_control_point_index == 0 {
+        if *i == *dragged_control_point_index {
+            // Objects with a single control point (measurements) don't have borders.
+            false
+        } else if *dragged_control_point_index == 0 {
             *i == points.len() - 1
         } else {
             *i == *dragged_control_point_index - 1
@@ -465,3 +483,202 @@ pub fn update_player_sensor_materials_system(
         }
     }
 }
+
+const COLLISION_OUTLINE_HEIGHT: f32 = 0.005;
+const COLLISION_OUTLINE_WIDTH: f32 = 0.04;
+
+const SPAWN_AREA_PREVIEW_HEIGHT: f32 = 0.004;
+
+/// Resources shared by the systems that render builder overlays on top of
+/// level objects.
+#[derive(SystemParam)]
+pub struct OverlayParams<'w, 's> {
+    time: Res<'w, GameTime>,
+    visibility_settings: Res<'w, VisibilitySettings>,
+    collider_simplification: Res<'w, ColliderSimplification>,
+    muddle_materials: Res<'w, MuddleMaterials>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Renders outlines of concave planes the same way as their colliders are
+/// calculated, so that builders could compare them with the visual meshes.
+pub fn update_collision_outlines_system(
+    mut commands: Commands,
+    // Maps level object entities to their outline entities.
+    mut outlines: Local<HashMap<Entity, (Entity, Handle<Mesh>)>>,
+    player_params: PlayerParams,
+    level_params: LevelParams,
+    mut outline_params: OverlayParams,
+    level_objects_query: Query<(Entity, &Spawned), With<LevelObjectTag>>,
+    mut transforms_query: Query<&mut Transform>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let is_builder = player_params
+        .current_player()
+        .map_or(false, |player| player.role == PlayerRole::Builder);
+    let show_outlines = is_builder && outline_params.visibility_settings.collision_outlines;
+
+    // Updated level objects get re-spawned as new entities, so we also need to
+    // clean up the outlines of the replaced ones.
+    outlines.retain(|level_object_entity, (outline_entity, mesh)| {
+        let is_spawned = level_objects_query
+            .get(*level_object_entity)
+            .map_or(false, |(_, spawned)| {
+                spawned.is_spawned(outline_params.time.frame_number)
+            });
+        if show_outlines && is_spawned {
+            return true;
+        }
+        commands.entity(*outline_entity).despawn();
+        outline_params.meshes.remove(mesh.clone_weak());
+        false
+    });
+
+    if !show_outlines {
+        return;
+    }
+
+    for (level_object_entity, spawned) in level_objects_query.iter() {
+        if !spawned.is_spawned(outline_params.time.frame_number) {
+            continue;
+        }
+        let Ok(level_object_transform) = transforms_query.get(level_object_entity) else {
+            continue;
+        };
+        let translation = level_object_transform
+            .translation
+            .truncate()
+            .extend(COLLISION_OUTLINE_HEIGHT);
+
+        // Planes can be moved along routes, so outlines need to follow them.
+        if let Some((outline_entity, _)) = outlines.get(&level_object_entity) {
+            if let Ok(mut outline_transform) = transforms_query.get_mut(*outline_entity) {
+                outline_transform.translation = translation;
+            }
+            continue;
+        }
+
+        let Some(LevelObjectDesc::Plane(PlaneDesc {
+            form_desc: PlaneFormDesc::Concave { points },
+            ..
+        })) = level_params
+            .level_object_by_entity(level_object_entity)
+            .map(|level_object| &level_object.desc)
+        else {
+            continue;
+        };
+        let mesh = outline_params.meshes.add(Mesh::from(XyOutline {
+            points: simplify_outline(points, outline_params.collider_simplification.tolerance),
+            width: COLLISION_OUTLINE_WIDTH,
+        }));
+        let outline_entity = commands
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: outline_params.muddle_materials.collision_outline.clone(),
+                transform: Transform::from_translation(translation),
+                ..Default::default()
+            })
+            .id();
+        outlines.insert(level_object_entity, (outline_entity, mesh));
+    }
+}
+
+/// Renders the areas where runners can be spawned (spawn area planes shrunk by
+/// the player radius) as translucent overlays.
+pub fn update_spawn_area_previews_system(
+    mut commands: Commands,
+    // Maps level object entities to their preview entities.
+    mut previews: Local<HashMap<Entity, (Entity, Handle<Mesh>)>>,
+    player_params: PlayerParams,
+    level_params: LevelParams,
+    mut overlay_params: OverlayParams,
+    level_objects_query: Query<(Entity, &Spawned), With<LevelObjectTag>>,
+    mut transforms_query: Query<&mut Transform>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let is_builder = player_params
+        .current_player()
+        .map_or(false, |player| player.role == PlayerRole::Builder);
+    let show_previews = is_builder && overlay_params.visibility_settings.spawn_areas;
+
+    // Toggling `is_spawn_area` re-spawns a level object as a new entity as well, so
+    // checking whether an entity is still spawned is enough.
+    previews.retain(|level_object_entity, (preview_entity, mesh)| {
+        let is_spawned = level_objects_query
+            .get(*level_object_entity)
+            .map_or(false, |(_, spawned)| {
+                spawned.is_spawned(overlay_params.time.frame_number)
+            });
+        if show_previews && is_spawned {
+            return true;
+        }
+        commands.entity(*preview_entity).despawn();
+        overlay_params.meshes.remove(mesh.clone_weak());
+        false
+    });
+
+    if !show_previews {
+        return;
+    }
+
+    for &spawn_area_net_id in level_params.level_state.spawn_areas() {
+        let Some(level_object_entity) = level_params.entity_registry.get_entity(spawn_area_net_id)
+        else {
+            continue;
+        };
+        if !level_objects_query
+            .get(level_object_entity)
+            .map_or(false, |(_, spawned)| {
+                spawned.is_spawned(overlay_params.time.frame_number)
+            })
+        {
+            continue;
+        }
+        let Ok(level_object_transform) = transforms_query.get(level_object_entity) else {
+            continue;
+        };
+        let translation = level_object_transform
+            .translation
+            .truncate()
+            .extend(SPAWN_AREA_PREVIEW_HEIGHT);
+
+        if let Some((preview_entity, _)) = previews.get(&level_object_entity) {
+            if let Ok(mut preview_transform) = transforms_query.get_mut(*preview_entity) {
+                preview_transform.translation = translation;
+            }
+            continue;
+        }
+
+        let Some(LevelObjectDesc::Plane(PlaneDesc { form_desc, .. })) = level_params
+            .level_object_by_net_id(spawn_area_net_id)
+            .map(|level_object| &level_object.desc)
+        else {
+            continue;
+        };
+        let mesh = match form_desc {
+            PlaneFormDesc::Circle { radius } if *radius > PLAYER_RADIUS => Mesh::from(XyCircle {
+                radius: radius - PLAYER_RADIUS,
+            }),
+            PlaneFormDesc::Rectangle { size } if size.min_element() > PLAYER_RADIUS * 2.0 => {
+                Mesh::from(XyPlane {
+                    size: *size - Vec2::splat(PLAYER_RADIUS * 2.0),
+                })
+            }
+            _ => continue,
+        };
+        let mesh = overlay_params.meshes.add(mesh);
+        let preview_entity = commands
+            .spawn(PbrBundle {
+                mesh: mesh.clone(),
+                material: overlay_params.muddle_materials.spawn_area_preview.clone(),
+                transform: Transform::from_translation(translation),
+                ..Default::default()
+            })
+            .id();
+        previews.insert(level_object_entity, (preview_entity, mesh));
+    }
+}
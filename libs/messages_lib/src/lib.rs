@@ -1,3 +1,5 @@
+pub mod validation;
+
 mod matchmaker;
 mod persistence;
 
@@ -95,6 +97,8 @@ mod tests {
                     player_capacity: 0,
                     player_count: 0,
                     request_id: Default::default(),
+                    version: Default::default(),
+                    draining: true,
                 }],
             },
             MatchmakerMessage::ServerUpdated(Server {
@@ -104,9 +108,30 @@ mod tests {
                 player_capacity: 0,
                 player_count: 0,
                 request_id: Default::default(),
+                version: ServerVersion::new(1),
+                draining: false,
             }),
             MatchmakerMessage::ServerRemoved("test".to_owned()),
             MatchmakerMessage::InvalidJwt(Default::default()),
+            MatchmakerMessage::ServerList(ServerListPage {
+                request_id: Default::default(),
+                servers: vec![ListedServer {
+                    server: Server {
+                        name: "test".to_owned(),
+                        state: GameServerState::Allocated,
+                        addr: "127.0.0.1:0".parse().unwrap(),
+                        player_capacity: PLAYER_CAPACITY,
+                        player_count: 1,
+                        request_id: Default::default(),
+                        version: ServerVersion::new(1),
+                        draining: false,
+                    },
+                    region: "eu".to_owned(),
+                    level_id: Some(1),
+                }],
+                offset: 0,
+                total: 1,
+            }),
         ];
 
         for message in messages {
@@ -119,9 +144,143 @@ mod tests {
                 MatchmakerMessage::Init { .. }
                 | MatchmakerMessage::ServerUpdated(_)
                 | MatchmakerMessage::ServerRemoved(_)
-                | MatchmakerMessage::InvalidJwt(_) => {}
+                | MatchmakerMessage::InvalidJwt(_)
+                | MatchmakerMessage::ServerList(_) => {}
+            }
+            assert_eq!(message, value);
+        }
+    }
+
+    #[test]
+    fn matchmaker_encodings() {
+        let message = MatchmakerMessage::ServerUpdated(Server {
+            name: "test".to_owned(),
+            state: GameServerState::Ready,
+            addr: "127.0.0.1:3455".parse().unwrap(),
+            player_capacity: PLAYER_CAPACITY,
+            player_count: 1,
+            request_id: Default::default(),
+            version: ServerVersion::new(1),
+            draining: false,
+        });
+        let requests = vec![
+            MatchmakerRequest::CreateServer {
+                init_level: InitLevel::Create {
+                    title: "Test".to_owned(),
+                    parent_id: Some(1),
+                },
+                request_id: Default::default(),
+                id_token: None,
+                protocol_version: PROTOCOL_VERSION,
+                kind: AllocationKind::Public,
+            },
+            MatchmakerRequest::CreateServer {
+                init_level: InitLevel::Existing(1),
+                request_id: Default::default(),
+                id_token: Some("token".to_owned()),
+                protocol_version: PROTOCOL_VERSION,
+                kind: AllocationKind::SoloPractice,
+            },
+            MatchmakerRequest::ListServers {
+                request_id: Default::default(),
+                filters: ServerFilters {
+                    region: Some("eu".to_owned()),
+                    min_free_slots: 1,
+                    level_id: None,
+                },
+                sort_order: ServerSortOrder::Name,
+                offset: 0,
+                limit: SERVER_LIST_MAX_LIMIT,
+            },
+        ];
+
+        for encoding in [MatchmakerEncoding::Bincode, MatchmakerEncoding::Json] {
+            let encoded = encoding.encode(&message).unwrap();
+            match (encoding, &encoded) {
+                (MatchmakerEncoding::Bincode, EncodedMessage::Binary(_))
+                | (MatchmakerEncoding::Json, EncodedMessage::Text(_)) => {}
+                _ => panic!("Unexpected frame type for {encoding:?}: {encoded:?}"),
             }
+            let value: MatchmakerMessage = encoding.decode(&encoded).unwrap();
             assert_eq!(message, value);
+
+            for request in &requests {
+                let encoded = encoding.encode(request).unwrap();
+                let value: MatchmakerRequest = encoding.decode(&encoded).unwrap();
+                assert_eq!(format!("{request:?}"), format!("{value:?}"));
+            }
+        }
+
+        // JSON clients don't have to know about bincode's layout.
+        let request: MatchmakerRequest = MatchmakerEncoding::Json
+            .decode(&EncodedMessage::Text(
+                r#"{"CreateServer":{"init_level":{"Existing":5},"request_id":"67e55044-10b1-426f-9247-bb680e5fe0c8","id_token":null,"protocol_version":4}}"#
+                    .to_owned(),
+            ))
+            .unwrap();
+        assert!(matches!(
+            request,
+            MatchmakerRequest::CreateServer {
+                init_level: InitLevel::Existing(5),
+                kind: AllocationKind::Public,
+                ..
+            }
+        ));
+        for kind in [AllocationKind::Public, AllocationKind::SoloPractice] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
         }
+        let binary_json = EncodedMessage::Binary(br#"{"ServerRemoved":"test"}"#.to_vec());
+        assert_eq!(
+            MatchmakerEncoding::Json
+                .decode::<MatchmakerMessage>(&binary_json)
+                .unwrap(),
+            MatchmakerMessage::ServerRemoved("test".to_owned())
+        );
+        assert!(matches!(
+            MatchmakerEncoding::Bincode
+                .decode::<MatchmakerMessage>(&EncodedMessage::Text("{}".to_owned())),
+            Err(EncodingError::UnexpectedText)
+        ));
+    }
+
+    #[test]
+    fn negotiate_matchmaker_encoding() {
+        assert_eq!(
+            MatchmakerEncoding::negotiate(MATCHMAKER_JSON_SUBPROTOCOL),
+            Some(MatchmakerEncoding::Json)
+        );
+        assert_eq!(
+            MatchmakerEncoding::negotiate("chat, mr-matchmaker.bincode, mr-matchmaker.json"),
+            Some(MatchmakerEncoding::Bincode)
+        );
+        assert_eq!(MatchmakerEncoding::negotiate("chat"), None);
+        assert_eq!(MatchmakerEncoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn parse_server_version() {
+        let version = ServerVersion {
+            protocol: 2,
+            build: 150,
+        };
+        assert_eq!(version.to_string(), "2-150");
+        assert_eq!("2-150".parse::<ServerVersion>(), Ok(version));
+        assert!("2".parse::<ServerVersion>().is_err());
+        assert!("2-".parse::<ServerVersion>().is_err());
+
+        assert!(
+            version
+                < ServerVersion {
+                    protocol: 2,
+                    build: 151
+                }
+        );
+        assert!(
+            version
+                > ServerVersion {
+                    protocol: 1,
+                    build: 200
+                }
+        );
     }
 }
@@ -1,10 +1,130 @@
+use crate::{deserialize_binary, serialize_binary};
 #[cfg(feature = "schemars")]
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt, net::SocketAddr, str::FromStr};
 
 pub const PLAYER_CAPACITY: u16 = 5;
 
+/// Needs to be bumped every time the client-server protocol changes in a
+/// backwards-incompatible way. Clients are allocated only on servers with the
+/// same protocol version.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Game servers set both an annotation and a label with this key to expose
+/// their version (the label is needed to make allocations selectable by a
+/// version). Note that the Agones SDK prefixes the key with `agones.dev/sdk-`.
+pub const SERVER_VERSION_KEY: &str = "version";
+/// The matchmaker sets this annotation for servers running an older version,
+/// signalling them to shut down as soon as they become idle.
+pub const SERVER_DRAIN_ANNOTATION: &str = "drain";
+/// The matchmaker annotates GameServerAllocations with the id of the request
+/// that caused them. Game servers include it in their logs, so that an
+/// allocation can be traced from a client to a server.
+pub const ALLOCATION_REQUEST_ID_ANNOTATION: &str = "request_id";
+/// The matchmaker annotates GameServerAllocations with the W3C `traceparent`
+/// of the allocation span, so that game servers (and the persistence calls
+/// they make) continue the same trace.
+pub const ALLOCATION_TRACE_PARENT_ANNOTATION: &str = "traceparent";
+/// The matchmaker annotates GameServerAllocations with the
+/// [`AllocationKind`], so that game servers know whether they are public.
+pub const ALLOCATION_KIND_ANNOTATION: &str = "allocation_kind";
+/// `MatchmakerRequest::ListServers` limits above this value are clamped.
+pub const SERVER_LIST_MAX_LIMIT: u32 = 50;
+/// WebSocket subprotocols (the `Sec-WebSocket-Protocol` header) that
+/// matchmaker clients use to choose an encoding of [`MatchmakerRequest`] and
+/// [`MatchmakerMessage`].
+pub const MATCHMAKER_BINCODE_SUBPROTOCOL: &str = "mr-matchmaker.bincode";
+pub const MATCHMAKER_JSON_SUBPROTOCOL: &str = "mr-matchmaker.json";
+
+/// Clients that don't request any subprotocol get bincode, which is what the
+/// game client speaks. JSON is meant for debugging and non-Rust clients.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MatchmakerEncoding {
+    #[default]
+    Bincode,
+    Json,
+}
+
+/// A WebSocket message payload: bincode is sent in binary frames, JSON in text
+/// ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodedMessage {
+    Binary(Vec<u8>),
+    Text(String),
+}
+
+#[derive(Debug)]
+pub enum EncodingError {
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+    /// Bincode messages can't be sent in text frames.
+    UnexpectedText,
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bincode(err) => write!(f, "invalid bincode message: {err}"),
+            Self::Json(err) => write!(f, "invalid JSON message: {err}"),
+            Self::UnexpectedText => f.write_str("expected a binary message, got a text one"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+impl MatchmakerEncoding {
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Self::Bincode => MATCHMAKER_BINCODE_SUBPROTOCOL,
+            Self::Json => MATCHMAKER_JSON_SUBPROTOCOL,
+        }
+    }
+
+    /// Accepts the value of a `Sec-WebSocket-Protocol` request header, which
+    /// lists subprotocols in the order of the client's preference. Returns
+    /// `None` if none of them are supported.
+    pub fn negotiate(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .map(str::trim)
+            .find_map(|subprotocol| match subprotocol {
+                MATCHMAKER_BINCODE_SUBPROTOCOL => Some(Self::Bincode),
+                MATCHMAKER_JSON_SUBPROTOCOL => Some(Self::Json),
+                _ => None,
+            })
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<EncodedMessage, EncodingError> {
+        match self {
+            Self::Bincode => serialize_binary(value)
+                .map(EncodedMessage::Binary)
+                .map_err(EncodingError::Bincode),
+            Self::Json => serde_json::to_string(value)
+                .map(EncodedMessage::Text)
+                .map_err(EncodingError::Json),
+        }
+    }
+
+    /// Some WebSocket libraries send strings in binary frames, so JSON is
+    /// accepted in both.
+    pub fn decode<T: DeserializeOwned>(self, message: &EncodedMessage) -> Result<T, EncodingError> {
+        match (self, message) {
+            (Self::Bincode, EncodedMessage::Binary(data)) => {
+                deserialize_binary(data).map_err(EncodingError::Bincode)
+            }
+            (Self::Bincode, EncodedMessage::Text(_)) => Err(EncodingError::UnexpectedText),
+            (Self::Json, EncodedMessage::Binary(data)) => {
+                serde_json::from_slice(data).map_err(EncodingError::Json)
+            }
+            (Self::Json, EncodedMessage::Text(text)) => {
+                serde_json::from_str(text).map_err(EncodingError::Json)
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum MatchmakerMessage {
     /// Is sent when a client is connected, contains a list of active servers.
@@ -16,6 +136,8 @@ pub enum MatchmakerMessage {
     /// Is sent when a user sends an invalid token id with a request (contains a
     /// request id).
     InvalidJwt(uuid::Uuid),
+    /// Is sent only to the client that has requested the list.
+    ServerList(ServerListPage),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,21 +149,78 @@ pub enum InitLevel {
     },
 }
 
+/// Solo practice servers are allocated for a single authenticated player to
+/// practice an existing level. They aren't listed publicly, and don't submit
+/// finishes to the lifetime stats, which lets them allow teleporting.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AllocationKind {
+    #[default]
+    Public,
+    SoloPractice,
+}
+
+// Is formatted to be a valid Kubernetes annotation value.
+impl fmt::Display for AllocationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Public => "public",
+            Self::SoloPractice => "solo_practice",
+        })
+    }
+}
+
+impl FromStr for AllocationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Self::Public),
+            "solo_practice" => Ok(Self::SoloPractice),
+            _ => Err(format!("Invalid allocation kind: {s}")),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum MatchmakerRequest {
+    /// Solo practice servers can be requested only for existing levels, and
+    /// only with an `id_token`.
     CreateServer {
         init_level: InitLevel,
         request_id: uuid::Uuid,
         id_token: Option<String>,
+        protocol_version: u32,
+        #[serde(default)]
+        kind: AllocationKind,
+    },
+    /// Is answered with [`MatchmakerMessage::ServerList`]. Unlike
+    /// `MatchmakerMessage::Init`, the list contains only the servers matching
+    /// the filters, a page at a time.
+    ListServers {
+        request_id: uuid::Uuid,
+        filters: ServerFilters,
+        sort_order: ServerSortOrder,
+        offset: u32,
+        limit: u32,
     },
 }
 
 impl MatchmakerRequest {
     pub fn request_id(&self) -> uuid::Uuid {
         match self {
-            Self::CreateServer { request_id, .. } => *request_id,
+            Self::CreateServer { request_id, .. } | Self::ListServers { request_id, .. } => {
+                *request_id
+            }
         }
     }
+
+    /// The W3C `traceparent` that the matchmaker continues when handling the
+    /// request. The trace id equals the request id, so clients can look up the
+    /// trace of their request without having to run an OpenTelemetry SDK.
+    pub fn trace_parent(&self) -> String {
+        let request_id = self.request_id().simple().to_string();
+        format!("00-{}-{}-01", request_id, &request_id[16..])
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -53,6 +232,111 @@ pub struct Server {
     pub player_count: u16,
     // If a request id is empty, it means that a server isn't allocated yet.
     pub request_id: uuid::Uuid,
+    pub version: ServerVersion,
+    /// Draining servers won't accept new allocations and will shut down once
+    /// all the players leave.
+    pub draining: bool,
+}
+
+impl Server {
+    pub fn is_compatible(&self) -> bool {
+        self.version.protocol == PROTOCOL_VERSION && !self.draining
+    }
+
+    pub fn free_slots(&self) -> u16 {
+        self.player_capacity.saturating_sub(self.player_count)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerFilters {
+    pub region: Option<String>,
+    pub min_free_slots: u16,
+    pub level_id: Option<i64>,
+}
+
+impl ServerFilters {
+    pub fn matches(&self, listed_server: &ListedServer) -> bool {
+        self.region
+            .as_ref()
+            .map_or(true, |region| *region == listed_server.region)
+            && listed_server.server.free_slots() >= self.min_free_slots
+            && self
+                .level_id
+                .map_or(true, |level_id| listed_server.level_id == Some(level_id))
+    }
+}
+
+/// Servers that compare equal are ordered by their names, so that pages don't
+/// overlap.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ServerSortOrder {
+    #[default]
+    MostPlayers,
+    MostFreeSlots,
+    Name,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListedServer {
+    pub server: Server,
+    pub region: String,
+    /// Is `None` for servers that haven't been allocated with an existing
+    /// level (or run locally).
+    pub level_id: Option<i64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServerListPage {
+    pub request_id: uuid::Uuid,
+    pub servers: Vec<ListedServer>,
+    pub offset: u32,
+    /// The number of servers matching the filters, across all the pages.
+    pub total: u32,
+}
+
+/// Servers are ordered by their protocol version first, and then by their build
+/// number, so the newest version is the greatest one.
+#[derive(
+    Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct ServerVersion {
+    pub protocol: u32,
+    pub build: u32,
+}
+
+impl ServerVersion {
+    pub fn new(build: u32) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            build,
+        }
+    }
+}
+
+// Is formatted to be a valid Kubernetes label value.
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.protocol, self.build)
+    }
+}
+
+impl FromStr for ServerVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (protocol, build) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid server version format: {s}"))?;
+        Ok(Self {
+            protocol: protocol
+                .parse()
+                .map_err(|err| format!("Invalid protocol version ({s}): {err:?}"))?,
+            build: build
+                .parse()
+                .map_err(|err| format!("Invalid build number ({s}): {err:?}"))?,
+        })
+    }
 }
 
 /// The list of all the possible states: https://github.com/googleforgames/agones/blob/7770aa67fa5a19b5fc37386d220ecedf1044c0c3/pkg/apis/agones/v1/gameserver.go#L35-L62.
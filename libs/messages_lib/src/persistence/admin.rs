@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+/// Admin commands a registered user is allowed to run on game servers. Users
+/// without a row in `admin_permissions` can't run any of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminPermissions {
+    pub kick: bool,
+    pub pause: bool,
+    pub reload: bool,
+    pub cvars: bool,
+    pub broadcast: bool,
+    pub ban: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminPermission {
+    Kick,
+    Pause,
+    Reload,
+    Cvars,
+    Broadcast,
+    Ban,
+}
+
+impl AdminPermission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kick => "kick",
+            Self::Pause => "pause",
+            Self::Reload => "reload",
+            Self::Cvars => "cvars",
+            Self::Broadcast => "broadcast",
+            Self::Ban => "ban",
+        }
+    }
+}
+
+impl AdminPermissions {
+    pub fn allows(&self, permission: AdminPermission) -> bool {
+        match permission {
+            AdminPermission::Kick => self.kick,
+            AdminPermission::Pause => self.pause,
+            AdminPermission::Reload => self.reload,
+            AdminPermission::Cvars => self.cvars,
+            AdminPermission::Broadcast => self.broadcast,
+            AdminPermission::Ban => self.ban,
+        }
+    }
+}
+
+/// An entry of the audit log, is sent by game servers for every admin command
+/// they have executed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostAdminActionRequest {
+    pub user_id: i64,
+    /// Is `None` for servers that aren't managed by Agones.
+    pub server_name: Option<String>,
+    pub permission: AdminPermission,
+    /// A human-readable description of the command arguments.
+    pub details: String,
+}
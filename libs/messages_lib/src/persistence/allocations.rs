@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Is reported by the matchmaker for every allocation request, whether it
+/// succeeded or not.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostAllocationRequest {
+    /// Doubles as a correlation id: it's also logged by clients and game
+    /// servers.
+    pub request_id: uuid::Uuid,
+    pub user_id: Option<i64>,
+    /// Is `None` if a new level was requested.
+    pub level_id: Option<i64>,
+    pub level_parent_id: Option<i64>,
+    pub protocol_version: u32,
+    /// Is `None` if the allocation has failed.
+    pub server_name: Option<String>,
+    /// The time between receiving a request and the outcome.
+    pub latency_millis: u32,
+    pub failure_reason: Option<AllocationFailureReason>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationFailureReason {
+    NoCompatibleServers,
+    InvalidJwt,
+    /// Creating a `GameServerAllocation` failed (or there were no local
+    /// servers to allocate).
+    AllocationError,
+    /// A `GameServerAllocation` was created, but no server got allocated for
+    /// the request in time.
+    TimedOut,
+    /// The request didn't pass validation (an invalid level title, for
+    /// example).
+    InvalidRequest,
+}
+
+impl fmt::Display for AllocationFailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoCompatibleServers => "no_compatible_servers",
+            Self::InvalidJwt => "invalid_jwt",
+            Self::AllocationError => "allocation_error",
+            Self::TimedOut => "timed_out",
+            Self::InvalidRequest => "invalid_request",
+        })
+    }
+}
@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+/// Distinguishes API tokens from OIDC JWTs in the `Authorization` header.
+pub const API_TOKEN_PREFIX: &str = "mrt_";
+pub const MAX_API_TOKENS_PER_USER: i64 = 10;
+
+/// What a personal API token can be used for. Tokens can't be used to manage
+/// other tokens or anything else that requires a browser login.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTokenScopes {
+    pub read_levels: bool,
+    /// Allows updating levels that the token owner has authored.
+    pub write_levels: bool,
+    pub read_leaderboards: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiTokenScope {
+    ReadLevels,
+    WriteLevels,
+    ReadLeaderboards,
+}
+
+impl ApiTokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadLevels => "read_levels",
+            Self::WriteLevels => "write_levels",
+            Self::ReadLeaderboards => "read_leaderboards",
+        }
+    }
+}
+
+impl ApiTokenScopes {
+    pub fn allows(&self, scope: ApiTokenScope) -> bool {
+        match scope {
+            ApiTokenScope::ReadLevels => self.read_levels,
+            ApiTokenScope::WriteLevels => self.write_levels,
+            ApiTokenScope::ReadLeaderboards => self.read_leaderboards,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !(self.read_levels || self.write_levels || self.read_leaderboards)
+    }
+}
+
+/// Token secrets are stored hashed, so they can't be listed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub scopes: ApiTokenScopes,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostApiTokenRequest {
+    pub name: String,
+    pub scopes: ApiTokenScopes,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostApiTokenResponse {
+    pub token: ApiToken,
+    /// Is returned only once, when a token is issued.
+    pub secret: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ApiTokenError {
+    NoScopes,
+    TooManyTokens { max_tokens: i64 },
+}
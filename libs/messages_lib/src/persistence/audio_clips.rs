@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Clips are meant to be short cues, not music tracks. The persistence service
+/// also rejects bodies larger than 256 KiB before they reach validation.
+pub const AUDIO_CLIP_MAX_SIZE: usize = 128 * 1024;
+pub const AUDIO_CLIP_CONTENT_TYPE: &str = "audio/ogg";
+
+/// Uploaded clips are served to other players only after being approved.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioClipModerationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl fmt::Display for AudioClipModerationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::Rejected => "rejected",
+        })
+    }
+}
+
+impl FromStr for AudioClipModerationStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "approved" => Ok(Self::Approved),
+            "rejected" => Ok(Self::Rejected),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostAudioClipResponse {
+    pub id: i64,
+    pub moderation_status: AudioClipModerationStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetAudioClipsQuery {
+    pub moderation_status: AudioClipModerationStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AudioClipSummary {
+    pub id: i64,
+    pub user_id: i64,
+    pub size: i32,
+    pub moderation_status: AudioClipModerationStatus,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Is sent by moderators.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PatchAudioClipRequest {
+    pub moderation_status: AudioClipModerationStatus,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AudioClipError {
+    Empty,
+    TooLarge {
+        max_size: usize,
+    },
+    /// Only Ogg Vorbis clips are accepted, as that's what the clients can
+    /// decode on all platforms.
+    UnsupportedFormat,
+}
+
+impl fmt::Display for AudioClipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Audio clip is empty"),
+            Self::TooLarge { max_size } => {
+                write!(
+                    f,
+                    "Audio clip must not be larger than {} KiB",
+                    max_size / 1024
+                )
+            }
+            Self::UnsupportedFormat => f.write_str("Audio clip must be an Ogg Vorbis file"),
+        }
+    }
+}
+
+pub fn validate_audio_clip(data: &[u8]) -> Result<(), AudioClipError> {
+    if data.is_empty() {
+        return Err(AudioClipError::Empty);
+    }
+    if data.len() > AUDIO_CLIP_MAX_SIZE {
+        return Err(AudioClipError::TooLarge {
+            max_size: AUDIO_CLIP_MAX_SIZE,
+        });
+    }
+    if !is_ogg_vorbis(data) {
+        return Err(AudioClipError::UnsupportedFormat);
+    }
+    Ok(())
+}
+
+/// Checks that the first Ogg page contains the Vorbis identification header.
+/// The rest of the stream is checked by the decoder, it's fine for a broken
+/// clip to fail to play.
+fn is_ogg_vorbis(data: &[u8]) -> bool {
+    const OGG_PAGE_HEADER_LEN: usize = 27;
+    const VORBIS_ID_HEADER: &[u8] = b"\x01vorbis";
+
+    if !data.starts_with(b"OggS") || data.len() < OGG_PAGE_HEADER_LEN {
+        return false;
+    }
+    let segments_count = data[OGG_PAGE_HEADER_LEN - 1] as usize;
+    let payload_start = OGG_PAGE_HEADER_LEN + segments_count;
+    data.get(payload_start..payload_start + VORBIS_ID_HEADER.len()) == Some(VORBIS_ID_HEADER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ogg_vorbis_header() -> Vec<u8> {
+        let mut data = b"OggS".to_vec();
+        data.resize(26, 0);
+        // A single segment in the segment table.
+        data.extend_from_slice(&[1, 30]);
+        data.extend_from_slice(b"\x01vorbis");
+        data
+    }
+
+    #[test]
+    fn test_validate_audio_clip() {
+        assert_eq!(validate_audio_clip(&ogg_vorbis_header()), Ok(()));
+        assert_eq!(validate_audio_clip(&[]), Err(AudioClipError::Empty));
+
+        let mut opus = ogg_vorbis_header();
+        let len = opus.len();
+        opus[len - 7..].copy_from_slice(b"OpusHea");
+        assert_eq!(
+            validate_audio_clip(&opus),
+            Err(AudioClipError::UnsupportedFormat)
+        );
+        assert_eq!(
+            validate_audio_clip(b"RIFF\0\0\0\0WAVEfmt "),
+            Err(AudioClipError::UnsupportedFormat)
+        );
+
+        let mut too_large = ogg_vorbis_header();
+        too_large.resize(AUDIO_CLIP_MAX_SIZE + 1, 0);
+        assert_eq!(
+            validate_audio_clip(&too_large),
+            Err(AudioClipError::TooLarge {
+                max_size: AUDIO_CLIP_MAX_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_ogg_header() {
+        let header = ogg_vorbis_header();
+        for len in 1..header.len() {
+            assert_eq!(
+                validate_audio_clip(&header[..len]),
+                Err(AudioClipError::UnsupportedFormat)
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+pub const BAN_REASON_MAX_LEN: usize = 280;
+
+/// Bans a user, an IP range (in CIDR notation) or both. Game servers check
+/// bans during handshakes, bans without `expires_at` are permanent.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ban {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub ip_range: Option<String>,
+    pub reason: String,
+    /// Is `None` for bans issued by operators (or by admins who have deleted
+    /// their accounts since).
+    pub banned_by: Option<i64>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+impl Ban {
+    pub fn matches(&self, user_id: Option<i64>, ip_addr: Option<IpAddr>) -> bool {
+        (self.user_id.is_some() && self.user_id == user_id)
+            || self
+                .ip_range
+                .as_deref()
+                .zip(ip_addr)
+                .map_or(false, |(ip_range, ip_addr)| {
+                    ip_range_contains(ip_range, ip_addr)
+                })
+    }
+
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostBanRequest {
+    pub user_id: Option<i64>,
+    pub ip_range: Option<String>,
+    pub reason: String,
+    pub banned_by: Option<i64>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Deletes all the bans of the user or the IP range (the range has to match
+/// exactly).
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeleteBansQuery {
+    pub user_id: Option<i64>,
+    pub ip_range: Option<String>,
+}
+
+/// Parses CIDR notation (`192.168.0.0/16`), a bare address is a range of a
+/// single address.
+pub fn parse_ip_range(ip_range: &str) -> Option<(IpAddr, u8)> {
+    let (ip_addr, prefix_len) = match ip_range.split_once('/') {
+        Some((ip_addr, prefix_len)) => (ip_addr.parse::<IpAddr>().ok()?, prefix_len.parse().ok()?),
+        None => {
+            let ip_addr = ip_range.parse::<IpAddr>().ok()?;
+            (ip_addr, max_prefix_len(ip_addr))
+        }
+    };
+    (prefix_len <= max_prefix_len(ip_addr)).then_some((ip_addr, prefix_len))
+}
+
+pub fn ip_range_contains(ip_range: &str, ip_addr: IpAddr) -> bool {
+    let Some((range_addr, prefix_len)) = parse_ip_range(ip_range) else {
+        return false;
+    };
+    // IPv4 clients may connect to dual-stack sockets.
+    let ip_addr = match ip_addr {
+        IpAddr::V6(ip_addr) => ip_addr
+            .to_ipv4_mapped()
+            .map_or(IpAddr::V6(ip_addr), IpAddr::V4),
+        ip_addr => ip_addr,
+    };
+    match (range_addr, ip_addr) {
+        (IpAddr::V4(range_addr), IpAddr::V4(ip_addr)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(range_addr) & mask == u32::from(ip_addr) & mask
+        }
+        (IpAddr::V6(range_addr), IpAddr::V6(ip_addr)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(range_addr) & mask == u128::from(ip_addr) & mask
+        }
+        _ => false,
+    }
+}
+
+fn max_prefix_len(ip_addr: IpAddr) -> u8 {
+    match ip_addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_range() {
+        assert_eq!(
+            parse_ip_range("192.168.0.0/16"),
+            Some(("192.168.0.0".parse().unwrap(), 16))
+        );
+        assert_eq!(
+            parse_ip_range("10.0.0.1"),
+            Some(("10.0.0.1".parse().unwrap(), 32))
+        );
+        assert_eq!(
+            parse_ip_range("2001:db8::/32"),
+            Some(("2001:db8::".parse().unwrap(), 32))
+        );
+        for invalid_range in [
+            "",
+            "10.0.0.0/33",
+            "10.0.0/8",
+            "10.0.0.0/",
+            "::/129",
+            "local",
+        ] {
+            assert_eq!(parse_ip_range(invalid_range), None, "{invalid_range:?}");
+        }
+    }
+
+    #[test]
+    fn test_ip_range_contains() {
+        let ip_addr = "192.168.1.20".parse().unwrap();
+        assert!(ip_range_contains("192.168.0.0/16", ip_addr));
+        assert!(ip_range_contains("192.168.1.20", ip_addr));
+        assert!(ip_range_contains("0.0.0.0/0", ip_addr));
+        assert!(!ip_range_contains("192.168.1.21", ip_addr));
+        assert!(!ip_range_contains("10.0.0.0/8", ip_addr));
+        assert!(!ip_range_contains("::/0", ip_addr));
+        assert!(ip_range_contains(
+            "192.168.0.0/16",
+            "::ffff:192.168.1.20".parse().unwrap()
+        ));
+        assert!(ip_range_contains(
+            "2001:db8::/32",
+            "2001:db8:1::1".parse().unwrap()
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub const BUG_REPORT_DESCRIPTION_MAX_LEN: usize = 2000;
+/// Clients send only the latest log lines, older ones are dropped by the
+/// persistence service as well.
+pub const BUG_REPORT_LOGS_MAX_LINES: usize = 200;
+pub const BUG_REPORT_LOG_LINE_MAX_LEN: usize = 1000;
+pub const BUG_REPORT_SCREENSHOT_MAX_SIZE: usize = 2 * 1024 * 1024;
+pub const BUG_REPORT_SCREENSHOT_CONTENT_TYPE: &str = "image/png";
+
+/// Describes the game session at the moment a player opened the report
+/// dialog. Everything except the description is collected by the client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostBugReportRequest {
+    pub description: String,
+    /// Is `None` if a player wasn't playing a level stored by the persistence
+    /// service.
+    pub level_id: Option<i64>,
+    pub frame_number: Option<u16>,
+    pub connection_stats: Option<BugReportConnectionStats>,
+    pub logs: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BugReportConnectionStats {
+    pub rtt_millis: f32,
+    /// Within [0.0, 1.0].
+    pub packet_loss: f32,
+    pub jitter_millis: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostBugReportResponse {
+    pub id: i64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BugReportError {
+    EmptyDescription,
+    DescriptionTooLong {
+        max_len: usize,
+    },
+    ScreenshotTooLarge {
+        max_size: usize,
+    },
+    UnsupportedScreenshotFormat,
+    /// Screenshots can be attached only once, right after submitting a report.
+    ScreenshotNotAllowed,
+}
+
+impl fmt::Display for BugReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyDescription => f.write_str("Describe what happened"),
+            Self::DescriptionTooLong { max_len } => {
+                write!(
+                    f,
+                    "Description must not be longer than {max_len} characters"
+                )
+            }
+            Self::ScreenshotTooLarge { max_size } => {
+                write!(
+                    f,
+                    "Screenshot must not be larger than {} MiB",
+                    max_size / 1024 / 1024
+                )
+            }
+            Self::UnsupportedScreenshotFormat => f.write_str("Screenshot must be a PNG file"),
+            Self::ScreenshotNotAllowed => {
+                f.write_str("A screenshot can't be attached to this report")
+            }
+        }
+    }
+}
+
+pub fn validate_bug_report_description(value: &str) -> Result<&str, BugReportError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(BugReportError::EmptyDescription);
+    }
+    if value.chars().count() > BUG_REPORT_DESCRIPTION_MAX_LEN {
+        return Err(BugReportError::DescriptionTooLong {
+            max_len: BUG_REPORT_DESCRIPTION_MAX_LEN,
+        });
+    }
+    Ok(value)
+}
+
+pub fn validate_bug_report_screenshot(data: &[u8]) -> Result<(), BugReportError> {
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    if data.len() > BUG_REPORT_SCREENSHOT_MAX_SIZE {
+        return Err(BugReportError::ScreenshotTooLarge {
+            max_size: BUG_REPORT_SCREENSHOT_MAX_SIZE,
+        });
+    }
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(BugReportError::UnsupportedScreenshotFormat);
+    }
+    Ok(())
+}
+
+/// Keeps the latest `BUG_REPORT_LOGS_MAX_LINES` lines, truncating the long
+/// ones.
+pub fn truncate_bug_report_logs(logs: &[String]) -> Vec<String> {
+    logs[logs.len().saturating_sub(BUG_REPORT_LOGS_MAX_LINES)..]
+        .iter()
+        .map(
+            |line| match line.char_indices().nth(BUG_REPORT_LOG_LINE_MAX_LEN) {
+                Some((end, _)) => line[..end].to_owned(),
+                None => line.clone(),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_bug_report_description() {
+        assert_eq!(
+            validate_bug_report_description("  Fell through the floor \n"),
+            Ok("Fell through the floor")
+        );
+        assert_eq!(
+            validate_bug_report_description(" \n"),
+            Err(BugReportError::EmptyDescription)
+        );
+        let too_long = "ы".repeat(BUG_REPORT_DESCRIPTION_MAX_LEN + 1);
+        assert_eq!(
+            validate_bug_report_description(&too_long),
+            Err(BugReportError::DescriptionTooLong {
+                max_len: BUG_REPORT_DESCRIPTION_MAX_LEN
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_bug_report_screenshot() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        assert_eq!(validate_bug_report_screenshot(&png), Ok(()));
+        assert_eq!(
+            validate_bug_report_screenshot(&[]),
+            Err(BugReportError::UnsupportedScreenshotFormat)
+        );
+        assert_eq!(
+            validate_bug_report_screenshot(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+            Err(BugReportError::UnsupportedScreenshotFormat)
+        );
+
+        let mut too_large = png;
+        too_large.resize(BUG_REPORT_SCREENSHOT_MAX_SIZE + 1, 0);
+        assert_eq!(
+            validate_bug_report_screenshot(&too_large),
+            Err(BugReportError::ScreenshotTooLarge {
+                max_size: BUG_REPORT_SCREENSHOT_MAX_SIZE
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncate_bug_report_logs() {
+        let logs = (0..BUG_REPORT_LOGS_MAX_LINES + 2)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        let truncated = truncate_bug_report_logs(&logs);
+        assert_eq!(truncated.len(), BUG_REPORT_LOGS_MAX_LINES);
+        assert_eq!(truncated[0], "2");
+
+        let long_line = "ы".repeat(BUG_REPORT_LOG_LINE_MAX_LEN + 5);
+        let truncated = truncate_bug_report_logs(&[long_line]);
+        assert_eq!(truncated[0].chars().count(), BUG_REPORT_LOG_LINE_MAX_LEN);
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendshipStatus {
+    Accepted,
+    /// The friend has sent a request that is waiting to be accepted.
+    IncomingRequest,
+    /// The user has sent a request that is waiting to be accepted by the
+    /// friend.
+    OutgoingRequest,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FriendDto {
+    pub user_id: i64,
+    pub display_name: Option<String>,
+    pub status: FriendshipStatus,
+    /// Is available only for accepted friends who are currently playing.
+    pub presence: Option<UserPresence>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostFriendRequest {
+    pub display_name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FriendRequestError {
+    AlreadyExists,
+    CannotAddSelf,
+}
+
+/// Describes where a user is playing at the moment.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserPresence {
+    /// Matches `Server::name` in the matchmaker's servers list.
+    pub server_name: String,
+    pub level_id: i64,
+    pub level_title: String,
+}
+
+/// Game servers periodically report the registered users that are connected to
+/// them. Users that are no longer listed are considered offline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PostPresenceRequest {
+    pub server_name: String,
+    pub level_id: i64,
+    pub level_title: String,
+    pub user_ids: Vec<i64>,
+}
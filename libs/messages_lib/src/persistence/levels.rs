@@ -1,6 +1,7 @@
 use crate::PaginationParams;
 use serde::{Deserialize, Serialize};
 use serde_with::rust::display_fromstr::deserialize as deserialize_fromstr;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct GetLevelsRequest {
@@ -30,6 +31,53 @@ pub struct LevelsListItem {
     pub updated_at: chrono::NaiveDateTime,
 }
 
+/// Levels are sorted by the last update time (most recent first), the cursor
+/// points to the last level of the previous page.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetLevelsSummaryRequest {
+    #[serde(flatten)]
+    pub user_filter: Option<GetLevelsUserFilter>,
+    #[serde(flatten)]
+    pub cursor: Option<LevelsCursor>,
+    #[serde(deserialize_with = "deserialize_fromstr")]
+    pub limit: i64,
+    /// Is ignored if `user_filter` is `None`, as the public list contains only
+    /// published levels.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_fromstr",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub include_unpublished: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LevelsCursor {
+    pub after_updated_at: chrono::NaiveDateTime,
+    #[serde(deserialize_with = "deserialize_fromstr")]
+    pub after_id: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct GetLevelsSummaryResponse {
+    pub levels: Vec<LevelSummary>,
+    /// Is `None` if there are no more levels to fetch.
+    pub next_cursor: Option<LevelsCursor>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LevelSummary {
+    #[serde(flatten)]
+    pub level: LevelsListItem,
+    pub builder_names: Vec<String>,
+    pub play_count: i64,
+    /// Average rating, is `None` if the level hasn't been rated yet.
+    pub rating: Option<f64>,
+    pub rating_count: i64,
+    #[serde(default)]
+    pub localizations: LevelLocalizations,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct LevelDto {
     pub id: i64,
@@ -40,6 +88,8 @@ pub struct LevelDto {
     pub parent_id: Option<i64>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub localizations: LevelLocalizations,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -50,6 +100,48 @@ pub struct GetLevelResponse {
     pub level_permissions: Vec<LevelPermissionDto>,
 }
 
+/// Titles and descriptions of a level in other languages, keyed by language
+/// codes (`en`, `pt-BR`, etc.). Builders edit them in the level settings, and
+/// the persistence service copies them from level data into a separate
+/// column, so that levels can be listed without loading their data.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct LevelLocalizations(pub BTreeMap<String, LevelLocalization>);
+
+impl LevelLocalizations {
+    /// Picks a localization for the first of the viewer's languages that has
+    /// one. Codes are matched exactly first (`pt-BR`), then by the primary
+    /// language (`pt`), so that a close translation is better than none.
+    pub fn for_languages<S: AsRef<str>>(&self, languages: &[S]) -> Option<&LevelLocalization> {
+        languages.iter().find_map(|language| {
+            let language = language.as_ref();
+            self.find(|code| code.eq_ignore_ascii_case(language))
+                .or_else(|| {
+                    let primary = primary_language(language);
+                    self.find(|code| primary_language(code).eq_ignore_ascii_case(primary))
+                })
+        })
+    }
+
+    fn find(&self, predicate: impl Fn(&str) -> bool) -> Option<&LevelLocalization> {
+        self.0
+            .iter()
+            .find(|(code, _)| predicate(code))
+            .map(|(_, localization)| localization)
+    }
+}
+
+fn primary_language(code: &str) -> &str {
+    code.split('-').next().unwrap_or(code)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LevelLocalization {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct LevelPermissionDto {
     pub user_id: i64,
@@ -91,6 +183,17 @@ pub enum LevelData {
 pub struct PatchLevelRequest {
     pub title: Option<String>,
     pub builder_ids: Option<Vec<i64>>,
+    #[serde(default)]
+    pub published: Option<bool>,
+}
+
+/// Only the fields that level authors are allowed to change. Unlike
+/// `PatchLevelRequest`, which is sent by game servers, this one is accepted by
+/// the public API.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PatchOwnLevelRequest {
+    pub title: Option<String>,
+    pub published: Option<bool>,
 }
 
 #[cfg(test)]
@@ -123,4 +226,79 @@ mod tests {
         let deserialized: GetLevelsRequest = serde_urlencoded::from_str(&serialized).unwrap();
         assert_eq!(deserialized, query);
     }
+
+    #[test]
+    fn test_get_levels_summary_request_query() {
+        let query = GetLevelsSummaryRequest {
+            user_filter: Some(GetLevelsUserFilter::BuilderId(1)),
+            cursor: Some(LevelsCursor {
+                after_updated_at: chrono::NaiveDate::from_ymd_opt(2023, 1, 15)
+                    .unwrap()
+                    .and_hms_micro_opt(14, 23, 10, 123456)
+                    .unwrap(),
+                after_id: 42,
+            }),
+            limit: 20,
+            include_unpublished: false,
+        };
+        let serialized = serde_urlencoded::to_string(&query).unwrap();
+        assert_eq!(
+            &serialized,
+            "builder_id=1&after_updated_at=2023-01-15T14%3A23%3A10.123456&after_id=42&limit=20"
+        );
+        let deserialized: GetLevelsSummaryRequest =
+            serde_urlencoded::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, query);
+
+        let query = GetLevelsSummaryRequest {
+            user_filter: None,
+            cursor: None,
+            limit: 20,
+            include_unpublished: false,
+        };
+        let serialized = serde_urlencoded::to_string(&query).unwrap();
+        assert_eq!(&serialized, "limit=20");
+        let deserialized: GetLevelsSummaryRequest =
+            serde_urlencoded::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, query);
+
+        let query = GetLevelsSummaryRequest {
+            user_filter: Some(GetLevelsUserFilter::AuthorId(1)),
+            cursor: None,
+            limit: 20,
+            include_unpublished: true,
+        };
+        let serialized = serde_urlencoded::to_string(&query).unwrap();
+        assert_eq!(&serialized, "author_id=1&limit=20&include_unpublished=true");
+        let deserialized: GetLevelsSummaryRequest =
+            serde_urlencoded::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, query);
+    }
+
+    #[test]
+    fn test_level_localizations_for_languages() {
+        let localization = |title: &str| LevelLocalization {
+            title: title.to_owned(),
+            description: String::new(),
+        };
+        let localizations = LevelLocalizations(BTreeMap::from([
+            ("de".to_owned(), localization("Erstes Level")),
+            ("pt-PT".to_owned(), localization("Primeiro nível")),
+            ("uk".to_owned(), localization("Перший рівень")),
+        ]));
+        let title = |languages: &[&str]| {
+            localizations
+                .for_languages(languages)
+                .map(|localization| localization.title.as_str())
+        };
+
+        assert_eq!(title(&["uk", "de"]), Some("Перший рівень"));
+        assert_eq!(title(&["UK"]), Some("Перший рівень"));
+        // A regional variant is better than falling back to the next language.
+        assert_eq!(title(&["pt-BR", "de"]), Some("Primeiro nível"));
+        assert_eq!(title(&["de-AT"]), Some("Erstes Level"));
+        assert_eq!(title(&["fr", "de"]), Some("Erstes Level"));
+        assert_eq!(title(&["fr", "en"]), None);
+        assert_eq!(title(&[]), None);
+    }
 }
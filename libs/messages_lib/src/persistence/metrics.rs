@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// Connection pool utilization of the persistence service.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GetPoolsMetricsResponse {
+    pub primary: PoolMetrics,
+    /// Is `None` if the read replica isn't configured.
+    pub replica: Option<PoolMetrics>,
+    /// The number of read queries that were routed to the primary because
+    /// the replica was unavailable, since the service start.
+    pub replica_fallbacks: u64,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct PoolMetrics {
+    /// Open connections, including the idle ones.
+    pub size: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+}
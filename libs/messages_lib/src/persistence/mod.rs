@@ -1,5 +1,23 @@
+mod admin;
+mod allocations;
+mod api_tokens;
+mod audio_clips;
+mod bans;
+mod bug_reports;
+mod friends;
 mod levels;
+mod metrics;
+mod player_stats;
 mod users;
 
+pub use admin::*;
+pub use allocations::*;
+pub use api_tokens::*;
+pub use audio_clips::*;
+pub use bans::*;
+pub use bug_reports::*;
+pub use friends::*;
 pub use levels::*;
+pub use metrics::*;
+pub use player_stats::*;
 pub use users::*;
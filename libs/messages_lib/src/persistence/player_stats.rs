@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Lifetime counters of a registered user, across all levels and sessions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub finishes: i64,
+    pub deaths: i64,
+}
+
+/// Game servers report increments rather than totals, so that several
+/// sessions of the same user running at once don't overwrite each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PostPlayerStatsRequest {
+    pub finishes: u32,
+    pub deaths: u32,
+}
+
+impl PostPlayerStatsRequest {
+    pub fn is_empty(&self) -> bool {
+        self.finishes == 0 && self.deaths == 0
+    }
+}
@@ -1,3 +1,4 @@
+use crate::validation::ValidationError;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -56,6 +57,7 @@ pub struct PatchUserRequest {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PatchUserError {
     DisplayNameTaken,
+    InvalidDisplayName(Vec<ValidationError>),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -63,3 +65,16 @@ pub struct GetRegisteredUserQuery {
     pub subject: String,
     pub issuer: String,
 }
+
+/// Consent flags are opt-in: users who have never changed their privacy
+/// settings have both of them disabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    /// Allows recording traces of a player's movement that are associated with
+    /// their account (replays, for instance).
+    pub allow_session_recording: bool,
+    /// Allows attributing gameplay analytics, such as heatmaps, to the account.
+    /// Analytics of players who don't consent are collected only in an
+    /// aggregated anonymous form.
+    pub allow_analytics: bool,
+}
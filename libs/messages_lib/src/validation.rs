@@ -0,0 +1,572 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+/// Matches the `varchar(255)` column of the `users` table.
+pub const DISPLAY_NAME_MAX_LEN: usize = 255;
+/// Matches the `varchar(255)` column of the `levels` table.
+pub const LEVEL_TITLE_MAX_LEN: usize = 255;
+/// Labels are stored inside level data, which has no column limits, but they
+/// are rendered in the builder UI next to other fields.
+pub const LEVEL_OBJECT_LABEL_MAX_LEN: usize = 64;
+/// Level data is stored as a `jsonb` value without any column limits. Real
+/// levels are well below this, even with hundreds of objects.
+pub const LEVEL_DATA_MAX_BYTES: usize = 512 * 1024;
+/// The deepest values of real levels (points of concave planes) are nested 9
+/// levels deep.
+pub const LEVEL_DATA_MAX_DEPTH: usize = 16;
+pub const LEVEL_DATA_MAX_ARRAY_LEN: usize = 2048;
+/// Matches the `varchar(64)` column of the `api_tokens` table.
+pub const API_TOKEN_NAME_MAX_LEN: usize = 64;
+/// Localizations are stored inside level data (and copied into a `jsonb`
+/// column), which has no column limits, but they are listed in the levels
+/// browser.
+pub const LEVEL_DESCRIPTION_MAX_LEN: usize = 500;
+pub const LEVEL_LOCALIZATIONS_MAX_COUNT: usize = 32;
+/// Language codes are expected to be BCP 47 tags, which are rarely longer
+/// than a language, a script and a region (`zh-Hant-TW`).
+pub const LANGUAGE_CODE_MAX_LEN: usize = 16;
+
+/// Masked by [`sanitize_text`], together with their plural and verb forms.
+const BLOCKED_WORDS: &[&str] = &[
+    "asshole", "bastard", "bitch", "cunt", "dick", "fuck", "nigger", "shit", "whore",
+];
+const BLOCKED_WORD_SUFFIXES: &[&str] = &["", "s", "es", "ed", "er", "ers", "ing"];
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ValidationError {
+    Empty,
+    TooLong { max_len: usize },
+    NonAscii,
+    ControlCharacters,
+    InvalidLanguageCode,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("must not be empty"),
+            Self::TooLong { max_len } => {
+                write!(f, "must not be longer than {max_len} characters")
+            }
+            Self::NonAscii => f.write_str("can contain only ASCII characters"),
+            Self::ControlCharacters => f.write_str("must not contain control characters"),
+            Self::InvalidLanguageCode => {
+                f.write_str("must be a language code, such as \"en\" or \"pt-BR\"")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LevelDataError {
+    TooLarge {
+        max_bytes: usize,
+    },
+    TooDeep {
+        max_depth: usize,
+    },
+    ArrayTooLong {
+        max_len: usize,
+    },
+    TooManyLocalizations {
+        max_count: usize,
+    },
+    InvalidLocalization {
+        message: String,
+    },
+    /// The value at `path` (a JSON pointer) doesn't match the level format.
+    InvalidFormat {
+        path: String,
+    },
+}
+
+impl fmt::Display for LevelDataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { max_bytes } => {
+                write!(f, "Level data must not be larger than {max_bytes} bytes")
+            }
+            Self::TooDeep { max_depth } => {
+                write!(
+                    f,
+                    "Level data must not be nested deeper than {max_depth} levels"
+                )
+            }
+            Self::ArrayTooLong { max_len } => {
+                write!(
+                    f,
+                    "Level data arrays must not be longer than {max_len} items"
+                )
+            }
+            Self::TooManyLocalizations { max_count } => {
+                write!(
+                    f,
+                    "A level must not have more than {max_count} localizations"
+                )
+            }
+            Self::InvalidLocalization { message } => f.write_str(message),
+            Self::InvalidFormat { path } => {
+                write!(f, "Level data has an invalid value at \"{path}\"")
+            }
+        }
+    }
+}
+
+/// Joins the errors into a single message, prefixed with the field name (for
+/// example, "Display name must not be empty, can contain only ASCII
+/// characters").
+pub fn format_errors(field_name: &str, errors: &[ValidationError]) -> String {
+    let errors = errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{field_name} {errors}")
+}
+
+/// Returns the trimmed value if it's valid, which is what has to be stored.
+pub fn validate_display_name(value: &str) -> Result<&str, Vec<ValidationError>> {
+    let value = value.trim();
+    let mut errors = Vec::new();
+    check_not_empty(value, &mut errors);
+    check_max_len(value, DISPLAY_NAME_MAX_LEN, &mut errors);
+    if !value.is_ascii() {
+        errors.push(ValidationError::NonAscii);
+    }
+    check_no_control_characters(value, &mut errors);
+    into_result(value, errors)
+}
+
+/// Returns the trimmed value if it's valid, which is what has to be stored.
+pub fn validate_level_title(value: &str) -> Result<&str, Vec<ValidationError>> {
+    let value = value.trim();
+    let mut errors = Vec::new();
+    check_not_empty(value, &mut errors);
+    check_max_len(value, LEVEL_TITLE_MAX_LEN, &mut errors);
+    check_no_control_characters(value, &mut errors);
+    into_result(value, errors)
+}
+
+/// Returns the trimmed value if it's valid, which is what has to be stored.
+pub fn validate_api_token_name(value: &str) -> Result<&str, Vec<ValidationError>> {
+    let value = value.trim();
+    let mut errors = Vec::new();
+    check_not_empty(value, &mut errors);
+    check_max_len(value, API_TOKEN_NAME_MAX_LEN, &mut errors);
+    check_no_control_characters(value, &mut errors);
+    into_result(value, errors)
+}
+
+/// Only the primary language subtag is required (`en`), others (a script or
+/// a region) are optional, but must be alphanumeric. Codes are used as keys,
+/// so unlike the other values, they aren't trimmed.
+pub fn validate_language_code(value: &str) -> Result<&str, Vec<ValidationError>> {
+    let mut errors = Vec::new();
+    check_not_empty(value, &mut errors);
+    check_max_len(value, LANGUAGE_CODE_MAX_LEN, &mut errors);
+    let mut subtags = value.split('-');
+    let primary_language = subtags.next().unwrap_or_default();
+    let is_valid = (2..=3).contains(&primary_language.len())
+        && primary_language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if !value.is_empty() && !is_valid {
+        errors.push(ValidationError::InvalidLanguageCode);
+    }
+    into_result(value, errors)
+}
+
+/// Returns the trimmed value if it's valid, which is what has to be stored.
+/// Descriptions are optional.
+pub fn validate_level_description(value: &str) -> Result<&str, Vec<ValidationError>> {
+    let value = value.trim();
+    let mut errors = Vec::new();
+    check_max_len(value, LEVEL_DESCRIPTION_MAX_LEN, &mut errors);
+    check_no_control_characters(value, &mut errors);
+    into_result(value, errors)
+}
+
+/// Accepts `(language, title, description)` tuples, as game servers and the
+/// persistence service keep localizations in different types.
+pub fn validate_level_localizations<'a>(
+    mut localizations: impl ExactSizeIterator<Item = (&'a str, &'a str, &'a str)>,
+) -> Result<(), LevelDataError> {
+    if localizations.len() > LEVEL_LOCALIZATIONS_MAX_COUNT {
+        return Err(LevelDataError::TooManyLocalizations {
+            max_count: LEVEL_LOCALIZATIONS_MAX_COUNT,
+        });
+    }
+    localizations.try_for_each(|(language, title, description)| {
+        validate_level_localization(language, title, description)
+    })
+}
+
+pub fn validate_level_localization(
+    language: &str,
+    title: &str,
+    description: &str,
+) -> Result<(), LevelDataError> {
+    let message = if let Err(errors) = validate_language_code(language) {
+        format_errors("Language code", &errors)
+    } else if let Err(errors) = validate_level_title(title) {
+        format_errors(&format!("Level title ({language})"), &errors)
+    } else if let Err(errors) = validate_level_description(description) {
+        format_errors(&format!("Level description ({language})"), &errors)
+    } else {
+        return Ok(());
+    };
+    Err(LevelDataError::InvalidLocalization { message })
+}
+
+/// Checks the size and the structure of level data before it gets stored.
+/// Only the fields that every level object has are checked, object
+/// descriptions are left to game servers, which parse them anyway.
+pub fn validate_level_data(data: &serde_json::Value) -> Result<(), LevelDataError> {
+    let size = serde_json::to_vec(data).map_or(usize::MAX, |bytes| bytes.len());
+    if size > LEVEL_DATA_MAX_BYTES {
+        return Err(LevelDataError::TooLarge {
+            max_bytes: LEVEL_DATA_MAX_BYTES,
+        });
+    }
+    check_level_data_nesting(data, 1)?;
+
+    // Levels saved before the settings were introduced are plain arrays of
+    // objects.
+    let (objects, objects_path) = match data {
+        serde_json::Value::Array(objects) => (objects, ""),
+        serde_json::Value::Object(level) => {
+            if !level
+                .get("settings")
+                .map_or(true, serde_json::Value::is_object)
+            {
+                return Err(invalid_format("/settings".to_owned()));
+            }
+            let objects = level
+                .get("objects")
+                .and_then(serde_json::Value::as_array)
+                .ok_or_else(|| invalid_format("/objects".to_owned()))?;
+            (objects, "/objects")
+        }
+        _ => return Err(invalid_format(String::new())),
+    };
+
+    for (i, object) in objects.iter().enumerate() {
+        let path = format!("{objects_path}/{i}");
+        let object = object
+            .as_object()
+            .ok_or_else(|| invalid_format(path.clone()))?;
+        let fields: [(&str, fn(&serde_json::Value) -> bool); 5] = [
+            ("net_id", serde_json::Value::is_u64),
+            ("label", serde_json::Value::is_string),
+            // Enums with data are serialized as objects with a single key.
+            ("desc", |desc| {
+                desc.as_object().map_or(false, |desc| desc.len() == 1)
+            }),
+            ("route", |route| route.is_null() || route.is_object()),
+            ("collision_logic", serde_json::Value::is_string),
+        ];
+        for (field, is_valid) in fields {
+            // Missing routes are deserialized as `None`.
+            let is_valid = match object.get(field) {
+                Some(value) => is_valid(value),
+                None => field == "route",
+            };
+            if !is_valid {
+                return Err(invalid_format(format!("{path}/{field}")));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_level_data_nesting(value: &serde_json::Value, depth: usize) -> Result<(), LevelDataError> {
+    let children: Box<dyn Iterator<Item = &serde_json::Value>> = match value {
+        serde_json::Value::Array(items) => {
+            if items.len() > LEVEL_DATA_MAX_ARRAY_LEN {
+                return Err(LevelDataError::ArrayTooLong {
+                    max_len: LEVEL_DATA_MAX_ARRAY_LEN,
+                });
+            }
+            Box::new(items.iter())
+        }
+        serde_json::Value::Object(fields) => Box::new(fields.values()),
+        _ => return Ok(()),
+    };
+    if depth > LEVEL_DATA_MAX_DEPTH {
+        return Err(LevelDataError::TooDeep {
+            max_depth: LEVEL_DATA_MAX_DEPTH,
+        });
+    }
+    for child in children {
+        check_level_data_nesting(child, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn invalid_format(path: String) -> LevelDataError {
+    LevelDataError::InvalidFormat { path }
+}
+
+/// Makes builder-provided text (level titles and object labels) safe to store,
+/// log and render: normalizes it to NFC, replaces line breaks and tabs with
+/// spaces, strips control and bidirectional formatting characters, collapses
+/// repeated whitespace, masks blocked words and truncates the result to
+/// `max_len` characters.
+///
+/// The result can still be empty, validation functions are expected to be
+/// called on top of it.
+pub fn sanitize_text(value: &str, max_len: usize) -> String {
+    let mut sanitized = String::with_capacity(value.len());
+    for c in value.nfc() {
+        if c.is_whitespace() {
+            if !sanitized.is_empty() && !sanitized.ends_with(' ') {
+                sanitized.push(' ');
+            }
+        } else if !c.is_control() && !is_bidi_control(c) {
+            sanitized.push(c);
+        }
+    }
+
+    let mut sanitized = mask_blocked_words(sanitized.trim_end());
+    if let Some((i, _)) = sanitized.char_indices().nth(max_len) {
+        sanitized.truncate(i);
+        sanitized.truncate(sanitized.trim_end().len());
+    }
+    sanitized
+}
+
+/// These can't be caught by `char::is_control`, but they reorder the
+/// surrounding text when rendered, which can make a title look different
+/// from what's stored.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+fn mask_blocked_words(value: &str) -> String {
+    let mut masked = String::with_capacity(value.len());
+    let mut word_start = None;
+    for (i, c) in value
+        .char_indices()
+        .chain(std::iter::once((value.len(), ' ')))
+    {
+        if c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = word_start.take() {
+            let word = &value[start..i];
+            if is_blocked_word(word) {
+                masked.extend(std::iter::repeat('*').take(word.chars().count()));
+            } else {
+                masked.push_str(word);
+            }
+        }
+        if i < value.len() {
+            masked.push(c);
+        }
+    }
+    masked
+}
+
+fn is_blocked_word(word: &str) -> bool {
+    let word = word.to_lowercase();
+    BLOCKED_WORDS.iter().any(|blocked| {
+        word.strip_prefix(blocked)
+            .map_or(false, |suffix| BLOCKED_WORD_SUFFIXES.contains(&suffix))
+    })
+}
+
+fn check_not_empty(value: &str, errors: &mut Vec<ValidationError>) {
+    if value.is_empty() {
+        errors.push(ValidationError::Empty);
+    }
+}
+
+fn check_max_len(value: &str, max_len: usize, errors: &mut Vec<ValidationError>) {
+    // Postgres limits `varchar` columns in characters, not bytes.
+    if value.chars().count() > max_len {
+        errors.push(ValidationError::TooLong { max_len });
+    }
+}
+
+fn check_no_control_characters(value: &str, errors: &mut Vec<ValidationError>) {
+    if value.chars().any(char::is_control) {
+        errors.push(ValidationError::ControlCharacters);
+    }
+}
+
+fn into_result(value: &str, errors: Vec<ValidationError>) -> Result<&str, Vec<ValidationError>> {
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_display_name() {
+        assert_eq!(validate_display_name("  mvlabat "), Ok("mvlabat"));
+        assert_eq!(
+            validate_display_name("   "),
+            Err(vec![ValidationError::Empty])
+        );
+        assert_eq!(
+            validate_display_name("мвлабат\t1"),
+            Err(vec![
+                ValidationError::NonAscii,
+                ValidationError::ControlCharacters
+            ])
+        );
+        assert_eq!(
+            validate_display_name(&"a".repeat(DISPLAY_NAME_MAX_LEN + 1)),
+            Err(vec![ValidationError::TooLong {
+                max_len: DISPLAY_NAME_MAX_LEN
+            }])
+        );
+    }
+
+    #[test]
+    fn test_validate_level_title() {
+        assert_eq!(validate_level_title(" Рівень 1 "), Ok("Рівень 1"));
+        assert_eq!(validate_level_title(""), Err(vec![ValidationError::Empty]));
+        // Multi-byte characters are counted as a single character each.
+        assert!(validate_level_title(&"ї".repeat(LEVEL_TITLE_MAX_LEN)).is_ok());
+    }
+
+    #[test]
+    fn test_validate_level_localization() {
+        assert_eq!(validate_language_code("pt-BR"), Ok("pt-BR"));
+        assert_eq!(validate_language_code("zh-Hant-TW"), Ok("zh-Hant-TW"));
+        for invalid_code in ["e", "english", "pt_BR", "pt-", " uk", "uk-UA-\u{0}"] {
+            assert_eq!(
+                validate_language_code(invalid_code),
+                Err(vec![ValidationError::InvalidLanguageCode]),
+                "{invalid_code:?}"
+            );
+        }
+
+        assert_eq!(validate_level_localization("uk", "Рівень", ""), Ok(()));
+        assert_eq!(
+            validate_level_localization("uk", " ", "Опис"),
+            Err(LevelDataError::InvalidLocalization {
+                message: "Level title (uk) must not be empty".to_owned()
+            })
+        );
+        assert_eq!(
+            validate_level_localization("uk", "Рівень", &"a".repeat(LEVEL_DESCRIPTION_MAX_LEN + 1)),
+            Err(LevelDataError::InvalidLocalization {
+                message: format!(
+                    "Level description (uk) must not be longer than {LEVEL_DESCRIPTION_MAX_LEN} \
+                     characters"
+                )
+            })
+        );
+    }
+
+    #[test]
+    fn test_sanitize_text() {
+        assert_eq!(
+            sanitize_text("  My\tfirst\r\n\n level\u{0} ", LEVEL_TITLE_MAX_LEN),
+            "My first level"
+        );
+        // Decomposed "й" is composed into a single character.
+        assert_eq!(sanitize_text("Мі\u{438}\u{306} рівень", 10), "Мій рівень");
+        assert_eq!(sanitize_text("abc\u{202e}def", 10), "abcdef");
+        assert_eq!(
+            sanitize_text("Shit happens, Dickens! FUCKING shitake", 64),
+            "**** happens, Dickens! ******* shitake"
+        );
+        assert_eq!(sanitize_text("Ground with spaces", 11), "Ground with");
+        assert_eq!(sanitize_text("Ground with spaces", 12), "Ground with");
+        assert_eq!(sanitize_text(" \n\u{7} ", 10), "");
+    }
+
+    #[test]
+    fn test_validate_level_data() {
+        let object = serde_json::json!({
+            "net_id": 1,
+            "label": "Ground",
+            "desc": {"Plane": {"position": [0.0, 0.0]}},
+            "route": null,
+            "collision_logic": "None",
+        });
+        assert_eq!(
+            validate_level_data(&serde_json::json!({"objects": [object.clone()], "settings": {}})),
+            Ok(())
+        );
+        // The legacy format.
+        assert_eq!(
+            validate_level_data(&serde_json::json!([object.clone()])),
+            Ok(())
+        );
+
+        let mut invalid_object = object.clone();
+        invalid_object["desc"] = serde_json::json!("Plane");
+        assert_eq!(
+            validate_level_data(&serde_json::json!({ "objects": [object, invalid_object] })),
+            Err(LevelDataError::InvalidFormat {
+                path: "/objects/1/desc".to_owned()
+            })
+        );
+        assert_eq!(
+            validate_level_data(&serde_json::json!({"settings": {}})),
+            Err(LevelDataError::InvalidFormat {
+                path: "/objects".to_owned()
+            })
+        );
+        assert_eq!(
+            validate_level_data(&serde_json::json!("level")),
+            Err(LevelDataError::InvalidFormat {
+                path: String::new()
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_level_data_limits() {
+        let mut nested = serde_json::json!([]);
+        for _ in 0..LEVEL_DATA_MAX_DEPTH {
+            nested = serde_json::json!([nested]);
+        }
+        assert_eq!(
+            validate_level_data(&nested),
+            Err(LevelDataError::TooDeep {
+                max_depth: LEVEL_DATA_MAX_DEPTH
+            })
+        );
+
+        let long_array =
+            serde_json::Value::Array(vec![serde_json::Value::Null; LEVEL_DATA_MAX_ARRAY_LEN + 1]);
+        assert_eq!(
+            validate_level_data(&long_array),
+            Err(LevelDataError::ArrayTooLong {
+                max_len: LEVEL_DATA_MAX_ARRAY_LEN
+            })
+        );
+
+        let large_label = serde_json::Value::String("a".repeat(LEVEL_DATA_MAX_BYTES));
+        assert_eq!(
+            validate_level_data(&serde_json::json!([{ "label": large_label }])),
+            Err(LevelDataError::TooLarge {
+                max_bytes: LEVEL_DATA_MAX_BYTES
+            })
+        );
+    }
+
+    #[test]
+    fn test_format_errors() {
+        assert_eq!(
+            format_errors(
+                "Display name",
+                &[ValidationError::Empty, ValidationError::NonAscii]
+            ),
+            "Display name must not be empty, can contain only ASCII characters"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! A stable entry point for embedding the muddle-run server into launchers and
+//! tools (for instance, to host a local game next to a client started with
+//! `mr_client_embed`).
+//!
+//! Unlike `mr_server`, an embedded server doesn't integrate with Agones and
+//! doesn't install a panic hook, as the process is owned by the embedder:
+//!
+//! ```no_run
+//! use mr_server_embed::{run_server, ServerConfig, ServerLifecycleEvent};
+//!
+//! let config = ServerConfig {
+//!     level_file: Some("levels/my_level.json".into()),
+//!     ..Default::default()
+//! }
+//! .on_lifecycle_event(|event| {
+//!     if let ServerLifecycleEvent::PlayerConnected(uuid) = event {
+//!         println!("{uuid} joined");
+//!     }
+//! });
+//! run_server(config);
+//! ```
+
+use bevy::{app::AppExit, log, prelude::*};
+use mr_server_lib::{
+    bootstrap, BootstrapOptions, GameServerPlugin, GameServerPlugins, MuddleServerConfig,
+    MuddleServerPlugin, PlayerEvent, PlayerEventSender, RetryPolicy, ServerVersion, TOKIO,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    ops::Deref,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use url::Url;
+
+/// The port that the server listens to if `listen_addr` isn't set (the same
+/// one that clients connect to by default).
+pub const DEFAULT_SERVER_PORT: u16 = 3455;
+
+type LifecycleCallback = Arc<dyn Fn(ServerLifecycleEvent) + Send + Sync>;
+type GameServerPluginFactory = Arc<dyn Fn() -> Box<dyn GameServerPlugin> + Send + Sync>;
+
+/// Configures a server started with [`run_server`].
+#[derive(Clone, Default)]
+pub struct ServerConfig {
+    /// Defaults to `0.0.0.0:3455`.
+    pub listen_addr: Option<SocketAddr>,
+    /// The address sent to clients to establish WebRTC connections, defaults
+    /// to the local IP address.
+    pub public_ip_addr: Option<IpAddr>,
+    /// Both persistence urls are required to load published levels and let
+    /// players save their levels.
+    pub public_persistence_url: Option<Url>,
+    pub private_persistence_url: Option<Url>,
+    /// Makes the server load the level from a local file instead of persistence
+    /// and apply the changes made to the file live.
+    pub level_file: Option<PathBuf>,
+    /// The server exits after staying without players for this long, defaults
+    /// to 5 minutes.
+    pub idle_timeout: Option<Duration>,
+    /// Enables tethering: connected runners get paired and can't get farther
+    /// than this distance from each other.
+    pub tether_distance: Option<f32>,
+    lifecycle_callback: Option<LifecycleCallback>,
+    game_server_plugins: Vec<GameServerPluginFactory>,
+}
+
+impl ServerConfig {
+    /// Sets the callback that gets called on every [`ServerLifecycleEvent`].
+    /// Player events are reported from a background thread, the rest of them
+    /// are reported from the game loop, so the callback is expected to return
+    /// quickly.
+    pub fn on_lifecycle_event(
+        mut self,
+        callback: impl Fn(ServerLifecycleEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.lifecycle_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Registers a plugin implementing a custom game mode (see
+    /// [`GameServerPlugin`]). As the config can be cloned, every server gets
+    /// its own copy of the plugin.
+    pub fn add_game_server_plugin(mut self, plugin: impl GameServerPlugin + Clone) -> Self {
+        self.game_server_plugins
+            .push(Arc::new(move || -> Box<dyn GameServerPlugin> {
+                Box::new(plugin.clone())
+            }));
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerLifecycleEvent {
+    /// The level is loaded, and the server starts listening for connections.
+    Started,
+    /// Contains the player's uuid.
+    PlayerConnected(String),
+    /// Contains the player's uuid.
+    PlayerDisconnected(String),
+    /// The server has been idle for too long, [`run_server`] is about to
+    /// return.
+    Stopping,
+}
+
+impl From<PlayerEvent> for ServerLifecycleEvent {
+    fn from(event: PlayerEvent) -> Self {
+        match event {
+            PlayerEvent::Connected(uuid) => Self::PlayerConnected(uuid),
+            PlayerEvent::Disconnected(uuid) => Self::PlayerDisconnected(uuid),
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+struct ServerLifecycleCallback(LifecycleCallback);
+
+/// Loads the level and runs the server until it becomes idle.
+///
+/// Blocks the calling thread, and can't be called more than once per process.
+pub fn run_server(config: ServerConfig) {
+    let mut app = App::new();
+    app.add_plugin(log::LogPlugin::default());
+
+    // Spawn the runtime on some other thread.
+    std::thread::spawn(|| TOKIO.deref()).join().unwrap();
+
+    let server_config = MuddleServerConfig {
+        public_persistence_url: config.public_persistence_url,
+        private_persistence_url: config.private_persistence_url,
+        idle_timeout_millis: config
+            .idle_timeout
+            .map(|idle_timeout| idle_timeout.as_millis() as u64),
+        collider_simplification_tolerance: None,
+        listen_port: Some(
+            config
+                .listen_addr
+                .map_or(DEFAULT_SERVER_PORT, |listen_addr| listen_addr.port()),
+        ),
+        listen_ip_addr: config.listen_addr.map(|listen_addr| listen_addr.ip()),
+        public_ip_addr: config.public_ip_addr,
+        simulation_cpu_core: None,
+        simulation_thread_nice: None,
+        level_file: config.level_file,
+        determinism_guard: None,
+        tether_distance: config.tether_distance,
+        interest_radius: None,
+        record_session: None,
+        runtime_config_file: None,
+        game_mode: None,
+    };
+
+    if let Some(callback) = config.lifecycle_callback {
+        let (player_tracking_tx, mut player_tracking_rx) =
+            tokio::sync::mpsc::unbounded_channel::<PlayerEvent>();
+        let player_events_callback = callback.clone();
+        TOKIO.spawn(async move {
+            while let Some(player_event) = player_tracking_rx.recv().await {
+                player_events_callback(player_event.into());
+            }
+        });
+
+        app.insert_resource(PlayerEventSender(Some(player_tracking_tx)))
+            .insert_resource(ServerLifecycleCallback(callback))
+            .add_startup_system(report_started_system)
+            .add_system_to_stage(CoreStage::Last, report_stopping_system);
+    } else {
+        app.insert_resource(PlayerEventSender(None));
+    }
+
+    let mut game_server_plugins = GameServerPlugins::default();
+    for plugin in &config.game_server_plugins {
+        game_server_plugins.add(plugin());
+    }
+    app.insert_resource(game_server_plugins);
+
+    app.insert_resource(server_config);
+    let bootstrap_options = BootstrapOptions {
+        agones_grpc_port: None,
+        version: ServerVersion::default(),
+        retry_policy: RetryPolicy::default(),
+    };
+    if let Err(err) = TOKIO.block_on(bootstrap(&mut app, bootstrap_options)) {
+        panic!("{}", err);
+    }
+    app.add_plugin(MuddleServerPlugin);
+    app.run();
+}
+
+fn report_started_system(callback: Res<ServerLifecycleCallback>) {
+    callback(ServerLifecycleEvent::Started);
+}
+
+fn report_stopping_system(
+    callback: Res<ServerLifecycleCallback>,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.iter().next().is_some() {
+        callback(ServerLifecycleEvent::Stopping);
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{
+    bans::BanList,
+    level_watch::LevelFile,
+    net::{broadcast_reliable_game_message, ConnectionStates, PlayerConnections, RegisteredUsers},
+    persistence::PersistenceRequest,
+    runtime_config::{apply_runtime_config, RuntimeConfig, RuntimeConfigFile, RuntimeSettings},
+    Agones, MuddleServerConfig, PersistenceRequestSender,
+};
+use bevy::{
+    ecs::system::{NonSend, NonSendMut, Res, ResMut, Resource, SystemParam},
+    log,
+    math::Vec2,
+    prelude::{Deref, DerefMut},
+    utils::HashMap,
+};
+use bevy_disturbulence::NetworkResource;
+use mr_messages_lib::{
+    parse_ip_range, validation::sanitize_text, AdminPermission, Ban, DeleteBansQuery,
+    PostAdminActionRequest, PostBanRequest, BAN_REASON_MAX_LEN,
+};
+use mr_shared_lib::{
+    game::commands::DeferredPlayerQueues,
+    messages::{
+        AdminCommand, AdminPermissions, BanTarget, DisconnectReason, ReliableServerMessage,
+        RunnerInput, ADMIN_BROADCAST_MAX_LEN,
+    },
+    net::ConnectionStatus,
+};
+
+/// Admin permissions of registered users, keyed by connection handles. Only
+/// users who have at least one permission get an entry.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct ConnectedAdmins(pub HashMap<u32, AdminPermissions>);
+
+/// Is set with `AdminCommand::Pause`. The simulation keeps running, so that the
+/// server stays responsive, but runners can't move.
+#[derive(Resource, Default)]
+pub struct AdminPause(pub bool);
+
+/// Messages that are broadcast to all the connected players, even if the game
+/// is paused.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct AdminBroadcasts(pub Vec<String>);
+
+pub fn admin_permissions(permissions: mr_messages_lib::AdminPermissions) -> AdminPermissions {
+    AdminPermissions {
+        kick: permissions.kick,
+        pause: permissions.pause,
+        reload: permissions.reload,
+        cvars: permissions.cvars,
+        broadcast: permissions.broadcast,
+        ban: permissions.ban,
+    }
+}
+
+fn command_permission(command: &AdminCommand) -> AdminPermission {
+    match command {
+        AdminCommand::Kick(_) => AdminPermission::Kick,
+        AdminCommand::Pause(_) => AdminPermission::Pause,
+        AdminCommand::Reload => AdminPermission::Reload,
+        AdminCommand::SetCvar { .. } => AdminPermission::Cvars,
+        AdminCommand::Broadcast(_) => AdminPermission::Broadcast,
+        AdminCommand::Ban { .. } | AdminCommand::Unban(_) => AdminPermission::Ban,
+    }
+}
+
+#[derive(SystemParam)]
+pub struct AdminCommandTargets<'w, 's> {
+    connection_states: ResMut<'w, ConnectionStates>,
+    admin_pause: ResMut<'w, AdminPause>,
+    admin_broadcasts: ResMut<'w, AdminBroadcasts>,
+    level_file: Option<Res<'w, LevelFile>>,
+    runtime_config_file: Option<Res<'w, RuntimeConfigFile>>,
+    runtime_settings: RuntimeSettings<'w, 's>,
+    server_config: Res<'w, MuddleServerConfig>,
+    net: NonSend<'w, NetworkResource>,
+    ban_list: ResMut<'w, BanList>,
+    registered_users: Res<'w, RegisteredUsers>,
+    persistence_req_tx: Res<'w, PersistenceRequestSender>,
+}
+
+impl<'w, 's> AdminCommandTargets<'w, 's> {
+    /// Returns the details of the command for the audit log.
+    fn execute(
+        &mut self,
+        command: AdminCommand,
+        admin_user_id: Option<i64>,
+        player_connections: &PlayerConnections,
+    ) -> String {
+        match command {
+            AdminCommand::Kick(player_net_id) => {
+                if let Some(connection_state) = player_connections
+                    .get_value(player_net_id)
+                    .and_then(|handle| self.connection_states.get_mut(&handle))
+                {
+                    if matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        connection_state
+                            .set_status(ConnectionStatus::Disconnecting(DisconnectReason::Kicked));
+                    }
+                }
+                format!("player {}", player_net_id.0)
+            }
+            AdminCommand::Pause(is_paused) => {
+                if self.admin_pause.0 != is_paused {
+                    self.admin_pause.0 = is_paused;
+                    let message = if is_paused {
+                        "The game has been paused by an admin"
+                    } else {
+                        "The game has been resumed by an admin"
+                    };
+                    self.admin_broadcasts.push(message.to_owned());
+                }
+                if is_paused { "paused" } else { "resumed" }.to_owned()
+            }
+            AdminCommand::Reload => {
+                let mut reloaded = Vec::new();
+                if let Some(level_file) = &self.level_file {
+                    level_file.request_reload();
+                    reloaded.push("level file");
+                }
+                if let Some(runtime_config_file) = &self.runtime_config_file {
+                    runtime_config_file.request_reload();
+                    reloaded.push("runtime config file");
+                }
+                if reloaded.is_empty() {
+                    log::warn!("Nothing to reload: the server doesn't watch any files");
+                    return "nothing to reload".to_owned();
+                }
+                reloaded.join(", ")
+            }
+            AdminCommand::SetCvar { name, value } => {
+                // Bare words are accepted as strings, to save admins from quoting them.
+                let json_value = serde_json::from_str(&value)
+                    .unwrap_or_else(|_| serde_json::Value::String(value.clone()));
+                let config = serde_json::Map::from_iter([(name.clone(), json_value)]);
+                match serde_json::from_value::<RuntimeConfig>(serde_json::Value::Object(config)) {
+                    Ok(config) => apply_runtime_config(
+                        config,
+                        self.server_config.tether_distance.is_some(),
+                        &mut self.runtime_settings,
+                    ),
+                    Err(err) => log::warn!("Ignoring an invalid cvar value ({name}): {err:?}"),
+                }
+                format!("{name} = {value}")
+            }
+            AdminCommand::Broadcast(message) => {
+                let message = sanitize_text(&message, ADMIN_BROADCAST_MAX_LEN);
+                if !message.is_empty() {
+                    self.admin_broadcasts.push(message.clone());
+                }
+                message
+            }
+            AdminCommand::Ban {
+                target,
+                duration_secs,
+                reason,
+            } => {
+                let Some((user_id, ip_range)) =
+                    self.resolve_ban_target(&target, player_connections)
+                else {
+                    log::warn!("Ignoring an invalid ban target: {:?}", target);
+                    return format!("invalid target {target:?}");
+                };
+                let now = chrono::Utc::now().naive_utc();
+                // Durations that overflow are as good as permanent.
+                let expires_at = duration_secs.and_then(|duration_secs| {
+                    chrono::Duration::from_std(std::time::Duration::from_secs(duration_secs))
+                        .ok()
+                        .and_then(|duration| now.checked_add_signed(duration))
+                });
+                let reason = sanitize_text(&reason, BAN_REASON_MAX_LEN);
+                let ban = Ban {
+                    id: 0,
+                    user_id,
+                    ip_range: ip_range.clone(),
+                    reason: reason.clone(),
+                    banned_by: admin_user_id,
+                    expires_at,
+                    created_at: now,
+                };
+
+                for (handle, connection) in self.net.connections.iter() {
+                    let ip_addr = connection.remote_address().map(|address| address.ip());
+                    if !ban.matches(self.registered_users.get(handle).copied(), ip_addr) {
+                        continue;
+                    }
+                    if let Some(connection_state) = self.connection_states.get_mut(handle) {
+                        if matches!(connection_state.status(), ConnectionStatus::Connected) {
+                            connection_state.set_status(ConnectionStatus::Disconnecting(
+                                DisconnectReason::Banned { expires_at },
+                            ));
+                        }
+                    }
+                }
+                self.ban_list.add(ban);
+
+                let details = format!(
+                    "user {user_id:?}, IP range {ip_range:?}, until {expires_at:?}: {reason}"
+                );
+                let request = PostBanRequest {
+                    user_id,
+                    ip_range,
+                    reason,
+                    banned_by: admin_user_id,
+                    expires_at,
+                };
+                if let Some(req_tx) = &**self.persistence_req_tx {
+                    if let Err(err) = req_tx.send(PersistenceRequest::Ban(request)) {
+                        log::error!("Failed to send a persistence request: {:?}", err);
+                    }
+                }
+                details
+            }
+            AdminCommand::Unban(target) => {
+                let Some((user_id, ip_range)) =
+                    self.resolve_ban_target(&target, player_connections)
+                else {
+                    log::warn!("Ignoring an invalid unban target: {:?}", target);
+                    return format!("invalid target {target:?}");
+                };
+                let query = DeleteBansQuery { user_id, ip_range };
+                self.ban_list.remove(&query);
+
+                let details = format!("user {:?}, IP range {:?}", query.user_id, query.ip_range);
+                if let Some(req_tx) = &**self.persistence_req_tx {
+                    if let Err(err) = req_tx.send(PersistenceRequest::Unban(query)) {
+                        log::error!("Failed to send a persistence request: {:?}", err);
+                    }
+                }
+                details
+            }
+        }
+    }
+
+    /// Returns the user id and the IP range to ban. Players are banned by both
+    /// (guests only by their IP address).
+    fn resolve_ban_target(
+        &self,
+        target: &BanTarget,
+        player_connections: &PlayerConnections,
+    ) -> Option<(Option<i64>, Option<String>)> {
+        match target {
+            BanTarget::Player(player_net_id) => {
+                let handle = player_connections.get_value(*player_net_id)?;
+                let user_id = self.registered_users.get(&handle).copied();
+                let ip_range = self
+                    .net
+                    .connections
+                    .get(&handle)
+                    .and_then(|connection| connection.remote_address())
+                    .map(|address| address.ip().to_string());
+                (user_id.is_some() || ip_range.is_some()).then_some((user_id, ip_range))
+            }
+            BanTarget::User(user_id) => Some((Some(*user_id), None)),
+            BanTarget::IpRange(ip_range) => {
+                parse_ip_range(ip_range).map(|_| (None, Some(ip_range.clone())))
+            }
+        }
+    }
+}
+
+/// Permissions are checked for every command, as clients can send commands
+/// that their UI doesn't show. Executed commands get reported to the audit
+/// log.
+pub fn process_admin_commands_system(
+    mut admin_commands: ResMut<DeferredPlayerQueues<AdminCommand>>,
+    player_connections: Res<PlayerConnections>,
+    connected_admins: Res<ConnectedAdmins>,
+    agones: Option<Res<Agones>>,
+    mut targets: AdminCommandTargets,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for (player_net_id, commands) in admin_commands.drain() {
+        let Some(handle) = player_connections.get_value(player_net_id) else {
+            continue;
+        };
+        let permissions = connected_admins.get(&handle).copied().unwrap_or_default();
+        for command in commands {
+            if !permissions.allows(&command) {
+                log::warn!(
+                    "Ignoring an admin command from Player ({}), as it's not permitted: {:?}",
+                    player_net_id.0,
+                    command
+                );
+                continue;
+            }
+
+            log::info!(
+                "Executing an admin command from Player ({}): {:?}",
+                player_net_id.0,
+                command
+            );
+            let permission = command_permission(&command);
+            // Only registered users can have permissions, but the user may have just
+            // disconnected.
+            let user_id = targets.registered_users.get(&handle).copied();
+            let details = targets.execute(command, user_id, &player_connections);
+
+            let (Some(user_id), Some(req_tx)) = (user_id, &**targets.persistence_req_tx) else {
+                continue;
+            };
+            let request = PostAdminActionRequest {
+                user_id,
+                server_name: agones.as_ref().and_then(|agones| {
+                    agones
+                        .game_server
+                        .object_meta
+                        .as_ref()
+                        .map(|metadata| metadata.name.clone())
+                }),
+                permission,
+                details,
+            };
+            if let Err(err) = req_tx.send(PersistenceRequest::ReportAdminAction(request)) {
+                log::error!("Failed to send a persistence request: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Runners keep predicting their movement on the client side, but the server
+/// simulates them standing still until the game is resumed.
+pub fn freeze_paused_runners_system(
+    admin_pause: Res<AdminPause>,
+    mut runner_inputs: ResMut<DeferredPlayerQueues<RunnerInput>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if !admin_pause.0 {
+        return;
+    }
+    for (player_net_id, inputs) in runner_inputs.drain() {
+        for mut input in inputs {
+            input.direction = Vec2::ZERO;
+            runner_inputs.push(player_net_id, input);
+        }
+    }
+}
+
+pub fn send_admin_broadcasts_system(
+    mut net: NonSendMut<NetworkResource>,
+    connection_states: Res<ConnectionStates>,
+    mut admin_broadcasts: ResMut<AdminBroadcasts>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for message in admin_broadcasts.drain(..) {
+        broadcast_reliable_game_message(
+            &mut net,
+            &connection_states,
+            ReliableServerMessage::AdminBroadcast(message),
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::net::{PlayerConnections, PrivacyConsents, RegisteredUsers};
+use bevy::{
+    ecs::system::{Query, Res, ResMut, Resource},
+    math::Vec2,
+    prelude::With,
+    utils::HashMap,
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::components::{PlayerTag, Position},
+    messages::PlayerNetId,
+    registry::EntityRegistry,
+    SimulationTime,
+};
+
+/// Is a power of two, so that sampling stays regular when frame numbers wrap.
+pub const ANALYTICS_SAMPLE_PERIOD: u16 = 16;
+pub const HEATMAP_CELL_SIZE: f32 = 2.0;
+
+#[derive(Default, Debug)]
+pub struct Heatmap(pub HashMap<(i32, i32), u32>);
+
+impl Heatmap {
+    pub fn add(&mut self, position: Vec2) {
+        let cell = (position / HEATMAP_CELL_SIZE).floor();
+        *self.0.entry((cell.x as i32, cell.y as i32)).or_default() += 1;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceSample {
+    pub frame_number: FrameNumber,
+    pub position: Vec2,
+}
+
+/// Analytics collected during the current session, is meant to be drained by
+/// an uploader.
+#[derive(Resource, Default, Debug)]
+pub struct SessionAnalytics {
+    /// Movement traces of the players who allowed session recording.
+    pub traces: HashMap<i64, Vec<TraceSample>>,
+    /// Heatmaps attributed to the players who allowed analytics.
+    pub user_heatmaps: HashMap<i64, Heatmap>,
+    /// Samples of every player end up here. It doesn't carry any identity, so
+    /// it's the only thing that gets collected for guests and players who
+    /// haven't consented.
+    pub anonymous_heatmap: Heatmap,
+}
+
+pub fn collect_session_analytics_system(
+    time: Res<SimulationTime>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    player_connections: Res<PlayerConnections>,
+    registered_users: Res<RegisteredUsers>,
+    privacy_consents: Res<PrivacyConsents>,
+    players: Query<&Position, With<PlayerTag>>,
+    mut analytics: ResMut<SessionAnalytics>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if time.server_frame.value() % ANALYTICS_SAMPLE_PERIOD != 0 {
+        return;
+    }
+
+    for (player_net_id, entity) in player_registry.iter() {
+        let Some(position) = players
+            .get(*entity)
+            .ok()
+            .and_then(|position| position.buffer.get(time.server_frame))
+            .copied()
+        else {
+            continue;
+        };
+        // Practice bots don't have connections, and they shouldn't skew heatmaps.
+        let Some(handle) = player_connections.get_value(*player_net_id) else {
+            continue;
+        };
+        analytics.anonymous_heatmap.add(position);
+
+        let (Some(user_id), Some(consent)) =
+            (registered_users.get(&handle), privacy_consents.get(&handle))
+        else {
+            continue;
+        };
+
+        if consent.allow_session_recording {
+            analytics
+                .traces
+                .entry(*user_id)
+                .or_default()
+                .push(TraceSample {
+                    frame_number: time.server_frame,
+                    position,
+                });
+        }
+        if consent.allow_analytics {
+            analytics
+                .user_heatmaps
+                .entry(*user_id)
+                .or_default()
+                .add(position);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{persistence::PersistenceRequest, PersistenceRequestSender};
+use bevy::{
+    ecs::system::{Local, Res, Resource},
+    log,
+    utils::Instant,
+};
+use mr_messages_lib::{Ban, DeleteBansQuery};
+use std::{net::IpAddr, time::Duration};
+
+/// Bans issued on other servers (or by operators) get enforced within this
+/// period.
+const BAN_LIST_REFRESH_PERIOD_SECS: u64 = 60;
+
+/// A cache of the active bans, which is checked during handshakes, so that
+/// connecting clients don't have to wait for the persistence service.
+#[derive(Resource, Default)]
+pub struct BanList {
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    pub fn find(&self, user_id: Option<i64>, ip_addr: Option<IpAddr>) -> Option<&Ban> {
+        let now = chrono::Utc::now().naive_utc();
+        self.bans
+            .iter()
+            .find(|ban| !ban.is_expired(now) && ban.matches(user_id, ip_addr))
+    }
+
+    /// Keeps the cached list if the persistence service is unavailable.
+    pub fn receive(&mut self, result: Result<Vec<Ban>, String>) {
+        match result {
+            Ok(bans) => {
+                log::debug!("Received {} active bans", bans.len());
+                self.bans = bans;
+            }
+            Err(err) => log::warn!("Failed to refresh the ban list: {err}"),
+        }
+    }
+
+    /// Bans issued on this server are enforced right away, the list gets
+    /// replaced with the stored one once the persistence service confirms
+    /// them. Without the persistence service, they last until the server
+    /// restarts.
+    pub fn add(&mut self, ban: Ban) {
+        self.bans.push(ban);
+    }
+
+    /// Mirrors the persistence route: the IP range has to match exactly.
+    pub fn remove(&mut self, query: &DeleteBansQuery) {
+        self.bans.retain(|ban| {
+            !(query.user_id.is_some() && ban.user_id == query.user_id
+                || query.ip_range.is_some() && ban.ip_range == query.ip_range)
+        });
+    }
+}
+
+pub fn refresh_ban_list_system(
+    mut last_requested: Local<Option<Instant>>,
+    request_tx: Res<PersistenceRequestSender>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let Some(request_tx) = &**request_tx else {
+        return;
+    };
+    if last_requested.map_or(false, |last_requested| {
+        Instant::now().duration_since(last_requested)
+            < Duration::from_secs(BAN_LIST_REFRESH_PERIOD_SECS)
+    }) {
+        return;
+    }
+    *last_requested = Some(Instant::now());
+
+    if let Err(err) = request_tx.send(PersistenceRequest::GetBans) {
+        log::error!("Failed to send a persistence request: {:?}", err);
+    }
+}
@@ -0,0 +1,603 @@
+//! Server startup, which depends on the Agones sidecar, the Kubernetes API and
+//! the persistence service. Any of them may be briefly unavailable (when a
+//! node is under pressure, or the control plane is being upgraded), so instead
+//! of crashing the pod into a backoff loop, every stage is retried before the
+//! server gives up and reports the failure to Sentry.
+//!
+//! The stages run in the order of `BootstrapStage`, the ones that aren't
+//! needed (for instance, Agones stages when running locally) are skipped.
+//! A GameServer is marked as Ready only after the persistence service is
+//! discovered, so that a server that can't load levels never gets allocated.
+
+use crate::{
+    default_level,
+    level_watch::read_level_file,
+    net::{watch_agones_updates, FetchedLevelInfo},
+    persistence::{create_level, get_user, load_level, InitLevelData},
+    solo_practice::SoloPractice,
+    Agones, DrainSignal, MuddleServerConfig,
+};
+use anyhow::Context;
+use bevy::{app::App, log};
+use kube::Client;
+use mr_messages_lib::{
+    AllocationKind, InitLevel, LevelData, ServerVersion, ALLOCATION_KIND_ANNOTATION,
+    ALLOCATION_REQUEST_ID_ANNOTATION, ALLOCATION_TRACE_PARENT_ANNOTATION, SERVER_VERSION_KEY,
+};
+use mr_shared_lib::{game::PlayerEventSender, player::PlayerEvent};
+use mr_utils_lib::{kube_discovery, telemetry::TraceSpan};
+use reqwest::Url;
+use rymder::GameServer;
+use std::{path::PathBuf, time::Duration};
+
+const AGONES_CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootstrapStage {
+    ConnectAgones,
+    DiscoverPersistence,
+    MarkReady,
+    AwaitAllocation,
+    FetchLevel,
+}
+
+impl BootstrapStage {
+    const FIRST: Self = Self::ConnectAgones;
+
+    fn next(self) -> Option<Self> {
+        match self {
+            Self::ConnectAgones => Some(Self::DiscoverPersistence),
+            Self::DiscoverPersistence => Some(Self::MarkReady),
+            Self::MarkReady => Some(Self::AwaitAllocation),
+            Self::AwaitAllocation => Some(Self::FetchLevel),
+            Self::FetchLevel => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BootstrapStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::ConnectAgones => "connect_agones",
+            Self::DiscoverPersistence => "discover_persistence",
+            Self::MarkReady => "mark_ready",
+            Self::AwaitAllocation => "await_allocation",
+            Self::FetchLevel => "fetch_level",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Retries of a stage are delayed exponentially, so that a server doesn't
+/// hammer a struggling service.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Includes the first attempt.
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before the attempt that follows the failed `attempt`
+    /// (starting from 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+pub struct BootstrapOptions {
+    /// Is set when the server runs as an Agones GameServer.
+    pub agones_grpc_port: Option<u16>,
+    pub version: ServerVersion,
+    pub retry_policy: RetryPolicy,
+}
+
+#[derive(Debug)]
+pub struct BootstrapError {
+    pub stage: BootstrapStage,
+    pub attempts: u32,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Server bootstrap failed at the {} stage (attempts: {}): {:#}",
+            self.stage, self.attempts, self.source
+        )
+    }
+}
+
+impl std::error::Error for BootstrapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl BootstrapError {
+    /// Logs the error and sends it to Sentry, tagged with the failed stage.
+    pub fn report(&self) {
+        log::error!("{}", self);
+        sentry::with_scope(
+            |scope| {
+                scope.set_tag("bootstrap_stage", self.stage);
+                scope.set_extra("bootstrap_attempts", self.attempts.into());
+            },
+            || sentry::capture_message(&self.to_string(), sentry::Level::Fatal),
+        );
+    }
+}
+
+/// Failures of the services the server depends on are transient, while
+/// invalid configuration or requests won't get fixed by retrying.
+enum StageError {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+impl From<anyhow::Error> for StageError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Transient(err)
+    }
+}
+
+fn permanent(err: impl Into<anyhow::Error>) -> StageError {
+    StageError::Permanent(err.into())
+}
+
+/// GET requests are idempotent, so they're retried as long as they fail
+/// before the persistence service responds with an error.
+fn persistence_error(err: anyhow::Error) -> StageError {
+    if err.downcast_ref::<reqwest::Error>().is_some() {
+        StageError::Transient(err)
+    } else {
+        StageError::Permanent(err)
+    }
+}
+
+enum LevelSource {
+    File(PathBuf),
+    Default,
+    /// Requested via the env variables.
+    Request(LevelRequest),
+    /// Requested via the annotations of the allocated GameServer, gets
+    /// replaced with the request once the server is allocated.
+    Allocation,
+}
+
+impl LevelSource {
+    fn requires_persistence(&self) -> bool {
+        matches!(self, Self::Request(_) | Self::Allocation)
+    }
+}
+
+struct LevelRequest {
+    user_id: Option<i64>,
+    init_level: InitLevel,
+    kind: AllocationKind,
+    trace_parent: Option<String>,
+}
+
+struct Bootstrap {
+    options: BootstrapOptions,
+    level_source: LevelSource,
+    persistence_urls: Option<(Url, Url)>,
+    agones: Option<Agones>,
+    drain_signal: DrainSignal,
+    player_tracking_tx: Option<tokio::sync::mpsc::UnboundedSender<PlayerEvent>>,
+    level: Option<(Option<FetchedLevelInfo>, InitLevelData)>,
+    solo_practice: Option<SoloPractice>,
+}
+
+/// Runs the bootstrap stages and inserts the resources that
+/// `MuddleServerPlugin` expects: the level to load and, if running in Agones,
+/// the SDK client. Expects `MuddleServerConfig` to be inserted.
+pub async fn bootstrap(app: &mut App, options: BootstrapOptions) -> Result<(), BootstrapError> {
+    let config = app.world.resource::<MuddleServerConfig>();
+    let level_source =
+        read_level_source(config, options.agones_grpc_port.is_some()).map_err(|source| {
+            BootstrapError {
+                stage: BootstrapStage::FetchLevel,
+                attempts: 1,
+                source,
+            }
+        })?;
+    let persistence_urls = config
+        .public_persistence_url
+        .clone()
+        .zip(config.private_persistence_url.clone());
+
+    let mut bootstrap = Bootstrap {
+        options,
+        level_source,
+        persistence_urls,
+        agones: None,
+        drain_signal: DrainSignal::default(),
+        player_tracking_tx: None,
+        level: None,
+        solo_practice: None,
+    };
+    let mut stage = Some(BootstrapStage::FIRST);
+    while let Some(current_stage) = stage {
+        if bootstrap.is_required(current_stage) {
+            bootstrap.run_with_retries(current_stage).await?;
+        }
+        stage = current_stage.next();
+    }
+
+    if let Some((public_persistence_url, private_persistence_url)) = bootstrap.persistence_urls {
+        let mut config = app.world.resource_mut::<MuddleServerConfig>();
+        config.public_persistence_url = Some(public_persistence_url);
+        config.private_persistence_url = Some(private_persistence_url);
+    }
+    if let Some(agones) = bootstrap.agones {
+        app.insert_resource(agones);
+        app.insert_resource(bootstrap.drain_signal);
+    }
+    // Embedded servers may report player events on their own.
+    let has_player_event_sender = app.world.contains_resource::<PlayerEventSender>();
+    if bootstrap.player_tracking_tx.is_some() || !has_player_event_sender {
+        app.insert_resource(PlayerEventSender(bootstrap.player_tracking_tx));
+    }
+    let (fetched_level_info, init_level_data) =
+        bootstrap.level.expect("Expected the level to be fetched");
+    app.insert_resource(init_level_data);
+    if let Some(fetched_level_info) = fetched_level_info {
+        app.insert_resource(fetched_level_info);
+    }
+    if let Some(solo_practice) = bootstrap.solo_practice {
+        log::info!(
+            "Running a solo practice server for user {}",
+            solo_practice.user_id
+        );
+        app.insert_resource(solo_practice);
+    }
+    Ok(())
+}
+
+impl Bootstrap {
+    fn is_required(&self, stage: BootstrapStage) -> bool {
+        let is_agones = self.options.agones_grpc_port.is_some();
+        match stage {
+            BootstrapStage::ConnectAgones
+            | BootstrapStage::MarkReady
+            | BootstrapStage::AwaitAllocation => is_agones,
+            BootstrapStage::DiscoverPersistence => {
+                self.persistence_urls.is_none() && self.level_source.requires_persistence()
+            }
+            BootstrapStage::FetchLevel => true,
+        }
+    }
+
+    async fn run_with_retries(&mut self, stage: BootstrapStage) -> Result<(), BootstrapError> {
+        let policy = self.options.retry_policy;
+        let mut attempt = 1;
+        loop {
+            log::info!("Running the {stage} bootstrap stage (attempt {attempt})...");
+            let source = match self.run(stage).await {
+                Ok(()) => return Ok(()),
+                Err(StageError::Transient(err)) if attempt < policy.max_attempts => {
+                    let delay = policy.delay(attempt);
+                    log::warn!(
+                        "The {stage} bootstrap stage failed, retrying in {delay:?}: {err:#}"
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(StageError::Transient(err) | StageError::Permanent(err)) => err,
+            };
+            return Err(BootstrapError {
+                stage,
+                attempts: attempt,
+                source,
+            });
+        }
+    }
+
+    async fn run(&mut self, stage: BootstrapStage) -> Result<(), StageError> {
+        match stage {
+            BootstrapStage::ConnectAgones => self.connect_agones().await,
+            BootstrapStage::DiscoverPersistence => self.discover_persistence().await,
+            BootstrapStage::MarkReady => self.mark_ready().await,
+            BootstrapStage::AwaitAllocation => self.await_allocation().await,
+            BootstrapStage::FetchLevel => self.fetch_level().await,
+        }
+    }
+
+    async fn connect_agones(&mut self) -> Result<(), StageError> {
+        let grpc_port = self.options.agones_grpc_port;
+        log::info!("Connecting to Agones...");
+        let (sdk, game_server) =
+            rymder::Sdk::connect(grpc_port, Some(AGONES_CONNECT_TIMEOUT), None)
+                .await
+                .context("Failed to connect to Agones")?;
+
+        // The tasks are spawned only once the connection succeeds, so that
+        // retrying doesn't duplicate them.
+        if let Some(health_spec) = &game_server.health_spec {
+            let health_check = sdk.health_check();
+            let period = health_spec.period;
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    log::debug!("Sending Health check message...");
+                    if let Err(err) = health_check.send(()).await {
+                        log::error!("Failed to send Health message: {:?}", err);
+                    }
+                }
+            });
+        }
+
+        let (player_tracking_tx, mut player_tracking_rx) =
+            tokio::sync::mpsc::unbounded_channel::<PlayerEvent>();
+        let mut player_tracking_client = sdk.clone();
+        tokio::spawn(async move {
+            while let Some(player_event) = player_tracking_rx.recv().await {
+                let result = match player_event {
+                    PlayerEvent::Connected(uuid) => {
+                        log::info!("Sending PlayerConnect event ({})...", uuid);
+                        player_tracking_client.player_connect(uuid).await
+                    }
+                    PlayerEvent::Disconnected(uuid) => {
+                        log::info!("Sending PlayerDisconnect event ({})...", uuid);
+                        player_tracking_client.player_disconnect(uuid).await
+                    }
+                };
+                if let Err(err) = result {
+                    log::error!("Failed to report a player event to Agones: {:?}", err);
+                }
+            }
+            log::warn!("Player tracking channel is closed");
+        });
+
+        self.player_tracking_tx = Some(player_tracking_tx);
+        self.agones = Some(Agones { sdk, game_server });
+        Ok(())
+    }
+
+    async fn discover_persistence(&mut self) -> Result<(), StageError> {
+        // Unlike the API calls, the client is created from the local config,
+        // which doesn't get fixed by retrying.
+        let client = Client::try_default().await.map_err(|err| {
+            permanent(anyhow::Error::new(err).context(
+                "Persistence URLs aren't configured, and the Kubernetes environment isn't detected",
+            ))
+        })?;
+        let persistence_urls = kube_discovery::discover_persistence(client)
+            .await
+            .context("Failed to discover the persistence service")?;
+        self.persistence_urls = Some(persistence_urls);
+        Ok(())
+    }
+
+    async fn mark_ready(&mut self) -> Result<(), StageError> {
+        let sdk = &mut self.agones.as_mut().unwrap().sdk;
+        let version = self.options.version.to_string();
+        log::info!("Setting the GameServer version to {version}...");
+        sdk.set_annotation(SERVER_VERSION_KEY, version.clone())
+            .await
+            .context("Failed to set the GameServer version annotation")?;
+        sdk.set_label(SERVER_VERSION_KEY, version)
+            .await
+            .context("Failed to set the GameServer version label")?;
+
+        log::info!("Marking the GameServer as Ready...");
+        sdk.mark_ready()
+            .await
+            .context("Failed to mark the GameServer as Ready")?;
+        Ok(())
+    }
+
+    async fn await_allocation(&mut self) -> Result<(), StageError> {
+        let sdk = self.agones.as_ref().unwrap().sdk.clone();
+        // The watch is started anew on every attempt, as the sender gets
+        // dropped only if the previous watch has failed.
+        let game_server = watch_agones_updates(sdk, self.drain_signal.clone())
+            .await
+            .context("GameServer watch has stopped before the allocation")?;
+
+        // Correlates the logs with the matchmaker's allocation audit.
+        let request_id = game_server
+            .object_meta
+            .as_ref()
+            .and_then(|metadata| metadata.annotations.get(ALLOCATION_REQUEST_ID_ANNOTATION));
+        if let Some(request_id) = request_id {
+            log::info!("Allocated for request {request_id}");
+            sentry::configure_scope(|scope| scope.set_tag("request_id", request_id));
+        }
+        // Retrying won't help if the matchmaker has requested an invalid level.
+        let request = read_allocation_level_request(&game_server).map_err(permanent)?;
+        self.level_source = LevelSource::Request(request);
+        Ok(())
+    }
+
+    async fn fetch_level(&mut self) -> Result<(), StageError> {
+        let request = match &self.level_source {
+            LevelSource::File(level_file) => {
+                log::info!("Loading the level from a file: {}", level_file.display());
+                let level = read_level_file(level_file)
+                    .context("Failed to read the level file")
+                    .map_err(permanent)?;
+                self.level = Some((None, InitLevelData(level)));
+                return Ok(());
+            }
+            LevelSource::Default => {
+                self.level = Some((None, InitLevelData(default_level())));
+                return Ok(());
+            }
+            LevelSource::Request(request) => request,
+            LevelSource::Allocation => unreachable!("Expected the GameServer to be allocated"),
+        };
+        if request.kind == AllocationKind::SoloPractice {
+            let user_id = request.user_id.ok_or_else(|| {
+                permanent(anyhow::Error::msg(
+                    "Expected `user_id` when solo practice is requested",
+                ))
+            })?;
+            self.solo_practice = Some(SoloPractice { user_id });
+        }
+        let (public_persistence_url, private_persistence_url) =
+            self.persistence_urls.clone().ok_or_else(|| {
+                permanent(anyhow::Error::msg(
+                    "Expected persistence URLs when booting from the Agones environment or requesting a level via the env variables",
+                ))
+            })?;
+
+        let span = TraceSpan::start("init_level_data", request.trace_parent.as_deref());
+        let (get_level_response, init_level_data) = match &request.init_level {
+            InitLevel::Existing(id) => {
+                load_level(public_persistence_url, *id, &span.child("load_level"))
+                    .await
+                    .context("Failed to load the level")
+                    .map_err(persistence_error)?
+            }
+            InitLevel::Create { title, parent_id } => {
+                let user_id = request.user_id.ok_or_else(|| {
+                    permanent(anyhow::Error::msg(
+                        "Expected `user_id` when creating a new level is requested",
+                    ))
+                })?;
+                let user = get_user(public_persistence_url, user_id, &span.child("get_user"))
+                    .await
+                    .context("Failed to get user info")
+                    .map_err(persistence_error)?;
+                let level_data = match parent_id {
+                    Some(parent_id) => LevelData::Forked {
+                        parent_id: *parent_id,
+                    },
+                    None => LevelData::Data {
+                        data: serde_json::to_value(default_level()).unwrap(),
+                    },
+                };
+                let level_response = create_level(
+                    private_persistence_url,
+                    user_id,
+                    user.display_name,
+                    title.clone(),
+                    level_data,
+                    &span.child("create_level"),
+                )
+                .await
+                .map_err(|err| {
+                    // Retrying is safe only if the request hasn't reached the
+                    // persistence service, otherwise we may create the level
+                    // twice.
+                    let is_connect_error = err
+                        .downcast_ref::<reqwest::Error>()
+                        .map_or(false, reqwest::Error::is_connect);
+                    let err = err.context("Failed to create a level");
+                    if is_connect_error {
+                        StageError::Transient(err)
+                    } else {
+                        StageError::Permanent(err)
+                    }
+                })?;
+                let level = serde_json::from_value(level_response.level.data.clone())
+                    .context("Failed to parse the created level")
+                    .map_err(permanent)?;
+                (level_response, InitLevelData(level))
+            }
+        };
+        self.level = Some((Some(FetchedLevelInfo(get_level_response)), init_level_data));
+        Ok(())
+    }
+}
+
+fn read_level_source(config: &MuddleServerConfig, is_agones: bool) -> anyhow::Result<LevelSource> {
+    if let Some(level_file) = &config.level_file {
+        return Ok(LevelSource::File(level_file.clone()));
+    }
+    if is_agones {
+        return Ok(LevelSource::Allocation);
+    }
+
+    let user_id = mr_utils_lib::var!("MUDDLE_USER_ID");
+    let title = mr_utils_lib::var!("MUDDLE_LEVEL_TITLE");
+    let parent_id = mr_utils_lib::var!("MUDDLE_LEVEL_PARENT_ID");
+    let level_id = mr_utils_lib::var!("MUDDLE_LEVEL_ID");
+    if user_id.is_some() || title.is_some() || parent_id.is_some() || level_id.is_some() {
+        let (user_id, init_level) = read_env_level_data(user_id, title, parent_id, level_id)?;
+        Ok(LevelSource::Request(LevelRequest {
+            user_id,
+            init_level,
+            kind: read_allocation_kind(mr_utils_lib::var!("MUDDLE_ALLOCATION_KIND"))?,
+            trace_parent: None,
+        }))
+    } else {
+        Ok(LevelSource::Default)
+    }
+}
+
+fn read_allocation_level_request(game_server: &GameServer) -> anyhow::Result<LevelRequest> {
+    let metadata = game_server
+        .object_meta
+        .as_ref()
+        .context("Expected GameServer metadata")?;
+    let annotation = |key: &str| metadata.annotations.get(key).cloned();
+    let (user_id, init_level) = read_env_level_data(
+        annotation("user_id"),
+        annotation("level_title"),
+        annotation("level_parent_id"),
+        annotation("level_id"),
+    )?;
+    Ok(LevelRequest {
+        user_id,
+        init_level,
+        kind: read_allocation_kind(annotation(ALLOCATION_KIND_ANNOTATION))?,
+        trace_parent: annotation(ALLOCATION_TRACE_PARENT_ANNOTATION),
+    })
+}
+
+/// Allocations made by older matchmakers don't have the annotation.
+fn read_allocation_kind(kind: Option<String>) -> anyhow::Result<AllocationKind> {
+    kind.map(|kind| {
+        kind.parse()
+            .map_err(anyhow::Error::msg)
+            .context("Failed to parse `allocation_kind`")
+    })
+    .transpose()
+    .map(Option::unwrap_or_default)
+}
+
+fn read_env_level_data(
+    user_id: Option<String>,
+    title: Option<String>,
+    parent_id: Option<String>,
+    level_id: Option<String>,
+) -> anyhow::Result<(Option<i64>, InitLevel)> {
+    let user_id = user_id
+        .map(|user_id| user_id.parse().context("Failed to parse `user_id`"))
+        .transpose()?;
+    let init_level = if let Some(title) = title {
+        InitLevel::Create {
+            title,
+            parent_id: parent_id
+                .map(|id| id.parse().context("Failed to parse `level_parent_id`"))
+                .transpose()?,
+        }
+    } else {
+        let level_id = level_id
+            .context("Expected a `level_id` annotation or `level_title` one for a new level (`MUDDLE_LEVEL_ID` or `MUDDLE_LEVEL_TITLE` env vars respectively)")?
+            .parse()
+            .context("Failed to parse `level_id`")?;
+        InitLevel::Existing(level_id)
+    };
+    Ok((user_id, init_level))
+}
@@ -0,0 +1,315 @@
+use crate::player_updates::SERVER_UPDATES_LIMIT;
+use bevy::{
+    ecs::{
+        query::With,
+        system::{Query, Res, ResMut, Resource, SystemParam},
+    },
+    log,
+    math::Vec2,
+    utils::HashMap,
+};
+use mr_messages_lib::PLAYER_CAPACITY;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        commands::{self, DeferredPlayerQueues, DeferredQueue, DespawnReason},
+        components::{rotate, PlayerTag, Position, Spawned},
+        level::LevelParams,
+        navigation::WalkabilityGraph,
+    },
+    messages::{BotDifficulty, PlayerNetId, PracticeBotsRequest},
+    player::{Player, PlayerDirectionUpdate, PlayerRole, PlayerUpdates, Players},
+    registry::EntityRegistry,
+    server::level_spawn_location_service::LevelSpawnLocationService,
+    GameTime, SimulationTime, SIMULATIONS_PER_SECOND,
+};
+use rand::Rng;
+use std::ops::Range;
+
+/// Bots don't have connections, so their ids are reserved in
+/// `PlayerConnections` to avoid collisions with the ids of real players.
+pub const BOT_NET_IDS: Range<u16> = (u16::MAX - PLAYER_CAPACITY)..u16::MAX;
+
+/// Levels with moving objects need the walkability graph to be rebuilt every
+/// once in a while, as platforms move away and hazards move in.
+const MOVING_OBJECTS_GRAPH_TTL: u16 = SIMULATIONS_PER_SECOND as u16 / 2;
+
+struct DifficultyPreset {
+    /// How often a bot reconsiders its direction.
+    reaction_frames: u16,
+    /// The max deviation from the optimal direction, in radians.
+    aim_error: f32,
+    /// The chance of a bot standing still until its next decision.
+    hesitation_chance: f64,
+    /// How many cells of the path a bot looks ahead, bigger values make bots
+    /// cut corners.
+    lookahead: usize,
+}
+
+fn difficulty_preset(difficulty: BotDifficulty) -> DifficultyPreset {
+    match difficulty {
+        BotDifficulty::Easy => DifficultyPreset {
+            reaction_frames: 30,
+            aim_error: 0.5,
+            hesitation_chance: 0.1,
+            lookahead: 1,
+        },
+        BotDifficulty::Normal => DifficultyPreset {
+            reaction_frames: 15,
+            aim_error: 0.25,
+            hesitation_chance: 0.03,
+            lookahead: 2,
+        },
+        BotDifficulty::Hard => DifficultyPreset {
+            reaction_frames: 5,
+            aim_error: 0.05,
+            hesitation_chance: 0.0,
+            lookahead: 3,
+        },
+    }
+}
+
+struct Bot {
+    difficulty: BotDifficulty,
+    direction: Vec2,
+    next_decision_at: FrameNumber,
+}
+
+struct CachedGraph {
+    level_revision: u64,
+    built_at: FrameNumber,
+    has_moving_objects: bool,
+    graph: WalkabilityGraph,
+}
+
+#[derive(Resource, Default)]
+pub struct PracticeBots {
+    bots: HashMap<PlayerNetId, Bot>,
+    /// Bots that clients haven't been told about yet.
+    pub spawned: Vec<PlayerNetId>,
+    /// Bots that have to be announced as disconnected.
+    pub removed: Vec<PlayerNetId>,
+    graph: Option<CachedGraph>,
+}
+
+impl PracticeBots {
+    fn remove_all(
+        &mut self,
+        time: &GameTime,
+        players: &mut Players,
+        despawn_player_commands: &mut DeferredQueue<commands::DespawnPlayer>,
+    ) {
+        for (bot_net_id, _) in self.bots.drain() {
+            log::info!("Removing a practice bot ({})", bot_net_id.0);
+            despawn_player_commands.push(commands::DespawnPlayer {
+                net_id: bot_net_id,
+                frame_number: time.frame_number,
+                reason: DespawnReason::Disconnect,
+            });
+            if let Some(player) = players.get_mut(&bot_net_id) {
+                player.is_connected = false;
+                player.respawning_at = None;
+            }
+            // Clients that haven't been told about a bot don't need to know it's gone.
+            if let Some(i) = self.spawned.iter().position(|id| *id == bot_net_id) {
+                self.spawned.remove(i);
+            } else {
+                self.removed.push(bot_net_id);
+            }
+        }
+        self.graph = None;
+    }
+}
+
+#[derive(SystemParam)]
+pub struct BotPlayerParams<'w, 's> {
+    players: Res<'w, Players>,
+    players_registry: Res<'w, EntityRegistry<PlayerNetId>>,
+    player_entities: Query<'w, 's, (&'static Position, &'static Spawned), With<PlayerTag>>,
+}
+
+impl<'w, 's> BotPlayerParams<'w, 's> {
+    /// Returns `None` if a bot isn't running at the moment, i.e. it's waiting
+    /// to be (re)spawned.
+    fn runner_position(&self, net_id: PlayerNetId, frame_number: FrameNumber) -> Option<Vec2> {
+        let player = self.players.get(&net_id)?;
+        if player.role != PlayerRole::Runner || player.respawning_at.is_some() {
+            return None;
+        }
+        let entity = self.players_registry.get_entity(net_id)?;
+        let (position, spawned) = self.player_entities.get(entity).ok()?;
+        if !spawned.is_spawned(frame_number) {
+            return None;
+        }
+        position
+            .buffer
+            .get_with_extrapolation(frame_number)
+            .map(|(_, position)| *position)
+    }
+}
+
+/// Bots are allowed only in practice sessions, they get removed as soon as
+/// another player joins (or the only one leaves).
+pub fn process_practice_bots_requests_system(
+    time: Res<GameTime>,
+    mut requests: ResMut<DeferredPlayerQueues<PracticeBotsRequest>>,
+    mut practice_bots: ResMut<PracticeBots>,
+    mut players: ResMut<Players>,
+    mut spawn_player_commands: ResMut<DeferredQueue<commands::SpawnPlayer>>,
+    mut despawn_player_commands: ResMut<DeferredQueue<commands::DespawnPlayer>>,
+    level_spawn_location_service: LevelSpawnLocationService,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let is_practice_session = players.is_practice_session();
+    if !is_practice_session && !practice_bots.bots.is_empty() {
+        practice_bots.remove_all(&time, &mut players, &mut despawn_player_commands);
+    }
+
+    for (player_net_id, player_requests) in requests.drain() {
+        if !is_practice_session {
+            log::warn!(
+                "Ignoring Player ({}) practice bots requests: not a practice session",
+                player_net_id.0
+            );
+            continue;
+        }
+
+        for request in player_requests {
+            match request {
+                PracticeBotsRequest::Spawn(difficulty) => {
+                    let connected_players = players
+                        .values()
+                        .filter(|player| player.is_connected)
+                        .count();
+                    // Ids of the removed bots may still be taken by players that wait
+                    // to be despawned.
+                    let free_net_id = BOT_NET_IDS
+                        .map(PlayerNetId)
+                        .find(|net_id| !players.contains_key(net_id));
+                    let Some(bot_net_id) =
+                        free_net_id.filter(|_| connected_players < PLAYER_CAPACITY as usize)
+                    else {
+                        log::warn!(
+                            "Ignoring Player ({}) request to spawn a bot: the server is full",
+                            player_net_id.0
+                        );
+                        break;
+                    };
+
+                    log::info!(
+                        "Spawning a practice bot ({}, {:?})",
+                        bot_net_id.0,
+                        difficulty
+                    );
+                    players.insert(
+                        bot_net_id,
+                        Player {
+                            uuid: uuid::Uuid::new_v4().to_string(),
+                            is_bot: true,
+                            ..Player::new_with_nickname(
+                                PlayerRole::Runner,
+                                format!("{} Bot", difficulty.label()),
+                            )
+                        },
+                    );
+                    spawn_player_commands.push(commands::SpawnPlayer {
+                        net_id: bot_net_id,
+                        start_position: level_spawn_location_service
+                            .spawn_position(time.frame_number),
+                        is_player_frame_simulated: false,
+                    });
+                    practice_bots.bots.insert(
+                        bot_net_id,
+                        Bot {
+                            difficulty,
+                            direction: Vec2::ZERO,
+                            next_decision_at: time.frame_number,
+                        },
+                    );
+                    practice_bots.spawned.push(bot_net_id);
+                }
+                PracticeBotsRequest::RemoveAll => {
+                    practice_bots.remove_all(&time, &mut players, &mut despawn_player_commands);
+                }
+            }
+        }
+    }
+}
+
+/// Bots write their inputs directly to `PlayerUpdates`, so they are simulated
+/// and broadcasted the same way as inputs of real players.
+pub fn drive_practice_bots_system(
+    time: Res<GameTime>,
+    simulation_time: Res<SimulationTime>,
+    mut practice_bots: ResMut<PracticeBots>,
+    bot_players: BotPlayerParams,
+    level_params: LevelParams,
+    mut updates: ResMut<PlayerUpdates>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if practice_bots.bots.is_empty() {
+        return;
+    }
+
+    let level_revision = level_params.level_state.revision();
+    let is_graph_outdated = practice_bots.graph.as_ref().map_or(true, |cached| {
+        cached.level_revision != level_revision
+            || (cached.has_moving_objects
+                && time.frame_number
+                    >= cached.built_at + FrameNumber::new(MOVING_OBJECTS_GRAPH_TTL))
+    });
+    if is_graph_outdated {
+        let objects = level_params.level_state.objects();
+        let graph = WalkabilityGraph::build(objects.values().filter_map(|level_object| {
+            let position = level_params.predict_object_position(
+                level_object.net_id,
+                simulation_time.server_generation,
+                time.frame_number,
+            )?;
+            Some((level_object, position))
+        }));
+        practice_bots.graph = Some(CachedGraph {
+            level_revision,
+            built_at: time.frame_number,
+            has_moving_objects: objects.values().any(|object| object.route.is_some()),
+            graph,
+        });
+    }
+
+    let practice_bots = &mut *practice_bots;
+    let graph = &practice_bots.graph.as_ref().unwrap().graph;
+    let mut rng = rand::thread_rng();
+    for (bot_net_id, bot) in practice_bots.bots.iter_mut() {
+        if time.frame_number >= bot.next_decision_at {
+            let preset = difficulty_preset(bot.difficulty);
+            bot.next_decision_at = time.frame_number + FrameNumber::new(preset.reaction_frames);
+            bot.direction = match bot_players.runner_position(*bot_net_id, time.frame_number) {
+                Some(_) if rng.gen_bool(preset.hesitation_chance) => Vec2::ZERO,
+                Some(position) => graph
+                    .direction_to_finish(position, preset.lookahead)
+                    .map_or(Vec2::ZERO, |direction| {
+                        rotate(
+                            direction,
+                            rng.gen_range(-preset.aim_error..=preset.aim_error),
+                        )
+                    }),
+                None => Vec2::ZERO,
+            };
+        }
+
+        let updates =
+            updates.get_direction_mut(*bot_net_id, time.frame_number, SERVER_UPDATES_LIMIT);
+        if updates.get(time.frame_number).is_none() && updates.can_insert(time.frame_number) {
+            updates.insert(
+                time.frame_number,
+                Some(PlayerDirectionUpdate {
+                    direction: bot.direction,
+                    is_processed_client_input: None,
+                }),
+            );
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::net::{
+    broadcast_reliable_game_message, send_reliable_game_message, ConnectionStates,
+    PlayerConnections,
+};
+use bevy::{
+    ecs::system::{Local, NonSendMut, Res, ResMut},
+    log,
+};
+use bevy_disturbulence::NetworkResource;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        commands::DeferredPlayerQueues,
+        determinism::{
+            DeterminismGuard, StateHashMessage, StateHashRequest, STATE_HASH_CHECKPOINT_INTERVAL,
+        },
+    },
+    messages::ReliableServerMessage,
+    net::ConnectionStatus,
+    SimulationTime,
+};
+
+/// Broadcasts checkpoint hashes for clients to compare their simulation
+/// against, and answers the requests of clients bisecting a divergence.
+pub fn send_state_hashes_system(
+    mut last_checkpoint: Local<Option<FrameNumber>>,
+    time: Res<SimulationTime>,
+    guard: Res<DeterminismGuard>,
+    mut requests: ResMut<DeferredPlayerQueues<StateHashRequest>>,
+    mut net: NonSendMut<NetworkResource>,
+    connection_states: Res<ConnectionStates>,
+    player_connections: Res<PlayerConnections>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if !guard.enabled {
+        requests.drain();
+        return;
+    }
+
+    // The system runs after the frame number has been incremented.
+    let frame_number = time.prev_frame().server_frame;
+    let checkpoint =
+        frame_number - FrameNumber::new(frame_number.value() % STATE_HASH_CHECKPOINT_INTERVAL);
+    if *last_checkpoint != Some(checkpoint) {
+        if let Some(hash) = guard.final_hash(checkpoint) {
+            *last_checkpoint = Some(checkpoint);
+            broadcast_reliable_game_message(
+                &mut net,
+                &connection_states,
+                ReliableServerMessage::StateHash(StateHashMessage::Checkpoint {
+                    frame_number: checkpoint,
+                    hash,
+                }),
+            );
+        }
+    }
+
+    for (player_net_id, requests) in requests.drain() {
+        let Some(connection_handle) = player_connections.get_value(player_net_id) else {
+            continue;
+        };
+        let Some(connection_state) = connection_states.get(&connection_handle) else {
+            continue;
+        };
+        if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+            continue;
+        }
+        for request in requests {
+            log::info!(
+                "Player ({}) is bisecting a divergence: {:?}",
+                player_net_id.0,
+                request
+            );
+            let response = match request {
+                StateHashRequest::Stages { from, to } => Some(guard.stage_hashes(from, to))
+                    .filter(|hashes| !hashes.is_empty())
+                    .map(StateHashMessage::Stages),
+                StateHashRequest::Entities {
+                    frame_number,
+                    stage,
+                } => guard.entity_hashes(frame_number, stage).map(|entities| {
+                    StateHashMessage::Entities {
+                        frame_number,
+                        stage,
+                        entities,
+                    }
+                }),
+            };
+            send_reliable_game_message(
+                &mut net,
+                connection_handle,
+                connection_state,
+                ReliableServerMessage::StateHash(response.unwrap_or(StateHashMessage::Expired)),
+            );
+        }
+    }
+}
@@ -1,69 +1,245 @@
-use bevy::ecs::{
-    event::EventReader,
-    system::{Res, ResMut},
+use crate::{persistence::PlayerStatsRecorder, solo_practice::SoloPractice};
+use bevy::{
+    ecs::{
+        entity::Entity,
+        event::EventReader,
+        query::{Changed, With},
+        system::{Query, Res, ResMut, Resource, SystemParam},
+    },
+    log,
+    math::Vec2,
+    prelude::{Deref, DerefMut},
+    utils::{HashMap, HashSet},
 };
 use mr_shared_lib::{
     framebuffer::FrameNumber,
     game::{
         commands,
-        commands::{DeferredQueue, DespawnPlayer, DespawnReason},
+        commands::{DeferredPlayerQueues, DeferredQueue, DespawnPlayer, DespawnReason},
+        components::{PlayerTag, Spawned},
         events::{PlayerDeath, PlayerFinish},
+        game_mode::CurrentGameMode,
+        level::LevelState,
+    },
+    messages::{
+        DeferredMessagesQueue, FinishResult, PlayerNetId, PracticeCheckpoint, RespawnPlayer,
+        RespawnPlayerReason, TeleportRequest,
     },
-    messages::{DeferredMessagesQueue, RespawnPlayer, RespawnPlayerReason},
-    player::{PlayerSystemParamsMut, Players},
+    player::{Player, PlayerRole, PlayerSystemParamsMut, Players},
+    registry::EntityRegistry,
     server::level_spawn_location_service::LevelSpawnLocationService,
-    util::PLAYER_RESPAWN_TIME,
+    util::PLAYER_CHECKPOINT_RESTART_TIME,
     SimulationTime,
 };
+use std::marker::PhantomData;
+
+/// Positions of the players that are going to be respawned at their practice
+/// checkpoints instead of the level spawn location.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct CheckpointRestarts(pub HashMap<PlayerNetId, Vec2>);
+
+struct RunStart {
+    spawned_at: FrameNumber,
+    /// Is `None` if the run isn't timed.
+    started_at: Option<u64>,
+}
+
+/// Finish times are measured from the moment a player gets (re)spawned.
+#[derive(Resource, Default)]
+pub struct RunStarts {
+    runs: HashMap<PlayerNetId, RunStart>,
+    /// Players that are going to be respawned at their practice checkpoints,
+    /// such runs aren't timed.
+    pending_checkpoint_restarts: HashSet<PlayerNetId>,
+    /// Absolute frames of the deaths that discarded players' checkpoints (if
+    /// the level is set up to do so).
+    checkpoints_reset_at: HashMap<PlayerNetId, u64>,
+}
+
+#[derive(SystemParam)]
+pub struct FinishTiming<'w, 's> {
+    run_starts: ResMut<'w, RunStarts>,
+    level_state: Res<'w, LevelState>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> FinishTiming<'w, 's> {
+    /// Returns `None` if the run isn't timed or has already been finished.
+    fn finish(&mut self, net_id: PlayerNetId, time: &SimulationTime) -> Option<FinishResult> {
+        let started_at = self.run_starts.runs.get_mut(&net_id)?.started_at.take()?;
+        let frames = absolute_frame(time).saturating_sub(started_at);
+        let mut result = FinishResult {
+            frames: frames.try_into().unwrap_or(u32::MAX),
+            medal: None,
+        };
+        result.medal = self
+            .level_state
+            .settings()
+            .medal_times
+            .and_then(|medal_times| medal_times.classify(result.secs()));
+        Some(result)
+    }
+}
+
+fn absolute_frame(time: &SimulationTime) -> u64 {
+    (time.server_generation << 16) + u64::from(time.server_frame.value())
+}
+
+/// Converts a frame number that is less than a half of the counter range away
+/// from the current server frame to an absolute one.
+fn absolute_frame_of(time: &SimulationTime, frame_number: FrameNumber) -> u64 {
+    let offset = frame_number.value().wrapping_sub(time.server_frame.value()) as i16;
+    absolute_frame(time).saturating_add_signed(i64::from(offset))
+}
+
+/// Any spawn of a player (connecting, switching roles, respawning) starts a
+/// new run.
+pub fn track_run_starts_system(
+    time: Res<SimulationTime>,
+    mut run_starts: ResMut<RunStarts>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    spawned_players: Query<(Entity, &Spawned), (With<PlayerTag>, Changed<Spawned>)>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for (entity, spawned) in spawned_players.iter() {
+        let (Some(spawned_at), Some(net_id)) =
+            (spawned.spawned_at(), player_registry.get_id(entity))
+        else {
+            continue;
+        };
+        if run_starts
+            .runs
+            .get(&net_id)
+            .map_or(false, |run| run.spawned_at == spawned_at)
+        {
+            continue;
+        }
+
+        let is_checkpoint_restart = run_starts.pending_checkpoint_restarts.remove(&net_id);
+        let started_at = absolute_frame(&time)
+            .saturating_sub(u64::from((time.server_frame - spawned_at).value()));
+        run_starts.runs.insert(
+            net_id,
+            RunStart {
+                spawned_at,
+                started_at: (!is_checkpoint_restart).then_some(started_at),
+            },
+        );
+    }
+}
+
+/// Finishes and deaths count towards the lifetime stats of registered users
+/// and towards the current match.
+#[derive(SystemParam)]
+pub struct PlayerEventRecorders<'w, 's> {
+    player_stats_recorder: PlayerStatsRecorder<'w, 's>,
+    current_game_mode: ResMut<'w, CurrentGameMode>,
+}
+
+impl<'w, 's> PlayerEventRecorders<'w, 's> {
+    fn record(
+        &mut self,
+        net_id: PlayerNetId,
+        reason: RespawnPlayerReason,
+        finish: Option<FinishResult>,
+    ) {
+        self.player_stats_recorder.record(net_id, reason);
+        self.current_game_mode
+            .current_match
+            .record(net_id, reason, finish);
+    }
+}
+
+#[derive(SystemParam)]
+pub struct RespawnQueues<'w, 's> {
+    respawn_player_messages: ResMut<'w, DeferredMessagesQueue<RespawnPlayer>>,
+    despawn_players_commands: ResMut<'w, DeferredQueue<commands::DespawnPlayer>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
 
 pub fn process_player_events_system(
     time: Res<SimulationTime>,
     mut player_finish_events: EventReader<PlayerFinish>,
     mut player_death_events: EventReader<PlayerDeath>,
     mut player_params: PlayerSystemParamsMut,
-    mut respawn_player_messages_queue: ResMut<DeferredMessagesQueue<RespawnPlayer>>,
-    mut despawn_players_commands: ResMut<DeferredQueue<commands::DespawnPlayer>>,
+    mut finish_timing: FinishTiming,
+    mut player_event_recorders: PlayerEventRecorders,
+    mut respawn_queues: RespawnQueues,
 ) {
-    let respawn_at = time.server_frame + PLAYER_RESPAWN_TIME;
+    let respawn_settings = finish_timing.level_state.settings().respawns;
 
     let mut respawns = Vec::new();
-    respawns.extend(
-        player_finish_events
-            .iter()
-            .map(|PlayerFinish(player_entity)| (player_entity, RespawnPlayerReason::Finish)),
-    );
-    respawns.extend(
-        player_death_events
-            .iter()
-            .map(|PlayerDeath(player_entity)| (player_entity, RespawnPlayerReason::Death)),
-    );
-
-    for (player_entity, reason) in respawns.into_iter() {
+    respawns.extend(player_finish_events.iter().map(|event| {
+        (
+            event.player_entity,
+            event.level_object_net_id,
+            RespawnPlayerReason::Finish,
+        )
+    }));
+    respawns.extend(player_death_events.iter().map(|event| {
+        (
+            event.player_entity,
+            event.level_object_net_id,
+            RespawnPlayerReason::Death,
+        )
+    }));
+
+    for (player_entity, level_object_net_id, reason) in respawns.into_iter() {
         let net_id = player_params
             .player_registry
-            .get_id(*player_entity)
+            .get_id(player_entity)
             .expect("Expected a registered player for a Finish event");
 
         let player = player_params
             .players
             .get_mut(&net_id)
             .expect("Expected a registered player for a Finish event");
+        let respawn_at = time.server_frame + respawn_settings.delay(reason);
         player.respawning_at = Some((respawn_at, reason));
+        let mut finish = None;
         match reason {
             RespawnPlayerReason::Finish => {
                 player.finishes += 1;
+                finish = finish_timing.finish(net_id, &time);
+                if let Some(finish) = finish {
+                    log::info!(
+                        "Player ({}) has finished in {:.2}s ({:?})",
+                        net_id.0,
+                        finish.secs(),
+                        finish.medal
+                    );
+                    player.record_finish(finish);
+                }
             }
             RespawnPlayerReason::Death => {
+                log::debug!(
+                    "Player ({}) has died (level object: {:?})",
+                    net_id.0,
+                    level_object_net_id
+                );
                 player.deaths += 1;
+                if respawn_settings.deaths_reset_checkpoints {
+                    finish_timing
+                        .run_starts
+                        .checkpoints_reset_at
+                        .insert(net_id, absolute_frame(&time));
+                }
             }
+            RespawnPlayerReason::Checkpoint => {}
         }
+        player_event_recorders.record(net_id, reason, finish);
 
-        respawn_player_messages_queue.push(RespawnPlayer {
+        respawn_queues.respawn_player_messages.push(RespawnPlayer {
             net_id,
             reason,
             frame_number: respawn_at,
+            finish,
         });
-        despawn_players_commands.push(DespawnPlayer {
+        respawn_queues.despawn_players_commands.push(DespawnPlayer {
             net_id,
             frame_number: time.server_frame + FrameNumber::new(1),
             reason: DespawnReason::DeathOrFinish,
@@ -71,18 +247,175 @@ pub fn process_player_events_system(
     }
 }
 
+/// Restarting from a checkpoint is basically a quick respawn at the checkpoint
+/// position. It's allowed only in practice sessions (i.e. when there's a single
+/// connected player), as otherwise it would give an unfair advantage.
+pub fn process_checkpoint_restart_requests_system(
+    time: Res<SimulationTime>,
+    mut restart_requests: ResMut<DeferredPlayerQueues<PracticeCheckpoint>>,
+    mut checkpoint_restarts: ResMut<CheckpointRestarts>,
+    run_starts: Res<RunStarts>,
+    mut players: ResMut<Players>,
+    mut respawn_queues: RespawnQueues,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let is_practice_session = players.is_practice_session();
+    for (player_net_id, checkpoints) in restart_requests.drain() {
+        // Only the latest request matters if several arrive within a frame.
+        let Some(checkpoint) = checkpoints.last() else {
+            continue;
+        };
+        if !is_practice_session {
+            log::warn!(
+                "Ignoring Player ({}) checkpoint restart request: not a practice session",
+                player_net_id.0
+            );
+            continue;
+        }
+        if !checkpoint.position.is_finite() {
+            log::warn!(
+                "Ignoring Player ({}) checkpoint restart request: invalid position",
+                player_net_id.0
+            );
+            continue;
+        }
+        let is_reset = run_starts
+            .checkpoints_reset_at
+            .get(&player_net_id)
+            .map_or(false, |reset_at| {
+                absolute_frame_of(&time, checkpoint.frame_number) <= *reset_at
+            });
+        if is_reset {
+            log::warn!(
+                "Ignoring Player ({}) checkpoint restart request: the checkpoint was reset by a death",
+                player_net_id.0
+            );
+            continue;
+        }
+        let Some(player) = players.get_mut(&player_net_id) else {
+            log::error!(
+                "Ignoring Player ({}) checkpoint restart request: player is not found",
+                player_net_id.0
+            );
+            continue;
+        };
+        restart_at(
+            &time,
+            player_net_id,
+            player,
+            checkpoint.position,
+            &mut checkpoint_restarts,
+            &mut respawn_queues,
+        );
+    }
+}
+
+/// Teleporting is a checkpoint restart at an arbitrary position, so such runs
+/// aren't timed either. It's allowed only on solo practice servers, as
+/// practicing a section of a level that hasn't been reached yet is the point
+/// of those.
+pub fn process_teleport_requests_system(
+    time: Res<SimulationTime>,
+    solo_practice: Option<Res<SoloPractice>>,
+    mut teleport_requests: ResMut<DeferredPlayerQueues<TeleportRequest>>,
+    mut checkpoint_restarts: ResMut<CheckpointRestarts>,
+    mut players: ResMut<Players>,
+    mut respawn_queues: RespawnQueues,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for (player_net_id, requests) in teleport_requests.drain() {
+        // Only the latest request matters if several arrive within a frame.
+        let Some(request) = requests.last() else {
+            continue;
+        };
+        if solo_practice.is_none() {
+            log::warn!(
+                "Ignoring Player ({}) teleport request: not a solo practice server",
+                player_net_id.0
+            );
+            continue;
+        }
+        if !request.position.is_finite() {
+            log::warn!(
+                "Ignoring Player ({}) teleport request: invalid position",
+                player_net_id.0
+            );
+            continue;
+        }
+        let Some(player) = players.get_mut(&player_net_id) else {
+            log::error!(
+                "Ignoring Player ({}) teleport request: player is not found",
+                player_net_id.0
+            );
+            continue;
+        };
+        restart_at(
+            &time,
+            player_net_id,
+            player,
+            request.position,
+            &mut checkpoint_restarts,
+            &mut respawn_queues,
+        );
+    }
+}
+
+/// Respawns a runner at the position, unless they're already respawning.
+fn restart_at(
+    time: &SimulationTime,
+    net_id: PlayerNetId,
+    player: &mut Player,
+    position: Vec2,
+    checkpoint_restarts: &mut CheckpointRestarts,
+    respawn_queues: &mut RespawnQueues,
+) {
+    if player.role != PlayerRole::Runner || player.respawning_at.is_some() {
+        return;
+    }
+
+    let respawn_at = time.server_frame + PLAYER_CHECKPOINT_RESTART_TIME;
+    player.respawning_at = Some((respawn_at, RespawnPlayerReason::Checkpoint));
+    checkpoint_restarts.insert(net_id, position);
+    respawn_queues.respawn_player_messages.push(RespawnPlayer {
+        net_id,
+        reason: RespawnPlayerReason::Checkpoint,
+        frame_number: respawn_at,
+        finish: None,
+    });
+    respawn_queues.despawn_players_commands.push(DespawnPlayer {
+        net_id,
+        frame_number: time.server_frame + FrameNumber::new(1),
+        reason: DespawnReason::DeathOrFinish,
+    });
+}
+
 pub fn process_scheduled_spawns_system(
     time: Res<SimulationTime>,
     level_spawn_location_service: LevelSpawnLocationService,
     mut spawn_players_commands: ResMut<DeferredQueue<commands::SpawnPlayer>>,
+    mut checkpoint_restarts: ResMut<CheckpointRestarts>,
+    mut run_starts: ResMut<RunStarts>,
     mut players: ResMut<Players>,
 ) {
     for (player_net_id, player) in players.iter_mut() {
         if let Some((spawn_at, _)) = player.respawning_at {
             if time.server_frame >= spawn_at {
+                let start_position = match checkpoint_restarts.remove(player_net_id) {
+                    Some(checkpoint_position) => {
+                        run_starts
+                            .pending_checkpoint_restarts
+                            .insert(*player_net_id);
+                        checkpoint_position
+                    }
+                    None => level_spawn_location_service.spawn_position(time.server_frame),
+                };
                 spawn_players_commands.push(commands::SpawnPlayer {
                     net_id: *player_net_id,
-                    start_position: level_spawn_location_service.spawn_position(time.server_frame),
+                    start_position,
                     is_player_frame_simulated: false,
                 });
                 player.respawning_at = None;
@@ -0,0 +1,90 @@
+use crate::net::{broadcast_reliable_game_message, ConnectionStates};
+use bevy::{
+    ecs::system::{NonSendMut, Res, ResMut, Resource},
+    log,
+    prelude::{Deref, DerefMut},
+};
+use bevy_disturbulence::NetworkResource;
+use mr_shared_lib::{
+    game::{commands::DeferredPlayerQueues, game_mode::CurrentGameMode},
+    messages::{MatchEnded, ReliableServerMessage},
+    player::{PlayerRole, Players},
+};
+
+/// Results of the matches that haven't been broadcast yet.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct EndedMatches(pub Vec<MatchEnded>);
+
+/// Runs after the player events of the frame are recorded. Eliminated runners
+/// become spectators until the next match starts, when they're brought back.
+pub fn evaluate_game_mode_system(
+    mut current_game_mode: ResMut<CurrentGameMode>,
+    players: Res<Players>,
+    mut switch_role_requests: ResMut<DeferredPlayerQueues<PlayerRole>>,
+    mut ended_matches: ResMut<EndedMatches>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let CurrentGameMode {
+        game_mode,
+        current_match,
+    } = &mut *current_game_mode;
+    current_match.elapsed_frames = current_match.elapsed_frames.saturating_add(1);
+
+    let eliminated = current_match
+        .stats
+        .iter()
+        .filter(|(_, stats)| game_mode.is_eliminated(stats))
+        .map(|(net_id, _)| *net_id)
+        .collect::<Vec<_>>();
+    for net_id in &eliminated {
+        if players
+            .get(net_id)
+            .map_or(false, |player| player.role == PlayerRole::Runner)
+        {
+            log::info!("Player ({}) has been eliminated", net_id.0);
+            switch_role_requests.push(*net_id, PlayerRole::Spectator);
+        }
+    }
+
+    let Some(outcome) = game_mode.evaluate(current_match, &players) else {
+        return;
+    };
+    log::info!(
+        "The match ({}) has ended, the winner: {:?}",
+        game_mode.kind().label(),
+        outcome.winner.map(|net_id| net_id.0)
+    );
+    ended_matches.push(MatchEnded {
+        game_mode: game_mode.kind(),
+        winner: outcome.winner,
+        standings: current_match.standings(),
+    });
+    *current_match = Default::default();
+
+    for net_id in eliminated {
+        if players.get(&net_id).map_or(false, |player| {
+            player.is_connected && player.role == PlayerRole::Spectator
+        }) {
+            switch_role_requests.push(net_id, PlayerRole::Runner);
+        }
+    }
+}
+
+pub fn send_match_results_system(
+    mut net: NonSendMut<NetworkResource>,
+    connection_states: Res<ConnectionStates>,
+    mut ended_matches: ResMut<EndedMatches>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for match_ended in ended_matches.drain(..) {
+        broadcast_reliable_game_message(
+            &mut net,
+            &connection_states,
+            ReliableServerMessage::MatchEnded(match_ended),
+        );
+    }
+}
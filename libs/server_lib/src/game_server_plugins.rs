@@ -0,0 +1,160 @@
+//! Custom game modes can be built on top of the stock server without forking
+//! it: a downstream binary implements [`GameServerPlugin`] and registers it
+//! with [`AddGameServerPlugin::add_game_server_plugin`] before adding
+//! `MuddleServerPlugin`.
+//!
+//! Hooks get exclusive access to the `World`, so plugins can read and modify
+//! any server state (`Players`, `LevelState`, the deferred message queues,
+//! etc.). They are called from the broadcast stage right before the updates
+//! get sent to clients, thus everything a hook changes is broadcast within
+//! the same frame.
+
+use bevy::{
+    app::App,
+    ecs::{
+        system::Resource,
+        world::{Mut, World},
+    },
+    log,
+    utils::HashSet,
+};
+use iyes_loopless::state::CurrentState;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    messages::{
+        DeferredMessagesQueue, FinishResult, PlayerNetId, RespawnPlayer, RespawnPlayerReason,
+    },
+    player::Players,
+    GameSessionState, GameTime,
+};
+
+/// All the hooks are optional. Bots are handled as any other players.
+pub trait GameServerPlugin: Send + Sync + 'static {
+    /// Is used for logging.
+    fn name(&self) -> &str;
+
+    /// Is called once the level is loaded and the game starts.
+    fn on_level_loaded(&mut self, _world: &mut World) {}
+
+    fn on_player_join(&mut self, _world: &mut World, _net_id: PlayerNetId) {}
+
+    fn on_player_leave(&mut self, _world: &mut World, _net_id: PlayerNetId) {}
+
+    /// The result is `None` for untimed runs (such as the ones resumed from
+    /// practice checkpoints).
+    fn on_finish(
+        &mut self,
+        _world: &mut World,
+        _net_id: PlayerNetId,
+        _result: Option<FinishResult>,
+    ) {
+    }
+
+    /// Is called every simulated frame, before the server broadcasts the
+    /// updates for it.
+    fn on_broadcast(&mut self, _world: &mut World, _frame_number: FrameNumber) {}
+}
+
+enum GameServerEvent {
+    LevelLoaded,
+    PlayerJoined(PlayerNetId),
+    PlayerLeft(PlayerNetId),
+    Finished(PlayerNetId, Option<FinishResult>),
+}
+
+#[derive(Resource, Default)]
+pub struct GameServerPlugins {
+    plugins: Vec<Box<dyn GameServerPlugin>>,
+    is_level_loaded: bool,
+    connected_players: HashSet<PlayerNetId>,
+}
+
+impl GameServerPlugins {
+    pub fn add(&mut self, plugin: Box<dyn GameServerPlugin>) {
+        log::info!("Registering game server plugin: {}", plugin.name());
+        self.plugins.push(plugin);
+    }
+
+    fn collect_events(&mut self, world: &World) -> Vec<GameServerEvent> {
+        let mut events = Vec::new();
+        if !self.is_level_loaded {
+            self.is_level_loaded = true;
+            events.push(GameServerEvent::LevelLoaded);
+        }
+
+        let connected_players = world
+            .resource::<Players>()
+            .iter()
+            .filter_map(|(net_id, player)| player.is_connected.then_some(*net_id))
+            .collect::<HashSet<_>>();
+        events.extend(
+            connected_players
+                .difference(&self.connected_players)
+                .map(|net_id| GameServerEvent::PlayerJoined(*net_id)),
+        );
+        events.extend(
+            self.connected_players
+                .difference(&connected_players)
+                .map(|net_id| GameServerEvent::PlayerLeft(*net_id)),
+        );
+        self.connected_players = connected_players;
+
+        // Respawn messages haven't been sent yet, as hooks run before broadcasting.
+        events.extend(
+            world
+                .resource::<DeferredMessagesQueue<RespawnPlayer>>()
+                .iter()
+                .filter(|respawn| respawn.reason == RespawnPlayerReason::Finish)
+                .map(|respawn| GameServerEvent::Finished(respawn.net_id, respawn.finish)),
+        );
+        events
+    }
+}
+
+pub trait AddGameServerPlugin {
+    fn add_game_server_plugin(&mut self, plugin: impl GameServerPlugin) -> &mut Self;
+}
+
+impl AddGameServerPlugin for App {
+    fn add_game_server_plugin(&mut self, plugin: impl GameServerPlugin) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(GameServerPlugins::default)
+            .add(Box::new(plugin));
+        self
+    }
+}
+
+pub fn run_game_server_plugins_system(world: &mut World) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    world.resource_scope(|world, mut game_server_plugins: Mut<GameServerPlugins>| {
+        if game_server_plugins.plugins.is_empty() {
+            return;
+        }
+        match world.resource::<CurrentState<GameSessionState>>().0 {
+            GameSessionState::Playing => {}
+            GameSessionState::Loading => {
+                game_server_plugins.is_level_loaded = false;
+                return;
+            }
+            GameSessionState::Paused => return,
+        }
+
+        let events = game_server_plugins.collect_events(world);
+        let frame_number = world.resource::<GameTime>().frame_number;
+        for plugin in &mut game_server_plugins.plugins {
+            for event in &events {
+                match *event {
+                    GameServerEvent::LevelLoaded => plugin.on_level_loaded(world),
+                    GameServerEvent::PlayerJoined(net_id) => plugin.on_player_join(world, net_id),
+                    GameServerEvent::PlayerLeft(net_id) => plugin.on_player_leave(world, net_id),
+                    GameServerEvent::Finished(net_id, result) => {
+                        plugin.on_finish(world, net_id, result)
+                    }
+                }
+            }
+            plugin.on_broadcast(world, frame_number);
+        }
+    });
+}
@@ -0,0 +1,260 @@
+use bevy::{
+    ecs::system::Resource,
+    log,
+    utils::{HashMap, Instant, Uuid},
+};
+use mr_shared_lib::{
+    messages::FinishResult,
+    player::{random_name, Player, PlayerRole},
+};
+use std::time::Duration;
+
+/// Guest ids are generated by clients, so the number of remembered sessions
+/// has to be capped.
+const MAX_GUEST_SESSIONS: usize = 1024;
+/// Sessions of guests that haven't reconnected for this long are forgotten.
+const GUEST_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Guests are players without an account, identified by the id their client
+/// has generated. The server remembers their nicknames and stats for
+/// `GUEST_SESSION_TTL` after they leave, so that reconnecting doesn't reset
+/// them.
+#[derive(Resource, Default)]
+pub struct GuestSessions {
+    sessions: HashMap<Uuid, GuestSession>,
+    /// Ids of the connected guests, keyed by connection handles.
+    connections: HashMap<u32, Uuid>,
+}
+
+struct GuestSession {
+    nickname: String,
+    finishes: u32,
+    deaths: u32,
+    best_finish: Option<FinishResult>,
+    /// Is `None` while the guest is connected.
+    left_at: Option<Instant>,
+}
+
+impl GuestSessions {
+    /// Returns a player with the remembered nickname and stats. A nickname
+    /// chosen by the guest replaces the remembered one, guests that join for
+    /// the first time without choosing one get a random nickname.
+    pub fn player(&self, guest_id: Uuid, nickname: Option<String>) -> Player {
+        let session = self
+            .sessions
+            .get(&guest_id)
+            .filter(|session| session.left_at.is_some());
+        let nickname = nickname
+            .or_else(|| session.map(|session| session.nickname.clone()))
+            .unwrap_or_else(random_name);
+        let player = Player::new_with_nickname(PlayerRole::Runner, nickname);
+        match session {
+            Some(session) => Player {
+                finishes: session.finishes,
+                deaths: session.deaths,
+                best_finish: session.best_finish,
+                ..player
+            },
+            None => player,
+        }
+    }
+
+    /// Remembers a guest, is expected to be called once their handshake has
+    /// succeeded. The stats of a returning guest are kept.
+    pub fn connect(&mut self, handle: u32, guest_id: Uuid, nickname: String, now: Instant) {
+        if self.connections.values().any(|id| *id == guest_id) {
+            // Another tab of the same browser, for instance.
+            log::info!(
+                "Guest {guest_id} is already connected, the new connection won't be remembered"
+            );
+            return;
+        }
+
+        self.expire(now);
+        if !self.sessions.contains_key(&guest_id) && self.sessions.len() >= MAX_GUEST_SESSIONS {
+            let least_recent_session = self
+                .sessions
+                .iter()
+                .filter_map(|(id, session)| Some((*id, session.left_at?)))
+                .min_by_key(|(_, left_at)| *left_at);
+            let Some((least_recent_id, _)) = least_recent_session else {
+                log::warn!("Too many guests are connected, guest {guest_id} won't be remembered");
+                return;
+            };
+            self.sessions.remove(&least_recent_id);
+        }
+
+        self.connections.insert(handle, guest_id);
+        let session = self
+            .sessions
+            .entry(guest_id)
+            .or_insert_with(|| GuestSession {
+                nickname: String::new(),
+                finishes: 0,
+                deaths: 0,
+                best_finish: None,
+                left_at: None,
+            });
+        session.nickname = nickname;
+        session.left_at = None;
+    }
+
+    /// Remembers the stats of a disconnecting guest. Connections of players
+    /// that aren't guests are ignored.
+    pub fn leave(&mut self, handle: u32, player: &Player, now: Instant) {
+        let Some(guest_id) = self.connections.remove(&handle) else {
+            return;
+        };
+        if let Some(session) = self.sessions.get_mut(&guest_id) {
+            session.finishes = player.finishes;
+            session.deaths = player.deaths;
+            session.best_finish = player.best_finish;
+            session.left_at = Some(now);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.sessions.retain(|_, session| {
+            session.left_at.map_or(true, |left_at| {
+                now.duration_since(left_at) < GUEST_SESSION_TTL
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(
+        guest_sessions: &mut GuestSessions,
+        handle: u32,
+        guest_id: Uuid,
+        nickname: Option<&str>,
+        now: Instant,
+    ) -> Player {
+        let player = guest_sessions.player(guest_id, nickname.map(ToOwned::to_owned));
+        guest_sessions.connect(handle, guest_id, player.nickname.clone(), now);
+        player
+    }
+
+    #[test]
+    fn test_rejoin() {
+        let mut guest_sessions = GuestSessions::default();
+        let guest_id = Uuid::from_u128(1);
+        let now = Instant::now();
+
+        let mut player = join(&mut guest_sessions, 1, guest_id, None, now);
+        let nickname = player.nickname.clone();
+        player.finishes = 2;
+        player.deaths = 3;
+        guest_sessions.leave(1, &player, now);
+
+        let player = join(&mut guest_sessions, 2, guest_id, None, now);
+        assert_eq!(player.nickname, nickname);
+        assert_eq!((player.finishes, player.deaths), (2, 3));
+
+        // Other guests start from scratch.
+        let other_player = join(&mut guest_sessions, 3, Uuid::from_u128(2), None, now);
+        assert_eq!((other_player.finishes, other_player.deaths), (0, 0));
+    }
+
+    #[test]
+    fn test_chosen_nickname() {
+        let mut guest_sessions = GuestSessions::default();
+        let guest_id = Uuid::from_u128(1);
+        let now = Instant::now();
+
+        let player = join(&mut guest_sessions, 1, guest_id, Some("Muddler"), now);
+        assert_eq!(player.nickname, "Muddler");
+        guest_sessions.leave(1, &player, now);
+
+        let player = join(&mut guest_sessions, 2, guest_id, None, now);
+        assert_eq!(player.nickname, "Muddler");
+        guest_sessions.leave(2, &player, now);
+
+        let player = join(&mut guest_sessions, 3, guest_id, Some("Runner"), now);
+        assert_eq!(player.nickname, "Runner");
+    }
+
+    #[test]
+    fn test_already_connected() {
+        let mut guest_sessions = GuestSessions::default();
+        let guest_id = Uuid::from_u128(1);
+        let now = Instant::now();
+
+        let mut player = join(&mut guest_sessions, 1, guest_id, Some("Muddler"), now);
+        player.finishes = 1;
+        // The second connection isn't remembered, so leaving with it doesn't
+        // affect the session.
+        let second_player = join(&mut guest_sessions, 2, guest_id, Some("Runner"), now);
+        guest_sessions.leave(2, &second_player, now);
+        guest_sessions.leave(1, &player, now);
+
+        let player = join(&mut guest_sessions, 3, guest_id, None, now);
+        assert_eq!(player.nickname, "Muddler");
+        assert_eq!(player.finishes, 1);
+    }
+
+    #[test]
+    fn test_leave_without_connecting() {
+        let mut guest_sessions = GuestSessions::default();
+        let now = Instant::now();
+
+        // Handshakes that fail never connect guests.
+        let player = guest_sessions.player(Uuid::from_u128(1), None);
+        guest_sessions.leave(1, &player, now);
+        assert!(guest_sessions.sessions.is_empty());
+        assert!(guest_sessions.connections.is_empty());
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let mut guest_sessions = GuestSessions::default();
+        let guest_id = Uuid::from_u128(1);
+        let now = Instant::now();
+
+        let mut player = join(&mut guest_sessions, 1, guest_id, Some("Muddler"), now);
+        player.finishes = 1;
+        guest_sessions.leave(1, &player, now);
+
+        let later = now + GUEST_SESSION_TTL;
+        join(&mut guest_sessions, 2, Uuid::from_u128(2), None, later);
+        assert!(!guest_sessions.sessions.contains_key(&guest_id));
+
+        let player = join(&mut guest_sessions, 3, guest_id, None, later);
+        assert_eq!(player.finishes, 0);
+    }
+
+    #[test]
+    fn test_sessions_are_capped() {
+        let mut guest_sessions = GuestSessions::default();
+        let now = Instant::now();
+
+        for i in 0..MAX_GUEST_SESSIONS as u32 {
+            let left_at = now + Duration::from_secs(i as u64);
+            let player = join(
+                &mut guest_sessions,
+                i,
+                Uuid::from_u128(i as u128),
+                None,
+                left_at,
+            );
+            guest_sessions.leave(i, &player, left_at);
+        }
+
+        // The session of the guest that has left first is evicted.
+        let handle = MAX_GUEST_SESSIONS as u32;
+        let later = now + Duration::from_secs(handle as u64);
+        join(
+            &mut guest_sessions,
+            handle,
+            Uuid::from_u128(u128::MAX),
+            None,
+            later,
+        );
+        assert_eq!(guest_sessions.sessions.len(), MAX_GUEST_SESSIONS);
+        assert!(!guest_sessions.sessions.contains_key(&Uuid::from_u128(0)));
+        assert!(guest_sessions.sessions.contains_key(&Uuid::from_u128(1)));
+    }
+}
@@ -0,0 +1,502 @@
+use crate::net::BuilderStates;
+use bevy::{
+    ecs::{
+        entity::Entity,
+        system::{Query, Res, ResMut, Resource},
+    },
+    math::Vec2,
+    utils::{HashMap, HashSet},
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::components::Position,
+    messages::{AreaOfInterestUpdate, PlayerNetId},
+    player::{PlayerRole, Players},
+    registry::EntityRegistry,
+    SimulationTime,
+};
+
+/// Players are bucketed into square cells, so that looking up the players
+/// around a client doesn't require checking every player on the server.
+#[derive(Default)]
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(PlayerNetId, Vec2)>>,
+    positions: HashMap<PlayerNetId, Vec2>,
+}
+
+impl SpatialGrid {
+    pub fn rebuild(&mut self, cell_size: f32, players: impl Iterator<Item = (PlayerNetId, Vec2)>) {
+        self.cell_size = cell_size;
+        self.cells.clear();
+        self.positions.clear();
+        for (net_id, position) in players {
+            self.cells
+                .entry(self.cell(position))
+                .or_default()
+                .push((net_id, position));
+            self.positions.insert(net_id, position);
+        }
+    }
+
+    pub fn position(&self, net_id: PlayerNetId) -> Option<Vec2> {
+        self.positions.get(&net_id).copied()
+    }
+
+    pub fn players_within(
+        &self,
+        center: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = PlayerNetId> + '_ {
+        let (min_x, min_y) = self.cell(center - Vec2::splat(radius));
+        let (max_x, max_y) = self.cell(center + Vec2::splat(radius));
+        (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |(_, position)| position.distance_squared(center) <= radius * radius)
+            .map(|(net_id, _)| *net_id)
+    }
+
+    fn cell(&self, position: Vec2) -> (i32, i32) {
+        let cell = (position / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+}
+
+/// Visible players are hidden again only once they get this many radii away.
+const LEAVE_RADIUS_FACTOR: f32 = 1.25;
+
+/// If the radius is set, `DeltaUpdate` messages include only the players
+/// within the radius around a client's runner (or a builder's camera).
+/// Spectators still receive everyone.
+#[derive(Resource, Default)]
+pub struct AreasOfInterest {
+    pub radius: Option<f32>,
+    grid: SpatialGrid,
+    /// The players that were included in the latest update, per client. Clients
+    /// without an entry receive everyone.
+    visible_players: HashMap<PlayerNetId, HashSet<PlayerNetId>>,
+}
+
+impl AreasOfInterest {
+    pub fn is_visible(&self, viewer: PlayerNetId, net_id: PlayerNetId) -> bool {
+        self.visible_players
+            .get(&viewer)
+            .map_or(true, |visible_players| visible_players.contains(&net_id))
+    }
+
+    /// Is called when a client receives `StartGame`, as it includes the state
+    /// of every player.
+    pub fn reset(&mut self, viewer: PlayerNetId, sent_players: impl Iterator<Item = PlayerNetId>) {
+        if self.radius.is_some() {
+            self.visible_players.insert(
+                viewer,
+                sent_players.chain(std::iter::once(viewer)).collect(),
+            );
+        }
+    }
+
+    /// Returns the players that entered or left the client's area since the
+    /// previous update, if there are any.
+    pub fn update(
+        &mut self,
+        viewer: PlayerNetId,
+        frame_number: FrameNumber,
+        players: &Players,
+        builder_states: &BuilderStates,
+    ) -> Option<AreaOfInterestUpdate> {
+        let role = players.get(&viewer).map(|player| player.role);
+        let center = match role {
+            Some(PlayerRole::Runner) => self.grid.position(viewer),
+            Some(PlayerRole::Builder) => builder_states
+                .get(&viewer)
+                .map(|builder_state| builder_state.camera_position),
+            _ => None,
+        };
+        let radius = match (self.radius, role) {
+            (Some(radius), Some(PlayerRole::Runner | PlayerRole::Builder)) => radius,
+            _ => {
+                // Players that were hidden are going to be included in updates again.
+                let previous = self.visible_players.remove(&viewer)?;
+                let entered = players
+                    .keys()
+                    .copied()
+                    .filter(|net_id| !previous.contains(net_id))
+                    .collect::<Vec<_>>();
+                return (!entered.is_empty()).then_some(AreaOfInterestUpdate {
+                    frame_number,
+                    entered,
+                    left: Vec::new(),
+                });
+            }
+        };
+        // Keeps the previous area until the position is known.
+        let center = center?;
+
+        let previous = self
+            .visible_players
+            .remove(&viewer)
+            .unwrap_or_else(|| players.keys().copied().collect());
+        // Players that are already visible leave only once they get further than
+        // the leave radius, so that moving along the edge doesn't make them enter
+        // and leave on every update.
+        let staying = self
+            .grid
+            .players_within(center, radius * LEAVE_RADIUS_FACTOR)
+            .filter(|net_id| previous.contains(net_id));
+        let visible = self
+            .grid
+            .players_within(center, radius)
+            .chain(staying)
+            .chain(std::iter::once(viewer))
+            .collect::<HashSet<_>>();
+        let entered = visible
+            .iter()
+            .copied()
+            .filter(|net_id| !previous.contains(net_id))
+            .collect::<Vec<_>>();
+        // Disconnected players are announced with `DisconnectedPlayer` messages.
+        let left = previous
+            .iter()
+            .copied()
+            .filter(|net_id| !visible.contains(net_id) && players.contains_key(net_id))
+            .collect::<Vec<_>>();
+
+        self.visible_players.insert(viewer, visible);
+
+        (!entered.is_empty() || !left.is_empty()).then_some(AreaOfInterestUpdate {
+            frame_number,
+            entered,
+            left,
+        })
+    }
+}
+
+pub fn update_areas_of_interest_system(
+    time: Res<SimulationTime>,
+    players: Res<Players>,
+    players_registry: Res<EntityRegistry<PlayerNetId>>,
+    mut areas_of_interest: ResMut<AreasOfInterest>,
+    player_entities: Query<(Entity, &Position)>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    // Matches the frame that `send_network_updates_system` broadcasts.
+    let time = time.prev_frame();
+
+    let AreasOfInterest {
+        radius,
+        grid,
+        visible_players,
+    } = &mut *areas_of_interest;
+    visible_players.retain(|net_id, _| players.contains_key(net_id));
+    let Some(radius) = *radius else {
+        return;
+    };
+
+    grid.rebuild(
+        radius,
+        player_entities.iter().filter_map(|(entity, position)| {
+            let net_id = players_registry.get_id(entity)?;
+            let position = position.buffer.get(time.server_frame)?;
+            Some((net_id, *position))
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mr_shared_lib::{messages::BuilderState, player::Player};
+
+    const RADIUS: f32 = 10.0;
+
+    fn net_ids(net_ids: impl IntoIterator<Item = PlayerNetId>) -> Vec<u16> {
+        let mut net_ids = net_ids
+            .into_iter()
+            .map(|net_id| net_id.0)
+            .collect::<Vec<_>>();
+        net_ids.sort_unstable();
+        net_ids
+    }
+
+    fn players(roles: &[(u16, PlayerRole)]) -> Players {
+        Players(
+            roles
+                .iter()
+                .map(|(net_id, role)| (PlayerNetId(*net_id), Player::new(*role)))
+                .collect(),
+        )
+    }
+
+    fn rebuild_grid(areas_of_interest: &mut AreasOfInterest, positions: &[(u16, Vec2)]) {
+        areas_of_interest.grid.rebuild(
+            RADIUS,
+            positions
+                .iter()
+                .map(|(net_id, position)| (PlayerNetId(*net_id), *position)),
+        );
+    }
+
+    /// Returns sorted entered and left players.
+    fn update(
+        areas_of_interest: &mut AreasOfInterest,
+        viewer: u16,
+        players: &Players,
+        builder_states: &BuilderStates,
+    ) -> Option<(Vec<u16>, Vec<u16>)> {
+        areas_of_interest
+            .update(
+                PlayerNetId(viewer),
+                FrameNumber::new(0),
+                players,
+                builder_states,
+            )
+            .map(|update| (net_ids(update.entered), net_ids(update.left)))
+    }
+
+    #[test]
+    fn test_players_within() {
+        let mut grid = SpatialGrid::default();
+        grid.rebuild(
+            RADIUS,
+            [
+                Vec2::new(0.0, 0.0),
+                // The same cell.
+                Vec2::new(9.9, 0.0),
+                // Exactly at the radius, in the next cells.
+                Vec2::new(10.0, 0.0),
+                Vec2::new(0.0, -10.0),
+                // Just across the cell boundary, in negative coordinates.
+                Vec2::new(-0.1, 0.0),
+                // In a cell that is checked, but outside the radius.
+                Vec2::new(9.0, 9.0),
+                // Outside the checked cells.
+                Vec2::new(25.0, 0.0),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(i, position)| (PlayerNetId(i as u16), position)),
+        );
+
+        assert_eq!(
+            net_ids(grid.players_within(Vec2::ZERO, RADIUS)),
+            vec![0, 1, 2, 3, 4]
+        );
+        // The center lies on a cell boundary.
+        assert_eq!(
+            net_ids(grid.players_within(Vec2::new(10.0, 0.0), 1.0)),
+            vec![1, 2]
+        );
+        assert_eq!(
+            net_ids(grid.players_within(Vec2::new(-100.0, 0.0), RADIUS)),
+            Vec::<u16>::new()
+        );
+        assert_eq!(grid.position(PlayerNetId(6)), Some(Vec2::new(25.0, 0.0)));
+    }
+
+    #[test]
+    fn test_update_entered_and_left() {
+        let players = players(&[
+            (1, PlayerRole::Runner),
+            (2, PlayerRole::Runner),
+            (3, PlayerRole::Runner),
+        ]);
+        let builder_states = BuilderStates::default();
+        let mut areas_of_interest = AreasOfInterest {
+            radius: Some(RADIUS),
+            ..Default::default()
+        };
+
+        // Clients start with everyone visible.
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[
+                (1, Vec2::ZERO),
+                (2, Vec2::new(5.0, 0.0)),
+                (3, Vec2::new(50.0, 0.0)),
+            ],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![3]))
+        );
+        assert!(areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(2)));
+        assert!(!areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(3)));
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            None
+        );
+
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[
+                (1, Vec2::ZERO),
+                (2, Vec2::new(50.0, 0.0)),
+                (3, Vec2::new(8.0, 0.0)),
+            ],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![3], vec![2]))
+        );
+
+        // The previous area is kept until the runner's position is known.
+        rebuild_grid(&mut areas_of_interest, &[(2, Vec2::ZERO)]);
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            None
+        );
+        assert!(!areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(2)));
+    }
+
+    #[test]
+    fn test_update_oscillating_at_the_edge() {
+        let players = players(&[(1, PlayerRole::Runner), (2, PlayerRole::Runner)]);
+        let builder_states = BuilderStates::default();
+        let mut areas_of_interest = AreasOfInterest {
+            radius: Some(RADIUS),
+            ..Default::default()
+        };
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[(1, Vec2::ZERO), (2, Vec2::new(20.0, 0.0))],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![2]))
+        );
+
+        // Approaching the edge from outside doesn't make the player enter.
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[(1, Vec2::ZERO), (2, Vec2::new(RADIUS + 0.5, 0.0))],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            None
+        );
+
+        for i in 0..10 {
+            let x = if i % 2 == 0 {
+                RADIUS - 0.5
+            } else {
+                RADIUS + 0.5
+            };
+            rebuild_grid(
+                &mut areas_of_interest,
+                &[(1, Vec2::ZERO), (2, Vec2::new(x, 0.0))],
+            );
+            let expected = (i == 0).then(|| (vec![2], vec![]));
+            assert_eq!(
+                update(&mut areas_of_interest, 1, &players, &builder_states),
+                expected
+            );
+            assert!(areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(2)));
+        }
+
+        // Getting past the leave radius hides the player again.
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[
+                (1, Vec2::ZERO),
+                (2, Vec2::new(RADIUS * LEAVE_RADIUS_FACTOR + 0.5, 0.0)),
+            ],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![2]))
+        );
+    }
+
+    #[test]
+    fn test_update_role_switch() {
+        let mut players = players(&[
+            (1, PlayerRole::Runner),
+            (2, PlayerRole::Runner),
+            (3, PlayerRole::Runner),
+        ]);
+        let mut builder_states = BuilderStates::default();
+        let mut areas_of_interest = AreasOfInterest {
+            radius: Some(RADIUS),
+            ..Default::default()
+        };
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[
+                (1, Vec2::ZERO),
+                (2, Vec2::new(5.0, 0.0)),
+                (3, Vec2::new(50.0, 0.0)),
+            ],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![3]))
+        );
+
+        // Builders see the players around their cameras.
+        players.get_mut(&PlayerNetId(1)).unwrap().role = PlayerRole::Builder;
+        builder_states.insert(
+            PlayerNetId(1),
+            BuilderState {
+                net_id: PlayerNetId(1),
+                camera_position: Vec2::new(45.0, 0.0),
+                selected_object: None,
+            },
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![3], vec![2]))
+        );
+
+        // Spectators see everyone.
+        players.get_mut(&PlayerNetId(1)).unwrap().role = PlayerRole::Spectator;
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![2], vec![]))
+        );
+        assert!(areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(2)));
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            None
+        );
+    }
+
+    #[test]
+    fn test_update_radius_toggle() {
+        let players = players(&[(1, PlayerRole::Runner), (2, PlayerRole::Runner)]);
+        let builder_states = BuilderStates::default();
+        let mut areas_of_interest = AreasOfInterest {
+            radius: Some(RADIUS),
+            ..Default::default()
+        };
+        rebuild_grid(
+            &mut areas_of_interest,
+            &[(1, Vec2::ZERO), (2, Vec2::new(50.0, 0.0))],
+        );
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![2]))
+        );
+
+        // Disabling the radius makes the hidden players enter again.
+        areas_of_interest.radius = None;
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![2], vec![]))
+        );
+        assert!(areas_of_interest.is_visible(PlayerNetId(1), PlayerNetId(2)));
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            None
+        );
+
+        // Enabling it again hides them once more.
+        areas_of_interest.radius = Some(RADIUS);
+        assert_eq!(
+            update(&mut areas_of_interest, 1, &players, &builder_states),
+            Some((vec![], vec![2]))
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{
+    level_watch::{apply_level_changes, LevelUpdates},
+    net::FetchedLevelInfo,
+    persistence::PersistenceRequest,
+    PersistenceRequestSender,
+};
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    log,
+};
+use mr_messages_lib::GetLevelResponse;
+use mr_shared_lib::{
+    game::{
+        commands::DeferredPlayerQueues,
+        level::{LevelState, SerializedLevel},
+    },
+    messages::EntityNetIdAllocator,
+    player::{PlayerRole, Players},
+    GameTime,
+};
+
+/// Is pushed for `ReliableClientMessage::ReloadLevel` messages.
+pub struct ReloadLevelRequest;
+
+#[derive(Resource, Default)]
+pub struct LevelReload {
+    /// Requests that arrive while the level is being fetched are ignored, as
+    /// they'd fetch the same data.
+    is_fetching: bool,
+    fetched_level: Option<(GetLevelResponse, SerializedLevel)>,
+}
+
+impl LevelReload {
+    pub fn receive(&mut self, result: Result<(GetLevelResponse, SerializedLevel), String>) {
+        self.is_fetching = false;
+        // Errors are already logged by the persistence task.
+        self.fetched_level = result.ok();
+    }
+}
+
+pub fn process_reload_level_requests_system(
+    mut requests: ResMut<DeferredPlayerQueues<ReloadLevelRequest>>,
+    mut level_reload: ResMut<LevelReload>,
+    players: Res<Players>,
+    fetched_level_info: Option<Res<FetchedLevelInfo>>,
+    persistence_req_tx: Res<PersistenceRequestSender>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for (player_net_id, _) in requests.drain() {
+        if players
+            .get(&player_net_id)
+            .map_or(true, |player| player.role != PlayerRole::Builder)
+        {
+            log::warn!(
+                "Ignoring Player ({}) reload requests: not a builder",
+                player_net_id.0
+            );
+            continue;
+        }
+        let (Some(fetched_level_info), Some(req_tx)) = (&fetched_level_info, &**persistence_req_tx)
+        else {
+            log::warn!(
+                "Ignoring Player ({}) reload requests: the level isn't persisted",
+                player_net_id.0
+            );
+            continue;
+        };
+        if level_reload.is_fetching {
+            continue;
+        }
+
+        log::info!("Player ({}) requested to reload the level", player_net_id.0);
+        level_reload.is_fetching = true;
+        if let Err(err) = req_tx.send(PersistenceRequest::ReloadLevel {
+            level_id: fetched_level_info.level.id,
+        }) {
+            log::error!("Failed to send a persistence request: {:?}", err);
+            level_reload.is_fetching = false;
+        }
+    }
+}
+
+/// Broadcasts the difference between the current level state and the
+/// re-fetched level, so that players don't need to reconnect.
+pub fn apply_level_reload_system(
+    time: Res<GameTime>,
+    mut level_reload: ResMut<LevelReload>,
+    fetched_level_info: Option<ResMut<FetchedLevelInfo>>,
+    level_state: Res<LevelState>,
+    mut entity_net_id_allocator: ResMut<EntityNetIdAllocator>,
+    mut updates: LevelUpdates,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let Some((level_info, level)) = level_reload.fetched_level.take() else {
+        return;
+    };
+
+    // Keeps the title and the other metadata up to date for autosaves.
+    if let Some(mut fetched_level_info) = fetched_level_info {
+        fetched_level_info.0 = level_info;
+    }
+    let (updated, despawned) = apply_level_changes(
+        level,
+        &time,
+        &level_state,
+        &mut entity_net_id_allocator,
+        &mut updates,
+    );
+    log::info!(
+        "Reloaded the level (updated objects: {}, despawned objects: {})",
+        updated,
+        despawned
+    );
+}
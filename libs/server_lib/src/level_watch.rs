@@ -0,0 +1,221 @@
+use bevy::{
+    ecs::system::{Res, ResMut, Resource, SystemParam},
+    log,
+    prelude::App,
+    utils::HashMap,
+};
+use mr_shared_lib::{
+    game::{
+        commands::{DeferredQueue, DespawnLevelObject, UpdateLevelObject, UpdateLevelSettings},
+        level::{LevelState, SerializedLevel},
+    },
+    messages::{DeferredMessagesQueue, EntityNetIdAllocator},
+    GameTime,
+};
+use notify::{RecursiveMode, Watcher};
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Is set when the server runs with `MUDDLE_LEVEL_FILE`: the level is read from
+/// the file instead of the persistence service, and the changes made to it get
+/// applied live.
+#[derive(Resource)]
+pub struct LevelFile {
+    pub path: PathBuf,
+    events: UnboundedReceiver<()>,
+    reload_tx: UnboundedSender<()>,
+}
+
+impl LevelFile {
+    /// Makes the file get re-read even if it hasn't changed.
+    pub fn request_reload(&self) {
+        let _ = self.reload_tx.send(());
+    }
+}
+
+pub fn read_level_file(path: &Path) -> anyhow::Result<SerializedLevel> {
+    let data = std::fs::read(path)?;
+    let mut level: SerializedLevel = serde_json::from_slice(&data)?;
+    level
+        .objects
+        .sort_by_key(|level_object| level_object.net_id.0);
+    Ok(level)
+}
+
+pub fn watch_level_file(app: &mut App, path: PathBuf) {
+    let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let reload_tx = events_tx.clone();
+    let file_name = path.file_name().map(ToOwned::to_owned);
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                if event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == file_name.as_deref())
+                {
+                    let _ = events_tx.send(());
+                }
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Level file watcher error: {:?}", err),
+        })
+        .expect("Failed to create a level file watcher");
+
+    // Editors often save files by replacing them, which breaks watching the file
+    // itself, so we watch the whole directory instead.
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .expect("Failed to watch the level file");
+    log::info!("Watching the level file for changes: {}", path.display());
+
+    app.insert_non_send_resource(watcher);
+    app.insert_resource(LevelFile {
+        path,
+        events: events_rx,
+        reload_tx,
+    });
+}
+
+/// Level changes that are applied as if a builder made the edits.
+#[derive(SystemParam)]
+pub struct LevelUpdates<'w, 's> {
+    update_level_object_commands: ResMut<'w, DeferredQueue<UpdateLevelObject>>,
+    update_level_object_messages: ResMut<'w, DeferredMessagesQueue<UpdateLevelObject>>,
+    despawn_level_object_commands: ResMut<'w, DeferredQueue<DespawnLevelObject>>,
+    despawn_level_object_messages: ResMut<'w, DeferredMessagesQueue<DespawnLevelObject>>,
+    update_level_settings_commands: ResMut<'w, DeferredQueue<UpdateLevelSettings>>,
+    update_level_settings_messages: ResMut<'w, DeferredMessagesQueue<UpdateLevelSettings>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Re-reads the level file on changes and broadcasts the difference with the
+/// current level state.
+pub fn apply_level_file_changes_system(
+    time: Res<GameTime>,
+    mut level_file: ResMut<LevelFile>,
+    level_state: Res<LevelState>,
+    mut entity_net_id_allocator: ResMut<EntityNetIdAllocator>,
+    mut updates: LevelUpdates,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    // A single save usually produces several events, we want to re-read the file
+    // only once.
+    let mut has_changed = false;
+    while level_file.events.try_recv().is_ok() {
+        has_changed = true;
+    }
+    if !has_changed {
+        return;
+    }
+
+    let level = match read_level_file(&level_file.path) {
+        Ok(level) => level,
+        Err(err) => {
+            // Might be a partially written file, the next event will bring the rest.
+            log::warn!("Failed to read the level file, keeping the current level: {err:?}");
+            return;
+        }
+    };
+
+    let (updated, despawned) = apply_level_changes(
+        level,
+        &time,
+        &level_state,
+        &mut entity_net_id_allocator,
+        &mut updates,
+    );
+    log::info!(
+        "Applied level file changes (updated objects: {}, despawned objects: {})",
+        updated,
+        despawned
+    );
+}
+
+/// Pushes the commands (and the messages to broadcast) that turn the current
+/// level state into `level`. Returns the numbers of updated and despawned
+/// objects.
+pub fn apply_level_changes(
+    level: SerializedLevel,
+    time: &GameTime,
+    level_state: &LevelState,
+    entity_net_id_allocator: &mut EntityNetIdAllocator,
+    updates: &mut LevelUpdates,
+) -> (usize, usize) {
+    let mut new_objects = HashMap::default();
+    for level_object in level.objects {
+        let net_id = level_object.net_id;
+        if new_objects.insert(net_id, level_object).is_some() {
+            log::warn!("Duplicate level object id in the level: {}", net_id.0);
+        }
+    }
+
+    let mut updated = 0;
+    for (net_id, level_object) in new_objects.iter() {
+        if level_state.object(*net_id) == Some(level_object) {
+            continue;
+        }
+        // Objects spawned by builders since the level was loaded may have taken
+        // the id of an object that has just been added to the level.
+        if level_state.object(*net_id).is_none() {
+            if let Err(err) = entity_net_id_allocator.reserve(*net_id) {
+                log::warn!("Skipping a level object: {err}");
+                continue;
+            }
+        }
+        let update_level_object = UpdateLevelObject {
+            object: level_object.clone(),
+            frame_number: time.frame_number,
+        };
+        updates
+            .update_level_object_commands
+            .push(update_level_object.clone());
+        updates
+            .update_level_object_messages
+            .push(update_level_object);
+        updated += 1;
+    }
+
+    let mut despawned = 0;
+    for net_id in level_state.objects().keys() {
+        if new_objects.contains_key(net_id) {
+            continue;
+        }
+        entity_net_id_allocator.free(*net_id);
+        let despawn_level_object = DespawnLevelObject {
+            net_id: *net_id,
+            frame_number: time.frame_number,
+        };
+        updates
+            .despawn_level_object_commands
+            .push(despawn_level_object.clone());
+        updates
+            .despawn_level_object_messages
+            .push(despawn_level_object);
+        despawned += 1;
+    }
+
+    if *level_state.settings() != level.settings {
+        let update_level_settings = UpdateLevelSettings {
+            settings: level.settings,
+        };
+        updates
+            .update_level_settings_commands
+            .push(update_level_settings.clone());
+        updates
+            .update_level_settings_messages
+            .push(update_level_settings);
+    }
+
+    (updated, despawned)
+}
@@ -1,65 +1,142 @@
 #![feature(hash_drain_filter)]
 #![feature(once_cell)]
 
-pub use crate::net::watch_agones_updates;
-pub use mr_shared_lib::{game::PlayerEventSender, player::PlayerEvent};
+pub use crate::{
+    bootstrap::{bootstrap, BootstrapError, BootstrapOptions, BootstrapStage, RetryPolicy},
+    game_server_plugins::{AddGameServerPlugin, GameServerPlugin, GameServerPlugins},
+    thread_isolation::{isolate_simulation_thread, reserve_simulation_core},
+};
+pub use mr_messages_lib::{ServerVersion, ALLOCATION_REQUEST_ID_ANNOTATION};
+pub use mr_shared_lib::{
+    game::PlayerEventSender, panic_quarantine::is_quarantined, player::PlayerEvent,
+};
 
 use crate::{
-    game_events::{process_player_events_system, process_scheduled_spawns_system},
+    admin::{
+        freeze_paused_runners_system, process_admin_commands_system, send_admin_broadcasts_system,
+        AdminBroadcasts, AdminPause, ConnectedAdmins,
+    },
+    analytics::{collect_session_analytics_system, SessionAnalytics},
+    bans::{refresh_ban_list_system, BanList},
+    bots::{
+        drive_practice_bots_system, process_practice_bots_requests_system, PracticeBots,
+        BOT_NET_IDS,
+    },
+    determinism::send_state_hashes_system,
+    game_events::{
+        process_checkpoint_restart_requests_system, process_player_events_system,
+        process_scheduled_spawns_system, process_teleport_requests_system, track_run_starts_system,
+        CheckpointRestarts, RunStarts,
+    },
+    game_mode::{evaluate_game_mode_system, send_match_results_system, EndedMatches},
+    game_server_plugins::run_game_server_plugins_system,
+    guests::GuestSessions,
+    interest_management::{update_areas_of_interest_system, AreasOfInterest},
+    level_reload::{
+        apply_level_reload_system, process_reload_level_requests_system, LevelReload,
+        ReloadLevelRequest,
+    },
+    level_watch::{apply_level_file_changes_system, watch_level_file},
     net::{
         broadcast_disconnected_players_system, process_network_events_system,
-        send_network_updates_system, startup, ConnectionStates, FetchedLevelInfo,
-        NewPlayerConnections, PlayerConnections,
+        send_network_updates_system, startup, BuilderStates, ConnectionStates,
+        NewPlayerConnections, PlayerConnections, PrivacyConsents, RegisteredUsers,
     },
     persistence::{
-        create_level, get_user, handle_persistence_requests, init_jwks_polling, load_level,
-        save_level_system, InitLevelObjects, Jwks, PersistenceConfig, PersistenceMessage,
+        handle_persistence_requests, init_jwks_polling, report_player_stats_system,
+        report_presence_system, save_level_system, send_level_save_errors_system, InitLevelData,
+        Jwks, LevelSaveErrors, PendingPlayerStats, PersistenceConfig, PersistenceMessage,
         PersistenceRequest,
     },
+    pings::{broadcast_pings_system, Pings},
     player_updates::{
-        process_despawn_level_object_requests_system, process_player_input_updates_system,
-        process_spawn_level_object_requests_system, process_switch_role_requests_system,
-        process_update_level_object_requests_system,
+        process_despawn_level_object_requests_system, process_invalid_level_object_shapes_system,
+        process_player_input_updates_system, process_spawn_level_object_requests_system,
+        process_switch_role_requests_system, process_update_level_object_requests_system,
+        process_update_level_settings_requests_system, InputViolations, LevelObjectEditors,
+    },
+    publishing::process_publish_level_requests_system,
+    runtime_config::{apply_runtime_config_changes_system, watch_runtime_config_file},
+    server_health::{
+        measure_server_health_system, start_tick_timer_system, ServerHealthMonitor,
+        SIMULATION_TIMESTEP_LABEL,
     },
+    session_recording::{save_session_recording_system, start_session_recording},
+    shutdown::{process_server_shutdown_system, ServerShutdown},
+    solo_practice::{SoloPractice, SOLO_PRACTICE_IDLE_TIMEOUT},
+    tethering::{pair_tethered_runners_system, respawn_tethered_partners_system},
 };
 use bevy::{
+    app::AppExit,
     log,
     prelude::*,
     time::{FixedTimestep, TimePlugin},
 };
 use iyes_loopless::prelude::*;
-use kube::Client;
-use mr_messages_lib::{InitLevel, LevelData};
+use mr_messages_lib::PLAYER_CAPACITY;
 use mr_shared_lib::{
     framebuffer::FrameNumber,
     game::{
-        commands::{DeferredPlayerQueues, DeferredQueue, DespawnLevelObject, UpdateLevelObject},
-        level::{CollisionLogic, LevelObject, LevelObjectDesc},
-        level_objects::{PlaneDesc, PlaneFormDesc},
+        commands::{
+            DeferredPlayerQueues, DeferredQueue, DespawnLevelObject, UpdateLevelObject,
+            UpdateLevelSettings,
+        },
+        determinism::{DeterminismGuard, StateHashRequest},
+        game_mode::{CurrentGameMode, GameModeKind},
+        level::{
+            spawnable_area, validate_spawnable_area, CollisionLogic, LevelObject, LevelObjectDesc,
+            LevelSettings, SerializedLevel,
+        },
+        level_objects::{ColliderSimplification, PlaneDesc, PlaneFormDesc},
+        movement::DistantObjectsStepping,
+        replay::record_session_frame_system,
+        tether::Tethers,
     },
     messages::{
-        self, DeferredMessagesQueue, EntityNetId, EntityNetIdCounter, PlayerNetIdCounter,
-        RespawnPlayer, RunnerInput, SpawnLevelObject, SpawnLevelObjectRequest,
+        self, AdminCommand, DeferredMessagesQueue, EntityNetId, EntityNetIdAllocator,
+        PracticeBotsRequest, PracticeCheckpoint, PublishLevelReport, PublishLevelRequest,
+        RespawnPlayer, RunnerInput, SpawnLevelObject, SpawnLevelObjectRequest, TeleportRequest,
     },
     player::{PlayerRole, Players},
     registry::IncrementId,
     AppState, GameSessionState, LevelObjectsToSpawnToLoad, MuddleSharedPlugin,
     SIMULATIONS_PER_SECOND,
 };
-use mr_utils_lib::kube_discovery;
 use reqwest::Url;
 use rymder::GameServer;
 use std::{
     net::IpAddr,
-    sync::LazyLock,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc, LazyLock},
     time::{Duration, Instant},
 };
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+mod admin;
+mod analytics;
+mod bans;
+mod bootstrap;
+mod bots;
+mod determinism;
 mod game_events;
+mod game_mode;
+mod game_server_plugins;
+mod guests;
+mod interest_management;
+mod level_reload;
+mod level_watch;
 mod net;
 mod persistence;
+mod pings;
 mod player_updates;
+mod publishing;
+mod runtime_config;
+mod server_health;
+mod session_recording;
+mod shutdown;
+mod solo_practice;
+mod tethering;
+mod thread_isolation;
 
 pub const DEFAULT_IDLE_TIMEOUT_MILLIS: u64 = 300_000;
 
@@ -70,10 +147,15 @@ pub struct Agones {
 }
 
 #[derive(Resource)]
-pub struct LastPlayerDisconnectedAt(pub Instant);
+pub(crate) struct LastPlayerDisconnectedAt(pub Instant);
 
 #[derive(Resource)]
-pub struct IdleTimeout(pub Duration);
+pub(crate) struct IdleTimeout(pub Duration);
+
+/// Is set when the matchmaker signals that the server runs an outdated version.
+/// A drained server shuts down as soon as the last player leaves.
+#[derive(Resource, Deref, DerefMut, Clone, Default)]
+pub struct DrainSignal(pub Arc<AtomicBool>);
 
 pub static TOKIO: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
     std::thread::Builder::new()
@@ -91,19 +173,44 @@ pub struct MuddleServerConfig {
     pub public_persistence_url: Option<Url>,
     pub private_persistence_url: Option<Url>,
     pub idle_timeout_millis: Option<u64>,
+    pub collider_simplification_tolerance: Option<f32>,
     pub listen_port: Option<u16>,
     pub listen_ip_addr: Option<IpAddr>,
     pub public_ip_addr: Option<IpAddr>,
+    /// Pins the simulation thread to the core and keeps other threads off it.
+    pub simulation_cpu_core: Option<usize>,
+    pub simulation_thread_nice: Option<i32>,
+    /// Makes the server load the level from a local file instead of persistence
+    /// and apply the changes made to the file live.
+    pub level_file: Option<PathBuf>,
+    /// Makes the server record state hashes of every simulation stage and
+    /// broadcast checkpoints, so that clients can bisect divergences.
+    pub determinism_guard: Option<bool>,
+    /// Enables tethering: connected runners get paired and can't get farther
+    /// than this distance from each other.
+    pub tether_distance: Option<f32>,
+    /// Enables interest management: clients receive updates only about the
+    /// players within this distance from their runner (or builder's camera).
+    pub interest_radius: Option<f32>,
+    /// Makes the server record the simulated frames to this file, so that the
+    /// session can be added to the replay corpus of the regression tests.
+    pub record_session: Option<PathBuf>,
+    /// A JSON file (normally a mounted ConfigMap) with the settings that can be
+    /// changed without restarting the server, such as the idle timeout.
+    pub runtime_config_file: Option<PathBuf>,
+    /// Decides how matches are won, defaults to `GameModeKind::FreeBuild`
+    /// (i.e. matches never end).
+    pub game_mode: Option<GameModeKind>,
 }
 
 #[derive(Resource, DerefMut, Deref)]
-pub struct PersistenceRequestSender(pub Option<UnboundedSender<PersistenceRequest>>);
+pub(crate) struct PersistenceRequestSender(pub Option<UnboundedSender<PersistenceRequest>>);
 #[derive(Resource, DerefMut, Deref)]
-pub struct PersistenceRequestReceiver(pub Option<UnboundedReceiver<PersistenceRequest>>);
+pub(crate) struct PersistenceRequestReceiver(pub Option<UnboundedReceiver<PersistenceRequest>>);
 #[derive(Resource, DerefMut, Deref)]
-pub struct PersistenceMessageSender(pub Option<UnboundedSender<PersistenceMessage>>);
+pub(crate) struct PersistenceMessageSender(pub Option<UnboundedSender<PersistenceMessage>>);
 #[derive(Resource, DerefMut, Deref)]
-pub struct PersistenceMessageReceiver(pub Option<UnboundedReceiver<PersistenceMessage>>);
+pub(crate) struct PersistenceMessageReceiver(pub Option<UnboundedReceiver<PersistenceMessage>>);
 
 pub struct MuddleServerPlugin;
 
@@ -159,11 +266,25 @@ impl Plugin for MuddleServerPlugin {
 
         app.add_system(process_idle_timeout);
 
-        let input_stage = SystemStage::parallel()
+        let mut input_stage = SystemStage::parallel()
+            .with_system(start_tick_timer_system.before(process_network_events_system))
+            .with_system(process_server_shutdown_system.before(process_network_events_system))
             .with_system(process_scheduled_spawns_system)
             .with_system(process_network_events_system)
-            .with_system(process_player_input_updates_system.after(process_network_events_system))
+            .with_system(freeze_paused_runners_system.after(process_network_events_system))
+            .with_system(
+                process_player_input_updates_system
+                    .after(process_network_events_system)
+                    .after(freeze_paused_runners_system),
+            )
+            .with_system(process_admin_commands_system.after(process_network_events_system))
             .with_system(process_switch_role_requests_system.after(process_network_events_system))
+            .with_system(
+                process_checkpoint_restart_requests_system.after(process_network_events_system),
+            )
+            .with_system(process_teleport_requests_system.after(process_network_events_system))
+            .with_system(process_practice_bots_requests_system.after(process_network_events_system))
+            .with_system(drive_practice_bots_system.after(process_practice_bots_requests_system))
             // It's ok to run the following in random order since object updates aren't possible
             // on the client before an authoritative confirmation that an object has been spawned.
             .with_system(
@@ -174,17 +295,73 @@ impl Plugin for MuddleServerPlugin {
             )
             .with_system(
                 process_despawn_level_object_requests_system.after(process_network_events_system),
+            )
+            .with_system(
+                process_update_level_settings_requests_system.after(process_network_events_system),
+            )
+            .with_system(process_publish_level_requests_system.after(process_network_events_system))
+            .with_system(process_reload_level_requests_system.after(process_network_events_system))
+            .with_system(apply_level_reload_system.after(process_network_events_system));
+        if let Some(level_file) = server_config.level_file.clone() {
+            watch_level_file(app, level_file);
+            input_stage.add_system(apply_level_file_changes_system);
+        }
+        if let Some(runtime_config_file) = server_config.runtime_config_file.clone() {
+            watch_runtime_config_file(
+                app,
+                runtime_config_file,
+                server_config.tether_distance.is_some(),
             );
-        let post_game_stage = SystemStage::single_threaded()
+            input_stage.add_system(apply_runtime_config_changes_system);
+        }
+        let mut post_game_stage = SystemStage::single_threaded()
+            .with_system(track_run_starts_system.before(process_player_events_system))
             .with_system(process_player_events_system)
-            .with_system(save_level_system);
+            .with_system(process_invalid_level_object_shapes_system)
+            .with_system(collect_session_analytics_system)
+            .with_system(save_level_system)
+            .with_system(report_presence_system)
+            .with_system(refresh_ban_list_system)
+            .with_system(report_player_stats_system.after(process_player_events_system))
+            .with_system(evaluate_game_mode_system.after(process_player_events_system));
+        if server_config.record_session.is_some() {
+            post_game_stage
+                .add_system(record_session_frame_system.after(process_player_events_system));
+            app.add_system_to_stage(CoreStage::Last, save_session_recording_system);
+        }
+        if server_config.tether_distance.is_some() {
+            input_stage.add_system(
+                pair_tethered_runners_system
+                    .after(process_network_events_system)
+                    .after(process_switch_role_requests_system),
+            );
+            post_game_stage
+                .add_system(respawn_tethered_partners_system.after(process_player_events_system));
+        }
         let broadcast_updates_stage = SystemStage::single_threaded()
             .with_system(broadcast_disconnected_players_system)
-            .with_system(send_network_updates_system.run_in_state(GameSessionState::Playing));
+            .with_system(send_admin_broadcasts_system)
+            .with_system(broadcast_pings_system)
+            .with_system(send_level_save_errors_system)
+            .with_system(send_match_results_system)
+            .with_system(measure_server_health_system.before(send_network_updates_system))
+            .with_system(run_game_server_plugins_system.before(send_network_updates_system))
+            .with_system(
+                update_areas_of_interest_system
+                    .run_in_state(GameSessionState::Playing)
+                    .before(send_network_updates_system),
+            )
+            .with_system(send_network_updates_system.run_in_state(GameSessionState::Playing))
+            .with_system(
+                send_state_hashes_system
+                    .run_in_state(GameSessionState::Playing)
+                    .after(send_network_updates_system),
+            );
 
         // Game.
         app.add_plugin(MuddleSharedPlugin::new(
-            FixedTimestep::steps_per_second(SIMULATIONS_PER_SECOND as f64),
+            FixedTimestep::steps_per_second(SIMULATIONS_PER_SECOND as f64)
+                .with_label(SIMULATION_TIMESTEP_LABEL),
             input_stage,
             post_game_stage,
             broadcast_updates_stage,
@@ -192,153 +369,123 @@ impl Plugin for MuddleServerPlugin {
             None,
         ));
 
+        if let Some(tether_distance) = server_config.tether_distance {
+            log::info!("Tethering is enabled (distance: {tether_distance})");
+            app.world.resource_mut::<Tethers>().max_distance = tether_distance;
+        }
+
+        let mut areas_of_interest = AreasOfInterest::default();
+        match server_config.interest_radius {
+            Some(radius) if radius.is_finite() && radius > 0.0 => {
+                log::info!("Interest management is enabled (radius: {radius})");
+                areas_of_interest.radius = Some(radius);
+            }
+            Some(_) => log::warn!("Ignoring MUDDLE_INTEREST_RADIUS: expected a positive value"),
+            None => {}
+        }
+        app.insert_resource(areas_of_interest);
+
+        let game_mode = server_config.game_mode.unwrap_or_default();
+        log::info!("Game mode: {}", game_mode.label());
+        app.insert_resource(CurrentGameMode::new(game_mode));
+
+        if server_config.determinism_guard.unwrap_or(false) {
+            log::info!("Determinism guard is enabled");
+            app.world.resource_mut::<DeterminismGuard>().enabled = true;
+        }
+
+        let collider_simplification = server_config
+            .collider_simplification_tolerance
+            .map_or_else(ColliderSimplification::default, |tolerance| {
+                ColliderSimplification { tolerance }
+            });
+        if let Some(path) = server_config.record_session.clone() {
+            start_session_recording(app, path, collider_simplification);
+        }
+
         // We override the initial state for server as we aren't using the loading state
         // atm.
         app.insert_resource(CurrentState(AppState::Playing));
 
-        app.init_resource::<EntityNetIdCounter>();
-        app.init_resource::<PlayerNetIdCounter>();
-        app.init_resource::<PlayerConnections>();
+        app.init_resource::<EntityNetIdAllocator>();
+        app.init_resource::<LevelObjectEditors>();
+        let mut player_connections = PlayerConnections::default();
+        player_connections
+            .reserve_range(BOT_NET_IDS)
+            .expect("Expected bot ids to be free");
+        app.insert_resource(player_connections);
         app.init_resource::<NewPlayerConnections>();
+        app.init_resource::<BuilderStates>();
+        app.init_resource::<RegisteredUsers>();
+        app.init_resource::<PrivacyConsents>();
+        app.init_resource::<GuestSessions>();
+        app.init_resource::<ConnectedAdmins>();
+        app.init_resource::<BanList>();
+        app.init_resource::<AdminPause>();
+        app.init_resource::<AdminBroadcasts>();
+        app.init_resource::<LevelSaveErrors>();
+        app.init_resource::<EndedMatches>();
+        app.init_resource::<PendingPlayerStats>();
+        app.init_resource::<SessionAnalytics>();
         app.init_resource::<ConnectionStates>();
         app.init_resource::<DeferredPlayerQueues<RunnerInput>>();
+        app.init_resource::<InputViolations>();
         app.init_resource::<DeferredPlayerQueues<PlayerRole>>();
         app.init_resource::<DeferredPlayerQueues<messages::SpawnLevelObjectRequestBody>>();
         app.init_resource::<DeferredPlayerQueues<SpawnLevelObjectRequest>>();
         app.init_resource::<DeferredPlayerQueues<LevelObject>>();
         app.init_resource::<DeferredPlayerQueues<EntityNetId>>();
+        app.init_resource::<DeferredPlayerQueues<LevelSettings>>();
+        app.init_resource::<DeferredPlayerQueues<PracticeCheckpoint>>();
+        app.init_resource::<DeferredPlayerQueues<PracticeBotsRequest>>();
+        app.init_resource::<DeferredPlayerQueues<TeleportRequest>>();
+        app.init_resource::<DeferredPlayerQueues<PublishLevelRequest>>();
+        app.init_resource::<DeferredPlayerQueues<PublishLevelReport>>();
+        app.init_resource::<DeferredPlayerQueues<ReloadLevelRequest>>();
+        app.init_resource::<LevelReload>();
+        app.init_resource::<DeferredPlayerQueues<messages::InvalidLevelObjectShape>>();
+        app.init_resource::<DeferredPlayerQueues<StateHashRequest>>();
+        app.init_resource::<DeferredPlayerQueues<AdminCommand>>();
+        app.init_resource::<PracticeBots>();
+        // Plugins may have been registered before this plugin was added.
+        app.init_resource::<GameServerPlugins>();
+        app.init_resource::<CheckpointRestarts>();
+        app.init_resource::<RunStarts>();
+        app.init_resource::<DistantObjectsStepping>();
+        app.init_resource::<ServerHealthMonitor>();
         app.init_resource::<DeferredMessagesQueue<RespawnPlayer>>();
         app.init_resource::<DeferredMessagesQueue<SpawnLevelObject>>();
         app.init_resource::<DeferredMessagesQueue<UpdateLevelObject>>();
         app.init_resource::<DeferredMessagesQueue<DespawnLevelObject>>();
+        app.init_resource::<DeferredMessagesQueue<UpdateLevelSettings>>();
+        app.init_resource::<DeferredMessagesQueue<Tethers>>();
         app.insert_resource(LastPlayerDisconnectedAt(Instant::now()));
-        app.insert_resource(IdleTimeout(
-            server_config
-                .idle_timeout_millis
-                .map(Duration::from_millis)
-                .unwrap_or_else(|| {
-                    log::info!(
-                        "Using the default value for MUDDLE_IDLE_TIMEOUT: {}",
-                        DEFAULT_IDLE_TIMEOUT_MILLIS
-                    );
-                    Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MILLIS)
-                }),
-        ));
-        app.init_resource::<Jwks>();
-    }
-}
-
-pub async fn init_level_data(app: &mut App, game_server: Option<GameServer>) {
-    let (user_id, init_level) = if let Some(game_server) = game_server {
-        let metadata = game_server
-            .object_meta
-            .expect("Expected GameServer metadata");
-        read_env_level_data(
-            metadata.annotations.get("user_id").cloned(),
-            metadata.annotations.get("level_title").cloned(),
-            metadata.annotations.get("level_parent_id").cloned(),
-            metadata.annotations.get("level_id").cloned(),
-        )
-    } else {
-        let user_id = mr_utils_lib::var!("MUDDLE_USER_ID");
-        let title = mr_utils_lib::var!("MUDDLE_LEVEL_TITLE");
-        let parent_id = mr_utils_lib::var!("MUDDLE_LEVEL_PARENT_ID");
-        let level_id = mr_utils_lib::var!("MUDDLE_LEVEL_ID");
-        if user_id.is_some() || title.is_some() || parent_id.is_some() || level_id.is_some() {
-            read_env_level_data(user_id, title, parent_id, level_id)
-        } else {
-            app.world
-                .insert_resource(InitLevelObjects(default_level_objects()));
-            return;
-        }
-    };
-
-    let mut server_config = app.world.get_resource_mut::<MuddleServerConfig>().unwrap();
-    if server_config.public_persistence_url.is_none()
-        || server_config.private_persistence_url.is_none()
-    {
-        match Client::try_default().await {
-            Ok(client) => {
-                let persistence_urls = kube_discovery::discover_persistence(client).await;
-                if let Some((public_persistence_url, private_persistence_url)) = persistence_urls {
-                    server_config.public_persistence_url = Some(public_persistence_url);
-                    server_config.private_persistence_url = Some(private_persistence_url);
-                }
-            }
-            Err(err) => {
-                log::warn!("Unable to detect kubernetes environment: {err:?}");
-            }
+        let mut idle_timeout = server_config
+            .idle_timeout_millis
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| {
+                log::info!(
+                    "Using the default value for MUDDLE_IDLE_TIMEOUT: {}",
+                    DEFAULT_IDLE_TIMEOUT_MILLIS
+                );
+                Duration::from_millis(DEFAULT_IDLE_TIMEOUT_MILLIS)
+            });
+        // Is inserted by `bootstrap`, which runs before the plugin is added.
+        if app.world.contains_resource::<SoloPractice>() {
+            idle_timeout = idle_timeout.min(SOLO_PRACTICE_IDLE_TIMEOUT);
         }
+        app.insert_resource(IdleTimeout(idle_timeout));
+        app.insert_resource(collider_simplification);
+        app.init_resource::<Jwks>();
+        app.init_resource::<DrainSignal>();
+        app.init_resource::<ServerShutdown>();
+        app.init_resource::<Pings>();
     }
-
-    let public_persistence_url = server_config
-        .public_persistence_url
-        .clone()
-        .expect("Expected public_persistence_url when booting from the Agones environment or requesting a level via the env variables");
-    let private_persistence_url = server_config
-        .private_persistence_url
-        .clone()
-        .expect("Expected private_persistence_url when booting from the Agones environment or requesting a level via the env variables");
-
-    let (get_level_response, init_level_objects) = match init_level {
-        InitLevel::Existing(id) => load_level(public_persistence_url, id)
-            .await
-            .expect("Failed to load the level"),
-        InitLevel::Create { title, parent_id } => {
-            let user_id =
-                user_id.expect("Expected `user_id` when creating a new level is requested");
-            let user = get_user(public_persistence_url, user_id)
-                .await
-                .expect("Failed to get user info");
-            let level_data = match parent_id {
-                Some(parent_id) => LevelData::Forked { parent_id },
-                None => LevelData::Data {
-                    data: serde_json::to_value(default_level_objects()).unwrap(),
-                },
-            };
-            let level_response = create_level(
-                private_persistence_url,
-                user_id,
-                user.display_name,
-                title,
-                level_data,
-            )
-            .await
-            .expect("Failed to create a level");
-            let level_objects = serde_json::from_value(level_response.level.data.clone()).unwrap();
-            (level_response, InitLevelObjects(level_objects))
-        }
-    };
-    app.world.insert_resource(init_level_objects);
-    app.world
-        .insert_resource(FetchedLevelInfo(get_level_response));
-}
-
-fn read_env_level_data(
-    user_id: Option<String>,
-    title: Option<String>,
-    parent_id: Option<String>,
-    level_id: Option<String>,
-) -> (Option<i64>, InitLevel) {
-    let user_id = user_id.map(|user_id| user_id.parse().expect("Failed to parse `user_id`"));
-    let init_level = if let Some(title) = title {
-        InitLevel::Create {
-            title,
-            parent_id: parent_id.map(|id| id.parse().expect("Failed to parse `level_parent_id`")),
-        }
-    } else {
-        let level_id = level_id
-            .expect("Expected a `level_id` annotation or `level_title` one for a new level (`MUDDLE_LEVEL_ID` or `MUDDLE_LEVEL_TITLE` env vars respectively)")
-            .parse()
-            .expect("Failed to parse `level_id`");
-        InitLevel::Existing(level_id)
-    };
-    (user_id, init_level)
 }
 
-fn default_level_objects() -> Vec<LevelObject> {
+pub(crate) fn default_level() -> SerializedLevel {
     let mut entity_net_id_counter = EntityNetId(0);
-    vec![LevelObject {
+    let objects = vec![LevelObject {
         net_id: entity_net_id_counter.increment(),
         label: "Ground".to_owned(),
         desc: LevelObjectDesc::Plane(PlaneDesc {
@@ -353,27 +500,49 @@ fn default_level_objects() -> Vec<LevelObject> {
                 ],
             },
             is_spawn_area: false,
+            appearance: Default::default(),
+            jump_pad: None,
         }),
         route: None,
         collision_logic: CollisionLogic::None,
-    }]
+    }];
+    SerializedLevel {
+        objects,
+        settings: LevelSettings::default(),
+    }
 }
 
-pub fn init_level(
+pub(crate) fn init_level(
     mut commands: Commands,
-    mut init_level_objects: ResMut<InitLevelObjects>,
-    mut entity_net_id_counter: ResMut<EntityNetIdCounter>,
+    mut init_level_data: ResMut<InitLevelData>,
+    mut entity_net_id_allocator: ResMut<EntityNetIdAllocator>,
     mut spawn_level_object_commands: ResMut<DeferredQueue<UpdateLevelObject>>,
+    mut update_level_settings_commands: ResMut<DeferredQueue<UpdateLevelSettings>>,
 ) {
-    let level_objects_to_spawn = std::mem::take(&mut init_level_objects.0);
+    let SerializedLevel {
+        objects: level_objects_to_spawn,
+        settings,
+    } = std::mem::take(&mut init_level_data.0);
     commands.insert_resource(LevelObjectsToSpawnToLoad(level_objects_to_spawn.len()));
     log::info!(
         "Level objects to spawn to load: {}",
         level_objects_to_spawn.len()
     );
+    // Builders can't make spawn areas too small, but levels saved before the check
+    // was introduced (or edited by hand) may still have them.
+    if let Err(err) =
+        validate_spawnable_area(spawnable_area(&level_objects_to_spawn), PLAYER_CAPACITY)
+    {
+        log::warn!("The loaded level is invalid: {err}");
+    }
 
+    update_level_settings_commands.push(UpdateLevelSettings { settings });
     for level_object in level_objects_to_spawn {
-        assert_eq!(entity_net_id_counter.increment(), level_object.net_id);
+        // Levels saved by the persistence service have sequential ids, but a level
+        // file edited by hand may have gaps, which get allocated for new objects.
+        if let Err(err) = entity_net_id_allocator.reserve(level_object.net_id) {
+            panic!("Level object ids are expected to be unique: {err}");
+        }
         spawn_level_object_commands.push(UpdateLevelObject {
             frame_number: FrameNumber::new(0),
             object: level_object,
@@ -381,19 +550,31 @@ pub fn init_level(
     }
 }
 
-pub fn process_idle_timeout(
-    mut is_shutting_down: Local<bool>,
+/// Shutting down takes a few seconds, see `ServerShutdown`.
+pub(crate) fn process_idle_timeout(
     idle_timeout: Res<IdleTimeout>,
+    drain_signal: Res<DrainSignal>,
     last_player_disconnected_at: Res<LastPlayerDisconnectedAt>,
     players: Res<Players>,
+    mut server_shutdown: ResMut<ServerShutdown>,
     agones: Option<Res<Agones>>,
+    mut app_exit_events: EventWriter<AppExit>,
 ) {
+    let now = Instant::now();
+    let is_draining = drain_signal.load(std::sync::atomic::Ordering::SeqCst);
     if players.is_empty()
-        && Instant::now().duration_since(last_player_disconnected_at.0) > idle_timeout.0
-        && !*is_shutting_down
+        && (is_draining || now.duration_since(last_player_disconnected_at.0) > idle_timeout.0)
+        && !server_shutdown.is_started()
     {
-        log::info!("Shutting down due to being idle...");
-        *is_shutting_down = true;
+        if is_draining {
+            log::info!("Shutting down due to being drained...");
+        } else {
+            log::info!("Shutting down due to being idle...");
+        }
+        server_shutdown.start(now);
+    }
+
+    if server_shutdown.take_exit(now) {
         if let Some(agones) = agones {
             let mut sdk = agones.sdk.clone();
             TOKIO.spawn(async move {
@@ -403,7 +584,8 @@ pub fn process_idle_timeout(
                 }
             });
         } else {
-            std::process::exit(0);
+            // Lets the app runner return, so that embedders get to clean up.
+            app_exit_events.send(AppExit);
         }
     }
 }
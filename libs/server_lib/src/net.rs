@@ -1,5 +1,16 @@
 use crate::{
-    Agones, LastPlayerDisconnectedAt, MuddleServerConfig, PersistenceMessage,
+    admin::{admin_permissions, ConnectedAdmins},
+    bans::BanList,
+    bots::PracticeBots,
+    guests::GuestSessions,
+    interest_management::AreasOfInterest,
+    level_reload::{LevelReload, ReloadLevelRequest},
+    pings::Pings,
+    player_updates::InputViolations,
+    server_health::ServerHealthMonitor,
+    shutdown::ServerShutdown,
+    solo_practice::SoloPractice,
+    Agones, DrainSignal, LastPlayerDisconnectedAt, MuddleServerConfig, PersistenceMessage,
     PersistenceMessageReceiver, PersistenceRequest, PersistenceRequestSender, TOKIO,
 };
 use bevy::{
@@ -9,23 +20,33 @@ use bevy::{
     utils::{Entry, HashMap, HashSet, Instant},
 };
 use bevy_disturbulence::{ConnectionHandle, NetworkEvent, NetworkResource, ServerAddrs};
-use mr_messages_lib::{GetLevelResponse, PLAYER_CAPACITY};
+use mr_messages_lib::{
+    validation::{format_errors, validate_display_name},
+    GetLevelResponse, PrivacySettings, PLAYER_CAPACITY, SERVER_DRAIN_ANNOTATION,
+};
 use mr_shared_lib::{
+    constants::ConstantsFingerprint,
+    delta_compression::{encode_player_states, StatePrecision},
     game::{
         commands::{self, DeferredPlayerQueues, DeferredQueue, DespawnReason},
         components::{PlayerDirection, Position, Spawned},
-        level::{LevelObject, LevelState},
+        determinism::{DeterminismGuard, StateHashRequest},
+        game_mode::CurrentGameMode,
+        level::{LevelObject, LevelSettings, LevelState},
+        level_objects::ColliderSimplification,
+        tether::Tethers,
         PlayerEventSender,
     },
     messages::{
-        DeferredMessagesQueue, DeltaUpdate, DisconnectReason, DisconnectedPlayer, EntityNetId,
-        Message, PlayerInputs, PlayerNetId, PlayerState, ReliableClientMessage,
-        ReliableServerMessage, RespawnPlayer, RunnerInput, SpawnLevelObject,
-        SpawnLevelObjectRequest, StartGame, SwitchRole, UnreliableClientMessage,
-        UnreliableServerMessage,
+        AdminCommand, BuilderState, DeferredMessagesQueue, DeltaUpdate, DisconnectReason,
+        DisconnectedPlayer, EntityNetId, InvalidLevelObjectShape, Message, PlayerInputs,
+        PlayerNetId, PlayerState, PracticeBotsRequest, PracticeCheckpoint, PublishLevelReport,
+        PublishLevelRequest, ReliableClientMessage, ReliableServerMessage, RespawnPlayer,
+        RunnerInput, ServerHealth, SessionStats, SpawnLevelObject, SpawnLevelObjectRequest,
+        StartGame, SwitchRole, TeleportRequest, UnreliableClientMessage, UnreliableServerMessage,
     },
     net::{ConnectionState, ConnectionStatus, MessageId, SessionId, CONNECTION_TIMEOUT_MILLIS},
-    player::{random_name, Player, PlayerEvent, PlayerRole, Players},
+    player::{random_name, LifetimeStats, Player, PlayerEvent, PlayerRole, Players},
     registry::{EntityRegistry, Registry},
     server::level_spawn_location_service::LevelSpawnLocationService,
     GameTime, SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT,
@@ -35,23 +56,23 @@ use std::{
     marker::PhantomData,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Deref,
+    sync::{atomic::Ordering, LazyLock},
     time::Duration,
 };
 use tokio::sync::mpsc::UnboundedSender;
 
-pub fn watch_agones_updates(
+static CONSTANTS_FINGERPRINT: LazyLock<ConstantsFingerprint> =
+    LazyLock::new(ConstantsFingerprint::current);
+
+/// Resolves once the GameServer gets allocated, the sender gets dropped if the
+/// watch fails before that. Is expected to be called after marking the
+/// GameServer as Ready.
+pub(crate) fn watch_agones_updates(
     mut agones_sdk: rymder::Sdk,
+    drain_signal: DrainSignal,
 ) -> tokio::sync::oneshot::Receiver<GameServer> {
     let (tx, rx) = tokio::sync::oneshot::channel();
     TOKIO.spawn(async move {
-        log::info!("Marking the GameServer as Ready...");
-        if let Err(err) = agones_sdk.mark_ready().await {
-            log::error!(
-                "Failed to mark the Game Server as Ready, exiting: {:?}",
-                err
-            );
-            std::process::exit(1);
-        }
         let mut stream = match agones_sdk.watch_gameserver().await {
             Ok(stream) => stream,
             Err(err) => {
@@ -62,6 +83,23 @@ pub fn watch_agones_updates(
         let mut tx = Some(tx);
         while let Some(Ok(game_server)) = stream.next().await {
             log::debug!("GameServer update: {:#?}", game_server);
+            let is_draining = game_server.object_meta.as_ref().map_or(false, |metadata| {
+                metadata
+                    .annotations
+                    .get(SERVER_DRAIN_ANNOTATION)
+                    .map_or(false, |drain| drain == "true")
+            });
+            if is_draining && !drain_signal.swap(true, Ordering::SeqCst) {
+                log::info!("Received a drain signal");
+                // Not being allocated yet, we can shut down straight away.
+                if tx.is_some() {
+                    log::info!("Shutting down the drained GameServer...");
+                    if let Err(err) = agones_sdk.shutdown().await {
+                        log::error!("Failed to request shutdown, exiting: {:?}", err);
+                        std::process::exit(0);
+                    }
+                }
+            }
             if let Some(status) = game_server.status.as_ref() {
                 if status.state == rymder::gameserver::State::Allocated {
                     if let Some(tx) = tx.take() {
@@ -118,6 +156,20 @@ pub struct ConnectionStates(pub HashMap<u32, ConnectionState>);
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct NewPlayerConnections(pub Vec<(PlayerNetId, u32)>);
 
+/// Maps connection handles to the ids of registered users. Is used for
+/// reporting presence to the persistence service.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct RegisteredUsers(pub HashMap<u32, i64>);
+
+/// Consent flags of registered users, keyed by connection handles. Guests
+/// don't have an entry, which is equivalent to not consenting to anything.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct PrivacyConsents(pub HashMap<u32, PrivacySettings>);
+
+/// The latest states reported by builders, are broadcast with delta updates.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct BuilderStates(pub HashMap<PlayerNetId, BuilderState>);
+
 #[derive(SystemParam)]
 pub struct UpdateParams<'w, 's> {
     deferred_player_updates: ResMut<'w, DeferredPlayerQueues<RunnerInput>>,
@@ -125,23 +177,66 @@ pub struct UpdateParams<'w, 's> {
     spawn_level_object_requests: ResMut<'w, DeferredPlayerQueues<SpawnLevelObjectRequest>>,
     update_level_object_requests: ResMut<'w, DeferredPlayerQueues<LevelObject>>,
     despawn_level_object_requests: ResMut<'w, DeferredPlayerQueues<EntityNetId>>,
+    update_level_settings_requests: ResMut<'w, DeferredPlayerQueues<LevelSettings>>,
+    publish_level_requests: ResMut<'w, DeferredPlayerQueues<PublishLevelRequest>>,
+    reload_level_requests: ResMut<'w, DeferredPlayerQueues<ReloadLevelRequest>>,
+    state_hash_requests: ResMut<'w, DeferredPlayerQueues<StateHashRequest>>,
+    admin_commands: ResMut<'w, DeferredPlayerQueues<AdminCommand>>,
     spawn_player_commands: ResMut<'w, DeferredQueue<commands::SpawnPlayer>>,
     despawn_player_commands: ResMut<'w, DeferredQueue<commands::DespawnPlayer>>,
+    coordination: CoordinationParams<'w, 's>,
+    practice: PracticeParams<'w, 's>,
+}
+
+/// Requests that are accepted only in practice sessions.
+#[derive(SystemParam)]
+pub struct PracticeParams<'w, 's> {
+    restart_from_checkpoint_requests: ResMut<'w, DeferredPlayerQueues<PracticeCheckpoint>>,
+    practice_bots_requests: ResMut<'w, DeferredPlayerQueues<PracticeBotsRequest>>,
+    teleport_requests: ResMut<'w, DeferredPlayerQueues<TeleportRequest>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// What players share to coordinate with each other.
+#[derive(SystemParam)]
+pub struct CoordinationParams<'w, 's> {
+    builder_states: ResMut<'w, BuilderStates>,
+    pings: ResMut<'w, Pings>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
 
+#[derive(SystemParam)]
+pub struct HandshakeParams<'w, 's> {
+    level_spawn_location_service: LevelSpawnLocationService<'w, 's>,
+    ban_list: ResMut<'w, BanList>,
+    /// New clients are rejected once the server starts shutting down.
+    server_shutdown: ResMut<'w, ServerShutdown>,
+    /// Only the player the server is allocated for can join a solo practice
+    /// server.
+    solo_practice: Option<Res<'w, SoloPractice>>,
+    guest_sessions: ResMut<'w, GuestSessions>,
+}
+
 #[derive(SystemParam)]
 pub struct NetworkParams<'w, 's> {
     net: NonSendMut<'w, NetworkResource>,
     connection_states: ResMut<'w, ConnectionStates>,
     player_connections: ResMut<'w, PlayerConnections>,
     new_player_connections: ResMut<'w, NewPlayerConnections>,
+    registered_users: ResMut<'w, RegisteredUsers>,
+    privacy_consents: ResMut<'w, PrivacyConsents>,
+    connected_admins: ResMut<'w, ConnectedAdmins>,
     last_player_disconnected_at: ResMut<'w, LastPlayerDisconnectedAt>,
     players_tracking_channel: ResMut<'w, PlayerEventSender>,
     pending_requests: Local<'s, HashMap<MessageId, ConnectionHandle>>,
     persistence_req_tx: Res<'w, PersistenceRequestSender>,
     persistence_msg_rx: ResMut<'w, PersistenceMessageReceiver>,
+    publish_level_reports: ResMut<'w, DeferredPlayerQueues<PublishLevelReport>>,
+    invalid_level_object_shapes: ResMut<'w, DeferredPlayerQueues<InvalidLevelObjectShape>>,
+    input_violations: ResMut<'w, InputViolations>,
+    level_reload: ResMut<'w, LevelReload>,
 }
 
 pub fn process_network_events_system(
@@ -151,7 +246,7 @@ pub fn process_network_events_system(
     mut network_events: EventReader<NetworkEvent>,
     mut network_params: NetworkParams,
     mut update_params: UpdateParams,
-    level_spawn_location_service: LevelSpawnLocationService,
+    mut handshake_params: HandshakeParams,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -207,7 +302,13 @@ pub fn process_network_events_system(
     if let Some(msg_rx) = &mut **network_params.persistence_msg_rx {
         while let Ok(persistence_message) = msg_rx.try_recv() {
             match persistence_message {
-                PersistenceMessage::UserInfoResponse { id, user } => {
+                PersistenceMessage::UserInfoResponse {
+                    id,
+                    user,
+                    privacy,
+                    stats,
+                    admin_permissions: permissions,
+                } => {
                     let handle = network_params
                         .pending_requests
                         .get(&id)
@@ -227,13 +328,70 @@ pub fn process_network_events_system(
                         ));
                         continue;
                     };
+                    if let Some(solo_practice) = &handshake_params.solo_practice {
+                        if solo_practice.user_id != user.id {
+                            log::info!(
+                                "Rejecting a user {} ({}): the solo practice server is allocated for user {}",
+                                user.id,
+                                handle,
+                                solo_practice.user_id
+                            );
+                            disconnect_messages_to_send.push((
+                                *handle,
+                                Message {
+                                    session_id: SessionId::new(0),
+                                    message: ReliableServerMessage::Disconnect(
+                                        DisconnectReason::PrivateServer,
+                                    ),
+                                },
+                            ));
+                            continue;
+                        }
+                    }
+                    let ip_addr = network_params
+                        .net
+                        .connections
+                        .get(handle)
+                        .and_then(|connection| connection.remote_address())
+                        .map(|address| address.ip());
+                    if let Some(ban) = handshake_params.ban_list.find(Some(user.id), ip_addr) {
+                        log::info!(
+                            "Rejecting a banned user {} ({}): {:?}",
+                            user.id,
+                            handle,
+                            ban
+                        );
+                        disconnect_messages_to_send.push((
+                            *handle,
+                            Message {
+                                session_id: SessionId::new(0),
+                                message: ReliableServerMessage::Disconnect(
+                                    DisconnectReason::Banned {
+                                        expires_at: ban.expires_at,
+                                    },
+                                ),
+                            },
+                        ));
+                        continue;
+                    }
+                    network_params.registered_users.insert(*handle, user.id);
+                    network_params.privacy_consents.insert(*handle, privacy);
+                    let permissions = admin_permissions(permissions);
+                    if permissions.any() {
+                        log::info!("User {} has admin permissions: {:?}", user.id, permissions);
+                        network_params.connected_admins.insert(*handle, permissions);
+                    }
 
                     let uuid = uuid::Uuid::new_v4().to_string();
                     let player = Player {
                         uuid,
+                        lifetime_stats: stats.map(|stats| LifetimeStats {
+                            finishes: stats.finishes.try_into().unwrap_or(u32::MAX),
+                            deaths: stats.deaths.try_into().unwrap_or(u32::MAX),
+                        }),
                         ..Player::new_with_nickname(
                             PlayerRole::Runner,
-                            user.display_name.unwrap_or_else(random_name),
+                            player_nickname(user.display_name),
                         )
                     };
                     log::debug!("Registering a player: {}", player.nickname);
@@ -251,13 +409,28 @@ pub fn process_network_events_system(
                         deps,
                         player,
                         &mut update_params,
-                        &level_spawn_location_service,
+                        &handshake_params.level_spawn_location_service,
                         *handle,
                     );
                     connection_state.set_status(ConnectionStatus::Handshaking);
                 }
                 PersistenceMessage::SaveLevelResponse(_) => {
-                    log::warn!("TODO: cover `PersistenceMessage::SaveLevelResponse`");
+                    // Errors are already logged by the persistence task.
+                    handshake_params.server_shutdown.receive_level_save();
+                }
+                PersistenceMessage::PublishLevelResponse {
+                    player_net_id,
+                    report,
+                } => {
+                    network_params
+                        .publish_level_reports
+                        .push(player_net_id, report);
+                }
+                PersistenceMessage::ReloadLevelResponse(result) => {
+                    network_params.level_reload.receive(result);
+                }
+                PersistenceMessage::BanListResponse(result) => {
+                    handshake_params.ban_list.receive(result);
                 }
             }
         }
@@ -265,6 +438,7 @@ pub fn process_network_events_system(
 
     // Reading message channels.
     for (handle, connection) in network_params.net.connections.iter_mut() {
+        let ip_addr = connection.remote_address().map(|address| address.ip());
         let channels = connection.channels().unwrap();
 
         while let Some(client_message) = channels.recv::<Message<UnreliableClientMessage>>() {
@@ -396,26 +570,61 @@ pub fn process_network_events_system(
                         }
                     }
 
-                    // Builders don't send any useful inputs that we need to track with unreliable
-                    // messages atm.
-                    if let PlayerInputs::Runner { inputs } = update.inputs {
-                        for input in inputs {
-                            if input.frame_number.diff_abs(time.frame_number).value()
-                                <= COMPONENT_FRAMEBUFFER_LIMIT / 2
-                            {
-                                update_params
-                                    .deferred_player_updates
-                                    .push(player_net_id, input);
-                            } else {
-                                log::warn!(
-                                    "Player {} is out of sync (input frame {}, current frame: {}), skipping the update",
-                                    player_net_id.0,
-                                    input.frame_number,
-                                    time.frame_number
+                    match update.inputs {
+                        PlayerInputs::Runner { inputs } => {
+                            for input in inputs {
+                                if input.frame_number.diff_abs(time.frame_number).value()
+                                    <= COMPONENT_FRAMEBUFFER_LIMIT / 2
+                                {
+                                    update_params
+                                        .deferred_player_updates
+                                        .push(player_net_id, input);
+                                } else {
+                                    log::warn!(
+                                        "Player {} is out of sync (input frame {}, current frame: {}), skipping the update",
+                                        player_net_id.0,
+                                        input.frame_number,
+                                        time.frame_number
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
+                        PlayerInputs::Builder {
+                            camera_position,
+                            selected_object,
+                        } => {
+                            let is_builder = players
+                                .get(&player_net_id)
+                                .map_or(false, |player| player.role == PlayerRole::Builder);
+                            if is_builder {
+                                update_params.coordination.builder_states.insert(
+                                    player_net_id,
+                                    BuilderState {
+                                        net_id: player_net_id,
+                                        camera_position,
+                                        selected_object,
+                                    },
                                 );
-                                continue;
                             }
                         }
+                        // Spectators don't send any useful inputs that we need to track with
+                        // unreliable messages atm.
+                        PlayerInputs::Spectator => {}
+                        // We keep simulating the last received inputs.
+                        PlayerInputs::Unchanged => {}
+                    }
+                }
+                UnreliableClientMessage::Ping(ping) => {
+                    let can_ping = players
+                        .get(&player_net_id)
+                        .map_or(false, |player| player.role != PlayerRole::Spectator);
+                    if can_ping {
+                        update_params.coordination.pings.receive(
+                            player_net_id,
+                            ping,
+                            Instant::now(),
+                        );
                     }
                 }
                 UnreliableClientMessage::Connect(_) => {}
@@ -446,6 +655,9 @@ pub fn process_network_events_system(
                 ReliableClientMessage::Handshake {
                     message_id: handshake_id,
                     id_token,
+                    guest_id,
+                    guest_nickname,
+                    constants,
                 } => {
                     log::info!("Client ({}) handshake: {}", handle, handshake_id);
                     let connection_state = network_params
@@ -466,6 +678,60 @@ pub fn process_network_events_system(
                         break;
                     }
 
+                    let mismatches = CONSTANTS_FINGERPRINT.mismatches(&constants);
+                    if !mismatches.is_empty() {
+                        log::warn!(
+                            "Rejecting a client ({}) built with different gameplay constants: {}",
+                            handle,
+                            mismatches
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                        disconnect_messages_to_send.push((
+                            *handle,
+                            Message {
+                                session_id: SessionId::new(0),
+                                message: ReliableServerMessage::Disconnect(
+                                    DisconnectReason::ConstantsMismatch,
+                                ),
+                            },
+                        ));
+                        break;
+                    }
+
+                    // Bans of registered users are checked once they are fetched.
+                    if let Some(ban) = handshake_params.ban_list.find(None, ip_addr) {
+                        log::info!("Rejecting a banned client ({}): {:?}", handle, ban);
+                        disconnect_messages_to_send.push((
+                            *handle,
+                            Message {
+                                session_id: SessionId::new(0),
+                                message: ReliableServerMessage::Disconnect(
+                                    DisconnectReason::Banned {
+                                        expires_at: ban.expires_at,
+                                    },
+                                ),
+                            },
+                        ));
+                        break;
+                    }
+
+                    if handshake_params.server_shutdown.is_started() {
+                        log::info!("Rejecting a client ({}): shutting down", handle);
+                        disconnect_messages_to_send.push((
+                            *handle,
+                            Message {
+                                session_id: SessionId::new(0),
+                                message: ReliableServerMessage::Disconnect(
+                                    DisconnectReason::ServerShutdown,
+                                ),
+                            },
+                        ));
+                        break;
+                    }
+
                     if let Some(id_token) = id_token {
                         let Some(req_tx) = &**network_params.persistence_req_tx else {
                             disconnect_messages_to_send.push((
@@ -490,12 +756,35 @@ pub fn process_network_events_system(
                         break;
                     }
 
-                    let nickname = random_name();
-                    let uuid = uuid::Uuid::new_v4().to_string();
+                    if handshake_params.solo_practice.is_some() {
+                        log::info!(
+                            "Rejecting an anonymous client ({}): solo practice server",
+                            handle
+                        );
+                        disconnect_messages_to_send.push((
+                            *handle,
+                            Message {
+                                session_id: SessionId::new(0),
+                                message: ReliableServerMessage::Disconnect(
+                                    DisconnectReason::PrivateServer,
+                                ),
+                            },
+                        ));
+                        break;
+                    }
+
+                    let guest_id = guest_id.filter(|guest_id| !guest_id.is_nil());
+                    let player = match guest_id {
+                        Some(guest_id) => handshake_params
+                            .guest_sessions
+                            .player(guest_id, guest_nickname.and_then(valid_guest_nickname)),
+                        None => Player::new_with_nickname(PlayerRole::Runner, random_name()),
+                    };
                     let player = Player {
-                        uuid,
-                        ..Player::new_with_nickname(PlayerRole::Runner, nickname)
+                        uuid: uuid::Uuid::new_v4().to_string(),
+                        ..player
                     };
+                    let guest = guest_id.map(|guest_id| (guest_id, player.nickname.clone()));
                     log::debug!("Registering an anonymous player: {}", player.nickname);
                     let deps = RegisterPlayerDeps {
                         players: &mut players,
@@ -511,9 +800,17 @@ pub fn process_network_events_system(
                         deps,
                         player,
                         &mut update_params,
-                        &level_spawn_location_service,
+                        &handshake_params.level_spawn_location_service,
                         *handle,
                     );
+                    if let Some((guest_id, nickname)) = guest {
+                        handshake_params.guest_sessions.connect(
+                            *handle,
+                            guest_id,
+                            nickname,
+                            Instant::now(),
+                        );
+                    }
                     connection_state.set_status(ConnectionStatus::Handshaking);
                 }
                 ReliableClientMessage::SwitchRole(role) => {
@@ -594,6 +891,170 @@ pub fn process_network_events_system(
                         .despawn_level_object_requests
                         .push(player_net_id, despawned_level_object_net_id);
                 }
+                ReliableClientMessage::UpdateLevelSettings(level_settings) => {
+                    log::trace!(
+                        "Client ({}) requests to update level settings: {:?}",
+                        handle,
+                        level_settings
+                    );
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .update_level_settings_requests
+                        .push(player_net_id, level_settings);
+                }
+                ReliableClientMessage::RestartFromCheckpoint(checkpoint) => {
+                    log::debug!(
+                        "Client ({}) requests to restart from a checkpoint: {:?}",
+                        handle,
+                        checkpoint
+                    );
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .practice
+                        .restart_from_checkpoint_requests
+                        .push(player_net_id, checkpoint);
+                }
+                ReliableClientMessage::PracticeBots(request) => {
+                    log::debug!("Client ({}) requests practice bots: {:?}", handle, request);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .practice
+                        .practice_bots_requests
+                        .push(player_net_id, request);
+                }
+                ReliableClientMessage::Teleport(request) => {
+                    log::debug!("Client ({}) requests to teleport: {:?}", handle, request);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .practice
+                        .teleport_requests
+                        .push(player_net_id, request);
+                }
+                ReliableClientMessage::PublishLevel(request) => {
+                    log::debug!(
+                        "Client ({}) requests to publish the level: {:?}",
+                        handle,
+                        request
+                    );
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .publish_level_requests
+                        .push(player_net_id, request);
+                }
+                ReliableClientMessage::ReloadLevel => {
+                    log::debug!("Client ({}) requests to reload the level", handle);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .reload_level_requests
+                        .push(player_net_id, ReloadLevelRequest);
+                }
+                ReliableClientMessage::StateHashRequest(request) => {
+                    log::debug!("Client ({}) requests state hashes: {:?}", handle, request);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params
+                        .state_hash_requests
+                        .push(player_net_id, request);
+                }
+                ReliableClientMessage::AdminCommand(command) => {
+                    log::debug!("Client ({}) sends an admin command: {:?}", handle, command);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+                        continue;
+                    }
+                    let player_net_id = network_params
+                        .player_connections
+                        .get_id(*handle)
+                        .expect("Expected a registered player net id for an existing connection");
+                    update_params.admin_commands.push(player_net_id, command);
+                }
+                ReliableClientMessage::Suspend => {
+                    log::info!("Client ({}) is suspended, disconnecting", handle);
+                    let connection_state = network_params
+                        .connection_states
+                        .get_mut(handle)
+                        .expect("Expected a connection state for an existing connection");
+                    if matches!(
+                        connection_state.status(),
+                        ConnectionStatus::Disconnecting(_) | ConnectionStatus::Disconnected
+                    ) {
+                        continue;
+                    }
+                    connection_state
+                        .set_status(ConnectionStatus::Disconnecting(DisconnectReason::Suspended));
+                }
             }
 
             if let Some(connection_state) = network_params.connection_states.get_mut(handle) {
@@ -637,6 +1098,7 @@ pub fn process_network_events_system(
         &mut network_params,
         &mut update_params,
         &mut players,
+        &mut handshake_params.guest_sessions,
     );
 }
 
@@ -696,6 +1158,7 @@ fn disconnect_players(
     network_params: &mut NetworkParams,
     update_params: &mut UpdateParams,
     players: &mut Players,
+    guest_sessions: &mut GuestSessions,
 ) {
     // Disconnecting players that have been failing to deliver updates for some
     // time.
@@ -739,6 +1202,21 @@ fn disconnect_players(
         }
     }
 
+    for player_net_id in network_params.input_violations.take_suspects() {
+        let Some(handle) = network_params.player_connections.get_value(player_net_id) else {
+            continue;
+        };
+        let Some(connection_state) = network_params.connection_states.get_mut(&handle) else {
+            continue;
+        };
+        if matches!(connection_state.status(), ConnectionStatus::Connected) {
+            log::warn!("Disconnecting {}: cheating is suspected", handle);
+            connection_state.set_status(ConnectionStatus::Disconnecting(
+                DisconnectReason::CheatSuspected,
+            ));
+        }
+    }
+
     // FixedTimestep may run this several times in a row. We want to make sure that
     // we despawn a player only once.
     despawned_players_for_handles
@@ -772,6 +1250,13 @@ fn disconnect_players(
                     .get_mut(&player_net_id)
                     .expect("Expected a registered player with an existing player_net_id");
                 player.is_connected = false;
+                guest_sessions.leave(*connection_handle, player, Instant::now());
+                update_params
+                    .coordination
+                    .builder_states
+                    .remove(&player_net_id);
+                update_params.coordination.pings.forget(player_net_id);
+                network_params.input_violations.forget(player_net_id);
                 // If a player is going to be respawned due to a Finish or Death event, we want
                 // to prevent it.
                 player.respawning_at = None;
@@ -796,6 +1281,9 @@ fn disconnect_players(
         network_params.connection_states.remove(&handle);
         network_params.net.disconnect(handle);
         network_params.player_connections.remove_by_value(handle);
+        network_params.registered_users.remove(&handle);
+        network_params.privacy_consents.remove(&handle);
+        network_params.connected_admins.remove(&handle);
     }
 }
 
@@ -806,6 +1294,9 @@ pub struct DeferredMessageQueues<'w, 's> {
     spawn_level_object_messages: ResMut<'w, DeferredMessagesQueue<SpawnLevelObject>>,
     update_level_object_messages: ResMut<'w, DeferredMessagesQueue<commands::UpdateLevelObject>>,
     despawn_level_object_messages: ResMut<'w, DeferredMessagesQueue<commands::DespawnLevelObject>>,
+    update_level_settings_messages:
+        ResMut<'w, DeferredMessagesQueue<commands::UpdateLevelSettings>>,
+    update_tethers_messages: ResMut<'w, DeferredMessagesQueue<Tethers>>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
@@ -817,6 +1308,10 @@ pub struct FetchedLevelInfo(pub GetLevelResponse);
 pub struct LevelParams<'w, 's> {
     fetched_level_info: Option<Res<'w, FetchedLevelInfo>>,
     level_state: Res<'w, LevelState>,
+    collider_simplification: Res<'w, ColliderSimplification>,
+    tethers: Res<'w, Tethers>,
+    current_game_mode: Res<'w, CurrentGameMode>,
+    solo_practice: Option<Res<'w, SoloPractice>>,
     #[system_param(ignore)]
     marker: PhantomData<&'s ()>,
 }
@@ -835,14 +1330,19 @@ pub struct PlayerParams<'w, 's> {
         ),
     >,
     players_registry: Res<'w, EntityRegistry<PlayerNetId>>,
+    builder_states: Res<'w, BuilderStates>,
+    areas_of_interest: ResMut<'w, AreasOfInterest>,
+    determinism_guard: Res<'w, DeterminismGuard>,
 }
 
 pub fn send_network_updates_system(
     mut network_params: NetworkParams,
     time: Res<SimulationTime>,
     level_params: LevelParams,
-    player_params: PlayerParams,
+    mut player_params: PlayerParams,
     mut deferred_message_queues: DeferredMessageQueues,
+    mut practice_bots: ResMut<PracticeBots>,
+    server_health_monitor: Res<ServerHealthMonitor>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
@@ -854,17 +1354,26 @@ pub fn send_network_updates_system(
     broadcast_start_game_messages(
         &mut network_params,
         &time,
-        level_params
-            .fetched_level_info
-            .as_deref()
-            .map(|info| info.deref()),
-        &level_params.level_state,
+        &level_params,
         &player_params.players,
         &player_params.player_entities,
         &player_params.players_registry,
+        &mut player_params.areas_of_interest,
     );
 
-    for (&_connection_player_net_id, &connection_handle) in network_params.player_connections.iter()
+    let new_players = network_params
+        .new_player_connections
+        .iter()
+        .map(|(player_net_id, _)| *player_net_id)
+        .chain(practice_bots.spawned.drain(..))
+        .collect::<Vec<_>>();
+    let skips_delta_updates = level_params
+        .solo_practice
+        .as_ref()
+        .map_or(false, |solo_practice| {
+            solo_practice.skips_broadcast(time.server_frame)
+        });
+    for (&connection_player_net_id, &connection_handle) in network_params.player_connections.iter()
     {
         let connection_state = network_params
             .connection_states
@@ -875,19 +1384,35 @@ pub fn send_network_updates_system(
             continue;
         }
 
-        broadcast_delta_update_messages(
-            &mut network_params.net,
-            &time,
+        if let Some(area_of_interest_update) = player_params.areas_of_interest.update(
+            connection_player_net_id,
+            time.server_frame,
             &player_params.players,
-            &player_params.player_entities,
-            &player_params.players_registry,
-            connection_handle,
-            connection_state,
-        );
+            &player_params.builder_states,
+        ) {
+            send_reliable_game_message(
+                &mut network_params.net,
+                connection_handle,
+                connection_state,
+                ReliableServerMessage::UpdateAreaOfInterest(area_of_interest_update),
+            );
+        }
+
+        if !skips_delta_updates {
+            broadcast_delta_update_messages(
+                &mut network_params.net,
+                &time,
+                &player_params,
+                server_health_monitor.health,
+                connection_player_net_id,
+                connection_handle,
+                connection_state,
+            );
+        }
 
         send_new_player_messages(
             &mut network_params.net,
-            &network_params.new_player_connections,
+            &new_players,
             &player_params.players,
             connection_handle,
             connection_state,
@@ -949,12 +1474,87 @@ pub fn send_network_updates_system(
             ReliableServerMessage::DespawnLevelObject(despawn_level_object_message),
         );
     }
+    for update_level_settings_message in deferred_message_queues
+        .update_level_settings_messages
+        .drain()
+        .into_iter()
+    {
+        broadcast_reliable_game_message(
+            &mut network_params.net,
+            &network_params.connection_states,
+            ReliableServerMessage::UpdateLevelSettings(update_level_settings_message),
+        );
+    }
+    for update_tethers_message in deferred_message_queues
+        .update_tethers_messages
+        .drain()
+        .into_iter()
+    {
+        broadcast_reliable_game_message(
+            &mut network_params.net,
+            &network_params.connection_states,
+            ReliableServerMessage::UpdateTethers(update_tethers_message),
+        );
+    }
+    let player_messages = network_params
+        .publish_level_reports
+        .drain()
+        .into_iter()
+        .flat_map(|(player_net_id, reports)| {
+            reports.into_iter().map(move |report| {
+                (
+                    player_net_id,
+                    ReliableServerMessage::PublishLevelReport(report),
+                )
+            })
+        })
+        .chain(
+            network_params
+                .invalid_level_object_shapes
+                .drain()
+                .into_iter()
+                .flat_map(|(player_net_id, shapes)| {
+                    shapes.into_iter().map(move |shape| {
+                        (
+                            player_net_id,
+                            ReliableServerMessage::InvalidLevelObjectShape(shape),
+                        )
+                    })
+                }),
+        )
+        .collect::<Vec<_>>();
+    for (player_net_id, message) in player_messages {
+        let Some(connection_handle) = network_params.player_connections.get_value(player_net_id)
+        else {
+            continue;
+        };
+        let Some(connection_state) = network_params.connection_states.get(&connection_handle)
+        else {
+            continue;
+        };
+        if !matches!(connection_state.status(), ConnectionStatus::Connected) {
+            continue;
+        }
+        if let Err(err) = network_params.net.send_message(
+            connection_handle,
+            Message {
+                session_id: connection_state.session_id,
+                message,
+            },
+        ) {
+            log::error!("Failed to send a message: {:?}", err);
+        }
+    }
 
     network_params.new_player_connections.clear();
 }
 
-pub fn broadcast_disconnected_players_system(mut network_params: NetworkParams) {
-    let mut disconnected_players = Vec::new();
+pub fn broadcast_disconnected_players_system(
+    mut network_params: NetworkParams,
+    mut practice_bots: ResMut<PracticeBots>,
+    players: Res<Players>,
+) {
+    let mut disconnected_players = std::mem::take(&mut practice_bots.removed);
     for (&connection_handle, connection_state) in network_params.connection_states.iter_mut() {
         let ConnectionStatus::Disconnecting(reason) = connection_state.status() else {
             continue;
@@ -964,6 +1564,22 @@ pub fn broadcast_disconnected_players_system(mut network_params: NetworkParams)
             network_params.player_connections.get_id(connection_handle)
         {
             disconnected_players.push(connection_player_net_id);
+
+            if let Some(player) = players.get(&connection_player_net_id) {
+                if let Err(err) = network_params.net.send_message(
+                    connection_handle,
+                    Message {
+                        session_id: connection_state.session_id,
+                        message: ReliableServerMessage::SessionStats(SessionStats {
+                            finishes: player.finishes,
+                            deaths: player.deaths,
+                            best_finish: player.best_finish,
+                        }),
+                    },
+                ) {
+                    log::error!("Failed to send a message: {:?}", err);
+                }
+            }
         }
 
         if let Err(err) = network_params.net.send_message(
@@ -1009,9 +1625,9 @@ pub fn broadcast_disconnected_players_system(mut network_params: NetworkParams)
 fn broadcast_delta_update_messages(
     net: &mut NetworkResource,
     time: &SimulationTime,
-    players: &Players,
-    player_entities: &Query<(Entity, &Position, &PlayerDirection, &Spawned)>,
-    players_registry: &EntityRegistry<PlayerNetId>,
+    player_params: &PlayerParams,
+    server_health: ServerHealth,
+    connection_player_net_id: PlayerNetId,
     connection_handle: u32,
     connection_state: &mut ConnectionState,
 ) {
@@ -1020,19 +1636,57 @@ fn broadcast_delta_update_messages(
         return;
     }
 
+    let players_state = player_params
+        .players
+        .iter()
+        .filter(|(&player_net_id, _player)| {
+            player_params
+                .areas_of_interest
+                .is_visible(connection_player_net_id, player_net_id)
+        })
+        .filter_map(|(&player_net_id, _player)| {
+            player_params
+                .players_registry
+                .get_entity(player_net_id)
+                .and_then(|entity| {
+                    create_player_state(player_net_id, time, entity, &player_params.player_entities)
+                })
+        })
+        .collect::<Vec<_>>();
+    // Clients wouldn't be able to reproduce the state hashes of the server
+    // with quantized positions.
+    let precision = if player_params.determinism_guard.enabled {
+        StatePrecision::Exact
+    } else {
+        StatePrecision::Quantized
+    };
+    let baseline = connection_state
+        .newest_acknowledged_outgoing_packet()
+        .and_then(|frame_number| {
+            connection_state
+                .player_states_history
+                .get(frame_number)
+                .map(|snapshot| (frame_number, snapshot))
+        });
+    let (players, snapshot) = encode_player_states(&players_state, precision, baseline);
+
     let message = UnreliableServerMessage::DeltaUpdate(DeltaUpdate {
         frame_number: time.server_frame,
         acknowledgments: connection_state.incoming_acknowledgments(),
-        players: players
-            .iter()
-            .filter_map(|(&player_net_id, _player)| {
-                players_registry
-                    .get_entity(player_net_id)
-                    .and_then(|entity| {
-                        create_player_state(player_net_id, time, entity, player_entities)
-                    })
+        players,
+        builders: player_params
+            .builder_states
+            .values()
+            .filter(|builder_state| {
+                // Builders may have switched roles since their last update.
+                player_params
+                    .players
+                    .get(&builder_state.net_id)
+                    .map_or(false, |player| player.role == PlayerRole::Builder)
             })
+            .cloned()
             .collect(),
+        server_health,
     });
 
     if let Err(err) = net.send_message(
@@ -1045,17 +1699,20 @@ fn broadcast_delta_update_messages(
         log::error!("Failed to send a message: {:?}", err);
     }
 
+    connection_state
+        .player_states_history
+        .push(time.server_frame, snapshot);
     connection_state.add_outgoing_packet(time.server_frame, Instant::now());
 }
 
 fn send_new_player_messages(
     net: &mut NetworkResource,
-    new_player_connections: &[(PlayerNetId, u32)],
+    new_players: &[PlayerNetId],
     players: &Players,
     connection_handle: u32,
     connection_state: &ConnectionState,
 ) {
-    if !new_player_connections.is_empty() {
+    if !new_players.is_empty() {
         log::trace!(
             "Sending new players to {}: {:?}",
             connection_handle,
@@ -1063,7 +1720,7 @@ fn send_new_player_messages(
         );
     }
     // Broadcasting updates about new connected players.
-    for (connected_player_net_id, _connection_handle) in new_player_connections.iter() {
+    for connected_player_net_id in new_players {
         let player = players
             .get(connected_player_net_id)
             .expect("Expected a registered Player");
@@ -1076,12 +1733,16 @@ fn send_new_player_messages(
 fn broadcast_start_game_messages(
     network_params: &mut NetworkParams,
     time: &SimulationTime,
-    level_info: Option<&GetLevelResponse>,
-    level_state: &LevelState,
+    level_params: &LevelParams,
     players: &Players,
     player_entities: &Query<(Entity, &Position, &PlayerDirection, &Spawned)>,
     players_registry: &EntityRegistry<PlayerNetId>,
+    areas_of_interest: &mut AreasOfInterest,
 ) {
+    let level_info: Option<&GetLevelResponse> = level_params
+        .fetched_level_info
+        .as_deref()
+        .map(|info| info.deref());
     // Broadcasting updates about new connected players.
     for (connected_player_net_id, connected_player_connection_handle) in
         &**network_params.new_player_connections
@@ -1116,6 +1777,10 @@ fn broadcast_start_game_messages(
                     })
             })
             .collect();
+        areas_of_interest.reset(
+            *connected_player_net_id,
+            players_state.iter().map(|player_state| player_state.net_id),
+        );
 
         let message = ReliableServerMessage::StartGame(StartGame {
             handshake_id: connection_state.handshake_id,
@@ -1123,14 +1788,25 @@ fn broadcast_start_game_messages(
             uuid: connected_player.uuid.clone(),
             nickname: connected_player.nickname.clone(),
             level_id: level_info.map(|level_info| level_info.level.id),
-            objects: level_state
-                .objects
+            level_title: level_info.map(|level_info| level_info.level.title.clone()),
+            collider_simplification: *level_params.collider_simplification,
+            tethers: level_params.tethers.clone(),
+            game_mode: level_params.current_game_mode.kind(),
+            admin_permissions: network_params
+                .connected_admins
+                .get(connected_player_connection_handle)
+                .copied()
+                .unwrap_or_default(),
+            objects: level_params
+                .level_state
+                .objects()
                 .iter()
                 .map(|(_, level_object)| commands::UpdateLevelObject {
                     object: level_object.clone(),
                     frame_number: time.server_frame,
                 })
                 .collect(),
+            settings: level_params.level_state.settings().clone(),
             players: players
                 .iter()
                 .map(|(net_id, player)| (*net_id, player.clone()))
@@ -1139,7 +1815,11 @@ fn broadcast_start_game_messages(
             game_state: DeltaUpdate {
                 frame_number: time.server_frame,
                 acknowledgments: connection_state.incoming_acknowledgments(),
-                players: players_state,
+                // Is sent once, so we don't bother with quantizing the states.
+                players: encode_player_states(&players_state, StatePrecision::Exact, None).0,
+                // Builder states will arrive with the next `DeltaUpdate` message.
+                builders: Vec::new(),
+                server_health: ServerHealth::default(),
             },
         });
 
@@ -1213,7 +1893,7 @@ fn create_player_state(
     })
 }
 
-fn broadcast_reliable_game_message(
+pub fn broadcast_reliable_game_message(
     net: &mut NetworkResource,
     connection_states: &HashMap<u32, ConnectionState>,
     message: ReliableServerMessage,
@@ -1227,7 +1907,7 @@ fn broadcast_reliable_game_message(
     }
 }
 
-fn send_reliable_game_message(
+pub fn send_reliable_game_message(
     net: &mut NetworkResource,
     connection_handle: u32,
     connection_state: &ConnectionState,
@@ -1312,3 +1992,33 @@ fn public_id_addr(
 
     None
 }
+
+/// Display names registered before validation was introduced may still be
+/// invalid, in which case players get a random name, as if they were anonymous.
+fn player_nickname(display_name: Option<String>) -> String {
+    match display_name.as_deref().map(validate_display_name) {
+        Some(Ok(display_name)) => display_name.to_owned(),
+        Some(Err(errors)) => {
+            log::warn!(
+                "Using a random nickname: {}",
+                format_errors("Display name", &errors)
+            );
+            random_name()
+        }
+        None => random_name(),
+    }
+}
+
+/// Guests can pick any nickname that would be a valid display name.
+fn valid_guest_nickname(nickname: String) -> Option<String> {
+    match validate_display_name(&nickname) {
+        Ok(nickname) => Some(nickname.to_owned()),
+        Err(errors) => {
+            log::warn!(
+                "Ignoring a guest nickname: {}",
+                format_errors("Nickname", &errors)
+            );
+            None
+        }
+    }
+}
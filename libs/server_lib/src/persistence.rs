@@ -1,29 +1,50 @@
 use crate::{
-    net::FetchedLevelInfo, PersistenceMessageSender, PersistenceRequestReceiver,
-    PersistenceRequestSender, TOKIO,
+    net::{
+        broadcast_reliable_game_message, ConnectionStates, FetchedLevelInfo, PlayerConnections,
+        RegisteredUsers,
+    },
+    shutdown::ServerShutdown,
+    solo_practice::SoloPractice,
+    Agones, PersistenceMessageSender, PersistenceRequestReceiver, PersistenceRequestSender, TOKIO,
 };
 use bevy::{
-    ecs::system::{Local, Res, ResMut, Resource},
+    ecs::system::{Local, NonSendMut, Res, ResMut, Resource, SystemParam},
     log,
     prelude::{Deref, DerefMut},
-    utils::{HashMap, Instant},
+    utils::{HashMap, HashSet, Instant},
 };
+use bevy_disturbulence::NetworkResource;
 use mr_messages_lib::{
-    ErrorResponse, GetLevelResponse, GetRegisteredUserQuery, GetUserResponse, LevelData, LevelDto,
-    PostLevelRequest, PostLevelResponse, RegisteredUser,
+    validation::{sanitize_text, LEVEL_OBJECT_LABEL_MAX_LEN},
+    AdminPermissions, Ban, DeleteBansQuery, ErrorResponse, GetLevelResponse,
+    GetRegisteredUserQuery, GetUserResponse, LevelData, LevelDto, PatchLevelRequest, PlayerStats,
+    PostAdminActionRequest, PostBanRequest, PostLevelRequest, PostLevelResponse,
+    PostPlayerStatsRequest, PostPresenceRequest, PrivacySettings, RegisteredUser,
 };
 use mr_shared_lib::{
-    game::level::{LevelObject, LevelState, ObjectRouteDesc},
-    messages::EntityNetId,
+    game::{
+        level::{
+            validate_level, LevelObject, LevelState, LevelValidationError, ObjectRouteDesc,
+            SerializedLevel,
+        },
+        level_objects::ColliderSimplification,
+    },
+    messages::{
+        EntityNetId, LevelCheck, PlayerNetId, PublishLevelReport, PublishLevelStatus,
+        ReliableServerMessage, RespawnPlayerReason,
+    },
     net::MessageId,
     registry::IncrementId,
 };
-use mr_utils_lib::jwks::poll_jwks;
+use mr_utils_lib::{jwks::poll_jwks, telemetry::TraceSpan};
 use reqwest::{Client, Url};
-use std::{ops::Deref, time::Duration};
+use std::{marker::PhantomData, ops::Deref, time::Duration};
 use tokio::sync::mpsc::UnboundedSender;
 
 const LEVEL_AUTOSAVE_PERIOD_SECS: u64 = 60;
+/// The persistence service forgets presence that isn't reported for 90 seconds.
+const PRESENCE_REPORT_PERIOD_SECS: u64 = 30;
+const PLAYER_STATS_REPORT_PERIOD_SECS: u64 = 10;
 
 #[derive(Resource, Clone)]
 pub struct PersistenceConfig {
@@ -39,8 +60,34 @@ pub struct Jwks(pub mr_utils_lib::jwks::Jwks);
 
 #[derive(Debug)]
 pub enum PersistenceRequest {
-    GetUser { id: MessageId, id_token: String },
+    GetUser {
+        id: MessageId,
+        id_token: String,
+    },
     SaveLevel(PostLevelRequest),
+    ReportPresence(PostPresenceRequest),
+    ReportPlayerStats {
+        user_id: i64,
+        stats: PostPlayerStatsRequest,
+    },
+    ReportAdminAction(PostAdminActionRequest),
+    GetBans,
+    /// Re-fetches the ban list once the ban is saved.
+    Ban(PostBanRequest),
+    /// Re-fetches the ban list once the bans are deleted.
+    Unban(DeleteBansQuery),
+    /// Saves the current state of the level before publishing it, so that
+    /// the published version is the one that has passed the checks.
+    PublishLevel {
+        player_net_id: PlayerNetId,
+        level_id: i64,
+        autosave: PostLevelRequest,
+        checks: Vec<LevelCheck>,
+    },
+    /// Re-fetches the level to apply the changes saved on other servers.
+    ReloadLevel {
+        level_id: i64,
+    },
 }
 
 #[derive(Debug)]
@@ -48,15 +95,30 @@ pub enum PersistenceMessage {
     UserInfoResponse {
         id: MessageId,
         user: Option<RegisteredUser>,
+        privacy: PrivacySettings,
+        /// Is `None` if the stats couldn't be fetched.
+        stats: Option<PlayerStats>,
+        /// Are all disabled if the permissions couldn't be fetched.
+        admin_permissions: AdminPermissions,
     },
     SaveLevelResponse(Result<PostLevelResponse, String>),
+    PublishLevelResponse {
+        player_net_id: PlayerNetId,
+        report: PublishLevelReport,
+    },
+    ReloadLevelResponse(Result<(GetLevelResponse, SerializedLevel), String>),
+    BanListResponse(Result<Vec<Ban>, String>),
 }
 
-pub async fn get_user(persistence_url: Url, user_id: i64) -> anyhow::Result<GetUserResponse> {
+pub async fn get_user(
+    persistence_url: Url,
+    user_id: i64,
+    span: &TraceSpan,
+) -> anyhow::Result<GetUserResponse> {
     let client = reqwest::Client::new();
 
-    let result = client
-        .get(persistence_url.join(&format!("users/{user_id}")).unwrap())
+    let result = span
+        .propagate(client.get(persistence_url.join(&format!("users/{user_id}")).unwrap()))
         .send()
         .await?;
 
@@ -79,17 +141,18 @@ pub async fn get_user(persistence_url: Url, user_id: i64) -> anyhow::Result<GetU
 }
 
 #[derive(Resource, Deref, DerefMut)]
-pub struct InitLevelObjects(pub Vec<LevelObject>);
+pub struct InitLevelData(pub SerializedLevel);
 
 pub async fn load_level(
     persistence_url: Url,
     level_id: i64,
-) -> anyhow::Result<(GetLevelResponse, InitLevelObjects)> {
+    span: &TraceSpan,
+) -> anyhow::Result<(GetLevelResponse, InitLevelData)> {
     log::info!("Loading a level: {level_id}...");
     let client = reqwest::Client::new();
 
-    let result = client
-        .get(persistence_url.join(&format!("levels/{level_id}")).unwrap())
+    let result = span
+        .propagate(client.get(persistence_url.join(&format!("levels/{level_id}")).unwrap()))
         .send()
         .await?;
 
@@ -109,8 +172,8 @@ pub async fn load_level(
     }
 
     let mut response: GetLevelResponse = serde_json::from_slice(&data)?;
-    let level_objects: Vec<LevelObject> = serde_json::from_value(response.level.data.take())?;
-    Ok((response, InitLevelObjects(level_objects)))
+    let level: SerializedLevel = serde_json::from_value(response.level.data.take())?;
+    Ok((response, InitLevelData(level)))
 }
 
 pub async fn create_level(
@@ -119,6 +182,7 @@ pub async fn create_level(
     user_name: Option<String>,
     title: String,
     level_data: LevelData,
+    span: &TraceSpan,
 ) -> anyhow::Result<GetLevelResponse> {
     let response = post_level(
         persistence_url,
@@ -127,6 +191,7 @@ pub async fn create_level(
             user_id,
             data: level_data.clone(),
         },
+        Some(span),
     )
     .await?;
     Ok(GetLevelResponse {
@@ -143,6 +208,8 @@ pub async fn create_level(
             },
             created_at: response.created_at,
             updated_at: response.updated_at,
+            // Game servers read localizations from the level settings.
+            localizations: Default::default(),
         },
         autosaved_versions: Vec::new(),
         level_permissions: Vec::new(),
@@ -152,14 +219,17 @@ pub async fn create_level(
 async fn post_level(
     persistence_url: Url,
     post_level_request: &PostLevelRequest,
+    span: Option<&TraceSpan>,
 ) -> anyhow::Result<PostLevelResponse> {
     let client = reqwest::Client::new();
 
-    let result = client
+    let mut request = client
         .post(persistence_url.join("levels").unwrap())
-        .json(post_level_request)
-        .send()
-        .await?;
+        .json(post_level_request);
+    if let Some(span) = span {
+        request = span.propagate(request);
+    }
+    let result = request.send().await?;
 
     let status = result.status();
     let data = result.bytes().await?;
@@ -179,6 +249,181 @@ async fn post_level(
     Ok(serde_json::from_slice(&data)?)
 }
 
+async fn patch_level(
+    client: Client,
+    persistence_url: Url,
+    level_id: i64,
+    patch_level_request: &PatchLevelRequest,
+) -> anyhow::Result<()> {
+    let result = client
+        .patch(persistence_url.join(&format!("levels/{level_id}")).unwrap())
+        .json(patch_level_request)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn publish_level(
+    client: Client,
+    persistence_url: Url,
+    level_id: i64,
+    autosave: &PostLevelRequest,
+) -> anyhow::Result<()> {
+    post_level(persistence_url.clone(), autosave, None).await?;
+    patch_level(
+        client,
+        persistence_url,
+        level_id,
+        &PatchLevelRequest {
+            title: None,
+            builder_ids: None,
+            published: Some(true),
+        },
+    )
+    .await
+}
+
+async fn post_presence(
+    client: Client,
+    persistence_url: Url,
+    post_presence_request: &PostPresenceRequest,
+) -> anyhow::Result<()> {
+    let result = client
+        .post(persistence_url.join("presence").unwrap())
+        .json(post_presence_request)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn post_player_stats(
+    client: Client,
+    persistence_url: Url,
+    user_id: i64,
+    post_player_stats_request: &PostPlayerStatsRequest,
+) -> anyhow::Result<()> {
+    let result = client
+        .post(
+            persistence_url
+                .join(&format!("users/{user_id}/stats"))
+                .unwrap(),
+        )
+        .json(post_player_stats_request)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn post_admin_action(
+    client: Client,
+    persistence_url: Url,
+    post_admin_action_request: &PostAdminActionRequest,
+) -> anyhow::Result<()> {
+    let result = client
+        .post(persistence_url.join("admin_actions").unwrap())
+        .json(post_admin_action_request)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn get_bans(client: &Client, persistence_url: &Url) -> anyhow::Result<Vec<Ban>> {
+    let response = client
+        .get(persistence_url.join("bans").unwrap())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+async fn post_ban(
+    client: &Client,
+    persistence_url: &Url,
+    post_ban_request: &PostBanRequest,
+) -> anyhow::Result<()> {
+    let result = client
+        .post(persistence_url.join("bans").unwrap())
+        .json(post_ban_request)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn delete_bans(
+    client: &Client,
+    persistence_url: &Url,
+    delete_bans_query: &DeleteBansQuery,
+) -> anyhow::Result<()> {
+    let result = client
+        .delete(persistence_url.join("bans").unwrap())
+        .query(delete_bans_query)
+        .send()
+        .await?;
+
+    let status = result.status();
+    if !status.is_success() {
+        let data = result.bytes().await?;
+        let error: ErrorResponse<()> = serde_json::from_slice(&data)?;
+        return Err(anyhow::Error::msg(error.message));
+    }
+
+    Ok(())
+}
+
+async fn refresh_ban_list(
+    client: Client,
+    persistence_url: Url,
+    response_tx: UnboundedSender<PersistenceMessage>,
+) {
+    let result = get_bans(&client, &persistence_url).await.map_err(|err| {
+        log::error!("Failed to get bans: {:?}", err);
+        "Failed to get bans".to_owned()
+    });
+    if let Err(err) = response_tx.send(PersistenceMessage::BanListResponse(result)) {
+        log::error!("Failed to send a persistence message: {:?}", err);
+    }
+}
+
 pub fn init_jwks_polling(config: Option<Res<PersistenceConfig>>, jwks: Res<Jwks>) {
     if config.is_none() {
         return;
@@ -198,47 +443,272 @@ pub fn init_jwks_polling(config: Option<Res<PersistenceConfig>>, jwks: Res<Jwks>
     TOKIO.spawn(poll_jwks(client, auth0_certs_url, jwks));
 }
 
+/// Problems that have prevented the latest autosave. Builders get notified
+/// whenever the list changes.
+#[derive(Resource, Default)]
+pub struct LevelSaveErrors {
+    errors: Vec<LevelValidationError>,
+    is_broadcast_pending: bool,
+}
+
+impl LevelSaveErrors {
+    fn reject(&mut self, errors: Vec<LevelValidationError>) {
+        self.errors = errors;
+        self.is_broadcast_pending = true;
+    }
+
+    fn clear(&mut self) {
+        if !self.errors.is_empty() {
+            self.errors.clear();
+            self.is_broadcast_pending = true;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Autosaves {
+    last_sent: Option<Instant>,
+    saved_revision: Option<u64>,
+}
+
+/// Saves the level right away once the server disconnects everyone before
+/// shutting down.
 pub fn save_level_system(
-    mut last_sent: Local<Option<Instant>>,
+    mut autosaves: Local<Autosaves>,
     request_tx: Res<PersistenceRequestSender>,
     fetched_level_info: Option<Res<FetchedLevelInfo>>,
     level_state: Res<LevelState>,
+    collider_simplification: Res<ColliderSimplification>,
+    mut level_save_errors: ResMut<LevelSaveErrors>,
+    mut server_shutdown: ResMut<ServerShutdown>,
 ) {
+    let is_flushing = server_shutdown.take_level_flush_request(Instant::now());
     let request_tx = match &**request_tx {
         Some(request_tx) => request_tx,
         None => return,
     };
+    let Autosaves {
+        last_sent,
+        saved_revision,
+    } = &mut *autosaves;
+
+    if !is_flushing {
+        if last_sent.is_none() {
+            *last_sent = Some(Instant::now());
+            return;
+        }
 
-    if last_sent.is_none() {
-        *last_sent = Some(Instant::now());
+        if Instant::now().duration_since(last_sent.unwrap())
+            < Duration::from_secs(LEVEL_AUTOSAVE_PERIOD_SECS)
+        {
+            return;
+        }
+    }
+    *last_sent = Some(Instant::now());
+
+    if *saved_revision == Some(level_state.revision()) {
         return;
     }
+    match saved_revision.and_then(|revision| level_state.changes_since(revision)) {
+        Some(changes) => {
+            let changed_objects = changes
+                .filter_map(|(_, change)| change.net_id())
+                .collect::<HashSet<_>>();
+            log::info!(
+                "Autosaving the level ({} objects changed)...",
+                changed_objects.len()
+            );
+        }
+        None => log::info!("Autosaving the level..."),
+    }
+    *saved_revision = Some(level_state.revision());
 
-    if Instant::now().duration_since(last_sent.unwrap())
-        < Duration::from_secs(LEVEL_AUTOSAVE_PERIOD_SECS)
-    {
+    // An invalid level would fail to load (or crash the server) next time.
+    if let Err(errors) = validate_level(level_state.objects().values(), *collider_simplification) {
+        log::warn!(
+            "Rejecting the autosave, the level is invalid: {}",
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        level_save_errors.reject(errors);
         return;
     }
+    level_save_errors.clear();
 
-    log::info!("Autosaving the level...");
-    *last_sent = Some(Instant::now());
+    let request = autosave_request(&fetched_level_info.unwrap(), &level_state);
+    if let Err(err) = request_tx.send(PersistenceRequest::SaveLevel(request)) {
+        log::error!("Failed to send a persistence request: {:?}", err);
+    } else if is_flushing {
+        server_shutdown.await_level_save();
+    }
+}
+
+pub fn send_level_save_errors_system(
+    mut net: NonSendMut<NetworkResource>,
+    connection_states: Res<ConnectionStates>,
+    mut level_save_errors: ResMut<LevelSaveErrors>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    if !level_save_errors.is_broadcast_pending {
+        return;
+    }
+    level_save_errors.is_broadcast_pending = false;
+    broadcast_reliable_game_message(
+        &mut net,
+        &connection_states,
+        ReliableServerMessage::LevelSaveErrors(level_save_errors.errors.clone()),
+    );
+}
 
-    let level_objects = remap_net_ids(&level_state.objects);
-    let fetched_level_info = fetched_level_info.unwrap().into_inner();
-    let request = PostLevelRequest {
+pub fn autosave_request(
+    fetched_level_info: &FetchedLevelInfo,
+    level_state: &LevelState,
+) -> PostLevelRequest {
+    let mut objects = remap_net_ids(level_state.objects());
+    // Labels are sanitized when builders update objects, but levels saved
+    // before that was introduced may still contain anything.
+    for object in &mut objects {
+        object.label = sanitize_text(&object.label, LEVEL_OBJECT_LABEL_MAX_LEN);
+    }
+    let level = SerializedLevel {
+        objects,
+        settings: level_state.settings().clone(),
+    };
+    PostLevelRequest {
         title: fetched_level_info.level.title.clone(),
         user_id: fetched_level_info.level.user_id,
         data: LevelData::Autosaved {
             autosaved_level_id: fetched_level_info.level.id,
-            data: serde_json::to_value(level_objects).unwrap(),
+            data: serde_json::to_value(level).unwrap(),
         },
+    }
+}
+
+pub fn report_presence_system(
+    mut last_reported: Local<Option<(Instant, Vec<i64>)>>,
+    request_tx: Res<PersistenceRequestSender>,
+    agones: Option<Res<Agones>>,
+    fetched_level_info: Option<Res<FetchedLevelInfo>>,
+    registered_users: Res<RegisteredUsers>,
+    solo_practice: Option<Res<SoloPractice>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    // Friends can join only the servers that are listed by the matchmaker.
+    let (Some(request_tx), Some(agones), Some(fetched_level_info), None) =
+        (&**request_tx, agones, fetched_level_info, solo_practice)
+    else {
+        return;
+    };
+    let Some(server_name) = agones
+        .game_server
+        .object_meta
+        .as_ref()
+        .map(|metadata| metadata.name.clone())
+    else {
+        return;
     };
 
-    if let Err(err) = request_tx.send(PersistenceRequest::SaveLevel(request)) {
+    let mut user_ids = registered_users.values().copied().collect::<Vec<_>>();
+    user_ids.sort_unstable();
+    user_ids.dedup();
+
+    if let Some((reported_at, reported_user_ids)) = &*last_reported {
+        if *reported_user_ids == user_ids
+            && Instant::now().duration_since(*reported_at)
+                < Duration::from_secs(PRESENCE_REPORT_PERIOD_SECS)
+        {
+            return;
+        }
+    }
+    *last_reported = Some((Instant::now(), user_ids.clone()));
+
+    let request = PostPresenceRequest {
+        server_name,
+        level_id: fetched_level_info.level.id,
+        level_title: fetched_level_info.level.title.clone(),
+        user_ids,
+    };
+    if let Err(err) = request_tx.send(PersistenceRequest::ReportPresence(request)) {
         log::error!("Failed to send a persistence request: {:?}", err);
     }
 }
 
+/// Finishes and deaths of registered users that haven't been reported to the
+/// persistence service yet, keyed by user ids. Counts of players who have
+/// already disconnected are still reported.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct PendingPlayerStats(pub HashMap<i64, PostPlayerStatsRequest>);
+
+#[derive(SystemParam)]
+pub struct PlayerStatsRecorder<'w, 's> {
+    player_connections: Res<'w, PlayerConnections>,
+    registered_users: Res<'w, RegisteredUsers>,
+    pending_player_stats: ResMut<'w, PendingPlayerStats>,
+    solo_practice: Option<Res<'w, SoloPractice>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> PlayerStatsRecorder<'w, 's> {
+    /// Respawns of guests and bots are ignored, as they don't have lifetime
+    /// stats. Solo practice runs don't count towards them either.
+    pub fn record(&mut self, net_id: PlayerNetId, reason: RespawnPlayerReason) {
+        if self.solo_practice.is_some() {
+            return;
+        }
+        let Some(user_id) = self
+            .player_connections
+            .get_value(net_id)
+            .and_then(|handle| self.registered_users.get(&handle))
+        else {
+            return;
+        };
+        let stats = self.pending_player_stats.entry(*user_id).or_default();
+        match reason {
+            RespawnPlayerReason::Finish => stats.finishes += 1,
+            RespawnPlayerReason::Death => stats.deaths += 1,
+            RespawnPlayerReason::Checkpoint => {}
+        }
+    }
+}
+
+pub fn report_player_stats_system(
+    mut last_reported: Local<Option<Instant>>,
+    request_tx: Res<PersistenceRequestSender>,
+    mut pending_player_stats: ResMut<PendingPlayerStats>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let Some(request_tx) = &**request_tx else {
+        return;
+    };
+    if last_reported.map_or(false, |last_reported| {
+        Instant::now().duration_since(last_reported)
+            < Duration::from_secs(PLAYER_STATS_REPORT_PERIOD_SECS)
+    }) {
+        return;
+    }
+    *last_reported = Some(Instant::now());
+
+    for (user_id, stats) in pending_player_stats.drain() {
+        if stats.is_empty() {
+            continue;
+        }
+        if let Err(err) = request_tx.send(PersistenceRequest::ReportPlayerStats { user_id, stats })
+        {
+            log::error!("Failed to send a persistence request: {:?}", err);
+        }
+    }
+}
+
 fn remap_net_ids(level_objects_map: &HashMap<EntityNetId, LevelObject>) -> Vec<LevelObject> {
     let mut level_objects: Vec<LevelObject> = Vec::new();
     let mut ids_map: HashMap<EntityNetId, EntityNetId> = HashMap::default();
@@ -344,7 +814,13 @@ pub fn handle_persistence_requests(
                         Err(err) => {
                             log::warn!("Invalid JWT: {:?}", err);
                             response_tx
-                                .send(PersistenceMessage::UserInfoResponse { id, user: None })
+                                .send(PersistenceMessage::UserInfoResponse {
+                                    id,
+                                    user: None,
+                                    privacy: PrivacySettings::default(),
+                                    stats: None,
+                                    admin_permissions: AdminPermissions::default(),
+                                })
                                 .expect("Failed to send a persistence message");
                             continue;
                         }
@@ -365,7 +841,7 @@ pub fn handle_persistence_requests(
                     let persistence_url = config.private_url.clone();
                     let response_tx = response_tx.clone();
                     tokio::spawn(async move {
-                        let result = post_level(persistence_url, &post_level_request)
+                        let result = post_level(persistence_url, &post_level_request, None)
                             .await
                             .map_err(|err| {
                                 log::error!("Failed to autosave the level: {:?}", err);
@@ -378,6 +854,127 @@ pub fn handle_persistence_requests(
                         }
                     });
                 }
+                Some(PersistenceRequest::PublishLevel {
+                    player_net_id,
+                    level_id,
+                    autosave,
+                    checks,
+                }) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    let response_tx = response_tx.clone();
+                    tokio::spawn(async move {
+                        let result =
+                            publish_level(client, persistence_url, level_id, &autosave).await;
+                        let status = match result {
+                            Ok(()) => PublishLevelStatus::Published,
+                            Err(err) => {
+                                log::error!("Failed to publish the level: {:?}", err);
+                                PublishLevelStatus::Failed("Failed to save the level".to_owned())
+                            }
+                        };
+                        if let Err(err) =
+                            response_tx.send(PersistenceMessage::PublishLevelResponse {
+                                player_net_id,
+                                report: PublishLevelReport { checks, status },
+                            })
+                        {
+                            log::error!("Failed to send a persistence message: {:?}", err);
+                        }
+                    });
+                }
+                Some(PersistenceRequest::ReloadLevel { level_id }) => {
+                    let persistence_url = config.public_url.clone();
+                    let response_tx = response_tx.clone();
+                    tokio::spawn(async move {
+                        let result = load_level(
+                            persistence_url,
+                            level_id,
+                            &TraceSpan::start("reload_level", None),
+                        )
+                        .await
+                        .map(|(response, InitLevelData(level))| (response, level))
+                        .map_err(|err| {
+                            log::error!("Failed to reload the level: {:?}", err);
+                            "Failed to reload the level".to_owned()
+                        });
+                        if let Err(err) =
+                            response_tx.send(PersistenceMessage::ReloadLevelResponse(result))
+                        {
+                            log::error!("Failed to send a persistence message: {:?}", err);
+                        }
+                    });
+                }
+                Some(PersistenceRequest::ReportPresence(post_presence_request)) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) =
+                            post_presence(client, persistence_url, &post_presence_request).await
+                        {
+                            log::warn!("Failed to report presence: {:?}", err);
+                        }
+                    });
+                }
+                Some(PersistenceRequest::ReportPlayerStats { user_id, stats }) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) =
+                            post_player_stats(client, persistence_url, user_id, &stats).await
+                        {
+                            log::warn!(
+                                "Failed to report player stats (user: {}, {:?}): {:?}",
+                                user_id,
+                                stats,
+                                err
+                            );
+                        }
+                    });
+                }
+                Some(PersistenceRequest::ReportAdminAction(request)) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = post_admin_action(client, persistence_url, &request).await
+                        {
+                            log::error!(
+                                "Failed to report an admin action ({:?}): {:?}",
+                                request,
+                                err
+                            );
+                        }
+                    });
+                }
+                Some(PersistenceRequest::GetBans) => {
+                    tokio::spawn(refresh_ban_list(
+                        client.clone(),
+                        config.private_url.clone(),
+                        response_tx.clone(),
+                    ));
+                }
+                Some(PersistenceRequest::Ban(request)) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    let response_tx = response_tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = post_ban(&client, &persistence_url, &request).await {
+                            log::error!("Failed to save a ban ({:?}): {:?}", request, err);
+                        }
+                        refresh_ban_list(client, persistence_url, response_tx).await;
+                    });
+                }
+                Some(PersistenceRequest::Unban(query)) => {
+                    let persistence_url = config.private_url.clone();
+                    let client = client.clone();
+                    let response_tx = response_tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = delete_bans(&client, &persistence_url, &query).await {
+                            log::warn!("Failed to delete bans ({:?}): {:?}", query, err);
+                        }
+                        refresh_ban_list(client, persistence_url, response_tx).await;
+                    });
+                }
                 None => {
                     log::error!("Persistence channel closed");
                     return;
@@ -408,6 +1005,9 @@ async fn get_registered_user(
                 .send(PersistenceMessage::UserInfoResponse {
                     id: request_id,
                     user: None,
+                    privacy: PrivacySettings::default(),
+                    stats: None,
+                    admin_permissions: AdminPermissions::default(),
                 })
                 .expect("Failed to send a persistence message");
             return;
@@ -422,16 +1022,111 @@ async fn get_registered_user(
                 .send(PersistenceMessage::UserInfoResponse {
                     id: request_id,
                     user: None,
+                    privacy: PrivacySettings::default(),
+                    stats: None,
+                    admin_permissions: AdminPermissions::default(),
                 })
                 .expect("Failed to send a persistence message");
             return;
         }
     };
 
+    // Failing to fetch the consent flags shouldn't prevent a player from joining,
+    // they are just treated as if they haven't consented to anything.
+    let privacy = get_privacy_settings(&client, &config, registered_user.id)
+        .await
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Failed to get privacy settings (user: {}): {:?}",
+                registered_user.id,
+                err
+            );
+            PrivacySettings::default()
+        });
+
+    let stats = get_player_stats(&client, &config, registered_user.id)
+        .await
+        .map_err(|err| {
+            log::warn!(
+                "Failed to get player stats (user: {}): {:?}",
+                registered_user.id,
+                err
+            );
+        })
+        .ok();
+
+    let admin_permissions = get_admin_permissions(&client, &config, registered_user.id)
+        .await
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Failed to get admin permissions (user: {}): {:?}",
+                registered_user.id,
+                err
+            );
+            AdminPermissions::default()
+        });
+
     response_tx
         .send(PersistenceMessage::UserInfoResponse {
             id: request_id,
             user: Some(registered_user),
+            privacy,
+            stats,
+            admin_permissions,
         })
         .expect("Failed to send a persistence message");
 }
+
+async fn get_privacy_settings(
+    client: &Client,
+    config: &PersistenceConfig,
+    user_id: i64,
+) -> anyhow::Result<PrivacySettings> {
+    let response = client
+        .get(
+            config
+                .private_url
+                .join(&format!("users/{user_id}/privacy"))
+                .unwrap(),
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+async fn get_player_stats(
+    client: &Client,
+    config: &PersistenceConfig,
+    user_id: i64,
+) -> anyhow::Result<PlayerStats> {
+    let response = client
+        .get(
+            config
+                .public_url
+                .join(&format!("users/{user_id}/stats"))
+                .unwrap(),
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+async fn get_admin_permissions(
+    client: &Client,
+    config: &PersistenceConfig,
+    user_id: i64,
+) -> anyhow::Result<AdminPermissions> {
+    let response = client
+        .get(
+            config
+                .private_url
+                .join(&format!("users/{user_id}/admin_permissions"))
+                .unwrap(),
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
@@ -0,0 +1,77 @@
+use crate::net::{ConnectionStates, PlayerConnections};
+use bevy::{
+    ecs::system::{NonSendMut, Res, ResMut, Resource},
+    log,
+    utils::HashMap,
+};
+use bevy_disturbulence::NetworkResource;
+use mr_shared_lib::{
+    messages::{
+        Message, Ping, PlayerNetId, PlayerPing, UnreliableServerMessage, PING_MIN_INTERVAL_MILLIS,
+    },
+    net::ConnectionStatus,
+};
+use std::time::{Duration, Instant};
+
+/// Pings accepted during the current frame, and when each player placed their
+/// latest one.
+#[derive(Resource, Default)]
+pub struct Pings {
+    pending: Vec<PlayerPing>,
+    last_accepted_at: HashMap<PlayerNetId, Instant>,
+}
+
+impl Pings {
+    /// Drops the pings that are placed more often than
+    /// `PING_MIN_INTERVAL_MILLIS`.
+    pub fn receive(&mut self, net_id: PlayerNetId, ping: Ping, now: Instant) {
+        if !ping.position.is_finite() {
+            return;
+        }
+        if let Some(last_accepted_at) = self.last_accepted_at.get(&net_id) {
+            if now.duration_since(*last_accepted_at)
+                < Duration::from_millis(PING_MIN_INTERVAL_MILLIS)
+            {
+                log::trace!("Dropping a ping from player {}: throttled", net_id.0);
+                return;
+            }
+        }
+        self.last_accepted_at.insert(net_id, now);
+        self.pending.push(PlayerPing { net_id, ping });
+    }
+
+    pub fn forget(&mut self, net_id: PlayerNetId) {
+        self.last_accepted_at.remove(&net_id);
+    }
+}
+
+pub fn broadcast_pings_system(
+    mut net: NonSendMut<NetworkResource>,
+    connection_states: Res<ConnectionStates>,
+    player_connections: Res<PlayerConnections>,
+    mut pings: ResMut<Pings>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for player_ping in std::mem::take(&mut pings.pending) {
+        let sender_handle = player_connections.get_value(player_ping.net_id);
+        for (&connection_handle, connection_state) in connection_states.iter() {
+            if Some(connection_handle) == sender_handle
+                || !matches!(connection_state.status(), ConnectionStatus::Connected)
+            {
+                continue;
+            }
+
+            if let Err(err) = net.send_message(
+                connection_handle,
+                Message {
+                    session_id: connection_state.session_id,
+                    message: UnreliableServerMessage::Ping(player_ping),
+                },
+            ) {
+                log::error!("Failed to send a message: {:?}", err);
+            }
+        }
+    }
+}
@@ -1,25 +1,142 @@
 use crate::net::{ConnectionStates, PlayerConnections};
 use bevy::{
-    ecs::system::{Res, ResMut},
+    ecs::{
+        event::EventReader,
+        system::{Res, ResMut, Resource, SystemParam},
+    },
     log,
+    math::Vec2,
+    prelude::{Deref, DerefMut},
+    utils::HashMap,
+};
+use mr_messages_lib::{
+    validation::{sanitize_text, validate_level_localizations, LEVEL_OBJECT_LABEL_MAX_LEN},
+    PLAYER_CAPACITY,
 };
 use mr_shared_lib::{
     framebuffer::FrameNumber,
     game::{
         commands::{
             DeferredPlayerQueues, DeferredQueue, DespawnLevelObject, SwitchPlayerRole,
-            UpdateLevelObject,
+            UpdateLevelObject, UpdateLevelSettings,
+        },
+        events::LevelObjectShapeInvalid,
+        level::{
+            validate_spawnable_area_change, CollisionLogic, LevelObject, LevelObjectDesc,
+            LevelSettings, LevelState,
         },
-        level::{CollisionLogic, LevelObject, LevelState},
     },
-    messages::{self, DeferredMessagesQueue, EntityNetId, EntityNetIdCounter, RunnerInput},
+    id_allocator::IdAllocationError,
+    messages::{
+        self, DeferredMessagesQueue, EntityNetId, EntityNetIdAllocator, PlayerNetId, RunnerInput,
+    },
     player::{Player, PlayerDirectionUpdate, PlayerRole, PlayerUpdates, Players},
-    registry::IncrementId,
     util::dedup_by_key_unsorted,
-    GameTime, SimulationTime, LAG_COMPENSATED_FRAMES,
+    GameTime, SimulationTime, LAG_COMPENSATED_FRAMES, SIMULATIONS_PER_SECOND,
+};
+use std::{
+    collections::VecDeque,
+    marker::PhantomData,
+    time::{Duration, Instant},
 };
 
 pub const SERVER_UPDATES_LIMIT: u16 = 64;
+/// Clients run ahead of the server by about a half of their RTT (plus a jitter
+/// buffer), a second is way more than any playable connection needs.
+pub const MAX_INPUT_FRAMES_AHEAD: FrameNumber = FrameNumber::new(SIMULATIONS_PER_SECOND as u16);
+/// Clients send only the inputs that change the direction, they can't change
+/// it more often than once per frame, and such a batch covers only the frames
+/// since the last acknowledged packet.
+pub const MAX_RUNNER_INPUTS_PER_BATCH: usize = SERVER_UPDATES_LIMIT as usize;
+/// Unmodified clients never send invalid inputs, but we still tolerate a few
+/// within `INPUT_VIOLATIONS_WINDOW` in case of bugs.
+pub const MAX_INPUT_VIOLATIONS: usize = 10;
+/// Older violations are forgotten, so that rare glitches don't add up over a
+/// long session.
+const INPUT_VIOLATIONS_WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts the inputs that an unmodified client can't send. Players that exceed
+/// `MAX_INPUT_VIOLATIONS` within `INPUT_VIOLATIONS_WINDOW` get disconnected
+/// with `DisconnectReason::CheatSuspected`.
+#[derive(Resource, Default)]
+pub struct InputViolations {
+    /// When the violations within the window were reported, per player.
+    reported_at: HashMap<PlayerNetId, VecDeque<Instant>>,
+    suspects: Vec<PlayerNetId>,
+}
+
+impl InputViolations {
+    fn report(&mut self, player_net_id: PlayerNetId, now: Instant, violation: &str) {
+        let reported_at = self.reported_at.entry(player_net_id).or_default();
+        while reported_at.front().map_or(false, |at| {
+            now.duration_since(*at) >= INPUT_VIOLATIONS_WINDOW
+        }) {
+            reported_at.pop_front();
+        }
+        reported_at.push_back(now);
+        let count = reported_at.len();
+        log::debug!(
+            "Player ({}) input violation ({}/{}): {}",
+            player_net_id.0,
+            count,
+            MAX_INPUT_VIOLATIONS,
+            violation
+        );
+        if count == MAX_INPUT_VIOLATIONS {
+            log::warn!(
+                "Player ({}) is suspected of cheating: {}",
+                player_net_id.0,
+                violation
+            );
+            self.suspects.push(player_net_id);
+        }
+    }
+
+    /// Returns the players that need to be disconnected.
+    pub fn take_suspects(&mut self) -> Vec<PlayerNetId> {
+        std::mem::take(&mut self.suspects)
+    }
+
+    /// Net ids get reused, so the violations are reset on disconnect.
+    pub fn forget(&mut self, player_net_id: PlayerNetId) {
+        self.reported_at.remove(&player_net_id);
+    }
+}
+
+/// Returns the direction clamped to the unit length, or `None` if it's not
+/// finite.
+fn sanitize_direction(direction: Vec2) -> Option<Vec2> {
+    direction
+        .is_finite()
+        .then(|| direction.clamp_length_max(1.0))
+}
+
+/// Builders who have spawned or updated level objects last, so that they can be
+/// notified if something goes wrong with their changes.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct LevelObjectEditors(pub HashMap<EntityNetId, PlayerNetId>);
+
+#[derive(SystemParam)]
+pub struct LevelObjectNetIds<'w, 's> {
+    allocator: ResMut<'w, EntityNetIdAllocator>,
+    editors: ResMut<'w, LevelObjectEditors>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> LevelObjectNetIds<'w, 's> {
+    pub fn allocate(&mut self, editor: PlayerNetId) -> Result<EntityNetId, IdAllocationError> {
+        let net_id = self.allocator.allocate()?;
+        self.editors.insert(net_id, editor);
+        Ok(net_id)
+    }
+
+    /// Returns the builder who has spawned or updated the object last.
+    pub fn free(&mut self, net_id: EntityNetId) -> Option<PlayerNetId> {
+        self.allocator.free(net_id);
+        self.editors.remove(&net_id)
+    }
+}
 
 pub fn process_player_input_updates_system(
     time: Res<GameTime>,
@@ -28,10 +145,13 @@ pub fn process_player_input_updates_system(
     mut simulation_time: ResMut<SimulationTime>,
     mut updates: ResMut<PlayerUpdates>,
     mut deferred_updates: ResMut<DeferredPlayerQueues<RunnerInput>>,
+    mut input_violations: ResMut<InputViolations>,
 ) {
     #[cfg(feature = "profiler")]
     puffin::profile_function!();
     let min_frame_number = time.frame_number - LAG_COMPENSATED_FRAMES;
+    let max_frame_number = time.frame_number + MAX_INPUT_FRAMES_AHEAD;
+    let now = Instant::now();
 
     let deferred_updates = deferred_updates.drain();
     for (player_net_id, mut player_updates) in deferred_updates {
@@ -50,10 +170,56 @@ pub fn process_player_input_updates_system(
         // We want to sort after deduping, to prevent users from re-ordering inputs.
         player_updates.sort_by_key(|update| update.frame_number);
 
-        let player_update = player_updates
-            .first()
-            .expect("Expected at least one update for a player hash map entry")
-            .clone();
+        // Inputs for the future frames would let a client move faster than the
+        // server simulates, so they are rejected.
+        let future_inputs = player_updates
+            .iter()
+            .filter(|update| update.frame_number > max_frame_number)
+            .count();
+        if future_inputs > 0 {
+            input_violations.report(
+                player_net_id,
+                now,
+                &format!(
+                    "{} input(s) are more than {} frames ahead (current: {})",
+                    future_inputs,
+                    MAX_INPUT_FRAMES_AHEAD.value(),
+                    time.frame_number
+                ),
+            );
+            player_updates.retain(|update| update.frame_number <= max_frame_number);
+        }
+        if player_updates.len() > MAX_RUNNER_INPUTS_PER_BATCH {
+            input_violations.report(
+                player_net_id,
+                now,
+                &format!("{} inputs within a single batch", player_updates.len()),
+            );
+            // The latest ones are the most relevant.
+            player_updates.drain(..player_updates.len() - MAX_RUNNER_INPUTS_PER_BATCH);
+        }
+        let mut invalid_directions = 0;
+        player_updates.retain_mut(|update| match sanitize_direction(update.direction) {
+            Some(direction) => {
+                update.direction = direction;
+                true
+            }
+            None => {
+                invalid_directions += 1;
+                false
+            }
+        });
+        if invalid_directions > 0 {
+            input_violations.report(
+                player_net_id,
+                now,
+                &format!("{} input(s) with non-finite directions", invalid_directions),
+            );
+        }
+
+        let Some(player_update) = player_updates.first().cloned() else {
+            continue;
+        };
         let frames_off_lag_compensation_limit = if player_update.frame_number > min_frame_number {
             FrameNumber::new(0)
         } else {
@@ -143,7 +309,7 @@ pub fn process_spawn_level_object_requests_system(
     mut spawn_level_object_requests: ResMut<
         DeferredPlayerQueues<messages::SpawnLevelObjectRequest>,
     >,
-    mut entity_net_id_counter: ResMut<EntityNetIdCounter>,
+    mut level_object_net_ids: LevelObjectNetIds,
     mut update_level_object_commands: ResMut<DeferredQueue<UpdateLevelObject>>,
     mut spawn_level_object_messages: ResMut<DeferredMessagesQueue<messages::SpawnLevelObject>>,
 ) {
@@ -177,7 +343,7 @@ pub fn process_spawn_level_object_requests_system(
             let desc = match spawn_level_object_request.body {
                 messages::SpawnLevelObjectRequestBody::New(desc) => desc,
                 messages::SpawnLevelObjectRequestBody::Copy(entity_net_id) => {
-                    if let Some(object) = level_state.objects.get(&entity_net_id) {
+                    if let Some(object) = level_state.object(entity_net_id) {
                         object.desc.clone()
                     } else {
                         log::warn!(
@@ -189,7 +355,24 @@ pub fn process_spawn_level_object_requests_system(
                     }
                 }
             };
-            let net_id = entity_net_id_counter.increment();
+            if matches!(&desc, LevelObjectDesc::Emitter(emitter) if !emitter.is_valid()) {
+                log::warn!(
+                    "Ignoring Player ({}) spawn request: invalid emitter",
+                    player_net_id.0
+                );
+                continue;
+            }
+            let net_id = match level_object_net_ids.allocate(player_net_id) {
+                Ok(net_id) => net_id,
+                Err(err) => {
+                    log::error!(
+                        "Ignoring Player ({}) spawn request: {}",
+                        player_net_id.0,
+                        err
+                    );
+                    continue;
+                }
+            };
             let spawn_level_object = UpdateLevelObject {
                 object: LevelObject {
                     net_id,
@@ -214,6 +397,7 @@ pub fn process_update_level_object_requests_system(
     players: Res<Players>,
     level_state: Res<LevelState>,
     mut update_level_object_requests: ResMut<DeferredPlayerQueues<LevelObject>>,
+    mut level_object_editors: ResMut<LevelObjectEditors>,
     mut spawn_level_object_commands: ResMut<DeferredQueue<UpdateLevelObject>>,
     mut update_level_object_messages: ResMut<DeferredMessagesQueue<UpdateLevelObject>>,
 ) {
@@ -243,10 +427,14 @@ pub fn process_update_level_object_requests_system(
             }
         }
 
-        for update_level_object_request in update_level_object_requests {
-            if !level_state
-                .objects
-                .contains_key(&update_level_object_request.net_id)
+        for mut update_level_object_request in update_level_object_requests {
+            update_level_object_request.label = sanitize_text(
+                &update_level_object_request.label,
+                LEVEL_OBJECT_LABEL_MAX_LEN,
+            );
+            if level_state
+                .object(update_level_object_request.net_id)
+                .is_none()
             {
                 log::warn!(
                     "Ignoring Player ({}) update request: updated level object ({}) doesn't exist",
@@ -255,6 +443,46 @@ pub fn process_update_level_object_requests_system(
                 );
                 continue;
             }
+            if update_level_object_request
+                .route
+                .as_ref()
+                .map_or(false, |route| !route.is_valid())
+            {
+                log::warn!(
+                    "Ignoring Player ({}) update request for level object ({}): invalid route",
+                    player_net_id.0,
+                    update_level_object_request.net_id.0
+                );
+                continue;
+            }
+            if matches!(
+                &update_level_object_request.desc,
+                LevelObjectDesc::Emitter(emitter) if !emitter.is_valid()
+            ) {
+                log::warn!(
+                    "Ignoring Player ({}) update request for level object ({}): invalid emitter",
+                    player_net_id.0,
+                    update_level_object_request.net_id.0
+                );
+                continue;
+            }
+            if let Err(err) = validate_spawnable_area_change(
+                level_state.spawnable_area(),
+                level_state.spawnable_area_after(
+                    update_level_object_request.net_id,
+                    Some(&update_level_object_request),
+                ),
+                PLAYER_CAPACITY,
+            ) {
+                log::warn!(
+                    "Ignoring Player ({}) update request for level object ({}): {}",
+                    player_net_id.0,
+                    update_level_object_request.net_id.0,
+                    err
+                );
+                continue;
+            }
+            level_object_editors.insert(update_level_object_request.net_id, player_net_id);
             let spawn_level_object = UpdateLevelObject {
                 object: update_level_object_request,
                 frame_number: time.frame_number,
@@ -270,6 +498,7 @@ pub fn process_despawn_level_object_requests_system(
     players: Res<Players>,
     level_state: Res<LevelState>,
     mut despawn_level_object_requests: ResMut<DeferredPlayerQueues<EntityNetId>>,
+    mut level_object_net_ids: LevelObjectNetIds,
     mut despawn_level_object_commands: ResMut<DeferredQueue<DespawnLevelObject>>,
     mut despawn_level_object_messages: ResMut<DeferredMessagesQueue<DespawnLevelObject>>,
 ) {
@@ -300,10 +529,7 @@ pub fn process_despawn_level_object_requests_system(
         }
 
         for despawned_level_object_net_id in despawn_level_object_requests {
-            if !level_state
-                .objects
-                .contains_key(&despawned_level_object_net_id)
-            {
+            if level_state.object(despawned_level_object_net_id).is_none() {
                 log::warn!(
                     "Ignoring Player ({}) despawn request: updated level object ({}) doesn't exist",
                     player_net_id.0,
@@ -311,6 +537,20 @@ pub fn process_despawn_level_object_requests_system(
                 );
                 continue;
             }
+            if let Err(err) = validate_spawnable_area_change(
+                level_state.spawnable_area(),
+                level_state.spawnable_area_after(despawned_level_object_net_id, None),
+                PLAYER_CAPACITY,
+            ) {
+                log::warn!(
+                    "Ignoring Player ({}) despawn request for level object ({}): {}",
+                    player_net_id.0,
+                    despawned_level_object_net_id.0,
+                    err
+                );
+                continue;
+            }
+            level_object_net_ids.free(despawned_level_object_net_id);
             let despawn_level_object = DespawnLevelObject {
                 net_id: despawned_level_object_net_id,
                 frame_number: time.frame_number,
@@ -320,3 +560,153 @@ pub fn process_despawn_level_object_requests_system(
         }
     }
 }
+
+/// Level objects with shapes that can't be calculated are despawned, and the
+/// builder who has made them gets notified.
+pub fn process_invalid_level_object_shapes_system(
+    time: Res<GameTime>,
+    level_state: Res<LevelState>,
+    mut shape_invalid_events: EventReader<LevelObjectShapeInvalid>,
+    mut level_object_net_ids: LevelObjectNetIds,
+    mut despawn_level_object_commands: ResMut<DeferredQueue<DespawnLevelObject>>,
+    mut despawn_level_object_messages: ResMut<DeferredMessagesQueue<DespawnLevelObject>>,
+    mut invalid_shape_messages: ResMut<DeferredPlayerQueues<messages::InvalidLevelObjectShape>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    for LevelObjectShapeInvalid { net_id, error } in shape_invalid_events.iter() {
+        let Some(level_object) = level_state.object(*net_id) else {
+            continue;
+        };
+        log::warn!(
+            "Despawning level object ({}) with an invalid shape: {}",
+            net_id.0,
+            error
+        );
+        let label = level_object.label.clone();
+        if let Some(editor) = level_object_net_ids.free(*net_id) {
+            invalid_shape_messages.push(
+                editor,
+                messages::InvalidLevelObjectShape {
+                    net_id: *net_id,
+                    label,
+                    error: error.clone(),
+                },
+            );
+        }
+        let despawn_level_object = DespawnLevelObject {
+            net_id: *net_id,
+            frame_number: time.frame_number,
+        };
+        despawn_level_object_commands.push(despawn_level_object.clone());
+        despawn_level_object_messages.push(despawn_level_object);
+    }
+}
+
+pub fn process_update_level_settings_requests_system(
+    players: Res<Players>,
+    mut update_level_settings_requests: ResMut<DeferredPlayerQueues<LevelSettings>>,
+    mut update_level_settings_commands: ResMut<DeferredQueue<UpdateLevelSettings>>,
+    mut update_level_settings_messages: ResMut<DeferredMessagesQueue<UpdateLevelSettings>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    for (player_net_id, update_level_settings_requests) in update_level_settings_requests.drain() {
+        match players.get(&player_net_id) {
+            Some(Player {
+                role: PlayerRole::Builder,
+                ..
+            }) => {}
+            Some(_) => {
+                log::warn!(
+                    "Ignoring Player ({}) level settings requests: player is not a builder",
+                    player_net_id.0
+                );
+                continue;
+            }
+            None => {
+                log::error!(
+                    "Ignoring Player ({}) level settings requests: player is not found",
+                    player_net_id.0
+                );
+                continue;
+            }
+        }
+
+        // Settings replace each other completely, so only the latest request matters.
+        if let Some(settings) = update_level_settings_requests.into_iter().last() {
+            if settings
+                .medal_times
+                .map_or(false, |medal_times| !medal_times.is_valid())
+            {
+                log::warn!(
+                    "Ignoring Player ({}) level settings request: invalid medal times",
+                    player_net_id.0
+                );
+                continue;
+            }
+            if !settings.respawns.is_valid() {
+                log::warn!(
+                    "Ignoring Player ({}) level settings request: invalid respawn delays",
+                    player_net_id.0
+                );
+                continue;
+            }
+            // The persistence service sanitizes localizations when saving the level, so
+            // they are only validated here, otherwise the text would change under the
+            // builder's cursor.
+            let localizations = settings
+                .localizations
+                .iter()
+                .map(|(language, localization)| {
+                    (
+                        language.as_str(),
+                        localization.title.as_str(),
+                        localization.description.as_str(),
+                    )
+                });
+            if let Err(err) = validate_level_localizations(localizations) {
+                log::warn!(
+                    "Ignoring Player ({}) level settings request: {}",
+                    player_net_id.0,
+                    err
+                );
+                continue;
+            }
+            let update_level_settings = UpdateLevelSettings { settings };
+            update_level_settings_commands.push(update_level_settings.clone());
+            update_level_settings_messages.push(update_level_settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_violations_window() {
+        let mut input_violations = InputViolations::default();
+        let player_net_id = PlayerNetId(1);
+        let started_at = Instant::now();
+
+        // Spread-out violations don't add up.
+        let interval = INPUT_VIOLATIONS_WINDOW / 5;
+        for i in 0..MAX_INPUT_VIOLATIONS as u32 * 3 {
+            input_violations.report(player_net_id, started_at + interval * i, "test");
+        }
+        assert!(input_violations.take_suspects().is_empty());
+
+        // A burst within the window gets the player disconnected.
+        let now = started_at + interval * MAX_INPUT_VIOLATIONS as u32 * 3;
+        for _ in 0..MAX_INPUT_VIOLATIONS {
+            input_violations.report(player_net_id, now, "test");
+        }
+        assert_eq!(input_violations.take_suspects(), vec![player_net_id]);
+        assert!(input_violations.take_suspects().is_empty());
+
+        input_violations.forget(player_net_id);
+        input_violations.report(player_net_id, now, "test");
+        assert_eq!(input_violations.reported_at[&player_net_id].len(), 1);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    net::FetchedLevelInfo,
+    persistence::{autosave_request, PersistenceRequest},
+    PersistenceRequestSender,
+};
+use bevy::{
+    ecs::system::{Res, ResMut},
+    log,
+    math::Vec2,
+};
+use mr_messages_lib::PLAYER_CAPACITY;
+use mr_shared_lib::{
+    game::{
+        commands::DeferredPlayerQueues,
+        level::{validate_spawnable_area, CollisionLogic, LevelState},
+        movement::player_movement_speed,
+        navigation::WalkabilityGraph,
+    },
+    messages::{
+        LevelCheck, LevelCheckSeverity, PublishLevelReport, PublishLevelRequest, PublishLevelStatus,
+    },
+    player::{PlayerRole, Players},
+    SIMULATIONS_PER_SECOND,
+};
+
+/// The playtest bot follows the same path as the hard practice bot, but
+/// without aim errors or hesitation, which makes its time a lower bound for
+/// real runners.
+const PLAYTEST_LOOKAHEAD: usize = 3;
+const PLAYTEST_TIMEOUT_SECS: f32 = 300.0;
+
+/// Runs the checks of `ReliableClientMessage::PublishLevel` requests. The
+/// level gets published only if none of the checks fail.
+pub fn process_publish_level_requests_system(
+    mut requests: ResMut<DeferredPlayerQueues<PublishLevelRequest>>,
+    mut reports: ResMut<DeferredPlayerQueues<PublishLevelReport>>,
+    players: Res<Players>,
+    level_state: Res<LevelState>,
+    fetched_level_info: Option<Res<FetchedLevelInfo>>,
+    persistence_req_tx: Res<PersistenceRequestSender>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    for (player_net_id, player_requests) in requests.drain() {
+        if players
+            .get(&player_net_id)
+            .map_or(true, |player| player.role != PlayerRole::Builder)
+        {
+            log::warn!(
+                "Ignoring Player ({}) publish requests: not a builder",
+                player_net_id.0
+            );
+            continue;
+        }
+        // Only the latest request matters, as they all check the same level.
+        let request = *player_requests
+            .last()
+            .expect("Expected at least one request in a player queue");
+
+        let checks = check_level(&level_state);
+        let (Some(fetched_level_info), Some(req_tx)) = (&fetched_level_info, &**persistence_req_tx)
+        else {
+            reports.push(
+                player_net_id,
+                PublishLevelReport {
+                    checks,
+                    status: PublishLevelStatus::Failed(
+                        "Only saved levels can be published".to_owned(),
+                    ),
+                },
+            );
+            continue;
+        };
+
+        let report = PublishLevelReport {
+            checks,
+            status: PublishLevelStatus::Checked,
+        };
+        if request == PublishLevelRequest::Check || report.has_errors() {
+            reports.push(player_net_id, report);
+            continue;
+        }
+
+        log::info!(
+            "Player ({}) is publishing the level ({})",
+            player_net_id.0,
+            fetched_level_info.level.id
+        );
+        let result = req_tx.send(PersistenceRequest::PublishLevel {
+            player_net_id,
+            level_id: fetched_level_info.level.id,
+            autosave: autosave_request(fetched_level_info, &level_state),
+            checks: report.checks,
+        });
+        if let Err(err) = result {
+            log::error!("Failed to send a persistence request: {:?}", err);
+        }
+    }
+}
+
+fn check_level(level_state: &LevelState) -> Vec<LevelCheck> {
+    let mut checks = Vec::new();
+    let objects = level_state.objects();
+
+    let has_finish = objects
+        .values()
+        .any(|object| object.effective_collision_logic() == CollisionLogic::Finish);
+    checks.push(if has_finish {
+        passed("The level has a finish".to_owned())
+    } else {
+        error("The level has no finish".to_owned())
+    });
+
+    let spawnable_area = level_state.spawnable_area();
+    checks.push(
+        match validate_spawnable_area(spawnable_area, PLAYER_CAPACITY) {
+            Err(err) => error(format!("Not enough room to spawn: {err}")),
+            Ok(()) if spawnable_area.is_none() => warning(
+                "The level has no spawn areas, runners will spawn at the level origin".to_owned(),
+            ),
+            Ok(()) => passed(format!("Spawn areas fit {PLAYER_CAPACITY} players")),
+        },
+    );
+
+    if !has_finish {
+        return checks;
+    }
+
+    // Moving objects are checked at their initial positions.
+    let graph = WalkabilityGraph::build(
+        objects
+            .values()
+            .filter_map(|object| Some((object, object.desc.position()?))),
+    );
+    let mut spawn_positions = level_state
+        .spawn_areas()
+        .iter()
+        .filter_map(|net_id| objects.get(net_id)?.desc.position())
+        .collect::<Vec<_>>();
+    if spawn_positions.is_empty() {
+        spawn_positions.push(Vec2::ZERO);
+    }
+    let step = player_movement_speed() / SIMULATIONS_PER_SECOND;
+    let max_frames = (PLAYTEST_TIMEOUT_SECS * SIMULATIONS_PER_SECOND) as usize;
+    let run_frames = spawn_positions
+        .into_iter()
+        .map(|position| graph.frames_to_finish(position, PLAYTEST_LOOKAHEAD, step, max_frames))
+        .collect::<Option<Vec<_>>>();
+    let Some(run_frames) = run_frames else {
+        checks.push(error(
+            "The playtest bot couldn't reach the finish from every spawn area".to_owned(),
+        ));
+        return checks;
+    };
+    let bot_time = run_frames.into_iter().max().unwrap_or_default() as f32 / SIMULATIONS_PER_SECOND;
+    checks.push(passed(format!(
+        "The playtest bot finished in {bot_time:.1}s"
+    )));
+    if objects.values().any(|object| object.route.is_some()) {
+        checks.push(warning(
+            "The playtest bot ignores moving objects, make sure to run the level yourself"
+                .to_owned(),
+        ));
+    }
+
+    match &level_state.settings().medal_times {
+        None => checks.push(warning("Medal times aren't set".to_owned())),
+        Some(medal_times) if medal_times.gold < bot_time => checks.push(warning(format!(
+            "The gold medal time ({:.1}s) is faster than the playtest bot",
+            medal_times.gold
+        ))),
+        Some(_) => checks.push(passed("Medal times are achievable".to_owned())),
+    }
+
+    checks
+}
+
+fn passed(message: String) -> LevelCheck {
+    LevelCheck {
+        severity: LevelCheckSeverity::Passed,
+        message,
+    }
+}
+
+fn warning(message: String) -> LevelCheck {
+    LevelCheck {
+        severity: LevelCheckSeverity::Warning,
+        message,
+    }
+}
+
+fn error(message: String) -> LevelCheck {
+    LevelCheck {
+        severity: LevelCheckSeverity::Error,
+        message,
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    interest_management::AreasOfInterest,
+    solo_practice::{SoloPractice, SOLO_PRACTICE_IDLE_TIMEOUT},
+    IdleTimeout,
+};
+use bevy::{
+    ecs::system::{ResMut, Resource, SystemParam},
+    log,
+    prelude::App,
+    utils::HashMap,
+};
+use mr_shared_lib::{
+    game::{determinism::DeterminismGuard, tether::Tethers},
+    messages::DeferredMessagesQueue,
+};
+use notify::{RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::{
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Is set when the server runs with `MUDDLE_RUNTIME_CONFIG_FILE` (normally a
+/// mounted ConfigMap). The settings that are safe to change mid-session get
+/// applied as soon as the file changes, the rest require a restart.
+#[derive(Resource)]
+pub struct RuntimeConfigFile {
+    pub path: PathBuf,
+    /// Tethering systems are added only if it's enabled on startup.
+    pub is_tethering_enabled: bool,
+    events: UnboundedReceiver<()>,
+    reload_tx: UnboundedSender<()>,
+}
+
+impl RuntimeConfigFile {
+    /// Makes the file get re-read even if it hasn't changed.
+    pub fn request_reload(&self) {
+        let _ = self.reload_tx.send(());
+    }
+}
+
+/// Settings that are missing in the file keep their current values.
+#[derive(Deserialize, Debug)]
+pub struct RuntimeConfig {
+    pub idle_timeout_millis: Option<u64>,
+    pub tether_distance: Option<f32>,
+    pub determinism_guard: Option<bool>,
+    /// Zero disables interest management.
+    pub interest_radius: Option<f32>,
+    #[serde(flatten)]
+    pub unsupported: HashMap<String, serde_json::Value>,
+}
+
+pub fn read_runtime_config_file(path: &Path) -> anyhow::Result<RuntimeConfig> {
+    let data = std::fs::read(path)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+pub fn watch_runtime_config_file(app: &mut App, path: PathBuf, is_tethering_enabled: bool) {
+    let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+    // The file is read on startup as well.
+    let _ = events_tx.send(());
+    let reload_tx = events_tx.clone();
+    let file_name = path.file_name().map(ToOwned::to_owned);
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            // Kubernetes updates ConfigMap mounts by swapping a symlink to a new
+            // directory, which comes as a create event rather than a modification.
+            Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                if event.paths.iter().any(|path| {
+                    path.file_name() == file_name.as_deref()
+                        || path
+                            .file_name()
+                            .map_or(false, |name| name.to_string_lossy().starts_with(".."))
+                }) {
+                    let _ = events_tx.send(());
+                }
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Runtime config file watcher error: {:?}", err),
+        })
+        .expect("Failed to create a runtime config file watcher");
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .expect("Failed to watch the runtime config file");
+    log::info!(
+        "Watching the runtime config file for changes: {}",
+        path.display()
+    );
+
+    app.insert_non_send_resource(watcher);
+    app.insert_resource(RuntimeConfigFile {
+        path,
+        is_tethering_enabled,
+        events: events_rx,
+        reload_tx,
+    });
+}
+
+/// The resources that runtime config settings get applied to.
+#[derive(SystemParam)]
+pub struct RuntimeSettings<'w, 's> {
+    idle_timeout: ResMut<'w, IdleTimeout>,
+    tethers: ResMut<'w, Tethers>,
+    update_tethers_messages: ResMut<'w, DeferredMessagesQueue<Tethers>>,
+    determinism_guard: ResMut<'w, DeterminismGuard>,
+    areas_of_interest: ResMut<'w, AreasOfInterest>,
+    solo_practice: Option<Res<'w, SoloPractice>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+pub fn apply_runtime_config_changes_system(
+    mut runtime_config_file: ResMut<RuntimeConfigFile>,
+    mut runtime_settings: RuntimeSettings,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    // A single save usually produces several events, we want to re-read the file
+    // only once.
+    let mut has_changed = false;
+    while runtime_config_file.events.try_recv().is_ok() {
+        has_changed = true;
+    }
+    if !has_changed {
+        return;
+    }
+
+    let config = match read_runtime_config_file(&runtime_config_file.path) {
+        Ok(config) => config,
+        Err(err) => {
+            log::warn!(
+                "Failed to read the runtime config file, keeping the current settings: {err:?}"
+            );
+            return;
+        }
+    };
+
+    apply_runtime_config(
+        config,
+        runtime_config_file.is_tethering_enabled,
+        &mut runtime_settings,
+    );
+}
+
+/// Is also used by admins to change settings with `AdminCommand::SetCvar`.
+pub fn apply_runtime_config(
+    config: RuntimeConfig,
+    is_tethering_enabled: bool,
+    runtime_settings: &mut RuntimeSettings,
+) {
+    let RuntimeSettings {
+        idle_timeout,
+        tethers,
+        update_tethers_messages,
+        determinism_guard,
+        areas_of_interest,
+        solo_practice,
+        ..
+    } = runtime_settings;
+
+    for key in config.unsupported.keys() {
+        log::warn!("Setting {key} can't be changed at runtime, restart the server to apply it");
+    }
+
+    if let Some(idle_timeout_millis) = config.idle_timeout_millis {
+        let mut value = Duration::from_millis(idle_timeout_millis);
+        if solo_practice.is_some() {
+            value = value.min(SOLO_PRACTICE_IDLE_TIMEOUT);
+        }
+        if idle_timeout_millis == 0 {
+            log::warn!("Ignoring idle_timeout_millis: expected a positive value");
+        } else if idle_timeout.0 != value {
+            log::info!(
+                "Changing idle_timeout_millis: {} -> {}",
+                idle_timeout.0.as_millis(),
+                value.as_millis()
+            );
+            idle_timeout.0 = value;
+        }
+    }
+
+    if let Some(tether_distance) = config.tether_distance {
+        if !is_tethering_enabled {
+            log::warn!(
+                "Setting tether_distance can't be changed at runtime, as tethering is disabled"
+            );
+        } else if !tether_distance.is_finite() || tether_distance <= 0.0 {
+            log::warn!("Ignoring tether_distance: expected a positive value");
+        } else if tethers.max_distance != tether_distance {
+            log::info!(
+                "Changing tether_distance: {} -> {}",
+                tethers.max_distance,
+                tether_distance
+            );
+            tethers.max_distance = tether_distance;
+            // Clients simulate tethers as well, so they need to know the new distance.
+            update_tethers_messages.push(tethers.clone());
+        }
+    }
+
+    if let Some(enabled) = config.determinism_guard {
+        if determinism_guard.enabled != enabled {
+            log::info!(
+                "Changing determinism_guard: {} -> {}",
+                determinism_guard.enabled,
+                enabled
+            );
+            determinism_guard.enabled = enabled;
+        }
+    }
+
+    if let Some(interest_radius) = config.interest_radius {
+        let radius = (interest_radius != 0.0).then_some(interest_radius);
+        if !interest_radius.is_finite() || interest_radius < 0.0 {
+            log::warn!("Ignoring interest_radius: expected a positive value (or 0 to disable)");
+        } else if areas_of_interest.radius != radius {
+            log::info!(
+                "Changing interest_radius: {:?} -> {:?}",
+                areas_of_interest.radius,
+                radius
+            );
+            // Clients get notified about the players that enter or leave their areas with
+            // the next update.
+            areas_of_interest.radius = radius;
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    time::FixedTimesteps,
+    utils::Instant,
+};
+use mr_shared_lib::messages::{ServerHealth, TickDurationBucket};
+
+/// Labels the fixed timestep of the simulation, to be able to read how many
+/// ticks are due.
+pub const SIMULATION_TIMESTEP_LABEL: &str = "simulation";
+
+/// Is reported to clients with every delta update.
+#[derive(Resource, Default)]
+pub struct ServerHealthMonitor {
+    tick_started_at: Option<Instant>,
+    pub health: ServerHealth,
+}
+
+pub fn start_tick_timer_system(mut server_health_monitor: ResMut<ServerHealthMonitor>) {
+    server_health_monitor.tick_started_at = Some(Instant::now());
+}
+
+/// Runs right before broadcasting updates, so the measured duration covers
+/// processing inputs and simulating the tick.
+pub fn measure_server_health_system(
+    mut server_health_monitor: ResMut<ServerHealthMonitor>,
+    fixed_timesteps: Res<FixedTimesteps>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let tick_duration = server_health_monitor
+        .tick_started_at
+        .map_or(Default::default(), |started_at| started_at.elapsed());
+    // The fixed timestep accumulates time while the server is busy, whole steps
+    // that are left in the accumulator are the ticks the server is late with.
+    let broadcast_queue_depth = fixed_timesteps
+        .get(SIMULATION_TIMESTEP_LABEL)
+        .map_or(0, |state| {
+            (state.accumulator() / state.step()).min(u8::MAX as f64) as u8
+        });
+    server_health_monitor.health = ServerHealth {
+        tick_duration: TickDurationBucket::from_duration(tick_duration),
+        broadcast_queue_depth,
+    };
+}
@@ -0,0 +1,70 @@
+use bevy::{
+    app::{App, AppExit},
+    ecs::{
+        event::EventReader,
+        system::{Local, Res, ResMut, Resource},
+    },
+    log,
+};
+use mr_shared_lib::game::{
+    command_log::{enable_command_log, CommandLog},
+    determinism::DeterminismGuard,
+    level_objects::ColliderSimplification,
+    replay::SessionRecorder,
+};
+use std::path::{Path, PathBuf};
+
+/// Is set when the server runs with `MUDDLE_RECORD_SESSION`: the simulated
+/// frames are recorded to be replayed by the regression tests (see
+/// `libs/shared_lib/replays`).
+#[derive(Resource)]
+pub struct SessionRecordingPath(pub PathBuf);
+
+/// Has to be called after the shared plugin is added, but before the level
+/// starts loading, so that the commands spawning the level get recorded too.
+pub fn start_session_recording(
+    app: &mut App,
+    path: PathBuf,
+    collider_simplification: ColliderSimplification,
+) {
+    log::info!("Recording the session to {}", path.display());
+    // The recorder drains the log every frame, but a level might have more
+    // objects than fit into the default capacity.
+    app.insert_resource(CommandLog::new(usize::MAX));
+    enable_command_log(&mut app.world);
+    app.world.resource_mut::<DeterminismGuard>().enabled = true;
+    app.insert_resource(SessionRecorder::new(collider_simplification));
+    app.insert_resource(SessionRecordingPath(path));
+}
+
+/// Saves the recording once the server exits or the recording reaches its
+/// limit.
+pub fn save_session_recording_system(
+    mut is_saved: Local<bool>,
+    path: Res<SessionRecordingPath>,
+    mut recorder: ResMut<SessionRecorder>,
+    mut command_log: ResMut<CommandLog>,
+    mut app_exit_events: EventReader<AppExit>,
+) {
+    let is_exiting = app_exit_events.iter().next().is_some();
+    if *is_saved || !(is_exiting || recorder.is_full()) {
+        return;
+    }
+    *is_saved = true;
+
+    recorder.collect_commands(&mut command_log);
+    match write_recording(&path.0, &recorder) {
+        Ok(()) => log::info!(
+            "Saved the session recording ({} frames) to {}",
+            recorder.recording().frames.len(),
+            path.0.display()
+        ),
+        Err(err) => log::error!("Failed to save the session recording: {:?}", err),
+    }
+}
+
+fn write_recording(path: &Path, recorder: &SessionRecorder) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(recorder.recording())?;
+    std::fs::write(path, data)?;
+    Ok(())
+}
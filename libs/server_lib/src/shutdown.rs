@@ -0,0 +1,155 @@
+//! Once `process_idle_timeout` decides to shut the server down, connected
+//! clients get a countdown and unsaved level changes get flushed before the
+//! process exits.
+
+use crate::net::{broadcast_reliable_game_message, ConnectionStates};
+use bevy::{
+    ecs::system::{NonSendMut, ResMut, Resource},
+    log,
+};
+use bevy_disturbulence::NetworkResource;
+use mr_shared_lib::{
+    messages::{DisconnectReason, ReliableServerMessage},
+    net::ConnectionStatus,
+};
+use std::time::{Duration, Instant};
+
+pub const SHUTDOWN_COUNTDOWN_SECS: u16 = 5;
+/// The server exits even if the simulation has stalled or the persistence
+/// service doesn't respond to the last autosave.
+const SHUTDOWN_TIMEOUT_SECS: u64 = 15;
+/// Lets `Disconnect` messages get delivered before exiting.
+const DISCONNECT_GRACE_PERIOD_MILLIS: u64 = 500;
+
+#[derive(Resource, Default)]
+pub struct ServerShutdown {
+    started_at: Option<Instant>,
+    /// Is set once connected clients are notified. If there aren't any, the
+    /// countdown ends right away.
+    countdown_ends_at: Option<Instant>,
+    level_flush: LevelFlush,
+    has_exited: bool,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum LevelFlush {
+    #[default]
+    Requested,
+    InProgress,
+    Done,
+}
+
+impl ServerShutdown {
+    pub fn start(&mut self, now: Instant) {
+        if self.started_at.is_none() {
+            self.started_at = Some(now);
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started_at.is_some()
+    }
+
+    /// Returns `true` once the countdown has ended, for `save_level_system` to
+    /// save the level without waiting for the next autosave. Builders can't
+    /// make changes after that, as they are disconnected.
+    pub fn take_level_flush_request(&mut self, now: Instant) -> bool {
+        let has_countdown_ended = self
+            .countdown_ends_at
+            .map_or(false, |countdown_ends_at| now >= countdown_ends_at);
+        if has_countdown_ended && self.level_flush == LevelFlush::Requested {
+            self.level_flush = LevelFlush::Done;
+            return true;
+        }
+        false
+    }
+
+    pub fn await_level_save(&mut self) {
+        self.level_flush = LevelFlush::InProgress;
+    }
+
+    /// Responses aren't matched with requests, as autosaves are rarely sent
+    /// right before the flush.
+    pub fn receive_level_save(&mut self) {
+        if self.level_flush == LevelFlush::InProgress {
+            self.level_flush = LevelFlush::Done;
+        }
+    }
+
+    /// Returns `true` once, when clients have been disconnected and the level
+    /// has been saved.
+    pub fn take_exit(&mut self, now: Instant) -> bool {
+        let Some(started_at) = self.started_at else {
+            return false;
+        };
+        if self.has_exited {
+            return false;
+        }
+
+        let are_clients_disconnected = self.countdown_ends_at.map_or(false, |countdown_ends_at| {
+            now.duration_since(countdown_ends_at)
+                >= Duration::from_millis(DISCONNECT_GRACE_PERIOD_MILLIS)
+        });
+        if !are_clients_disconnected || self.level_flush != LevelFlush::Done {
+            if now.duration_since(started_at) < Duration::from_secs(SHUTDOWN_TIMEOUT_SECS) {
+                return false;
+            }
+            log::warn!("Timed out waiting for the clients to be disconnected and the level to be saved, exiting anyway");
+        }
+        self.has_exited = true;
+        true
+    }
+}
+
+/// Runs before `process_network_events_system`, so that disconnected players
+/// get despawned as usual.
+pub fn process_server_shutdown_system(
+    mut net: NonSendMut<NetworkResource>,
+    mut connection_states: ResMut<ConnectionStates>,
+    mut server_shutdown: ResMut<ServerShutdown>,
+) {
+    if !server_shutdown.is_started() {
+        return;
+    }
+
+    let now = Instant::now();
+    let countdown_ends_at = match server_shutdown.countdown_ends_at {
+        Some(countdown_ends_at) => countdown_ends_at,
+        None => {
+            let has_connected_clients = connection_states.values().any(|connection_state| {
+                matches!(connection_state.status(), ConnectionStatus::Connected)
+            });
+            let countdown = if has_connected_clients {
+                log::info!(
+                    "Notifying connected clients about the shutdown in {} seconds",
+                    SHUTDOWN_COUNTDOWN_SECS
+                );
+                broadcast_reliable_game_message(
+                    &mut net,
+                    &connection_states,
+                    ReliableServerMessage::ShutdownCountdown(SHUTDOWN_COUNTDOWN_SECS),
+                );
+                Duration::from_secs(SHUTDOWN_COUNTDOWN_SECS.into())
+            } else {
+                Duration::ZERO
+            };
+            *server_shutdown.countdown_ends_at.insert(now + countdown)
+        }
+    };
+    if now < countdown_ends_at {
+        return;
+    }
+
+    for (handle, connection_state) in connection_states.iter_mut() {
+        if matches!(
+            connection_state.status(),
+            ConnectionStatus::Disconnecting(_) | ConnectionStatus::Disconnected
+        ) {
+            continue;
+        }
+        log::info!("Disconnecting {}: the server is shutting down", handle);
+        connection_state.set_status(ConnectionStatus::Disconnecting(
+            DisconnectReason::ServerShutdown,
+        ));
+    }
+}
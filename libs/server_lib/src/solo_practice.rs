@@ -0,0 +1,28 @@
+//! Solo practice servers are allocated for a single registered player to
+//! practice a level on their own. They aren't listed publicly, don't submit
+//! stats, broadcast updates less often and shut down sooner once the player
+//! leaves.
+
+use bevy::ecs::system::Resource;
+use mr_shared_lib::{framebuffer::FrameNumber, TICKS_PER_NETWORK_BROADCAST};
+use std::time::Duration;
+
+pub const SOLO_PRACTICE_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Delta updates are broadcast every this many regular broadcasts. Clients
+/// still predict their own runner, so a lower rate only affects the
+/// interpolation of moving level objects.
+pub const SOLO_PRACTICE_BROADCAST_INTERVAL: u16 = 2;
+
+/// Is inserted only for solo practice allocations.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SoloPractice {
+    /// The only player allowed to join the server.
+    pub user_id: i64,
+}
+
+impl SoloPractice {
+    pub fn skips_broadcast(&self, server_frame: FrameNumber) -> bool {
+        let ticks = TICKS_PER_NETWORK_BROADCAST * SOLO_PRACTICE_BROADCAST_INTERVAL;
+        server_frame.value() % ticks >= TICKS_PER_NETWORK_BROADCAST
+    }
+}
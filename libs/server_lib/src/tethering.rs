@@ -0,0 +1,110 @@
+use bevy::{
+    ecs::{
+        event::EventReader,
+        system::{Res, ResMut},
+    },
+    log,
+};
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    game::{
+        commands::{DeferredQueue, DespawnPlayer, DespawnReason},
+        events::PlayerDeath,
+        level::LevelState,
+        tether::Tethers,
+    },
+    messages::{DeferredMessagesQueue, PlayerNetId, RespawnPlayer, RespawnPlayerReason},
+    player::{PlayerRole, PlayerSystemParamsMut, Players},
+    SimulationTime,
+};
+
+/// Keeps connected runners tethered in pairs. Existing pairs stay intact for as
+/// long as both runners are around, the rest get paired in the order of their
+/// ids (a runner without a pair isn't tethered).
+pub fn pair_tethered_runners_system(
+    players: Res<Players>,
+    mut tethers: ResMut<Tethers>,
+    mut update_tethers_messages: ResMut<DeferredMessagesQueue<Tethers>>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let is_tetherable = |net_id: &PlayerNetId| {
+        players.get(net_id).map_or(false, |player| {
+            player.is_connected && player.role == PlayerRole::Runner
+        })
+    };
+
+    let mut pairs = tethers
+        .pairs
+        .iter()
+        .copied()
+        .filter(|(a, b)| is_tetherable(a) && is_tetherable(b))
+        .collect::<Vec<_>>();
+    let mut unpaired = players
+        .keys()
+        .copied()
+        .filter(|net_id| is_tetherable(net_id))
+        .filter(|net_id| !pairs.iter().any(|(a, b)| a == net_id || b == net_id))
+        .collect::<Vec<_>>();
+    unpaired.sort_by_key(|net_id| net_id.0);
+    pairs.extend(
+        unpaired
+            .chunks_exact(2)
+            .map(|runners| (runners[0], runners[1])),
+    );
+
+    if pairs != tethers.pairs {
+        log::info!("Tethering runners: {:?}", pairs);
+        tethers.pairs = pairs;
+        update_tethers_messages.push(tethers.clone());
+    }
+}
+
+/// If a tethered runner dies, their partner gets respawned as well.
+pub fn respawn_tethered_partners_system(
+    time: Res<SimulationTime>,
+    tethers: Res<Tethers>,
+    mut player_death_events: EventReader<PlayerDeath>,
+    mut player_params: PlayerSystemParamsMut,
+    mut respawn_player_messages_queue: ResMut<DeferredMessagesQueue<RespawnPlayer>>,
+    mut despawn_players_commands: ResMut<DeferredQueue<DespawnPlayer>>,
+    level_state: Res<LevelState>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    let respawn_at = time.server_frame
+        + level_state
+            .settings()
+            .respawns
+            .delay(RespawnPlayerReason::Death);
+
+    for PlayerDeath { player_entity, .. } in player_death_events.iter() {
+        let Some(partner_net_id) = player_params
+            .player_registry
+            .get_id(*player_entity)
+            .and_then(|net_id| tethers.partner(net_id))
+        else {
+            continue;
+        };
+        let Some(partner) = player_params.players.get_mut(&partner_net_id) else {
+            continue;
+        };
+        // The partner might have died or finished at the same frame.
+        if partner.respawning_at.is_some() {
+            continue;
+        }
+
+        partner.respawning_at = Some((respawn_at, RespawnPlayerReason::Death));
+        respawn_player_messages_queue.push(RespawnPlayer {
+            net_id: partner_net_id,
+            reason: RespawnPlayerReason::Death,
+            frame_number: respawn_at,
+            finish: None,
+        });
+        despawn_players_commands.push(DespawnPlayer {
+            net_id: partner_net_id,
+            frame_number: time.server_frame + FrameNumber::new(1),
+            reason: DespawnReason::DeathOrFinish,
+        });
+    }
+}
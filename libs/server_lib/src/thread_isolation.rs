@@ -0,0 +1,134 @@
+//! Threads inherit the CPU affinity of the thread that spawns them. To give the
+//! simulation thread a dedicated core, we first exclude the core from the main
+//! thread's affinity before any other threads (the Tokio runtime, Bevy task
+//! pools) are spawned, and pin the main thread to it right before running the
+//! app.
+
+use crate::MuddleServerConfig;
+use bevy::log;
+
+/// Must be called before spawning any threads.
+pub fn reserve_simulation_core(config: &MuddleServerConfig) {
+    let Some(simulation_core) = config.simulation_cpu_core else {
+        return;
+    };
+
+    let mut cores = match imp::current_thread_cores() {
+        Ok(cores) => cores,
+        Err(err) => {
+            log::warn!("Failed to get the main thread CPU affinity: {:?}", err);
+            return;
+        }
+    };
+    if !cores.contains(&simulation_core) {
+        log::warn!(
+            "CPU core {} isn't available for the server (available cores: {:?})",
+            simulation_core,
+            cores
+        );
+        return;
+    }
+    if cores.len() < 2 {
+        log::warn!(
+            "Can't reserve a CPU core for the simulation thread: only one core is available"
+        );
+        return;
+    }
+
+    cores.retain(|core| *core != simulation_core);
+    match imp::set_current_thread_cores(&cores) {
+        Ok(()) => log::info!("Reserved CPU core {} for the simulation", simulation_core),
+        Err(err) => log::warn!("Failed to set the main thread CPU affinity: {:?}", err),
+    }
+}
+
+/// Must be called from the thread that runs the app schedule (the main one),
+/// after all the plugins are added.
+pub fn isolate_simulation_thread(config: &MuddleServerConfig) {
+    if let Some(simulation_core) = config.simulation_cpu_core {
+        match imp::set_current_thread_cores(&[simulation_core]) {
+            Ok(()) => log::info!(
+                "Pinned the simulation thread to CPU core {}",
+                simulation_core
+            ),
+            Err(err) => log::warn!("Failed to pin the simulation thread: {:?}", err),
+        }
+    }
+
+    if let Some(nice) = config.simulation_thread_nice {
+        match imp::set_current_thread_nice(nice) {
+            Ok(()) => log::info!("Set the simulation thread nice value to {}", nice),
+            // Negative values require `CAP_SYS_NICE`.
+            Err(err) => log::warn!("Failed to set the simulation thread priority: {:?}", err),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{io, mem::size_of};
+
+    pub fn current_thread_cores() -> io::Result<Vec<usize>> {
+        // SAFETY: `cpu_set_t` is a plain bitmask, zeroed value is an empty set.
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            if libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((0..libc::CPU_SETSIZE as usize)
+                .filter(|core| libc::CPU_ISSET(*core, &set))
+                .collect())
+        }
+    }
+
+    pub fn set_current_thread_cores(cores: &[usize]) -> io::Result<()> {
+        // SAFETY: `cpu_set_t` is a plain bitmask, zeroed value is an empty set.
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for core in cores {
+                libc::CPU_SET(*core, &mut set);
+            }
+            // Pid 0 stands for the calling thread.
+            if libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_current_thread_nice(nice: i32) -> io::Result<()> {
+        // Linux tracks nice values per thread, so passing a thread id affects only the
+        // calling thread, unlike what POSIX says about `setpriority`.
+        unsafe {
+            let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+            if libc::setpriority(libc::PRIO_PROCESS, tid, nice) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Thread isolation is supported only on Linux",
+        )
+    }
+
+    pub fn current_thread_cores() -> io::Result<Vec<usize>> {
+        Err(unsupported())
+    }
+
+    pub fn set_current_thread_cores(_cores: &[usize]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn set_current_thread_nice(_nice: i32) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
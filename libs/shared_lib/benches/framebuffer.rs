@@ -0,0 +1,90 @@
+//! Compares memory and lookup costs of framebuffers for typical movement
+//! patterns. Besides timings, `cargo bench` prints how many bytes each
+//! pattern allocates, compared to storing every frame.
+
+#![feature(test)]
+
+extern crate test;
+
+use bevy::math::Vec2;
+use mr_shared_lib::{
+    framebuffer::{FrameNumber, Framebuffer},
+    COMPONENT_FRAMEBUFFER_LIMIT,
+};
+use test::{black_box, Bencher};
+
+/// A runner that holds each direction for half a second, then releases the
+/// keys for a bit.
+fn runner_direction(frame: u16) -> Option<Vec2> {
+    match frame / 60 % 4 {
+        0 => Some(Vec2::X),
+        1 => Some(Vec2::new(1.0, 1.0).normalize()),
+        2 => Some(Vec2::Y),
+        _ => None,
+    }
+}
+
+fn runner_position(frame: u16) -> Vec2 {
+    Vec2::new(frame as f32 * 0.1, (frame as f32 * 0.01).sin())
+}
+
+/// Fills a buffer twice over, so that it's at its limit.
+fn fill<T: PartialEq + Clone>(value: impl Fn(u16) -> T) -> Framebuffer<T> {
+    let mut buffer = Framebuffer::new(FrameNumber::new(0), COMPONENT_FRAMEBUFFER_LIMIT);
+    for frame in 0..COMPONENT_FRAMEBUFFER_LIMIT * 2 {
+        buffer.push(value(frame));
+    }
+    buffer
+}
+
+fn report_memory<T>(pattern: &str, buffer: &Framebuffer<T>) {
+    let dense_bytes = COMPONENT_FRAMEBUFFER_LIMIT as usize * std::mem::size_of::<T>();
+    println!(
+        "{pattern}: {} bytes allocated ({dense_bytes} bytes without compaction)",
+        buffer.allocated_bytes()
+    );
+}
+
+#[bench]
+fn idle_position(b: &mut Bencher) {
+    report_memory("idle position", &fill(|_| Vec2::ZERO));
+    b.iter(|| black_box(fill(|_| Vec2::ZERO)));
+}
+
+#[bench]
+fn runner_positions(b: &mut Bencher) {
+    report_memory("runner positions", &fill(runner_position));
+    b.iter(|| black_box(fill(runner_position)));
+}
+
+#[bench]
+fn runner_directions(b: &mut Bencher) {
+    report_memory("runner directions", &fill(runner_direction));
+    b.iter(|| black_box(fill(runner_direction)));
+}
+
+/// Simulations mostly read the latest frames.
+#[bench]
+fn get_latest_frames(b: &mut Bencher) {
+    let buffer = fill(runner_direction);
+    let end_frame = buffer.end_frame();
+    b.iter(|| {
+        for i in 0..10 {
+            black_box(buffer.get(end_frame - FrameNumber::new(i)));
+        }
+    });
+}
+
+/// Rewinds (on receiving authoritative updates) re-insert values for a few
+/// dozens of past frames.
+#[bench]
+fn rewind_inserts(b: &mut Bencher) {
+    let mut buffer = fill(runner_direction);
+    let end_frame = buffer.end_frame();
+    b.iter(|| {
+        for i in (0..30).rev() {
+            let frame_number = end_frame - FrameNumber::new(i);
+            buffer.insert(frame_number, runner_direction(frame_number.value()));
+        }
+    });
+}
@@ -0,0 +1,99 @@
+//! Compares serializing messages into fresh buffers with reusing the ones from
+//! `MessageBufferPool`. Besides timings, `cargo bench` prints how many
+//! allocations serializing a message takes with either approach.
+
+#![feature(test)]
+
+extern crate test;
+
+use bevy::math::Vec2;
+use mr_shared_lib::{
+    framebuffer::FrameNumber,
+    messages::{Message, PlayerInputs, PlayerUpdate, RunnerInput, UnreliableClientMessage},
+    net::{MessageBufferPool, SessionId},
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use test::{black_box, Bencher};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const MESSAGES_COUNT: usize = 1000;
+
+/// An update of a runner that has a few unacknowledged inputs.
+fn player_update(frame_number: u16) -> Message<UnreliableClientMessage> {
+    Message {
+        session_id: SessionId::new(0),
+        message: UnreliableClientMessage::PlayerUpdate(PlayerUpdate {
+            frame_number: FrameNumber::new(frame_number),
+            acknowledgments: (Some(FrameNumber::new(frame_number)), u64::MAX),
+            inputs: PlayerInputs::Runner {
+                inputs: (0..8)
+                    .map(|i| RunnerInput {
+                        frame_number: FrameNumber::new(frame_number.wrapping_sub(i)),
+                        direction: Vec2::new(1.0, 1.0).normalize(),
+                    })
+                    .collect(),
+            },
+        }),
+    }
+}
+
+fn report_allocations(approach: &str, serialize: impl FnMut(&Message<UnreliableClientMessage>)) {
+    let messages = (0..MESSAGES_COUNT as u16)
+        .map(player_update)
+        .collect::<Vec<_>>();
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    messages.iter().for_each(serialize);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    println!(
+        "{approach}: {:.2} allocations per message",
+        allocations as f64 / MESSAGES_COUNT as f64
+    );
+}
+
+#[bench]
+fn serialize_fresh_buffers(b: &mut Bencher) {
+    report_allocations("fresh buffers", |message| {
+        black_box(bincode::serialize(message).unwrap());
+    });
+    let message = player_update(0);
+    b.iter(|| black_box(bincode::serialize(&message).unwrap()));
+}
+
+#[bench]
+fn serialize_pooled_buffers(b: &mut Bencher) {
+    let mut pool = MessageBufferPool::default();
+    report_allocations("pooled buffers", |message| {
+        let buffer = pool.serialize(message).unwrap();
+        pool.give_back(black_box(buffer));
+    });
+    let message = player_update(0);
+    b.iter(|| {
+        let buffer = pool.serialize(&message).unwrap();
+        pool.give_back(black_box(buffer));
+    });
+}
@@ -0,0 +1,176 @@
+use crate::{client::assets::CustomObjectMaterials, messages::EntityNetId};
+use bevy::{
+    asset::{Assets, Handle},
+    ecs::system::{ResMut, Resource},
+    prelude::StandardMaterial,
+    render::mesh::Mesh,
+    utils::HashMap,
+};
+use std::collections::VecDeque;
+
+/// Freeing a concave plane mesh may take a while, so reloading a level with
+/// hundreds of objects is spread over several frames instead of causing a
+/// single long one.
+pub const ASSET_GC_BUDGET_PER_FRAME: usize = 32;
+
+/// Tracks the meshes and custom materials of level objects (including their
+/// ghosts), and frees them after the objects get despawned or replaced.
+///
+/// Handles are kept strong until collected, so that assets are never freed
+/// while an object (or its ghost) still references them.
+#[derive(Resource, Default)]
+pub struct LevelObjectAssetsGc {
+    tracked: HashMap<EntityNetId, TrackedAssets>,
+    garbage_meshes: VecDeque<Handle<Mesh>>,
+    /// Custom materials are shared between objects of the same appearance, so
+    /// they are freed only if no tracked object uses them anymore.
+    garbage_materials: VecDeque<Handle<StandardMaterial>>,
+}
+
+#[derive(Default)]
+struct TrackedAssets {
+    meshes: Vec<Handle<Mesh>>,
+    materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl LevelObjectAssetsGc {
+    pub fn track_mesh(&mut self, net_id: EntityNetId, mesh: Handle<Mesh>) {
+        self.tracked.entry(net_id).or_default().meshes.push(mesh);
+    }
+
+    pub fn track_material(&mut self, net_id: EntityNetId, material: Handle<StandardMaterial>) {
+        let materials = &mut self.tracked.entry(net_id).or_default().materials;
+        if !materials.contains(&material) {
+            materials.push(material);
+        }
+    }
+
+    /// Is called when an object gets despawned or replaced with an updated
+    /// one. The assets tracked after this call belong to the new object.
+    pub fn release(&mut self, net_id: EntityNetId) {
+        if let Some(assets) = self.tracked.remove(&net_id) {
+            self.push_garbage(assets);
+        }
+    }
+
+    /// Is called when the game world gets reset.
+    pub fn release_all(&mut self) {
+        let tracked = std::mem::take(&mut self.tracked);
+        for assets in tracked.into_values() {
+            self.push_garbage(assets);
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.garbage_meshes.len() + self.garbage_materials.len()
+    }
+
+    fn push_garbage(&mut self, assets: TrackedAssets) {
+        self.garbage_meshes.extend(assets.meshes);
+        for material in assets.materials {
+            if !self.garbage_materials.contains(&material) {
+                self.garbage_materials.push_back(material);
+            }
+        }
+    }
+
+    /// Returns the assets that can be freed, at most `budget` of them in
+    /// total. Materials that got reused by another object are just forgotten.
+    fn take_garbage(
+        &mut self,
+        budget: usize,
+    ) -> (Vec<Handle<Mesh>>, Vec<Handle<StandardMaterial>>) {
+        let meshes_count = self.garbage_meshes.len().min(budget);
+        let meshes = self.garbage_meshes.drain(..meshes_count).collect();
+
+        let mut materials = Vec::new();
+        while meshes_count + materials.len() < budget {
+            let Some(material) = self.garbage_materials.pop_front() else {
+                break;
+            };
+            let is_used = self
+                .tracked
+                .values()
+                .any(|assets| assets.materials.contains(&material));
+            if !is_used {
+                materials.push(material);
+            }
+        }
+
+        (meshes, materials)
+    }
+}
+
+pub fn collect_level_object_assets_system(
+    mut assets_gc: ResMut<LevelObjectAssetsGc>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut custom_materials: ResMut<CustomObjectMaterials>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    if assets_gc.pending_count() == 0 {
+        return;
+    }
+
+    let (garbage_meshes, garbage_materials) = assets_gc.take_garbage(ASSET_GC_BUDGET_PER_FRAME);
+    for mesh in garbage_meshes {
+        meshes.remove(mesh);
+    }
+    for material in garbage_materials {
+        custom_materials.evict(&material);
+        materials.remove(material);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::HandleId;
+
+    fn mesh() -> Handle<Mesh> {
+        Handle::weak(HandleId::random::<Mesh>())
+    }
+
+    #[test]
+    fn test_take_garbage_within_budget() {
+        let mut assets_gc = LevelObjectAssetsGc::default();
+        for i in 0..5 {
+            assets_gc.track_mesh(EntityNetId(i), mesh());
+            assets_gc.track_mesh(EntityNetId(i), mesh());
+        }
+
+        assets_gc.release(EntityNetId(0));
+        assert_eq!(assets_gc.pending_count(), 2);
+
+        assets_gc.release_all();
+        assert_eq!(assets_gc.pending_count(), 10);
+
+        let (meshes, materials) = assets_gc.take_garbage(4);
+        assert_eq!(meshes.len(), 4);
+        assert!(materials.is_empty());
+        assert_eq!(assets_gc.pending_count(), 6);
+
+        assets_gc.take_garbage(4);
+        assets_gc.take_garbage(4);
+        assert_eq!(assets_gc.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_shared_materials_are_kept_while_used() {
+        let mut assets_gc = LevelObjectAssetsGc::default();
+        let shared: Handle<StandardMaterial> = Handle::weak(HandleId::random::<StandardMaterial>());
+        assets_gc.track_material(EntityNetId(0), shared.clone());
+        assets_gc.track_material(EntityNetId(1), shared.clone());
+
+        assets_gc.release(EntityNetId(0));
+        assert_eq!(assets_gc.pending_count(), 1);
+        let (_, materials) = assets_gc.take_garbage(ASSET_GC_BUDGET_PER_FRAME);
+        assert!(materials.is_empty());
+        assert_eq!(assets_gc.pending_count(), 0);
+
+        assets_gc.release(EntityNetId(1));
+        let (_, materials) = assets_gc.take_garbage(ASSET_GC_BUDGET_PER_FRAME);
+        assert_eq!(materials, vec![shared]);
+    }
+}
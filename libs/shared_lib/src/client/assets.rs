@@ -1,4 +1,4 @@
-use crate::PLAYER_SENSOR_RADIUS;
+use crate::{game::level_objects::ObjectAppearance, PLAYER_SENSOR_RADIUS};
 use bevy::{
     asset::{Assets, Handle},
     ecs::system::{Commands, Res, ResMut, Resource, SystemParam},
@@ -8,9 +8,23 @@ use bevy::{
         color::Color,
         mesh::{shape::Icosphere, Mesh},
     },
+    utils::HashMap,
 };
 use std::marker::PhantomData;
 
+// Default sRGB colors of level objects.
+pub const PLANE_COLOR: [f32; 3] = [0.3, 0.5, 0.3];
+pub const PLANE_DEATH_COLOR: [f32; 3] = [0.55, 0.15, 0.2];
+pub const PLANE_FINISH_COLOR: [f32; 3] = [0.2, 0.25, 0.75];
+pub const CUBE_COLOR: [f32; 3] = [0.4, 0.4, 0.4];
+pub const CUBE_DEATH_COLOR: [f32; 3] = [0.8, 0.35, 0.35];
+pub const EMITTER_COLOR: [f32; 3] = [0.6, 0.2, 0.45];
+pub const DEATH_ZONE_COLOR: [f32; 3] = [0.9, 0.1, 0.1];
+pub const FINISH_ZONE_COLOR: [f32; 3] = [0.95, 0.8, 0.2];
+const GHOST_ALPHA: f32 = 0.5;
+/// Zones are translucent, so that the planes they mark stay visible.
+const ZONE_ALPHA: f32 = 0.7;
+
 #[derive(SystemParam)]
 pub struct MuddleAssets<'w, 's> {
     pub materials: Res<'w, MuddleMaterials>,
@@ -22,12 +36,15 @@ pub struct MuddleAssets<'w, 's> {
 #[derive(Resource)]
 pub struct MuddleMaterials {
     pub player: Handle<StandardMaterial>,
+    pub player_ghost: Handle<StandardMaterial>,
     pub player_sensor_death: Handle<StandardMaterial>,
     pub player_sensor_normal: Handle<StandardMaterial>,
     pub normal: ObjectMaterials,
     pub ghost: ObjectMaterials,
     pub control_point_normal: Handle<StandardMaterial>,
     pub control_point_hovered: Handle<StandardMaterial>,
+    pub collision_outline: Handle<StandardMaterial>,
+    pub spawn_area_preview: Handle<StandardMaterial>,
 }
 
 #[derive(Resource)]
@@ -41,9 +58,10 @@ pub fn init_muddle_assets_system(
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    let a = 0.5;
+    let a = GHOST_ALPHA;
     commands.insert_resource(MuddleMaterials {
         player: materials.add(Color::rgb(0.8, 0.7, 0.6).into()),
+        player_ghost: materials.add(with_blend_alpha_mode(Color::rgba(0.8, 0.7, 0.6, a).into())),
         player_sensor_death: {
             let mut material: StandardMaterial = Color::rgb(1.0, 0.2, 0.25).into();
             material.reflectance = 0.0;
@@ -57,30 +75,37 @@ pub fn init_muddle_assets_system(
             materials.add(material)
         },
         normal: ObjectMaterials {
-            plane: materials.add(Color::rgb(0.3, 0.5, 0.3).into()),
-            plane_death: materials.add(Color::rgb(0.55, 0.15, 0.2).into()),
-            plane_finish: materials.add(Color::rgb(0.2, 0.25, 0.75).into()),
-            cube: materials.add(Color::rgb(0.4, 0.4, 0.4).into()),
-            cube_death: materials.add(Color::rgb(0.8, 0.35, 0.35).into()),
+            plane: materials.add(srgb(PLANE_COLOR, 1.0).into()),
+            plane_death: materials.add(srgb(PLANE_DEATH_COLOR, 1.0).into()),
+            plane_finish: materials.add(srgb(PLANE_FINISH_COLOR, 1.0).into()),
+            cube: materials.add(srgb(CUBE_COLOR, 1.0).into()),
+            cube_death: materials.add(srgb(CUBE_DEATH_COLOR, 1.0).into()),
             route_point: {
                 let mut material: StandardMaterial = Color::rgb(0.4, 0.4, 0.7).into();
                 material.reflectance = 0.0;
                 material.metallic = 0.0;
                 materials.add(material)
             },
+            annotation: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.95, 0.85, 0.3),
+                unlit: true,
+                ..Default::default()
+            }),
+            camera_anchor: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.35, 0.8, 0.95),
+                unlit: true,
+                ..Default::default()
+            }),
+            emitter: materials.add(srgb(EMITTER_COLOR, 1.0).into()),
+            death_zone: materials.add(zone_material(DEATH_ZONE_COLOR, ZONE_ALPHA)),
+            finish_zone: materials.add(zone_material(FINISH_ZONE_COLOR, ZONE_ALPHA)),
         },
         ghost: ObjectMaterials {
-            plane: materials.add(with_blend_alpha_mode(Color::rgba(0.3, 0.5, 0.3, a).into())),
-            plane_death: materials.add(with_blend_alpha_mode(
-                Color::rgba(0.55, 0.15, 0.2, a).into(),
-            )),
-            plane_finish: materials.add(with_blend_alpha_mode(
-                Color::rgba(0.2, 0.25, 0.75, a).into(),
-            )),
-            cube: materials.add(with_blend_alpha_mode(Color::rgba(0.4, 0.4, 0.4, a).into())),
-            cube_death: materials.add(with_blend_alpha_mode(
-                Color::rgba(0.8, 0.35, 0.35, a).into(),
-            )),
+            plane: materials.add(with_blend_alpha_mode(srgb(PLANE_COLOR, a).into())),
+            plane_death: materials.add(with_blend_alpha_mode(srgb(PLANE_DEATH_COLOR, a).into())),
+            plane_finish: materials.add(with_blend_alpha_mode(srgb(PLANE_FINISH_COLOR, a).into())),
+            cube: materials.add(with_blend_alpha_mode(srgb(CUBE_COLOR, a).into())),
+            cube_death: materials.add(with_blend_alpha_mode(srgb(CUBE_DEATH_COLOR, a).into())),
             route_point: {
                 let mut material: StandardMaterial =
                     with_blend_alpha_mode(Color::rgba(0.4, 0.4, 0.7, a).into());
@@ -88,12 +113,36 @@ pub fn init_muddle_assets_system(
                 material.metallic = 0.0;
                 materials.add(material)
             },
+            annotation: materials.add(with_blend_alpha_mode(StandardMaterial {
+                base_color: Color::rgba(0.95, 0.85, 0.3, a),
+                unlit: true,
+                ..Default::default()
+            })),
+            camera_anchor: materials.add(with_blend_alpha_mode(StandardMaterial {
+                base_color: Color::rgba(0.35, 0.8, 0.95, a),
+                unlit: true,
+                ..Default::default()
+            })),
+            emitter: materials.add(with_blend_alpha_mode(srgb(EMITTER_COLOR, a).into())),
+            death_zone: materials.add(zone_material(DEATH_ZONE_COLOR, ZONE_ALPHA * a)),
+            finish_zone: materials.add(zone_material(FINISH_ZONE_COLOR, ZONE_ALPHA * a)),
         },
         control_point_normal: materials
             .add(with_blend_alpha_mode(Color::rgb(1.0, 0.992, 0.816).into())),
         control_point_hovered: materials
             .add(with_blend_alpha_mode(Color::rgb(0.5, 0.492, 0.816).into())),
+        collision_outline: materials.add(StandardMaterial {
+            base_color: Color::rgb(1.0, 0.55, 0.1),
+            unlit: true,
+            ..Default::default()
+        }),
+        spawn_area_preview: materials.add(with_blend_alpha_mode(StandardMaterial {
+            base_color: Color::rgba(0.3, 0.85, 0.4, 0.35),
+            unlit: true,
+            ..Default::default()
+        })),
     });
+    commands.insert_resource(CustomObjectMaterials::default());
     commands.insert_resource(MuddleMeshes {
         player_sensor: meshes.add(Mesh::from(Icosphere {
             radius: PLAYER_SENSOR_RADIUS,
@@ -106,11 +155,23 @@ pub fn init_muddle_assets_system(
     });
 }
 
+fn srgb([r, g, b]: [f32; 3], a: f32) -> Color {
+    Color::rgba(r, g, b, a)
+}
+
 fn with_blend_alpha_mode(mut material: StandardMaterial) -> StandardMaterial {
     material.alpha_mode = AlphaMode::Blend;
     material
 }
 
+fn zone_material(color: [f32; 3], a: f32) -> StandardMaterial {
+    with_blend_alpha_mode(StandardMaterial {
+        base_color: srgb(color, a),
+        unlit: true,
+        ..Default::default()
+    })
+}
+
 pub struct ObjectMaterials {
     pub plane: Handle<StandardMaterial>,
     pub plane_death: Handle<StandardMaterial>,
@@ -118,4 +179,62 @@ pub struct ObjectMaterials {
     pub cube: Handle<StandardMaterial>,
     pub cube_death: Handle<StandardMaterial>,
     pub route_point: Handle<StandardMaterial>,
+    pub annotation: Handle<StandardMaterial>,
+    pub camera_anchor: Handle<StandardMaterial>,
+    pub emitter: Handle<StandardMaterial>,
+    pub death_zone: Handle<StandardMaterial>,
+    pub finish_zone: Handle<StandardMaterial>,
+}
+
+/// Materials of level objects with a custom appearance. Objects that look the
+/// same share a material instance, so recoloring objects doesn't create a new
+/// asset on every edit.
+#[derive(Resource, Default)]
+pub struct CustomObjectMaterials(HashMap<CustomMaterialKey, Handle<StandardMaterial>>);
+
+/// Colors are quantized to 8 bits per channel, which also bounds the number of
+/// materials that can be created.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct CustomMaterialKey {
+    color: [u8; 3],
+    emissive: u8,
+    is_ghost: bool,
+}
+
+impl CustomObjectMaterials {
+    pub fn get_or_add(
+        &mut self,
+        materials: &mut Assets<StandardMaterial>,
+        appearance: &ObjectAppearance,
+        default_color: [f32; 3],
+        is_ghost: bool,
+    ) -> Handle<StandardMaterial> {
+        let quantize = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let [r, g, b] = appearance.color.unwrap_or(default_color);
+        let key = CustomMaterialKey {
+            color: [quantize(r), quantize(g), quantize(b)],
+            emissive: quantize(appearance.emissive),
+            is_ghost,
+        };
+        self.0
+            .entry(key)
+            .or_insert_with(|| {
+                let [r, g, b] = key.color.map(|channel| channel as f32 / 255.0);
+                let emissive = key.emissive as f32 / 255.0;
+                let alpha = if is_ghost { GHOST_ALPHA } else { 1.0 };
+                let mut material: StandardMaterial = srgb([r, g, b], alpha).into();
+                material.emissive = Color::rgb(r * emissive, g * emissive, b * emissive);
+                if is_ghost {
+                    material = with_blend_alpha_mode(material);
+                }
+                materials.add(material)
+            })
+            .clone()
+    }
+
+    /// Is called by the asset GC, once none of the level objects uses the
+    /// material.
+    pub fn evict(&mut self, material: &Handle<StandardMaterial>) {
+        self.0.retain(|_, cached| cached != material);
+    }
 }
@@ -3,3 +3,7 @@ use bevy::ecs::component::Component;
 /// If an entity has this component, it'll be visible only if debug UI is shown.
 #[derive(Component)]
 pub struct DebugUiVisibility;
+
+/// Replays the current player's personal best run on a level.
+#[derive(Component)]
+pub struct PersonalBestGhost;
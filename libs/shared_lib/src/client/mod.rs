@@ -1,3 +1,4 @@
+pub mod asset_gc;
 pub mod assets;
 pub mod components;
 
@@ -24,8 +25,30 @@ impl Default for XyCircle {
 
 impl From<XyCircle> for Mesh {
     fn from(plane: XyCircle) -> Self {
-        let segments = plane.optimal_segments_count();
-        let radius = Vec2::new(plane.radius, 0.0);
+        Mesh::from(XyRegularPolygon {
+            radius: plane.radius,
+            sides: plane.optimal_segments_count(),
+        })
+    }
+}
+
+impl XyCircle {
+    pub fn optimal_segments_count(&self) -> u32 {
+        (self.radius.sqrt() * 24.0) as u32
+    }
+}
+
+/// A regular polygon on the XZ plane, inscribed in a circle of the radius.
+#[derive(Debug, Copy, Clone)]
+pub struct XyRegularPolygon {
+    pub radius: f32,
+    pub sides: u32,
+}
+
+impl From<XyRegularPolygon> for Mesh {
+    fn from(polygon: XyRegularPolygon) -> Self {
+        let segments = polygon.sides.max(3);
+        let radius = Vec2::new(polygon.radius, 0.0);
 
         let mut positions = vec![[0.0, 0.0, 0.0]];
         let mut indices = Vec::new();
@@ -59,12 +82,6 @@ impl From<XyCircle> for Mesh {
     }
 }
 
-impl XyCircle {
-    fn optimal_segments_count(&self) -> u32 {
-        (self.radius.sqrt() * 24.0) as u32
-    }
-}
-
 /// A rectangle on the XZ plane.
 #[derive(Debug, Copy, Clone)]
 pub struct XyPlane {
@@ -111,6 +128,40 @@ impl From<XyPlane> for Mesh {
     }
 }
 
+/// A closed outline on the XZ plane, made of quads of the same width.
+#[derive(Debug, Clone)]
+pub struct XyOutline {
+    pub points: Vec<Vec2>,
+    pub width: f32,
+}
+
+impl From<XyOutline> for Mesh {
+    fn from(outline: XyOutline) -> Self {
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut indices = Vec::new();
+        for (i, &start) in outline.points.iter().enumerate() {
+            let end = outline.points[(i + 1) % outline.points.len()];
+            let half_width = (end - start).normalize_or_zero().perp() * outline.width / 2.0;
+
+            let index = positions.len() as u32;
+            positions.push((start - half_width).extend(0.0).into());
+            positions.push((end - half_width).extend(0.0).into());
+            positions.push((end + half_width).extend(0.0).into());
+            positions.push((start + half_width).extend(0.0).into());
+            indices.extend([index, index + 1, index + 2, index, index + 2, index + 3]);
+        }
+        let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
+        let uvs = vec![[0.0, 0.0]; positions.len()];
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Pyramid {
     pub height: f32,
@@ -1,4 +1,4 @@
-use bevy_rapier2d::prelude::CollisionGroups;
+use bevy_rapier2d::{geometry::Group, prelude::CollisionGroups};
 
 pub mod groups {
     use bevy_rapier2d::geometry::Group;
@@ -38,3 +38,9 @@ pub fn level_object_collision_groups(server_simulated: bool) -> CollisionGroups
         CollisionGroups::new(groups::LEVEL_OBJECT, groups::PLAYER | groups::PLAYER_SENSOR)
     }
 }
+
+/// Objects that aren't simulated (annotations, camera anchors) must never
+/// collide with anything.
+pub fn non_simulated_collision_groups() -> CollisionGroups {
+    CollisionGroups::new(Group::NONE, Group::NONE)
+}
@@ -0,0 +1,162 @@
+//! Gameplay constants have to match between clients and servers, otherwise
+//! simulations subtly diverge. Some of them can be overridden at build time
+//! (`SIMULATIONS_PER_SECOND`), so clients send theirs with the handshake.
+
+use crate::{
+    delta_compression::STATE_QUANTUM,
+    game::{
+        determinism::StateHasher,
+        emitter::{BEAM_HALF_WIDTH, EMITTER_MAX_REACH, PROJECTILE_MAX_SPEED, PROJECTILE_RADIUS},
+        jump_pad::JUMP_PAD_BOOST_FRAMES,
+    },
+    COMPONENT_FRAMEBUFFER_LIMIT, GHOST_SIZE_MULTIPLIER, LAG_COMPENSATED_FRAMES,
+    MAX_INPUT_SEND_INTERVAL, MAX_LAG_COMPENSATION_MILLIS, PLANE_SIZE, PLAYER_RADIUS,
+    PLAYER_SENSOR_RADIUS, SIMULATIONS_PER_SECOND, TICKS_PER_NETWORK_BROADCAST,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ConstantsFingerprint {
+    pub hash: u64,
+    /// Formatted values of the constants, to list the ones that differ.
+    pub values: Vec<(String, String)>,
+}
+
+impl ConstantsFingerprint {
+    pub fn current() -> Self {
+        Self::new(vec![
+            constant("SIMULATIONS_PER_SECOND", SIMULATIONS_PER_SECOND),
+            constant("TICKS_PER_NETWORK_BROADCAST", TICKS_PER_NETWORK_BROADCAST),
+            constant("MAX_LAG_COMPENSATION_MILLIS", MAX_LAG_COMPENSATION_MILLIS),
+            constant("LAG_COMPENSATED_FRAMES", LAG_COMPENSATED_FRAMES.value()),
+            constant("MAX_INPUT_SEND_INTERVAL", MAX_INPUT_SEND_INTERVAL.value()),
+            constant("COMPONENT_FRAMEBUFFER_LIMIT", COMPONENT_FRAMEBUFFER_LIMIT),
+            constant("STATE_QUANTUM", STATE_QUANTUM),
+            constant("PLAYER_RADIUS", PLAYER_RADIUS),
+            constant("PLAYER_SENSOR_RADIUS", PLAYER_SENSOR_RADIUS),
+            constant("GHOST_SIZE_MULTIPLIER", GHOST_SIZE_MULTIPLIER),
+            constant("PLANE_SIZE", PLANE_SIZE),
+            constant("BEAM_HALF_WIDTH", BEAM_HALF_WIDTH),
+            constant("PROJECTILE_RADIUS", PROJECTILE_RADIUS),
+            constant("PROJECTILE_MAX_SPEED", PROJECTILE_MAX_SPEED),
+            constant("EMITTER_MAX_REACH", EMITTER_MAX_REACH),
+            constant("JUMP_PAD_BOOST_FRAMES", JUMP_PAD_BOOST_FRAMES),
+        ])
+    }
+
+    fn new(values: Vec<(String, String)>) -> Self {
+        let mut hasher = StateHasher::default();
+        for (name, value) in &values {
+            hasher.write(name.as_bytes());
+            hasher.write(b"=");
+            hasher.write(value.as_bytes());
+            hasher.write(b";");
+        }
+        Self {
+            hash: hasher.finish(),
+            values,
+        }
+    }
+
+    /// Returns an empty list if the fingerprints match.
+    pub fn mismatches(&self, remote: &Self) -> Vec<ConstantMismatch> {
+        if self.hash == remote.hash {
+            return Vec::new();
+        }
+
+        let remote_value = |name: &str| {
+            remote
+                .values
+                .iter()
+                .find(|(remote_name, _)| remote_name == name)
+                .map(|(_, value)| value.clone())
+        };
+        let mut mismatches = self
+            .values
+            .iter()
+            .filter_map(|(name, value)| {
+                let remote = remote_value(name);
+                (remote.as_ref() != Some(value)).then(|| ConstantMismatch {
+                    name: name.clone(),
+                    local: Some(value.clone()),
+                    remote,
+                })
+            })
+            .collect::<Vec<_>>();
+        mismatches.extend(
+            remote
+                .values
+                .iter()
+                .filter(|(name, _)| self.values.iter().all(|(local_name, _)| local_name != name))
+                .map(|(name, value)| ConstantMismatch {
+                    name: name.clone(),
+                    local: None,
+                    remote: Some(value.clone()),
+                }),
+        );
+        mismatches
+    }
+}
+
+/// Constants that exist only on one side have `None` values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstantMismatch {
+    pub name: String,
+    pub local: Option<String>,
+    pub remote: Option<String>,
+}
+
+impl fmt::Display for ConstantMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (local: {}, remote: {})",
+            self.name,
+            self.local.as_deref().unwrap_or("missing"),
+            self.remote.as_deref().unwrap_or("missing")
+        )
+    }
+}
+
+/// Floats are formatted with `Debug`, which doesn't lose precision.
+fn constant(name: &str, value: impl fmt::Debug) -> (String, String) {
+    (name.to_owned(), format!("{value:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constants_fingerprint_mismatches() {
+        let local = ConstantsFingerprint::new(vec![
+            constant("SIMULATIONS_PER_SECOND", 120.0f32),
+            constant("PLAYER_RADIUS", 0.35f32),
+            constant("PLANE_SIZE", 20.0f32),
+        ]);
+        assert!(local.mismatches(&local.clone()).is_empty());
+        assert!(ConstantsFingerprint::current()
+            .mismatches(&ConstantsFingerprint::current())
+            .is_empty());
+
+        let remote = ConstantsFingerprint::new(vec![
+            constant("SIMULATIONS_PER_SECOND", 60.0f32),
+            constant("PLAYER_RADIUS", 0.35f32),
+            constant("PLAYER_SENSOR_RADIUS", 0.05f32),
+        ]);
+        assert_ne!(local.hash, remote.hash);
+        let mismatches = local.mismatches(&remote);
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "SIMULATIONS_PER_SECOND (local: 120.0, remote: 60.0)",
+                "PLANE_SIZE (local: 20.0, remote: missing)",
+                "PLAYER_SENSOR_RADIUS (local: missing, remote: 0.05)",
+            ]
+        );
+    }
+}
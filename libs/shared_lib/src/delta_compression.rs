@@ -0,0 +1,495 @@
+//! Delta compression of player states in `DeltaUpdate` messages. The server
+//! encodes states against the latest update that a client has acknowledged,
+//! so players that stand still cost a couple of bytes, and moving ones send
+//! small differences instead of full positions.
+
+use crate::{
+    framebuffer::FrameNumber,
+    messages::{PlayerNetId, PlayerState},
+};
+use bevy::{math::Vec2, utils::HashMap};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use thiserror::Error;
+
+/// Positions and directions are rounded to multiples of this value, unless
+/// they are sent with `StatePrecision::Exact`.
+pub const STATE_QUANTUM: f32 = 1.0 / 4096.0;
+/// Covers all the outgoing packets that `ConnectionState` tracks
+/// acknowledgments for.
+const HISTORY_LEN: usize = 64;
+
+const TAG_UNCHANGED: u32 = 0;
+const TAG_DELTA: u32 = 1;
+const TAG_FULL: u32 = 2;
+const TAG_BITS: u32 = 2;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StatePrecision {
+    #[default]
+    Quantized,
+    /// Keeps the exact bits of the values. Is used while the determinism guard
+    /// is enabled, as clients wouldn't be able to reproduce the state hashes
+    /// of the server otherwise.
+    Exact,
+}
+
+impl StatePrecision {
+    fn encode_value(self, value: f32) -> u32 {
+        match self {
+            Self::Quantized => (value / STATE_QUANTUM).round() as i32 as u32,
+            Self::Exact => value.to_bits(),
+        }
+    }
+
+    fn decode_value(self, code: u32) -> f32 {
+        match self {
+            Self::Quantized => code as i32 as f32 * STATE_QUANTUM,
+            Self::Exact => f32::from_bits(code),
+        }
+    }
+
+    /// Small changes result in small numbers, which take fewer bytes once
+    /// they are written as varints.
+    fn delta(self, code: u32, baseline: u32) -> u32 {
+        match self {
+            Self::Quantized => zigzag(code.wrapping_sub(baseline) as i32),
+            Self::Exact => code ^ baseline,
+        }
+    }
+
+    fn apply_delta(self, baseline: u32, delta: u32) -> u32 {
+        match self {
+            Self::Quantized => baseline.wrapping_add(unzigzag(delta) as u32),
+            Self::Exact => baseline ^ delta,
+        }
+    }
+}
+
+/// Player states of a `DeltaUpdate` message, each player is encoded as a
+/// varint header (a net id and a tag), followed by varints of the changed
+/// values, if there are any.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncodedPlayerStates {
+    /// The frame of the acknowledged update that the states are encoded
+    /// against. Is `None` if all the states are encoded in full.
+    pub baseline_frame: Option<FrameNumber>,
+    pub precision: StatePrecision,
+    pub data: Vec<u8>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("baseline frame {0} is missing")]
+    MissingBaseline(FrameNumber),
+    #[error("player {} is missing in the baseline", .0.0)]
+    MissingBaselineState(PlayerNetId),
+    #[error("player {} is encoded more than once", .0.0)]
+    DuplicatePlayer(PlayerNetId),
+    #[error("invalid tag: {0}")]
+    InvalidTag(u32),
+    #[error("invalid player net id: {0}")]
+    InvalidNetId(u32),
+    #[error("invalid varint")]
+    InvalidVarint,
+    #[error("unexpected end of data")]
+    UnexpectedEnd,
+}
+
+/// Encoded `[position.x, position.y, direction.x, direction.y]`.
+type StateCodes = [u32; 4];
+
+/// Encoded player states of a sent (or received) update, which later updates
+/// can be encoded against.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlayerStatesSnapshot {
+    precision: StatePrecision,
+    states: HashMap<PlayerNetId, StateCodes>,
+}
+
+/// Snapshots of the latest updates sent to (or received from) a peer.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerStatesHistory {
+    snapshots: VecDeque<(FrameNumber, PlayerStatesSnapshot)>,
+}
+
+impl PlayerStatesHistory {
+    pub fn push(&mut self, frame_number: FrameNumber, snapshot: PlayerStatesSnapshot) {
+        if self.snapshots.len() == HISTORY_LEN {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((frame_number, snapshot));
+    }
+
+    pub fn get(&self, frame_number: FrameNumber) -> Option<&PlayerStatesSnapshot> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|(snapshot_frame_number, _)| *snapshot_frame_number == frame_number)
+            .map(|(_, snapshot)| snapshot)
+    }
+}
+
+/// Returns the encoded states and the snapshot to store in the history. The
+/// baseline is ignored if its precision doesn't match.
+pub fn encode_player_states(
+    players: &[PlayerState],
+    precision: StatePrecision,
+    baseline: Option<(FrameNumber, &PlayerStatesSnapshot)>,
+) -> (EncodedPlayerStates, PlayerStatesSnapshot) {
+    let baseline = baseline.filter(|(_, snapshot)| snapshot.precision == precision);
+    let mut data = Vec::with_capacity(players.len() * 4);
+    let mut snapshot = PlayerStatesSnapshot {
+        precision,
+        states: HashMap::with_capacity(players.len()),
+    };
+
+    for player in players {
+        let codes = [
+            player.position.x,
+            player.position.y,
+            player.direction.x,
+            player.direction.y,
+        ]
+        .map(|value| precision.encode_value(value));
+        let net_id = (player.net_id.0 as u32) << TAG_BITS;
+        match baseline.and_then(|(_, baseline)| baseline.states.get(&player.net_id)) {
+            Some(baseline_codes) if *baseline_codes == codes => {
+                write_varint(&mut data, net_id | TAG_UNCHANGED);
+            }
+            Some(baseline_codes) => {
+                write_varint(&mut data, net_id | TAG_DELTA);
+                for (code, baseline_code) in codes.iter().zip(baseline_codes) {
+                    write_varint(&mut data, precision.delta(*code, *baseline_code));
+                }
+            }
+            None => {
+                write_varint(&mut data, net_id | TAG_FULL);
+                for code in codes {
+                    write_varint(&mut data, precision.delta(code, 0));
+                }
+            }
+        }
+        snapshot.states.insert(player.net_id, codes);
+    }
+
+    let encoded = EncodedPlayerStates {
+        baseline_frame: baseline.map(|(frame_number, _)| frame_number),
+        precision,
+        data,
+    };
+    (encoded, snapshot)
+}
+
+/// Expects the snapshot of `EncodedPlayerStates::baseline_frame`, if it's set.
+/// Returns the states and the snapshot to store in the history.
+pub fn decode_player_states(
+    encoded: &EncodedPlayerStates,
+    baseline: Option<&PlayerStatesSnapshot>,
+) -> Result<(Vec<PlayerState>, PlayerStatesSnapshot), DecodeError> {
+    let precision = encoded.precision;
+    let baseline = match (encoded.baseline_frame, baseline) {
+        (None, _) => None,
+        (Some(_), Some(baseline)) if baseline.precision == precision => Some(baseline),
+        (Some(frame_number), _) => return Err(DecodeError::MissingBaseline(frame_number)),
+    };
+    let mut players = Vec::new();
+    let mut snapshot = PlayerStatesSnapshot {
+        precision,
+        states: HashMap::default(),
+    };
+
+    let mut reader = encoded.data.as_slice();
+    while !reader.is_empty() {
+        let header = read_varint(&mut reader)?;
+        let net_id = u16::try_from(header >> TAG_BITS)
+            .map(PlayerNetId)
+            .map_err(|_| DecodeError::InvalidNetId(header >> TAG_BITS))?;
+        let baseline_codes = || {
+            baseline
+                .and_then(|baseline| baseline.states.get(&net_id))
+                .copied()
+                .ok_or(DecodeError::MissingBaselineState(net_id))
+        };
+        let codes = match header & ((1 << TAG_BITS) - 1) {
+            TAG_UNCHANGED => baseline_codes()?,
+            TAG_DELTA => read_codes(&mut reader, precision, baseline_codes()?)?,
+            TAG_FULL => read_codes(&mut reader, precision, [0; 4])?,
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        if snapshot.states.insert(net_id, codes).is_some() {
+            return Err(DecodeError::DuplicatePlayer(net_id));
+        }
+
+        let [position_x, position_y, direction_x, direction_y] =
+            codes.map(|code| precision.decode_value(code));
+        players.push(PlayerState {
+            net_id,
+            position: Vec2::new(position_x, position_y),
+            direction: Vec2::new(direction_x, direction_y),
+        });
+    }
+
+    Ok((players, snapshot))
+}
+
+fn read_codes(
+    reader: &mut &[u8],
+    precision: StatePrecision,
+    baseline_codes: StateCodes,
+) -> Result<StateCodes, DecodeError> {
+    let mut codes = baseline_codes;
+    for code in &mut codes {
+        *code = precision.apply_delta(*code, read_varint(reader)?);
+    }
+    Ok(codes)
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(reader: &mut &[u8]) -> Result<u32, DecodeError> {
+    let mut value = 0;
+    for shift in (0..32).step_by(7) {
+        let (&byte, rest) = reader.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+        *reader = rest;
+        // The last byte may contain only the 4 remaining bits.
+        if shift == 28 && byte > 0x0f {
+            return Err(DecodeError::InvalidVarint);
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidVarint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn player_state(net_id: u16, position: Vec2, direction: Vec2) -> PlayerState {
+        PlayerState {
+            net_id: PlayerNetId(net_id),
+            position,
+            direction,
+        }
+    }
+
+    fn quantized(player: &PlayerState) -> PlayerState {
+        let quantize = |value: f32| (value / STATE_QUANTUM).round() * STATE_QUANTUM;
+        PlayerState {
+            net_id: player.net_id,
+            position: Vec2::new(quantize(player.position.x), quantize(player.position.y)),
+            direction: Vec2::new(quantize(player.direction.x), quantize(player.direction.y)),
+        }
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX - 1, u32::MAX] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut reader = data.as_slice();
+            assert_eq!(read_varint(&mut reader), Ok(value));
+            assert!(reader.is_empty());
+        }
+        for value in [0, 1, -1, i32::MAX, i32::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+
+        let mut reader = [0xff, 0xff, 0xff, 0xff, 0x1f].as_slice();
+        assert_eq!(read_varint(&mut reader), Err(DecodeError::InvalidVarint));
+        let mut reader = [0x80].as_slice();
+        assert_eq!(read_varint(&mut reader), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_unchanged_players_are_compact() {
+        let players = (0..10)
+            .map(|i| player_state(i, Vec2::new(i as f32 * 3.5, -20.25), Vec2::X))
+            .collect::<Vec<_>>();
+        let (full, snapshot) = encode_player_states(&players, StatePrecision::Quantized, None);
+        let (delta, _) = encode_player_states(
+            &players,
+            StatePrecision::Quantized,
+            Some((FrameNumber::new(2), &snapshot)),
+        );
+
+        assert_eq!(full.baseline_frame, None);
+        assert_eq!(delta.baseline_frame, Some(FrameNumber::new(2)));
+        assert_eq!(delta.data.len(), players.len());
+        assert!(full.data.len() > delta.data.len() * 8);
+
+        let (decoded, _) = decode_player_states(&delta, Some(&snapshot)).unwrap();
+        assert_eq!(decoded, players);
+    }
+
+    #[test]
+    fn test_exact_precision_keeps_bits() {
+        let players = vec![player_state(
+            1,
+            Vec2::new(0.1 + 0.2, std::f32::consts::PI),
+            Vec2::new(1.0, 1.0).normalize(),
+        )];
+        let (encoded, snapshot) = encode_player_states(&players, StatePrecision::Exact, None);
+        let (decoded, _) = decode_player_states(&encoded, None).unwrap();
+        assert_eq!(decoded, players);
+
+        // A baseline of a different precision is ignored.
+        let (encoded, _) = encode_player_states(
+            &players,
+            StatePrecision::Quantized,
+            Some((FrameNumber::new(2), &snapshot)),
+        );
+        assert_eq!(encoded.baseline_frame, None);
+    }
+
+    #[test]
+    fn test_missing_baseline() {
+        let players = vec![player_state(1, Vec2::ZERO, Vec2::ZERO)];
+        let (_, snapshot) = encode_player_states(&players, StatePrecision::Quantized, None);
+        let (encoded, _) = encode_player_states(
+            &players,
+            StatePrecision::Quantized,
+            Some((FrameNumber::new(2), &snapshot)),
+        );
+        assert_eq!(
+            decode_player_states(&encoded, None),
+            Err(DecodeError::MissingBaseline(FrameNumber::new(2)))
+        );
+        assert_eq!(
+            decode_player_states(&encoded, Some(&PlayerStatesSnapshot::default())),
+            Err(DecodeError::MissingBaselineState(PlayerNetId(1)))
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = PlayerStatesHistory::default();
+        for frame in 0..HISTORY_LEN as u16 + 10 {
+            let players = vec![player_state(frame, Vec2::ZERO, Vec2::ZERO)];
+            let (_, snapshot) = encode_player_states(&players, StatePrecision::Quantized, None);
+            history.push(FrameNumber::new(frame * 2), snapshot);
+        }
+        assert!(history.get(FrameNumber::new(18)).is_none());
+        assert!(history.get(FrameNumber::new(20)).is_some());
+        assert!(history.get(FrameNumber::new(21)).is_none());
+    }
+
+    /// Simulates a server that sends updates of players that join, leave, move
+    /// and stand still, against random acknowledged baselines.
+    #[test]
+    fn fuzz_roundtrip() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut sent_history = PlayerStatesHistory::default();
+        let mut received_history = PlayerStatesHistory::default();
+        let mut players = Vec::<PlayerState>::new();
+        let mut sent_frames = Vec::new();
+
+        for frame in 0..2000u16 {
+            let frame_number = FrameNumber::new(frame * 2);
+            players.retain(|_| rng.gen_bool(0.98));
+            for player in &mut players {
+                match rng.gen_range(0..4) {
+                    0 => {}
+                    1 => player.position += Vec2::new(rng.gen_range(-1.0..1.0), 0.0),
+                    2 => player.direction = Vec2::new(rng.gen(), rng.gen()).normalize_or_zero(),
+                    _ => player.position = Vec2::new(rng.gen_range(-1e4..1e4), rng.gen()),
+                }
+            }
+            if rng.gen_bool(0.1) {
+                let net_id = rng.gen_range(0..64);
+                if !players.iter().any(|player| player.net_id.0 == net_id) {
+                    players.push(player_state(
+                        net_id,
+                        Vec2::new(rng.gen(), rng.gen()),
+                        Vec2::ZERO,
+                    ));
+                }
+            }
+            let precision = if rng.gen_bool(0.05) {
+                StatePrecision::Exact
+            } else {
+                StatePrecision::Quantized
+            };
+
+            // Recent updates are more likely to be acknowledged.
+            let baseline = (!sent_frames.is_empty() && rng.gen_bool(0.9))
+                .then(|| {
+                    sent_frames[sent_frames.len() - 1 - rng.gen_range(0..sent_frames.len().min(8))]
+                })
+                .and_then(|frame_number| {
+                    sent_history
+                        .get(frame_number)
+                        .map(|snapshot| (frame_number, snapshot))
+                });
+            let (encoded, sent_snapshot) = encode_player_states(&players, precision, baseline);
+            sent_history.push(frame_number, sent_snapshot.clone());
+            sent_frames.push(frame_number);
+
+            let received_baseline = encoded
+                .baseline_frame
+                .and_then(|frame_number| received_history.get(frame_number));
+            let (decoded, received_snapshot) =
+                decode_player_states(&encoded, received_baseline).unwrap();
+            assert_eq!(received_snapshot, sent_snapshot);
+            let expected = match precision {
+                StatePrecision::Quantized => players.iter().map(quantized).collect::<Vec<_>>(),
+                StatePrecision::Exact => players.clone(),
+            };
+            assert_eq!(decoded, expected);
+            received_history.push(frame_number, received_snapshot);
+        }
+    }
+
+    /// Decoding corrupted or malicious data must not panic.
+    #[test]
+    fn fuzz_decode_garbage() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let players = (0..16)
+            .map(|i| player_state(i, Vec2::new(rng.gen(), rng.gen()), Vec2::Y))
+            .collect::<Vec<_>>();
+        let (valid, baseline) = encode_player_states(&players, StatePrecision::Quantized, None);
+
+        for _ in 0..10_000 {
+            let mut data = if rng.gen_bool(0.5) {
+                (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect()
+            } else {
+                valid.data.clone()
+            };
+            if !data.is_empty() {
+                for _ in 0..rng.gen_range(1..4) {
+                    let i = rng.gen_range(0..data.len());
+                    data[i] = rng.gen();
+                }
+                data.truncate(rng.gen_range(0..=data.len()));
+            }
+            let encoded = EncodedPlayerStates {
+                baseline_frame: rng.gen_bool(0.5).then_some(FrameNumber::new(0)),
+                precision: if rng.gen_bool(0.5) {
+                    StatePrecision::Quantized
+                } else {
+                    StatePrecision::Exact
+                },
+                data,
+            };
+            let _ = decode_player_states(&encoded, Some(&baseline));
+        }
+    }
+}
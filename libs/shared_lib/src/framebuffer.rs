@@ -4,39 +4,67 @@ use std::collections::VecDeque;
 
 pub type FrameNumber = WrappedCounter<u16>;
 
+/// Buffers of entities that don't move (or players that hold the same input)
+/// store a single run for hundreds of frames, so runs are allocated on demand
+/// and the allocation is shrunk once values stop changing as often.
+const MIN_RUNS_CAPACITY: usize = 16;
+/// How often (in pushed frames) buffers check whether they need to switch
+/// between storing runs and storing every frame.
+const STORAGE_ADAPTATION_PERIOD: u16 = 120;
+
+/// Stores a value per frame. Consecutive frames with equal values are
+/// compacted into runs, unless values change too often for that to save
+/// memory. The storage doesn't affect lookups.
 pub struct Framebuffer<T> {
     start_frame: FrameNumber,
-    /// Stores a frame number as the first element of the tuple.
-    buffer: VecDeque<T>,
+    storage: Storage<T>,
+    /// The number of stored frames.
+    len: u16,
     limit: FrameNumber,
+    pushes_since_adapted: u16,
+}
+
+enum Storage<T> {
+    /// Every frame is stored, for values that change (almost) every frame,
+    /// such as positions of moving players.
+    Dense(VecDeque<T>),
+    /// Each run lasts until the next one starts.
+    Runs(VecDeque<Run<T>>),
+}
+
+impl<T> Default for Storage<T> {
+    fn default() -> Self {
+        Self::Runs(VecDeque::new())
+    }
+}
+
+struct Run<T> {
+    start_frame: FrameNumber,
+    value: T,
 }
 
 impl std::fmt::Debug for Framebuffer<Vec2> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let buffer_edge_elements: Vec<String> = if self.buffer.len() > 6 {
-            vec![self.buffer[0].to_string(), "...".to_owned()]
-                .into_iter()
-                .chain(
-                    self.buffer
-                        .iter()
-                        .rev()
-                        .take(5)
-                        .map(ToString::to_string)
-                        .rev(),
-                )
-                .collect()
-        } else {
-            self.buffer
-                .iter()
-                .take(6)
+        let buffer_edge_elements: Vec<String> = if self.len() > 6 {
+            let mut elements: Vec<String> = self
+                .values()
+                .rev()
+                .take(5)
                 .map(ToString::to_string)
-                .collect()
+                .collect();
+            elements.push("...".to_owned());
+            elements.push(self.first().unwrap().to_string());
+            elements.reverse();
+            elements
+        } else {
+            self.values().take(6).map(ToString::to_string).collect()
         };
 
         f.debug_struct("Framebuffer")
             .field("start_frame", &self.start_frame())
             .field("end_frame", &self.end_frame())
             .field("limit", &self.limit())
+            .field("runs", &self.runs_len())
             .field(
                 "buffer",
                 &format_args!("[{}]", buffer_edge_elements.join(", ")),
@@ -55,19 +83,22 @@ impl<T: std::fmt::Debug> std::fmt::Debug for Framebuffer<Option<T>> {
             }
         }
 
-        let buffer_edge_elements: Vec<String> = if self.buffer.len() > 6 {
-            vec![format_option(&self.buffer[0]), "...".to_owned()]
-                .into_iter()
-                .chain(self.buffer.iter().rev().take(5).map(format_option).rev())
-                .collect()
+        let buffer_edge_elements: Vec<String> = if self.len() > 6 {
+            let mut elements: Vec<String> =
+                self.values().rev().take(5).map(format_option).collect();
+            elements.push("...".to_owned());
+            elements.push(format_option(self.first().unwrap()));
+            elements.reverse();
+            elements
         } else {
-            self.buffer.iter().take(6).map(format_option).collect()
+            self.values().take(6).map(format_option).collect()
         };
 
         f.debug_struct("Framebuffer")
             .field("start_frame", &self.start_frame())
             .field("end_frame", &self.end_frame())
             .field("limit", &self.limit())
+            .field("runs", &self.runs_len())
             .field(
                 "buffer",
                 &format_args!("[{}]", buffer_edge_elements.join(", ")),
@@ -81,8 +112,10 @@ impl<T> Framebuffer<T> {
         assert!(limit >= 1, "Framebuffer limit can't be lesser than 1");
         Self {
             start_frame,
-            buffer: VecDeque::with_capacity(limit as usize),
+            storage: Storage::default(),
+            len: 0,
             limit: FrameNumber::new(limit),
+            pushes_since_adapted: 0,
         }
     }
 
@@ -95,110 +128,369 @@ impl<T> Framebuffer<T> {
     }
 
     pub fn len(&self) -> u16 {
-        self.buffer.len() as u16
+        self.len
     }
 
     pub fn is_empty(&self) -> bool {
-        self.buffer.is_empty()
+        self.len == 0
     }
 
     pub fn limit(&self) -> u16 {
         self.limit.value()
     }
 
+    /// Includes the reserved capacity.
+    pub fn allocated_bytes(&self) -> usize {
+        match &self.storage {
+            Storage::Dense(values) => values.capacity() * std::mem::size_of::<T>(),
+            Storage::Runs(runs) => runs.capacity() * std::mem::size_of::<Run<T>>(),
+        }
+    }
+
     pub fn set_limit(&mut self, limit: u16) {
         assert!(limit >= 1, "Framebuffer limit can't be lesser than 1");
         self.limit = FrameNumber::new(limit);
-        for _ in self.limit.value() as usize..self.buffer.len() {
-            self.start_frame += FrameNumber::new(1);
-            self.buffer.pop_front();
+        while self.len > self.limit.value() {
+            self.pop_front();
         }
     }
 
-    pub fn push(&mut self, value: T) {
-        if self.buffer.len() == self.limit.value() as usize {
-            self.start_frame += FrameNumber::new(1);
-            self.buffer.pop_front();
+    pub fn get(&self, frame_number: FrameNumber) -> Option<&T> {
+        self.run_index(frame_number)
+            .map(|index| self.run_value(index))
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        match &self.storage {
+            Storage::Dense(values) => values.front(),
+            Storage::Runs(runs) => runs.front().map(|run| &run.value),
         }
-        self.buffer.push_back(value);
     }
 
-    pub fn get(&self, frame_number: FrameNumber) -> Option<&T> {
-        self.buffer
-            .get((frame_number - self.start_frame).value() as usize)
+    pub fn last(&self) -> Option<&T> {
+        match &self.storage {
+            Storage::Dense(values) => values.back(),
+            Storage::Runs(runs) => runs.back().map(|run| &run.value),
+        }
     }
 
-    pub fn get_mut(&mut self, frame_number: FrameNumber) -> Option<&mut T> {
-        self.buffer
-            .get_mut((frame_number - self.start_frame).value() as usize)
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (FrameNumber, &T)> {
+        (0..self.runs_len()).flat_map(move |index| {
+            let run_start = self.run_start(index);
+            let value = self.run_value(index);
+            (0..(self.run_end(index) - run_start).value())
+                .map(move |i| (run_start + FrameNumber::new(i), value))
+        })
     }
 
-    pub fn first(&self) -> Option<&T> {
-        self.buffer.front()
+    pub fn can_insert(&self, frame_number: FrameNumber) -> bool {
+        let frame_len = FrameNumber::new(self.len);
+        frame_number + self.limit >= self.start_frame + frame_len
     }
 
-    pub fn last(&self) -> Option<&T> {
-        self.buffer.back()
+    pub fn take(&mut self) -> Self {
+        let buf = Framebuffer {
+            start_frame: self.start_frame,
+            storage: std::mem::take(&mut self.storage),
+            len: std::mem::take(&mut self.len),
+            limit: self.limit,
+            pushes_since_adapted: std::mem::take(&mut self.pushes_since_adapted),
+        };
+        self.start_frame = self.end_frame();
+        buf
     }
 
-    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (FrameNumber, &T)> {
+    fn values(&self) -> impl DoubleEndedIterator<Item = &T> {
+        self.iter().map(|(_, value)| value)
+    }
+
+    /// Dense storage has a run per frame.
+    fn runs_len(&self) -> usize {
+        match &self.storage {
+            Storage::Dense(values) => values.len(),
+            Storage::Runs(runs) => runs.len(),
+        }
+    }
+
+    fn run_start(&self, index: usize) -> FrameNumber {
+        match &self.storage {
+            Storage::Dense(_) => self.start_frame + FrameNumber::new(index as u16),
+            Storage::Runs(runs) => runs[index].start_frame,
+        }
+    }
+
+    /// Returns the frame that follows the last frame of the run.
+    fn run_end(&self, index: usize) -> FrameNumber {
+        match &self.storage {
+            Storage::Dense(_) => self.start_frame + FrameNumber::new(index as u16 + 1),
+            Storage::Runs(runs) => runs
+                .get(index + 1)
+                .map_or(self.start_frame + FrameNumber::new(self.len), |next| {
+                    next.start_frame
+                }),
+        }
+    }
+
+    fn run_value(&self, index: usize) -> &T {
+        match &self.storage {
+            Storage::Dense(values) => &values[index],
+            Storage::Runs(runs) => &runs[index].value,
+        }
+    }
+
+    /// Returns the index of the run that contains the frame.
+    fn run_index(&self, frame_number: FrameNumber) -> Option<usize> {
+        let offset = (frame_number - self.start_frame).value();
+        if offset >= self.len {
+            return None;
+        }
         let start_frame = self.start_frame;
-        self.buffer
-            .iter()
-            .enumerate()
-            .map(move |(i, v)| (FrameNumber::new(i as u16) + start_frame, v))
+        match &self.storage {
+            Storage::Dense(_) => Some(offset as usize),
+            Storage::Runs(runs) => Some(
+                runs.partition_point(|run| (run.start_frame - start_frame).value() <= offset) - 1,
+            ),
+        }
+    }
+
+    fn pop_front(&mut self) {
+        self.start_frame += FrameNumber::new(1);
+        self.len -= 1;
+        let start_frame = self.start_frame;
+        match &mut self.storage {
+            Storage::Dense(values) => {
+                values.pop_front();
+            }
+            Storage::Runs(runs) => {
+                if self.len == 0 {
+                    runs.clear();
+                } else if runs
+                    .get(1)
+                    .map_or(false, |next| next.start_frame == start_frame)
+                {
+                    runs.pop_front();
+                    if runs.capacity() > MIN_RUNS_CAPACITY && runs.len() * 4 < runs.capacity() {
+                        runs.shrink_to((runs.len() * 2).max(MIN_RUNS_CAPACITY));
+                    }
+                } else {
+                    runs[0].start_frame = start_frame;
+                }
+            }
+        }
+    }
+}
+
+impl<T: PartialEq + Clone> Framebuffer<T> {
+    pub fn push(&mut self, value: T) {
+        if self.len == self.limit.value() {
+            self.pop_front();
+        }
+        let frame_number = self.start_frame + FrameNumber::new(self.len);
+        self.len += 1;
+        let limit = self.limit() as usize;
+        match &mut self.storage {
+            Storage::Dense(values) => values.push_back(value),
+            Storage::Runs(runs) => {
+                if runs.back().map_or(true, |run| run.value != value) {
+                    if runs.len() == runs.capacity() {
+                        // Runs of buffers that can't be compacted shouldn't grow past the limit
+                        // until the storage is adapted.
+                        let max_additional = limit.saturating_sub(runs.len());
+                        let additional = runs.len().max(MIN_RUNS_CAPACITY).min(max_additional);
+                        runs.reserve_exact(additional.max(1));
+                    }
+                    runs.push_back(Run {
+                        start_frame: frame_number,
+                        value,
+                    });
+                }
+            }
+        }
+
+        self.pushes_since_adapted += 1;
+        if self.pushes_since_adapted >= STORAGE_ADAPTATION_PERIOD {
+            self.pushes_since_adapted = 0;
+            self.adapt_storage();
+        }
     }
 
+    /// Every frame gets a run of its own, so that values can be changed
+    /// independently. The storage is compacted again once more values are
+    /// pushed.
     pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = (FrameNumber, &mut T)> {
         let start_frame = self.start_frame;
-        self.buffer
+        self.make_dense()
             .iter_mut()
             .enumerate()
             .map(move |(i, v)| (FrameNumber::new(i as u16) + start_frame, v))
     }
 
-    pub fn can_insert(&self, frame_number: FrameNumber) -> bool {
-        let frame_len = FrameNumber::new(self.buffer.len() as u16);
-        frame_number + self.limit >= self.start_frame + frame_len
+    /// The frame gets a run of its own, so that changing the value doesn't
+    /// affect neighbouring frames.
+    pub fn get_mut(&mut self, frame_number: FrameNumber) -> Option<&mut T> {
+        let index = self.run_index(frame_number)?;
+        let index = self.split_run(index, frame_number);
+        Some(match &mut self.storage {
+            Storage::Dense(values) => &mut values[index],
+            Storage::Runs(runs) => &mut runs[index].value,
+        })
     }
 
-    pub fn take(&mut self) -> Self {
-        let buf = Framebuffer {
-            start_frame: self.start_frame,
-            buffer: std::mem::take(&mut self.buffer),
-            limit: self.limit,
+    fn push_front(&mut self, value: T) {
+        self.start_frame -= FrameNumber::new(1);
+        self.len += 1;
+        let start_frame = self.start_frame;
+        match &mut self.storage {
+            Storage::Dense(values) => values.push_front(value),
+            Storage::Runs(runs) => {
+                if runs.front().map_or(false, |run| run.value == value) {
+                    runs[0].start_frame = start_frame;
+                } else {
+                    runs.push_front(Run { start_frame, value });
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, frame_number: FrameNumber, value: T) {
+        let index = self
+            .run_index(frame_number)
+            .expect("Expected a stored frame");
+        if *self.run_value(index) == value {
+            return;
+        }
+        let index = self.split_run(index, frame_number);
+        match &mut self.storage {
+            Storage::Dense(values) => values[index] = value,
+            Storage::Runs(runs) => {
+                runs[index].value = value;
+                // Merges the run with its neighbours if they store equal values.
+                if index + 1 < runs.len() && runs[index + 1].value == runs[index].value {
+                    runs.remove(index + 1);
+                }
+                if index > 0 && runs[index - 1].value == runs[index].value {
+                    runs.remove(index);
+                }
+            }
+        }
+    }
+
+    /// Splits the run so that the frame gets a run of its own, returns the
+    /// index of that run.
+    fn split_run(&mut self, index: usize, frame_number: FrameNumber) -> usize {
+        let run_end = self.run_end(index);
+        let Storage::Runs(runs) = &mut self.storage else {
+            return index;
         };
-        self.start_frame = self.end_frame();
-        buf
+        let next_frame = frame_number + FrameNumber::new(1);
+        if next_frame != run_end {
+            let value = runs[index].value.clone();
+            runs.insert(
+                index + 1,
+                Run {
+                    start_frame: next_frame,
+                    value,
+                },
+            );
+        }
+        if frame_number != runs[index].start_frame {
+            let value = runs[index].value.clone();
+            runs.insert(
+                index + 1,
+                Run {
+                    start_frame: frame_number,
+                    value,
+                },
+            );
+            return index + 1;
+        }
+        index
+    }
+
+    /// Switches to storing every frame if values change too often for runs to
+    /// save memory, and back to runs once they save at least a half.
+    fn adapt_storage(&mut self) {
+        let len = self.len as usize;
+        let runs_len = match &self.storage {
+            Storage::Dense(values) => {
+                values
+                    .iter()
+                    .zip(values.iter().skip(1))
+                    .filter(|(a, b)| a != b)
+                    .count()
+                    .min(len.saturating_sub(1))
+                    + 1
+            }
+            Storage::Runs(runs) => runs.len(),
+        };
+        let runs_bytes = runs_len * std::mem::size_of::<Run<T>>();
+        let dense_bytes = len * std::mem::size_of::<T>();
+        match &self.storage {
+            Storage::Dense(_) if runs_bytes * 2 <= dense_bytes => self.make_runs(),
+            Storage::Runs(_) if runs_bytes > dense_bytes => {
+                self.make_dense();
+            }
+            _ => {}
+        }
+    }
+
+    fn make_dense(&mut self) -> &mut VecDeque<T> {
+        if let Storage::Runs(_) = &self.storage {
+            let mut values = VecDeque::with_capacity(self.limit() as usize);
+            values.extend(self.values().cloned());
+            self.storage = Storage::Dense(values);
+        }
+        match &mut self.storage {
+            Storage::Dense(values) => values,
+            Storage::Runs(_) => unreachable!(),
+        }
+    }
+
+    fn make_runs(&mut self) {
+        let Storage::Dense(values) = std::mem::take(&mut self.storage) else {
+            return;
+        };
+        let mut runs: VecDeque<Run<T>> = VecDeque::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if runs.back().map_or(true, |run| run.value != value) {
+                runs.push_back(Run {
+                    start_frame: self.start_frame + FrameNumber::new(i as u16),
+                    value,
+                });
+            }
+        }
+        runs.shrink_to_fit();
+        self.storage = Storage::Runs(runs);
     }
 }
 
-impl<T: Default + std::fmt::Debug> Framebuffer<T> {
+impl<T: Default + PartialEq + Clone + std::fmt::Debug> Framebuffer<T> {
     pub fn insert(&mut self, frame_number: FrameNumber, value: T) {
-        let frame_len = FrameNumber::new(self.buffer.len() as u16);
+        let frame_len = FrameNumber::new(self.len);
         assert!(self.can_insert(frame_number), "Inserting for a frame {} would remove future history (start_frame: {}, limit: {}, len: {})", frame_number, self.start_frame, self.limit, frame_len);
 
         if frame_number < self.start_frame {
             for _ in frame_number + FrameNumber::new(1)..self.start_frame {
-                self.buffer.push_front(T::default());
+                self.push_front(T::default());
             }
-            self.buffer.push_front(value);
-            self.start_frame = frame_number;
+            self.push_front(value);
             return;
         }
 
-        let frame_len = FrameNumber::new(self.buffer.len() as u16);
         let end_frame = self.start_frame + frame_len - FrameNumber::new(1);
-        if self.buffer.is_empty() {
+        if self.is_empty() {
             self.start_frame = frame_number;
             self.push(value);
         } else if frame_number >= end_frame + self.limit {
-            self.buffer.clear();
+            match &mut self.storage {
+                Storage::Dense(values) => values.clear(),
+                Storage::Runs(runs) => runs.clear(),
+            }
+            self.len = 0;
             self.start_frame = frame_number;
             self.push(value);
         } else if frame_number <= end_frame {
-            let offset = frame_len - FrameNumber::new(1) - (end_frame - frame_number);
-            self.buffer[offset.value() as usize] = value;
+            self.set(frame_number, value);
         } else {
             for _ in end_frame + FrameNumber::new(1)..frame_number {
                 self.push(T::default());
@@ -228,8 +520,8 @@ impl<T> Framebuffer<Option<T>> {
         &self,
         mut frame_number: FrameNumber,
     ) -> Option<(FrameNumber, &T)> {
-        let max_frame = self.start_frame + FrameNumber::new(self.buffer.len() as u16) + self.limit
-            - FrameNumber::new(1);
+        let max_frame =
+            self.start_frame + FrameNumber::new(self.len) + self.limit - FrameNumber::new(1);
         if frame_number > max_frame {
             log::warn!(
                 "Requested frame {} is larger than max frame: {}",
@@ -241,25 +533,22 @@ impl<T> Framebuffer<Option<T>> {
         if frame_number > self.end_frame() {
             frame_number = self.end_frame();
         }
-        let skip = self.end_frame() - frame_number;
         log::trace!(
             "Skipping {} frames to look for an extrapolated value (requested frame: {})",
-            skip.value(),
+            (self.end_frame() - frame_number).value(),
             frame_number.value()
         );
-        let result = self
-            .buffer
-            .iter()
-            .rev()
-            .skip(skip.value() as usize)
-            .enumerate()
-            .find(|(_, v)| v.is_some())
-            .map(|(i, v)| {
-                (
-                    frame_number - FrameNumber::new(i as u16),
-                    v.as_ref().unwrap(),
-                )
-            });
+        let result = self.run_index(frame_number).and_then(|index| {
+            (0..=index).rev().find_map(|i| {
+                let value = self.run_value(i).as_ref()?;
+                let frame_number = if i == index {
+                    frame_number
+                } else {
+                    self.run_end(i) - FrameNumber::new(1)
+                };
+                Some((frame_number, value))
+            })
+        });
         if result.is_none() {
             log::trace!(
                 "No value found to extrapolate for frame {} (start_frame: {}, limit: {})",
@@ -273,13 +562,9 @@ impl<T> Framebuffer<Option<T>> {
 
     pub fn iter_with_interpolation(&self) -> impl Iterator<Item = (FrameNumber, &T)> {
         let mut last_some: Option<&T> = None;
-        let start_frame = self.start_frame;
-        self.buffer
-            .iter()
-            .enumerate()
+        self.iter()
             .skip_while(|(_, v)| v.is_none())
-            .map(move |(i, v)| {
-                let frame_number = FrameNumber::new(i as u16) + start_frame;
+            .map(move |(frame_number, v)| {
                 let value = if let Some(value) = v {
                     last_some = Some(value);
                     value
@@ -297,16 +582,14 @@ impl<T> Framebuffer<Option<T>> {
         let mut last_some: Option<&T> = None;
         let start_frame = self.start_frame;
         let first_some_index = self
-            .buffer
-            .iter()
+            .values()
             .position(|v| v.is_some())
-            .unwrap_or(self.buffer.len());
+            .unwrap_or(self.len as usize);
 
         (start_frame..=end_frame)
             .skip(first_some_index)
             .map(move |frame_number| {
-                let i = (frame_number - start_frame).value() as usize;
-                let value = if let Some(value) = self.buffer.get(i).and_then(|v| v.as_ref()) {
+                let value = if let Some(value) = self.get(frame_number).and_then(|v| v.as_ref()) {
                     last_some = Some(value);
                     value
                 } else {
@@ -319,7 +602,13 @@ impl<T> Framebuffer<Option<T>> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{framebuffer::Framebuffer, FrameNumber};
+    use crate::{
+        framebuffer::{Framebuffer, Storage, STORAGE_ADAPTATION_PERIOD},
+        FrameNumber,
+    };
+    use bevy::math::Vec2;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::VecDeque;
 
     #[test]
     fn test_push() {
@@ -328,7 +617,7 @@ mod tests {
         buffer.push(2);
         buffer.push(3);
 
-        assert_eq!(buffer.buffer.len(), 2);
+        assert_eq!(buffer.len(), 2);
         assert_eq!(buffer.start_frame, FrameNumber::new(1));
         assert_eq!(buffer.limit, FrameNumber::new(2));
         assert_eq!(
@@ -346,7 +635,7 @@ mod tests {
         buffer.push(1);
         buffer.set_limit(1);
 
-        assert_eq!(buffer.buffer.len(), 1);
+        assert_eq!(buffer.len(), 1);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(1));
         assert_eq!(
@@ -360,7 +649,7 @@ mod tests {
         let mut buffer = Framebuffer::<usize>::new(FrameNumber::new(0), 2);
         buffer.set_limit(1);
 
-        assert_eq!(buffer.buffer.len(), 0);
+        assert_eq!(buffer.len(), 0);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(1));
         assert_eq!(buffer.iter().collect::<Vec<_>>(), Vec::new());
@@ -373,7 +662,7 @@ mod tests {
         buffer.push(2);
         buffer.set_limit(1);
 
-        assert_eq!(buffer.buffer.len(), 1);
+        assert_eq!(buffer.len(), 1);
         assert_eq!(buffer.start_frame, FrameNumber::new(1));
         assert_eq!(buffer.limit, FrameNumber::new(1));
         assert_eq!(
@@ -389,7 +678,7 @@ mod tests {
         buffer.push(2);
         buffer.set_limit(3);
 
-        assert_eq!(buffer.buffer.len(), 2);
+        assert_eq!(buffer.len(), 2);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(3));
         assert_eq!(
@@ -407,7 +696,7 @@ mod tests {
         buffer.push(1);
 
         buffer.insert(FrameNumber::new(0) - FrameNumber::new(2), 2);
-        assert_eq!(buffer.buffer.len(), 3);
+        assert_eq!(buffer.len(), 3);
         assert_eq!(buffer.start_frame, FrameNumber::new(u16::MAX - 1));
         assert_eq!(buffer.limit, FrameNumber::new(3));
         assert_eq!(
@@ -435,7 +724,7 @@ mod tests {
         let mut buffer = Framebuffer::<usize>::new(FrameNumber::new(0), 2);
         buffer.insert(FrameNumber::new(1), 1);
 
-        assert_eq!(buffer.buffer.len(), 1);
+        assert_eq!(buffer.len(), 1);
         assert_eq!(buffer.start_frame, FrameNumber::new(1));
         assert_eq!(buffer.limit, FrameNumber::new(2));
         assert_eq!(
@@ -451,7 +740,7 @@ mod tests {
         buffer.push(2);
         buffer.insert(FrameNumber::new(3), 3);
 
-        assert_eq!(buffer.buffer.len(), 1);
+        assert_eq!(buffer.len(), 1);
         assert_eq!(buffer.start_frame, FrameNumber::new(3));
         assert_eq!(buffer.limit, FrameNumber::new(2));
         assert_eq!(
@@ -467,7 +756,7 @@ mod tests {
         buffer.push(2);
         buffer.insert(FrameNumber::new(1), 3);
 
-        assert_eq!(buffer.buffer.len(), 2);
+        assert_eq!(buffer.len(), 2);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(2));
         assert_eq!(
@@ -485,7 +774,7 @@ mod tests {
         buffer.push(1);
         buffer.insert(FrameNumber::new(1), 3);
 
-        assert_eq!(buffer.buffer.len(), 2);
+        assert_eq!(buffer.len(), 2);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(2));
         assert_eq!(
@@ -503,7 +792,7 @@ mod tests {
         buffer.push(1);
         buffer.insert(FrameNumber::new(2), 3);
 
-        assert_eq!(buffer.buffer.len(), 3);
+        assert_eq!(buffer.len(), 3);
         assert_eq!(buffer.start_frame, FrameNumber::new(0));
         assert_eq!(buffer.limit, FrameNumber::new(3));
         assert_eq!(
@@ -524,7 +813,7 @@ mod tests {
         buffer.push(3);
         buffer.insert(FrameNumber::new(4), 5);
 
-        assert_eq!(buffer.buffer.len(), 3);
+        assert_eq!(buffer.len(), 3);
         assert_eq!(buffer.start_frame, FrameNumber::new(2));
         assert_eq!(buffer.limit, FrameNumber::new(3));
         assert_eq!(
@@ -632,4 +921,232 @@ mod tests {
             ]
         )
     }
+
+    #[test]
+    fn test_push_compacts_equal_values() {
+        let mut buffer = Framebuffer::<usize>::new(FrameNumber::new(0), 5);
+        buffer.push(1);
+        buffer.push(1);
+        buffer.push(1);
+        buffer.push(2);
+        buffer.push(2);
+
+        assert_eq!(buffer.runs_len(), 2);
+        assert_eq!(buffer.get(FrameNumber::new(2)), Some(&1));
+        assert_eq!(buffer.get(FrameNumber::new(3)), Some(&2));
+        assert_eq!(buffer.get(FrameNumber::new(5)), None);
+
+        buffer.push(2);
+        buffer.push(2);
+        buffer.push(2);
+        assert_eq!(buffer.runs_len(), 1);
+        assert_eq!(buffer.start_frame, FrameNumber::new(3));
+        assert_eq!(
+            buffer.iter().collect::<Vec<_>>(),
+            (3..8)
+                .map(|frame| (FrameNumber::new(frame), &2usize))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_insert_splits_and_merges_runs() {
+        let mut buffer = Framebuffer::<usize>::new(FrameNumber::new(0), 5);
+        for _ in 0..5 {
+            buffer.push(1);
+        }
+
+        buffer.insert(FrameNumber::new(2), 2);
+        assert_eq!(buffer.runs_len(), 3);
+        assert_eq!(
+            buffer.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![1, 1, 2, 1, 1]
+        );
+
+        buffer.insert(FrameNumber::new(2), 1);
+        assert_eq!(buffer.runs_len(), 1);
+        assert_eq!(
+            buffer.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![1, 1, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn test_get_mut_splits_run() {
+        let mut buffer = Framebuffer::<usize>::new(FrameNumber::new(0), 3);
+        buffer.push(1);
+        buffer.push(1);
+        buffer.push(1);
+
+        *buffer.get_mut(FrameNumber::new(1)).unwrap() = 2;
+        assert_eq!(
+            buffer.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+            vec![1, 2, 1]
+        );
+
+        for (_, value) in buffer.iter_mut() {
+            *value += 1;
+        }
+        assert_eq!(
+            buffer.iter().collect::<Vec<_>>(),
+            vec![
+                (FrameNumber::new(0), &2usize),
+                (FrameNumber::new(1), &3usize),
+                (FrameNumber::new(2), &2usize)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_idle_buffer_allocates_less() {
+        let mut buffer = Framebuffer::<Option<usize>>::new(FrameNumber::new(0), 1200);
+        for frame in 0..2400 {
+            buffer.push(Some(frame / 600));
+        }
+
+        assert_eq!(buffer.runs_len(), 2);
+        assert!(buffer.allocated_bytes() < 1200 * std::mem::size_of::<Option<usize>>() / 10);
+    }
+
+    #[test]
+    fn test_storage_adapts_to_changing_values() {
+        let mut buffer = Framebuffer::<Vec2>::new(FrameNumber::new(0), 1200);
+        for frame in 0..STORAGE_ADAPTATION_PERIOD {
+            buffer.push(Vec2::new(frame as f32, 0.0));
+        }
+        assert!(matches!(buffer.storage, Storage::Dense(_)));
+        assert!(buffer.allocated_bytes() <= 1200 * std::mem::size_of::<Vec2>());
+
+        for _ in 0..STORAGE_ADAPTATION_PERIOD * 10 {
+            buffer.push(Vec2::ZERO);
+        }
+        assert!(matches!(buffer.storage, Storage::Runs(_)));
+        assert_eq!(buffer.len(), 1200);
+        assert_eq!(
+            buffer.get(FrameNumber::new(STORAGE_ADAPTATION_PERIOD * 11 - 1)),
+            Some(&Vec2::ZERO)
+        );
+        assert_eq!(
+            buffer.get(FrameNumber::new(STORAGE_ADAPTATION_PERIOD - 1)),
+            None
+        );
+    }
+
+    /// Stores every frame, the way buffers did before compaction.
+    struct DenseFramebuffer {
+        start_frame: FrameNumber,
+        buffer: VecDeque<Option<u8>>,
+        limit: FrameNumber,
+    }
+
+    impl DenseFramebuffer {
+        fn push(&mut self, value: Option<u8>) {
+            if self.buffer.len() == self.limit.value() as usize {
+                self.start_frame += FrameNumber::new(1);
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back(value);
+        }
+
+        fn insert(&mut self, frame_number: FrameNumber, value: Option<u8>) {
+            if frame_number < self.start_frame {
+                for _ in frame_number + FrameNumber::new(1)..self.start_frame {
+                    self.buffer.push_front(None);
+                }
+                self.buffer.push_front(value);
+                self.start_frame = frame_number;
+                return;
+            }
+            let frame_len = FrameNumber::new(self.buffer.len() as u16);
+            let end_frame = self.start_frame + frame_len - FrameNumber::new(1);
+            if self.buffer.is_empty() {
+                self.start_frame = frame_number;
+                self.push(value);
+            } else if frame_number >= end_frame + self.limit {
+                self.buffer.clear();
+                self.start_frame = frame_number;
+                self.push(value);
+            } else if frame_number <= end_frame {
+                self.buffer[(frame_number - self.start_frame).value() as usize] = value;
+            } else {
+                for _ in end_frame + FrameNumber::new(1)..frame_number {
+                    self.push(None);
+                }
+                self.push(value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_dense_buffer_under_rewinds() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let limit = 20;
+        let start_frame = FrameNumber::new(u16::MAX - 100);
+        let mut buffer = Framebuffer::<Option<u8>>::new(start_frame, limit);
+        let mut dense = DenseFramebuffer {
+            start_frame,
+            buffer: VecDeque::new(),
+            limit: FrameNumber::new(limit),
+        };
+
+        let mut used_dense_storage = false;
+        let mut used_runs_storage = false;
+        for i in 0..10000 {
+            // Few distinct values, to get runs of different lengths. Alternating between
+            // noisy and steady values makes the storage switch back and forth.
+            let value = if i / 1000 % 2 == 0 || rng.gen_bool(0.05) {
+                rng.gen_bool(0.8).then(|| rng.gen_range(0..3))
+            } else {
+                Some(1)
+            };
+            match rng.gen_range(0..4) {
+                0 => {
+                    buffer.push(value);
+                    dense.push(value);
+                }
+                1 => {
+                    if let Some(slot) = buffer.get_mut(buffer.start_frame() + FrameNumber::new(3)) {
+                        *slot = value;
+                        dense.buffer[3] = value;
+                    }
+                }
+                _ => {
+                    let frame_number = buffer.end_frame().add_signed(rng.gen_range(-25..25));
+                    if buffer.can_insert(frame_number) {
+                        buffer.insert(frame_number, value);
+                        dense.insert(frame_number, value);
+                    }
+                }
+            }
+
+            match buffer.storage {
+                Storage::Dense(_) => used_dense_storage = true,
+                Storage::Runs(_) => used_runs_storage = true,
+            }
+            assert_eq!(buffer.start_frame(), dense.start_frame);
+            assert_eq!(buffer.len() as usize, dense.buffer.len());
+            assert_eq!(
+                buffer.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+                dense.buffer.iter().copied().collect::<Vec<_>>()
+            );
+            for offset in 0..buffer.len() + 5 {
+                let frame_number = buffer.start_frame() + FrameNumber::new(offset);
+                assert_eq!(buffer.get(frame_number), dense.buffer.get(offset as usize));
+                let expected = dense
+                    .buffer
+                    .iter()
+                    .take(offset as usize + 1)
+                    .enumerate()
+                    .rev()
+                    .find_map(|(i, v)| {
+                        v.as_ref()
+                            .map(|v| (buffer.start_frame() + FrameNumber::new(i as u16), v))
+                    });
+                if offset < buffer.len() {
+                    assert_eq!(buffer.get_with_interpolation(frame_number), expected);
+                }
+            }
+        }
+        assert!(used_dense_storage && used_runs_storage);
+    }
 }
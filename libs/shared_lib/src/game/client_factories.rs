@@ -1,10 +1,21 @@
-use crate::game::{level::CollisionLogic, level_objects::*};
 #[cfg(feature = "client")]
 use crate::{
-    client::{assets::MuddleAssets, components::DebugUiVisibility, *},
+    client::{
+        asset_gc::LevelObjectAssetsGc,
+        assets::{
+            CustomObjectMaterials, MuddleAssets, CUBE_COLOR, CUBE_DEATH_COLOR, PLANE_COLOR,
+            PLANE_DEATH_COLOR, PLANE_FINISH_COLOR,
+        },
+        components::{DebugUiVisibility, PersonalBestGhost},
+        *,
+    },
     game::components::PredictedPosition,
     GHOST_SIZE_MULTIPLIER, PLAYER_RADIUS,
 };
+use crate::{
+    game::{emitter::EmitterDesc, level::CollisionLogic, level_objects::*},
+    messages::EntityNetId,
+};
 use bevy::{
     ecs::system::{EntityCommands, SystemParam},
     math::Vec2,
@@ -71,6 +82,40 @@ impl<'w, 's> ClientFactory<'w, 's> for PlayerClientFactory {
     }
 }
 
+pub struct PlayerGhostClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for PlayerGhostClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = Vec2;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        position: Self::Input,
+    ) {
+        // Is drawn below players, so that it doesn't cover the current player when they
+        // overlap.
+        commands.insert(PbrBundle {
+            mesh: deps.meshes.add(Mesh::from(XyCircle {
+                radius: PLAYER_RADIUS,
+            })),
+            material: deps.assets.materials.player_ghost.clone(),
+            transform: Transform::from_translation(position.extend(0.005)),
+            ..Default::default()
+        });
+        commands.insert(PersonalBestGhost);
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<PersonalBestGhost>();
+        let mesh = deps.mesh_query.get(commands.id()).unwrap().clone();
+        deps.meshes.remove(mesh);
+    }
+}
+
 pub struct PlayerSensorClientFactory;
 
 impl<'w, 's> ClientFactory<'w, 's> for PlayerSensorClientFactory {
@@ -105,6 +150,9 @@ pub struct PlaneClientFactory;
 
 #[derive(Clone)]
 pub struct LevelObjectInput<T: Clone> {
+    /// Assets of an object are tracked by its id, to be freed once it's
+    /// despawned.
+    pub net_id: EntityNetId,
     pub desc: T,
     pub collision_logic: CollisionLogic,
     pub is_ghost: bool,
@@ -129,10 +177,30 @@ impl<'w, 's> ClientFactory<'w, 's> for PlaneClientFactory {
             1.0
         };
 
+        let mut lod = None;
         let mesh = match &input.desc.form_desc {
-            PlaneFormDesc::Circle { radius } => Mesh::from(XyCircle {
-                radius: radius * ghost_size_multiplier,
-            }),
+            PlaneFormDesc::Circle { radius } => {
+                let circle = XyCircle {
+                    radius: radius * ghost_size_multiplier,
+                };
+                // Ghosts are only shown to builders, who need to see them in detail.
+                if !input.is_ghost {
+                    lod = Some((
+                        circle.radius,
+                        vec![
+                            Mesh::from(XyRegularPolygon {
+                                radius: circle.radius,
+                                sides: circle.optimal_segments_count() / 3,
+                            }),
+                            Mesh::from(XyRegularPolygon {
+                                radius: circle.radius,
+                                sides: LOD_BILLBOARD_POLYGON_SIDES,
+                            }),
+                        ],
+                    ));
+                }
+                Mesh::from(circle)
+            }
             PlaneFormDesc::Rectangle { size } => Mesh::from(XyPlane {
                 size: *size * ghost_size_multiplier,
             }),
@@ -203,6 +271,11 @@ impl<'w, 's> ClientFactory<'w, 's> for PlaneClientFactory {
             }
         };
 
+        let mesh = deps.add_level_object_mesh(input.net_id, mesh);
+        if let Some((radius, variants)) = lod {
+            let lod = deps.level_object_lod(&input, mesh.clone(), radius, variants);
+            commands.insert(lod);
+        }
         commands.insert(PbrBundle {
             visibility: Visibility {
                 is_visible: if input.is_ghost {
@@ -211,8 +284,8 @@ impl<'w, 's> ClientFactory<'w, 's> for PlaneClientFactory {
                     true
                 },
             },
-            mesh: deps.meshes.add(mesh),
-            material: {
+            mesh,
+            material: if input.desc.appearance.is_default() {
                 let materials = if input.is_ghost {
                     &deps.assets.materials.ghost
                 } else {
@@ -223,6 +296,18 @@ impl<'w, 's> ClientFactory<'w, 's> for PlaneClientFactory {
                     CollisionLogic::Death => materials.plane_death.clone(),
                     CollisionLogic::None => materials.plane.clone(),
                 }
+            } else {
+                let default_color = match input.collision_logic {
+                    CollisionLogic::Finish => PLANE_FINISH_COLOR,
+                    CollisionLogic::Death => PLANE_DEATH_COLOR,
+                    CollisionLogic::None => PLANE_COLOR,
+                };
+                deps.add_custom_material(
+                    input.net_id,
+                    &input.desc.appearance,
+                    default_color,
+                    input.is_ghost,
+                )
             },
             transform: Transform::from_translation(
                 input
@@ -236,11 +321,10 @@ impl<'w, 's> ClientFactory<'w, 's> for PlaneClientFactory {
     }
 
     #[cfg(feature = "client")]
-    fn remove_components(commands: &mut EntityCommands, deps: &mut Self::Dependencies) {
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
         commands.remove::<PbrBundle>();
         commands.remove::<bevy_mod_picking::PickableBundle>();
-        let mesh = deps.mesh_query.get(commands.id()).unwrap().clone();
-        deps.meshes.remove(mesh);
+        commands.remove::<LevelObjectLod>();
     }
 }
 
@@ -261,6 +345,28 @@ impl<'w, 's> ClientFactory<'w, 's> for CubeClientFactory {
         } else {
             1.0
         };
+        let mesh = deps.add_level_object_mesh(
+            input.net_id,
+            Mesh::from(shape::Cube {
+                size: input.desc.size * 2.0 * ghost_size_multiplier,
+            }),
+        );
+        if !input.is_ghost {
+            // From afar, only the top face of a cube is noticeable.
+            let top_face = lifted_mesh(
+                Mesh::from(XyPlane {
+                    size: Vec2::splat(input.desc.size * 2.0),
+                }),
+                input.desc.size,
+            );
+            let lod = deps.level_object_lod(
+                &input,
+                mesh.clone(),
+                input.desc.size * std::f32::consts::SQRT_2,
+                vec![top_face],
+            );
+            commands.insert(lod);
+        }
         commands.insert(PbrBundle {
             visibility: Visibility {
                 is_visible: if input.is_ghost {
@@ -269,10 +375,8 @@ impl<'w, 's> ClientFactory<'w, 's> for CubeClientFactory {
                     true
                 },
             },
-            mesh: deps.meshes.add(Mesh::from(shape::Cube {
-                size: input.desc.size * 2.0 * ghost_size_multiplier,
-            })),
-            material: {
+            mesh,
+            material: if input.desc.appearance.is_default() {
                 let materials = if input.is_ghost {
                     &deps.assets.materials.ghost
                 } else {
@@ -284,6 +388,17 @@ impl<'w, 's> ClientFactory<'w, 's> for CubeClientFactory {
                     // TODO: actually, reachable as we don't validate user's input yet: https://github.com/mvlabat/muddle-run/issues/36
                     CollisionLogic::Finish => unreachable!(),
                 }
+            } else {
+                let default_color = match input.collision_logic {
+                    CollisionLogic::Death => CUBE_DEATH_COLOR,
+                    CollisionLogic::None | CollisionLogic::Finish => CUBE_COLOR,
+                };
+                deps.add_custom_material(
+                    input.net_id,
+                    &input.desc.appearance,
+                    default_color,
+                    input.is_ghost,
+                )
             },
             transform: Transform::from_translation(
                 input
@@ -297,11 +412,10 @@ impl<'w, 's> ClientFactory<'w, 's> for CubeClientFactory {
     }
 
     #[cfg(feature = "client")]
-    fn remove_components(commands: &mut EntityCommands, deps: &mut Self::Dependencies) {
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
         commands.remove::<PbrBundle>();
         commands.remove::<bevy_mod_picking::PickableBundle>();
-        let mesh = deps.mesh_query.get(commands.id()).unwrap().clone();
-        deps.meshes.remove(mesh);
+        commands.remove::<LevelObjectLod>();
     }
 }
 
@@ -333,10 +447,13 @@ impl<'w, 's> ClientFactory<'w, 's> for RoutePointClientFactory {
                     deps.visibility_settings.route_points
                 },
             },
-            mesh: deps.meshes.add(Mesh::from(Pyramid {
-                height: ROUTE_POINT_HEIGHT * ghost_size_multiplier,
-                base_edge_half_len: ROUTE_POINT_BASE_EDGE_HALF_LEN * ghost_size_multiplier,
-            })),
+            mesh: deps.add_level_object_mesh(
+                input.net_id,
+                Mesh::from(Pyramid {
+                    height: ROUTE_POINT_HEIGHT * ghost_size_multiplier,
+                    base_edge_half_len: ROUTE_POINT_BASE_EDGE_HALF_LEN * ghost_size_multiplier,
+                }),
+            ),
             material: if input.is_ghost {
                 deps.assets.materials.ghost.route_point.clone()
             } else {
@@ -349,31 +466,404 @@ impl<'w, 's> ClientFactory<'w, 's> for RoutePointClientFactory {
     }
 
     #[cfg(feature = "client")]
-    fn remove_components(commands: &mut EntityCommands, deps: &mut Self::Dependencies) {
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<bevy_mod_picking::PickableBundle>();
+    }
+}
+
+/// Annotations are rendered with egui, the anchor is only needed to make them
+/// pickable with a mouse.
+pub const ANNOTATION_ANCHOR_RADIUS: f32 = 0.2;
+
+pub struct AnnotationClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for AnnotationClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = LevelObjectInput<AnnotationDesc>;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        input: Self::Input,
+    ) {
+        commands.insert(PbrBundle {
+            visibility: Visibility {
+                is_visible: if input.is_ghost {
+                    deps.visibility_settings.ghosts
+                } else {
+                    deps.visibility_settings.route_points && deps.visibility_settings.annotations
+                },
+            },
+            mesh: deps.add_level_object_mesh(
+                input.net_id,
+                Mesh::from(XyCircle {
+                    radius: ANNOTATION_ANCHOR_RADIUS,
+                }),
+            ),
+            material: if input.is_ghost {
+                deps.assets.materials.ghost.annotation.clone()
+            } else {
+                deps.assets.materials.normal.annotation.clone()
+            },
+            transform: Transform::from_translation(input.desc.position.extend(0.01)),
+            ..Default::default()
+        });
+        commands.insert(bevy_mod_picking::PickableBundle::default());
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<bevy_mod_picking::PickableBundle>();
+    }
+}
+
+pub const CAMERA_ANCHOR_RADIUS: f32 = 0.25;
+
+pub struct CameraAnchorClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for CameraAnchorClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = LevelObjectInput<CameraAnchorDesc>;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        input: Self::Input,
+    ) {
+        commands.insert(PbrBundle {
+            visibility: Visibility {
+                is_visible: if input.is_ghost {
+                    deps.visibility_settings.ghosts
+                } else {
+                    deps.visibility_settings.route_points
+                },
+            },
+            mesh: deps.add_level_object_mesh(
+                input.net_id,
+                Mesh::from(XyCircle {
+                    radius: CAMERA_ANCHOR_RADIUS,
+                }),
+            ),
+            material: if input.is_ghost {
+                deps.assets.materials.ghost.camera_anchor.clone()
+            } else {
+                deps.assets.materials.normal.camera_anchor.clone()
+            },
+            transform: Transform::from_translation(input.desc.position.extend(0.01)),
+            ..Default::default()
+        });
+        commands.insert(bevy_mod_picking::PickableBundle::default());
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<bevy_mod_picking::PickableBundle>();
+    }
+}
+
+pub const EMITTER_RADIUS: f32 = 0.3;
+
+pub struct EmitterClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for EmitterClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = LevelObjectInput<EmitterDesc>;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        input: Self::Input,
+    ) {
+        // Unlike route points, emitters are visible for runners, as they need to see
+        // where hazards come from.
+        commands.insert(PbrBundle {
+            visibility: Visibility {
+                is_visible: !input.is_ghost || deps.visibility_settings.ghosts,
+            },
+            mesh: deps.add_level_object_mesh(
+                input.net_id,
+                Mesh::from(XyCircle {
+                    radius: EMITTER_RADIUS,
+                }),
+            ),
+            material: if input.is_ghost {
+                deps.assets.materials.ghost.emitter.clone()
+            } else {
+                deps.assets.materials.normal.emitter.clone()
+            },
+            transform: Transform::from_translation(input.desc.position.extend(0.01)),
+            ..Default::default()
+        });
+        commands.insert(bevy_mod_picking::PickableBundle::default());
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<bevy_mod_picking::PickableBundle>();
+    }
+}
+
+/// Zones are drawn slightly above planes, as they usually mark a part of one.
+const ZONE_HEIGHT: f32 = 0.003;
+
+pub struct DeathZoneClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for DeathZoneClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = LevelObjectInput<DeathZoneDesc>;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        input: Self::Input,
+    ) {
+        let material = if input.is_ghost {
+            deps.assets.materials.ghost.death_zone.clone()
+        } else {
+            deps.assets.materials.normal.death_zone.clone()
+        };
+        insert_zone_components(
+            commands,
+            deps,
+            input.net_id,
+            input.desc.position,
+            input.desc.size,
+            input.is_ghost,
+            material,
+        );
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
+        commands.remove::<PbrBundle>();
+        commands.remove::<bevy_mod_picking::PickableBundle>();
+    }
+}
+
+pub struct FinishZoneClientFactory;
+
+impl<'w, 's> ClientFactory<'w, 's> for FinishZoneClientFactory {
+    type Dependencies = PbrClientParams<'w, 's>;
+    type Input = LevelObjectInput<FinishZoneDesc>;
+
+    #[cfg(feature = "client")]
+    fn insert_components(
+        commands: &mut EntityCommands,
+        deps: &mut Self::Dependencies,
+        input: Self::Input,
+    ) {
+        let material = if input.is_ghost {
+            deps.assets.materials.ghost.finish_zone.clone()
+        } else {
+            deps.assets.materials.normal.finish_zone.clone()
+        };
+        insert_zone_components(
+            commands,
+            deps,
+            input.net_id,
+            input.desc.position,
+            input.desc.size,
+            input.is_ghost,
+            material,
+        );
+    }
+
+    #[cfg(feature = "client")]
+    fn remove_components(commands: &mut EntityCommands, _deps: &mut Self::Dependencies) {
         commands.remove::<PbrBundle>();
         commands.remove::<bevy_mod_picking::PickableBundle>();
-        let mesh = deps.mesh_query.get(commands.id()).unwrap().clone();
-        deps.meshes.remove(mesh);
     }
 }
 
 #[cfg(feature = "client")]
-#[derive(Resource, Default)]
+fn insert_zone_components(
+    commands: &mut EntityCommands,
+    deps: &mut PbrClientParams,
+    net_id: EntityNetId,
+    position: Vec2,
+    size: Vec2,
+    is_ghost: bool,
+    material: Handle<StandardMaterial>,
+) {
+    let ghost_size_multiplier = if is_ghost { GHOST_SIZE_MULTIPLIER } else { 1.0 };
+    commands.insert(PbrBundle {
+        visibility: Visibility {
+            is_visible: !is_ghost || deps.visibility_settings.ghosts,
+        },
+        mesh: deps.add_level_object_mesh(
+            net_id,
+            Mesh::from(XyPlane {
+                size: size * ghost_size_multiplier,
+            }),
+        ),
+        material,
+        transform: Transform::from_translation(position.extend(ZONE_HEIGHT)),
+        ..Default::default()
+    });
+    commands.insert(bevy_mod_picking::PickableBundle::default());
+}
+
+#[cfg(feature = "client")]
+#[derive(Resource)]
 pub struct VisibilitySettings {
     pub debug: bool,
     pub route_points: bool,
     pub ghosts: bool,
+    /// Builders can preview simplified collision outlines of concave planes.
+    pub collision_outlines: bool,
+    /// Builders can see where runners can be spawned.
+    pub spawn_areas: bool,
+    /// Builders can hide annotations if they get in the way.
+    pub annotations: bool,
+}
+
+#[cfg(feature = "client")]
+impl Default for VisibilitySettings {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            route_points: false,
+            ghosts: false,
+            collision_outlines: false,
+            spawn_areas: true,
+            annotations: true,
+        }
+    }
+}
+
+/// The distance from the camera to an object's bounding circle, starting from
+/// which each of the simplified variants is shown.
+pub const LOD_VARIANT_DISTANCES: [f32; 2] = [40.0, 80.0];
+/// Far away circles are indistinguishable from octagons.
+pub const LOD_BILLBOARD_POLYGON_SIDES: u32 = 8;
+
+/// Describes cheaper variants of a level object's mesh, which the client swaps
+/// in as the object gets further from the camera. Ghosts don't have them.
+#[cfg(feature = "client")]
+#[derive(Component, Clone, Debug)]
+pub struct LevelObjectLod {
+    /// Are ordered by `min_distance`, the first variant is the full mesh.
+    pub variants: Vec<LodVariant>,
+    /// The radius of the object's bounding circle.
+    pub radius: f32,
+    /// Objects without collision logic don't affect runners, so the client can
+    /// hide them altogether to stay within its draw calls budget.
+    pub is_decorative: bool,
+}
+
+#[cfg(feature = "client")]
+#[derive(Clone, Debug)]
+pub struct LodVariant {
+    pub min_distance: f32,
+    pub mesh: Handle<Mesh>,
+}
+
+#[cfg(feature = "client")]
+impl LevelObjectLod {
+    /// Returns the index of the variant to show at the distance from the
+    /// object's center.
+    pub fn variant_index(&self, distance: f32) -> usize {
+        let distance = distance - self.radius;
+        self.variants
+            .iter()
+            .rposition(|variant| variant.min_distance <= distance)
+            .unwrap_or(0)
+    }
+
+    pub fn is_simplest(&self, variant_index: usize) -> bool {
+        variant_index + 1 >= self.variants.len()
+    }
+}
+
+/// Moves the mesh vertices along the Z axis.
+#[cfg(feature = "client")]
+fn lifted_mesh(mut mesh: Mesh, z: f32) -> Mesh {
+    if let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for position in positions {
+            position[2] += z;
+        }
+    }
+    mesh
 }
 
 #[cfg(feature = "client")]
 #[derive(SystemParam)]
 pub struct PbrClientParams<'w, 's> {
     meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    custom_materials: ResMut<'w, CustomObjectMaterials>,
+    assets_gc: ResMut<'w, LevelObjectAssetsGc>,
     assets: MuddleAssets<'w, 's>,
     visibility_settings: Res<'w, VisibilitySettings>,
     mesh_query: Query<'w, 's, &'static Handle<Mesh>>,
 }
 
+#[cfg(feature = "client")]
+impl<'w, 's> PbrClientParams<'w, 's> {
+    /// Despawned objects don't free their assets right away, see
+    /// [`LevelObjectAssetsGc`].
+    pub fn release_level_object_assets(&mut self, net_id: EntityNetId) {
+        self.assets_gc.release(net_id);
+    }
+
+    fn add_level_object_mesh(&mut self, net_id: EntityNetId, mesh: Mesh) -> Handle<Mesh> {
+        let handle = self.meshes.add(mesh);
+        self.assets_gc.track_mesh(net_id, handle.clone());
+        handle
+    }
+
+    fn level_object_lod<T: Clone>(
+        &mut self,
+        input: &LevelObjectInput<T>,
+        full_mesh: Handle<Mesh>,
+        radius: f32,
+        simplified_meshes: Vec<Mesh>,
+    ) -> LevelObjectLod {
+        let mut variants = vec![LodVariant {
+            min_distance: 0.0,
+            mesh: full_mesh,
+        }];
+        for (min_distance, mesh) in LOD_VARIANT_DISTANCES.into_iter().zip(simplified_meshes) {
+            variants.push(LodVariant {
+                min_distance,
+                mesh: self.add_level_object_mesh(input.net_id, mesh),
+            });
+        }
+        LevelObjectLod {
+            variants,
+            radius,
+            is_decorative: input.collision_logic == CollisionLogic::None,
+        }
+    }
+
+    fn add_custom_material(
+        &mut self,
+        net_id: EntityNetId,
+        appearance: &ObjectAppearance,
+        default_color: [f32; 3],
+        is_ghost: bool,
+    ) -> Handle<StandardMaterial> {
+        let handle = self.custom_materials.get_or_add(
+            &mut self.materials,
+            appearance,
+            default_color,
+            is_ghost,
+        );
+        self.assets_gc.track_material(net_id, handle.clone());
+        handle
+    }
+}
+
 #[cfg(not(feature = "client"))]
 #[derive(SystemParam)]
 pub struct PbrClientParams<'w, 's> {
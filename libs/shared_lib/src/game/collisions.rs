@@ -5,23 +5,32 @@ use crate::{
             PlayerSensorState, PlayerSensors, Position, Spawned,
         },
         events::{CollisionLogicChanged, PlayerDeath, PlayerFinish},
-        level::LevelParams,
+        level::{CollisionLogic, LevelParams},
     },
+    messages::{EntityNetId, PlayerNetId},
+    registry::EntityRegistry,
     util::get_item,
     SimulationTime,
 };
 use bevy::{
     ecs::{
+        change_detection::Mut,
         entity::Entity,
         event::{EventReader, EventWriter},
         query::QueryEntityError,
         system::{In, Query, RemovedComponents, Res, SystemParam},
     },
     log,
-    utils::HashSet,
+    utils::{HashMap, HashSet},
 };
 use bevy_rapier2d::pipeline::CollisionEvent;
 
+/// Players' contact updates are applied in parallel if there are at least
+/// this many players to update. Spawning tasks isn't worth it for a couple of
+/// contacts.
+const PARALLEL_CONTACT_UPDATES_MIN_PLAYERS: usize = 4;
+const CONTACT_UPDATES_BATCH_SIZE: usize = 2;
+
 #[derive(SystemParam)]
 pub struct CollisionQueries<'w, 's> {
     players: Query<
@@ -36,9 +45,49 @@ pub struct CollisionQueries<'w, 's> {
     >,
     player_sensors: Query<'w, 's, (Entity, &'static PlayerSensor)>,
     all_entities: Query<'w, 's, Entity>,
+    player_registry: Res<'w, EntityRegistry<PlayerNetId>>,
+}
+
+struct ContactUpdate {
+    /// Is `None` for the player collider itself.
+    sensor_entity: Option<Entity>,
+    level_object_entity: Entity,
+    collision_logic: CollisionLogic,
+    contacting: bool,
 }
 
-/// The system returns player entities whose intersections were changed.
+impl ContactUpdate {
+    fn apply(&self, player_sensors: &mut PlayerSensors) {
+        let sensor_state = match self.sensor_entity {
+            Some(sensor_entity) => {
+                let (_, sensor_state) = player_sensors
+                    .sensors
+                    .iter_mut()
+                    .find(|(entity, _)| *entity == sensor_entity)
+                    .expect("Player is expected to know a sensor connected to it");
+                sensor_state
+            }
+            None => &mut player_sensors.main,
+        };
+        if self.contacting {
+            sensor_state
+                .contacting
+                .push((self.level_object_entity, self.collision_logic));
+        } else {
+            sensor_state
+                .contacting
+                .drain_filter(|(entity, _)| *entity == self.level_object_entity);
+        }
+    }
+}
+
+/// The system returns player entities whose intersections were changed,
+/// sorted by their net ids and simulated frames.
+///
+/// Every collision event touches the sensors of a single player, so events
+/// are grouped by players (which makes them independent collision islands)
+/// and then applied in parallel. Events of the same player keep their order,
+/// which makes the result identical to applying them one by one.
 pub fn process_collision_events_system(
     time: Res<SimulationTime>,
     mut collision_events: EventReader<CollisionEvent>,
@@ -48,8 +97,12 @@ pub fn process_collision_events_system(
     level_object_server_ghost_parents: Query<&LevelObjectServerGhostParent>,
     level: LevelParams,
 ) -> Vec<Entity> {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
     let mut changed_players = HashSet::default();
     let removed_level_objects = removed_level_objects.iter().collect::<Vec<_>>();
+    let mut contact_updates: HashMap<Entity, Vec<ContactUpdate>> = HashMap::default();
 
     for event in collision_events.iter() {
         let (contacting, mut entity1, mut entity2) = match event {
@@ -93,55 +146,54 @@ pub fn process_collision_events_system(
             entity2
         );
 
-        if let Ok((player_sensor_entity, PlayerSensor(player_entity))) =
-            queries.player_sensors.get(other_entity)
-        {
-            let (_, spawned, player_frame_simulated, mut player_sensors) = queries
-                .players
-                .get_mut(*player_entity)
-                .expect("Expected a player for an existing sensor");
-            let (_, sensor_state) = player_sensors
-                .sensors
-                .iter_mut()
-                .find(|(sensor_entity, _)| *sensor_entity == player_sensor_entity)
-                .expect("Player is expected to know a sensor connected to it");
-
-            if contacting {
-                sensor_state
-                    .contacting
-                    .push((level_object_entity, level_object.collision_logic));
-            } else {
-                sensor_state
-                    .contacting
-                    .drain_filter(|(entity, _)| *entity == level_object_entity);
-            }
-            if spawned.is_spawned(time.entity_simulation_frame(player_frame_simulated)) {
-                changed_players.insert(*player_entity);
-            }
-        } else if let Ok((_, spawned, player_frame_simulated, mut player_sensors)) =
-            queries.players.get_mut(other_entity)
-        {
-            let player_entity = other_entity;
-            // Intersection with a player collider itself.
-            if contacting {
-                player_sensors
-                    .main
-                    .contacting
-                    .push((level_object_entity, level_object.collision_logic));
+        let (player_entity, sensor_entity) =
+            if let Ok((player_sensor_entity, PlayerSensor(player_entity))) =
+                queries.player_sensors.get(other_entity)
+            {
+                (*player_entity, Some(player_sensor_entity))
+            } else if queries.players.contains(other_entity) {
+                // Intersection with a player collider itself.
+                (other_entity, None)
             } else {
-                player_sensors
-                    .main
-                    .contacting
-                    .drain_filter(|(entity, _)| *entity == level_object_entity);
-            }
-            if spawned.is_spawned(time.entity_simulation_frame(player_frame_simulated)) {
-                changed_players.insert(player_entity);
-            }
+                log::warn!(
+                    "Contact event for neither a player, nor a player sensor: {:?}",
+                    other_entity
+                );
+                continue;
+            };
+        let (_, spawned, player_frame_simulated, _) = queries
+            .players
+            .get(player_entity)
+            .expect("Expected a player for an existing sensor");
+        if spawned.is_spawned(time.entity_simulation_frame(player_frame_simulated)) {
+            changed_players.insert(player_entity);
+        }
+        contact_updates
+            .entry(player_entity)
+            .or_default()
+            .push(ContactUpdate {
+                sensor_entity,
+                level_object_entity,
+                collision_logic: level_object.effective_collision_logic(),
+                contacting,
+            });
+    }
+
+    if !contact_updates.is_empty() {
+        let apply_contact_updates =
+            |(player_entity, _, _, mut player_sensors): (Entity, _, _, Mut<PlayerSensors>)| {
+                if let Some(updates) = contact_updates.get(&player_entity) {
+                    for update in updates {
+                        update.apply(&mut player_sensors);
+                    }
+                }
+            };
+        if contact_updates.len() < PARALLEL_CONTACT_UPDATES_MIN_PLAYERS {
+            queries.players.for_each_mut(apply_contact_updates);
         } else {
-            log::warn!(
-                "Contact event for neither a player, nor a player sensor: {:?}",
-                other_entity
-            );
+            queries
+                .players
+                .par_for_each_mut(CONTACT_UPDATES_BATCH_SIZE, apply_contact_updates);
         }
     }
 
@@ -180,13 +232,35 @@ pub fn process_collision_events_system(
         }
     }
 
-    changed_players.into_iter().collect()
+    let mut changed_players = changed_players
+        .into_iter()
+        .map(|player_entity| {
+            let (_, _, player_frame_simulated, _) = queries
+                .players
+                .get(player_entity)
+                .expect("Expected an existing player for a collision event");
+            (
+                queries
+                    .player_registry
+                    .get_id(player_entity)
+                    .map(|player_net_id| player_net_id.0),
+                time.entity_simulation_frame(player_frame_simulated),
+                player_entity,
+            )
+        })
+        .collect::<Vec<_>>();
+    changed_players.sort_unstable();
+    changed_players
+        .into_iter()
+        .map(|(_, _, player_entity)| player_entity)
+        .collect()
 }
 
 pub fn process_players_with_new_collisions_system(
     In(players_with_new_collisions): In<Vec<Entity>>,
     time: Res<SimulationTime>,
     players: Query<(&Position, Option<&PlayerFrameSimulated>, &PlayerSensors)>,
+    level_object_registry: Res<EntityRegistry<EntityNetId>>,
     mut player_death_events: EventWriter<PlayerDeath>,
     mut player_finish_events: EventWriter<PlayerFinish>,
 ) {
@@ -203,6 +277,11 @@ pub fn process_players_with_new_collisions_system(
             }
         };
 
+        let level_object_net_id = |collision_logic| {
+            player_sensors
+                .contacted_object(collision_logic)
+                .and_then(|entity| level_object_registry.get_id(entity))
+        };
         if player_sensors.player_is_dead() {
             #[cfg(not(feature = "client"))]
             log::debug!(
@@ -210,7 +289,10 @@ pub fn process_players_with_new_collisions_system(
                 entity,
                 _player_position
             );
-            player_death_events.send(PlayerDeath(entity));
+            player_death_events.send(PlayerDeath {
+                player_entity: entity,
+                level_object_net_id: level_object_net_id(CollisionLogic::Death),
+            });
         } else if player_sensors.player_has_finished() {
             #[cfg(not(feature = "client"))]
             log::debug!(
@@ -218,7 +300,61 @@ pub fn process_players_with_new_collisions_system(
                 entity,
                 _player_position
             );
-            player_finish_events.send(PlayerFinish(entity));
+            player_finish_events.send(PlayerFinish {
+                player_entity: entity,
+                level_object_net_id: level_object_net_id(CollisionLogic::Finish),
+            });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact_update(
+        sensor_entity: Option<Entity>,
+        level_object_entity: Entity,
+        contacting: bool,
+    ) -> ContactUpdate {
+        ContactUpdate {
+            sensor_entity,
+            level_object_entity,
+            collision_logic: CollisionLogic::None,
+            contacting,
+        }
+    }
+
+    #[test]
+    fn test_contact_updates() {
+        let sensor = Entity::from_raw(1);
+        let (platform, finish) = (Entity::from_raw(2), Entity::from_raw(3));
+        let mut player_sensors = PlayerSensors {
+            main: PlayerSensorState::default(),
+            sensors: vec![(sensor, PlayerSensorState::default())],
+        };
+
+        // Starting and stopping a contact within the same frame leaves nothing.
+        for update in [
+            contact_update(None, platform, true),
+            contact_update(Some(sensor), finish, true),
+            contact_update(None, finish, true),
+            contact_update(None, finish, false),
+        ] {
+            update.apply(&mut player_sensors);
+        }
+        assert_eq!(
+            player_sensors.main.contacting,
+            vec![(platform, CollisionLogic::None)]
+        );
+        assert_eq!(
+            player_sensors.sensors[0].1.contacting,
+            vec![(finish, CollisionLogic::None)]
+        );
+        assert_eq!(
+            player_sensors.contacted_object(CollisionLogic::None),
+            Some(platform)
+        );
+        assert_eq!(player_sensors.contacted_object(CollisionLogic::Death), None);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::{
+    framebuffer::FrameNumber,
+    game::commands::{
+        DeferredCommand, DeferredQueue, DespawnLevelObject, DespawnPlayer, SpawnPlayer,
+        SwitchPlayerRole, UpdateLevelObject, UpdateLevelSettings,
+    },
+};
+use bevy::ecs::{
+    system::{ResMut, Resource, SystemParam},
+    world::World,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt::Write, marker::PhantomData};
+
+pub const DEFAULT_COMMAND_LOG_CAPACITY: usize = 4096;
+
+/// A copy of a drained command, so that the exact stream of commands can be
+/// inspected or replayed later.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecordedCommand {
+    SwitchPlayerRole(SwitchPlayerRole),
+    DespawnPlayer(DespawnPlayer),
+    DespawnLevelObject(DespawnLevelObject),
+    UpdateLevelObject(UpdateLevelObject),
+    UpdateLevelSettings(UpdateLevelSettings),
+    SpawnPlayer(SpawnPlayer),
+}
+
+impl RecordedCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SwitchPlayerRole(_) => "SwitchPlayerRole",
+            Self::DespawnPlayer(_) => "DespawnPlayer",
+            Self::DespawnLevelObject(_) => "DespawnLevelObject",
+            Self::UpdateLevelObject(_) => "UpdateLevelObject",
+            Self::UpdateLevelSettings(_) => "UpdateLevelSettings",
+            Self::SpawnPlayer(_) => "SpawnPlayer",
+        }
+    }
+
+    pub fn frame_number(&self) -> Option<FrameNumber> {
+        match self {
+            Self::SwitchPlayerRole(command) => Some(command.frame_number),
+            Self::DespawnPlayer(command) => Some(command.frame_number),
+            Self::DespawnLevelObject(command) => Some(command.frame_number),
+            Self::UpdateLevelObject(command) => Some(command.frame_number),
+            Self::UpdateLevelSettings(_) | Self::SpawnPlayer(_) => None,
+        }
+    }
+
+    /// A short description of the payload, full level objects are way too
+    /// verbose to be dumped.
+    pub fn summary(&self) -> String {
+        match self {
+            Self::SwitchPlayerRole(command) => {
+                format!("player {} -> {:?}", command.net_id.0, command.role)
+            }
+            Self::DespawnPlayer(command) => {
+                format!("player {} ({:?})", command.net_id.0, command.reason)
+            }
+            Self::DespawnLevelObject(command) => format!("object {}", command.net_id.0),
+            Self::UpdateLevelObject(command) => format!(
+                "object {} ({})",
+                command.object.net_id.0,
+                command.object.desc.label()
+            ),
+            Self::UpdateLevelSettings(_) => String::new(),
+            Self::SpawnPlayer(command) => format!(
+                "player {} at {:?}",
+                command.net_id.0, command.start_position
+            ),
+        }
+    }
+
+    /// Pushes the command back to its queue.
+    pub fn push_to_queue(self, world: &mut World) {
+        match self {
+            Self::SwitchPlayerRole(command) => push_command(world, command),
+            Self::DespawnPlayer(command) => push_command(world, command),
+            Self::DespawnLevelObject(command) => push_command(world, command),
+            Self::UpdateLevelObject(command) => push_command(world, command),
+            Self::UpdateLevelSettings(command) => push_command(world, command),
+            Self::SpawnPlayer(command) => push_command(world, command),
+        }
+    }
+}
+
+fn push_command<T: DeferredCommand + RecordCommand>(world: &mut World, command: T) {
+    world
+        .get_resource_mut::<DeferredQueue<T>>()
+        .expect("Expected a command queue to replay a command")
+        .push(command);
+}
+
+/// Implemented by the commands that [`CommandLog`] is able to record.
+pub trait RecordCommand: Clone + Send + Sync + 'static {
+    fn record(&self) -> RecordedCommand;
+}
+
+impl RecordCommand for SwitchPlayerRole {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::SwitchPlayerRole(self.clone())
+    }
+}
+
+impl RecordCommand for DespawnPlayer {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::DespawnPlayer(self.clone())
+    }
+}
+
+impl RecordCommand for DespawnLevelObject {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::DespawnLevelObject(self.clone())
+    }
+}
+
+impl RecordCommand for UpdateLevelObject {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::UpdateLevelObject(self.clone())
+    }
+}
+
+impl RecordCommand for UpdateLevelSettings {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::UpdateLevelSettings(self.clone())
+    }
+}
+
+impl RecordCommand for SpawnPlayer {
+    fn record(&self) -> RecordedCommand {
+        RecordedCommand::SpawnPlayer(self.clone())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandLogEntry {
+    /// The simulation frame on which the command was drained.
+    pub drained_at: FrameNumber,
+    pub command: RecordedCommand,
+}
+
+/// A ring buffer of the drained commands, is meant for debugging the order in
+/// which commands get processed. Recording is disabled by default.
+#[derive(Resource)]
+pub struct CommandLog {
+    enabled: bool,
+    capacity: usize,
+    entries: VecDeque<CommandLogEntry>,
+}
+
+impl Default for CommandLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_COMMAND_LOG_CAPACITY)
+    }
+}
+
+impl CommandLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            enabled: false,
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn push(&mut self, entry: CommandLogEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &CommandLogEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Removes and returns the recorded entries, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = CommandLogEntry> + '_ {
+        self.entries.drain(..)
+    }
+
+    /// Formats the log as a table, one command per line.
+    pub fn dump(&self) -> String {
+        let mut dump = String::new();
+        for entry in &self.entries {
+            let frame_number = entry.command.frame_number().map_or_else(
+                || "-".to_owned(),
+                |frame_number| frame_number.value().to_string(),
+            );
+            let _ = writeln!(
+                dump,
+                "{:>5} {:<20} {:>5} {}",
+                entry.drained_at.value(),
+                entry.command.name(),
+                frame_number,
+                entry.command.summary()
+            );
+        }
+        dump
+    }
+}
+
+/// Enables the log and makes the queues record drained commands right away,
+/// instead of picking the flag up on the next
+/// `collect_recorded_commands_system` run. Lets the commands drained on the
+/// very first frame get recorded too.
+pub fn enable_command_log(world: &mut World) {
+    fn start_recording<T: DeferredCommand + RecordCommand>(world: &mut World) {
+        world.resource_mut::<DeferredQueue<T>>().take_recorded(true);
+    }
+
+    world.resource_mut::<CommandLog>().set_enabled(true);
+    start_recording::<SwitchPlayerRole>(world);
+    start_recording::<DespawnPlayer>(world);
+    start_recording::<DespawnLevelObject>(world);
+    start_recording::<UpdateLevelObject>(world);
+    start_recording::<UpdateLevelSettings>(world);
+    start_recording::<SpawnPlayer>(world);
+}
+
+/// Pushes the commands recorded at `frame_number` back to their queues, to be
+/// drained by a world that runs the same frame. Is meant for reproducing
+/// command processing order bugs in tests.
+pub fn replay_recorded_commands<'a>(
+    world: &mut World,
+    entries: impl IntoIterator<Item = &'a CommandLogEntry>,
+    frame_number: FrameNumber,
+) {
+    for entry in entries {
+        if entry.drained_at == frame_number {
+            entry.command.clone().push_to_queue(world);
+        }
+    }
+}
+
+#[derive(SystemParam)]
+pub struct CommandQueues<'w, 's> {
+    switch_player_role: ResMut<'w, DeferredQueue<SwitchPlayerRole>>,
+    despawn_player: ResMut<'w, DeferredQueue<DespawnPlayer>>,
+    despawn_level_object: ResMut<'w, DeferredQueue<DespawnLevelObject>>,
+    update_level_object: ResMut<'w, DeferredQueue<UpdateLevelObject>>,
+    update_level_settings: ResMut<'w, DeferredQueue<UpdateLevelSettings>>,
+    spawn_player: ResMut<'w, DeferredQueue<SpawnPlayer>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+/// Moves the commands recorded by the queues to the log. The queues are
+/// visited in the same order as the `SPAWN` stage drains them.
+pub fn collect_recorded_commands_system(
+    mut command_log: ResMut<CommandLog>,
+    mut queues: CommandQueues,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let enabled = command_log.enabled;
+    let recorded = [
+        queues.switch_player_role.take_recorded(enabled),
+        queues.despawn_player.take_recorded(enabled),
+        queues.despawn_level_object.take_recorded(enabled),
+        queues.update_level_object.take_recorded(enabled),
+        queues.update_level_settings.take_recorded(enabled),
+        queues.spawn_player.take_recorded(enabled),
+    ];
+    for entry in recorded.into_iter().flatten() {
+        command_log.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{commands::DespawnReason, level::LevelSettings},
+        messages::{EntityNetId, PlayerNetId},
+        test_harness::cube,
+        SimulationTime,
+    };
+    use bevy::math::Vec2;
+
+    fn init_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(SimulationTime::default());
+        world.insert_resource(CommandLog::default());
+        world.insert_resource(DeferredQueue::<SwitchPlayerRole>::default());
+        world.insert_resource(DeferredQueue::<DespawnPlayer>::default());
+        world.insert_resource(DeferredQueue::<DespawnLevelObject>::default());
+        world.insert_resource(DeferredQueue::<UpdateLevelObject>::default());
+        world.insert_resource(DeferredQueue::<UpdateLevelSettings>::default());
+        world.insert_resource(DeferredQueue::<SpawnPlayer>::default());
+        world
+    }
+
+    fn set_frame(world: &mut World, frame_number: u16) {
+        let mut time = world.resource_mut::<SimulationTime>();
+        time.player_frame = FrameNumber::new(frame_number);
+        time.server_frame = FrameNumber::new(frame_number);
+    }
+
+    /// Drains the queues in the same order as the `SPAWN` stage does and
+    /// collects what's been drained.
+    fn run_frame(world: &mut World, frame_number: u16) -> Vec<RecordedCommand> {
+        set_frame(world, frame_number);
+        let time = world.remove_resource::<SimulationTime>().unwrap();
+        let mut drained = Vec::new();
+        macro_rules! drain {
+            ($command:ty) => {
+                drained.extend(
+                    world
+                        .resource_mut::<DeferredQueue<$command>>()
+                        .drain(&time)
+                        .iter()
+                        .map(RecordCommand::record),
+                );
+            };
+        }
+        drain!(SwitchPlayerRole);
+        drain!(DespawnPlayer);
+        drain!(DespawnLevelObject);
+        drain!(UpdateLevelObject);
+        drain!(UpdateLevelSettings);
+        drain!(SpawnPlayer);
+        world.insert_resource(time);
+
+        let mut stage = bevy::ecs::schedule::SystemStage::single_threaded()
+            .with_system(collect_recorded_commands_system);
+        bevy::ecs::schedule::Stage::run(&mut stage, world);
+        drained
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut command_log = CommandLog::new(2);
+        for i in 0..3 {
+            command_log.push(CommandLogEntry {
+                drained_at: FrameNumber::new(i),
+                command: RecordedCommand::UpdateLevelSettings(UpdateLevelSettings {
+                    settings: LevelSettings::default(),
+                }),
+            });
+        }
+        assert_eq!(command_log.len(), 2);
+        assert_eq!(
+            command_log
+                .entries()
+                .map(|entry| entry.drained_at)
+                .collect::<Vec<_>>(),
+            [FrameNumber::new(1), FrameNumber::new(2)]
+        );
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let mut world = init_world();
+        // Queues start recording once they see the log is enabled.
+        world.resource_mut::<CommandLog>().set_enabled(true);
+        run_frame(&mut world, 0);
+
+        world
+            .resource_mut::<DeferredQueue<UpdateLevelObject>>()
+            .push(UpdateLevelObject {
+                object: cube(1, Vec2::ZERO, 0.5),
+                frame_number: FrameNumber::new(1),
+            });
+        world
+            .resource_mut::<DeferredQueue<UpdateLevelObject>>()
+            .push(UpdateLevelObject {
+                object: cube(1, Vec2::ZERO, 1.0),
+                frame_number: FrameNumber::new(2),
+            });
+        world
+            .resource_mut::<DeferredQueue<SpawnPlayer>>()
+            .push(SpawnPlayer {
+                net_id: PlayerNetId(0),
+                start_position: Vec2::ZERO,
+                is_player_frame_simulated: false,
+            });
+        world
+            .resource_mut::<DeferredQueue<DespawnPlayer>>()
+            .push(DespawnPlayer {
+                net_id: PlayerNetId(0),
+                frame_number: FrameNumber::new(2),
+                reason: DespawnReason::DeathOrFinish,
+            });
+        world
+            .resource_mut::<DeferredQueue<DespawnLevelObject>>()
+            .push(DespawnLevelObject {
+                net_id: EntityNetId(1),
+                frame_number: FrameNumber::new(2),
+            });
+
+        let original_frames = (1..=2)
+            .map(|frame_number| run_frame(&mut world, frame_number))
+            .collect::<Vec<_>>();
+        let command_log = world.remove_resource::<CommandLog>().unwrap();
+        assert_eq!(command_log.len(), 5);
+        assert_eq!(
+            command_log
+                .entries()
+                .map(|entry| (entry.drained_at.value(), entry.command.name()))
+                .collect::<Vec<_>>(),
+            [
+                (1, "UpdateLevelObject"),
+                (1, "SpawnPlayer"),
+                (2, "DespawnPlayer"),
+                (2, "DespawnLevelObject"),
+                (2, "UpdateLevelObject"),
+            ]
+        );
+        assert_eq!(command_log.dump().lines().count(), 5);
+
+        let mut fresh_world = init_world();
+        for (frame_number, original_frame) in (1..=2).zip(original_frames) {
+            replay_recorded_commands(
+                &mut fresh_world,
+                command_log.entries(),
+                FrameNumber::new(frame_number),
+            );
+            assert_eq!(run_frame(&mut fresh_world, frame_number), original_frame);
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
     framebuffer::FrameNumber,
-    game::level::LevelObject,
+    game::{
+        command_log::{CommandLogEntry, RecordCommand},
+        level::{LevelObject, LevelSettings},
+    },
     messages::{EntityNetId, PlayerNetId},
     player::PlayerRole,
     SimulationTime,
@@ -21,40 +24,77 @@ pub trait DeferredCommand {
 #[derive(Resource)]
 pub struct DeferredQueue<T> {
     commands: Vec<T>,
+    /// Is toggled by `collect_recorded_commands_system` to follow `CommandLog`.
+    recording: bool,
+    recorded: Vec<CommandLogEntry>,
 }
 
 impl<T> Default for DeferredQueue<T> {
     fn default() -> Self {
         Self {
             commands: Vec::new(),
+            recording: false,
+            recorded: Vec::new(),
         }
     }
 }
 
-impl<T: DeferredCommand> DeferredQueue<T> {
+impl<T: DeferredCommand + RecordCommand> DeferredQueue<T> {
     pub fn push(&mut self, command: T) {
         self.commands.push(command);
     }
 
     pub fn drain(&mut self, time: &SimulationTime) -> Vec<T> {
-        self.commands
+        let mut drained_at = Vec::new();
+        let commands: Vec<T> = self
+            .commands
             .drain_filter(|command| {
                 let current_frame_number = if command.is_player_frame_simulated() {
                     time.player_frame
                 } else {
                     time.server_frame
                 };
-                command
+                let drain = command
                     .frame_number()
-                    .map_or(true, |frame_number| frame_number <= current_frame_number)
+                    .map_or(true, |frame_number| frame_number <= current_frame_number);
+                if drain {
+                    drained_at.push(current_frame_number);
+                }
+                drain
             })
-            .collect()
+            .collect();
+        if self.recording {
+            self.recorded.extend(
+                commands
+                    .iter()
+                    .zip(drained_at)
+                    .map(|(command, drained_at)| CommandLogEntry {
+                        drained_at,
+                        command: command.record(),
+                    }),
+            );
+        }
+        commands
+    }
+
+    /// Drops the pending commands. Unlike resetting the queue, keeps recording
+    /// (if it's enabled) and the recorded commands that haven't been collected.
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    /// Returns the commands recorded since the last call and sets whether the
+    /// following ones should be recorded.
+    pub fn take_recorded(&mut self, recording: bool) -> Vec<CommandLogEntry> {
+        self.recording = recording;
+        std::mem::take(&mut self.recorded)
     }
 }
 
 // NOTE: after adding a new command, remember to clean them up in the
 // `reset_game_world_system` system.
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SwitchPlayerRole {
     pub net_id: PlayerNetId,
     pub role: PlayerRole,
@@ -130,6 +170,15 @@ impl DeferredCommand for DespawnLevelObject {
     }
 }
 
+/// Level settings aren't simulated, so the command is applied on the nearest
+/// frame.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UpdateLevelSettings {
+    pub settings: LevelSettings,
+}
+
+impl DeferredCommand for UpdateLevelSettings {}
+
 #[derive(Resource)]
 pub struct DeferredPlayerQueues<T> {
     updates: HashMap<PlayerNetId, Vec<T>>,
@@ -1,7 +1,7 @@
 use crate::{
     framebuffer::{FrameNumber, Framebuffer},
     game::{commands::DespawnReason, level::CollisionLogic},
-    COMPONENT_FRAMEBUFFER_LIMIT,
+    COMPONENT_FRAMEBUFFER_LIMIT, SIMULATIONS_PER_SECOND,
 };
 use bevy::{
     ecs::{bundle::Bundle, component::Component, entity::Entity},
@@ -55,6 +55,20 @@ impl PlayerSensors {
             .any(|(_, sensor)| sensor.has(CollisionLogic::Finish));
         self.main.has(CollisionLogic::Finish) || sensors_contact_finish
     }
+
+    /// Returns the first contacted level object with the passed logic, the
+    /// player collider itself is checked before the sensors.
+    pub fn contacted_object(&self, collision_logic: CollisionLogic) -> Option<Entity> {
+        std::iter::once(&self.main)
+            .chain(self.sensors.iter().map(|(_, sensor)| sensor))
+            .find_map(|sensor| {
+                sensor
+                    .contacting
+                    .iter()
+                    .find(|(_, logic)| *logic == collision_logic)
+                    .map(|(entity, _)| *entity)
+            })
+    }
 }
 
 #[derive(Default, Debug)]
@@ -196,6 +210,14 @@ impl Spawned {
         res
     }
 
+    /// Returns the frame of the latest command if it's a spawn one.
+    pub fn spawned_at(&self) -> Option<FrameNumber> {
+        match self.commands.back() {
+            Some((SpawnCommand::Spawn, frame_number)) => Some(*frame_number),
+            _ => None,
+        }
+    }
+
     pub fn can_be_removed(&self, frame_number: FrameNumber) -> bool {
         if let Some((SpawnCommand::Despawn(_), command_frame_number)) = self.commands.back() {
             return frame_number
@@ -270,7 +292,11 @@ pub struct LockPhysics(pub bool);
 
 #[derive(Clone)]
 pub struct LevelObjectMovementPoint {
+    /// Route progress at which an object arrives at the point.
     pub progress: f32,
+    /// How much route progress an object spends waiting at the point after
+    /// arriving.
+    pub wait: f32,
     pub position: Vec2,
     pub entity: Entity,
 }
@@ -309,6 +335,38 @@ impl LevelObjectMovement {
             .collect()
     }
 
+    /// Returns the fastest speed (in units per second) that an object moves
+    /// with along its route. Jumps between points that an object passes
+    /// within no time make the speed infinite.
+    pub fn max_speed(&self) -> f32 {
+        if self.period == FrameNumber::new(0) {
+            return 0.0;
+        }
+
+        let period_secs = self.period.value() as f32 / SIMULATIONS_PER_SECOND;
+        match self.movement_type {
+            LevelObjectMovementType::Linear => self
+                .points_progress
+                .windows(2)
+                .map(|points| {
+                    let distance = points[0].position.distance(points[1].position);
+                    let travel_progress =
+                        points[1].progress - (points[0].progress + points[0].wait);
+                    if distance == 0.0 {
+                        0.0
+                    } else if travel_progress <= 0.0 {
+                        f32::INFINITY
+                    } else {
+                        distance / (travel_progress * period_secs)
+                    }
+                })
+                .fold(0.0, f32::max),
+            LevelObjectMovementType::Radial => {
+                self.init_vec.length() * std::f32::consts::PI * 2.0 / period_secs
+            }
+        }
+    }
+
     pub fn current_position(&self, frame_number: FrameNumber) -> Vec2 {
         match self.movement_type {
             LevelObjectMovementType::Linear => self.current_position_linear(frame_number),
@@ -320,37 +378,28 @@ impl LevelObjectMovement {
         if self.points_progress.is_empty() {
             return self.init_vec;
         }
-        if self.points_progress.len() == 1 {
-            return self.points_progress[0].position;
-        }
 
         let progress = self.total_progress(frame_number);
-        let mut current_point_progress = 0.0;
-        let mut next_point_progress = 0.0;
-        let mut next_point_index = 0usize;
-        for (i, point) in self.points_progress.iter().enumerate() {
-            if point.progress > progress {
-                next_point_index = i;
-                next_point_progress = point.progress;
-                break;
+        let mut prev_point = &self.points_progress[0];
+        if progress <= prev_point.progress + prev_point.wait {
+            return prev_point.position;
+        }
+        for point in self.points_progress.iter().skip(1) {
+            let departed_at = prev_point.progress + prev_point.wait;
+            if progress < point.progress {
+                let progress_between_points =
+                    (progress - departed_at) / (point.progress - departed_at);
+                return prev_point
+                    .position
+                    .lerp(point.position, progress_between_points);
             }
-            if point.progress - current_point_progress > f32::EPSILON {
-                current_point_progress = point.progress;
+            if progress <= point.progress + point.wait {
+                return point.position;
             }
+            prev_point = point;
         }
 
-        #[allow(clippy::float_cmp)]
-        if (progress - 1.0).abs() > f32::EPSILON {
-            assert_ne!(current_point_progress, next_point_progress);
-        } else {
-            return self.points_progress[next_point_index].position;
-        }
-        let progress_between_points =
-            1.0 - (next_point_progress - progress) / (next_point_progress - current_point_progress);
-
-        let current_point_position = self.points_progress[next_point_index - 1].position;
-        let next_point_position = self.points_progress[next_point_index].position;
-        current_point_position.lerp(next_point_position, progress_between_points)
+        prev_point.position
     }
 
     fn current_position_radial(&self, frame_number: FrameNumber) -> Vec2 {
@@ -409,6 +458,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_current_position_linear_with_waits() {
+        let point = |progress, wait, x| LevelObjectMovementPoint {
+            progress,
+            wait,
+            position: Vec2::new(x, 0.0),
+            entity: Entity::from_raw(0),
+        };
+        // Waits for 2 frames at the start and for 4 frames in the middle.
+        let level_object_movement = LevelObjectMovement {
+            frame_started: FrameNumber::new(0),
+            init_vec: Vec2::ZERO,
+            period: FrameNumber::new(10),
+            points_progress: vec![
+                point(0.0, 0.2, 0.0),
+                point(0.4, 0.4, 4.0),
+                point(1.0, 0.0, 0.0),
+            ],
+            movement_type: LevelObjectMovementType::Linear,
+        };
+        let x = |frame| {
+            level_object_movement
+                .current_position(FrameNumber::new(frame))
+                .x
+        };
+        assert!(x(1).abs() < 0.001);
+        assert!((x(3) - 2.0).abs() < 0.001);
+        assert!((x(4) - 4.0).abs() < 0.001);
+        assert!((x(7) - 4.0).abs() < 0.001);
+        assert!((x(9) - 2.0).abs() < 0.001);
+    }
+
     #[test]
     fn test_rotate() {
         assert_eq_vec(
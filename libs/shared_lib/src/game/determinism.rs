@@ -0,0 +1,514 @@
+use crate::{
+    framebuffer::FrameNumber,
+    game::components::{PlayerDirection, PlayerFrameSimulated, Position, Spawned},
+    messages::{EntityNetId, PlayerNetId},
+    registry::EntityRegistry,
+    SimulationTime, SIMULATIONS_PER_SECOND,
+};
+use bevy::{
+    ecs::{
+        entity::Entity,
+        system::{IntoSystem, Query, Res, ResMut, Resource, System},
+    },
+    transform::components::Transform,
+};
+use bevy_rapier2d::dynamics::Velocity;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+/// The server broadcasts a checkpoint hash once per this many frames.
+pub const STATE_HASH_CHECKPOINT_INTERVAL: u16 = SIMULATIONS_PER_SECOND as u16;
+/// Hashes of older frames are dropped. A divergence is detected and bisected
+/// a couple of round trips after the divergent frame, so the history has to
+/// outlive that with a good margin.
+pub const STATE_HASH_HISTORY_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 * 4;
+/// Frames preceding a divergent checkpoint that get bisected.
+pub const BISECT_WINDOW_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 / 2;
+
+/// The stages of the simulation schedule, in the order they run. Hashes are
+/// recorded at the end of each of them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SimulationStage {
+    Spawn,
+    PreGame,
+    Game,
+    Physics,
+    PostPhysics,
+    PostGame,
+}
+
+impl SimulationStage {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Spawn => crate::stage::SPAWN,
+            Self::PreGame => crate::stage::PRE_GAME,
+            Self::Game => crate::stage::GAME,
+            Self::Physics => crate::stage::PHYSICS,
+            Self::PostPhysics => crate::stage::POST_PHYSICS,
+            Self::PostGame => crate::stage::POST_GAME,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HashedEntity {
+    Player(PlayerNetId),
+    LevelObject(EntityNetId),
+}
+
+impl HashedEntity {
+    fn sort_key(&self) -> (u8, u16) {
+        match self {
+            Self::Player(net_id) => (0, net_id.0),
+            Self::LevelObject(net_id) => (1, net_id.0),
+        }
+    }
+}
+
+impl fmt::Display for HashedEntity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Player(net_id) => write!(f, "player {}", net_id.0),
+            Self::LevelObject(net_id) => write!(f, "level object {}", net_id.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FrameStageHashes {
+    pub frame_number: FrameNumber,
+    pub stages: Vec<(SimulationStage, u64)>,
+}
+
+/// Is sent by the server to clients if the guard is enabled.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum StateHashMessage {
+    /// Is broadcast every `STATE_HASH_CHECKPOINT_INTERVAL` frames.
+    Checkpoint {
+        frame_number: FrameNumber,
+        hash: u64,
+    },
+    /// A response to `StateHashRequest::Stages`.
+    Stages(Vec<FrameStageHashes>),
+    /// A response to `StateHashRequest::Entities`.
+    Entities {
+        frame_number: FrameNumber,
+        stage: SimulationStage,
+        entities: Vec<(HashedEntity, u64)>,
+    },
+    /// The requested frames aren't in the history anymore.
+    Expired,
+}
+
+/// Bisecting a divergence goes from coarse to fine: clients first ask for the
+/// hashes of every stage of the frames preceding a divergent checkpoint, and
+/// then for the hashes of every entity of the first divergent stage.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateHashRequest {
+    Stages {
+        from: FrameNumber,
+        to: FrameNumber,
+    },
+    Entities {
+        frame_number: FrameNumber,
+        stage: SimulationStage,
+    },
+}
+
+#[derive(Default)]
+struct FrameRecord {
+    frame_number: FrameNumber,
+    stages: HashMap<SimulationStage, HashMap<HashedEntity, u64>>,
+}
+
+impl FrameRecord {
+    fn stage_hash(&self, stage: SimulationStage) -> Option<u64> {
+        let mut entities = self.stages.get(&stage)?.iter().collect::<Vec<_>>();
+        entities.sort_by_key(|(entity, _)| entity.sort_key());
+        let mut hasher = StateHasher::default();
+        for (entity, hash) in entities {
+            let (kind, id) = entity.sort_key();
+            hasher.write(&[kind]);
+            hasher.write(&id.to_le_bytes());
+            hasher.write(&hash.to_le_bytes());
+        }
+        Some(hasher.finish())
+    }
+
+    fn stage_hashes(&self) -> FrameStageHashes {
+        let mut stages = self
+            .stages
+            .keys()
+            .filter_map(|&stage| Some((stage, self.stage_hash(stage)?)))
+            .collect::<Vec<_>>();
+        stages.sort_by_key(|(stage, _)| *stage);
+        FrameStageHashes {
+            frame_number: self.frame_number,
+            stages,
+        }
+    }
+
+    fn final_hash(&self) -> Option<u64> {
+        self.stage_hash(*self.stages.keys().max()?)
+    }
+}
+
+/// Records hashes of the simulated state at the end of every simulation
+/// stage, so that a client and the server can compare their simulations of
+/// the same frames.
+///
+/// Clients re-simulate frames every time authoritative updates arrive, which
+/// overwrites the hashes of the re-simulated frames. This means that the
+/// latest recorded hashes of a frame come from the run with the most
+/// authoritative inputs, and a mismatch with the server after that is a
+/// non-deterministic simulation rather than a misprediction.
+#[derive(Resource, Default)]
+pub struct DeterminismGuard {
+    /// Is enabled on the server with `MUDDLE_DETERMINISM_GUARD`, and on clients
+    /// once they receive the first checkpoint.
+    pub enabled: bool,
+    history: VecDeque<FrameRecord>,
+}
+
+impl DeterminismGuard {
+    pub fn record(
+        &mut self,
+        frame_number: FrameNumber,
+        stage: SimulationStage,
+        entity: HashedEntity,
+        hash: u64,
+    ) {
+        let index = match self
+            .history
+            .binary_search_by(|record| record.frame_number.cmp(&frame_number))
+        {
+            Ok(index) => index,
+            Err(index) => {
+                if index == 0
+                    && self.history.front().map_or(false, |record| {
+                        record.frame_number - frame_number
+                            > FrameNumber::new(STATE_HASH_HISTORY_FRAMES)
+                    })
+                {
+                    return;
+                }
+                self.history.insert(
+                    index,
+                    FrameRecord {
+                        frame_number,
+                        ..Default::default()
+                    },
+                );
+                index
+            }
+        };
+        self.history[index]
+            .stages
+            .entry(stage)
+            .or_default()
+            .insert(entity, hash);
+
+        let newest_frame = self.history.back().unwrap().frame_number;
+        while self.history.front().map_or(false, |record| {
+            newest_frame - record.frame_number > FrameNumber::new(STATE_HASH_HISTORY_FRAMES)
+        }) {
+            self.history.pop_front();
+        }
+    }
+
+    /// Returns the hashes of the recorded frames in the inclusive range.
+    pub fn stage_hashes(&self, from: FrameNumber, to: FrameNumber) -> Vec<FrameStageHashes> {
+        self.history
+            .iter()
+            .filter(|record| record.frame_number >= from && record.frame_number <= to)
+            .map(FrameRecord::stage_hashes)
+            .collect()
+    }
+
+    pub fn entity_hashes(
+        &self,
+        frame_number: FrameNumber,
+        stage: SimulationStage,
+    ) -> Option<Vec<(HashedEntity, u64)>> {
+        let mut entities = self
+            .frame(frame_number)?
+            .stages
+            .get(&stage)?
+            .iter()
+            .map(|(&entity, &hash)| (entity, hash))
+            .collect::<Vec<_>>();
+        entities.sort_by_key(|(entity, _)| entity.sort_key());
+        Some(entities)
+    }
+
+    pub fn final_hash(&self, frame_number: FrameNumber) -> Option<u64> {
+        self.frame(frame_number)?.final_hash()
+    }
+
+    pub fn stage_hash(&self, frame_number: FrameNumber, stage: SimulationStage) -> Option<u64> {
+        self.frame(frame_number)?.stage_hash(stage)
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    fn frame(&self, frame_number: FrameNumber) -> Option<&FrameRecord> {
+        self.history
+            .binary_search_by(|record| record.frame_number.cmp(&frame_number))
+            .ok()
+            .map(|index| &self.history[index])
+    }
+}
+
+/// Returns the earliest frame and stage which hashes differ. Frames missing
+/// on either side are skipped.
+pub fn first_divergent_stage(
+    local: &[FrameStageHashes],
+    authoritative: &[FrameStageHashes],
+) -> Option<(FrameNumber, SimulationStage)> {
+    authoritative.iter().find_map(|authoritative_frame| {
+        let local_frame = local
+            .iter()
+            .find(|frame| frame.frame_number == authoritative_frame.frame_number)?;
+        authoritative_frame
+            .stages
+            .iter()
+            .find(|(stage, hash)| {
+                local_frame
+                    .stages
+                    .iter()
+                    .find(|(local_stage, _)| local_stage == stage)
+                    .map_or(false, |(_, local_hash)| local_hash != hash)
+            })
+            .map(|(stage, _)| (authoritative_frame.frame_number, *stage))
+    })
+}
+
+/// Returns the entities which hashes differ, including the ones that exist
+/// only on one of the sides.
+pub fn divergent_entities(
+    local: &[(HashedEntity, u64)],
+    authoritative: &[(HashedEntity, u64)],
+) -> Vec<HashedEntity> {
+    let local = local.iter().copied().collect::<HashMap<_, _>>();
+    let authoritative = authoritative.iter().copied().collect::<HashMap<_, _>>();
+    let mut entities = local
+        .keys()
+        .chain(authoritative.keys())
+        .filter(|entity| local.get(entity) != authoritative.get(entity))
+        .copied()
+        .collect::<Vec<_>>();
+    entities.sort_by_key(HashedEntity::sort_key);
+    entities.dedup();
+    entities
+}
+
+/// FNV-1a, as hashes have to match between builds and platforms, which isn't
+/// guaranteed for `std::hash::Hasher` implementations.
+pub(crate) struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl StateHasher {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+type HashedEntityQuery = (
+    Entity,
+    &'static Spawned,
+    Option<&'static PlayerFrameSimulated>,
+    &'static Transform,
+    Option<&'static Velocity>,
+    Option<&'static Position>,
+    Option<&'static PlayerDirection>,
+);
+
+pub fn record_state_hashes_system(stage: SimulationStage) -> impl System<In = (), Out = ()> {
+    IntoSystem::into_system(
+        move |mut guard: ResMut<DeterminismGuard>,
+              time: Res<SimulationTime>,
+              player_registry: Res<EntityRegistry<PlayerNetId>>,
+              object_registry: Res<EntityRegistry<EntityNetId>>,
+              entities: Query<HashedEntityQuery>| {
+            #[cfg(feature = "profiler")]
+            puffin::profile_function!();
+            if !guard.enabled {
+                return;
+            }
+
+            for (
+                entity,
+                spawned,
+                player_frame_simulated,
+                transform,
+                velocity,
+                position,
+                direction,
+            ) in entities.iter()
+            {
+                let frame_number = time.entity_simulation_frame(player_frame_simulated);
+                if !spawned.is_spawned(frame_number) {
+                    continue;
+                }
+                // Ghosts of level objects aren't registered, and they aren't simulated.
+                let hashed_entity = if let Some(net_id) = player_registry.get_id(entity) {
+                    HashedEntity::Player(net_id)
+                } else if let Some(net_id) = object_registry.get_id(entity) {
+                    HashedEntity::LevelObject(net_id)
+                } else {
+                    continue;
+                };
+
+                let mut hasher = StateHasher::default();
+                hasher.write_f32(transform.translation.x);
+                hasher.write_f32(transform.translation.y);
+                if let Some(velocity) = velocity {
+                    hasher.write_f32(velocity.linvel.x);
+                    hasher.write_f32(velocity.linvel.y);
+                }
+                if let Some(position) = position.and_then(|p| p.buffer.get(frame_number)) {
+                    hasher.write_f32(position.x);
+                    hasher.write_f32(position.y);
+                }
+                if let Some(direction) = direction.and_then(|d| d.buffer.get(frame_number)) {
+                    let direction = direction.unwrap_or_default();
+                    hasher.write_f32(direction.x);
+                    hasher.write_f32(direction.y);
+                }
+                guard.record(frame_number, stage, hashed_entity, hasher.finish());
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id: u16) -> HashedEntity {
+        HashedEntity::Player(PlayerNetId(id))
+    }
+
+    fn object(id: u16) -> HashedEntity {
+        HashedEntity::LevelObject(EntityNetId(id))
+    }
+
+    #[test]
+    fn test_stage_hashes_ignore_recording_order() {
+        let mut a = DeterminismGuard::default();
+        a.record(FrameNumber::new(10), SimulationStage::Game, player(1), 1);
+        a.record(FrameNumber::new(10), SimulationStage::Game, object(1), 2);
+        let mut b = DeterminismGuard::default();
+        b.record(FrameNumber::new(10), SimulationStage::Game, object(1), 2);
+        b.record(FrameNumber::new(10), SimulationStage::Game, player(1), 1);
+        assert_eq!(
+            a.final_hash(FrameNumber::new(10)),
+            b.final_hash(FrameNumber::new(10))
+        );
+
+        // Re-simulating a frame overwrites its hashes.
+        b.record(FrameNumber::new(10), SimulationStage::Game, player(1), 3);
+        assert_ne!(
+            a.final_hash(FrameNumber::new(10)),
+            b.final_hash(FrameNumber::new(10))
+        );
+        assert_eq!(a.final_hash(FrameNumber::new(11)), None);
+    }
+
+    #[test]
+    fn test_history_is_limited() {
+        let mut guard = DeterminismGuard::default();
+        for frame in 0..STATE_HASH_HISTORY_FRAMES * 2 {
+            guard.record(
+                FrameNumber::new(frame),
+                SimulationStage::Spawn,
+                player(1),
+                1,
+            );
+        }
+        let newest = FrameNumber::new(STATE_HASH_HISTORY_FRAMES * 2 - 1);
+        let oldest = newest - FrameNumber::new(STATE_HASH_HISTORY_FRAMES);
+        let expected_len = STATE_HASH_HISTORY_FRAMES as usize + 1;
+        assert_eq!(guard.stage_hashes(oldest, newest).len(), expected_len);
+        assert_eq!(
+            guard
+                .stage_hashes(oldest - FrameNumber::new(1), newest)
+                .len(),
+            expected_len
+        );
+
+        // Frames that are too old aren't recorded.
+        guard.record(FrameNumber::new(0), SimulationStage::Spawn, player(1), 1);
+        assert_eq!(guard.final_hash(FrameNumber::new(0)), None);
+    }
+
+    #[test]
+    fn test_bisect() {
+        let mut local = DeterminismGuard::default();
+        let mut authoritative = DeterminismGuard::default();
+        for frame in 0..4 {
+            let frame = FrameNumber::new(frame);
+            for stage in [SimulationStage::Game, SimulationStage::Physics] {
+                for guard in [&mut local, &mut authoritative] {
+                    guard.record(frame, stage, player(1), 1);
+                    guard.record(frame, stage, object(2), 2);
+                }
+            }
+        }
+        local.record(FrameNumber::new(2), SimulationStage::Physics, object(2), 3);
+        local.record(FrameNumber::new(3), SimulationStage::Game, object(3), 1);
+
+        let from = FrameNumber::new(0);
+        let to = FrameNumber::new(3);
+        assert_eq!(
+            first_divergent_stage(
+                &local.stage_hashes(from, to),
+                &authoritative.stage_hashes(from, to)
+            ),
+            Some((FrameNumber::new(2), SimulationStage::Physics))
+        );
+        assert_eq!(
+            divergent_entities(
+                &local
+                    .entity_hashes(FrameNumber::new(2), SimulationStage::Physics)
+                    .unwrap(),
+                &authoritative
+                    .entity_hashes(FrameNumber::new(2), SimulationStage::Physics)
+                    .unwrap()
+            ),
+            vec![object(2)]
+        );
+        assert_eq!(
+            divergent_entities(
+                &local
+                    .entity_hashes(FrameNumber::new(3), SimulationStage::Game)
+                    .unwrap(),
+                &authoritative
+                    .entity_hashes(FrameNumber::new(3), SimulationStage::Game)
+                    .unwrap()
+            ),
+            vec![object(3)]
+        );
+    }
+}
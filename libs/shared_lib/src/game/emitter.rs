@@ -0,0 +1,351 @@
+use crate::{
+    framebuffer::FrameNumber,
+    game::{
+        components::{rotate, LevelObjectTag, PlayerTag, Position, Spawned},
+        events::PlayerDeath,
+        level::{LevelObjectDesc, LevelParams},
+        level_objects::closest_start_frame_to_time,
+        spawn::{iter_spawned_read_only, SpawnedQuery},
+    },
+    SimulationTime, PLAYER_RADIUS, SIMULATIONS_PER_SECOND,
+};
+use bevy::{
+    ecs::{
+        entity::Entity,
+        event::EventWriter,
+        query::{With, WorldQuery},
+        system::{Query, Res},
+    },
+    log,
+    math::Vec2,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+pub const BEAM_HALF_WIDTH: f32 = 0.1;
+pub const PROJECTILE_RADIUS: f32 = 0.2;
+/// How far beams and projectiles can reach (in units).
+pub const EMITTER_MAX_REACH: f32 = 50.0;
+/// The fastest speed (in units per second) of projectiles.
+pub const PROJECTILE_MAX_SPEED: f32 = 40.0;
+pub const EMITTER_MIN_PERIOD_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 / 4;
+pub const EMITTER_MAX_PERIOD_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 * 30;
+
+/// Emits hazards that kill runners on touch. Hazards aren't level objects
+/// themselves: their state is derived from the frame number and the emitter
+/// config, so neither the server needs to replicate them, nor rewinding needs
+/// to restore them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EmitterDesc {
+    pub position: Vec2,
+    /// The direction (in degrees) of emitted hazards, 0 points along the X
+    /// axis.
+    pub angle_degrees: f32,
+    /// How often (in frames) the emitter repeats its pattern.
+    pub period: u16,
+    /// Shifts the pattern (in frames), so that emitters with the same period
+    /// can take turns.
+    pub start_frame_offset: u16,
+    pub pattern: EmitterPattern,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EmitterPattern {
+    /// A beam that sweeps back and forth across `sweep_degrees` (centered at
+    /// the emitter direction) once a period.
+    Beam { length: f32, sweep_degrees: f32 },
+    /// A projectile that is fired at the start of every period and flies
+    /// until it covers `range`.
+    Projectile { speed: f32, range: f32 },
+}
+
+impl EmitterPattern {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Beam { .. } => "Sweeping beam",
+            Self::Projectile { .. } => "Projectile",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hazard {
+    Beam { start: Vec2, end: Vec2 },
+    Projectile { position: Vec2 },
+}
+
+impl Hazard {
+    pub fn hits(&self, position: Vec2, radius: f32) -> bool {
+        match *self {
+            Self::Beam { start, end } => {
+                distance_to_segment(position, start, end) < radius + BEAM_HALF_WIDTH
+            }
+            Self::Projectile {
+                position: projectile_position,
+            } => (position - projectile_position).length() < radius + PROJECTILE_RADIUS,
+        }
+    }
+}
+
+impl Default for EmitterDesc {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            angle_degrees: 0.0,
+            period: SIMULATIONS_PER_SECOND as u16 * 2,
+            start_frame_offset: 0,
+            pattern: EmitterPattern::Projectile {
+                speed: 5.0,
+                range: 8.0,
+            },
+        }
+    }
+}
+
+impl EmitterDesc {
+    pub fn period_range() -> RangeInclusive<u16> {
+        EMITTER_MIN_PERIOD_FRAMES..=EMITTER_MAX_PERIOD_FRAMES
+    }
+
+    pub fn period(&self) -> FrameNumber {
+        FrameNumber::new(
+            self.period
+                .clamp(EMITTER_MIN_PERIOD_FRAMES, EMITTER_MAX_PERIOD_FRAMES),
+        )
+    }
+
+    /// Returns the share of the current period that has passed.
+    pub fn progress(&self, generation: u64, frame_number: FrameNumber) -> f32 {
+        let period = self.period();
+        let start_frame_offset = FrameNumber::new(self.start_frame_offset % period.value());
+        let frame_started =
+            closest_start_frame_to_time(generation, frame_number, start_frame_offset, period);
+        // The start frame points to the future before the first period begins.
+        let elapsed = if frame_started > frame_number {
+            frame_number + period - frame_started
+        } else {
+            frame_number - frame_started
+        };
+        (elapsed.value() % period.value()) as f32 / period.value() as f32
+    }
+
+    /// Returns `None` if the emitter has nothing in the air at the moment.
+    pub fn hazard(
+        &self,
+        origin: Vec2,
+        generation: u64,
+        frame_number: FrameNumber,
+    ) -> Option<Hazard> {
+        let progress = self.progress(generation, frame_number);
+        let direction = Vec2::from_angle(self.angle_degrees.to_radians());
+        match self.pattern {
+            EmitterPattern::Beam {
+                length,
+                sweep_degrees,
+            } => {
+                // Goes from one edge of the sweep to the other one and back.
+                let sweep = 1.0 - (progress * 2.0 - 1.0).abs() * 2.0;
+                let direction = rotate(direction, (sweep_degrees / 2.0 * sweep).to_radians());
+                Some(Hazard::Beam {
+                    start: origin,
+                    end: origin + direction * length,
+                })
+            }
+            EmitterPattern::Projectile { speed, range } => {
+                let secs = progress * self.period().value() as f32 / SIMULATIONS_PER_SECOND;
+                let distance = speed * secs;
+                (distance <= range).then(|| Hazard::Projectile {
+                    position: origin + direction * distance,
+                })
+            }
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let reach = 0.0..=EMITTER_MAX_REACH;
+        let is_pattern_valid = match self.pattern {
+            EmitterPattern::Beam {
+                length,
+                sweep_degrees,
+            } => reach.contains(&length) && (0.0..=360.0).contains(&sweep_degrees),
+            EmitterPattern::Projectile { speed, range } => {
+                (0.0..=PROJECTILE_MAX_SPEED).contains(&speed) && reach.contains(&range)
+            }
+        };
+        self.angle_degrees.is_finite()
+            && Self::period_range().contains(&self.period)
+            && self.start_frame_offset < self.period
+            && is_pattern_valid
+    }
+}
+
+fn distance_to_segment(point: Vec2, start: Vec2, end: Vec2) -> f32 {
+    let segment = end - start;
+    let length_squared = segment.length_squared();
+    if length_squared <= f32::EPSILON {
+        return (point - start).length();
+    }
+    let t = ((point - start).dot(segment) / length_squared).clamp(0.0, 1.0);
+    (point - (start + segment * t)).length()
+}
+
+#[derive(WorldQuery)]
+pub struct HazardTargetQuery<'w> {
+    entity: Entity,
+    position: &'w Position,
+}
+
+/// Runners die once they start touching a hazard, the same way as when they
+/// start touching death objects. Touching a hazard at the previous frame is
+/// checked against the previous positions, so re-simulating frames gives the
+/// same result.
+pub fn process_emitter_hazards_system(
+    time: Res<SimulationTime>,
+    level: LevelParams,
+    level_objects: Query<(&Position, &Spawned), With<LevelObjectTag>>,
+    players: Query<SpawnedQuery<HazardTargetQuery>, With<PlayerTag>>,
+    mut player_death_events: EventWriter<PlayerDeath>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    let emitters = level
+        .level_state
+        .objects()
+        .values()
+        .filter_map(|level_object| match &level_object.desc {
+            LevelObjectDesc::Emitter(emitter) => {
+                let (position, spawned) = level_objects
+                    .get(level.entity_registry.get_entity(level_object.net_id)?)
+                    .ok()?;
+                Some((level_object.net_id, emitter, position, spawned))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if emitters.is_empty() {
+        return;
+    }
+
+    for player in iter_spawned_read_only(players.iter(), &time) {
+        let (frame_number, generation) = if player.player_frame_simulated.is_some() {
+            (time.player_frame, time.player_generation)
+        } else {
+            (time.server_frame, time.server_generation)
+        };
+        let prev_frame_number = frame_number - FrameNumber::new(1);
+        let prev_generation = if frame_number.value() == 0 {
+            generation - 1
+        } else {
+            generation
+        };
+
+        let Some(position) = player.item.position.buffer.get(frame_number) else {
+            continue;
+        };
+        let prev_position = player
+            .spawned
+            .is_spawned(prev_frame_number)
+            .then(|| player.item.position.buffer.get(prev_frame_number))
+            .flatten();
+
+        let hit_by = emitters
+            .iter()
+            .find_map(|(net_id, emitter, emitter_position, spawned)| {
+                let touches = |generation, frame_number, position| {
+                    if !spawned.is_spawned(frame_number) {
+                        return false;
+                    }
+                    let origin = emitter_position
+                        .buffer
+                        .get(frame_number)
+                        .copied()
+                        .unwrap_or(emitter.position);
+                    emitter
+                        .hazard(origin, generation, frame_number)
+                        .map_or(false, |hazard| hazard.hits(position, PLAYER_RADIUS))
+                };
+                let starts_touching = touches(generation, frame_number, *position)
+                    && !prev_position.map_or(false, |prev_position| {
+                        touches(prev_generation, prev_frame_number, *prev_position)
+                    });
+                starts_touching.then_some(*net_id)
+            });
+        if let Some(emitter_net_id) = hit_by {
+            log::debug!(
+                "Player {:?} has been hit by a hazard at position {:?}",
+                player.item.entity,
+                position
+            );
+            player_death_events.send(PlayerDeath {
+                player_entity: player.item.entity,
+                level_object_net_id: Some(emitter_net_id),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitter(pattern: EmitterPattern) -> EmitterDesc {
+        EmitterDesc {
+            period: 100,
+            pattern,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_projectile() {
+        let emitter = emitter(EmitterPattern::Projectile {
+            speed: SIMULATIONS_PER_SECOND / 10.0,
+            range: 5.0,
+        });
+        // Covers a unit per 10 frames.
+        let position = |frame| match emitter.hazard(Vec2::ZERO, 1, FrameNumber::new(frame)) {
+            Some(Hazard::Projectile { position }) => Some(position),
+            _ => None,
+        };
+        assert_eq!(position(0), Some(Vec2::ZERO));
+        assert!((position(30).unwrap() - Vec2::new(3.0, 0.0)).length() < 0.001);
+        assert_eq!(position(60), None);
+        // The next one is fired once the period ends.
+        assert_eq!(position(100), Some(Vec2::ZERO));
+    }
+
+    #[test]
+    fn test_beam_sweeps_back_and_forth() {
+        let emitter = emitter(EmitterPattern::Beam {
+            length: 10.0,
+            sweep_degrees: 90.0,
+        });
+        let end = |frame| match emitter.hazard(Vec2::ZERO, 1, FrameNumber::new(frame)) {
+            Some(Hazard::Beam { end, .. }) => end,
+            _ => unreachable!(),
+        };
+        let at_angle = |degrees: f32| Vec2::from_angle(degrees.to_radians()) * 10.0;
+        assert!((end(0) - at_angle(-45.0)).length() < 0.001);
+        assert!((end(25) - at_angle(0.0)).length() < 0.001);
+        assert!((end(50) - at_angle(45.0)).length() < 0.001);
+        assert!((end(75) - at_angle(0.0)).length() < 0.001);
+
+        let hazard = emitter.hazard(Vec2::ZERO, 1, FrameNumber::new(25)).unwrap();
+        assert!(hazard.hits(Vec2::new(5.0, 0.3), PLAYER_RADIUS));
+        assert!(!hazard.hits(Vec2::new(5.0, 1.0), PLAYER_RADIUS));
+        assert!(!hazard.hits(Vec2::new(11.0, 0.0), PLAYER_RADIUS));
+    }
+
+    #[test]
+    fn test_start_frame_offset() {
+        let mut emitter = emitter(EmitterPattern::Projectile {
+            speed: 1.0,
+            range: 10.0,
+        });
+        emitter.start_frame_offset = 40;
+        assert!(emitter.progress(1, FrameNumber::new(40)).abs() < f32::EPSILON);
+        assert!((emitter.progress(1, FrameNumber::new(90)) - 0.5).abs() < f32::EPSILON);
+        assert!((emitter.progress(1, FrameNumber::new(30)) - 0.9).abs() < f32::EPSILON);
+    }
+}
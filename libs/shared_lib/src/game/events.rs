@@ -1,4 +1,7 @@
-use crate::game::level::CollisionLogic;
+use crate::{
+    game::level::{ColliderShapeError, CollisionLogic},
+    messages::EntityNetId,
+};
 use bevy::ecs::entity::Entity;
 
 pub struct CollisionLogicChanged {
@@ -10,10 +13,26 @@ pub struct CollisionLogicChanged {
 /// to respawn a player. Client may only provide visual feedback, such as
 /// animations; respawning the player happens only on receiving `DeltaUpdate`
 /// message that reflects that.
-pub struct PlayerDeath(pub Entity);
+pub struct PlayerDeath {
+    pub player_entity: Entity,
+    /// The object that has killed the player, is `None` if the player has
+    /// fallen off the level.
+    pub level_object_net_id: Option<EntityNetId>,
+}
 
 /// Triggered for both the client and the server. Server should send a command
 /// to respawn a player. Client may only provide visual feedback, such as
 /// animations; respawning the player happens only on receiving `DeltaUpdate`
 /// message that reflects that.
-pub struct PlayerFinish(pub Entity);
+pub struct PlayerFinish {
+    pub player_entity: Entity,
+    pub level_object_net_id: Option<EntityNetId>,
+}
+
+/// Triggered for both the client and the server when a collider shape of a
+/// level object can't be calculated. Server should despawn the object, client
+/// just stops waiting for the shape.
+pub struct LevelObjectShapeInvalid {
+    pub net_id: EntityNetId,
+    pub error: ColliderShapeError,
+}
@@ -0,0 +1,406 @@
+use crate::{
+    messages::{FinishResult, PlayerNetId, RespawnPlayerReason},
+    player::{PlayerRole, Players},
+    SIMULATIONS_PER_SECOND,
+};
+use bevy::{ecs::system::Resource, utils::HashMap};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+pub const DEFAULT_RACE_LAPS: u16 = 3;
+pub const DEFAULT_TIME_TRIAL_DURATION_SECS: u16 = 180;
+
+/// Is selected with the server config and sent to clients in `StartGame`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GameModeKind {
+    /// Players build and run freely, matches never end.
+    #[default]
+    FreeBuild,
+    /// The first runner to finish `laps` times wins.
+    Race { laps: u16 },
+    /// The runner with the fastest finish by the end of the time limit wins.
+    TimeTrial { duration_secs: u16 },
+    /// Runners are eliminated on their first death, the last one standing
+    /// wins.
+    Elimination,
+}
+
+impl GameModeKind {
+    pub fn label(&self) -> String {
+        match self {
+            Self::FreeBuild => "Free build".to_owned(),
+            Self::Race { laps } => format!("Race ({laps} laps)"),
+            Self::TimeTrial { duration_secs } => {
+                format!(
+                    "Time trial ({}:{:02})",
+                    duration_secs / 60,
+                    duration_secs % 60
+                )
+            }
+            Self::Elimination => "Elimination".to_owned(),
+        }
+    }
+
+    pub fn game_mode(&self) -> Box<dyn GameMode> {
+        match *self {
+            Self::FreeBuild => Box::new(FreeBuild),
+            Self::Race { laps } => Box::new(Race { laps: laps.max(1) }),
+            Self::TimeTrial { duration_secs } => Box::new(TimeTrial {
+                duration_secs: duration_secs.max(1),
+            }),
+            Self::Elimination => Box::new(Elimination),
+        }
+    }
+}
+
+// Is the format of the `MUDDLE_GAME_MODE` env variable: `free_build`,
+// `race[:<laps>]`, `time_trial[:<duration_secs>]` or `elimination`.
+impl fmt::Display for GameModeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FreeBuild => f.write_str("free_build"),
+            Self::Race { laps } => write!(f, "race:{laps}"),
+            Self::TimeTrial { duration_secs } => write!(f, "time_trial:{duration_secs}"),
+            Self::Elimination => f.write_str("elimination"),
+        }
+    }
+}
+
+impl FromStr for GameModeKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let parse_param = |default: u16| {
+            param.map_or(Ok(default), |param| match param.parse::<u16>() {
+                Ok(0) => Err(format!(
+                    "Invalid game mode ({s}): expected a positive number"
+                )),
+                Ok(value) => Ok(value),
+                Err(err) => Err(format!("Invalid game mode ({s}): {err:?}")),
+            })
+        };
+        match name {
+            "free_build" if param.is_none() => Ok(Self::FreeBuild),
+            "race" => Ok(Self::Race {
+                laps: parse_param(DEFAULT_RACE_LAPS)?,
+            }),
+            "time_trial" => Ok(Self::TimeTrial {
+                duration_secs: parse_param(DEFAULT_TIME_TRIAL_DURATION_SECS)?,
+            }),
+            "elimination" if param.is_none() => Ok(Self::Elimination),
+            _ => Err(format!("Unknown game mode: {s}")),
+        }
+    }
+}
+
+/// How a runner has been doing since the current match started.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MatchPlayerStats {
+    pub finishes: u32,
+    pub deaths: u32,
+    /// The fastest timed finish within the match.
+    pub best_finish: Option<FinishResult>,
+}
+
+#[derive(Default)]
+pub struct Match {
+    pub elapsed_frames: u32,
+    pub stats: HashMap<PlayerNetId, MatchPlayerStats>,
+}
+
+impl Match {
+    pub fn record(
+        &mut self,
+        net_id: PlayerNetId,
+        reason: RespawnPlayerReason,
+        finish: Option<FinishResult>,
+    ) {
+        let stats = self.stats.entry(net_id).or_default();
+        match reason {
+            RespawnPlayerReason::Finish => {
+                stats.finishes += 1;
+                if let Some(finish) = finish {
+                    if stats
+                        .best_finish
+                        .map_or(true, |best_finish| finish.frames < best_finish.frames)
+                    {
+                        stats.best_finish = Some(finish);
+                    }
+                }
+            }
+            RespawnPlayerReason::Death => stats.deaths += 1,
+            RespawnPlayerReason::Checkpoint => {}
+        }
+    }
+
+    /// Sorted by player ids, as hash map iteration order isn't stable.
+    pub fn standings(&self) -> Vec<(PlayerNetId, MatchPlayerStats)> {
+        let mut standings = self
+            .stats
+            .iter()
+            .map(|(net_id, stats)| (*net_id, *stats))
+            .collect::<Vec<_>>();
+        standings.sort_by_key(|(net_id, _)| net_id.0);
+        standings
+    }
+
+    fn fastest_finisher(
+        &self,
+        filter: impl Fn(&MatchPlayerStats) -> bool,
+    ) -> Option<(PlayerNetId, &MatchPlayerStats)> {
+        self.stats
+            .iter()
+            .filter(|(_, stats)| filter(stats))
+            .min_by_key(|(net_id, stats)| {
+                (
+                    stats.best_finish.map_or(u32::MAX, |finish| finish.frames),
+                    net_id.0,
+                )
+            })
+            .map(|(net_id, stats)| (*net_id, stats))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchOutcome {
+    /// Is `None` if nobody has won (all the runners got eliminated, for
+    /// instance).
+    pub winner: Option<PlayerNetId>,
+}
+
+/// The rules that decide when a match ends and who wins it. Matches are
+/// evaluated by the server only, clients just get notified about the
+/// outcomes.
+pub trait GameMode: Send + Sync + 'static {
+    fn kind(&self) -> GameModeKind;
+
+    /// Eliminated runners can't take part in the rest of the match.
+    fn is_eliminated(&self, _stats: &MatchPlayerStats) -> bool {
+        false
+    }
+
+    /// Returns `None` while the match isn't decided.
+    fn evaluate(&self, current_match: &Match, players: &Players) -> Option<MatchOutcome>;
+}
+
+pub struct FreeBuild;
+
+impl GameMode for FreeBuild {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::FreeBuild
+    }
+
+    fn evaluate(&self, _current_match: &Match, _players: &Players) -> Option<MatchOutcome> {
+        None
+    }
+}
+
+pub struct Race {
+    laps: u16,
+}
+
+impl GameMode for Race {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::Race { laps: self.laps }
+    }
+
+    fn evaluate(&self, current_match: &Match, _players: &Players) -> Option<MatchOutcome> {
+        // If several runners complete their last lap at the same frame, the
+        // fastest lap decides.
+        let (winner, _) =
+            current_match.fastest_finisher(|stats| stats.finishes >= u32::from(self.laps))?;
+        Some(MatchOutcome {
+            winner: Some(winner),
+        })
+    }
+}
+
+pub struct TimeTrial {
+    duration_secs: u16,
+}
+
+impl GameMode for TimeTrial {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::TimeTrial {
+            duration_secs: self.duration_secs,
+        }
+    }
+
+    fn evaluate(&self, current_match: &Match, _players: &Players) -> Option<MatchOutcome> {
+        let duration_frames = u32::from(self.duration_secs) * SIMULATIONS_PER_SECOND as u32;
+        if current_match.elapsed_frames < duration_frames {
+            return None;
+        }
+        Some(MatchOutcome {
+            winner: current_match
+                .fastest_finisher(|stats| stats.best_finish.is_some())
+                .map(|(net_id, _)| net_id),
+        })
+    }
+}
+
+pub struct Elimination;
+
+impl GameMode for Elimination {
+    fn kind(&self) -> GameModeKind {
+        GameModeKind::Elimination
+    }
+
+    fn is_eliminated(&self, stats: &MatchPlayerStats) -> bool {
+        stats.deaths > 0
+    }
+
+    fn evaluate(&self, current_match: &Match, players: &Players) -> Option<MatchOutcome> {
+        // A match doesn't end until somebody gets eliminated, otherwise a single
+        // runner would win as soon as they join.
+        let has_eliminations = current_match
+            .stats
+            .values()
+            .any(|stats| self.is_eliminated(stats));
+        if !has_eliminations {
+            return None;
+        }
+        let mut standing = players.iter().filter(|(net_id, player)| {
+            player.is_connected
+                && player.role == PlayerRole::Runner
+                && !current_match
+                    .stats
+                    .get(*net_id)
+                    .map_or(false, |stats| self.is_eliminated(stats))
+        });
+        match (standing.next(), standing.next()) {
+            (Some(_), Some(_)) => None,
+            (winner, _) => Some(MatchOutcome {
+                winner: winner.map(|(net_id, _)| *net_id),
+            }),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct CurrentGameMode {
+    pub game_mode: Box<dyn GameMode>,
+    pub current_match: Match,
+}
+
+impl CurrentGameMode {
+    pub fn new(kind: GameModeKind) -> Self {
+        Self {
+            game_mode: kind.game_mode(),
+            current_match: Match::default(),
+        }
+    }
+
+    pub fn kind(&self) -> GameModeKind {
+        self.game_mode.kind()
+    }
+}
+
+impl Default for CurrentGameMode {
+    fn default() -> Self {
+        Self::new(GameModeKind::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Player;
+
+    fn finish(frames: u32) -> Option<FinishResult> {
+        Some(FinishResult {
+            frames,
+            medal: None,
+        })
+    }
+
+    fn runners(count: u16) -> Players {
+        let mut players = Players::default();
+        for i in 0..count {
+            players.insert(PlayerNetId(i), Player::new(PlayerRole::Runner));
+        }
+        players
+    }
+
+    #[test]
+    fn test_parse_game_mode() {
+        for kind in [
+            GameModeKind::FreeBuild,
+            GameModeKind::Race { laps: 5 },
+            GameModeKind::TimeTrial { duration_secs: 60 },
+            GameModeKind::Elimination,
+        ] {
+            assert_eq!(kind.to_string().parse::<GameModeKind>(), Ok(kind));
+        }
+        assert_eq!(
+            "race".parse::<GameModeKind>(),
+            Ok(GameModeKind::Race {
+                laps: DEFAULT_RACE_LAPS
+            })
+        );
+        assert!("race:0".parse::<GameModeKind>().is_err());
+        assert!("elimination:3".parse::<GameModeKind>().is_err());
+        assert!("battle_royale".parse::<GameModeKind>().is_err());
+    }
+
+    #[test]
+    fn test_race() {
+        let players = runners(2);
+        let race = GameModeKind::Race { laps: 2 }.game_mode();
+        let mut current_match = Match::default();
+        current_match.record(PlayerNetId(0), RespawnPlayerReason::Finish, finish(300));
+        current_match.record(PlayerNetId(1), RespawnPlayerReason::Finish, finish(200));
+        assert_eq!(race.evaluate(&current_match, &players), None);
+
+        current_match.record(PlayerNetId(0), RespawnPlayerReason::Finish, finish(250));
+        assert_eq!(
+            race.evaluate(&current_match, &players),
+            Some(MatchOutcome {
+                winner: Some(PlayerNetId(0))
+            })
+        );
+    }
+
+    #[test]
+    fn test_time_trial() {
+        let players = runners(2);
+        let time_trial = GameModeKind::TimeTrial { duration_secs: 1 }.game_mode();
+        let mut current_match = Match::default();
+        current_match.record(PlayerNetId(0), RespawnPlayerReason::Finish, finish(300));
+        current_match.record(PlayerNetId(1), RespawnPlayerReason::Finish, finish(200));
+        current_match.record(PlayerNetId(1), RespawnPlayerReason::Finish, finish(400));
+        assert_eq!(time_trial.evaluate(&current_match, &players), None);
+
+        current_match.elapsed_frames = SIMULATIONS_PER_SECOND as u32;
+        assert_eq!(
+            time_trial.evaluate(&current_match, &players),
+            Some(MatchOutcome {
+                winner: Some(PlayerNetId(1))
+            })
+        );
+    }
+
+    #[test]
+    fn test_elimination() {
+        let mut players = runners(3);
+        let elimination = GameModeKind::Elimination.game_mode();
+        let mut current_match = Match::default();
+        assert_eq!(elimination.evaluate(&current_match, &players), None);
+
+        current_match.record(PlayerNetId(0), RespawnPlayerReason::Death, None);
+        assert_eq!(elimination.evaluate(&current_match, &players), None);
+
+        // Disconnected players aren't standing anymore.
+        players.get_mut(&PlayerNetId(1)).unwrap().is_connected = false;
+        assert_eq!(
+            elimination.evaluate(&current_match, &players),
+            Some(MatchOutcome {
+                winner: Some(PlayerNetId(2))
+            })
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    framebuffer::FrameNumber, game::movement::player_movement_speed, messages::EntityNetId,
+    COMPONENT_FRAMEBUFFER_LIMIT, SIMULATIONS_PER_SECOND,
+};
+use bevy::{ecs::component::Component, math::Vec2};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A boost from a jump pad decays linearly to zero over this many frames.
+pub const JUMP_PAD_BOOST_FRAMES: u16 = (SIMULATIONS_PER_SECOND / 2.0) as u16;
+pub const JUMP_PAD_MIN_COOLDOWN_FRAMES: u16 = 1;
+pub const JUMP_PAD_MAX_COOLDOWN_FRAMES: u16 = SIMULATIONS_PER_SECOND as u16 * 5;
+
+/// Makes a plane push runners that step on it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct JumpPad {
+    /// Is added to the runner's velocity at the moment of contact.
+    pub impulse: Vec2,
+    /// The same runner isn't pushed by the pad again within this many frames,
+    /// otherwise standing on a pad would accelerate a runner every frame.
+    pub cooldown_frames: u16,
+}
+
+impl Default for JumpPad {
+    fn default() -> Self {
+        Self {
+            impulse: Vec2::new(0.0, player_movement_speed() * 3.0),
+            cooldown_frames: SIMULATIONS_PER_SECOND as u16,
+        }
+    }
+}
+
+impl JumpPad {
+    pub fn cooldown_frames(&self) -> u16 {
+        self.cooldown_frames
+            .clamp(JUMP_PAD_MIN_COOLDOWN_FRAMES, JUMP_PAD_MAX_COOLDOWN_FRAMES)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JumpPadTrigger {
+    pub frame_number: FrameNumber,
+    pub pad: EntityNetId,
+    pub impulse: Vec2,
+}
+
+/// Jump pads a runner has triggered recently.
+///
+/// Triggers are derived from the player's contacts while simulating a frame,
+/// so when the server re-simulates frames after receiving late inputs, or a
+/// client corrects its mispredictions, the triggers of the re-simulated frames
+/// are evaluated anew. This way a runner that has reached a pad according to
+/// their late input gets pushed at the same frame as they predicted it.
+#[derive(Component, Default, Debug)]
+pub struct JumpPadBoost {
+    /// Ordered by frame numbers.
+    triggers: VecDeque<JumpPadTrigger>,
+}
+
+impl JumpPadBoost {
+    /// Is expected to be called before triggering pads for a frame.
+    pub fn start_frame(&mut self, frame_number: FrameNumber) {
+        while self
+            .triggers
+            .back()
+            .map_or(false, |trigger| trigger.frame_number >= frame_number)
+        {
+            self.triggers.pop_back();
+        }
+        // The cooldowns still have to work for the frames we can rewind to.
+        while self.triggers.front().map_or(false, |trigger| {
+            (frame_number - trigger.frame_number).value()
+                > JUMP_PAD_MAX_COOLDOWN_FRAMES + COMPONENT_FRAMEBUFFER_LIMIT
+        }) {
+            self.triggers.pop_front();
+        }
+    }
+
+    /// Returns `false` if the pad is cooling down for this runner.
+    pub fn trigger(
+        &mut self,
+        frame_number: FrameNumber,
+        pad: EntityNetId,
+        jump_pad: &JumpPad,
+    ) -> bool {
+        let is_cooling_down = self.triggers.iter().any(|trigger| {
+            trigger.pad == pad
+                && (frame_number - trigger.frame_number).value() < jump_pad.cooldown_frames()
+        });
+        if is_cooling_down {
+            return false;
+        }
+        self.triggers.push_back(JumpPadTrigger {
+            frame_number,
+            pad,
+            impulse: jump_pad.impulse,
+        });
+        true
+    }
+
+    pub fn velocity(&self, frame_number: FrameNumber) -> Vec2 {
+        self.triggers
+            .iter()
+            .filter(|trigger| trigger.frame_number <= frame_number)
+            .map(|trigger| {
+                let elapsed = (frame_number - trigger.frame_number).value();
+                if elapsed >= JUMP_PAD_BOOST_FRAMES {
+                    return Vec2::ZERO;
+                }
+                trigger.impulse * (1.0 - elapsed as f32 / JUMP_PAD_BOOST_FRAMES as f32)
+            })
+            .fold(Vec2::ZERO, |acc, velocity| acc + velocity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jump_pad(cooldown_frames: u16) -> JumpPad {
+        JumpPad {
+            impulse: Vec2::new(0.0, 10.0),
+            cooldown_frames,
+        }
+    }
+
+    #[test]
+    fn test_boost_decays() {
+        let mut boost = JumpPadBoost::default();
+        let start_frame = FrameNumber::new(100);
+        boost.start_frame(start_frame);
+        assert!(boost.trigger(start_frame, EntityNetId(1), &jump_pad(60)));
+
+        assert_eq!(boost.velocity(start_frame), Vec2::new(0.0, 10.0));
+        let halfway = start_frame + FrameNumber::new(JUMP_PAD_BOOST_FRAMES / 2);
+        assert!((boost.velocity(halfway).y - 5.0).abs() < 0.1);
+        let end = start_frame + FrameNumber::new(JUMP_PAD_BOOST_FRAMES);
+        assert_eq!(boost.velocity(end), Vec2::ZERO);
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut boost = JumpPadBoost::default();
+        let start_frame = FrameNumber::new(u16::MAX - 10);
+        let pad = EntityNetId(1);
+        assert!(boost.trigger(start_frame, pad, &jump_pad(30)));
+
+        // The cooldown works across the frame number wrapping.
+        let frame_number = start_frame + FrameNumber::new(29);
+        boost.start_frame(frame_number);
+        assert!(!boost.trigger(frame_number, pad, &jump_pad(30)));
+        // Other pads aren't affected.
+        assert!(boost.trigger(frame_number, EntityNetId(2), &jump_pad(30)));
+
+        let frame_number = start_frame + FrameNumber::new(30);
+        boost.start_frame(frame_number);
+        assert!(boost.trigger(frame_number, pad, &jump_pad(30)));
+    }
+
+    #[test]
+    fn test_rewind_forgets_resimulated_triggers() {
+        let mut boost = JumpPadBoost::default();
+        let pad = EntityNetId(1);
+        let frame_number = FrameNumber::new(10);
+        boost.start_frame(frame_number);
+        assert!(boost.trigger(frame_number, pad, &jump_pad(60)));
+
+        // A late input has moved the runner off the pad, so the frame gets
+        // re-simulated without the trigger.
+        boost.start_frame(frame_number);
+        assert_eq!(boost.velocity(frame_number), Vec2::ZERO);
+        // And the pad can trigger at a later frame.
+        let frame_number = frame_number + FrameNumber::new(5);
+        boost.start_frame(frame_number);
+        assert!(boost.trigger(frame_number, pad, &jump_pad(60)));
+    }
+}
@@ -1,20 +1,28 @@
 use crate::{
-    collider_flags::level_object_collision_groups,
+    collider_flags::{level_object_collision_groups, non_simulated_collision_groups},
     framebuffer::FrameNumber,
     game::{
-        client_factories::ROUTE_POINT_BASE_EDGE_HALF_LEN,
-        components::{LevelObjectTag, PhysicsBundle},
+        client_factories::{
+            ANNOTATION_ANCHOR_RADIUS, CAMERA_ANCHOR_RADIUS, EMITTER_RADIUS,
+            ROUTE_POINT_BASE_EDGE_HALF_LEN,
+        },
+        commands::{DespawnLevelObject, UpdateLevelObject, UpdateLevelSettings},
+        components::PhysicsBundle,
+        emitter::EmitterDesc,
+        jump_pad::JumpPad,
         level_objects::*,
         spawn::ColliderShapeSender,
     },
-    messages::EntityNetId,
+    messages::{EntityNetId, RespawnPlayerReason},
+    panic_quarantine::quarantine,
     registry::EntityRegistry,
+    util::{PLAYER_CHECKPOINT_RESTART_TIME, PLAYER_RESPAWN_TIME},
+    PLAYER_RADIUS, SIMULATIONS_PER_SECOND,
 };
 use bevy::{
     ecs::{
         entity::Entity,
-        query::Added,
-        system::{Query, Res, ResMut, SystemParam},
+        system::{Res, SystemParam},
     },
     math::Vec2,
     prelude::Resource,
@@ -28,7 +36,20 @@ use bevy_rapier2d::{
     rapier::geometry::ColliderShape,
 };
 use serde::{Deserialize, Serialize};
-use std::marker::PhantomData;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    marker::PhantomData,
+    ops::RangeInclusive,
+};
+
+/// How many changes `LevelState` remembers, older ones can't be diffed against.
+pub const LEVEL_STATE_CHANGES_LIMIT: usize = 1024;
+/// Spawn areas need to have this much room (in square units) per player, so
+/// that runners don't get spawned on top of each other.
+pub const SPAWNABLE_AREA_PER_PLAYER: f32 = PLAYER_RADIUS * PLAYER_RADIUS * 16.0;
+/// The fastest speed (in units per second) that builders can make objects
+/// move with by setting `ObjectRoute::desired_speed`.
+pub const MAX_ROUTE_SPEED: f32 = 30.0;
 
 #[derive(SystemParam)]
 pub struct LevelParams<'w, 's> {
@@ -46,14 +67,616 @@ impl<'w, 's> LevelParams<'w, 's> {
     }
 
     pub fn level_object_by_net_id(&self, entity_net_id: EntityNetId) -> Option<&LevelObject> {
-        self.level_state.objects.get(&entity_net_id)
+        self.level_state.object(entity_net_id)
+    }
+
+    /// See [`predict_object_position`].
+    pub fn predict_object_position(
+        &self,
+        entity_net_id: EntityNetId,
+        generation: u64,
+        frame_number: FrameNumber,
+    ) -> Option<Vec2> {
+        predict_object_position(
+            &self.level_state,
+            &self.entity_registry,
+            generation,
+            frame_number,
+            entity_net_id,
+        )
     }
 }
 
+/// The level is mutated only by applying `UpdateLevelObject`,
+/// `DespawnLevelObject` and `UpdateLevelSettings` commands. Every applied
+/// command bumps the revision and gets recorded as a `LevelStateChange`, so
+/// consumers can check whether anything has changed since they last looked, and
+/// what exactly.
 #[derive(Resource, Default)]
 pub struct LevelState {
-    pub objects: HashMap<EntityNetId, LevelObject>,
-    pub spawn_areas: Vec<EntityNetId>,
+    objects: HashMap<EntityNetId, LevelObject>,
+    spawn_areas: Vec<EntityNetId>,
+    settings: LevelSettings,
+    revision: u64,
+    /// Changes paired with the revisions they resulted in.
+    changes: VecDeque<(u64, LevelStateChange)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LevelStateChange {
+    Spawned(LevelObject),
+    Updated {
+        old: LevelObject,
+        new: LevelObject,
+    },
+    Despawned(LevelObject),
+    SettingsUpdated {
+        old: LevelSettings,
+        new: LevelSettings,
+    },
+}
+
+impl LevelStateChange {
+    /// Returns `None` for changes that aren't related to a level object.
+    pub fn net_id(&self) -> Option<EntityNetId> {
+        match self {
+            Self::Spawned(object) | Self::Despawned(object) => Some(object.net_id),
+            Self::Updated { new, .. } => Some(new.net_id),
+            Self::SettingsUpdated { .. } => None,
+        }
+    }
+}
+
+impl LevelState {
+    pub fn objects(&self) -> &HashMap<EntityNetId, LevelObject> {
+        &self.objects
+    }
+
+    pub fn object(&self, entity_net_id: EntityNetId) -> Option<&LevelObject> {
+        self.objects.get(&entity_net_id)
+    }
+
+    /// Spawn areas are the planes that have `is_spawn_area` set.
+    pub fn spawn_areas(&self) -> &[EntityNetId] {
+        &self.spawn_areas
+    }
+
+    pub fn settings(&self) -> &LevelSettings {
+        &self.settings
+    }
+
+    /// Returns `None` if the level doesn't have spawn areas.
+    pub fn spawnable_area(&self) -> Option<f32> {
+        spawnable_area(
+            self.spawn_areas
+                .iter()
+                .filter_map(|id| self.objects.get(id)),
+        )
+    }
+
+    /// Spawnable area as if the object was replaced with the passed one (or
+    /// despawned, if `None` is passed).
+    pub fn spawnable_area_after(
+        &self,
+        net_id: EntityNetId,
+        replacement: Option<&LevelObject>,
+    ) -> Option<f32> {
+        spawnable_area(
+            self.spawn_areas
+                .iter()
+                .filter(|id| **id != net_id)
+                .filter_map(|id| self.objects.get(id))
+                .chain(replacement),
+        )
+    }
+
+    /// Starts with 0 for an empty level and increases with every applied
+    /// command.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn apply_update(&mut self, command: &UpdateLevelObject) {
+        let object = command.object.clone();
+        let net_id = object.net_id;
+
+        let is_spawn_area =
+            matches!(&object.desc, LevelObjectDesc::Plane(plane) if plane.is_spawn_area);
+        let spawn_area_index = self.spawn_areas.iter().position(|id| *id == net_id);
+        match (spawn_area_index, is_spawn_area) {
+            (Some(i), false) => {
+                self.spawn_areas.remove(i);
+            }
+            (None, true) => {
+                self.spawn_areas.push(net_id);
+            }
+            _ => {}
+        }
+
+        let change = match self.objects.insert(net_id, object.clone()) {
+            Some(old) => LevelStateChange::Updated { old, new: object },
+            None => LevelStateChange::Spawned(object),
+        };
+        self.push_change(change);
+    }
+
+    /// Returns the despawned object, or `None` if it didn't exist (the
+    /// revision isn't bumped in this case).
+    pub fn apply_despawn(&mut self, command: &DespawnLevelObject) -> Option<LevelObject> {
+        let object = self.objects.remove(&command.net_id)?;
+        self.spawn_areas.retain(|net_id| *net_id != command.net_id);
+        self.push_change(LevelStateChange::Despawned(object.clone()));
+        Some(object)
+    }
+
+    /// Doesn't bump the revision if the settings are the same.
+    pub fn apply_settings(&mut self, command: &UpdateLevelSettings) {
+        if self.settings == command.settings {
+            return;
+        }
+        let old = std::mem::replace(&mut self.settings, command.settings.clone());
+        self.push_change(LevelStateChange::SettingsUpdated {
+            old,
+            new: command.settings.clone(),
+        });
+    }
+
+    /// Returns changes made after the passed revision, oldest first, or `None`
+    /// if some of them have already been forgotten.
+    pub fn changes_since(
+        &self,
+        revision: u64,
+    ) -> Option<impl Iterator<Item = &(u64, LevelStateChange)>> {
+        let oldest_remembered = self
+            .changes
+            .front()
+            .map_or(self.revision + 1, |(change_revision, _)| *change_revision);
+        if revision + 1 < oldest_remembered {
+            return None;
+        }
+        Some(
+            self.changes
+                .iter()
+                .skip_while(move |(change_revision, _)| *change_revision <= revision),
+        )
+    }
+
+    fn push_change(&mut self, change: LevelStateChange) {
+        self.revision += 1;
+        if self.changes.len() == LEVEL_STATE_CHANGES_LIMIT {
+            self.changes.pop_front();
+        }
+        self.changes.push_back((self.revision, change));
+    }
+}
+
+#[derive(thiserror::Error, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LevelValidationError {
+    #[error(
+        "spawn areas are too small for {max_players} players ({area:.1} of {required:.1} square units)"
+    )]
+    NotEnoughSpawnableArea {
+        area: f32,
+        required: f32,
+        max_players: u16,
+    },
+    #[error("spawn areas \"{first_label}\" and \"{second_label}\" overlap")]
+    OverlappingSpawnAreas {
+        first: EntityNetId,
+        first_label: String,
+        second: EntityNetId,
+        second_label: String,
+    },
+    #[error("the level has no finish")]
+    NoFinish,
+    #[error("\"{label}\" has an invalid shape: {error}")]
+    InvalidColliderShape {
+        net_id: EntityNetId,
+        label: String,
+        error: ColliderShapeError,
+    },
+}
+
+/// Checks whether a level can be saved: it must have a finish, its spawn
+/// areas can't overlap, and collider shapes of all its objects must be
+/// computable (otherwise parry panics once the level gets loaded). Returns all
+/// the found problems, so that builders can fix them in one go.
+pub fn validate_level<'a>(
+    objects: impl IntoIterator<Item = &'a LevelObject>,
+    collider_simplification: ColliderSimplification,
+) -> Result<(), Vec<LevelValidationError>> {
+    let mut objects = objects.into_iter().collect::<Vec<_>>();
+    // Level state objects come from a hash map, sorting keeps the errors stable.
+    objects.sort_by_key(|object| object.net_id.0);
+    let mut errors = Vec::new();
+
+    if !objects
+        .iter()
+        .any(|object| object.effective_collision_logic() == CollisionLogic::Finish)
+    {
+        errors.push(LevelValidationError::NoFinish);
+    }
+
+    let spawn_areas = objects
+        .iter()
+        .filter_map(|object| match &object.desc {
+            LevelObjectDesc::Plane(plane) if plane.is_spawn_area => Some((*object, plane)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (i, (first, first_plane)) in spawn_areas.iter().enumerate() {
+        for (second, second_plane) in &spawn_areas[i + 1..] {
+            if planes_overlap(first_plane, second_plane) {
+                errors.push(LevelValidationError::OverlappingSpawnAreas {
+                    first: first.net_id,
+                    first_label: first.label.clone(),
+                    second: second.net_id,
+                    second_label: second.label.clone(),
+                });
+            }
+        }
+    }
+
+    // Other shapes are primitives that can always be calculated.
+    for object in &objects {
+        if let LevelObjectDesc::Plane(PlaneDesc {
+            form_desc: PlaneFormDesc::Concave { points },
+            ..
+        }) = &object.desc
+        {
+            let simplified_points = simplify_outline(points, collider_simplification.tolerance);
+            if let Err(error) = concave_shape(&simplified_points) {
+                errors.push(LevelValidationError::InvalidColliderShape {
+                    net_id: object.net_id,
+                    label: object.label.clone(),
+                    error,
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Planes are axis-aligned. Concave planes can't be spawn areas, so they are
+/// never considered overlapping.
+fn planes_overlap(first: &PlaneDesc, second: &PlaneDesc) -> bool {
+    let circle_overlaps_rectangle = |center: Vec2, radius: f32, position: Vec2, size: Vec2| {
+        let closest_point = center.clamp(position - size / 2.0, position + size / 2.0);
+        closest_point.distance(center) < radius
+    };
+    match (&first.form_desc, &second.form_desc) {
+        (PlaneFormDesc::Circle { radius: r1 }, PlaneFormDesc::Circle { radius: r2 }) => {
+            first.position.distance(second.position) < r1 + r2
+        }
+        (PlaneFormDesc::Rectangle { size: s1 }, PlaneFormDesc::Rectangle { size: s2 }) => {
+            let distance = (first.position - second.position).abs();
+            let max_distance = (*s1 + *s2) / 2.0;
+            distance.x < max_distance.x && distance.y < max_distance.y
+        }
+        (PlaneFormDesc::Circle { radius }, PlaneFormDesc::Rectangle { size }) => {
+            circle_overlaps_rectangle(first.position, *radius, second.position, *size)
+        }
+        (PlaneFormDesc::Rectangle { size }, PlaneFormDesc::Circle { radius }) => {
+            circle_overlaps_rectangle(second.position, *radius, first.position, *size)
+        }
+        (PlaneFormDesc::Concave { .. }, _) | (_, PlaneFormDesc::Concave { .. }) => false,
+    }
+}
+
+/// Sums up the spawnable area of the objects that are spawn areas, returns
+/// `None` if there are none.
+pub fn spawnable_area<'a>(objects: impl IntoIterator<Item = &'a LevelObject>) -> Option<f32> {
+    objects
+        .into_iter()
+        .filter_map(|object| match &object.desc {
+            LevelObjectDesc::Plane(plane) if plane.is_spawn_area => {
+                Some(plane.form_desc.inner_area(PLAYER_RADIUS))
+            }
+            _ => None,
+        })
+        .fold(None, |total, area| Some(total.unwrap_or(0.0) + area))
+}
+
+/// Levels without spawn areas are fine, runners get spawned at the origin.
+pub fn validate_spawnable_area(
+    area: Option<f32>,
+    max_players: u16,
+) -> Result<(), LevelValidationError> {
+    let Some(area) = area else {
+        return Ok(());
+    };
+    let required = SPAWNABLE_AREA_PER_PLAYER * max_players as f32;
+    if area < required {
+        return Err(LevelValidationError::NotEnoughSpawnableArea {
+            area,
+            required,
+            max_players,
+        });
+    }
+    Ok(())
+}
+
+/// An edit can't leave a level with too small spawn areas, unless they were
+/// already too small and the edit doesn't shrink them further, so that such
+/// levels can be fixed step by step.
+pub fn validate_spawnable_area_change(
+    old_area: Option<f32>,
+    new_area: Option<f32>,
+    max_players: u16,
+) -> Result<(), LevelValidationError> {
+    match validate_spawnable_area(new_area, max_players) {
+        Err(_) if new_area.unwrap_or(0.0) >= old_area.unwrap_or(0.0) => Ok(()),
+        result => result,
+    }
+}
+
+/// Settings of a level that don't affect the game simulation.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelSettings {
+    /// sRGB.
+    pub clear_color: [f32; 3],
+    /// Illuminance of the directional light (in lux), 0 disables it.
+    pub light_illuminance: f32,
+    /// Elevation of the light source above the horizon (in degrees), 90 means
+    /// that the light is shining straight down.
+    pub light_angle: f32,
+    pub music_track: Option<MusicTrack>,
+    /// Levels without target times don't award medals.
+    #[serde(default)]
+    pub medal_times: Option<MedalTimes>,
+    #[serde(default)]
+    pub audio_cues: LevelAudioCues,
+    #[serde(default)]
+    pub respawns: RespawnSettings,
+    /// Titles and descriptions in other languages, keyed by language codes.
+    #[serde(default)]
+    pub localizations: BTreeMap<String, LevelLocalization>,
+}
+
+impl Default for LevelSettings {
+    fn default() -> Self {
+        // Matches the look of the levels that were created before the settings were
+        // introduced.
+        Self {
+            clear_color: [0.4, 0.4, 0.4],
+            light_illuminance: 0.0,
+            light_angle: 60.0,
+            music_track: None,
+            medal_times: None,
+            audio_cues: LevelAudioCues::default(),
+            respawns: RespawnSettings::default(),
+            localizations: BTreeMap::new(),
+        }
+    }
+}
+
+/// Mirrors `mr_messages_lib::LevelLocalization`, as the persistence service
+/// copies localizations from level data to list levels in the viewer's
+/// language.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LevelLocalization {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MusicTrack {
+    Calm,
+    Upbeat,
+    Tense,
+}
+
+impl MusicTrack {
+    pub const ALL: [MusicTrack; 3] = [Self::Calm, Self::Upbeat, Self::Tense];
+
+    pub fn asset_path(&self) -> &'static str {
+        match self {
+            Self::Calm => "music/calm.ogg",
+            Self::Upbeat => "music/upbeat.ogg",
+            Self::Tense => "music/tense.ogg",
+        }
+    }
+}
+
+impl std::fmt::Display for MusicTrack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Calm => "Calm",
+            Self::Upbeat => "Upbeat",
+            Self::Tense => "Tense",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Medal {
+    Gold,
+    Silver,
+    Bronze,
+}
+
+impl Medal {
+    pub const ALL: [Medal; 3] = [Self::Gold, Self::Silver, Self::Bronze];
+}
+
+impl std::fmt::Display for Medal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Gold => "Gold",
+            Self::Silver => "Silver",
+            Self::Bronze => "Bronze",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Target finish times (in seconds) set by builders.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct MedalTimes {
+    pub gold: f32,
+    pub silver: f32,
+    pub bronze: f32,
+}
+
+impl Default for MedalTimes {
+    fn default() -> Self {
+        Self {
+            gold: 30.0,
+            silver: 45.0,
+            bronze: 60.0,
+        }
+    }
+}
+
+impl MedalTimes {
+    pub fn get(&self, medal: Medal) -> f32 {
+        match medal {
+            Medal::Gold => self.gold,
+            Medal::Silver => self.silver,
+            Medal::Bronze => self.bronze,
+        }
+    }
+
+    pub fn get_mut(&mut self, medal: Medal) -> &mut f32 {
+        match medal {
+            Medal::Gold => &mut self.gold,
+            Medal::Silver => &mut self.silver,
+            Medal::Bronze => &mut self.bronze,
+        }
+    }
+
+    /// Times must be positive, and the better medals must require faster (or
+    /// equal) times.
+    pub fn is_valid(&self) -> bool {
+        self.gold.is_finite()
+            && self.silver.is_finite()
+            && self.bronze.is_finite()
+            && 0.0 < self.gold
+            && self.gold <= self.silver
+            && self.silver <= self.bronze
+    }
+
+    /// Returns the best medal a finish time qualifies for.
+    pub fn classify(&self, time_secs: f32) -> Option<Medal> {
+        Medal::ALL
+            .into_iter()
+            .find(|medal| time_secs <= self.get(*medal))
+    }
+}
+
+/// Respawn timing and death penalties, enforced by the server.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RespawnSettings {
+    /// How long (in frames) a player waits to respawn after dying.
+    pub death_respawn_delay: FrameNumber,
+    /// How long (in frames) a player waits to start a new run after finishing.
+    pub finish_cooldown: FrameNumber,
+    /// Makes dying discard the practice checkpoint set by a player.
+    pub deaths_reset_checkpoints: bool,
+}
+
+impl Default for RespawnSettings {
+    fn default() -> Self {
+        Self {
+            death_respawn_delay: PLAYER_RESPAWN_TIME,
+            finish_cooldown: PLAYER_RESPAWN_TIME,
+            deaths_reset_checkpoints: false,
+        }
+    }
+}
+
+impl RespawnSettings {
+    /// A player has to be despawned before getting respawned, so delays can't
+    /// be shorter than a checkpoint restart.
+    pub fn delay_range() -> RangeInclusive<u16> {
+        PLAYER_CHECKPOINT_RESTART_TIME.value()..=SIMULATIONS_PER_SECOND as u16 * 30
+    }
+
+    pub fn delay(&self, reason: RespawnPlayerReason) -> FrameNumber {
+        match reason {
+            RespawnPlayerReason::Finish => self.finish_cooldown,
+            RespawnPlayerReason::Death => self.death_respawn_delay,
+            RespawnPlayerReason::Checkpoint => PLAYER_CHECKPOINT_RESTART_TIME,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        Self::delay_range().contains(&self.death_respawn_delay.value())
+            && Self::delay_range().contains(&self.finish_cooldown.value())
+    }
+}
+
+/// Ids of the audio clips (stored by the persistence service) that are played
+/// to runners on level events.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LevelAudioCues {
+    pub finish: Option<i64>,
+    pub death: Option<i64>,
+    pub checkpoint: Option<i64>,
+}
+
+impl LevelAudioCues {
+    pub fn get(&self, event: RespawnPlayerReason) -> Option<i64> {
+        match event {
+            RespawnPlayerReason::Finish => self.finish,
+            RespawnPlayerReason::Death => self.death,
+            RespawnPlayerReason::Checkpoint => self.checkpoint,
+        }
+    }
+
+    pub fn get_mut(&mut self, event: RespawnPlayerReason) -> &mut Option<i64> {
+        match event {
+            RespawnPlayerReason::Finish => &mut self.finish,
+            RespawnPlayerReason::Death => &mut self.death,
+            RespawnPlayerReason::Checkpoint => &mut self.checkpoint,
+        }
+    }
+
+    pub fn clip_ids(&self) -> impl Iterator<Item = i64> {
+        [self.finish, self.death, self.checkpoint]
+            .into_iter()
+            .flatten()
+    }
+}
+
+/// The format levels are stored in by the persistence service.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(from = "SerializedLevelRepr")]
+pub struct SerializedLevel {
+    pub objects: Vec<LevelObject>,
+    pub settings: LevelSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SerializedLevelRepr {
+    /// Levels saved before the settings were introduced are stored as plain
+    /// arrays of objects.
+    Objects(Vec<LevelObject>),
+    Level {
+        objects: Vec<LevelObject>,
+        #[serde(default)]
+        settings: LevelSettings,
+    },
+}
+
+impl From<SerializedLevelRepr> for SerializedLevel {
+    fn from(repr: SerializedLevelRepr) -> Self {
+        match repr {
+            SerializedLevelRepr::Level { objects, settings } => Self { objects, settings },
+            SerializedLevelRepr::Objects(objects) => Self {
+                objects,
+                settings: LevelSettings::default(),
+            },
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -66,11 +689,111 @@ pub struct LevelObject {
     pub collision_logic: CollisionLogic,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+impl LevelObject {
+    /// Zones ignore `collision_logic`, as their logic is defined by their type.
+    pub fn effective_collision_logic(&self) -> CollisionLogic {
+        self.desc
+            .zone_collision_logic()
+            .unwrap_or(self.collision_logic)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ObjectRoute {
+    /// Includes the time that an object spends waiting at waypoints.
     pub period: FrameNumber,
     pub start_frame_offset: FrameNumber,
     pub desc: ObjectRouteDesc,
+    /// How long (in frames) an object waits at each waypoint before moving on,
+    /// indexed in the order of `ObjectRouteDesc` points. Missing values mean
+    /// no waiting. The final waypoint of `ObjectRouteDesc::ForwardCycle` ends
+    /// the period, so its wait is ignored.
+    #[serde(default)]
+    pub waits: Vec<FrameNumber>,
+    /// Speed (in units per second) that builders want an object to move with.
+    /// If set, the builder UI derives the period from the route length.
+    #[serde(default)]
+    pub desired_speed: Option<f32>,
+}
+
+impl ObjectRoute {
+    pub fn new(period: FrameNumber, desc: ObjectRouteDesc) -> Self {
+        Self {
+            period,
+            start_frame_offset: FrameNumber::new(0),
+            desc,
+            waits: Vec::new(),
+            desired_speed: None,
+        }
+    }
+
+    pub fn speed_range() -> RangeInclusive<f32> {
+        0.1..=MAX_ROUTE_SPEED
+    }
+
+    pub fn wait(&self, waypoint_index: usize) -> FrameNumber {
+        self.waits
+            .get(waypoint_index)
+            .copied()
+            .unwrap_or(FrameNumber::new(0))
+    }
+
+    /// Returns the waits for every point that an object passes during a
+    /// period (`ObjectRouteDesc::ForwardBackwardsCycle` passes the middle
+    /// waypoints twice). The final point always gets zero.
+    pub fn point_waits(&self) -> Vec<FrameNumber> {
+        let mut waits = match &self.desc {
+            ObjectRouteDesc::Attached(_) | ObjectRouteDesc::Radial(_) => {
+                vec![FrameNumber::new(0)]
+            }
+            ObjectRouteDesc::ForwardCycle(points) => {
+                (0..points.len()).map(|i| self.wait(i)).collect()
+            }
+            ObjectRouteDesc::ForwardBackwardsCycle(points) => (0..points.len())
+                .chain((0..points.len().saturating_sub(1)).rev())
+                .map(|i| self.wait(i))
+                .collect(),
+        };
+        if let Some(last) = waits.last_mut() {
+            *last = FrameNumber::new(0);
+        }
+        waits
+    }
+
+    pub fn total_wait(&self) -> u32 {
+        self.point_waits()
+            .into_iter()
+            .map(|wait| wait.value() as u32)
+            .sum()
+    }
+
+    /// Returns the period with which an object covers `route_length` at
+    /// `speed` (in units per second), waits included. Returns `None` if such
+    /// a period doesn't fit `FrameNumber`.
+    pub fn period_for_speed(&self, route_length: f32, speed: f32) -> Option<FrameNumber> {
+        let travel_frames = (route_length / speed * SIMULATIONS_PER_SECOND)
+            .ceil()
+            .max(1.0);
+        let period = travel_frames as u32 + self.total_wait();
+        u16::try_from(period).ok().map(FrameNumber::new)
+    }
+
+    /// Objects need to spend at least one frame of a period moving, and only
+    /// the waypoints of linear routes can have waits.
+    pub fn is_valid(&self) -> bool {
+        let waypoints_count = match &self.desc {
+            ObjectRouteDesc::Attached(_) | ObjectRouteDesc::Radial(_) => 0,
+            ObjectRouteDesc::ForwardCycle(points)
+            | ObjectRouteDesc::ForwardBackwardsCycle(points) => points.len(),
+        };
+        let is_speed_valid = self
+            .desired_speed
+            .map_or(true, |speed| Self::speed_range().contains(&speed));
+        self.start_frame_offset.value() < self.period.value().max(1)
+            && self.waits.len() <= waypoints_count
+            && (self.waits.is_empty() || self.total_wait() < self.period.value() as u32)
+            && is_speed_valid
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -86,6 +809,11 @@ pub enum LevelObjectDesc {
     Plane(PlaneDesc),
     Cube(CubeDesc),
     RoutePoint(RoutePointDesc),
+    Annotation(AnnotationDesc),
+    CameraAnchor(CameraAnchorDesc),
+    Emitter(EmitterDesc),
+    DeathZone(DeathZoneDesc),
+    FinishZone(FinishZoneDesc),
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -96,20 +824,70 @@ pub enum CollisionLogic {
 }
 
 pub enum ColliderShapeResponse {
-    Immediate(ColliderShape),
+    Immediate(LevelObjectShape),
     Promise,
 }
 
+#[derive(Clone)]
+pub struct LevelObjectShape {
+    pub collider: ColliderShape,
+    /// Clients render concave planes from their original outlines, so if a
+    /// collider got simplified, the non-simplified shape is stored here.
+    pub visual: Option<ColliderShape>,
+}
+
+impl LevelObjectShape {
+    pub fn new(collider: ColliderShape) -> Self {
+        Self {
+            collider,
+            visual: None,
+        }
+    }
+
+    pub fn visual(&self) -> &ColliderShape {
+        self.visual.as_ref().unwrap_or(&self.collider)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ColliderShapeError {
+    /// An outline needs at least 3 distinct points.
+    InvalidOutline,
+    /// Computing the shape has panicked (contains the panic message).
+    Panicked(String),
+}
+
+impl std::fmt::Display for ColliderShapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOutline => f.write_str("The outline has less than 3 distinct points"),
+            Self::Panicked(message) => write!(f, "Failed to calculate the collider: {message}"),
+        }
+    }
+}
+
 impl LevelObjectDesc {
     pub fn label(&self) -> String {
         match self {
             Self::Plane(_) => "Plane",
             Self::Cube(_) => "Cube",
             Self::RoutePoint(_) => "Route Point",
+            Self::Annotation(_) => "Annotation",
+            Self::CameraAnchor(_) => "Camera Anchor",
+            Self::Emitter(_) => "Emitter",
+            Self::DeathZone(_) => "Death Zone",
+            Self::FinishZone(_) => "Finish Zone",
         }
         .to_owned()
     }
 
+    /// Annotations exist only for builders, and camera anchors are only used
+    /// by clients to play level intros, they don't take part in the game
+    /// simulation.
+    pub fn is_simulated(&self) -> bool {
+        !matches!(self, Self::Annotation(_) | Self::CameraAnchor(_))
+    }
+
     pub fn is_movable_with_mouse(&self) -> bool {
         !matches!(self, Self::Plane(_))
     }
@@ -119,6 +897,11 @@ impl LevelObjectDesc {
             Self::Plane(plane) => Some(plane.position),
             Self::Cube(cube) => Some(cube.position),
             Self::RoutePoint(route_point) => Some(route_point.position),
+            Self::Annotation(annotation) => Some(annotation.position),
+            Self::CameraAnchor(camera_anchor) => Some(camera_anchor.position),
+            Self::Emitter(emitter) => Some(emitter.position),
+            Self::DeathZone(death_zone) => Some(death_zone.position),
+            Self::FinishZone(finish_zone) => Some(finish_zone.position),
         }
     }
 
@@ -127,15 +910,55 @@ impl LevelObjectDesc {
             Self::Plane(plane) => Some(&mut plane.position),
             Self::Cube(cube) => Some(&mut cube.position),
             Self::RoutePoint(route_point) => Some(&mut route_point.position),
+            Self::Annotation(annotation) => Some(&mut annotation.position),
+            Self::CameraAnchor(camera_anchor) => Some(&mut camera_anchor.position),
+            Self::Emitter(emitter) => Some(&mut emitter.position),
+            Self::DeathZone(death_zone) => Some(&mut death_zone.position),
+            Self::FinishZone(finish_zone) => Some(&mut finish_zone.position),
+        }
+    }
+
+    /// Only planes and cubes can be customized.
+    pub fn appearance(&self) -> Option<&ObjectAppearance> {
+        match self {
+            Self::Plane(plane) => Some(&plane.appearance),
+            Self::Cube(cube) => Some(&cube.appearance),
+            Self::RoutePoint(_)
+            | Self::Annotation(_)
+            | Self::CameraAnchor(_)
+            | Self::Emitter(_)
+            | Self::DeathZone(_)
+            | Self::FinishZone(_) => None,
+        }
+    }
+
+    pub fn appearance_mut(&mut self) -> Option<&mut ObjectAppearance> {
+        match self {
+            Self::Plane(plane) => Some(&mut plane.appearance),
+            Self::Cube(cube) => Some(&mut cube.appearance),
+            Self::RoutePoint(_)
+            | Self::Annotation(_)
+            | Self::CameraAnchor(_)
+            | Self::Emitter(_)
+            | Self::DeathZone(_)
+            | Self::FinishZone(_) => None,
+        }
+    }
+
+    pub fn jump_pad(&self) -> Option<&JumpPad> {
+        match self {
+            Self::Plane(plane) => plane.jump_pad.as_ref(),
+            _ => None,
         }
     }
 
     pub fn calculate_collider_shape(
         &self,
         entity: Entity,
+        collider_simplification: ColliderSimplification,
         collider_shape_sender: ColliderShapeSender,
     ) -> ColliderShapeResponse {
-        ColliderShapeResponse::Immediate(match self {
+        ColliderShapeResponse::Immediate(LevelObjectShape::new(match self {
             Self::Plane(plane) => match &plane.form_desc {
                 PlaneFormDesc::Circle { radius } => ColliderShape::ball(*radius),
                 PlaneFormDesc::Rectangle { size } => {
@@ -143,37 +966,22 @@ impl LevelObjectDesc {
                     ColliderShape::cuboid(hsize.x, hsize.y)
                 }
                 PlaneFormDesc::Concave { points } => {
-                    assert!(points.len() > 2);
-                    let vertices = points
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(i, point)| {
-                            if i > 0 && points[i - 1] == *point {
-                                None
-                            } else {
-                                Some(Point2::new(point.x, point.y))
-                            }
-                        })
-                        .collect::<Vec<_>>();
-                    let mut indices = (0..vertices.len() - 1)
-                        .map(|i| [i as u32, i as u32 + 1])
-                        .collect::<Vec<_>>();
-                    indices.push([indices.last().unwrap()[1], 0]);
+                    let simplified_points =
+                        simplify_outline(points, collider_simplification.tolerance);
+                    // Servers don't render anything, so they don't need the original shape.
+                    let visual_points =
+                        if cfg!(feature = "client") && simplified_points.len() < points.len() {
+                            Some(points.clone())
+                        } else {
+                            None
+                        };
                     AsyncComputeTaskPool::get()
                         .spawn(async move {
-                            let r = std::panic::catch_unwind(|| {
-                                ColliderShape::convex_decomposition_with_params(
-                                    &vertices,
-                                    &indices,
-                                    &VHACDParameters {
-                                        concavity: 0.01,
-                                        resolution: 64,
-                                        ..Default::default()
-                                    },
-                                )
-                            })
-                            .ok();
-                            collider_shape_sender.send((entity, r)).unwrap();
+                            let visual =
+                                visual_points.and_then(|points| concave_shape(&points).ok());
+                            let shape = concave_shape(&simplified_points)
+                                .map(|collider| LevelObjectShape { collider, visual });
+                            collider_shape_sender.send((entity, shape)).unwrap();
                         })
                         .detach();
                     return ColliderShapeResponse::Promise;
@@ -184,7 +992,15 @@ impl LevelObjectDesc {
                 ROUTE_POINT_BASE_EDGE_HALF_LEN * 2.0,
                 ROUTE_POINT_BASE_EDGE_HALF_LEN * 2.0,
             ),
-        })
+            Self::Annotation(_) => ColliderShape::ball(ANNOTATION_ANCHOR_RADIUS),
+            Self::CameraAnchor(_) => ColliderShape::ball(CAMERA_ANCHOR_RADIUS),
+            Self::Emitter(_) => ColliderShape::ball(EMITTER_RADIUS),
+            Self::DeathZone(DeathZoneDesc { size, .. })
+            | Self::FinishZone(FinishZoneDesc { size, .. }) => {
+                let hsize = *size / 2.0;
+                ColliderShape::cuboid(hsize.x, hsize.y)
+            }
+        }))
     }
 
     pub fn physics_bundle(
@@ -193,7 +1009,13 @@ impl LevelObjectDesc {
         server_simulated: bool,
     ) -> (PhysicsBundle, Option<Sensor>) {
         match self {
-            Self::Plane(_) | Self::RoutePoint(_) => (
+            // Emitters kill runners with their hazards, touching an emitter itself is
+            // harmless.
+            Self::Plane(_)
+            | Self::RoutePoint(_)
+            | Self::Emitter(_)
+            | Self::DeathZone(_)
+            | Self::FinishZone(_) => (
                 PhysicsBundle {
                     rigid_body: RigidBody::KinematicPositionBased,
                     collider: shape.into(),
@@ -211,6 +1033,17 @@ impl LevelObjectDesc {
                 },
                 None,
             ),
+            // Annotations and camera anchors don't interact with anything, but we still
+            // need a collider for them to be handled the same way as other level objects.
+            Self::Annotation(_) | Self::CameraAnchor(_) => (
+                PhysicsBundle {
+                    rigid_body: RigidBody::KinematicPositionBased,
+                    collider: shape.into(),
+                    collision_groups: non_simulated_collision_groups(),
+                    locked_axes: LockedAxes::TRANSLATION_LOCKED_Z,
+                },
+                Some(Sensor),
+            ),
         }
     }
 
@@ -220,6 +1053,10 @@ impl LevelObjectDesc {
                 form_desc: PlaneFormDesc::Concave { points },
                 ..
             }) => points.clone(),
+            Self::Annotation(AnnotationDesc {
+                kind: AnnotationKind::Measurement { end },
+                ..
+            }) => vec![*end],
             _ => Vec::new(),
         }
     }
@@ -232,6 +1069,13 @@ impl LevelObjectDesc {
             }) => {
                 points[index] = point;
             }
+            Self::Annotation(AnnotationDesc {
+                kind: AnnotationKind::Measurement { ref mut end },
+                ..
+            }) => {
+                assert_eq!(index, 0);
+                *end = point;
+            }
             _ => unimplemented!(),
         }
     }
@@ -241,35 +1085,401 @@ impl LevelObjectDesc {
         match self {
             Self::Plane(_) => vec![CollisionLogic::Finish, CollisionLogic::Death],
             Self::Cube(_) => vec![CollisionLogic::Death],
-            Self::RoutePoint(_) => vec![],
+            Self::RoutePoint(_)
+            | Self::Annotation(_)
+            | Self::CameraAnchor(_)
+            | Self::Emitter(_)
+            | Self::DeathZone(_)
+            | Self::FinishZone(_) => vec![],
         }
     }
-}
 
-pub fn maintain_available_spawn_areas_system(
-    mut level_state: ResMut<LevelState>,
-    updated_level_objects: Query<&EntityNetId, Added<LevelObjectTag>>,
-) {
-    for level_object_net_id in updated_level_objects.iter().copied() {
-        let is_spawn_area =
-            level_state
-                .objects
-                .get(&level_object_net_id)
-                .map_or(false, |level_object| match &level_object.desc {
-                    LevelObjectDesc::Plane(plane_desc) => plane_desc.is_spawn_area,
-                    _ => false,
-                });
+    /// Zones have their collision logic built in, it can't be changed.
+    pub fn zone_collision_logic(&self) -> Option<CollisionLogic> {
+        match self {
+            Self::DeathZone(_) => Some(CollisionLogic::Death),
+            Self::FinishZone(_) => Some(CollisionLogic::Finish),
+            _ => None,
+        }
+    }
+}
 
-        if let Some(i) = level_state
-            .spawn_areas
-            .iter()
-            .position(|net_id| *net_id == level_object_net_id)
-        {
-            if !is_spawn_area {
-                level_state.spawn_areas.remove(i);
+/// Convex decomposition is known to panic on some outlines (see
+/// https://github.com/dimforge/rapier/issues/223), so it runs quarantined.
+fn concave_shape(points: &[Vec2]) -> Result<ColliderShape, ColliderShapeError> {
+    let vertices = points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, point)| {
+            if i > 0 && points[i - 1] == *point {
+                None
+            } else {
+                Some(Point2::new(point.x, point.y))
             }
-        } else if is_spawn_area {
-            level_state.spawn_areas.push(level_object_net_id);
+        })
+        .collect::<Vec<_>>();
+    if vertices.len() < 3 {
+        return Err(ColliderShapeError::InvalidOutline);
+    }
+    let mut indices = (0..vertices.len() - 1)
+        .map(|i| [i as u32, i as u32 + 1])
+        .collect::<Vec<_>>();
+    indices.push([indices.last().unwrap()[1], 0]);
+    quarantine(|| {
+        ColliderShape::convex_decomposition_with_params(
+            &vertices,
+            &indices,
+            &VHACDParameters {
+                concavity: 0.01,
+                resolution: 64,
+                ..Default::default()
+            },
+        )
+    })
+    .map_err(ColliderShapeError::Panicked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plane(net_id: u16, is_spawn_area: bool) -> UpdateLevelObject {
+        UpdateLevelObject {
+            object: LevelObject {
+                net_id: EntityNetId(net_id),
+                label: format!("Plane {}", net_id),
+                desc: LevelObjectDesc::Plane(PlaneDesc {
+                    position: Vec2::ZERO,
+                    form_desc: PlaneFormDesc::Circle { radius: 1.0 },
+                    is_spawn_area,
+                    appearance: Default::default(),
+                    jump_pad: None,
+                }),
+                route: None,
+                collision_logic: CollisionLogic::None,
+            },
+            frame_number: FrameNumber::new(0),
+        }
+    }
+
+    fn despawn(net_id: u16) -> DespawnLevelObject {
+        DespawnLevelObject {
+            net_id: EntityNetId(net_id),
+            frame_number: FrameNumber::new(0),
+        }
+    }
+
+    #[test]
+    fn test_medal_classification() {
+        let medal_times = MedalTimes {
+            gold: 10.0,
+            silver: 20.0,
+            bronze: 30.0,
+        };
+        assert!(medal_times.is_valid());
+        assert_eq!(medal_times.classify(5.0), Some(Medal::Gold));
+        assert_eq!(medal_times.classify(10.0), Some(Medal::Gold));
+        assert_eq!(medal_times.classify(15.0), Some(Medal::Silver));
+        assert_eq!(medal_times.classify(30.0), Some(Medal::Bronze));
+        assert_eq!(medal_times.classify(30.5), None);
+
+        assert!(!MedalTimes {
+            gold: 20.0,
+            ..medal_times
+        }
+        .is_valid());
+        assert!(!MedalTimes {
+            gold: 0.0,
+            ..medal_times
+        }
+        .is_valid());
+        assert!(!MedalTimes {
+            bronze: f32::INFINITY,
+            ..medal_times
         }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_respawn_settings() {
+        let respawns = RespawnSettings::default();
+        assert!(respawns.is_valid());
+        assert_eq!(
+            respawns.delay(RespawnPlayerReason::Death),
+            PLAYER_RESPAWN_TIME
+        );
+        assert_eq!(
+            respawns.delay(RespawnPlayerReason::Checkpoint),
+            PLAYER_CHECKPOINT_RESTART_TIME
+        );
+
+        let respawns = RespawnSettings {
+            finish_cooldown: FrameNumber::new(600),
+            ..respawns
+        };
+        assert_eq!(
+            respawns.delay(RespawnPlayerReason::Finish),
+            FrameNumber::new(600)
+        );
+        assert!(!RespawnSettings {
+            death_respawn_delay: FrameNumber::new(0),
+            ..respawns
+        }
+        .is_valid());
+        assert!(!RespawnSettings {
+            finish_cooldown: FrameNumber::new(u16::MAX),
+            ..respawns
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_revision_is_bumped_on_applied_commands() {
+        let mut level_state = LevelState::default();
+        assert_eq!(level_state.revision(), 0);
+
+        level_state.apply_update(&plane(1, false));
+        level_state.apply_update(&plane(1, true));
+        assert_eq!(level_state.revision(), 2);
+
+        assert!(level_state.apply_despawn(&despawn(2)).is_none());
+        assert_eq!(level_state.revision(), 2);
+
+        assert!(level_state.apply_despawn(&despawn(1)).is_some());
+        assert_eq!(level_state.revision(), 3);
+        assert!(level_state.objects().is_empty());
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let mut level_state = LevelState::default();
+        level_state.apply_update(&plane(1, false));
+        level_state.apply_update(&plane(1, true));
+        level_state.apply_despawn(&despawn(1));
+
+        let changes = level_state.changes_since(1).unwrap().collect::<Vec<_>>();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0],
+            &(
+                2,
+                LevelStateChange::Updated {
+                    old: plane(1, false).object,
+                    new: plane(1, true).object,
+                }
+            )
+        );
+        assert_eq!(
+            changes[1],
+            &(3, LevelStateChange::Despawned(plane(1, true).object))
+        );
+        assert_eq!(level_state.changes_since(3).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_changes_since_forgotten_revision() {
+        let mut level_state = LevelState::default();
+        for _ in 0..LEVEL_STATE_CHANGES_LIMIT + 1 {
+            level_state.apply_update(&plane(1, false));
+        }
+
+        assert!(level_state.changes_since(0).is_none());
+        assert_eq!(
+            level_state.changes_since(1).unwrap().count(),
+            LEVEL_STATE_CHANGES_LIMIT
+        );
+    }
+
+    #[test]
+    fn test_spawn_areas() {
+        let mut level_state = LevelState::default();
+        level_state.apply_update(&plane(1, true));
+        level_state.apply_update(&plane(2, false));
+        level_state.apply_update(&plane(3, true));
+        assert_eq!(level_state.spawn_areas(), &[EntityNetId(1), EntityNetId(3)]);
+
+        level_state.apply_update(&plane(1, false));
+        assert_eq!(level_state.spawn_areas(), &[EntityNetId(3)]);
+
+        level_state.apply_despawn(&despawn(3));
+        assert!(level_state.spawn_areas().is_empty());
+    }
+
+    #[test]
+    fn test_spawnable_area() {
+        let mut level_state = LevelState::default();
+        assert_eq!(level_state.spawnable_area(), None);
+        assert_eq!(validate_spawnable_area(None, 5), Ok(()));
+
+        level_state.apply_update(&plane(1, true));
+        level_state.apply_update(&plane(2, false));
+        let one_plane_area = level_state.spawnable_area().unwrap();
+        assert!(one_plane_area > 0.0);
+        assert!(validate_spawnable_area(Some(one_plane_area), 1).is_err());
+
+        let mut big_plane = plane(3, true).object;
+        big_plane.desc = LevelObjectDesc::Plane(PlaneDesc {
+            position: Vec2::ZERO,
+            form_desc: PlaneFormDesc::Rectangle {
+                size: Vec2::new(10.0, 10.0),
+            },
+            is_spawn_area: true,
+            appearance: Default::default(),
+            jump_pad: None,
+        });
+        let area = level_state
+            .spawnable_area_after(big_plane.net_id, Some(&big_plane))
+            .unwrap();
+        assert!((area - one_plane_area - 9.3 * 9.3).abs() < 1e-3);
+        assert_eq!(validate_spawnable_area(Some(area), 5), Ok(()));
+        assert_eq!(level_state.spawnable_area_after(EntityNetId(1), None), None);
+    }
+
+    #[test]
+    fn test_validate_spawnable_area_change() {
+        let enough = SPAWNABLE_AREA_PER_PLAYER * 2.0;
+        let not_enough = SPAWNABLE_AREA_PER_PLAYER;
+        assert_eq!(
+            validate_spawnable_area_change(Some(enough), Some(not_enough), 2),
+            Err(LevelValidationError::NotEnoughSpawnableArea {
+                area: not_enough,
+                required: enough,
+                max_players: 2,
+            })
+        );
+        // Removing all the spawn areas or adding the first one is fine.
+        assert_eq!(
+            validate_spawnable_area_change(Some(enough), None, 2),
+            Ok(())
+        );
+        assert_eq!(
+            validate_spawnable_area_change(None, Some(not_enough), 2),
+            Ok(())
+        );
+        // Levels that are already invalid can be fixed gradually.
+        assert_eq!(
+            validate_spawnable_area_change(Some(not_enough), Some(not_enough * 1.5), 2),
+            Ok(())
+        );
+        assert!(
+            validate_spawnable_area_change(Some(not_enough), Some(not_enough / 2.0), 2).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_level() {
+        let mut finish = plane(1, false).object;
+        finish.collision_logic = CollisionLogic::Finish;
+        let mut spawn_area = plane(2, true).object;
+        assert_eq!(
+            validate_level([&spawn_area], Default::default()),
+            Err(vec![LevelValidationError::NoFinish])
+        );
+        if let LevelObjectDesc::Plane(plane) = &mut spawn_area.desc {
+            plane.position = Vec2::new(5.0, 0.0);
+        }
+        assert_eq!(
+            validate_level([&finish, &spawn_area], Default::default()),
+            Ok(())
+        );
+        // Finish zones don't need their collision logic to be set.
+        let finish_zone = LevelObject {
+            desc: LevelObjectDesc::FinishZone(FinishZoneDesc {
+                position: Vec2::new(-5.0, 0.0),
+                size: Vec2::ONE,
+            }),
+            ..plane(5, false).object
+        };
+        assert_eq!(
+            validate_level([&finish_zone, &spawn_area], Default::default()),
+            Ok(())
+        );
+
+        // Overlaps with the first spawn area.
+        let mut overlapping_spawn_area = plane(3, true).object;
+        overlapping_spawn_area.desc = LevelObjectDesc::Plane(PlaneDesc {
+            position: Vec2::new(7.0, 0.0),
+            form_desc: PlaneFormDesc::Rectangle {
+                size: Vec2::new(3.0, 3.0),
+            },
+            is_spawn_area: true,
+            appearance: Default::default(),
+            jump_pad: None,
+        });
+        let mut invalid_shape = plane(4, false).object;
+        invalid_shape.desc = LevelObjectDesc::Plane(PlaneDesc {
+            position: Vec2::ZERO,
+            form_desc: PlaneFormDesc::Concave {
+                points: vec![Vec2::ZERO, Vec2::ONE, Vec2::ONE],
+            },
+            is_spawn_area: false,
+            appearance: Default::default(),
+            jump_pad: None,
+        });
+        assert_eq!(
+            validate_level(
+                [
+                    &invalid_shape,
+                    &overlapping_spawn_area,
+                    &finish,
+                    &spawn_area
+                ],
+                Default::default()
+            ),
+            Err(vec![
+                LevelValidationError::OverlappingSpawnAreas {
+                    first: EntityNetId(2),
+                    first_label: "Plane 2".to_owned(),
+                    second: EntityNetId(3),
+                    second_label: "Plane 3".to_owned(),
+                },
+                LevelValidationError::InvalidColliderShape {
+                    net_id: EntityNetId(4),
+                    label: "Plane 4".to_owned(),
+                    error: ColliderShapeError::InvalidOutline,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_apply_settings() {
+        let mut level_state = LevelState::default();
+        let settings = LevelSettings {
+            music_track: Some(MusicTrack::Calm),
+            ..LevelSettings::default()
+        };
+        let command = UpdateLevelSettings {
+            settings: settings.clone(),
+        };
+
+        level_state.apply_settings(&command);
+        assert_eq!(level_state.revision(), 1);
+        assert_eq!(level_state.settings(), &settings);
+
+        // Applying the same settings again is a no-op.
+        level_state.apply_settings(&command);
+        assert_eq!(level_state.revision(), 1);
+    }
+
+    #[test]
+    fn test_deserialize_legacy_level() {
+        let objects = vec![plane(1, false).object];
+        let legacy = serde_json::to_value(&objects).unwrap();
+
+        let level: SerializedLevel = serde_json::from_value(legacy).unwrap();
+        assert_eq!(level.objects, objects);
+        assert_eq!(level.settings, LevelSettings::default());
+
+        let level = SerializedLevel {
+            objects,
+            settings: LevelSettings {
+                clear_color: [0.1, 0.2, 0.3],
+                ..LevelSettings::default()
+            },
+        };
+        let deserialized: SerializedLevel =
+            serde_json::from_value(serde_json::to_value(&level).unwrap()).unwrap();
+        assert_eq!(deserialized, level);
     }
 }
@@ -5,7 +5,8 @@ use crate::{
             LevelObjectMovement, LevelObjectMovementPoint, LevelObjectMovementType, LevelObjectTag,
             Position, Spawned,
         },
-        level::{LevelState, ObjectRouteDesc},
+        jump_pad::JumpPad,
+        level::{LevelObject, LevelState, ObjectRouteDesc},
     },
     messages::EntityNetId,
     registry::EntityRegistry,
@@ -15,18 +16,24 @@ use bevy::{
     ecs::{
         entity::Entity,
         query::{With, WorldQuery},
-        system::{Commands, Query, Res},
+        system::{Commands, Query, Res, Resource},
     },
     math::Vec2,
     utils::HashMap,
 };
 use serde::{Deserialize, Serialize};
 
+pub const DEFAULT_COLLIDER_SIMPLIFICATION_TOLERANCE: f32 = 0.05;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PlaneDesc {
     pub position: Vec2,
     pub form_desc: PlaneFormDesc,
     pub is_spawn_area: bool,
+    #[serde(default)]
+    pub appearance: ObjectAppearance,
+    #[serde(default)]
+    pub jump_pad: Option<JumpPad>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -46,10 +53,180 @@ impl std::fmt::Display for PlaneFormDesc {
     }
 }
 
+impl PlaneFormDesc {
+    /// The area where the center of an object with the passed radius can be
+    /// placed without the object sticking out of the plane. Concave planes
+    /// can't be spawn areas, so it's always zero for them.
+    pub fn inner_area(&self, object_radius: f32) -> f32 {
+        match self {
+            PlaneFormDesc::Circle { radius } => {
+                std::f32::consts::PI * (radius - object_radius).max(0.0).powi(2)
+            }
+            PlaneFormDesc::Rectangle { size } => {
+                let inner_size = (*size - Vec2::splat(object_radius * 2.0)).max(Vec2::ZERO);
+                inner_size.x * inner_size.y
+            }
+            PlaneFormDesc::Concave { .. } => 0.0,
+        }
+    }
+}
+
+/// Parameters of simplifying concave plane outlines before calculating their
+/// colliders. Servers send them to clients on game start, as both sides need
+/// to simulate identical colliders.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ColliderSimplification {
+    /// Outline points that are closer than this distance to the simplified
+    /// outline get removed. Zero disables simplification.
+    pub tolerance: f32,
+}
+
+impl Default for ColliderSimplification {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_COLLIDER_SIMPLIFICATION_TOLERANCE,
+        }
+    }
+}
+
+/// Simplifies a closed outline with the Douglas-Peucker algorithm. Returns the
+/// original points if the simplified outline would degenerate into less than 3
+/// points.
+pub fn simplify_outline(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if tolerance <= 0.0 || points.len() <= 3 {
+        return points.to_vec();
+    }
+
+    // Closed outlines don't have natural ends, so we split them into two chains
+    // at the first point and the one that is the farthest from it.
+    let (split_index, _) = points.iter().enumerate().skip(1).fold(
+        (0, 0.0),
+        |(farthest_index, farthest_distance), (i, point)| {
+            let distance = point.distance_squared(points[0]);
+            if distance > farthest_distance {
+                (i, distance)
+            } else {
+                (farthest_index, farthest_distance)
+            }
+        },
+    );
+    if split_index == 0 {
+        return points.to_vec();
+    }
+
+    let mut closed_outline = points.to_vec();
+    closed_outline.push(points[0]);
+    let mut keep = vec![false; closed_outline.len()];
+    keep[0] = true;
+    keep[split_index] = true;
+    douglas_peucker(&closed_outline, 0, split_index, tolerance, &mut keep);
+    douglas_peucker(
+        &closed_outline,
+        split_index,
+        points.len(),
+        tolerance,
+        &mut keep,
+    );
+
+    let simplified = points
+        .iter()
+        .zip(keep)
+        .filter_map(|(point, keep)| keep.then_some(*point))
+        .collect::<Vec<_>>();
+    if simplified.len() < 3 {
+        return points.to_vec();
+    }
+    simplified
+}
+
+fn douglas_peucker(points: &[Vec2], start: usize, end: usize, tolerance: f32, keep: &mut [bool]) {
+    if end <= start + 1 {
+        return;
+    }
+
+    let (farthest_index, farthest_distance) =
+        (start + 1..end).fold((start, 0.0), |(farthest_index, farthest_distance), i| {
+            let distance = distance_to_segment(points[i], points[start], points[end]);
+            if distance > farthest_distance {
+                (i, distance)
+            } else {
+                (farthest_index, farthest_distance)
+            }
+        });
+    if farthest_distance > tolerance {
+        keep[farthest_index] = true;
+        douglas_peucker(points, start, farthest_index, tolerance, keep);
+        douglas_peucker(points, farthest_index, end, tolerance, keep);
+    }
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let length_squared = ab.length_squared();
+    if length_squared == 0.0 {
+        return point.distance(a);
+    }
+    let t = ((point - a).dot(ab) / length_squared).clamp(0.0, 1.0);
+    point.distance(a + ab * t)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CubeDesc {
     pub size: f32,
     pub position: Vec2,
+    #[serde(default)]
+    pub appearance: ObjectAppearance,
+}
+
+/// Objects without a custom color are rendered with the default one for their
+/// type and collision logic.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObjectAppearance {
+    /// sRGB.
+    pub color: Option<[f32; 3]>,
+    /// How much an object glows with its own color, from 0.0 to 1.0.
+    pub emissive: f32,
+}
+
+impl ObjectAppearance {
+    pub fn is_default(&self) -> bool {
+        self.color.is_none() && self.emissive <= 0.0
+    }
+}
+
+/// Perceptual difference (CIE76 delta E) between two sRGB colors. Values below
+/// ~2.3 aren't noticeable, values above ~50 mean that colors are very
+/// different.
+pub fn color_difference(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = srgb_to_lab(a);
+    let [l2, a2, b2] = srgb_to_lab(b);
+    ((l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)).sqrt()
+}
+
+fn srgb_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+
+    // Normalized by the D65 white point.
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -57,6 +234,73 @@ pub struct RoutePointDesc {
     pub position: Vec2,
 }
 
+/// Builder-only annotations. They are stored with the rest of the level, but
+/// aren't simulated and aren't shown to runners.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnnotationDesc {
+    pub position: Vec2,
+    pub kind: AnnotationKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AnnotationKind {
+    Measurement {
+        /// The other end of a measured line, relative to the annotation
+        /// position.
+        end: Vec2,
+    },
+    Note {
+        text: String,
+    },
+    /// A rectangle centered at the annotation position.
+    Region {
+        size: Vec2,
+    },
+}
+
+impl std::fmt::Display for AnnotationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnotationKind::Measurement { .. } => write!(f, "Measurement"),
+            AnnotationKind::Note { .. } => write!(f, "Note"),
+            AnnotationKind::Region { .. } => write!(f, "Region"),
+        }
+    }
+}
+
+pub const CAMERA_ANCHOR_MIN_DURATION_SECS: f32 = 0.1;
+pub const CAMERA_ANCHOR_MAX_DURATION_SECS: f32 = 10.0;
+
+/// A point of the level intro, which the camera flies through when a player
+/// joins the level. Like annotations, camera anchors aren't simulated.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CameraAnchorDesc {
+    pub position: Vec2,
+    /// The camera visits anchors in the ascending order.
+    pub order: u16,
+    /// How long the camera flies from this anchor to the next one. For the
+    /// last anchor, it's how long the camera stays there.
+    pub duration_secs: f32,
+}
+
+pub const ZONE_MIN_SIZE: f32 = 0.1;
+
+/// A rectangle that kills runners touching it. Unlike planes with
+/// `CollisionLogic::Death`, death zones are drawn on top of planes, so they
+/// can mark a part of one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeathZoneDesc {
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
+/// A rectangle that finishes the level for runners touching it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FinishZoneDesc {
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
 pub fn update_level_object_movement_route_settings_system(
     mut commands: Commands,
     time: Res<SimulationTime>,
@@ -78,7 +322,7 @@ pub fn update_level_object_movement_route_settings_system(
         .iter_mut()
         .filter(|(_, _, _, spawned)| spawned.is_spawned(time.player_frame))
     {
-        let level_def = match level.objects.get(&objects_registry.get_id(entity).unwrap()) {
+        let level_def = match level.object(objects_registry.get_id(entity).unwrap()) {
             Some(level_def) => level_def.clone(),
             // The object is might be removed from the level when we are rewinding to the frame
             // when it still existed. We can skip it, it's not the end of the world.
@@ -92,26 +336,7 @@ pub fn update_level_object_movement_route_settings_system(
 
         let mut is_invalid = false;
         let new_level_object_movement = level_def.route.and_then(|route| {
-            let (movement_type, points) = match route.desc {
-                ObjectRouteDesc::Attached(None) | ObjectRouteDesc::Radial(None) => return None,
-                ObjectRouteDesc::Attached(Some(point)) | ObjectRouteDesc::Radial(Some(point)) => {
-                    (LevelObjectMovementType::Radial, vec![point])
-                }
-                ObjectRouteDesc::ForwardCycle(points) => {
-                    if points.is_empty() {
-                        return None;
-                    }
-                    (LevelObjectMovementType::Linear, points)
-                }
-                ObjectRouteDesc::ForwardBackwardsCycle(mut points) => {
-                    if points.is_empty() {
-                        return None;
-                    }
-                    let mut cycle = points.iter().rev().skip(1).cloned().collect::<Vec<_>>();
-                    points.append(&mut cycle);
-                    (LevelObjectMovementType::Linear, points)
-                }
-            };
+            let (movement_type, points) = route_points(&route.desc)?;
 
             let has_invalid_point = points
                 .iter()
@@ -129,8 +354,10 @@ pub fn update_level_object_movement_route_settings_system(
             );
             let mut points_progress = points
                 .into_iter()
-                .map(|point| LevelObjectMovementPoint {
+                .zip(route.point_waits())
+                .map(|(point, wait)| LevelObjectMovementPoint {
                     progress: 0.0,
+                    wait: wait.value() as f32 / route.period.value().max(1) as f32,
                     position: Vec2::ZERO,
                     entity: objects_registry.get_entity(point).unwrap(),
                 })
@@ -139,8 +366,7 @@ pub fn update_level_object_movement_route_settings_system(
             let init_vec = match movement_type {
                 LevelObjectMovementType::Radial => {
                     let attached_point = level
-                        .objects
-                        .get(&objects_registry.get_id(points_progress[0].entity).unwrap())
+                        .object(objects_registry.get_id(points_progress[0].entity).unwrap())
                         .map_or(initial_object_position, |level_object| {
                             level_object
                                 .desc
@@ -187,7 +413,8 @@ pub fn update_level_object_movement_route_settings_system(
 
                 if !yes {
                     for (p1, p2) in points_progress.iter().zip(movement.points_progress.iter()) {
-                        if p1.entity != p2.entity {
+                        #[allow(clippy::float_cmp)]
+                        if p1.entity != p2.entity || p1.wait != p2.wait {
                             yes = true;
                             break;
                         }
@@ -309,11 +536,6 @@ fn resolve_new_path_recursive(
         assert_eq!(movement.movement_type, LevelObjectMovementType::Linear);
 
         let mut points_progress = Vec::new();
-        let mut total_distance = 0.0;
-        let mut prev_point_position = movement
-            .points_progress
-            .first()
-            .map_or(Vec2::ZERO, |point| point.position);
         for point in &movement.points_progress {
             let position: Vec2 = object_updates.get(&point.entity).map_or_else(
                 || {
@@ -326,27 +548,18 @@ fn resolve_new_path_recursive(
                 },
                 |(position, _)| *position,
             );
-            total_distance += (position - prev_point_position).length();
-            prev_point_position = position;
-
             points_progress.push(LevelObjectMovementPoint {
                 progress: 0.0,
+                wait: point.wait,
                 position,
                 entity: point.entity,
             });
         }
-        let mut current_distance = 0.0;
-        let mut prev_point_position = movement
+        let prev_first_point_position = movement
             .points_progress
             .first()
             .map_or(Vec2::ZERO, |point| point.position);
-        for i in 1..points_progress.len() - 1 {
-            let point = points_progress.get_mut(i).unwrap();
-            current_distance += (point.position - prev_point_position).length();
-            prev_point_position = point.position;
-            point.progress = current_distance / total_distance;
-        }
-        points_progress.last_mut().unwrap().progress = 1.0;
+        assign_linear_progress(&mut points_progress, prev_first_point_position);
 
         points_progress
     };
@@ -365,7 +578,181 @@ fn resolve_new_path_recursive(
     );
 }
 
-fn closest_start_frame_to_time(
+/// Predicts the position of a level object at the given frame by evaluating
+/// its route (and the routes of the points it depends on) from the level
+/// description, without stepping the physics. Returns `None` if the object
+/// doesn't exist, doesn't have a position, or its route can't be resolved
+/// (it references missing points or forms a cycle).
+///
+/// Spawning isn't taken into account: the result is where the object will be
+/// if it's spawned at that frame.
+pub fn predict_object_position(
+    level: &LevelState,
+    objects_registry: &EntityRegistry<EntityNetId>,
+    generation: u64,
+    frame_number: FrameNumber,
+    entity_net_id: EntityNetId,
+) -> Option<Vec2> {
+    predict_object_position_recursive(
+        level,
+        objects_registry,
+        generation,
+        frame_number,
+        &mut vec![entity_net_id],
+    )
+}
+
+fn predict_object_position_recursive(
+    level: &LevelState,
+    objects_registry: &EntityRegistry<EntityNetId>,
+    generation: u64,
+    frame_number: FrameNumber,
+    entities_stack: &mut Vec<EntityNetId>,
+) -> Option<Vec2> {
+    let level_object = level.object(*entities_stack.last().unwrap())?;
+    let initial_object_position = level_object.desc.position()?;
+    let Some((route, (movement_type, points))) = level_object
+        .route
+        .as_ref()
+        .and_then(|route| Some((route, route_points(&route.desc)?)))
+    else {
+        return Some(initial_object_position);
+    };
+
+    let attached_point = points[0];
+    let mut points_progress = Vec::with_capacity(points.len());
+    for (point, wait) in points.into_iter().zip(route.point_waits()) {
+        // Objects with cyclic dependencies aren't moved by the simulation either.
+        if entities_stack.contains(&point) {
+            return None;
+        }
+        let entity = objects_registry.get_entity(point)?;
+        entities_stack.push(point);
+        let position = predict_object_position_recursive(
+            level,
+            objects_registry,
+            generation,
+            frame_number,
+            entities_stack,
+        );
+        entities_stack.pop();
+        points_progress.push(LevelObjectMovementPoint {
+            progress: 0.0,
+            wait: wait.value() as f32 / route.period.value().max(1) as f32,
+            position: position?,
+            entity,
+        });
+    }
+    let first_point_position = points_progress[0].position;
+    assign_linear_progress(&mut points_progress, first_point_position);
+
+    let init_vec = match movement_type {
+        LevelObjectMovementType::Radial => {
+            let attached_point = level
+                .object(attached_point)
+                .and_then(|level_object| level_object.desc.position())
+                .unwrap_or(initial_object_position);
+            initial_object_position - attached_point
+        }
+        LevelObjectMovementType::Linear => Vec2::ZERO,
+    };
+    let movement = LevelObjectMovement {
+        frame_started: closest_start_frame_to_time(
+            generation,
+            frame_number,
+            route.start_frame_offset,
+            route.period,
+        ),
+        init_vec,
+        period: route.period,
+        points_progress,
+        movement_type,
+    };
+    Some(movement.current_position(frame_number))
+}
+
+/// Returns the distance that an object covers during a period of its route,
+/// measured between the initial positions of the route points. Returns `None`
+/// if the route doesn't have any points or references missing ones.
+pub fn route_length(level: &LevelState, level_object: &LevelObject) -> Option<f32> {
+    let route = level_object.route.as_ref()?;
+    let (movement_type, points) = route_points(&route.desc)?;
+    let positions = points
+        .into_iter()
+        .map(|point| level.object(point)?.desc.position())
+        .collect::<Option<Vec<_>>>()?;
+    match movement_type {
+        LevelObjectMovementType::Radial => {
+            let radius = (level_object.desc.position()? - positions[0]).length();
+            Some(radius * std::f32::consts::PI * 2.0)
+        }
+        LevelObjectMovementType::Linear => Some(
+            positions
+                .windows(2)
+                .map(|points| (points[1] - points[0]).length())
+                .sum(),
+        ),
+    }
+}
+
+/// Returns `None` if a route doesn't have any points.
+fn route_points(
+    route_desc: &ObjectRouteDesc,
+) -> Option<(LevelObjectMovementType, Vec<EntityNetId>)> {
+    match route_desc {
+        ObjectRouteDesc::Attached(None) | ObjectRouteDesc::Radial(None) => None,
+        ObjectRouteDesc::Attached(Some(point)) | ObjectRouteDesc::Radial(Some(point)) => {
+            Some((LevelObjectMovementType::Radial, vec![*point]))
+        }
+        ObjectRouteDesc::ForwardCycle(points) => {
+            if points.is_empty() {
+                return None;
+            }
+            Some((LevelObjectMovementType::Linear, points.clone()))
+        }
+        ObjectRouteDesc::ForwardBackwardsCycle(points) => {
+            if points.is_empty() {
+                return None;
+            }
+            let mut points = points.clone();
+            let mut cycle = points.iter().rev().skip(1).cloned().collect::<Vec<_>>();
+            points.append(&mut cycle);
+            Some((LevelObjectMovementType::Linear, points))
+        }
+    }
+}
+
+/// Distributes the route progress that isn't spent waiting between points
+/// proportionally to the distances between them. The final point always gets
+/// `1.0`.
+fn assign_linear_progress(
+    points_progress: &mut [LevelObjectMovementPoint],
+    prev_first_point_position: Vec2,
+) {
+    let total_wait = points_progress.iter().map(|point| point.wait).sum::<f32>();
+    let mut total_distance = 0.0;
+    let mut prev_point_position = prev_first_point_position;
+    for point in points_progress.iter() {
+        total_distance += (point.position - prev_point_position).length();
+        prev_point_position = point.position;
+    }
+
+    let mut current_distance = 0.0;
+    let mut prev_point_position = prev_first_point_position;
+    let mut waited = points_progress[0].wait;
+    let points_count = points_progress.len();
+    for point in points_progress.iter_mut().take(points_count - 1).skip(1) {
+        current_distance += (point.position - prev_point_position).length();
+        prev_point_position = point.position;
+        point.progress = waited + current_distance / total_distance * (1.0 - total_wait);
+        waited += point.wait;
+    }
+    points_progress.last_mut().unwrap().progress = 1.0;
+}
+
+/// Returns the frame at which the current period of a route has started (or
+/// will start, if the first period is yet to begin).
+pub fn closest_start_frame_to_time(
     generation: u64,
     frame_number: FrameNumber,
     start_frame_offset: FrameNumber,
@@ -407,6 +794,137 @@ fn closest_start_frame_to_time(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::game::{
+        commands::UpdateLevelObject,
+        level::{CollisionLogic, LevelObject, LevelObjectDesc, ObjectRoute},
+    };
+
+    fn level_object(net_id: u16, position: Vec2, route: Option<ObjectRouteDesc>) -> LevelObject {
+        LevelObject {
+            net_id: EntityNetId(net_id),
+            label: String::new(),
+            desc: LevelObjectDesc::RoutePoint(RoutePointDesc { position }),
+            route: route.map(|desc| ObjectRoute::new(FrameNumber::new(100), desc)),
+            collision_logic: CollisionLogic::None,
+        }
+    }
+
+    #[test]
+    fn test_predict_object_position() {
+        let mut level = LevelState::default();
+        let mut objects_registry = EntityRegistry::default();
+        for object in [
+            level_object(1, Vec2::ZERO, None),
+            level_object(2, Vec2::new(10.0, 0.0), None),
+            level_object(
+                3,
+                Vec2::ZERO,
+                Some(ObjectRouteDesc::ForwardCycle(vec![
+                    EntityNetId(1),
+                    EntityNetId(2),
+                ])),
+            ),
+            // Rotates around the moving object.
+            level_object(
+                4,
+                Vec2::new(12.0, 0.0),
+                Some(ObjectRouteDesc::Radial(Some(EntityNetId(3)))),
+            ),
+            level_object(
+                5,
+                Vec2::ZERO,
+                Some(ObjectRouteDesc::Attached(Some(EntityNetId(6)))),
+            ),
+            level_object(
+                6,
+                Vec2::ZERO,
+                Some(ObjectRouteDesc::Attached(Some(EntityNetId(5)))),
+            ),
+            level_object(
+                7,
+                Vec2::ZERO,
+                Some(ObjectRouteDesc::ForwardCycle(vec![EntityNetId(99)])),
+            ),
+        ] {
+            objects_registry.register(object.net_id, Entity::from_raw(object.net_id.0 as u32));
+            level.apply_update(&UpdateLevelObject {
+                object,
+                frame_number: FrameNumber::new(0),
+            });
+        }
+
+        let predict = |net_id| {
+            predict_object_position(
+                &level,
+                &objects_registry,
+                0,
+                FrameNumber::new(25),
+                EntityNetId(net_id),
+            )
+        };
+        assert_eq!(predict(1), Some(Vec2::ZERO));
+        assert_eq!(predict(3), Some(Vec2::new(2.5, 0.0)));
+        let radial = predict(4).unwrap();
+        assert!((radial - Vec2::new(2.5, 12.0)).length() < 0.001, "{radial}");
+        // Cyclic dependencies and missing points.
+        assert_eq!(predict(5), None);
+        assert_eq!(predict(7), None);
+        assert_eq!(predict(100), None);
+    }
+
+    #[test]
+    fn test_predict_object_position_with_waits() {
+        let mut level = LevelState::default();
+        let mut objects_registry = EntityRegistry::default();
+        let mut moving_object = level_object(
+            3,
+            Vec2::ZERO,
+            Some(ObjectRouteDesc::ForwardBackwardsCycle(vec![
+                EntityNetId(1),
+                EntityNetId(2),
+            ])),
+        );
+        // Waits at the first point for 40% of the period.
+        moving_object.route.as_mut().unwrap().waits = vec![FrameNumber::new(40)];
+        for object in [
+            level_object(1, Vec2::ZERO, None),
+            level_object(2, Vec2::new(10.0, 0.0), None),
+            moving_object.clone(),
+        ] {
+            objects_registry.register(object.net_id, Entity::from_raw(object.net_id.0 as u32));
+            level.apply_update(&UpdateLevelObject {
+                object,
+                frame_number: FrameNumber::new(0),
+            });
+        }
+
+        let predict = |frame_number| {
+            predict_object_position(
+                &level,
+                &objects_registry,
+                0,
+                FrameNumber::new(frame_number),
+                EntityNetId(3),
+            )
+            .unwrap()
+        };
+        assert!(predict(25).length() < 0.001);
+        assert!((predict(55) - Vec2::new(5.0, 0.0)).length() < 0.001);
+        assert!((predict(70) - Vec2::new(10.0, 0.0)).length() < 0.001);
+        assert!((predict(85) - Vec2::new(5.0, 0.0)).length() < 0.001);
+        assert_eq!(route_length(&level, &moving_object), Some(20.0));
+    }
+
+    #[test]
+    fn test_color_difference() {
+        assert!(color_difference([0.3, 0.5, 0.3], [0.3, 0.5, 0.3]) < f32::EPSILON);
+        let black_white = color_difference([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+        assert!((black_white - 100.0).abs() < 0.1, "{black_white}");
+        assert!(
+            color_difference([0.3, 0.5, 0.3], [0.31, 0.5, 0.3])
+                < color_difference([0.3, 0.5, 0.3], [0.55, 0.15, 0.2])
+        );
+    }
 
     #[test]
     fn test_closest_start_frame_to_time_zero_generation_less_than_period() {
@@ -511,4 +1029,72 @@ mod tests {
             FrameNumber::new(4464)
         );
     }
+
+    #[test]
+    fn test_simplify_outline_removes_collinear_points() {
+        let points = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(1.0, 2.0),
+            Vec2::new(0.0, 2.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert_eq!(
+            simplify_outline(&points, 0.01),
+            vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(2.0, 0.0),
+                Vec2::new(2.0, 2.0),
+                Vec2::new(0.0, 2.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_simplify_outline_respects_tolerance() {
+        let points = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.03),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(1.0, 0.5),
+            Vec2::new(2.0, 2.0),
+            Vec2::new(0.0, 2.0),
+        ];
+        assert_eq!(
+            simplify_outline(&points, 0.05),
+            vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(2.0, 0.0),
+                Vec2::new(1.0, 0.5),
+                Vec2::new(2.0, 2.0),
+                Vec2::new(0.0, 2.0),
+            ]
+        );
+        assert_eq!(simplify_outline(&points, 0.01), points);
+    }
+
+    #[test]
+    fn test_simplify_outline_disabled() {
+        let points = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(1.0, 1.0),
+        ];
+        assert_eq!(simplify_outline(&points, 0.0), points);
+    }
+
+    #[test]
+    fn test_simplify_outline_keeps_degenerate_outlines() {
+        let points = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.01),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(1.0, -0.01),
+        ];
+        assert_eq!(simplify_outline(&points, 0.05), points);
+    }
 }
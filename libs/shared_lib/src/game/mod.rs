@@ -9,9 +9,11 @@ use crate::{
     game::{
         commands::{
             DeferredQueue, DespawnLevelObject, DespawnPlayer, SpawnPlayer, SwitchPlayerRole,
-            UpdateLevelObject,
+            UpdateLevelObject, UpdateLevelSettings,
         },
         components::{LevelObjectServerGhostParent, LevelObjectStaticGhostParent, PlayerSensor},
+        level::LevelState,
+        tether::Tethers,
     },
     messages::{EntityNetId, PlayerNetId},
     player::{PlayerEvent, PlayerUpdates, Players},
@@ -33,13 +35,23 @@ use bevy::{
 
 pub mod client_factories;
 pub mod collisions;
+pub mod command_log;
 pub mod commands;
 pub mod components;
+pub mod determinism;
+pub mod emitter;
 pub mod events;
+pub mod game_mode;
+pub mod jump_pad;
 pub mod level;
 pub mod level_objects;
 pub mod movement;
+pub mod navigation;
+pub mod polygon;
+pub mod replay;
+pub mod rollback;
 pub mod spawn;
+pub mod tether;
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct PlayerEventSender(pub Option<tokio::sync::mpsc::UnboundedSender<PlayerEvent>>);
@@ -57,6 +69,8 @@ pub fn reset_game_world_system(world: &mut World) {
 
     let mut players = world.get_resource_mut::<Players>().unwrap();
     players.clear();
+    let mut tethers = world.get_resource_mut::<Tethers>().unwrap();
+    tethers.pairs.clear();
 
     let mut entities_to_despawn = Vec::new();
 
@@ -95,24 +109,18 @@ pub fn reset_game_world_system(world: &mut World) {
             net_id.0
         );
         entities_to_despawn.push(*object_entity);
-        // Clean up custom meshes.
-        #[cfg(feature = "client")]
-        {
-            let handle = world
-                .query::<&bevy::asset::Handle<bevy::render::mesh::Mesh>>()
-                .get(world, *object_entity)
-                .unwrap()
-                .clone();
-            world
-                .get_resource_mut::<bevy::asset::Assets<bevy::render::mesh::Mesh>>()
-                .unwrap()
-                .remove(handle);
-        }
     }
     world
         .get_resource_mut::<EntityRegistry<EntityNetId>>()
         .unwrap()
         .clear();
+    // Meshes and materials of the objects (and their ghosts) are freed during the
+    // next frames.
+    #[cfg(feature = "client")]
+    world
+        .get_resource_mut::<crate::client::asset_gc::LevelObjectAssetsGc>()
+        .unwrap()
+        .release_all();
 
     // Drop static ghosts of level objects.
     for static_ghost_entity in world
@@ -134,21 +142,30 @@ pub fn reset_game_world_system(world: &mut World) {
         world.despawn(entity);
     }
 
-    *world
+    world
         .get_resource_mut::<DeferredQueue<SpawnPlayer>>()
-        .unwrap() = Default::default();
-    *world
+        .unwrap()
+        .clear();
+    world
         .get_resource_mut::<DeferredQueue<DespawnPlayer>>()
-        .unwrap() = Default::default();
-    *world
+        .unwrap()
+        .clear();
+    world
         .get_resource_mut::<DeferredQueue<UpdateLevelObject>>()
-        .unwrap() = Default::default();
-    *world
+        .unwrap()
+        .clear();
+    world
         .get_resource_mut::<DeferredQueue<DespawnLevelObject>>()
-        .unwrap() = Default::default();
-    *world
+        .unwrap()
+        .clear();
+    world
+        .get_resource_mut::<DeferredQueue<UpdateLevelSettings>>()
+        .unwrap()
+        .clear();
+    world
         .get_resource_mut::<DeferredQueue<SwitchPlayerRole>>()
-        .unwrap() = Default::default();
+        .unwrap()
+        .clear();
     *world.get_resource_mut().unwrap() = PlayerUpdates::default();
 }
 
@@ -194,6 +211,8 @@ pub fn switch_player_role_system(
             continue;
         }
 
+        #[cfg(not(feature = "client"))]
+        let prev_role = player.role;
         player.role = switch_role_command.role;
         log::info!(
             "Switching player ({}) role to {:?}",
@@ -202,14 +221,16 @@ pub fn switch_player_role_system(
         );
 
         // If a player is going to be respawned due to a Finish or Death event, we want
-        // to prevent it, as players shouldn't be respawned when in Builder
-        // mode.
+        // to prevent it, as players shouldn't be respawned when in Builder or
+        // Spectator mode.
         player.respawning_at = None;
 
         #[cfg(not(feature = "client"))]
         {
-            match player.role {
-                PlayerRole::Runner => {
+            // Only runners have bodies, switching between builders and spectators
+            // doesn't spawn or despawn anything.
+            match (prev_role, player.role) {
+                (_, PlayerRole::Runner) => {
                     spawn_player_commands.push(SpawnPlayer {
                         net_id: switch_role_command.net_id,
                         start_position: level_spawn_location_service
@@ -217,13 +238,14 @@ pub fn switch_player_role_system(
                         is_player_frame_simulated: switch_role_command.is_player_frame_simulated,
                     });
                 }
-                PlayerRole::Builder => {
+                (PlayerRole::Runner, _) => {
                     despawn_player_commands.push(DespawnPlayer {
                         net_id: switch_role_command.net_id,
                         frame_number: switch_role_command.frame_number,
                         reason: DespawnReason::SwitchRole,
                     });
                 }
+                _ => {}
             }
 
             switch_role_messages.push(SwitchRole {
@@ -235,6 +257,20 @@ pub fn switch_player_role_system(
     }
 }
 
+pub fn update_level_settings_system(
+    time: Res<SimulationTime>,
+    mut update_level_settings_commands: ResMut<DeferredQueue<UpdateLevelSettings>>,
+    mut level_state: ResMut<LevelState>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    // Only the latest settings matter.
+    if let Some(command) = update_level_settings_commands.drain(&time).pop() {
+        log::debug!("Updating level settings: {:?}", command.settings);
+        level_state.apply_settings(&command);
+    }
+}
+
 pub fn remove_disconnected_players_system(
     player_entities: Res<EntityRegistry<PlayerNetId>>,
     mut players: ResMut<Players>,
@@ -247,8 +283,12 @@ pub fn remove_disconnected_players_system(
         if remove {
             log::info!("Player {} is disconnected and removed", player_net_id.0);
 
+            // Bots aren't tracked, as they don't have sessions.
             #[cfg(not(feature = "client"))]
-            if let Some(players_tracking_channel) = &mut **players_tracking_channel {
+            if let Some(players_tracking_channel) = (**players_tracking_channel)
+                .as_mut()
+                .filter(|_| !player.is_bot)
+            {
                 if let Err(err) =
                     players_tracking_channel.send(PlayerEvent::Disconnected(player.uuid.clone()))
                 {
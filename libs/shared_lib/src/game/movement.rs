@@ -5,29 +5,35 @@ use crate::{
     framebuffer::FrameNumber,
     game::{
         components::{
-            LevelObjectServerGhostChild, LevelObjectTag, LockPhysics, PlayerDirection,
-            PlayerFrameSimulated, PlayerSensor, PlayerTag, Position, PredictedPosition, Spawned,
+            LevelObjectMovement, LevelObjectServerGhostChild, LevelObjectTag, LockPhysics,
+            PlayerDirection, PlayerFrameSimulated, PlayerSensor, PlayerSensors, PlayerTag,
+            Position, PredictedPosition, Spawned,
         },
+        jump_pad::JumpPadBoost,
+        level::LevelParams,
+        rollback::RerunDirtyFlags,
         spawn::{iter_spawned, SpawnedQuery, SpawnedQueryItem},
+        tether::{solve_tether_constraint, TetheredBody, Tethers},
     },
     messages::PlayerNetId,
     player::PlayerUpdates,
     registry::EntityRegistry,
-    GameTime, SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT, SIMULATIONS_PER_SECOND,
+    GameTime, SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT, PLAYER_RADIUS, SIMULATIONS_PER_SECOND,
 };
 use bevy::{
     ecs::{
         entity::Entity,
         query::{With, Without, WorldQuery},
-        system::{Query, Res, ResMut},
+        system::{Query, Res, ResMut, Resource},
     },
     log,
     math::Vec2,
     transform::components::Transform,
+    utils::HashMap,
 };
 use bevy_rapier2d::{
     dynamics::{RigidBody, Velocity},
-    geometry::Group,
+    geometry::{Collider, Group},
     prelude::CollisionGroups,
 };
 
@@ -36,8 +42,70 @@ fn lerp_factor() -> f32 {
     1.0 / SIMULATIONS_PER_SECOND * 4.0
 }
 
+/// Routed objects that are far from all players can't collide with anyone, so
+/// syncing their positions to the physics backend every frame is wasted work.
+/// Their `Position` is still evaluated from the route math every frame, so an
+/// object catches up with its route exactly as soon as a player approaches.
+///
+/// Is inserted only by the server, as clients don't simulate many sprawling
+/// levels at once and their rollbacks are more sensitive to the physics state.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct DistantObjectsStepping {
+    /// Players aren't expected to move faster than this (units per second),
+    /// jump pad boosts included.
+    pub max_player_speed: f32,
+    /// Distant objects are synced once per this many frames.
+    pub sync_interval: u16,
+}
+
+impl Default for DistantObjectsStepping {
+    fn default() -> Self {
+        Self {
+            // The default jump pad impulse triples the speed of a runner.
+            max_player_speed: player_movement_speed() * 4.0,
+            sync_interval: 8,
+        }
+    }
+}
+
+impl DistantObjectsStepping {
+    /// The distance a player can cover before a distant object gets synced
+    /// again. Objects whose colliders are closer than this (plus the player's
+    /// radius and the object's own reach) to any player are synced every
+    /// frame.
+    pub fn player_reach(&self) -> f32 {
+        self.reach(self.max_player_speed)
+    }
+
+    /// `object_speed` is the fastest speed (in units per second) that an object
+    /// moves with along its route.
+    pub fn needs_sync(
+        &self,
+        entity: Entity,
+        frame_number: FrameNumber,
+        object_position: Vec2,
+        object_radius: f32,
+        object_speed: f32,
+        player_positions: &[Vec2],
+    ) -> bool {
+        let sync_distance =
+            self.player_reach() + self.reach(object_speed) + PLAYER_RADIUS + object_radius;
+        let is_near = player_positions.iter().any(|player_position| {
+            player_position.distance_squared(object_position) <= sync_distance * sync_distance
+        });
+        // Distant objects are staggered, so that they aren't synced all at once.
+        let sync_interval = self.sync_interval.max(1) as u32;
+        is_near || (frame_number.value() as u32 + entity.index()) % sync_interval == 0
+    }
+
+    /// The distance covered with `speed` (in units per second) between syncs.
+    fn reach(&self, speed: f32) -> f32 {
+        speed * self.sync_interval.max(1) as f32 / SIMULATIONS_PER_SECOND
+    }
+}
+
 /// The scaling factor for the player's linear velocity.
-fn player_movement_speed() -> f32 {
+pub fn player_movement_speed() -> f32 {
     360.0 / SIMULATIONS_PER_SECOND
 }
 
@@ -139,10 +207,15 @@ pub struct PlayerQuery<'w> {
     velocity: &'w mut Velocity,
     direction: &'w PlayerDirection,
     position: &'w Position,
+    sensors: &'w PlayerSensors,
+    jump_pad_boost: &'w mut JumpPadBoost,
 }
 
 pub fn player_movement_system(
     time: Res<SimulationTime>,
+    tethers: Res<Tethers>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    level: LevelParams,
     mut players: Query<SpawnedQuery<PlayerQuery>>,
 ) {
     #[cfg(feature = "profiler")]
@@ -152,19 +225,31 @@ pub fn player_movement_system(
         time.server_frame,
         time.player_frame
     );
+    let mut tethered_bodies = HashMap::default();
     for SpawnedQueryItem {
         item: mut player,
         player_frame_simulated,
         spawned,
     } in iter_spawned(players.iter_mut(), &time)
     {
+        let frame_number = time.entity_simulation_frame(player_frame_simulated);
+
         // Skip non-local entities if we are correcting client's mispredictions.
         if time.player_frame_simulated_only() && player_frame_simulated.is_none() {
+            // Tethered local players still need to be held by them.
+            if let Some(position) = player.position.buffer.get(frame_number) {
+                tethered_bodies.insert(
+                    player.entity,
+                    TetheredBody {
+                        position: *position,
+                        velocity: Vec2::ZERO,
+                        is_fixed: true,
+                    },
+                );
+            }
             continue;
         }
 
-        let frame_number = time.entity_simulation_frame(player_frame_simulated);
-
         let body_position = &mut player.transform;
         let current_position = player.position.buffer.get(frame_number).unwrap_or_else(|| {
             // This can happen only if our `sync_position` haven't created a new position for
@@ -196,7 +281,80 @@ pub fn player_movement_system(
                 );
                 (FrameNumber::new(0), &zero_vec)
             });
-        player.velocity.linvel = current_direction.normalize_or_zero() * player_movement_speed();
+
+        // Pads are triggered in the order of their ids, so that clients and the
+        // server push runners standing on several pads the same way.
+        player.jump_pad_boost.start_frame(frame_number);
+        let mut jump_pads = player
+            .sensors
+            .main
+            .contacting
+            .iter()
+            .filter_map(|(entity, _)| {
+                let level_object = level.level_object_by_entity(*entity)?;
+                Some((level_object.net_id, level_object.desc.jump_pad()?))
+            })
+            .collect::<Vec<_>>();
+        jump_pads.sort_by_key(|(net_id, _)| net_id.0);
+        jump_pads.dedup_by_key(|(net_id, _)| net_id.0);
+        for (net_id, jump_pad) in jump_pads {
+            if player
+                .jump_pad_boost
+                .trigger(frame_number, net_id, jump_pad)
+            {
+                log::trace!(
+                    "Player (entity: {:?}) has triggered jump pad {} (frame {})",
+                    player.entity,
+                    net_id.0,
+                    frame_number
+                );
+            }
+        }
+
+        player.velocity.linvel = current_direction.normalize_or_zero() * player_movement_speed()
+            + player.jump_pad_boost.velocity(frame_number);
+        tethered_bodies.insert(
+            player.entity,
+            TetheredBody {
+                position: *current_position,
+                velocity: player.velocity.linvel,
+                is_fixed: false,
+            },
+        );
+    }
+
+    // Tethers are solved pair by pair, in the order the server has paired the
+    // runners, so that clients arrive at the same velocities.
+    for (a, b) in tethers.pairs.iter() {
+        let Some((a_entity, b_entity)) = player_registry
+            .get_entity(*a)
+            .zip(player_registry.get_entity(*b))
+        else {
+            continue;
+        };
+        let (Some(a_body), Some(b_body)) = (
+            tethered_bodies.get(&a_entity).copied(),
+            tethered_bodies.get(&b_entity).copied(),
+        ) else {
+            continue;
+        };
+        let (a_velocity, b_velocity) = solve_tether_constraint(
+            a_body,
+            b_body,
+            tethers.max_distance,
+            1.0 / SIMULATIONS_PER_SECOND,
+        );
+        for (entity, body, velocity) in [
+            (a_entity, a_body, a_velocity),
+            (b_entity, b_body, b_velocity),
+        ] {
+            if body.is_fixed {
+                continue;
+            }
+            if let Ok(mut player) = players.get_mut(entity) {
+                player.item.velocity.linvel = velocity;
+            }
+        }
     }
 }
 
@@ -208,6 +366,8 @@ pub struct LevelObjectQuery<'w> {
     position: &'w Position,
     server_ghost: Option<&'w LevelObjectServerGhostChild>,
     frame_simulated: Option<&'w PlayerFrameSimulated>,
+    movement: Option<&'w LevelObjectMovement>,
+    collider: Option<&'w Collider>,
     _tag: With<LevelObjectTag>,
 }
 
@@ -262,7 +422,10 @@ pub fn isolate_client_mispredicted_world_system(
 
 pub fn load_object_positions_system(
     time: Res<SimulationTime>,
+    distant_objects_stepping: Option<Res<DistantObjectsStepping>>,
+    rerun_dirty_flags: Res<RerunDirtyFlags>,
     mut level_objects: Query<SpawnedQuery<LevelObjectQuery>>,
+    players: Query<(&Position, &Spawned), With<PlayerTag>>,
     #[cfg_attr(not(feature = "client"), allow(unused_variables, unused_mut))]
     mut server_ghost_level_objects: Query<&mut Transform, Without<LevelObjectTag>>,
 ) {
@@ -274,6 +437,17 @@ pub fn load_object_positions_system(
         time.player_frame
     );
 
+    let player_positions = if distant_objects_stepping.is_some() {
+        let frame_number = time.entity_simulation_frame(None);
+        players
+            .iter()
+            .filter(|(_, spawned)| spawned.is_spawned(frame_number))
+            .filter_map(|(position, _)| position.buffer.get(frame_number).copied())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
     for SpawnedQueryItem {
         item: mut level_object,
         player_frame_simulated,
@@ -298,8 +472,26 @@ pub fn load_object_positions_system(
                     level_object.position.buffer.len()
                 );
             });
-        body_position.translation.x = current_position.x;
-        body_position.translation.y = current_position.y;
+        let needs_sync = match (&distant_objects_stepping, level_object.movement) {
+            (Some(stepping), Some(movement)) => stepping.needs_sync(
+                level_object.entity,
+                frame_number,
+                *current_position,
+                level_object.collider.map_or(0.0, |collider| {
+                    collider.raw.compute_local_aabb().half_extents().norm()
+                }),
+                movement.max_speed(),
+                &player_positions,
+            ),
+            _ => true,
+        };
+        // Objects out of the player's reach are excluded from re-runs.
+        let is_clean = rerun_dirty_flags.is_clean(level_object.entity);
+        // Not touching `Transform` keeps the physics backend from updating the body.
+        if needs_sync && !is_clean {
+            body_position.translation.x = current_position.x;
+            body_position.translation.y = current_position.y;
+        }
 
         #[cfg(feature = "client")]
         if let Some(LevelObjectServerGhostChild(server_ghost)) = level_object.server_ghost {
@@ -330,6 +522,7 @@ pub struct SimulatedEntityQuery<'w> {
 pub fn sync_position_system(
     game_time: Res<GameTime>,
     time: Res<SimulationTime>,
+    rerun_dirty_flags: Res<RerunDirtyFlags>,
     mut simulated_entities: Query<SpawnedQuery<SimulatedEntityQuery>>,
 ) {
     #[cfg(feature = "profiler")]
@@ -346,6 +539,17 @@ pub fn sync_position_system(
     } in iter_spawned(simulated_entities.iter_mut(), &time)
     {
         let frame_number = time.entity_simulation_frame(player_frame_simulated);
+        // Nothing can move objects that are excluded from a re-run, so their
+        // positions recorded by the original run stay valid.
+        if rerun_dirty_flags.is_clean(simulated_entity.entity)
+            && simulated_entity
+                .position
+                .buffer
+                .get(frame_number + FrameNumber::new(1))
+                .is_some()
+        {
+            continue;
+        }
         let body_position = simulated_entity.transform.translation;
         let new_position = body_position.truncate();
         if let Some(predicted_position) = simulated_entity.predicted_position.as_mut() {
@@ -389,3 +593,126 @@ pub fn sync_position_system(
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::components::{LevelObjectMovementPoint, LevelObjectMovementType};
+
+    #[test]
+    fn test_distant_objects_need_sync() {
+        let stepping = DistantObjectsStepping {
+            max_player_speed: SIMULATIONS_PER_SECOND,
+            sync_interval: 4,
+        };
+        assert_eq!(stepping.player_reach(), 4.0);
+        let entity = Entity::from_raw(1);
+        let player_positions = [Vec2::new(100.0, 0.0), Vec2::new(0.0, 4.0)];
+
+        // Objects near any player are synced every frame.
+        assert!((0..8).all(|frame| stepping.needs_sync(
+            entity,
+            FrameNumber::new(frame),
+            Vec2::ZERO,
+            0.0,
+            0.0,
+            &player_positions
+        )));
+
+        // Distant ones are synced once per interval, with the entity as an offset.
+        let synced_frames: Vec<_> = (0..8)
+            .filter(|frame| {
+                stepping.needs_sync(
+                    entity,
+                    FrameNumber::new(*frame),
+                    Vec2::new(50.0, 50.0),
+                    0.0,
+                    0.0,
+                    &player_positions,
+                )
+            })
+            .collect();
+        assert_eq!(synced_frames, vec![3, 7]);
+
+        // Without players, all routed objects are distant.
+        assert!(!stepping.needs_sync(entity, FrameNumber::new(0), Vec2::ZERO, 0.0, 0.0, &[]));
+    }
+
+    #[test]
+    fn test_large_distant_objects_need_sync() {
+        let stepping = DistantObjectsStepping {
+            max_player_speed: SIMULATIONS_PER_SECOND,
+            sync_interval: 4,
+        };
+        let entity = Entity::from_raw(1);
+        // The object's center is far, but its edge is within the player's reach.
+        let player_positions = [Vec2::new(0.0, 20.0)];
+        let object_radius = Collider::cuboid(15.0, 15.0)
+            .raw
+            .compute_local_aabb()
+            .half_extents()
+            .norm();
+
+        assert!(!stepping.needs_sync(
+            entity,
+            FrameNumber::new(0),
+            Vec2::ZERO,
+            0.0,
+            0.0,
+            &player_positions
+        ));
+        assert!((0..8).all(|frame| stepping.needs_sync(
+            entity,
+            FrameNumber::new(frame),
+            Vec2::ZERO,
+            object_radius,
+            0.0,
+            &player_positions
+        )));
+    }
+
+    #[test]
+    fn test_fast_distant_objects_need_sync() {
+        let stepping = DistantObjectsStepping {
+            max_player_speed: SIMULATIONS_PER_SECOND,
+            sync_interval: 4,
+        };
+        let entity = Entity::from_raw(1);
+        let player_positions = [Vec2::ZERO];
+        // Covers the route in a second, and 8 units per sync interval.
+        let movement = LevelObjectMovement {
+            frame_started: FrameNumber::new(0),
+            init_vec: Vec2::ZERO,
+            period: FrameNumber::new(SIMULATIONS_PER_SECOND as u16),
+            points_progress: [0.0, 1.0]
+                .into_iter()
+                .map(|progress| LevelObjectMovementPoint {
+                    progress,
+                    wait: 0.0,
+                    position: Vec2::new(SIMULATIONS_PER_SECOND * 2.0 * progress, 0.0),
+                    entity: Entity::from_raw(2),
+                })
+                .collect(),
+            movement_type: LevelObjectMovementType::Linear,
+        };
+        assert_eq!(movement.max_speed(), SIMULATIONS_PER_SECOND * 2.0);
+
+        // A slow object at the same distance would still be out of reach.
+        assert!(!stepping.needs_sync(
+            entity,
+            FrameNumber::new(0),
+            Vec2::new(10.0, 0.0),
+            0.0,
+            0.0,
+            &player_positions
+        ));
+        assert!((0..8).all(|frame| stepping.needs_sync(
+            entity,
+            FrameNumber::new(frame),
+            Vec2::new(10.0, 0.0),
+            0.0,
+            movement.max_speed(),
+            &player_positions
+        )));
+    }
+}
@@ -0,0 +1,443 @@
+use crate::{
+    game::{
+        level::{CollisionLogic, LevelObject, LevelObjectDesc},
+        level_objects::{DeathZoneDesc, FinishZoneDesc, PlaneDesc, PlaneFormDesc},
+        polygon::is_point_in_polygon,
+    },
+    PLAYER_RADIUS,
+};
+use bevy::math::Vec2;
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+/// Bigger levels get coarser grids.
+const MAX_CELLS: usize = 128 * 128;
+const ORTHOGONAL_COST: u32 = 5;
+const DIAGONAL_COST: u32 = 7;
+const UNREACHABLE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cell {
+    Blocked,
+    Walkable,
+    Finish,
+}
+
+/// A grid over the level that marks where runners can stand without dying,
+/// storing distances to the closest finish for every cell.
+pub struct WalkabilityGraph {
+    origin: Vec2,
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    distances: Vec<u32>,
+}
+
+impl WalkabilityGraph {
+    /// Accepts level objects paired with their current positions, as routed
+    /// objects may be far from the positions they are described with.
+    pub fn build<'a>(objects: impl IntoIterator<Item = (&'a LevelObject, Vec2)>) -> Self {
+        let mut planes = Vec::new();
+        let mut cubes = Vec::new();
+        // Zones are rectangles that count as ground, like planes do.
+        let mut zones = Vec::new();
+        for (level_object, position) in objects {
+            match &level_object.desc {
+                LevelObjectDesc::Plane(plane) => {
+                    planes.push((plane, position, level_object.collision_logic));
+                }
+                LevelObjectDesc::Cube(cube) => cubes.push((position, cube.size)),
+                LevelObjectDesc::DeathZone(DeathZoneDesc { size, .. })
+                | LevelObjectDesc::FinishZone(FinishZoneDesc { size, .. }) => {
+                    zones.push((
+                        position,
+                        *size / 2.0,
+                        level_object.effective_collision_logic(),
+                    ));
+                }
+                LevelObjectDesc::RoutePoint(_)
+                | LevelObjectDesc::Annotation(_)
+                | LevelObjectDesc::CameraAnchor(_)
+                | LevelObjectDesc::Emitter(_) => {}
+            }
+        }
+        if planes.is_empty() && zones.is_empty() {
+            return Self {
+                origin: Vec2::ZERO,
+                cell_size: PLAYER_RADIUS,
+                width: 0,
+                height: 0,
+                cells: Vec::new(),
+                distances: Vec::new(),
+            };
+        }
+
+        let (min, max) = planes
+            .iter()
+            .map(|(plane, position, _)| plane_bounds(plane, *position))
+            .chain(
+                zones.iter().map(|(position, half_size, _)| {
+                    (*position - *half_size, *position + *half_size)
+                }),
+            )
+            .fold(
+                (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+                |(min, max), (object_min, object_max)| (min.min(object_min), max.max(object_max)),
+            );
+        let mut cell_size = PLAYER_RADIUS;
+        let grid_size = |cell_size: f32| {
+            let size = (max - min) / cell_size;
+            (size.x.ceil() as usize + 1, size.y.ceil() as usize + 1)
+        };
+        while grid_size(cell_size).0 * grid_size(cell_size).1 > MAX_CELLS {
+            cell_size *= 2.0;
+        }
+        let (width, height) = grid_size(cell_size);
+
+        // A runner dies if any part of it touches a deadly plane or hangs over
+        // the void, so a few points of its outline are checked as well.
+        let samples = [
+            Vec2::ZERO,
+            Vec2::X * PLAYER_RADIUS,
+            -Vec2::X * PLAYER_RADIUS,
+            Vec2::Y * PLAYER_RADIUS,
+            -Vec2::Y * PLAYER_RADIUS,
+        ];
+        let is_on_plane = |point: Vec2, accepts: fn(CollisionLogic) -> bool| {
+            planes.iter().any(|(plane, position, collision_logic)| {
+                accepts(*collision_logic) && plane_contains(plane, *position, point)
+            }) || zones.iter().any(|(position, half_size, collision_logic)| {
+                accepts(*collision_logic) && (point - *position).abs().cmple(*half_size).all()
+            })
+        };
+        let cells = (0..width * height)
+            .map(|i| {
+                let center = min + Vec2::new((i % width) as f32, (i / width) as f32) * cell_size;
+                let points = samples.map(|offset| center + offset);
+                let is_hazard = points.iter().any(|point| {
+                    is_on_plane(*point, |logic| logic == CollisionLogic::Death)
+                        || cubes.iter().any(|(position, half_size)| {
+                            (*point - *position).abs().max_element() <= *half_size
+                        })
+                });
+                if is_hazard {
+                    Cell::Blocked
+                } else if points
+                    .iter()
+                    .any(|point| is_on_plane(*point, |logic| logic == CollisionLogic::Finish))
+                {
+                    Cell::Finish
+                } else if points
+                    .iter()
+                    .all(|point| is_on_plane(*point, |logic| logic != CollisionLogic::Death))
+                {
+                    Cell::Walkable
+                } else {
+                    Cell::Blocked
+                }
+            })
+            .collect();
+
+        let mut graph = Self {
+            origin: min,
+            cell_size,
+            width,
+            height,
+            cells,
+            distances: vec![UNREACHABLE; width * height],
+        };
+        graph.calculate_distances();
+        graph
+    }
+
+    /// Returns a normalized direction that leads towards the closest finish,
+    /// aiming at the cell that is `lookahead` steps ahead on the path. Returns
+    /// `None` if a finish can't be reached from the position.
+    pub fn direction_to_finish(&self, from: Vec2, lookahead: usize) -> Option<Vec2> {
+        let mut cell = self.closest_reachable_cell(from)?;
+        for _ in 0..lookahead {
+            if self.cells[cell] == Cell::Finish {
+                break;
+            }
+            let Some(next) = self
+                .neighbours(cell)
+                .map(|(neighbour, _)| neighbour)
+                .min_by_key(|neighbour| self.distances[*neighbour])
+            else {
+                break;
+            };
+            if self.distances[next] >= self.distances[cell] {
+                break;
+            }
+            cell = next;
+        }
+        Some((self.cell_center(cell) - from).normalize_or_zero())
+    }
+
+    /// Follows the path to the closest finish, moving by `step` every frame,
+    /// and returns the number of frames it takes to reach it. Returns `None`
+    /// if the finish is unreachable or takes more than `max_frames` to reach.
+    pub fn frames_to_finish(
+        &self,
+        mut from: Vec2,
+        lookahead: usize,
+        step: f32,
+        max_frames: usize,
+    ) -> Option<usize> {
+        for frame in 0..max_frames {
+            if self
+                .cell_at(from)
+                .map_or(false, |cell| self.cells[cell] == Cell::Finish)
+            {
+                return Some(frame);
+            }
+            let direction = self.direction_to_finish(from, lookahead)?;
+            if direction == Vec2::ZERO {
+                return None;
+            }
+            from += direction * step;
+        }
+        None
+    }
+
+    fn calculate_distances(&mut self) {
+        let mut queue = BinaryHeap::new();
+        for (i, cell) in self.cells.iter().enumerate() {
+            if *cell == Cell::Finish {
+                self.distances[i] = 0;
+                queue.push(Reverse((0, i)));
+            }
+        }
+        while let Some(Reverse((distance, cell))) = queue.pop() {
+            if distance > self.distances[cell] {
+                continue;
+            }
+            let neighbours = self.neighbours(cell).collect::<Vec<_>>();
+            for (neighbour, cost) in neighbours {
+                let neighbour_distance = distance + cost;
+                if neighbour_distance < self.distances[neighbour] {
+                    self.distances[neighbour] = neighbour_distance;
+                    queue.push(Reverse((neighbour_distance, neighbour)));
+                }
+            }
+        }
+    }
+
+    /// Passable neighbours paired with the costs of moving to them. Diagonal
+    /// moves can't cut corners of blocked cells.
+    fn neighbours(&self, cell: usize) -> impl Iterator<Item = (usize, u32)> + '_ {
+        let (x, y) = ((cell % self.width) as isize, (cell / self.width) as isize);
+        let passable = move |x: isize, y: isize| {
+            if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                return None;
+            }
+            let i = y as usize * self.width + x as usize;
+            (self.cells[i] != Cell::Blocked).then_some(i)
+        };
+        [
+            (-1, 0),
+            (1, 0),
+            (0, -1),
+            (0, 1),
+            (-1, -1),
+            (-1, 1),
+            (1, -1),
+            (1, 1),
+        ]
+        .into_iter()
+        .filter_map(move |(dx, dy)| {
+            let neighbour = passable(x + dx, y + dy)?;
+            if dx != 0 && dy != 0 {
+                passable(x + dx, y)?;
+                passable(x, y + dy)?;
+                Some((neighbour, DIAGONAL_COST))
+            } else {
+                Some((neighbour, ORTHOGONAL_COST))
+            }
+        })
+    }
+
+    /// Runners may stand on the edge of a cell that is considered blocked, or
+    /// get pushed off the grid, so the neighbouring cells are looked at too.
+    fn closest_reachable_cell(&self, position: Vec2) -> Option<usize> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let grid_position = ((position - self.origin) / self.cell_size).round();
+        let x = (grid_position.x.max(0.0) as usize).min(self.width - 1) as isize;
+        let y = (grid_position.y.max(0.0) as usize).min(self.height - 1) as isize;
+        (-2..=2)
+            .flat_map(|dy| (-2..=2).map(move |dx| (x + dx, y + dy)))
+            .filter(|(x, y)| {
+                *x >= 0 && *y >= 0 && (*x as usize) < self.width && (*y as usize) < self.height
+            })
+            .map(|(x, y)| y as usize * self.width + x as usize)
+            .filter(|cell| self.distances[*cell] != UNREACHABLE)
+            .min_by(|a, b| {
+                self.cell_center(*a)
+                    .distance_squared(position)
+                    .total_cmp(&self.cell_center(*b).distance_squared(position))
+            })
+    }
+
+    fn cell_at(&self, position: Vec2) -> Option<usize> {
+        let grid_position = ((position - self.origin) / self.cell_size).round();
+        if grid_position.x < 0.0 || grid_position.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (grid_position.x as usize, grid_position.y as usize);
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    fn cell_center(&self, cell: usize) -> Vec2 {
+        self.origin
+            + Vec2::new((cell % self.width) as f32, (cell / self.width) as f32) * self.cell_size
+    }
+}
+
+fn plane_bounds(plane: &PlaneDesc, position: Vec2) -> (Vec2, Vec2) {
+    match &plane.form_desc {
+        PlaneFormDesc::Circle { radius } => (
+            position - Vec2::splat(*radius),
+            position + Vec2::splat(*radius),
+        ),
+        PlaneFormDesc::Rectangle { size } => (position - *size / 2.0, position + *size / 2.0),
+        PlaneFormDesc::Concave { points } => points.iter().fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(min, max), point| (min.min(position + *point), max.max(position + *point)),
+        ),
+    }
+}
+
+fn plane_contains(plane: &PlaneDesc, position: Vec2, point: Vec2) -> bool {
+    let point = point - position;
+    match &plane.form_desc {
+        PlaneFormDesc::Circle { radius } => point.length_squared() <= radius * radius,
+        PlaneFormDesc::Rectangle { size } => point.abs().cmple(*size / 2.0).all(),
+        PlaneFormDesc::Concave { points } => is_point_in_polygon(point, points),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::EntityNetId;
+
+    fn rectangle(net_id: u16, size: Vec2, collision_logic: CollisionLogic) -> LevelObject {
+        LevelObject {
+            net_id: EntityNetId(net_id),
+            label: String::new(),
+            desc: LevelObjectDesc::Plane(PlaneDesc {
+                position: Vec2::ZERO,
+                form_desc: PlaneFormDesc::Rectangle { size },
+                is_spawn_area: false,
+                appearance: Default::default(),
+                jump_pad: None,
+            }),
+            route: None,
+            collision_logic,
+        }
+    }
+
+    /// Follows the graph and returns the visited positions.
+    fn walk(graph: &WalkabilityGraph, mut position: Vec2) -> Vec<Vec2> {
+        let mut path = vec![position];
+        for _ in 0..1000 {
+            let Some(direction) = graph.direction_to_finish(position, 2) else {
+                break;
+            };
+            if direction == Vec2::ZERO {
+                break;
+            }
+            position += direction * 0.05;
+            path.push(position);
+        }
+        path
+    }
+
+    #[test]
+    fn test_direction_to_finish() {
+        let ground = rectangle(0, Vec2::new(10.0, 2.0), CollisionLogic::None);
+        let finish = rectangle(1, Vec2::new(2.0, 2.0), CollisionLogic::Finish);
+        let graph =
+            WalkabilityGraph::build([(&ground, Vec2::ZERO), (&finish, Vec2::new(6.0, 0.0))]);
+
+        let direction = graph.direction_to_finish(Vec2::new(-4.0, 0.0), 2).unwrap();
+        assert!(direction.x > 0.9, "{direction}");
+        assert!(walk(&graph, Vec2::new(-4.0, 0.0)).last().unwrap().x > 4.5);
+    }
+
+    #[test]
+    fn test_frames_to_finish() {
+        let ground = rectangle(0, Vec2::new(10.0, 2.0), CollisionLogic::None);
+        let finish = rectangle(1, Vec2::new(2.0, 2.0), CollisionLogic::Finish);
+        let graph =
+            WalkabilityGraph::build([(&ground, Vec2::ZERO), (&finish, Vec2::new(6.0, 0.0))]);
+
+        // The finish starts at 5.0, a runner touches it a radius earlier.
+        let frames = graph
+            .frames_to_finish(Vec2::new(-4.0, 0.0), 2, 0.05, 1000)
+            .unwrap();
+        assert!((160..=200).contains(&frames), "{frames}");
+        assert_eq!(
+            graph.frames_to_finish(Vec2::new(-4.0, 0.0), 2, 0.05, 100),
+            None
+        );
+        assert_eq!(
+            graph.frames_to_finish(Vec2::new(6.0, 0.0), 2, 0.05, 1000),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_paths_avoid_hazards() {
+        let ground = rectangle(0, Vec2::new(10.0, 6.0), CollisionLogic::None);
+        let death = rectangle(1, Vec2::new(2.0, 4.0), CollisionLogic::Death);
+        let finish = rectangle(2, Vec2::new(2.0, 6.0), CollisionLogic::Finish);
+        let death_position = Vec2::new(0.0, -1.0);
+        let graph = WalkabilityGraph::build([
+            (&ground, Vec2::ZERO),
+            (&death, death_position),
+            (&finish, Vec2::new(6.0, 0.0)),
+        ]);
+
+        let path = walk(&graph, Vec2::new(-4.0, -2.0));
+        for position in &path {
+            let distance_to_death = (*position - death_position).abs() - Vec2::new(1.0, 2.0);
+            assert!(distance_to_death.max_element() > 0.0, "{position}");
+        }
+        assert!(path.last().unwrap().x > 4.5);
+    }
+
+    #[test]
+    fn test_finish_zone() {
+        let ground = rectangle(0, Vec2::new(10.0, 2.0), CollisionLogic::None);
+        let finish_zone = LevelObject {
+            desc: LevelObjectDesc::FinishZone(FinishZoneDesc {
+                position: Vec2::ZERO,
+                size: Vec2::new(2.0, 2.0),
+            }),
+            ..rectangle(1, Vec2::ZERO, CollisionLogic::None)
+        };
+        let graph =
+            WalkabilityGraph::build([(&ground, Vec2::ZERO), (&finish_zone, Vec2::new(4.0, 0.0))]);
+
+        let direction = graph.direction_to_finish(Vec2::new(-4.0, 0.0), 2).unwrap();
+        assert!(direction.x > 0.9, "{direction}");
+    }
+
+    #[test]
+    fn test_unreachable_finish() {
+        let ground = rectangle(0, Vec2::new(4.0, 2.0), CollisionLogic::None);
+        let finish = rectangle(1, Vec2::new(2.0, 2.0), CollisionLogic::Finish);
+        let graph =
+            WalkabilityGraph::build([(&ground, Vec2::ZERO), (&finish, Vec2::new(6.0, 0.0))]);
+
+        assert_eq!(graph.direction_to_finish(Vec2::ZERO, 2), None);
+        assert_eq!(graph.frames_to_finish(Vec2::ZERO, 2, 0.05, 1000), None);
+        assert_eq!(
+            WalkabilityGraph::build([]).direction_to_finish(Vec2::ZERO, 2),
+            None
+        );
+    }
+}
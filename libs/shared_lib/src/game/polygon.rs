@@ -0,0 +1,539 @@
+//! Boolean operations between simple polygons and brush circles, used by the
+//! builder terrain brush to edit concave planes.
+
+use bevy::math::Vec2;
+use thiserror::Error;
+
+/// The number of segments brush circles are approximated with.
+pub const BRUSH_CIRCLE_SEGMENTS: usize = 24;
+/// Points that are closer than this are merged.
+const POINT_EPSILON: f32 = 1e-4;
+/// Intersections this close to segment ends are considered degenerate.
+const INTERSECTION_EPSILON: f32 = 1e-5;
+/// Degenerate cases are resolved by rotating the brush circle and retrying.
+const DEGENERATE_RETRIES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushOperation {
+    Add,
+    Subtract,
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum BrushError {
+    #[error("the brush doesn't touch the polygon")]
+    Disjoint,
+    #[error("the polygon would get a hole")]
+    Hole,
+    #[error("the polygon would be split into several ones")]
+    Split,
+    #[error("nothing would be left of the polygon")]
+    Empty,
+    #[error("the polygon or the brush are degenerate")]
+    Degenerate,
+}
+
+/// Applies a brush circle to a simple polygon, keeping the result a single
+/// simple polygon. The returned points are in the counter-clockwise order.
+pub fn apply_brush(
+    points: &[Vec2],
+    center: Vec2,
+    radius: f32,
+    operation: BrushOperation,
+) -> Result<Vec<Vec2>, BrushError> {
+    let subject = normalize_polygon(points);
+    if subject.len() < 3 || radius <= POINT_EPSILON {
+        return Err(BrushError::Degenerate);
+    }
+
+    for retry in 0..DEGENERATE_RETRIES {
+        let rotation = retry as f32 * 0.37 * std::f32::consts::TAU / BRUSH_CIRCLE_SEGMENTS as f32;
+        let brush = circle_polygon(center, radius, BRUSH_CIRCLE_SEGMENTS, rotation);
+        match clip(&subject, &brush, operation) {
+            Err(BrushError::Degenerate) => continue,
+            Ok(result) if !is_simple_polygon(&result) => continue,
+            result => return result,
+        }
+    }
+    Err(BrushError::Degenerate)
+}
+
+pub fn circle_polygon(center: Vec2, radius: f32, segments: usize, rotation: f32) -> Vec<Vec2> {
+    (0..segments)
+        .map(|i| {
+            let angle = rotation + i as f32 * std::f32::consts::TAU / segments as f32;
+            center + Vec2::new(angle.cos(), angle.sin()) * radius
+        })
+        .collect()
+}
+
+/// Positive for counter-clockwise polygons.
+pub fn signed_area(points: &[Vec2]) -> f32 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum::<f32>()
+        / 2.0
+}
+
+pub fn is_point_in_polygon(point: Vec2, points: &[Vec2]) -> bool {
+    let mut is_inside = false;
+    for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < (b.x - a.x) * (point.y - a.y) / (b.y - a.y) + a.x
+        {
+            is_inside = !is_inside;
+        }
+    }
+    is_inside
+}
+
+/// Checks that no edges of a polygon intersect, except for the adjacent ones
+/// sharing their ends.
+pub fn is_simple_polygon(points: &[Vec2]) -> bool {
+    let len = points.len();
+    if len < 3 {
+        return false;
+    }
+    for i in 0..len {
+        let (a1, a2) = (points[i], points[(i + 1) % len]);
+        if a1.distance_squared(a2) < POINT_EPSILON * POINT_EPSILON {
+            return false;
+        }
+        for j in i + 1..len {
+            let is_adjacent = j == i + 1 || (i == 0 && j == len - 1);
+            if is_adjacent {
+                continue;
+            }
+            let (b1, b2) = (points[j], points[(j + 1) % len]);
+            if segments_touch(a1, a2, b1, b2) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Removes duplicate points and makes the polygon counter-clockwise.
+fn normalize_polygon(points: &[Vec2]) -> Vec<Vec2> {
+    let mut normalized: Vec<Vec2> = Vec::with_capacity(points.len());
+    for point in points {
+        if normalized
+            .last()
+            .map_or(true, |last| last.distance(*point) > POINT_EPSILON)
+        {
+            normalized.push(*point);
+        }
+    }
+    while normalized.len() > 1
+        && normalized[0].distance(*normalized.last().unwrap()) <= POINT_EPSILON
+    {
+        normalized.pop();
+    }
+    if signed_area(&normalized) < 0.0 {
+        normalized.reverse();
+    }
+    normalized
+}
+
+struct Node {
+    point: Vec2,
+    intersection: Option<Intersection>,
+}
+
+#[derive(Clone, Copy)]
+struct Intersection {
+    /// Index of the same intersection in the other polygon's list.
+    neighbor: usize,
+    /// Whether the traversal goes forward after reaching this node.
+    forward: bool,
+    is_visited: bool,
+}
+
+struct EdgeIntersection {
+    subject_edge: usize,
+    subject_alpha: f32,
+    brush_edge: usize,
+    brush_alpha: f32,
+    point: Vec2,
+}
+
+/// A variation of the Greiner-Hormann algorithm. Both polygons are expected to
+/// be counter-clockwise.
+fn clip(
+    subject: &[Vec2],
+    brush: &[Vec2],
+    operation: BrushOperation,
+) -> Result<Vec<Vec2>, BrushError> {
+    let intersections = find_intersections(subject, brush)?;
+
+    if intersections.is_empty() {
+        let is_subject_inside = is_point_in_polygon(subject[0], brush);
+        let is_brush_inside = is_point_in_polygon(brush[0], subject);
+        return match (operation, is_subject_inside, is_brush_inside) {
+            (BrushOperation::Add, true, _) => Ok(brush.to_vec()),
+            (BrushOperation::Add, false, true) => Ok(subject.to_vec()),
+            (BrushOperation::Add, false, false) => Err(BrushError::Disjoint),
+            (BrushOperation::Subtract, true, _) => Err(BrushError::Empty),
+            (BrushOperation::Subtract, false, true) => Err(BrushError::Hole),
+            (BrushOperation::Subtract, false, false) => Ok(subject.to_vec()),
+        };
+    }
+
+    // For both operations, we walk the subject outside of the brush. The brush is
+    // walked forward outside of the subject when adding, and backward inside of
+    // it when subtracting.
+    let subject_starts_inside = is_point_in_polygon(subject[0], brush);
+    let brush_starts_inside = is_point_in_polygon(brush[0], subject);
+    let (mut subject_nodes, subject_indices) = build_nodes(
+        subject,
+        &intersections,
+        |intersection| (intersection.subject_edge, intersection.subject_alpha),
+        subject_starts_inside,
+    );
+    let (mut brush_nodes, brush_indices) = build_nodes(
+        brush,
+        &intersections,
+        |intersection| (intersection.brush_edge, intersection.brush_alpha),
+        match operation {
+            BrushOperation::Add => brush_starts_inside,
+            BrushOperation::Subtract => !brush_starts_inside,
+        },
+    );
+    for (subject_index, brush_index) in subject_indices.iter().zip(brush_indices.iter()) {
+        subject_nodes[*subject_index]
+            .intersection
+            .as_mut()
+            .unwrap()
+            .neighbor = *brush_index;
+        brush_nodes[*brush_index]
+            .intersection
+            .as_mut()
+            .unwrap()
+            .neighbor = *subject_index;
+    }
+
+    let mut loops = Vec::new();
+    // Starting only from the nodes followed by forward traversal keeps the
+    // resulting loops counter-clockwise, unless they are holes.
+    while let Some(start) = subject_indices.iter().copied().find(|index| {
+        let intersection = subject_nodes[*index].intersection.as_ref().unwrap();
+        intersection.forward && !intersection.is_visited
+    }) {
+        let mut result = Vec::new();
+        let mut is_subject = true;
+        let mut index = start;
+        loop {
+            let nodes = if is_subject {
+                &mut subject_nodes
+            } else {
+                &mut brush_nodes
+            };
+            let intersection = nodes[index].intersection.as_mut().unwrap();
+            if intersection.is_visited {
+                break;
+            }
+            intersection.is_visited = true;
+            let forward = intersection.forward;
+            result.push(nodes[index].point);
+
+            let len = nodes.len();
+            loop {
+                index = if forward {
+                    (index + 1) % len
+                } else {
+                    (index + len - 1) % len
+                };
+                if nodes[index].intersection.is_some() {
+                    break;
+                }
+                result.push(nodes[index].point);
+            }
+            let intersection = nodes[index].intersection.as_mut().unwrap();
+            intersection.is_visited = true;
+            index = intersection.neighbor;
+            is_subject = !is_subject;
+        }
+        let result = normalize_loop(result);
+        if result.len() >= 3 && signed_area(&result).abs() > POINT_EPSILON {
+            loops.push(result);
+        }
+    }
+
+    match loops.len() {
+        0 => Err(BrushError::Empty),
+        1 if signed_area(&loops[0]) > 0.0 => Ok(loops.pop().unwrap()),
+        1 => Err(BrushError::Degenerate),
+        _ if loops.iter().any(|points| signed_area(points) < 0.0) => Err(BrushError::Hole),
+        _ => Err(BrushError::Split),
+    }
+}
+
+fn find_intersections(
+    subject: &[Vec2],
+    brush: &[Vec2],
+) -> Result<Vec<EdgeIntersection>, BrushError> {
+    let mut intersections = Vec::new();
+    for subject_edge in 0..subject.len() {
+        let p = subject[subject_edge];
+        let r = subject[(subject_edge + 1) % subject.len()] - p;
+        for brush_edge in 0..brush.len() {
+            let q = brush[brush_edge];
+            let s = brush[(brush_edge + 1) % brush.len()] - q;
+            let denominator = r.perp_dot(s);
+            let qp = q - p;
+            if denominator.abs() <= f32::EPSILON * r.length() * s.length() {
+                // Parallel edges are only a problem if they are collinear and overlap.
+                if qp.perp_dot(r).abs() <= POINT_EPSILON * r.length()
+                    && segments_touch(p, p + r, q, q + s)
+                {
+                    return Err(BrushError::Degenerate);
+                }
+                continue;
+            }
+            let subject_alpha = qp.perp_dot(s) / denominator;
+            let brush_alpha = qp.perp_dot(r) / denominator;
+            let range = -INTERSECTION_EPSILON..=1.0 + INTERSECTION_EPSILON;
+            if !range.contains(&subject_alpha) || !range.contains(&brush_alpha) {
+                continue;
+            }
+            let strict_range = INTERSECTION_EPSILON..=1.0 - INTERSECTION_EPSILON;
+            if !strict_range.contains(&subject_alpha) || !strict_range.contains(&brush_alpha) {
+                return Err(BrushError::Degenerate);
+            }
+            intersections.push(EdgeIntersection {
+                subject_edge,
+                subject_alpha,
+                brush_edge,
+                brush_alpha,
+                point: p + r * subject_alpha,
+            });
+        }
+    }
+    Ok(intersections)
+}
+
+/// Inserts intersections into the list of polygon points. Returns the nodes and
+/// the node indices of the intersections (in the order they are passed).
+fn build_nodes(
+    points: &[Vec2],
+    intersections: &[EdgeIntersection],
+    edge_alpha: impl Fn(&EdgeIntersection) -> (usize, f32),
+    starts_inside: bool,
+) -> (Vec<Node>, Vec<usize>) {
+    let mut sorted = (0..intersections.len()).collect::<Vec<_>>();
+    sorted.sort_by(|a, b| {
+        let (a_edge, a_alpha) = edge_alpha(&intersections[*a]);
+        let (b_edge, b_alpha) = edge_alpha(&intersections[*b]);
+        a_edge.cmp(&b_edge).then(a_alpha.total_cmp(&b_alpha))
+    });
+
+    let mut nodes = Vec::with_capacity(points.len() + intersections.len());
+    let mut indices = vec![0; intersections.len()];
+    let mut sorted = sorted.into_iter().peekable();
+    // Crossing the other polygon's border flips the direction.
+    let mut forward = starts_inside;
+    for (i, point) in points.iter().enumerate() {
+        nodes.push(Node {
+            point: *point,
+            intersection: None,
+        });
+        while let Some(intersection_index) =
+            sorted.next_if(|index| edge_alpha(&intersections[*index]).0 == i)
+        {
+            indices[intersection_index] = nodes.len();
+            nodes.push(Node {
+                point: intersections[intersection_index].point,
+                intersection: Some(Intersection {
+                    neighbor: 0,
+                    forward,
+                    is_visited: false,
+                }),
+            });
+            forward = !forward;
+        }
+    }
+    (nodes, indices)
+}
+
+fn normalize_loop(points: Vec<Vec2>) -> Vec<Vec2> {
+    let mut normalized: Vec<Vec2> = Vec::with_capacity(points.len());
+    for point in points {
+        if normalized
+            .last()
+            .map_or(true, |last| last.distance(point) > POINT_EPSILON)
+        {
+            normalized.push(point);
+        }
+    }
+    while normalized.len() > 1
+        && normalized[0].distance(*normalized.last().unwrap()) <= POINT_EPSILON
+    {
+        normalized.pop();
+    }
+    normalized
+}
+
+fn segments_touch(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool {
+    let orientation = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+    let on_segment = |p: Vec2, q: Vec2, r: Vec2| {
+        r.x >= p.x.min(q.x) && r.x <= p.x.max(q.x) && r.y >= p.y.min(q.y) && r.y <= p.y.max(q.y)
+    };
+
+    let d1 = orientation(b1, b2, a1);
+    let d2 = orientation(b1, b2, a2);
+    let d3 = orientation(a1, a2, b1);
+    let d4 = orientation(a1, a2, b2);
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(b1, b2, a1))
+        || (d2 == 0.0 && on_segment(b1, b2, a2))
+        || (d3 == 0.0 && on_segment(a1, a2, b1))
+        || (d4 == 0.0 && on_segment(a1, a2, b2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(size: f32) -> Vec<Vec2> {
+        vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(size, 0.0),
+            Vec2::new(size, size),
+            Vec2::new(0.0, size),
+        ]
+    }
+
+    fn brush_area(radius: f32) -> f32 {
+        signed_area(&circle_polygon(
+            Vec2::ZERO,
+            radius,
+            BRUSH_CIRCLE_SEGMENTS,
+            0.0,
+        ))
+    }
+
+    #[test]
+    fn test_add() {
+        // A quarter of the brush overlaps the square.
+        let result = apply_brush(
+            &square(10.0),
+            Vec2::new(10.0, 10.0),
+            2.0,
+            BrushOperation::Add,
+        )
+        .unwrap();
+        assert!(is_simple_polygon(&result));
+        let expected_area = 100.0 + brush_area(2.0) * 0.75;
+        assert!((signed_area(&result) - expected_area).abs() < 0.1);
+
+        // Clockwise input is accepted as well.
+        let mut clockwise = square(10.0);
+        clockwise.reverse();
+        let result =
+            apply_brush(&clockwise, Vec2::new(10.0, 10.0), 2.0, BrushOperation::Add).unwrap();
+        assert!((signed_area(&result) - expected_area).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_subtract() {
+        let result = apply_brush(
+            &square(10.0),
+            Vec2::new(10.0, 10.0),
+            2.0,
+            BrushOperation::Subtract,
+        )
+        .unwrap();
+        assert!(is_simple_polygon(&result));
+        let expected_area = 100.0 - brush_area(2.0) * 0.25;
+        assert!((signed_area(&result) - expected_area).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_brush_without_intersections() {
+        let square = square(10.0);
+        assert_eq!(
+            apply_brush(&square, Vec2::new(5.0, 5.0), 1.0, BrushOperation::Add),
+            Ok(square.clone())
+        );
+        assert_eq!(
+            apply_brush(&square, Vec2::new(5.0, 5.0), 1.0, BrushOperation::Subtract),
+            Err(BrushError::Hole)
+        );
+        assert_eq!(
+            apply_brush(&square, Vec2::new(20.0, 5.0), 1.0, BrushOperation::Add),
+            Err(BrushError::Disjoint)
+        );
+        assert_eq!(
+            apply_brush(&square, Vec2::new(20.0, 5.0), 1.0, BrushOperation::Subtract),
+            Ok(square.clone())
+        );
+        assert_eq!(
+            apply_brush(&square, Vec2::new(5.0, 5.0), 20.0, BrushOperation::Subtract),
+            Err(BrushError::Empty)
+        );
+        let result = apply_brush(&square, Vec2::new(5.0, 5.0), 20.0, BrushOperation::Add).unwrap();
+        assert!((signed_area(&result) - brush_area(20.0)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_invalid_results() {
+        // Cutting a thin strip in the middle.
+        let strip = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(10.0, 0.0),
+            Vec2::new(10.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert_eq!(
+            apply_brush(&strip, Vec2::new(5.0, 0.5), 2.0, BrushOperation::Subtract),
+            Err(BrushError::Split)
+        );
+
+        // Closing the gap of a U shape.
+        let u_shape = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(10.0, 0.0),
+            Vec2::new(10.0, 10.0),
+            Vec2::new(7.0, 10.0),
+            Vec2::new(7.0, 3.0),
+            Vec2::new(3.0, 3.0),
+            Vec2::new(3.0, 10.0),
+            Vec2::new(0.0, 10.0),
+        ];
+        assert_eq!(
+            apply_brush(&u_shape, Vec2::new(5.0, 10.0), 2.5, BrushOperation::Add),
+            Err(BrushError::Hole)
+        );
+    }
+
+    #[test]
+    fn test_repeated_stamps() {
+        // Stamping at the same place produces coincident points, which have to be
+        // resolved by rotating the brush.
+        let mut points = square(10.0);
+        for _ in 0..3 {
+            points = apply_brush(&points, Vec2::new(10.0, 5.0), 2.0, BrushOperation::Add).unwrap();
+            assert!(is_simple_polygon(&points));
+        }
+        let expected_area = 100.0 + brush_area(2.0) * 0.5;
+        assert!((signed_area(&points) - expected_area).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_is_simple_polygon() {
+        assert!(is_simple_polygon(&square(1.0)));
+        let bow_tie = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert!(!is_simple_polygon(&bow_tie));
+    }
+}
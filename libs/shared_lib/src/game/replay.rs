@@ -0,0 +1,533 @@
+//! Recording sessions simulated by the server, and replaying them to check
+//! that the simulation still arrives at the same state.
+//!
+//! A recording contains everything the simulation consumes: the commands
+//! drained at every frame (including the ones that loaded the level), the
+//! directions players moved in, and tether changes. Server-only systems (such
+//! as processing deaths and finishes) aren't replayed, but their outcomes are
+//! recorded as commands, so the replayed simulation doesn't need them.
+
+use crate::{
+    framebuffer::FrameNumber,
+    game::{
+        command_log::{CommandLog, RecordedCommand},
+        components::{PlayerDirection, Spawned},
+        determinism::{DeterminismGuard, SimulationStage},
+        level_objects::ColliderSimplification,
+        tether::Tethers,
+    },
+    messages::PlayerNetId,
+    player::{PlayerDirectionUpdate, PlayerUpdates},
+    registry::EntityRegistry,
+    AppState, GameSessionState, GameTime, LevelObjectsToSpawnToLoad, MuddleSharedPlugin,
+    SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT,
+};
+use bevy::{
+    app::App,
+    core::CorePlugin,
+    ecs::{
+        entity::Entity,
+        schedule::{IntoSystemDescriptor, ShouldRun, SystemStage},
+        system::{IntoSystem, Query, Res, ResMut, Resource},
+        world::{Mut, World},
+    },
+    log,
+    math::Vec2,
+    time::TimePlugin,
+};
+use iyes_loopless::state::CurrentState;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Is bumped on every incompatible change of the format, recordings of other
+/// versions can't be replayed.
+pub const SESSION_RECORDING_VERSION: u32 = 1;
+/// Later stages run server-only systems, which aren't replayed, so hashes are
+/// compared as of the end of this stage.
+pub const REPLAY_HASHED_STAGE: SimulationStage = SimulationStage::PostPhysics;
+/// Recording stops after this many frames (10 minutes), to keep recordings
+/// of idle servers from eating up memory.
+pub const SESSION_RECORDING_FRAMES_LIMIT: usize = crate::SIMULATIONS_PER_SECOND as usize * 600;
+/// Loading levels with concave planes involves calculating their collider
+/// shapes in the background, which may take a while.
+const MAX_LOADING_UPDATES: usize = 600;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SessionRecording {
+    pub version: u32,
+    pub collider_simplification: ColliderSimplification,
+    /// The commands that were drained before the first simulated frame, i.e.
+    /// loading the level.
+    pub initial_commands: Vec<RecordedCommand>,
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl SessionRecording {
+    pub fn new(collider_simplification: ColliderSimplification) -> Self {
+        Self {
+            version: SESSION_RECORDING_VERSION,
+            collider_simplification,
+            initial_commands: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordedFrame {
+    pub frame_number: FrameNumber,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<RecordedCommand>,
+    /// Directions of the spawned players, sorted by their ids. Players without
+    /// an input at this frame are omitted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<(PlayerNetId, Vec2)>,
+    /// Is set only at the frames when tethers changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tethers: Option<Tethers>,
+    /// The hash of `REPLAY_HASHED_STAGE`.
+    pub hash: Option<u64>,
+}
+
+/// Records the simulation if inserted, is meant to be used on the server with
+/// `DeterminismGuard` and `CommandLog` enabled.
+#[derive(Resource)]
+pub struct SessionRecorder {
+    recording: SessionRecording,
+    last_tethers: Tethers,
+}
+
+impl SessionRecorder {
+    pub fn new(collider_simplification: ColliderSimplification) -> Self {
+        Self {
+            recording: SessionRecording::new(collider_simplification),
+            last_tethers: Tethers::default(),
+        }
+    }
+
+    pub fn recording(&self) -> &SessionRecording {
+        &self.recording
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.recording.frames.len() >= SESSION_RECORDING_FRAMES_LIMIT
+    }
+
+    /// Moves the commands from the log to the frames they were drained at.
+    /// Commands get to the log only after all the frames of a game tick are
+    /// simulated, so this has to be called once more before saving the
+    /// recording.
+    pub fn collect_commands(&mut self, command_log: &mut CommandLog) {
+        let is_full = self.is_full();
+        for entry in command_log.drain() {
+            if self.recording.frames.is_empty() {
+                self.recording.initial_commands.push(entry.command);
+                continue;
+            }
+            match self
+                .recording
+                .frames
+                .iter_mut()
+                .rev()
+                .find(|frame| frame.frame_number == entry.drained_at)
+            {
+                Some(frame) => frame.commands.push(entry.command),
+                // The frames after the limit aren't recorded.
+                None if is_full => {}
+                None => log::warn!(
+                    "Can't record a command drained outside of the recorded frames: {:?}",
+                    entry
+                ),
+            }
+        }
+    }
+}
+
+/// Runs at the end of every simulated frame, after the hashes of
+/// `REPLAY_HASHED_STAGE` are recorded.
+pub fn record_session_frame_system(
+    mut recorder: ResMut<SessionRecorder>,
+    mut command_log: ResMut<CommandLog>,
+    time: Res<SimulationTime>,
+    guard: Res<DeterminismGuard>,
+    tethers: Res<Tethers>,
+    player_registry: Res<EntityRegistry<PlayerNetId>>,
+    players: Query<(Entity, &PlayerDirection, &Spawned)>,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+    // The commands collected so far were drained at the previous frames.
+    recorder.collect_commands(&mut command_log);
+    if recorder.is_full() {
+        return;
+    }
+
+    let frame_number = time.server_frame;
+    let mut inputs = players
+        .iter()
+        .filter(|(_, _, spawned)| spawned.is_spawned(frame_number))
+        .filter_map(|(entity, direction, _)| {
+            let direction = (*direction.buffer.get(frame_number)?)?;
+            Some((player_registry.get_id(entity)?, direction))
+        })
+        .collect::<Vec<_>>();
+    inputs.sort_by_key(|(net_id, _)| net_id.0);
+
+    let tethers = if *tethers != recorder.last_tethers {
+        recorder.last_tethers = tethers.clone();
+        Some(tethers.clone())
+    } else {
+        None
+    };
+
+    recorder.recording.frames.push(RecordedFrame {
+        frame_number,
+        commands: Vec::new(),
+        inputs,
+        tethers,
+        hash: guard.stage_hash(frame_number, REPLAY_HASHED_STAGE),
+    });
+    if recorder.is_full() {
+        log::warn!(
+            "Session recording has reached the limit of {} frames, stopping",
+            SESSION_RECORDING_FRAMES_LIMIT
+        );
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    #[error(
+        "unsupported recording version {0} (expected {})",
+        SESSION_RECORDING_VERSION
+    )]
+    UnsupportedVersion(u32),
+    #[error("the level hasn't loaded in {} updates", MAX_LOADING_UPDATES)]
+    LoadingTimeout,
+    /// The replayed simulation arrived at a different state. Frame numbers of
+    /// long sessions wrap, so the index of the frame in the recording is
+    /// reported as well.
+    #[error("state hash mismatch at frame {frame_number} (#{frame_index}): expected {expected}, got {actual:?}")]
+    Mismatch {
+        frame_number: FrameNumber,
+        frame_index: usize,
+        expected: u64,
+        actual: Option<u64>,
+    },
+}
+
+#[derive(Resource)]
+struct SessionReplay {
+    recording: SessionRecording,
+    /// The index of the frame which commands are queued next.
+    next_commands: usize,
+    /// The index of the frame which inputs are queued next.
+    next_inputs: usize,
+    /// The index of the frame which hash is verified next.
+    next_verified: usize,
+    error: Option<ReplayError>,
+}
+
+impl SessionReplay {
+    fn is_finished(&self) -> bool {
+        self.error.is_some() || self.next_verified == self.recording.frames.len()
+    }
+}
+
+/// Replays the recording with a headless simulation and compares its state
+/// hashes with the recorded ones. Returns the number of verified frames.
+pub fn replay_session(recording: SessionRecording) -> Result<usize, ReplayError> {
+    if recording.version != SESSION_RECORDING_VERSION {
+        return Err(ReplayError::UnsupportedVersion(recording.version));
+    }
+
+    let mut app = build_replay_app(recording);
+    let mut loading_updates = 0;
+    while !app.world.resource::<SessionReplay>().is_finished() {
+        if app.world.resource::<CurrentState<GameSessionState>>().0 == GameSessionState::Loading {
+            loading_updates += 1;
+            if loading_updates > MAX_LOADING_UPDATES {
+                return Err(ReplayError::LoadingTimeout);
+            }
+        }
+        app.update();
+    }
+
+    let replay = app.world.remove_resource::<SessionReplay>().unwrap();
+    match replay.error {
+        Some(err) => Err(err),
+        None => Ok(replay.next_verified),
+    }
+}
+
+fn build_replay_app(recording: SessionRecording) -> App {
+    let mut app = App::new();
+    app.add_plugin(CorePlugin::default())
+        .add_plugin(TimePlugin::default())
+        .add_plugin(MuddleSharedPlugin::new(
+            IntoSystem::into_system(|| ShouldRun::Yes),
+            SystemStage::single_threaded()
+                .with_system(queue_replayed_inputs_system)
+                .with_system(queue_replayed_commands_system),
+            SystemStage::single_threaded()
+                .with_system(verify_replayed_frame_system)
+                .with_system(
+                    queue_next_replayed_commands_system.after(verify_replayed_frame_system),
+                ),
+            SystemStage::single_threaded(),
+            SystemStage::single_threaded(),
+            None,
+        ))
+        // As on the server, there's no main menu to go through.
+        .insert_resource(CurrentState(AppState::Playing))
+        .insert_resource(recording.collider_simplification);
+    app.world.resource_mut::<DeterminismGuard>().enabled = true;
+
+    let objects_to_load = recording
+        .initial_commands
+        .iter()
+        .filter(|command| matches!(command, RecordedCommand::UpdateLevelObject(_)))
+        .count();
+    app.insert_resource(LevelObjectsToSpawnToLoad(objects_to_load));
+    for command in recording.initial_commands.clone() {
+        command.push_to_queue(&mut app.world);
+    }
+    app.insert_resource(SessionReplay {
+        recording,
+        next_commands: 0,
+        next_inputs: 0,
+        next_verified: 0,
+        error: None,
+    });
+    app
+}
+
+/// Inputs are read once per game tick, before simulating the frames that
+/// the tick advances to.
+fn queue_replayed_inputs_system(
+    mut replay: ResMut<SessionReplay>,
+    game_time: Res<GameTime>,
+    time: Res<SimulationTime>,
+    mut player_updates: ResMut<PlayerUpdates>,
+) {
+    let replay = &mut *replay;
+    while let Some(frame) = replay.recording.frames.get(replay.next_inputs) {
+        if frame.frame_number < time.server_frame || frame.frame_number > game_time.frame_number {
+            break;
+        }
+        for (net_id, direction) in &frame.inputs {
+            player_updates
+                .get_direction_mut(*net_id, frame.frame_number, COMPONENT_FRAMEBUFFER_LIMIT)
+                .insert(
+                    frame.frame_number,
+                    Some(PlayerDirectionUpdate {
+                        direction: *direction,
+                        is_processed_client_input: None,
+                    }),
+                );
+        }
+        replay.next_inputs += 1;
+    }
+}
+
+/// Queues the commands of the first frame of a game tick. Level objects get
+/// updated while loading outside of the simulation, so nothing's queued until
+/// the level is loaded.
+fn queue_replayed_commands_system(world: &mut World) {
+    if world.resource::<CurrentState<GameSessionState>>().0 != GameSessionState::Playing {
+        return;
+    }
+    let frame_number = world.resource::<SimulationTime>().server_frame;
+    queue_replayed_commands(world, frame_number);
+}
+
+/// Queues the commands of the following frame of a game tick.
+fn queue_next_replayed_commands_system(world: &mut World) {
+    let frame_number = world.resource::<SimulationTime>().server_frame + FrameNumber::new(1);
+    queue_replayed_commands(world, frame_number);
+}
+
+fn queue_replayed_commands(world: &mut World, frame_number: FrameNumber) {
+    world.resource_scope(|world, mut replay: Mut<SessionReplay>| {
+        let Some(frame) = replay.recording.frames.get(replay.next_commands) else {
+            return;
+        };
+        if frame.frame_number != frame_number {
+            return;
+        }
+
+        if let Some(tethers) = frame.tethers.clone() {
+            world.insert_resource(tethers);
+        }
+        for command in frame.commands.clone() {
+            // The server follows up on role switches by spawning or despawning
+            // players, which is already recorded.
+            if !matches!(command, RecordedCommand::SwitchPlayerRole(_)) {
+                command.push_to_queue(world);
+            }
+        }
+        replay.next_commands += 1;
+    });
+}
+
+fn verify_replayed_frame_system(
+    mut replay: ResMut<SessionReplay>,
+    time: Res<SimulationTime>,
+    guard: Res<DeterminismGuard>,
+) {
+    let replay = &mut *replay;
+    if replay.error.is_some() {
+        return;
+    }
+    let Some(frame) = replay.recording.frames.get(replay.next_verified) else {
+        return;
+    };
+    if frame.frame_number != time.server_frame {
+        return;
+    }
+
+    let actual = guard.stage_hash(frame.frame_number, REPLAY_HASHED_STAGE);
+    if let Some(expected) = frame.hash.filter(|expected| Some(*expected) != actual) {
+        replay.error = Some(ReplayError::Mismatch {
+            frame_number: frame.frame_number,
+            frame_index: replay.next_verified,
+            expected,
+            actual,
+        });
+    }
+    replay.next_verified += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        game::{
+            commands::{SpawnPlayer, UpdateLevelObject},
+            level::{CollisionLogic, LevelObject, LevelObjectDesc},
+            level_objects::CubeDesc,
+        },
+        messages::EntityNetId,
+    };
+    use std::path::Path;
+
+    fn recorded_frame(frame_number: u16, inputs: Vec<(PlayerNetId, Vec2)>) -> RecordedFrame {
+        RecordedFrame {
+            frame_number: FrameNumber::new(frame_number),
+            commands: Vec::new(),
+            inputs,
+            tethers: None,
+            hash: None,
+        }
+    }
+
+    /// A player runs into a cube and slides along it.
+    fn synthetic_recording() -> SessionRecording {
+        let mut recording = SessionRecording::new(ColliderSimplification::default());
+        recording
+            .initial_commands
+            .push(RecordedCommand::UpdateLevelObject(UpdateLevelObject {
+                object: LevelObject {
+                    net_id: EntityNetId(0),
+                    label: String::new(),
+                    desc: LevelObjectDesc::Cube(CubeDesc {
+                        size: 0.5,
+                        position: Vec2::new(2.0, 0.0),
+                        appearance: Default::default(),
+                    }),
+                    route: None,
+                    collision_logic: CollisionLogic::None,
+                },
+                frame_number: FrameNumber::new(0),
+            }));
+        let player = PlayerNetId(1);
+        for frame_number in 0..120 {
+            let direction = if frame_number < 60 {
+                Vec2::X
+            } else {
+                Vec2::new(1.0, 1.0).normalize()
+            };
+            recording
+                .frames
+                .push(recorded_frame(frame_number, vec![(player, direction)]));
+        }
+        recording.frames[0]
+            .commands
+            .push(RecordedCommand::SpawnPlayer(SpawnPlayer {
+                net_id: player,
+                start_position: Vec2::ZERO,
+                is_player_frame_simulated: false,
+            }));
+        recording
+    }
+
+    fn record_hashes(mut recording: SessionRecording) -> SessionRecording {
+        let hashes = replay_hashes(recording.clone());
+        for (frame, hash) in recording.frames.iter_mut().zip(hashes) {
+            frame.hash = hash;
+        }
+        recording
+    }
+
+    /// Replaying a recording without hashes can't fail, so it's used to
+    /// record them.
+    fn replay_hashes(recording: SessionRecording) -> Vec<Option<u64>> {
+        let frames = recording.frames.len();
+        let mut app = build_replay_app(recording);
+        let mut hashes = Vec::new();
+        while hashes.len() < frames {
+            app.update();
+            let replay = app.world.resource::<SessionReplay>();
+            let guard = app.world.resource::<DeterminismGuard>();
+            while hashes.len() < replay.next_verified {
+                let frame_number = replay.recording.frames[hashes.len()].frame_number;
+                hashes.push(guard.stage_hash(frame_number, REPLAY_HASHED_STAGE));
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn test_replay_synthetic_session() {
+        let recording = record_hashes(synthetic_recording());
+        assert!(recording.frames.iter().all(|frame| frame.hash.is_some()));
+        assert_eq!(replay_session(recording.clone()), Ok(120));
+
+        // Serializing keeps the recording intact.
+        let json = serde_json::to_string(&recording).unwrap();
+        let deserialized: SessionRecording = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, recording);
+
+        // A different input makes the player end up somewhere else.
+        let mut diverged = recording;
+        diverged.frames[90].inputs[0].1 = Vec2::Y;
+        assert!(matches!(
+            replay_session(diverged),
+            Err(ReplayError::Mismatch { frame_index, .. }) if frame_index >= 90
+        ));
+    }
+
+    /// Replays the sessions recorded by servers with `MUDDLE_RECORD_SESSION`,
+    /// see `replays/README.md`.
+    #[test]
+    fn test_replay_corpus() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("replays");
+        let mut paths = std::fs::read_dir(&corpus)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "json")
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let recording: SessionRecording =
+                serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            if let Err(err) = replay_session(recording) {
+                panic!("{}: {}", path.display(), err);
+            }
+        }
+    }
+}
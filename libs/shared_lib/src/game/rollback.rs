@@ -0,0 +1,413 @@
+//! When a client corrects its mispredictions, it re-runs only the frames of the
+//! local player and level objects (see
+//! `SimulationTime::player_frames_to_rerun`). Most of the level objects can't
+//! be reached by the player during a re-run though, so we split bodies into
+//! collision islands and keep the islands that the player can't touch out of
+//! the re-run: their bodies are excluded from the physics step, the same way
+//! `isolate_client_mispredicted_world_system` excludes entities that aren't
+//! simulated at the player frame, and their `Position` buffers aren't written,
+//! as nothing can move them.
+
+use crate::{
+    framebuffer::FrameNumber,
+    game::{
+        components::{
+            LevelObjectMovement, LevelObjectTag, PlayerFrameSimulated, PlayerTag, Position,
+        },
+        movement::player_movement_speed,
+        tether::Tethers,
+    },
+    GameTime, SimulationTime, SIMULATIONS_PER_SECOND,
+};
+use bevy::{
+    ecs::{
+        entity::Entity,
+        query::{Or, With, WorldQuery},
+        system::{Query, Res, ResMut, Resource},
+    },
+    log,
+    math::Vec2,
+    transform::components::Transform,
+    utils::{HashMap, HashSet},
+};
+use bevy_rapier2d::{
+    dynamics::RigidBody,
+    geometry::{Collider, CollisionGroups, Group},
+};
+
+/// Accounts for contact skin and float rounding when checking whether bodies
+/// touch.
+const ISLAND_MARGIN: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IslandBody {
+    pub position: Vec2,
+    /// The radius of the bounding circle, including the distance that the body
+    /// can travel during a re-run.
+    pub radius: f32,
+    pub is_dynamic: bool,
+}
+
+impl IslandBody {
+    fn touches(&self, other: &IslandBody) -> bool {
+        let distance = self.radius + other.radius;
+        self.position.distance_squared(other.position) <= distance * distance
+    }
+}
+
+/// Returns which bodies share a collision island with any of the `seeds`.
+/// Islands are formed the same way as in rapier: dynamic bodies that touch
+/// each other get merged into one island, and a static body belongs to the
+/// islands of all the dynamic bodies it touches, without linking them.
+pub fn dirty_island_bodies(bodies: &[IslandBody], seeds: &[usize]) -> Vec<bool> {
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let dynamic_bodies = (0..bodies.len())
+        .filter(|i| bodies[*i].is_dynamic)
+        .collect::<Vec<_>>();
+    let mut parents = (0..bodies.len()).collect::<Vec<_>>();
+    for (n, a) in dynamic_bodies.iter().copied().enumerate() {
+        for b in dynamic_bodies[n + 1..].iter().copied() {
+            if bodies[a].touches(&bodies[b]) {
+                let root_a = find(&mut parents, a);
+                let root_b = find(&mut parents, b);
+                parents[root_a] = root_b;
+            }
+        }
+    }
+
+    let roots = (0..bodies.len())
+        .map(|i| find(&mut parents, i))
+        .collect::<Vec<_>>();
+    let dirty_roots = seeds
+        .iter()
+        .map(|seed| roots[*seed])
+        .collect::<HashSet<_>>();
+    let dirty_dynamic_bodies = dynamic_bodies
+        .into_iter()
+        .filter(|i| dirty_roots.contains(&roots[*i]))
+        .collect::<Vec<_>>();
+    bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| {
+            dirty_roots.contains(&roots[i])
+                || (!body.is_dynamic
+                    && dirty_dynamic_bodies
+                        .iter()
+                        .any(|dynamic_body| bodies[*dynamic_body].touches(body)))
+        })
+        .collect()
+}
+
+/// Level objects that the local player can't reach during the current re-run.
+#[derive(Resource, Default, Debug)]
+pub struct RerunDirtyFlags {
+    /// Collision groups of the excluded bodies, to restore them once they get
+    /// dirty or the re-run is over.
+    clean: HashMap<Entity, CollisionGroups>,
+    /// If a rewind happens in the middle of a re-run, the player frame jumps
+    /// back, and the flags have to be recalculated.
+    next_player_frame: Option<FrameNumber>,
+}
+
+impl RerunDirtyFlags {
+    pub fn is_clean(&self, entity: Entity) -> bool {
+        self.clean.contains_key(&entity)
+    }
+}
+
+#[derive(WorldQuery)]
+pub struct IslandBodyQuery<'w> {
+    entity: Entity,
+    position: &'w Position,
+    transform: &'w Transform,
+    collider: Option<&'w Collider>,
+    player_tag: Option<&'w PlayerTag>,
+    movement: Option<&'w LevelObjectMovement>,
+}
+
+pub fn mark_rerun_dirty_islands_system(
+    game_time: Res<GameTime>,
+    time: Res<SimulationTime>,
+    tethers: Res<Tethers>,
+    mut dirty_flags: ResMut<RerunDirtyFlags>,
+    bodies: Query<
+        IslandBodyQuery,
+        (
+            With<PlayerFrameSimulated>,
+            Or<(With<PlayerTag>, With<LevelObjectTag>)>,
+        ),
+    >,
+    mut level_object_bodies: Query<
+        (&mut RigidBody, &mut CollisionGroups),
+        (With<PlayerFrameSimulated>, With<LevelObjectTag>),
+    >,
+) {
+    #[cfg(feature = "profiler")]
+    puffin::profile_function!();
+
+    // Tethers can pull players faster than they run, so we can't tell how far
+    // a tethered player gets.
+    let clean = if !time.player_frame_simulated_only() || !tethers.pairs.is_empty() {
+        dirty_flags.next_player_frame = None;
+        HashSet::default()
+    } else {
+        let is_same_rerun = dirty_flags.next_player_frame == Some(time.player_frame);
+        dirty_flags.next_player_frame = Some(time.player_frame + FrameNumber::new(1));
+        if is_same_rerun {
+            return;
+        }
+
+        let frames_to_rerun = (game_time.frame_number - time.player_frame).value() + 1;
+        let player_reach =
+            player_movement_speed() * frames_to_rerun as f32 / SIMULATIONS_PER_SECOND;
+        clean_level_objects(&time, bodies.iter(), player_reach)
+    };
+
+    for (entity, collision_groups) in std::mem::take(&mut dirty_flags.clean) {
+        if clean.contains(&entity) {
+            dirty_flags.clean.insert(entity, collision_groups);
+            continue;
+        }
+        if let Ok((mut rigid_body, mut groups)) = level_object_bodies.get_mut(entity) {
+            log::trace!("Restore physics for {:?}", entity);
+            *rigid_body = RigidBody::KinematicPositionBased;
+            *groups = collision_groups;
+        }
+    }
+    for entity in clean {
+        if dirty_flags.is_clean(entity) {
+            continue;
+        }
+        // Objects that are still calculating their shapes don't have bodies yet.
+        let Ok((mut rigid_body, mut groups)) = level_object_bodies.get_mut(entity) else {
+            continue;
+        };
+        dirty_flags.clean.insert(entity, *groups);
+        *rigid_body = RigidBody::Fixed;
+        *groups = CollisionGroups::new(Group::NONE, Group::NONE);
+    }
+}
+
+fn clean_level_objects<'a>(
+    time: &SimulationTime,
+    bodies: impl Iterator<Item = IslandBodyQueryItem<'a>>,
+    player_reach: f32,
+) -> HashSet<Entity> {
+    let mut entities = Vec::new();
+    let mut island_bodies = Vec::new();
+    let mut seeds = Vec::new();
+    for IslandBodyQueryItem {
+        entity,
+        position,
+        transform,
+        collider,
+        player_tag,
+        movement,
+    } in bodies
+    {
+        // Routed objects move on their own, so they are never clean. Neither are
+        // objects that haven't been spawned yet.
+        if movement.is_some() {
+            continue;
+        }
+        let Some(position) = position.buffer.get(time.player_frame) else {
+            continue;
+        };
+        // Objects moved by a builder meanwhile need their bodies updated.
+        if player_tag.is_none() && transform.translation.truncate() != *position {
+            continue;
+        }
+
+        let mut radius = collider.map_or(0.0, |collider| {
+            collider.raw.compute_local_aabb().half_extents().norm()
+        }) + ISLAND_MARGIN;
+        if player_tag.is_some() {
+            seeds.push(island_bodies.len());
+            radius += player_reach;
+        }
+        island_bodies.push(IslandBody {
+            position: *position,
+            radius,
+            is_dynamic: player_tag.is_some(),
+        });
+        entities.push(entity);
+    }
+
+    let dirty_bodies = dirty_island_bodies(&island_bodies, &seeds);
+    let clean = entities
+        .into_iter()
+        .zip(dirty_bodies)
+        .filter_map(|(entity, is_dirty)| (!is_dirty).then_some(entity))
+        .collect::<HashSet<_>>();
+    log::trace!(
+        "Re-running frames from {}, {} objects are out of reach",
+        time.player_frame,
+        clean.len()
+    );
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        collider_flags::level_object_collision_groups,
+        game::{components::Spawned, movement::sync_position_system},
+        PLAYER_RADIUS,
+    };
+    use bevy::ecs::{
+        schedule::{Stage, SystemStage},
+        world::World,
+    };
+
+    fn body(x: f32, radius: f32, is_dynamic: bool) -> IslandBody {
+        IslandBody {
+            position: Vec2::new(x, 0.0),
+            radius,
+            is_dynamic,
+        }
+    }
+
+    #[test]
+    fn test_dirty_island_bodies() {
+        let bodies = [
+            // The seed, touches a static body and another dynamic one.
+            body(0.0, 1.0, true),
+            body(1.5, 1.0, false),
+            body(-1.5, 1.0, true),
+            // Touches the second dynamic body only.
+            body(-3.0, 1.0, false),
+            // A static body touching another static one doesn't join the island.
+            body(3.0, 1.0, false),
+            // A separate dynamic island.
+            body(10.0, 1.0, true),
+            body(11.5, 1.0, false),
+        ];
+        assert_eq!(
+            dirty_island_bodies(&bodies, &[0]),
+            vec![true, true, true, true, false, false, false]
+        );
+        assert_eq!(
+            dirty_island_bodies(&bodies, &[5]),
+            vec![false, false, false, false, false, true, true]
+        );
+        assert_eq!(dirty_island_bodies(&bodies, &[]), vec![false; 7]);
+    }
+
+    fn spawn_level_object(world: &mut World, position: Vec2) -> Entity {
+        world
+            .spawn((
+                LevelObjectTag,
+                PlayerFrameSimulated,
+                Spawned::new(FrameNumber::new(0)),
+                Position::new(position, FrameNumber::new(0), 10),
+                Transform::from_translation(position.extend(0.0)),
+                Collider::cuboid(1.0, 1.0),
+                RigidBody::KinematicPositionBased,
+                level_object_collision_groups(false),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn test_clean_islands_are_excluded_from_reruns() {
+        let mut world = World::new();
+        world.insert_resource(GameTime {
+            session: 0,
+            frame_number: FrameNumber::new(7),
+        });
+        world.insert_resource(SimulationTime {
+            player_frame: FrameNumber::new(5),
+            server_frame: FrameNumber::new(5),
+            player_frames_to_rerun: Some(FrameNumber::new(3)),
+            ..Default::default()
+        });
+        world.insert_resource(Tethers::default());
+        world.insert_resource(RerunDirtyFlags::default());
+        world.spawn((
+            PlayerTag,
+            PlayerFrameSimulated,
+            Spawned::new(FrameNumber::new(0)),
+            Position::new(Vec2::ZERO, FrameNumber::new(0), 10),
+            Transform::default(),
+            Collider::ball(PLAYER_RADIUS),
+        ));
+        let near_object = spawn_level_object(&mut world, Vec2::new(1.5, 0.0));
+        let far_object = spawn_level_object(&mut world, Vec2::new(50.0, 0.0));
+        // Lets us tell whether the positions of the re-run frame get written.
+        let stale_position = Vec2::new(100.0, 100.0);
+        for entity in [near_object, far_object] {
+            world
+                .get_mut::<Position>(entity)
+                .unwrap()
+                .buffer
+                .insert(FrameNumber::new(6), stale_position);
+        }
+
+        let mut mark_stage =
+            SystemStage::single_threaded().with_system(mark_rerun_dirty_islands_system);
+        let mut sync_stage = SystemStage::single_threaded().with_system(sync_position_system);
+        mark_stage.run(&mut world);
+        sync_stage.run(&mut world);
+
+        let dirty_flags = world.resource::<RerunDirtyFlags>();
+        assert!(!dirty_flags.is_clean(near_object));
+        assert!(dirty_flags.is_clean(far_object));
+
+        // The far object is kept out of the physics step, and its position isn't
+        // written.
+        assert!(matches!(
+            world.get::<RigidBody>(far_object),
+            Some(RigidBody::Fixed)
+        ));
+        let groups = world.get::<CollisionGroups>(far_object).unwrap();
+        assert_eq!(groups.memberships, Group::NONE);
+        assert_eq!(groups.filters, Group::NONE);
+        let far_position = world.get::<Position>(far_object).unwrap();
+        assert_eq!(
+            far_position.buffer.get(FrameNumber::new(6)),
+            Some(&stale_position)
+        );
+
+        // The object that the player can reach is simulated as usual.
+        assert!(matches!(
+            world.get::<RigidBody>(near_object),
+            Some(RigidBody::KinematicPositionBased)
+        ));
+        let groups = world.get::<CollisionGroups>(near_object).unwrap();
+        assert_eq!(
+            groups.memberships,
+            level_object_collision_groups(false).memberships
+        );
+        let near_position = world.get::<Position>(near_object).unwrap();
+        assert_eq!(
+            near_position.buffer.get(FrameNumber::new(6)),
+            Some(&Vec2::new(1.5, 0.0))
+        );
+
+        // Once the re-run is over, the far object gets its body back.
+        world
+            .resource_mut::<SimulationTime>()
+            .player_frames_to_rerun = None;
+        mark_stage.run(&mut world);
+        assert!(!world.resource::<RerunDirtyFlags>().is_clean(far_object));
+        assert!(matches!(
+            world.get::<RigidBody>(far_object),
+            Some(RigidBody::KinematicPositionBased)
+        ));
+        let groups = world.get::<CollisionGroups>(far_object).unwrap();
+        assert_eq!(
+            groups.memberships,
+            level_object_collision_groups(false).memberships
+        );
+        assert_eq!(groups.filters, level_object_collision_groups(false).filters);
+    }
+}
@@ -3,9 +3,10 @@ use crate::{
     framebuffer::FrameNumber,
     game::{
         client_factories::{
-            ClientFactory, CubeClientFactory, LevelObjectInput, PbrClientParams,
-            PlaneClientFactory, PlayerClientFactory, PlayerSensorClientFactory,
-            RoutePointClientFactory,
+            AnnotationClientFactory, CameraAnchorClientFactory, ClientFactory, CubeClientFactory,
+            DeathZoneClientFactory, EmitterClientFactory, FinishZoneClientFactory,
+            LevelObjectInput, PbrClientParams, PlaneClientFactory, PlayerClientFactory,
+            PlayerSensorClientFactory, RoutePointClientFactory,
         },
         commands::{
             DeferredQueue, DespawnLevelObject, DespawnPlayer, DespawnReason, SpawnPlayer,
@@ -17,7 +18,13 @@ use crate::{
             PhysicsBundle, PlayerDirection, PlayerFrameSimulated, PlayerSensor, PlayerSensorState,
             PlayerSensors, PlayerTag, Position, SpawnCommand, Spawned,
         },
-        level::{ColliderShapeResponse, LevelObject, LevelObjectDesc, LevelState},
+        events::LevelObjectShapeInvalid,
+        jump_pad::JumpPadBoost,
+        level::{
+            ColliderShapeError, ColliderShapeResponse, LevelObject, LevelObjectDesc,
+            LevelObjectShape, LevelState,
+        },
+        level_objects::ColliderSimplification,
     },
     messages::{EntityNetId, PlayerNetId},
     registry::EntityRegistry,
@@ -37,10 +44,9 @@ use bevy_rapier2d::{
     dynamics::{LockedAxes, RigidBody, Velocity},
     geometry::{ActiveEvents, Collider, Group, Sensor},
     prelude::CollisionGroups,
-    rapier::geometry::ColliderShape,
 };
 use iyes_loopless::state::NextState;
-use std::fmt::Debug;
+use std::{fmt::Debug, marker::PhantomData};
 
 #[derive(WorldQuery)]
 #[world_query(mutable)]
@@ -76,7 +82,7 @@ pub fn iter_spawned_read_only<'w, T: WorldQuery>(
     )
 }
 
-pub type ColliderShapePromiseResult = (Entity, Option<ColliderShape>);
+pub type ColliderShapePromiseResult = (Entity, Result<LevelObjectShape, ColliderShapeError>);
 
 #[derive(Resource, Deref, DerefMut, Clone)]
 pub struct ColliderShapeSender(pub crossbeam_channel::Sender<ColliderShapePromiseResult>);
@@ -226,6 +232,7 @@ pub fn spawn_players_system(
             ))
             .insert(GlobalTransform::IDENTITY)
             .insert(Velocity::zero())
+            .insert(JumpPadBoost::default())
             .insert(Spawned::new(time.server_frame));
 
         // Insert client components later, as they can overwrite some of them (z
@@ -347,6 +354,7 @@ pub struct UpdateLevelObjectQuery<'w> {
 pub struct LevelObjectsParams<'w, 's> {
     object_entities: ResMut<'w, EntityRegistry<EntityNetId>>,
     level_state: ResMut<'w, LevelState>,
+    collider_simplification: Res<'w, ColliderSimplification>,
     level_object_query: Query<'w, 's, UpdateLevelObjectQuery<'static>>,
 }
 
@@ -387,6 +395,8 @@ pub fn update_level_objects_system(
             level_object_params
                 .object_entities
                 .remove_by_id(command.object.net_id);
+            #[cfg(feature = "client")]
+            pbr_client_params.release_level_object_assets(command.object.net_id);
             commands.entity(existing_entity).despawn();
             let updated_level_object = level_object_params
                 .level_object_query
@@ -409,16 +419,13 @@ pub fn update_level_objects_system(
         }
 
         log::info!("Spawning an object: {:?}", command);
-        level_object_params
-            .level_state
-            .objects
-            .insert(command.object.net_id, command.object.clone());
+        level_object_params.level_state.apply_update(&command);
         let mut entity_commands = commands.spawn_empty();
-        let shape = match command
-            .object
-            .desc
-            .calculate_collider_shape(entity_commands.id(), shape_sender.clone())
-        {
+        let shape = match command.object.desc.calculate_collider_shape(
+            entity_commands.id(),
+            *level_object_params.collider_simplification,
+            shape_sender.clone(),
+        ) {
             ColliderShapeResponse::Immediate(shape) => Some(shape),
             ColliderShapeResponse::Promise => None,
         };
@@ -474,14 +481,14 @@ pub fn update_level_objects_system(
             let (physics_bundle, sensor) = command
                 .object
                 .desc
-                .physics_bundle(shape.clone(), cfg!(not(feature = "client")));
+                .physics_bundle(shape.collider.clone(), cfg!(not(feature = "client")));
             // Insert client components later, as they can overwrite some of them
             // (z coordinates of translations for instance).
             insert_client_components(
                 &mut entity_commands,
                 &command.object,
                 false,
-                &physics_bundle.collider.raw,
+                shape,
                 &mut pbr_client_params,
             );
             entity_commands.insert(physics_bundle);
@@ -531,7 +538,8 @@ pub fn update_level_objects_system(
                 .insert(transform)
                 .insert(GlobalTransform::IDENTITY);
             if let Some(shape) = shape {
-                let (physics_bundle, sensor) = command.object.desc.physics_bundle(shape, true);
+                let (physics_bundle, sensor) =
+                    command.object.desc.physics_bundle(shape.collider, true);
                 server_ghost_commands.insert(physics_bundle);
                 if let Some(sensor) = sensor {
                     server_ghost_commands.insert(sensor);
@@ -557,6 +565,14 @@ type GhostEntites = Option<(
     &'static LevelObjectServerGhostChild,
 )>;
 
+#[derive(SystemParam)]
+pub struct CalculatedShapesParams<'w, 's> {
+    collider_shape_receiver: Res<'w, ColliderShapeReceiver>,
+    shape_invalid_events: EventWriter<'w, LevelObjectShapeInvalid>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
 pub fn poll_calculating_shapes_system(
     mut commands: Commands,
     mut level_objects_to_spawn_to_load: Option<ResMut<LevelObjectsToSpawnToLoad>>,
@@ -564,9 +580,11 @@ pub fn poll_calculating_shapes_system(
     level_state: Res<LevelState>,
     mut pbr_client_params: PbrClientParams,
     level_objects_query: Query<(&EntityNetId, &Spawned, GhostEntites)>,
-    collider_shape_receiver: Res<ColliderShapeReceiver>,
+    mut calculated_shapes_params: CalculatedShapesParams,
 ) {
-    while let Ok((entity, shape_result)) = collider_shape_receiver.try_recv() {
+    while let Ok((entity, shape_result)) =
+        calculated_shapes_params.collider_shape_receiver.try_recv()
+    {
         let (entity_net_id, spawned, ghost_entities) = match level_objects_query.get(entity) {
             Ok(r) => r,
             Err(_) => continue,
@@ -576,12 +594,23 @@ pub fn poll_calculating_shapes_system(
             continue;
         }
 
-        let level_object = level_state.objects.get(entity_net_id).unwrap();
+        let level_object = level_state.object(*entity_net_id).unwrap();
 
         let mut entity_commands = commands.entity(entity);
 
+        // This resource exists if we've just started the game. Once all the objects are
+        // spawned, we must remove the resource and switch to the
+        // `GameSessionState::Playing` state. Objects with invalid shapes are never
+        // going to be spawned, so we stop waiting for them as well.
+        //
+        // IMPORTANT: the same logic is present in `update_level_objects_system`,
+        // remember to update it as well if there are any changes to this code.
+        if let Some(level_objects_to_spawn_to_load) = &mut level_objects_to_spawn_to_load {
+            level_objects_to_spawn_to_load.0 -= 1;
+        }
+
         let shape = match shape_result {
-            Some(shape) => {
+            Ok(shape) => {
                 log::debug!(
                     "Calculating shape for {:?} has finished (frame: {})",
                     entity,
@@ -589,34 +618,32 @@ pub fn poll_calculating_shapes_system(
                 );
                 shape
             }
-            None => {
+            Err(error) => {
                 log::error!(
-                    "Calculating shape for {:?} has failed (frame: {})",
+                    "Calculating shape for {:?} ({:?}) has failed (frame: {}): {}",
                     entity,
-                    time.frame_number
+                    entity_net_id,
+                    time.frame_number,
+                    error
                 );
+                calculated_shapes_params
+                    .shape_invalid_events
+                    .send(LevelObjectShapeInvalid {
+                        net_id: *entity_net_id,
+                        error,
+                    });
                 continue;
             }
         };
 
-        // This resource exists if we've just started the game. Once all the objects are
-        // spawned, we must remove the resource and switch to the
-        // `GameSessionState::Playing` state.
-        //
-        // IMPORTANT: the same logic is present in `update_level_objects_system`,
-        // remember to update it as well if there are any changes to this code.
-        if let Some(level_objects_to_spawn_to_load) = &mut level_objects_to_spawn_to_load {
-            level_objects_to_spawn_to_load.0 -= 1;
-        }
-
         let (physics_bundle, sensor) = level_object
             .desc
-            .physics_bundle(shape.clone(), cfg!(not(feature = "client")));
+            .physics_bundle(shape.collider.clone(), cfg!(not(feature = "client")));
         insert_client_components(
             &mut entity_commands,
             level_object,
             false,
-            &physics_bundle.collider.raw,
+            &shape,
             &mut pbr_client_params,
         );
         entity_commands.insert(physics_bundle);
@@ -639,7 +666,7 @@ pub fn poll_calculating_shapes_system(
             );
 
             let mut server_ghost_commands = commands.entity(*server_ghost_entity);
-            let (physics_bundle, sensor) = level_object.desc.physics_bundle(shape, true);
+            let (physics_bundle, sensor) = level_object.desc.physics_bundle(shape.collider, true);
             server_ghost_commands.insert(physics_bundle);
             if let Some(sensor) = sensor {
                 server_ghost_commands.insert(sensor);
@@ -652,7 +679,7 @@ fn insert_client_components(
     entity_commands: &mut EntityCommands,
     level_object: &LevelObject,
     is_ghost: bool,
-    collider_shape: &ColliderShape,
+    shape: &LevelObjectShape,
     pbr_client_params: &mut PbrClientParams,
 ) {
     match &level_object.desc {
@@ -661,17 +688,19 @@ fn insert_client_components(
             pbr_client_params,
             (
                 LevelObjectInput {
+                    net_id: level_object.net_id,
                     desc: plane.clone(),
                     collision_logic: level_object.collision_logic,
                     is_ghost,
                 },
-                Some(collider_shape.clone()),
+                Some(shape.visual().clone()),
             ),
         ),
         LevelObjectDesc::Cube(cube) => CubeClientFactory::insert_components(
             entity_commands,
             pbr_client_params,
             LevelObjectInput {
+                net_id: level_object.net_id,
                 desc: cube.clone(),
                 collision_logic: level_object.collision_logic,
                 is_ghost,
@@ -681,11 +710,64 @@ fn insert_client_components(
             entity_commands,
             pbr_client_params,
             LevelObjectInput {
+                net_id: level_object.net_id,
                 desc: route_point.clone(),
                 collision_logic: level_object.collision_logic,
                 is_ghost,
             },
         ),
+        LevelObjectDesc::Annotation(annotation) => AnnotationClientFactory::insert_components(
+            entity_commands,
+            pbr_client_params,
+            LevelObjectInput {
+                net_id: level_object.net_id,
+                desc: annotation.clone(),
+                collision_logic: level_object.collision_logic,
+                is_ghost,
+            },
+        ),
+        LevelObjectDesc::CameraAnchor(camera_anchor) => {
+            CameraAnchorClientFactory::insert_components(
+                entity_commands,
+                pbr_client_params,
+                LevelObjectInput {
+                    net_id: level_object.net_id,
+                    desc: camera_anchor.clone(),
+                    collision_logic: level_object.collision_logic,
+                    is_ghost,
+                },
+            )
+        }
+        LevelObjectDesc::Emitter(emitter) => EmitterClientFactory::insert_components(
+            entity_commands,
+            pbr_client_params,
+            LevelObjectInput {
+                net_id: level_object.net_id,
+                desc: emitter.clone(),
+                collision_logic: level_object.collision_logic,
+                is_ghost,
+            },
+        ),
+        LevelObjectDesc::DeathZone(death_zone) => DeathZoneClientFactory::insert_components(
+            entity_commands,
+            pbr_client_params,
+            LevelObjectInput {
+                net_id: level_object.net_id,
+                desc: death_zone.clone(),
+                collision_logic: level_object.collision_logic,
+                is_ghost,
+            },
+        ),
+        LevelObjectDesc::FinishZone(finish_zone) => FinishZoneClientFactory::insert_components(
+            entity_commands,
+            pbr_client_params,
+            LevelObjectInput {
+                net_id: level_object.net_id,
+                desc: finish_zone.clone(),
+                collision_logic: level_object.collision_logic,
+                is_ghost,
+            },
+        ),
     };
 }
 
@@ -742,9 +824,10 @@ pub fn despawn_level_objects_system(
             command.frame_number
         );
         collider_flags.memberships = Group::NONE;
+        #[cfg(feature = "client")]
+        pbr_client_params.release_level_object_assets(command.net_id);
         match level_state
-            .objects
-            .remove(&command.net_id)
+            .apply_despawn(&command)
             .expect("Expected a removed level object to exist in the level state")
             .desc
         {
@@ -784,6 +867,66 @@ pub fn despawn_level_objects_system(
                     );
                 }
             }
+            LevelObjectDesc::Annotation(_) => {
+                AnnotationClientFactory::remove_components(
+                    &mut commands.entity(entity),
+                    &mut pbr_client_params,
+                );
+                if let Some(LevelObjectStaticGhostChild(ghost_entity)) = ghost_parent {
+                    AnnotationClientFactory::remove_components(
+                        &mut commands.entity(*ghost_entity),
+                        &mut pbr_client_params,
+                    );
+                }
+            }
+            LevelObjectDesc::CameraAnchor(_) => {
+                CameraAnchorClientFactory::remove_components(
+                    &mut commands.entity(entity),
+                    &mut pbr_client_params,
+                );
+                if let Some(LevelObjectStaticGhostChild(ghost_entity)) = ghost_parent {
+                    CameraAnchorClientFactory::remove_components(
+                        &mut commands.entity(*ghost_entity),
+                        &mut pbr_client_params,
+                    );
+                }
+            }
+            LevelObjectDesc::Emitter(_) => {
+                EmitterClientFactory::remove_components(
+                    &mut commands.entity(entity),
+                    &mut pbr_client_params,
+                );
+                if let Some(LevelObjectStaticGhostChild(ghost_entity)) = ghost_parent {
+                    EmitterClientFactory::remove_components(
+                        &mut commands.entity(*ghost_entity),
+                        &mut pbr_client_params,
+                    );
+                }
+            }
+            LevelObjectDesc::DeathZone(_) => {
+                DeathZoneClientFactory::remove_components(
+                    &mut commands.entity(entity),
+                    &mut pbr_client_params,
+                );
+                if let Some(LevelObjectStaticGhostChild(ghost_entity)) = ghost_parent {
+                    DeathZoneClientFactory::remove_components(
+                        &mut commands.entity(*ghost_entity),
+                        &mut pbr_client_params,
+                    );
+                }
+            }
+            LevelObjectDesc::FinishZone(_) => {
+                FinishZoneClientFactory::remove_components(
+                    &mut commands.entity(entity),
+                    &mut pbr_client_params,
+                );
+                if let Some(LevelObjectStaticGhostChild(ghost_entity)) = ghost_parent {
+                    FinishZoneClientFactory::remove_components(
+                        &mut commands.entity(*ghost_entity),
+                        &mut pbr_client_params,
+                    );
+                }
+            }
         }
         spawned.push_command(
             command.frame_number,
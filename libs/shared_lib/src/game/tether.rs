@@ -0,0 +1,114 @@
+use crate::messages::PlayerNetId;
+use bevy::{ecs::system::Resource, math::Vec2};
+use serde::{Deserialize, Serialize};
+
+/// Runners that are tethered in pairs: they can't get farther than
+/// `max_distance` from each other, and if one of them dies, both respawn.
+/// Pairing is managed by the server, clients only mirror it.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Tethers {
+    pub max_distance: f32,
+    pub pairs: Vec<(PlayerNetId, PlayerNetId)>,
+}
+
+impl Tethers {
+    pub fn partner(&self, net_id: PlayerNetId) -> Option<PlayerNetId> {
+        self.pairs.iter().find_map(|&(a, b)| {
+            if a == net_id {
+                Some(b)
+            } else if b == net_id {
+                Some(a)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TetheredBody {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    /// Fixed bodies aren't moved by the constraint, for instance, when clients
+    /// re-simulate only their own player to correct mispredictions.
+    pub is_fixed: bool,
+}
+
+/// Returns the velocities that keep the bodies within `max_distance` after
+/// moving for `dt`. The overshoot is split evenly between the bodies, unless
+/// one of them is fixed.
+pub fn solve_tether_constraint(
+    a: TetheredBody,
+    b: TetheredBody,
+    max_distance: f32,
+    dt: f32,
+) -> (Vec2, Vec2) {
+    let next_a = a.position + a.velocity * dt;
+    let next_b = b.position + b.velocity * dt;
+    let delta = next_b - next_a;
+    let distance = delta.length();
+    if distance <= max_distance || distance == 0.0 {
+        return (a.velocity, b.velocity);
+    }
+
+    let (a_share, b_share) = match (a.is_fixed, b.is_fixed) {
+        (false, false) => (0.5, 0.5),
+        (false, true) => (1.0, 0.0),
+        (true, false) => (0.0, 1.0),
+        (true, true) => return (a.velocity, b.velocity),
+    };
+    let correction = delta / distance * (distance - max_distance) / dt;
+    (
+        a.velocity + correction * a_share,
+        b.velocity - correction * b_share,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(x: f32, velocity_x: f32, is_fixed: bool) -> TetheredBody {
+        TetheredBody {
+            position: Vec2::new(x, 0.0),
+            velocity: Vec2::new(velocity_x, 0.0),
+            is_fixed,
+        }
+    }
+
+    #[test]
+    fn test_tether_partner() {
+        let tethers = Tethers {
+            max_distance: 5.0,
+            pairs: vec![(PlayerNetId(1), PlayerNetId(4))],
+        };
+        assert_eq!(tethers.partner(PlayerNetId(1)), Some(PlayerNetId(4)));
+        assert_eq!(tethers.partner(PlayerNetId(4)), Some(PlayerNetId(1)));
+        assert_eq!(tethers.partner(PlayerNetId(2)), None);
+    }
+
+    #[test]
+    fn test_solve_tether_constraint() {
+        // Within the distance, velocities stay intact.
+        assert_eq!(
+            solve_tether_constraint(body(0.0, 1.0, false), body(4.0, 1.0, false), 5.0, 1.0),
+            (Vec2::new(1.0, 0.0), Vec2::new(1.0, 0.0))
+        );
+
+        // Moving apart, both bodies get pulled back by a half of the overshoot.
+        assert_eq!(
+            solve_tether_constraint(body(0.0, -1.0, false), body(4.0, 1.0, false), 5.0, 1.0),
+            (Vec2::new(-0.5, 0.0), Vec2::new(0.5, 0.0))
+        );
+
+        // A fixed body doesn't move, the other one takes the whole correction.
+        assert_eq!(
+            solve_tether_constraint(body(0.0, 0.0, true), body(4.0, 2.0, false), 5.0, 1.0),
+            (Vec2::ZERO, Vec2::new(1.0, 0.0))
+        );
+        assert_eq!(
+            solve_tether_constraint(body(0.0, 0.0, true), body(8.0, 0.0, true), 5.0, 1.0),
+            (Vec2::ZERO, Vec2::ZERO)
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use bevy::utils::HashSet;
+use std::{collections::VecDeque, hash::Hash, marker::PhantomData, ops::Range};
+
+/// Net ids are sent as `u16` values, this trait lets [`IdAllocator`] work with
+/// their typed wrappers.
+pub trait RawId: Copy + Eq + Hash {
+    fn from_raw(raw: u16) -> Self;
+    fn raw(self) -> u16;
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum IdAllocationError {
+    #[error("all ids are in use")]
+    Exhausted,
+    #[error("id {0} is already in use")]
+    Collision(u16),
+}
+
+/// Allocates net ids, making sure that an id is never handed out twice while
+/// it's in use.
+///
+/// Fresh ids are allocated sequentially. Freed ids go to a free list and get
+/// reused only once fresh ids run out, the least recently freed ones first,
+/// so that messages still referencing a removed entity (or player) are
+/// unlikely to be mistaken for a new one.
+///
+/// Ids coming from outside (for example, from a loaded level) must be
+/// registered with [`IdAllocator::reserve`] or [`IdAllocator::reserve_range`],
+/// which fail instead of silently reusing an id.
+pub struct IdAllocator<K: RawId> {
+    /// The next never allocated id, `u16::MAX + 1` once fresh ids run out.
+    next_fresh: u32,
+    free: VecDeque<u16>,
+    in_use: HashSet<u16>,
+    marker: PhantomData<K>,
+}
+
+impl<K: RawId> Default for IdAllocator<K> {
+    fn default() -> Self {
+        Self {
+            next_fresh: 0,
+            free: VecDeque::new(),
+            in_use: HashSet::default(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<K: RawId> IdAllocator<K> {
+    pub fn allocate(&mut self) -> Result<K, IdAllocationError> {
+        // Reserved ids may be ahead of the fresh ids counter, skipping them.
+        while self.next_fresh <= u16::MAX as u32 {
+            let id = self.next_fresh as u16;
+            self.next_fresh += 1;
+            if self.in_use.insert(id) {
+                return Ok(K::from_raw(id));
+            }
+        }
+        // Freed ids might have been reserved again after they were freed.
+        while let Some(id) = self.free.pop_front() {
+            if self.in_use.insert(id) {
+                return Ok(K::from_raw(id));
+            }
+        }
+        Err(IdAllocationError::Exhausted)
+    }
+
+    /// Marks an id that was allocated elsewhere as used.
+    pub fn reserve(&mut self, id: K) -> Result<(), IdAllocationError> {
+        if !self.in_use.insert(id.raw()) {
+            return Err(IdAllocationError::Collision(id.raw()));
+        }
+        Ok(())
+    }
+
+    /// Marks all the ids of the range as used. Nothing is reserved if any of
+    /// the ids is already in use.
+    pub fn reserve_range(&mut self, range: Range<u16>) -> Result<(), IdAllocationError> {
+        if let Some(id) = range.clone().find(|id| self.in_use.contains(id)) {
+            return Err(IdAllocationError::Collision(id));
+        }
+        self.in_use.extend(range);
+        Ok(())
+    }
+
+    /// Returns `false` if the id wasn't in use.
+    pub fn free(&mut self, id: K) -> bool {
+        if !self.in_use.remove(&id.raw()) {
+            return false;
+        }
+        // Fresh ids will be allocated anyway before any id from the free list.
+        if (id.raw() as u32) < self.next_fresh {
+            self.free.push_back(id.raw());
+        }
+        true
+    }
+
+    pub fn is_in_use(&self, id: K) -> bool {
+        self.in_use.contains(&id.raw())
+    }
+
+    pub fn in_use_count(&self) -> usize {
+        self.in_use.len()
+    }
+
+    /// Forgets about all the allocated ids, but keeps protecting them from
+    /// being reused right away.
+    pub fn free_all(&mut self) {
+        let mut in_use = self
+            .in_use
+            .drain()
+            .filter(|id| (*id as u32) < self.next_fresh)
+            .collect::<Vec<_>>();
+        in_use.sort_unstable();
+        self.free.extend(in_use);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    struct TestId(u16);
+
+    impl RawId for TestId {
+        fn from_raw(raw: u16) -> Self {
+            Self(raw)
+        }
+
+        fn raw(self) -> u16 {
+            self.0
+        }
+    }
+
+    fn exhaust_fresh_ids(allocator: &mut IdAllocator<TestId>) {
+        while allocator.next_fresh <= u16::MAX as u32 {
+            allocator.allocate().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_allocate_sequentially() {
+        let mut allocator = IdAllocator::<TestId>::default();
+        assert_eq!(allocator.allocate(), Ok(TestId(0)));
+        assert_eq!(allocator.allocate(), Ok(TestId(1)));
+        assert!(allocator.free(TestId(0)));
+        assert!(!allocator.free(TestId(0)));
+        // Freed ids aren't reused while there are fresh ones.
+        assert_eq!(allocator.allocate(), Ok(TestId(2)));
+        assert_eq!(allocator.in_use_count(), 2);
+    }
+
+    #[test]
+    fn test_free_list_reuse() {
+        let mut allocator = IdAllocator::<TestId>::default();
+        exhaust_fresh_ids(&mut allocator);
+        assert_eq!(allocator.allocate(), Err(IdAllocationError::Exhausted));
+
+        allocator.free(TestId(10));
+        allocator.free(TestId(5));
+        allocator.free(TestId(7));
+        allocator.reserve(TestId(5)).unwrap();
+        assert_eq!(allocator.allocate(), Ok(TestId(10)));
+        assert_eq!(allocator.allocate(), Ok(TestId(7)));
+        assert_eq!(allocator.allocate(), Err(IdAllocationError::Exhausted));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut allocator = IdAllocator::<TestId>::default();
+        allocator.reserve(TestId(1)).unwrap();
+        assert_eq!(
+            allocator.reserve(TestId(1)),
+            Err(IdAllocationError::Collision(1))
+        );
+        assert_eq!(allocator.allocate(), Ok(TestId(0)));
+        assert_eq!(allocator.allocate(), Ok(TestId(2)));
+
+        allocator.reserve_range(3..6).unwrap();
+        assert_eq!(
+            allocator.reserve_range(5..10),
+            Err(IdAllocationError::Collision(5))
+        );
+        // A failed reservation doesn't reserve anything.
+        assert!(!allocator.is_in_use(TestId(6)));
+        assert_eq!(allocator.allocate(), Ok(TestId(6)));
+    }
+
+    #[test]
+    fn test_restart_with_loaded_level() {
+        // A level saved with gaps in its ids, as levels edited by hand may have.
+        let level_ids = [0, 1, 2, 5, 8];
+
+        let mut allocator = IdAllocator::<TestId>::default();
+        for id in level_ids {
+            allocator.reserve(TestId(id)).unwrap();
+        }
+        let runtime_ids = (0..4)
+            .map(|_| allocator.allocate().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(runtime_ids, [TestId(3), TestId(4), TestId(6), TestId(7)]);
+        assert_eq!(allocator.allocate(), Ok(TestId(9)));
+
+        // A level containing an id twice is rejected.
+        let mut allocator = IdAllocator::<TestId>::default();
+        allocator.reserve(TestId(3)).unwrap();
+        assert_eq!(
+            allocator.reserve(TestId(3)),
+            Err(IdAllocationError::Collision(3))
+        );
+    }
+
+    #[test]
+    fn test_level_reload() {
+        let mut allocator = IdAllocator::<TestId>::default();
+        allocator.reserve_range(0..3).unwrap();
+        assert_eq!(allocator.allocate(), Ok(TestId(3)));
+
+        // Loading a different level: the ids of the previous one shouldn't get
+        // reused right away.
+        allocator.free_all();
+        assert_eq!(allocator.in_use_count(), 0);
+        allocator.reserve_range(0..2).unwrap();
+        assert_eq!(allocator.allocate(), Ok(TestId(4)));
+
+        // A runtime allocation collides with the reloaded level.
+        assert_eq!(
+            allocator.reserve(TestId(4)),
+            Err(IdAllocationError::Collision(4))
+        );
+
+        exhaust_fresh_ids(&mut allocator);
+        assert_eq!(allocator.allocate(), Ok(TestId(2)));
+        assert_eq!(allocator.allocate(), Ok(TestId(3)));
+        assert_eq!(allocator.allocate(), Err(IdAllocationError::Exhausted));
+    }
+}
@@ -10,27 +10,34 @@ use crate::{
     framebuffer::FrameNumber,
     game::{
         collisions::{process_collision_events_system, process_players_with_new_collisions_system},
+        command_log::{collect_recorded_commands_system, CommandLog},
         commands::{
             DeferredQueue, DespawnLevelObject, DespawnPlayer, SpawnPlayer, SwitchPlayerRole,
-            UpdateLevelObject,
+            UpdateLevelObject, UpdateLevelSettings,
         },
         components::PlayerFrameSimulated,
-        events::{CollisionLogicChanged, PlayerDeath, PlayerFinish},
-        level::{maintain_available_spawn_areas_system, LevelState},
+        determinism::{record_state_hashes_system, DeterminismGuard, SimulationStage},
+        emitter::process_emitter_hazards_system,
+        events::{CollisionLogicChanged, LevelObjectShapeInvalid, PlayerDeath, PlayerFinish},
+        level::LevelState,
         level_objects::{
             process_objects_route_graph_system, update_level_object_movement_route_settings_system,
+            ColliderSimplification,
         },
         movement::{
             isolate_client_mispredicted_world_system, load_object_positions_system,
             player_movement_system, read_movement_updates_system, sync_position_system,
         },
         remove_disconnected_players_system, reset_game_world_system,
+        rollback::{mark_rerun_dirty_islands_system, RerunDirtyFlags},
         spawn::{
             despawn_level_objects_system, despawn_players_system, poll_calculating_shapes_system,
             process_spawned_entities_system, spawn_players_system, update_level_objects_system,
             ColliderShapePromiseResult, ColliderShapeReceiver, ColliderShapeSender,
         },
         switch_player_role_system,
+        tether::Tethers,
+        update_level_settings_system,
     },
     messages::{DeferredMessagesQueue, SwitchRole},
     net::network_setup_system,
@@ -54,14 +61,20 @@ use std::sync::Mutex;
 #[cfg(feature = "client")]
 pub mod client;
 pub mod collider_flags;
+pub mod constants;
+pub mod delta_compression;
 pub mod framebuffer;
 pub mod game;
+pub mod id_allocator;
 pub mod messages;
 pub mod net;
+pub mod panic_quarantine;
 pub mod player;
 pub mod registry;
 #[cfg(not(feature = "client"))]
 pub mod server;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod test_harness;
 pub mod util;
 pub mod wrapped_counter;
 
@@ -115,6 +128,13 @@ pub const LAG_COMPENSATED_FRAMES: FrameNumber = {
     let v = (MAX_LAG_COMPENSATION_MILLIS as f32 / (1000.0 / SIMULATIONS_PER_SECOND)) as u16;
     FrameNumber::new(v)
 };
+/// Clients may replace unchanged inputs with `PlayerInputs::Unchanged` for
+/// this many frames at most, then they resend them in full, in case the server
+/// has dropped them (i.e. as out of sync).
+pub const MAX_INPUT_SEND_INTERVAL: FrameNumber = {
+    let v = (MAX_LAG_COMPENSATION_MILLIS as f32 / 2.0 / (1000.0 / SIMULATIONS_PER_SECOND)) as u16;
+    FrameNumber::new(v - v % TICKS_PER_NETWORK_BROADCAST)
+};
 
 const SIMULATIONS_PER_SECOND_DEFAULT: u16 = 120;
 
@@ -200,6 +220,7 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                     .with_system(Events::<CollisionEvent>::update_system)
                     .with_system(Events::<PlayerFinish>::update_system)
                     .with_system(Events::<PlayerDeath>::update_system)
+                    .with_system(Events::<LevelObjectShapeInvalid>::update_system)
                     .with_system(switch_player_role_system)
                     .with_system(despawn_players_system.after(switch_player_role_system))
                     .with_system(despawn_level_objects_system)
@@ -212,19 +233,19 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                     // will lead to crash. Executing this system before `update_level_objects` helps
                     // to avoid this scenario.
                     .with_system(poll_calculating_shapes_system.before(update_level_objects_system))
-                    .with_system(
-                        maintain_available_spawn_areas_system.after(update_level_objects_system),
-                    )
+                    .with_system(update_level_settings_system)
                     .with_system(
                         spawn_players_system
                             .after(despawn_players_system)
-                            .after(maintain_available_spawn_areas_system),
-                    ),
+                            .after(update_level_objects_system),
+                    )
+                    .with_system(record_state_hashes_system(SimulationStage::Spawn).at_end()),
             )
             .with_stage(
                 stage::PRE_GAME,
                 SystemStage::single_threaded()
-                    .with_system(update_level_object_movement_route_settings_system),
+                    .with_system(update_level_object_movement_route_settings_system)
+                    .with_system(record_state_hashes_system(SimulationStage::PreGame).at_end()),
             )
             .with_stage(
                 stage::GAME,
@@ -232,9 +253,14 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                     .with_system(isolate_client_mispredicted_world_system)
                     .with_system(player_movement_system)
                     .with_system(process_objects_route_graph_system)
+                    .with_system(mark_rerun_dirty_islands_system)
                     .with_system(
-                        load_object_positions_system.after(process_objects_route_graph_system),
-                    ),
+                        load_object_positions_system
+                            .after(process_objects_route_graph_system)
+                            .after(mark_rerun_dirty_islands_system),
+                    )
+                    .with_system(process_emitter_hazards_system.after(load_object_positions_system))
+                    .with_system(record_state_hashes_system(SimulationStage::Game).at_end()),
             )
             .with_stage(
                 stage::PHYSICS,
@@ -252,7 +278,8 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                         RapierPhysicsPlugin::<()>::get_systems(PhysicsStages::Writeback)
                             .label(PhysicsSystemSetLabel::Writeback)
                             .after(PhysicsSystemSetLabel::StepSimulation),
-                    ),
+                    )
+                    .with_system(record_state_hashes_system(SimulationStage::Physics).at_end()),
             )
             .with_stage(
                 stage::POST_PHYSICS,
@@ -264,13 +291,15 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                     .with_system(sync_position_system)
                     .with_system_set(RapierPhysicsPlugin::<()>::get_systems(
                         PhysicsStages::DetectDespawn,
-                    )),
+                    ))
+                    .with_system(record_state_hashes_system(SimulationStage::PostPhysics).at_end()),
             )
             .with_stage(
                 stage::POST_GAME,
                 post_game_stage
                     .take()
-                    .expect("Can't initialize the plugin more than once"),
+                    .expect("Can't initialize the plugin more than once")
+                    .with_system(record_state_hashes_system(SimulationStage::PostGame).at_end()),
             )
             .with_stage(
                 stage::SIMULATION_FINAL,
@@ -307,12 +336,16 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
                             .label("update_level_objects")
                             .after("poll_shapes"),
                     )
+                    .with_system(
+                        update_level_settings_system.run_in_state(GameSessionState::Loading),
+                    )
                     .with_system(
                         tick_game_frame_system
                             .run_not_in_state(GameSessionState::Paused)
                             .after("update_level_objects"),
                     )
                     .with_system(process_spawned_entities_system.after(tick_game_frame_system))
+                    .with_system(collect_recorded_commands_system.after("update_level_objects"))
                     // Removing disconnected players doesn't depend on ticks, so it's fine to have
                     // in unordered.
                     .with_system(remove_disconnected_players_system),
@@ -373,24 +406,34 @@ impl<S: System<In = (), Out = ShouldRun>> Plugin for MuddleSharedPlugin<S> {
         app.add_startup_system(network_setup_system);
 
         #[cfg(feature = "client")]
-        app.add_startup_system(client::assets::init_muddle_assets_system);
+        app.add_startup_system(client::assets::init_muddle_assets_system)
+            .add_system(client::asset_gc::collect_level_object_assets_system);
 
         let world = &mut app.world;
         world.get_resource_or_insert_with(GameTime::default);
         world.get_resource_or_insert_with(SimulationTime::default);
         world.get_resource_or_insert_with(LevelState::default);
+        world.get_resource_or_insert_with(ColliderSimplification::default);
         world.get_resource_or_insert_with(PlayerUpdates::default);
         world.get_resource_or_insert_with(DeferredQueue::<SpawnPlayer>::default);
         world.get_resource_or_insert_with(DeferredQueue::<DespawnPlayer>::default);
         world.get_resource_or_insert_with(DeferredQueue::<UpdateLevelObject>::default);
         world.get_resource_or_insert_with(DeferredQueue::<DespawnLevelObject>::default);
+        world.get_resource_or_insert_with(DeferredQueue::<UpdateLevelSettings>::default);
         world.get_resource_or_insert_with(DeferredQueue::<SwitchPlayerRole>::default);
+        world.get_resource_or_insert_with(CommandLog::default);
+        world.get_resource_or_insert_with(DeterminismGuard::default);
         world.get_resource_or_insert_with(EntityRegistry::<PlayerNetId>::default);
         world.get_resource_or_insert_with(EntityRegistry::<EntityNetId>::default);
         world.get_resource_or_insert_with(Players::default);
         world.get_resource_or_insert_with(Events::<CollisionLogicChanged>::default);
         world.get_resource_or_insert_with(Events::<PlayerDeath>::default);
         world.get_resource_or_insert_with(Events::<PlayerFinish>::default);
+        world.get_resource_or_insert_with(Events::<LevelObjectShapeInvalid>::default);
+        world.get_resource_or_insert_with(Tethers::default);
+        world.get_resource_or_insert_with(RerunDirtyFlags::default);
+        #[cfg(feature = "client")]
+        world.get_resource_or_insert_with(client::asset_gc::LevelObjectAssetsGc::default);
         // Is used only on the server side.
         world.get_resource_or_insert_with(DeferredMessagesQueue::<SwitchRole>::default);
 
@@ -458,6 +501,8 @@ pub struct SimulationTime {
     pub server_frame: FrameNumber,
     pub server_generation: u64,
     player_frames_to_rerun: Option<FrameNumber>,
+    rewind_count: u64,
+    last_rewind_depth: u16,
 }
 
 impl Default for SimulationTime {
@@ -468,6 +513,8 @@ impl Default for SimulationTime {
             server_frame: Default::default(),
             server_generation: 1,
             player_frames_to_rerun: Default::default(),
+            rewind_count: 0,
+            last_rewind_depth: 0,
         }
     }
 }
@@ -484,6 +531,16 @@ impl SimulationTime {
         }
     }
 
+    /// The player generation that a frame will belong to, assuming the frame
+    /// is in the future (but less than half of the counter range ahead).
+    pub fn player_generation_at(&self, frame_number: FrameNumber) -> u64 {
+        if frame_number.value() < self.player_frame.value() {
+            self.player_generation + 1
+        } else {
+            self.player_generation
+        }
+    }
+
     pub fn rewind(&mut self, frame_number: FrameNumber) {
         let prev_server = self.server_frame;
         let prev_player = self.player_frame;
@@ -535,6 +592,11 @@ impl SimulationTime {
             self.player_frame = frame_number;
         }
 
+        if prev_player > self.player_frame {
+            self.rewind_count += 1;
+            self.last_rewind_depth = (prev_player - self.player_frame).value();
+        }
+
         log::trace!(
             "Rewind to {{server: {} (prev: {}), player: {} (prev: {}), frame: {}}}",
             self.server_frame,
@@ -545,6 +607,17 @@ impl SimulationTime {
         );
     }
 
+    /// Counts the rewinds that have moved the player frame back, so that debug
+    /// tools can spot them by comparing the value with the previous one.
+    pub fn rewind_count(&self) -> u64 {
+        self.rewind_count
+    }
+
+    /// The number of player frames that the latest rewind has moved back.
+    pub fn last_rewind_depth(&self) -> u16 {
+        self.last_rewind_depth
+    }
+
     pub fn player_frames_ahead(&self) -> u16 {
         assert!(self.player_frame >= self.server_frame);
         (self.player_frame - self.server_frame).value()
@@ -576,6 +649,8 @@ impl SimulationTime {
             server_frame: self.server_frame - FrameNumber::new(1),
             server_generation,
             player_frames_to_rerun: self.player_frames_to_rerun,
+            rewind_count: self.rewind_count,
+            last_rewind_depth: self.last_rewind_depth,
         }
     }
 
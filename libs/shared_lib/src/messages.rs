@@ -1,18 +1,30 @@
 use crate::{
+    constants::ConstantsFingerprint,
+    delta_compression::EncodedPlayerStates,
     framebuffer::FrameNumber,
     game::{
         commands,
         commands::UpdateLevelObject,
-        level::{LevelObject, LevelObjectDesc},
+        determinism::{StateHashMessage, StateHashRequest},
+        game_mode::{GameModeKind, MatchPlayerStats},
+        level::{
+            ColliderShapeError, LevelObject, LevelObjectDesc, LevelSettings, LevelValidationError,
+            Medal,
+        },
+        level_objects::ColliderSimplification,
+        tether::Tethers,
     },
+    id_allocator::{IdAllocator, RawId},
     net::{MessageId, SessionId},
     player::{Player, PlayerRole},
     registry::IncrementId,
+    SIMULATIONS_PER_SECOND,
 };
 use bevy::{
     ecs::{component::Component, system::Resource},
     math::Vec2,
     prelude::{Deref, DerefMut},
+    utils::Uuid,
 };
 use serde::{Deserialize, Serialize};
 
@@ -37,15 +49,19 @@ impl<T: Serialize> DeferredMessagesQueue<T> {
     pub fn drain(&mut self) -> Vec<T> {
         std::mem::take(&mut self.messages)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.messages.iter()
+    }
 }
 
-// TODO: refactor to be a part of entity registry, implement reclaiming ids of
-// removed entities.
 #[derive(Component, Serialize, Deserialize, Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct EntityNetId(pub u16);
 
+/// Is used by the server to allocate ids of level objects. The ids of a loaded
+/// level must be reserved before any new objects get spawned.
 #[derive(Resource, Deref, DerefMut, Default)]
-pub struct EntityNetIdCounter(pub EntityNetId);
+pub struct EntityNetIdAllocator(pub IdAllocator<EntityNetId>);
 
 impl IncrementId for EntityNetId {
     fn increment(&mut self) -> Self {
@@ -55,14 +71,20 @@ impl IncrementId for EntityNetId {
     }
 }
 
-// TODO: refactor to be a part of player registry, implement reclaiming ids of
-// removed players.
+impl RawId for EntityNetId {
+    fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    fn raw(self) -> u16 {
+        self.0
+    }
+}
+
+/// Player ids are allocated by the server's `PlayerConnections` registry.
 #[derive(Serialize, Deserialize, Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct PlayerNetId(pub u16);
 
-#[derive(Resource, Deref, DerefMut, Default)]
-pub struct PlayerNetIdCounter(pub PlayerNetId);
-
 impl IncrementId for PlayerNetId {
     fn increment(&mut self) -> Self {
         let old = *self;
@@ -71,6 +93,16 @@ impl IncrementId for PlayerNetId {
     }
 }
 
+impl RawId for PlayerNetId {
+    fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    fn raw(self) -> u16 {
+        self.0
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Message<T> {
     pub session_id: SessionId,
@@ -81,6 +113,9 @@ pub struct Message<T> {
 pub enum UnreliableClientMessage {
     Connect(MessageId),
     PlayerUpdate(PlayerUpdate),
+    /// Is accepted from runners and builders, once per
+    /// `PING_MIN_INTERVAL_MILLIS`.
+    Ping(Ping),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -92,11 +127,210 @@ pub enum ReliableClientMessage {
     Handshake {
         message_id: MessageId,
         id_token: Option<String>,
+        /// Identifies players without an account (i.e. without `id_token`),
+        /// so that servers keep their nicknames and stats when they reconnect.
+        /// Is generated by clients and stored along with their settings.
+        guest_id: Option<Uuid>,
+        /// Is chosen by guests, servers pick a random one if it's empty.
+        guest_nickname: Option<String>,
+        /// Servers reject clients built with different gameplay constants.
+        constants: ConstantsFingerprint,
     },
     SwitchRole(PlayerRole),
     SpawnLevelObject(SpawnLevelObjectRequest),
     UpdateLevelObject(LevelObject),
     DespawnLevelObject(EntityNetId),
+    UpdateLevelSettings(LevelSettings),
+    /// Is accepted only in practice sessions, i.e. when the player is the only
+    /// one connected to the server.
+    RestartFromCheckpoint(PracticeCheckpoint),
+    /// Is accepted only in practice sessions as well.
+    PracticeBots(PracticeBotsRequest),
+    /// Is accepted only on solo practice servers.
+    Teleport(TeleportRequest),
+    /// Is accepted only from builders of a persisted level.
+    PublishLevel(PublishLevelRequest),
+    /// Is accepted only from builders of a persisted level. The server
+    /// re-fetches the level and applies the difference, so that the changes
+    /// saved by builders on another server show up without restarting this
+    /// one. Edits that haven't been saved on this server are overwritten.
+    ReloadLevel,
+    /// Is sent when a client stops being rendered (a browser tab gets
+    /// backgrounded, for instance), so that the server doesn't wait for the
+    /// connection to time out. The client rejoins once it's visible again.
+    Suspend,
+    /// Is sent to bisect a divergence after a mismatching
+    /// `StateHashMessage::Checkpoint`.
+    StateHashRequest(StateHashRequest),
+    /// Is executed only if the player has the corresponding permission, see
+    /// `StartGame::admin_permissions`.
+    AdminCommand(AdminCommand),
+}
+
+/// A manual checkpoint set by a runner, to be able to replay a difficult
+/// section of a level without rerunning the whole level.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PracticeCheckpoint {
+    pub frame_number: FrameNumber,
+    pub position: Vec2,
+}
+
+/// Respawns the runner at the position, as if it was a checkpoint. Unlike
+/// checkpoints, the position doesn't have to be reached first.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TeleportRequest {
+    pub position: Vec2,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PracticeBotsRequest {
+    Spawn(BotDifficulty),
+    RemoveAll,
+}
+
+/// Mirrors the admin permissions that the persistence service stores for
+/// registered users. Guests don't have any.
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AdminPermissions {
+    pub kick: bool,
+    pub pause: bool,
+    pub reload: bool,
+    pub cvars: bool,
+    pub broadcast: bool,
+    pub ban: bool,
+}
+
+impl AdminPermissions {
+    pub fn any(&self) -> bool {
+        self.kick || self.pause || self.reload || self.cvars || self.broadcast || self.ban
+    }
+
+    pub fn allows(&self, command: &AdminCommand) -> bool {
+        match command {
+            AdminCommand::Kick(_) => self.kick,
+            AdminCommand::Pause(_) => self.pause,
+            AdminCommand::Reload => self.reload,
+            AdminCommand::SetCvar { .. } => self.cvars,
+            AdminCommand::Broadcast(_) => self.broadcast,
+            AdminCommand::Ban { .. } | AdminCommand::Unban(_) => self.ban,
+        }
+    }
+}
+
+pub const ADMIN_BROADCAST_MAX_LEN: usize = 280;
+/// Runtime config settings that can be changed with `AdminCommand::SetCvar`.
+pub const ADMIN_CVARS: [&str; 4] = [
+    "idle_timeout_millis",
+    "tether_distance",
+    "determinism_guard",
+    "interest_radius",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AdminCommand {
+    Kick(PlayerNetId),
+    /// Stops (`true`) or resumes (`false`) the simulation on the server.
+    Pause(bool),
+    /// Re-reads the level and runtime config files, if the server watches them.
+    Reload,
+    /// Changes one of the runtime config settings, the value is parsed as JSON.
+    SetCvar {
+        name: String,
+        value: String,
+    },
+    Broadcast(String),
+    /// Kicks the matching players and stores the ban in the persistence
+    /// service, so that it's enforced by every server. Bans without a duration
+    /// are permanent.
+    Ban {
+        target: BanTarget,
+        duration_secs: Option<u64>,
+        reason: String,
+    },
+    Unban(BanTarget),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum BanTarget {
+    /// Bans the player's user id (if they are registered) and their IP address.
+    Player(PlayerNetId),
+    User(i64),
+    /// An IP address or a range in CIDR notation.
+    IpRange(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PublishLevelRequest {
+    /// Runs the checks without publishing the level.
+    Check,
+    /// Runs the checks and publishes the level if none of them fail.
+    Publish,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PublishLevelReport {
+    pub checks: Vec<LevelCheck>,
+    pub status: PublishLevelStatus,
+}
+
+impl PublishLevelReport {
+    pub fn has_errors(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.severity == LevelCheckSeverity::Error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PublishLevelStatus {
+    Checked,
+    Published,
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LevelCheck {
+    pub severity: LevelCheckSeverity,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelCheckSeverity {
+    Passed,
+    Warning,
+    /// Blocks publishing.
+    Error,
+}
+
+/// The object gets despawned, so it carries the label for displaying it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InvalidLevelObjectShape {
+    pub net_id: EntityNetId,
+    pub label: String,
+    pub error: ColliderShapeError,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BotDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl BotDifficulty {
+    pub const ALL: [BotDifficulty; 3] = [
+        BotDifficulty::Easy,
+        BotDifficulty::Normal,
+        BotDifficulty::Hard,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BotDifficulty::Easy => "Easy",
+            BotDifficulty::Normal => "Normal",
+            BotDifficulty::Hard => "Hard",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -127,8 +361,35 @@ pub enum ReliableServerMessage {
     SpawnLevelObject(SpawnLevelObject),
     UpdateLevelObject(commands::UpdateLevelObject),
     DespawnLevelObject(commands::DespawnLevelObject),
+    UpdateLevelSettings(commands::UpdateLevelSettings),
     SwitchRole(SwitchRole),
     RespawnPlayer(RespawnPlayer),
+    /// Is sent as a response to client's `ReliableClientMessage::PublishLevel`.
+    PublishLevelReport(PublishLevelReport),
+    /// Is sent only if the server has the determinism guard enabled.
+    StateHash(StateHashMessage),
+    /// Is broadcast every time the server re-pairs tethered runners.
+    UpdateTethers(Tethers),
+    /// Is sent to the builder who has spawned or updated a level object,
+    /// if its collider shape can't be calculated.
+    InvalidLevelObjectShape(InvalidLevelObjectShape),
+    /// Is broadcast when an autosave gets rejected because the level is
+    /// invalid, and with an empty list once the level is saved again.
+    LevelSaveErrors(Vec<LevelValidationError>),
+    /// Is sent right before `Disconnect`, for the client to summarize the
+    /// session.
+    SessionStats(SessionStats),
+    /// A message from an admin to everyone on the server.
+    AdminBroadcast(String),
+    /// Is broadcast once the server decides a match of the current game mode,
+    /// the next match starts right away.
+    MatchEnded(MatchEnded),
+    /// Is sent only if the server has interest management enabled.
+    UpdateAreaOfInterest(AreaOfInterestUpdate),
+    /// Is broadcast once the server starts shutting down, the connections get
+    /// closed with `DisconnectReason::ServerShutdown` in the given amount of
+    /// seconds.
+    ShutdownCountdown(u16),
     Disconnect(DisconnectReason),
 }
 
@@ -139,6 +400,21 @@ pub enum DisconnectReason {
     Timeout,
     Closed,
     Aborted,
+    Suspended,
+    /// The client has been sending inputs that an unmodified client can't send.
+    CheatSuspected,
+    Kicked,
+    /// Bans without `expires_at` are permanent.
+    Banned {
+        expires_at: Option<chrono::NaiveDateTime>,
+    },
+    /// The server has been idle or drained, and is about to exit.
+    ServerShutdown,
+    /// The client has been built with different gameplay constants, see
+    /// `ConstantsFingerprint`.
+    ConstantsMismatch,
+    /// The server is allocated for solo practice of another player.
+    PrivateServer,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -150,8 +426,18 @@ pub struct PlayerUpdate {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum PlayerInputs {
-    Runner { inputs: Vec<RunnerInput> },
-    Builder,
+    Runner {
+        inputs: Vec<RunnerInput>,
+    },
+    Builder {
+        camera_position: Vec2,
+        selected_object: Option<EntityNetId>,
+    },
+    Spectator,
+    /// Replaces inputs that the server has already acknowledged. Updates are
+    /// still sent every broadcast tick, as they acknowledge delta updates,
+    /// which keeps syncing the clock and the delta compression baselines.
+    Unchanged,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -159,6 +445,8 @@ pub enum UnreliableServerMessage {
     /// Is sent as a response to client's `UnreliableClientMessage::Connect`.
     Handshake(MessageId),
     DeltaUpdate(DeltaUpdate),
+    /// Is broadcast to everyone except the player who has placed the ping.
+    Ping(PlayerPing),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -169,8 +457,14 @@ pub struct StartGame {
     pub uuid: String,
     pub nickname: String,
     pub objects: Vec<commands::UpdateLevelObject>,
+    pub settings: LevelSettings,
     pub players: Vec<(PlayerNetId, Player)>,
     pub level_id: Option<i64>,
+    pub level_title: Option<String>,
+    pub collider_simplification: ColliderSimplification,
+    pub tethers: Tethers,
+    pub admin_permissions: AdminPermissions,
+    pub game_mode: GameModeKind,
     pub generation: u64,
     /// Full game state encoded as a DeltaUpdate.
     pub game_state: DeltaUpdate,
@@ -181,12 +475,72 @@ pub struct DisconnectedPlayer {
     pub net_id: PlayerNetId,
 }
 
+/// Players outside a client's area of interest aren't included in its
+/// `DeltaUpdate` messages, until they enter the area again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AreaOfInterestUpdate {
+    pub frame_number: FrameNumber,
+    pub entered: Vec<PlayerNetId>,
+    pub left: Vec<PlayerNetId>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct DeltaUpdate {
     pub frame_number: FrameNumber,
     /// Frame number is `None` if a player hasn't sent any input yet.
     pub acknowledgments: (Option<FrameNumber>, u64),
-    pub players: Vec<PlayerState>,
+    /// Are encoded against the latest update that a client has acknowledged,
+    /// see `delta_compression::encode_player_states`.
+    pub players: EncodedPlayerStates,
+    /// Builders don't have bodies, so they aren't a part of `players`.
+    pub builders: Vec<BuilderState>,
+    pub server_health: ServerHealth,
+}
+
+/// Lets clients tell an overloaded server from a bad network, as both result
+/// in late updates.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServerHealth {
+    pub tick_duration: TickDurationBucket,
+    /// The number of simulation ticks the server was behind the schedule when
+    /// the update was broadcast. Updates of a lagging server arrive in bursts.
+    pub broadcast_queue_depth: u8,
+}
+
+impl ServerHealth {
+    pub fn is_overloaded(&self) -> bool {
+        self.tick_duration == TickDurationBucket::Overrun || self.broadcast_queue_depth > 1
+    }
+}
+
+/// The duration of the last simulated tick, relative to the time budget of a
+/// tick (`1 / SIMULATIONS_PER_SECOND`).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TickDurationBucket {
+    /// Under a quarter of the budget.
+    #[default]
+    Light,
+    /// Under a half of the budget.
+    Moderate,
+    /// Under the budget.
+    Heavy,
+    /// The tick took longer than the budget, the server can't keep up.
+    Overrun,
+}
+
+impl TickDurationBucket {
+    pub fn from_duration(duration: std::time::Duration) -> Self {
+        let budget_share = duration.as_secs_f32() * SIMULATIONS_PER_SECOND;
+        if budget_share < 0.25 {
+            Self::Light
+        } else if budget_share < 0.5 {
+            Self::Moderate
+        } else if budget_share < 1.0 {
+            Self::Heavy
+        } else {
+            Self::Overrun
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -198,6 +552,43 @@ pub struct PlayerState {
     pub direction: Vec2,
 }
 
+/// Pings are throttled by the server, clients also don't let players place
+/// them more often.
+pub const PING_MIN_INTERVAL_MILLIS: u64 = 500;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PingKind {
+    LookHere,
+    Danger,
+    BuildHere,
+}
+
+impl PingKind {
+    pub const ALL: [PingKind; 3] = [PingKind::LookHere, PingKind::Danger, PingKind::BuildHere];
+}
+
+/// A temporary marker that players place to point others at something.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Ping {
+    pub position: Vec2,
+    pub kind: PingKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PlayerPing {
+    pub net_id: PlayerNetId,
+    pub ping: Ping,
+}
+
+/// Lets other players see what builders are up to.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BuilderState {
+    pub net_id: PlayerNetId,
+    /// The position that the builder's camera is looking at.
+    pub camera_position: Vec2,
+    pub selected_object: Option<EntityNetId>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct RunnerInput {
     pub frame_number: FrameNumber,
@@ -224,10 +615,49 @@ pub struct RespawnPlayer {
     pub net_id: PlayerNetId,
     pub reason: RespawnPlayerReason,
     pub frame_number: FrameNumber,
+    /// Is `None` for deaths and finishes of runs that weren't timed.
+    pub finish: Option<FinishResult>,
+}
+
+/// Runs resumed from practice checkpoints aren't timed, so they don't get
+/// results.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FinishResult {
+    /// The number of simulated frames since the run has started.
+    pub frames: u32,
+    pub medal: Option<Medal>,
+}
+
+impl FinishResult {
+    pub fn secs(&self) -> f32 {
+        self.frames as f32 / SIMULATIONS_PER_SECOND
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MatchEnded {
+    pub game_mode: GameModeKind,
+    pub winner: Option<PlayerNetId>,
+    pub standings: Vec<(PlayerNetId, MatchPlayerStats)>,
+}
+
+/// The counters of a disconnecting player, as they are known to the server.
+/// They cover only the latest connection, i.e. they start from zero after
+/// rejoining.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionStats {
+    pub finishes: u32,
+    pub deaths: u32,
+    pub best_finish: Option<FinishResult>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RespawnPlayerReason {
     Finish,
     Death,
+    Checkpoint,
+}
+
+impl RespawnPlayerReason {
+    pub const ALL: [RespawnPlayerReason; 3] = [Self::Finish, Self::Death, Self::Checkpoint];
 }
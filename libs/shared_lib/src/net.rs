@@ -1,4 +1,5 @@
 use crate::{
+    delta_compression::PlayerStatesHistory,
     framebuffer::FrameNumber,
     messages::{
         DisconnectReason, Message, ReliableClientMessage, ReliableServerMessage,
@@ -12,11 +13,17 @@ use bevy_disturbulence::{
     ConnectionChannelsBuilder, MessageChannelMode, MessageChannelSettings, NetworkResource,
     ReliableChannelSettings, UnreliableChannelSettings,
 };
+use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, time::Duration};
 use thiserror::Error;
 
 pub const CONNECTION_TIMEOUT_MILLIS: u64 = 10000;
 const NET_STAT_UPDATE_FACTOR: f32 = 0.2;
+/// Buffers that grew past this capacity (i.e. while serializing a level) are
+/// dropped instead of being returned to the pool, to avoid holding on to their
+/// memory.
+const MAX_POOLED_BUFFER_CAPACITY: usize = 16 * 1024;
+const MAX_POOLED_BUFFERS: usize = 256;
 
 pub type MessageId = WrappedCounter<u16>;
 pub type SessionId = WrappedCounter<u16>;
@@ -79,6 +86,9 @@ pub struct ConnectionState {
     packet_loss: f32,
     jitter_millis: f32,
     rtt_millis: f32,
+    /// Player states of the `DeltaUpdate` messages that we've sent (or
+    /// received), which newer updates are encoded against.
+    pub player_states_history: PlayerStatesHistory,
 }
 
 impl Default for ConnectionState {
@@ -95,6 +105,7 @@ impl Default for ConnectionState {
             packet_loss: 0.0,
             jitter_millis: 0.0,
             rtt_millis: 100.0,
+            player_states_history: PlayerStatesHistory::default(),
         }
     }
 }
@@ -149,6 +160,13 @@ impl ConnectionState {
             .map(|ack| ack.frame_number)
     }
 
+    pub fn newest_acknowledged_outgoing_packet(&self) -> Option<FrameNumber> {
+        self.outgoing_packets_acks
+            .iter()
+            .rfind(|ack| ack.is_acknowledged)
+            .map(|ack| ack.frame_number)
+    }
+
     pub fn set_status(&mut self, status: ConnectionStatus) {
         let session_id = self.session_id;
         let handshake_id = self.handshake_id;
@@ -413,6 +431,68 @@ impl Acknowledgment {
     }
 }
 
+/// Reuses `Vec<u8>` buffers for serializing messages, so that encoding updates
+/// for every connection at each tick doesn't allocate fresh buffers.
+///
+/// Buffers are taken either with `take` or `serialize`, and are expected to be
+/// given back with `give_back` once the bytes are sent (or decoded).
+#[derive(Resource, Default)]
+pub struct MessageBufferPool {
+    buffers: Vec<Vec<u8>>,
+    allocated_count: u64,
+}
+
+impl MessageBufferPool {
+    /// Returns an empty buffer, which keeps the capacity of a previously used
+    /// one if there are any.
+    pub fn take(&mut self) -> Vec<u8> {
+        self.buffers.pop().unwrap_or_else(|| {
+            self.allocated_count += 1;
+            Vec::new()
+        })
+    }
+
+    pub fn give_back(&mut self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > MAX_POOLED_BUFFER_CAPACITY
+            || self.buffers.len() == MAX_POOLED_BUFFERS
+        {
+            return;
+        }
+        buffer.clear();
+        self.buffers.push(buffer);
+    }
+
+    pub fn serialize<T: Serialize>(&mut self, value: &T) -> bincode::Result<Vec<u8>> {
+        let mut buffer = self.take();
+        match serialize_into(&mut buffer, value) {
+            Ok(()) => Ok(buffer),
+            Err(err) => {
+                self.give_back(buffer);
+                Err(err)
+            }
+        }
+    }
+
+    /// Counts the buffers that the pool had to allocate since it was created,
+    /// i.e. when there wasn't any buffer to reuse.
+    pub fn allocated_count(&self) -> u64 {
+        self.allocated_count
+    }
+}
+
+/// Replaces the contents of `buffer` with the serialized `value`, keeping its
+/// capacity.
+pub fn serialize_into<T: Serialize>(buffer: &mut Vec<u8>, value: &T) -> bincode::Result<()> {
+    buffer.clear();
+    bincode::serialize_into(buffer, value)
+}
+
+/// Decoded messages may borrow from `bytes`, so the buffer can be given back to
+/// `MessageBufferPool` only after the message is dropped.
+pub fn deserialize<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> bincode::Result<T> {
+    bincode::deserialize(bytes)
+}
+
 pub fn network_setup_system(mut net: NonSendMut<NetworkResource>) {
     net.set_channels_builder(|builder: &mut ConnectionChannelsBuilder| {
         builder
@@ -501,11 +581,16 @@ const SERVER_DELTA_UPDATE_MESSAGE_SETTINGS: MessageChannelSettings = MessageChan
 #[cfg(test)]
 mod tests {
     use crate::{
+        delta_compression::PlayerStatesHistory,
         framebuffer::FrameNumber,
-        net::{Acknowledgment, ConnectionState, ConnectionStatus, MessageId, SessionId},
+        messages::{Message, PlayerInputs, PlayerUpdate, RunnerInput, UnreliableClientMessage},
+        net::{
+            deserialize, Acknowledgment, ConnectionState, ConnectionStatus, MessageBufferPool,
+            MessageId, SessionId, MAX_POOLED_BUFFER_CAPACITY,
+        },
         TICKS_PER_NETWORK_BROADCAST,
     };
-    use bevy::utils::Instant;
+    use bevy::{math::Vec2, utils::Instant};
     use std::collections::VecDeque;
 
     macro_rules! assert_eq_bitset {
@@ -548,6 +633,7 @@ mod tests {
             packet_loss: 0.0,
             jitter_millis: 0.0,
             rtt_millis: 0.0,
+            player_states_history: PlayerStatesHistory::default(),
         }
     }
 
@@ -699,4 +785,47 @@ mod tests {
             0b1111111111111111000000000000000000000000000000000000000000000001,
         );
     }
+
+    fn player_update(frame_number: u16) -> Message<UnreliableClientMessage> {
+        Message {
+            session_id: SessionId::new(0),
+            message: UnreliableClientMessage::PlayerUpdate(PlayerUpdate {
+                frame_number: FrameNumber::new(frame_number),
+                acknowledgments: (Some(FrameNumber::new(frame_number)), u64::MAX),
+                inputs: PlayerInputs::Runner {
+                    inputs: vec![RunnerInput {
+                        frame_number: FrameNumber::new(frame_number),
+                        direction: Vec2::X,
+                    }],
+                },
+            }),
+        }
+    }
+
+    #[test]
+    fn test_message_buffer_pool_reuses_buffers() {
+        let mut pool = MessageBufferPool::default();
+        for frame_number in 0..10 {
+            let message = player_update(frame_number);
+            let buffer = pool.serialize(&message).unwrap();
+            assert_eq!(buffer, bincode::serialize(&message).unwrap());
+            assert_eq!(
+                deserialize::<Message<UnreliableClientMessage>>(&buffer).unwrap(),
+                message
+            );
+            pool.give_back(buffer);
+        }
+        assert_eq!(pool.allocated_count(), 1);
+
+        // Serializing into a reused buffer doesn't leave the previous bytes behind.
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(&[0xff; 64]);
+        pool.give_back(buffer);
+        let buffer = pool.serialize(&player_update(1)).unwrap();
+        assert_eq!(buffer, bincode::serialize(&player_update(1)).unwrap());
+
+        // Buffers that have grown too large aren't kept.
+        pool.give_back(Vec::with_capacity(MAX_POOLED_BUFFER_CAPACITY + 1));
+        assert!(pool.buffers.is_empty());
+    }
 }
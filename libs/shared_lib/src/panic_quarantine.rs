@@ -0,0 +1,64 @@
+//! Isolates panics of computations that are known to panic on some inputs
+//! (such as convex decomposition in parry, see
+//! https://github.com/dimforge/rapier/issues/223), so that they don't take the
+//! whole process down.
+
+use std::{
+    any::Any,
+    cell::Cell,
+    panic::{catch_unwind, AssertUnwindSafe},
+};
+
+thread_local! {
+    static IS_QUARANTINED: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f`, converting its panic into an error with the panic message.
+///
+/// Panic hooks still get called for such panics, they are expected to check
+/// [`is_quarantined`] to avoid treating them as fatal.
+pub fn quarantine<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    let was_quarantined = IS_QUARANTINED.with(|is_quarantined| is_quarantined.replace(true));
+    let result = catch_unwind(AssertUnwindSafe(f));
+    IS_QUARANTINED.with(|is_quarantined| is_quarantined.set(was_quarantined));
+    result.map_err(panic_message)
+}
+
+/// Returns `true` if called from a panic hook for a panic that is going to be
+/// caught by [`quarantine`].
+pub fn is_quarantined() -> bool {
+    IS_QUARANTINED.with(Cell::get)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Unknown panic".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine() {
+        assert!(!is_quarantined());
+        assert_eq!(quarantine(|| 42), Ok(42));
+        assert_eq!(
+            quarantine(|| {
+                assert!(is_quarantined());
+                panic!("Invalid {}", "shape");
+            }),
+            Err::<(), _>("Invalid shape".to_owned())
+        );
+        assert_eq!(
+            quarantine(|| panic!("Invalid shape")),
+            Err::<(), _>("Invalid shape".to_owned())
+        );
+        assert!(!is_quarantined());
+    }
+}
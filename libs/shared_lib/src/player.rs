@@ -1,6 +1,6 @@
 use crate::{
     framebuffer::{FrameNumber, Framebuffer},
-    messages::{PlayerNetId, RespawnPlayerReason},
+    messages::{FinishResult, PlayerNetId, RespawnPlayerReason},
     registry::EntityRegistry,
 };
 use bevy::{
@@ -16,6 +16,17 @@ use std::marker::PhantomData;
 #[derive(Resource, Deref, DerefMut, Default)]
 pub struct Players(pub HashMap<PlayerNetId, Player>);
 
+impl Players {
+    /// A practice session is when there's only one (human) player connected.
+    /// Such players can restart from checkpoints and race against bots.
+    pub fn is_practice_session(&self) -> bool {
+        self.values()
+            .filter(|player| player.is_connected && !player.is_bot)
+            .count()
+            == 1
+    }
+}
+
 #[derive(Debug)]
 pub enum PlayerEvent {
     Connected(String),
@@ -38,7 +49,7 @@ pub struct PlayerUpdates {
     pub position: HashMap<PlayerNetId, Framebuffer<Option<Vec2>>>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PlayerDirectionUpdate {
     pub direction: Vec2,
     pub is_processed_client_input: Option<bool>,
@@ -94,6 +105,12 @@ pub struct Player {
     pub is_connected: bool,
     pub finishes: u32,
     pub deaths: u32,
+    /// Bots are simulated by the server and don't have connections.
+    pub is_bot: bool,
+    /// The fastest timed finish within the current session.
+    pub best_finish: Option<FinishResult>,
+    /// Totals of the previous sessions, are known only for registered players.
+    pub lifetime_stats: Option<LifetimeStats>,
 }
 
 impl Player {
@@ -106,6 +123,9 @@ impl Player {
             is_connected: true,
             finishes: 0,
             deaths: 0,
+            is_bot: false,
+            best_finish: None,
+            lifetime_stats: None,
         }
     }
 
@@ -118,14 +138,47 @@ impl Player {
             is_connected: true,
             finishes: 0,
             deaths: 0,
+            is_bot: false,
+            best_finish: None,
+            lifetime_stats: None,
+        }
+    }
+
+    /// Includes the current session.
+    pub fn lifetime_finishes(&self) -> Option<u32> {
+        self.lifetime_stats
+            .map(|stats| stats.finishes.saturating_add(self.finishes))
+    }
+
+    /// Includes the current session.
+    pub fn lifetime_deaths(&self) -> Option<u32> {
+        self.lifetime_stats
+            .map(|stats| stats.deaths.saturating_add(self.deaths))
+    }
+
+    /// Keeps the result if it's the new best one.
+    pub fn record_finish(&mut self, result: FinishResult) {
+        if self
+            .best_finish
+            .map_or(true, |best_finish| result.frames < best_finish.frames)
+        {
+            self.best_finish = Some(result);
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LifetimeStats {
+    pub finishes: u32,
+    pub deaths: u32,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PlayerRole {
     Runner,
     Builder,
+    /// Watches the game without a runner body and can't edit the level.
+    Spectator,
 }
 
 pub fn random_name() -> String {
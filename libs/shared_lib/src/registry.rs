@@ -1,24 +1,44 @@
+use crate::id_allocator::{IdAllocationError, IdAllocator, RawId};
 use bevy::{prelude::*, utils::HashMap};
-use std::hash::Hash;
+use std::{hash::Hash, ops::Range};
 
-#[derive(Resource, Default)]
-pub struct Registry<K: Copy + Hash + IncrementId + Eq, V: Copy + Hash> {
-    counter: K,
+#[derive(Resource)]
+pub struct Registry<K: RawId, V: Copy + Hash> {
+    ids: IdAllocator<K>,
     value_by_id: HashMap<K, V>,
     id_by_value: HashMap<V, K>,
 }
 
-impl<K: Copy + Hash + IncrementId + Eq, V: Copy + Hash + Eq> Registry<K, V> {
+impl<K: RawId, V: Copy + Hash> Default for Registry<K, V> {
+    fn default() -> Self {
+        Self {
+            ids: IdAllocator::default(),
+            value_by_id: HashMap::default(),
+            id_by_value: HashMap::default(),
+        }
+    }
+}
+
+impl<K: RawId, V: Copy + Hash + Eq> Registry<K, V> {
     pub fn register(&mut self, value: V) -> K {
-        let net_id = self.counter.increment();
+        let net_id = self
+            .ids
+            .allocate()
+            .expect("Expected a free id to register a value");
         self.value_by_id.insert(net_id, value);
         self.id_by_value.insert(value, net_id);
         net_id
     }
 
+    /// Keeps the ids of the range from being allocated by `register`.
+    pub fn reserve_range(&mut self, range: Range<u16>) -> Result<(), IdAllocationError> {
+        self.ids.reserve_range(range)
+    }
+
     pub fn remove_by_value(&mut self, value: V) -> Option<K> {
         if let Some(id) = self.id_by_value.remove(&value) {
             self.value_by_id.remove(&id);
+            self.ids.free(id);
             return Some(id);
         }
         None
@@ -27,6 +47,7 @@ impl<K: Copy + Hash + IncrementId + Eq, V: Copy + Hash + Eq> Registry<K, V> {
     pub fn remove_by_id(&mut self, id: K) -> Option<V> {
         if let Some(value) = self.value_by_id.remove(&id) {
             self.id_by_value.remove(&value);
+            self.ids.free(id);
             return Some(value);
         }
         None
@@ -30,7 +30,7 @@ impl<'w, 's> LevelSpawnLocationService<'w, 's> {
     pub fn spawn_position(&self, frame_number: FrameNumber) -> Vec2 {
         let available_shapes = self
             .level_state
-            .spawn_areas
+            .spawn_areas()
             .iter()
             .copied()
             .filter_map(|net_id| {
@@ -0,0 +1,288 @@
+//! A headless simulation for testing the game logic without running a client
+//! or a server. [`TestApp`] wires `MuddleSharedPlugin` with a run criteria that
+//! advances the game only when asked to, so tests can step the simulation
+//! frame by frame, inject player inputs (including the late ones, followed by
+//! a rewind) and assert on the `Position` buffers.
+//!
+//! Other crates can use it in their tests by enabling the `test-harness`
+//! feature for their dev-dependency on `mr_shared_lib`.
+
+use crate::{
+    framebuffer::FrameNumber,
+    game::{
+        commands::{DeferredQueue, SpawnPlayer, UpdateLevelObject},
+        components::Position,
+        determinism::DeterminismGuard,
+        level::{CollisionLogic, LevelObject, LevelObjectDesc},
+        level_objects::CubeDesc,
+        replay::REPLAY_HASHED_STAGE,
+    },
+    messages::{EntityNetId, PlayerNetId},
+    player::{Player, PlayerDirectionUpdate, PlayerRole, PlayerUpdates, Players},
+    registry::EntityRegistry,
+    AppState, GameSessionState, GameTime, LevelObjectsToSpawnToLoad, MuddleSharedPlugin,
+    SimulationTime, COMPONENT_FRAMEBUFFER_LIMIT,
+};
+use bevy::{
+    app::App,
+    core::CorePlugin,
+    ecs::{
+        schedule::{ShouldRun, SystemStage},
+        system::{IntoSystem, ResMut, Resource},
+    },
+    math::Vec2,
+    time::TimePlugin,
+};
+use iyes_loopless::state::CurrentState;
+
+/// Loading levels with concave planes involves calculating their collider
+/// shapes in the background, which may take a while.
+const MAX_LOADING_UPDATES: usize = 600;
+
+/// The number of game frames that the main schedule is allowed to advance.
+#[derive(Resource, Default)]
+struct PendingTicks(usize);
+
+fn manual_run_criteria(mut pending_ticks: ResMut<PendingTicks>) -> ShouldRun {
+    if pending_ticks.0 > 0 {
+        pending_ticks.0 -= 1;
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+#[derive(Default)]
+pub struct TestAppBuilder {
+    level_objects: Vec<LevelObject>,
+    players: Vec<(PlayerNetId, Vec2)>,
+    determinism_guard: bool,
+}
+
+impl TestAppBuilder {
+    pub fn with_level_object(mut self, level_object: LevelObject) -> Self {
+        self.level_objects.push(level_object);
+        self
+    }
+
+    /// Players are spawned as runners at the first simulated frame.
+    pub fn with_player(mut self, net_id: PlayerNetId, start_position: Vec2) -> Self {
+        self.players.push((net_id, start_position));
+        self
+    }
+
+    /// Enables recording state hashes, see [`DeterminismGuard`].
+    pub fn with_determinism_guard(mut self) -> Self {
+        self.determinism_guard = true;
+        self
+    }
+
+    /// Builds the app and loads the level, so that the first frame is ready
+    /// to be simulated.
+    pub fn build(self) -> TestApp {
+        let mut app = App::new();
+        app.add_plugin(CorePlugin::default())
+            .add_plugin(TimePlugin::default())
+            .add_plugin(MuddleSharedPlugin::new(
+                IntoSystem::into_system(manual_run_criteria),
+                SystemStage::single_threaded(),
+                SystemStage::single_threaded(),
+                SystemStage::single_threaded(),
+                SystemStage::single_threaded(),
+                None,
+            ))
+            .init_resource::<PendingTicks>()
+            // There's no main menu to go through, as on the server.
+            .insert_resource(CurrentState(AppState::Playing))
+            .insert_resource(LevelObjectsToSpawnToLoad(self.level_objects.len()));
+        // Nobody tracks the players' sessions.
+        #[cfg(not(feature = "client"))]
+        app.insert_resource(crate::game::PlayerEventSender(None));
+        app.world.resource_mut::<DeterminismGuard>().enabled = self.determinism_guard;
+
+        for object in self.level_objects {
+            app.world
+                .resource_mut::<DeferredQueue<UpdateLevelObject>>()
+                .push(UpdateLevelObject {
+                    object,
+                    frame_number: FrameNumber::new(0),
+                });
+        }
+
+        let mut test_app = TestApp { app };
+        let mut loading_updates = 0;
+        while test_app.game_session_state() == GameSessionState::Loading {
+            loading_updates += 1;
+            assert!(
+                loading_updates <= MAX_LOADING_UPDATES,
+                "The level hasn't loaded in {MAX_LOADING_UPDATES} updates"
+            );
+            test_app.step();
+        }
+
+        let frame_number = test_app.simulation_time().server_frame;
+        for (net_id, start_position) in self.players {
+            test_app
+                .app
+                .world
+                .resource_mut::<Players>()
+                .insert(net_id, Player::new(PlayerRole::Runner));
+            test_app
+                .app
+                .world
+                .resource_mut::<DeferredQueue<SpawnPlayer>>()
+                .push(SpawnPlayer {
+                    net_id,
+                    start_position,
+                    is_player_frame_simulated: false,
+                });
+            test_app.set_player_direction(net_id, frame_number, Vec2::ZERO);
+        }
+        test_app
+    }
+}
+
+pub struct TestApp {
+    pub app: App,
+}
+
+impl TestApp {
+    pub fn builder() -> TestAppBuilder {
+        TestAppBuilder::default()
+    }
+
+    /// Advances the game by a single frame, simulating it along with the
+    /// frames that need to be re-simulated after a rewind.
+    pub fn step(&mut self) {
+        self.app.world.resource_mut::<PendingTicks>().0 = 1;
+        self.app.update();
+    }
+
+    pub fn step_frames(&mut self, frames: u16) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+    /// Sets the direction a player moves in, starting from the given frame.
+    /// If the frame has already been simulated, the simulation needs to be
+    /// rewound to apply the direction (see [`TestApp::rewind`]).
+    pub fn set_player_direction(
+        &mut self,
+        net_id: PlayerNetId,
+        frame_number: FrameNumber,
+        direction: Vec2,
+    ) {
+        self.app
+            .world
+            .resource_mut::<PlayerUpdates>()
+            .get_direction_mut(net_id, frame_number, COMPONENT_FRAMEBUFFER_LIMIT)
+            .insert(
+                frame_number,
+                Some(PlayerDirectionUpdate {
+                    direction,
+                    is_processed_client_input: None,
+                }),
+            );
+    }
+
+    /// The frames starting from `frame_number` get re-simulated with the next
+    /// step, as the server does when it receives late inputs.
+    pub fn rewind(&mut self, frame_number: FrameNumber) {
+        self.app
+            .world
+            .resource_mut::<SimulationTime>()
+            .rewind(frame_number);
+    }
+
+    pub fn game_time(&self) -> &GameTime {
+        self.app.world.resource::<GameTime>()
+    }
+
+    pub fn simulation_time(&self) -> &SimulationTime {
+        self.app.world.resource::<SimulationTime>()
+    }
+
+    pub fn game_session_state(&self) -> GameSessionState {
+        self.app
+            .world
+            .resource::<CurrentState<GameSessionState>>()
+            .0
+            .clone()
+    }
+
+    pub fn player_position_buffer(&self, net_id: PlayerNetId) -> Option<&Position> {
+        let entity = self
+            .app
+            .world
+            .resource::<EntityRegistry<PlayerNetId>>()
+            .get_entity(net_id)?;
+        self.app.world.get::<Position>(entity)
+    }
+
+    /// Returns the position of a player as of the end of the given frame.
+    pub fn player_position(&self, net_id: PlayerNetId, frame_number: FrameNumber) -> Option<Vec2> {
+        self.player_position_buffer(net_id)?
+            .buffer
+            .get(frame_number)
+            .copied()
+    }
+
+    /// Returns the hash of the state after simulating the given frame, if the
+    /// determinism guard is enabled.
+    pub fn state_hash(&self, frame_number: FrameNumber) -> Option<u64> {
+        self.app
+            .world
+            .resource::<DeterminismGuard>()
+            .stage_hash(frame_number, REPLAY_HASHED_STAGE)
+    }
+}
+
+/// A plain cube without a route or collision logic, for the tests that need
+/// level objects but don't care about their shapes.
+pub fn cube(net_id: u16, position: Vec2, size: f32) -> LevelObject {
+    LevelObject {
+        net_id: EntityNetId(net_id),
+        label: String::new(),
+        desc: LevelObjectDesc::Cube(CubeDesc {
+            position,
+            size,
+            appearance: Default::default(),
+        }),
+        route: None,
+        collision_logic: CollisionLogic::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_and_rewind() {
+        let player = PlayerNetId(1);
+        let mut test_app = TestApp::builder()
+            .with_player(player, Vec2::ZERO)
+            .with_determinism_guard()
+            .build();
+        let start_frame = test_app.simulation_time().server_frame;
+        test_app.set_player_direction(player, start_frame, Vec2::X);
+        test_app.step_frames(30);
+
+        let frame_number = test_app.simulation_time().server_frame - FrameNumber::new(1);
+        let position = test_app.player_position(player, frame_number).unwrap();
+        assert!(position.x > 0.0);
+        assert!(position.y.abs() < f32::EPSILON);
+        let hash = test_app.state_hash(frame_number);
+        assert!(hash.is_some());
+
+        // A late input changes the outcome once the frames get re-simulated.
+        let late_frame = start_frame + FrameNumber::new(10);
+        test_app.set_player_direction(player, late_frame, Vec2::Y);
+        test_app.rewind(late_frame);
+        test_app.step();
+        let rerun_position = test_app.player_position(player, frame_number).unwrap();
+        assert!(rerun_position.y > 0.0);
+        assert_ne!(test_app.state_hash(frame_number), hash);
+    }
+}
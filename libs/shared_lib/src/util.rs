@@ -14,6 +14,8 @@ use bevy_rapier2d::geometry::ColliderView;
 use rand::Rng;
 
 pub const PLAYER_RESPAWN_TIME: FrameNumber = FrameNumber::new(SIMULATIONS_PER_SECOND as u16 * 3);
+pub const PLAYER_CHECKPOINT_RESTART_TIME: FrameNumber =
+    FrameNumber::new(SIMULATIONS_PER_SECOND as u16 / 2);
 
 pub fn player_sensor_outline() -> Vec<Vec2> {
     let sensors_count = 8;
@@ -0,0 +1,235 @@
+//! Spawning tasks and waiting for timers, regardless of the target.
+//!
+//! Native builds run tasks with tokio, web builds run them on the browser's
+//! event loop with `wasm-bindgen-futures`. Natively, [`spawn_local`] has to be
+//! called from a future started with [`run_detached`] (or from within any other
+//! `tokio::task::LocalSet`).
+
+use futures::{channel::oneshot, FutureExt};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub use futures::channel::oneshot::Canceled;
+
+/// Resolves to the output of a spawned task, or to [`Canceled`] if the task
+/// panicked. Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T>(oneshot::Receiver<T>);
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.0.poll_unpin(cx)
+    }
+}
+
+fn with_join_handle<F: Future>(future: F) -> (impl Future<Output = ()>, JoinHandle<F::Output>) {
+    let (output_tx, output_rx) = oneshot::channel();
+    let task = async move {
+        // Fails if the handle has been dropped, which is fine.
+        let _ = output_tx.send(future.await);
+    };
+    (task, JoinHandle(output_rx))
+}
+
+/// Returned by [`timeout`] if the future didn't resolve in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// Awaits the future for at most `duration`. The future is dropped if it
+/// doesn't resolve in time.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    futures::select! {
+        output = future.fuse() => Ok(output),
+        _ = sleep(duration).fuse() => Err(Elapsed),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+#[cfg(target_arch = "wasm32")]
+pub use web::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use super::{with_join_handle, JoinHandle};
+    use std::{future::Future, time::Duration};
+
+    /// Runs the future on a new thread with a single-threaded runtime, so that
+    /// it can spawn local tasks.
+    pub fn run_detached<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Cannot start tokio runtime");
+
+            let local = tokio::task::LocalSet::new();
+            local.block_on(&rt, future);
+        });
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (task, join_handle) = with_join_handle(future);
+        tokio::spawn(task);
+        join_handle
+    }
+
+    pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (task, join_handle) = with_join_handle(future);
+        tokio::task::spawn_local(task);
+        join_handle
+    }
+
+    pub async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Ticks every `period`, the first tick completes immediately. If ticks
+    /// are missed, they complete one after another to catch up.
+    pub struct Interval(tokio::time::Interval);
+
+    /// Panics if `period` is zero.
+    pub fn interval(period: Duration) -> Interval {
+        Interval(tokio::time::interval(period))
+    }
+
+    impl Interval {
+        pub async fn tick(&mut self) {
+            self.0.tick().await;
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use super::{with_join_handle, JoinHandle};
+    use std::{future::Future, time::Duration};
+    use wasm_timer::Instant;
+
+    /// There's only one thread in a browser, so the future runs on the event
+    /// loop, as any other task.
+    pub fn run_detached<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        spawn_local(future)
+    }
+
+    pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let (task, join_handle) = with_join_handle(future);
+        wasm_bindgen_futures::spawn_local(task);
+        join_handle
+    }
+
+    pub async fn sleep(duration: Duration) {
+        // Browser timers don't fail.
+        wasm_timer::Delay::new(duration)
+            .await
+            .expect("Failed to wait for a timer");
+    }
+
+    /// Ticks every `period`, the first tick completes immediately. If ticks
+    /// are missed, they complete one after another to catch up.
+    pub struct Interval {
+        period: Duration,
+        next_tick: Option<Instant>,
+    }
+
+    /// Panics if `period` is zero.
+    pub fn interval(period: Duration) -> Interval {
+        assert!(period > Duration::ZERO, "`period` must be non-zero");
+        Interval {
+            period,
+            next_tick: None,
+        }
+    }
+
+    impl Interval {
+        pub async fn tick(&mut self) {
+            let now = Instant::now();
+            let next_tick = *self.next_tick.get_or_insert(now);
+            if next_tick > now {
+                sleep(next_tick - now).await;
+            }
+            self.next_tick = Some(next_tick + self.period);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        tokio::task::LocalSet::new().block_on(&rt, future)
+    }
+
+    #[test]
+    fn test_timeout() {
+        block_on(async {
+            assert_eq!(timeout(Duration::from_secs(5), async { 1 }).await, Ok(1));
+            assert_eq!(
+                timeout(Duration::from_millis(10), sleep(Duration::from_secs(5))).await,
+                Err(Elapsed)
+            );
+        });
+    }
+
+    #[test]
+    fn test_join_handle() {
+        block_on(async {
+            let local_handle = spawn_local(async {
+                sleep(Duration::from_millis(1)).await;
+                2
+            });
+            assert_eq!(local_handle.await, Ok(2));
+
+            let panicked_handle = spawn(async { panic!("Expected panic") });
+            assert_eq!(panicked_handle.await, Err::<(), _>(Canceled));
+
+            let mut interval = interval(Duration::from_millis(1));
+            interval.tick().await;
+            interval.tick().await;
+        });
+    }
+}
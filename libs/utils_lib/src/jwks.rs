@@ -1,4 +1,7 @@
-use crate::JwtAuthClaims;
+use crate::{
+    synthetic_auth::{SyntheticAuth, SYNTHETIC_AUDIENCE, SYNTHETIC_KEY_ID},
+    JwtAuthClaims,
+};
 #[cfg(feature = "bevy_logging")]
 use bevy::log;
 use headers::Header;
@@ -38,6 +41,8 @@ pub enum KeyUse {
 #[derive(Clone)]
 pub struct Jwks {
     keys: std::sync::Arc<tokio::sync::RwLock<Vec<Key>>>,
+    /// Is set only in the pentest mode, see [`SyntheticAuth::from_env`].
+    synthetic_auth: Option<SyntheticAuth>,
 }
 
 pub struct Key {
@@ -65,9 +70,14 @@ impl Jwks {
     pub fn new() -> Self {
         Self {
             keys: std::sync::Arc::new(tokio::sync::RwLock::new(Vec::new())),
+            synthetic_auth: SyntheticAuth::from_env(),
         }
     }
 
+    pub fn synthetic_auth(&self) -> Option<&SyntheticAuth> {
+        self.synthetic_auth.as_ref()
+    }
+
     pub async fn update(&self, issuer: &Url, new_keys: Vec<(String, RsaPublicKey)>) {
         let mut keys = self.keys.write().await;
         keys.retain(|key| key.certs_url != *issuer);
@@ -95,6 +105,19 @@ impl Jwks {
             return Err(InvalidTokenError::KeyIdMissing);
         };
 
+        if kid == SYNTHETIC_KEY_ID {
+            let Some(synthetic_auth) = &self.synthetic_auth else {
+                return Err(InvalidTokenError::UnknownSigner);
+            };
+            let verified_token = synthetic_auth
+                .verify(&token)
+                .map_err(InvalidTokenError::Invalid)?;
+            if verified_token.claims().custom.aud != SYNTHETIC_AUDIENCE {
+                return Err(InvalidTokenError::InvalidAudience);
+            }
+            return Ok(verified_token);
+        }
+
         let Some(key) = self.get(kid).await else {
             return Err(InvalidTokenError::UnknownSigner)
         };
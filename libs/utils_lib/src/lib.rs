@@ -3,10 +3,15 @@
 use serde::{Deserialize, Serialize};
 
 pub mod env;
+#[cfg(feature = "executor")]
+pub mod executor;
 #[cfg(feature = "jwks")]
 pub mod jwks;
 #[cfg(feature = "kube_discovery")]
 pub mod kube_discovery;
+#[cfg(feature = "jwks")]
+pub mod synthetic_auth;
+pub mod telemetry;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct JwtAuthClaims {
@@ -0,0 +1,135 @@
+use crate::JwtAuthClaims;
+#[cfg(feature = "bevy_logging")]
+use bevy::log;
+use jwt_compact::{
+    alg::{Hs256, Hs256Key},
+    AlgorithmExt, Claims, CreationError, Header, Token, UntrustedToken, ValidationError,
+};
+
+/// Enables accepting synthetic identities, is respected only if `MUDDLE_ENV`
+/// equals `staging`.
+pub const PENTEST_MODE_ENV: &str = "MUDDLE_PENTEST_MODE";
+/// The secret synthetic tokens are signed with, it must be the same for all the
+/// services and the scenario runner.
+pub const SYNTHETIC_AUTH_SECRET_ENV: &str = "MUDDLE_SYNTHETIC_AUTH_SECRET";
+
+pub const SYNTHETIC_ISSUER: &str = "https://synthetic.muddle.run";
+pub const SYNTHETIC_AUDIENCE: &str = "muddle-run-synthetic";
+/// Synthetic tokens are told apart from the ones issued by Google and Auth0 by
+/// this `kid` header value.
+pub const SYNTHETIC_KEY_ID: &str = "synthetic";
+
+const STAGING_ENV: &str = "staging";
+
+/// Issues and verifies tokens of synthetic users, which QA uses to run
+/// scripted scenarios through the same auth path as real players.
+///
+/// Synthetic users are identified by their indexes, the persistence service
+/// pre-seeds them on start, so that tokens don't need to go through
+/// registration.
+#[derive(Clone)]
+pub struct SyntheticAuth {
+    key: Hs256Key,
+}
+
+impl SyntheticAuth {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: Hs256Key::new(secret),
+        }
+    }
+
+    /// Returns `None` unless the pentest mode is enabled (in the staging
+    /// environment only) and the secret is set.
+    pub fn from_env() -> Option<Self> {
+        let is_enabled = std::env::var(PENTEST_MODE_ENV)
+            .map_or(false, |value| matches!(value.as_str(), "1" | "true"));
+        if !is_enabled {
+            return None;
+        }
+
+        let muddle_env = std::env::var("MUDDLE_ENV").unwrap_or_default();
+        if muddle_env != STAGING_ENV {
+            log::error!(
+                "Ignoring {PENTEST_MODE_ENV}: synthetic identities are allowed only in the {STAGING_ENV} environment (current: {muddle_env:?})"
+            );
+            return None;
+        }
+
+        let Ok(secret) = std::env::var(SYNTHETIC_AUTH_SECRET_ENV) else {
+            log::error!("Ignoring {PENTEST_MODE_ENV}: {SYNTHETIC_AUTH_SECRET_ENV} is not set");
+            return None;
+        };
+        log::warn!("Pentest mode is enabled, synthetic identities are accepted");
+        Some(Self::new(secret.as_bytes()))
+    }
+
+    pub fn issue_token(&self, index: u32, ttl: chrono::Duration) -> Result<String, CreationError> {
+        let claims = Claims::new(JwtAuthClaims {
+            iss: SYNTHETIC_ISSUER.to_owned(),
+            sub: synthetic_subject(index),
+            email: Some(synthetic_email(index)),
+            aud: SYNTHETIC_AUDIENCE.to_owned(),
+        })
+        .set_duration_and_issuance(&Default::default(), ttl);
+        Hs256.token(
+            Header::default().with_key_id(SYNTHETIC_KEY_ID),
+            &claims,
+            &self.key,
+        )
+    }
+
+    pub fn verify(&self, token: &UntrustedToken) -> Result<Token<JwtAuthClaims>, ValidationError> {
+        let token: Token<JwtAuthClaims> = Hs256.validate_integrity(token, &self.key)?;
+        token.claims().validate_expiration(&Default::default())?;
+        Ok(token)
+    }
+}
+
+pub fn synthetic_subject(index: u32) -> String {
+    format!("synthetic-{index}")
+}
+
+pub fn synthetic_email(index: u32) -> String {
+    format!("synthetic-{index}@qa.muddle.run")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_verify() {
+        let synthetic_auth = SyntheticAuth::new(b"secret");
+        let token = synthetic_auth
+            .issue_token(3, chrono::Duration::minutes(5))
+            .unwrap();
+        let token = UntrustedToken::new(&token).unwrap();
+        assert_eq!(token.header().key_id.as_deref(), Some(SYNTHETIC_KEY_ID));
+
+        let claims = synthetic_auth
+            .verify(&token)
+            .unwrap()
+            .claims()
+            .custom
+            .clone();
+        assert_eq!(claims.iss, SYNTHETIC_ISSUER);
+        assert_eq!(claims.sub, "synthetic-3");
+        assert_eq!(claims.aud, SYNTHETIC_AUDIENCE);
+
+        // Tokens signed with a different secret are rejected.
+        assert!(SyntheticAuth::new(b"another secret")
+            .verify(&token)
+            .is_err());
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let synthetic_auth = SyntheticAuth::new(b"secret");
+        let token = synthetic_auth
+            .issue_token(0, chrono::Duration::minutes(-5))
+            .unwrap();
+        let token = UntrustedToken::new(&token).unwrap();
+        assert!(synthetic_auth.verify(&token).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Distributed tracing across the matchmaker, game servers and persistence.
+//!
+//! Spans are exported with OTLP only if the crate is built with the
+//! `telemetry` feature and `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Otherwise,
+//! [`TraceSpan`] just passes the incoming trace context through, so that a
+//! service without the feature doesn't break the trace for the services
+//! after it.
+
+use std::borrow::Cow;
+
+/// The W3C Trace Context header.
+pub const TRACE_PARENT_HEADER: &str = "traceparent";
+
+const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Installs the global tracer. Must be called from within a Tokio runtime, as
+/// spans are exported by a background task.
+pub fn init(service_name: &'static str) {
+    // Even if spans aren't exported, the incoming trace context still needs to
+    // be propagated.
+    #[cfg(feature = "telemetry")]
+    opentelemetry::global::set_text_map_propagator(
+        opentelemetry::sdk::propagation::TraceContextPropagator::new(),
+    );
+
+    if std::env::var(OTLP_ENDPOINT_ENV).is_err() {
+        return;
+    }
+
+    #[cfg(feature = "telemetry")]
+    {
+        use opentelemetry::{
+            sdk::{trace, Resource},
+            KeyValue,
+        };
+        use opentelemetry_otlp::WithExportConfig;
+
+        let resource = Resource::new(vec![KeyValue::new("service.name", service_name)]);
+        let result = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_env())
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(opentelemetry::runtime::Tokio);
+        match result {
+            Ok(_) => log::info!("Exporting traces as {}", service_name),
+            Err(err) => log::error!("Failed to initialize tracing: {:?}", err),
+        }
+    }
+    #[cfg(not(feature = "telemetry"))]
+    log::warn!(
+        "{} is set, but {} is built without the telemetry feature",
+        OTLP_ENDPOINT_ENV,
+        service_name
+    );
+}
+
+/// Flushes the spans that haven't been exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "telemetry")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// A span that ends when dropped.
+pub struct TraceSpan {
+    #[cfg(feature = "telemetry")]
+    context: opentelemetry::Context,
+    #[cfg(not(feature = "telemetry"))]
+    trace_parent: Option<String>,
+}
+
+impl TraceSpan {
+    /// Starts a span continuing the trace of `trace_parent` (a `traceparent`
+    /// header value), or a new trace if it's missing or malformed.
+    pub fn start(name: impl Into<Cow<'static, str>>, trace_parent: Option<&str>) -> Self {
+        #[cfg(feature = "telemetry")]
+        {
+            use opentelemetry::global;
+            use std::collections::HashMap;
+
+            let carrier: HashMap<String, String> = trace_parent
+                .map(|trace_parent| (TRACE_PARENT_HEADER.to_owned(), trace_parent.to_owned()))
+                .into_iter()
+                .collect();
+            let parent = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+            Self::start_with_parent(name, &parent)
+        }
+        #[cfg(not(feature = "telemetry"))]
+        {
+            let _ = name;
+            Self {
+                trace_parent: trace_parent.map(ToOwned::to_owned),
+            }
+        }
+    }
+
+    pub fn child(&self, name: impl Into<Cow<'static, str>>) -> Self {
+        #[cfg(feature = "telemetry")]
+        {
+            Self::start_with_parent(name, &self.context)
+        }
+        #[cfg(not(feature = "telemetry"))]
+        {
+            let _ = name;
+            Self {
+                trace_parent: self.trace_parent.clone(),
+            }
+        }
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn start_with_parent(
+        name: impl Into<Cow<'static, str>>,
+        parent: &opentelemetry::Context,
+    ) -> Self {
+        use opentelemetry::trace::{TraceContextExt, Tracer};
+
+        let span = opentelemetry::global::tracer("muddle-run").start_with_context(name, parent);
+        Self {
+            context: parent.with_span(span),
+        }
+    }
+
+    /// The `traceparent` header value to propagate to the next service.
+    pub fn trace_parent(&self) -> Option<String> {
+        #[cfg(feature = "telemetry")]
+        {
+            use std::collections::HashMap;
+
+            let mut carrier = HashMap::new();
+            opentelemetry::global::get_text_map_propagator(|propagator| {
+                propagator.inject_context(&self.context, &mut carrier)
+            });
+            carrier.remove(TRACE_PARENT_HEADER)
+        }
+        #[cfg(not(feature = "telemetry"))]
+        {
+            self.trace_parent.clone()
+        }
+    }
+
+    /// Adds the `traceparent` header, so that the receiving service continues
+    /// the trace.
+    #[cfg(feature = "reqwest")]
+    pub fn propagate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.trace_parent() {
+            Some(trace_parent) => request.header(TRACE_PARENT_HEADER, trace_parent),
+            None => request,
+        }
+    }
+
+    pub fn set_attribute(&self, key: &'static str, value: impl Into<String>) {
+        #[cfg(feature = "telemetry")]
+        {
+            use opentelemetry::{trace::TraceContextExt, KeyValue};
+            self.context
+                .span()
+                .set_attribute(KeyValue::new(key, value.into()));
+        }
+        #[cfg(not(feature = "telemetry"))]
+        let _ = (key, value);
+    }
+
+    pub fn set_error(&self, message: impl Into<Cow<'static, str>>) {
+        #[cfg(feature = "telemetry")]
+        {
+            use opentelemetry::trace::{Status, TraceContextExt};
+            self.context.span().set_status(Status::error(message));
+        }
+        #[cfg(not(feature = "telemetry"))]
+        let _ = message;
+    }
+}
+
+#[cfg(feature = "telemetry")]
+impl Drop for TraceSpan {
+    fn drop(&mut self) {
+        use opentelemetry::trace::TraceContextExt;
+        self.context.span().end();
+    }
+}